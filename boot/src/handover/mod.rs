@@ -1,23 +1,34 @@
+use alloc::string::String;
+use alloc::vec::Vec;
 use bks::Framebuffer;
+use bks::Module;
 use bks::Psf1Font;
 use bks::Psf1Header;
 use bks::PAGE_SIZE;
 use log::error;
 use log::info;
 use uefi::prelude::*;
-use uefi::proto::console::gop::GraphicsOutput;
+use uefi::proto::console::gop::{GraphicsOutput, PixelFormat};
+use uefi::proto::media::file::Directory;
 use uefi::proto::media::file::File;
+use uefi::proto::media::file::FileAttribute;
 use uefi::proto::media::file::FileInfo;
+use uefi::proto::media::file::FileMode;
+use uefi::proto::media::file::RegularFile;
 use uefi::table::boot::AllocateType;
 use uefi::table::boot::MemoryType;
 use uefi::table::Runtime;
+use uefi::CString16;
 use uefi::Handle;
 
-use crate::load_file;
+use crate::{load_file, open_root_volume};
 
 const PSF1_MAGIC0: u8 = 0x36;
 const PSF1_MAGIC1: u8 = 0x04;
 
+/// The directory on the boot volume all modules are loaded from
+const MODULE_DIRECTORY: &str = "modules";
+
 pub fn init_gop(_handle: Handle, table: &SystemTable<Boot>) -> Framebuffer {
     let gop = unsafe {
         &mut *(table
@@ -27,12 +38,20 @@ pub fn init_gop(_handle: Handle, table: &SystemTable<Boot>) -> Framebuffer {
             .get())
     };
 
+    let format = match gop.current_mode_info().pixel_format() {
+        PixelFormat::Rgb => bks::PixelFormat::Rgb,
+        PixelFormat::Bgr => bks::PixelFormat::Bgr,
+        PixelFormat::Bitmask => bks::PixelFormat::Bitmask,
+        PixelFormat::BltOnly => bks::PixelFormat::BltOnly,
+    };
+
     Framebuffer::new(
         gop.frame_buffer().as_mut_ptr() as u64,
         gop.frame_buffer().size(),
         gop.current_mode_info().resolution().0,
         gop.current_mode_info().resolution().1,
         gop.current_mode_info().stride(),
+        format,
     )
 }
 
@@ -127,3 +146,76 @@ pub fn find_rsdp(table: &SystemTable<Runtime>) -> u64 {
 
     rsdp as u64
 }
+
+/// # Load Modules
+/// Loads every file in the `/modules` directory of the boot volume into memory, so that it can be
+/// handed over to the kernel. A missing directory simply means that there are no modules.
+pub fn load_modules(handle: Handle, table: &SystemTable<Boot>) -> Vec<Module> {
+    let mut modules = Vec::new();
+    let mut root = open_root_volume(handle, table);
+    let mut directory = match root.open(
+        &CString16::try_from(MODULE_DIRECTORY).unwrap(),
+        FileMode::Read,
+        FileAttribute::READ_ONLY,
+    ) {
+        Ok(fh) => unsafe { Directory::new(fh.unwrap()) },
+        Err(_) => {
+            info!("No modules found");
+            return modules;
+        }
+    };
+
+    let mut info_buf: [u8; 512] = [0; 512];
+    loop {
+        let info = match directory.read_entry(&mut info_buf) {
+            Ok(entry) => match entry.unwrap() {
+                Some(info) => info,
+                None => break,
+            },
+            Err(e) => {
+                error!("Failed to read the module directory: {:?}", e.status());
+                break;
+            }
+        };
+        if info.attribute().contains(FileAttribute::DIRECTORY) {
+            continue; // Also skips `.` and `..`
+        }
+
+        let name: String = info.file_name().iter().map(|c| char::from(*c)).collect();
+        if modules.len() >= bks::MAX_MODULES {
+            error!("Too many modules, dropping '{}'", name);
+            continue;
+        }
+
+        let size = info.file_size() as usize;
+        let mut file = match directory.open(
+            &CString16::try_from(name.as_str()).unwrap(),
+            FileMode::Read,
+            FileAttribute::READ_ONLY,
+        ) {
+            Ok(fh) => unsafe { RegularFile::new(fh.unwrap()) },
+            Err(e) => {
+                error!("Failed to open module '{}': {:?}", name, e.status());
+                continue;
+            }
+        };
+        let pages = (size + PAGE_SIZE as usize - 1) / PAGE_SIZE as usize;
+        let ptr = table
+            .boot_services()
+            .allocate_pages(
+                AllocateType::AnyPages,
+                MemoryType::LOADER_DATA,
+                pages.max(1),
+            )
+            .expect_success("Failed to allocate memory for a module");
+        let buffer = unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, size) };
+        let read = file
+            .read(buffer)
+            .expect_success("Failed to read module into buffer");
+        assert_eq!(read, size);
+
+        info!("Loaded module '{}' ({} bytes) at {:#x?}", name, size, ptr);
+        modules.push(Module::new(&name, ptr, size as u64));
+    }
+    modules
+}
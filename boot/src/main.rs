@@ -22,7 +22,10 @@ use uefi::{
             fs::SimpleFileSystem,
         },
     },
-    table::boot::{AllocateType, MemoryType, OpenProtocolAttributes, OpenProtocolParams},
+    table::boot::{
+        AllocateType, MemoryAttribute, MemoryDescriptor, MemoryType, OpenProtocolAttributes,
+        OpenProtocolParams,
+    },
 };
 use uefi::{CString16, ResultExt};
 use xmas_elf::{
@@ -31,12 +34,9 @@ use xmas_elf::{
     ElfFile,
 };
 
-pub fn load_file<'a>(
-    dir: Option<Directory>,
-    path: &str,
-    handle: Handle,
-    table: &SystemTable<Boot>,
-) -> Result<RegularFile, Status> {
+/// # Open Root Volume
+/// Opens the root directory of the volume the bootloader was loaded from
+pub fn open_root_volume(handle: Handle, table: &SystemTable<Boot>) -> Directory {
     let loaded_img = unsafe {
         &mut *(table
             .boot_services()
@@ -69,11 +69,20 @@ pub fn load_file<'a>(
             .get())
     };
 
+    filesystem
+        .open_volume()
+        .expect_success("Failed to open root volume")
+}
+
+pub fn load_file<'a>(
+    dir: Option<Directory>,
+    path: &str,
+    handle: Handle,
+    table: &SystemTable<Boot>,
+) -> Result<RegularFile, Status> {
     let mut directory = match dir {
         Some(d) => d,
-        None => filesystem
-            .open_volume()
-            .expect_success("Failed to open root volume"),
+        None => open_root_volume(handle, table),
     };
 
     let filehandle = match directory.open(
@@ -199,6 +208,8 @@ fn efi_main(handle: uefi::Handle, mut table: SystemTable<Boot>) -> Status {
         }
     };
 
+    let modules = handover::load_modules(handle, &mut table);
+
     let config = Config::new(Language::English, KeyboardLayout::German);
 
     let kmain: extern "sysv64" fn(info: Handover) = unsafe { core::mem::transmute(entry) };
@@ -217,6 +228,9 @@ fn efi_main(handle: uefi::Handle, mut table: SystemTable<Boot>) -> Status {
     let mut storage = vec![0_u8; max_mmap_size].into_boxed_slice();
     let entries = sizes.map_size / sizes.entry_size;
     let slice = &mut vec![EfiMemoryDescriptor::empty(); entries][..];
+    // Nothing can be allocated once the boot services are gone
+    let runtime_map = &mut vec![MemoryDescriptor::default(); entries][..];
+    let mut runtime_entries = 0;
 
     info!("Exiting boot services...");
     let (mut rt_table, map_iter) = table
@@ -225,12 +239,17 @@ fn efi_main(handle: uefi::Handle, mut table: SystemTable<Boot>) -> Status {
 
     let rsdp = handover::find_rsdp(&mut rt_table);
 
-    let mut ents = 0;
-    unsafe {
-        let _ = map_iter
+    // The descriptors are copied into a densely packed array, so the firmware's stride
+    // (`sizes.entry_size`) does not apply to the handed over map
+    let ents = unsafe {
+        map_iter
             .copied()
             .zip(slice.iter_mut())
             .fold(0, |count, (a, b)| {
+                if a.att.contains(MemoryAttribute::RUNTIME) {
+                    runtime_map[runtime_entries] = a;
+                    runtime_entries += 1;
+                }
                 // Reserved Memory should not be included
                 if a.ty == MemoryType::RESERVED {
                     *b = EfiMemoryDescriptor::new(
@@ -242,11 +261,22 @@ fn efi_main(handle: uefi::Handle, mut table: SystemTable<Boot>) -> Status {
                     );
                     return count + 1;
                 }
-                ents += 1;
                 *b = core::mem::transmute(a);
                 count + 1
-            });
+            })
+    };
+
+    // The runtime services keep their physical addresses, which the kernel identity maps
+    for desc in runtime_map[..runtime_entries].iter_mut() {
+        desc.virt_start = desc.phys_start;
     }
+    let virtual_map = unsafe {
+        rt_table
+            .runtime_services()
+            .set_virtual_address_map(&mut runtime_map[..runtime_entries])
+    }
+    .is_ok();
+    let runtime_services = rt_table.runtime_services() as *const _ as u64;
 
     // I am not sure about this
     // But, as the kernel uses it as mut, I do not wish
@@ -257,13 +287,18 @@ fn efi_main(handle: uefi::Handle, mut table: SystemTable<Boot>) -> Status {
         font,
         slice.as_mut_ptr(),
         max_mmap_size,
-        sizes.entry_size,
+        core::mem::size_of::<EfiMemoryDescriptor>(),
         ents,
         config,
         initramfs_base,
         initramfs_size,
         rsdp,
     );
+    // `load_modules` never returns more than `bks::MAX_MODULES` modules
+    for module in modules {
+        handover.push_module(module);
+    }
+    handover.set_runtime_services(runtime_services, virtual_map);
 
     kmain(handover);
     Status::SUCCESS
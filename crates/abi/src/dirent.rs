@@ -0,0 +1,21 @@
+/// The type of a file a `Dirent` names is not known
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+pub const DT_SOCK: u8 = 12;
+
+/// # Dirent
+/// The head of a record `getdents()` fills. The NUL terminated name follows it right away and
+/// the next record starts `reclen` bytes after this one, which keeps every record 8 byte aligned.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dirent {
+    /// The `Stat::ino` of the file
+    pub ino: u64,
+    /// The length of the whole record, padding included
+    pub reclen: u16,
+    /// One of the `DT_*` constants
+    pub ty: u8,
+    pub name: [u8; 0],
+}
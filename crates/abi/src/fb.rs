@@ -0,0 +1,40 @@
+//! # FB
+//! The requests `ioctl(fd, request, arg)` takes on `/dev/fb0`. The framebuffer itself is mapped
+//! with `mmap(NULL, size, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0)`.
+enumtastic::const_enum! {
+    /// What `ioctl()` does on the framebuffer
+    pub enum FbRequest: u64 => {
+        /// Writes the `FbInfo` of the framebuffer to `arg`
+        GetInfo = 0x4600,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    /// The order of the bytes of a pixel, every format has 32 bits per pixel
+    pub enum FbFormat: u32 => {
+        /// Red, green, blue and an unused byte
+        Rgbx = 0,
+        /// Blue, green, red and an unused byte
+        Bgrx = 1,
+    }
+
+    impl {}
+}
+
+/// # FB Info
+/// The struct `ioctl(fd, FbRequest::GetInfo, arg)` fills
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FbInfo {
+    /// The bytes a mapping of the whole framebuffer takes
+    pub size: u64,
+    /// The visible pixels per line
+    pub width: u32,
+    pub height: u32,
+    /// The bytes from the start of one line to the start of the next
+    pub pitch: u32,
+    /// An `FbFormat`
+    pub format: u32,
+}
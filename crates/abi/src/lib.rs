@@ -0,0 +1,37 @@
+//! # ABI
+//! Everything userspace and the kernel have to agree on: The numbers of the system calls, the
+//! errno values they fail with and the layout of the structs they exchange. The kernel checks
+//! its own structs against the ones defined here when it is built.
+#![no_std]
+pub mod dirent;
+pub mod errno;
+pub mod fb;
+pub mod random;
+pub mod reboot;
+pub mod signal;
+pub mod stat;
+pub mod syscall;
+pub mod sysinfo;
+pub mod time;
+pub mod tty;
+pub mod utsname;
+pub mod vmstat;
+
+pub use dirent::Dirent;
+pub use errno::ErrorCode;
+pub use fb::{FbFormat, FbInfo, FbRequest};
+pub use reboot::RebootCommand;
+pub use signal::Signal;
+pub use stat::Stat;
+pub use syscall::SyscallNumber;
+pub use sysinfo::{SysInfo, SysInfoTag};
+pub use time::Timespec;
+pub use tty::{TtyMode, TtyRequest};
+pub use utsname::Utsname;
+pub use vmstat::VmStat;
+
+/// # ABI Version
+/// The version of this interface, which `api_version()` returns. It changes whenever a system
+/// call, an errno or a struct changes in a way a program built against an older version would
+/// misread, so such a program can stop instead of corrupting memory.
+pub const ABI_VERSION: u32 = 2;
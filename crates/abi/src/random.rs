@@ -0,0 +1,8 @@
+//! # Random
+//! The flags of `getrandom(buf, len, flags)`, the same as on Linux
+/// Fail with EAGAIN instead of waiting for the generator to be seeded
+pub const GRND_NONBLOCK: u64 = 0x1;
+/// Accepted for compatibility, there is only one generator
+pub const GRND_RANDOM: u64 = 0x2;
+/// Return random bytes even if the generator is not seeded yet
+pub const GRND_INSECURE: u64 = 0x4;
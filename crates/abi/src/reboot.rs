@@ -0,0 +1,16 @@
+//! # Reboot
+//! The arguments of `reboot(magic1, magic2, cmd)`, the same as on Linux. Both magic numbers have
+//! to match, so a stray call with the wrong number in `rax` does not turn the machine off.
+pub const REBOOT_MAGIC1: u64 = 0xFEE1_DEAD;
+pub const REBOOT_MAGIC2: u64 = 672_274_793;
+
+enumtastic::const_enum! {
+    /// What `reboot()` does
+    pub enum RebootCommand: u64 => {
+        Restart = 0x0123_4567,
+        Halt = 0xCDEF_0123,
+        PowerOff = 0x4321_FEDC,
+    }
+
+    impl {}
+}
@@ -0,0 +1,50 @@
+//! # Signal
+//! The numbers of the signals `kill()` sends, the same as on Linux
+enumtastic::const_enum! {
+    /// A signal, what it does if nothing handles it is in the comment
+    pub enum Signal: u32 => {
+        /// Terminates, the controlling terminal went away
+        SIGHUP = 1,
+        /// Terminates, Ctrl-C on the terminal
+        SIGINT = 2,
+        /// Terminates
+        SIGQUIT = 3,
+        /// Terminates, the process ran an invalid instruction
+        SIGILL = 4,
+        /// Terminates, a breakpoint or a debug trap
+        SIGTRAP = 5,
+        /// Terminates, a misaligned access
+        SIGBUS = 7,
+        /// Terminates, an arithmetic fault like a division by zero
+        SIGFPE = 8,
+        /// Terminates, cannot be ignored
+        SIGKILL = 9,
+        /// Terminates
+        SIGUSR1 = 10,
+        /// Terminates, the process accessed memory or a port it may not
+        SIGSEGV = 11,
+        /// Terminates
+        SIGUSR2 = 12,
+        /// Terminates
+        SIGPIPE = 13,
+        /// Terminates
+        SIGALRM = 14,
+        /// Terminates
+        SIGTERM = 15,
+        /// Ignored
+        SIGCHLD = 17,
+        /// Continues a stopped process, otherwise ignored
+        SIGCONT = 18,
+        /// Stops, cannot be ignored
+        SIGSTOP = 19,
+        /// Stops, Ctrl-Z on the terminal
+        SIGTSTP = 20,
+        /// Ignored
+        SIGWINCH = 28,
+    }
+
+    impl {}
+}
+
+/// The highest signal number, `kill()` fails with `EINVAL` on anything above it
+pub const SIGNAL_MAX: u32 = 31;
@@ -0,0 +1,47 @@
+enumtastic::const_enum! {
+    /// The number of a system call, passed in `rax`. They follow the numbering of Linux, calls
+    /// Linux does not have come after its own.
+    pub enum SyscallNumber: u64 => {
+        Read = 0,
+        Write = 1,
+        Open = 2,
+        Close = 3,
+        Stat = 4,
+        Fstat = 5,
+        Mmap = 9,
+        Munmap = 11,
+        Ioctl = 16,
+        Mincore = 27,
+        NanoSleep = 35,
+        Socket = 41,
+        SendTo = 44,
+        RecvFrom = 45,
+        Bind = 49,
+        /// Sends a `Signal` to a process, or to a process group for a negative pid
+        Kill = 62,
+        Uname = 63,
+        /// Fills a buffer with `Dirent` records
+        GetDents = 78,
+        SysInfo = 99,
+        SetPgid = 109,
+        SetSid = 112,
+        GetPgid = 121,
+        Reboot = 169,
+        /// Allows or denies the calling process access to a range of I/O ports
+        Ioperm = 173,
+        Futex = 202,
+        GetRandom = 318,
+        ShmOpen = 1024,
+        ShmUnlink = 1025,
+        /// Returns the `ABI_VERSION` of the kernel
+        ApiVersion = 1026,
+        /// Writes the event trace of the kernel to its serial port, a debugging aid
+        TraceDump = 1027,
+        /// Fills a `VmStat` with the page counts of the address space of a process
+        VmStat = 1028,
+        /// Adds to the nice value of the calling process, returning `20 - nice` like Linux does
+        Nice = 1029,
+    }
+
+    impl {}
+}
@@ -0,0 +1,45 @@
+/// The number of allocation tags in `SysInfo::top_consumers`
+pub const SYSINFO_TOP_CONSUMERS: usize = 4;
+/// The longest tag name `SysInfoTag` holds, longer ones are cut off
+pub const SYSINFO_TAG_NAME_LEN: usize = 16;
+
+/// # Sys Info Tag
+/// The heap usage of an allocation tag
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SysInfoTag {
+    /// The name of the tag, padded with zeroes. An unused entry has an empty name.
+    pub name: [u8; SYSINFO_TAG_NAME_LEN],
+    pub bytes: u64,
+    /// The number of live allocations
+    pub count: u64,
+}
+
+/// # Sys Info
+/// The struct `sysinfo()` fills. Fields are only ever appended, `valid` tells which of them the
+/// kernel filled.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SysInfo {
+    /// The size of the struct as the caller knows it, set by the caller
+    pub size: u32,
+    pub _reserved: u32,
+    /// The fields that are valid, bit 0 for `total_ram` and so on
+    pub valid: u64,
+    /// The usable RAM in bytes
+    pub total_ram: u64,
+    /// The RAM no one allocated in bytes
+    pub free_ram: u64,
+    /// The seconds since boot
+    pub uptime_secs: u64,
+    /// The number of tasks that did not exit
+    pub procs: u64,
+    pub page_size: u64,
+    /// The bytes of all live heap allocations
+    pub heap_bytes: u64,
+    /// The allocation tags using the most heap, the largest first
+    pub top_consumers: [SysInfoTag; SYSINFO_TOP_CONSUMERS],
+    /// The time all online CPUs were idle since they joined the scheduler, in tenths of a
+    /// percent
+    pub idle_permille: u64,
+}
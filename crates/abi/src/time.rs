@@ -0,0 +1,8 @@
+/// # Timespec
+/// A `struct timespec` as passed to system calls
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
@@ -0,0 +1,30 @@
+//! # TTY
+//! The requests `ioctl(fd, request, arg)` takes on the console. The process group requests are
+//! the same as on Linux, the mode is a plain `TtyMode` instead of a `struct termios`.
+enumtastic::const_enum! {
+    /// What `ioctl()` does on the console
+    pub enum TtyRequest: u64 => {
+        /// Writes the current `TtyMode` as a `u32` to `arg`
+        GetMode = 0x5480,
+        /// Switches to the `TtyMode` `arg`, discarding the input not read yet
+        SetMode = 0x5481,
+        /// `TIOCGPGRP`, writes the foreground process group as an `i32` to `arg`
+        GetForegroundGroup = 0x540F,
+        /// `TIOCSPGRP`, makes the `i32` at `arg` the foreground process group
+        SetForegroundGroup = 0x5410,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    /// How the console passes on what is typed
+    pub enum TtyMode: u32 => {
+        /// Line by line once enter is pressed, echoed and editable
+        Canonical = 0,
+        /// Byte by byte as it is typed, without echo
+        Raw = 1,
+    }
+
+    impl {}
+}
@@ -0,0 +1,21 @@
+/// The length of every field of `Utsname`, including the terminating NUL
+pub const UTSNAME_FIELD_LEN: usize = 65;
+
+/// # Utsname
+/// The struct `uname()` fills, laid out like `struct utsname` on Linux. Every field is a NUL
+/// terminated string.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Utsname {
+    /// The name of the kernel
+    pub sysname: [u8; UTSNAME_FIELD_LEN],
+    /// The name of the machine on the network
+    pub nodename: [u8; UTSNAME_FIELD_LEN],
+    /// The version of the kernel
+    pub release: [u8; UTSNAME_FIELD_LEN],
+    /// The build of the kernel: its commit and when it was built
+    pub version: [u8; UTSNAME_FIELD_LEN],
+    /// The architecture
+    pub machine: [u8; UTSNAME_FIELD_LEN],
+    pub domainname: [u8; UTSNAME_FIELD_LEN],
+}
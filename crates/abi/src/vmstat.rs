@@ -0,0 +1,18 @@
+/// # VM Stat
+/// The struct `vmstat()` fills, the page counts of the address space of a process
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VmStat {
+    /// The pages of every mapping, `PROT_NONE` ones included
+    pub mapped_pages: u64,
+    /// The pages that have a frame of their own mapped
+    pub resident_pages: u64,
+    /// The pages shared copy-on-write with another process
+    pub cow_shared_pages: u64,
+    /// The pages that are only mapped on their first access and were not accessed yet
+    pub lazy_pages: u64,
+    /// The number of mappings
+    pub regions: u64,
+    /// The pages that were read but not written, which map the shared zero frame
+    pub zero_pages: u64,
+}
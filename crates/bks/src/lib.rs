@@ -106,6 +106,100 @@ impl core::fmt::Debug for EfiMemoryDescriptor {
     }
 }
 
+/// # EFI Memory Descriptors
+/// An iterator over a raw memory map with an arbitrary stride
+#[derive(Clone, Copy)]
+pub struct EfiMemoryDescriptors {
+    base: *const u8,
+    entries: usize,
+    entry_size: usize,
+    idx: usize,
+}
+
+impl EfiMemoryDescriptors {
+    pub fn new(base: *const u8, entries: usize, entry_size: usize) -> Self {
+        Self {
+            base,
+            entries,
+            entry_size: entry_size.max(core::mem::size_of::<EfiMemoryDescriptor>()),
+            idx: 0,
+        }
+    }
+}
+
+impl Iterator for EfiMemoryDescriptors {
+    type Item = EfiMemoryDescriptor;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx >= self.entries {
+            return None;
+        }
+        let desc = unsafe {
+            (self.base.add(self.idx * self.entry_size) as *const EfiMemoryDescriptor)
+                .read_unaligned()
+        };
+        self.idx += 1;
+        Some(desc)
+    }
+}
+
+/// The maximum number of modules the bootloader can hand over
+pub const MAX_MODULES: usize = 16;
+pub const MODULE_NAME_LENGTH: usize = 32;
+
+/// # Module
+/// A file loaded by the bootloader alongside the kernel
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Module {
+    /// The file name, padded with zeroes
+    pub name: [u8; MODULE_NAME_LENGTH],
+    pub phys_start: u64,
+    pub len: u64,
+}
+
+impl Module {
+    pub const fn empty() -> Self {
+        Self {
+            name: [0; MODULE_NAME_LENGTH],
+            phys_start: 0,
+            len: 0,
+        }
+    }
+
+    /// # New
+    /// Creates a new module, names longer than `MODULE_NAME_LENGTH` bytes are truncated
+    pub fn new(name: &str, phys_start: u64, len: u64) -> Self {
+        let mut module = Self {
+            phys_start,
+            len,
+            ..Self::empty()
+        };
+        let len = name.len().min(MODULE_NAME_LENGTH);
+        module.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        module
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(MODULE_NAME_LENGTH);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+impl core::fmt::Debug for Module {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Module")
+            .field("name", &self.name())
+            .field("phys_start", &format_args!("{:#x}", self.phys_start))
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
 #[repr(C)]
 pub struct Handover {
     // Must always be 42: If not, a bad bootloader was used
@@ -121,6 +215,13 @@ pub struct Handover {
     pub initramfs_base: u64,
     pub initramfs_size: usize,
     pub rsdp: u64,
+    modules: [Module; MAX_MODULES],
+    module_count: usize,
+    /// The address of the UEFI runtime services table, 0 if there is none
+    pub runtime_services: u64,
+    /// Whether the bootloader called `SetVirtualAddressMap`, with virtual addresses equal to the
+    /// physical ones. Otherwise the runtime services are still in physical mode.
+    pub runtime_virtual_map: bool,
 }
 
 impl Handover {
@@ -149,9 +250,38 @@ impl Handover {
             initramfs_base,
             initramfs_size,
             rsdp,
+            modules: [Module::empty(); MAX_MODULES],
+            module_count: 0,
+            runtime_services: 0,
+            runtime_virtual_map: false,
         }
     }
 
+    /// # Set Runtime Services
+    /// Hands over the UEFI runtime services table at `table`, which is in virtual mode if
+    /// `virtual_map` is set
+    pub fn set_runtime_services(&mut self, table: u64, virtual_map: bool) {
+        self.runtime_services = table;
+        self.runtime_virtual_map = virtual_map;
+    }
+
+    /// # Push Module
+    /// Adds a module to the handover.
+    /// ## Returns
+    /// - bool = false if there already are `MAX_MODULES` modules
+    pub fn push_module(&mut self, module: Module) -> bool {
+        if self.module_count >= MAX_MODULES {
+            return false;
+        }
+        self.modules[self.module_count] = module;
+        self.module_count += 1;
+        true
+    }
+
+    pub fn modules(&self) -> &[Module] {
+        &self.modules[..self.module_count]
+    }
+
     pub fn checknum(&self) -> u32 {
         self.checknum
     }
@@ -176,6 +306,18 @@ impl Handover {
         self.memory_map.as_mut_ptr()
     }
 
+    /// # Descriptors
+    /// Iterates over the memory map as handed over by the firmware.
+    /// Unlike `memory_map()`, this respects `mmap_entry_size`, which may be bigger than
+    /// `size_of::<EfiMemoryDescriptor>()`.
+    pub fn descriptors(&mut self) -> EfiMemoryDescriptors {
+        EfiMemoryDescriptors::new(
+            self.memory_map.as_mut_ptr() as *const u8,
+            self.mmap_entries,
+            self.mmap_entry_size,
+        )
+    }
+
     unsafe fn retrieve_memory_map(&mut self) -> &mut [EfiMemoryDescriptor] {
         core::slice::from_raw_parts_mut(self.memory_map.as_mut_ptr(), self.mmap_entries)
     }
@@ -244,6 +386,20 @@ impl Psf1Font {
     }
 }
 
+enum_with_options! {
+    /// The layout of a pixel, mirrors the GOP pixel formats
+    pub enum PixelFormat: u32 => {
+        /// 32 bits per pixel: red, green, blue, reserved
+        Rgb     = 0,
+        /// 32 bits per pixel: blue, green, red, reserved
+        Bgr     = 1,
+        /// Described by a bitmask the kernel does not receive
+        Bitmask = 2,
+        /// No linear framebuffer
+        BltOnly = 3,
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Framebuffer {
@@ -252,16 +408,25 @@ pub struct Framebuffer {
     pub width: usize,
     pub height: usize,
     pub stride: usize,
+    pub format: PixelFormat,
 }
 
 impl Framebuffer {
-    pub fn new(base: u64, size: usize, width: usize, height: usize, stride: usize) -> Self {
+    pub fn new(
+        base: u64,
+        size: usize,
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+    ) -> Self {
         Self {
             base,
             size,
             width,
             height,
             stride,
+            format,
         }
     }
 
@@ -299,6 +464,7 @@ impl core::fmt::Display for Framebuffer {
         writeln!(f, "   Width: {}", self.width)?;
         writeln!(f, "   Height: {}", self.height)?;
         writeln!(f, "   Stride: {}", self.stride)?;
+        writeln!(f, "   Format: {:?}", self.format)?;
         Ok(())
     }
 }
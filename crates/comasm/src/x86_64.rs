@@ -3,3 +3,9 @@ use super::single_instruction;
 single_instruction!(clear_interrupts -> "cli");
 single_instruction!(halt -> "hlt");
 single_instruction!(reload_interrupt_flags -> "sti");
+single_instruction!(pause -> "pause");
+// The instruction after `sti` runs before interrupts are enabled, so an interrupt that is
+// already pending wakes the `hlt` instead of being taken right before it
+single_instruction!(enable_interrupts_and_halt -> "sti; hlt");
+// Writes back every modified cache line, so RAM holds what was written before a reset
+single_instruction!(write_back_and_invalidate_caches -> "wbinvd");
@@ -1,5 +1,26 @@
 #![no_std]
 
+/// # Const Value
+/// The type of the variants of a `const_enum!`, which `try_from_value` compares raw values with
+pub trait ConstValue {
+    /// The value as an `usize`, `None` if it does not fit
+    fn to_usize(&self) -> Option<usize>;
+}
+
+macro_rules! impl_const_value {
+    ($($ty:ty),*) => {
+        $(
+            impl ConstValue for $ty {
+                fn to_usize(&self) -> Option<usize> {
+                    usize::try_from(*self).ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_const_value!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
 #[macro_export]
 macro_rules! count {
     () => (0usize);
@@ -36,6 +57,26 @@ macro_rules! const_enum {
 
             type Me = $integer_type;
 
+            /// Every variant, in the order they are declared in
+            pub const ALL: &[Me] = &[$($name_of_variant),*];
+
+            /// The name of the first variant with the value of `me`
+            pub fn name(me: &Me) -> &'static str {
+                $(
+                    if *me == $name_of_variant {
+                        return stringify!($name_of_variant);
+                    }
+                )*
+                "Unknown"
+            }
+
+            /// The variant with the raw value `value`, `None` for gaps between the variants
+            pub fn try_from_value(value: usize) -> Option<Me> {
+                ALL.iter()
+                    .copied()
+                    .find(|me| enumtastic::ConstValue::to_usize(me) == Some(value))
+            }
+
             $(
                 $visi fn $name_of_fn ($($arg : $typ)*) -> $ret $blck
             )*
@@ -69,6 +110,26 @@ macro_rules! const_enum {
 
             type Me = $integer_type;
 
+            /// Every variant, in the order they are declared in
+            pub const ALL: &[Me] = &[$($name_of_variant),*];
+
+            /// The name of the first variant with the value of `me`
+            pub fn name(me: &Me) -> &'static str {
+                $(
+                    if *me == $name_of_variant {
+                        return stringify!($name_of_variant);
+                    }
+                )*
+                "Unknown"
+            }
+
+            /// The variant with the raw value `value`, `None` for gaps between the variants
+            pub fn try_from_value(value: usize) -> Option<Me> {
+                ALL.iter()
+                    .copied()
+                    .find(|me| enumtastic::ConstValue::to_usize(me) == Some(value))
+            }
+
             $(
                 $visi fn $name_of_fn ($($arg : $typ)*) -> $ret $blck
             )*
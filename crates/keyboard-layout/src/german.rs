@@ -1,11 +1,59 @@
-use super::KeyboardLayout;
+use super::{Key, Layout, NONE};
 
-pub const GERMAN_QWERTZ_LAYOUT: KeyboardLayout = [
-    0 as char, /* escape */
-    0 as char, /* ^ */
-    '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', 'ß', '\'', 0 as char, /* backspace */
-    0 as char, /* Tab */
-    'q', 'w', 'e', 'r', 't', 'z', 'u', 'i', 'o', 'p', 'ü', '+', 0 as char, 0 as char, 'a', 's',
-    'd', 'f', 'g', 'h', 'j', 'k', 'l', 'ö', 'ä', '#', 0 as char, '<', 'y', 'x', 'c', 'v', 'b', 'n',
-    'm', ',', '.', '_', 0 as char, '*', 0 as char, ' ',
-];
+/// The accents that are dead keys on German keyboards type themselves right away
+pub const GERMAN_QWERTZ_LAYOUT: Layout = Layout {
+    name: "de",
+    description: "German QWERTZ",
+    keys: &[
+        Key::new(0x29, '^', '°', NONE),
+        Key::new(0x02, '1', '!', NONE),
+        Key::new(0x03, '2', '"', '²'),
+        Key::new(0x04, '3', '§', '³'),
+        Key::new(0x05, '4', '$', NONE),
+        Key::new(0x06, '5', '%', NONE),
+        Key::new(0x07, '6', '&', NONE),
+        Key::new(0x08, '7', '/', '{'),
+        Key::new(0x09, '8', '(', '['),
+        Key::new(0x0A, '9', ')', ']'),
+        Key::new(0x0B, '0', '=', '}'),
+        Key::new(0x0C, 'ß', '?', '\\'),
+        Key::new(0x0D, '´', '`', NONE),
+        Key::new(0x10, 'q', 'Q', '@'),
+        Key::letter(0x11, 'w'),
+        Key::new(0x12, 'e', 'E', '€'),
+        Key::letter(0x13, 'r'),
+        Key::letter(0x14, 't'),
+        Key::letter(0x15, 'z'),
+        Key::letter(0x16, 'u'),
+        Key::letter(0x17, 'i'),
+        Key::letter(0x18, 'o'),
+        Key::letter(0x19, 'p'),
+        Key::new(0x1A, 'ü', 'Ü', NONE),
+        Key::new(0x1B, '+', '*', '~'),
+        Key::letter(0x1E, 'a'),
+        Key::letter(0x1F, 's'),
+        Key::letter(0x20, 'd'),
+        Key::letter(0x21, 'f'),
+        Key::letter(0x22, 'g'),
+        Key::letter(0x23, 'h'),
+        Key::letter(0x24, 'j'),
+        Key::letter(0x25, 'k'),
+        Key::letter(0x26, 'l'),
+        Key::new(0x27, 'ö', 'Ö', NONE),
+        Key::new(0x28, 'ä', 'Ä', NONE),
+        Key::new(0x2B, '#', '\'', NONE),
+        Key::letter(0x2C, 'y'),
+        Key::letter(0x2D, 'x'),
+        Key::letter(0x2E, 'c'),
+        Key::letter(0x2F, 'v'),
+        Key::letter(0x30, 'b'),
+        Key::letter(0x31, 'n'),
+        Key::new(0x32, 'm', 'M', 'µ'),
+        Key::new(0x33, ',', ';', NONE),
+        Key::new(0x34, '.', ':', NONE),
+        Key::new(0x35, '-', '_', NONE),
+        Key::new(0x37, '*', '*', NONE),
+        Key::new(0x56, '<', '>', '|'),
+    ],
+    keypad_decimal: ',',
+};
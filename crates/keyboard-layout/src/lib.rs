@@ -9,23 +9,90 @@ use qwerty::QWERTY_LAYOUT;
 use bks::KEYBOARD_LAYOUTS_SUPPORTED_NUM;
 
 pub use translator::*;
-const ASCII_CHAR_NUM: usize = 58;
 
 enumtastic::const_enum! {
-    // ASCII Table:
+    // Scancode set 1
     pub enum Modifier: u8 => {
         LeftShift = 0x2A,
         RightShift = 0x36,
+        LeftControl = 0x1D,
+        LeftAlt = 0x38,
         Enter = 0x1C,
         BackSpace = 0x0E,
         Spacebar = 0x39,
+        CapsLock = 0x3A,
+        NumLock = 0x45,
+        ScrollLock = 0x46,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    // Keys sent after `EXTENDED_PREFIX`
+    pub enum ExtendedKey: u8 => {
+        RightControl = 0x1D,
+        /// AltGr on layouts that have it
+        RightAlt = 0x38,
+        PageUp = 0x49,
+        PageDown = 0x51,
     }
 
     impl {}
 }
 
 pub const RELEASED_COUNTERPART: u8 = 0x80;
+/// Precedes the scancodes of `ExtendedKey`s
+pub const EXTENDED_PREFIX: u8 = 0xE0;
+/// Where a key types nothing
+pub const NONE: char = '\0';
+
+/// # Key
+/// What a key types on its own, with Shift and with AltGr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub scancode: u8,
+    pub normal: char,
+    pub shifted: char,
+    pub altgr: char,
+}
 
-type KeyboardLayout = [char; ASCII_CHAR_NUM];
-pub const KEYBOARD_LAYOUTS: [KeyboardLayout; KEYBOARD_LAYOUTS_SUPPORTED_NUM] =
-    [QWERTY_LAYOUT, GERMAN_QWERTZ_LAYOUT];
+impl Key {
+    pub const fn new(scancode: u8, normal: char, shifted: char, altgr: char) -> Self {
+        Self {
+            scancode,
+            normal,
+            shifted,
+            altgr,
+        }
+    }
+
+    /// A letter, which Shift and CapsLock turn into its uppercase
+    pub const fn letter(scancode: u8, letter: char) -> Self {
+        Self::new(scancode, letter, letter.to_ascii_uppercase(), NONE)
+    }
+}
+
+/// # Layout
+/// The keys of a layout that type something, the ones of the keypad excluded
+#[derive(Debug)]
+pub struct Layout {
+    /// What the layout is selected by, e.g. `de`
+    pub name: &'static str,
+    pub description: &'static str,
+    pub keys: &'static [Key],
+    /// What the decimal key of the keypad types
+    pub keypad_decimal: char,
+}
+
+/// Every layout, indexed by `bks::KeyboardLayout`
+pub static KEYBOARD_LAYOUTS: [&Layout; KEYBOARD_LAYOUTS_SUPPORTED_NUM] =
+    [&QWERTY_LAYOUT, &GERMAN_QWERTZ_LAYOUT];
+
+/// # Find Layout
+/// The index of the layout called `name` in `KEYBOARD_LAYOUTS`
+pub fn find_layout(name: &str) -> Option<usize> {
+    KEYBOARD_LAYOUTS
+        .iter()
+        .position(|layout| layout.name == name)
+}
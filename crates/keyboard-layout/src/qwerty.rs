@@ -1,9 +1,59 @@
-use super::KeyboardLayout;
+use super::{Key, Layout, NONE};
 
-//https://github.com/Absurdponcho/PonchoOS/blob/Episode-13-KB-Scancodes/kernel/src/userinput/kbScancodeTranslation.cpp
-pub const QWERTY_LAYOUT: KeyboardLayout = [
-    0 as char, 0 as char, '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '-', '=', 0 as char,
-    0 as char, 'q', 'w', 'e', 'r', 't', 'y', 'u', 'i', 'o', 'p', '[', ']', 0 as char, 0 as char,
-    'a', 's', 'd', 'f', 'g', 'h', 'j', 'k', 'l', ';', '\'', '`', 0 as char, '\\', 'z', 'x', 'c',
-    'v', 'b', 'n', 'm', ',', '.', '/', 0 as char, '*', 0 as char, ' ',
-];
+pub const QWERTY_LAYOUT: Layout = Layout {
+    name: "us",
+    description: "US QWERTY",
+    keys: &[
+        Key::new(0x02, '1', '!', NONE),
+        Key::new(0x03, '2', '@', NONE),
+        Key::new(0x04, '3', '#', NONE),
+        Key::new(0x05, '4', '$', NONE),
+        Key::new(0x06, '5', '%', NONE),
+        Key::new(0x07, '6', '^', NONE),
+        Key::new(0x08, '7', '&', NONE),
+        Key::new(0x09, '8', '*', NONE),
+        Key::new(0x0A, '9', '(', NONE),
+        Key::new(0x0B, '0', ')', NONE),
+        Key::new(0x0C, '-', '_', NONE),
+        Key::new(0x0D, '=', '+', NONE),
+        Key::letter(0x10, 'q'),
+        Key::letter(0x11, 'w'),
+        Key::letter(0x12, 'e'),
+        Key::letter(0x13, 'r'),
+        Key::letter(0x14, 't'),
+        Key::letter(0x15, 'y'),
+        Key::letter(0x16, 'u'),
+        Key::letter(0x17, 'i'),
+        Key::letter(0x18, 'o'),
+        Key::letter(0x19, 'p'),
+        Key::new(0x1A, '[', '{', NONE),
+        Key::new(0x1B, ']', '}', NONE),
+        Key::letter(0x1E, 'a'),
+        Key::letter(0x1F, 's'),
+        Key::letter(0x20, 'd'),
+        Key::letter(0x21, 'f'),
+        Key::letter(0x22, 'g'),
+        Key::letter(0x23, 'h'),
+        Key::letter(0x24, 'j'),
+        Key::letter(0x25, 'k'),
+        Key::letter(0x26, 'l'),
+        Key::new(0x27, ';', ':', NONE),
+        Key::new(0x28, '\'', '"', NONE),
+        Key::new(0x29, '`', '~', NONE),
+        Key::new(0x2B, '\\', '|', NONE),
+        Key::letter(0x2C, 'z'),
+        Key::letter(0x2D, 'x'),
+        Key::letter(0x2E, 'c'),
+        Key::letter(0x2F, 'v'),
+        Key::letter(0x30, 'b'),
+        Key::letter(0x31, 'n'),
+        Key::letter(0x32, 'm'),
+        Key::new(0x33, ',', '<', NONE),
+        Key::new(0x34, '.', '>', NONE),
+        Key::new(0x35, '/', '?', NONE),
+        Key::new(0x37, '*', '*', NONE),
+        // The extra key of ISO keyboards
+        Key::new(0x56, '\\', '|', NONE),
+    ],
+    keypad_decimal: '.',
+};
@@ -1,18 +1,46 @@
-use bks::KEYBOARD_LAYOUTS_SUPPORTED_NUM;
+use crate::{Layout, NONE};
 
-use crate::{ASCII_CHAR_NUM, KEYBOARD_LAYOUTS};
+/// The keypad, from 7 at `KEYPAD_FIRST` to the decimal key
+const KEYPAD: [char; 13] = [
+    '7', '8', '9', '-', '4', '5', '6', '+', '1', '2', '3', '0', '.',
+];
+const KEYPAD_FIRST: u8 = 0x47;
 
-pub fn translate_from_u8(scancode: u8, uppercase: bool, layout: usize) -> char {
-    if scancode > ASCII_CHAR_NUM as u8 {
-        return 0 as char;
-    }
-    if layout > KEYBOARD_LAYOUTS_SUPPORTED_NUM {
-        return 0 as char;
+/// # Modifiers
+/// The state of the keys that change what the others type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub altgr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+/// # Translate
+/// What the key pressed with `scancode` types on `layout`. Shift is inverted by CapsLock for
+/// letters, AltGr takes precedence over both, and the digits of the keypad need NumLock.
+pub fn translate(layout: &Layout, scancode: u8, modifiers: Modifiers) -> Option<char> {
+    if let Some(key) = scancode
+        .checked_sub(KEYPAD_FIRST)
+        .and_then(|idx| KEYPAD.get(idx as usize))
+    {
+        return match *key {
+            key @ ('-' | '+') => Some(key),
+            '.' if modifiers.num_lock => Some(layout.keypad_decimal),
+            key if modifiers.num_lock => Some(key),
+            _ => None,
+        };
     }
-    if uppercase {
-        // - 32 returns uppercase of this variable
-        return (KEYBOARD_LAYOUTS[layout][scancode as usize] as u8 - 32) as char;
+    let key = layout.keys.iter().find(|key| key.scancode == scancode)?;
+    let typed = if modifiers.altgr {
+        key.altgr
     } else {
-        return KEYBOARD_LAYOUTS[layout][scancode as usize];
-    }
+        let is_letter = key.normal.is_alphabetic() && key.shifted.is_alphabetic();
+        if modifiers.shift ^ (modifiers.caps_lock && is_letter) {
+            key.shifted
+        } else {
+            key.normal
+        }
+    };
+    (typed != NONE).then(|| typed)
 }
@@ -13,6 +13,8 @@ pub struct TarEntry<'data> {
     pub data: &'data [u8],
     pub size: usize,
     pub ty: u8,
+    /// Seconds since 1970-01-01 00:00:00 UTC, 0 if the header has none
+    pub mtime: usize,
 }
 
 impl<'data> TarEntry<'data> {
@@ -22,6 +24,7 @@ impl<'data> TarEntry<'data> {
             data: data,
             size: data.len(),
             ty: ty,
+            mtime: 0,
         }
     }
 
@@ -31,6 +34,7 @@ impl<'data> TarEntry<'data> {
             data: &[],
             size: 0,
             ty: 0,
+            mtime: 0,
         }
     }
 
@@ -80,11 +84,12 @@ impl<'data> Iterator for TarIter<'data> {
         let end = begin + block_count * BLOCK_SIZE;
 
         let bytes = &self.data[begin..end][0..octal_ascii_size_as_usize(block_header.size)];
-        let entry = TarEntry::new(
+        let mut entry = TarEntry::new(
             as_string(block_header.name),
             bytes,
             TarEntryType::RegularFile,
         );
+        entry.mtime = usize::from_str_radix(as_string(block_header.mtime).trim(), 8).unwrap_or(0);
 
         self.idx += block_count + 1;
 
@@ -1,3 +1,59 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 pub fn main() {
-    println!("cargo:rerun-if-changed=../.targets/x86_64/kernel.lds")
+    println!("cargo:rerun-if-changed=../.targets/x86_64/kernel.lds");
+    write_build_info();
+}
+
+/// # Write Build Info
+/// Generates the constants `src/buildinfo.rs` includes: the commit, the time of the build, the
+/// enabled cargo features and the target. `SOURCE_DATE_EPOCH` overrides the time, for
+/// reproducible builds.
+fn write_build_info() {
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| String::from("unknown"));
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs())
+        });
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            let feature = key.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("buildinfo.rs");
+    let info = format!(
+        "pub const GIT_HASH: &str = {:?};\n\
+         pub const BUILD_TIMESTAMP: u64 = {};\n\
+         pub const FEATURES: &[&str] = &{:?};\n\
+         pub const TARGET: &str = {:?};\n\
+         pub const PROFILE: &str = {:?};\n",
+        git_hash,
+        timestamp,
+        features,
+        env::var("TARGET").unwrap_or_default(),
+        env::var("PROFILE").unwrap_or_default(),
+    );
+    fs::write(out, info).unwrap();
 }
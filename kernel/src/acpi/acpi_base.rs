@@ -1,37 +1,161 @@
-use super::SDTHeader;
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ops::Deref;
 
-pub trait ACPIFindable<'name>: Sized + ACPITable {
+use super::{tables, SDTHeader};
+use crate::error::{Error, Result};
+
+/// The size of the type and the length that start every record in the tail of a table
+pub const RECORD_HEADER_SIZE: usize = 2;
+
+/// # ACPI Findable
+/// A table that can be looked up in the registry by its signature, `NAME`
+pub trait ACPIFindable<'name>: Sized + ACPITable + 'static {
     const NAME: &'name str;
 
-    fn find<'lt>(sdt: &SDTHeader) -> Option<&'lt Self> {
-        sdt.find_table()
+    fn find() -> Option<Table<'static, Self>> {
+        tables::find(Self::NAME)?.map().ok()
     }
+}
 
-    fn find_mut<'lt>(sdt: &SDTHeader) -> Option<&'lt mut Self> {
-        sdt.find_table_mut()
+/// # ACPI Table
+/// A structure of a firmware table. Every implementor is `repr(packed)`, so it can be read from
+/// bytes at any address.
+pub trait ACPITable: Sized {
+    /// # From Bytes
+    /// The start of `bytes` as a `Self`
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = `bytes` is shorter than a `Self`, or not aligned for one
+    fn from_bytes(bytes: &[u8]) -> Result<&Self> {
+        if bytes.len() < size_of::<Self>() || bytes.as_ptr() as usize % align_of::<Self>() != 0 {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(unsafe { &*(bytes.as_ptr() as *const Self) })
     }
 }
 
-pub trait ACPITable: Sized {
-    fn new<'lt>(addr: u64) -> Option<&'lt Self> {
-        unsafe {
-            let ptr = addr as *mut u64 as *mut Self;
-            if ptr.is_null() {
-                None
-            } else {
-                Some(&*ptr)
-            }
+/// # Table
+/// A table starting with an `SDTHeader` as a `T`, together with all the bytes its header says it
+/// spans. These are at least `size_of::<T>()`, and whatever follows the `T` can only be read
+/// through the length checked `tail()`, `entries()` and `records()`.
+pub struct Table<'a, T> {
+    bytes: &'a [u8],
+    _table: PhantomData<&'a T>,
+}
+
+impl<'a, T: ACPITable> Table<'a, T> {
+    /// # New
+    /// The table at the start of `bytes`
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = The length in the header is shorter than a `T` or longer than
+    ///   `bytes`
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        let len = SDTHeader::from_bytes(bytes)?.length as usize;
+        if len < size_of::<T>() || len > bytes.len() {
+            return Err(Error::InvalidArgument);
         }
+        let bytes = &bytes[..len];
+        T::from_bytes(bytes)?;
+        Ok(Self {
+            bytes,
+            _table: PhantomData,
+        })
     }
-    fn new_mut<'lt>(addr: u64) -> Option<&'lt mut Self> {
-        unsafe {
-            let ptr = addr as *mut u64 as *mut Self;
-            if ptr.is_null() {
-                None
-            } else {
-                Some(&mut *ptr)
-            }
+
+    pub fn get(&self) -> &'a T {
+        unsafe { &*(self.bytes.as_ptr() as *const T) }
+    }
+
+    /// The length of the whole table, header included
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// # Tail
+    /// The bytes of the table after the `T`
+    pub fn tail(&self) -> &'a [u8] {
+        &self.bytes[size_of::<T>()..]
+    }
+
+    /// # Entries
+    /// The tail as an array of `E`, like the allocations of the MCFG. Bytes at the end that are
+    /// too few for another `E` are left out.
+    pub fn entries<E: ACPITable + 'a>(&self) -> impl Iterator<Item = &'a E> {
+        self.tail()
+            .chunks_exact(size_of::<E>())
+            .filter_map(|entry| E::from_bytes(entry).ok())
+    }
+
+    /// # Records
+    /// The tail as records that start with their type and length, like those of the MADT
+    pub fn records(&self) -> Records<'a> {
+        Records { bytes: self.tail() }
+    }
+}
+
+impl<'a, T: ACPITable> Deref for Table<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.get()
+    }
+}
+
+/// # Record
+/// A record in the tail of a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub kind: u8,
+    /// The whole record, its type and length included
+    pub bytes: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// # View
+    /// The record as an `R`
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = The record is shorter than an `R`
+    pub fn view<R: ACPITable>(&self) -> Result<&'a R> {
+        R::from_bytes(self.bytes)
+    }
+}
+
+/// # Records
+/// The records of a table, see `Table::records()`. A record whose length is too short to hold
+/// the length itself, or runs past the end of the table, ends the iteration with an error.
+pub struct Records<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let len = self.bytes.get(1).copied().unwrap_or(0) as usize;
+        if len < RECORD_HEADER_SIZE || len > self.bytes.len() {
+            self.bytes = &[];
+            return Some(Err(Error::InvalidArgument));
         }
+        let (record, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(Ok(Record {
+            kind: record[0],
+            bytes: record,
+        }))
     }
 }
 
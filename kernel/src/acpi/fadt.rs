@@ -0,0 +1,109 @@
+//! # FADT
+//! The Fixed ACPI Description Table, signature "FACP", which describes the fixed hardware
+//! registers of the power management and points at the DSDT. Only the fields up to ACPI 1.0
+//! are in `Fadt`, the ones ACPI 2.0 added after them are read from the tail with
+//! `FadtExtension` when the table is long enough.
+use super::{ACPIFindable, ACPITable, SDTHeader};
+use crate::{impl_acpi_findable, impl_acpi_table};
+
+enumtastic::const_enum! {
+    /// Where the register of a `GenericAddress` is
+    pub enum AddressSpace: u8 => {
+        Memory = 0,
+        Io = 1,
+        PciConfig = 2,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum FadtFlag: u32 => {
+        /// The reset register can be used
+        ResetRegSupported = 1 << 10,
+        HardwareReducedAcpi = 1 << 20,
+    }
+
+    impl {}
+}
+
+/// # Generic Address
+/// A register in one of the address spaces of `AddressSpace`
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl_acpi_table!(GenericAddress);
+
+#[repr(packed)]
+pub struct Fadt {
+    pub sdt_header: SDTHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    _reserved: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_interrupt: u16,
+    /// The I/O port `acpi_enable` is written to, to hand the power management over to the OS
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_request: u8,
+    pub pstate_control: u8,
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: u32,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm2_control_block: u32,
+    pub pm_timer_block: u32,
+    pub gpe0_block: u32,
+    pub gpe1_block: u32,
+    pub pm1_event_length: u8,
+    pub pm1_control_length: u8,
+    pub pm2_control_length: u8,
+    pub pm_timer_length: u8,
+    pub gpe0_length: u8,
+    pub gpe1_length: u8,
+    pub gpe1_base: u8,
+    pub cstate_control: u8,
+    pub worst_c2_latency: u16,
+    pub worst_c3_latency: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alarm: u8,
+    pub month_alarm: u8,
+    pub century: u8,
+    pub boot_architecture_flags: u16,
+    _reserved2: u8,
+    pub flags: u32,
+}
+
+impl_acpi_findable!(Fadt -> "FACP");
+
+/// # FADT Extension
+/// The fields of ACPI 2.0 that follow `Fadt`, up to the 64 bit address of the DSDT
+#[repr(packed)]
+pub struct FadtExtension {
+    pub reset_register: GenericAddress,
+    pub reset_value: u8,
+    pub arm_boot_architecture_flags: u16,
+    pub minor_version: u8,
+    pub x_firmware_ctrl: u64,
+    pub x_dsdt: u64,
+}
+
+impl_acpi_table!(FadtExtension);
+
+impl Fadt {
+    pub fn has_flag(&self, flag: u32) -> bool {
+        let flags = self.flags;
+        flags & flag != 0
+    }
+}
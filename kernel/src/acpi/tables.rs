@@ -0,0 +1,247 @@
+//! # Tables
+//! The registry of the ACPI tables the firmware lists in the XSDT, or in the RSDT on ACPI 1.0.
+//! It keeps where every table is in physical memory and maps it on demand: Once the kernel runs
+//! on its own page tables, nothing guarantees that the memory of the firmware is mapped, so
+//! tables are only ever reached through the direct map.
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use bks::PAGE_SIZE;
+use spin::Once;
+
+use super::{ACPITable, Rsdp2, SDTHeader, Table};
+use crate::error::{Error, Result};
+use crate::memory::paging::page_table_manager::{translate, PageTableFlag, PAGE_TABLE_MANAGER};
+use crate::memory::paging::tlb;
+use crate::memory::{phys_to_virt, PhysicalAddress, VirtualAddress};
+use crate::warn;
+
+pub const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// The first revision of the RSDP that points to an XSDT
+const RSDP_XSDT_REVISION: u8 = 2;
+
+static TABLES: Once<Vec<TableEntry>> = Once::new();
+static PLATFORM: Once<Platform> = Once::new();
+
+crate::counter!(pub MAPPED_PAGES = "acpi.mapped_pages");
+
+/// # Table Entry
+/// Where a table is, and what it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableEntry {
+    pub signature: [u8; 4],
+    pub phys: PhysicalAddress,
+    /// The length of the whole table, header included
+    pub len: u32,
+}
+
+impl TableEntry {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    /// # Map
+    /// Maps the table and returns it as a `T`
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = The table is shorter than a `T`, or its header no longer says
+    ///   it is as long as it was when it was registered
+    pub fn map<T: ACPITable + 'static>(&self) -> Result<Table<'static, T>> {
+        let table = Table::new(map_bytes(self.phys, self.len as u64)?)?;
+        if table.len() != self.len as usize {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(table)
+    }
+}
+
+/// # Platform
+/// What the RSDP says about the firmware, kept so it need not be mapped again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Platform {
+    /// The revision of the RSDP, 0 for ACPI 1.0
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    /// The root table, the XSDT if there is one and the RSDT otherwise
+    pub root: u64,
+    pub has_xsdt: bool,
+}
+
+impl Platform {
+    pub fn oem_id(&self) -> &str {
+        core::str::from_utf8(&self.oem_id).unwrap_or("??????")
+    }
+
+    /// # At
+    /// Reads the RSDP at `rsdp`
+    ///
+    /// ## Returns
+    /// - Error::NoSuchDevice = There is no RSDP at `rsdp`
+    pub fn at(rsdp: PhysicalAddress) -> Result<Self> {
+        if rsdp.is_null() {
+            return Err(Error::NoSuchDevice);
+        }
+        let rsdp = Rsdp2::from_bytes(map_bytes(rsdp, size_of::<Rsdp2>() as u64)?)?;
+        if rsdp.signature != *RSDP_SIGNATURE {
+            return Err(Error::NoSuchDevice);
+        }
+        let has_xsdt = rsdp.revision >= RSDP_XSDT_REVISION && { rsdp.xsdt_address } != 0;
+        Ok(Self {
+            revision: rsdp.revision,
+            oem_id: rsdp.oem_id,
+            root: if has_xsdt {
+                rsdp.xsdt_address
+            } else {
+                rsdp.rsdt_address as u64
+            },
+            has_xsdt,
+        })
+    }
+}
+
+/// # Checksum Ok
+/// Whether the bytes of a table sum up to zero, as the checksum in its header makes them
+pub fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// # Map Table
+/// Makes sure the `len` bytes at `phys` are mapped in the direct map. Pages that are not are
+/// mapped in place, read only, as firmware tables are not written.
+///
+/// ## Returns
+/// - VirtualAddress = The address `phys` can be accessed at
+pub fn map_table(phys: PhysicalAddress, len: u64) -> Result<VirtualAddress> {
+    let end = phys
+        .as_u64()
+        .checked_add(len)
+        .filter(|_| len > 0)
+        .ok_or(Error::InvalidArgument)?;
+    let start = phys.as_u64() & !(PAGE_SIZE - 1);
+    PhysicalAddress::try_new(end - 1).map_err(|_| Error::InvalidArgument)?;
+
+    let mut manager = PAGE_TABLE_MANAGER.lock();
+    let manager = unsafe { manager.assume_init_mut() };
+    for page in (start..end).step_by(PAGE_SIZE as usize) {
+        let virt = phys_to_virt(PhysicalAddress::new(page));
+        if translate(virt.as_u64()) == Some(page) {
+            continue;
+        }
+        manager.map_page(virt.as_u64(), page, PageTableFlag::PRESENT);
+        tlb::flush_page(virt);
+        MAPPED_PAGES.increment();
+    }
+    Ok(phys_to_virt(phys))
+}
+
+/// # Map Bytes
+/// Like `map_table()`, but returns the `len` bytes at `phys`
+pub fn map_bytes(phys: PhysicalAddress, len: u64) -> Result<&'static [u8]> {
+    let virt = map_table(phys, len)?;
+    Ok(unsafe { core::slice::from_raw_parts(virt.as_ptr::<u8>(), len as usize) })
+}
+
+/// # Parse
+/// Collects the tables the root table the RSDP at `rsdp` points to lists, the root table
+/// itself first. Entries that cannot be mapped are skipped.
+///
+/// ## Returns
+/// - Error::NoSuchDevice = There is no RSDP at `rsdp`
+/// - Error::InvalidArgument = The root table is not a valid table
+pub fn parse(rsdp: PhysicalAddress) -> Result<Vec<TableEntry>> {
+    let platform = Platform::at(rsdp)?;
+    let entry_size = if platform.has_xsdt {
+        size_of::<u64>()
+    } else {
+        size_of::<u32>()
+    };
+    let root = table_at(platform.root)?;
+    let entries = root.map::<SDTHeader>()?.tail().chunks_exact(entry_size);
+
+    let mut tables = Vec::with_capacity(entries.len() + 1);
+    tables.push(root);
+    for entry in entries {
+        // Little endian, and the entries of the XSDT are only 4 byte aligned
+        let phys = entry
+            .iter()
+            .rev()
+            .fold(0u64, |phys, byte| phys << 8 | *byte as u64);
+        match table_at(phys) {
+            Ok(table) => tables.push(table),
+            Err(e) => warn!("acpi: Skipping the table at {:#x}: {}", phys, e),
+        }
+    }
+    Ok(tables)
+}
+
+/// # Table At
+/// The table whose header is at `phys`, with all of it mapped
+pub fn table_at(phys: u64) -> Result<TableEntry> {
+    let phys = PhysicalAddress::try_new(phys).map_err(|_| Error::InvalidArgument)?;
+    if phys.is_null() {
+        return Err(Error::InvalidArgument);
+    }
+    let header = SDTHeader::from_bytes(map_bytes(phys, size_of::<SDTHeader>() as u64)?)?;
+    let len = header.length;
+    if (len as usize) < size_of::<SDTHeader>() {
+        return Err(Error::InvalidArgument);
+    }
+    map_table(phys, len as u64)?;
+    Ok(TableEntry {
+        signature: header.signature,
+        phys,
+        len,
+    })
+}
+
+/// # Register
+/// Parses the tables of the RSDP at `rsdp` into the registry, once
+///
+/// ## Returns
+/// - usize = The number of tables in the registry
+pub fn register(rsdp: PhysicalAddress) -> Result<usize> {
+    let platform = Platform::at(rsdp)?;
+    let tables = parse(rsdp)?;
+    PLATFORM.call_once(|| platform);
+    Ok(TABLES.call_once(|| tables).len())
+}
+
+/// # Platform
+/// The summary of the RSDP the registry was built from, `None` until `register()` ran
+pub fn platform() -> Option<&'static Platform> {
+    PLATFORM.get()
+}
+
+/// # Tables
+/// Every table in the registry, empty until `register()` ran
+pub fn tables() -> &'static [TableEntry] {
+    TABLES.get().map_or(&[], |tables| tables.as_slice())
+}
+
+/// # Find
+/// The first table with the signature `signature`
+pub fn find(signature: &str) -> Option<&'static TableEntry> {
+    tables()
+        .iter()
+        .find(|table| &table.signature[..] == signature.as_bytes())
+}
+
+/// # Nth
+/// The `index`th table with the signature `signature`, as some, like the SSDT, come in numbers
+pub fn nth(signature: &str, index: usize) -> Option<&'static TableEntry> {
+    tables()
+        .iter()
+        .filter(|table| &table.signature[..] == signature.as_bytes())
+        .nth(index)
+}
+
+/// # Raw Bytes
+/// The whole of the `index`th table with the signature `signature`, header included, e.g. to
+/// dump tables the kernel does not parse
+///
+/// ## Returns
+/// - Error::NoSuchDevice = There are not that many tables with the signature
+pub fn raw_bytes(signature: &str, index: usize) -> Result<&'static [u8]> {
+    let table = nth(signature, index).ok_or(Error::NoSuchDevice)?;
+    map_bytes(table.phys, table.len as u64)
+}
@@ -0,0 +1,189 @@
+//! # Local APIC
+//! The per-CPU interrupt controller. For now it is only used for inter-processor interrupts,
+//! the timer and the legacy devices still go through the PIC.
+use spin::Once;
+
+use crate::{
+    arch::interrupts::{interrupt_frame::InterruptFrame, IrqScope},
+    iobus::msr::{read_msr, write_msr, MsrRegister},
+    memory::register::{ReadOnly, VolatileCell, WriteOnly},
+    memory::{map_mmio, paging::pat::MemoryType, phys_to_virt, PhysicalAddress},
+    warn,
+};
+
+/// The vector used to make other CPUs run their queued calls (see `smp::call`)
+pub const CALL_FUNCTION_VECTOR: u8 = 0xF0;
+/// The vector used to wake up a CPU that is idle in `hlt` after a task was queued for it
+pub const RESCHEDULE_VECTOR: u8 = 0xF1;
+/// The vector of spurious interrupts, which must not be acknowledged
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// Bit 11 of the APIC base MSR globally enables the local APIC
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_MASK: u64 = 0xF_FFFF_F000;
+/// Bit 8 of the spurious interrupt vector register software-enables the local APIC
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+/// The size of the register window, of which `LapicRegisters` are the start
+const APIC_SIZE: u64 = 0x1000;
+/// Set in the low interrupt command register while the IPI has not been accepted yet
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+/// The delivery mode of the interrupt command register (bits 8-10) for NMIs
+const DELIVERY_MODE_NMI: u32 = 0b100 << 8;
+
+crate::register_block! {
+    /// # Local APIC Registers
+    /// Intel SDM Vol. 3, 10.4.1 - Local APIC Register Address Map. Every register is 16 byte
+    /// aligned, only its first 4 bytes are used.
+    pub struct LapicRegisters: 0x400 {
+        0x000 => _reserved0: [u8; 0x20],
+        0x020 => pub id: ReadOnly<u32>,
+        0x024 => _reserved1: [u8; 0xC],
+        0x030 => pub version: ReadOnly<u32>,
+        0x034 => _reserved2: [u8; 0x4C],
+        0x080 => pub task_priority: VolatileCell<u32>,
+        0x084 => _reserved3: [u8; 0x2C],
+        0x0B0 => pub end_of_interrupt: WriteOnly<u32>,
+        0x0B4 => _reserved4: [u8; 0x3C],
+        0x0F0 => pub spurious_interrupt_vector: VolatileCell<u32>,
+        0x0F4 => _reserved5: [u8; 0x18C],
+        0x280 => pub error_status: VolatileCell<u32>,
+        0x284 => _reserved6: [u8; 0x7C],
+        0x300 => pub interrupt_command_low: VolatileCell<u32>,
+        0x304 => _reserved7: [u8; 0xC],
+        0x310 => pub interrupt_command_high: VolatileCell<u32>,
+        0x314 => _reserved8: [u8; 0xEC],
+    }
+}
+
+enumtastic::const_enum! {
+    /// The destination shorthand of the interrupt command register (bits 18-19)
+    pub enum IpiDestination: u32 => {
+        Target = 0b00 << 18,
+        OnlySelf = 0b01 << 18,
+        AllIncludingSelf = 0b10 << 18,
+        AllExcludingSelf = 0b11 << 18,
+    }
+
+    impl {}
+}
+
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+
+/// # Local APIC
+/// The register window of the local APIC. Every CPU sees its own APIC at the same address,
+/// so a single instance is shared by all of them.
+pub struct LocalApic {
+    regs: &'static LapicRegisters,
+}
+
+impl LocalApic {
+    /// # New
+    /// Locates the local APIC through the APIC base MSR and maps its registers
+    fn new() -> Self {
+        let base = PhysicalAddress::new(read_msr(MsrRegister::Apic) & APIC_BASE_MASK);
+        let base = map_mmio(base, APIC_SIZE, MemoryType::Uncacheable).unwrap_or_else(|err| {
+            warn!("Failed to map the local APIC: {}", err.text());
+            phys_to_virt(base)
+        });
+        Self {
+            regs: unsafe { &*base.as_ptr::<LapicRegisters>() },
+        }
+    }
+
+    /// # Enable
+    /// Enables the local APIC of the calling CPU and lets it accept every interrupt
+    pub fn enable(&self) {
+        write_msr(
+            MsrRegister::Apic,
+            read_msr(MsrRegister::Apic) | APIC_BASE_ENABLE,
+        );
+        self.regs
+            .spurious_interrupt_vector
+            .write(SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+        self.regs.task_priority.write(0);
+    }
+
+    /// The APIC id of the calling CPU
+    pub fn id(&self) -> u32 {
+        self.regs.id.read() >> 24
+    }
+
+    /// # End Of Interrupt
+    /// Acknowledges the interrupt currently being handled by the calling CPU
+    pub fn eoi(&self) {
+        self.regs.end_of_interrupt.write(0);
+    }
+
+    /// # Send IPI
+    /// Sends a fixed interrupt with `vector` to the CPU with the APIC id `dest_apic_id`
+    pub fn send_ipi(&self, dest_apic_id: u32, vector: u8) {
+        self.regs.interrupt_command_high.write(dest_apic_id << 24);
+        self.command(IpiDestination::Target, vector);
+    }
+
+    /// # Send NMI
+    /// Sends a non-maskable interrupt to the CPU with the APIC id `dest_apic_id`
+    pub fn send_nmi(&self, dest_apic_id: u32) {
+        self.regs.interrupt_command_high.write(dest_apic_id << 24);
+        // NMIs ignore the vector, they always go through vector 2
+        self.command(IpiDestination::Target | DELIVERY_MODE_NMI, 0);
+    }
+
+    /// # Broadcast IPI
+    /// Sends a fixed interrupt with `vector` to every other CPU
+    pub fn broadcast_ipi(&self, vector: u8) {
+        self.command(IpiDestination::AllExcludingSelf, vector);
+    }
+
+    /// # Broadcast IPI Including Self
+    /// Sends a fixed interrupt with `vector` to every CPU, the calling one included
+    pub fn broadcast_ipi_including_self(&self, vector: u8) {
+        self.command(IpiDestination::AllIncludingSelf, vector);
+    }
+
+    fn command(&self, destination: u32, vector: u8) {
+        self.regs
+            .interrupt_command_low
+            .write(destination | LEVEL_ASSERT | vector as u32);
+        while self.regs.interrupt_command_low.read() & DELIVERY_PENDING != 0 {
+            comasm::pause();
+        }
+    }
+}
+
+/// # Init Local APIC
+/// Enables the local APIC of the calling CPU. Has to be called once on every CPU.
+pub fn init_local_apic() -> &'static LocalApic {
+    let apic = LOCAL_APIC.call_once(LocalApic::new);
+    apic.enable();
+    apic
+}
+
+/// The local APIC, if it has been initialized
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.get()
+}
+
+pub extern "x86-interrupt" fn call_function_interrupt_handler(_frame: InterruptFrame) {
+    let _irq = IrqScope::enter(CALL_FUNCTION_VECTOR as usize);
+    crate::smp::call::handle_calls();
+    if let Some(apic) = local_apic() {
+        apic.eoi();
+    }
+}
+
+/// The halted CPU just has to wake up, its idle loop looks at the run queue again
+pub extern "x86-interrupt" fn reschedule_interrupt_handler(frame: InterruptFrame) {
+    {
+        let _irq = IrqScope::enter(RESCHEDULE_VECTOR as usize);
+        if let Some(apic) = local_apic() {
+            apic.eoi();
+        }
+    }
+    crate::scheduler::preempt(frame.rflags);
+}
+
+pub extern "x86-interrupt" fn spurious_interrupt_handler(_frame: InterruptFrame) {
+    let _irq = IrqScope::enter(SPURIOUS_VECTOR as usize);
+}
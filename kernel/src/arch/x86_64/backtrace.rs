@@ -0,0 +1,67 @@
+//! # Backtrace
+//! Walking the chain of saved frame pointers. The kernel target keeps frame pointers, so every
+//! frame starts with the caller's `rbp` followed by the return address.
+//! `unwind` walks with the unwind tables instead and falls back to this. Both end the walk at the
+//! first return address outside the kernel's `.text`.
+use crate::memory::kernel_image;
+
+/// Stops runaway walks through corrupted stacks
+pub const MAX_FRAMES: usize = 32;
+/// The furthest two neighbouring frames are assumed to be apart
+pub const MAX_FRAME_SIZE: u64 = 0x10_0000;
+
+/// # Frame Pointer
+/// The frame pointer of the calling function
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let rbp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+    rbp
+}
+
+/// # Stack Pointer
+/// The stack pointer of the calling function
+#[inline(always)]
+pub fn stack_pointer() -> u64 {
+    let rsp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    }
+    rsp
+}
+
+/// # Instruction Pointer
+/// The address the calling function executes at
+#[inline(always)]
+pub fn instruction_pointer() -> u64 {
+    let rip: u64;
+    unsafe {
+        core::arch::asm!("lea {}, [rip]", out(reg) rip, options(nomem, nostack, preserves_flags));
+    }
+    rip
+}
+
+/// # Walk
+/// Calls `f` with the return address of every frame, starting with the frame `rbp` points to.
+/// The walk ends at the first frame that does not look like one, or that returns to somewhere
+/// that is not kernel code.
+pub fn walk(mut rbp: u64, mut f: impl FnMut(u64)) {
+    for _ in 0..MAX_FRAMES {
+        if rbp == 0 || rbp % 8 != 0 {
+            return;
+        }
+        let frame = rbp as *const u64;
+        let (caller_rbp, return_address) = unsafe { (frame.read(), frame.add(1).read()) };
+        if !kernel_image::is_text(return_address) {
+            return;
+        }
+        f(return_address);
+        // Callers live further up the stack
+        if caller_rbp <= rbp || caller_rbp - rbp > MAX_FRAME_SIZE {
+            return;
+        }
+        rbp = caller_rbp;
+    }
+}
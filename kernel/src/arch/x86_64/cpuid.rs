@@ -0,0 +1,31 @@
+//! # CPUID
+//! What the CPU reports about itself through `cpuid`, for the leaves more than one part of the
+//! kernel needs.
+use core::arch::x86_64::__cpuid;
+
+/// Feature information, CPUID.01H
+const FEATURES_LEAF: u32 = 0x1;
+/// CPUID.01H:EDX, the CPU has a local APIC
+const FEATURE_APIC: u32 = 1 << 9;
+/// The highest extended leaf, CPUID.80000000H:EAX
+const EXTENDED_MAX_LEAF: u32 = 0x8000_0000;
+/// Address sizes, CPUID.80000008H
+const ADDRESS_SIZES_LEAF: u32 = 0x8000_0008;
+/// Used if the CPU does not report its physical address width
+pub const DEFAULT_PHYSICAL_BITS: u32 = 36;
+
+/// # Physical Address Bits
+/// The number of physical address bits the CPU supports, CPUID.80000008H:EAX bits 0-7
+pub fn physical_address_bits() -> u32 {
+    let max = unsafe { __cpuid(EXTENDED_MAX_LEAF) }.eax;
+    if max < ADDRESS_SIZES_LEAF {
+        return DEFAULT_PHYSICAL_BITS;
+    }
+    unsafe { __cpuid(ADDRESS_SIZES_LEAF) }.eax & 0xFF
+}
+
+/// # Has APIC
+/// Whether the CPU has a local APIC, CPUID.01H:EDX bit 9
+pub fn has_apic() -> bool {
+    unsafe { __cpuid(FEATURES_LEAF) }.edx & FEATURE_APIC != 0
+}
@@ -0,0 +1,303 @@
+//! # FPU
+//! The x87, SSE and AVX state of tasks.
+//!
+//! The kernel itself is built with soft-float, so neither it nor its interrupt handlers touch
+//! the vector registers and interrupt entry does not have to save them. What is in them belongs
+//! to the running task and is saved into its `FpuState` when it is switched away from.
+//!
+//! With XSAVEOPT, which skips parts that did not change, the state is switched eagerly on every
+//! context switch. Otherwise it is restored lazily: CR0.TS is set when a task is switched in,
+//! its first FPU instruction raises #NM and only then is its state loaded. A task that did not
+//! use the FPU since it was switched in still has TS set and is not saved either.
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use core::ptr::{null_mut, NonNull};
+use core::sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+
+use crate::arch::interrupts::interrupt_frame::InterruptFrame;
+use crate::smp::{current_cpu, MAX_CPUS};
+use crate::{cmdline, info, warn};
+
+/// The command line flag that restores the state lazily even if XSAVEOPT is available
+pub const LAZY_OPTION: &str = "lazyfpu";
+
+/// CPUID.01H:EDX.FXSR
+const CPUID_FXSR: u32 = 1 << 24;
+/// CPUID.01H:ECX.XSAVE
+const CPUID_XSAVE: u32 = 1 << 26;
+/// CPUID.(EAX=0DH,ECX=1):EAX.XSAVEOPT
+const CPUID_XSAVEOPT: u32 = 1 << 0;
+const CR0_MP: u64 = 1 << 1;
+const CR0_EM: u64 = 1 << 2;
+const CR0_TS: u64 = 1 << 3;
+const CR0_NE: u64 = 1 << 5;
+const CR4_OSFXSR: u64 = 1 << 9;
+const CR4_OSXMMEXCPT: u64 = 1 << 10;
+const CR4_OSXSAVE: u64 = 1 << 18;
+/// x87, SSE and AVX, the state components that are enabled if the CPU has them
+const XCR0_X87: u64 = 1 << 0;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+
+/// The size of the legacy area FXSAVE writes, which XSAVE starts with
+const FXSAVE_SIZE: usize = 512;
+/// Every exception masked, round to nearest
+const DEFAULT_FCW: u16 = 0x037F;
+const DEFAULT_MXCSR: u32 = 0x1F80;
+const FCW_OFFSET: usize = 0;
+const MXCSR_OFFSET: usize = 24;
+/// The XSTATE_BV field of the XSAVE header, which follows the legacy area
+const XSTATE_BV_OFFSET: usize = FXSAVE_SIZE;
+const SAVE_AREA_ALIGN: usize = 64;
+
+crate::counter!(pub LAZY_RESTORES = "fpu.lazy_restores");
+
+/// # FPU Mode
+/// How the state is saved and restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FpuMode {
+    /// There is no FXSR, the state is not touched
+    None,
+    /// FXSAVE, restored lazily
+    Fxsave,
+    /// XSAVE, restored lazily
+    Xsave,
+    /// XSAVEOPT, switched eagerly
+    XsaveOpt,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(FpuMode::None as u8);
+static SAVE_AREA_SIZE: AtomicUsize = AtomicUsize::new(FXSAVE_SIZE);
+const NO_STATE: AtomicPtr<FpuState> = AtomicPtr::new(null_mut());
+/// The state of the task running on every CPU, which #NM loads
+static CURRENT: [AtomicPtr<FpuState>; MAX_CPUS] = [NO_STATE; MAX_CPUS];
+
+pub fn mode() -> FpuMode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => FpuMode::Fxsave,
+        2 => FpuMode::Xsave,
+        3 => FpuMode::XsaveOpt,
+        _ => FpuMode::None,
+    }
+}
+
+/// Whether the state is restored on the first use after a switch instead of on the switch
+pub fn is_lazy() -> bool {
+    matches!(mode(), FpuMode::Fxsave | FpuMode::Xsave)
+}
+
+/// # Init FPU
+/// Enables the FPU, SSE and, if the CPU has XSAVE, AVX. Has to be called on every CPU before
+/// the scheduler runs.
+pub fn init_fpu() {
+    let (leaf1, max_leaf) = unsafe { (__cpuid(1), __cpuid(0).eax) };
+    if leaf1.edx & CPUID_FXSR == 0 {
+        warn!("fpu: The CPU has no FXSAVE, the FPU state of tasks is not saved");
+        return;
+    }
+    let xsave = leaf1.ecx & CPUID_XSAVE != 0 && max_leaf >= 0xD;
+
+    unsafe {
+        write_cr0((read_cr0() | CR0_MP | CR0_NE) & !(CR0_EM | CR0_TS));
+        let mut cr4 = read_cr4() | CR4_OSFXSR | CR4_OSXMMEXCPT;
+        if xsave {
+            cr4 |= CR4_OSXSAVE;
+        }
+        write_cr4(cr4);
+        asm!("fninit", options(nomem, nostack));
+    }
+
+    let mode = if xsave {
+        let leaf = unsafe { __cpuid_count(0xD, 0) };
+        let supported = leaf.eax as u64 | (leaf.edx as u64) << 32;
+        let xcr0 = supported & (XCR0_X87 | XCR0_SSE | XCR0_AVX);
+        unsafe { write_xcr0(xcr0) };
+        // EBX is the size for the components enabled in XCR0, so it is read after setting it
+        let size = unsafe { __cpuid_count(0xD, 0) }.ebx as usize;
+        SAVE_AREA_SIZE.fetch_max(size, Ordering::Relaxed);
+        let xsaveopt = unsafe { __cpuid_count(0xD, 1) }.eax & CPUID_XSAVEOPT != 0;
+        info!(
+            "fpu: XSAVE with components {:#x}, {} bytes per task",
+            xcr0, size
+        );
+        if xsaveopt && !cmdline::flag(LAZY_OPTION) {
+            FpuMode::XsaveOpt
+        } else {
+            FpuMode::Xsave
+        }
+    } else {
+        info!("fpu: FXSAVE, {} bytes per task", FXSAVE_SIZE);
+        FpuMode::Fxsave
+    };
+    MODE.store(mode as u8, Ordering::Relaxed);
+    info!(
+        "fpu: Saving the state {}",
+        if is_lazy() { "lazily" } else { "eagerly" }
+    );
+}
+
+/// # FPU State
+/// A save area for the FPU registers of a task, aligned for XSAVE
+pub struct FpuState {
+    area: NonNull<u8>,
+    layout: Layout,
+}
+
+// The area is only accessed by the CPU the owning task runs on
+unsafe impl Send for FpuState {}
+unsafe impl Sync for FpuState {}
+
+impl FpuState {
+    /// # New
+    /// The initial state: Empty registers with every exception masked. Needs the heap.
+    pub fn new() -> Self {
+        let size = SAVE_AREA_SIZE.load(Ordering::Relaxed);
+        let layout = Layout::from_size_align(size, SAVE_AREA_ALIGN).unwrap();
+        let area = match NonNull::new(unsafe { alloc_zeroed(layout) }) {
+            Some(area) => area,
+            None => handle_alloc_error(layout),
+        };
+        unsafe {
+            let area = area.as_ptr();
+            (area.add(FCW_OFFSET) as *mut u16).write(DEFAULT_FCW);
+            (area.add(MXCSR_OFFSET) as *mut u32).write(DEFAULT_MXCSR);
+            if size > FXSAVE_SIZE {
+                // The registers are taken from the (zeroed) area, not reset to their init state
+                (area.add(XSTATE_BV_OFFSET) as *mut u64).write(XCR0_X87 | XCR0_SSE);
+            }
+        }
+        Self { area, layout }
+    }
+
+    /// # Save
+    /// Stores the registers of the calling CPU
+    ///
+    /// ## Safety
+    /// CR0.TS must be clear
+    unsafe fn save(&mut self) {
+        let area = self.area.as_ptr();
+        match mode() {
+            FpuMode::None => {}
+            FpuMode::Fxsave => asm!("fxsave64 [{}]", in(reg) area, options(nostack)),
+            FpuMode::Xsave => asm!(
+                "xsave64 [{}]",
+                in(reg) area,
+                in("eax") u32::MAX,
+                in("edx") u32::MAX,
+                options(nostack)
+            ),
+            FpuMode::XsaveOpt => asm!(
+                "xsaveopt64 [{}]",
+                in(reg) area,
+                in("eax") u32::MAX,
+                in("edx") u32::MAX,
+                options(nostack)
+            ),
+        }
+    }
+
+    /// # Restore
+    /// Loads the registers of the calling CPU
+    ///
+    /// ## Safety
+    /// CR0.TS must be clear
+    unsafe fn restore(&self) {
+        let area = self.area.as_ptr();
+        match mode() {
+            FpuMode::None => {}
+            FpuMode::Fxsave => asm!("fxrstor64 [{}]", in(reg) area, options(nostack)),
+            FpuMode::Xsave | FpuMode::XsaveOpt => asm!(
+                "xrstor64 [{}]",
+                in(reg) area,
+                in("eax") u32::MAX,
+                in("edx") u32::MAX,
+                options(nostack)
+            ),
+        }
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.area.as_ptr(), self.layout) };
+    }
+}
+
+/// # Switch
+/// Saves the registers of the task that is switched away from, if it used them, and restores
+/// or arranges to restore the ones of the task that is switched to
+///
+/// ## Safety
+/// Must be called with interrupts disabled, right before the context switch. Both states have to
+/// stay alive until they are switched away from again.
+pub unsafe fn switch(old: *mut FpuState, new: *mut FpuState) {
+    CURRENT[current_cpu()].store(new, Ordering::Relaxed);
+    match mode() {
+        FpuMode::None => {}
+        FpuMode::XsaveOpt => {
+            (*old).save();
+            (*new).restore();
+        }
+        FpuMode::Fxsave | FpuMode::Xsave => {
+            if read_cr0() & CR0_TS == 0 {
+                (*old).save();
+            }
+            write_cr0(read_cr0() | CR0_TS);
+        }
+    }
+}
+
+/// # Handle Device Not Available
+/// Loads the state of the current task on its first FPU instruction since it was switched in.
+/// The recovery handler of #NM.
+///
+/// ## Returns
+/// - bool = Whether the #NM was caused by lazy restoring and has been handled
+pub fn handle_device_not_available(_: &mut InterruptFrame, _: Option<u64>) -> bool {
+    let state = CURRENT[current_cpu()].load(Ordering::Relaxed);
+    if !is_lazy() || state.is_null() {
+        return false;
+    }
+    unsafe {
+        asm!("clts", options(nomem, nostack));
+        (*state).restore();
+    }
+    LAZY_RESTORES.increment();
+    true
+}
+
+fn read_cr0() -> u64 {
+    let cr0: u64;
+    unsafe {
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+    }
+    cr0
+}
+
+unsafe fn write_cr0(cr0: u64) {
+    asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+}
+
+fn read_cr4() -> u64 {
+    let cr4: u64;
+    unsafe {
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    }
+    cr4
+}
+
+unsafe fn write_cr4(cr4: u64) {
+    asm!("mov cr4, {}", in(reg) cr4, options(nostack, preserves_flags));
+}
+
+unsafe fn write_xcr0(xcr0: u64) {
+    asm!(
+        "xsetbv",
+        in("ecx") 0,
+        in("eax") xcr0 as u32,
+        in("edx") (xcr0 >> 32) as u32,
+        options(nomem, nostack, preserves_flags)
+    );
+}
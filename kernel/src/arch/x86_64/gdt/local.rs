@@ -0,0 +1,276 @@
+//! # CPU Local GDT
+//! Every CPU has a GDT of its own, as the TSS descriptor in it points at the TSS of that CPU and
+//! is marked busy once it is loaded. The TSS holds the stack the CPU switches to when an
+//! interrupt arrives in user space, which the scheduler updates for every task it switches to,
+//! and the interrupt stacks that double faults, NMIs and machine checks always run on. The I/O
+//! permission bitmap after it holds the ports the running task may access from user space.
+//!
+//! The bootstrap processor loads its tables before there is a heap, so they are statics. Every
+//! other CPU allocates them when it is brought up.
+use alloc::boxed::Box;
+use alloc::vec;
+use core::cell::UnsafeCell;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{store_gdt, upload_gdt, GDTDescriptor, GdtEntryType, GlobalDescriptorTable, Ring};
+use crate::arch::segment::*;
+use crate::arch::tss::{IoBitmap, Tss64, TSS64_LIMIT};
+use crate::smp::{cpu, current_cpu};
+
+/// The size of every interrupt stack
+pub const INTERRUPT_STACK_SIZE: usize = 0x4000;
+/// The number of interrupt stacks of every CPU, see `InterruptStack`
+pub const INTERRUPT_STACKS: usize = 3;
+/// The alignment of the top of every stack in the TSS
+const STACK_ALIGN: u64 = 16;
+
+enumtastic::const_enum! {
+    /// The interrupt stacks of a TSS, numbered the way the IDT refers to them
+    pub enum InterruptStack: u8 => {
+        DoubleFault = 1,
+        NonMaskable = 2,
+        MachineCheck = 3,
+    }
+
+    impl {}
+}
+
+#[repr(C, align(16))]
+struct BootStack([u8; INTERRUPT_STACK_SIZE]);
+
+static mut BOOT_GDT: CpuLocalGdt = CpuLocalGdt::new(0);
+// The linker script keeps them apart from `.bss`, see `kernel_image`
+#[link_section = ".stack"]
+static mut BOOT_STACKS: [BootStack; INTERRUPT_STACKS] = {
+    const STACK: BootStack = BootStack([0; INTERRUPT_STACK_SIZE]);
+    [STACK; INTERRUPT_STACKS]
+};
+
+/// # CPU Local GDT
+/// The GDT and TSS of a single CPU, which only that CPU loads and updates
+#[repr(C, align(16))]
+pub struct CpuLocalGdt {
+    gdt: GlobalDescriptorTable,
+    tss: UnsafeCell<Tss64>,
+    /// Whether the I/O permission bitmap allows any port
+    io_bitmap_loaded: AtomicBool,
+    cpu: usize,
+}
+
+// The TSS is only written by the CPU it belongs to
+unsafe impl Sync for CpuLocalGdt {}
+
+impl CpuLocalGdt {
+    const fn new(cpu: usize) -> Self {
+        Self {
+            gdt: GlobalDescriptorTable::new(),
+            tss: UnsafeCell::new(Tss64::new([0; 3], [0; 7])),
+            io_bitmap_loaded: AtomicBool::new(false),
+            cpu,
+        }
+    }
+
+    /// # Init
+    /// Sets up the GDT and TSS of `cpu` with fresh interrupt stacks, loads them and stores them in
+    /// the per-CPU area. Called on `cpu` itself, once during its bring-up. The bootstrap processor
+    /// calls it before the heap is initialized.
+    ///
+    /// ## Panics
+    /// If the calling CPU is not `cpu`, or `cpu` already has its tables
+    pub fn init(cpu: usize) -> &'static Self {
+        assert!(
+            self::cpu(cpu).gdt().is_none(),
+            "CPU {} initializes its GDT twice",
+            cpu
+        );
+        let (gdt, stacks): (&'static mut Self, [u64; INTERRUPT_STACKS]) = if cpu == 0 {
+            let mut stacks = [0; INTERRUPT_STACKS];
+            for (top, stack) in stacks.iter_mut().zip(unsafe { BOOT_STACKS.iter() }) {
+                *top = stack_top(stack.0.as_ptr() as u64, INTERRUPT_STACK_SIZE);
+            }
+            (unsafe { &mut BOOT_GDT }, stacks)
+        } else {
+            let stacks = [(); INTERRUPT_STACKS].map(|_| {
+                let stack = vec![0u8; INTERRUPT_STACK_SIZE].leak();
+                stack_top(stack.as_ptr() as u64, INTERRUPT_STACK_SIZE)
+            });
+            (Box::leak(Box::new(Self::new(cpu))), stacks)
+        };
+
+        let tss = gdt.tss.get_mut();
+        for (idx, top) in stacks.into_iter().enumerate() {
+            // The IDT numbers the stacks from 1, the TSS from 0
+            tss.0.set_ist(idx, top);
+        }
+        let base = gdt.tss.get() as u64;
+        gdt.gdt.set_tss(base, TSS64_LIMIT);
+
+        let gdt: &'static Self = gdt;
+        unsafe { gdt.load() };
+        self::cpu(cpu).set_gdt(gdt);
+        gdt
+    }
+
+    /// # Load
+    /// Loads the GDT, reloads every segment register from it and loads the task register
+    ///
+    /// ## Safety
+    /// The tables have to stay around for as long as the CPU runs
+    ///
+    /// ## Panics
+    /// If the calling CPU is not the one the tables belong to
+    unsafe fn load(&self) {
+        assert_eq!(
+            self.cpu,
+            current_cpu(),
+            "CPU {} loads the GDT of CPU {}",
+            current_cpu(),
+            self.cpu
+        );
+        let limit = (size_of::<GlobalDescriptorTable>() - 1)
+            .try_into()
+            .expect("GDT is way too large");
+        let mut desc = GDTDescriptor::new(limit, self.gdt.as_ptr());
+        upload_gdt(&mut desc);
+        upload_to_cs(Segment::new(Ring::Ring0, GdtEntryType::KernelCode)); // 0x08
+        upload_to_ds(Segment::new(Ring::Ring0, GdtEntryType::KernelData)); // 0x10
+        upload_to_es(Segment::new(Ring::Ring0, GdtEntryType::KernelData)); // 0x10
+        upload_to_fs(Segment::new(Ring::Ring0, GdtEntryType::KernelData)); // 0x10
+        upload_to_gs(Segment::new(Ring::Ring0, GdtEntryType::KernelData)); // 0x10
+        upload_to_ss(Segment::new(Ring::Ring0, GdtEntryType::KernelData)); // 0x10
+        load_task_register(Segment::new(Ring::Ring0, GdtEntryType::Tss)); // 0x38
+    }
+
+    pub fn cpu(&self) -> usize {
+        self.cpu
+    }
+
+    pub fn gdt(&self) -> &GlobalDescriptorTable {
+        &self.gdt
+    }
+
+    /// The address of the TSS
+    pub fn tss_address(&self) -> u64 {
+        self.tss.get() as u64
+    }
+
+    /// # Is Loaded
+    /// Whether the GDT register of the calling CPU points at this GDT
+    pub fn is_loaded(&self) -> bool {
+        store_gdt().offset() == self.gdt.as_ptr()
+    }
+
+    /// The stack the CPU switches to for an interrupt from user space
+    pub fn kernel_stack(&self) -> u64 {
+        let tss = self.tss.get();
+        unsafe { core::ptr::addr_of!((*tss).0.rsp).read_unaligned()[0] }
+    }
+
+    /// The top of the interrupt stack `stack`
+    pub fn interrupt_stack(&self, stack: u8) -> Option<u64> {
+        let idx = (stack as usize).checked_sub(1)?;
+        let tss = self.tss.get();
+        let ist = unsafe { core::ptr::addr_of!((*tss).0.ist).read_unaligned() };
+        ist.get(idx).copied()
+    }
+
+    /// # Set Kernel Stack
+    /// Makes interrupts from user space switch to the stack with the top `top`
+    ///
+    /// ## Panics
+    /// If the calling CPU runs on another GDT, which means it would update a TSS that is used by
+    /// someone else
+    pub fn set_kernel_stack(&self, top: u64) {
+        debug_assert!(
+            self.is_loaded(),
+            "CPU {} updates the TSS of CPU {}",
+            current_cpu(),
+            self.cpu
+        );
+        let tss = self.tss.get();
+        unsafe {
+            let rsp = core::ptr::addr_of_mut!((*tss).0.rsp) as *mut u64;
+            rsp.write_unaligned(top & !(STACK_ALIGN - 1));
+        }
+    }
+
+    /// # Load I/O Bitmap
+    /// Lets user space access the ports `bitmap` allows, or none for `None`. The bitmap is
+    /// copied, which only tasks that called `ioperm()` pay for.
+    ///
+    /// ## Panics
+    /// If the calling CPU runs on another GDT
+    pub fn load_io_bitmap(&self, bitmap: Option<&IoBitmap>) {
+        debug_assert!(
+            self.is_loaded(),
+            "CPU {} updates the TSS of CPU {}",
+            current_cpu(),
+            self.cpu
+        );
+        let tss = self.tss.get();
+        let map = unsafe { &mut (*tss).1 };
+        match bitmap {
+            Some(bitmap) => {
+                map.copy_from_slice(bitmap.bits());
+                self.io_bitmap_loaded.store(true, Ordering::Relaxed);
+            }
+            None => {
+                if self.io_bitmap_loaded.swap(false, Ordering::Relaxed) {
+                    map.fill(0xFF);
+                }
+            }
+        }
+    }
+
+    /// Whether user space on the CPU may access `port`
+    pub fn io_port_allowed(&self, port: u16) -> bool {
+        let tss = self.tss.get();
+        unsafe { (*tss).1[port as usize / 8] & 1 << (port % 8) == 0 }
+    }
+
+    /// Where the I/O permission bitmap starts, relative to the TSS
+    pub fn io_bitmap_offset(&self) -> u16 {
+        let tss = self.tss.get();
+        unsafe { core::ptr::addr_of!((*tss).0.io_base).read_unaligned() }
+    }
+}
+
+fn stack_top(bottom: u64, size: usize) -> u64 {
+    (bottom + size as u64) & !(STACK_ALIGN - 1)
+}
+
+/// # Set Kernel Stack
+/// Makes interrupts from user space on `cpu`, the calling CPU, switch to the stack with the top
+/// `top`. Called by the scheduler when a task is switched in. Does nothing before the CPU loaded
+/// its tables.
+pub fn set_kernel_stack(cpu: usize, top: u64) {
+    if let Some(gdt) = self::cpu(cpu).gdt() {
+        gdt.set_kernel_stack(top);
+    }
+}
+
+/// # Load I/O Bitmap
+/// Lets user space on `cpu`, the calling CPU, access the ports `bitmap` allows, or none for
+/// `None`. Called by the scheduler when a task is switched in. Does nothing before the CPU
+/// loaded its tables.
+pub fn load_io_bitmap(cpu: usize, bitmap: Option<&IoBitmap>) {
+    if let Some(gdt) = self::cpu(cpu).gdt() {
+        gdt.load_io_bitmap(bitmap);
+    }
+}
+
+/// # Current
+/// The tables of the calling CPU
+///
+/// ## Panics
+/// If the calling CPU runs on the tables of another CPU
+pub fn current() -> Option<&'static CpuLocalGdt> {
+    let gdt = cpu(current_cpu()).gdt()?;
+    assert!(
+        gdt.is_loaded(),
+        "CPU {} runs on a GDT that is not its own",
+        current_cpu()
+    );
+    Some(gdt)
+}
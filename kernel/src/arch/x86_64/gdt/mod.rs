@@ -1,5 +1,9 @@
 use core::arch::asm;
 
+pub mod local;
+
+pub use local::CpuLocalGdt;
+
 pub unsafe fn upload_gdt(gdt: *mut GDTDescriptor) {
     asm!("lgdt [{}]", in(reg) gdt);
 }
@@ -19,7 +23,7 @@ enumtastic::const_enum! {
         UserData = 5,
         UserCode = 6,
         Tss = 7,
-        TssHigh = 9,
+        TssHigh = 8,
     }
 
     impl {}
@@ -65,6 +69,18 @@ impl GDTDescriptor {
     pub fn new(size: u16, offset: *const u64) -> Self {
         Self { size, offset }
     }
+
+    pub fn offset(&self) -> *const u64 {
+        self.offset
+    }
+}
+
+/// # Store GDT
+/// The GDT register of the calling CPU
+pub fn store_gdt() -> GDTDescriptor {
+    let mut desc = GDTDescriptor::new(0, core::ptr::null());
+    unsafe { asm!("sgdt [{}]", in(reg) &mut desc as *mut GDTDescriptor, options(nostack)) };
+    desc
 }
 
 #[repr(packed)]
@@ -137,11 +153,13 @@ pub struct GlobalDescriptorTable {
     null: GDTEntry,        // + 0x0
     kernel_code: GDTEntry, // + 0x08
     kernel_data: GDTEntry, // + 0x10
-    user_dummy: GDTEntry,
-    user_data: GDTEntry,
-    user_code: GDTEntry,
-    tsslo: GDTEntry, // Must be 16 bits
-    tsshi: GDTEntry, // Must be 16 bits
+    kernel_tls: GDTEntry,  // + 0x18
+    // `sysret` loads the user selectors relative to this one
+    user_code32: GDTEntry, // + 0x20
+    user_data: GDTEntry,   // + 0x28
+    user_code: GDTEntry,   // + 0x30
+    tsslo: GDTEntry,       // + 0x38
+    tsshi: GDTEntry,       // + 0x40, the upper half of the base of the TSS
 }
 
 impl GlobalDescriptorTable {
@@ -163,16 +181,22 @@ impl GlobalDescriptorTable {
             GdtAccess::Present | GdtAccess::Ring0 | GdtAccess::System | GdtAccess::Privilege,
             GdtFlag::LongMode,
         );
+        let kernel_tls = GDTEntry::new32(
+            0,
+            0xFFFFF,
+            GdtAccess::Present | GdtAccess::Ring0 | GdtAccess::System | GdtAccess::Privilege,
+            GdtFlag::LongMode,
+        );
 
-        let user_dummy = GDTEntry::new32(
+        let user_code32 = GDTEntry::new32(
             0,
             0,
             GdtAccess::Present
-                | GdtAccess::Ring0
+                | GdtAccess::Ring3
                 | GdtAccess::System
                 | GdtAccess::Privilege
                 | GdtAccess::Executable,
-            GdtFlag::LongMode,
+            GdtFlag::ProtectedMode,
         );
         let user_data = GDTEntry::new32(
             0,
@@ -191,21 +215,16 @@ impl GlobalDescriptorTable {
             GdtFlag::LongMode,
         );
 
-        // TSSlo
-        let tsslo = GDTEntry::new32(
-            0,
-            0,
-            GdtAccess::Present | GdtAccess::Ring3 | GdtAccess::TssAvailable,
-            0,
-        );
+        // Not present until `set_tss()` points it at a TSS
+        let tsslo = GDTEntry::new32(0, 0, GdtAccess::Ring0 | GdtAccess::TssAvailable, 0);
         let tsshi = GDTEntry::new32(0, 0, 0, 0);
-        let reserved = GDTEntry::new32(0, 0, 0, 0);
 
         Self {
             null,
             kernel_code,
             kernel_data,
-            user_dummy,
+            kernel_tls,
+            user_code32,
             user_data,
             user_code,
             tsslo,
@@ -217,10 +236,42 @@ impl GlobalDescriptorTable {
         self as *const Self as *const u64
     }
 
-    pub fn get_entry(&mut self, idx: usize) -> &mut GDTEntry {
-        unsafe {
-            &mut *((self as *mut Self as *mut GDTEntry).add(idx * core::mem::size_of::<GDTEntry>()))
-        }
+    /// # Set TSS
+    /// Points the TSS descriptor at the TSS at `base`, which spans `limit + 1` bytes
+    pub fn set_tss(&mut self, base: u64, limit: u32) {
+        self.tsslo = GDTEntry::new32(
+            base as u32,
+            limit,
+            GdtAccess::Present | GdtAccess::Ring0 | GdtAccess::TssAvailable,
+            0,
+        );
+        // The second half holds bits 32 to 63 of the base where the first one holds its limit
+        self.tsshi = GDTEntry::new((base >> 32) as u16, (base >> 48) as u16, 0, 0, 0, 0);
+    }
+
+    /// # TSS Base
+    /// The address of the TSS the TSS descriptor points at
+    pub fn tss_base(&self) -> u64 {
+        let (lo, hi) = (&self.tsslo, &self.tsshi);
+        lo.base_0 as u64
+            | (lo.base_1 as u64) << 16
+            | (lo.base_2 as u64) << 24
+            | (hi.limit_0 as u64) << 32
+            | (hi.base_0 as u64) << 48
+    }
+
+    /// # TSS Limit
+    /// The offset of the last byte of the TSS the TSS descriptor points at
+    pub fn tss_limit(&self) -> u32 {
+        let lo = &self.tsslo;
+        lo.limit_0 as u32 | ((lo.limit_1_flags & 0x0F) as u32) << 16
+    }
+
+    /// Whether the TSS descriptor is present and describes a 64-bit TSS, busy once it is loaded
+    pub fn has_tss(&self) -> bool {
+        let access = self.tsslo.access;
+        access & GdtAccess::Present != 0
+            && access & !GdtAccess::Privilege & 0xF == GdtAccess::TssAvailable
     }
 }
 
@@ -265,6 +316,3 @@ impl InitialGlobalDescriptorTable {
 }
 
 pub static INITIAL_KERNEL_GDT: InitialGlobalDescriptorTable = InitialGlobalDescriptorTable::new();
-
-#[thread_local]
-pub static GLOBAL_GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
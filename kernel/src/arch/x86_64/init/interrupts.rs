@@ -1,11 +1,17 @@
 use bks::Handover;
 
+use crate::arch::fpu;
+use crate::arch::gdt::local::InterruptStack;
 use crate::arch::interrupts::exceptions::IDTException::*;
-use crate::arch::interrupts::exceptions::{Exception, ExceptionHandler};
-use crate::arch::interrupts::set_interrupt_handler;
+use crate::arch::interrupts::exceptions::{
+    register_recovery_handler, Exception, ExceptionHandler, ExceptionWithErrorCode,
+};
+use crate::arch::interrupts::{
+    set_interrupt_handler, set_interrupt_handler_with_error_code, set_interrupt_stack,
+};
 use crate::arch::pic::PicInterrupt;
 use crate::arch::scheduler::pit::{pit_interrupt_handler, PIT_INTERRUPT};
-use crate::drivers::input::ps2_keyboard::ps2_keyboard_int_handler;
+use crate::breadcrumb::{self, Milestone};
 use crate::drivers::input::ps2_mouse::ps2_mouse_interrupt_handler;
 use crate::memory::paging::page_frame_allocator::request_page;
 use crate::{arch::interrupts::exceptions::IDTException, kprintln};
@@ -25,114 +31,149 @@ pub fn init_interrupts(_: &mut Handover) {
 
     crate::arch::scheduler::pit::set_divisor(65535 / 3);
 
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::PageFault as u64,
+        IDTException::error_code(&PageFault),
         ExceptionHandler::<PageFault>::handle,
     );
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::DoubleFault as u64,
+        IDTException::error_code(&DoubleFault),
         ExceptionHandler::<DoubleFault>::handle,
     );
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::GeneralProtectionFault as u64,
+        IDTException::error_code(&GeneralProtectionFault),
         ExceptionHandler::<GeneralProtectionFault>::handle,
     );
 
     set_interrupt_handler(
         IDTException::DivideByZero as u64,
+        IDTException::error_code(&DivideByZero),
         ExceptionHandler::<DivideByZero>::handle,
     );
     set_interrupt_handler(
         IDTException::Debug as u64,
+        IDTException::error_code(&Debug),
         ExceptionHandler::<Debug>::handle,
     );
     set_interrupt_handler(
         IDTException::NonMaskable as u64,
+        IDTException::error_code(&NonMaskable),
         ExceptionHandler::<NonMaskable>::handle,
     );
     set_interrupt_handler(
         IDTException::Breakpoint as u64,
+        IDTException::error_code(&Breakpoint),
         ExceptionHandler::<Breakpoint>::handle,
     );
     set_interrupt_handler(
         IDTException::Overflow as u64,
+        IDTException::error_code(&Overflow),
         ExceptionHandler::<Overflow>::handle,
     );
     set_interrupt_handler(
         IDTException::BoundRangeExceeded as u64,
+        IDTException::error_code(&BoundRangeExceeded),
         ExceptionHandler::<BoundRangeExceeded>::handle,
     );
     set_interrupt_handler(
         IDTException::InvalidOpcode as u64,
+        IDTException::error_code(&InvalidOpcode),
         ExceptionHandler::<InvalidOpcode>::handle,
     );
     set_interrupt_handler(
         IDTException::DeviceNotAvailable as u64,
+        IDTException::error_code(&DeviceNotAvailable),
         ExceptionHandler::<DeviceNotAvailable>::handle,
     );
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::InvalidTSS as u64,
+        IDTException::error_code(&InvalidTSS),
         ExceptionHandler::<InvalidTSS>::handle,
     );
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::SegmentNotPresent as u64,
+        IDTException::error_code(&SegmentNotPresent),
         ExceptionHandler::<SegmentNotPresent>::handle,
     );
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::StackSegmentFault as u64,
+        IDTException::error_code(&StackSegmentFault),
         ExceptionHandler::<StackSegmentFault>::handle,
     );
     set_interrupt_handler(
         IDTException::X87FloatingPointException as u64,
+        IDTException::error_code(&X87FloatingPointException),
         ExceptionHandler::<X87FloatingPointException>::handle,
     );
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::AlignmentCheck as u64,
+        IDTException::error_code(&AlignmentCheck),
         ExceptionHandler::<AlignmentCheck>::handle,
     );
     set_interrupt_handler(
         IDTException::MachineCheck as u64,
+        IDTException::error_code(&MachineCheck),
         ExceptionHandler::<MachineCheck>::handle,
     );
     set_interrupt_handler(
         IDTException::SIMDFloatingPointException as u64,
+        IDTException::error_code(&SIMDFloatingPointException),
         ExceptionHandler::<SIMDFloatingPointException>::handle,
     );
     set_interrupt_handler(
         IDTException::VirtualizationException as u64,
+        IDTException::error_code(&VirtualizationException),
         ExceptionHandler::<VirtualizationException>::handle,
     );
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::ControlProtection as u64,
+        IDTException::error_code(&ControlProtection),
         ExceptionHandler::<ControlProtection>::handle,
     );
     set_interrupt_handler(
         IDTException::HypervisorInjection as u64,
+        IDTException::error_code(&HypervisorInjection),
         ExceptionHandler::<HypervisorInjection>::handle,
     );
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::VMMCommunicationException as u64,
+        IDTException::error_code(&VMMCommunicationException),
         ExceptionHandler::<VMMCommunicationException>::handle,
     );
-    set_interrupt_handler(
+    set_interrupt_handler_with_error_code(
         IDTException::SecurityException as u64,
+        IDTException::error_code(&SecurityException),
         ExceptionHandler::<SecurityException>::handle,
     );
 
-    // Add PS2 Interrupt Handler
-    set_interrupt_handler(
-        PicInterrupt::Ps2KeyboardInterrupt as u64,
-        ps2_keyboard_int_handler,
+    // A double fault may come from an overflowed stack, an NMI or machine check from anywhere
+    set_interrupt_stack(
+        IDTException::DoubleFault as u64,
+        InterruptStack::DoubleFault,
+    );
+    set_interrupt_stack(
+        IDTException::NonMaskable as u64,
+        InterruptStack::NonMaskable,
+    );
+    set_interrupt_stack(
+        IDTException::MachineCheck as u64,
+        InterruptStack::MachineCheck,
     );
 
+    // Faults the kernel can recover from
+    register_recovery_handler(DeviceNotAvailable, fpu::handle_device_not_available);
+
     // Add the Mouse Interrupt Handler
     set_interrupt_handler(
         PicInterrupt::Ps2MouseInterrupt as u64,
+        "ps2-mouse",
         ps2_mouse_interrupt_handler,
     );
 
     // Set PIT Interrupt Handler
-    set_interrupt_handler(PIT_INTERRUPT as u64, pit_interrupt_handler);
+    set_interrupt_handler(PIT_INTERRUPT as u64, "pit", pit_interrupt_handler);
 
     // Loading the IDT
     info!("Loading IDTR");
@@ -140,6 +181,7 @@ pub fn init_interrupts(_: &mut Handover) {
         // Load the IDT
         upload_idt(IDT_REGISTER.lock().assume_init_mut());
     }
+    breadcrumb::reach(Milestone::IdtLoaded);
 
     success!("Finished preparing interrupts");
 }
@@ -1,7 +1,20 @@
 use bks::{Handover, PAGE_SIZE};
 
+use crate::arch::cpuid;
+use crate::arch::interrupts::without_interrupts;
+use crate::breadcrumb::{self, Milestone};
+use crate::error::Result;
+use crate::framebuffer::{FramebufferInfo, FRAMEBUFFER_GUARD};
 use crate::heap::Heap;
-use crate::memory::paging::page_table_manager::{PageTable, PageTableManager, PAGE_TABLE_MANAGER};
+use crate::init::KernelInitError;
+use crate::math::ByteSize;
+use crate::memory::map::memory_map;
+use crate::memory::paging::page_table_manager::{
+    active_pml4, PageTable, PageTableFlag, PageTableManager, PAGE_TABLE_MANAGER,
+};
+use crate::memory::paging::pat::MemoryType;
+use crate::memory::paging::{mtrr, pat};
+use crate::memory::{kernel_image, phys_to_virt, reserved, PhysicalAddress, VirtualAddress};
 use crate::{arch::HEAP_ADDRESS, arch::HEAP_LENGTH, debug, info, kprint, success};
 use crate::{
     kprintln,
@@ -9,55 +22,51 @@ use crate::{
 };
 use core::arch::asm;
 
-// Defined in Linker Script
-#[no_mangle]
-pub static _KERNEL_START: u64 = 0;
-
-// Defined in Linker Script
-#[no_mangle]
-pub static _KERNEL_END: u64 = 0;
-
-// Defined in Linker Script
-#[no_mangle]
-pub static _KERNEL_OFFSET: u64 = 0;
-
 /// Initializes the memory (Paging, Heap, etc)
-pub fn init_initial_paging(handover: &mut Handover) {
+///
+/// ## Returns
+/// - KernelInitError::MemoryMapEmpty = The memory map has no usable memory
+pub fn init_initial_paging(handover: &mut Handover) -> Result<(), KernelInitError> {
     info!("Preparing Memory");
+    PhysicalAddress::set_max_width(cpuid::physical_address_bits());
+    debug!(
+        "Physical addresses have {} bits",
+        PhysicalAddress::max_width()
+    );
     unsafe {
         // Set the Global PageFrameAllocator
-        PAGE_FRAME_ALLOCATOR.lock().write(PageFrameAllocator::new(
-            handover.raw_memory_map() as *mut u8,
-            handover.mmap_entries,
-            handover.mmap_size,
-            handover.mmap_entry_size,
-        ));
+        PAGE_FRAME_ALLOCATOR.lock().write(PageFrameAllocator::new());
+        let map = memory_map(handover);
+        if map.summary().usable == 0 {
+            return Err(KernelInitError::MemoryMapEmpty);
+        }
+        let image = kernel_image::sections().image;
+        reserved::reserve("kernel image", image.physical(), image.len());
+        for module in handover.modules() {
+            reserved::reserve(
+                "boot module",
+                PhysicalAddress::new(module.phys_start),
+                module.len,
+            );
+        }
+        crate::crashlog::reserve(map.clone());
         // "Initialize" the PageFrameAllocator
         PAGE_FRAME_ALLOCATOR
             .lock()
             .assume_init_mut()
-            .read_memory_map();
+            .read_memory_map(map.clone());
+        info!("meminfo: {}", map.summary());
 
         PAGE_FRAME_ALLOCATOR
             .lock()
             .assume_init_mut()
             .lock_pages(handover.initramfs_base, handover.initramfs_size);
-        return;
     }
+    Ok(())
 }
 
 pub fn map_memory(handover: &mut Handover) {
     unsafe {
-        {
-            let kernel_size = _KERNEL_END - _KERNEL_START;
-            let kernel_pages = kernel_size / PAGE_SIZE + 1;
-            // Lock the memory at which the kernel has been loaded
-            PAGE_FRAME_ALLOCATOR
-                .lock()
-                .assume_init_mut()
-                .lock_pages(_KERNEL_START, kernel_pages as usize);
-        }
-
         {
             // The PageMapLevel4
             let pml4: &mut PageTable;
@@ -71,8 +80,7 @@ pub fn map_memory(handover: &mut Handover) {
             //memset(address_of!(pml4), 0, bks::PAGE_SIZE as usize);
             let pml4_addr = pml4 as *const PageTable as u64;
             let mut page_table_manager = PageTableManager::new(pml4);
-            // Mapping (and locking) the framebuffer
-            info!("Mapping Framebuffer...");
+            // Locking the framebuffer, the console maps what it draws to below
             let fb_base = handover.framebuffer().base;
             let fb_size = handover.framebuffer().size + PAGE_SIZE as usize;
             PAGE_FRAME_ALLOCATOR
@@ -80,20 +88,14 @@ pub fn map_memory(handover: &mut Handover) {
                 .assume_init_mut()
                 .lock_pages(fb_base, fb_size / PAGE_SIZE as usize + 1);
             let fb_end = fb_base + fb_size as u64;
-            for i in (fb_base..fb_end).step_by(PAGE_SIZE as usize) {
-                let _ = &mut page_table_manager.map_memory(i, i);
-            }
+            let console = FRAMEBUFFER_GUARD.lock().assume_init_ref().info();
+            let console_virt = console.map(|info| map_framebuffer(&mut page_table_manager, &info));
 
             // Step through the memory mapping phys x -> virt x
             let total_mem = PAGE_FRAME_ALLOCATOR.lock().assume_init_mut().total_memory();
-            debug!(
-                "Mapping Memory ({} bytes, {}kb, {}mb)...",
-                total_mem,
-                total_mem / 1024,
-                total_mem / 1024 / 1024,
-            );
+            debug!("Mapping Memory ({})...", ByteSize(total_mem));
 
-            for i in (_KERNEL_START..(total_mem)).step_by(0x1000) {
+            for i in (0..total_mem).step_by(0x1000) {
                 if (fb_base..fb_end).contains(&i) {
                     continue;
                 } // No double mapping of the framebuffer
@@ -105,8 +107,13 @@ pub fn map_memory(handover: &mut Handover) {
 
             //debug!("{:x?}", &*(pml4_addr as *mut PageTable));
 
-            let value = pml4_addr | 0; //(1 << 3) as u64;
-            asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+            if let Err(e) = switch_pml4(pml4_addr, console_virt) {
+                panic!(
+                    "The framebuffer is not mapped in the kernel page tables: {}",
+                    e.text()
+                );
+            }
+            breadcrumb::reach(Milestone::PagingSwitched);
 
             PAGE_TABLE_MANAGER.lock().write(page_table_manager);
             success!("Finished preparing memory!");
@@ -121,3 +128,59 @@ pub fn map_memory(handover: &mut Handover) {
         }
     }
 }
+
+/// # Map Framebuffer
+/// Maps what the console draws to of the framebuffer `info` write-combining into the tables
+/// of `manager`, at the direct map address of the framebuffer
+///
+/// ## Returns
+/// - VirtualAddress = Where the first pixel is mapped
+fn map_framebuffer(manager: &mut PageTableManager, info: &FramebufferInfo) -> VirtualAddress {
+    let start = info.base & !(PAGE_SIZE - 1);
+    let end = info.base + info.mapped_len();
+    info!(
+        "Mapping Framebuffer ({} at {})...",
+        ByteSize(end - start),
+        PhysicalAddress::new(info.base)
+    );
+    let flags =
+        PageTableFlag::PRESENT | PageTableFlag::READ_WRITE | MemoryType::WriteCombining.flags();
+    for page in (start..end).step_by(PAGE_SIZE as usize) {
+        let virt = phys_to_virt(PhysicalAddress::new(page));
+        manager.map_page(virt.as_u64(), page, flags);
+    }
+    phys_to_virt(PhysicalAddress::new(info.base))
+}
+
+/// # Switch PML4
+/// Loads the page tables at `pml4` on the calling CPU, which draws the console through
+/// `framebuffer` in them from then on. The console is held across the switch, so nothing is
+/// drawn through a mapping that is gone and logging goes on right after it.
+///
+/// ## Returns
+/// - Error::InvalidArgument = The framebuffer is not mapped at `framebuffer` in the tables,
+///   the previous ones are loaded again
+///
+/// ## Safety
+/// The tables have to map the kernel like the active ones do
+pub unsafe fn switch_pml4(pml4: u64, framebuffer: Option<VirtualAddress>) -> Result<()> {
+    let previous = active_pml4();
+    without_interrupts(|| {
+        let mut console = FRAMEBUFFER_GUARD.lock();
+        asm!("mov cr3, {}", in(reg) pml4, options(nostack, preserves_flags));
+        let remapped = match framebuffer {
+            Some(virt) => console.assume_init_mut().remap(virt),
+            None => Ok(()),
+        };
+        if remapped.is_err() {
+            asm!("mov cr3, {}", in(reg) previous, options(nostack, preserves_flags));
+        }
+        remapped
+    })
+}
+
+/// Loads the PAT of the bootstrap CPU and logs the memory types the firmware set up
+pub fn init_memory_types(_handover: &mut Handover) {
+    pat::init_pat();
+    mtrr::log_mtrrs();
+}
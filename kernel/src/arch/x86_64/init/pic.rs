@@ -10,7 +10,7 @@ pub fn init_pic(_: &mut Handover) {
     crate::info!("Initializing the PIC");
     pic::remap_pic(0x20, 0x08);
 
-    // Unmask the PS2-Keyboard Interrupts
+    // Unmask the PIT, drivers unmask their interrupts themselves
     outb(PicPort::Pic1Data, PicUtilValue::Pic1Mask);
     outb(PicPort::Pic2Data, PicUtilValue::Pic2Mask);
 
@@ -1,6 +1,35 @@
+use crate::arch::pic::PicPort;
 use crate::arch::scheduler::pit::*;
-use bks::Handover;
+use crate::device::tree::{self, DeviceClass, Resource};
+use crate::error::Result;
 
-pub fn init_pit(_: &mut Handover) {
+/// The channel data ports and the mode/command port
+const PIT_PORT_COUNT: u16 = 4;
+/// The line of the PIT on the PIC
+const PIT_IRQ: u8 = 0;
+
+crate::initcall! {
+    name: "pit",
+    stage: Interrupts,
+    deps: [],
+    fatal: true,
+    init: init_pit,
+}
+
+pub fn init_pit() -> Result<()> {
+    tree::register(
+        "pit",
+        DeviceClass::Timer,
+        Some(tree::platform()?),
+        "pit",
+        &[
+            Resource::IoPorts {
+                base: PicPort::PitPort,
+                len: PIT_PORT_COUNT,
+            },
+            Resource::Irq(PIT_IRQ),
+        ],
+    )?;
     set_divisor(DIVISOR_MAX / 10);
+    Ok(())
 }
@@ -1,3 +1,52 @@
 use bks::Handover;
 
-pub fn init_smp(_handover: &mut Handover) {}
+use crate::arch::apic::{
+    call_function_interrupt_handler, init_local_apic, reschedule_interrupt_handler,
+    spurious_interrupt_handler, CALL_FUNCTION_VECTOR, RESCHEDULE_VECTOR, SPURIOUS_VECTOR,
+};
+use crate::arch::cpuid;
+use crate::arch::interrupts::set_interrupt_handler;
+use crate::breadcrumb::{self, Milestone};
+use crate::init::KernelInitError;
+use crate::smp::percpu::register_cpu;
+use crate::time::clock;
+use crate::{debug, info};
+
+/// # Init SMP
+/// Enables the local APIC of the bootstrap CPU and registers it as CPU 0
+///
+/// ## Returns
+/// - KernelInitError::ApicUnsupported = The CPU has no local APIC
+pub fn init_smp(_handover: &mut Handover) -> Result<(), KernelInitError> {
+    info!("Initializing SMP");
+    if !cpuid::has_apic() {
+        return Err(KernelInitError::ApicUnsupported);
+    }
+    set_interrupt_handler(
+        SPURIOUS_VECTOR as u64,
+        "spurious",
+        spurious_interrupt_handler,
+    );
+    set_interrupt_handler(
+        CALL_FUNCTION_VECTOR as u64,
+        "call-function",
+        call_function_interrupt_handler,
+    );
+    set_interrupt_handler(
+        RESCHEDULE_VECTOR as u64,
+        "reschedule",
+        reschedule_interrupt_handler,
+    );
+
+    let apic = init_local_apic();
+    breadcrumb::reach(Milestone::ApicEnabled);
+    let cpu = register_cpu(apic.id());
+    let offset = clock::measure_tsc_offset();
+    debug!(
+        "CPU {} is online (APIC id {}, TSC offset {})",
+        cpu,
+        apic.id(),
+        offset
+    );
+    Ok(())
+}
@@ -3,9 +3,14 @@ use bks::Handover;
 use crate::{
     arch::gdt::GdtEntryType,
     arch::iobus::msr::{read_msr, write_msr, MsrRegister},
-    arch::syscall::syscall_handler,
+    arch::smap::RFLAGS_AC,
+    arch::syscall::{init_syscall_cpu, syscall_handler},
+    smp::current_cpu,
 };
 
+/// # Init Syscalls
+/// Enables `syscall` on the calling CPU, which needs the per-CPU area of the entry path as its
+/// kernel GS base. Has to be called on every CPU.
 pub fn init_syscalls() {
     let syscall_base = GdtEntryType::KernelCode << 3;
     let sysret_base = (GdtEntryType::UserCode32Unused << 3) | 3;
@@ -15,8 +20,12 @@ pub fn init_syscalls() {
     write_msr(MsrRegister::Star, (star_hi as u64) << 32);
     write_msr(MsrRegister::LStar, syscall_handler as u64);
 
-    // Clear Trap Flag
-    write_msr(MsrRegister::SyscallMask, 0x0300);
+    // Clear the Trap, Interrupt and Alignment Check Flags, user memory is only accessed
+    // through memory::usermem
+    write_msr(MsrRegister::SyscallMask, 0x0300 | RFLAGS_AC);
+
+    // `swapgs` on entry exchanges the GS base of userspace with this
+    write_msr(MsrRegister::KernelBase, init_syscall_cpu(current_cpu()));
 
     let efer_val = read_msr(MsrRegister::Efer);
     write_msr(MsrRegister::Efer, efer_val | 1);
@@ -1,13 +1,23 @@
 pub use self::IDTException::*;
 
 use super::interrupt_frame::InterruptFrame;
+use crate::arch::paging::page_table_manager::{effective_flags, PageTableFlag};
+use crate::arch::smap;
+use crate::scheduler::spin::Mutex;
+use crate::warn;
 use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-#[allow(unused)]
+/// # Exception Type
+/// How an exception is reported, per the Intel SDM Vol. 3A, 6.5
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExceptionType {
+    /// Reported before the faulting instruction, which is retried once the cause is fixed
     Fault,
+    /// The state of the program is lost, it cannot be resumed
     Abort,
     Interrupt,
+    /// Reported after the trapping instruction, execution continues with the next one
     Trap,
 }
 
@@ -72,41 +82,101 @@ enumtastic::const_enum! {
              }
         }
 
+        // Whether the CPU pushes an error code after the frame for the exception
+        pub fn has_error_code(me: &Me) -> bool {
+            matches!(
+                *me,
+                DoubleFault
+                    | InvalidTSS
+                    | SegmentNotPresent
+                    | StackSegmentFault
+                    | GeneralProtectionFault
+                    | PageFault
+                    | AlignmentCheck
+                    | ControlProtection
+                    | VMMCommunicationException
+                    | SecurityException
+            )
+        }
+
+        // Whether the error code of the exception is a `SelectorErrorCode`
+        pub fn has_selector_error_code(me: &Me) -> bool {
+            matches!(
+                *me,
+                InvalidTSS | SegmentNotPresent | StackSegmentFault | GeneralProtectionFault
+            )
+        }
+
+        // The class of the exception. #DB is a fault for instruction breakpoints and a trap
+        // for everything else, it is listed as a trap. Reserved vectors are aborts.
         pub fn type_(me: &Me) -> super::ExceptionType {
+             use super::ExceptionType::*;
              match *me {
-                 DivideByZero => todo!(),
-                 Debug => todo!(),
-                 NonMaskable => todo!(),
-                 Breakpoint => todo!(),
-                 Overflow => todo!(),
-                 BoundRangeExceeded => todo!(),
-                 InvalidOpcode => todo!(),
-                 DeviceNotAvailable => todo!(),
-                 DoubleFault => todo!(),
-                 InvalidTSS => todo!(),
-                 SegmentNotPresent => todo!(),
-                 StackSegmentFault => todo!(),
-                 GeneralProtectionFault => todo!(),
-                 PageFault => todo!(),
-                 X87FloatingPointException => todo!(),
-                 AlignmentCheck => todo!(),
-                 MachineCheck => todo!(),
-                 SIMDFloatingPointException => todo!(),
-                 VirtualizationException => todo!(),
-                 ControlProtection => todo!(),
-                 HypervisorInjection => todo!(),
-                 VMMCommunicationException => todo!(),
-                 SecurityException => todo!(),
-                 _ => todo!(),
+                 DivideByZero => Fault,
+                 Debug => Trap,
+                 NonMaskable => Interrupt,
+                 Breakpoint => Trap,
+                 Overflow => Trap,
+                 BoundRangeExceeded => Fault,
+                 InvalidOpcode => Fault,
+                 DeviceNotAvailable => Fault,
+                 DoubleFault => Abort,
+                 InvalidTSS => Fault,
+                 SegmentNotPresent => Fault,
+                 StackSegmentFault => Fault,
+                 GeneralProtectionFault => Fault,
+                 PageFault => Fault,
+                 X87FloatingPointException => Fault,
+                 AlignmentCheck => Fault,
+                 MachineCheck => Abort,
+                 SIMDFloatingPointException => Fault,
+                 VirtualizationException => Fault,
+                 ControlProtection => Fault,
+                 HypervisorInjection => Fault,
+                 VMMCommunicationException => Fault,
+                 SecurityException => Fault,
+                 _ => Abort,
              }
         }
+
+        // Whether execution can continue after the exception, which aborts rule out
+        pub fn is_recoverable(me: &Me) -> bool {
+            type_(me) != super::ExceptionType::Abort
+        }
+
+        // The signal a fault in user space sends to the task that caused it
+        pub fn signal(me: &Me) -> u32 {
+            use ::abi::Signal;
+            match *me {
+                DivideByZero | X87FloatingPointException | SIMDFloatingPointException => {
+                    Signal::SIGFPE
+                }
+                Debug | Breakpoint => Signal::SIGTRAP,
+                InvalidOpcode => Signal::SIGILL,
+                AlignmentCheck => Signal::SIGBUS,
+                _ => Signal::SIGSEGV,
+            }
+        }
     }
 }
 
 pub trait Exception<const T: usize> {
     extern "x86-interrupt" fn handle(frame: InterruptFrame);
     fn get_name() -> &'static str {
-        stringify!(T)
+        IDTException::name(&T)
+    }
+
+    fn get_error_code() -> &'static str {
+        IDTException::error_code(&T)
+    }
+}
+
+/// # Exception With Error Code
+/// An exception the CPU pushes an error code for, see `IDTException::has_error_code`
+pub trait ExceptionWithErrorCode<const T: usize> {
+    extern "x86-interrupt" fn handle(frame: InterruptFrame, error_code: u64);
+    fn get_name() -> &'static str {
+        IDTException::name(&T)
     }
 
     fn get_error_code() -> &'static str {
@@ -115,6 +185,254 @@ pub trait Exception<const T: usize> {
 }
 pub struct ExceptionHandler<const T: usize>;
 
+/// # Descriptor Table
+/// The table a `SelectorErrorCode` refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+/// # Selector Error Code
+/// The error code of #TS, #NP, #SS and #GP, which refers to the segment or gate that caused the
+/// exception. A #GP that is not caused by a segment has an error code of zero.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct SelectorErrorCode(pub u64);
+
+impl SelectorErrorCode {
+    /// Whether the exception happened while delivering an external event, e.g. an interrupt
+    pub fn external(&self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    pub fn table(&self) -> DescriptorTable {
+        match (self.0 >> 1) & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            _ => DescriptorTable::Idt,
+        }
+    }
+
+    /// The index of the descriptor within `table()`
+    pub fn index(&self) -> u16 {
+        ((self.0 >> 3) & 0x1FFF) as u16
+    }
+}
+
+impl core::fmt::Debug for SelectorErrorCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("SelectorErrorCode")
+            .field("external", &self.external())
+            .field("table", &self.table())
+            .field("index", &format_args!("{:#x}", self.index()))
+            .finish()
+    }
+}
+
+/// # Unhandled
+/// Panics with the name of the exception with the vector `vector`
+fn unhandled(vector: usize) -> ! {
+    match IDTException::try_from_value(vector) {
+        Some(exception) => panic!(
+            "Triggered Fault {} ({:#x?}) with opcode {}",
+            IDTException::name(&exception),
+            exception,
+            IDTException::error_code(&exception)
+        ),
+        None => panic!("Triggered reserved exception {:#x?}", vector),
+    }
+}
+
+/// # Unhandled With Error Code
+/// Panics with the name of the exception with the vector `vector` and its decoded `error_code`
+fn unhandled_with_error_code(vector: usize, frame: &InterruptFrame, error_code: u64) -> ! {
+    let rip = frame.rip;
+    if IDTException::has_selector_error_code(&vector) {
+        panic!(
+            "Triggered Fault {} ({:#x?}) with opcode {} at {:#x}: {:?}",
+            IDTException::name(&vector),
+            vector,
+            IDTException::error_code(&vector),
+            rip,
+            SelectorErrorCode(error_code)
+        )
+    } else {
+        panic!(
+            "Triggered Fault {} ({:#x?}) with opcode {} at {:#x}: error code {:#x}",
+            IDTException::name(&vector),
+            vector,
+            IDTException::error_code(&vector),
+            rip,
+            error_code
+        )
+    }
+}
+
+/// # From User
+/// Whether the exception interrupted user space
+fn from_user(frame: &InterruptFrame) -> bool {
+    let cs = frame.cs;
+    cs & 3 == 3
+}
+
+/// # User Fault
+/// Sends the signal of the fault `vector` to the task that caused it in user space, which ends
+/// it, see `scheduler::signal::fault`. The `IrqScope` of the handler has to be dropped before.
+fn user_fault(vector: usize) -> ! {
+    crate::scheduler::signal::fault(IDTException::signal(&vector))
+}
+
+/// No fault is expected
+const NO_EXPECTATION: usize = usize::MAX;
+/// The vector of the fault a test is about to cause
+static EXPECTED: AtomicUsize = AtomicUsize::new(NO_EXPECTATION);
+static CAUGHT: Mutex<Option<CaughtFault>> = Mutex::new(None);
+
+/// # Caught Fault
+/// An expected fault, as seen by its handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaughtFault {
+    pub vector: usize,
+    pub error_code: Option<u64>,
+    /// The faulting instruction
+    pub rip: u64,
+    /// The address that was accessed, only known for page faults
+    pub address: Option<u64>,
+}
+
+/// # Set Test Expectation
+/// Makes the next exception with `vector` return from the function that caused it instead of
+/// panicking, see `take_caught_fault()`. Any other exception still panics. The faulting
+/// instruction has to be in a leaf function that did not touch its stack, so that its return
+/// address is at the top of the stack.
+pub fn set_test_expectation(vector: usize) {
+    *CAUGHT.lock() = None;
+    EXPECTED.store(vector, Ordering::SeqCst);
+}
+
+/// # Take Caught Fault
+/// The fault caught since `set_test_expectation()`, if there was one. Clears the expectation.
+pub fn take_caught_fault() -> Option<CaughtFault> {
+    EXPECTED.store(NO_EXPECTATION, Ordering::SeqCst);
+    CAUGHT.lock().take()
+}
+
+/// # Catch Expected
+/// Swallows the exception if a test expected it: The faulting function returns to its caller.
+///
+/// ## Returns
+/// - bool = Whether the exception was expected, otherwise the handler has to deal with it
+fn catch_expected(
+    frame: &mut InterruptFrame,
+    vector: usize,
+    error_code: Option<u64>,
+    address: Option<u64>,
+) -> bool {
+    if EXPECTED
+        .compare_exchange(vector, NO_EXPECTATION, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return false;
+    }
+    let rip = frame.rip;
+    *CAUGHT.lock() = Some(CaughtFault {
+        vector,
+        error_code,
+        rip,
+        address,
+    });
+    // The frame is the one the CPU pushed, writing it changes where `iretq` returns to. The
+    // writes are volatile, as the compiler considers the frame a local that is dead afterwards.
+    unsafe {
+        let rsp = frame.rsp;
+        let return_address = *(rsp as *const u64);
+        core::ptr::write_volatile(core::ptr::addr_of_mut!(frame.rip), return_address);
+        core::ptr::write_volatile(core::ptr::addr_of_mut!(frame.rsp), rsp + 8);
+    }
+    true
+}
+
+/// # Recovery Handler
+/// Tries to fix the cause of a fault, e.g. by mapping the missing page. Gets the error code if
+/// the exception has one.
+///
+/// ## Returns
+/// - bool = Whether the cause is fixed, the faulting instruction is retried then
+pub type RecoveryHandler = fn(&mut InterruptFrame, Option<u64>) -> bool;
+
+/// The number of vectors reserved for exceptions
+pub const EXCEPTIONS: usize = 32;
+
+static RECOVERY_HANDLERS: Mutex<[Option<RecoveryHandler>; EXCEPTIONS]> =
+    Mutex::new([None; EXCEPTIONS]);
+
+/// # Register Recovery Handler
+/// Lets `handler` try to recover from the fault `vector` before the kernel panics. A vector has
+/// one recovery handler, registering another one replaces it.
+///
+/// ## Returns
+/// - Option<RecoveryHandler> = The handler that was replaced
+///
+/// ## Panics
+/// If the exception cannot be recovered from, see `IDTException::is_recoverable`
+pub fn register_recovery_handler(
+    vector: usize,
+    handler: RecoveryHandler,
+) -> Option<RecoveryHandler> {
+    assert!(
+        vector < EXCEPTIONS && IDTException::is_recoverable(&vector),
+        "Exception {:#x} cannot be recovered from",
+        vector
+    );
+    RECOVERY_HANDLERS.lock()[vector].replace(handler)
+}
+
+/// # Unregister Recovery Handler
+/// Removes the recovery handler of `vector`, faults panic again
+pub fn unregister_recovery_handler(vector: usize) -> Option<RecoveryHandler> {
+    RECOVERY_HANDLERS.lock().get_mut(vector)?.take()
+}
+
+/// # Recovery Handler
+/// The recovery handler registered for `vector`
+pub fn recovery_handler(vector: usize) -> Option<RecoveryHandler> {
+    RECOVERY_HANDLERS.lock().get(vector).copied().flatten()
+}
+
+/// # Dispatch
+/// Handles an exception by its type: Aborts are never handled, traps are logged and execution
+/// continues, faults are handed to a test that expects them or to the recovery handler of
+/// `vector`.
+///
+/// ## Returns
+/// - bool = Whether the exception is handled, otherwise the kernel has to panic
+fn dispatch(
+    frame: &mut InterruptFrame,
+    vector: usize,
+    error_code: Option<u64>,
+    address: Option<u64>,
+) -> bool {
+    if !IDTException::is_recoverable(&vector) {
+        return false;
+    }
+    if catch_expected(frame, vector, error_code, address) {
+        return true;
+    }
+    if IDTException::type_(&vector) == ExceptionType::Trap {
+        let rip = frame.rip;
+        warn!(
+            "Trap {} ({}) before {:#x}",
+            IDTException::name(&vector),
+            IDTException::error_code(&vector),
+            rip
+        );
+        return true;
+    }
+    recovery_handler(vector).map_or(false, |handler| handler(frame, error_code))
+}
+
 macro_rules! impl_generic_exception_handler {
     (
         $(
@@ -123,8 +441,40 @@ macro_rules! impl_generic_exception_handler {
     ) => {
         $(
             impl Exception<$op> for ExceptionHandler<$op> {
-                extern "x86-interrupt" fn handle(_frame: InterruptFrame) {
-                    panic!("Triggered Fault {} ({:#x?}) with opcode {}", stringify!($op), $op, IDTException::error_code(&$op))
+                extern "x86-interrupt" fn handle(mut frame: InterruptFrame) {
+                    let irq = super::IrqScope::enter($op);
+                    if dispatch(&mut frame, $op, None, None) {
+                        return;
+                    }
+                    if from_user(&frame) {
+                        drop(irq);
+                        user_fault($op)
+                    }
+                    unhandled($op)
+                }
+            }
+        )*
+    }
+}
+
+macro_rules! impl_generic_exception_handler_with_error_code {
+    (
+        $(
+            $op:ident,
+        )*
+    ) => {
+        $(
+            impl ExceptionWithErrorCode<$op> for ExceptionHandler<$op> {
+                extern "x86-interrupt" fn handle(mut frame: InterruptFrame, error_code: u64) {
+                    let irq = super::IrqScope::enter($op);
+                    if dispatch(&mut frame, $op, Some(error_code), None) {
+                        return;
+                    }
+                    if from_user(&frame) {
+                        drop(irq);
+                        user_fault($op)
+                    }
+                    unhandled_with_error_code($op, &frame, error_code)
                 }
             }
         )*
@@ -134,30 +484,33 @@ macro_rules! impl_generic_exception_handler {
 impl_generic_exception_handler! {
     DivideByZero,
     Debug,
-    NonMaskable,
     Breakpoint,
     Overflow,
     BoundRangeExceeded,
     InvalidOpcode,
     DeviceNotAvailable,
-    InvalidTSS,
-    SegmentNotPresent,
-    StackSegmentFault,
     // 0xF = Reserved,
     X87FloatingPointException ,
-    AlignmentCheck ,
     MachineCheck ,
-    DoubleFault,
     SIMDFloatingPointException ,
     VirtualizationException ,
-    ControlProtection ,
     HypervisorInjection ,
-    VMMCommunicationException ,
-    SecurityException ,
     // 0x1F = Reserved,
     // TripleFault does not have a code,
 }
 
+impl_generic_exception_handler_with_error_code! {
+    DoubleFault,
+    InvalidTSS,
+    SegmentNotPresent,
+    StackSegmentFault,
+    GeneralProtectionFault,
+    AlignmentCheck,
+    ControlProtection,
+    VMMCommunicationException,
+    SecurityException,
+}
+
 bitflags::bitflags! {
     #[repr(transparent)]
     pub struct PageFaultErrorCode: u64 {
@@ -169,22 +522,73 @@ bitflags::bitflags! {
     }
 }
 
-impl Exception<PageFault> for ExceptionHandler<PageFault> {
-    extern "x86-interrupt" fn handle(_frame: InterruptFrame) {
-        let code: u64;
+crate::counter!(pub PAGE_FAULTS = "mm.page_faults");
+
+impl ExceptionWithErrorCode<PageFault> for ExceptionHandler<PageFault> {
+    extern "x86-interrupt" fn handle(mut frame: InterruptFrame, error_code: u64) {
+        let irq = super::IrqScope::enter(PageFault);
+        PAGE_FAULTS.increment();
         let cr2: u64;
         unsafe {
-            asm!("mov {}, rsp", out(reg) code);
             asm!("mov {}, cr2", out(reg) cr2);
         };
-        let err = PageFaultErrorCode::from_bits_truncate(code);
+        crate::trace_event!(PageFault, cr2, error_code);
+        if dispatch(&mut frame, PageFault, Some(error_code), Some(cr2)) {
+            return;
+        }
+        let rip = frame.rip;
+        let err = PageFaultErrorCode::from_bits_truncate(error_code);
+        if let Some(violation) = supervisor_violation(&frame, cr2, err) {
+            panic!("{} at address {:#x} from {:#x}", violation, cr2, rip);
+        }
+        if crate::memory::vmm::handle_fault(
+            cr2,
+            err.contains(PageFaultErrorCode::CAUSED_BY_WRITE_ACCESS),
+        ) {
+            return;
+        }
+        if from_user(&frame) {
+            drop(irq);
+            user_fault(PageFault)
+        }
         panic!(
-            "Page Fault Occured at address {:#x?} with code {:#?}",
-            cr2, err
+            "Page Fault Occured at address {:#x?} from {:#x} with code {:#?}",
+            cr2, rip, err
         );
     }
 }
 
-impl Exception<GeneralProtectionFault> for ExceptionHandler<GeneralProtectionFault> {
-    extern "x86-interrupt" fn handle(_frame: InterruptFrame) {}
+/// # Supervisor Violation
+/// Describes a page fault SMEP or SMAP raised: The kernel ran code of a user page, or accessed
+/// one without going through `memory::usermem`
+fn supervisor_violation(
+    frame: &InterruptFrame,
+    address: u64,
+    err: PageFaultErrorCode,
+) -> Option<&'static str> {
+    if err.contains(PageFaultErrorCode::USER_MODE)
+        || !err.contains(PageFaultErrorCode::PAGE_PROTECTON_VIOLATION)
+    {
+        return None;
+    }
+    let flags = effective_flags(address)?;
+    if !flags.contains(PageTableFlag::USER_ACCESSIBLE) {
+        return None;
+    }
+    if err.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        return smap::is_smep_enabled().then(|| "SMEP: kernel executed user memory");
+    }
+    let rflags = frame.rflags;
+    (smap::is_smap_enabled() && rflags & smap::RFLAGS_AC == 0)
+        .then(|| "SMAP: kernel accessed user memory outside usermem helpers")
+}
+
+impl Exception<NonMaskable> for ExceptionHandler<NonMaskable> {
+    extern "x86-interrupt" fn handle(frame: InterruptFrame) {
+        let _irq = super::IrqScope::enter(NonMaskable);
+        if crate::watchdog::handle_nmi(&frame) {
+            return;
+        }
+        unhandled(NonMaskable)
+    }
 }
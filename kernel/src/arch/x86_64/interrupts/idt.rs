@@ -1,8 +1,7 @@
 use core::mem::MaybeUninit;
 
-use spin::Mutex;
-
 use super::interrupt_frame::InterruptFrame;
+use crate::scheduler::spin::Mutex;
 
 // https://wiki.osdev.org/Interrupt_Descriptor_Table
 #[repr(u8)]
@@ -57,6 +56,15 @@ impl IDTDescriptorEntry {
         Self::new(as_u64, type_and_attrs, segment_selector)
     }
 
+    /// Like `with_function`, for the exceptions that push an error code after the frame
+    pub fn with_error_code_function(
+        func: extern "x86-interrupt" fn(InterruptFrame, u64),
+        type_and_attrs: u8,
+        segment_selector: u16,
+    ) -> Self {
+        Self::new(func as u64, type_and_attrs, segment_selector)
+    }
+
     /// Sets the entire offset using a single u64
     pub fn set_offset(&mut self, offset: u64) {
         self.offset_0 = (  offset & 0x000000000000ffff) as u16 /* Get Lo */;
@@ -64,6 +72,11 @@ impl IDTDescriptorEntry {
         self.offset_2 = ((offset & 0xffffffff00000000) >> 16) as u32;
     }
 
+    /// Makes the CPU switch to the interrupt stack `ist` of its TSS for this entry, 0 for none
+    pub fn set_interrupt_stack(&mut self, ist: u8) {
+        self.interrupt_stack_table_offset = ist & 0b111;
+    }
+
     pub fn get_offset(&self) -> u64 {
         let mut offset = 0;
         offset |= (self.offset_0) as u64;
@@ -97,6 +110,17 @@ pub fn upload_idt_entry_at(offset: u64, value: IDTDescriptorEntry) {
     *idt_entry = value;
 }
 
+/// Sets the interrupt stack of the entry at `offset`, keeping the handler
+pub fn set_idt_entry_stack(offset: u64, ist: u8) {
+    let idt_entry = unsafe {
+        &mut *((IDT_REGISTER.lock().assume_init_mut().offset
+            + (offset * core::mem::size_of::<IDTDescriptorEntry>() as u64))
+            as *mut u64 as *mut IDTDescriptorEntry)
+    };
+
+    idt_entry.set_interrupt_stack(ist);
+}
+
 pub static IDT_REGISTER: Mutex<MaybeUninit<IDTRegister>> = Mutex::new(MaybeUninit::uninit());
 
 #[inline(always)]
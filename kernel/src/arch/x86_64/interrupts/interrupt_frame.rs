@@ -1,5 +1,10 @@
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct InterruptFrame {
-    pub _ignore: u64,
+    /// Where the interrupted code continues
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
 }
@@ -1,15 +1,225 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use self::{
-    idt::{upload_idt_entry_at, IDTDescriptorEntry, IDTTypesAndAttrs},
+    exceptions::{IDTException, EXCEPTIONS},
+    idt::{set_idt_entry_stack, upload_idt_entry_at, IDTDescriptorEntry, IDTTypesAndAttrs},
     interrupt_frame::InterruptFrame,
 };
+use crate::arch::tsc;
+use crate::scheduler::cputime;
+use crate::scheduler::spin::{Mutex, MutexGuard};
+use crate::smp::{current_cpu, MAX_CPUS};
+use crate::stats::{IRQ_COUNT, IRQ_MAX_CYCLES};
 
 pub mod exceptions;
 pub mod idt;
 pub mod interrupt_frame;
 pub mod register;
 
-pub fn set_interrupt_handler(offset: u64, handler: extern "x86-interrupt" fn(InterruptFrame)) {
+/// # Are Enabled
+/// Returns whether maskable interrupts are currently enabled (`RFLAGS.IF`).
+/// Interrupt gates clear the flag, so this is `false` inside every interrupt handler.
+pub fn are_enabled() -> bool {
+    let rflags: u64;
+    unsafe {
+        core::arch::asm!("pushfq; pop {}", out(reg) rflags, options(preserves_flags));
+    }
+    rflags & (1 << 9) != 0
+}
+
+/// # Without Interrupts
+/// Runs `f` with interrupts disabled and restores the previous state afterwards
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let were_enabled = are_enabled();
+    if were_enabled {
+        comasm::clear_interrupts();
+    }
+    let ret = f();
+    if were_enabled {
+        comasm::reload_interrupt_flags();
+    }
+    ret
+}
+
+pub const VECTORS: usize = 256;
+
+/// The name every vector's handler was registered with
+static HANDLER_NAMES: Mutex<[Option<&'static str>; VECTORS]> = Mutex::new([None; VECTORS]);
+
+/// # Set Interrupt Handler
+/// Installs `handler` for the vector `offset`. `name` shows up in the interrupt statistics.
+///
+/// ## Panics
+/// If the CPU pushes an error code for `offset`, see `set_interrupt_handler_with_error_code`
+pub fn set_interrupt_handler(
+    offset: u64,
+    name: &'static str,
+    handler: extern "x86-interrupt" fn(InterruptFrame),
+) {
+    assert!(
+        !IDTException::has_error_code(&(offset as usize)),
+        "Vector {:#x} pushes an error code",
+        offset
+    );
     let idt_desc =
         IDTDescriptorEntry::with_function(handler, IDTTypesAndAttrs::InterruptGate as u8, 0x08);
-    upload_idt_entry_at(offset, idt_desc)
+    install_handler(offset, name, idt_desc);
+}
+
+/// # Set Interrupt Handler With Error Code
+/// Installs `handler` for the exception `offset`, which the CPU pushes an error code for
+///
+/// ## Panics
+/// If the CPU pushes no error code for `offset`, see `set_interrupt_handler`
+pub fn set_interrupt_handler_with_error_code(
+    offset: u64,
+    name: &'static str,
+    handler: extern "x86-interrupt" fn(InterruptFrame, u64),
+) {
+    assert!(
+        IDTException::has_error_code(&(offset as usize)),
+        "Vector {:#x} pushes no error code",
+        offset
+    );
+    let idt_desc = IDTDescriptorEntry::with_error_code_function(
+        handler,
+        IDTTypesAndAttrs::InterruptGate as u8,
+        0x08,
+    );
+    install_handler(offset, name, idt_desc);
+}
+
+/// # Set Raw Interrupt Handler
+/// Installs `entry` for the vector `offset`, an entry written in assembly that saves what it
+/// uses itself and returns with `iretq`
+///
+/// ## Safety
+/// `entry` has to keep the frame the CPU pushed, including the error code if there is one
+pub unsafe fn set_raw_interrupt_handler(
+    offset: u64,
+    name: &'static str,
+    entry: unsafe extern "C" fn(),
+) {
+    let idt_desc = IDTDescriptorEntry::new(
+        entry as usize as u64,
+        IDTTypesAndAttrs::InterruptGate as u8,
+        0x08,
+    );
+    install_handler(offset, name, idt_desc);
+}
+
+/// # Set Interrupt Stack
+/// Makes the handler of the vector `offset`, which has to be installed already, always run on
+/// the interrupt stack `stack` of the TSS of the CPU, see `gdt::local::InterruptStack`. Meant for
+/// the exceptions that may arrive while the current stack is unusable.
+pub fn set_interrupt_stack(offset: u64, stack: u8) {
+    set_idt_entry_stack(offset, stack);
+}
+
+fn install_handler(offset: u64, name: &'static str, idt_desc: IDTDescriptorEntry) {
+    upload_idt_entry_at(offset, idt_desc);
+    if let Some(slot) = HANDLER_NAMES.lock().get_mut(offset as usize) {
+        *slot = Some(name);
+    }
+}
+
+/// # Remove Handler Name
+/// Forgets the name of the handler of `vector` once it is no longer used, the entry itself stays
+/// installed
+pub fn remove_handler_name(vector: usize) {
+    if let Some(slot) = HANDLER_NAMES.lock().get_mut(vector) {
+        *slot = None;
+    }
+}
+
+/// The name the handler of `vector` was registered with
+pub fn handler_name(vector: usize) -> Option<&'static str> {
+    HANDLER_NAMES.lock().get(vector).copied().flatten()
+}
+
+const NO_EXCEPTIONS: AtomicUsize = AtomicUsize::new(0);
+/// The number of exception handlers running on each CPU, nested ones counted separately
+static EXCEPTION_DEPTH: [AtomicUsize; MAX_CPUS] = [NO_EXCEPTIONS; MAX_CPUS];
+/// The sum of `EXCEPTION_DEPTH`, which spares the common case looking up the CPU
+static EXCEPTIONS_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// # In Exception Context
+/// Whether the calling CPU runs an exception handler, including a panic that started in one.
+/// The code the exception interrupted may hold any lock, so waiting for one could never end.
+pub fn in_exception_context() -> bool {
+    EXCEPTIONS_RUNNING.load(Ordering::Acquire) != 0
+        && EXCEPTION_DEPTH[current_cpu()].load(Ordering::Acquire) != 0
+}
+
+/// # Exception Safe Lock
+/// Locks `mutex`, but only tries to in exception context: The code the exception interrupted
+/// may hold it, and would never get to unlock it.
+///
+/// ## Returns
+/// - None = The calling CPU runs an exception handler and `mutex` is held
+pub fn exception_safe_lock<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    if in_exception_context() {
+        mutex.try_lock()
+    } else {
+        Some(mutex.lock())
+    }
+}
+
+/// # IRQ Scope
+/// Accounts for a single run of an interrupt handler: Counts it when entered and records how
+/// long it took when dropped. Every handler creates one first thing. For exceptions it keeps
+/// `in_exception_context()` true until it is dropped, which a handler that panics never does.
+/// For interrupts, dropping it runs the pending softirqs, so it has to outlive the end of
+/// interrupt.
+///
+/// ## Example
+/// ```
+/// let _irq = IrqScope::enter(PIT_INTERRUPT as usize);
+/// ```
+pub struct IrqScope {
+    vector: usize,
+    start: u64,
+    /// The CPU the exception runs on, `None` for interrupts
+    exception_cpu: Option<usize>,
+    /// Whether user space was interrupted, its time is accounted as kernel time until the end
+    from_user: bool,
+}
+
+impl IrqScope {
+    #[inline(always)]
+    pub fn enter(vector: usize) -> Self {
+        IRQ_COUNT.increment(vector);
+        crate::trace_event!(IrqEntry, vector);
+        let exception_cpu = (vector < EXCEPTIONS).then(|| {
+            let cpu = current_cpu();
+            EXCEPTION_DEPTH[cpu].fetch_add(1, Ordering::AcqRel);
+            EXCEPTIONS_RUNNING.fetch_add(1, Ordering::AcqRel);
+            cpu
+        });
+        Self {
+            vector,
+            start: tsc::read(),
+            exception_cpu,
+            from_user: cputime::enter_kernel(),
+        }
+    }
+}
+
+impl Drop for IrqScope {
+    #[inline(always)]
+    fn drop(&mut self) {
+        let cycles = tsc::read().wrapping_sub(self.start);
+        IRQ_MAX_CYCLES.record_max(self.vector, cycles);
+        crate::trace_event!(IrqExit, self.vector, cycles);
+        match self.exception_cpu {
+            Some(cpu) => {
+                EXCEPTION_DEPTH[cpu].fetch_sub(1, Ordering::AcqRel);
+                EXCEPTIONS_RUNNING.fetch_sub(1, Ordering::AcqRel);
+            }
+            None => crate::irq::softirq::irq_exit(self.from_user),
+        }
+        if self.from_user {
+            cputime::exit_to_user();
+        }
+    }
 }
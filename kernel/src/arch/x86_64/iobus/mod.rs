@@ -57,3 +57,23 @@ pub fn inl(port: u16) -> u32 {
     };
     retval
 }
+
+/// # In Word
+/// Reads a u16 from the given port
+#[inline(always)]
+pub fn inw(port: u16) -> u16 {
+    let mut retval: u16;
+    unsafe {
+        asm!("in ax, dx", in("dx") port, out("ax") retval, options(preserves_flags, nomem, nostack));
+    }
+    retval
+}
+
+/// # Out Word
+/// Writes a u16 to the given port
+#[inline(always)]
+pub fn outw(port: u16, value: u16) {
+    unsafe {
+        asm!("out dx, ax", in("dx") port, in("ax") value, options(preserves_flags, nomem, nostack));
+    }
+}
@@ -2,6 +2,12 @@ enumtastic::const_enum! {
     // https://wiki.osdev.org/PIC#Programming_the_PIC_chips
     pub enum MsrRegister: u64 => {
         Apic = 0x1B,
+        MtrrCapabilities = 0xFE,
+        /// The first variable range MTRR, range `n` is at `MtrrPhysBase0 + 2 * n`
+        MtrrPhysBase0 = 0x200,
+        MtrrPhysMask0 = 0x201,
+        Pat = 0x277,
+        MtrrDefaultType = 0x2FF,
         Efer = 0xC0000080,
         Star = 0xC0000081,
         LStar = 0xC0000082,
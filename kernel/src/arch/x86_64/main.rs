@@ -1,17 +1,26 @@
-use crate::{arch::init, config::set_handover};
+use crate::init::error::check;
+use crate::{arch::init, buildinfo, config::set_handover, info};
 use bks::Handover;
 
 #[no_mangle]
 extern "sysv64" fn kmain(mut handover: Handover) -> u32 {
+    info!("{}", buildinfo::Banner);
     crate::init::config::init_config(&mut handover);
+    crate::cmdline::init_cmdline(&handover);
+    crate::breadcrumb::init();
+    crate::memory::kernel_image::init();
     init::gdt::init_gdt(&mut handover);
-    crate::init::common::init_common(&mut handover);
-    init::memory::init_initial_paging(&mut handover);
+    check("console", crate::init::common::init_common(&mut handover));
+    check("memory", init::memory::init_initial_paging(&mut handover));
     init::interrupts::init_interrupts(&mut handover);
     init::pic::init_pic(&mut handover);
-    init::pit::init_pit(&mut handover);
+    // The framebuffer is mapped write-combining in the kernel page tables, which needs the PAT
+    init::memory::init_memory_types(&mut handover);
     init::memory::map_memory(&mut handover);
-    init::smp::init_smp(&mut handover);
+    crate::memory::memtest::run();
+    check("smp", init::smp::init_smp(&mut handover));
     set_handover(handover);
+    crate::arch::smap::init_smap();
+    crate::arch::fpu::init_fpu();
     crate::main();
 }
@@ -0,0 +1,283 @@
+//! # Mem
+//! Bulk copies and fills that are faster than the byte loops of compiler-builtins.
+//!
+//! `copy_fast()` picks a variant by size: `rep movsb` on CPUs with ERMS, a loop of 16 byte SSE2
+//! moves otherwise, and non-temporal stores for copies so large they would only push everything
+//! else out of the cache, more than half of the last level cache. `bench mem` measures every
+//! variant, `memnt=<bytes>` on the command line moves the non-temporal threshold.
+//!
+//! The kernel is built with soft-float, so the vector registers belong to the running task. The
+//! SSE2 loop saves the ones it uses and puts them back, an #NM on the way loads the state of the
+//! task first like for any other FPU instruction.
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+use spin::Once;
+
+use crate::arch::fpu::{self, FpuMode};
+use crate::cmdline;
+
+/// The command line option that sets the non-temporal threshold in bytes
+pub const NONTEMPORAL_OPTION: &str = "memnt";
+/// Used if the CPU does not report its caches
+pub const DEFAULT_NONTEMPORAL_THRESHOLD: usize = 0x10_0000;
+/// Copies up to this size are left to compiler-builtins, setting up a faster one costs more
+pub const SMALL_COPY: usize = 64;
+/// CPUID.(EAX=07H,ECX=0):EBX.ERMS
+const CPUID_ERMS: u32 = 1 << 9;
+/// Deterministic cache parameters, CPUID.04H
+const CACHE_PARAMETERS_LEAF: u32 = 4;
+/// L2 and L3 cache information of AMD, CPUID.80000006H
+const EXTENDED_CACHE_LEAF: u32 = 0x8000_0006;
+/// The bytes the SSE2 loop moves per iteration, in four registers
+const SSE2_BLOCK: usize = 64;
+const NONTEMPORAL_WORD: usize = 8;
+
+/// # Copy Variant
+/// A way of copying memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyVariant {
+    /// `core::ptr::copy()`, whatever compiler-builtins provides
+    Builtin,
+    RepMovsb,
+    Sse2,
+    /// `movnti`, which bypasses the cache
+    NonTemporal,
+}
+
+impl CopyVariant {
+    pub const ALL: [CopyVariant; 4] = [
+        CopyVariant::Builtin,
+        CopyVariant::RepMovsb,
+        CopyVariant::Sse2,
+        CopyVariant::NonTemporal,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CopyVariant::Builtin => "builtin",
+            CopyVariant::RepMovsb => "rep-movsb",
+            CopyVariant::Sse2 => "sse2",
+            CopyVariant::NonTemporal => "nontemporal",
+        }
+    }
+
+    /// Whether the variant can be used on this CPU. `rep movsb` always works, but is only fast
+    /// with ERMS.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            CopyVariant::Sse2 => fpu::mode() != FpuMode::None,
+            _ => true,
+        }
+    }
+}
+
+struct Features {
+    erms: bool,
+    nontemporal_threshold: usize,
+}
+
+static FEATURES: Once<Features> = Once::new();
+
+fn features() -> &'static Features {
+    FEATURES.call_once(|| {
+        let max_leaf = unsafe { __cpuid(0) }.eax;
+        let erms = max_leaf >= 7 && unsafe { __cpuid_count(7, 0) }.ebx & CPUID_ERMS != 0;
+        let nontemporal_threshold = cmdline::value(NONTEMPORAL_OPTION)
+            .and_then(|value| value.parse().ok())
+            .or_else(|| last_level_cache(max_leaf).map(|size| size / 2))
+            .unwrap_or(DEFAULT_NONTEMPORAL_THRESHOLD);
+        Features {
+            erms,
+            nontemporal_threshold,
+        }
+    })
+}
+
+/// # Last Level Cache
+/// The size of the largest cache in bytes, from CPUID.04H on Intel and CPUID.80000006H on AMD
+fn last_level_cache(max_leaf: u32) -> Option<usize> {
+    let mut largest = None;
+    if max_leaf >= CACHE_PARAMETERS_LEAF {
+        for index in 0.. {
+            let leaf = unsafe { __cpuid_count(CACHE_PARAMETERS_LEAF, index) };
+            // Type 0 means there are no more caches
+            if leaf.eax & 0x1F == 0 {
+                break;
+            }
+            let ways = (leaf.ebx >> 22) as usize + 1;
+            let partitions = ((leaf.ebx >> 12) & 0x3FF) as usize + 1;
+            let line = (leaf.ebx & 0xFFF) as usize + 1;
+            let sets = leaf.ecx as usize + 1;
+            largest = largest.max(Some(ways * partitions * line * sets));
+        }
+    }
+    if largest.is_none() && unsafe { __cpuid(0x8000_0000) }.eax >= EXTENDED_CACHE_LEAF {
+        let leaf = unsafe { __cpuid(EXTENDED_CACHE_LEAF) };
+        let l2 = (leaf.ecx >> 16) as usize * 1024;
+        let l3 = (leaf.edx >> 18) as usize * 512 * 1024;
+        largest = Some(l2.max(l3)).filter(|size| *size != 0);
+    }
+    largest
+}
+
+/// Whether the CPU has Enhanced REP MOVSB/STOSB
+pub fn has_erms() -> bool {
+    features().erms
+}
+
+/// # Non-Temporal Threshold
+/// The size from which on `copy_fast()` bypasses the cache
+pub fn nontemporal_threshold() -> usize {
+    features().nontemporal_threshold
+}
+
+/// # Select
+/// The variant `copy_fast()` uses for `len` bytes
+pub fn select(len: usize) -> CopyVariant {
+    if len <= SMALL_COPY {
+        CopyVariant::Builtin
+    } else if len >= nontemporal_threshold() {
+        CopyVariant::NonTemporal
+    } else if has_erms() {
+        CopyVariant::RepMovsb
+    } else if CopyVariant::Sse2.is_supported() {
+        CopyVariant::Sse2
+    } else {
+        CopyVariant::RepMovsb
+    }
+}
+
+/// # Copy Fast
+/// Copies `len` bytes from `src` to `dst` with the variant `select()` picks. The ranges may
+/// overlap.
+///
+/// ## Safety
+/// Like `core::ptr::copy()`: `src` has to be valid for reads and `dst` for writes of `len` bytes
+pub unsafe fn copy_fast(dst: *mut u8, src: *const u8, len: usize) {
+    copy_with(select(len), dst, src, len);
+}
+
+/// # Copy Non-Temporal
+/// Copies `len` bytes from `src` to `dst` without pulling `dst` into the cache. The ranges may
+/// overlap.
+///
+/// ## Safety
+/// See `copy_fast()`
+pub unsafe fn copy_nontemporal(dst: *mut u8, src: *const u8, len: usize) {
+    copy_with(CopyVariant::NonTemporal, dst, src, len);
+}
+
+/// # Copy With
+/// Copies `len` bytes from `src` to `dst` with `variant`, or with `core::ptr::copy()` if the
+/// ranges overlap such that copying forwards would overwrite bytes before they are read
+///
+/// ## Safety
+/// See `copy_fast()`. `variant` has to be supported.
+pub unsafe fn copy_with(variant: CopyVariant, dst: *mut u8, src: *const u8, len: usize) {
+    let (dst_addr, src_addr) = (dst as usize, src as usize);
+    if dst_addr > src_addr && dst_addr < src_addr.saturating_add(len) {
+        core::ptr::copy(src, dst, len);
+        return;
+    }
+    // Every variant copies forwards, which is correct for any other overlap
+    match variant {
+        CopyVariant::Builtin => core::ptr::copy(src, dst, len),
+        CopyVariant::RepMovsb => rep_movsb(dst, src, len),
+        CopyVariant::Sse2 => sse2(dst, src, len),
+        CopyVariant::NonTemporal => nontemporal(dst, src, len),
+    }
+}
+
+/// # Set Fast
+/// Sets `len` bytes from `dst` on to `value`, with `rep stosb` on CPUs with ERMS
+///
+/// ## Safety
+/// `dst` has to be valid for writes of `len` bytes
+pub unsafe fn set_fast(dst: *mut u8, value: u8, len: usize) {
+    if len <= SMALL_COPY || !has_erms() {
+        core::ptr::write_bytes(dst, value, len);
+        return;
+    }
+    asm!(
+        "rep stosb",
+        inout("rcx") len => _,
+        inout("rdi") dst => _,
+        in("al") value,
+        options(nostack, preserves_flags)
+    );
+}
+
+unsafe fn rep_movsb(dst: *mut u8, src: *const u8, len: usize) {
+    asm!(
+        "rep movsb",
+        inout("rcx") len => _,
+        inout("rdi") dst => _,
+        inout("rsi") src => _,
+        options(nostack, preserves_flags)
+    );
+}
+
+unsafe fn sse2(dst: *mut u8, src: *const u8, len: usize) {
+    let blocks = len / SSE2_BLOCK;
+    if blocks != 0 {
+        let mut saved = [0u8; SSE2_BLOCK];
+        asm!(
+            "movdqu [{saved}], xmm0",
+            "movdqu [{saved} + 16], xmm1",
+            "movdqu [{saved} + 32], xmm2",
+            "movdqu [{saved} + 48], xmm3",
+            "2:",
+            "movdqu xmm0, [{src}]",
+            "movdqu xmm1, [{src} + 16]",
+            "movdqu xmm2, [{src} + 32]",
+            "movdqu xmm3, [{src} + 48]",
+            "movdqu [{dst}], xmm0",
+            "movdqu [{dst} + 16], xmm1",
+            "movdqu [{dst} + 32], xmm2",
+            "movdqu [{dst} + 48], xmm3",
+            "add {src}, 64",
+            "add {dst}, 64",
+            "dec {blocks}",
+            "jnz 2b",
+            "movdqu xmm0, [{saved}]",
+            "movdqu xmm1, [{saved} + 16]",
+            "movdqu xmm2, [{saved} + 32]",
+            "movdqu xmm3, [{saved} + 48]",
+            saved = in(reg) saved.as_mut_ptr(),
+            src = inout(reg) src => _,
+            dst = inout(reg) dst => _,
+            blocks = inout(reg) blocks => _,
+            options(nostack)
+        );
+    }
+    let done = blocks * SSE2_BLOCK;
+    core::ptr::copy(src.add(done), dst.add(done), len - done);
+}
+
+unsafe fn nontemporal(dst: *mut u8, src: *const u8, len: usize) {
+    // `movnti` stores whole aligned words
+    let head = dst.align_offset(NONTEMPORAL_WORD).min(len);
+    core::ptr::copy(src, dst, head);
+    let words = (len - head) / NONTEMPORAL_WORD;
+    if words != 0 {
+        asm!(
+            "2:",
+            "mov {tmp}, [{src}]",
+            "movnti [{dst}], {tmp}",
+            "add {src}, 8",
+            "add {dst}, 8",
+            "dec {words}",
+            "jnz 2b",
+            // Non-temporal stores are weakly ordered
+            "sfence",
+            tmp = out(reg) _,
+            src = inout(reg) src.add(head) => _,
+            dst = inout(reg) dst.add(head) => _,
+            words = inout(reg) words => _,
+            options(nostack)
+        );
+    }
+    let done = head + words * NONTEMPORAL_WORD;
+    core::ptr::copy(src.add(done), dst.add(done), len - done);
+}
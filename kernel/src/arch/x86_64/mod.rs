@@ -1,32 +1,42 @@
 pub mod segment;
 pub mod syscall;
 
-use spin::Once;
-
 use crate::memory::VirtualAddress;
+pub mod apic;
+pub mod backtrace;
+pub mod cpuid;
+pub mod fpu;
 pub mod gdt;
 pub mod init;
 pub mod interrupts;
 pub mod iobus;
 pub mod main;
+pub mod mem;
+pub mod mwait;
 pub mod paging;
 pub mod pic;
 pub mod scheduler;
+pub mod smap;
 pub mod structures;
+pub mod tsc;
 pub mod tss;
+pub mod unwind;
 
 pub const HEAP_ADDRESS: u64 = 0x0000900000;
 pub const HEAP_LENGTH: usize = bks::PAGE_SIZE as usize;
+/// The scratch window `memaccess` maps physical memory into, in the higher half
+pub const PHYS_WINDOW_ADDRESS: u64 = 0xffff_ff00_0000_0000;
 
 pub const USERSPACE_STACK_SIZE: u64 = 0x64000;
 pub const USERSPACE_ADDRESS_MASK_SHIFT: u64 = 47; // If we ever do lvl 5 paging: 56
+/// User space is the lower half of the address space, every address from here on belongs to
+/// the kernel
+pub const USER_END: u64 = 1 << USERSPACE_ADDRESS_MASK_SHIFT;
 
 pub const USER_STACK_TOP: VirtualAddress = VirtualAddress::const_new_unchecked(0x7fffffffe000);
 pub const USER_STACK_BOTTOM: VirtualAddress =
     VirtualAddress::const_new_unchecked(USER_STACK_TOP.as_u64() - USERSPACE_STACK_SIZE);
 
 pub fn userspace_get_last_address() -> u64 {
-    static ADDRESS: Once<u64> = Once::new();
-
-    *ADDRESS.call_once(|| 1 << USERSPACE_ADDRESS_MASK_SHIFT)
+    USER_END
 }
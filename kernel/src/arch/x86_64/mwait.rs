@@ -0,0 +1,38 @@
+//! # MONITOR/MWAIT
+//! `monitor` arms the address monitor on a cache line, `mwait` then waits until the line is
+//! written or an interrupt arrives. An idle CPU waiting like this is woken by a plain store from
+//! another CPU, without an IPI.
+use core::arch::x86_64::__cpuid;
+
+/// CPUID.01H:ECX.MONITOR
+const CPUID_MONITOR: u32 = 1 << 3;
+/// The MWAIT hint for C1, the shallowest state
+pub const HINT_C1: u32 = 0;
+
+/// # Is Supported
+/// Whether the CPU has MONITOR and MWAIT
+pub fn is_supported() -> bool {
+    unsafe { __cpuid(1) }.ecx & CPUID_MONITOR != 0
+}
+
+/// # Monitor
+/// Arms the address monitor on the cache line of `addr`
+///
+/// ## Safety
+/// The CPU has to support MONITOR, see `is_supported()`
+#[inline]
+pub unsafe fn monitor(addr: *const u8) {
+    core::arch::asm!("monitor", in("rax") addr, in("ecx") 0, in("edx") 0, options(nostack));
+}
+
+/// # Enable Interrupts And Wait
+/// Enables interrupts and waits in the C-state of `hint` until the monitored line is written
+/// or an interrupt arrives. Like `sti; hlt`, an interrupt that is already pending ends the
+/// wait instead of being taken before it.
+///
+/// ## Safety
+/// The CPU has to support MWAIT and `monitor()` has to have been called
+#[inline]
+pub unsafe fn enable_interrupts_and_wait(hint: u32) {
+    core::arch::asm!("sti", "mwait", in("eax") hint, in("ecx") 0, options(nostack));
+}
@@ -1,2 +1,7 @@
+pub mod mtrr;
 pub mod page_frame_allocator;
 pub mod page_table_manager;
+pub mod pat;
+pub mod tlb;
+
+pub use page_table_manager::{active_pml4, dump, walk, Mapping, WalkStep};
@@ -0,0 +1,153 @@
+//! # MTRR
+//! Reading the memory type range registers the firmware set up (Intel SDM Vol. 3, 11.11).
+//!
+//! The MTRRs give physical memory a type, which the type of a mapping is combined with (see
+//! `pat::MemoryType::effective`). They are only read, the firmware settings are kept. The
+//! fixed range MTRRs for the first megabyte are ignored.
+use super::pat::CacheType;
+use crate::arch::cpuid::physical_address_bits;
+use crate::iobus::msr::{read_msr, MsrRegister};
+use crate::{debug, info};
+
+/// CPUID.01H:EDX bit 12
+const CPUID_MTRR: u32 = 1 << 12;
+/// The number of variable ranges, in MTRRcap
+const VARIABLE_COUNT_MASK: u64 = 0xFF;
+const DEFAULT_TYPE_MASK: u64 = 0xFF;
+/// Enables the fixed range MTRRs
+const FIXED_ENABLE: u64 = 1 << 10;
+/// Enables all MTRRs, without it all memory is uncacheable
+const ENABLE: u64 = 1 << 11;
+const TYPE_MASK: u64 = 0xFF;
+/// Set in the mask of variable ranges that are used
+const RANGE_VALID: u64 = 1 << 11;
+
+/// # Variable Range
+/// The addresses `addr` with `addr & mask == base & mask` have the memory type `ty`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VariableRange {
+    pub base: u64,
+    pub mask: u64,
+    pub ty: u8,
+}
+
+impl VariableRange {
+    pub fn contains(&self, addr: u64) -> bool {
+        addr & self.mask == self.base & self.mask
+    }
+
+    /// The size of the range, assuming its mask is contiguous
+    pub fn size(&self) -> u64 {
+        1 << self.mask.trailing_zeros()
+    }
+}
+
+/// # MTRRs
+/// The settings of the MTRRs of the calling CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mtrrs {
+    pub enabled: bool,
+    pub fixed_enabled: bool,
+    pub default_type: u8,
+    /// The number of variable ranges, not all of them have to be used
+    pub variable_count: u64,
+}
+
+impl Mtrrs {
+    /// # Read
+    /// Reads the settings of the calling CPU, `None` if it has no MTRRs
+    pub fn read() -> Option<Self> {
+        let features = unsafe { core::arch::x86_64::__cpuid(1) }.edx;
+        if features & CPUID_MTRR == 0 {
+            return None;
+        }
+        let default = read_msr(MsrRegister::MtrrDefaultType);
+        Some(Self {
+            enabled: default & ENABLE != 0,
+            fixed_enabled: default & FIXED_ENABLE != 0,
+            default_type: (default & DEFAULT_TYPE_MASK) as u8,
+            variable_count: read_msr(MsrRegister::MtrrCapabilities) & VARIABLE_COUNT_MASK,
+        })
+    }
+
+    /// # Ranges
+    /// Reads the variable ranges that are used
+    pub fn ranges(&self) -> impl Iterator<Item = VariableRange> {
+        let address_mask = (1u64 << physical_address_bits()) - 1;
+        (0..self.variable_count).filter_map(move |idx| {
+            let base = read_msr(MsrRegister::MtrrPhysBase0 + 2 * idx);
+            let mask = read_msr(MsrRegister::MtrrPhysMask0 + 2 * idx);
+            if mask & RANGE_VALID == 0 {
+                return None;
+            }
+            Some(VariableRange {
+                base: base & address_mask & !0xFFF,
+                mask: mask & address_mask & !0xFFF,
+                ty: (base & TYPE_MASK) as u8,
+            })
+        })
+    }
+
+    /// # Memory Type
+    /// The type the MTRRs give the memory at `addr`
+    pub fn memory_type(&self, addr: u64) -> u8 {
+        if !self.enabled {
+            return CacheType::Uncacheable;
+        }
+        self.ranges()
+            .filter(|range| range.contains(addr))
+            .map(|range| range.ty)
+            .reduce(overlap)
+            .unwrap_or(self.default_type)
+    }
+}
+
+/// # Overlap
+/// The type of memory that two variable ranges of type `a` and `b` cover
+/// (Intel SDM Vol. 3, 11.11.4.1)
+pub fn overlap(a: u8, b: u8) -> u8 {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (CacheType::Uncacheable, _) | (_, CacheType::Uncacheable) => CacheType::Uncacheable,
+        (CacheType::WriteThrough, CacheType::WriteBack)
+        | (CacheType::WriteBack, CacheType::WriteThrough) => CacheType::WriteThrough,
+        // Any other overlap is undefined, so nothing is cached
+        _ => CacheType::Uncacheable,
+    }
+}
+
+/// # Memory Type
+/// The type the MTRRs of the calling CPU give the memory at `addr`, write-back if the CPU has
+/// no MTRRs
+pub fn memory_type(addr: u64) -> u8 {
+    match Mtrrs::read() {
+        Some(mtrrs) => mtrrs.memory_type(addr),
+        None => CacheType::WriteBack,
+    }
+}
+
+/// # Log MTRRs
+/// Logs the default type and the variable ranges
+pub fn log_mtrrs() {
+    let mtrrs = match Mtrrs::read() {
+        Some(mtrrs) => mtrrs,
+        None => {
+            info!("mtrr: Not supported");
+            return;
+        }
+    };
+    info!(
+        "mtrr: {}, default {}, fixed ranges {}",
+        if mtrrs.enabled { "enabled" } else { "disabled" },
+        CacheType::abbreviation(mtrrs.default_type),
+        if mtrrs.fixed_enabled { "on" } else { "off" },
+    );
+    for range in mtrrs.ranges() {
+        debug!(
+            "mtrr: {:#014x}-{:#014x} {}",
+            range.base,
+            range.base + range.size() - 1,
+            CacheType::abbreviation(range.ty),
+        );
+    }
+}
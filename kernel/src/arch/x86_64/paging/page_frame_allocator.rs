@@ -1,13 +1,15 @@
 use core::mem::MaybeUninit;
 
-use bks::{EfiMemoryDescriptor, MemoryType, PAGE_SIZE};
+use bks::PAGE_SIZE;
 
-use crate::arch::init::memory::_KERNEL_OFFSET;
-use crate::math::is_aligned;
-use crate::{debug, kprintln};
+use crate::math::{is_aligned, ByteSize};
+use crate::{counter, debug, warn};
 
 use crate::memory::bitmap::Bitmap;
-use spin::Mutex;
+use crate::memory::frame_info::{self, FrameFlags};
+use crate::memory::map::{MemoryKind, MemoryMap};
+use crate::memory::{pressure, reserved, PhysicalAddress};
+use crate::scheduler::spin::Mutex;
 
 pub static PAGE_FRAME_ALLOCATOR: Mutex<MaybeUninit<PageFrameAllocator>> =
     Mutex::new(MaybeUninit::uninit());
@@ -15,94 +17,122 @@ static mut IS_PAGE_FRAME_ALLOCATOR_INITIALIZED: bool = false;
 pub static mut REJECTS: u64 = 0;
 pub static mut ACCEPTS: u64 = 0;
 
-pub struct PageFrameAllocator<'a> {
-    base: u64,
-    map: &'a mut [EfiMemoryDescriptor],
-    entries: usize,
-    entry_size: usize,
-    size: usize,
+counter!(pub FRAMES_ALLOCATED = "mm.frames_allocated");
+counter!(pub FRAMES_FREED = "mm.frames_freed");
+
+pub struct PageFrameAllocator {
     bitmap: Bitmap,
     free: i64, // FIXME: During Initialization, the value may drop below zero
     reserved: i64,
     used: i64,
     last_bmap_index: u64,
-    first_conventional_mem: u64,
-    last_conventional_mem: u64,
+    /// The first address after the highest region of RAM
+    memory_top: u64,
 }
 
-impl<'a> PageFrameAllocator<'a> {
-    pub fn new(base: *mut u8, entries: usize, size: usize, entry_size: usize) -> Self {
+impl PageFrameAllocator {
+    pub fn new() -> Self {
         if unsafe { IS_PAGE_FRAME_ALLOCATOR_INITIALIZED } {
             panic!("Tried to create a PageFrameAllocator twice in the same session.")
         }
 
-        let map =
-            unsafe { core::slice::from_raw_parts_mut(base as *mut EfiMemoryDescriptor, entries) };
         let bitmap = Bitmap::new(core::ptr::null_mut(), 0);
 
         unsafe {
             IS_PAGE_FRAME_ALLOCATOR_INITIALIZED = true;
         }
         Self {
-            base: base as u64,
-            map,
-            entries,
-            size,
-            entry_size,
             bitmap,
             free: 0,
             reserved: 0,
             used: 0,
             last_bmap_index: 0,
-            first_conventional_mem: 0,
-            last_conventional_mem: 0,
+            memory_top: 0,
         }
     }
 
-    pub fn read_memory_map(&mut self) {
-        let mut current_largest_free_segment: u64 = 0;
-        let mut current_largest_free_segment_size = 0;
-        for ent in self.map.iter() {
-            match ent.ty {
-                MemoryType::ConventialMemory => {
-                    if ent.page_count * PAGE_SIZE > current_largest_free_segment_size {
-                        current_largest_free_segment = ent.phys_base;
-                        current_largest_free_segment_size = ent.page_count * PAGE_SIZE;
-                    }
-                }
-                _ => {}
+    /// # Read Memory Map
+    /// Places the bitmap and the frame metadata (see `frame_info`) into the largest usable
+    /// region and reserves everything that is not usable, including holes in the map
+    ///
+    /// ## Panics
+    /// If the largest usable region cannot hold both
+    pub fn read_memory_map(&mut self, map: MemoryMap) {
+        let mut largest_free_segment: u64 = 0;
+        let mut largest_free_segment_size = 0;
+        for region in map.clone() {
+            if region.kind == MemoryKind::Usable && region.size() > largest_free_segment_size {
+                largest_free_segment = region.start.as_u64();
+                largest_free_segment_size = region.size();
             }
         }
 
+        self.memory_top = map.top_of_ram();
+        // Nothing beyond the physical address width can be reached
+        let limit = PhysicalAddress::max().as_u64() + 1;
+        if self.memory_top > limit {
+            warn!(
+                "RAM ends at {:#x}, beyond the physical address width",
+                self.memory_top
+            );
+            self.memory_top = limit;
+        }
         let mem_sz = self.total_memory();
-        debug!("{}mb", mem_sz / 1024 / 1024 / 1024);
         self.free = mem_sz as i64;
         // One for each page
         let bitmap_size = mem_sz / PAGE_SIZE / 8 + 1;
-        debug!("{}", bitmap_size);
+        debug!(
+            "Bitmap: {} at {} for {}",
+            ByteSize(bitmap_size),
+            PhysicalAddress::new(largest_free_segment),
+            ByteSize(mem_sz)
+        );
 
         // Initialize Bitmap
-        self.initialize_bitmap(bitmap_size as usize, current_largest_free_segment);
-        self.lock_pages(self.bitmap.base, self.bitmap.size / PAGE_SIZE as usize + 1);
-
-        let mut first_conventional_mem = 0;
-        let mut last_conventional_mem = 0;
-        for i in 0..self.entries {
-            let ent = self.map[i];
-            if ent.ty != MemoryType::ConventialMemory {
-                if ent.phys_base < first_conventional_mem {
-                    first_conventional_mem = ent.phys_base
-                } else if ent.phys_base > last_conventional_mem {
-                    last_conventional_mem = ent.phys_base
-                };
-                self.reserve_pages(ent.phys_base, ent.page_count as usize);
-            } else if ent.phys_base <= _KERNEL_OFFSET {
-                self.reserve_pages(ent.phys_base, ent.page_count as usize);
+        self.initialize_bitmap(bitmap_size as usize, largest_free_segment);
+        let bitmap_pages = self.bitmap.size / PAGE_SIZE as usize + 1;
+        // The metadata follows the bitmap, it has to be there before anything is locked
+        let frame_count = (mem_sz / PAGE_SIZE) as usize;
+        let info_pages = frame_info::size_for(frame_count) / PAGE_SIZE as usize + 1;
+        if ((bitmap_pages + info_pages) as u64 * PAGE_SIZE) > largest_free_segment_size {
+            panic!(
+                "Out of Memory: No room for the bitmap and the metadata of {} frames",
+                frame_count
+            );
+        }
+        let info_base = self.bitmap.base + bitmap_pages as u64 * PAGE_SIZE;
+        unsafe { frame_info::init(PhysicalAddress::new(info_base), frame_count) };
+        self.lock_pages(self.bitmap.base, bitmap_pages);
+        self.lock_pages(info_base, info_pages);
+
+        let mut last_end = 0;
+        for region in map {
+            let start = region.start.as_u64();
+            if start >= mem_sz {
+                break;
+            }
+            // Holes are not backed by anything we could use
+            if start > last_end {
+                self.reserve_pages(last_end, ((start - last_end) / PAGE_SIZE) as usize);
+            }
+            last_end = region.end().as_u64();
+
+            // Nothing is handed out of the region at address zero, the kernel image is
+            // reserved through `reserved`
+            if region.kind != MemoryKind::Usable || start == 0 {
+                let end = region.end().as_u64().min(mem_sz);
+                self.reserve_pages(start, ((end - start) / PAGE_SIZE) as usize);
             }
         }
-        self.last_conventional_mem = last_conventional_mem;
-        self.first_conventional_mem = first_conventional_mem;
-        debug!("{}", self.free);
+
+        for region in reserved::mark_applied().iter().flatten() {
+            self.reserve_pages(region.start.as_u64(), region.pages() as usize);
+        }
+        debug!(
+            "Frames: {} free, {} reserved",
+            ByteSize(self.free.max(0) as u64),
+            ByteSize(self.reserved.max(0) as u64)
+        );
     }
 
     fn initialize_bitmap(&mut self, bmp_size: usize, addr: u64) {
@@ -122,6 +152,8 @@ impl<'a> PageFrameAllocator<'a> {
             return;
         }
         if self.bitmap.set(idx as usize, false) {
+            Self::mark(addr, FrameFlags::FREE, 0);
+            FRAMES_FREED.increment();
             self.free += PAGE_SIZE as i64;
             self.used -= PAGE_SIZE as i64;
             if self.last_bmap_index > idx {
@@ -145,6 +177,7 @@ impl<'a> PageFrameAllocator<'a> {
         }
 
         if self.bitmap.set(idx as usize, true) {
+            Self::mark(addr, FrameFlags::empty(), 1);
             self.free -= PAGE_SIZE as i64;
             self.used += PAGE_SIZE as i64;
         } else {
@@ -169,6 +202,7 @@ impl<'a> PageFrameAllocator<'a> {
             return;
         }
         if self.bitmap.set(idx as usize, true) {
+            Self::mark(addr, FrameFlags::RESERVED, 0);
             self.free -= PAGE_SIZE as i64;
             self.reserved += PAGE_SIZE as i64;
         }
@@ -182,11 +216,12 @@ impl<'a> PageFrameAllocator<'a> {
 
     fn release_page(&mut self, addr: u64) {
         let idx = addr / PAGE_SIZE;
-        // Already allocated
+        // Already free
         if self.bitmap[idx as usize] == false {
             return;
         }
-        if self.bitmap.set(idx as usize, true) {
+        if self.bitmap.set(idx as usize, false) {
+            Self::mark(addr, FrameFlags::FREE, 0);
             self.free += PAGE_SIZE as i64;
             self.reserved -= PAGE_SIZE as i64;
             if self.last_bmap_index > idx {
@@ -201,11 +236,21 @@ impl<'a> PageFrameAllocator<'a> {
         }
     }
 
+    /// Keeps the metadata of the frame at `addr` in step with the bitmap, frames beyond the
+    /// top of RAM have none
+    fn mark(addr: u64, flags: FrameFlags, refcount: u32) {
+        if let Some(info) = frame_info::try_frame_info(addr) {
+            info.reset(flags, refcount);
+        }
+    }
+
     // TODO: Optimize
     pub fn request_page(&mut self) -> u64 {
         while self.last_bmap_index < (self.bitmap.size as u64 * 8 as u64) {
             if self.bitmap[self.last_bmap_index as usize] == false {
                 self.lock_page(self.last_bmap_index * PAGE_SIZE);
+                FRAMES_ALLOCATED.increment();
+                pressure::check(self.free);
                 unsafe {
                     ACCEPTS += 1;
                 }
@@ -229,6 +274,8 @@ impl<'a> PageFrameAllocator<'a> {
             if self.bitmap[temp_index as usize] == false {
                 if is_aligned(temp_index * PAGE_SIZE, align) {
                     self.lock_page(temp_index * PAGE_SIZE);
+                    FRAMES_ALLOCATED.increment();
+                    pressure::check(self.free);
                     unsafe {
                         ACCEPTS += 1;
                     }
@@ -247,6 +294,65 @@ impl<'a> PageFrameAllocator<'a> {
         );
     }
 
+    /// # Request Contiguous Pages
+    /// Allocates `count` physically contiguous pages, the first one aligned to `align` and the
+    /// last one ending at or below `max_addr`.
+    ///
+    /// ## Returns
+    /// - Option<u64> = The address of the first page, `None` if there is no such range
+    pub fn request_contiguous_pages(
+        &mut self,
+        count: usize,
+        align: u64,
+        max_addr: u64,
+    ) -> Option<u64> {
+        let min_addr = self.last_bmap_index * PAGE_SIZE;
+        self.request_contiguous_pages_from(count, align, min_addr, max_addr)
+    }
+
+    /// # Request Contiguous Pages Below
+    /// Like `request_contiguous_pages()`, but the search starts at the bottom of memory rather
+    /// than where the last allocation left off, which may already be beyond `max_addr`
+    pub fn request_contiguous_pages_below(
+        &mut self,
+        count: usize,
+        align: u64,
+        max_addr: u64,
+    ) -> Option<u64> {
+        self.request_contiguous_pages_from(count, align, 0, max_addr)
+    }
+
+    /// # Request Contiguous Pages From
+    /// Like `request_contiguous_pages()`, but the range starts at or above `min_addr`
+    pub fn request_contiguous_pages_from(
+        &mut self,
+        count: usize,
+        align: u64,
+        min_addr: u64,
+        max_addr: u64,
+    ) -> Option<u64> {
+        let max_addr = max_addr.min(PhysicalAddress::max().as_u64());
+        let page_count = (self.bitmap.size as u64 * 8).min(max_addr.saturating_add(1) / PAGE_SIZE);
+        let mut start = (min_addr + PAGE_SIZE - 1) / PAGE_SIZE;
+        'search: while start + count as u64 <= page_count {
+            if !is_aligned(start * PAGE_SIZE, align) {
+                start += 1;
+                continue;
+            }
+            for idx in start..start + count as u64 {
+                if self.bitmap[idx as usize] {
+                    start = idx + 1;
+                    continue 'search;
+                }
+            }
+            self.lock_pages(start * PAGE_SIZE, count);
+            FRAMES_ALLOCATED.add(count as u64);
+            pressure::check(self.free);
+            return Some(start * PAGE_SIZE);
+        }
+        None
+    }
+
     pub fn allocate_from_addr_to_count_unchecked(&mut self, addr: u64, count: usize) -> bool {
         return if self.lock_pages(addr, count) == true {
             true
@@ -255,25 +361,17 @@ impl<'a> PageFrameAllocator<'a> {
         };
     }
 
+    /// # Total Memory
+    /// The size of the physical address range managed by the allocator, i.e. the first address
+    /// after the highest region of RAM
     pub fn total_memory(&self) -> u64 {
-        unsafe {
-            static mut MEM_SZ_BYTES: u64 = 0;
-            // If calculated before, just return it
-            if MEM_SZ_BYTES > 0 {
-                return MEM_SZ_BYTES;
-            }
-            debug!("ENTries: {} : LENII {}", self.entries, self.map.len());
-            for i in self.map.iter() {
-                MEM_SZ_BYTES += i.page_count * PAGE_SIZE;
-            }
-            kprintln!(
-                "MEM_SZ_BYTES: {} :: MEM_SZ_KILOBYTES: {} :: MEM_SZ_MEGABYTES: {}",
-                MEM_SZ_BYTES,
-                MEM_SZ_BYTES / 1024,
-                MEM_SZ_BYTES / 1024 / 1024
-            );
-            MEM_SZ_BYTES
-        }
+        self.memory_top
+    }
+
+    /// # Is Free
+    /// Whether the frame at `addr` could be handed out
+    pub fn is_free(&self, addr: u64) -> bool {
+        addr < self.memory_top && !self.bitmap[(addr / PAGE_SIZE) as usize]
     }
 
     pub fn get_free_memory(&self) -> i64 {
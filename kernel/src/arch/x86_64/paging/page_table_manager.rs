@@ -1,10 +1,10 @@
 use core::{
     mem::MaybeUninit,
-    ops::{Index, IndexMut},
+    ops::{Index, IndexMut, Range},
 };
 
-use spin::Mutex;
-
+use crate::memory::{PhysicalAddress, VirtualAddress};
+use crate::scheduler::spin::Mutex;
 use crate::{address_of, kprintln, memory::paging::page_frame_allocator::request_page};
 
 pub static PAGE_TABLE_MANAGER: Mutex<MaybeUninit<PageTableManager>> =
@@ -31,6 +31,12 @@ impl PageDescriptorEntry {
         let _ = self.entry = 0;
     }
 
+    /// The whole entry, address and flags
+    #[inline]
+    pub const fn bits(&self) -> u64 {
+        self.entry
+    }
+
     #[inline]
     pub const fn flags(&self) -> PageTableFlag {
         PageTableFlag::from_bits_truncate(self.entry)
@@ -79,6 +85,9 @@ bitflags::bitflags! {
         const ACCESSED = 1 << 5;
         const DIRTY = 1 << 6;
         const LARGE_PAGE = 1 << 7;
+        /// Selects the upper half of the PAT in the entry of a 4 KiB page, where bit 7 is not
+        /// needed for `LARGE_PAGE` (see `pat::MemoryType`)
+        const PAT = 1 << 7;
         const GLOBAL = 1 << 8;
         const BIT_9 = 1 << 9;
         const BIT_10 = 1 << 10;
@@ -99,6 +108,19 @@ bitflags::bitflags! {
 }
 
 const ENTRIES: usize = 512;
+/// Bits 12-51 of an entry hold the physical address of the page or the next table
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+/// The PAT bit of an entry that maps a 2 MiB or 1 GiB page
+const LARGE_PAGE_PAT: u64 = 1 << 12;
+/// The size of the memory an entry maps on every level, from the PML4 down
+pub const LEVEL_SIZES: [u64; 4] = [0x80_0000_0000, 0x4000_0000, 0x20_0000, 0x1000];
+pub const LEVEL_NAMES: [&str; 4] = ["PML4", "PDPT", "PD", "PT"];
+/// The flags a table entry keeps when it points to a new table, the rest applies to pages
+const TABLE_FLAGS: PageTableFlag = PageTableFlag::from_bits_truncate(
+    PageTableFlag::PRESENT.bits()
+        | PageTableFlag::READ_WRITE.bits()
+        | PageTableFlag::USER_ACCESSIBLE.bits(),
+);
 #[repr(align(0x1000))]
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -240,6 +262,334 @@ impl<'table> PageTableManager<'table> {
 
         //pt.entries[indexer.p_idx] = page_pde; // FIXME: This causes a Page Fault
     }
+
+    /// # Map Page
+    /// Maps the 4 KiB page at `virtual_mem` to `physical_mem` with `flags`, which should
+    /// include `PRESENT`. Large pages in the way are split, keeping their mappings. The tables
+    /// on the way to a `USER_ACCESSIBLE` page become user accessible as well. The caller has to
+    /// flush the TLB.
+    ///
+    /// ## Panics
+    /// If `physical_mem` is beyond the physical address width, which the entry could not hold
+    pub fn map_page(&mut self, virtual_mem: u64, physical_mem: u64, flags: PageTableFlag) {
+        assert!(
+            physical_mem <= PhysicalAddress::max().as_u64(),
+            "Mapping {:#x}, which is beyond the physical address width",
+            physical_mem
+        );
+        let indexer = PageMapIndexer::new(virtual_mem);
+        let user = flags & PageTableFlag::USER_ACCESSIBLE;
+        let pdp = next_table(&mut self.pml4[indexer.pdp_idx], None, user);
+        let pd = next_table(&mut pdp[indexer.pd_idx], Some(0x4000_0000), user);
+        let pt = next_table(&mut pd[indexer.pt_idx], Some(0x20_0000), user);
+        pt[indexer.p_idx] = PageDescriptorEntry {
+            entry: (physical_mem & ADDRESS_MASK) | flags.bits(),
+        };
+    }
+
+    /// # Unmap Page
+    /// Removes the mapping of the 4 KiB page at `virtual_mem`, if there is one. Large pages are
+    /// left alone, and so are the tables on the way. The caller has to shoot down the TLB
+    /// before the memory is reused.
+    pub fn unmap_page(&mut self, virtual_mem: u64) {
+        let indexer = PageMapIndexer::new(virtual_mem);
+        let mut table = &mut *self.pml4;
+        for idx in [indexer.pdp_idx, indexer.pd_idx, indexer.pt_idx] {
+            let flags = table[idx].flags();
+            if !flags.contains(PageTableFlag::PRESENT) || flags.contains(PageTableFlag::LARGE_PAGE)
+            {
+                return;
+            }
+            table = addr_to_page_table(table[idx].entry & ADDRESS_MASK);
+        }
+        table[indexer.p_idx].set_unused();
+    }
+}
+
+/// # Leaf
+/// The entry that maps `addr` in the active page tables, the size of the page it maps and
+/// whether every table on the way to it is user accessible. `None` if `addr` is not mapped.
+fn leaf(addr: u64) -> Option<(PageDescriptorEntry, u64, bool)> {
+    let indexer = PageMapIndexer::new(addr);
+    let mut table = addr_to_page_table(active_pml4());
+    let mut user = true;
+    for (level, idx) in [
+        indexer.pdp_idx,
+        indexer.pd_idx,
+        indexer.pt_idx,
+        indexer.p_idx,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let flags = table[idx].flags();
+        if !flags.contains(PageTableFlag::PRESENT) {
+            return None;
+        }
+        user &= flags.contains(PageTableFlag::USER_ACCESSIBLE);
+        // The PML4 has no large pages, the page table only has pages
+        if level == 3 || (level > 0 && flags.contains(PageTableFlag::LARGE_PAGE)) {
+            return Some((table[idx], LEVEL_SIZES[level], user));
+        }
+        table = addr_to_page_table(table[idx].entry & ADDRESS_MASK);
+    }
+    None
+}
+
+/// # Effective Flags
+/// The flags of the page `addr` is in, according to the active page tables. A page is only
+/// user accessible if every table on the way to it is. `None` if `addr` is not mapped.
+pub fn effective_flags(addr: u64) -> Option<PageTableFlag> {
+    leaf(addr).map(|(entry, _, user)| {
+        let mut flags = entry.flags();
+        flags.set(PageTableFlag::USER_ACCESSIBLE, user);
+        flags
+    })
+}
+
+/// # Translate
+/// The physical address behind `addr` according to the active page tables, `None` if `addr`
+/// is not mapped
+pub fn translate(addr: u64) -> Option<u64> {
+    leaf(addr).map(|(entry, size, _)| leaf_base(entry, size) + (addr & (size - 1)))
+}
+
+/// # Active PML4
+/// The physical address of the PML4 the calling CPU translates with
+pub fn active_pml4() -> u64 {
+    let cr3: u64;
+    unsafe {
+        core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+    }
+    cr3 & ADDRESS_MASK
+}
+
+/// # Mapping
+/// Virtual memory that is mapped with the same flags and memory type throughout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub start: u64,
+    pub len: u64,
+    /// The physical address of the start, `None` if the memory behind the range is not
+    /// contiguous
+    pub phys: Option<u64>,
+    /// `PRESENT`, `READ_WRITE`, `USER_ACCESSIBLE`, `GLOBAL` and `NO_EXECUTE` as they apply to
+    /// the pages, so taking the tables on the way to them into account
+    pub flags: PageTableFlag,
+    /// The entry of the PAT that selects the memory type, see `pat`
+    pub pat_index: u8,
+}
+
+impl Mapping {
+    pub fn end(&self) -> u64 {
+        self.start + self.len
+    }
+
+    /// # Merge
+    /// Extends the mapping by `next` if it directly follows it with the same flags and type
+    ///
+    /// ## Returns
+    /// - bool = Whether `next` was merged
+    fn merge(&mut self, next: &Mapping) -> bool {
+        if self.end() != next.start || self.flags != next.flags || self.pat_index != next.pat_index
+        {
+            return false;
+        }
+        if self.phys.map(|phys| phys + self.len) != next.phys {
+            self.phys = None;
+        }
+        self.len += next.len;
+        true
+    }
+}
+
+/// # Walk Step
+/// The entry a translation used on one level
+#[derive(Debug, Clone, Copy)]
+pub struct WalkStep {
+    /// 0 for the PML4, 3 for the page table
+    pub level: usize,
+    /// The physical address of the table
+    pub table: u64,
+    pub index: usize,
+    pub entry: PageDescriptorEntry,
+}
+
+impl WalkStep {
+    /// Whether the entry maps memory instead of pointing to the next table
+    pub fn is_leaf(&self) -> bool {
+        self.level == 3
+            || (self.level > 0 && self.entry.flags().contains(PageTableFlag::LARGE_PAGE))
+    }
+}
+
+/// # Walk
+/// Translates `addr` with the tables of `pml4` and passes the entry used on every level to `f`.
+/// If the translation fails, the last entry passed is the one that is not present.
+///
+/// ## Returns
+/// - u64 = The physical address behind `addr`
+/// - None = `addr` is not canonical, which fails before the PML4, or is not mapped
+pub fn walk(pml4: u64, addr: u64, mut f: impl FnMut(&WalkStep)) -> Option<u64> {
+    if VirtualAddress::truncate(addr).as_u64() != addr {
+        return None;
+    }
+    let indexer = PageMapIndexer::new(addr);
+    let mut table = pml4;
+    for (level, index) in [
+        indexer.pdp_idx,
+        indexer.pd_idx,
+        indexer.pt_idx,
+        indexer.p_idx,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let step = WalkStep {
+            level,
+            table,
+            index,
+            entry: addr_to_page_table(table)[index],
+        };
+        f(&step);
+        if !step.entry.flags().contains(PageTableFlag::PRESENT) {
+            return None;
+        }
+        if step.is_leaf() {
+            let size = LEVEL_SIZES[level];
+            return Some(leaf_base(step.entry, size) + (addr & (size - 1)));
+        }
+        table = step.entry.entry & ADDRESS_MASK;
+    }
+    None
+}
+
+/// # Dump
+/// Passes everything `pml4` maps in `range` to `f`, ordered by address. Neighbouring pages with
+/// the same flags and memory type are merged into one mapping, whether the memory behind them
+/// is contiguous or not. Large pages only count once.
+pub fn dump(pml4: u64, range: Range<u64>, mut f: impl FnMut(&Mapping)) {
+    let mut current: Option<Mapping> = None;
+    let rights = PageTableFlag::READ_WRITE | PageTableFlag::USER_ACCESSIBLE;
+    dump_table(pml4, 0, 0, rights, &range, &mut |page| {
+        if !current
+            .as_mut()
+            .map_or(false, |mapping| mapping.merge(&page))
+        {
+            if let Some(mapping) = current.replace(page) {
+                f(&mapping);
+            }
+        }
+    });
+    if let Some(mapping) = current {
+        f(&mapping);
+    }
+}
+
+/// # Dump Table
+/// Passes every page the table at `table` on `level` maps in `range` to `f`. `base` is the
+/// address the table starts to map at and `rights` are the rights the tables above allow.
+fn dump_table(
+    table: u64,
+    level: usize,
+    base: u64,
+    rights: PageTableFlag,
+    range: &Range<u64>,
+    f: &mut impl FnMut(Mapping),
+) {
+    let size = LEVEL_SIZES[level];
+    for (idx, entry) in addr_to_page_table(table).entries.iter().enumerate() {
+        let start = VirtualAddress::truncate(base + idx as u64 * size).as_u64();
+        let last = start + (size - 1);
+        let flags = entry.flags();
+        if last < range.start || start >= range.end || !flags.contains(PageTableFlag::PRESENT) {
+            continue;
+        }
+        // Writes and user accesses need every level to allow them, one level forbids execution
+        let mut rights = rights & (flags | PageTableFlag::NO_EXECUTE);
+        rights |= flags & PageTableFlag::NO_EXECUTE;
+        let step = WalkStep {
+            level,
+            table,
+            index: idx,
+            entry: *entry,
+        };
+        if !step.is_leaf() {
+            dump_table(
+                entry.entry & ADDRESS_MASK,
+                level + 1,
+                start,
+                rights,
+                range,
+                f,
+            );
+            continue;
+        }
+        // Only the part in `range`
+        let from = start.max(range.start);
+        let to = last.min(range.end - 1);
+        let pat = match size {
+            0x1000 => entry.entry & PageTableFlag::PAT.bits() != 0,
+            _ => entry.entry & LARGE_PAGE_PAT != 0,
+        };
+        f(Mapping {
+            start: from,
+            len: to - from + 1,
+            phys: Some(leaf_base(*entry, size) + (from - start)),
+            flags: rights | (flags & (PageTableFlag::PRESENT | PageTableFlag::GLOBAL)),
+            pat_index: (pat as u8) << 2
+                | (flags.contains(PageTableFlag::NO_CACHE) as u8) << 1
+                | flags.contains(PageTableFlag::WRITE_THROUGH) as u8,
+        });
+    }
+}
+
+/// The physical address of the page of `size` bytes `entry` maps
+fn leaf_base(entry: PageDescriptorEntry, size: u64) -> u64 {
+    let mut base = entry.entry & ADDRESS_MASK;
+    if size > 0x1000 {
+        base &= !LARGE_PAGE_PAT;
+    }
+    base & !(size - 1)
+}
+
+/// # Next Table
+/// Returns the table `entry` points to, creating it if the entry is unused. If the entry maps
+/// a large page of `large_page_size` bytes, it is replaced by a table of 512 smaller pages
+/// that map the same memory with the same flags. `user` is added to the flags of the entry.
+fn next_table<'retval>(
+    entry: &mut PageDescriptorEntry,
+    large_page_size: Option<u64>,
+    user: PageTableFlag,
+) -> &'retval mut PageTable {
+    let flags = entry.flags();
+    if flags.contains(PageTableFlag::PRESENT) && !flags.contains(PageTableFlag::LARGE_PAGE) {
+        entry.entry |= user.bits();
+        return addr_to_page_table(entry.entry & ADDRESS_MASK);
+    }
+    let table = request_page::<PageTable>();
+    unsafe { memset(address_of!(table), 0, 0x1000) };
+    let mut table_flags = PageTableFlag::PRESENT | PageTableFlag::READ_WRITE;
+    if let (true, Some(size)) = (flags.contains(PageTableFlag::PRESENT), large_page_size) {
+        let base = entry.entry & ADDRESS_MASK & !LARGE_PAGE_PAT;
+        let mut page_flags = entry.entry & !ADDRESS_MASK;
+        // The entries of 4 KiB pages have no large page bit, PAT moves into its place
+        if size == 0x20_0000 {
+            page_flags &= !PageTableFlag::LARGE_PAGE.bits();
+            if entry.entry & LARGE_PAGE_PAT != 0 {
+                page_flags |= PageTableFlag::PAT.bits();
+            }
+        } else {
+            page_flags |= entry.entry & LARGE_PAGE_PAT;
+        }
+        let step = size / ENTRIES as u64;
+        for (idx, page) in table.entries.iter_mut().enumerate() {
+            page.entry = (base + idx as u64 * step) | page_flags;
+        }
+        table_flags = flags & TABLE_FLAGS;
+    }
+    entry.entry = address_of!(table) | (table_flags | user).bits();
+    table
 }
 
 core::arch::global_asm!(include_str!("paging.s"));
@@ -0,0 +1,121 @@
+//! # PAT
+//! Memory types of mappings, selected through the page attribute table (Intel SDM Vol. 3, 11.12).
+//!
+//! The PWT, PCD and PAT bits of a page table entry index one of the eight entries of the table.
+//! The first four entries keep their power-on types, so mappings that only use PWT and PCD mean
+//! the same with and without our table. Entry 4 is changed to write-combining.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::page_table_manager::PageTableFlag;
+use super::tlb;
+use crate::iobus::msr::{write_msr, MsrRegister};
+use crate::{debug, warn};
+
+enumtastic::const_enum! {
+    /// The encoding of memory types shared by the PAT and the MTRRs
+    pub enum CacheType: u8 => {
+        Uncacheable = 0,
+        WriteCombining = 1,
+        WriteThrough = 4,
+        WriteProtect = 5,
+        WriteBack = 6,
+        /// Uncacheable, unless an MTRR makes the memory write-combining. Only valid in the PAT.
+        UncacheableMinus = 7,
+    }
+
+    impl {
+        pub fn abbreviation(ty: u8) -> &'static str {
+            match ty {
+                Uncacheable => "UC",
+                WriteCombining => "WC",
+                WriteThrough => "WT",
+                WriteProtect => "WP",
+                WriteBack => "WB",
+                UncacheableMinus => "UC-",
+                _ => "??",
+            }
+        }
+    }
+}
+
+/// The page attribute table, entry `n` is in byte `n`
+const PAT: [u8; 8] = [
+    CacheType::WriteBack,
+    CacheType::WriteThrough,
+    CacheType::UncacheableMinus,
+    CacheType::Uncacheable,
+    CacheType::WriteCombining,
+    CacheType::WriteThrough,
+    CacheType::UncacheableMinus,
+    CacheType::Uncacheable,
+];
+/// CPUID.01H:EDX bit 16
+const CPUID_PAT: u32 = 1 << 16;
+
+/// Whether our table has been loaded, without it entry 4 is write-back
+static PAT_LOADED: AtomicBool = AtomicBool::new(false);
+
+/// # Memory Type
+/// How accesses to a mapping are cached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    WriteBack,
+    /// Writes are buffered and combined, reads are not cached. For framebuffers.
+    WriteCombining,
+    /// For device registers
+    Uncacheable,
+    WriteThrough,
+}
+
+impl MemoryType {
+    /// # Flags
+    /// The PWT, PCD and PAT bits of a page table entry of a 4 KiB page with this type. Without
+    /// our table, write-combining falls back to uncacheable.
+    pub fn flags(self) -> PageTableFlag {
+        match self {
+            MemoryType::WriteBack => PageTableFlag::empty(),
+            MemoryType::WriteThrough => PageTableFlag::WRITE_THROUGH,
+            MemoryType::Uncacheable => PageTableFlag::WRITE_THROUGH | PageTableFlag::NO_CACHE,
+            MemoryType::WriteCombining if PAT_LOADED.load(Ordering::Relaxed) => PageTableFlag::PAT,
+            MemoryType::WriteCombining => MemoryType::Uncacheable.flags(),
+        }
+    }
+
+    pub fn cache_type(self) -> u8 {
+        match self {
+            MemoryType::WriteBack => CacheType::WriteBack,
+            MemoryType::WriteCombining => CacheType::WriteCombining,
+            MemoryType::Uncacheable => CacheType::Uncacheable,
+            MemoryType::WriteThrough => CacheType::WriteThrough,
+        }
+    }
+
+    /// # Effective
+    /// The type accesses actually get if the MTRRs say `mtrr` for the memory
+    /// (Intel SDM Vol. 3, Table 11-7)
+    pub fn effective(self, mtrr: u8) -> u8 {
+        match (self, mtrr) {
+            (MemoryType::Uncacheable, _) => CacheType::Uncacheable,
+            (MemoryType::WriteCombining, _) => CacheType::WriteCombining,
+            (MemoryType::WriteThrough, CacheType::WriteBack) => CacheType::WriteThrough,
+            (MemoryType::WriteThrough, CacheType::WriteCombining) => CacheType::Uncacheable,
+            (MemoryType::WriteThrough, mtrr) => mtrr,
+            (MemoryType::WriteBack, mtrr) => mtrr,
+        }
+    }
+}
+
+/// # Init PAT
+/// Loads our page attribute table. Has to be called once on every CPU, before any mapping is
+/// made write-combining.
+pub fn init_pat() {
+    let features = unsafe { core::arch::x86_64::__cpuid(1) }.edx;
+    if features & CPUID_PAT == 0 {
+        warn!("The CPU has no PAT, write-combining mappings are uncacheable");
+        return;
+    }
+    write_msr(MsrRegister::Pat, u64::from_le_bytes(PAT));
+    tlb::flush_all();
+    PAT_LOADED.store(true, Ordering::Relaxed);
+    debug!("Loaded the PAT");
+}
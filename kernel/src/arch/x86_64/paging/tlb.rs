@@ -0,0 +1,47 @@
+//! # TLB
+//! Invalidating cached translations after page table entries changed.
+//!
+//! Every CPU has its own TLB, so whenever a mapping that other CPUs may use is removed or
+//! restricted, the change has to be shot down on all of them before the memory is reused.
+use crate::memory::VirtualAddress;
+use crate::smp;
+
+/// # Flush Page
+/// Drops the translation of the page containing `addr` from the TLB of the calling CPU
+pub fn flush_page(addr: VirtualAddress) {
+    unsafe {
+        core::arch::asm!("invlpg [{}]", in(reg) addr.as_u64(), options(nostack, preserves_flags));
+    }
+}
+
+/// # Flush All
+/// Drops every non-global translation from the TLB of the calling CPU by reloading CR3
+pub fn flush_all() {
+    unsafe {
+        core::arch::asm!(
+            "mov {0}, cr3",
+            "mov cr3, {0}",
+            out(reg) _,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// # Shootdown Page
+/// Invalidates the page containing `addr` on every online CPU and waits until all of them did.
+/// Has to be called after unmapping or write-protecting a shared kernel mapping.
+///
+/// ## Notes
+/// Queued calls cannot carry an address, so the other CPUs flush their whole TLB
+pub fn shootdown_page(addr: VirtualAddress) {
+    flush_page(addr);
+    if smp::online_count() > 1 {
+        smp::call_all(|_| flush_all()).wait();
+    }
+}
+
+/// # Shootdown All
+/// Flushes the TLB of every online CPU and waits until all of them did
+pub fn shootdown_all() {
+    smp::call_all(|_| flush_all()).wait();
+}
@@ -24,7 +24,8 @@ enumtastic::const_enum! {
 
 enumtastic::const_enum! {
     pub enum PicUtilValue: u8 => {
-        Pic1Mask = 0b11111000,
+        // The PS2 keyboard is unmasked by its driver, once its handler is installed
+        Pic1Mask = 0b11111010,
         Pic2Mask = 0b11101111,
     }
 
@@ -120,3 +121,14 @@ pub fn remap_pic(offset_1: u8, offset_2: u8) {
     outb(PicPort::Pic2Data, chip2);
     io_wait();
 }
+
+/// # Unmask
+/// Lets the interrupts of `irq` (0-15) through
+pub fn unmask(irq: u8) {
+    let (port, line) = match irq {
+        0..=7 => (PicPort::Pic1Data, irq),
+        _ => (PicPort::Pic2Data, irq - 8),
+    };
+    let mask = inb(port);
+    outb(port, mask & !(1 << line));
+}
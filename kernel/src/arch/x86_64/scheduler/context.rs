@@ -0,0 +1,85 @@
+//! # Context
+//! Switching between kernel stacks.
+//!
+//! Only the callee-saved registers and `RFLAGS` are saved: `switch_context` is an ordinary
+//! function call as far as the compiler is concerned, so everything else has already been saved
+//! by the caller.
+use core::arch::global_asm;
+
+global_asm!(
+    "
+.global switch_context
+switch_context:
+    pushfq
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov [rdi], rsp
+    mov rsp, rsi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    popfq
+    ret
+
+.global task_trampoline
+task_trampoline:
+    mov rdi, r12
+    call task_start
+    ud2
+"
+);
+
+extern "C" {
+    /// # Switch Context
+    /// Saves the current stack pointer to `old_rsp` and continues at the one given in `new_rsp`
+    ///
+    /// ## Safety
+    /// `new_rsp` must have been saved by `switch_context` or prepared by `prepare_stack`
+    pub fn switch_context(old_rsp: *mut u64, new_rsp: u64);
+    fn task_trampoline();
+}
+
+/// The number of words `prepare_stack` pushes (7 registers + the return address)
+const INITIAL_FRAME_WORDS: usize = 8;
+/// Interrupts enabled + the always-one reserved bit
+const INITIAL_RFLAGS: u64 = 0x202;
+
+/// # Prepare Stack
+/// Builds a frame on a fresh stack so that the first `switch_context` to it "returns" into
+/// `task_start(arg)`.
+///
+/// ## Returns
+/// - u64 = The stack pointer to switch to
+///
+/// ## Safety
+/// `stack_top` must point to the (exclusive) end of a writable stack
+pub unsafe fn prepare_stack(stack_top: u64, arg: u64) -> u64 {
+    let top = stack_top & !0xf;
+    let frame = (top as *mut u64).sub(INITIAL_FRAME_WORDS);
+    let values: [u64; INITIAL_FRAME_WORDS] = [
+        0,   // r15
+        0,   // r14
+        0,   // r13
+        arg, // r12, handed to task_start by task_trampoline
+        0,   // rbx
+        0,   // rbp
+        INITIAL_RFLAGS,
+        task_trampoline as usize as u64,
+    ];
+    for (idx, value) in values.iter().enumerate() {
+        frame.add(idx).write(*value);
+    }
+    frame as u64
+}
+
+#[no_mangle]
+extern "C" fn task_start(arg: u64) -> ! {
+    crate::scheduler::task_entry(arg)
+}
@@ -1,19 +1,15 @@
-use spin::mutex::Mutex;
-use volatile::Volatile;
-
+use crate::scheduler::spin::Mutex;
 use crate::{
-    arch::interrupts::interrupt_frame::InterruptFrame,
+    arch::interrupts::{interrupt_frame::InterruptFrame, IrqScope},
     arch::pic::{end_main_pic, PicPort},
     iobus::{io_wait, outb},
+    time,
 };
 
-/// Without the Volatile, the compiler *may* optimize sleep into an infinite loop
-pub static TIME_SINCE_BOOT: Mutex<Volatile<f64>> = Mutex::new(Volatile::new_rw(0.0));
-
 pub const PIT_INTERRUPT: u64 = 0x20;
 
 /// How often the PIT Chip oscillates per second
-const BASE_FREQUENCY: u64 = 1193182;
+pub const BASE_FREQUENCY: u64 = 1193182;
 
 pub const DIVISOR_MAX: u16 = 65535;
 // No reason for this, but everything below this is *very* fast
@@ -29,12 +25,7 @@ static DIVISOR: Mutex<u16> = Mutex::new(DIVISOR_MAX);
 /// ## Notes
 /// If milliseconds are desired, `msleep()` should be used, for microseconds `usleep` and if nanoseconds are desired `nanosleep()` should be used
 pub fn sleep(seconds: f64) {
-    let start = TIME_SINCE_BOOT.lock().read();
-    while TIME_SINCE_BOOT.lock().read() < (start + seconds) {
-        unsafe {
-            core::arch::asm!("hlt");
-        }
-    }
+    time::sleep_ms((seconds * 1000.0) as u64)
 }
 
 /// # Millisecond-Sleep
@@ -45,7 +36,7 @@ pub fn sleep(seconds: f64) {
 /// ## Notes
 /// If seconds are desired, `sleep()` should be used, for microseconds `usleep` and if nanoseconds are desired `nanosleep()` should be used
 pub fn msleep(millis: u64) {
-    sleep((millis / 1000) as f64)
+    time::sleep_ms(millis)
 }
 
 /// # Microsecond-Sleep
@@ -108,18 +99,24 @@ pub fn set_frequency(freq: u64) {
 }
 
 /// # Tick
-/// Is called everytime the PIT calls an interrupt (`get_frequency` times per second)
+/// Is called everytime the PIT calls an interrupt (`get_frequency` times per second) and passes
+/// the time between two interrupts on to the clock, see `time::uptime_ns()`
 ///
 /// ## Notes
 /// Do not call manually
 pub fn tick() {
-    let old_value = TIME_SINCE_BOOT.lock().read();
-    TIME_SINCE_BOOT
-        .lock()
-        .write(old_value + (1f64 / get_frequency() as f64));
+    time::tick(1_000_000_000 / get_frequency());
 }
 
-pub extern "x86-interrupt" fn pit_interrupt_handler(_a: InterruptFrame) {
-    tick();
-    end_main_pic();
+pub extern "x86-interrupt" fn pit_interrupt_handler(frame: InterruptFrame) {
+    {
+        let _irq = IrqScope::enter(PIT_INTERRUPT as usize);
+        tick();
+        crate::watchdog::tick(&frame);
+        crate::profile::tick(&frame);
+        crate::scheduler::tick();
+        end_main_pic();
+    }
+    // Once the interrupt is over, the task may be switched away from for a while
+    crate::scheduler::preempt(frame.rflags);
 }
@@ -63,3 +63,15 @@ pub unsafe fn upload_to_fs(seg: Segment) {
 pub unsafe fn upload_to_gs(seg: Segment) {
     asm!("mov {}, gs", in(reg) seg.bits());
 }
+
+/// Loads the TSS `seg` selects into the task register, which marks its descriptor as busy
+pub unsafe fn load_task_register(seg: Segment) {
+    asm!("ltr {:x}", in(reg) seg.bits(), options(nostack, preserves_flags));
+}
+
+/// The selector in the task register of the calling CPU
+pub fn store_task_register() -> Segment {
+    let bits: u16;
+    unsafe { asm!("str {:x}", out(reg) bits, options(nomem, nostack, preserves_flags)) };
+    Segment::raw(bits)
+}
@@ -0,0 +1,105 @@
+//! # SMEP and SMAP
+//! Supervisor mode execution and access prevention: With SMEP the kernel faults when it runs
+//! code of a user page, with SMAP when it reads or writes a user page while RFLAGS.AC is clear.
+//! Only `memory::usermem` sets AC, so it is the only code that can touch user memory.
+//!
+//! CR0.WP is set along with them, so the kernel faults on writes to read-only pages like user
+//! space does. Otherwise a write through `usermem` would land in the shared zero frame.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{cmdline, info, warn};
+
+/// The command line flag that leaves SMEP disabled
+pub const NO_SMEP_OPTION: &str = "nosmep";
+/// The command line flag that leaves SMAP disabled
+pub const NO_SMAP_OPTION: &str = "nosmap";
+
+/// CPUID.(EAX=07H,ECX=0):EBX.SMEP
+const CPUID_SMEP: u32 = 1 << 7;
+/// CPUID.(EAX=07H,ECX=0):EBX.SMAP
+const CPUID_SMAP: u32 = 1 << 20;
+const CR4_SMEP: u64 = 1 << 20;
+const CR4_SMAP: u64 = 1 << 21;
+const CR0_WP: u64 = 1 << 16;
+/// The alignment check flag, which allows supervisor accesses to user pages under SMAP
+pub const RFLAGS_AC: u64 = 1 << 18;
+
+static SMEP_ENABLED: AtomicBool = AtomicBool::new(false);
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// # Init SMAP
+/// Enables SMEP and SMAP if the CPU supports them and they are not disabled on the command
+/// line, and write protection for the kernel. Has to be called on every CPU.
+pub fn init_smap() {
+    let features = unsafe {
+        if core::arch::x86_64::__cpuid(0).eax < 7 {
+            0
+        } else {
+            core::arch::x86_64::__cpuid_count(7, 0).ebx
+        }
+    };
+    let mut cr4 = read_cr4();
+    if enable(features, CPUID_SMEP, NO_SMEP_OPTION, "SMEP") {
+        cr4 |= CR4_SMEP;
+        SMEP_ENABLED.store(true, Ordering::Relaxed);
+    }
+    if enable(features, CPUID_SMAP, NO_SMAP_OPTION, "SMAP") {
+        // AC has to be clear before SMAP takes effect, or every access would be allowed
+        unsafe { core::arch::asm!("clac", options(nomem, nostack)) };
+        cr4 |= CR4_SMAP;
+        SMAP_ENABLED.store(true, Ordering::Relaxed);
+    }
+    unsafe {
+        core::arch::asm!("mov cr4, {}", in(reg) cr4, options(nostack, preserves_flags));
+        let cr0: u64;
+        core::arch::asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        core::arch::asm!("mov cr0, {}", in(reg) cr0 | CR0_WP, options(nostack, preserves_flags));
+    }
+}
+
+fn enable(features: u32, feature: u32, option: &str, name: &str) -> bool {
+    if features & feature == 0 {
+        info!("The CPU has no {}", name);
+        false
+    } else if cmdline::flag(option) {
+        warn!("{} is disabled by '{}'", name, option);
+        false
+    } else {
+        info!("Enabled {}", name);
+        true
+    }
+}
+
+fn read_cr4() -> u64 {
+    let cr4: u64;
+    unsafe {
+        core::arch::asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    }
+    cr4
+}
+
+pub fn is_smep_enabled() -> bool {
+    SMEP_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn is_smap_enabled() -> bool {
+    SMAP_ENABLED.load(Ordering::Relaxed)
+}
+
+/// # STAC
+/// Sets AC, allowing accesses to user pages
+#[inline(always)]
+pub fn stac() {
+    if is_smap_enabled() {
+        unsafe { core::arch::asm!("stac", options(nomem, nostack)) };
+    }
+}
+
+/// # CLAC
+/// Clears AC, user pages fault again
+#[inline(always)]
+pub fn clac() {
+    if is_smap_enabled() {
+        unsafe { core::arch::asm!("clac", options(nomem, nostack)) };
+    }
+}
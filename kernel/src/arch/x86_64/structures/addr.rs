@@ -1,7 +1,9 @@
 use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use bit_field::BitField;
 
+use crate::arch::USER_END;
 use crate::math;
 
 /// # Virtual Address
@@ -27,10 +29,12 @@ impl VirtualAddress {
 
     #[inline]
     /// # Try New
-    /// Tries to create the struct, if any value in the 47..64 area is set, it returns an error
+    /// Tries to create the struct. Bits 47..64 have to be all zeros or all ones. If only bit 47
+    /// is set, the address is sign extended into the higher half, as happens when an address is
+    /// put together from page table indices. Otherwise bits 47..64 are returned as an error.
     pub fn try_new(addr: u64) -> Result<VirtualAddress, u64> {
         match addr.get_bits(47..64) {
-            0 | 0x1fff => Ok(VirtualAddress(addr)),
+            0 | 0x1ffff => Ok(VirtualAddress(addr)),
             1 => Ok(VirtualAddress::truncate(addr)),
             bad => Err(bad),
         }
@@ -91,6 +95,20 @@ impl VirtualAddress {
         self.0 == 0
     }
 
+    /// # Is User
+    /// Whether the address is in the lower half, where user space lives
+    #[inline]
+    pub const fn is_user(&self) -> bool {
+        self.0 < USER_END
+    }
+
+    /// # Is Kernel
+    /// Whether the address is in the higher half, which only the kernel can access
+    #[inline]
+    pub const fn is_kernel(&self) -> bool {
+        !self.is_user()
+    }
+
     #[inline]
     pub fn set(&mut self, addr: u64) {
         self.try_set(addr)
@@ -99,21 +117,12 @@ impl VirtualAddress {
 
     #[inline(always)]
     /// # Try Set
-    /// Tries to set the value, if any value within the range is set, a tuple is returned
-    /// The first value within it contains the original address, the second one contains
-    /// the bits, which were bad
+    /// Tries to set the value the same way `try_new` creates it. If bits 47..64 are invalid, a
+    /// tuple is returned: The first value within it contains the original address, the second
+    /// one contains the bits, which were bad
     pub fn try_set(&mut self, addr: u64) -> Result<(), (u64, u64)> {
-        match addr.get_bits(47..64) {
-            0 | 0x1fff => {
-                self.0 = addr;
-                return Ok(());
-            }
-            1 => {
-                self.0 = ((addr << 16) as i64 >> 16) as u64;
-                return Ok(());
-            }
-            bad => Err((bad, Self::truncate(addr).as_u64())),
-        }
+        *self = Self::try_new(addr).map_err(|bad| (addr, bad))?;
+        Ok(())
     }
 
     #[inline(always)]
@@ -165,6 +174,12 @@ impl core::fmt::Debug for VirtualAddress {
     }
 }
 
+impl core::fmt::Display for VirtualAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        core::fmt::Display::fmt(&math::GroupedHex(self.0), f)
+    }
+}
+
 impl core::fmt::Binary for VirtualAddress {
     #[inline]
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
@@ -272,47 +287,148 @@ impl Sub<VirtualAddress> for VirtualAddress {
     }
 }
 
+/// # User Virtual Address
+/// A virtual address in the lower half, as passed by user space. It can only be created from
+/// a `VirtualAddress` for which `is_user()` holds, so a pointer into the kernel half never
+/// makes it to the code that accesses user memory. It may still be null or unmapped.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct UserVirtualAddress(VirtualAddress);
+
+impl UserVirtualAddress {
+    #[inline]
+    pub const fn null() -> Self {
+        Self(VirtualAddress::zero())
+    }
+
+    #[inline]
+    pub const fn as_u64(&self) -> u64 {
+        self.0.as_u64()
+    }
+
+    #[inline]
+    pub const fn as_virt(&self) -> VirtualAddress {
+        self.0
+    }
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        self.0.is_null()
+    }
+
+    /// # Checked Add
+    /// The address `offset` bytes further, `None` if it is not in the lower half anymore
+    #[inline]
+    pub fn checked_add(&self, offset: u64) -> Option<Self> {
+        let addr = self.as_u64().checked_add(offset)?;
+        (addr < USER_END).then(|| Self(VirtualAddress(addr)))
+    }
+}
+
+impl TryFrom<VirtualAddress> for UserVirtualAddress {
+    /// The address, which is in the kernel half
+    type Error = VirtualAddress;
+
+    #[inline]
+    fn try_from(addr: VirtualAddress) -> Result<Self, Self::Error> {
+        if addr.is_user() {
+            Ok(Self(addr))
+        } else {
+            Err(addr)
+        }
+    }
+}
+
+impl From<UserVirtualAddress> for VirtualAddress {
+    #[inline]
+    fn from(addr: UserVirtualAddress) -> Self {
+        addr.0
+    }
+}
+
+impl core::fmt::Debug for UserVirtualAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_tuple("UserVirtAddr")
+            .field(&format_args!("{:#x}", self.as_u64()))
+            .finish()
+    }
+}
+
+impl core::fmt::LowerHex for UserVirtualAddress {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        core::fmt::LowerHex::fmt(&self.as_u64(), f)
+    }
+}
+
+/// The number of bits physical addresses have, 52 until the CPU reported its own width
+static MAX_WIDTH: AtomicU32 = AtomicU32::new(PhysicalAddress::ARCH_MAX_WIDTH);
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 /// # Physical Address
-/// Represents an 8-bit address, even on non 64-bit systems
-/// `x86_64`, only the 52 lower bits of a physical address can be used. The top 12 bits need
-/// to be zero. This type guarantees that it always represents a valid physical address.
+/// Represents a physical address, even on non 64-bit systems.
+/// On `x86_64`, physical addresses have at most 52 bits, real CPUs support anywhere from 36 to
+/// 52 and report it through CPUID. Once the width is known (see `set_max_width()`), this type
+/// guarantees that it represents an address the CPU can reach. Before, it only checks the 52.
 /// The idea to implement said structure comes from the `x86_64` crate: https://docs.rs/x86_64/0.14.8/src/x86_64/addr.rs.html#35
 pub struct PhysicalAddress(u64);
 
 impl PhysicalAddress {
+    /// The most bits a physical address can have on `x86_64`
+    pub const ARCH_MAX_WIDTH: u32 = 52;
+
+    /// # New
+    /// ## Panics
+    /// If `addr` is wider than the physical address width
     #[inline]
     pub fn new(addr: u64) -> Self {
-        let ret = if addr.get_bits(52..64) != 0 {
-            Self::truncate(addr)
-        } else {
-            Self(addr)
-        };
-
-        assert!(
-            ret.0.get_bits(52..64) == 0,
-            "Even after truncating, the bits 52..64 were set"
-        );
-        ret
+        Self::try_new(addr).expect("The physical address is wider than the CPU supports")
     }
 
     #[inline]
     /// # Try New
     /// Tries to create a new value, returns a tuple on error.
-    /// The first value within it contains the original address, the second one contains
-    /// the bits, which were bad
+    /// The first value within it contains the bits above the physical address width, the
+    /// second one contains the address without them
     pub fn try_new(addr: u64) -> Result<Self, (u64, u64)> {
-        if addr.get_bits(52..64) != 0 {
-            Err((addr.get_bits(52..64), Self::truncate(addr).as_u64()))
+        let width = Self::max_width();
+        if addr.get_bits(width as usize..64) != 0 {
+            Err((
+                addr.get_bits(width as usize..64),
+                Self::truncate(addr).as_u64(),
+            ))
         } else {
             Ok(Self(addr))
         }
     }
 
+    /// # Set Max Width
+    /// Makes `bits` the physical address width, as reported by the CPU. Widths above 52 are
+    /// cut to 52. Addresses created before are not checked again.
+    pub fn set_max_width(bits: u32) {
+        MAX_WIDTH.store(bits.min(Self::ARCH_MAX_WIDTH), Ordering::Relaxed);
+    }
+
+    /// # Max Width
+    /// The number of bits physical addresses have
     #[inline]
-    pub const fn truncate(addr: u64) -> Self {
-        Self(addr % (1 << 52))
+    pub fn max_width() -> u32 {
+        MAX_WIDTH.load(Ordering::Relaxed)
+    }
+
+    /// # Max
+    /// The highest physical address
+    #[inline]
+    pub fn max() -> Self {
+        Self((1 << Self::max_width()) - 1)
+    }
+
+    /// # Truncate
+    /// Clears the bits above the physical address width
+    #[inline]
+    pub fn truncate(addr: u64) -> Self {
+        Self(addr & Self::max().0)
     }
 
     #[inline]
@@ -333,27 +449,18 @@ impl PhysicalAddress {
     #[inline]
     pub fn set(&mut self, addr: u64) {
         self.try_set(addr)
-            .expect("Failed to set the address due to bits 47..56 being occupied");
+            .expect("Failed to set the address as it is wider than the CPU supports");
     }
 
     /// # Try Set
-    /// Tries to set the address, returns a tuple on error.
+    /// Tries to set the address, returns a tuple on error and leaves the address as it was.
     /// The first value within it contains the original address, the second one contains
     /// the bits, which were bad
     #[inline]
     pub fn try_set(&mut self, addr: u64) -> Result<(), (u64, u64)> {
-        if addr.get_bits(52..64) != 0 {
-            let new = Self::truncate(addr).as_u64();
-            if new.get_bits(52..64) != 0 {
-                Err((addr, Self::truncate(new).as_u64()))
-            } else {
-                self.0 = new;
-                Ok(())
-            }
-        } else {
-            self.0 = addr;
-            Ok(())
-        }
+        let new = Self::try_new(addr).map_err(|(bad, _)| (addr, bad))?;
+        self.0 = new.0;
+        Ok(())
     }
 
     #[inline]
@@ -405,6 +512,12 @@ impl core::fmt::Debug for PhysicalAddress {
     }
 }
 
+impl core::fmt::Display for PhysicalAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        core::fmt::Display::fmt(&math::GroupedHex(self.0), f)
+    }
+}
+
 impl core::fmt::Binary for PhysicalAddress {
     #[inline]
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
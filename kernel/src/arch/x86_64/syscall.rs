@@ -1,21 +1,96 @@
-use super::gdt::GdtEntryType;
+//! # System Call Entry
+//! `syscall` leaves RSP pointing at the user stack, which the kernel must not write to: It may
+//! be tiny, unmapped or hold data in the red zone below RSP. The entry therefore switches to a
+//! kernel stack before the first push, using the per-CPU `SyscallCpu` that GS points to after
+//! `swapgs`. That stack is the top of the kernel stack of the current task, or a per-CPU
+//! scratch stack for tasks without one.
+use alloc::vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use super::gdt::Ring;
+use super::gdt::{self, GdtEntryType};
 use super::segment::Segment;
-use super::tss::ProcessorControl;
-use crate::arch::tss::Tss;
 use crate::info;
+use crate::scheduler::cputime;
+use crate::smp::MAX_CPUS;
+use crate::stats::SYSCALL_COUNT;
 use crate::{arch::interrupts::register::Registers, syscall};
-use core::arch::asm;
+
+/// The size of the per-CPU scratch stack
+pub const SYSCALL_STACK_SIZE: usize = 0x4000;
+/// The alignment of the stack when the dispatcher is called, per the System V ABI
+pub const STACK_ALIGN: u64 = 16;
+
+/// # Syscall CPU
+/// What the entry path of a CPU needs, the kernel GS base points here
+#[repr(C, align(64))]
+pub struct SyscallCpu {
+    /// The top of the stack the next system call runs on
+    pub kernel_rsp: AtomicU64,
+    /// The user stack pointer, only valid between entry and pushing it onto the kernel stack
+    pub user_rsp: AtomicU64,
+    /// The top of the scratch stack
+    pub scratch_rsp: AtomicU64,
+}
+
+impl SyscallCpu {
+    const fn new() -> Self {
+        Self {
+            kernel_rsp: AtomicU64::new(0),
+            user_rsp: AtomicU64::new(0),
+            scratch_rsp: AtomicU64::new(0),
+        }
+    }
+}
+
+const NO_CPU: SyscallCpu = SyscallCpu::new();
+pub static SYSCALL_CPUS: [SyscallCpu; MAX_CPUS] = [NO_CPU; MAX_CPUS];
+
+/// # Init Syscall CPU
+/// Allocates the scratch stack of `cpu`
+///
+/// ## Returns
+/// - u64 = The address of its `SyscallCpu`, which has to become the kernel GS base
+pub fn init_syscall_cpu(cpu: usize) -> u64 {
+    let area = &SYSCALL_CPUS[cpu];
+    if area.scratch_rsp.load(Ordering::Relaxed) == 0 {
+        let stack = vec![0u8; SYSCALL_STACK_SIZE].leak();
+        let top = (stack.as_ptr() as u64 + SYSCALL_STACK_SIZE as u64) & !(STACK_ALIGN - 1);
+        area.scratch_rsp.store(top, Ordering::Relaxed);
+        area.kernel_rsp.store(top, Ordering::Relaxed);
+    }
+    area as *const SyscallCpu as u64
+}
+
+/// # Set Kernel Stack
+/// Makes system calls and interrupts from user space on `cpu`, the calling CPU, run on the stack
+/// with the top `top`, the scratch stack if it is `None`. Called by the scheduler when a task is
+/// switched in.
+pub fn set_kernel_stack(cpu: usize, top: Option<u64>) {
+    let area = &SYSCALL_CPUS[cpu];
+    let top = top
+        .map(|top| top & !(STACK_ALIGN - 1))
+        .unwrap_or_else(|| area.scratch_rsp.load(Ordering::Relaxed));
+    area.kernel_rsp.store(top, Ordering::Relaxed);
+    gdt::local::set_kernel_stack(cpu, top);
+}
 
 #[no_mangle]
 pub unsafe extern "C" fn syscall_dispatcher(regs: *mut Registers) {
     info!("Called syscall!");
     let regs = &mut *regs;
+    // Tests call the dispatcher from the kernel, only a call from user space returns there
+    let from_user = cputime::enter_kernel();
+    SYSCALL_COUNT.increment(regs.rax as usize);
     // Return code is in rax
     regs.rax = {
         syscall::syscall(
             regs.rax, regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9, regs.rbp, regs,
         )
+    };
+    if from_user {
+        cputime::exit_to_user();
     }
 }
 
@@ -48,8 +123,8 @@ pub unsafe extern "C" fn syscall_dispatcher(regs: *mut Registers) {
 pub unsafe extern "C" fn syscall_handler() {
     asm!(
         "
-        swapgs                    // Set gs segment to TSS
-        mov gs:[{sp}], rsp        // Save userspace stack pointer
+        swapgs                    // Set gs to the SyscallCpu of this CPU
+        mov gs:[{sp}], rsp        // Save userspace stack pointer, nothing is pushed onto it
         mov rsp, gs:[{ksp}]       // Load kernel stack pointer
         push QWORD PTR {ss_sel}   // Push fake userspace SS (resembling iret frame)
         push QWORD PTR gs:[{sp}]  // Push userspace rsp
@@ -72,6 +147,7 @@ pub unsafe extern "C" fn syscall_handler() {
         push r13
         push r14
         push r15
+        // 20 pushes onto the aligned kernel stack keep it aligned for the call
         mov rdi, rsp
         call syscall_dispatcher
         pop r15
@@ -113,7 +189,7 @@ pub unsafe extern "C" fn syscall_handler() {
             pop r11                 // Pop rflags
             pop QWORD PTR gs:[{sp}] // Pop userspace stack pointer
             mov rsp, gs:[{sp}]      // Restore userspace stack pointer
-            swapgs                  // Restore gs from TSS to user data
+            swapgs                  // Restore the gs of userspace
             sysretq                 // Return into userspace; RCX=>RIP,R11=>RFLAGS
     1:
             // Slow iretq
@@ -121,10 +197,10 @@ pub unsafe extern "C" fn syscall_handler() {
             xor r11, r11
             swapgs
             iretq
-        ", 
-        sp = const(memoffset::offset_of!(ProcessorControl, user_rsp)),
+        ",
+        sp = const(memoffset::offset_of!(SyscallCpu, user_rsp)),
         ss_sel = const(Segment::new(Ring::Ring3, GdtEntryType::UserData).bits()),
-        ksp = const((memoffset::offset_of!(ProcessorControl, tss) + memoffset::offset_of!(Tss, rsp))),
+        ksp = const(memoffset::offset_of!(SyscallCpu, kernel_rsp)),
         cs_sel = const(Segment::new(Ring::Ring3, GdtEntryType::UserCode).bits()),
         options(noreturn),
     );
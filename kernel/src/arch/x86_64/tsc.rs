@@ -0,0 +1,103 @@
+//! # TSC
+//! The time stamp counter, which counts CPU cycles since reset. At boot its frequency is
+//! measured against channel 2 of the PIT, after which cycles can be converted to nanoseconds.
+//! The counter is assumed to tick at a constant rate on every CPU.
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::scheduler::pit::BASE_FREQUENCY;
+use crate::error::{Error, Result};
+use crate::info;
+use crate::iobus::{inb, outb};
+
+/// Channel 2 of the PIT, which is gated by port 0x61 and needs no interrupt
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 2, low then high byte, mode 0 (interrupt on terminal count), binary
+const PIT_CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+/// Bit 0 gates channel 2, bit 1 connects it to the speaker, bit 5 is its output
+const SYSTEM_CONTROL_PORT: u16 = 0x61;
+const GATE: u8 = 1 << 0;
+const SPEAKER: u8 = 1 << 1;
+const OUTPUT: u8 = 1 << 5;
+
+/// How long a single measurement lasts
+const CALIBRATION_MS: u64 = 10;
+/// The fastest of this many measurements is taken, slower ones were interrupted
+const CALIBRATION_RUNS: usize = 3;
+/// Polls of the PIT output after which it is assumed to be missing
+const MAX_POLLS: usize = 10_000_000;
+
+/// Zero until `calibrate()` succeeded
+static KHZ: AtomicU64 = AtomicU64::new(0);
+
+crate::initcall! {
+    name: "tsc",
+    stage: Interrupts,
+    deps: [],
+    fatal: false,
+    init: calibrate,
+}
+
+/// # Read
+/// The current value of the time stamp counter of the calling CPU
+#[inline(always)]
+pub fn read() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// # Calibrate
+/// Measures the frequency of the TSC by counting its cycles while channel 2 of the PIT counts
+/// down `CALIBRATION_MS` milliseconds
+///
+/// ## Returns
+/// - Error::NoSuchDevice = The PIT never finished counting
+pub fn calibrate() -> Result<()> {
+    let latch = BASE_FREQUENCY * CALIBRATION_MS / 1000;
+    let mut fastest = u64::MAX;
+    for _ in 0..CALIBRATION_RUNS {
+        fastest = fastest.min(measure(latch as u16)?);
+    }
+    let khz = fastest * BASE_FREQUENCY / (latch * 1000);
+    KHZ.store(khz, Ordering::Relaxed);
+    crate::time::clock::use_tsc(khz);
+    info!("tsc: {}.{:03} MHz", khz / 1000, khz % 1000);
+    Ok(())
+}
+
+/// # Measure
+/// The TSC cycles passing while channel 2 of the PIT counts down from `latch`, busy waiting
+/// for `latch` periods of the PIT base frequency independent of interrupts
+pub fn measure(latch: u16) -> Result<u64> {
+    // Gate the channel on, but keep the speaker quiet
+    outb(
+        SYSTEM_CONTROL_PORT,
+        (inb(SYSTEM_CONTROL_PORT) & !SPEAKER) | GATE,
+    );
+    outb(PIT_COMMAND, PIT_CHANNEL_2_ONE_SHOT);
+    outb(PIT_CHANNEL_2, latch as u8);
+    outb(PIT_CHANNEL_2, (latch >> 8) as u8);
+
+    let start = read();
+    for _ in 0..MAX_POLLS {
+        if inb(SYSTEM_CONTROL_PORT) & OUTPUT != 0 {
+            return Ok(read() - start);
+        }
+    }
+    Err(Error::NoSuchDevice)
+}
+
+/// # kHz
+/// The measured frequency of the TSC in kHz, `None` if it has not been calibrated
+pub fn khz() -> Option<u64> {
+    match KHZ.load(Ordering::Relaxed) {
+        0 => None,
+        khz => Some(khz),
+    }
+}
+
+/// # Cycles To Nanoseconds
+/// Converts a number of TSC cycles into nanoseconds, `None` if the TSC has not been calibrated
+pub fn cycles_to_ns(cycles: u64) -> Option<u64> {
+    let khz = khz()?;
+    Some((cycles as u128 * 1_000_000 / khz as u128) as u64)
+}
@@ -1,4 +1,19 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use core::mem::size_of;
+use core::ops::Range;
+
 use crate::arch::gdt::Ring;
+use crate::error::{Error, Result};
+
+/// The number of I/O ports
+pub const IO_PORTS: u32 = 0x1_0000;
+/// The size of an I/O permission bitmap, one bit for every port
+pub const IO_BITMAP_SIZE: usize = IO_PORTS as usize / 8;
+/// Where the I/O permission bitmap starts, right after the TSS
+pub const IO_BITMAP_OFFSET: u16 = size_of::<Tss>() as u16;
+/// The limit of the TSS descriptor, which covers the bitmap and the byte after it
+pub const TSS64_LIMIT: u32 = IO_BITMAP_OFFSET as u32 + IO_BITMAP_SIZE as u32;
 
 #[derive(Clone, Copy, Debug)]
 #[repr(C, packed)]
@@ -55,29 +70,75 @@ impl Tss {
     }
 }
 
+/// # TSS 64
+/// The TSS with its I/O permission bitmap. A set bit denies user space access to its port, the
+/// CPU reads two bytes of the bitmap at a time, so a byte of ones follows it.
 #[repr(align(16), C)]
-pub struct Tss64(Tss);
+pub struct Tss64(pub Tss, pub [u8; IO_BITMAP_SIZE], u8);
 
-pub struct ProcessorControl {
-    pub tcb: usize,
-    pub user_rsp: usize,
-    pub tss: Tss64,
+impl Tss64 {
+    /// A TSS that denies every port
+    pub const fn new(rsp: [u64; 3], ist: [u64; 7]) -> Self {
+        Self(
+            Tss::new(rsp, ist, IO_BITMAP_OFFSET),
+            [0xFF; IO_BITMAP_SIZE],
+            0xFF,
+        )
+    }
 }
 
-impl ProcessorControl {
-    pub const fn new(tcb: usize, user_rsp: usize, tss: Tss) -> Self {
-        Self {
-            tcb,
-            user_rsp,
-            tss: Tss64(tss),
+/// # I/O Bitmap
+/// The I/O ports a task may access from user space, in the format of the TSS: A set bit denies
+/// the port, so a new bitmap denies all of them.
+#[derive(Clone)]
+pub struct IoBitmap(Box<[u8; IO_BITMAP_SIZE]>);
+
+impl IoBitmap {
+    pub fn new() -> Self {
+        // Built on the heap, the bitmap is half a kernel stack
+        let bits = vec![0xFF; IO_BITMAP_SIZE].into_boxed_slice();
+        Self(
+            bits.try_into()
+                .expect("The bitmap has the size of the bitmap"),
+        )
+    }
+
+    /// # Set
+    /// Allows or denies access to `ports`
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = `ports` reaches beyond the last port
+    pub fn set(&mut self, ports: Range<u32>, allowed: bool) -> Result<()> {
+        if ports.start > ports.end || ports.end > IO_PORTS {
+            return Err(Error::InvalidArgument);
         }
+        for port in ports {
+            let (byte, bit) = (port as usize / 8, 1 << (port % 8));
+            if allowed {
+                self.0[byte] &= !bit;
+            } else {
+                self.0[byte] |= bit;
+            }
+        }
+        Ok(())
     }
-}
 
-#[thread_local]
-pub static mut PROC_CONT: ProcessorControl =
-    ProcessorControl::new(0, 0, Tss::new([0; 3], [0; 7], 0xFFFF));
+    pub fn is_allowed(&self, port: u16) -> bool {
+        self.0[port as usize / 8] & 1 << (port % 8) == 0
+    }
+
+    /// Whether any port is allowed
+    pub fn allows_any(&self) -> bool {
+        self.0.iter().any(|byte| *byte != 0xFF)
+    }
+
+    pub fn bits(&self) -> &[u8; IO_BITMAP_SIZE] {
+        &self.0
+    }
+}
 
-pub unsafe fn set_tss_stack(ptr: u64) {
-    PROC_CONT.tss.0.rsp[0] = ptr;
+impl Default for IoBitmap {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,698 @@
+//! # CFI
+//! The call frame information in `.eh_frame`: Its CIEs and FDEs, and the instructions that
+//! describe, for every address of a function, where its caller's registers are. Running them up
+//! to an address gives the `Row` of that address. Only what the compiler emits for x86_64 is
+//! supported, rules that are DWARF expressions make a row unusable.
+//!
+//! This runs while the kernel panics, so nothing in here may panic: Every read is checked,
+//! arithmetic wraps and malformed tables end in `None`. `MAX_INSTRUCTIONS` bounds the work
+//! for a row.
+
+/// The most instructions run for a row, the CIE's included
+pub const MAX_INSTRUCTIONS: usize = 1024;
+/// The most rows `DW_CFA_remember_state` keeps
+const STATE_STACK: usize = 4;
+/// The most entries a linear search through `.eh_frame` looks at
+pub const MAX_ENTRIES: usize = 0x10_0000;
+
+/// The DWARF number of `rsp`
+pub const RSP: u16 = 7;
+/// The DWARF number of `rbp`
+pub const RBP: u16 = 6;
+/// The CFA register of a row whose CFA is a DWARF expression
+const CFA_EXPRESSION: u16 = u16::MAX;
+
+enumtastic::const_enum! {
+    /// How a pointer is encoded, the format in the low and the base in the high bits
+    pub enum PointerEncoding: u8 => {
+        AbsPtr = 0x00,
+        Uleb128 = 0x01,
+        Udata2 = 0x02,
+        Udata4 = 0x03,
+        Udata8 = 0x04,
+        Sleb128 = 0x09,
+        Sdata2 = 0x0A,
+        Sdata4 = 0x0B,
+        Sdata8 = 0x0C,
+        PcRel = 0x10,
+        DataRel = 0x30,
+        Indirect = 0x80,
+        Omit = 0xFF,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    /// The call frame instructions without an operand in their low bits
+    pub enum CallFrameInstruction: u8 => {
+        Nop = 0x00,
+        SetLoc = 0x01,
+        AdvanceLoc1 = 0x02,
+        AdvanceLoc2 = 0x03,
+        AdvanceLoc4 = 0x04,
+        OffsetExtended = 0x05,
+        RestoreExtended = 0x06,
+        Undefined = 0x07,
+        SameValue = 0x08,
+        Register = 0x09,
+        RememberState = 0x0A,
+        RestoreState = 0x0B,
+        DefCfa = 0x0C,
+        DefCfaRegister = 0x0D,
+        DefCfaOffset = 0x0E,
+        DefCfaExpression = 0x0F,
+        Expression = 0x10,
+        OffsetExtendedSf = 0x11,
+        DefCfaSf = 0x12,
+        DefCfaOffsetSf = 0x13,
+        ValOffset = 0x14,
+        ValOffsetSf = 0x15,
+        ValExpression = 0x16,
+        GnuArgsSize = 0x2E,
+        GnuNegativeOffsetExtended = 0x2F,
+    }
+
+    impl {}
+}
+
+/// The instructions with an operand in their low bits, told apart by their high bits
+const ADVANCE_LOC: u8 = 0x40;
+const OFFSET: u8 = 0x80;
+const RESTORE: u8 = 0xC0;
+
+/// # Reader
+/// A cursor over bytes that are at `base` in memory
+#[derive(Debug, Clone, Copy)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    base: u64,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], base: u64) -> Self {
+        Self { data, pos: 0, base }
+    }
+
+    /// The address of the next byte
+    pub fn address(&self) -> u64 {
+        self.base.wrapping_add(self.pos as u64)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+
+    /// # Split
+    /// The next `len` bytes as a reader of their own, which this one skips
+    pub fn split(&mut self, len: usize) -> Option<Self> {
+        let base = self.address();
+        self.bytes(len).map(|data| Self::new(data, base))
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).and_then(|bytes| bytes.first().copied())
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        let bytes = self.bytes(4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        let bytes = self.bytes(8)?;
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    pub fn uleb128(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    pub fn sleb128(&mut self) -> Option<i64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7F) as i64) << shift;
+            if byte & 0x80 == 0 {
+                // Sign extended from the last bit read
+                if shift < 57 && byte & 0x40 != 0 {
+                    value |= -1 << (shift + 7);
+                }
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// A string up to its terminating zero, which is skipped
+    pub fn cstr(&mut self) -> Option<&'a [u8]> {
+        let rest = self.data.get(self.pos..)?;
+        let len = rest.iter().position(|byte| *byte == 0)?;
+        let string = self.bytes(len)?;
+        self.pos += 1;
+        Some(string)
+    }
+
+    /// # Pointer
+    /// A pointer in `encoding`, `DataRel` ones relative to `data_base`
+    ///
+    /// ## Returns
+    /// - None = The encoding is omitted or not supported, or there are too few bytes
+    pub fn pointer(&mut self, encoding: u8, data_base: u64) -> Option<u64> {
+        if encoding == PointerEncoding::Omit || encoding & PointerEncoding::Indirect != 0 {
+            return None;
+        }
+        let field = self.address();
+        let value = match encoding & 0x0F {
+            PointerEncoding::AbsPtr | PointerEncoding::Udata8 | PointerEncoding::Sdata8 => {
+                self.u64()?
+            }
+            PointerEncoding::Uleb128 => self.uleb128()?,
+            PointerEncoding::Udata2 => self.u16()? as u64,
+            PointerEncoding::Udata4 => self.u32()? as u64,
+            PointerEncoding::Sleb128 => self.sleb128()? as u64,
+            PointerEncoding::Sdata2 => self.u16()? as i16 as i64 as u64,
+            PointerEncoding::Sdata4 => self.u32()? as i32 as i64 as u64,
+            _ => return None,
+        };
+        let base = match encoding & 0x70 {
+            0 => 0,
+            PointerEncoding::PcRel => field,
+            PointerEncoding::DataRel => data_base,
+            _ => return None,
+        };
+        Some(base.wrapping_add(value))
+    }
+}
+
+/// # CIE
+/// The common information of the FDEs that refer to it
+#[derive(Debug, Clone, Copy)]
+pub struct Cie<'a> {
+    pub code_alignment: u64,
+    pub data_alignment: i64,
+    /// The register that holds the return address
+    pub return_register: u16,
+    /// How the addresses of the FDEs are encoded
+    pub fde_encoding: u8,
+    /// Whether FDEs have augmentation data, which is skipped
+    has_augmentation_data: bool,
+    /// The instructions every row starts with
+    pub instructions: Reader<'a>,
+}
+
+/// # FDE
+/// The call frame information of a function
+#[derive(Debug, Clone, Copy)]
+pub struct Fde<'a> {
+    pub cie: Cie<'a>,
+    /// The first address of the function
+    pub start: u64,
+    pub len: u64,
+    pub instructions: Reader<'a>,
+}
+
+impl<'a> Fde<'a> {
+    pub fn contains(&self, pc: u64) -> bool {
+        pc >= self.start && pc - self.start < self.len
+    }
+
+    /// # Row
+    /// Where the caller's registers are at `pc`
+    ///
+    /// ## Returns
+    /// - None = The instructions are malformed, use unsupported rules or take too long
+    pub fn row(&self, pc: u64) -> Option<Row> {
+        let mut budget = MAX_INSTRUCTIONS;
+        let mut row = Row::new(self.cie.return_register);
+        let initial = row;
+        run(
+            self.cie.instructions,
+            &self.cie,
+            self.start,
+            u64::MAX,
+            &mut row,
+            &initial,
+            &mut budget,
+        )?;
+        let initial = row;
+        run(
+            self.instructions,
+            &self.cie,
+            self.start,
+            pc,
+            &mut row,
+            &initial,
+            &mut budget,
+        )?;
+        Some(row)
+    }
+}
+
+/// # Entry
+/// A CIE or an FDE in `.eh_frame`
+#[derive(Debug, Clone, Copy)]
+struct Entry<'a> {
+    /// Where the CIE id or pointer is
+    id_offset: usize,
+    /// 0 for a CIE, for an FDE how far its CIE is before `id_offset`
+    id: u32,
+    /// Everything behind the id
+    body: Reader<'a>,
+    /// The offset of the next entry
+    next: usize,
+}
+
+/// # Eh Frame
+/// The `.eh_frame` section, at `base` in memory
+#[derive(Debug, Clone, Copy)]
+pub struct EhFrame<'a> {
+    data: &'a [u8],
+    base: u64,
+}
+
+impl<'a> EhFrame<'a> {
+    pub fn new(data: &'a [u8], base: u64) -> Self {
+        Self { data, base }
+    }
+
+    fn reader_at(&self, offset: usize) -> Option<Reader<'a>> {
+        let data = self.data.get(offset..)?;
+        Some(Reader::new(data, self.base.wrapping_add(offset as u64)))
+    }
+
+    /// The entry at `offset`, `None` at the terminator or the end
+    fn entry_at(&self, offset: usize) -> Option<Entry<'a>> {
+        let mut reader = self.reader_at(offset)?;
+        let len = match reader.u32()? {
+            0 => return None,
+            u32::MAX => reader.u64()?,
+            len => len as u64,
+        };
+        let header = reader.pos;
+        let mut contents = reader.split(usize::try_from(len).ok()?)?;
+        let id_offset = offset.checked_add(header)?;
+        let id = contents.u32()?;
+        Some(Entry {
+            id_offset,
+            id,
+            body: contents,
+            next: id_offset.checked_add(usize::try_from(len).ok()?)?,
+        })
+    }
+
+    /// # CIE At
+    /// The CIE at `offset`
+    pub fn cie_at(&self, offset: usize) -> Option<Cie<'a>> {
+        let entry = self.entry_at(offset)?;
+        if entry.id != 0 {
+            return None;
+        }
+        let mut body = entry.body;
+        let version = body.u8()?;
+        if version != 1 && version != 3 {
+            return None;
+        }
+        let augmentation = body.cstr()?;
+        let code_alignment = body.uleb128()?;
+        let data_alignment = body.sleb128()?;
+        let return_register = if version == 1 {
+            body.u8()? as u64
+        } else {
+            body.uleb128()?
+        };
+        let mut cie = Cie {
+            code_alignment,
+            data_alignment,
+            return_register: u16::try_from(return_register).ok()?,
+            fde_encoding: PointerEncoding::AbsPtr,
+            has_augmentation_data: false,
+            instructions: body,
+        };
+        if let Some((b'z', rest)) = augmentation.split_first() {
+            let len = usize::try_from(body.uleb128()?).ok()?;
+            let mut data = body.split(len)?;
+            for kind in rest {
+                match kind {
+                    b'R' => cie.fde_encoding = data.u8()?,
+                    b'L' => {
+                        data.u8()?;
+                    }
+                    // The personality routine, only read past, so where it points to does
+                    // not matter
+                    b'P' => {
+                        let encoding = data.u8()?;
+                        data.pointer(encoding & !PointerEncoding::Indirect, 0)?;
+                    }
+                    // A signal frame, which the kernel has none of, or anything this knows
+                    // nothing about, which the length lets it skip
+                    _ => break,
+                }
+            }
+            cie.has_augmentation_data = true;
+        } else if !augmentation.is_empty() {
+            return None;
+        }
+        cie.instructions = body;
+        Some(cie)
+    }
+
+    /// # FDE At
+    /// The FDE at `offset`, with its CIE
+    pub fn fde_at(&self, offset: usize) -> Option<Fde<'a>> {
+        let entry = self.entry_at(offset)?;
+        if entry.id == 0 {
+            return None;
+        }
+        let cie = self.cie_at(entry.id_offset.checked_sub(entry.id as usize)?)?;
+        let mut body = entry.body;
+        let start = body.pointer(cie.fde_encoding, 0)?;
+        // The length has the format of the address, but is not relative to anything
+        let len = body.pointer(cie.fde_encoding & 0x0F, 0)?;
+        if cie.has_augmentation_data {
+            let skip = usize::try_from(body.uleb128()?).ok()?;
+            body.bytes(skip)?;
+        }
+        Some(Fde {
+            cie,
+            start,
+            len,
+            instructions: body,
+        })
+    }
+
+    /// # FDE At Address
+    /// The FDE at `address` in memory
+    pub fn fde_at_address(&self, address: u64) -> Option<Fde<'a>> {
+        let offset = usize::try_from(address.checked_sub(self.base)?).ok()?;
+        self.fde_at(offset)
+    }
+
+    /// # Find FDE
+    /// The FDE of the function `pc` is in, by looking at every entry
+    pub fn find_fde(&self, pc: u64) -> Option<Fde<'a>> {
+        let mut offset = 0;
+        for _ in 0..MAX_ENTRIES {
+            let entry = self.entry_at(offset)?;
+            if entry.id != 0 {
+                if let Some(fde) = self.fde_at(offset).filter(|fde| fde.contains(pc)) {
+                    return Some(fde);
+                }
+            }
+            offset = entry.next;
+        }
+        None
+    }
+}
+
+/// # Eh Frame Hdr
+/// The `.eh_frame_hdr` section, which has a table of every FDE sorted by the first address of
+/// its function, at `base` in memory
+#[derive(Debug, Clone, Copy)]
+pub struct EhFrameHdr<'a> {
+    data: &'a [u8],
+    base: u64,
+}
+
+impl<'a> EhFrameHdr<'a> {
+    pub fn new(data: &'a [u8], base: u64) -> Self {
+        Self { data, base }
+    }
+
+    /// # Find
+    /// The address of the FDE that may cover `pc`, by a binary search through the table
+    ///
+    /// ## Returns
+    /// - None = There is no table, it is encoded other than lld does or no function is before
+    ///   `pc`
+    pub fn find(&self, pc: u64) -> Option<u64> {
+        let mut reader = Reader::new(self.data, self.base);
+        if reader.u8()? != 1 {
+            return None;
+        }
+        let frame_encoding = reader.u8()?;
+        let count_encoding = reader.u8()?;
+        let table_encoding = reader.u8()?;
+        reader.pointer(frame_encoding, self.base)?;
+        let count = reader.pointer(count_encoding, self.base)?;
+        // Pairs of 32 bit offsets from the start of the section, which lld always uses
+        if table_encoding != PointerEncoding::DataRel | PointerEncoding::Sdata4 {
+            return None;
+        }
+        let table = reader.split(usize::try_from(count).ok()?.checked_mul(8)?)?;
+        let entry = |idx: usize| {
+            let mut entry = table;
+            entry.bytes(idx.checked_mul(8)?)?;
+            let start = entry.pointer(table_encoding, self.base)?;
+            let fde = entry.pointer(table_encoding, self.base)?;
+            Some((start, fde))
+        };
+        // The last function that starts at or before `pc`
+        let (mut low, mut high) = (0, count as usize);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if entry(mid)?.0 <= pc {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        entry(low.checked_sub(1)?).map(|(_, fde)| fde)
+    }
+}
+
+/// # Rule
+/// Where the caller's value of a register is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// It is lost, or it is the register of the CIE's initial rule
+    Undefined,
+    /// The register was not changed
+    SameValue,
+    /// Saved at the CFA plus the offset
+    Offset(i64),
+    /// It is the CFA plus the offset
+    ValOffset(i64),
+    /// A rule this does not support, like DWARF expressions
+    Unsupported,
+}
+
+/// # Row
+/// The rules of the registers the unwinder needs at an address. The CFA, the canonical frame
+/// address, is the stack pointer of the caller right before the call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Row {
+    pub cfa_register: u16,
+    pub cfa_offset: i64,
+    pub rbp: Rule,
+    /// Where the return address is
+    pub return_address: Rule,
+    return_register: u16,
+}
+
+impl Row {
+    fn new(return_register: u16) -> Self {
+        Self {
+            cfa_register: RSP,
+            cfa_offset: 0,
+            rbp: Rule::SameValue,
+            return_address: Rule::Undefined,
+            return_register,
+        }
+    }
+
+    fn set(&mut self, register: u64, rule: Rule) {
+        if register == RBP as u64 {
+            self.rbp = rule;
+        } else if register == self.return_register as u64 {
+            self.return_address = rule;
+        }
+    }
+
+    fn get(&self, register: u64) -> Option<Rule> {
+        if register == RBP as u64 {
+            Some(self.rbp)
+        } else if register == self.return_register as u64 {
+            Some(self.return_address)
+        } else {
+            None
+        }
+    }
+}
+
+/// Runs `instructions` of a function starting at `start` until the row that covers `pc`
+fn run(
+    mut instructions: Reader,
+    cie: &Cie,
+    start: u64,
+    pc: u64,
+    row: &mut Row,
+    initial: &Row,
+    budget: &mut usize,
+) -> Option<()> {
+    let mut location = start;
+    let mut states = [*row; STATE_STACK];
+    let mut depth = 0;
+    let factored = |value: u64| (value as i64).wrapping_mul(cie.data_alignment);
+    let restore = |row: &mut Row, register: u64| {
+        if let Some(rule) = initial.get(register) {
+            row.set(register, rule);
+        }
+    };
+    while !instructions.is_empty() {
+        *budget = budget.checked_sub(1)?;
+        let opcode = instructions.u8()?;
+        let operand = (opcode & 0x3F) as u64;
+        let advance = match opcode & 0xC0 {
+            ADVANCE_LOC => Some(operand),
+            OFFSET => {
+                row.set(operand, Rule::Offset(factored(instructions.uleb128()?)));
+                None
+            }
+            RESTORE => {
+                restore(row, operand);
+                None
+            }
+            _ => match opcode {
+                CallFrameInstruction::Nop => None,
+                CallFrameInstruction::SetLoc => {
+                    let target = instructions.pointer(cie.fde_encoding, 0)?;
+                    if target > pc {
+                        return Some(());
+                    }
+                    location = target;
+                    None
+                }
+                CallFrameInstruction::AdvanceLoc1 => Some(instructions.u8()? as u64),
+                CallFrameInstruction::AdvanceLoc2 => Some(instructions.u16()? as u64),
+                CallFrameInstruction::AdvanceLoc4 => Some(instructions.u32()? as u64),
+                CallFrameInstruction::OffsetExtended => {
+                    let register = instructions.uleb128()?;
+                    row.set(register, Rule::Offset(factored(instructions.uleb128()?)));
+                    None
+                }
+                CallFrameInstruction::OffsetExtendedSf => {
+                    let register = instructions.uleb128()?;
+                    let offset = instructions.sleb128()?.wrapping_mul(cie.data_alignment);
+                    row.set(register, Rule::Offset(offset));
+                    None
+                }
+                CallFrameInstruction::GnuNegativeOffsetExtended => {
+                    let register = instructions.uleb128()?;
+                    let offset = factored(instructions.uleb128()?).wrapping_neg();
+                    row.set(register, Rule::Offset(offset));
+                    None
+                }
+                CallFrameInstruction::ValOffset => {
+                    let register = instructions.uleb128()?;
+                    row.set(register, Rule::ValOffset(factored(instructions.uleb128()?)));
+                    None
+                }
+                CallFrameInstruction::ValOffsetSf => {
+                    let register = instructions.uleb128()?;
+                    let offset = instructions.sleb128()?.wrapping_mul(cie.data_alignment);
+                    row.set(register, Rule::ValOffset(offset));
+                    None
+                }
+                CallFrameInstruction::RestoreExtended => {
+                    restore(row, instructions.uleb128()?);
+                    None
+                }
+                CallFrameInstruction::Undefined => {
+                    row.set(instructions.uleb128()?, Rule::Undefined);
+                    None
+                }
+                CallFrameInstruction::SameValue => {
+                    row.set(instructions.uleb128()?, Rule::SameValue);
+                    None
+                }
+                CallFrameInstruction::Register => {
+                    let register = instructions.uleb128()?;
+                    instructions.uleb128()?;
+                    row.set(register, Rule::Unsupported);
+                    None
+                }
+                CallFrameInstruction::Expression | CallFrameInstruction::ValExpression => {
+                    let register = instructions.uleb128()?;
+                    let len = usize::try_from(instructions.uleb128()?).ok()?;
+                    instructions.bytes(len)?;
+                    row.set(register, Rule::Unsupported);
+                    None
+                }
+                CallFrameInstruction::RememberState => {
+                    *states.get_mut(depth)? = *row;
+                    depth += 1;
+                    None
+                }
+                CallFrameInstruction::RestoreState => {
+                    depth = depth.checked_sub(1)?;
+                    *row = *states.get(depth)?;
+                    None
+                }
+                CallFrameInstruction::DefCfa => {
+                    row.cfa_register = u16::try_from(instructions.uleb128()?).ok()?;
+                    row.cfa_offset = instructions.uleb128()? as i64;
+                    None
+                }
+                CallFrameInstruction::DefCfaSf => {
+                    row.cfa_register = u16::try_from(instructions.uleb128()?).ok()?;
+                    row.cfa_offset = instructions.sleb128()?.wrapping_mul(cie.data_alignment);
+                    None
+                }
+                CallFrameInstruction::DefCfaRegister => {
+                    row.cfa_register = u16::try_from(instructions.uleb128()?).ok()?;
+                    None
+                }
+                CallFrameInstruction::DefCfaOffset => {
+                    row.cfa_offset = instructions.uleb128()? as i64;
+                    None
+                }
+                CallFrameInstruction::DefCfaOffsetSf => {
+                    row.cfa_offset = instructions.sleb128()?.wrapping_mul(cie.data_alignment);
+                    None
+                }
+                CallFrameInstruction::DefCfaExpression => {
+                    let len = usize::try_from(instructions.uleb128()?).ok()?;
+                    instructions.bytes(len)?;
+                    row.cfa_register = CFA_EXPRESSION;
+                    None
+                }
+                CallFrameInstruction::GnuArgsSize => {
+                    instructions.uleb128()?;
+                    None
+                }
+                _ => return None,
+            },
+        };
+        if let Some(delta) = advance {
+            let next = location.wrapping_add(delta.wrapping_mul(cie.code_alignment));
+            // The row so far covers every address up to the next location
+            if next > pc {
+                return Some(());
+            }
+            location = next;
+        }
+    }
+    Some(())
+}
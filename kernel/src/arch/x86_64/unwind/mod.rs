@@ -0,0 +1,167 @@
+//! # Unwind
+//! Walking the stack with the unwind tables in `.eh_frame`, which the linker script keeps in the
+//! kernel image. Unlike the chain of frame pointers they are right in prologues and epilogues,
+//! and across functions that do not keep a frame pointer. Frames the tables do not cover, or
+//! whose rules this does not support, are stepped over with their frame pointer.
+//!
+//! Used by the panic handler, so a corrupted stack or corrupted tables end the walk instead of
+//! panicking: Every step only reads aligned words between the stack pointer and the CFA.
+pub mod cfi;
+
+use super::backtrace::{MAX_FRAMES, MAX_FRAME_SIZE};
+use crate::memory::kernel_image;
+use cfi::{EhFrame, EhFrameHdr, Fde, Rule, RBP, RSP};
+
+// Frames stepped over with their frame pointer because the tables did not help
+crate::counter!(pub FALLBACK_FRAMES = "unwind.fallback_frames");
+
+extern "C" {
+    // Defined in the Linker Script
+    static __eh_frame_hdr_start: u8;
+    static __eh_frame_hdr_end: u8;
+    static __eh_frame_start: u8;
+    static __eh_frame_end: u8;
+}
+
+/// # Registers
+/// What a frame needs to find its caller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+}
+
+fn section(start: &'static u8, end: &'static u8) -> (&'static [u8], u64) {
+    let start = start as *const u8;
+    let len = (end as *const u8 as usize).saturating_sub(start as usize);
+    (
+        unsafe { core::slice::from_raw_parts(start, len) },
+        start as u64,
+    )
+}
+
+fn eh_frame_hdr() -> EhFrameHdr<'static> {
+    let (data, base) = unsafe { section(&__eh_frame_hdr_start, &__eh_frame_hdr_end) };
+    EhFrameHdr::new(data, base)
+}
+
+fn eh_frame() -> EhFrame<'static> {
+    let (data, base) = unsafe { section(&__eh_frame_start, &__eh_frame_end) };
+    EhFrame::new(data, base)
+}
+
+/// # Has Tables
+/// Whether the kernel image has unwind tables
+pub fn has_tables() -> bool {
+    unsafe { &__eh_frame_start as *const u8 != &__eh_frame_end as *const u8 }
+}
+
+/// # Find FDE
+/// The FDE of the function `pc` is in, through the binary search table if the linker made one
+pub fn find_fde(pc: u64) -> Option<Fde<'static>> {
+    let eh_frame = eh_frame();
+    match eh_frame_hdr().find(pc) {
+        Some(address) => eh_frame
+            .fde_at_address(address)
+            .filter(|fde| fde.contains(pc)),
+        None => eh_frame.find_fde(pc),
+    }
+}
+
+/// Reads the word at `address` of a frame that spans `rsp..cfa`
+fn read_frame(address: u64, rsp: u64, cfa: u64) -> Option<u64> {
+    if address % 8 != 0 || address < rsp || address.checked_add(8)? > cfa {
+        return None;
+    }
+    Some(unsafe { (address as *const u64).read() })
+}
+
+/// # Step DWARF
+/// The registers of the caller of the frame `regs`, by the unwind tables. `first` is whether
+/// `regs` are those of the innermost frame, where `rip` is not a return address.
+///
+/// ## Returns
+/// - None = The tables do not cover `rip`, use rules this does not support or lead somewhere
+///   that does not look like a frame
+pub fn step_dwarf(regs: Registers, first: bool) -> Option<Registers> {
+    // A return address may be the start of the next function if the call was the last
+    // instruction of its own
+    let pc = if first {
+        regs.rip
+    } else {
+        regs.rip.wrapping_sub(1)
+    };
+    let row = find_fde(pc)?.row(pc)?;
+    let base = match row.cfa_register {
+        RSP => regs.rsp,
+        RBP => regs.rbp,
+        _ => return None,
+    };
+    let cfa = base.wrapping_add(row.cfa_offset as u64);
+    // Callers live further up the stack
+    if cfa <= regs.rsp || cfa - regs.rsp > MAX_FRAME_SIZE {
+        return None;
+    }
+    let rip = match row.return_address {
+        Rule::Offset(offset) => read_frame(cfa.wrapping_add(offset as u64), regs.rsp, cfa)?,
+        _ => return None,
+    };
+    let rbp = match row.rbp {
+        Rule::SameValue | Rule::Undefined => regs.rbp,
+        Rule::Offset(offset) => read_frame(cfa.wrapping_add(offset as u64), regs.rsp, cfa)?,
+        Rule::ValOffset(offset) => cfa.wrapping_add(offset as u64),
+        Rule::Unsupported => return None,
+    };
+    Some(Registers { rip, rsp: cfa, rbp })
+}
+
+/// # Step Frame Pointer
+/// The registers of the caller of the frame `regs`, by its saved frame pointer
+///
+/// ## Returns
+/// - None = `rbp` does not point to a frame
+pub fn step_frame_pointer(regs: Registers) -> Option<Registers> {
+    let frame = regs.rbp;
+    if frame == 0 || frame % 8 != 0 || frame < regs.rsp || frame - regs.rsp > MAX_FRAME_SIZE {
+        return None;
+    }
+    let cfa = frame.checked_add(16)?;
+    Some(Registers {
+        rip: read_frame(frame + 8, regs.rsp, cfa)?,
+        rsp: cfa,
+        rbp: read_frame(frame, regs.rsp, cfa)?,
+    })
+}
+
+/// # Backtrace DWARF
+/// Calls `f` with the return address of every frame, starting with the caller of the frame
+/// `rip`, `rsp` and `rbp` belong to. Each frame is stepped over with the unwind tables, or its
+/// frame pointer if they do not help. The walk ends at the first frame neither can step over,
+/// or that returns to somewhere that is not kernel code.
+///
+/// ## Returns
+/// The number of frames stepped over with their frame pointer
+pub fn backtrace_dwarf(rip: u64, rsp: u64, rbp: u64, mut f: impl FnMut(u64)) -> usize {
+    let mut regs = Registers { rip, rsp, rbp };
+    let mut fallbacks = 0;
+    for frame in 0..MAX_FRAMES {
+        let caller = match step_dwarf(regs, frame == 0) {
+            Some(caller) => caller,
+            None => match step_frame_pointer(regs) {
+                Some(caller) => {
+                    fallbacks += 1;
+                    FALLBACK_FRAMES.increment();
+                    caller
+                }
+                None => break,
+            },
+        };
+        if !kernel_image::is_text(caller.rip) {
+            break;
+        }
+        f(caller.rip);
+        regs = caller;
+    }
+    fallbacks
+}
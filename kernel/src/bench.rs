@@ -0,0 +1,234 @@
+//! # Benchmarks
+//! Micro benchmarks of the system call path, of memory mappings and of context switches, run by
+//! `bench` in the shell or at boot with the `bench` command line flag. Every benchmark runs
+//! `WARMUP` iterations that are not timed, then times `ITERATIONS` single iterations with the
+//! TSC and reports the median and the 99th percentile in cycles.
+//!
+//! Besides the console, every result goes to COM1 as one line `BENCH <name> <median> <p99>`,
+//! which `scripts/benchcompare.py` compares between two runs, e.g. of two commits in CI.
+//!
+//! `bench mem` is separate from the suite: It times every variant of `arch::mem` copying
+//! `MEM_SIZES`, to tune when `copy_fast()` switches between them.
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::arch::interrupts::register::Registers;
+use crate::arch::interrupts::without_interrupts;
+use crate::arch::mem::{self, CopyVariant};
+use crate::arch::tsc;
+use crate::drivers::serial::SERIAL;
+use crate::error::{Error, Result};
+use crate::fs;
+use crate::ipc::shm::SharedMemory;
+use crate::memory::usermem::{self, user_address};
+use crate::memory::UserVirtualAddress;
+use crate::scheduler;
+use crate::syscall::{self, mman, SyscallNumber};
+use crate::{info, warn};
+
+/// The command line flag that runs every benchmark at boot and then leaves QEMU
+pub const BENCH_FLAG: &str = "bench";
+/// What every line of results on COM1 starts with
+pub const PREFIX: &str = "BENCH";
+pub const ITERATIONS: usize = 1000;
+pub const WARMUP: usize = 100;
+/// The size of the mapping of the `mmap` benchmark
+pub const MMAP_SIZE: u64 = 0x10_0000;
+/// The sizes of the copies `mem_copy()` is run with
+pub const MEM_SIZES: [usize; 6] = [0x40, 0x400, 0x4000, 0x4_0000, 0x40_0000, 0x100_0000];
+/// The timed copies per variant and size, fewer than `ITERATIONS` as the largest take long
+pub const MEM_ITERATIONS: usize = 64;
+const MEM_WARMUP: usize = 4;
+
+/// # Benchmark
+/// A benchmark and the samples it takes, `WARMUP` excluded
+pub struct Benchmark {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub run: fn() -> Result<Vec<u64>>,
+}
+
+/// Every benchmark, in the order `run_all()` runs them
+pub static BENCHMARKS: &[Benchmark] = &[
+    Benchmark {
+        name: "syscall",
+        help: "A system call that does nothing, through the dispatcher",
+        run: null_syscall,
+    },
+    Benchmark {
+        name: "mmap",
+        help: "mmap() of 1 MiB, writing every page and munmap()",
+        run: mmap,
+    },
+    Benchmark {
+        name: "switch",
+        help: "Waking a task and blocking until it runs",
+        run: switch,
+    },
+];
+
+/// # Summary
+/// The percentiles of the samples of a benchmark, in cycles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub median: u64,
+    pub p99: u64,
+}
+
+impl Summary {
+    /// # Of
+    /// Sorts `samples` and summarizes them, `None` if there are none
+    pub fn of(samples: &mut [u64]) -> Option<Self> {
+        samples.sort_unstable();
+        Some(Self {
+            median: percentile(samples, 50)?,
+            p99: percentile(samples, 99)?,
+        })
+    }
+}
+
+/// # Percentile
+/// The smallest of the sorted `samples` that at least `pct` percent of them are not larger than
+pub fn percentile(sorted: &[u64], pct: usize) -> Option<u64> {
+    let rank = (sorted.len() * pct.min(100) + 99) / 100;
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+pub fn find(name: &str) -> Option<&'static Benchmark> {
+    BENCHMARKS.iter().find(|bench| bench.name == name)
+}
+
+/// # Run
+/// Runs `bench` and reports its result to COM1
+pub fn run(bench: &Benchmark) -> Result<Summary> {
+    let mut samples = (bench.run)()?;
+    let summary = Summary::of(&mut samples).ok_or(Error::InvalidArgument)?;
+    let _ = writeln!(
+        SERIAL.lock(),
+        "{} {} {} {}",
+        PREFIX,
+        bench.name,
+        summary.median,
+        summary.p99
+    );
+    Ok(summary)
+}
+
+/// # Run All
+/// Runs every benchmark, called at boot with `BENCH_FLAG`. One that fails is left out of the
+/// results and logged.
+pub fn run_all() {
+    info!("bench: Running {} benchmarks", BENCHMARKS.len());
+    for bench in BENCHMARKS {
+        match run(bench) {
+            Ok(summary) => info!(
+                "bench: {}: median {} cycles, p99 {} cycles",
+                bench.name, summary.median, summary.p99
+            ),
+            Err(err) => warn!("bench: {} failed: {}", bench.name, err.text()),
+        }
+    }
+}
+
+/// # Measure
+/// Times `ITERATIONS` calls of `iteration` after `WARMUP` untimed ones. With `quiet`, every
+/// timed call runs with interrupts disabled, so no interrupt handler is measured along with it.
+fn measure(quiet: bool, mut iteration: impl FnMut() -> Result<()>) -> Result<Vec<u64>> {
+    for _ in 0..WARMUP {
+        iteration()?;
+    }
+    let mut samples = Vec::with_capacity(ITERATIONS);
+    for _ in 0..ITERATIONS {
+        let mut timed = || {
+            let start = tsc::read();
+            iteration().map(|_| tsc::read() - start)
+        };
+        let cycles = if quiet {
+            without_interrupts(timed)
+        } else {
+            timed()
+        }?;
+        samples.push(cycles);
+    }
+    Ok(samples)
+}
+
+/// `api_version()`, which only returns a constant, through the whole dispatcher
+fn null_syscall() -> Result<Vec<u64>> {
+    // The dispatcher only reads the registers of calls that need the user state
+    let mut regs: Registers = unsafe { core::mem::zeroed() };
+    measure(true, || {
+        let value = syscall::syscall(SyscallNumber::ApiVersion, 0, 0, 0, 0, 0, 0, 0, &mut regs);
+        match value as i64 {
+            errno if errno < 0 => Err(Error::InvalidArgument),
+            _ => Ok(()),
+        }
+    })
+}
+
+fn mmap() -> Result<Vec<u64>> {
+    let fd = fs::shared_memory_fd(SharedMemory::new(MMAP_SIZE)?)?;
+    let samples = measure(false, || {
+        let addr = mman::sys_mmap(
+            UserVirtualAddress::null(),
+            MMAP_SIZE,
+            mman::PROT_READ | mman::PROT_WRITE,
+            mman::MAP_SHARED,
+            fd,
+            0,
+        )?;
+        let touched = (addr..addr + MMAP_SIZE)
+            .step_by(bks::PAGE_SIZE as usize)
+            .try_for_each(|page| usermem::write_user(user_address(page)?, 1u8));
+        mman::sys_munmap(user_address(addr)?, MMAP_SIZE)?;
+        touched
+    });
+    fs::close_fd(fd)?;
+    samples
+}
+
+fn switch() -> Result<Vec<u64>> {
+    let mut latencies = scheduler::bench::switch_latencies((WARMUP + ITERATIONS) as u64 + 1)?;
+    Ok(latencies.split_off(WARMUP.min(latencies.len())))
+}
+
+/// # Mem Copy
+/// Times `MEM_ITERATIONS` copies of `size` bytes between two buffers on the heap with `variant`
+/// and reports the result to COM1 as `mem.<variant>.<size>`
+///
+/// ## Returns
+/// - Error::InvalidArgument = The CPU does not support `variant`
+/// - Error::OutOfMemory = The buffers do not fit into the heap
+pub fn mem_copy(variant: CopyVariant, size: usize) -> Result<Summary> {
+    if !variant.is_supported() {
+        return Err(Error::InvalidArgument);
+    }
+    let mut src = Vec::new();
+    let mut dst = Vec::new();
+    src.try_reserve_exact(size)
+        .and_then(|_| dst.try_reserve_exact(size))
+        .map_err(|_| Error::OutOfMemory)?;
+    src.resize(size, 0xA5u8);
+    dst.resize(size, 0u8);
+
+    let mut samples = Vec::with_capacity(MEM_ITERATIONS);
+    for iteration in 0..MEM_WARMUP + MEM_ITERATIONS {
+        let start = tsc::read();
+        unsafe { mem::copy_with(variant, dst.as_mut_ptr(), src.as_ptr(), size) };
+        let cycles = tsc::read() - start;
+        if iteration >= MEM_WARMUP {
+            samples.push(cycles);
+        }
+    }
+    let summary = Summary::of(&mut samples).ok_or(Error::InvalidArgument)?;
+    let _ = writeln!(
+        SERIAL.lock(),
+        "{} mem.{}.{} {} {}",
+        PREFIX,
+        variant.name(),
+        size,
+        summary.median,
+        summary.p99
+    );
+    Ok(summary)
+}
@@ -0,0 +1,280 @@
+//! # Cache
+//! The page cache: Pages of block devices kept in frames of their own, so reading the same data
+//! again does not go to the disk. Pages are keyed by the device and their page aligned offset
+//! on it, and spread over `SHARDS` shards by a hash of the key, each behind a reader-writer
+//! lock, so hits on different shards or of many readers do not contend.
+//!
+//! A page holds a weak reference to its device, which keeps the address of the device from
+//! being reused while the page is cached, so the address can serve as the id of the device.
+//! Frames are given back to the allocator when memory runs low (see `memory::pressure`), least
+//! recently used first and the pages of devices that are gone before all others.
+//!
+//! The frame of a cached page is marked `PAGE_CACHE` in its `FrameInfo`, with the id of the
+//! device as its owner, until it is freed.
+//!
+//! Only reads are cached. Writes through `BlockDevice::write_blocks()` are not seen by cached
+//! pages, a writer has to `invalidate()` the device.
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use bks::PAGE_SIZE;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use super::BlockDevice;
+use crate::error::Result;
+use crate::memory::frame_info::{frame_info, FrameFlags};
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::{phys_to_virt, pressure, Frame, PhysicalAddress};
+use crate::scheduler::spin::RwLock;
+
+/// The number of shards, a power of two
+pub const SHARDS: usize = 16;
+const PAGE: usize = PAGE_SIZE as usize;
+
+crate::counter!(pub HITS = "cache.hits");
+crate::counter!(pub MISSES = "cache.misses");
+crate::counter!(pub EVICTIONS = "cache.evictions");
+// Reads that bypassed the cache, as there was no frame for them or the block size of the
+// device does not divide the page size
+crate::counter!(pub UNCACHED = "cache.uncached");
+
+/// The id of the device and the offset of the page on it
+type Key = (usize, u64);
+
+/// # Cached Page
+/// A page of a device in a frame
+struct CachedPage {
+    device: Weak<dyn BlockDevice>,
+    frame: u64,
+    /// The value of `CLOCK` when the page was last read
+    last_used: AtomicU64,
+}
+
+impl CachedPage {
+    fn data(&self) -> &[u8] {
+        let virt = phys_to_virt(PhysicalAddress::new(self.frame));
+        unsafe { core::slice::from_raw_parts(virt.as_u64() as *const u8, PAGE) }
+    }
+
+    fn touch(&self) {
+        self.last_used
+            .store(CLOCK.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+static CACHE: [RwLock<BTreeMap<Key, CachedPage>>; SHARDS] = {
+    const SHARD: RwLock<BTreeMap<Key, CachedPage>> = RwLock::new(BTreeMap::new());
+    [SHARD; SHARDS]
+};
+/// Counts reads, so the least recently used page has the lowest `last_used`
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+static PAGES: AtomicUsize = AtomicUsize::new(0);
+
+crate::initcall! {
+    name: "page-cache",
+    stage: EarlyMemory,
+    deps: [],
+    fatal: false,
+    init: init_cache,
+}
+
+fn init_cache() -> Result<()> {
+    pressure::register("page-cache", shrink);
+    Ok(())
+}
+
+fn id(device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(device) as *const u8 as usize
+}
+
+fn shard(key: Key) -> &'static RwLock<BTreeMap<Key, CachedPage>> {
+    let hash = (key.0 as u64 ^ (key.1 / PAGE_SIZE)).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    &CACHE[(hash >> 32) as usize % SHARDS]
+}
+
+/// # Read
+/// Reads `buf.len()` bytes from `offset` on `device` through the cache. Bytes beyond the end of
+/// the device read as zero.
+pub fn read(device: &Arc<dyn BlockDevice>, offset: u64, buf: &mut [u8]) -> Result<()> {
+    let block_size = device.block_size();
+    if block_size > PAGE || PAGE % block_size != 0 {
+        UNCACHED.increment();
+        return read_uncached(&**device, offset, buf);
+    }
+    let mut done = 0;
+    while done < buf.len() {
+        let position = offset + done as u64;
+        let page = position - position % PAGE_SIZE;
+        let within = (position - page) as usize;
+        let count = (PAGE - within).min(buf.len() - done);
+        read_page(device, page, within, &mut buf[done..done + count])?;
+        done += count;
+    }
+    Ok(())
+}
+
+/// Copies the part of the page at `page` from `within` on into `out`, reading the page first
+/// if it is not cached
+fn read_page(
+    device: &Arc<dyn BlockDevice>,
+    page: u64,
+    within: usize,
+    out: &mut [u8],
+) -> Result<()> {
+    let key = (id(device), page);
+    if let Some(cached) = shard(key).read().get(&key) {
+        out.copy_from_slice(&cached.data()[within..within + out.len()]);
+        cached.touch();
+        HITS.increment();
+        return Ok(());
+    }
+    MISSES.increment();
+
+    pressure::relieve();
+    let frame = match allocate_frame() {
+        Some(frame) => frame,
+        None => {
+            UNCACHED.increment();
+            return read_uncached(&**device, page + within as u64, out);
+        }
+    };
+    let data = unsafe {
+        core::slice::from_raw_parts_mut(
+            phys_to_virt(PhysicalAddress::new(frame)).as_u64() as *mut u8,
+            PAGE,
+        )
+    };
+    // The last page of a device may be partial
+    let block_size = device.block_size() as u64;
+    let first = page / block_size;
+    let blocks = (PAGE_SIZE / block_size).min(device.block_count().saturating_sub(first));
+    let len = (blocks * block_size) as usize;
+    data[len..].fill(0);
+    if let Err(err) = device.read_blocks(first, &mut data[..len]) {
+        free_frame(frame);
+        return Err(err);
+    }
+
+    let mut pages = shard(key).write();
+    // Read by someone else in the meantime, theirs is kept
+    if let Some(cached) = pages.get(&key) {
+        out.copy_from_slice(&cached.data()[within..within + out.len()]);
+        cached.touch();
+        drop(pages);
+        free_frame(frame);
+        return Ok(());
+    }
+    out.copy_from_slice(&data[within..within + out.len()]);
+    let info = frame_info(Frame::which_contains(PhysicalAddress::new(frame)));
+    info.insert(FrameFlags::PAGE_CACHE);
+    info.set_owner(key.0 as u64);
+    pages.insert(
+        key,
+        CachedPage {
+            device: Arc::downgrade(device),
+            frame,
+            last_used: AtomicU64::new(CLOCK.fetch_add(1, Ordering::Relaxed)),
+        },
+    );
+    PAGES.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Reads the blocks covering the range directly into a buffer of their own
+fn read_uncached(device: &dyn BlockDevice, offset: u64, buf: &mut [u8]) -> Result<()> {
+    let block_size = device.block_size() as u64;
+    let first = offset / block_size;
+    let end = (offset + buf.len() as u64 + block_size - 1) / block_size;
+    let blocks = end.min(device.block_count()).saturating_sub(first);
+    let mut data = vec![0u8; (end - first) as usize * block_size as usize];
+    device.read_blocks(first, &mut data[..(blocks * block_size) as usize])?;
+    let within = (offset - first * block_size) as usize;
+    buf.copy_from_slice(&data[within..within + buf.len()]);
+    Ok(())
+}
+
+fn allocate_frame() -> Option<u64> {
+    unsafe {
+        PAGE_FRAME_ALLOCATOR
+            .lock()
+            .assume_init_mut()
+            .request_contiguous_pages(1, PAGE_SIZE, u64::MAX)
+    }
+}
+
+fn free_frame(frame: u64) {
+    unsafe {
+        PAGE_FRAME_ALLOCATOR
+            .lock()
+            .assume_init_mut()
+            .free_page(frame)
+    };
+}
+
+/// Removes the pages for which `evict` returns true and frees their frames
+fn evict_where(mut evict: impl FnMut(&Key, &CachedPage) -> bool) -> usize {
+    let mut frames = Vec::new();
+    for shard in CACHE.iter() {
+        shard.write().retain(|key, page| {
+            let keep = !evict(key, page);
+            if !keep {
+                frames.push(page.frame);
+            }
+            keep
+        });
+    }
+    for frame in &frames {
+        free_frame(*frame);
+    }
+    PAGES.fetch_sub(frames.len(), Ordering::Relaxed);
+    EVICTIONS.add(frames.len() as u64);
+    frames.len()
+}
+
+/// # Shrink
+/// Evicts up to `wanted` pages, those of devices that are gone first and then the least
+/// recently used ones
+///
+/// ## Returns
+/// - usize = The number of pages evicted
+pub fn shrink(wanted: usize) -> usize {
+    let gone = evict_where(|_, page| page.device.strong_count() == 0);
+    if gone >= wanted {
+        return gone;
+    }
+    let mut ages: Vec<u64> = CACHE
+        .iter()
+        .flat_map(|shard| {
+            shard
+                .read()
+                .values()
+                .map(|page| page.last_used.load(Ordering::Relaxed))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    ages.sort_unstable();
+    let oldest = match ages.get(wanted - gone - 1) {
+        Some(age) => *age,
+        None => u64::MAX,
+    };
+    gone + evict_where(|_, page| page.last_used.load(Ordering::Relaxed) <= oldest)
+}
+
+/// # Invalidate
+/// Drops every cached page of `device`, e.g. after it was written to
+///
+/// ## Returns
+/// - usize = The number of pages dropped
+pub fn invalidate(device: &Arc<dyn BlockDevice>) -> usize {
+    let id = id(device);
+    evict_where(|key, _| key.0 == id)
+}
+
+/// # Pages
+/// The number of pages in the cache
+pub fn pages() -> usize {
+    PAGES.load(Ordering::Relaxed)
+}
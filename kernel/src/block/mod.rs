@@ -0,0 +1,214 @@
+//! # Block
+//! Devices that are read and written in fixed-size blocks, independent of the driver behind them.
+//!
+//! Drivers register their disks with `register_disk()`, which names them `disk0`, `disk1`, ...
+//! and registers every partition found on them as `disk0p1`, `disk0p2`, ... A driver that lets
+//! go of its device removes them again with `unregister_disks()`.
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::{Error, Result};
+use crate::scheduler::spin::Mutex;
+use crate::{info, warn};
+
+pub mod cache;
+pub mod partition;
+
+use partition::{PartitionEntry, PartitionType};
+
+/// # Block Device
+/// A device that is accessed in blocks of `block_size()` bytes
+pub trait BlockDevice: Send + Sync {
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u64;
+    /// Reads `buf.len() / block_size()` blocks starting at `lba`
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()>;
+    /// Writes `buf.len() / block_size()` blocks starting at `lba`
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()>;
+
+    /// # Write Blocks Polled
+    /// Writes like `write_blocks()`, but without waiting for a lock and polling for the
+    /// completion while interrupts are disabled, for the panic handler
+    ///
+    /// ## Returns
+    /// - Error::OperationWouldBlock = The device is in use, or cannot be written without blocking
+    fn write_blocks_polled(&self, _lba: u64, _buf: &[u8]) -> Result<()> {
+        Err(Error::OperationWouldBlock)
+    }
+
+    fn size(&self) -> u64 {
+        self.block_count() * self.block_size() as u64
+    }
+}
+
+/// # Blocks In
+/// The number of blocks `len` bytes make up, requiring them to be a multiple of the block size
+pub fn blocks_in(device: &dyn BlockDevice, len: usize) -> Result<u64> {
+    if len % device.block_size() != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    Ok((len / device.block_size()) as u64)
+}
+
+/// # Partition
+/// A range of blocks of another device, addressed from zero
+pub struct Partition {
+    parent: Arc<dyn BlockDevice>,
+    start: u64,
+    count: u64,
+}
+
+impl Partition {
+    pub fn new(parent: Arc<dyn BlockDevice>, start: u64, count: u64) -> Self {
+        Self {
+            parent,
+            start,
+            count,
+        }
+    }
+
+    fn translate(&self, lba: u64, len: usize) -> Result<u64> {
+        let blocks = blocks_in(self, len)?;
+        if lba.checked_add(blocks).map_or(true, |end| end > self.count) {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(self.start + lba)
+    }
+}
+
+impl BlockDevice for Partition {
+    fn block_size(&self) -> usize {
+        self.parent.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        let lba = self.translate(lba, buf.len())?;
+        self.parent.read_blocks(lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        let lba = self.translate(lba, buf.len())?;
+        self.parent.write_blocks(lba, buf)
+    }
+
+    fn write_blocks_polled(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        let lba = self.translate(lba, buf.len())?;
+        self.parent.write_blocks_polled(lba, buf)
+    }
+}
+
+/// # Block Device Entry
+/// A registered device together with what it is
+#[derive(Clone)]
+pub struct BlockDeviceEntry {
+    pub name: String,
+    pub device: Arc<dyn BlockDevice>,
+    /// The kind of partition, `None` for whole disks
+    pub partition: Option<PartitionType>,
+}
+
+static BLOCK_DEVICES: Mutex<Vec<BlockDeviceEntry>> = Mutex::new(Vec::new());
+static NEXT_DISK: AtomicUsize = AtomicUsize::new(0);
+
+/// # Register Disk
+/// Registers a whole disk and all partitions on it.
+///
+/// ## Returns
+/// - String = The name of the disk
+pub fn register_disk(device: Arc<dyn BlockDevice>) -> String {
+    let name = format!("disk{}", NEXT_DISK.fetch_add(1, Ordering::Relaxed));
+    let partitions = match partition::scan(&*device) {
+        Ok(partitions) => partitions,
+        Err(err) => {
+            warn!(
+                "{}: Failed to read the partition table: {}",
+                name,
+                err.text()
+            );
+            Vec::new()
+        }
+    };
+
+    let mut devices = BLOCK_DEVICES.lock();
+    devices.push(BlockDeviceEntry {
+        name: name.clone(),
+        device: device.clone(),
+        partition: None,
+    });
+    for PartitionEntry {
+        index,
+        start,
+        count,
+        ty,
+    } in partitions
+    {
+        let partition_name = format!("{}p{}", name, index);
+        info!("{}: {} blocks at {} ({})", partition_name, count, start, ty);
+        devices.push(BlockDeviceEntry {
+            name: partition_name,
+            device: Arc::new(Partition::new(device.clone(), start, count)),
+            partition: Some(ty),
+        });
+    }
+    name
+}
+
+/// # Unregister Disks
+/// Removes every disk in `disks` and the partitions on them, or none of them if one of them is
+/// still in use
+///
+/// ## Returns
+/// - Error::NoSuchDevice = A disk is not registered
+/// - Error::DeviceOrResourceBusy = A disk or a partition on it is used, e.g. by a filesystem.
+///   Besides the registry and the partitions, only the caller may hold a reference to a disk.
+pub fn unregister_disks(disks: &[&dyn BlockDevice]) -> Result<()> {
+    let address = |device: &dyn BlockDevice| device as *const dyn BlockDevice as *const u8;
+    let mut devices = BLOCK_DEVICES.lock();
+    let mut names = Vec::new();
+    for disk in disks {
+        let entry = devices
+            .iter()
+            .find(|entry| entry.partition.is_none() && address(&*entry.device) == address(*disk))
+            .ok_or(Error::NoSuchDevice)?;
+        let prefix = format!("{}p", entry.name);
+        let partitions: Vec<_> = devices
+            .iter()
+            .filter(|other| other.name.starts_with(&prefix))
+            .collect();
+        // Every partition holds the disk as its parent
+        if Arc::strong_count(&entry.device) > 2 + partitions.len()
+            || partitions
+                .iter()
+                .any(|partition| Arc::strong_count(&partition.device) > 1)
+        {
+            return Err(Error::DeviceOrResourceBusy);
+        }
+        names.push((entry.name.clone(), prefix));
+    }
+    devices.retain(|entry| {
+        !names
+            .iter()
+            .any(|(name, prefix)| entry.name == *name || entry.name.starts_with(prefix.as_str()))
+    });
+    Ok(())
+}
+
+/// # Find
+/// The device registered as `name`
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .find(|entry| entry.name == name)
+        .map(|entry| entry.device.clone())
+}
+
+/// # Devices
+/// All registered devices, partitions following the disk they are on
+pub fn devices() -> Vec<BlockDeviceEntry> {
+    BLOCK_DEVICES.lock().clone()
+}
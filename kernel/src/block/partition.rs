@@ -0,0 +1,240 @@
+//! # Partition
+//! Parsing of MBR and GPT partition tables.
+use alloc::{vec, vec::Vec};
+
+use super::BlockDevice;
+use crate::error::Result;
+use crate::math::crc32;
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_ENTRIES_OFFSET: usize = 0x1BE;
+const MBR_ENTRY_SIZE: usize = 16;
+/// The MBR partition type of the protective entry in front of a GPT
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_LBA: u64 = 1;
+/// The smallest header defined by the specification, anything shorter is corrupt
+const GPT_MIN_HEADER_SIZE: usize = 92;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+/// Sanity limit, the specification requires room for 128 entries
+const GPT_MAX_ENTRIES: usize = 1024;
+
+/// # GUID
+/// A GUID as stored on disk, the first three fields being little endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const EFI_SYSTEM: Self = Self::parse("C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+    pub const BASIC_DATA: Self = Self::parse("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7");
+    pub const LINUX_FILESYSTEM: Self = Self::parse("0FC63DAF-8483-4772-8E79-3D69D8477DE4");
+    /// A partition the kernel log is kept on, see `logdisk`
+    pub const ESQUE_LOG: Self = Self::parse("8A3C9F6E-5B1D-4E27-9C4F-2D6E0B7A1C53");
+
+    /// # Parse
+    /// Turns the textual form (`XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX`) into the on-disk layout
+    pub const fn parse(text: &str) -> Self {
+        // Where the byte at each position ends up, accounting for the little endian fields
+        const ORDER: [usize; 16] = [3, 2, 1, 0, 5, 4, 7, 6, 8, 9, 10, 11, 12, 13, 14, 15];
+        const fn nibble(c: u8) -> u8 {
+            match c {
+                b'0'..=b'9' => c - b'0',
+                b'a'..=b'f' => c - b'a' + 10,
+                b'A'..=b'F' => c - b'A' + 10,
+                _ => panic!("Invalid GUID"),
+            }
+        }
+
+        let text = text.as_bytes();
+        let mut bytes = [0u8; 16];
+        let mut byte = 0;
+        let mut i = 0;
+        while i < text.len() {
+            if text[i] == b'-' {
+                i += 1;
+                continue;
+            }
+            bytes[ORDER[byte]] = nibble(text[i]) << 4 | nibble(text[i + 1]);
+            byte += 1;
+            i += 2;
+        }
+        Self(bytes)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|byte| *byte == 0)
+    }
+
+    /// The name of well-known partition types
+    pub fn type_name(&self) -> Option<&'static str> {
+        Some(match *self {
+            Self::EFI_SYSTEM => "EFI System",
+            Self::BASIC_DATA => "Basic Data",
+            Self::LINUX_FILESYSTEM => "Linux Filesystem",
+            Self::ESQUE_LOG => "Esque Log",
+            _ => return None,
+        })
+    }
+}
+
+impl core::fmt::Display for Guid {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        for byte in &b[10..] {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// # Partition Type
+/// The type of a partition as given by its partition table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    Mbr(u8),
+    Gpt(Guid),
+}
+
+impl core::fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Mbr(ty) => write!(f, "MBR {:#04x}", ty),
+            Self::Gpt(guid) => match guid.type_name() {
+                Some(name) => write!(f, "{} ({})", name, guid),
+                None => write!(f, "{}", guid),
+            },
+        }
+    }
+}
+
+/// # Partition Entry
+/// A partition found by `scan()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /// The number of the partition, starting at 1
+    pub index: usize,
+    pub start: u64,
+    pub count: u64,
+    pub ty: PartitionType,
+}
+
+fn read_block(device: &dyn BlockDevice, lba: u64) -> Result<Vec<u8>> {
+    let mut block = vec![0u8; device.block_size()];
+    device.read_blocks(lba, &mut block)?;
+    Ok(block)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// # Scan
+/// Reads the partition table of `device`. A disk without one has no partitions.
+pub fn scan(device: &dyn BlockDevice) -> Result<Vec<PartitionEntry>> {
+    let mbr = read_block(device, 0)?;
+    if mbr.len() < 512 || mbr[510..512] != BOOT_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let mut partitions = Vec::new();
+    for index in 0..4 {
+        let entry = &mbr[MBR_ENTRIES_OFFSET + index * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        let ty = entry[4];
+        if ty == MBR_TYPE_GPT_PROTECTIVE {
+            return scan_gpt(device);
+        }
+        let start = u32_at(entry, 8) as u64;
+        let count = u32_at(entry, 12) as u64;
+        if ty == 0 || count == 0 || start + count > device.block_count() {
+            continue;
+        }
+        partitions.push(PartitionEntry {
+            index: index + 1,
+            start,
+            count,
+            ty: PartitionType::Mbr(ty),
+        });
+    }
+    Ok(partitions)
+}
+
+/// # Scan GPT
+/// Parses the primary GPT, falling back to the backup at the end of the disk if the primary one
+/// is corrupt
+fn scan_gpt(device: &dyn BlockDevice) -> Result<Vec<PartitionEntry>> {
+    if let Some(partitions) = read_gpt(device, GPT_HEADER_LBA)? {
+        return Ok(partitions);
+    }
+    crate::warn!("The primary GPT is corrupt, using the backup");
+    Ok(read_gpt(device, device.block_count() - 1)?.unwrap_or_default())
+}
+
+/// # Read GPT
+/// Parses the GPT whose header is at `lba`.
+///
+/// ## Returns
+/// - Option<Vec<PartitionEntry>> = `None` if the header or the entries fail validation
+fn read_gpt(device: &dyn BlockDevice, lba: u64) -> Result<Option<Vec<PartitionEntry>>> {
+    let header = read_block(device, lba)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+    let header_size = u32_at(&header, 12) as usize;
+    if header_size < GPT_MIN_HEADER_SIZE || header_size > header.len() {
+        return Ok(None);
+    }
+    // The checksum is calculated with its own field zeroed
+    let mut checked = header[..header_size].to_vec();
+    checked[16..20].fill(0);
+    if crc32(&checked) != u32_at(&header, 16) || u64_at(&header, 24) != lba {
+        return Ok(None);
+    }
+
+    let entries_lba = u64_at(&header, 72);
+    let entry_count = u32_at(&header, 80) as usize;
+    let entry_size = u32_at(&header, 84) as usize;
+    if entry_size < GPT_MIN_ENTRY_SIZE || entry_count > GPT_MAX_ENTRIES {
+        return Ok(None);
+    }
+    let block_size = device.block_size();
+    let len = entry_count * entry_size;
+    let mut entries = vec![0u8; (len + block_size - 1) / block_size * block_size];
+    device.read_blocks(entries_lba, &mut entries)?;
+    let entries = &entries[..len];
+    if crc32(entries) != u32_at(&header, 88) {
+        return Ok(None);
+    }
+
+    let mut partitions = Vec::new();
+    for (index, entry) in entries.chunks_exact(entry_size).enumerate() {
+        let ty = Guid(entry[0..16].try_into().unwrap());
+        if ty.is_zero() {
+            continue;
+        }
+        let first = u64_at(entry, 32);
+        let last = u64_at(entry, 40);
+        if last < first || last >= device.block_count() {
+            continue;
+        }
+        partitions.push(PartitionEntry {
+            index: index + 1,
+            start: first,
+            count: last - first + 1,
+            ty: PartitionType::Gpt(ty),
+        });
+    }
+    Ok(Some(partitions))
+}
@@ -0,0 +1,25 @@
+//! # Boot Modules
+//! Files the bootloader loaded alongside the kernel (see `bks::Module`)
+use bks::Handover;
+
+use crate::memory::{phys_to_virt, PhysicalAddress};
+
+pub trait HandoverModules {
+    /// # Module
+    /// Returns the contents of the module called `name`, accessed through the direct map
+    fn module(&self, name: &str) -> Option<&'static [u8]>;
+}
+
+impl HandoverModules for Handover {
+    fn module(&self, name: &str) -> Option<&'static [u8]> {
+        let module = self.modules().iter().find(|module| module.name() == name)?;
+        let addr = phys_to_virt(PhysicalAddress::new(module.phys_start));
+        Some(unsafe { core::slice::from_raw_parts(addr.as_ptr::<u8>(), module.len as usize) })
+    }
+}
+
+/// # Module
+/// Returns the contents of the boot module called `name`
+pub fn module(name: &str) -> Option<&'static [u8]> {
+    crate::config::handover().module(name)
+}
@@ -0,0 +1,205 @@
+//! # Breadcrumb
+//! Tells where the last boot stopped, for failures like a triple fault that reset the machine
+//! before anything is printed. At every `Milestone` of the boot the kernel writes the code of
+//! the milestone into otherwise unused CMOS registers, which keep their contents across a
+//! reset. The next boot reads them first thing: If the last boot never reached
+//! `Milestone::BootComplete`, it logs the last milestone reached and makes the early log
+//! verbose until this boot is complete.
+//!
+//! `breadcrumbs=off` leaves the CMOS alone, for firmware that uses the registers itself.
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use crate::time::rtc;
+use crate::{cmdline, earlylog, info, warn};
+
+/// The command line option that disables the breadcrumbs with `off`
+pub const OPTION: &str = "breadcrumbs";
+/// The first of the CMOS registers, beyond those the firmware of QEMU and most PCs use
+pub const FIRST_REGISTER: u8 = 0x7C;
+/// The registers: Magic, milestone, failed boots, check
+pub const REGISTERS: usize = 4;
+pub const MAGIC: u8 = 0xE5;
+
+/// Whether `reach()` writes the CMOS
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// The milestones reached by this boot, by code, which stay below 64
+static REACHED: AtomicU64 = AtomicU64::new(0);
+/// The incomplete boots before this one
+static FAILED_BOOTS: AtomicU8 = AtomicU8::new(0);
+
+/// # Milestone
+/// A point of the boot. The codes are written to the CMOS and read by whatever kernel boots
+/// next, so they never change: New milestones get the next free code, regardless of when they
+/// are reached, and the codes of removed ones are not reused.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Milestone {
+    /// `kmain` was entered and the command line read
+    KernelEntered = 1,
+    /// The IDT is loaded, exceptions are reported from here on
+    IdtLoaded = 2,
+    /// The kernel runs on its own page tables
+    PagingSwitched = 3,
+    /// The local APIC of the bootstrap CPU is enabled
+    ApicEnabled = 4,
+    /// A spawned task ran for the first time
+    FirstTaskStarted = 5,
+    /// Everything is initialized, the boot did not fail
+    BootComplete = 6,
+}
+
+impl Milestone {
+    pub const ALL: [Self; 6] = [
+        Self::KernelEntered,
+        Self::IdtLoaded,
+        Self::PagingSwitched,
+        Self::ApicEnabled,
+        Self::FirstTaskStarted,
+        Self::BootComplete,
+    ];
+
+    /// # From Code
+    /// The milestone with `code`
+    ///
+    /// ## Returns
+    /// - None = No milestone has that code, a newer kernel may know it
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|milestone| *milestone as u8 == code)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::KernelEntered => "kernel entered",
+            Self::IdtLoaded => "IDT loaded",
+            Self::PagingSwitched => "paging switched",
+            Self::ApicEnabled => "APIC enabled",
+            Self::FirstTaskStarted => "first task started",
+            Self::BootComplete => "boot complete",
+        }
+    }
+}
+
+/// # Breadcrumb
+/// What the registers hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breadcrumb {
+    /// The code of the last milestone reached
+    pub milestone: u8,
+    /// How many boots in a row did not complete before the one that left the breadcrumb
+    pub failed_boots: u8,
+}
+
+impl Breadcrumb {
+    /// # Encode
+    /// The values of the registers, starting at `FIRST_REGISTER`
+    pub fn encode(&self) -> [u8; REGISTERS] {
+        [
+            MAGIC,
+            self.milestone,
+            self.failed_boots,
+            check(self.milestone, self.failed_boots),
+        ]
+    }
+
+    /// # Decode
+    /// The breadcrumb in the registers `raw`
+    ///
+    /// ## Returns
+    /// - None = The registers hold something else, like after a power cycle
+    pub fn decode(raw: [u8; REGISTERS]) -> Option<Self> {
+        let [magic, milestone, failed_boots, sum] = raw;
+        (magic == MAGIC && sum == check(milestone, failed_boots)).then(|| Self {
+            milestone,
+            failed_boots,
+        })
+    }
+
+    /// Whether the boot that left it never completed
+    pub fn is_incomplete(&self) -> bool {
+        self.milestone != Milestone::BootComplete as u8
+    }
+}
+
+fn check(milestone: u8, failed_boots: u8) -> u8 {
+    !(MAGIC ^ milestone ^ failed_boots.rotate_left(4))
+}
+
+/// # Read
+/// The breadcrumb in the CMOS, which `init()` replaced with the one of this boot
+pub fn read() -> Option<Breadcrumb> {
+    let mut raw = [0; REGISTERS];
+    for (i, value) in raw.iter_mut().enumerate() {
+        *value = rtc::read_register(FIRST_REGISTER + i as u8);
+    }
+    Breadcrumb::decode(raw)
+}
+
+fn write(breadcrumb: Breadcrumb) {
+    for (i, value) in breadcrumb.encode().into_iter().enumerate() {
+        rtc::write_register(FIRST_REGISTER + i as u8, value);
+    }
+}
+
+/// # Init
+/// Reports the last boot if it did not complete and starts the breadcrumbs of this one. Called
+/// right after the command line is read.
+pub fn init() {
+    if cmdline::value(OPTION) != Some("off") {
+        if let Some(last) = read().filter(Breadcrumb::is_incomplete) {
+            report(last);
+        }
+        ENABLED.store(true, Ordering::Release);
+    }
+    reach(Milestone::KernelEntered);
+}
+
+fn report(last: Breadcrumb) {
+    earlylog::set_verbose(true);
+    let failed = last.failed_boots.saturating_add(1);
+    FAILED_BOOTS.store(failed, Ordering::Relaxed);
+    match Milestone::from_code(last.milestone) {
+        Some(milestone) => warn!(
+            "breadcrumb: The last boot stopped after '{}' ({} incomplete boots in a row)",
+            milestone.name(),
+            failed
+        ),
+        None => warn!(
+            "breadcrumb: The last boot stopped after unknown milestone {} ({} incomplete boots in a row)",
+            last.milestone, failed
+        ),
+    }
+    info!("breadcrumb: Verbose logging until the boot is complete");
+}
+
+/// # Reach
+/// Notes that the boot reached `milestone`, only the first time. Cheap once it was reached, so
+/// it may be called on hot paths.
+pub fn reach(milestone: Milestone) {
+    let bit = 1 << milestone as u8;
+    if REACHED.fetch_or(bit, Ordering::AcqRel) & bit != 0 || !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    let complete = milestone == Milestone::BootComplete;
+    write(Breadcrumb {
+        milestone: milestone as u8,
+        failed_boots: if complete {
+            0
+        } else {
+            FAILED_BOOTS.load(Ordering::Relaxed)
+        },
+    });
+    if earlylog::is_verbose() {
+        info!("breadcrumb: Reached '{}'", milestone.name());
+        if complete {
+            earlylog::set_verbose(false);
+        }
+    }
+}
+
+/// # Has Reached
+/// Whether this boot reached `milestone`
+pub fn has_reached(milestone: Milestone) -> bool {
+    REACHED.load(Ordering::Acquire) & (1 << milestone as u8) != 0
+}
@@ -0,0 +1,57 @@
+//! # Build Info
+//! What the kernel was built from, generated by build.rs when it is compiled: the commit, the time
+//! of the build, the enabled cargo features and the target. It is the first line of the boot
+//! log, `version` prints it in the shell and `uname()` returns it to userspace.
+use core::fmt::{self, Display};
+
+use crate::time::DateTime;
+
+include!(concat!(env!("OUT_DIR"), "/buildinfo.rs"));
+
+pub const NAME: &str = "esque";
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// # Has Feature
+/// Whether the kernel was built with the cargo feature `feature`
+pub fn has_feature(feature: &str) -> bool {
+    FEATURES.contains(&feature)
+}
+
+/// # Build Time
+/// When the kernel was built, in UTC
+pub fn build_time() -> DateTime {
+    DateTime::from_unix_seconds(BUILD_TIMESTAMP as i64)
+}
+
+/// # Machine
+/// The architecture of `TARGET`, such as `x86_64`
+pub fn machine() -> &'static str {
+    TARGET.split('-').next().unwrap_or(TARGET)
+}
+
+/// # Banner
+/// The build on one line, e.g. `esque 0.1.0 (0123456789ab, built 2024-02-29 12:34:56 UTC for
+/// x86_64-unknown-none, debug) features: gdbstub nvme`
+pub struct Banner;
+
+impl Display for Banner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({}, built {} UTC for {}, {})",
+            NAME,
+            VERSION,
+            GIT_HASH,
+            build_time(),
+            TARGET,
+            PROFILE
+        )?;
+        if !FEATURES.is_empty() {
+            write!(f, " features:")?;
+            for feature in FEATURES {
+                write!(f, " {}", feature)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! # Command Line
+//! Options for the kernel, separated by whitespace: Either a plain `flag` or a `name=value`
+//! pair. The bootloader has no notion of a command line, so it is read from the boot module
+//! called `cmdline`.
+use bks::Handover;
+use spin::Once;
+
+use crate::boot_modules::{self, HandoverModules};
+
+/// The name of the boot module holding the command line
+pub const CMDLINE_MODULE: &str = "cmdline";
+
+static CMDLINE: Once<&'static str> = Once::new();
+
+/// # Init Command Line
+/// Reads the command line from the modules of `handover`. Called first thing on boot, so the
+/// options can be read before the handover is stored for good.
+pub fn init_cmdline(handover: &Handover) {
+    CMDLINE.call_once(|| parse_module(handover.module(CMDLINE_MODULE)));
+}
+
+/// # Command Line
+/// The whole command line, empty if there is none
+pub fn cmdline() -> &'static str {
+    CMDLINE.call_once(|| parse_module(boot_modules::module(CMDLINE_MODULE)))
+}
+
+fn parse_module(module: Option<&'static [u8]>) -> &'static str {
+    module
+        .and_then(|module| core::str::from_utf8(module).ok())
+        .unwrap_or("")
+        .trim()
+}
+
+fn options() -> impl Iterator<Item = (&'static str, Option<&'static str>)> {
+    cmdline()
+        .split_whitespace()
+        .map(|option| match option.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (option, None),
+        })
+}
+
+/// # Value
+/// The value of the last `name=value` option
+pub fn value(name: &str) -> Option<&'static str> {
+    options()
+        .filter(|(option, _)| *option == name)
+        .filter_map(|(_, value)| value)
+        .last()
+}
+
+/// # Values
+/// The values of every `name=value` option, for options that can be given more than once
+pub fn values<'a>(name: &'a str) -> impl Iterator<Item = &'static str> + 'a {
+    options()
+        .filter(move |(option, _)| *option == name)
+        .filter_map(|(_, value)| value)
+}
+
+/// # Flag
+/// Whether `name` has been given, either on its own or with a value other than `0` or `false`
+pub fn flag(name: &str) -> bool {
+    options()
+        .filter(|(option, _)| *option == name)
+        .last()
+        .map_or(false, |(_, value)| {
+            !matches!(value, Some("0") | Some("false"))
+        })
+}
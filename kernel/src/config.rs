@@ -2,7 +2,9 @@ use core::mem::MaybeUninit;
 
 use alloc::sync::Arc;
 use bks::{Config, Handover};
-use spin::{Mutex, Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use spin::Once;
+
+use crate::scheduler::spin::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 pub static KCONFIG: Mutex<Config> = Mutex::new(Config::default());
 
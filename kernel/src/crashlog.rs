@@ -0,0 +1,293 @@
+//! # Crash Log
+//! A record of the last panic that survives a soft reboot, for machines nobody watches the
+//! screen or the serial port of. The panic handler writes the panic message, the end of the
+//! kernel log and the statistics into a reserved region of RAM, the next boot logs what it
+//! finds there and clears it. A reset keeps the contents of RAM, a power cycle does not.
+//!
+//! The region is at `DEFAULT_ADDRESS` unless `crashlog=<address>` moves it, `crashlog=off`
+//! disables it. It is only used if the memory map says it is usable RAM.
+//!
+//! A panic is also noted in a UEFI variable, see `uefi_rt::Variable::LastBootFailed`, which the
+//! next boot finds even after a power cycle.
+use core::fmt::{Display, Write};
+use core::mem::size_of;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use bks::PAGE_SIZE;
+use memoffset::offset_of;
+use static_assertions::const_assert_eq;
+
+use crate::error::Result;
+use crate::memory::map::{MemoryKind, MemoryMap};
+use crate::memory::{phys_to_virt, reserved, PhysicalAddress};
+use crate::stats::Stat;
+use crate::{cmdline, info, klog, math, stats, warn};
+
+/// The command line option with the address of the region, or `off`
+pub const OPTION: &str = "crashlog";
+/// Where the region is unless `OPTION` says otherwise
+pub const DEFAULT_ADDRESS: u64 = 0x0200_0000;
+pub const MAGIC: [u8; 8] = *b"ESQCRASH";
+/// The version of the layout, records of other versions are discarded
+pub const VERSION: u32 = 1;
+/// The size of the region
+pub const RECORD_SIZE: usize = 0x8000;
+const HEADER_SIZE: usize = 32;
+pub const MESSAGE_SIZE: usize = 0x400;
+/// How much of the end of the kernel log is kept
+pub const LOG_TAIL_SIZE: usize = 0x4000;
+pub const STATS_SIZE: usize = RECORD_SIZE - HEADER_SIZE - MESSAGE_SIZE - LOG_TAIL_SIZE;
+
+/// The physical address of the region, zero without one
+static ADDRESS: AtomicU64 = AtomicU64::new(0);
+/// Set by the first panic, the record of a second one would only describe the first
+static SAVED: AtomicBool = AtomicBool::new(false);
+
+crate::initcall! {
+    name: "crashlog",
+    stage: EarlyMemory,
+    deps: [],
+    fatal: false,
+    init: restore,
+}
+
+/// # Record State
+/// What a region holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordState {
+    /// Nothing, the last boot did not panic
+    Empty,
+    /// A record that does not match its checksum or is of another version
+    Corrupt,
+    Valid,
+}
+
+/// # Crash Record
+/// The layout of the region. The checksum covers everything after it, the sections hold text
+/// and are cut off once they are full.
+#[repr(C)]
+pub struct CrashRecord {
+    magic: [u8; 8],
+    version: u32,
+    checksum: u32,
+    message_len: u32,
+    log_len: u32,
+    stats_len: u32,
+    _reserved: u32,
+    message: [u8; MESSAGE_SIZE],
+    log: [u8; LOG_TAIL_SIZE],
+    stats: [u8; STATS_SIZE],
+}
+
+const_assert_eq!(size_of::<CrashRecord>(), RECORD_SIZE);
+const_assert_eq!(offset_of!(CrashRecord, message), HEADER_SIZE);
+
+impl CrashRecord {
+    /// # Write
+    /// Fills the record and seals it with the magic and the checksum. `read_log` copies the end
+    /// of the log into the buffer it is given and returns the number of bytes. Statistics that
+    /// are zero are left out.
+    pub fn write(
+        &mut self,
+        message: &dyn Display,
+        read_log: impl FnOnce(&mut [u8]) -> usize,
+        stats: impl Iterator<Item = Stat>,
+    ) {
+        self.clear();
+        let mut section = Section::new(&mut self.message);
+        let _ = write!(section, "{}", message);
+        self.message_len = section.len as u32;
+
+        self.log_len = read_log(&mut self.log).min(LOG_TAIL_SIZE) as u32;
+
+        let mut section = Section::new(&mut self.stats);
+        for stat in stats.filter(|stat| stat.value != 0) {
+            let _ = writeln!(section, "{}", stat);
+        }
+        self.stats_len = section.len as u32;
+
+        self.magic = MAGIC;
+        self.version = VERSION;
+        self.checksum = self.compute_checksum();
+    }
+
+    pub fn state(&self) -> RecordState {
+        if self.magic != MAGIC {
+            RecordState::Empty
+        } else if self.version != VERSION
+            || self.checksum != self.compute_checksum()
+            || self.message_len as usize > MESSAGE_SIZE
+            || self.log_len as usize > LOG_TAIL_SIZE
+            || self.stats_len as usize > STATS_SIZE
+        {
+            RecordState::Corrupt
+        } else {
+            RecordState::Valid
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        text(&self.message, self.message_len)
+    }
+
+    /// The end of the kernel log, possibly starting in the middle of a line
+    pub fn log(&self) -> &str {
+        text(&self.log, self.log_len)
+    }
+
+    /// One `Stat` per line
+    pub fn stats(&self) -> &str {
+        text(&self.stats, self.stats_len)
+    }
+
+    pub fn clear(&mut self) {
+        unsafe { core::ptr::write_bytes(self as *mut Self, 0, 1) };
+    }
+
+    /// The CRC32 of everything after the checksum
+    fn compute_checksum(&self) -> u32 {
+        let start = offset_of!(CrashRecord, message_len);
+        let bytes =
+            unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, RECORD_SIZE) };
+        math::crc32(&bytes[start..])
+    }
+}
+
+/// # Section
+/// Writes text into a section of the record, cutting off what does not fit
+struct Section<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Section<'a> {
+    fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, len: 0 }
+    }
+}
+
+impl Write for Section<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let len = s.len().min(self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// The valid UTF-8 of the first `len` bytes of `section`, without a character that was cut off
+/// at either end
+fn text(section: &[u8], len: u32) -> &str {
+    let bytes = &section[..(len as usize).min(section.len())];
+    // A log tail may start in the middle of a character
+    let start = bytes
+        .iter()
+        .take(3)
+        .position(|byte| byte & 0xC0 != 0x80)
+        .unwrap_or(0);
+    match core::str::from_utf8(&bytes[start..]) {
+        Ok(text) => text,
+        Err(e) => core::str::from_utf8(&bytes[start..start + e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+/// # Reserve
+/// Keeps the frame allocator away from the region, if there is one. Called before the frame
+/// allocator reads the memory map `map`.
+pub fn reserve(mut map: MemoryMap) {
+    let address = match cmdline::value(OPTION) {
+        None => DEFAULT_ADDRESS,
+        Some("off") => return,
+        Some(value) => match math::parse_u64(value) {
+            Some(address) if address % PAGE_SIZE == 0 => address,
+            _ => {
+                warn!("crashlog: Invalid address '{}'", value);
+                return;
+            }
+        },
+    };
+    let end = address.saturating_add(RECORD_SIZE as u64);
+    let usable = PhysicalAddress::try_new(end - 1).is_ok()
+        && map.any(|region| {
+            region.kind == MemoryKind::Usable
+                && region.start.as_u64() <= address
+                && end <= region.end().as_u64()
+        });
+    if !usable {
+        warn!(
+            "crashlog: {:#x} is not usable RAM, not keeping a crash log",
+            address
+        );
+        return;
+    }
+    reserved::reserve(
+        "crash log",
+        PhysicalAddress::new(address),
+        RECORD_SIZE as u64,
+    );
+    ADDRESS.store(address, Ordering::Release);
+}
+
+/// # Record
+/// The record in the region, if there is one
+///
+/// ## Safety
+/// There may be only one reference at a time, which `save()` and `restore()` make sure of
+unsafe fn record() -> Option<&'static mut CrashRecord> {
+    let address = ADDRESS.load(Ordering::Acquire);
+    if address == 0 {
+        return None;
+    }
+    Some(&mut *phys_to_virt(PhysicalAddress::new(address)).as_mut_ptr::<CrashRecord>())
+}
+
+/// # Save
+/// Writes the record of a panic. Called by the panic handler once it printed its report, so the
+/// report is part of the log tail. Only the first panic is saved.
+pub fn save(info: &PanicInfo) {
+    if SAVED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    crate::uefi_rt::record_failed_boot();
+    let record = match unsafe { record() } {
+        Some(record) => record,
+        None => return,
+    };
+    record.write(
+        info,
+        |buffer| klog::try_read_tail(buffer).unwrap_or(0),
+        stats::snapshot(),
+    );
+    // A reset does not necessarily write back the caches
+    unsafe { comasm::write_back_and_invalidate_caches() };
+}
+
+/// # Restore
+/// Logs the record the previous boot left behind and clears the region
+fn restore() -> Result<()> {
+    // A panic before this ran would overwrite the record that is being read
+    if SAVED.swap(true, Ordering::AcqRel) {
+        return Ok(());
+    }
+    let record = match unsafe { record() } {
+        Some(record) => record,
+        None => return Ok(()),
+    };
+    match record.state() {
+        RecordState::Empty => {}
+        RecordState::Corrupt => warn!("crashlog: Discarding a damaged crash record"),
+        RecordState::Valid => {
+            warn!("previous boot crashed: {}", record.message());
+            for line in record.log().lines() {
+                info!("previous boot: {}", line);
+            }
+            for line in record.stats().lines() {
+                info!("previous boot: {}", line);
+            }
+        }
+    }
+    record.clear();
+    SAVED.store(false, Ordering::Release);
+    Ok(())
+}
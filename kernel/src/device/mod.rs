@@ -9,6 +9,7 @@ use crate::error::Result;
 use self::{kernel::Kernel, traits::Device};
 pub mod kernel;
 pub mod traits;
+pub mod tree;
 
 pub type ArcDevice = Arc<dyn Device + Send + Sync>;
 
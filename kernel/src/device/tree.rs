@@ -0,0 +1,396 @@
+//! # Device Tree
+//! Every device a driver found, in a tree of buses and the devices on them. Drivers register
+//! a node when they bind, together with the I/O ports, MMIO ranges and IRQs they claim. A node
+//! has a stable path made of the names from the root down, e.g. `/platform/i8042/keyboard`
+//! or `/pci/00:1f.2`, which does not depend on the order the drivers ran in.
+//!
+//! Two nodes never hold overlapping I/O ports or MMIO ranges, a registration that would is
+//! refused. IRQs can be shared.
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::error::{Error, Result};
+use crate::scheduler::spin::Mutex;
+use crate::warn;
+
+/// The name of the root node legacy devices are registered below
+pub const PLATFORM: &str = "platform";
+
+/// # Device ID
+/// The index of a node, which stays the same while the node is registered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId(usize);
+
+/// # Device Class
+/// What kind of device a node is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceClass {
+    Bus,
+    Bridge,
+    Storage,
+    Network,
+    Display,
+    Input,
+    Serial,
+    Timer,
+    Other,
+}
+
+impl DeviceClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceClass::Bus => "bus",
+            DeviceClass::Bridge => "bridge",
+            DeviceClass::Storage => "storage",
+            DeviceClass::Network => "network",
+            DeviceClass::Display => "display",
+            DeviceClass::Input => "input",
+            DeviceClass::Serial => "serial",
+            DeviceClass::Timer => "timer",
+            DeviceClass::Other => "other",
+        }
+    }
+}
+
+/// # Resource
+/// Something a driver claims for its device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// `len` I/O ports starting at `base`
+    IoPorts { base: u16, len: u16 },
+    /// `len` bytes of physical memory starting at `base`
+    Mmio { base: u64, len: u64 },
+    /// A line of the interrupt controller
+    Irq(u8),
+}
+
+impl Resource {
+    /// The first and the last address of a port or memory range
+    fn span(&self) -> Option<(u64, u64)> {
+        match *self {
+            Resource::IoPorts { base, len } if len > 0 => {
+                Some((base as u64, base as u64 + len as u64 - 1))
+            }
+            Resource::Mmio { base, len } if len > 0 => Some((base, base.saturating_add(len - 1))),
+            _ => None,
+        }
+    }
+
+    /// # Overlaps
+    /// Whether `self` and `other` are ranges of the same kind that share an address. IRQs never
+    /// overlap.
+    pub fn overlaps(&self, other: &Resource) -> bool {
+        let same_kind = matches!(
+            (self, other),
+            (Resource::IoPorts { .. }, Resource::IoPorts { .. })
+                | (Resource::Mmio { .. }, Resource::Mmio { .. })
+        );
+        match (self.span(), other.span()) {
+            (Some((start, end)), Some((other_start, other_end))) if same_kind => {
+                start <= other_end && other_start <= end
+            }
+            _ => false,
+        }
+    }
+}
+
+impl core::fmt::Display for Resource {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match (self, self.span()) {
+            (Resource::Irq(irq), _) => write!(f, "irq {}", irq),
+            (Resource::IoPorts { .. }, Some((start, end))) => {
+                write!(f, "io {:#x}-{:#x}", start, end)
+            }
+            (Resource::Mmio { .. }, Some((start, end))) => {
+                write!(f, "mmio {:#x}-{:#x}", start, end)
+            }
+            (Resource::IoPorts { base, .. }, None) => write!(f, "io {:#x} (empty)", base),
+            (Resource::Mmio { base, .. }, None) => write!(f, "mmio {:#x} (empty)", base),
+        }
+    }
+}
+
+/// # Shutdown Hook
+/// Called with `data` before the machine powers off or restarts, to stop the device in a way it
+/// survives losing power in, e.g. with its write cache flushed. Runs with interrupts disabled,
+/// once every other CPU has been stopped, so it must neither block nor take locks others may hold.
+#[derive(Debug, Clone, Copy)]
+pub struct ShutdownHook {
+    pub func: fn(usize),
+    pub data: usize,
+}
+
+/// # Device Node
+/// A device and what its driver claimed for it
+#[derive(Debug, Clone)]
+pub struct DeviceNode {
+    pub name: String,
+    pub class: DeviceClass,
+    pub parent: Option<DeviceId>,
+    /// The driver that registered the node
+    pub driver: &'static str,
+    pub resources: Vec<Resource>,
+    pub shutdown: Option<ShutdownHook>,
+}
+
+/// Registered nodes by their ID, the slot of an unregistered node stays empty
+static DEVICES: Mutex<Vec<Option<DeviceNode>>> = Mutex::new(Vec::new());
+
+/// The path of the node at `id` in `nodes`
+fn path_in(nodes: &[Option<DeviceNode>], id: DeviceId) -> String {
+    let mut names = Vec::new();
+    let mut next = Some(id);
+    while let Some(DeviceId(idx)) = next {
+        match nodes.get(idx).and_then(|node| node.as_ref()) {
+            Some(node) => {
+                names.push(node.name.as_str());
+                next = node.parent;
+            }
+            None => break,
+        }
+    }
+    let mut path = String::new();
+    for name in names.iter().rev() {
+        path.push('/');
+        path.push_str(name);
+    }
+    path
+}
+
+fn child_in(
+    nodes: &[Option<DeviceNode>],
+    parent: Option<DeviceId>,
+    name: &str,
+) -> Option<DeviceId> {
+    nodes
+        .iter()
+        .position(|node| {
+            node.as_ref()
+                .map_or(false, |node| node.parent == parent && node.name == name)
+        })
+        .map(DeviceId)
+}
+
+/// # Register
+/// Adds the device `name` below `parent`, or as a root node, claiming `resources` for it
+///
+/// ## Returns
+/// - DeviceId = The ID of the new node
+/// - Error::NoSuchDevice = `parent` is not registered
+/// - Error::InvalidArgument = `name` is empty or contains a `/`
+/// - Error::AlreadyExists = `parent` has a child called `name` already
+/// - Error::DeviceOrResourceBusy = Another node holds a port or memory range in `resources`,
+///   both claimants are logged
+pub fn register(
+    name: &str,
+    class: DeviceClass,
+    parent: Option<DeviceId>,
+    driver: &'static str,
+    resources: &[Resource],
+) -> Result<DeviceId> {
+    if name.is_empty() || name.contains('/') {
+        return Err(Error::InvalidArgument);
+    }
+    let mut nodes = DEVICES.lock();
+    if let Some(DeviceId(idx)) = parent {
+        if nodes.get(idx).map_or(true, |node| node.is_none()) {
+            return Err(Error::NoSuchDevice);
+        }
+    }
+    if child_in(&nodes, parent, name).is_some() {
+        return Err(Error::AlreadyExists);
+    }
+    for (idx, node) in nodes.iter().enumerate() {
+        let node = match node {
+            Some(node) => node,
+            None => continue,
+        };
+        for held in &node.resources {
+            if let Some(claimed) = resources.iter().find(|claimed| claimed.overlaps(held)) {
+                let mut path = parent.map_or(String::new(), |parent| path_in(&nodes, parent));
+                path.push('/');
+                path.push_str(name);
+                warn!(
+                    "devices: {} ({}) claims {}, but {} ({}) holds {}",
+                    path,
+                    driver,
+                    claimed,
+                    path_in(&nodes, DeviceId(idx)),
+                    node.driver,
+                    held
+                );
+                return Err(Error::DeviceOrResourceBusy);
+            }
+        }
+    }
+    let node = Some(DeviceNode {
+        name: String::from(name),
+        class,
+        parent,
+        driver,
+        resources: resources.to_vec(),
+        shutdown: None,
+    });
+    let idx = match nodes.iter().position(|node| node.is_none()) {
+        Some(idx) => {
+            nodes[idx] = node;
+            idx
+        }
+        None => {
+            nodes.push(node);
+            nodes.len() - 1
+        }
+    };
+    Ok(DeviceId(idx))
+}
+
+/// # Unregister
+/// Removes the node `id` and releases its resources
+///
+/// ## Returns
+/// - Error::NoSuchDevice = `id` is not registered
+/// - Error::DeviceOrResourceBusy = The node has children, they have to go first
+pub fn unregister(id: DeviceId) -> Result<()> {
+    let mut nodes = DEVICES.lock();
+    if nodes.get(id.0).map_or(true, |node| node.is_none()) {
+        return Err(Error::NoSuchDevice);
+    }
+    if nodes.iter().flatten().any(|node| node.parent == Some(id)) {
+        return Err(Error::DeviceOrResourceBusy);
+    }
+    nodes[id.0] = None;
+    Ok(())
+}
+
+/// # Set Shutdown Hook
+/// Makes `func` run with `data` for the node `id` when the machine powers off or restarts,
+/// replacing the hook it had
+///
+/// ## Returns
+/// - Error::NoSuchDevice = `id` is not registered
+pub fn set_shutdown_hook(id: DeviceId, func: fn(usize), data: usize) -> Result<()> {
+    let mut nodes = DEVICES.lock();
+    let node = nodes
+        .get_mut(id.0)
+        .and_then(|node| node.as_mut())
+        .ok_or(Error::NoSuchDevice)?;
+    node.shutdown = Some(ShutdownHook { func, data });
+    Ok(())
+}
+
+/// # Clear Shutdown Hook
+/// Removes the shutdown hook of the node `id`, once its driver let go of the device
+///
+/// ## Returns
+/// - Error::NoSuchDevice = `id` is not registered
+pub fn clear_shutdown_hook(id: DeviceId) -> Result<()> {
+    let mut nodes = DEVICES.lock();
+    let node = nodes
+        .get_mut(id.0)
+        .and_then(|node| node.as_mut())
+        .ok_or(Error::NoSuchDevice)?;
+    node.shutdown = None;
+    Ok(())
+}
+
+/// The depth of the node `id` below `root`, which is the whole tree for `None`
+fn depth_in(nodes: &[Option<DeviceNode>], id: DeviceId, root: Option<DeviceId>) -> Option<usize> {
+    let mut depth = 0;
+    let mut next = Some(id);
+    while let Some(current) = next {
+        if Some(current) == root {
+            return Some(depth);
+        }
+        next = nodes.get(current.0)?.as_ref()?.parent;
+        depth += 1;
+    }
+    root.is_none().then(|| depth)
+}
+
+/// # Run Shutdown Hooks
+/// Runs the shutdown hooks of `root` and every node below it, or of the whole tree for `None`.
+/// Children go before their parents, so a device is stopped before the bus it sits on. The tree
+/// is not locked while the hooks run.
+///
+/// ## Returns
+/// - usize = The number of hooks that ran, none if the tree is held by someone else
+pub fn run_shutdown_hooks(root: Option<DeviceId>) -> usize {
+    let mut hooks = match DEVICES.try_lock() {
+        Some(nodes) => nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, node)| {
+                let hook = node.as_ref()?.shutdown?;
+                Some((depth_in(&nodes, DeviceId(idx), root)?, hook))
+            })
+            .collect::<Vec<_>>(),
+        None => {
+            warn!("devices: The tree is held, skipping the shutdown hooks");
+            return 0;
+        }
+    };
+    hooks.sort_by_key(|(depth, _)| core::cmp::Reverse(*depth));
+    for (_, hook) in &hooks {
+        (hook.func)(hook.data);
+    }
+    hooks.len()
+}
+
+/// # Platform
+/// The root node of legacy devices, such as the PIT or COM1, registered by the first caller
+pub fn platform() -> Result<DeviceId> {
+    match find(&["/", PLATFORM].concat()) {
+        Some(id) => Ok(id),
+        None => register(PLATFORM, DeviceClass::Bus, None, PLATFORM, &[]),
+    }
+}
+
+/// # Get
+/// A copy of the node `id`
+pub fn get(id: DeviceId) -> Option<DeviceNode> {
+    DEVICES.lock().get(id.0).cloned().flatten()
+}
+
+/// # Path
+/// The path of the node `id`, such as `/pci/00:02.0`
+pub fn path(id: DeviceId) -> Option<String> {
+    let nodes = DEVICES.lock();
+    match nodes.get(id.0) {
+        Some(Some(_)) => Some(path_in(&nodes, id)),
+        _ => None,
+    }
+}
+
+/// # Find
+/// The node at the absolute `path`
+pub fn find(path: &str) -> Option<DeviceId> {
+    let nodes = DEVICES.lock();
+    let mut found = None;
+    for name in path.strip_prefix('/')?.split('/') {
+        found = Some(child_in(&nodes, found, name)?);
+    }
+    found
+}
+
+/// # Walk
+/// Calls `f` with the depth, the ID and the node of every registered node, depth first. The
+/// children of a node follow it ordered by their IDs.
+pub fn walk(mut f: impl FnMut(usize, DeviceId, &DeviceNode)) {
+    let nodes = DEVICES.lock().clone();
+    let mut stack: Vec<(usize, DeviceId)> = Vec::new();
+    let children = |parent: Option<DeviceId>| {
+        nodes
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(move |(_, node)| node.as_ref().map_or(false, |node| node.parent == parent))
+            .map(|(idx, _)| DeviceId(idx))
+    };
+    stack.extend(children(None).map(|id| (0, id)));
+    while let Some((depth, id)) = stack.pop() {
+        if let Some(node) = &nodes[id.0] {
+            f(depth, id, node);
+        }
+        stack.extend(children(Some(id)).map(|child| (depth + 1, child)));
+    }
+}
@@ -0,0 +1,705 @@
+//! # AHCI
+//! A driver for SATA drives attached to an AHCI controller (PCI class 01:06).
+//!
+//! Commands are issued one at a time through slot 0 of every port. Their completion is signalled
+//! through MSI-X or MSI, unless the `ahci.poll` flag is given on the command line or neither can
+//! be set up, and is polled for otherwise. Commands issued while identifying the drives are always
+//! polled.
+use alloc::{sync::Arc, vec::Vec};
+use bks::PAGE_SIZE;
+
+use crate::arch::interrupts;
+use crate::block::{self, blocks_in, BlockDevice};
+use crate::error::{Error, Result};
+use crate::irq::msi::{self, MsiVectors};
+use crate::memory::dma::{BounceBuffer, DmaBuffer, DmaConstraints, DmaLayout, DmaRange};
+use crate::memory::paging::pat::MemoryType;
+use crate::memory::{map_mmio, virt_to_phys, PhysicalAddress, VirtualAddress};
+use crate::pci::{self, PciDevice, PciDriver};
+use crate::power::notifier::{self, PowerAction};
+use crate::scheduler::spin::Mutex;
+use crate::scheduler::{self, sync, WaitQueue};
+use crate::{cmdline, debug, info, warn, watchdog};
+
+pub const SECTOR_SIZE: usize = 512;
+pub const PCI_CLASS_MASS_STORAGE: u8 = 0x01;
+pub const PCI_SUBCLASS_SATA: u8 = 0x06;
+/// ABAR is always BAR5
+const ABAR_INDEX: u64 = 5;
+const MAX_PORTS: usize = 32;
+/// How often a register is polled before giving up
+const TIMEOUT_SPINS: u32 = 1_000_000;
+/// How long a command may take when waiting for its interrupt
+const TIMEOUT_MS: u64 = 5000;
+/// Command line flag that disables MSI-X and MSI, completions are polled instead
+pub const POLL_FLAG: &str = "ahci.poll";
+
+/// The command table (header and PRDT) fills exactly one page
+const PRDT_ENTRIES: usize = (PAGE_SIZE as usize - 0x80) / core::mem::size_of::<PrdtEntry>();
+/// Every PRDT entry describes at most one page of the buffer, which may not be page aligned
+const MAX_SECTORS_PER_COMMAND: usize = (PRDT_ENTRIES - 1) * PAGE_SIZE as usize / SECTOR_SIZE;
+
+enumtastic::const_enum! {
+    // Serial ATA AHCI 1.3.1, 3.1 - Generic Host Control
+    pub enum HbaRegister: u64 => {
+        Capabilities = 0x00,
+        GlobalHostControl = 0x04,
+        InterruptStatus = 0x08,
+        PortsImplemented = 0x0C,
+        Version = 0x10,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum GlobalHostControl: u32 => {
+        HbaReset = 1 << 0,
+        InterruptEnable = 1 << 1,
+        AhciEnable = 1 << 31,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    // Serial ATA AHCI 1.3.1, 3.3 - Port Registers, relative to the port
+    pub enum PortRegister: u64 => {
+        CommandListBase = 0x00,
+        CommandListBaseUpper = 0x04,
+        FisBase = 0x08,
+        FisBaseUpper = 0x0C,
+        InterruptStatus = 0x10,
+        InterruptEnable = 0x14,
+        Command = 0x18,
+        TaskFileData = 0x20,
+        Signature = 0x24,
+        SataStatus = 0x28,
+        SataControl = 0x2C,
+        SataError = 0x30,
+        SataActive = 0x34,
+        CommandIssue = 0x38,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum PortCommand: u32 => {
+        Start = 1 << 0,
+        SpinUpDevice = 1 << 1,
+        PowerOnDevice = 1 << 2,
+        FisReceiveEnable = 1 << 4,
+        FisReceiveRunning = 1 << 14,
+        CommandListRunning = 1 << 15,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum AtaCommand: u8 => {
+        ReadDmaExt = 0x25,
+        WriteDmaExt = 0x35,
+        FlushCacheExt = 0xEA,
+        Identify = 0xEC,
+    }
+
+    impl {}
+}
+
+const PORT_REGISTERS_BASE: u64 = 0x100;
+const PORT_REGISTERS_SIZE: u64 = 0x80;
+const SIGNATURE_ATA: u32 = 0x0000_0101;
+/// CAP.S64A: The HBA takes 64 bit addresses, otherwise all its memory has to be below 4 GiB
+const CAP_64BIT_ADDRESSING: u32 = 1 << 31;
+/// SataStatus: Device present and communication established
+const DEVICE_DETECTED: u32 = 0x3;
+const TASK_FILE_BUSY: u32 = 1 << 7;
+const TASK_FILE_DRQ: u32 = 1 << 3;
+/// PxIS.TFES: The device reported an error
+const TASK_FILE_ERROR: u32 = 1 << 30;
+/// PxIS.DHRS: The device sent a register FIS, which ends every command
+const DEVICE_TO_HOST_REGISTER: u32 = 1 << 0;
+const FIS_TYPE_REG_H2D: u8 = 0x27;
+/// Set in a host to device register FIS to mark it as a command
+const FIS_COMMAND: u8 = 1 << 7;
+/// LBA mode in the device register
+const DEVICE_LBA: u8 = 1 << 6;
+const COMMAND_HEADER_WRITE: u16 = 1 << 6;
+/// The command list holds 32 command headers
+const COMMAND_LIST_SIZE: usize = 32 * core::mem::size_of::<CommandHeader>();
+const FIS_RECEIVE_SIZE: usize = 256;
+
+#[repr(C)]
+struct CommandHeader {
+    /// Command FIS length in dwords, ATAPI, write, prefetchable, ...
+    flags: u16,
+    /// The number of PRDT entries
+    prdt_length: u16,
+    /// The number of bytes transferred
+    prd_byte_count: u32,
+    command_table_base: u64,
+    _reserved: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PrdtEntry {
+    data_base: u64,
+    _reserved: u32,
+    /// Byte count minus one in bits 0-21, interrupt on completion in bit 31
+    byte_count: u32,
+}
+
+#[repr(C)]
+struct CommandTable {
+    command_fis: [u8; 64],
+    atapi_command: [u8; 16],
+    _reserved: [u8; 48],
+    prdt: [PrdtEntry; PRDT_ENTRIES],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct FisRegH2D {
+    fis_type: u8,
+    /// Port multiplier in bits 0-3, command/control in bit 7
+    flags: u8,
+    command: u8,
+    feature_low: u8,
+    lba0: u8,
+    lba1: u8,
+    lba2: u8,
+    device: u8,
+    lba3: u8,
+    lba4: u8,
+    lba5: u8,
+    feature_high: u8,
+    count_low: u8,
+    count_high: u8,
+    icc: u8,
+    control: u8,
+    _reserved: [u8; 4],
+}
+
+/// Tasks waiting for a command to complete, woken by every interrupt of any HBA
+static COMMAND_WAITERS: WaitQueue = WaitQueue::new();
+
+/// # AHCI Port
+/// A port with a SATA drive attached
+pub struct AhciPort {
+    /// The virtual address of the HBA registers
+    abar: u64,
+    /// The virtual address of the port registers
+    registers: u64,
+    port: usize,
+    /// Where the HBA can access memory, data out of its reach is bounced
+    constraints: DmaConstraints,
+    /// The command list, the FIS receive area and the command table of slot 0
+    memory: DmaBuffer,
+    command_list: DmaRange,
+    fis: DmaRange,
+    command_table: DmaRange,
+    /// The number of addressable sectors
+    pub sectors: u64,
+    model: [u8; 40],
+    /// The vector of the HBA, shared by its ports. None while completions are polled.
+    msi: Option<Arc<MsiVectors>>,
+}
+
+/// # AHCI Disk
+/// A SATA drive as a block device
+pub struct AhciDisk {
+    port: sync::Mutex<AhciPort>,
+    sectors: u64,
+}
+
+impl AhciDisk {
+    pub fn port(&self) -> sync::MutexGuard<'_, AhciPort> {
+        self.port.lock()
+    }
+}
+
+impl BlockDevice for AhciDisk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        let count = blocks_in(self, buf.len())? as usize;
+        self.port.lock().read_sectors(lba, count, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        let count = blocks_in(self, buf.len())? as usize;
+        self.port.lock().write_sectors(lba, count, buf)
+    }
+
+    fn write_blocks_polled(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        let count = blocks_in(self, buf.len())? as usize;
+        let mut port = self.port.try_lock().ok_or(Error::OperationWouldBlock)?;
+        port.write_sectors(lba, count, buf)
+    }
+}
+
+/// All SATA drives found by `init_ahci()`
+pub static AHCI_DISKS: Mutex<Vec<Arc<AhciDisk>>> = Mutex::new(Vec::new());
+
+fn read(base: u64, register: u64) -> u32 {
+    unsafe { core::ptr::read_volatile((base + register) as *const u32) }
+}
+
+fn write(base: u64, register: u64, value: u32) {
+    unsafe { core::ptr::write_volatile((base + register) as *mut u32, value) }
+}
+
+/// # Wait While
+/// Polls until `condition` returns false
+fn wait_while(mut condition: impl FnMut() -> bool) -> Result<()> {
+    for _ in 0..TIMEOUT_SPINS {
+        if !condition() {
+            return Ok(());
+        }
+        watchdog::touch();
+        comasm::pause();
+    }
+    Err(Error::ConnectionTimedOut)
+}
+
+impl AhciPort {
+    fn read(&self, register: u64) -> u32 {
+        read(self.registers, register)
+    }
+
+    fn write(&self, register: u64, value: u32) {
+        write(self.registers, register, value)
+    }
+
+    /// # New
+    /// Sets up the command list and FIS receive area of `port` and starts it
+    fn new(abar: u64, port: usize, constraints: DmaConstraints) -> Result<Self> {
+        let mut layout = DmaLayout::new();
+        let command_list = layout.push(COMMAND_LIST_SIZE, 1024);
+        let fis = layout.push(FIS_RECEIVE_SIZE, 256);
+        let command_table = layout.push(core::mem::size_of::<CommandTable>(), 128);
+        let mut this = Self {
+            abar,
+            registers: abar + PORT_REGISTERS_BASE + port as u64 * PORT_REGISTERS_SIZE,
+            port,
+            constraints,
+            memory: DmaBuffer::with_layout(&layout, constraints)?,
+            command_list,
+            fis,
+            command_table,
+            sectors: 0,
+            model: [0; 40],
+            msi: None,
+        };
+        this.stop()?;
+
+        let command_list = this.memory.phys_of(this.command_list).as_u64();
+        let fis = this.memory.phys_of(this.fis).as_u64();
+        this.write(PortRegister::CommandListBase, command_list as u32);
+        this.write(
+            PortRegister::CommandListBaseUpper,
+            (command_list >> 32) as u32,
+        );
+        this.write(PortRegister::FisBase, fis as u32);
+        this.write(PortRegister::FisBaseUpper, (fis >> 32) as u32);
+        this.header().command_table_base = this.memory.phys_of(this.command_table).as_u64();
+
+        // Clear all pending errors and interrupts
+        this.write(PortRegister::SataError, u32::MAX);
+        this.write(PortRegister::InterruptStatus, u32::MAX);
+        this.start()?;
+        this.identify()?;
+        Ok(this)
+    }
+
+    fn stop(&self) -> Result<()> {
+        let command = self.read(PortRegister::Command);
+        self.write(
+            PortRegister::Command,
+            command & !(PortCommand::Start | PortCommand::FisReceiveEnable),
+        );
+        wait_while(|| {
+            self.read(PortRegister::Command)
+                & (PortCommand::CommandListRunning | PortCommand::FisReceiveRunning)
+                != 0
+        })
+    }
+
+    fn start(&self) -> Result<()> {
+        wait_while(|| self.read(PortRegister::Command) & PortCommand::CommandListRunning != 0)?;
+        let command = self.read(PortRegister::Command)
+            | PortCommand::PowerOnDevice
+            | PortCommand::SpinUpDevice
+            | PortCommand::FisReceiveEnable;
+        self.write(PortRegister::Command, command);
+        self.write(PortRegister::Command, command | PortCommand::Start);
+        Ok(())
+    }
+
+    fn header(&mut self) -> &mut CommandHeader {
+        unsafe { &mut *self.memory.ptr_of::<CommandHeader>(self.command_list) }
+    }
+
+    fn table(&mut self) -> &mut CommandTable {
+        unsafe { &mut *self.memory.ptr_of::<CommandTable>(self.command_table) }
+    }
+
+    /// # Identify
+    /// Reads the size and model of the drive
+    fn identify(&mut self) -> Result<()> {
+        let mut data = [0u16; 256];
+        let buffer =
+            unsafe { core::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, SECTOR_SIZE) };
+        self.issue(
+            AtaCommand::Identify,
+            0,
+            0,
+            buffer.as_mut_ptr() as u64,
+            SECTOR_SIZE,
+            false,
+        )?;
+
+        // Words 100-103: Number of sectors addressable with 48 bit LBA
+        self.sectors = (0..4).fold(0, |sectors, i| sectors | (data[100 + i] as u64) << (16 * i));
+        // Words 27-46: Model number, the bytes of every word are swapped
+        for (i, word) in data[27..47].iter().enumerate() {
+            self.model[i * 2] = (word >> 8) as u8;
+            self.model[i * 2 + 1] = *word as u8;
+        }
+        Ok(())
+    }
+
+    pub fn model(&self) -> &str {
+        core::str::from_utf8(&self.model).unwrap_or("").trim()
+    }
+
+    pub fn port(&self) -> usize {
+        self.port
+    }
+
+    /// # Enable Interrupts
+    /// Signals the completion of commands, and errors, through `msi` from now on
+    fn enable_interrupts(&mut self, msi: Arc<MsiVectors>) {
+        self.write(PortRegister::InterruptStatus, u32::MAX);
+        self.write(
+            PortRegister::InterruptEnable,
+            DEVICE_TO_HOST_REGISTER | TASK_FILE_ERROR,
+        );
+        self.msi = Some(msi);
+    }
+
+    /// Clears the interrupts of the port, the HBA raises no new one while they are pending
+    fn clear_interrupts(&self) {
+        self.write(PortRegister::InterruptStatus, u32::MAX);
+        write(self.abar, HbaRegister::InterruptStatus, 1 << self.port);
+    }
+
+    /// # Read Sectors
+    /// Reads `count` sectors starting at `lba` into `buf`, which has to hold at least
+    /// `count * SECTOR_SIZE` bytes and be 2 byte aligned
+    pub fn read_sectors(&mut self, lba: u64, count: usize, buf: &mut [u8]) -> Result<()> {
+        self.transfer(lba, count, buf.as_mut_ptr() as u64, buf.len(), false)
+    }
+
+    /// # Write Sectors
+    /// Writes `count` sectors from `buf` to the drive, starting at `lba`
+    pub fn write_sectors(&mut self, lba: u64, count: usize, buf: &[u8]) -> Result<()> {
+        self.transfer(lba, count, buf.as_ptr() as u64, buf.len(), true)
+    }
+
+    /// # Flush Cache
+    /// Waits until the drive wrote everything in its write cache to the medium
+    pub fn flush_cache(&mut self) -> Result<()> {
+        self.issue(AtaCommand::FlushCacheExt, 0, 0, 0, 0, false)
+    }
+
+    /// # Transfer
+    /// Splits the transfer into commands that fit into a single command table
+    fn transfer(
+        &mut self,
+        lba: u64,
+        count: usize,
+        addr: u64,
+        len: usize,
+        write: bool,
+    ) -> Result<()> {
+        if len < count * SECTOR_SIZE || addr % 2 != 0 {
+            return Err(Error::InvalidArgument);
+        }
+        if lba + count as u64 > self.sectors {
+            return Err(Error::IOError);
+        }
+        let command = if write {
+            AtaCommand::WriteDmaExt
+        } else {
+            AtaCommand::ReadDmaExt
+        };
+
+        let mut done = 0;
+        while done < count {
+            let sectors = (count - done).min(MAX_SECTORS_PER_COMMAND);
+            self.issue(
+                command,
+                lba + done as u64,
+                sectors as u16,
+                addr + (done * SECTOR_SIZE) as u64,
+                sectors * SECTOR_SIZE,
+                write,
+            )?;
+            done += sectors;
+        }
+        Ok(())
+    }
+
+    /// # Issue
+    /// Runs a single command in slot 0 and waits until it completed, sleeping until its interrupt
+    /// arrived if it can
+    fn issue(
+        &mut self,
+        command: u8,
+        lba: u64,
+        sectors: u16,
+        addr: u64,
+        len: usize,
+        write: bool,
+    ) -> Result<()> {
+        wait_while(|| {
+            self.read(PortRegister::TaskFileData) & (TASK_FILE_BUSY | TASK_FILE_DRQ) != 0
+        })?;
+        self.clear_interrupts();
+
+        let data = unsafe { BounceBuffer::new(addr, len, self.constraints, write)? };
+        let addr = data.addr();
+        // One PRDT entry per page touched by the buffer, as it is only virtually contiguous
+        let mut entries = 0;
+        let mut offset = 0;
+        while offset < len {
+            let virt = addr + offset as u64;
+            let chunk = (PAGE_SIZE - virt % PAGE_SIZE).min((len - offset) as u64);
+            self.table().prdt[entries] = PrdtEntry {
+                data_base: virt_to_phys(VirtualAddress::new(virt)).as_u64(),
+                _reserved: 0,
+                byte_count: chunk as u32 - 1,
+            };
+            entries += 1;
+            offset += chunk as usize;
+        }
+
+        let fis = FisRegH2D {
+            fis_type: FIS_TYPE_REG_H2D,
+            flags: FIS_COMMAND,
+            command,
+            lba0: lba as u8,
+            lba1: (lba >> 8) as u8,
+            lba2: (lba >> 16) as u8,
+            device: DEVICE_LBA,
+            lba3: (lba >> 24) as u8,
+            lba4: (lba >> 32) as u8,
+            lba5: (lba >> 40) as u8,
+            count_low: sectors as u8,
+            count_high: (sectors >> 8) as u8,
+            ..Default::default()
+        };
+        let table = self.table();
+        table.command_fis = [0; 64];
+        unsafe {
+            core::ptr::write_unaligned(table.command_fis.as_mut_ptr() as *mut FisRegH2D, fis);
+        }
+
+        let header = self.header();
+        header.flags = (core::mem::size_of::<FisRegH2D>() / 4) as u16;
+        if write {
+            header.flags |= COMMAND_HEADER_WRITE;
+        }
+        header.prdt_length = entries as u16;
+        header.prd_byte_count = 0;
+
+        let sleep = self.msi.is_some() && scheduler::is_running() && interrupts::are_enabled();
+        self.write(PortRegister::CommandIssue, 1);
+        let busy = || {
+            self.read(PortRegister::CommandIssue) & 1 != 0
+                && self.read(PortRegister::InterruptStatus) & TASK_FILE_ERROR == 0
+        };
+        let result = if !sleep {
+            wait_while(busy)
+        } else if COMMAND_WAITERS.wait_until_timeout(|| !busy(), TIMEOUT_MS) {
+            Ok(())
+        } else {
+            Err(Error::ConnectionTimedOut)
+        };
+        let status = self.read(PortRegister::InterruptStatus);
+        self.clear_interrupts();
+        if status & TASK_FILE_ERROR != 0 {
+            return Err(Error::IOError);
+        }
+        result?;
+        data.finish();
+        Ok(())
+    }
+}
+
+/// Wakes every task waiting for a command, each of them checks its own port
+fn ahci_interrupt(_index: usize, _data: usize) {
+    COMMAND_WAITERS.wake_all();
+}
+
+/// # Init Controller
+/// Resets the HBA of `device` and sets up every port with a SATA drive attached
+fn init_controller(device: PciDevice) -> Result<Vec<AhciPort>> {
+    device.enable_bus_mastering();
+    let abar_phys = device.bar(ABAR_INDEX);
+    let abar_size = PORT_REGISTERS_BASE + MAX_PORTS as u64 * PORT_REGISTERS_SIZE;
+    let abar = map_mmio(
+        PhysicalAddress::new(abar_phys),
+        abar_size,
+        MemoryType::Uncacheable,
+    )?
+    .as_u64();
+
+    // Reset the HBA, which also clears AHCI enable
+    let control = read(abar, HbaRegister::GlobalHostControl);
+    write(
+        abar,
+        HbaRegister::GlobalHostControl,
+        control | GlobalHostControl::AhciEnable,
+    );
+    write(
+        abar,
+        HbaRegister::GlobalHostControl,
+        control | GlobalHostControl::AhciEnable | GlobalHostControl::HbaReset,
+    );
+    wait_while(|| read(abar, HbaRegister::GlobalHostControl) & GlobalHostControl::HbaReset != 0)?;
+    write(
+        abar,
+        HbaRegister::GlobalHostControl,
+        GlobalHostControl::AhciEnable,
+    );
+
+    let constraints = if read(abar, HbaRegister::Capabilities) & CAP_64BIT_ADDRESSING != 0 {
+        DmaConstraints::ANY
+    } else {
+        debug!("AHCI: The controller only takes 32 bit addresses");
+        DmaConstraints::BELOW_4GIB
+    };
+    let implemented = read(abar, HbaRegister::PortsImplemented);
+    let mut ports = Vec::new();
+    for port in (0..MAX_PORTS).filter(|port| implemented & (1 << port) != 0) {
+        let registers = abar + PORT_REGISTERS_BASE + port as u64 * PORT_REGISTERS_SIZE;
+        // Links need a moment to come back up after the reset
+        let present =
+            wait_while(|| read(registers, PortRegister::SataStatus) & 0xF != DEVICE_DETECTED)
+                .is_ok();
+        if !present || read(registers, PortRegister::Signature) != SIGNATURE_ATA {
+            continue;
+        }
+        match AhciPort::new(abar, port, constraints) {
+            Ok(ahci_port) => ports.push(ahci_port),
+            Err(err) => warn!("AHCI: Failed to initialize port {}: {}", port, err.text()),
+        }
+    }
+
+    if ports.is_empty() || cmdline::flag(POLL_FLAG) {
+        return Ok(ports);
+    }
+    match msi::allocate_msi(&device, 1, "ahci", ahci_interrupt, 0) {
+        Ok(vectors) => {
+            let vectors = Arc::new(vectors);
+            for port in &mut ports {
+                port.enable_interrupts(vectors.clone());
+            }
+            write(abar, HbaRegister::InterruptStatus, u32::MAX);
+            write(
+                abar,
+                HbaRegister::GlobalHostControl,
+                GlobalHostControl::AhciEnable | GlobalHostControl::InterruptEnable,
+            );
+        }
+        Err(err) => warn!("AHCI: Falling back to polling: {}", err.text()),
+    }
+    Ok(ports)
+}
+
+/// # AHCI Driver
+/// Binds to AHCI controllers. Its disks cannot be taken away again, so it keeps the default
+/// `detach()`.
+pub struct AhciDriver;
+
+pub static AHCI_DRIVER: AhciDriver = AhciDriver;
+
+impl PciDriver for AhciDriver {
+    fn name(&self) -> &'static str {
+        "ahci"
+    }
+
+    fn matches(&self, device: &PciDevice) -> bool {
+        device.class == PCI_CLASS_MASS_STORAGE && device.subclass == PCI_SUBCLASS_SATA
+    }
+
+    fn attach(&self, device: PciDevice) -> Result<()> {
+        debug!(
+            "AHCI: Controller {:04x}:{:04x}",
+            device.vendor_id, device.device_id
+        );
+        for port in init_controller(device)? {
+            info!(
+                "AHCI: Port {}: {} ({} sectors)",
+                port.port(),
+                port.model(),
+                port.sectors
+            );
+            let disk = Arc::new(AhciDisk {
+                sectors: port.sectors,
+                port: sync::Mutex::new(port),
+            });
+            AHCI_DISKS.lock().push(disk.clone());
+            block::register_disk(disk);
+        }
+        Ok(())
+    }
+}
+
+crate::initcall! {
+    name: "ahci",
+    stage: Scheduled,
+    deps: ["pci"],
+    fatal: false,
+    init: init_ahci,
+}
+
+/// # Init AHCI
+/// Claims every AHCI controller registered on the PCI bus
+pub fn init_ahci() -> Result<()> {
+    notifier::register("ahci", notifier::PRIORITY_DEVICES, flush_caches);
+    pci::register_driver(&AHCI_DRIVER).map(|_| ())
+}
+
+/// Flushes the write caches of all drives before the machine goes down, so what was written
+/// is on the disk before the reset
+fn flush_caches(_: PowerAction) {
+    let disks = match AHCI_DISKS.try_lock() {
+        Some(disks) => disks.clone(),
+        None => {
+            warn!("AHCI: The drives are held, not flushing their caches");
+            return;
+        }
+    };
+    for disk in disks {
+        let mut port = disk.port();
+        if let Err(err) = port.flush_cache() {
+            warn!(
+                "AHCI: Port {}: Cannot flush the cache: {}",
+                port.port(),
+                err
+            );
+        }
+    }
+}
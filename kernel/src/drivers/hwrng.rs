@@ -0,0 +1,224 @@
+//! # Hardware RNG
+//! The random number generators built into the CPU. RDSEED returns the output of the entropy
+//! source itself and is preferred for seeding, RDRAND returns the output of a DRBG the entropy
+//! source reseeds and is the fallback.
+//!
+//! Every sample goes through a repetition count test (NIST SP 800-90B 4.4.1). A source that
+//! repeats itself is taken to be stuck and is never used again, so `rand` falls back to the
+//! next source instead of seeding from a constant.
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+use spin::Once;
+
+use crate::error::{Error, Result};
+use crate::scheduler::sync::IrqSpinLock;
+use crate::{info, warn};
+
+/// CPUID.01H:ECX.RDRAND
+const CPUID_RDRAND: u32 = 1 << 30;
+/// CPUID.(EAX=07H,ECX=0):EBX.RDSEED
+const CPUID_RDSEED: u32 = 1 << 18;
+const CPUID_EXTENDED_FEATURES: u32 = 7;
+/// Both may fail while their entropy source is drained, Intel recommends ten retries
+pub const RETRIES: usize = 10;
+/// A source returning the same 64 bits this often in a row is stuck. For a working source,
+/// that happens with a probability of 2^-64.
+pub const REPETITION_CUTOFF: usize = 2;
+/// The samples every source has to pass when it is first used
+const STARTUP_SAMPLES: usize = 16;
+
+crate::initcall! {
+    name: "hwrng",
+    stage: Device,
+    deps: [],
+    fatal: false,
+    init: init_hwrng,
+}
+
+/// # Source
+/// A random number generator of the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Rdseed,
+    Rdrand,
+}
+
+impl Source {
+    /// Every source, the preferred one first
+    pub const ALL: [Source; 2] = [Source::Rdseed, Source::Rdrand];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Source::Rdseed => "RDSEED",
+            Source::Rdrand => "RDRAND",
+        }
+    }
+
+    /// Whether the CPU has the instruction
+    pub fn is_supported(&self) -> bool {
+        let (rdseed, rdrand) = *SUPPORTED.call_once(|| unsafe {
+            let rdseed = __cpuid(0).eax >= CPUID_EXTENDED_FEATURES
+                && __cpuid_count(CPUID_EXTENDED_FEATURES, 0).ebx & CPUID_RDSEED != 0;
+            (rdseed, __cpuid(1).ecx & CPUID_RDRAND != 0)
+        });
+        match self {
+            Source::Rdseed => rdseed,
+            Source::Rdrand => rdrand,
+        }
+    }
+
+    fn health(&self) -> &'static IrqSpinLock<Health> {
+        match self {
+            Source::Rdseed => &RDSEED_HEALTH,
+            Source::Rdrand => &RDRAND_HEALTH,
+        }
+    }
+}
+
+/// # Repetition Count Test
+/// Fails once the same sample arrives `REPETITION_CUTOFF` times in a row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepetitionCountTest {
+    last: u64,
+    count: usize,
+}
+
+impl RepetitionCountTest {
+    pub const fn new() -> Self {
+        Self { last: 0, count: 0 }
+    }
+
+    /// # Sample
+    /// Counts `value`
+    ///
+    /// ## Returns
+    /// - bool = Whether the source still passes
+    pub fn sample(&mut self, value: u64) -> bool {
+        if self.count != 0 && value == self.last {
+            self.count += 1;
+        } else {
+            self.last = value;
+            self.count = 1;
+        }
+        self.count < REPETITION_CUTOFF
+    }
+}
+
+struct Health {
+    test: RepetitionCountTest,
+    disqualified: bool,
+}
+
+impl Health {
+    const fn new() -> Self {
+        Self {
+            test: RepetitionCountTest::new(),
+            disqualified: false,
+        }
+    }
+}
+
+/// Whether the CPU has RDSEED and RDRAND
+static SUPPORTED: Once<(bool, bool)> = Once::new();
+static RDSEED_HEALTH: IrqSpinLock<Health> = crate::named_lock!("rdseed_health", Health::new());
+static RDRAND_HEALTH: IrqSpinLock<Health> = crate::named_lock!("rdrand_health", Health::new());
+
+/// # Is Usable
+/// Whether `source` is supported and did not fail its health test
+pub fn is_usable(source: Source) -> bool {
+    source.is_supported() && !source.health().lock().disqualified
+}
+
+/// # Read
+/// A sample of `source` that passed the health test
+///
+/// ## Returns
+/// - Error::NoSuchDevice = The CPU does not have `source`, or it failed its health test
+/// - Error::TryAgain = The source stayed drained for `RETRIES` attempts
+pub fn read(source: Source) -> Result<u64> {
+    if !source.is_supported() {
+        return Err(Error::NoSuchDevice);
+    }
+    let mut health = source.health().lock();
+    if health.disqualified {
+        return Err(Error::NoSuchDevice);
+    }
+    let value = (0..RETRIES)
+        .find_map(|_| match source {
+            Source::Rdseed => rdseed(),
+            Source::Rdrand => rdrand(),
+        })
+        .ok_or(Error::TryAgain)?;
+    if !health.test.sample(value) {
+        health.disqualified = true;
+        warn!(
+            "hwrng: {} returned {:#x} {} times in a row, no longer using it",
+            source.name(),
+            value,
+            REPETITION_CUTOFF
+        );
+        return Err(Error::NoSuchDevice);
+    }
+    Ok(value)
+}
+
+/// # Read U64
+/// A sample of the first usable source
+///
+/// ## Returns
+/// - Source = Where the sample came from
+pub fn read_u64() -> Option<(u64, Source)> {
+    Source::ALL
+        .iter()
+        .find_map(|source| read(*source).ok().map(|value| (value, *source)))
+}
+
+fn rdseed() -> Option<u64> {
+    let value: u64;
+    let ok: u8;
+    unsafe {
+        core::arch::asm!(
+            "rdseed {}",
+            "setc {}",
+            out(reg) value,
+            out(reg_byte) ok,
+            options(nomem, nostack),
+        );
+    }
+    (ok != 0).then(|| value)
+}
+
+fn rdrand() -> Option<u64> {
+    let value: u64;
+    let ok: u8;
+    unsafe {
+        core::arch::asm!(
+            "rdrand {}",
+            "setc {}",
+            out(reg) value,
+            out(reg_byte) ok,
+            options(nomem, nostack),
+        );
+    }
+    (ok != 0).then(|| value)
+}
+
+/// # Init HW RNG
+/// Runs the startup test of every supported source
+pub fn init_hwrng() -> Result<()> {
+    for source in Source::ALL {
+        if !source.is_supported() {
+            continue;
+        }
+        for _ in 0..STARTUP_SAMPLES {
+            if let Err(Error::NoSuchDevice) = read(source) {
+                break;
+            }
+        }
+    }
+    match Source::ALL.iter().find(|source| is_usable(**source)) {
+        Some(source) => info!("hwrng: Seeding from {}", source.name()),
+        None => warn!("hwrng: No usable source, seeding from TSC jitter"),
+    }
+    Ok(())
+}
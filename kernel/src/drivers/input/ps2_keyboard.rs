@@ -1,87 +1,577 @@
-use keyboard_layout::{translator, Modifier, RELEASED_COUNTERPART};
-use spin::Mutex;
+//! # PS/2 Keyboard
+//! A driver for the keyboard on the first port of the i8042 controller, in scancode set 1.
+//!
+//! Keys are translated through the selected layout of `keyboard_layout`, which is `keymap=<name>`
+//! from the command line or the layout of the boot configuration, and can be switched with
+//! `set_layout()`. The lock keys are reflected on the LEDs of the keyboard. Ctrl with a letter
+//! types its control character, so Ctrl-C and Ctrl-Z reach the TTY.
+//!
+//! Commands to the keyboard are answered with an ACK or a request to resend, which arrive
+//! through the same interrupt as the keystrokes. They are queued in `COMMANDS`, and every byte
+//! is only sent once the previous one was acknowledged.
+//!
+//! The interrupt handler only answers those and queues every other byte in `SCANCODES`. The
+//! scancodes are decoded later by the `SoftirqKind::Input` softirq, with interrupts enabled.
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use keyboard_layout::{
+    find_layout, translate, ExtendedKey, Layout, Modifier, Modifiers, EXTENDED_PREFIX,
+    KEYBOARD_LAYOUTS, RELEASED_COUNTERPART,
+};
 
-use crate::config;
-use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::device::tree::{self, DeviceClass, Resource};
+use crate::error::{Error, Result};
+use crate::framebuffer::{self, FRAMEBUFFER_GUARD};
+use crate::irq::softirq::{self, SoftirqKind};
+use crate::scheduler::IrqSpinLock;
+use crate::tty::{self, Echo};
 use crate::{
-    arch::interrupts::interrupt_frame::InterruptFrame,
-    arch::iobus::inb,
-    arch::pic::{end_main_pic, PicPort},
+    arch::interrupts::{interrupt_frame::InterruptFrame, set_interrupt_handler, IrqScope},
+    arch::iobus::{inb, outb},
+    arch::pic::{self, end_main_pic, PicInterrupt, PicPort},
     kprintln,
 };
-use core::fmt::Write;
+use crate::{cmdline, config, warn};
 
-pub extern "x86-interrupt" fn ps2_keyboard_int_handler(_a: InterruptFrame) {
-    // Get Keyboard Scancode
-    let scancode = inb(PicPort::Ps2KeyboardScancodePort);
-    handle_keyboard(scancode);
-    end_main_pic();
+/// The line of the keyboard on the PIC
+const PS2_KEYBOARD_IRQ: u8 = 1;
+/// The status and command port of the controller
+const PS2_COMMAND_PORT: u16 = 0x64;
+/// Status: The controller has not taken the last byte written to it yet
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// How often the status is polled before a byte is written anyway
+const WRITE_TIMEOUT: usize = 100_000;
+/// Selects the layout by its name, e.g. `keymap=de`
+pub const KEYMAP_OPTION: &str = "keymap";
+/// The most commands that can wait for the keyboard
+pub const COMMAND_QUEUE_SIZE: usize = 8;
+/// How often a byte is resent before its command is given up on
+pub const MAX_RESENDS: u8 = 3;
+/// The most scancodes that can wait to be decoded
+pub const SCANCODE_BUFFER_SIZE: usize = 64;
+/// How long a key is held before it repeats, and how often it repeats per second
+pub const DEFAULT_TYPEMATIC_DELAY_MS: u32 = 500;
+pub const DEFAULT_TYPEMATIC_RATE: u32 = 20;
+
+enumtastic::const_enum! {
+    pub enum KeyboardCommand: u8 => {
+        SetLeds = 0xED,
+        SetTypematic = 0xF3,
+    }
+
+    impl {}
 }
 
-static MODIFIER_STATE: Mutex<[bool; 2]> = Mutex::new([false, false]);
-pub fn switch_state_of_mod(modi: u8) {
-    let num = match modi {
-        Modifier::LeftShift => 0,
-        Modifier::RightShift => 1,
-        _ => 0,
-    };
-    let prev = *MODIFIER_STATE.lock().get(num).unwrap();
-    change_state_of_mod_to(modi, !prev);
+enumtastic::const_enum! {
+    pub enum KeyboardResponse: u8 => {
+        Ack = 0xFA,
+        Resend = 0xFE,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum KeyboardLed: u8 => {
+        ScrollLock = 1 << 0,
+        NumLock = 1 << 1,
+        CapsLock = 1 << 2,
+    }
+
+    impl {}
+}
+
+crate::counter!(pub DROPPED_SCANCODES = "keyboard.dropped_scancodes");
+
+crate::initcall! {
+    name: "ps2-keyboard",
+    stage: Device,
+    deps: [],
+    fatal: false,
+    init: init_ps2_keyboard,
+}
+
+/// # Command
+/// A command and its data byte, if it has one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Command {
+    bytes: [u8; 2],
+    len: usize,
+}
+
+/// # Command Queue
+/// The commands waiting for the keyboard. The first one is being sent, `sent` of its bytes were
+/// acknowledged already.
+#[derive(Debug)]
+pub struct CommandQueue {
+    commands: [Command; COMMAND_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+    sent: usize,
+    resends: u8,
+}
+
+impl CommandQueue {
+    pub const fn new() -> Self {
+        Self {
+            commands: [Command {
+                bytes: [0; 2],
+                len: 0,
+            }; COMMAND_QUEUE_SIZE],
+            head: 0,
+            len: 0,
+            sent: 0,
+            resends: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The byte the keyboard is expected to answer next
+    fn current(&self) -> Option<u8> {
+        (!self.is_empty()).then(|| self.commands[self.head].bytes[self.sent])
+    }
+
+    /// # Push
+    /// Queues `command` with its data byte, if it has one
+    ///
+    /// ## Returns
+    /// - Some(u8) = The queue was empty, the first byte has to be sent now
+    /// - Error::InvalidArgument = `bytes` is empty or longer than a command and its data
+    /// - Error::DeviceOrResourceBusy = The queue is full
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Option<u8>> {
+        if bytes.is_empty() || bytes.len() > 2 {
+            return Err(Error::InvalidArgument);
+        }
+        if self.len == COMMAND_QUEUE_SIZE {
+            return Err(Error::DeviceOrResourceBusy);
+        }
+        let mut command = Command {
+            bytes: [0; 2],
+            len: bytes.len(),
+        };
+        command.bytes[..bytes.len()].copy_from_slice(bytes);
+        self.commands[(self.head + self.len) % COMMAND_QUEUE_SIZE] = command;
+        self.len += 1;
+        Ok(if self.len == 1 { self.current() } else { None })
+    }
+
+    /// # Respond
+    /// Takes the answer of the keyboard to the byte that was sent last
+    ///
+    /// ## Returns
+    /// - Some(u8) = The byte to send next, the same one again if the keyboard asked for it
+    pub fn respond(&mut self, response: u8) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        match response {
+            KeyboardResponse::Ack => {
+                self.resends = 0;
+                self.sent += 1;
+                if self.sent == self.commands[self.head].len {
+                    self.pop();
+                }
+            }
+            KeyboardResponse::Resend if self.resends < MAX_RESENDS => self.resends += 1,
+            // The whole command, so its data byte is not taken for a command of its own
+            KeyboardResponse::Resend => self.pop(),
+            _ => return None,
+        }
+        self.current()
+    }
+
+    fn pop(&mut self) {
+        self.head = (self.head + 1) % COMMAND_QUEUE_SIZE;
+        self.len -= 1;
+        self.sent = 0;
+        self.resends = 0;
+    }
+}
+
+/// # Scancode Buffer
+/// The scancodes received and not decoded yet, oldest first
+#[derive(Debug)]
+pub struct ScancodeBuffer {
+    scancodes: [u8; SCANCODE_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl ScancodeBuffer {
+    pub const fn new() -> Self {
+        Self {
+            scancodes: [0; SCANCODE_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// # Push
+    /// Queues `scancode` behind the others
+    ///
+    /// ## Returns
+    /// - bool = Whether it was queued, false if the buffer is full
+    pub fn push(&mut self, scancode: u8) -> bool {
+        if self.len == SCANCODE_BUFFER_SIZE {
+            return false;
+        }
+        self.scancodes[(self.start + self.len) % SCANCODE_BUFFER_SIZE] = scancode;
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let scancode = self.scancodes[self.start];
+        self.start = (self.start + 1) % SCANCODE_BUFFER_SIZE;
+        self.len -= 1;
+        Some(scancode)
+    }
+}
+
+/// # Keyboard State
+/// The modifiers that are held and the locks that are on
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardState {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_control: bool,
+    pub right_control: bool,
+    pub altgr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
 }
 
-pub fn change_state_of_mod_to(modi: u8, value: bool) {
-    let num = match modi {
-        Modifier::LeftShift => 0,
-        Modifier::RightShift => 1,
-        _ => 0,
+impl KeyboardState {
+    pub const fn new() -> Self {
+        Self {
+            left_shift: false,
+            right_shift: false,
+            left_control: false,
+            right_control: false,
+            altgr: false,
+            caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
+        }
+    }
+
+    pub fn control(&self) -> bool {
+        self.left_control || self.right_control
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.left_shift || self.right_shift,
+            altgr: self.altgr,
+            caps_lock: self.caps_lock,
+            num_lock: self.num_lock,
+        }
+    }
+
+    /// The data byte of `KeyboardCommand::SetLeds`
+    pub fn leds(&self) -> u8 {
+        let mut leds = 0;
+        if self.scroll_lock {
+            leds |= KeyboardLed::ScrollLock;
+        }
+        if self.num_lock {
+            leds |= KeyboardLed::NumLock;
+        }
+        if self.caps_lock {
+            leds |= KeyboardLed::CapsLock;
+        }
+        leds
+    }
+}
+
+static COMMANDS: IrqSpinLock<CommandQueue> =
+    crate::named_lock!("ps2_commands", CommandQueue::new());
+static SCANCODES: IrqSpinLock<ScancodeBuffer> =
+    crate::named_lock!("ps2_scancodes", ScancodeBuffer::new());
+static STATE: IrqSpinLock<KeyboardState> = crate::named_lock!("ps2_state", KeyboardState::new());
+/// The index of the selected layout in `KEYBOARD_LAYOUTS`
+static LAYOUT: AtomicUsize = AtomicUsize::new(0);
+/// Set after `EXTENDED_PREFIX` until the scancode it precedes arrives
+static EXTENDED: AtomicBool = AtomicBool::new(false);
+
+/// # Init PS2 Keyboard
+/// Adds the controller and the keyboard to the device tree, installs the interrupt handler
+/// and unmasks the keyboard's interrupt. Then selects the layout and sets the typematic rate
+/// and the LEDs.
+pub fn init_ps2_keyboard() -> Result<()> {
+    let controller = tree::register(
+        "i8042",
+        DeviceClass::Bus,
+        Some(tree::platform()?),
+        "i8042",
+        &[
+            Resource::IoPorts {
+                base: PicPort::Ps2KeyboardScancodePort,
+                len: 1,
+            },
+            Resource::IoPorts {
+                base: PS2_COMMAND_PORT,
+                len: 1,
+            },
+        ],
+    )?;
+    tree::register(
+        "keyboard",
+        DeviceClass::Input,
+        Some(controller),
+        "ps2-keyboard",
+        &[Resource::Irq(PS2_KEYBOARD_IRQ)],
+    )?;
+    set_interrupt_handler(
+        PicInterrupt::Ps2KeyboardInterrupt as u64,
+        "ps2-keyboard",
+        ps2_keyboard_int_handler,
+    );
+    softirq::register(SoftirqKind::Input, decode_scancodes);
+    // A key pressed while the interrupt was masked stays in the buffer and would keep the
+    // controller from raising further interrupts
+    inb(PicPort::Ps2KeyboardScancodePort);
+    pic::unmask(PS2_KEYBOARD_IRQ);
+
+    let configured = config().layout as usize;
+    let layout = match cmdline::value(KEYMAP_OPTION) {
+        Some(name) => find_layout(name).unwrap_or_else(|| {
+            warn!("keyboard: There is no layout {}", name);
+            configured
+        }),
+        None => configured,
     };
-    *MODIFIER_STATE.lock().get_mut(num).unwrap() = value;
+    LAYOUT.store(layout.min(KEYBOARD_LAYOUTS.len() - 1), Ordering::Relaxed);
+    set_typematic(DEFAULT_TYPEMATIC_DELAY_MS, DEFAULT_TYPEMATIC_RATE)?;
+    update_leds()
 }
 
-pub fn handle_keyboard(scancode: u8) {
-    // Special Keys
+pub extern "x86-interrupt" fn ps2_keyboard_int_handler(_a: InterruptFrame) {
+    let _irq = IrqScope::enter(PicInterrupt::Ps2KeyboardInterrupt as usize);
+    // Get Keyboard Scancode
+    let scancode = inb(PicPort::Ps2KeyboardScancodePort);
     match scancode {
-        Modifier::LeftShift => change_state_of_mod_to(Modifier::LeftShift, true),
-        _ if scancode == Modifier::LeftShift + RELEASED_COUNTERPART => {
-            change_state_of_mod_to(Modifier::LeftShift, false)
+        KeyboardResponse::Ack | KeyboardResponse::Resend => {
+            let mut commands = COMMANDS.lock();
+            if let Some(next) = commands.respond(scancode) {
+                write_data(next);
+            }
+        }
+        _ => {
+            if !SCANCODES.lock().push(scancode) {
+                DROPPED_SCANCODES.increment();
+            }
+            softirq::raise(SoftirqKind::Input);
         }
+    }
+    end_main_pic();
+}
 
-        Modifier::RightShift => change_state_of_mod_to(Modifier::LeftShift, true),
-        _ if scancode == Modifier::RightShift + RELEASED_COUNTERPART => {
-            change_state_of_mod_to(Modifier::RightShift, false)
+/// # Decode Scancodes
+/// The handler of `SoftirqKind::Input`, handles every scancode the interrupt queued
+fn decode_scancodes() {
+    loop {
+        // Not held while decoding, which draws on the screen
+        let scancode = SCANCODES.lock().pop();
+        match scancode {
+            Some(scancode) => handle_keyboard(scancode),
+            None => return,
         }
+    }
+}
+
+/// Writes `byte` to the keyboard once the controller took the previous one
+fn write_data(byte: u8) {
+    for _ in 0..WRITE_TIMEOUT {
+        if inb(PS2_COMMAND_PORT) & STATUS_INPUT_FULL == 0 {
+            break;
+        }
+        comasm::pause();
+    }
+    outb(PicPort::Ps2KeyboardScancodePort, byte);
+}
+
+/// # Send
+/// Queues `command` with its data byte for the keyboard
+///
+/// ## Returns
+/// - Error::DeviceOrResourceBusy = Too many commands wait for the keyboard already
+pub fn send(command: &[u8]) -> Result<()> {
+    // Held while writing, so the answer is not handled before the byte was sent
+    let mut commands = COMMANDS.lock();
+    if let Some(first) = commands.push(command)? {
+        write_data(first);
+    }
+    Ok(())
+}
 
-        Modifier::Spacebar => unsafe {
+/// # Update LEDs
+/// Lights the LEDs of the locks that are on
+pub fn update_leds() -> Result<()> {
+    let leds = STATE.lock().leds();
+    send(&[KeyboardCommand::SetLeds, leds])
+}
+
+/// # Typematic
+/// The data byte of `KeyboardCommand::SetTypematic` that comes closest to repeating a key
+/// `rate` times per second after it was held for `delay_ms`
+pub fn typematic(delay_ms: u32, rate: u32) -> u8 {
+    // 250 ms to 1 s in steps of 250 ms
+    let delay = ((delay_ms + 125) / 250).clamp(1, 4) - 1;
+    // The period is (8 + A) * 2^B * 4.17 ms, for A in bits 0-2 and B in bits 3-4
+    let rate = (0..32u32)
+        .min_by_key(|code| {
+            let period_us = ((8 + (code & 0b111)) << ((code >> 3) & 0b11)) * 4170;
+            let millirate = 1_000_000_000 / period_us;
+            (millirate as i64 - rate as i64 * 1000).abs()
+        })
+        .unwrap_or(0);
+    (delay << 5 | rate) as u8
+}
+
+/// # Set Typematic
+/// Makes a held key repeat `rate` times per second after `delay_ms`, as far as the keyboard
+/// can
+pub fn set_typematic(delay_ms: u32, rate: u32) -> Result<()> {
+    send(&[KeyboardCommand::SetTypematic, typematic(delay_ms, rate)])
+}
+
+/// # Layout
+/// The layout keys are translated through
+pub fn layout() -> &'static Layout {
+    KEYBOARD_LAYOUTS[LAYOUT.load(Ordering::Relaxed)]
+}
+
+/// # Set Layout
+/// Translates keys through the layout called `name` from now on
+///
+/// ## Returns
+/// - Error::NoSuchFileOrDirectory = There is no such layout
+pub fn set_layout(name: &str) -> Result<()> {
+    let layout = find_layout(name).ok_or(Error::NoSuchFileOrDirectory)?;
+    LAYOUT.store(layout, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn state() -> KeyboardState {
+    *STATE.lock()
+}
+
+/// # Control Char
+/// What Ctrl and the key that types `c` type together, e.g. `\x03` for Ctrl-C
+///
+/// ## Returns
+/// - None = Ctrl and the key type nothing, like for digits
+pub fn control_char(c: char) -> Option<char> {
+    match c.to_ascii_uppercase() {
+        c @ '@'..='_' => Some((c as u8 & 0x1F) as char),
+        _ => None,
+    }
+}
+
+/// Passes `c` on to the console and echoes it
+fn type_char(c: char) {
+    match tty::input(c) {
+        Echo::Char(c) => unsafe {
             FRAMEBUFFER_GUARD
                 .lock()
                 .assume_init_mut()
-                .write_char(' ')
+                .write_char(c)
                 .unwrap();
         },
-
-        Modifier::Enter => {
-            kprintln!("\n");
-        }
-        Modifier::BackSpace => {
-            unsafe {
-                FRAMEBUFFER_GUARD.lock().assume_init_mut().clear_last_char();
-            };
+        Echo::Erase(chars) => {
+            let mut guard = FRAMEBUFFER_GUARD.lock();
+            for _ in 0..chars {
+                unsafe { guard.assume_init_mut() }.clear_last_char();
+            }
         }
+        Echo::NewLine => kprintln!(),
+        Echo::Interrupt => kprintln!("^C"),
+        Echo::Suspend => kprintln!("^Z"),
+        Echo::Nothing => {}
+    }
+}
+
+/// Flips a lock with `toggle` and shows it on the LEDs
+fn toggle_lock(toggle: impl FnOnce(&mut KeyboardState)) {
+    toggle(&mut STATE.lock());
+    if let Err(err) = update_leds() {
+        warn!("keyboard: Cannot update the LEDs: {}", err);
+    }
+}
+
+pub fn handle_keyboard(scancode: u8) {
+    if scancode == EXTENDED_PREFIX {
+        EXTENDED.store(true, Ordering::Relaxed);
+        return;
+    }
+    if EXTENDED.swap(false, Ordering::Relaxed) {
+        handle_extended(scancode);
+        return;
+    }
+    let released = scancode & RELEASED_COUNTERPART != 0;
+    let key = scancode & !RELEASED_COUNTERPART;
+    // Special Keys
+    match key {
+        Modifier::LeftShift => STATE.lock().left_shift = !released,
+        Modifier::RightShift => STATE.lock().right_shift = !released,
+        Modifier::LeftControl => STATE.lock().left_control = !released,
+        _ if released => {}
+
+        Modifier::CapsLock => toggle_lock(|state| state.caps_lock = !state.caps_lock),
+        Modifier::NumLock => toggle_lock(|state| state.num_lock = !state.num_lock),
+        Modifier::ScrollLock => toggle_lock(|state| state.scroll_lock = !state.scroll_lock),
+
+        Modifier::Spacebar => type_char(' '),
+        Modifier::Enter => type_char('\n'),
+        Modifier::BackSpace => type_char('\x08'),
         _ => {
-            let is_lshift_pressed = MODIFIER_STATE.lock()[1];
-            let uppercase = MODIFIER_STATE.lock()[0] | is_lshift_pressed;
-            let ascii =
-                translator::translate_from_u8(scancode, uppercase, config().layout as usize);
-            // NULLs cannot be displayed
-            if ascii != 0 as char {
-                unsafe {
-                    FRAMEBUFFER_GUARD
-                        .lock()
-                        .assume_init_mut()
-                        .write_char(ascii)
-                        .unwrap();
+            let state = state();
+            match translate(layout(), key, state.modifiers()) {
+                Some(c) if state.control() => {
+                    if let Some(c) = control_char(c) {
+                        type_char(c);
+                    }
                 }
+                Some(c) => type_char(c),
+                None => {}
             }
         }
     }
 }
+
+/// # Handle Extended
+/// Tracks AltGr. Shift+PageUp and Shift+PageDown scroll the screen through the scrollback.
+fn handle_extended(scancode: u8) {
+    let released = scancode & RELEASED_COUNTERPART != 0;
+    let state = state();
+    let shifted = state.left_shift || state.right_shift;
+    match scancode & !RELEASED_COUNTERPART {
+        ExtendedKey::RightAlt => STATE.lock().altgr = !released,
+        ExtendedKey::RightControl => STATE.lock().right_control = !released,
+        _ if released => {}
+        ExtendedKey::PageUp if shifted => framebuffer::scroll_pages(1),
+        ExtendedKey::PageDown if shifted => framebuffer::scroll_pages(-1),
+        _ => {}
+    }
+}
@@ -1,7 +1,7 @@
 use enumtastic::const_enum;
 
 use crate::{
-    arch::interrupts::interrupt_frame::InterruptFrame,
+    arch::interrupts::{interrupt_frame::InterruptFrame, IrqScope},
     arch::iobus::{inb, outb},
     arch::pic,
     debug,
@@ -55,6 +55,7 @@ const_enum! {
 }
 
 pub extern "x86-interrupt" fn ps2_mouse_interrupt_handler(_a: InterruptFrame) {
+    let _irq = IrqScope::enter(pic::PicInterrupt::Ps2MouseInterrupt as usize);
     // Read the input
     let _data = inb(Ps2MousePicPort::DataPort);
     debug!("Mouse handler called");
@@ -1,7 +1,9 @@
 use bks::Handover;
 
+pub mod ahci;
+pub mod hwrng;
 pub mod input;
-
-pub fn init_drivers() {
-    //input::ps2_mouse::ps2_mouse_init();
-}
+#[cfg(feature = "nvme")]
+pub mod nvme;
+pub mod serial;
+pub mod virtio;
@@ -0,0 +1,815 @@
+//! # NVMe
+//! A driver for NVMe controllers (PCI class 01:08:02).
+//!
+//! Every controller gets the admin queue pair and a single I/O queue pair, through which
+//! commands are issued one at a time. Completions on the I/O queue are signalled through MSI-X or
+//! MSI, unless the `nvme.poll` flag is given on the command line or neither can be set up. Admin
+//! commands are only used while initializing and are always polled.
+//!
+//! A controller can be unbound while none of its namespaces is in use. It is disabled before
+//! its queues are freed, so it cannot write into them once they are reused.
+use alloc::{string::String, sync::Arc, vec::Vec};
+use bks::PAGE_SIZE;
+
+use crate::arch::interrupts;
+use crate::block::{self, blocks_in, BlockDevice};
+use crate::device::tree;
+use crate::error::{Error, Result};
+use crate::irq::msi::{self, MsiVectors};
+use crate::memory::dma::{DmaBuffer, DmaConstraints};
+use crate::memory::paging::pat::MemoryType;
+use crate::memory::{map_mmio, virt_to_phys, PhysicalAddress, VirtualAddress};
+use crate::pci::{self, PciDevice, PciDriver};
+use crate::scheduler::spin::Mutex;
+use crate::scheduler::{self, sync, WaitQueue};
+use crate::{cmdline, debug, info, warn, watchdog};
+
+pub const PCI_CLASS_MASS_STORAGE: u8 = 0x01;
+pub const PCI_SUBCLASS_NVM: u8 = 0x08;
+pub const PCI_PROGRAM_INTERFACE_NVME: u8 = 0x02;
+/// Command line flag that disables MSI-X and MSI, completions are polled instead
+pub const POLL_FLAG: &str = "nvme.poll";
+/// How often the controller is polled before giving up
+const TIMEOUT_SPINS: u32 = 10_000_000;
+const ADMIN_QUEUE_ENTRIES: u16 = 32;
+const IO_QUEUE_ENTRIES: u16 = 64;
+const IO_QUEUE_ID: u16 = 1;
+/// Namespaces with a higher id are ignored
+const MAX_NAMESPACES: u32 = 16;
+/// The PRP list of a command fills exactly one page
+const PRP_LIST_ENTRIES: usize = PAGE_SIZE as usize / 8;
+/// PRP1 covers (a part of) the first page, the PRP list every further one
+const MAX_TRANSFER: usize = PRP_LIST_ENTRIES * PAGE_SIZE as usize;
+/// The number of logical blocks is a 16 bit field (minus one)
+const MAX_BLOCKS_PER_COMMAND: u64 = 0x1_0000;
+
+enumtastic::const_enum! {
+    // NVM Express Base Specification 1.4, 3.1 - Register Definition
+    pub enum NvmeRegister: u64 => {
+        Capabilities = 0x00,
+        Version = 0x08,
+        InterruptMaskSet = 0x0C,
+        InterruptMaskClear = 0x10,
+        Configuration = 0x14,
+        Status = 0x1C,
+        AdminQueueAttributes = 0x24,
+        AdminSubmissionQueue = 0x28,
+        AdminCompletionQueue = 0x30,
+        Doorbells = 0x1000,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum ControllerConfiguration: u32 => {
+        Enable = 1 << 0,
+        /// 2^6 = 64 byte submission queue entries
+        IoSubmissionEntrySize = 6 << 16,
+        /// 2^4 = 16 byte completion queue entries
+        IoCompletionEntrySize = 4 << 20,
+        NormalShutdown = 1 << 14,
+        ShutdownMask = 3 << 14,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum ControllerStatus: u32 => {
+        Ready = 1 << 0,
+        FatalStatus = 1 << 1,
+        ShutdownComplete = 2 << 2,
+        ShutdownMask = 3 << 2,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum AdminOpcode: u8 => {
+        CreateIoSubmissionQueue = 0x01,
+        CreateIoCompletionQueue = 0x05,
+        Identify = 0x06,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum IoOpcode: u8 => {
+        Write = 0x01,
+        Read = 0x02,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    /// The controller or namespace structure IDENTIFY returns
+    pub enum IdentifyCns: u32 => {
+        Namespace = 0x00,
+        Controller = 0x01,
+    }
+
+    impl {}
+}
+
+/// CREATE I/O {SUBMISSION, COMPLETION} QUEUE: The queue is physically contiguous
+const QUEUE_PHYSICALLY_CONTIGUOUS: u32 = 1 << 0;
+/// CREATE I/O COMPLETION QUEUE: Signal completions through the interrupt vector in bits 16-31
+const QUEUE_INTERRUPTS_ENABLED: u32 = 1 << 1;
+/// The phase tag in the status field of a completion
+const COMPLETION_PHASE: u16 = 1 << 0;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct SubmissionEntry {
+    opcode: u8,
+    flags: u8,
+    command_id: u16,
+    namespace_id: u32,
+    _reserved: u64,
+    metadata: u64,
+    prp1: u64,
+    prp2: u64,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct CompletionEntry {
+    result: u32,
+    _reserved: u32,
+    sq_head: u16,
+    sq_id: u16,
+    command_id: u16,
+    /// The phase tag in bit 0, the status code above
+    status: u16,
+}
+
+/// Tasks waiting for a completion on any controller
+static COMPLETION_WAITERS: WaitQueue = WaitQueue::new();
+
+fn read(base: u64, register: u64) -> u32 {
+    unsafe { core::ptr::read_volatile((base + register) as *const u32) }
+}
+
+fn read_u64(base: u64, register: u64) -> u64 {
+    unsafe { core::ptr::read_volatile((base + register) as *const u64) }
+}
+
+fn write(base: u64, register: u64, value: u32) {
+    unsafe { core::ptr::write_volatile((base + register) as *mut u32, value) }
+}
+
+fn write_u64(base: u64, register: u64, value: u64) {
+    unsafe { core::ptr::write_volatile((base + register) as *mut u64, value) }
+}
+
+/// # Wait While
+/// Polls until `condition` returns false
+fn wait_while(mut condition: impl FnMut() -> bool) -> Result<()> {
+    for _ in 0..TIMEOUT_SPINS {
+        if !condition() {
+            return Ok(());
+        }
+        watchdog::touch();
+        comasm::pause();
+    }
+    Err(Error::ConnectionTimedOut)
+}
+
+/// # Queue Pair
+/// A submission queue together with the completion queue it completes to
+struct QueuePair {
+    submission: DmaBuffer,
+    completion: DmaBuffer,
+    entries: u16,
+    sq_tail: u16,
+    cq_head: u16,
+    /// The phase tag of completions posted in the current pass, flips every time the queue wraps
+    phase: bool,
+    sq_doorbell: u64,
+    cq_doorbell: u64,
+    next_command_id: u16,
+}
+
+impl QueuePair {
+    fn new(registers: u64, id: u16, entries: u16, doorbell_stride: u64) -> Result<Self> {
+        let doorbell = registers + NvmeRegister::Doorbells + 2 * id as u64 * doorbell_stride;
+        Ok(Self {
+            submission: DmaBuffer::new_zeroed(
+                entries as usize * core::mem::size_of::<SubmissionEntry>(),
+                DmaConstraints::ANY,
+            )?,
+            completion: DmaBuffer::new_zeroed(
+                entries as usize * core::mem::size_of::<CompletionEntry>(),
+                DmaConstraints::ANY,
+            )?,
+            entries,
+            sq_tail: 0,
+            cq_head: 0,
+            phase: true,
+            sq_doorbell: doorbell,
+            cq_doorbell: doorbell + doorbell_stride,
+            next_command_id: 0,
+        })
+    }
+
+    fn submit(&mut self, mut command: SubmissionEntry) -> u16 {
+        let id = self.next_command_id;
+        self.next_command_id = self.next_command_id.wrapping_add(1);
+        command.command_id = id;
+        unsafe {
+            let entry = self
+                .submission
+                .as_mut_ptr::<SubmissionEntry>()
+                .add(self.sq_tail as usize);
+            core::ptr::write_volatile(entry, command);
+        }
+        self.sq_tail = (self.sq_tail + 1) % self.entries;
+        write(self.sq_doorbell, 0, self.sq_tail as u32);
+        id
+    }
+
+    /// The completion at the head, if the controller already posted it
+    fn peek(&self) -> Option<CompletionEntry> {
+        let entry = unsafe {
+            core::ptr::read_volatile(
+                self.completion
+                    .as_ptr::<CompletionEntry>()
+                    .add(self.cq_head as usize),
+            )
+        };
+        (((entry.status & COMPLETION_PHASE) != 0) == self.phase).then(|| entry)
+    }
+
+    fn pop(&mut self) -> Option<CompletionEntry> {
+        let entry = self.peek()?;
+        self.cq_head += 1;
+        if self.cq_head == self.entries {
+            self.cq_head = 0;
+            self.phase = !self.phase;
+        }
+        write(self.cq_doorbell, 0, self.cq_head as u32);
+        Some(entry)
+    }
+
+    /// # Execute
+    /// Submits `command` and waits for its completion, either by polling or, if `sleep` is set,
+    /// by sleeping until the completion interrupt arrived
+    fn execute(&mut self, command: SubmissionEntry, sleep: bool) -> Result<CompletionEntry> {
+        let id = self.submit(command);
+        loop {
+            if sleep {
+                COMPLETION_WAITERS.wait_until(|| self.peek().is_some());
+            } else {
+                wait_while(|| self.peek().is_none())?;
+            }
+            let entry = self.pop().unwrap();
+            // A late completion of a command that timed out
+            if entry.command_id != id {
+                continue;
+            }
+            return match entry.status >> 1 {
+                0 => Ok(entry),
+                _ => Err(Error::IOError),
+            };
+        }
+    }
+}
+
+/// # I/O Queue
+/// The I/O queue pair and the PRP list its commands use
+struct IoQueue {
+    queue: QueuePair,
+    prp_list: DmaBuffer,
+}
+
+impl IoQueue {
+    /// # Read Write
+    /// Transfers `blocks` blocks between the namespace and the `len` bytes at `addr` with a
+    /// single command
+    fn read_write(
+        &mut self,
+        command: IoCommand,
+        blocks: u64,
+        addr: u64,
+        len: usize,
+        sleep: bool,
+    ) -> Result<()> {
+        // PRP1 may point into the middle of a page, every further entry to the start of one
+        let first_chunk = (PAGE_SIZE - addr % PAGE_SIZE) as usize;
+        let phys = |virt: u64| virt_to_phys(VirtualAddress::new(virt)).as_u64();
+        let prp2 = if len <= first_chunk {
+            0
+        } else if len <= first_chunk + PAGE_SIZE as usize {
+            phys(addr + first_chunk as u64)
+        } else {
+            let list = self.prp_list.as_mut_ptr::<u64>();
+            let pages = (addr + first_chunk as u64..addr + len as u64).step_by(PAGE_SIZE as usize);
+            for (idx, page) in pages.enumerate() {
+                unsafe { list.add(idx).write(phys(page)) };
+            }
+            self.prp_list.phys().as_u64()
+        };
+
+        self.queue.execute(
+            SubmissionEntry {
+                opcode: command.opcode,
+                namespace_id: command.namespace,
+                prp1: phys(addr),
+                prp2,
+                cdw10: command.lba as u32,
+                cdw11: (command.lba >> 32) as u32,
+                cdw12: (blocks - 1) as u32,
+                ..Default::default()
+            },
+            sleep,
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct IoCommand {
+    opcode: u8,
+    namespace: u32,
+    lba: u64,
+}
+
+/// # Controller
+/// An initialized controller with its queues
+pub struct Controller {
+    device: PciDevice,
+    /// The virtual address of the registers
+    registers: u64,
+    admin: Mutex<QueuePair>,
+    io: sync::Mutex<IoQueue>,
+    /// Whether completions on the I/O queue raise an interrupt
+    use_interrupts: bool,
+    /// The vector completions are signalled with, masked when the controller is dropped
+    _msi: Option<MsiVectors>,
+    /// The largest number of bytes a single command may transfer
+    max_transfer: usize,
+    namespace_count: u32,
+    model: String,
+}
+
+impl Controller {
+    /// # Identify
+    /// Runs IDENTIFY and returns the page it filled in
+    fn identify(&self, cns: u32, namespace: u32) -> Result<DmaBuffer> {
+        let data = DmaBuffer::new_zeroed(PAGE_SIZE as usize, DmaConstraints::ANY)?;
+        self.admin.lock().execute(
+            SubmissionEntry {
+                opcode: AdminOpcode::Identify,
+                namespace_id: namespace,
+                prp1: data.phys().as_u64(),
+                cdw10: cns,
+                ..Default::default()
+            },
+            false,
+        )?;
+        Ok(data)
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// # Disable
+    /// Shuts the controller down and disables it, it accesses no memory afterwards
+    fn disable(&self) {
+        shutdown_controller(self.registers as usize);
+        let config = read(self.registers, NvmeRegister::Configuration);
+        write(
+            self.registers,
+            NvmeRegister::Configuration,
+            config & !ControllerConfiguration::Enable,
+        );
+        if wait_while(|| read(self.registers, NvmeRegister::Status) & ControllerStatus::Ready != 0)
+            .is_err()
+        {
+            warn!("NVMe: The controller did not become disabled");
+        }
+    }
+
+    /// # Transfer
+    /// Splits the transfer into commands the controller and the PRP list can handle. A `polled`
+    /// transfer fails with `Error::OperationWouldBlock` instead of waiting for the I/O queue.
+    fn transfer(
+        &self,
+        namespace: &NvmeNamespace,
+        lba: u64,
+        addr: u64,
+        len: usize,
+        write: bool,
+        polled: bool,
+    ) -> Result<()> {
+        let count = blocks_in(namespace, len)?;
+        if addr % 4 != 0 {
+            return Err(Error::InvalidArgument);
+        }
+        if lba
+            .checked_add(count)
+            .map_or(true, |end| end > namespace.blocks)
+        {
+            return Err(Error::IOError);
+        }
+        let opcode = if write {
+            IoOpcode::Write
+        } else {
+            IoOpcode::Read
+        };
+        let block_size = namespace.block_size as u64;
+        let per_command = (self.max_transfer as u64 / block_size).min(MAX_BLOCKS_PER_COMMAND);
+        let sleep = self.use_interrupts && scheduler::is_running() && interrupts::are_enabled();
+
+        let mut io = if polled {
+            self.io.try_lock().ok_or(Error::OperationWouldBlock)?
+        } else {
+            self.io.lock()
+        };
+        let mut done = 0;
+        while done < count {
+            let blocks = (count - done).min(per_command);
+            io.read_write(
+                IoCommand {
+                    opcode,
+                    namespace: namespace.id,
+                    lba: lba + done,
+                },
+                blocks,
+                addr + done * block_size,
+                (blocks * block_size) as usize,
+                sleep,
+            )?;
+            done += blocks;
+        }
+        Ok(())
+    }
+}
+
+/// # NVMe Namespace
+/// A namespace of a controller as a block device
+pub struct NvmeNamespace {
+    controller: Arc<Controller>,
+    id: u32,
+    block_size: usize,
+    blocks: u64,
+}
+
+impl NvmeNamespace {
+    pub fn controller(&self) -> &Controller {
+        &self.controller
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl BlockDevice for NvmeNamespace {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        self.controller
+            .transfer(self, lba, buf.as_mut_ptr() as u64, buf.len(), false, false)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        self.controller
+            .transfer(self, lba, buf.as_ptr() as u64, buf.len(), true, false)
+    }
+
+    fn write_blocks_polled(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        self.controller
+            .transfer(self, lba, buf.as_ptr() as u64, buf.len(), true, true)
+    }
+}
+
+/// All namespaces of the bound controllers
+pub static NVME_NAMESPACES: Mutex<Vec<Arc<NvmeNamespace>>> = Mutex::new(Vec::new());
+/// The bound controllers, also the ones without an active namespace
+static CONTROLLERS: Mutex<Vec<Arc<Controller>>> = Mutex::new(Vec::new());
+
+/// Wakes every task waiting for a completion, each of them checks its own queue
+fn nvme_interrupt(_index: usize, _data: usize) {
+    COMPLETION_WAITERS.wake_all();
+}
+
+/// # Shutdown Controller
+/// The shutdown hook of a controller with its registers at `registers`: Tells it the power goes
+/// away, so it writes back its cache, and waits until it is done
+fn shutdown_controller(registers: usize) {
+    let registers = registers as u64;
+    let config = read(registers, NvmeRegister::Configuration);
+    write(
+        registers,
+        NvmeRegister::Configuration,
+        config & !ControllerConfiguration::ShutdownMask | ControllerConfiguration::NormalShutdown,
+    );
+    if wait_while(|| {
+        read(registers, NvmeRegister::Status) & ControllerStatus::ShutdownMask
+            != ControllerStatus::ShutdownComplete
+    })
+    .is_err()
+    {
+        warn!("NVMe: The controller did not finish its shutdown");
+    }
+}
+
+/// # Init Controller
+/// Resets the controller of `device`, sets up its queues and identifies it
+fn init_controller(device: PciDevice) -> Result<Arc<Controller>> {
+    device.enable_bus_mastering();
+    let registers_phys = device.bar(0);
+    // The doorbells are mapped once their stride is known
+    let registers = map_mmio(
+        PhysicalAddress::new(registers_phys),
+        NvmeRegister::Doorbells,
+        MemoryType::Uncacheable,
+    )?
+    .as_u64();
+    let capabilities = read_u64(registers, NvmeRegister::Capabilities);
+    // Bits 32-35: The doorbells are 2^(2 + DSTRD) bytes apart
+    let doorbell_stride = 4 << ((capabilities >> 32) & 0xF);
+    // Bits 48-51: The smallest supported page size is 2^(12 + MPSMIN)
+    if (capabilities >> 48) & 0xF != 0 {
+        return Err(Error::NoSuchDevice);
+    }
+    // Bits 0-15: The largest supported queue size minus one
+    let max_entries = (capabilities & 0xFFFF) as u16 + 1;
+
+    let registers_size = NvmeRegister::Doorbells + 2 * (IO_QUEUE_ID as u64 + 1) * doorbell_stride;
+    map_mmio(
+        PhysicalAddress::new(registers_phys),
+        registers_size,
+        MemoryType::Uncacheable,
+    )?;
+
+    // Reset
+    write(registers, NvmeRegister::Configuration, 0);
+    wait_while(|| read(registers, NvmeRegister::Status) & ControllerStatus::Ready != 0)?;
+
+    let admin_entries = ADMIN_QUEUE_ENTRIES.min(max_entries);
+    let admin = QueuePair::new(registers, 0, admin_entries, doorbell_stride)?;
+    let attributes = (admin_entries as u32 - 1) << 16 | (admin_entries as u32 - 1);
+    write(registers, NvmeRegister::AdminQueueAttributes, attributes);
+    write_u64(
+        registers,
+        NvmeRegister::AdminSubmissionQueue,
+        admin.submission.phys().as_u64(),
+    );
+    write_u64(
+        registers,
+        NvmeRegister::AdminCompletionQueue,
+        admin.completion.phys().as_u64(),
+    );
+    write(
+        registers,
+        NvmeRegister::Configuration,
+        ControllerConfiguration::Enable
+            | ControllerConfiguration::IoSubmissionEntrySize
+            | ControllerConfiguration::IoCompletionEntrySize,
+    );
+    wait_while(|| {
+        read(registers, NvmeRegister::Status)
+            & (ControllerStatus::Ready | ControllerStatus::FatalStatus)
+            == 0
+    })?;
+    if read(registers, NvmeRegister::Status) & ControllerStatus::FatalStatus != 0 {
+        return Err(Error::IOError);
+    }
+
+    let msi = if cmdline::flag(POLL_FLAG) {
+        None
+    } else {
+        match msi::allocate_msi(&device, 1, "nvme", nvme_interrupt, 0) {
+            Ok(vectors) => Some(vectors),
+            Err(err) => {
+                warn!("NVMe: Falling back to polling: {}", err.text());
+                None
+            }
+        }
+    };
+    let use_interrupts = msi.is_some();
+    let io_entries = IO_QUEUE_ENTRIES.min(max_entries);
+    let mut controller = Controller {
+        device,
+        registers,
+        admin: Mutex::new(admin),
+        io: sync::Mutex::new(IoQueue {
+            queue: QueuePair::new(registers, IO_QUEUE_ID, io_entries, doorbell_stride)?,
+            prp_list: DmaBuffer::new_zeroed(PAGE_SIZE as usize, DmaConstraints::ANY)?,
+        }),
+        use_interrupts,
+        _msi: msi,
+        max_transfer: MAX_TRANSFER,
+        namespace_count: 0,
+        model: String::new(),
+    };
+
+    let identify = controller.identify(IdentifyCns::Controller, 0)?;
+    let data = identify.as_slice();
+    // Bytes 24-63: The model number, padded with spaces
+    controller.model = String::from_utf8_lossy(&data[24..64]).trim().into();
+    // Byte 77: The maximum transfer size as a power of two in units of the minimum page size
+    if data[77] != 0 {
+        controller.max_transfer = MAX_TRANSFER.min((PAGE_SIZE as usize) << data[77]);
+    }
+    // Bytes 516-519: The number of namespaces
+    controller.namespace_count = u32::from_le_bytes(data[516..520].try_into().unwrap());
+
+    let queue_size = (io_entries as u32 - 1) << 16 | IO_QUEUE_ID as u32;
+    let mut flags = QUEUE_PHYSICALLY_CONTIGUOUS;
+    if use_interrupts {
+        // Message 0 is left in bits 16-31
+        flags |= QUEUE_INTERRUPTS_ENABLED;
+    }
+    let (submission, completion) = {
+        let io = controller.io.get_mut();
+        (
+            io.queue.submission.phys().as_u64(),
+            io.queue.completion.phys().as_u64(),
+        )
+    };
+    let mut admin = controller.admin.lock();
+    admin.execute(
+        SubmissionEntry {
+            opcode: AdminOpcode::CreateIoCompletionQueue,
+            prp1: completion,
+            cdw10: queue_size,
+            cdw11: flags,
+            ..Default::default()
+        },
+        false,
+    )?;
+    admin.execute(
+        SubmissionEntry {
+            opcode: AdminOpcode::CreateIoSubmissionQueue,
+            prp1: submission,
+            cdw10: queue_size,
+            cdw11: (IO_QUEUE_ID as u32) << 16 | QUEUE_PHYSICALLY_CONTIGUOUS,
+            ..Default::default()
+        },
+        false,
+    )?;
+    drop(admin);
+
+    match device.node() {
+        Some(node) => tree::set_shutdown_hook(node, shutdown_controller, registers as usize)?,
+        None => warn!("NVMe: {} is not in the device tree", device.location()),
+    }
+    Ok(Arc::new(controller))
+}
+
+/// # Init Namespaces
+/// Identifies the active namespaces of `controller`
+fn init_namespaces(controller: &Arc<Controller>) -> Result<Vec<NvmeNamespace>> {
+    let mut namespaces = Vec::new();
+    for id in 1..=controller.namespace_count.min(MAX_NAMESPACES) {
+        let identify = controller.identify(IdentifyCns::Namespace, id)?;
+        let data = identify.as_slice();
+        // Bytes 0-7: The size in logical blocks, 0 for inactive namespaces
+        let blocks = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        // Byte 26, bits 0-3: The LBA format in use, described at byte 128 onwards
+        let format = 128 + (data[26] & 0xF) as usize * 4;
+        let metadata_size = u16::from_le_bytes([data[format], data[format + 1]]);
+        let block_shift = data[format + 2] as u32;
+        if blocks == 0 {
+            continue;
+        }
+        if metadata_size != 0 || !(9..=12).contains(&block_shift) {
+            warn!("NVMe: Namespace {} has an unsupported format", id);
+            continue;
+        }
+        namespaces.push(NvmeNamespace {
+            controller: controller.clone(),
+            id,
+            block_size: 1 << block_shift,
+            blocks,
+        });
+    }
+    Ok(namespaces)
+}
+
+/// # NVMe Driver
+/// Binds to NVMe controllers
+pub struct NvmeDriver;
+
+pub static NVME_DRIVER: NvmeDriver = NvmeDriver;
+
+impl PciDriver for NvmeDriver {
+    fn name(&self) -> &'static str {
+        "nvme"
+    }
+
+    fn matches(&self, device: &PciDevice) -> bool {
+        device.class == PCI_CLASS_MASS_STORAGE
+            && device.subclass == PCI_SUBCLASS_NVM
+            && device.program_interface == PCI_PROGRAM_INTERFACE_NVME
+    }
+
+    fn attach(&self, device: PciDevice) -> Result<()> {
+        debug!(
+            "NVMe: Controller {:04x}:{:04x}",
+            device.vendor_id, device.device_id
+        );
+        let controller = init_controller(device)?;
+        let namespaces = init_namespaces(&controller).map_err(|err| {
+            controller.disable();
+            err
+        })?;
+        CONTROLLERS.lock().push(controller);
+        for namespace in namespaces {
+            info!(
+                "NVMe: {} namespace {}: {} blocks of {} bytes{}",
+                namespace.controller.model(),
+                namespace.id,
+                namespace.blocks,
+                namespace.block_size,
+                if namespace.controller.use_interrupts {
+                    ""
+                } else {
+                    " (polling)"
+                }
+            );
+            let namespace = Arc::new(namespace);
+            NVME_NAMESPACES.lock().push(namespace.clone());
+            block::register_disk(namespace);
+        }
+        Ok(())
+    }
+
+    /// # Detach
+    /// Unregisters the namespaces of the controller of `device` and disables it
+    ///
+    /// ## Returns
+    /// - Error::DeviceOrResourceBusy = A namespace or a partition on it is in use
+    fn detach(&self, device: &PciDevice) -> Result<()> {
+        let is_ours = |controller: &Controller| controller.device.address == device.address;
+        let namespaces = {
+            let mut all = NVME_NAMESPACES.lock();
+            let (ours, others): (Vec<_>, Vec<_>) = all
+                .drain(..)
+                .partition(|namespace| is_ours(&namespace.controller));
+            *all = others;
+            let disks: Vec<&dyn BlockDevice> = ours
+                .iter()
+                .map(|namespace| &**namespace as &dyn BlockDevice)
+                .collect();
+            if let Err(err) = block::unregister_disks(&disks) {
+                all.extend(ours);
+                return Err(err);
+            }
+            ours
+        };
+        let controller = {
+            let mut controllers = CONTROLLERS.lock();
+            let idx = controllers
+                .iter()
+                .position(|controller| is_ours(controller))
+                .ok_or(Error::NoSuchDevice)?;
+            controllers.remove(idx)
+        };
+        // Stopped before the queues and the vector go away with the last reference
+        controller.disable();
+        drop(namespaces);
+        debug_assert_eq!(
+            Arc::strong_count(&controller),
+            1,
+            "NVMe: The queues of {} are still referenced",
+            device.location()
+        );
+        Ok(())
+    }
+}
+
+crate::initcall! {
+    name: "nvme",
+    stage: Scheduled,
+    deps: ["pci", "ahci"],
+    fatal: false,
+    feature: "nvme",
+    init: init_nvme,
+}
+
+/// # Init NVMe
+/// Claims every NVMe controller registered on the PCI bus
+pub fn init_nvme() -> Result<()> {
+    pci::register_driver(&NVME_DRIVER).map(|_| ())
+}
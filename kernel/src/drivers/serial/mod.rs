@@ -0,0 +1,419 @@
+//! # Serial
+//! A polling driver for 16550 compatible UARTs, used for logging when there is no
+//! (usable) framebuffer. `serial=com2,115200` on the command line copies the kernel log to a
+//! port at the given baud rate, the option can be given once per port.
+//! Based on https://wiki.osdev.org/Serial_Ports
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::arch::interrupts::exception_safe_lock;
+use crate::device::tree::{self, DeviceClass, Resource};
+use crate::error::{Error, Result};
+use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::iobus::{inb, outb};
+use crate::klog::{self, LogSink};
+use crate::scheduler::spin::Mutex;
+use crate::{cmdline, info, warn};
+
+enumtastic::const_enum! {
+    pub enum SerialPort: u16 => {
+        Com1 = 0x3F8,
+        Com2 = 0x2F8,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    /// Offsets from the base port
+    pub enum UartRegister: u16 => {
+        Data = 0,
+        InterruptEnable = 1,
+        /// Divisor Latch Low Byte (if DLAB is set)
+        DivisorLow = 0,
+        /// Divisor Latch High Byte (if DLAB is set)
+        DivisorHigh = 1,
+        FifoControl = 2,
+        LineControl = 3,
+        ModemControl = 4,
+        LineStatus = 5,
+        /// Holds whatever was written to it, the UART itself does not use it
+        Scratch = 7,
+    }
+
+    impl {}
+}
+
+/// The command line option configuring a port, `serial=<port>[,<baud>]`
+pub const OPTION: &str = "serial";
+/// Set in the line status register while a received byte waits in the data register
+const LINE_STATUS_DATA_READY: u8 = 1;
+/// Set in the line status register once the transmitter can accept a byte
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+/// Set in the line status register once the last byte has left the shift register
+const LINE_STATUS_TRANSMITTER_IDLE: u8 = 1 << 6;
+/// The baud rate of a divisor of 1
+pub const BASE_BAUD: u32 = 115_200;
+/// The baud rate ports are initialized with
+pub const DEFAULT_BAUD: u32 = 38_400;
+/// The patterns written to the scratch register by `probe()`
+const SCRATCH_TEST_BYTES: [u8; 2] = [0x55, 0xAA];
+/// The byte sent during the loopback test
+const LOOPBACK_TEST_BYTE: u8 = 0xAE;
+/// The number of registers of a UART
+const UART_PORT_COUNT: u16 = 8;
+/// How often the transmit register is polled before a byte is dropped
+const TRANSMIT_TIMEOUT: usize = 100_000;
+
+pub static SERIAL: Mutex<Serial> = Mutex::new(Serial::new(SerialPort::Com1));
+pub static SERIAL2: Mutex<Serial> = Mutex::new(Serial::new(SerialPort::Com2));
+/// Whether COM1 and COM2 passed initialization
+static IS_PORT_PRESENT: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+static COM1_SINK: SerialSink = SerialSink {
+    name: "com1",
+    port: SerialPort::Com1,
+    serial: &SERIAL,
+};
+static COM2_SINK: SerialSink = SerialSink {
+    name: "com2",
+    port: SerialPort::Com2,
+    serial: &SERIAL2,
+};
+
+crate::counter!(pub RAW_BYTES = "serial.raw_bytes");
+
+/// # Ports
+/// The name, the driver and the log sink of every port
+pub fn ports() -> [(&'static str, &'static Mutex<Serial>, &'static SerialSink); 2] {
+    [
+        ("com1", &SERIAL, &COM1_SINK),
+        ("com2", &SERIAL2, &COM2_SINK),
+    ]
+}
+
+/// # Find Port
+/// The port called `name`, e.g. `com2`
+pub fn find_port(name: &str) -> Option<(&'static Mutex<Serial>, &'static SerialSink)> {
+    ports()
+        .into_iter()
+        .find(|(port, _, _)| *port == name)
+        .map(|(_, serial, sink)| (serial, sink))
+}
+
+/// The line of a port on the PIC
+fn irq(port: u16) -> u8 {
+    match port {
+        SerialPort::Com2 => 3,
+        _ => 4,
+    }
+}
+
+fn presence(port: u16) -> &'static AtomicBool {
+    match port {
+        SerialPort::Com2 => &IS_PORT_PRESENT[1],
+        _ => &IS_PORT_PRESENT[0],
+    }
+}
+
+/// # Divisor
+/// The divisor latch value for `baud`
+///
+/// ## Returns
+/// - Error::InvalidArgument = `baud` is not `BASE_BAUD` divided by a whole number
+pub fn divisor(baud: u32) -> Result<u16> {
+    if baud == 0 || BASE_BAUD % baud != 0 || BASE_BAUD / baud > u16::MAX as u32 {
+        return Err(Error::InvalidArgument);
+    }
+    Ok((BASE_BAUD / baud) as u16)
+}
+
+pub struct Serial {
+    port: u16,
+    baud: u32,
+}
+
+impl Serial {
+    pub const fn new(port: u16) -> Self {
+        Self {
+            port,
+            baud: DEFAULT_BAUD,
+        }
+    }
+
+    pub fn baud(&self) -> u32 {
+        self.baud
+    }
+
+    pub fn is_present(&self) -> bool {
+        presence(self.port).load(Ordering::Relaxed)
+    }
+
+    /// # Probe
+    /// Checks whether there is a UART at the port by writing patterns to its scratch register
+    /// and reading them back. Nothing else is written, so it is safe for ports that are not
+    /// there or are in use.
+    pub fn probe(&mut self) -> bool {
+        let saved = self.read_register(UartRegister::Scratch);
+        let present = SCRATCH_TEST_BYTES.iter().all(|pattern| {
+            self.write_register(UartRegister::Scratch, *pattern);
+            self.read_register(UartRegister::Scratch) == *pattern
+        });
+        self.write_register(UartRegister::Scratch, saved);
+        present
+    }
+
+    /// # Init
+    /// Initializes the UART with `baud`, 8N1 and checks that it exists by sending a byte in
+    /// loopback mode.
+    ///
+    /// ## Returns
+    /// - bool = Whether the UART passed the loopback test
+    pub fn init(&mut self, baud: u32) -> bool {
+        let divisor = match divisor(baud) {
+            Ok(divisor) => divisor,
+            Err(_) => return false,
+        };
+        self.write_register(UartRegister::InterruptEnable, 0x00);
+        self.write_divisor(divisor);
+        self.baud = baud;
+        // Enable and clear the FIFOs, 14 byte threshold
+        self.write_register(UartRegister::FifoControl, 0xC7);
+        // Loopback mode for the test
+        self.write_register(UartRegister::ModemControl, 0x1E);
+        self.write_register(UartRegister::Data, LOOPBACK_TEST_BYTE);
+        if self.read_register(UartRegister::Data) != LOOPBACK_TEST_BYTE {
+            return false;
+        }
+        // Normal operation: DTR, RTS, OUT1 and OUT2 set
+        self.write_register(UartRegister::ModemControl, 0x0F);
+        presence(self.port).store(true, Ordering::SeqCst);
+        true
+    }
+
+    /// # Set Baud
+    /// Reprograms the divisor latch for `baud`. Everything written before is sent at the old
+    /// rate first, as the transmitter would garble the bytes it is shifting out. The caller
+    /// holds the lock of the port, so nothing is written in between.
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = See `divisor()`
+    /// - Error::NoSuchDevice = The port is not present
+    /// - Error::ConnectionTimedOut = The transmitter did not become idle
+    pub fn set_baud(&mut self, baud: u32) -> Result<()> {
+        let divisor = divisor(baud)?;
+        if !self.is_present() {
+            return Err(Error::NoSuchDevice);
+        }
+        self.flush()?;
+        self.write_divisor(divisor);
+        self.baud = baud;
+        Ok(())
+    }
+
+    /// # Flush
+    /// Waits until the FIFO and the shift register are empty
+    ///
+    /// ## Returns
+    /// - Error::ConnectionTimedOut = The transmitter did not become idle
+    pub fn flush(&mut self) -> Result<()> {
+        for _ in 0..TRANSMIT_TIMEOUT {
+            if self.read_register(UartRegister::LineStatus) & LINE_STATUS_TRANSMITTER_IDLE != 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::ConnectionTimedOut)
+    }
+
+    /// Sets the baud rate divisor and leaves the line at 8N1 with DLAB disabled
+    fn write_divisor(&mut self, divisor: u16) {
+        // Enable DLAB, then set the baud rate divisor
+        self.write_register(UartRegister::LineControl, 0x80);
+        self.write_register(UartRegister::DivisorLow, (divisor & 0xff) as u8);
+        self.write_register(UartRegister::DivisorHigh, (divisor >> 8) as u8);
+        // 8 bits, no parity, one stop bit, DLAB disabled
+        self.write_register(UartRegister::LineControl, 0x03);
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        for _ in 0..TRANSMIT_TIMEOUT {
+            if self.read_register(UartRegister::LineStatus) & LINE_STATUS_TRANSMIT_EMPTY != 0 {
+                self.write_register(UartRegister::Data, byte);
+                return;
+            }
+        }
+    }
+
+    /// # Read Byte
+    /// The next received byte, if there is one
+    pub fn read_byte(&mut self) -> Option<u8> {
+        (self.read_register(UartRegister::LineStatus) & LINE_STATUS_DATA_READY != 0)
+            .then(|| self.read_register(UartRegister::Data))
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        outb(self.port + register, value);
+    }
+
+    fn read_register(&mut self, register: u16) -> u8 {
+        inb(self.port + register)
+    }
+}
+
+impl Write for Serial {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if !self.is_present() {
+            return Ok(());
+        }
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+/// # Serial Sink
+/// Copies the kernel log to a port
+pub struct SerialSink {
+    name: &'static str,
+    port: u16,
+    serial: &'static Mutex<Serial>,
+}
+
+impl LogSink for SerialSink {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn write_str(&self, s: &str) {
+        match exception_safe_lock(self.serial) {
+            Some(mut serial) => {
+                let _ = serial.write_str(s);
+            }
+            None => write_raw(self.port, s),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(mut serial) = self.serial.try_lock() {
+            let _ = serial.flush();
+        }
+    }
+}
+
+/// # Write Raw
+/// Writes `s` to `port` without its lock, for exception handlers that interrupted the holder.
+/// A `Serial` is no more than the number of its port, so a copy of it writes just as well,
+/// only the bytes of both writers may end up interleaved.
+pub fn write_raw(port: u16, s: &str) {
+    let mut serial = Serial::new(port);
+    if serial.is_present() {
+        RAW_BYTES.add(s.len() as u64);
+        let _ = serial.write_str(s);
+    }
+}
+
+/// # Init Serial
+/// Initializes COM1. Writing to a port that failed initialization is a no-op.
+pub fn init_serial() -> bool {
+    SERIAL.lock().init(DEFAULT_BAUD)
+}
+
+pub fn is_present() -> bool {
+    SERIAL.lock().is_present()
+}
+
+/// # Parse Option
+/// Splits the value of a `serial=` option into the port and its baud rate, `DEFAULT_BAUD`
+/// if none is given
+///
+/// ## Returns
+/// - Error::NoSuchDevice = There is no such port
+/// - Error::InvalidArgument = The baud rate is not a number or no valid rate, see `divisor()`
+pub fn parse_option(option: &str) -> Result<(&'static str, u32)> {
+    let (port, baud) = match option.split_once(',') {
+        Some((port, baud)) => (port, baud.parse().map_err(|_| Error::InvalidArgument)?),
+        None => (option, DEFAULT_BAUD),
+    };
+    divisor(baud)?;
+    let (name, _, _) = ports()
+        .into_iter()
+        .find(|(name, _, _)| *name == port)
+        .ok_or(Error::NoSuchDevice)?;
+    Ok((name, baud))
+}
+
+/// # Attach
+/// Sets up the port for the `serial=` option `option` and copies the kernel log to it
+fn attach(option: &str) -> Result<()> {
+    let (name, baud) = parse_option(option)?;
+    let (serial, sink) = find_port(name).ok_or(Error::NoSuchDevice)?;
+    let is_console = unsafe { FRAMEBUFFER_GUARD.lock().assume_init_ref().is_serial_only() };
+    let mut port = serial.lock();
+    if !port.probe() {
+        return Err(Error::NoSuchDevice);
+    }
+    if port.is_present() {
+        port.set_baud(baud)?;
+    } else if !port.init(baud) {
+        return Err(Error::NoSuchDevice);
+    }
+    drop(port);
+    // In serial-only mode the console writes to COM1 already
+    if name == "com1" && is_console {
+        return Ok(());
+    }
+    klog::register_sink(sink)
+}
+
+/// # Init Serial Sinks
+/// Attaches every port given by a `serial=` option, then adds the ports that are present to
+/// the device tree
+fn init_serial_sinks() -> Result<()> {
+    for option in cmdline::values(OPTION) {
+        match attach(option) {
+            Ok(()) => info!("serial: Logging to {}", option),
+            Err(e) => warn!("serial: Cannot log to {}: {}", option, e),
+        }
+    }
+    let platform = tree::platform()?;
+    for (name, serial, _) in ports() {
+        let port = {
+            let serial = serial.lock();
+            if !serial.is_present() {
+                continue;
+            }
+            serial.port
+        };
+        let resources = [
+            Resource::IoPorts {
+                base: port,
+                len: UART_PORT_COUNT,
+            },
+            Resource::Irq(irq(port)),
+        ];
+        if let Err(e) = tree::register(
+            name,
+            DeviceClass::Serial,
+            Some(platform),
+            "serial",
+            &resources,
+        ) {
+            warn!("serial: Cannot add {} to the device tree: {}", name, e);
+        }
+    }
+    Ok(())
+}
+
+crate::initcall! {
+    name: "serial",
+    stage: Device,
+    deps: [],
+    fatal: false,
+    init: init_serial_sinks,
+}
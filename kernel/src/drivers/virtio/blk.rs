@@ -0,0 +1,342 @@
+//! # Virtio Block
+//! A driver for virtio block devices, as QEMU provides them with `-drive if=virtio`. Every
+//! device is registered with the block layer as a disk.
+//!
+//! Requests go through a single queue, one at a time, and are polled for. A request is a chain
+//! of a header the device reads, the data split at page boundaries and a status byte the device
+//! writes (Virtio 1.1, 5.2.6). Transfers with more segments than the device takes are split
+//! into several requests, and buffers that are not sector aligned are staged in a DMA buffer so
+//! every request covers whole sectors.
+use alloc::{sync::Arc, vec::Vec};
+use bks::PAGE_SIZE;
+
+use super::queue::Buffer;
+use super::{VirtioPci, Virtqueue, MODERN_DEVICE_ID_BASE, VIRTIO_VENDOR_ID};
+use crate::block::{self, blocks_in, BlockDevice};
+use crate::error::{Error, Result};
+use crate::memory::dma::{DmaBuffer, DmaConstraints};
+use crate::memory::{virt_to_phys, VirtualAddress};
+use crate::pci::{self, PciDevice, PciDriver};
+use crate::scheduler::spin::Mutex;
+use crate::scheduler::sync;
+use crate::{debug, info, watchdog};
+
+pub const DEVICE_TYPE_BLOCK: u16 = 2;
+/// The id of block devices that support both the legacy and the modern interface
+pub const TRANSITIONAL_DEVICE_ID_BLOCK: u16 = 0x1001;
+/// The unit of the capacity and of the sector of a request, whatever the block size
+pub const SECTOR_SIZE: usize = 512;
+/// `seg_max` holds the number of segments a request may have
+const VIRTIO_BLK_F_SEG_MAX: u64 = 1 << 2;
+/// The device cannot be written to
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+/// `blk_size` holds the block size of the device
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+const REQUEST_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 128;
+/// Offsets into the device configuration
+const CONFIG_CAPACITY: u64 = 0;
+const CONFIG_SEG_MAX: u64 = 12;
+const CONFIG_BLK_SIZE: u64 = 20;
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const STATUS_OK: u8 = 0;
+const STATUS_IO_ERROR: u8 = 1;
+const STATUS_UNSUPPORTED: u8 = 2;
+/// What the status byte holds until the device wrote it
+const STATUS_PENDING: u8 = 0xFF;
+const HEADER_SIZE: usize = 16;
+const TIMEOUT_SPINS: u32 = 10_000_000;
+
+crate::counter!(pub REQUESTS = "virtio_blk.requests");
+crate::counter!(pub ERRORS = "virtio_blk.errors");
+crate::counter!(pub STAGED = "virtio_blk.staged_transfers");
+
+/// # Request Queue
+/// The queue and the header and status byte of the request on it
+struct RequestQueue {
+    queue: Virtqueue,
+    /// The header, followed by the status byte
+    header: DmaBuffer,
+}
+
+impl RequestQueue {
+    /// # Request
+    /// Issues a request of `kind` for the data in `segments`, starting at the 512 byte sector
+    /// `sector`, and waits until the device completed it
+    ///
+    /// ## Returns
+    /// - Error::IOError = The device failed the request or does not support it
+    /// - Error::ConnectionTimedOut = The device did not complete the request
+    fn request(&mut self, kind: u32, sector: u64, segments: &[Buffer]) -> Result<()> {
+        let data = self.header.as_mut_slice();
+        data[0..4].copy_from_slice(&kind.to_le_bytes());
+        data[4..8].fill(0);
+        data[8..16].copy_from_slice(&sector.to_le_bytes());
+        data[HEADER_SIZE] = STATUS_PENDING;
+
+        let phys = self.header.phys().as_u64();
+        let mut chain = Vec::with_capacity(segments.len() + 2);
+        chain.push(Buffer {
+            phys,
+            len: HEADER_SIZE as u32,
+            device_writable: false,
+        });
+        chain.extend_from_slice(segments);
+        chain.push(Buffer {
+            phys: phys + HEADER_SIZE as u64,
+            len: 1,
+            device_writable: true,
+        });
+        let token = self.queue.add_buffer(&chain)?;
+        self.queue.notify();
+        REQUESTS.increment();
+
+        let mut spins = 0;
+        loop {
+            match self.queue.poll_used() {
+                Some((done, _)) if done == token => break,
+                // Only one request is ever pending
+                Some(_) => continue,
+                None if spins == TIMEOUT_SPINS => return Err(Error::ConnectionTimedOut),
+                None => {
+                    spins += 1;
+                    watchdog::touch();
+                    comasm::pause();
+                }
+            }
+        }
+
+        match self.header.as_slice()[HEADER_SIZE] {
+            STATUS_OK => Ok(()),
+            status => {
+                ERRORS.increment();
+                let reason = match status {
+                    STATUS_IO_ERROR => "I/O error",
+                    STATUS_UNSUPPORTED => "unsupported",
+                    _ => "unknown status",
+                };
+                debug!(
+                    "virtio-blk: Request {} at sector {} failed: {}",
+                    kind, sector, reason
+                );
+                Err(Error::IOError)
+            }
+        }
+    }
+}
+
+/// # Virtio Block
+/// A virtio block device
+pub struct VirtioBlk {
+    transport: VirtioPci,
+    /// The capacity in 512 byte sectors
+    capacity: u64,
+    block_size: usize,
+    /// The data segments a request may have
+    seg_max: usize,
+    read_only: bool,
+    queue: sync::Mutex<RequestQueue>,
+}
+
+impl VirtioBlk {
+    fn new(device: PciDevice) -> Result<Self> {
+        let transport = VirtioPci::new(device)?;
+        let features =
+            transport.negotiate(VIRTIO_BLK_F_SEG_MAX | VIRTIO_BLK_F_BLK_SIZE | VIRTIO_BLK_F_RO)?;
+        let capacity = transport.read_device_config_u64(CONFIG_CAPACITY);
+        let block_size = match transport.read_device_config_u32(CONFIG_BLK_SIZE) as usize {
+            size if features & VIRTIO_BLK_F_BLK_SIZE != 0
+                && size >= SECTOR_SIZE
+                && size.is_power_of_two() =>
+            {
+                size
+            }
+            _ => SECTOR_SIZE,
+        };
+        let queue = transport.setup_queue(REQUEST_QUEUE, QUEUE_SIZE)?;
+        // The header and the status byte take a descriptor each
+        let descriptors = queue.size() as usize - 2;
+        let seg_max = match transport.read_device_config_u32(CONFIG_SEG_MAX) as usize {
+            seg_max if features & VIRTIO_BLK_F_SEG_MAX != 0 && seg_max != 0 => {
+                seg_max.min(descriptors)
+            }
+            _ => 1,
+        };
+        let header = DmaBuffer::new_zeroed(HEADER_SIZE + 1, DmaConstraints::ANY)?;
+        transport.driver_ok();
+
+        Ok(Self {
+            transport,
+            capacity,
+            block_size,
+            seg_max,
+            read_only: features & VIRTIO_BLK_F_RO != 0,
+            queue: sync::Mutex::new(RequestQueue { queue, header }),
+        })
+    }
+
+    pub fn pci_device(&self) -> &PciDevice {
+        self.transport.pci_device()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// # Transfer
+    /// Transfers the `len` bytes at `addr` from or to the device, starting at `lba`
+    fn transfer(
+        &self,
+        queue: &mut RequestQueue,
+        lba: u64,
+        addr: u64,
+        len: usize,
+        write: bool,
+    ) -> Result<()> {
+        let count = blocks_in(self, len)?;
+        if lba + count > self.block_count() {
+            return Err(Error::IOError);
+        }
+        if write && self.read_only {
+            return Err(Error::ReadOnlyFileSystem);
+        }
+        let sector = lba * (self.block_size / SECTOR_SIZE) as u64;
+        if addr % SECTOR_SIZE as u64 == 0 {
+            return self.transfer_aligned(queue, sector, addr, len, write);
+        }
+
+        // Page boundaries of an unaligned buffer fall into the middle of a sector
+        STAGED.increment();
+        let mut staging = DmaBuffer::new(len, DmaConstraints::ANY)?;
+        if write {
+            unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, staging.as_mut_ptr(), len) };
+        }
+        self.transfer_aligned(queue, sector, staging.virt().as_u64(), len, write)?;
+        if !write {
+            unsafe { core::ptr::copy_nonoverlapping(staging.as_ptr(), addr as *mut u8, len) };
+        }
+        Ok(())
+    }
+
+    /// Transfers the sector aligned buffer at `addr` in requests of at most `seg_max` segments
+    fn transfer_aligned(
+        &self,
+        queue: &mut RequestQueue,
+        sector: u64,
+        addr: u64,
+        len: usize,
+        write: bool,
+    ) -> Result<()> {
+        let kind = if write { REQUEST_OUT } else { REQUEST_IN };
+        let mut segments: Vec<Buffer> = Vec::with_capacity(self.seg_max);
+        let mut done = 0;
+        while done < len {
+            segments.clear();
+            let mut size = 0;
+            while done + size < len {
+                let virt = addr + (done + size) as u64;
+                let chunk = (PAGE_SIZE - virt % PAGE_SIZE).min((len - done - size) as u64);
+                let phys = virt_to_phys(VirtualAddress::new(virt)).as_u64();
+                match segments.last_mut() {
+                    // Pages that are contiguous in physical memory as well share a segment
+                    Some(last) if last.phys + last.len as u64 == phys => last.len += chunk as u32,
+                    _ => {
+                        if segments.len() == self.seg_max {
+                            break;
+                        }
+                        segments.push(Buffer {
+                            phys,
+                            len: chunk as u32,
+                            device_writable: !write,
+                        });
+                    }
+                }
+                size += chunk as usize;
+            }
+            queue.request(kind, sector + (done / SECTOR_SIZE) as u64, &segments)?;
+            done += size;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.capacity / (self.block_size / SECTOR_SIZE) as u64
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        let mut queue = self.queue.lock();
+        self.transfer(&mut queue, lba, buf.as_mut_ptr() as u64, buf.len(), false)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        let mut queue = self.queue.lock();
+        self.transfer(&mut queue, lba, buf.as_ptr() as u64, buf.len(), true)
+    }
+
+    fn write_blocks_polled(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        let mut queue = self.queue.try_lock().ok_or(Error::OperationWouldBlock)?;
+        self.transfer(&mut queue, lba, buf.as_ptr() as u64, buf.len(), true)
+    }
+}
+
+/// All virtio block devices found by `init_virtio_blk()`
+pub static VIRTIO_BLK_DISKS: Mutex<Vec<Arc<VirtioBlk>>> = Mutex::new(Vec::new());
+
+/// # Virtio Block Driver
+/// Binds to virtio block devices. The block layer keeps its disks, so it keeps the default
+/// `detach()`.
+pub struct VirtioBlkDriver;
+
+pub static VIRTIO_BLK_DRIVER: VirtioBlkDriver = VirtioBlkDriver;
+
+impl PciDriver for VirtioBlkDriver {
+    fn name(&self) -> &'static str {
+        "virtio-blk"
+    }
+
+    fn matches(&self, device: &PciDevice) -> bool {
+        device.vendor_id == VIRTIO_VENDOR_ID
+            && (device.device_id == MODERN_DEVICE_ID_BASE + DEVICE_TYPE_BLOCK
+                || device.device_id == TRANSITIONAL_DEVICE_ID_BLOCK)
+    }
+
+    fn attach(&self, device: PciDevice) -> Result<()> {
+        debug!(
+            "virtio-blk: Device {:04x}:{:04x}",
+            device.vendor_id, device.device_id
+        );
+        let disk = Arc::new(VirtioBlk::new(device)?);
+        let name = block::register_disk(disk.clone());
+        info!(
+            "virtio-blk: {}: {} blocks of {} bytes, {} segments per request{}",
+            name,
+            disk.block_count(),
+            disk.block_size,
+            disk.seg_max,
+            if disk.read_only { ", read-only" } else { "" }
+        );
+        VIRTIO_BLK_DISKS.lock().push(disk);
+        Ok(())
+    }
+}
+
+crate::initcall! {
+    name: "virtio-blk",
+    stage: Scheduled,
+    deps: ["pci"],
+    fatal: false,
+    init: init_virtio_blk,
+}
+
+/// # Init Virtio Block
+/// Claims every virtio block device on the PCI bus
+pub fn init_virtio_blk() -> Result<()> {
+    pci::register_driver(&VIRTIO_BLK_DRIVER).map(|_| ())
+}
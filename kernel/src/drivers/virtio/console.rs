@@ -0,0 +1,251 @@
+//! # Virtio Console
+//! A driver for virtio console devices, as QEMU provides them with
+//! `-device virtio-serial-pci -device virtconsole,chardev=<id>`. Only the first port is used.
+//! The first device copies the kernel log to the host and is a source of input for the console
+//! `tty`, so the kernel shell can be driven from the host.
+//!
+//! Like with virtio-net, text is copied through fixed pools of DMA buffers. Received text is
+//! picked up by polling from the idle loop (see `poll()`). Writing never waits for the device:
+//! whatever does not fit into the free transmit buffers is dropped and counted in `TX_DROPPED`.
+use spin::Once;
+
+use super::queue::BufferQueue;
+use super::{VirtioPci, MODERN_DEVICE_ID_BASE, VIRTIO_VENDOR_ID};
+use crate::error::Result;
+use crate::klog::{self, LogSink};
+use crate::pci::{self, PciDevice, PciDriver};
+use crate::scheduler::spin::Mutex;
+use crate::tty::{self, Echo};
+use crate::{debug, info, warn};
+
+pub const DEVICE_TYPE_CONSOLE: u16 = 3;
+/// The id of console devices that support both the legacy and the modern interface
+pub const TRANSITIONAL_DEVICE_ID_CONSOLE: u16 = 0x1003;
+/// The name of the log sink of the console
+pub const SINK_NAME: &str = "hvc0";
+/// The queues of the first port
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+const RECEIVE_BUFFERS: u16 = 8;
+const TRANSMIT_BUFFERS: u16 = 32;
+const BUFFER_SIZE: usize = 256;
+/// How often `flush()` checks for the device to finish before it gives up
+const FLUSH_TIMEOUT: usize = 1_000_000;
+
+crate::counter!(pub TX_DROPPED = "virtio_console.tx_dropped");
+
+static CONSOLE: Once<VirtioConsole> = Once::new();
+
+/// # Virtio Console
+/// The first port of a virtio console device
+pub struct VirtioConsole {
+    transport: VirtioPci,
+    receive: Mutex<BufferQueue>,
+    transmit: Mutex<BufferQueue>,
+}
+
+impl VirtioConsole {
+    fn new(device: PciDevice) -> Result<Self> {
+        let transport = VirtioPci::new(device)?;
+        // Neither the size of the console nor multiple ports are of any use
+        transport.negotiate(0)?;
+
+        let mut receive = BufferQueue::new(
+            transport.setup_queue(RECEIVE_QUEUE, RECEIVE_BUFFERS)?,
+            BUFFER_SIZE,
+        )?;
+        let transmit = BufferQueue::new(
+            transport.setup_queue(TRANSMIT_QUEUE, TRANSMIT_BUFFERS)?,
+            BUFFER_SIZE,
+        )?;
+        transport.driver_ok();
+
+        while let Some(idx) = receive.free.pop() {
+            receive.submit(idx, BUFFER_SIZE, true)?;
+        }
+        receive.queue.notify();
+
+        Ok(Self {
+            transport,
+            receive: Mutex::new(receive),
+            transmit: Mutex::new(transmit),
+        })
+    }
+
+    pub fn pci_device(&self) -> &PciDevice {
+        self.transport.pci_device()
+    }
+
+    /// # Write
+    /// Queues `s` with its line feeds turned into CR LF, as far as it fits into the free transmit
+    /// buffers. Never waits, not even for a writer on another CPU, the rest of `s` is dropped and
+    /// counted in `TX_DROPPED`.
+    ///
+    /// ## Returns
+    /// - usize = The number of bytes queued
+    pub fn write(&self, s: &str) -> usize {
+        let len = s.len() + s.bytes().filter(|byte| *byte == b'\n').count();
+        let mut transmit = match self.transmit.try_lock() {
+            Some(transmit) => transmit,
+            None => {
+                TX_DROPPED.add(len as u64);
+                return 0;
+            }
+        };
+        transmit.reclaim();
+
+        let mut bytes = s
+            .bytes()
+            .flat_map(|byte| (byte == b'\n').then(|| b'\r').into_iter().chain(Some(byte)))
+            .peekable();
+        let mut written = 0;
+        while bytes.peek().is_some() {
+            let idx = match transmit.free.pop() {
+                Some(idx) => idx,
+                None => break,
+            };
+            let chunk = transmit
+                .data(idx)
+                .iter_mut()
+                .zip(&mut bytes)
+                .map(|(slot, byte)| *slot = byte)
+                .count();
+            if transmit.submit(idx, chunk, false).is_err() {
+                transmit.free.push(idx);
+                break;
+            }
+            written += chunk;
+        }
+        if written != 0 {
+            transmit.queue.notify();
+        }
+        TX_DROPPED.add((len - written) as u64);
+        written
+    }
+
+    /// # Read
+    /// Passes every byte received so far to `f`
+    pub fn read(&self, mut f: impl FnMut(u8)) {
+        let mut receive = self.receive.lock();
+        let mut received = false;
+        while let Some((idx, len)) = receive.complete() {
+            let len = len.min(BUFFER_SIZE);
+            receive.data(idx)[..len].iter().for_each(|byte| f(*byte));
+            // Give the buffer back right away, its content has been passed on
+            if receive.submit(idx, BUFFER_SIZE, true).is_err() {
+                receive.free.push(idx);
+            }
+            received = true;
+        }
+        if received {
+            receive.queue.notify();
+        }
+    }
+}
+
+impl LogSink for VirtioConsole {
+    fn name(&self) -> &str {
+        SINK_NAME
+    }
+
+    fn write_str(&self, s: &str) {
+        self.write(s);
+    }
+
+    fn flush(&self) {
+        if let Some(mut transmit) = self.transmit.try_lock() {
+            for _ in 0..FLUSH_TIMEOUT {
+                transmit.reclaim();
+                if transmit.free.len() == transmit.queue.size() as usize {
+                    break;
+                }
+                comasm::pause();
+            }
+        }
+    }
+}
+
+pub fn console() -> Option<&'static VirtioConsole> {
+    CONSOLE.get()
+}
+
+/// # Poll
+/// Passes what was received on the console to the `tty` and echoes it, called by the idle loop
+pub fn poll() {
+    let console = match console() {
+        Some(console) => console,
+        None => return,
+    };
+    console.read(|byte| match tty::input(byte as char) {
+        Echo::Char(c) => {
+            console.write(c.encode_utf8(&mut [0; 4]));
+        }
+        Echo::Erase(chars) => {
+            for _ in 0..chars {
+                console.write("\x08 \x08");
+            }
+        }
+        Echo::NewLine => {
+            console.write("\n");
+        }
+        Echo::Interrupt => {
+            console.write("^C\n");
+        }
+        Echo::Suspend => {
+            console.write("^Z\n");
+        }
+        Echo::Nothing => {}
+    });
+}
+
+/// # Virtio Console Driver
+/// Binds to the first virtio console device. The log keeps its sinks, so it keeps the default
+/// `detach()`.
+pub struct VirtioConsoleDriver;
+
+pub static VIRTIO_CONSOLE_DRIVER: VirtioConsoleDriver = VirtioConsoleDriver;
+
+impl PciDriver for VirtioConsoleDriver {
+    fn name(&self) -> &'static str {
+        "virtio-console"
+    }
+
+    /// Only the first device becomes the console
+    fn matches(&self, device: &PciDevice) -> bool {
+        CONSOLE.get().is_none()
+            && device.vendor_id == VIRTIO_VENDOR_ID
+            && (device.device_id == MODERN_DEVICE_ID_BASE + DEVICE_TYPE_CONSOLE
+                || device.device_id == TRANSITIONAL_DEVICE_ID_CONSOLE)
+    }
+
+    fn attach(&self, device: PciDevice) -> Result<()> {
+        debug!(
+            "virtio-console: Device {:04x}:{:04x}",
+            device.vendor_id, device.device_id
+        );
+        let console = VirtioConsole::new(device)?;
+        let console = CONSOLE.call_once(|| console);
+        match klog::register_sink(console) {
+            Ok(()) => info!("virtio-console: Logging to {}", SINK_NAME),
+            Err(err) => warn!(
+                "virtio-console: Cannot register the log sink: {}",
+                err.text()
+            ),
+        }
+        Ok(())
+    }
+}
+
+crate::initcall! {
+    name: "virtio-console",
+    stage: Scheduled,
+    deps: ["pci"],
+    fatal: false,
+    init: init_virtio_console,
+}
+
+/// # Init Virtio Console
+/// Claims the first virtio console device on the PCI bus and copies the log to it
+pub fn init_virtio_console() -> Result<()> {
+    pci::register_driver(&VIRTIO_CONSOLE_DRIVER).map(|_| ())
+}
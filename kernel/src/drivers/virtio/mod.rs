@@ -0,0 +1,277 @@
+//! # Virtio
+//! The transport for modern (virtio 1.0) devices on the PCI bus.
+//!
+//! The configuration structures are found through vendor specific PCI capabilities, every one of
+//! them points into one of the BARs of the device (Virtio 1.1, 4.1.4).
+use crate::error::{Error, Result};
+use crate::memory::paging::pat::MemoryType;
+use crate::memory::{map_mmio, PhysicalAddress};
+use crate::pci::{PciCapability, PciDevice};
+
+pub mod blk;
+pub mod console;
+pub mod net;
+pub mod queue;
+
+pub use queue::Virtqueue;
+
+pub const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+/// Modern devices have an id of 0x1040 plus their device type
+pub const MODERN_DEVICE_ID_BASE: u16 = 0x1040;
+/// Set by devices that follow virtio 1.0 or later, legacy devices are not supported
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+/// Written as an MSI-X table entry to raise no interrupt, read back if the device could not take
+/// the entry
+pub const VIRTIO_MSI_NO_VECTOR: u16 = 0xFFFF;
+
+enumtastic::const_enum! {
+    /// The `cfg_type` of a virtio PCI capability
+    pub enum VirtioCapability: u8 => {
+        CommonConfig = 1,
+        NotifyConfig = 2,
+        IsrConfig = 3,
+        DeviceConfig = 4,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum DeviceStatus: u8 => {
+        Acknowledge = 1,
+        Driver = 2,
+        DriverOk = 4,
+        FeaturesOk = 8,
+        DeviceNeedsReset = 64,
+        Failed = 128,
+    }
+
+    impl {}
+}
+
+/// # Common Config
+/// The common configuration structure (Virtio 1.1, 4.1.4.3)
+#[repr(C)]
+struct CommonConfig {
+    device_feature_select: u32,
+    device_feature: u32,
+    driver_feature_select: u32,
+    driver_feature: u32,
+    msix_config: u16,
+    num_queues: u16,
+    device_status: u8,
+    config_generation: u8,
+    queue_select: u16,
+    queue_size: u16,
+    queue_msix_vector: u16,
+    queue_enable: u16,
+    queue_notify_off: u16,
+    queue_desc: u64,
+    queue_driver: u64,
+    queue_device: u64,
+}
+
+/// Reads a field of the common configuration
+macro_rules! common_read {
+    ($self:ident.$field:ident) => {
+        unsafe { core::ptr::addr_of!((*$self.common).$field).read_volatile() }
+    };
+}
+
+/// Writes a field of the common configuration
+macro_rules! common_write {
+    ($self:ident.$field:ident, $value:expr) => {
+        unsafe { core::ptr::addr_of_mut!((*$self.common).$field).write_volatile($value) }
+    };
+}
+
+/// # Virtio PCI
+/// A virtio device and the configuration structures it exposes
+pub struct VirtioPci {
+    device: PciDevice,
+    common: *mut CommonConfig,
+    notify_base: u64,
+    notify_multiplier: u32,
+    device_config: u64,
+}
+
+unsafe impl Send for VirtioPci {}
+unsafe impl Sync for VirtioPci {}
+
+impl VirtioPci {
+    /// # New
+    /// Locates the configuration structures of `device`
+    pub fn new(device: PciDevice) -> Result<Self> {
+        device.enable_bus_mastering();
+        let mut common = None;
+        let mut notify = None;
+        let mut device_config = None;
+        for (_, capability) in device
+            .capabilities()
+            .filter(|(id, _)| *id == PciCapability::VendorSpecific)
+        {
+            let bar = device.read_u8(capability + 4);
+            let offset = device.read_u32(capability + 8) as u64;
+            let length = device.read_u32(capability + 12) as u64;
+            if bar > 5 {
+                continue;
+            }
+            let phys = device.bar(bar as u64) + offset;
+            let addr =
+                map_mmio(PhysicalAddress::new(phys), length, MemoryType::Uncacheable)?.as_u64();
+
+            // Only the first capability of every type is used
+            match device.read_u8(capability + 3) {
+                VirtioCapability::CommonConfig if common.is_none() => common = Some(addr),
+                VirtioCapability::NotifyConfig if notify.is_none() => {
+                    notify = Some((addr, device.read_u32(capability + 16)))
+                }
+                VirtioCapability::DeviceConfig if device_config.is_none() => {
+                    device_config = Some(addr)
+                }
+                _ => {}
+            }
+        }
+
+        let (notify_base, notify_multiplier) = notify.ok_or(Error::NoSuchDevice)?;
+        Ok(Self {
+            device,
+            common: common.ok_or(Error::NoSuchDevice)? as *mut CommonConfig,
+            notify_base,
+            notify_multiplier,
+            device_config: device_config.unwrap_or(0),
+        })
+    }
+
+    pub fn pci_device(&self) -> &PciDevice {
+        &self.device
+    }
+
+    pub fn status(&self) -> u8 {
+        common_read!(self.device_status)
+    }
+
+    fn add_status(&self, status: u8) {
+        common_write!(self.device_status, self.status() | status);
+    }
+
+    /// # Reset
+    /// Resets the device, which also forgets all negotiated features and queues
+    pub fn reset(&self) {
+        common_write!(self.device_status, 0);
+        while self.status() != 0 {
+            comasm::pause();
+        }
+    }
+
+    /// # Negotiate
+    /// Resets the device and accepts the features out of `wanted` that the device offers.
+    /// `VIRTIO_F_VERSION_1` is always required.
+    ///
+    /// ## Returns
+    /// - u64 = The accepted features
+    pub fn negotiate(&self, wanted: u64) -> Result<u64> {
+        self.reset();
+        self.add_status(DeviceStatus::Acknowledge);
+        self.add_status(DeviceStatus::Driver);
+
+        let mut offered = 0u64;
+        for select in 0..2 {
+            common_write!(self.device_feature_select, select);
+            offered |= (common_read!(self.device_feature) as u64) << (32 * select);
+        }
+        if offered & VIRTIO_F_VERSION_1 == 0 {
+            self.add_status(DeviceStatus::Failed);
+            return Err(Error::NoSuchDevice);
+        }
+        let accepted = offered & (wanted | VIRTIO_F_VERSION_1);
+        for select in 0..2 {
+            common_write!(self.driver_feature_select, select);
+            common_write!(self.driver_feature, (accepted >> (32 * select)) as u32);
+        }
+
+        self.add_status(DeviceStatus::FeaturesOk);
+        if self.status() & DeviceStatus::FeaturesOk == 0 {
+            self.add_status(DeviceStatus::Failed);
+            return Err(Error::NoSuchDevice);
+        }
+        Ok(accepted)
+    }
+
+    /// # Setup Queue
+    /// Allocates the virtqueue `index` with at most `max_size` entries and hands it to the device
+    pub fn setup_queue(&self, index: u16, max_size: u16) -> Result<Virtqueue> {
+        common_write!(self.queue_select, index);
+        let device_size = common_read!(self.queue_size);
+        if device_size == 0 {
+            return Err(Error::NoSuchDevice);
+        }
+        // Queue sizes are powers of two
+        let size = device_size.min(max_size.next_power_of_two());
+        let notify_offset = common_read!(self.queue_notify_off) as u64;
+        let notify = self.notify_base + notify_offset * self.notify_multiplier as u64;
+
+        let queue = Virtqueue::allocate(index, size, notify)?;
+        common_write!(self.queue_size, size);
+        common_write!(self.queue_desc, queue.descriptors_phys());
+        common_write!(self.queue_driver, queue.driver_phys());
+        common_write!(self.queue_device, queue.device_phys());
+        common_write!(self.queue_enable, 1);
+        Ok(queue)
+    }
+
+    /// # Set Queue Vector
+    /// Has the device raise MSI-X table entry `entry` for used buffers of virtqueue `index`, and
+    /// no interrupt for configuration changes. MSI-X has to be enabled on the function already.
+    /// Called before `setup_queue()`, which selects the queue again.
+    ///
+    /// ## Returns
+    /// - Error::DeviceOrResourceBusy = The device could not take the entry (Virtio 1.1, 4.1.5.1.2)
+    pub fn set_queue_vector(&self, index: u16, entry: u16) -> Result<()> {
+        common_write!(self.msix_config, VIRTIO_MSI_NO_VECTOR);
+        common_write!(self.queue_select, index);
+        common_write!(self.queue_msix_vector, entry);
+        if common_read!(self.queue_msix_vector) != entry {
+            return Err(Error::DeviceOrResourceBusy);
+        }
+        Ok(())
+    }
+
+    /// # Driver OK
+    /// Tells the device that it is fully set up
+    pub fn driver_ok(&self) {
+        self.add_status(DeviceStatus::DriverOk);
+    }
+
+    /// # Read Device Config
+    /// Reads a byte of the device specific configuration
+    pub fn read_device_config(&self, offset: u64) -> u8 {
+        if self.device_config == 0 {
+            return 0;
+        }
+        unsafe { core::ptr::read_volatile((self.device_config + offset) as *const u8) }
+    }
+
+    /// # Read Device Config U32
+    /// Reads a 32 bit field of the device specific configuration
+    pub fn read_device_config_u32(&self, offset: u64) -> u32 {
+        if self.device_config == 0 {
+            return 0;
+        }
+        unsafe { core::ptr::read_volatile((self.device_config + offset) as *const u32) }
+    }
+
+    /// # Read Device Config U64
+    /// Reads a 64 bit field of the device specific configuration. It is read in two halves, so
+    /// this retries until the configuration did not change in between (Virtio 1.1, 2.4.1).
+    pub fn read_device_config_u64(&self, offset: u64) -> u64 {
+        loop {
+            let generation = common_read!(self.config_generation);
+            let value = self.read_device_config_u32(offset) as u64
+                | (self.read_device_config_u32(offset + 4) as u64) << 32;
+            if common_read!(self.config_generation) == generation {
+                return value;
+            }
+        }
+    }
+}
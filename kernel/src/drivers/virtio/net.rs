@@ -0,0 +1,208 @@
+//! # Virtio Net
+//! A driver for virtio network devices.
+//!
+//! Every received and transmitted frame is copied through a fixed pool of DMA buffers, one
+//! buffer per frame. The receive queue raises an MSI-X interrupt, whose handler only raises
+//! `SoftirqKind::NetRx` for the network stack to pick the frames up. Devices without MSI-X are
+//! polled instead.
+use alloc::{sync::Arc, vec::Vec};
+
+use super::queue::BufferQueue;
+use super::{VirtioPci, Virtqueue, MODERN_DEVICE_ID_BASE, VIRTIO_VENDOR_ID};
+use crate::debug;
+use crate::error::{Error, Result};
+use crate::irq::msi::{allocate_msi, MsiVectors};
+use crate::irq::softirq::{self, SoftirqKind};
+use crate::net::{self, MacAddress, NetworkDevice};
+use crate::pci::{self, PciDevice, PciDriver};
+use crate::scheduler::spin::Mutex;
+
+pub const DEVICE_TYPE_NET: u16 = 1;
+/// The id of network devices that support both the legacy and the modern interface
+pub const TRANSITIONAL_DEVICE_ID_NET: u16 = 0x1000;
+/// The device config starts with the MAC address
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+const RECEIVE_BUFFERS: u16 = 64;
+const TRANSMIT_BUFFERS: u16 = 16;
+/// `virtio_net_hdr`, which includes `num_buffers` with `VIRTIO_F_VERSION_1`
+const HEADER_SIZE: usize = 12;
+/// Fits the header and a full sized frame
+const BUFFER_SIZE: usize = 2048;
+/// Used if the device does not tell us its address
+const FALLBACK_MAC: MacAddress = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+
+/// # Virtio Net
+/// A virtio network device
+pub struct VirtioNet {
+    transport: VirtioPci,
+    mac: MacAddress,
+    receive: Mutex<BufferQueue>,
+    transmit: Mutex<BufferQueue>,
+    /// The interrupt of the receive queue, `None` if it is polled
+    interrupt: Option<MsiVectors>,
+}
+
+impl VirtioNet {
+    fn new(device: PciDevice) -> Result<Self> {
+        let transport = VirtioPci::new(device)?;
+        let features = transport.negotiate(VIRTIO_NET_F_MAC)?;
+        let mac = if features & VIRTIO_NET_F_MAC != 0 {
+            let mut mac = [0u8; 6];
+            for (idx, byte) in mac.iter_mut().enumerate() {
+                *byte = transport.read_device_config(idx as u64);
+            }
+            MacAddress(mac)
+        } else {
+            FALLBACK_MAC
+        };
+
+        // Before the queue is set up, so it never runs without its interrupt
+        let interrupt = match allocate_msi(
+            transport.pci_device(),
+            1,
+            "virtio-net",
+            receive_interrupt,
+            0,
+        ) {
+            Ok(vectors) if vectors.is_msix() => transport
+                .set_queue_vector(RECEIVE_QUEUE, 0)
+                .ok()
+                .map(|_| vectors),
+            _ => None,
+        };
+        if interrupt.is_none() {
+            debug!("virtio-net: No MSI-X, the receive queue is polled");
+        }
+
+        let mut receive = BufferQueue::new(
+            transport.setup_queue(RECEIVE_QUEUE, RECEIVE_BUFFERS)?,
+            BUFFER_SIZE,
+        )?;
+        let transmit = BufferQueue::new(
+            transport.setup_queue(TRANSMIT_QUEUE, TRANSMIT_BUFFERS)?,
+            BUFFER_SIZE,
+        )?;
+        transport.driver_ok();
+
+        while let Some(idx) = receive.free.pop() {
+            receive.submit(idx, BUFFER_SIZE, true)?;
+        }
+        receive.queue.notify();
+
+        Ok(Self {
+            transport,
+            mac,
+            receive: Mutex::new(receive),
+            transmit: Mutex::new(transmit),
+            interrupt,
+        })
+    }
+
+    pub fn pci_device(&self) -> &PciDevice {
+        self.transport.pci_device()
+    }
+}
+
+/// Raised for used buffers of the receive queue. MSI-X needs no acknowledgement from the
+/// driver, so all that is left is deferring the frames.
+fn receive_interrupt(_index: usize, _data: usize) {
+    softirq::raise(SoftirqKind::NetRx);
+}
+
+impl NetworkDevice for VirtioNet {
+    fn mac(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn send(&self, frame: &[u8]) -> Result<()> {
+        if frame.len() > BUFFER_SIZE - HEADER_SIZE {
+            return Err(Error::MessageTooLong);
+        }
+        let mut transmit = self.transmit.lock();
+        transmit.reclaim();
+        let idx = transmit.free.pop().ok_or(Error::NoBufferSpaceAvailable)?;
+
+        let data = transmit.data(idx);
+        // No offloads are negotiated, so the header stays zeroed
+        data[..HEADER_SIZE].fill(0);
+        data[HEADER_SIZE..HEADER_SIZE + frame.len()].copy_from_slice(frame);
+        if let Err(err) = transmit.submit(idx, HEADER_SIZE + frame.len(), false) {
+            transmit.free.push(idx);
+            return Err(err);
+        }
+        transmit.queue.notify();
+        Ok(())
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        let mut receive = self.receive.lock();
+        loop {
+            let (idx, len) = receive.complete()?;
+            let frame = match len {
+                len if (HEADER_SIZE..=BUFFER_SIZE).contains(&len) => {
+                    Some(receive.data(idx)[HEADER_SIZE..len].to_vec())
+                }
+                _ => {
+                    net::RX_BAD_LENGTH.increment();
+                    None
+                }
+            };
+            // Give the buffer back right away, the frame has been copied out
+            if receive.submit(idx, BUFFER_SIZE, true).is_err() {
+                receive.free.push(idx);
+            }
+            receive.queue.notify();
+            if frame.is_some() {
+                return frame;
+            }
+        }
+    }
+
+    fn raises_interrupts(&self) -> bool {
+        self.interrupt.is_some()
+    }
+}
+
+/// # Virtio Net Driver
+/// Binds to virtio network devices. The network stack keeps its devices, so it keeps the
+/// default `detach()`.
+pub struct VirtioNetDriver;
+
+pub static VIRTIO_NET_DRIVER: VirtioNetDriver = VirtioNetDriver;
+
+impl PciDriver for VirtioNetDriver {
+    fn name(&self) -> &'static str {
+        "virtio-net"
+    }
+
+    fn matches(&self, device: &PciDevice) -> bool {
+        device.vendor_id == VIRTIO_VENDOR_ID
+            && (device.device_id == MODERN_DEVICE_ID_BASE + DEVICE_TYPE_NET
+                || device.device_id == TRANSITIONAL_DEVICE_ID_NET)
+    }
+
+    fn attach(&self, device: PciDevice) -> Result<()> {
+        debug!(
+            "virtio-net: Device {:04x}:{:04x}",
+            device.vendor_id, device.device_id
+        );
+        net::register_device(Arc::new(VirtioNet::new(device)?));
+        Ok(())
+    }
+}
+
+crate::initcall! {
+    name: "virtio-net",
+    stage: Scheduled,
+    deps: ["pci"],
+    fatal: false,
+    init: init_virtio_net,
+}
+
+/// # Init Virtio Net
+/// Claims every virtio network device on the PCI bus
+pub fn init_virtio_net() -> Result<()> {
+    pci::register_driver(&VIRTIO_NET_DRIVER).map(|_| ())
+}
@@ -0,0 +1,385 @@
+//! # Virtqueue
+//! A split virtqueue: The descriptor table, the driver (available) ring and the device (used)
+//! ring share a DMA buffer (Virtio 1.1, 2.6). Drivers that copy their data through a fixed pool
+//! of DMA buffers pair a queue with one in a `BufferQueue`.
+//!
+//! Completions are either polled with `poll_used()`, or passed to the callback set with
+//! `set_completion()` by `handle_interrupt()`, which the interrupt handler of the device calls.
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{fence, Ordering};
+
+use crate::error::{Error, Result};
+use crate::memory::dma::{DmaBuffer, DmaConstraints, DmaLayout, DmaRange};
+
+/// The buffer continues in the descriptor in `next`
+const DESCRIPTOR_NEXT: u16 = 1 << 0;
+/// The device writes to the buffer instead of reading it
+const DESCRIPTOR_WRITE: u16 = 1 << 1;
+/// The largest queue the transport allows
+pub const MAX_QUEUE_SIZE: u16 = 0x8000;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+impl Descriptor {
+    pub fn has_next(&self) -> bool {
+        self.flags & DESCRIPTOR_NEXT != 0
+    }
+
+    pub fn device_writable(&self) -> bool {
+        self.flags & DESCRIPTOR_WRITE != 0
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct UsedElement {
+    pub id: u32,
+    pub len: u32,
+}
+
+/// # Buffer
+/// A physically contiguous part of a request
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub phys: u64,
+    pub len: u32,
+    /// Whether the device writes to the buffer (e.g. a receive buffer)
+    pub device_writable: bool,
+}
+
+/// # Descriptor Token
+/// Names a request from `add_buffer()` until `poll_used()` returns it. It is the index of the
+/// first descriptor of the chain, so it is reused once the request completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DescriptorToken(u16);
+
+impl DescriptorToken {
+    /// The index of the first descriptor, below the size of the queue
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// # Completion
+/// Called by `handle_interrupt()` with a request the device is done with and the number of bytes
+/// it wrote
+pub type Completion = fn(DescriptorToken, u32);
+
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    rings: DmaBuffer,
+    descriptors: DmaRange,
+    /// flags, idx, ring[size], used_event
+    driver: DmaRange,
+    /// flags, idx, ring[size], avail_event
+    device: DmaRange,
+    /// The free descriptors
+    free: Vec<u16>,
+    /// The index in the used ring up to which completions have been processed
+    last_used: u16,
+    /// The notification register of the queue, 0 if there is none
+    notify: u64,
+    completion: Option<Completion>,
+}
+
+impl Virtqueue {
+    /// # Layout
+    /// Places the descriptor table and the rings of a queue of `size` entries, aligned as
+    /// Virtio 1.1, 2.6 requires
+    ///
+    /// ## Returns
+    /// - (DmaLayout, [DmaRange; 3]) = The layout and the descriptor table, driver and device ring
+    pub fn layout(size: u16) -> (DmaLayout, [DmaRange; 3]) {
+        let size = size as usize;
+        let mut layout = DmaLayout::new();
+        let descriptors = layout.push(size * core::mem::size_of::<Descriptor>(), 16);
+        let driver = layout.push(6 + 2 * size, 2);
+        let device = layout.push(6 + size * core::mem::size_of::<UsedElement>(), 4);
+        (layout, [descriptors, driver, device])
+    }
+
+    /// # New
+    /// Lays out a queue of `size` entries in `rings`, which is zeroed. The queue has no
+    /// notification register, `notify()` does nothing until the transport sets one up.
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = `size` is not a power of two up to `MAX_QUEUE_SIZE`, or
+    ///   `rings` is too small or not aligned to 16 bytes
+    pub fn new(size: u16, mut rings: DmaBuffer) -> Result<Self> {
+        let (layout, [descriptors, driver, device]) = Self::layout(size);
+        // The indices wrap at 2^16, which only lines up with the ring if its size divides it
+        if !size.is_power_of_two()
+            || size > MAX_QUEUE_SIZE
+            || rings.len() < layout.size()
+            || rings.phys().as_u64() % 16 != 0
+        {
+            return Err(Error::InvalidArgument);
+        }
+        rings.as_mut_slice().fill(0);
+        Ok(Self {
+            index: 0,
+            size,
+            rings,
+            descriptors,
+            driver,
+            device,
+            free: (0..size).rev().collect(),
+            last_used: 0,
+            notify: 0,
+            completion: None,
+        })
+    }
+
+    /// # Allocate
+    /// Allocates the rings for the queue `index` of a device, which is notified through the
+    /// register at `notify`
+    pub(super) fn allocate(index: u16, size: u16, notify: u64) -> Result<Self> {
+        let (layout, _) = Self::layout(size);
+        let rings = DmaBuffer::with_layout(&layout, DmaConstraints::ANY)?;
+        Ok(Self {
+            index,
+            notify,
+            ..Self::new(size, rings)?
+        })
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// The number of descriptors that are not part of a pending request
+    pub fn free_descriptors(&self) -> usize {
+        self.free.len()
+    }
+
+    pub fn descriptors_phys(&self) -> u64 {
+        self.rings.phys_of(self.descriptors).as_u64()
+    }
+
+    pub fn driver_phys(&self) -> u64 {
+        self.rings.phys_of(self.driver).as_u64()
+    }
+
+    pub fn device_phys(&self) -> u64 {
+        self.rings.phys_of(self.device).as_u64()
+    }
+
+    fn descriptor(&self, idx: u16) -> *mut Descriptor {
+        unsafe {
+            self.rings
+                .ptr_of::<Descriptor>(self.descriptors)
+                .add(idx as usize)
+        }
+    }
+
+    /// The `idx` field of the driver ring, followed by the ring itself
+    fn driver_ring(&self) -> *mut u16 {
+        unsafe { self.rings.ptr_of::<u16>(self.driver).add(1) }
+    }
+
+    /// # Add Buffer
+    /// Makes a request made up of the chain `buffers` available to the device. The device is
+    /// not notified, see `notify()`.
+    ///
+    /// ## Returns
+    /// - DescriptorToken = Names the request until `poll_used()` returns it
+    /// - Error::NoBufferSpaceAvailable = There are not enough free descriptors for the chain
+    pub fn add_buffer(&mut self, buffers: &[Buffer]) -> Result<DescriptorToken> {
+        if buffers.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+        if buffers.len() > self.free.len() {
+            return Err(Error::NoBufferSpaceAvailable);
+        }
+
+        // Build the chain back to front, so every descriptor knows its successor
+        let mut next = None;
+        for buffer in buffers.iter().rev() {
+            let idx = self.free.pop().unwrap();
+            let mut flags = 0;
+            if buffer.device_writable {
+                flags |= DESCRIPTOR_WRITE;
+            }
+            if next.is_some() {
+                flags |= DESCRIPTOR_NEXT;
+            }
+            unsafe {
+                self.descriptor(idx).write_volatile(Descriptor {
+                    addr: buffer.phys,
+                    len: buffer.len,
+                    flags,
+                    next: next.unwrap_or(0),
+                });
+            }
+            next = Some(idx);
+        }
+        let head = next.unwrap();
+
+        unsafe {
+            let ring = self.driver_ring();
+            let avail_idx = ring.read_volatile();
+            ring.add(1 + (avail_idx % self.size) as usize)
+                .write_volatile(head);
+            // The descriptors and the ring entry have to be visible before the index (2.6.13)
+            fence(Ordering::SeqCst);
+            ring.write_volatile(avail_idx.wrapping_add(1));
+        }
+        Ok(DescriptorToken(head))
+    }
+
+    /// # Notify
+    /// Tells the device that new requests are available
+    pub fn notify(&self) {
+        if self.notify == 0 {
+            return;
+        }
+        // The index has to be visible before the device looks at it (2.6.13.6)
+        fence(Ordering::SeqCst);
+        unsafe { core::ptr::write_volatile(self.notify as *mut u16, self.index) }
+    }
+
+    /// # Poll Used
+    /// Takes the next request the device is done with and frees its descriptors. Requests
+    /// complete in the order the device finishes them, not the one they were added in.
+    ///
+    /// ## Returns
+    /// - (DescriptorToken, u32) = The request and the number of bytes the device wrote
+    pub fn poll_used(&mut self) -> Option<(DescriptorToken, u32)> {
+        let used_idx = unsafe { self.rings.ptr_of::<u16>(self.device).add(1).read_volatile() };
+        if used_idx == self.last_used {
+            return None;
+        }
+        // The element may only be read once the index that covers it was (2.6.14)
+        fence(Ordering::SeqCst);
+        let element = unsafe {
+            let ring = self.rings.ptr_of::<u8>(self.device).add(4) as *const UsedElement;
+            ring.add((self.last_used % self.size) as usize)
+                .read_volatile()
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = element.id as u16;
+        let mut idx = head;
+        loop {
+            let descriptor = unsafe { self.descriptor(idx).read_volatile() };
+            self.free.push(idx);
+            if !descriptor.has_next() {
+                break;
+            }
+            idx = descriptor.next;
+        }
+        Some((DescriptorToken(head), element.len))
+    }
+
+    /// # Set Completion
+    /// Has `handle_interrupt()` pass every completed request to `completion`
+    pub fn set_completion(&mut self, completion: Completion) {
+        self.completion = Some(completion);
+    }
+
+    /// # Handle Interrupt
+    /// Passes every request the device is done with to the completion callback, called by the
+    /// interrupt handler of the device. Without a callback, the requests are left for
+    /// `poll_used()`.
+    ///
+    /// ## Returns
+    /// - usize = The number of requests passed on
+    pub fn handle_interrupt(&mut self) -> usize {
+        let completion = match self.completion {
+            Some(completion) => completion,
+            None => return 0,
+        };
+        let mut count = 0;
+        while let Some((token, len)) = self.poll_used() {
+            completion(token, len);
+            count += 1;
+        }
+        count
+    }
+}
+
+/// # Buffer Queue
+/// A virtqueue and the pool of equally sized buffers its requests use, one buffer per request
+pub(super) struct BufferQueue {
+    pub(super) queue: Virtqueue,
+    buffers: DmaBuffer,
+    buffer_size: usize,
+    /// The buffer every pending request uses, indexed by the id of the request
+    pending: Vec<Option<usize>>,
+    pub(super) free: Vec<usize>,
+}
+
+impl BufferQueue {
+    pub(super) fn new(queue: Virtqueue, buffer_size: usize) -> Result<Self> {
+        let count = queue.size() as usize;
+        Ok(Self {
+            buffers: DmaBuffer::new_zeroed(count * buffer_size, DmaConstraints::ANY)?,
+            buffer_size,
+            pending: vec![None; count],
+            free: (0..count).collect(),
+            queue,
+        })
+    }
+
+    pub(super) fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    fn buffer(&self, idx: usize) -> Buffer {
+        Buffer {
+            phys: self.buffers.phys().as_u64() + (idx * self.buffer_size) as u64,
+            len: self.buffer_size as u32,
+            device_writable: false,
+        }
+    }
+
+    pub(super) fn data(&mut self, idx: usize) -> &mut [u8] {
+        let size = self.buffer_size;
+        &mut self.buffers.as_mut_slice()[idx * size..(idx + 1) * size]
+    }
+
+    /// # Submit
+    /// Hands the buffer `idx` to the device, `len` bytes of it
+    pub(super) fn submit(&mut self, idx: usize, len: usize, device_writable: bool) -> Result<()> {
+        let buffer = Buffer {
+            len: len as u32,
+            device_writable,
+            ..self.buffer(idx)
+        };
+        let token = self.queue.add_buffer(&[buffer])?;
+        self.pending[token.index()] = Some(idx);
+        Ok(())
+    }
+
+    /// # Complete
+    /// Takes the next request the device is done with
+    ///
+    /// ## Returns
+    /// - (usize, usize) = The buffer of the request and the number of bytes the device wrote
+    pub(super) fn complete(&mut self) -> Option<(usize, usize)> {
+        let (token, len) = self.queue.poll_used()?;
+        let idx = self.pending.get_mut(token.index())?.take()?;
+        Some((idx, len as usize))
+    }
+
+    /// # Reclaim
+    /// Takes back the buffers of every request the device is done with, for queues whose
+    /// completions carry nothing of interest, such as transmit queues
+    pub(super) fn reclaim(&mut self) {
+        while let Some((idx, _)) = self.complete() {
+            self.free.push(idx);
+        }
+    }
+}
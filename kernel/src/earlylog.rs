@@ -0,0 +1,147 @@
+//! # Early Log
+//! Logging from the first instruction of `kmain` on, before the framebuffer and the heap exist.
+//! Until `finish()` the log macros write here: Into a static buffer and, if enabled, straight
+//! to COM1. `finish()` replays the buffer into the kernel log and onto the console, so nothing
+//! written early is lost.
+//!
+//! Only the bootstrap CPU runs this early and interrupts are off, so there are no locks.
+use core::cell::UnsafeCell;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::drivers::serial::{Serial, SerialPort};
+use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::klog;
+
+/// The size of the buffer, anything beyond it is dropped
+pub const EARLY_LOG_SIZE: usize = 0x4000;
+
+struct EarlyBuffer(UnsafeCell<[u8; EARLY_LOG_SIZE]>);
+
+// Only written by the bootstrap CPU before `finish()`, only read after it
+unsafe impl Sync for EarlyBuffer {}
+
+static BUFFER: EarlyBuffer = EarlyBuffer(UnsafeCell::new([0; EARLY_LOG_SIZE]));
+static LEN: AtomicUsize = AtomicUsize::new(0);
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+static ACTIVE: AtomicBool = AtomicBool::new(true);
+/// Whether everything is written to COM1 as well. The `early-serial` feature enables it from
+/// the start, otherwise it is enabled once the port is initialized.
+static RAW_SERIAL: AtomicBool = AtomicBool::new(cfg!(feature = "early-serial"));
+/// Whether `debug!` logs in release builds as well
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// # Is Active
+/// Whether the log macros write to the early log
+#[inline]
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// # Enable Serial
+/// Writes everything to COM1 from now on, as well as into the buffer
+pub fn enable_serial() {
+    RAW_SERIAL.store(true, Ordering::Relaxed);
+}
+
+/// # Set Verbose
+/// Enables or disables the `debug!` messages of release builds. Enabling it writes everything
+/// to COM1 right away as well, a boot that dies early leaves its log on the serial port.
+pub fn set_verbose(verbose: bool) {
+    if verbose {
+        enable_serial();
+    }
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+/// # Is Verbose
+/// Whether `debug!` logs in release builds
+#[inline]
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// # Write
+/// Appends `s` to the buffer, and writes it to COM1 if enabled
+pub fn write(s: &str) {
+    if RAW_SERIAL.load(Ordering::Relaxed) {
+        write_serial(s);
+    }
+    let len = LEN.load(Ordering::Relaxed);
+    let count = s.len().min(EARLY_LOG_SIZE - len);
+    unsafe {
+        let buffer = &mut *BUFFER.0.get();
+        buffer[len..len + count].copy_from_slice(&s.as_bytes()[..count]);
+    }
+    LEN.store(len + count, Ordering::Relaxed);
+    DROPPED.fetch_add(s.len() - count, Ordering::Relaxed);
+}
+
+/// # Write Fmt
+/// Used by `kprint!` and `kprintln!` while the early log is active
+pub fn write_fmt(args: core::fmt::Arguments) {
+    let _ = EarlyLog.write_fmt(args);
+}
+
+/// Writes to COM1 without its lock and without checking that the driver found it, the
+/// firmware may have set it up
+fn write_serial(s: &str) {
+    let mut serial = Serial::new(SerialPort::Com1);
+    for byte in s.bytes() {
+        if byte == b'\n' {
+            serial.write_byte(b'\r');
+        }
+        serial.write_byte(byte);
+    }
+}
+
+struct EarlyLog;
+
+impl Write for EarlyLog {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write(s);
+        Ok(())
+    }
+}
+
+/// # Contents
+/// Everything written to the early log, and how many bytes did not fit
+pub fn contents() -> (&'static str, usize) {
+    let len = LEN.load(Ordering::Relaxed);
+    let bytes = unsafe { &(&*BUFFER.0.get())[..len] };
+    // A character may have been cut in half when the buffer filled up
+    let text = match core::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => unsafe { core::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) },
+    };
+    (text, DROPPED.load(Ordering::Relaxed))
+}
+
+/// # Finish
+/// Routes the log macros to the console and the kernel log, and replays what was written
+/// early into both. Has to be called once the framebuffer guard is initialized.
+pub fn finish() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let (text, dropped) = contents();
+    klog::write(text);
+    let mut guard = FRAMEBUFFER_GUARD.lock();
+    let guard = unsafe { guard.assume_init_mut() };
+    // In serial-only mode the console is COM1, which has seen it already
+    if !(guard.is_serial_only() && RAW_SERIAL.load(Ordering::Relaxed)) {
+        guard.write_console(text);
+    }
+    drop(guard);
+    if dropped != 0 {
+        crate::warn!("earlylog: {} bytes did not fit into the buffer", dropped);
+    }
+}
+
+/// # Panic
+/// Writes a panic that happened while the early log was active to it, and to COM1 regardless
+/// of whether it is enabled
+pub fn panic(info: &core::panic::PanicInfo) {
+    enable_serial();
+    let _ = writeln!(EarlyLog, "Kernel panic during early boot: {}", info);
+}
@@ -0,0 +1,38 @@
+//! # Entropy
+//! The fallback seed of `rand` on CPUs without a usable hardware RNG: the jitter of the TSC over
+//! short busy loops, mixed into a state. It is unpredictable enough to move things around, but
+//! only as good a seed for cryptography as the timing of the machine is hard to guess.
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::tsc;
+
+/// The number of TSC samples mixed into a jitter value
+const JITTER_SAMPLES: usize = 64;
+
+/// The state the TSC jitter is mixed into
+static STATE: AtomicU64 = AtomicU64::new(0x853c_49e6_748f_ea9b);
+
+/// # Jitter
+/// 64 bits mixed from the TSC jitter of `JITTER_SAMPLES` busy loops
+pub fn jitter() -> u64 {
+    let mut state = STATE.load(Ordering::Relaxed);
+    for _ in 0..JITTER_SAMPLES {
+        let start = tsc::read();
+        // The loop length depends on the state, so the timing does as well
+        for _ in 0..(state & 0xF) {
+            core::hint::spin_loop();
+        }
+        let delta = tsc::read().wrapping_sub(start);
+        state = mix(state ^ delta.rotate_left((delta & 0x3F) as u32));
+    }
+    STATE.store(state, Ordering::Relaxed);
+    mix(state)
+}
+
+/// The SplitMix64 finalizer, every input bit affects every output bit
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
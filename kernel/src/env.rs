@@ -8,7 +8,9 @@ use alloc::{
     sync::Arc,
     vec::Vec,
 };
-use spin::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use spin::Once;
+
+use crate::scheduler::spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 static ENVIRONMENT: Once<Arc<RwLock<Environment>>> = Once::new();
 
@@ -0,0 +1,238 @@
+//! # Blit
+//! Drawing glyphs into the framebuffer a row at a time.
+//!
+//! A `ColorTable` holds the pixels of every 4 pixel pattern in two colors, so every byte of a
+//! glyph row becomes two copies of 16 bytes, which the compiler emits as pairs of 64-bit
+//! stores. With the heap, a `GlyphCache` keeps glyphs that were drawn in full, later ones are
+//! copied a whole row at a time.
+use alloc::{vec, vec::Vec};
+
+use super::font::Psf;
+use super::Cell;
+
+/// The number of glyphs the cache holds
+pub const GLYPH_CACHE_SLOTS: usize = 256;
+/// Pixels per pattern of the color table
+const PATTERN_WIDTH: usize = 4;
+
+crate::counter!(pub GLYPH_CACHE_HITS = "fb.glyph_cache_hits");
+crate::counter!(pub GLYPH_CACHE_MISSES = "fb.glyph_cache_misses");
+
+/// # Color Table
+/// The pixels of all 16 patterns of 4 pixels in a foreground and a background color, the most
+/// significant bit is the leftmost pixel
+#[derive(Clone, Copy)]
+pub struct ColorTable {
+    foreground: u32,
+    background: u32,
+    patterns: [[u32; PATTERN_WIDTH]; 16],
+}
+
+impl ColorTable {
+    pub const fn new(foreground: u32, background: u32) -> Self {
+        let mut patterns = [[background; PATTERN_WIDTH]; 16];
+        let mut bits = 0;
+        while bits < 16 {
+            let mut x = 0;
+            while x < PATTERN_WIDTH {
+                if bits & (0x8 >> x) != 0 {
+                    patterns[bits][x] = foreground;
+                }
+                x += 1;
+            }
+            bits += 1;
+        }
+        Self {
+            foreground,
+            background,
+            patterns,
+        }
+    }
+
+    /// Whether the table is the one for the colors of `cell`
+    pub fn matches(&self, cell: Cell) -> bool {
+        self.foreground == cell.foreground && self.background == cell.background
+    }
+
+    /// # Blit Row
+    /// Writes the `width` pixels of the glyph row `bits` to `dst`
+    ///
+    /// ## Safety
+    /// `dst` has to be valid for `width` pixels
+    #[inline]
+    pub unsafe fn blit_row(&self, dst: *mut u32, bits: &[u8], width: usize) {
+        let mut x = 0;
+        for byte in bits {
+            for pattern in [byte >> 4, byte & 0xF] {
+                let count = (width - x).min(PATTERN_WIDTH);
+                if count == 0 {
+                    return;
+                }
+                core::ptr::copy_nonoverlapping(
+                    self.patterns[pattern as usize].as_ptr(),
+                    dst.add(x),
+                    count,
+                );
+                x += count;
+            }
+        }
+    }
+}
+
+/// The bytes of every row of a glyph
+fn glyph_rows<'a>(font: &Psf, glyph: &'a [u8]) -> core::slice::Chunks<'a, u8> {
+    glyph.chunks((font.width() + 7) / 8)
+}
+
+/// # Is Blank
+/// Whether `chr` draws nothing but its background
+pub fn is_blank(font: &Psf, chr: u8) -> bool {
+    font.glyph(chr).iter().all(|bits| *bits == 0)
+}
+
+/// # Draw Glyph
+/// Draws `cell` to `dst`, the top left pixel of its character cell, a row at a time. If the
+/// cell is `cleared`, i.e. only its background color is there, rows without foreground pixels
+/// are skipped.
+///
+/// ## Safety
+/// The character cell has to be in the framebuffer, which has `stride` pixels per scanline
+pub unsafe fn draw_glyph(
+    dst: *mut u32,
+    stride: usize,
+    font: &Psf,
+    cell: Cell,
+    table: &ColorTable,
+    cleared: bool,
+) {
+    for (y, bits) in glyph_rows(font, font.glyph(cell.chr)).enumerate() {
+        if cleared && bits.iter().all(|bits| *bits == 0) {
+            continue;
+        }
+        table.blit_row(dst.add(y * stride), bits, font.width());
+    }
+}
+
+/// # Draw Glyph Per Pixel
+/// Draws `cell` like `draw_glyph()`, a pixel at a time. This is how glyphs used to be drawn,
+/// the faster ways are checked and measured against it.
+///
+/// ## Safety
+/// See `draw_glyph()`
+pub unsafe fn draw_glyph_per_pixel(dst: *mut u32, stride: usize, font: &Psf, cell: Cell) {
+    let glyph = font.glyph(cell.chr);
+    for y in 0..font.height() {
+        for x in 0..font.width() {
+            *dst.add(y * stride + x) = if font.is_set(glyph, x, y) {
+                cell.foreground
+            } else {
+                cell.background
+            };
+        }
+    }
+}
+
+/// # Draw Glyph Rotated
+/// Draws `cell` a pixel at a time like `draw_glyph_per_pixel()`, for a rotated framebuffer.
+/// `dst` is the pixel of the top left of the glyph as it is looked at, a step to the right
+/// moves the index of the pixel by `dx` and a step down by `dy`, see `Rotation::steps()`.
+///
+/// ## Safety
+/// Every pixel of the glyph has to be in the framebuffer
+pub unsafe fn draw_glyph_rotated(
+    dst: *mut u32,
+    dx: isize,
+    dy: isize,
+    font: &Psf,
+    cell: Cell,
+    cleared: bool,
+) {
+    let glyph = font.glyph(cell.chr);
+    for (y, bits) in glyph_rows(font, glyph).enumerate() {
+        if cleared && bits.iter().all(|bits| *bits == 0) {
+            continue;
+        }
+        let row = dst.offset(y as isize * dy);
+        for x in 0..font.width() {
+            *row.offset(x as isize * dx) = if font.is_set(glyph, x, y) {
+                cell.foreground
+            } else {
+                cell.background
+            };
+        }
+    }
+}
+
+/// # Glyph Cache
+/// Drawn glyphs of a single font, each cell maps to one slot and replaces what was there. It
+/// has to be replaced when the font changes.
+pub struct GlyphCache {
+    width: usize,
+    height: usize,
+    keys: Vec<Option<Cell>>,
+    pixels: Vec<u32>,
+}
+
+impl GlyphCache {
+    /// # New
+    /// An empty cache for glyphs of `font`. Needs the heap.
+    pub fn new(font: &Psf) -> Self {
+        Self {
+            width: font.width(),
+            height: font.height(),
+            keys: vec![None; GLYPH_CACHE_SLOTS],
+            pixels: vec![0; GLYPH_CACHE_SLOTS * font.width() * font.height()],
+        }
+    }
+
+    fn slot(cell: Cell) -> usize {
+        let hash = (cell.chr as u32)
+            ^ cell.foreground.wrapping_mul(0x9E37_79B9)
+            ^ cell.background.wrapping_mul(0x85EB_CA6B).rotate_left(16);
+        hash as usize % GLYPH_CACHE_SLOTS
+    }
+
+    /// # Get
+    /// The pixels of `cell`, row by row, drawn with `table` if they are not cached yet
+    pub fn get(&mut self, font: &Psf, cell: Cell, table: &ColorTable) -> &[u32] {
+        let slot = Self::slot(cell);
+        let size = self.width * self.height;
+        let pixels = &mut self.pixels[slot * size..(slot + 1) * size];
+        if self.keys[slot] == Some(cell) {
+            GLYPH_CACHE_HITS.increment();
+            return pixels;
+        }
+        GLYPH_CACHE_MISSES.increment();
+        unsafe { draw_glyph(pixels.as_mut_ptr(), self.width, font, cell, table, false) };
+        self.keys[slot] = Some(cell);
+        pixels
+    }
+
+    /// # Draw
+    /// Draws `cell` like `draw_glyph()`, copying whole rows from the cache
+    ///
+    /// ## Safety
+    /// See `draw_glyph()`
+    pub unsafe fn draw(
+        &mut self,
+        dst: *mut u32,
+        stride: usize,
+        font: &Psf,
+        cell: Cell,
+        table: &ColorTable,
+        cleared: bool,
+    ) {
+        let width = self.width;
+        let pixels = self.get(font, cell, table);
+        for (y, bits) in glyph_rows(font, font.glyph(cell.chr)).enumerate() {
+            if cleared && bits.iter().all(|bits| *bits == 0) {
+                continue;
+            }
+            core::ptr::copy_nonoverlapping(
+                pixels[y * width..].as_ptr(),
+                dst.add(y * stride),
+                width,
+            );
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! # Cells
+//! The text on the screen, kept so the screen can be drawn again.
+//!
+//! Before the heap exists, a single screen is kept in a static buffer. `upgrade()` moves it to
+//! the heap, from then on the rows scrolled off the top are kept as well, up to
+//! `SCROLLBACK_SCREENS` screens.
+use alloc::collections::VecDeque;
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::Cell;
+
+/// The number of screens kept in the scrollback
+pub const SCROLLBACK_SCREENS: usize = 8;
+
+/// Enough for 1920x1080 with an 8x16 font
+const EARLY_CELLS: usize = 240 * 68;
+const NO_CELL: Cell = Cell {
+    chr: 0,
+    foreground: 0,
+    background: 0,
+};
+static mut EARLY: [Cell; EARLY_CELLS] = [NO_CELL; EARLY_CELLS];
+static EARLY_TAKEN: AtomicBool = AtomicBool::new(false);
+
+enum Storage {
+    /// Nothing is recorded
+    None,
+    Early(&'static mut [Cell]),
+    Heap(Vec<Cell>),
+}
+
+/// # Cell Buffer
+/// The cells of a screen of `columns`x`rows` characters and the rows scrolled off its top
+pub struct CellBuffer {
+    columns: usize,
+    rows: usize,
+    screen: Storage,
+    /// Oldest first
+    history: VecDeque<Vec<Cell>>,
+    /// The number of rows `history` may hold
+    history_limit: usize,
+    /// The number of rows the view is scrolled back by
+    view: usize,
+}
+
+impl CellBuffer {
+    /// # Disabled
+    /// A buffer that records nothing
+    pub fn disabled() -> Self {
+        Self {
+            columns: 0,
+            rows: 0,
+            screen: Storage::None,
+            history: VecDeque::new(),
+            history_limit: 0,
+            view: 0,
+        }
+    }
+
+    /// # Early
+    /// A buffer in static memory, disabled if the static memory is in use or too small
+    pub fn early(columns: usize, rows: usize, blank: Cell) -> Self {
+        let mut buffer = Self::disabled();
+        let len = columns * rows;
+        if len > EARLY_CELLS || EARLY_TAKEN.swap(true, Ordering::AcqRel) {
+            return buffer;
+        }
+        // Only a single buffer can hold the static memory
+        let cells = unsafe { &mut EARLY[..len] };
+        cells.fill(blank);
+        buffer.columns = columns;
+        buffer.rows = rows;
+        buffer.screen = Storage::Early(cells);
+        buffer
+    }
+
+    /// # Upgrade
+    /// Moves the screen to the heap and starts keeping `screens` screens of scrollback. What
+    /// was recorded in static memory is kept.
+    pub fn upgrade(&mut self, columns: usize, rows: usize, blank: Cell, screens: usize) {
+        let mut cells = vec![blank; columns * rows];
+        if self.columns == columns && self.rows == rows {
+            cells.copy_from_slice(self.cells());
+        }
+        self.columns = columns;
+        self.rows = rows;
+        self.screen = Storage::Heap(cells);
+        self.history_limit = rows * screens;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self.screen, Storage::None)
+    }
+
+    pub fn has_scrollback(&self) -> bool {
+        self.history_limit > 0
+    }
+
+    /// # Cells
+    /// The screen, row by row with `columns` cells each
+    pub fn cells(&self) -> &[Cell] {
+        match &self.screen {
+            Storage::None => &[],
+            Storage::Early(cells) => &cells[..],
+            Storage::Heap(cells) => &cells[..],
+        }
+    }
+
+    fn cells_mut(&mut self) -> &mut [Cell] {
+        match &mut self.screen {
+            Storage::None => &mut [],
+            Storage::Early(cells) => &mut cells[..],
+            Storage::Heap(cells) => &mut cells[..],
+        }
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, cell: Cell) {
+        if col >= self.columns || row >= self.rows {
+            return;
+        }
+        let columns = self.columns;
+        self.cells_mut()[row * columns + col] = cell;
+    }
+
+    /// # Clear
+    /// Blanks the screen, the scrollback is kept
+    pub fn clear(&mut self, blank: Cell) {
+        self.cells_mut().fill(blank);
+    }
+
+    /// # Scroll Up
+    /// Moves every row up by one, the top row goes to the scrollback
+    pub fn scroll_up(&mut self, blank: Cell) {
+        if !self.is_enabled() {
+            return;
+        }
+        let columns = self.columns;
+        if self.has_scrollback() {
+            let top = self.cells()[..columns].to_vec();
+            self.push_history(top);
+        }
+        let cells = self.cells_mut();
+        cells.copy_within(columns.., 0);
+        let len = cells.len();
+        cells[len - columns..].fill(blank);
+    }
+
+    fn push_history(&mut self, row: Vec<Cell>) {
+        self.history.push_back(row);
+        while self.history.len() > self.history_limit {
+            self.history.pop_front();
+        }
+    }
+
+    /// # Resize
+    /// Changes the screen to `columns`x`rows`. The top `shift` rows go to the scrollback, the
+    /// rest is cut off or padded with `blank`. Needs the heap.
+    pub fn resize(&mut self, columns: usize, rows: usize, shift: usize, blank: Cell) {
+        if !self.is_enabled() {
+            return;
+        }
+        let fit = |row: &[Cell]| {
+            let mut row = row.to_vec();
+            row.resize(columns, blank);
+            row
+        };
+        let old_columns = self.columns;
+        let old = self.cells().to_vec();
+        let mut cells = Vec::with_capacity(columns * rows);
+        for (idx, row) in old.chunks(old_columns).enumerate() {
+            if idx < shift {
+                if self.has_scrollback() {
+                    self.push_history(row.to_vec());
+                }
+            } else if idx - shift < rows {
+                cells.extend(fit(row));
+            }
+        }
+        cells.resize(columns * rows, blank);
+        for row in self.history.iter_mut() {
+            row.resize(columns, blank);
+        }
+
+        self.columns = columns;
+        self.rows = rows;
+        self.view = 0;
+        self.screen = Storage::Heap(cells);
+    }
+
+    /// # Reshape
+    /// Changes the screen to `columns`x`rows` and blanks it, the scrollback is dropped. Unlike
+    /// `resize()`, it keeps to the static memory, which is given up if it is too small.
+    pub fn reshape(&mut self, columns: usize, rows: usize, blank: Cell) {
+        let len = columns * rows;
+        self.screen = match core::mem::replace(&mut self.screen, Storage::None) {
+            Storage::None => return,
+            // The buffer holds the static memory, the old slice of it is gone
+            Storage::Early(_) if len <= EARLY_CELLS => {
+                let cells = unsafe { &mut EARLY[..len] };
+                cells.fill(blank);
+                Storage::Early(cells)
+            }
+            Storage::Early(_) => {
+                self.columns = 0;
+                self.rows = 0;
+                return;
+            }
+            Storage::Heap(_) => Storage::Heap(vec![blank; len]),
+        };
+        self.columns = columns;
+        self.rows = rows;
+        self.history.clear();
+        self.view = 0;
+    }
+
+    /// # View
+    /// The number of rows the view is scrolled back by, 0 if it shows the screen
+    pub fn view(&self) -> usize {
+        self.view
+    }
+
+    /// # Scroll View
+    /// Scrolls the view back by `rows` rows, or forward if negative
+    ///
+    /// ## Returns
+    /// - bool = Whether the view changed
+    pub fn scroll_view(&mut self, rows: isize) -> bool {
+        let old = self.view;
+        self.view = if rows < 0 {
+            old.saturating_sub(rows.unsigned_abs())
+        } else {
+            (old + rows as usize).min(self.history.len())
+        };
+        self.view != old
+    }
+
+    /// # Visible Row
+    /// Row `row` of what the view shows
+    pub fn visible_row(&self, row: usize) -> &[Cell] {
+        let idx = self.history.len() - self.view + row;
+        match self.history.get(idx) {
+            Some(row) => row,
+            None => {
+                let row = idx - self.history.len();
+                &self.cells()[row * self.columns..(row + 1) * self.columns]
+            }
+        }
+    }
+}
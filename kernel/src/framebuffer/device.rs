@@ -0,0 +1,117 @@
+//! # Device
+//! `/dev/fb0`, which lets a program draw to the framebuffer itself: `FbRequest::GetInfo` tells
+//! its geometry, and `mmap()` maps it write-combined. The console keeps drawing as well, a
+//! program that wants the screen to itself has to keep the console quiet.
+use alloc::{sync::Arc, vec::Vec};
+use bks::{PixelFormat, PAGE_SIZE};
+
+use super::{FramebufferInfo, BYTES_PER_PIXEL, FRAMEBUFFER_GUARD};
+use crate::error::{Error, Result};
+use crate::fs::devfs::{self, char_device};
+use crate::fs::{DirEntry, File, Metadata};
+use crate::memory::paging::pat::MemoryType;
+use crate::memory::usermem::{self, user_address};
+use crate::memory::vmm::Backing;
+
+pub use ::abi::fb::{FbFormat, FbRequest};
+
+/// # FB Info
+/// The struct `FbRequest::GetInfo` fills, its layout never changes
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FbInfo {
+    /// The bytes a mapping of the whole framebuffer takes
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    /// The bytes from the start of one line to the start of the next
+    pub pitch: u32,
+    pub format: u32,
+}
+
+impl From<FramebufferInfo> for FbInfo {
+    fn from(info: FramebufferInfo) -> Self {
+        Self {
+            size: info.size as u64,
+            width: info.width as u32,
+            height: info.height as u32,
+            pitch: (info.stride * BYTES_PER_PIXEL) as u32,
+            format: match info.format {
+                PixelFormat::Bgr => FbFormat::Bgrx,
+                // `validate()` lets no other format through
+                _ => FbFormat::Rgbx,
+            },
+        }
+    }
+}
+
+/// # Framebuffer Device
+/// The framebuffer as a character device, its size is that of the framebuffer
+pub struct FramebufferDevice {
+    info: FramebufferInfo,
+}
+
+impl FramebufferDevice {
+    pub fn new(info: FramebufferInfo) -> Self {
+        Self { info }
+    }
+
+    /// The bytes a mapping can take, the framebuffer rounded up to whole pages
+    fn mappable_len(&self) -> u64 {
+        (self.info.size as u64 + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE
+    }
+}
+
+impl File for FramebufferDevice {
+    fn metadata(&self) -> Metadata {
+        char_device(self.info.size as u64)
+    }
+
+    /// The pixels are only accessed through a mapping
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize> {
+        Err(Error::InvalidArgument)
+    }
+
+    fn read_dir(&self, _offset: u64) -> Result<Vec<DirEntry>> {
+        Err(Error::NotADirectory)
+    }
+
+    fn ioctl(&self, request: u64, arg: u64) -> Result<i32> {
+        match request {
+            FbRequest::GetInfo => usermem::write_user(user_address(arg)?, FbInfo::from(self.info))?,
+            _ => return Err(Error::InvalidArgument),
+        }
+        Ok(0)
+    }
+
+    /// ## Returns
+    /// - Error::InvalidArgument = `offset` is at or beyond the end of the framebuffer
+    fn mmap(&self, offset: u64) -> Result<Backing> {
+        if offset >= self.mappable_len() {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(Backing::Device {
+            phys: self.info.base + offset,
+            len: self.mappable_len() - offset,
+            memory_type: MemoryType::WriteCombining,
+        })
+    }
+}
+
+crate::initcall! {
+    name: "fb-device",
+    stage: Late,
+    deps: ["devfs"],
+    fatal: false,
+    init: init_fb_device,
+}
+
+/// # Init FB Device
+/// Registers `/dev/fb0`, unless the kernel only writes to the serial port
+pub fn init_fb_device() -> Result<()> {
+    let info = unsafe { FRAMEBUFFER_GUARD.lock().assume_init_ref().info() };
+    match info {
+        Some(info) => devfs::register("fb0", Arc::new(FramebufferDevice::new(info))),
+        None => Ok(()),
+    }
+}
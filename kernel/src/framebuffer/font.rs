@@ -0,0 +1,191 @@
+//! # Fonts
+//! PC screen fonts (PSF) the console draws with.
+//!
+//! The bootloader hands over a PSF1 font. With the `embedded-fonts` feature, further fonts are
+//! built into the kernel image, they are used if the boot font is unusable and can be switched
+//! to at runtime.
+use bks::Psf1Font;
+
+use crate::scheduler::spin::Mutex;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_HEADER_SIZE: usize = 4;
+/// The font has 512 instead of 256 glyphs
+const PSF1_MODE_512: u8 = 0x01;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+
+/// The name of the font the bootloader handed over
+pub const BOOT_FONT: &str = "boot";
+
+/// The fonts built into the kernel image
+#[cfg(feature = "embedded-fonts")]
+static EMBEDDED: &[(&str, &[u8])] = &[
+    (
+        "builtin-8x16",
+        include_bytes!("../../../binaries/font/font.psf"),
+    ),
+    (
+        "builtin-16x32",
+        include_bytes!("../../../binaries/font/font-16x32.psf"),
+    ),
+];
+#[cfg(not(feature = "embedded-fonts"))]
+static EMBEDDED: &[(&str, &[u8])] = &[];
+
+static BOOT: Mutex<Option<Psf>> = Mutex::new(None);
+
+/// # PSF
+/// A font of `glyph_count` glyphs of `width`x`height` pixels. Every row of a glyph is padded
+/// to whole bytes, the most significant bit is the leftmost pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct Psf {
+    name: &'static str,
+    width: usize,
+    height: usize,
+    glyph_count: usize,
+    glyphs: &'static [u8],
+}
+
+impl Psf {
+    /// # Parse
+    /// Reads a PSF1 or PSF2 font from `data`, `None` if it is malformed
+    pub fn parse(name: &'static str, data: &'static [u8]) -> Option<Self> {
+        if data.starts_with(&PSF1_MAGIC) && data.len() >= PSF1_HEADER_SIZE {
+            let glyph_count = if data[2] & PSF1_MODE_512 != 0 {
+                512
+            } else {
+                256
+            };
+            return Self::new(
+                name,
+                8,
+                data[3] as usize,
+                glyph_count,
+                &data[PSF1_HEADER_SIZE..],
+            );
+        }
+        if data.starts_with(&PSF2_MAGIC) && data.len() >= PSF2_HEADER_SIZE {
+            let field = |idx: usize| {
+                let bytes = &data[idx * 4..idx * 4 + 4];
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+            };
+            let (header_size, glyph_count) = (field(2), field(4));
+            let (glyph_size, height, width) = (field(5), field(6), field(7));
+            if header_size > data.len() || glyph_size != (width + 7) / 8 * height {
+                return None;
+            }
+            return Self::new(name, width, height, glyph_count, &data[header_size..]);
+        }
+        None
+    }
+
+    /// # From Boot
+    /// The font the bootloader handed over, `None` if it is unusable
+    pub fn from_boot(font: &Psf1Font) -> Option<Self> {
+        if font.buffer == 0 || font.header.magic != PSF1_MAGIC {
+            return None;
+        }
+        let glyph_count = if font.header.mode & PSF1_MODE_512 != 0 {
+            512
+        } else {
+            256
+        };
+        let glyphs = unsafe { core::slice::from_raw_parts(font.buffer as *const u8, font.size) };
+        Self::new(
+            BOOT_FONT,
+            8,
+            font.header.charsize as usize,
+            glyph_count,
+            glyphs,
+        )
+    }
+
+    fn new(
+        name: &'static str,
+        width: usize,
+        height: usize,
+        glyph_count: usize,
+        glyphs: &'static [u8],
+    ) -> Option<Self> {
+        let font = Self {
+            name,
+            width,
+            height,
+            glyph_count,
+            glyphs,
+        };
+        // Everything up to '~' has to be there
+        if width == 0 || height == 0 || glyph_count < 0x7F {
+            return None;
+        }
+        if glyphs.len() < glyph_count * font.glyph_size() {
+            return None;
+        }
+        Some(font)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyph_count
+    }
+
+    fn glyph_size(&self) -> usize {
+        (self.width + 7) / 8 * self.height
+    }
+
+    /// # Glyph
+    /// The bitmap of `chr`, the one of '?' if the font has no glyph for it
+    pub fn glyph(&self, chr: u8) -> &'static [u8] {
+        let idx = if (chr as usize) < self.glyph_count {
+            chr as usize
+        } else {
+            b'?' as usize
+        };
+        let size = self.glyph_size();
+        &self.glyphs[idx * size..(idx + 1) * size]
+    }
+
+    /// # Is Set
+    /// Whether the pixel at `x`, `y` of `glyph` is drawn in the foreground color
+    pub fn is_set(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        let bytes_per_row = (self.width + 7) / 8;
+        glyph[y * bytes_per_row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+/// # Set Boot Font
+/// Remembers the font the bootloader handed over, so it can be switched back to
+pub fn set_boot_font(font: Psf) {
+    *BOOT.lock() = Some(font);
+}
+
+/// # Embedded
+/// The fonts built into the kernel image, nothing without the `embedded-fonts` feature
+pub fn embedded() -> impl Iterator<Item = Psf> {
+    EMBEDDED
+        .iter()
+        .filter_map(|(name, data)| Psf::parse(name, data))
+}
+
+/// # Fonts
+/// All fonts the console can switch to, the boot font first
+pub fn fonts() -> impl Iterator<Item = Psf> {
+    let boot = *BOOT.lock();
+    boot.into_iter().chain(embedded())
+}
+
+pub fn find(name: &str) -> Option<Psf> {
+    fonts().find(|font| font.name() == name)
+}
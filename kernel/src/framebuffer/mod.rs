@@ -1,10 +1,34 @@
 use core::{fmt::Write, mem::MaybeUninit};
 
-use spin::Mutex;
-
-use bks::{Framebuffer, Psf1Font};
+use bks::{Framebuffer, PixelFormat};
 extern crate compiler_builtins;
 
+use crate::arch::interrupts::exception_safe_lock;
+use crate::arch::mem;
+use crate::drivers::serial::{self, SerialPort, SERIAL};
+use crate::error::{Error, Result};
+use crate::klog;
+use crate::memory::paging::page_table_manager::translate;
+use crate::memory::{PhysicalAddress, VirtualAddress};
+use crate::power::notifier::{self, PowerAction};
+use crate::scheduler::spin::{Mutex, MutexGuard};
+
+use self::blit::{ColorTable, GlyphCache};
+use self::cells::{CellBuffer, SCROLLBACK_SCREENS};
+use self::font::Psf;
+use self::qr::{QrCode, QUIET_ZONE};
+use self::rotation::Rotation;
+
+pub mod blit;
+pub mod cells;
+pub mod device;
+pub mod font;
+pub mod qr;
+pub mod rotation;
+
+/// The number of bytes per pixel, all supported formats use 32 bits
+pub const BYTES_PER_PIXEL: usize = 4;
+
 // DISCUSS: Should Option be used here?
 pub static FRAMEBUFFER_GUARD: Mutex<MaybeUninit<FramebufferGuard>> =
     Mutex::new(MaybeUninit::uninit());
@@ -34,38 +58,489 @@ impl Into<u32> for Color {
     }
 }
 
-#[derive(Clone, Copy)]
+/// # Framebuffer Info
+/// The geometry of a framebuffer that passed `validate()`
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferInfo {
+    pub base: u64,
+    pub size: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Pixels per scanline
+    pub stride: usize,
+    pub format: PixelFormat,
+}
+
+impl core::fmt::Display for FramebufferInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "Base Address: {:#x}", self.base)?;
+        writeln!(f, "Size: {:#x}", self.size)?;
+        writeln!(f, "Resolution: {}x{}", self.width, self.height)?;
+        writeln!(f, "Stride: {}", self.stride)?;
+        write!(f, "Format: {:?}", self.format)
+    }
+}
+
+impl FramebufferInfo {
+    /// # Mapped Len
+    /// The bytes the console draws to, `stride` × `height` pixels. The bootloader may report a
+    /// larger `size`.
+    pub fn mapped_len(&self) -> u64 {
+        (self.stride * self.height * BYTES_PER_PIXEL) as u64
+    }
+}
+
+/// # Framebuffer Error
+/// Why a framebuffer handed over by the bootloader is unusable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramebufferError {
+    NullBase,
+    BadResolution { width: usize, height: usize },
+    BadStride { stride: usize, width: usize },
+    BufferTooSmall { size: usize, required: usize },
+    UnsupportedFormat(PixelFormat),
+    BadFont,
+}
+
+impl core::fmt::Display for FramebufferError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NullBase => write!(f, "base address is null"),
+            Self::BadResolution { width, height } => {
+                write!(f, "resolution {}x{} is too small", width, height)
+            }
+            Self::BadStride { stride, width } => {
+                write!(f, "stride {} is smaller than the width {}", stride, width)
+            }
+            Self::BufferTooSmall { size, required } => write!(
+                f,
+                "size {:#x} is smaller than the {:#x} bytes the geometry requires",
+                size, required
+            ),
+            Self::UnsupportedFormat(format) => {
+                write!(f, "pixel format {:?} is unsupported", format)
+            }
+            Self::BadFont => write!(f, "font is missing or unreadable"),
+        }
+    }
+}
+
+/// # Validate
+/// Checks that `framebuffer` can be drawn to with `font`, which is `None` if there is no
+/// usable font
+pub fn validate(
+    framebuffer: &Framebuffer,
+    font: Option<&Psf>,
+) -> Result<FramebufferInfo, FramebufferError> {
+    if framebuffer.base == 0 {
+        return Err(FramebufferError::NullBase);
+    }
+    let font = match font {
+        Some(font) => font,
+        None => return Err(FramebufferError::BadFont),
+    };
+    if !fits(framebuffer.width, framebuffer.height, font) {
+        return Err(FramebufferError::BadResolution {
+            width: framebuffer.width,
+            height: framebuffer.height,
+        });
+    }
+    if framebuffer.stride < framebuffer.width {
+        return Err(FramebufferError::BadStride {
+            stride: framebuffer.stride,
+            width: framebuffer.width,
+        });
+    }
+    let required = framebuffer
+        .stride
+        .saturating_mul(framebuffer.height)
+        .saturating_mul(BYTES_PER_PIXEL);
+    if framebuffer.size < required {
+        return Err(FramebufferError::BufferTooSmall {
+            size: framebuffer.size,
+            required,
+        });
+    }
+    match framebuffer.format {
+        PixelFormat::Rgb | PixelFormat::Bgr => {}
+        format => return Err(FramebufferError::UnsupportedFormat(format)),
+    }
+
+    Ok(FramebufferInfo {
+        base: framebuffer.base,
+        size: framebuffer.size,
+        width: framebuffer.width,
+        height: framebuffer.height,
+        stride: framebuffer.stride,
+        format: framebuffer.format,
+    })
+}
+
+/// Whether a screen of `width`x`height` pixels can show text in `font`. Scrolling needs room
+/// for at least three lines.
+fn fits(width: usize, height: usize, font: &Psf) -> bool {
+    width >= font.width() && height >= font.height() * 3
+}
+
+/// # Move Pixels
+/// Copies `count` pixels from `src` to `dst` with `mem::copy_fast()`, the ranges may overlap
+///
+/// ## Safety
+/// Both ranges have to be in the framebuffer
+unsafe fn move_pixels(src: *const u32, dst: *mut u32, count: usize) {
+    mem::copy_fast(dst as *mut u8, src as *const u8, count * 4);
+}
+
+/// # Cell
+/// A character on the screen and its colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub chr: u8,
+    pub foreground: u32,
+    pub background: u32,
+}
+
 pub struct FramebufferGuard {
+    /// `None` if there is no usable framebuffer, all output goes to the serial port instead
+    info: Option<FramebufferInfo>,
     framebuffer: Framebuffer,
-    pub framebuffer_buffer: u32,
-    font: Psf1Font,
+    /// The first pixel, zero in serial-only mode
+    phys: PhysicalAddress,
+    /// Where the first pixel is mapped in the active page tables, see `remap()`
+    virt: u64,
+    /// `None` in serial-only mode
+    font: Option<Psf>,
     col: usize,
     row: usize,
     background: u32,
     foreground: u32,
     column_starting_point: usize,
+    /// What is on the screen, all text output goes through it
+    cells: CellBuffer,
+    /// The colors of the last cell drawn
+    colors: ColorTable,
+    /// `None` until `set_glyph_cache()` enables it
+    glyph_cache: Option<GlyphCache>,
+    /// How the panel is mounted, everything but the drawing works in logical coordinates
+    rotation: Rotation,
 }
 
 impl FramebufferGuard {
+    /// # New
+    /// Creates a guard drawing to `framebuffer` with `font`, which must have passed `validate()`
     pub fn new(
-        mut framebuffer: Framebuffer,
-        font: Psf1Font,
+        info: FramebufferInfo,
+        framebuffer: Framebuffer,
+        font: Psf,
         background: Color,
         foreground: Color,
     ) -> Self {
-        Self {
-            framebuffer_buffer: framebuffer.raw_buffer() as *mut u32 as u32,
+        let mut guard = Self {
+            info: Some(info),
+            phys: PhysicalAddress::new(info.base),
+            // The bootloader maps the framebuffer one to one
+            virt: info.base,
             framebuffer: framebuffer,
-            font: font,
+            font: Some(font),
             row: 0,
             col: 0,
             background: background as u32,
             foreground: foreground as u32,
             column_starting_point: 0,
+            cells: CellBuffer::disabled(),
+            colors: ColorTable::new(foreground as u32, background as u32),
+            glyph_cache: None,
+            rotation: Rotation::Rotate0,
+        };
+        let (columns, rows) = guard.geometry();
+        guard.cells = CellBuffer::early(columns, rows, guard.blank());
+        guard
+    }
+
+    /// # Serial Only
+    /// Creates a guard that never touches `framebuffer` and writes everything to the serial
+    /// port instead
+    pub fn serial_only(framebuffer: Framebuffer) -> Self {
+        Self {
+            info: None,
+            phys: PhysicalAddress::new(0),
+            virt: 0,
+            framebuffer,
+            font: None,
+            row: 0,
+            col: 0,
+            background: Color::Black as u32,
+            foreground: Color::White as u32,
+            column_starting_point: 0,
+            cells: CellBuffer::disabled(),
+            colors: ColorTable::new(Color::White as u32, Color::Black as u32),
+            glyph_cache: None,
+            rotation: Rotation::Rotate0,
+        }
+    }
+
+    /// # Info
+    /// The validated geometry of the framebuffer, `None` in serial-only mode
+    pub fn info(&self) -> Option<FramebufferInfo> {
+        self.info
+    }
+
+    pub fn is_serial_only(&self) -> bool {
+        self.info.is_none()
+    }
+
+    /// # Physical Base
+    /// The physical address of the first pixel, `None` in serial-only mode
+    pub fn physical_base(&self) -> Option<PhysicalAddress> {
+        self.info.map(|_| self.phys)
+    }
+
+    /// # Mapped Base
+    /// The virtual address of the first pixel the console draws to, zero in serial-only mode
+    pub fn mapped_base(&self) -> u64 {
+        self.virt
+    }
+
+    /// # Remap
+    /// Draws through `virt` from now on, where the framebuffer has to be mapped in the active
+    /// page tables. Code that switches page tables holds the guard across the switch and calls
+    /// this before letting go of it, so nothing draws through a mapping that is gone.
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = A page of the framebuffer is not mapped to it at `virt`, the
+    ///   guard keeps drawing through the old mapping
+    pub fn remap(&mut self, virt: VirtualAddress) -> Result<()> {
+        let info = match self.info {
+            Some(info) => info,
+            None => return Ok(()),
+        };
+        let len = info.mapped_len();
+        let mismatch = (0..len)
+            .step_by(bks::PAGE_SIZE as usize)
+            .chain(core::iter::once(len - 1))
+            .any(|offset| translate(virt.as_u64() + offset) != Some(self.phys.as_u64() + offset));
+        if mismatch {
+            return Err(Error::InvalidArgument);
+        }
+        self.virt = virt.as_u64();
+        Ok(())
+    }
+
+    /// # Write Console
+    /// Writes `s` to the screen, or to COM1 in serial-only mode, without adding it to the
+    /// kernel log
+    pub fn write_console(&mut self, s: &str) {
+        if self.is_serial_only() {
+            write_serial_console(s);
+            return;
+        }
+        unsafe {
+            self.print(s);
+        };
+    }
+
+    /// # Font
+    /// The font the console draws with, `None` in serial-only mode
+    pub fn font(&self) -> Option<Psf> {
+        self.font
+    }
+
+    /// # Geometry
+    /// The number of columns and rows of characters that fit on the screen
+    pub fn geometry(&self) -> (usize, usize) {
+        let (width, height) = self.size();
+        match self.font {
+            Some(font) => (width / font.width(), height / font.height()),
+            None => (0, 0),
+        }
+    }
+
+    /// # Size
+    /// The width and height of the screen in pixels as it is looked at, those of the
+    /// framebuffer swapped if it is rotated by 90 or 270 degrees. (0, 0) in serial-only mode.
+    pub fn size(&self) -> (usize, usize) {
+        match self.info {
+            Some(info) => self.rotation.logical_size(info.width, info.height),
+            None => (0, 0),
+        }
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// # Set Rotation
+    /// Draws rotated by `rotation`, for a panel that is mounted rotated. The screen is cleared
+    /// and the scrollback is dropped.
+    pub fn set_rotation(&mut self, rotation: Rotation) -> Result<(), FramebufferError> {
+        let (info, font) = match (self.info, self.font) {
+            (Some(info), Some(font)) => (info, font),
+            _ => return Ok(()),
+        };
+        let (width, height) = rotation.logical_size(info.width, info.height);
+        if !fits(width, height, &font) {
+            return Err(FramebufferError::BadResolution { width, height });
+        }
+        self.rotation = rotation;
+        let (columns, rows) = self.geometry();
+        let blank = self.blank();
+        self.cells.reshape(columns, rows, blank);
+        self.column_starting_point = 0;
+        unsafe { self.clear_color(self.background) };
+        Ok(())
+    }
+
+    /// The width and height of a character in pixels
+    fn glyph_size(&self) -> (usize, usize) {
+        match self.font {
+            Some(font) => (font.width(), font.height()),
+            None => (8, 16),
+        }
+    }
+
+    /// # Cells
+    /// What is on the screen, row by row. Empty if the screen is too large for the static
+    /// memory and `enable_backing_store()` was not called yet.
+    pub fn cells(&self) -> &[Cell] {
+        self.cells.cells()
+    }
+
+    /// # Scrollback
+    /// The number of rows that scrolled off the top and are kept
+    pub fn scrollback(&self) -> usize {
+        self.cells.history_len()
+    }
+
+    /// # Cursor
+    /// The row and column of the character cell the next character is drawn into
+    pub fn cursor(&self) -> (usize, usize) {
+        let (width, height) = self.glyph_size();
+        (self.row / height, self.col / width)
+    }
+
+    /// # Enable Backing Store
+    /// Moves the cells to the heap and starts keeping `SCROLLBACK_SCREENS` screens of
+    /// scrollback. The glyph cache is enabled as well. Needs the heap.
+    pub fn enable_backing_store(&mut self) {
+        if self.is_serial_only() {
+            return;
+        }
+        let (columns, rows) = self.geometry();
+        let blank = self.blank();
+        self.cells.upgrade(columns, rows, blank, SCROLLBACK_SCREENS);
+        self.set_glyph_cache(true);
+    }
+
+    /// # Set Glyph Cache
+    /// Enables or disables the glyph cache, see `blit::GlyphCache`. Enabling it needs the heap.
+    pub fn set_glyph_cache(&mut self, enabled: bool) {
+        self.glyph_cache = match (enabled, self.font) {
+            (true, Some(font)) => Some(GlyphCache::new(&font)),
+            _ => None,
+        };
+    }
+
+    pub fn has_glyph_cache(&self) -> bool {
+        self.glyph_cache.is_some()
+    }
+
+    fn blank(&self) -> Cell {
+        Cell {
+            chr: b' ',
+            foreground: self.foreground,
+            background: self.background,
+        }
+    }
+
+    /// Records `cell` as drawn at the pixel position `row`, `col`
+    fn record(&mut self, row: usize, col: usize, cell: Cell) {
+        let (width, height) = self.glyph_size();
+        self.cells.set(row / height, col / width, cell);
+    }
+
+    /// # Set Font
+    /// Switches to `font`. The cursor stays in its character cell, clamped to the new
+    /// geometry. With the backing store enabled, the screen is redrawn in the new font, keeping
+    /// the rows up to the cursor if fewer fit, the others go to the scrollback. Without it, the
+    /// screen is cleared.
+    pub fn set_font(&mut self, font: &Psf) -> Result<(), FramebufferError> {
+        let old = match (self.info, self.font) {
+            (Some(_), Some(old)) => old,
+            // Nothing is drawn
+            _ => return Ok(()),
+        };
+        let (width, height) = self.size();
+        if !fits(width, height, font) {
+            return Err(FramebufferError::BadResolution { width, height });
+        }
+        let cursor_row = self.row / old.height();
+        let cursor_col = self.col / old.width();
+        let start_col = self.column_starting_point / old.width();
+
+        self.font = Some(*font);
+        if self.glyph_cache.is_some() {
+            self.set_glyph_cache(true);
+        }
+        let (columns, rows) = self.geometry();
+        let shift = (cursor_row + 1).saturating_sub(rows);
+        self.column_starting_point = start_col.min(columns - 1) * font.width();
+
+        if !self.cells.is_enabled() {
+            unsafe { self.clear_color(self.background) };
+            return Ok(());
+        }
+        let blank = self.blank();
+        self.cells.resize(columns, rows, shift, blank);
+        self.row = (cursor_row - shift) * font.height();
+        self.col = cursor_col.min(columns - 1) * font.width();
+        self.redraw();
+        Ok(())
+    }
+
+    /// # Redraw
+    /// Draws the cells over the whole screen, e.g. after something else drew to it. Does
+    /// nothing if there are no cells.
+    pub fn redraw(&mut self) {
+        if self.is_serial_only() || !self.cells.is_enabled() {
+            return;
+        }
+        let (width, height) = self.glyph_size();
+        let rows = self.geometry().1;
+        unsafe { self.fill(self.background) };
+        for row in 0..rows {
+            for col in 0..self.cells.columns() {
+                let cell = self.cells.visible_row(row)[col];
+                let cleared = cell.background == self.background;
+                unsafe { self.draw_cell(row * height, col * width, cell, cleared) };
+            }
+        }
+    }
+
+    /// # Scroll View
+    /// Scrolls the view back into the scrollback by `rows` rows, or forward if negative. Any
+    /// output scrolls it back to the screen.
+    pub fn scroll_view(&mut self, rows: isize) {
+        if self.cells.scroll_view(rows) {
+            self.redraw();
         }
     }
 
+    /// # View
+    /// The number of rows the view is scrolled back by
+    pub fn view(&self) -> usize {
+        self.cells.view()
+    }
+
+    /// Scrolls the view back to the screen before it is drawn to
+    fn follow(&mut self) {
+        let view = self.cells.view() as isize;
+        self.scroll_view(-view);
+    }
+
     pub fn resolution(&mut self) -> (usize, usize, usize) {
+        if self.is_serial_only() {
+            return (0, 0, 0);
+        }
         (
             self.framebuffer.width,
             self.framebuffer.height,
@@ -82,20 +557,75 @@ impl FramebufferGuard {
     where
         T: Into<u32>,
     {
-        let base = self.framebuffer_buffer as u64;
-        let bytes_per_line = self.framebuffer.stride * 4;
+        if self.is_serial_only() {
+            return;
+        }
         let color_as_u32: u32 = color.into();
+        self.follow();
+        self.fill(color_as_u32);
+        let blank = Cell {
+            chr: b' ',
+            foreground: self.foreground,
+            background: color_as_u32,
+        };
+        self.cells.clear(blank);
+        self.set_location(0, 0);
+    }
+
+    unsafe fn fill(&mut self, color: u32) {
+        let (width, height) = self.size();
+        self.fill_rect(0, 0, width, height, color);
+    }
+
+    /// # Fill Rect
+    /// Fills `width`x`height` pixels from `x`, `y` with `color`, as far as they are on the
+    /// screen. The cells do not change, text drawn over the rectangle may only be partly drawn
+    /// until `redraw()`.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        let info = match self.info {
+            Some(info) => info,
+            None => return,
+        };
+        let (screen_width, screen_height) = self.size();
+        let right = x.saturating_add(width).min(screen_width);
+        let bottom = y.saturating_add(height).min(screen_height);
+        if x >= right || y >= bottom {
+            return;
+        }
+        // Rotated, the rectangle is still aligned to the scanlines, between its corners
+        let (x0, y0) = self.rotation.to_physical(x, y, info.width, info.height);
+        let (x1, y1) = self
+            .rotation
+            .to_physical(right - 1, bottom - 1, info.width, info.height);
+        let (left, right) = (x0.min(x1), x0.max(x1) + 1);
+        let base = self.pixels();
+        for row in y0.min(y1)..=y0.max(y1) {
+            let line = unsafe {
+                core::slice::from_raw_parts_mut(base.add(row * info.stride + left), right - left)
+            };
+            line.fill(color);
+        }
+    }
+
+    fn pixels(&self) -> *mut u32 {
+        self.virt as *mut u32
+    }
 
-        for vertical in 0..self.framebuffer.height {
-            let pix_ptr_base = base + (vertical as u64 * bytes_per_line as u64);
-            let mut pix_ptr = pix_ptr_base as *mut u32;
-            *pix_ptr = 0xff;
-            while pix_ptr < ((pix_ptr_base + bytes_per_line as u64) as *mut u32) {
-                *pix_ptr = color_as_u32;
-                pix_ptr = pix_ptr.add(1);
+    /// # Draw QR
+    /// Draws `code` with its quiet zone from `x`, `y`, every module as `scale`x`scale` pixels
+    pub fn draw_qr(&mut self, code: &QrCode, x: usize, y: usize, scale: usize) {
+        let modules = code.size() + 2 * QUIET_ZONE;
+        let light = Color::White as u32;
+        self.fill_rect(x, y, modules * scale, modules * scale, light);
+        for row in 0..code.size() {
+            for col in 0..code.size() {
+                if code.is_dark(col, row) {
+                    let px = x + (col + QUIET_ZONE) * scale;
+                    let py = y + (row + QUIET_ZONE) * scale;
+                    self.fill_rect(px, py, scale, scale, Color::Black as u32);
+                }
             }
         }
-        self.set_location(0, 0);
     }
 
     pub fn set_column_starting_point(&mut self, new: usize) {
@@ -122,40 +652,61 @@ impl FramebufferGuard {
 
     fn new_line(&mut self) {
         self.col = self.column_starting_point;
-        self.row += 16;
+        self.row += self.glyph_size().1;
         self.new_line_checks();
     }
 
     fn new_line_checks(&mut self) {
-        if self.row + 16 >= self.framebuffer.height - (16 * 2) {
-            let top_row_max = self.framebuffer.stride * 4 * 16;
+        let height = self.glyph_size().1;
+        let rows = self.geometry().1;
+        if self.row + height > rows * height {
+            unsafe { self.scroll() };
+            self.row = (rows - 1) * height;
+        }
+    }
 
-            for i in 0..top_row_max {
-                unsafe {
-                    *(self.framebuffer_buffer as *mut u8).add(i) = 0x00;
+    /// Moves the text up by one row, the top row goes to the scrollback
+    unsafe fn scroll(&mut self) {
+        let info = match self.info {
+            Some(info) => info,
+            None => return,
+        };
+        let rows = self.geometry().1;
+        let height = self.glyph_size().1;
+        let stride = info.stride;
+        // The logical height of the rows that stay on the screen
+        let kept = height * (rows - 1);
+        let base = self.pixels();
+        match self.rotation {
+            Rotation::Rotate0 => move_pixels(base.add(height * stride), base, kept * stride),
+            // The rows of text are at the bottom and move down
+            Rotation::Rotate180 => {
+                let top = (info.height - rows * height) * stride;
+                move_pixels(
+                    base.add(top),
+                    base.add(top + height * stride),
+                    kept * stride,
+                );
+            }
+            // The rows of text are columns of pixels, they move within every scanline
+            Rotation::Rotate90 => {
+                let left = info.width - rows * height;
+                for y in 0..info.height {
+                    let line = base.add(y * stride + left);
+                    move_pixels(line, line.add(height), kept);
                 }
             }
-
-            unsafe {
-                //let base = self.framebuffer_buffer as u64 + top_row_max as u64;
-                //let size = self.framebuffer().size;
-                //let slice_a: &mut [u8] = core::slice::from_raw_parts_mut(base as *mut u8, size);
-                //let slice_b: &mut [u8] = core::slice::from_raw_parts_mut(
-                //    self.framebuffer_buffer as *mut u8,
-                //    self.framebuffer().size,
-                //);
-                ////for (old, new) in slice_a.iter().zip(slice_b) {
-                //    *new = *old;
-                //}
-                // TEMP: Remove FIXME
-                self.clear_color(self.background);
-                self.row = 1;
-                self.col = 0;
-
-                // TODO: core::ptr::copy(base as *mut u8, self.framebuffer_buffer as *mut u8, size);
+            Rotation::Rotate270 => {
+                for y in 0..info.height {
+                    let line = base.add(y * stride);
+                    move_pixels(line.add(height), line, kept);
+                }
             }
-            self.row -= 1;
         }
+        let width = self.size().0;
+        self.fill_rect(0, kept, width, height, self.background);
+        let blank = self.blank();
+        self.cells.scroll_up(blank);
     }
 
     // DISCUSS: Should printing be considered unsafe?
@@ -169,6 +720,7 @@ impl FramebufferGuard {
         }
     }
     unsafe fn draw_char(&mut self, c: char) {
+        self.follow();
         match c {
             '\n' | '\r' => {
                 self.new_line();
@@ -179,71 +731,164 @@ impl FramebufferGuard {
                 }
             }
             _ => {
+                let width = self.glyph_size().0;
                 self.put_char(c);
-                if self.framebuffer().width == self.col {
+                self.col += width;
+                // The next character has to fit as well
+                if self.col + width > self.size().0 {
                     self.new_line();
-                } else {
-                    self.col += 8;
                 }
             }
         }
     }
 
     pub fn clear_last_char(&mut self) {
+        if self.is_serial_only() {
+            // Erase the character on the terminal
+            let _ = SERIAL.lock().write_str("\x08 \x08");
+            return;
+        }
+        self.follow();
+        let (width, height) = self.glyph_size();
+        let line_end = self.geometry().0 * width;
         // Check that we do not clear nonexistant screen space
         if self.col == 0 {
-            self.col = self.framebuffer().width;
-            if (self.row as isize) - 16 < 0 {
+            self.col = line_end;
+            if (self.row as isize) - (height as isize) < 0 {
                 self.row = 0;
             } else {
-                self.row -= 16;
+                self.row -= height;
             }
         }
 
-        let stride = self.framebuffer.stride;
-
-        for y in self.row..(self.row + 16) {
-            for x in (self.col - 8)..(self.col) {
-                let offset = x + (y * stride);
-                unsafe {
-                    let ptr = (self.framebuffer_buffer as *mut u32).add(offset);
-                    *ptr = self.background;
-                }
-            }
-        }
-        if (self.col as isize) - 8 < 0 {
-            self.col = self.framebuffer().width;
-            if (self.row as isize) - 16 < 0 {
+        self.fill_rect(self.col - width, self.row, width, height, self.background);
+        let blank = self.blank();
+        self.record(self.row, self.col - width, blank);
+        if (self.col as isize) - (width as isize) < 0 {
+            self.col = line_end;
+            if (self.row as isize) - (height as isize) < 0 {
                 self.row = 0;
             } else {
-                self.row -= 16;
+                self.row -= height;
             }
         } else {
-            self.col -= 8;
+            self.col -= width;
         }
     }
 
     unsafe fn put_char(&mut self, chr: char) {
-        let charsize = self.font.header().charsize as usize;
-        let stride = self.framebuffer.stride;
-        let font_offset = (chr as u8 as usize * charsize) as usize;
-        let mut font_ptr = (self.font.buffer as *mut u8).add(font_offset);
-
-        for y in self.row..(self.row + 16) {
-            for x in self.col..(self.col + 8) {
-                let offset = x + (y * stride);
-                let ptr = (self.framebuffer_buffer as *mut u32).add(offset);
-                if (*font_ptr & (0b10000000 >> (x - self.col))) > 0 {
-                    *ptr = self.foreground;
-                } else {
-                    *ptr = self.background;
-                }
+        let cell = Cell {
+            chr: chr as u8,
+            foreground: self.foreground,
+            background: self.background,
+        };
+        let cleared = match (self.cell_at(self.row, self.col), self.font) {
+            (Some(old), Some(font)) => {
+                old.background == cell.background && blit::is_blank(&font, old.chr)
             }
-            font_ptr = font_ptr.add(1);
+            _ => false,
+        };
+        self.record(self.row, self.col, cell);
+        self.draw_cell(self.row, self.col, cell, cleared);
+    }
+
+    /// The cell recorded at the pixel position `row`, `col`
+    fn cell_at(&self, row: usize, col: usize) -> Option<Cell> {
+        let (width, height) = self.glyph_size();
+        let (row, col) = (row / height, col / width);
+        if col >= self.cells.columns() {
+            return None;
+        }
+        self.cells
+            .cells()
+            .get(row * self.cells.columns() + col)
+            .copied()
+    }
+
+    /// Draws `cell` at the pixel position `row`, `col`. If it is `cleared`, only the
+    /// background color of `cell` is there.
+    unsafe fn draw_cell(&mut self, row: usize, col: usize, cell: Cell, cleared: bool) {
+        let (info, font) = match (self.info, self.font) {
+            (Some(info), Some(font)) => (info, font),
+            _ => return,
+        };
+        let stride = info.stride;
+        if self.rotation != Rotation::Rotate0 {
+            let (x, y) = self.rotation.to_physical(col, row, info.width, info.height);
+            let (dx, dy) = self.rotation.steps(stride);
+            let dst = self.pixels().add(y * stride + x);
+            blit::draw_glyph_rotated(dst, dx, dy, &font, cell, cleared);
+            return;
+        }
+        let dst = self.pixels().add(row * stride + col);
+        if !self.colors.matches(cell) {
+            self.colors = ColorTable::new(cell.foreground, cell.background);
+        }
+        match &mut self.glyph_cache {
+            Some(cache) => cache.draw(dst, stride, &font, cell, &self.colors, cleared),
+            None => blit::draw_glyph(dst, stride, &font, cell, &self.colors, cleared),
         }
     }
 }
 
+/// # Lock Console
+/// Locks the framebuffer guard, see `exception_safe_lock()`. What exception handlers printed
+/// while it was held is written first.
+///
+/// ## Returns
+/// - None = The calling CPU runs an exception handler and the console is held
+pub fn lock_console() -> Option<MutexGuard<'static, MaybeUninit<FramebufferGuard>>> {
+    let mut guard = exception_safe_lock(&FRAMEBUFFER_GUARD)?;
+    klog::take_overflow(|text| {
+        let console = unsafe { guard.assume_init_mut() };
+        // In serial-only mode the console is COM1, which has seen it already
+        if !console.is_serial_only() {
+            console.write_console(text);
+        }
+        klog::write(text);
+    });
+    Some(guard)
+}
+
+/// # Write Console Fmt
+/// Used by `kprint!` and `kprintln!` once the early log is finished. An exception handler that
+/// finds the console held writes to COM1 directly, and keeps the text for the console until
+/// it is free again.
+pub fn write_console_fmt(args: core::fmt::Arguments) {
+    match lock_console() {
+        Some(mut guard) => {
+            let _ = unsafe { guard.assume_init_mut() }.write_fmt(args);
+        }
+        None => {
+            let _ = HeldConsole.write_fmt(args);
+        }
+    }
+}
+
+/// # Write Serial Console
+/// Writes `s` to COM1, the console in serial-only mode. Exception handlers write without its
+/// lock if it is held.
+fn write_serial_console(s: &str) {
+    match exception_safe_lock(&SERIAL) {
+        Some(mut serial) => {
+            let _ = serial.write_str(s);
+        }
+        None => serial::write_raw(SerialPort::Com1, s),
+    }
+}
+
+/// # Held Console
+/// Where exception handlers print to while the console is held, see `write_console_fmt()`
+struct HeldConsole;
+
+impl Write for HeldConsole {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        serial::write_raw(SerialPort::Com1, s);
+        klog::push_overflow(s);
+        Ok(())
+    }
+}
+
 pub fn clear_screen<T>(color: T)
 where
     T: Into<u32>,
@@ -256,19 +901,69 @@ where
     }
 }
 
+/// # Enable Backing Store
+/// Starts keeping the scrollback, see `FramebufferGuard::enable_backing_store()`
+pub fn enable_backing_store() {
+    unsafe {
+        FRAMEBUFFER_GUARD
+            .lock()
+            .assume_init_mut()
+            .enable_backing_store();
+    }
+}
+
+/// # Scroll Pages
+/// Scrolls the view back by `pages` screens, or forward if negative. A screen keeps one row
+/// of the last one in view.
+pub fn scroll_pages(pages: isize) {
+    let mut guard = FRAMEBUFFER_GUARD.lock();
+    let guard = unsafe { guard.assume_init_mut() };
+    let page = guard.geometry().1.saturating_sub(1).max(1) as isize;
+    guard.scroll_view(pages * page);
+}
+
+/// # Flush
+/// Writes what exception handlers printed while the console was held and scrolls the view back
+/// to the screen, so the last messages are visible once the machine stops
+pub fn flush() {
+    if let Some(mut guard) = lock_console() {
+        let console = unsafe { guard.assume_init_mut() };
+        console.scroll_view(-(console.view() as isize));
+    }
+}
+
+crate::initcall! {
+    name: "framebuffer-flush",
+    stage: EarlyMemory,
+    deps: ["power-notifiers", "klog-flush"],
+    fatal: false,
+    init: init_flush,
+}
+
+/// Flushes the console before the machine goes down, after the log
+fn init_flush() -> crate::error::Result<()> {
+    notifier::register("framebuffer", notifier::PRIORITY_FLUSH, |_: PowerAction| {
+        flush()
+    });
+    Ok(())
+}
+
 impl Write for FramebufferGuard {
     fn write_char(&mut self, c: char) -> core::fmt::Result {
-        unsafe {
-            self.draw_char(c);
-        };
+        if self.is_serial_only() {
+            write_serial_console(c.encode_utf8(&mut [0; 4]));
+        } else {
+            unsafe {
+                self.draw_char(c);
+            };
+        }
+        crate::klog::write(c.encode_utf8(&mut [0; 4]));
         Ok(())
     }
 
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        unsafe {
-            self.print(s);
-        };
-
+        self.write_console(s);
+        crate::klog::write(s);
         Ok(())
     }
 }
@@ -279,26 +974,29 @@ impl Write for FramebufferGuard {
 #[macro_export]
 macro_rules! kprintln {
     () => ({
-        use crate::framebuffer::FRAMEBUFFER_GUARD;
-        use core::fmt::Write;
-
-        unsafe { FRAMEBUFFER_GUARD.lock().assume_init_mut().write_str("\n").unwrap(); };
+        if crate::earlylog::is_active() {
+            crate::earlylog::write("\n");
+        } else {
+            crate::framebuffer::write_console_fmt(format_args!("\n"));
+        }
     });
     ($($arg:tt)*) => ({
-        use crate::framebuffer::FRAMEBUFFER_GUARD;
-        use core::fmt::Write;
-
-        unsafe { FRAMEBUFFER_GUARD.lock().assume_init_mut().write_fmt(format_args_nl!($($arg)*)).unwrap(); }
+        if crate::earlylog::is_active() {
+            crate::earlylog::write_fmt(format_args_nl!($($arg)*));
+        } else {
+            crate::framebuffer::write_console_fmt(format_args_nl!($($arg)*));
+        }
     })
 }
 
 #[macro_export]
 macro_rules! kprint {
     ($($arg:tt)*) => ({
-        use crate::framebuffer::FRAMEBUFFER_GUARD;
-        use core::fmt::Write;
-
-        unsafe { FRAMEBUFFER_GUARD.lock().assume_init_mut().write_fmt(format_args!($($arg)*)).unwrap(); };
+        if crate::earlylog::is_active() {
+            crate::earlylog::write_fmt(format_args!($($arg)*));
+        } else {
+            crate::framebuffer::write_console_fmt(format_args!($($arg)*));
+        }
     })
 }
 
@@ -383,21 +1081,34 @@ macro_rules! debug {
     }};
 }
 
+/// Release builds only log debug messages while the early log is verbose
 #[cfg(not(debug_assertions))]
 #[macro_export]
 macro_rules! debug {
-    ($($arg:tt)*) => {{}};
+    ($($arg:tt)*) => {{
+        if crate::earlylog::is_verbose() {
+            use crate::{kprint, kprintln};
+            use crate::kscopedcolorchange;
+            use crate::framebuffer::Color;
+            kscopedcolorchange!(bg: Color::Cyan, fg: Color::Yellow => {
+                kprint!("[ DEBUG ][{}:{}:{}] -> ", file!(), line!(), column!());
+            });
+            kscopedcolorchange!(bg: Color::Cyan, fg: Color::White => {
+                kprintln!($($arg)*);
+            })
+        }
+    }};
 }
 
 #[macro_export]
 macro_rules! kcolorchange {
     (bg: $bg:expr, fg: $fg:expr) => {{
-        use crate::framebuffer::FRAMEBUFFER_GUARD;
-        unsafe {
-            FRAMEBUFFER_GUARD
-                .lock()
-                .assume_init_mut()
-                .set_color($bg, $fg);
+        // The early log has no colors, neither has a console held by the code an exception
+        // interrupted
+        if !crate::earlylog::is_active() {
+            if let Some(mut guard) = crate::framebuffer::lock_console() {
+                unsafe { guard.assume_init_mut().set_color($bg, $fg) };
+            }
         }
     }};
 }
@@ -405,15 +1116,22 @@ macro_rules! kcolorchange {
 #[macro_export]
 macro_rules! kscopedcolorchange {
     (bg: $bg:expr, fg: $fg:expr => $blck:block) => {{
-        use crate::framebuffer::FRAMEBUFFER_GUARD;
         use crate::kcolorchange;
-        let old = unsafe {
-            FRAMEBUFFER_GUARD.lock().assume_init_mut().get_color()
+        let old = if crate::earlylog::is_active() {
+            None
+        } else {
+            crate::framebuffer::lock_console()
+                .map(|mut guard| unsafe { guard.assume_init_mut().get_color() })
         };
-        kcolorchange!(bg: $bg, fg: $fg);
-        {
-            $blck
+        match old {
+            Some(old) => {
+                kcolorchange!(bg: $bg, fg: $fg);
+                {
+                    $blck
+                }
+                kcolorchange!(bg: old.0, fg: old.1);
+            }
+            None => $blck,
         }
-        kcolorchange!(bg: old.0, fg: old.1);
     }};
 }
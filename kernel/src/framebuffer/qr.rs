@@ -0,0 +1,474 @@
+//! # QR
+//! A QR code encoder for short binary payloads like the crash record of the panic screen.
+//!
+//! Only byte mode and error correction level L are supported, in versions 1 to 10, i.e. up to
+//! `MAX_PAYLOAD` bytes. The smallest version that fits is chosen. Nothing is allocated, so
+//! codes can be built while the heap is unusable.
+use crate::error::{Error, Result};
+
+pub const MAX_VERSION: usize = 10;
+/// The number of modules along a side of the largest code
+pub const MAX_SIZE: usize = 17 + 4 * MAX_VERSION;
+/// The number of bytes the largest code holds
+pub const MAX_PAYLOAD: usize = 271;
+/// The number of modules around the code that have to stay light
+pub const QUIET_ZONE: usize = 4;
+
+const MAX_CODEWORDS: usize = 346;
+const MAX_BLOCKS: usize = 4;
+const MAX_EC_PER_BLOCK: usize = 30;
+/// Level L in the format information
+const EC_LEVEL_L: u32 = 0b01;
+const MODE_BYTE: u32 = 0b0100;
+
+/// # Version Layout
+/// Per version: The number of codewords, the error correction codewords per block and the
+/// number of blocks
+const LAYOUTS: [(usize, usize, usize); MAX_VERSION] = [
+    (26, 7, 1),
+    (44, 10, 1),
+    (70, 15, 1),
+    (100, 20, 1),
+    (134, 26, 1),
+    (172, 18, 2),
+    (196, 20, 2),
+    (242, 24, 2),
+    (292, 30, 2),
+    (346, 18, 4),
+];
+
+/// The centers of the alignment patterns per version, on both axes
+const ALIGNMENT: [&[usize]; MAX_VERSION] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+    &[6, 28, 50],
+];
+
+/// # QR Code
+/// A QR code of `size`x`size` modules, dark modules are `true`
+pub struct QrCode {
+    version: usize,
+    size: usize,
+    modules: [[bool; MAX_SIZE]; MAX_SIZE],
+    /// The modules of the finder, timing, alignment and format patterns, which are not masked
+    function: [[bool; MAX_SIZE]; MAX_SIZE],
+    codewords: [u8; MAX_CODEWORDS],
+}
+
+impl QrCode {
+    pub const fn new() -> Self {
+        Self {
+            version: 0,
+            size: 0,
+            modules: [[false; MAX_SIZE]; MAX_SIZE],
+            function: [[false; MAX_SIZE]; MAX_SIZE],
+            codewords: [0; MAX_CODEWORDS],
+        }
+    }
+
+    /// # Encode
+    /// Replaces the code with one holding `payload`
+    ///
+    /// ## Returns
+    /// - Error::Overflow = `payload` is longer than `MAX_PAYLOAD`
+    pub fn encode(&mut self, payload: &[u8]) -> Result<()> {
+        let version = match (1..=MAX_VERSION).find(|&version| capacity(version) >= payload.len()) {
+            Some(version) => version,
+            None => return Err(Error::Overflow),
+        };
+        self.version = version;
+        self.size = 17 + 4 * version;
+        self.modules = [[false; MAX_SIZE]; MAX_SIZE];
+        self.function = [[false; MAX_SIZE]; MAX_SIZE];
+
+        self.draw_function_patterns();
+        let data_len = self.write_data(payload);
+        self.add_error_correction(data_len);
+        self.draw_codewords();
+
+        let mut best = (u32::MAX, 0);
+        for mask in 0..8 {
+            self.apply_mask(mask);
+            self.draw_format(mask);
+            let penalty = self.penalty();
+            if penalty < best.0 {
+                best = (penalty, mask);
+            }
+            // Masking twice undoes it
+            self.apply_mask(mask);
+        }
+        self.apply_mask(best.1);
+        self.draw_format(best.1);
+        Ok(())
+    }
+
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// # Is Dark
+    /// Whether the module in column `x` of row `y` is dark, modules outside are light
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y][x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y][x] = dark;
+        self.function[y][x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            // The finder pattern and the light separator around it
+            for dy in -4isize..=4 {
+                for dx in -4isize..=4 {
+                    let (mx, my) = (x as isize + dx, y as isize + dy);
+                    if mx < 0 || my < 0 || mx >= size as isize || my >= size as isize {
+                        continue;
+                    }
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(mx as usize, my as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+
+        let centers = ALIGNMENT[self.version - 1];
+        let last = centers.len().saturating_sub(1);
+        for (i, &x) in centers.iter().enumerate() {
+            for (j, &y) in centers.iter().enumerate() {
+                // These overlap the finder patterns
+                if (i == 0 && j == 0) || (i == 0 && j == last) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in -2isize..=2 {
+                    for dx in -2isize..=2 {
+                        let distance = dx.abs().max(dy.abs());
+                        let (mx, my) = ((x as isize + dx) as usize, (y as isize + dy) as usize);
+                        self.set_function(mx, my, distance != 1);
+                    }
+                }
+            }
+        }
+
+        // Reserves the format information, it is drawn once the mask is known
+        self.draw_format(0);
+
+        if self.version >= 7 {
+            let mut remainder = self.version as u32;
+            for _ in 0..12 {
+                remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+            }
+            let bits = (self.version as u32) << 12 | remainder;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_format(&mut self, mask: u32) {
+        let data = EC_LEVEL_L << 3 | mask;
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+
+        // Around the top left finder pattern
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        // Split between the other two
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Writes the mode, length, `payload` and padding into the codewords
+    ///
+    /// ## Returns
+    /// - usize = The number of data codewords
+    fn write_data(&mut self, payload: &[u8]) -> usize {
+        let (total, ec_per_block, blocks) = LAYOUTS[self.version - 1];
+        let data_len = total - ec_per_block * blocks;
+        self.codewords = [0; MAX_CODEWORDS];
+
+        let mut bits = BitWriter {
+            buffer: &mut self.codewords[..data_len],
+            position: 0,
+        };
+        bits.write(MODE_BYTE, 4);
+        let count_bits = if self.version < 10 { 8 } else { 16 };
+        bits.write(payload.len() as u32, count_bits);
+        for &byte in payload {
+            bits.write(byte as u32, 8);
+        }
+        // The terminator, as far as there is room, then up to the next byte
+        let terminator = (data_len * 8 - bits.position).min(4);
+        bits.write(0, terminator);
+        bits.position = (bits.position + 7) / 8 * 8;
+        for pad in [0xEC, 0x11].iter().cycle() {
+            if bits.position >= data_len * 8 {
+                break;
+            }
+            bits.write(*pad, 8);
+        }
+        data_len
+    }
+
+    /// Splits the `data_len` data codewords into blocks, appends their error correction
+    /// codewords and interleaves them
+    fn add_error_correction(&mut self, data_len: usize) {
+        let (total, ec_per_block, blocks) = LAYOUTS[self.version - 1];
+        // The last blocks hold one data codeword more
+        let short_blocks = blocks - total % blocks;
+        let short_len = total / blocks - ec_per_block;
+
+        let mut divisor = [0u8; MAX_EC_PER_BLOCK];
+        reed_solomon_divisor(&mut divisor[..ec_per_block]);
+
+        let mut data = [0u8; MAX_CODEWORDS];
+        data[..data_len].copy_from_slice(&self.codewords[..data_len]);
+        let mut ecc = [[0u8; MAX_EC_PER_BLOCK]; MAX_BLOCKS];
+        let mut starts = [0usize; MAX_BLOCKS + 1];
+        for block in 0..blocks {
+            let len = short_len + (block >= short_blocks) as usize;
+            starts[block + 1] = starts[block] + len;
+            reed_solomon_remainder(
+                &data[starts[block]..starts[block + 1]],
+                &divisor[..ec_per_block],
+                &mut ecc[block][..ec_per_block],
+            );
+        }
+
+        let mut out = 0;
+        for i in 0..=short_len {
+            for block in 0..blocks {
+                if i < starts[block + 1] - starts[block] {
+                    self.codewords[out] = data[starts[block] + i];
+                    out += 1;
+                }
+            }
+        }
+        for i in 0..ec_per_block {
+            for block in ecc.iter().take(blocks) {
+                self.codewords[out] = block[i];
+                out += 1;
+            }
+        }
+    }
+
+    /// Places the codewords in the zigzag order, two columns at a time from the bottom right
+    fn draw_codewords(&mut self) {
+        let size = self.size;
+        let bit_count = LAYOUTS[self.version - 1].0 * 8;
+        let mut bit = 0;
+        let mut right = size as isize - 1;
+        while right >= 1 {
+            // The vertical timing pattern is skipped
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward {
+                    size - 1 - vertical
+                } else {
+                    vertical
+                };
+                for j in 0..2 {
+                    let x = right as usize - j;
+                    if self.function[y][x] {
+                        continue;
+                    }
+                    // The remainder bits are light
+                    if bit < bit_count {
+                        self.modules[y][x] = (self.codewords[bit / 8] >> (7 - bit % 8)) & 1 != 0;
+                        bit += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if invert && !self.function[y][x] {
+                    self.modules[y][x] = !self.modules[y][x];
+                }
+            }
+        }
+    }
+
+    /// The penalty score of the masked code, the mask with the lowest score is used
+    fn penalty(&self) -> u32 {
+        let size = self.size;
+        let mut penalty = 0;
+        for line in 0..size {
+            penalty += self.line_penalty(|i| self.modules[line][i]);
+            penalty += self.line_penalty(|i| self.modules[i][line]);
+        }
+
+        let mut dark = 0;
+        for y in 0..size {
+            for x in 0..size {
+                let color = self.modules[y][x];
+                dark += color as i32;
+                if x + 1 < size
+                    && y + 1 < size
+                    && color == self.modules[y][x + 1]
+                    && color == self.modules[y + 1][x]
+                    && color == self.modules[y + 1][x + 1]
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // Every 5% away from an even balance of dark and light
+        let total = (size * size) as i32;
+        let k = (((dark * 20 - total * 10).abs() + total - 1) / total - 1).max(0);
+        penalty + k as u32 * 10
+    }
+
+    /// The penalty of runs of a color and of patterns that look like finder patterns in a row
+    /// or column, `module(i)` is its `i`th module
+    fn line_penalty(&self, module: impl Fn(usize) -> bool) -> u32 {
+        const FINDER_LIKE: [bool; 11] = [
+            false, false, false, false, true, false, true, true, true, false, true,
+        ];
+        let size = self.size as isize;
+        let at = |i: isize| i >= 0 && i < size && module(i as usize);
+        let mut penalty = 0;
+
+        let mut run = 1;
+        for i in 1..=size {
+            if i < size && at(i) == at(i - 1) {
+                run += 1;
+                continue;
+            }
+            if run >= 5 {
+                penalty += 3 + (run - 5);
+            }
+            run = 1;
+        }
+
+        // The light modules beyond the code count as well
+        for start in -4..size - 6 {
+            let forward = (0..11).all(|i| at(start + i) == FINDER_LIKE[i as usize]);
+            let backward = (0..11).all(|i| at(start + i) == FINDER_LIKE[10 - i as usize]);
+            penalty += 40 * (forward as u32 + backward as u32);
+        }
+        penalty
+    }
+}
+
+/// # Capacity
+/// The number of payload bytes `version` holds
+pub fn capacity(version: usize) -> usize {
+    let (total, ec_per_block, blocks) = LAYOUTS[version - 1];
+    let count_bits = if version < 10 { 8 } else { 16 };
+    ((total - ec_per_block * blocks) * 8 - 4 - count_bits) / 8
+}
+
+struct BitWriter<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+}
+
+impl BitWriter<'_> {
+    /// Appends the lowest `count` bits of `value`, most significant first
+    fn write(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            if (value >> i) & 1 != 0 {
+                self.buffer[self.position / 8] |= 0x80 >> (self.position % 8);
+            }
+            self.position += 1;
+        }
+    }
+}
+
+/// Multiplies in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut product: u8 = 0;
+    for i in (0..8).rev() {
+        product = (product << 1) ^ ((product >> 7) * 0x1D);
+        product ^= ((y >> i) & 1) * x;
+    }
+    product
+}
+
+/// The generator polynomial of degree `divisor.len()`, without its leading coefficient
+fn reed_solomon_divisor(divisor: &mut [u8]) {
+    let degree = divisor.len();
+    divisor.fill(0);
+    divisor[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_multiply(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+}
+
+/// The error correction codewords of `data`
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8], remainder: &mut [u8]) {
+    remainder.fill(0);
+    let last = remainder.len() - 1;
+    for &byte in data {
+        let factor = byte ^ remainder[0];
+        remainder.copy_within(1.., 0);
+        remainder[last] = 0;
+        for (coefficient, slot) in divisor.iter().zip(remainder.iter_mut()) {
+            *slot ^= gf_multiply(*coefficient, factor);
+        }
+    }
+}
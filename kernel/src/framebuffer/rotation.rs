@@ -0,0 +1,79 @@
+//! # Rotation
+//! Panels that are mounted rotated, set with `fbrotate=90|180|270` on the command line. The
+//! console works in logical coordinates, with (0, 0) at the top left of the panel as it is
+//! looked at. Only the framebuffer guard maps them to pixels, by turning the picture clockwise
+//! by the rotation.
+
+/// The command line option setting the rotation, in degrees
+pub const OPTION: &str = "fbrotate";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    /// # From Degrees
+    /// The rotation by `degrees`, which has to be a multiple of 90 below 360
+    pub fn from_degrees(degrees: u32) -> Option<Self> {
+        match degrees {
+            0 => Some(Rotation::Rotate0),
+            90 => Some(Rotation::Rotate90),
+            180 => Some(Rotation::Rotate180),
+            270 => Some(Rotation::Rotate270),
+            _ => None,
+        }
+    }
+
+    pub fn degrees(&self) -> u32 {
+        match self {
+            Rotation::Rotate0 => 0,
+            Rotation::Rotate90 => 90,
+            Rotation::Rotate180 => 180,
+            Rotation::Rotate270 => 270,
+        }
+    }
+
+    /// Whether the logical width is the height of the framebuffer
+    pub fn is_swapped(&self) -> bool {
+        matches!(self, Rotation::Rotate90 | Rotation::Rotate270)
+    }
+
+    /// # Logical Size
+    /// The width and height of a framebuffer of `width`x`height` pixels in logical coordinates
+    pub fn logical_size(&self, width: usize, height: usize) -> (usize, usize) {
+        if self.is_swapped() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// # To Physical
+    /// The pixel of a framebuffer of `width`x`height` pixels at the logical `x`, `y`, which
+    /// has to be within the logical size
+    pub fn to_physical(&self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::Rotate0 => (x, y),
+            Rotation::Rotate90 => (width - 1 - y, x),
+            Rotation::Rotate180 => (width - 1 - x, height - 1 - y),
+            Rotation::Rotate270 => (y, height - 1 - x),
+        }
+    }
+
+    /// # Steps
+    /// How far the index of a pixel moves for a step to the right and a step down in logical
+    /// coordinates, with `stride` pixels per scanline
+    pub fn steps(&self, stride: usize) -> (isize, isize) {
+        let stride = stride as isize;
+        match self {
+            Rotation::Rotate0 => (1, stride),
+            Rotation::Rotate90 => (stride, -1),
+            Rotation::Rotate180 => (-1, -stride),
+            Rotation::Rotate270 => (-stride, 1),
+        }
+    }
+}
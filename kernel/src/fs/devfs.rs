@@ -0,0 +1,231 @@
+//! # Devfs
+//! The device nodes in `/dev`. Drivers register a character device under a name, and every
+//! open of `/dev/<name>` hands out that same device. There are no subdirectories. Inodes are
+//! numbered in the order the devices were registered, the directory itself is inode 0.
+//!
+//! `null`, `zero` and `console` are registered along with the mount.
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{DirEntry, File, FileSystem, FileType, Metadata};
+use crate::error::{Error, Result};
+use crate::memory::vmm::Backing;
+use crate::scheduler::spin::Mutex;
+use crate::tty;
+
+/// Where devfs is mounted
+pub const DEV_MOUNT_POINT: &str = "/dev";
+
+static DEVICES: Mutex<BTreeMap<String, Arc<DeviceNode>>> = Mutex::new(BTreeMap::new());
+static NEXT_INODE: AtomicU64 = AtomicU64::new(1);
+
+/// # Char Device
+/// The metadata of a character device that is `size` bytes large, devfs fills in the inode
+pub fn char_device(size: u64) -> Metadata {
+    Metadata {
+        ty: FileType::CharacterDevice,
+        size,
+        inode: 0,
+        mtime: None,
+    }
+}
+
+/// # Register
+/// Adds `device` as `/dev/<name>`
+///
+/// ## Returns
+/// - Error::InvalidArgument = `name` is empty or has a slash
+/// - Error::AlreadyExists = There is a device with that name
+pub fn register(name: &str, device: Arc<dyn File>) -> Result<()> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(Error::InvalidArgument);
+    }
+    let mut devices = DEVICES.lock();
+    if devices.contains_key(name) {
+        return Err(Error::AlreadyExists);
+    }
+    let inode = NEXT_INODE.fetch_add(1, Ordering::Relaxed);
+    devices.insert(String::from(name), Arc::new(DeviceNode { inode, device }));
+    Ok(())
+}
+
+/// # Unregister
+/// Removes `/dev/<name>`, descriptors open on it keep the device
+///
+/// ## Returns
+/// - Error::NoSuchFileOrDirectory = There is no device with that name
+pub fn unregister(name: &str) -> Result<()> {
+    DEVICES
+        .lock()
+        .remove(name)
+        .map(|_| ())
+        .ok_or(Error::NoSuchFileOrDirectory)
+}
+
+/// # Device Node
+/// A registered device with its inode
+struct DeviceNode {
+    inode: u64,
+    device: Arc<dyn File>,
+}
+
+impl File for DeviceNode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            inode: self.inode,
+            ..self.device.metadata()
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.device.read_at(offset, buf)
+    }
+
+    fn read_dir(&self, _offset: u64) -> Result<Vec<DirEntry>> {
+        Err(Error::NotADirectory)
+    }
+
+    fn ioctl(&self, request: u64, arg: u64) -> Result<i32> {
+        self.device.ioctl(request, arg)
+    }
+
+    fn mmap(&self, offset: u64) -> Result<Backing> {
+        self.device.mmap(offset)
+    }
+}
+
+/// # Root
+/// `/dev` itself
+struct Root;
+
+impl File for Root {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ty: FileType::Directory,
+            size: 0,
+            inode: 0,
+            mtime: None,
+        }
+    }
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize> {
+        Err(Error::IsADirectory)
+    }
+
+    fn read_dir(&self, offset: u64) -> Result<Vec<DirEntry>> {
+        Ok(DEVICES
+            .lock()
+            .iter()
+            .skip(offset as usize)
+            .map(|(name, node)| DirEntry {
+                name: name.clone(),
+                metadata: node.metadata(),
+            })
+            .collect())
+    }
+}
+
+/// # Dev FS
+pub struct DevFs;
+
+impl DevFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self)
+    }
+}
+
+impl FileSystem for DevFs {
+    fn open(&self, components: &[&str]) -> Result<Arc<dyn File>> {
+        match components {
+            [] => Ok(Arc::new(Root)),
+            [name] => DEVICES
+                .lock()
+                .get(*name)
+                .map(|node| node.clone() as Arc<dyn File>)
+                .ok_or(Error::NoSuchFileOrDirectory),
+            [name, ..] if DEVICES.lock().contains_key(*name) => Err(Error::NotADirectory),
+            _ => Err(Error::NoSuchFileOrDirectory),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+}
+
+/// # Null
+/// `/dev/null`, which is always at its end
+pub struct Null;
+
+impl File for Null {
+    fn metadata(&self) -> Metadata {
+        char_device(0)
+    }
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn read_dir(&self, _offset: u64) -> Result<Vec<DirEntry>> {
+        Err(Error::NotADirectory)
+    }
+}
+
+/// # Zero
+/// `/dev/zero`, which reads as endless zeros
+pub struct Zero;
+
+impl File for Zero {
+    fn metadata(&self) -> Metadata {
+        char_device(0)
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn read_dir(&self, _offset: u64) -> Result<Vec<DirEntry>> {
+        Err(Error::NotADirectory)
+    }
+}
+
+/// # Console
+/// `/dev/console`, the same `tty` as the standard streams
+pub struct Console;
+
+impl File for Console {
+    fn metadata(&self) -> Metadata {
+        char_device(0)
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize> {
+        tty::read(buf, false)
+    }
+
+    fn read_dir(&self, _offset: u64) -> Result<Vec<DirEntry>> {
+        Err(Error::NotADirectory)
+    }
+
+    fn ioctl(&self, request: u64, arg: u64) -> Result<i32> {
+        tty::ioctl(request, arg)
+    }
+}
+
+crate::initcall! {
+    name: "devfs",
+    stage: Late,
+    deps: [],
+    fatal: false,
+    init: init_devfs,
+}
+
+/// # Init Devfs
+/// Registers the devices every system has and mounts devfs at `DEV_MOUNT_POINT`
+pub fn init_devfs() -> Result<()> {
+    let _tag = crate::alloc_tag!("vfs");
+    register("null", Arc::new(Null))?;
+    register("zero", Arc::new(Zero))?;
+    register("console", Arc::new(Console))?;
+    super::mount(DEV_MOUNT_POINT, DevFs::new())
+}
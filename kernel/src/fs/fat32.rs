@@ -0,0 +1,539 @@
+//! # FAT32
+//! A read-only FAT32 driver on top of a `BlockDevice`.
+//!
+//! Cluster chains are validated while they are walked: Chains that leave the data area, hit a bad
+//! cluster or loop back onto themselves make the access fail with an I/O error.
+//!
+//! The FAT and the clusters are read through the page cache, the boot sector is not. The chains
+//! walked last are kept as well, so reading a file does not walk its chain every time.
+use alloc::{collections::BTreeSet, string::String, sync::Arc, vec, vec::Vec};
+
+use super::{DirEntry, File, FileSystem, FileType, Metadata};
+use crate::block::{cache, BlockDevice};
+use crate::error::{Error, Result};
+use crate::scheduler::spin::Mutex;
+use crate::time::DateTime;
+
+crate::counter!(pub CHAIN_HITS = "fat32.chain_hits");
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const FAT_BAD_CLUSTER: u32 = 0x0FFF_FFF7;
+/// Every value from here on marks the end of a chain
+const FAT_END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+/// Clusters 0 and 1 do not exist, the data area starts with cluster 2
+const FIRST_CLUSTER: u32 = 2;
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+/// Stands in for a leading 0xE5, which would mark the entry as deleted
+const ENTRY_KANJI_E5: u8 = 0x05;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const ATTR_LONG_NAME_MASK: u8 = 0x3F;
+const LFN_LAST_ENTRY: u8 = 0x40;
+const LFN_SEQUENCE_MASK: u8 = 0x1F;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+/// Where the UCS-2 characters of a long file name entry are stored
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// Flags set by Windows NT in short entries whose base name or extension are all lowercase
+const NT_LOWERCASE_BASE: u8 = 0x08;
+const NT_LOWERCASE_EXT: u8 = 0x10;
+/// The number of cluster chains a volume keeps
+const CHAIN_CACHE_SIZE: usize = 8;
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// # Volume
+/// The parsed BIOS parameter block together with the device
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    bytes_per_sector: usize,
+    sectors_per_cluster: usize,
+    fat_start: u64,
+    data_start: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    /// The chains walked last by their first cluster, the most recent first. The volume is
+    /// read-only, so they never go stale.
+    chains: Mutex<Vec<(u32, Arc<[u32]>)>>,
+}
+
+impl Volume {
+    fn new(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        let mut boot = vec![0u8; device.block_size()];
+        device.read_blocks(0, &mut boot)?;
+        if boot.len() < 512 || boot[510..512] != BOOT_SIGNATURE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let bytes_per_sector = u16_at(&boot, 11) as usize;
+        let sectors_per_cluster = boot[13] as usize;
+        let reserved_sectors = u16_at(&boot, 14) as u64;
+        let fat_count = boot[16] as u64;
+        let root_entries = u16_at(&boot, 17);
+        let total_sectors = match u16_at(&boot, 19) {
+            0 => u32_at(&boot, 32) as u64,
+            total => total as u64,
+        };
+        let sectors_per_fat_16 = u16_at(&boot, 22);
+        let sectors_per_fat = u32_at(&boot, 36) as u64;
+        let root_cluster = u32_at(&boot, 44);
+
+        // FAT12 and FAT16 have a fixed root directory and a 16 bit FAT size
+        let is_fat32 = root_entries == 0 && sectors_per_fat_16 == 0 && sectors_per_fat != 0;
+        if !is_fat32
+            || bytes_per_sector != device.block_size()
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let data_start = reserved_sectors + fat_count * sectors_per_fat;
+        if data_start >= total_sectors || total_sectors > device.block_count() {
+            return Err(Error::InvalidArgument);
+        }
+        let fat_entries = sectors_per_fat * bytes_per_sector as u64 / 4;
+        let cluster_count = ((total_sectors - data_start) / sectors_per_cluster as u64)
+            .min(fat_entries - FIRST_CLUSTER as u64) as u32;
+
+        let volume = Self {
+            device,
+            bytes_per_sector,
+            sectors_per_cluster,
+            fat_start: reserved_sectors,
+            data_start,
+            cluster_count,
+            root_cluster,
+            chains: Mutex::new(Vec::new()),
+        };
+        volume.check_cluster(root_cluster)?;
+        Ok(volume)
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    fn check_cluster(&self, cluster: u32) -> Result<()> {
+        if cluster < FIRST_CLUSTER || cluster - FIRST_CLUSTER >= self.cluster_count {
+            return Err(Error::IOError);
+        }
+        Ok(())
+    }
+
+    /// # FAT Entry
+    /// Looks up the FAT entry of `cluster`, going through the page cache
+    fn fat_entry(&self, cluster: u32) -> Result<u32> {
+        let offset = self.fat_start * self.bytes_per_sector as u64 + cluster as u64 * 4;
+        let mut entry = [0u8; 4];
+        cache::read(&self.device, offset, &mut entry)?;
+        Ok(u32::from_le_bytes(entry) & FAT_ENTRY_MASK)
+    }
+
+    /// # Chain
+    /// All clusters of the chain starting at `start`, walked only if it is not cached
+    fn chain(&self, start: u32) -> Result<Arc<[u32]>> {
+        {
+            let mut chains = self.chains.lock();
+            if let Some(idx) = chains.iter().position(|(first, _)| *first == start) {
+                CHAIN_HITS.increment();
+                let entry = chains.remove(idx);
+                let chain = entry.1.clone();
+                chains.insert(0, entry);
+                return Ok(chain);
+            }
+        }
+        // Not under the lock, the FAT may have to be read from the disk
+        let chain: Arc<[u32]> = self.walk_chain(start)?.into();
+        let mut chains = self.chains.lock();
+        chains.retain(|(first, _)| *first != start);
+        chains.insert(0, (start, chain.clone()));
+        chains.truncate(CHAIN_CACHE_SIZE);
+        Ok(chain)
+    }
+
+    /// # Walk Chain
+    /// Follows the FAT from `start` to the end of the chain
+    fn walk_chain(&self, start: u32) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut cluster = start;
+        loop {
+            self.check_cluster(cluster)?;
+            if !visited.insert(cluster) {
+                // A loop
+                return Err(Error::IOError);
+            }
+            chain.push(cluster);
+            match self.fat_entry(cluster)? {
+                FAT_BAD_CLUSTER => return Err(Error::IOError),
+                next if next >= FAT_END_OF_CHAIN => return Ok(chain),
+                next => cluster = next,
+            }
+        }
+    }
+
+    fn read_cluster(&self, cluster: u32, buf: &mut [u8]) -> Result<()> {
+        let sector =
+            self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster as u64;
+        cache::read(&self.device, sector * self.bytes_per_sector as u64, buf)
+    }
+
+    /// # Read Directory
+    /// Parses every entry of the directory starting at `cluster`
+    fn read_directory(&self, cluster: u32) -> Result<Vec<RawEntry>> {
+        let mut data = vec![0u8; self.cluster_size()];
+        let mut parser = DirectoryParser::new(cluster);
+        for &cluster in self.chain(cluster)?.iter() {
+            self.read_cluster(cluster, &mut data)?;
+            for entry in data.chunks_exact(DIR_ENTRY_SIZE) {
+                if !parser.feed(entry) {
+                    return Ok(parser.entries);
+                }
+            }
+        }
+        Ok(parser.entries)
+    }
+}
+
+/// # Raw Entry
+/// A directory entry together with its long name, if it has a valid one
+#[derive(Debug, Clone)]
+struct RawEntry {
+    name: String,
+    short_name: String,
+    attributes: u8,
+    cluster: u32,
+    size: u32,
+    /// The directory the entry is in and its position there
+    position: (u32, u32),
+    mtime: Option<i64>,
+}
+
+impl RawEntry {
+    fn metadata(&self) -> Metadata {
+        // A directory may also be opened through `..`, where its entry is not known, but it
+        // always has a cluster of its own. Empty files have none, so files go by the position
+        // of their entry, which is beyond every cluster number.
+        let (ty, size, inode) = if self.attributes & ATTR_DIRECTORY != 0 {
+            (FileType::Directory, 0, self.cluster as u64)
+        } else {
+            let (directory, index) = self.position;
+            (
+                FileType::File,
+                self.size as u64,
+                (directory as u64) << 32 | index as u64,
+            )
+        };
+        Metadata {
+            ty,
+            size,
+            inode,
+            mtime: self.mtime,
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.short_name.eq_ignore_ascii_case(name)
+    }
+}
+
+/// # Short Name Checksum
+/// The checksum long file name entries store to tie themselves to their short entry
+fn short_name_checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// # Short Name
+/// Turns the padded 8.3 name of an entry into `NAME.EXT`
+fn short_name(entry: &[u8]) -> String {
+    let nt_flags = entry[12];
+    let mut base: Vec<u8> = entry[0..8].to_vec();
+    if base[0] == ENTRY_KANJI_E5 {
+        base[0] = ENTRY_DELETED;
+    }
+    let convert = |bytes: &[u8], lowercase: bool| -> String {
+        bytes
+            .iter()
+            .rev()
+            .skip_while(|byte| **byte == b' ')
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .map(|byte| {
+                let c = char::from(*byte);
+                if lowercase {
+                    c.to_ascii_lowercase()
+                } else {
+                    c
+                }
+            })
+            .collect()
+    };
+
+    let mut name = convert(&base, nt_flags & NT_LOWERCASE_BASE != 0);
+    let extension = convert(&entry[8..11], nt_flags & NT_LOWERCASE_EXT != 0);
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(&extension);
+    }
+    name
+}
+
+/// # Timestamp
+/// The seconds since 1970 of the date and time of an entry, `None` if it has none
+fn timestamp(date: u16, time: u16) -> Option<i64> {
+    let date_time = DateTime {
+        year: 1980 + (date >> 9),
+        month: (date >> 5 & 0xF) as u8,
+        day: (date & 0x1F) as u8,
+        hour: (time >> 11) as u8,
+        minute: (time >> 5 & 0x3F) as u8,
+        second: (time & 0x1F) as u8 * 2,
+    };
+    date_time.is_valid().then(|| date_time.unix_seconds())
+}
+
+/// # Directory Parser
+/// Collects the long file name entries in front of every short entry
+struct DirectoryParser {
+    /// The first cluster of the directory
+    directory: u32,
+    /// The number of 32 byte entries fed so far
+    index: u32,
+    /// (Sequence number, checksum, characters) in the order found on disk, i.e. last part first
+    long_name: Vec<(u8, u8, [u16; LFN_CHARS_PER_ENTRY])>,
+    entries: Vec<RawEntry>,
+}
+
+impl DirectoryParser {
+    fn new(directory: u32) -> Self {
+        Self {
+            directory,
+            index: 0,
+            long_name: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// # Feed
+    /// Parses the next 32 byte entry.
+    ///
+    /// ## Returns
+    /// - bool = False once the end of the directory has been reached
+    fn feed(&mut self, entry: &[u8]) -> bool {
+        let index = self.index;
+        self.index += 1;
+        match entry[0] {
+            ENTRY_END => return false,
+            ENTRY_DELETED => {
+                self.long_name.clear();
+                return true;
+            }
+            _ => {}
+        }
+
+        let attributes = entry[11];
+        if attributes & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+            if entry[0] & LFN_LAST_ENTRY != 0 {
+                // The first entry on disk holds the last part of the name
+                self.long_name.clear();
+            }
+            let mut chars = [0u16; LFN_CHARS_PER_ENTRY];
+            for (c, offset) in chars.iter_mut().zip(LFN_CHAR_OFFSETS) {
+                *c = u16_at(entry, offset);
+            }
+            self.long_name
+                .push((entry[0] & LFN_SEQUENCE_MASK, entry[13], chars));
+            return true;
+        }
+        if attributes & ATTR_VOLUME_ID != 0 {
+            self.long_name.clear();
+            return true;
+        }
+
+        let short = short_name(entry);
+        let name = self
+            .take_long_name(short_name_checksum(&entry[0..11]))
+            .unwrap_or_else(|| short.clone());
+        if name != "." && name != ".." {
+            self.entries.push(RawEntry {
+                name,
+                short_name: short,
+                attributes,
+                cluster: (u16_at(entry, 20) as u32) << 16 | u16_at(entry, 26) as u32,
+                size: u32_at(entry, 28),
+                position: (self.directory, index),
+                mtime: timestamp(u16_at(entry, 24), u16_at(entry, 22)),
+            });
+        }
+        true
+    }
+
+    /// # Take Long Name
+    /// Assembles the collected long name, if it is complete and belongs to the short entry with
+    /// `checksum`
+    fn take_long_name(&mut self, checksum: u8) -> Option<String> {
+        let parts = core::mem::take(&mut self.long_name);
+        let complete = !parts.is_empty()
+            && parts
+                .iter()
+                .rev()
+                .enumerate()
+                .all(|(idx, (sequence, sum, _))| *sequence as usize == idx + 1 && *sum == checksum);
+        if !complete {
+            return None;
+        }
+        let units = parts
+            .iter()
+            .rev()
+            .flat_map(|(_, _, chars)| chars.iter().copied())
+            .take_while(|c| *c != 0);
+        Some(
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+        )
+    }
+}
+
+/// # Node
+/// A file or directory of a FAT32 volume
+struct Node {
+    volume: Arc<Volume>,
+    /// 0 for empty files
+    cluster: u32,
+    metadata: Metadata,
+}
+
+impl File for Node {
+    fn metadata(&self) -> Metadata {
+        self.metadata
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        if self.metadata.ty == FileType::Directory {
+            return Err(Error::IsADirectory);
+        }
+        if offset >= self.metadata.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min((self.metadata.size - offset) as usize);
+        let cluster_size = self.volume.cluster_size();
+        let chain = self.volume.chain(self.cluster)?;
+
+        let mut data = vec![0u8; cluster_size];
+        let mut done = 0;
+        while done < len {
+            let position = offset as usize + done;
+            let cluster = *chain.get(position / cluster_size).ok_or(Error::IOError)?; // The chain is shorter than the file
+            let within = position % cluster_size;
+            let count = (cluster_size - within).min(len - done);
+            self.volume.read_cluster(cluster, &mut data)?;
+            buf[done..done + count].copy_from_slice(&data[within..within + count]);
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn read_dir(&self, offset: u64) -> Result<Vec<DirEntry>> {
+        if self.metadata.ty != FileType::Directory {
+            return Err(Error::NotADirectory);
+        }
+        Ok(self
+            .volume
+            .read_directory(self.cluster)?
+            .into_iter()
+            .skip(offset as usize)
+            .map(|entry| DirEntry {
+                metadata: entry.metadata(),
+                name: entry.name,
+            })
+            .collect())
+    }
+}
+
+/// # FAT32
+/// A mounted FAT32 volume
+pub struct Fat32 {
+    volume: Arc<Volume>,
+}
+
+impl Fat32 {
+    /// # New
+    /// Reads the BIOS parameter block of `device`, failing if it does not hold a FAT32 volume
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            volume: Arc::new(Volume::new(device)?),
+        }))
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn open(&self, components: &[&str]) -> Result<Arc<dyn File>> {
+        let root = self.volume.root_cluster;
+        // The clusters of the directories walked through, to resolve `..`
+        let mut path = vec![root];
+        let mut current: Option<RawEntry> = None;
+        for component in components {
+            if let Some(entry) = &current {
+                if entry.metadata().ty != FileType::Directory {
+                    return Err(Error::NotADirectory);
+                }
+                path.push(entry.cluster);
+            }
+            if *component == ".." {
+                path.pop();
+                if path.is_empty() {
+                    path.push(root);
+                }
+                current = None;
+                continue;
+            }
+            let directory = *path.last().unwrap();
+            current = Some(
+                self.volume
+                    .read_directory(directory)?
+                    .into_iter()
+                    .find(|entry| entry.matches(component))
+                    .ok_or(Error::NoSuchFileOrDirectory)?,
+            );
+        }
+
+        let (cluster, metadata) = match current {
+            Some(entry) => (entry.cluster, entry.metadata()),
+            None => (
+                *path.last().unwrap(),
+                Metadata {
+                    ty: FileType::Directory,
+                    size: 0,
+                    inode: *path.last().unwrap() as u64,
+                    mtime: None,
+                },
+            ),
+        };
+        Ok(Arc::new(Node {
+            volume: self.volume.clone(),
+            cluster,
+            metadata,
+        }))
+    }
+
+    fn name(&self) -> &'static str {
+        "fat32"
+    }
+}
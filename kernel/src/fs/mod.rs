@@ -0,0 +1,529 @@
+//! # FS
+//! The virtual filesystem: Filesystems are mounted at a path and files are opened by absolute
+//! paths, which are resolved by the filesystem mounted at the longest matching prefix.
+//!
+//! The initramfs is mounted at `/`, the device nodes of `devfs` at `/dev`, and the FAT32
+//! partition that has a `DISK_MARKER` in its root directory at `DISK_MOUNT_POINT`.
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use crate::block;
+use crate::error::{Error, Result};
+use crate::ipc::shm::SharedMemory;
+use crate::memory::vmm::Backing;
+use crate::net::udp::UdpSocket;
+use crate::scheduler::spin::Mutex;
+use crate::tty;
+use crate::{info, warn};
+
+pub mod devfs;
+pub mod fat32;
+pub mod tarfs;
+
+/// Where the partition holding a `DISK_MARKER` is mounted
+pub const DISK_MOUNT_POINT: &str = "/disk";
+/// A file in the root directory of a FAT32 filesystem asking for it to be mounted at
+/// `DISK_MOUNT_POINT`, put there by the image builder
+pub const DISK_MARKER: &str = "ESQUE.MNT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    /// The console, or a device node of `devfs`
+    CharacterDevice,
+    Socket,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub ty: FileType,
+    pub size: u64,
+    /// Tells the file apart from every other one of its filesystem, whichever path it is
+    /// opened by
+    pub inode: u64,
+    /// The seconds since 1970-01-01 00:00:00 UTC of the last change, if the filesystem keeps it
+    pub mtime: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// # File
+/// A file or directory of a mounted filesystem
+pub trait File: Send + Sync {
+    fn metadata(&self) -> Metadata;
+    /// Reads from `offset` into `buf`, returning the number of bytes read (0 at the end)
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize>;
+    /// Lists a directory, starting with its entry number `offset`
+    fn read_dir(&self, offset: u64) -> Result<Vec<DirEntry>>;
+
+    /// # Ioctl
+    /// Handles `ioctl(fd, request, arg)` on a descriptor of the file
+    ///
+    /// ## Returns
+    /// - Error::NotTTY = The file takes no requests, which is the default
+    fn ioctl(&self, _request: u64, _arg: u64) -> Result<i32> {
+        Err(Error::NotTTY)
+    }
+
+    /// # Mmap
+    /// What a `MAP_SHARED` mapping of the file from the page aligned `offset` on maps
+    ///
+    /// ## Returns
+    /// - Error::NoSuchDevice = The file cannot be mapped, which is the default
+    fn mmap(&self, _offset: u64) -> Result<Backing> {
+        Err(Error::NoSuchDevice)
+    }
+}
+
+/// # File System
+pub trait FileSystem: Send + Sync {
+    /// # Open
+    /// Opens `path`, which is relative to the mount point and split into its components
+    fn open(&self, components: &[&str]) -> Result<Arc<dyn File>>;
+    fn name(&self) -> &'static str;
+}
+
+/// # Mount
+/// A filesystem in the mount table. Every descriptor open on the filesystem holds a reference
+/// to its mount, which keeps it from being unmounted.
+pub struct Mount {
+    /// The absolute path of the mount point, without `.` or `..`
+    pub path: String,
+    pub fs: Arc<dyn FileSystem>,
+}
+
+/// # Mount Info
+/// An entry of the mount table, as `mounts()` lists it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    pub path: String,
+    pub fs: &'static str,
+    /// Descriptors open on the filesystem, and lookups going on in it
+    pub users: usize,
+}
+
+static MOUNTS: Mutex<Vec<Arc<Mount>>> = Mutex::new(Vec::new());
+
+fn components(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect()
+}
+
+/// # Normalize
+/// The components of the absolute path `path` with `.` and `..` resolved without looking at
+/// the filesystems, `..` at the root stays at the root
+fn normalize(path: &str) -> Result<Vec<&str>> {
+    if !path.starts_with('/') {
+        return Err(Error::InvalidArgument);
+    }
+    let mut normalized = Vec::new();
+    for component in components(path) {
+        if component == ".." {
+            normalized.pop();
+        } else {
+            normalized.push(component);
+        }
+    }
+    Ok(normalized)
+}
+
+/// # Mount
+/// Mounts `fs` at the absolute path `path`. The mount point does not have to exist in the
+/// filesystem it is in, and a filesystem may be mounted inside of another mounted one.
+///
+/// ## Returns
+/// - Error::DeviceOrResourceBusy = Something is mounted at `path` already
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<()> {
+    let _tag = crate::alloc_tag!("vfs");
+    let path = format_path(&normalize(path)?);
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(Error::DeviceOrResourceBusy);
+    }
+    info!("Mounted {} at {}", fs.name(), path);
+    mounts.push(Arc::new(Mount { path, fs }));
+    Ok(())
+}
+
+/// # Unmount
+/// Unmounts the filesystem mounted at `path`
+///
+/// ## Returns
+/// - Error::InvalidArgument = Nothing is mounted at `path`
+/// - Error::DeviceOrResourceBusy = Files of the filesystem are open, or another filesystem is
+///   mounted inside of it
+pub fn umount(path: &str) -> Result<()> {
+    let components = normalize(path)?;
+    let path = format_path(&components);
+    let mut mounts = MOUNTS.lock();
+    let idx = mounts
+        .iter()
+        .position(|mount| mount.path == path)
+        .ok_or(Error::InvalidArgument)?;
+    let nested = mounts
+        .iter()
+        .any(|mount| mount.path != path && self::components(&mount.path).starts_with(&components));
+    // The table holds the only reference of a mount nobody uses
+    if nested || Arc::strong_count(&mounts[idx]) > 1 {
+        return Err(Error::DeviceOrResourceBusy);
+    }
+    let mount = mounts.remove(idx);
+    info!("Unmounted {} from {}", mount.fs.name(), mount.path);
+    Ok(())
+}
+
+/// # Mounts
+/// The mount table, sorted by mount point
+pub fn mounts() -> Vec<MountInfo> {
+    let mut mounts: Vec<MountInfo> = MOUNTS
+        .lock()
+        .iter()
+        .map(|mount| MountInfo {
+            path: mount.path.clone(),
+            fs: mount.fs.name(),
+            users: Arc::strong_count(mount) - 1,
+        })
+        .collect();
+    mounts.sort_by(|a, b| a.path.cmp(&b.path));
+    mounts
+}
+
+fn format_path(components: &[&str]) -> String {
+    let mut path = String::new();
+    for component in components {
+        path.push('/');
+        path.push_str(component);
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
+/// # Lookup
+/// The mount the path made of `components` is on, the one at the longest matching prefix, and
+/// how many of the components lead to its mount point
+fn lookup(components: &[&str]) -> Result<(Arc<Mount>, usize)> {
+    MOUNTS
+        .lock()
+        .iter()
+        .filter_map(|mount| {
+            let mount_components = self::components(&mount.path);
+            components
+                .starts_with(&mount_components)
+                .then(|| (mount.clone(), mount_components.len()))
+        })
+        .max_by_key(|(_, depth)| *depth)
+        .ok_or(Error::NoSuchFileOrDirectory)
+}
+
+/// # Resolve
+/// Walks the absolute path `path` component by component, switching filesystems at mount
+/// points. `..` leads to the parent of a directory, which for the root of a mounted filesystem
+/// is the directory it is mounted on, and stays at the root at `/`.
+fn resolve(path: &str) -> Result<(Arc<Mount>, Arc<dyn File>)> {
+    if !path.starts_with('/') {
+        return Err(Error::InvalidArgument);
+    }
+    let mut resolved: Vec<&str> = Vec::new();
+    for component in components(path) {
+        if component != ".." {
+            resolved.push(component);
+            continue;
+        }
+        if resolved.is_empty() {
+            continue;
+        }
+        // Only a directory that exists has a parent
+        let (mount, depth) = lookup(&resolved)?;
+        if mount.fs.open(&resolved[depth..])?.metadata().ty != FileType::Directory {
+            return Err(Error::NotADirectory);
+        }
+        resolved.pop();
+    }
+    let (mount, depth) = lookup(&resolved)?;
+    let file = mount.fs.open(&resolved[depth..])?;
+    Ok((mount, file))
+}
+
+/// # Open
+/// Opens the file at the absolute path `path`
+pub fn open(path: &str) -> Result<Arc<dyn File>> {
+    let _tag = crate::alloc_tag!("vfs");
+    resolve(path).map(|(_, file)| file)
+}
+
+/// # Stat
+/// The metadata of the file at the absolute path `path`
+pub fn stat(path: &str) -> Result<Metadata> {
+    open(path).map(|file| file.metadata())
+}
+
+/// # Read To End
+/// Reads the whole file at `path`
+pub fn read_to_end(path: &str) -> Result<Vec<u8>> {
+    let file = open(path)?;
+    if file.metadata().ty == FileType::Directory {
+        return Err(Error::IsADirectory);
+    }
+    let mut data = alloc::vec![0u8; file.metadata().size as usize];
+    let mut read = 0;
+    while read < data.len() {
+        match file.read_at(read as u64, &mut data[read..])? {
+            0 => break,
+            count => read += count,
+        }
+    }
+    data.truncate(read);
+    Ok(data)
+}
+
+enum OpenFile {
+    File {
+        file: Arc<dyn File>,
+        offset: u64,
+        /// Keeps the filesystem mounted while the file is open
+        _mount: Arc<Mount>,
+    },
+    Socket(Arc<UdpSocket>),
+    SharedMemory(Arc<SharedMemory>),
+}
+
+/// # Open Files
+/// The open file table. There are no per-process tables yet, so descriptors are global.
+static OPEN_FILES: Mutex<BTreeMap<u64, OpenFile>> = Mutex::new(BTreeMap::new());
+/// 0, 1 and 2 are the standard streams, which are all the console
+const FIRST_FD: u64 = 3;
+
+fn insert_fd(open_file: OpenFile) -> Result<u64> {
+    let mut files = OPEN_FILES.lock();
+    let fd = (FIRST_FD..)
+        .find(|fd| !files.contains_key(fd))
+        .ok_or(Error::TooManyOpenFiles)?;
+    files.insert(fd, open_file);
+    Ok(fd)
+}
+
+/// # Is Console FD
+/// Whether `fd` is one of the standard streams, which read from the console `tty`
+pub fn is_console_fd(fd: u64) -> bool {
+    fd < FIRST_FD
+}
+
+pub fn is_open_fd(fd: u64) -> bool {
+    is_console_fd(fd) || OPEN_FILES.lock().contains_key(&fd)
+}
+
+/// # Open FD
+/// Opens `path` and returns a descriptor for it
+pub fn open_fd(path: &str) -> Result<u64> {
+    let (mount, file) = resolve(path)?;
+    insert_fd(OpenFile::File {
+        file,
+        offset: 0,
+        _mount: mount,
+    })
+}
+
+/// # Socket FD
+/// Returns a new descriptor for `socket`
+pub fn socket_fd(socket: Arc<UdpSocket>) -> Result<u64> {
+    insert_fd(OpenFile::Socket(socket))
+}
+
+/// # Socket
+/// The socket behind `fd`
+pub fn socket(fd: u64) -> Result<Arc<UdpSocket>> {
+    match OPEN_FILES.lock().get(&fd) {
+        Some(OpenFile::Socket(socket)) => Ok(socket.clone()),
+        Some(_) => Err(Error::SocketOperationOnNonSocket),
+        None => Err(Error::BadFileNumber),
+    }
+}
+
+/// # Shared Memory FD
+/// Returns a new descriptor for the shared memory object `object`
+pub fn shared_memory_fd(object: Arc<SharedMemory>) -> Result<u64> {
+    insert_fd(OpenFile::SharedMemory(object))
+}
+
+/// # Shared Memory
+/// The shared memory object behind `fd`
+///
+/// ## Returns
+/// - Error::NoSuchDevice = `fd` is something that cannot be mapped
+pub fn shared_memory(fd: u64) -> Result<Arc<SharedMemory>> {
+    match OPEN_FILES.lock().get(&fd) {
+        Some(OpenFile::SharedMemory(object)) => Ok(object.clone()),
+        Some(_) => Err(Error::NoSuchDevice),
+        None => Err(Error::BadFileNumber),
+    }
+}
+
+/// # Ioctl FD
+/// Hands `ioctl(fd, request, arg)` to the file behind `fd`, `tty::ioctl()` for the console
+///
+/// ## Returns
+/// - Error::NotTTY = `fd` is a socket or shared memory, or a file that takes no requests
+pub fn ioctl_fd(fd: u64, request: u64, arg: u64) -> Result<i32> {
+    if is_console_fd(fd) {
+        return tty::ioctl(request, arg);
+    }
+    let file = match OPEN_FILES.lock().get(&fd).ok_or(Error::BadFileNumber)? {
+        OpenFile::File { file, .. } => file.clone(),
+        _ => return Err(Error::NotTTY),
+    };
+    // Not under the table lock, the request may access user memory
+    file.ioctl(request, arg)
+}
+
+/// # Mmap FD
+/// What a `MAP_SHARED` mapping of `fd` from the page aligned `offset` on maps
+///
+/// ## Returns
+/// - Error::NoSuchDevice = `fd` is something that cannot be mapped
+pub fn mmap_fd(fd: u64, offset: u64) -> Result<Backing> {
+    if is_console_fd(fd) {
+        return Err(Error::NoSuchDevice);
+    }
+    let file = match OPEN_FILES.lock().get(&fd).ok_or(Error::BadFileNumber)? {
+        OpenFile::SharedMemory(object) => {
+            return Ok(Backing::Shared {
+                object: object.clone(),
+                offset,
+            })
+        }
+        OpenFile::File { file, .. } => file.clone(),
+        OpenFile::Socket(_) => return Err(Error::NoSuchDevice),
+    };
+    file.mmap(offset)
+}
+
+/// # Read FD
+/// Reads from the current offset of `fd` and advances it. Reading a socket takes the next
+/// datagram, reading the console waits for a line or, in raw mode, for a key.
+pub fn read_fd(fd: u64, buf: &mut [u8]) -> Result<usize> {
+    if is_console_fd(fd) {
+        return tty::read(buf, false);
+    }
+    let files = OPEN_FILES.lock();
+    let (file, offset) = match files.get(&fd).ok_or(Error::BadFileNumber)? {
+        OpenFile::File { file, offset, .. } => (file.clone(), *offset),
+        OpenFile::Socket(socket) => {
+            // Receiving may block, which must not happen with the table locked
+            let socket = socket.clone();
+            drop(files);
+            return socket.receive_from(buf, false).map(|(read, _)| read);
+        }
+        // Shared memory is only accessed through its mappings
+        OpenFile::SharedMemory(_) => return Err(Error::InvalidArgument),
+    };
+    // Reading may wait for the disk, neither may that happen with the table locked
+    drop(files);
+    let read = file.read_at(offset, buf)?;
+    advance(fd, &file, offset + read as u64);
+    Ok(read)
+}
+
+/// # Advance
+/// Moves the offset of `fd` to `offset`, unless `fd` was closed, and maybe reused for another
+/// file, since `file` was taken from it
+fn advance(fd: u64, file: &Arc<dyn File>, offset: u64) {
+    if let Some(OpenFile::File {
+        file: current,
+        offset: current_offset,
+        ..
+    }) = OPEN_FILES.lock().get_mut(&fd)
+    {
+        // By address only, the vtables of the same type may differ between codegen units
+        if Arc::as_ptr(current) as *const () == Arc::as_ptr(file) as *const () {
+            *current_offset = offset;
+        }
+    }
+}
+
+/// # Stat FD
+/// The metadata of the file behind `fd`. The console, sockets and shared memory are not part of
+/// a filesystem, their inode is made up.
+pub fn stat_fd(fd: u64) -> Result<Metadata> {
+    let made_up = |ty, size, inode| Metadata {
+        ty,
+        size,
+        inode,
+        mtime: None,
+    };
+    if is_console_fd(fd) {
+        return Ok(made_up(FileType::CharacterDevice, 0, 0));
+    }
+    match OPEN_FILES.lock().get(&fd).ok_or(Error::BadFileNumber)? {
+        OpenFile::File { file, .. } => Ok(file.metadata()),
+        OpenFile::Socket(socket) => Ok(made_up(FileType::Socket, 0, Arc::as_ptr(socket) as u64)),
+        OpenFile::SharedMemory(object) => Ok(made_up(
+            FileType::File,
+            object.len(),
+            Arc::as_ptr(object) as u64,
+        )),
+    }
+}
+
+/// # Read Dir FD
+/// Hands the entries of the directory `fd` to `fill` from its current offset on, until `fill`
+/// returns false for one, and advances the offset past the entries taken
+///
+/// ## Returns
+/// - usize = The number of entries taken, 0 at the end of the directory
+/// - Error::NotADirectory = `fd` is not a directory
+pub fn read_dir_fd(fd: u64, mut fill: impl FnMut(&DirEntry) -> bool) -> Result<usize> {
+    if is_console_fd(fd) {
+        return Err(Error::NotADirectory);
+    }
+    // Cloned out of the table, `fill` may write to user memory and the directory be on disk
+    let (file, offset) = match OPEN_FILES.lock().get(&fd).ok_or(Error::BadFileNumber)? {
+        OpenFile::File { file, offset, .. } => (file.clone(), *offset),
+        _ => return Err(Error::NotADirectory),
+    };
+    let taken = file
+        .read_dir(offset)?
+        .iter()
+        .take_while(|entry| fill(entry))
+        .count();
+    advance(fd, &file, offset + taken as u64);
+    Ok(taken)
+}
+
+pub fn close_fd(fd: u64) -> Result<()> {
+    // Dropped after the table is unlocked, closing the last reference to shared memory frees it
+    let file = OPEN_FILES.lock().remove(&fd);
+    file.map(|_| ()).ok_or(Error::BadFileNumber)
+}
+
+/// # Init FS
+/// Mounts the first FAT32 filesystem with a `DISK_MARKER` found on the registered block devices
+/// at `DISK_MOUNT_POINT`, preferring partitions over whole disks
+pub fn init_fs() {
+    let _tag = crate::alloc_tag!("vfs");
+    let mut devices = block::devices();
+    devices.sort_by_key(|entry| entry.partition.is_none());
+    for entry in devices {
+        let fs = match fat32::Fat32::new(entry.device.clone()) {
+            Ok(fs) => fs,
+            Err(_) => continue,
+        };
+        if fs.open(&[DISK_MARKER]).is_err() {
+            continue;
+        }
+        if let Err(err) = mount(DISK_MOUNT_POINT, fs) {
+            warn!("Failed to mount {}: {}", entry.name, err.text());
+        }
+        return;
+    }
+    warn!(
+        "No filesystem with a {} found to mount at {}",
+        DISK_MARKER, DISK_MOUNT_POINT
+    );
+}
@@ -0,0 +1,197 @@
+//! # Tar FS
+//! A tar archive as a read-only filesystem, which is how the initramfs is mounted.
+//!
+//! Archives do not need an entry for every directory, so a directory is any path that is a
+//! prefix of an entry, and the tree is found by looking at every entry. Inodes are a hash of
+//! the path, the archive has nothing better.
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use tar::tar::{Tar, TarEntry};
+
+use super::{DirEntry, File, FileSystem, FileType, Metadata};
+use crate::error::{Error, Result};
+
+/// # Tar FS
+pub struct TarFs {
+    tar: Tar<'static>,
+}
+
+impl TarFs {
+    pub fn new(tar: Tar<'static>) -> Arc<Self> {
+        Arc::new(Self { tar })
+    }
+}
+
+/// # Normalize
+/// The path of an entry without a leading `./` or trailing `/`, and whether it had the latter,
+/// which marks a directory
+fn normalize(name: &str) -> (&str, bool) {
+    let name = name.trim_start_matches("./");
+    match name.strip_suffix('/') {
+        Some(name) => (name, true),
+        None => (name, false),
+    }
+}
+
+/// # Child Of
+/// What is left of `path` after the directory `dir` and a slash, if it is in `dir`
+fn child_of<'path>(dir: &str, path: &'path str) -> Option<&'path str> {
+    if dir.is_empty() {
+        return (!path.is_empty()).then(|| path);
+    }
+    path.strip_prefix(dir)?
+        .strip_prefix('/')
+        .filter(|rest| !rest.is_empty())
+}
+
+fn join(dir: &str, name: &str) -> String {
+    let mut path = String::from(dir);
+    if !path.is_empty() {
+        path.push('/');
+    }
+    path.push_str(name);
+    path
+}
+
+/// FNV-1a
+fn inode(path: &str) -> u64 {
+    path.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+fn directory(path: &str) -> Metadata {
+    Metadata {
+        ty: FileType::Directory,
+        size: 0,
+        inode: inode(path),
+        mtime: None,
+    }
+}
+
+fn entry_metadata(path: &str, entry: &TarEntry, is_directory: bool) -> Metadata {
+    Metadata {
+        ty: if is_directory {
+            FileType::Directory
+        } else {
+            FileType::File
+        },
+        size: if is_directory { 0 } else { entry.size as u64 },
+        inode: inode(path),
+        mtime: (entry.mtime != 0).then(|| entry.mtime as i64),
+    }
+}
+
+/// # Node
+/// A file or directory of the archive, `path` is normalized
+struct Node {
+    tar: Tar<'static>,
+    path: String,
+    metadata: Metadata,
+    data: &'static [u8],
+}
+
+impl File for Node {
+    fn metadata(&self) -> Metadata {
+        self.metadata
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        if self.metadata.ty == FileType::Directory {
+            return Err(Error::IsADirectory);
+        }
+        if offset >= self.data.len() as u64 {
+            return Ok(0);
+        }
+        let data = &self.data[offset as usize..];
+        let len = buf.len().min(data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn read_dir(&self, offset: u64) -> Result<Vec<DirEntry>> {
+        if self.metadata.ty != FileType::Directory {
+            return Err(Error::NotADirectory);
+        }
+        // Sorted by name, so the offsets stay the same between calls
+        let mut children = BTreeMap::new();
+        for entry in self.tar.iter() {
+            let (path, is_directory) = normalize(entry.filename.as_str());
+            let rest = match child_of(&self.path, path) {
+                Some(rest) => rest,
+                None => continue,
+            };
+            match rest.find('/') {
+                // Deeper down, the entry of the directory itself is preferred if there is one
+                Some(end) => {
+                    let name = &rest[..end];
+                    if !children.contains_key(name) {
+                        let metadata = directory(&join(&self.path, name));
+                        children.insert(String::from(name), metadata);
+                    }
+                }
+                None => {
+                    let metadata = entry_metadata(path, &entry, is_directory);
+                    children.insert(String::from(rest), metadata);
+                }
+            }
+        }
+        Ok(children
+            .into_iter()
+            .skip(offset as usize)
+            .map(|(name, metadata)| DirEntry { name, metadata })
+            .collect())
+    }
+}
+
+impl FileSystem for TarFs {
+    fn open(&self, components: &[&str]) -> Result<Arc<dyn File>> {
+        let mut resolved: Vec<&str> = Vec::new();
+        for component in components {
+            match *component {
+                ".." => {
+                    resolved.pop();
+                }
+                component => resolved.push(component),
+            }
+        }
+        let path = resolved.join("/");
+
+        let mut found = path.is_empty().then(|| (directory(&path), &[][..]));
+        for entry in self.tar.iter() {
+            let (name, is_directory) = normalize(entry.filename.as_str());
+            // An archive of `.` has an entry `./` for the root, which stays a directory
+            if name == path && !path.is_empty() {
+                found = Some((entry_metadata(name, &entry, is_directory), entry.data));
+                break;
+            }
+            if found.is_none() && child_of(&path, name).is_some() {
+                found = Some((directory(&path), &[][..]));
+            }
+        }
+        let (metadata, data) = match found {
+            Some(found) => found,
+            // Something on the way may be a file rather than a directory
+            None => {
+                let through_file = self.tar.iter().any(|entry| {
+                    let (name, is_directory) = normalize(entry.filename.as_str());
+                    !is_directory && child_of(name, &path).is_some()
+                });
+                return Err(if through_file {
+                    Error::NotADirectory
+                } else {
+                    Error::NoSuchFileOrDirectory
+                });
+            }
+        };
+        Ok(Arc::new(Node {
+            tar: self.tar,
+            path,
+            metadata,
+            data,
+        }))
+    }
+
+    fn name(&self) -> &'static str {
+        "tarfs"
+    }
+}
@@ -0,0 +1,645 @@
+//! # GDB Stub
+//! Lets GDB debug the kernel over COM2 with its remote serial protocol, built with the
+//! `gdbstub` feature. With QEMU started with `-serial stdio -serial pty`, `target remote` to
+//! the pty QEMU names connects to it.
+//!
+//! The kernel stops in the stub on every `int3`, after every single step, when it panics and,
+//! with `gdb` on the command line, once its devices are initialized. While it is stopped, GDB
+//! can read and write the registers and kernel memory, set software breakpoints, continue and
+//! single-step. Only the CPU that stopped waits for GDB, the others keep running, and GDB
+//! cannot interrupt the kernel while it runs.
+//!
+//! Everything the stub needs is static, the heap may be what is broken.
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use bks::PAGE_SIZE;
+
+use self::packet::{
+    decode_hex, parse_hex, read_packet, write_packet, Connection, Response, PACKET_SIZE,
+};
+use crate::arch::interrupts::exceptions::{Breakpoint, Debug, IDTException};
+use crate::arch::interrupts::register::Registers;
+use crate::arch::interrupts::{set_raw_interrupt_handler, IrqScope};
+use crate::arch::paging::page_table_manager::{effective_flags, PageTableFlag};
+use crate::drivers::serial::{Serial, SerialPort, SERIAL2};
+use crate::error::{Error, Result};
+use crate::scheduler::spin::Mutex;
+use crate::smp::current_cpu;
+use crate::{cmdline, info, klog, warn};
+
+pub mod packet;
+
+/// The command line flag that stops the kernel for GDB once its devices are initialized
+pub const OPTION: &str = "gdb";
+/// The baud rate of COM2, which GDB has to use as well (`set serial baud`) on a real port
+pub const BAUD: u32 = 115_200;
+/// The number of software breakpoints that can be set at once
+pub const MAX_BREAKPOINTS: usize = 32;
+/// `int3`, which replaces the first byte of the instruction a breakpoint is set on
+pub const INT3: u8 = 0xCC;
+/// Raises #DB after the next instruction
+const RFLAGS_TF: u64 = 1 << 8;
+/// The stop reply for SIGTRAP, which GDB expects for breakpoints and single steps
+const STOP_TRAP: &[u8] = b"S05";
+/// The error reply for EFAULT, an address that cannot be accessed
+const ERROR_FAULT: &[u8] = b"E0e";
+const ERROR_INVALID: &[u8] = b"E16";
+/// The error reply for ENOSPC, every breakpoint is in use
+const ERROR_NO_SPACE: &[u8] = b"E1c";
+/// The registers of the `g` packet, up to the first segment selector: 16 general purpose
+/// registers and RIP of 8 bytes and RFLAGS of 4
+pub const GDB_REGISTER_BYTES: usize = 17 * 8 + 4;
+const NO_OWNER: usize = usize::MAX;
+
+/// # Software Breakpoint
+/// An `int3` the stub patched in, and the byte it replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoftwareBreakpoint {
+    pub addr: u64,
+    pub original: u8,
+}
+
+/// # Breakpoints
+/// The software breakpoints that are set
+pub struct Breakpoints {
+    slots: [Option<SoftwareBreakpoint>; MAX_BREAKPOINTS],
+}
+
+impl Breakpoints {
+    pub const fn new() -> Self {
+        Self {
+            slots: [None; MAX_BREAKPOINTS],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SoftwareBreakpoint> {
+        self.slots.iter().flatten()
+    }
+
+    pub fn get(&self, addr: u64) -> Option<SoftwareBreakpoint> {
+        self.iter()
+            .find(|breakpoint| breakpoint.addr == addr)
+            .copied()
+    }
+
+    /// # Set
+    /// Patches an `int3` into `addr`. Setting one that is set already is fine.
+    ///
+    /// ## Returns
+    /// - Err(&[u8]) = The error reply: `addr` cannot be written or every breakpoint is in use
+    pub fn set(&mut self, addr: u64) -> core::result::Result<(), &'static [u8]> {
+        if self.get(addr).is_some() {
+            return Ok(());
+        }
+        if !is_accessible(addr, 1, true) {
+            return Err(ERROR_FAULT);
+        }
+        let slot = self
+            .slots
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(ERROR_NO_SPACE)?;
+        let original = unsafe { core::ptr::read_volatile(addr as *const u8) };
+        *slot = Some(SoftwareBreakpoint { addr, original });
+        self.patch(addr);
+        Ok(())
+    }
+
+    /// # Remove
+    /// Puts back the byte the breakpoint at `addr` replaced, if there is one
+    pub fn remove(&mut self, addr: u64) {
+        self.unpatch(addr);
+        for slot in self.slots.iter_mut() {
+            if slot.map_or(false, |breakpoint| breakpoint.addr == addr) {
+                *slot = None;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        while let Some(breakpoint) = self.iter().next().copied() {
+            self.remove(breakpoint.addr);
+        }
+    }
+
+    /// Writes the `int3` of the breakpoint at `addr`, if it is still set
+    fn patch(&mut self, addr: u64) {
+        if self.get(addr).is_some() {
+            unsafe { core::ptr::write_volatile(addr as *mut u8, INT3) };
+        }
+    }
+
+    /// Writes back the byte the breakpoint at `addr` replaced
+    fn unpatch(&mut self, addr: u64) {
+        if let Some(breakpoint) = self.get(addr) {
+            unsafe { core::ptr::write_volatile(addr as *mut u8, breakpoint.original) };
+        }
+    }
+}
+
+/// # Resume
+/// How a command lets the stopped CPU go on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Step,
+    /// GDB is gone, there is nobody to report the next stop to
+    Detach,
+}
+
+/// # Stub
+/// What the stub keeps between stops
+pub struct Stub {
+    pub breakpoints: Breakpoints,
+    /// The breakpoint that was lifted to step over it, the #DB of the step puts it back
+    stepping_over: Option<u64>,
+    /// Whether GDB asked for the current single step, and not only the stub
+    stepping: bool,
+    /// Whether GDB waits for a stop reply, which it does after it resumed the kernel
+    resumed: bool,
+    packet: [u8; PACKET_SIZE],
+    response: Response,
+}
+
+impl Stub {
+    pub const fn new() -> Self {
+        Self {
+            breakpoints: Breakpoints::new(),
+            stepping_over: None,
+            stepping: false,
+            resumed: false,
+            packet: [0; PACKET_SIZE],
+            response: Response::new(),
+        }
+    }
+
+    /// # Trap
+    /// Handles the #DB or #BP `vector` of the code `regs` belongs to: Serves GDB until it
+    /// resumes, unless the #DB is the end of a step over a breakpoint GDB did not ask for.
+    pub fn trap(&mut self, conn: &mut impl Connection, regs: &mut Registers, vector: usize) {
+        match vector {
+            Breakpoint => {
+                // RIP is past the `int3`. One of the stub is reported at its address, so that
+                // resuming runs the instruction it replaced.
+                let addr = { regs.rip }.wrapping_sub(1);
+                if self.breakpoints.get(addr).is_some() {
+                    regs.rip = addr;
+                }
+            }
+            Debug => {
+                regs.rflags &= !RFLAGS_TF;
+                if let Some(addr) = self.stepping_over.take() {
+                    self.breakpoints.patch(addr);
+                    if !self.stepping {
+                        return;
+                    }
+                }
+                self.stepping = false;
+            }
+            _ => {}
+        }
+        self.serve(conn, regs);
+    }
+
+    /// # Serve
+    /// Answers the commands of GDB until one of them resumes the kernel
+    pub fn serve(&mut self, conn: &mut impl Connection, regs: &mut Registers) {
+        if self.resumed {
+            write_packet(conn, STOP_TRAP);
+        }
+        loop {
+            let len = read_packet(conn, &mut self.packet);
+            self.response.clear();
+            match self.command(len, regs) {
+                Some(Resume::Detach) => {
+                    write_packet(conn, self.response.as_bytes());
+                    self.resume(regs, Resume::Detach);
+                    return;
+                }
+                Some(resume) => {
+                    self.resume(regs, resume);
+                    return;
+                }
+                None => write_packet(conn, self.response.as_bytes()),
+            }
+        }
+    }
+
+    /// # Command
+    /// Runs the command in the first `len` bytes of the packet buffer. Its reply, empty for
+    /// commands the stub does not know, is left in the response.
+    ///
+    /// ## Returns
+    /// - Some(Resume) = The command resumes the kernel. Only a detach has a reply.
+    fn command(&mut self, len: usize, regs: &mut Registers) -> Option<Resume> {
+        let Self {
+            breakpoints,
+            stepping_over,
+            packet,
+            response,
+            ..
+        } = self;
+        let (&kind, args) = match packet[..len].split_first() {
+            Some(command) => command,
+            None => return None,
+        };
+        let reply: &[u8] = match kind {
+            b'?' => STOP_TRAP,
+            b'g' => {
+                read_registers(response, regs);
+                return None;
+            }
+            b'G' => match write_registers(regs, args) {
+                Some(()) => b"OK",
+                None => ERROR_INVALID,
+            },
+            b'm' => match read_memory(response, args) {
+                Ok(()) => return None,
+                Err(error) => {
+                    response.clear();
+                    error
+                }
+            },
+            b'M' => match write_memory(args) {
+                Ok(()) => b"OK",
+                Err(error) => error,
+            },
+            b'c' | b's' => {
+                if !args.is_empty() {
+                    match parse_hex(args) {
+                        Some(addr) => regs.rip = addr,
+                        None => {
+                            response.push(ERROR_INVALID);
+                            return None;
+                        }
+                    }
+                }
+                return Some(if kind == b's' {
+                    Resume::Step
+                } else {
+                    Resume::Continue
+                });
+            }
+            b'Z' | b'z' => match parse_breakpoint(args) {
+                Some(Ok(addr)) if kind == b'Z' => match breakpoints.set(addr) {
+                    Ok(()) => b"OK",
+                    Err(error) => error,
+                },
+                Some(Ok(addr)) => {
+                    breakpoints.remove(addr);
+                    if *stepping_over == Some(addr) {
+                        *stepping_over = None;
+                    }
+                    b"OK"
+                }
+                Some(Err(error)) => error,
+                // Hardware breakpoints and watchpoints
+                None => b"",
+            },
+            b'D' => {
+                breakpoints.clear();
+                *stepping_over = None;
+                response.push(b"OK");
+                return Some(Resume::Detach);
+            }
+            // Kill, which has no reply. There is nothing to kill, the kernel goes on without
+            // GDB.
+            b'k' => {
+                breakpoints.clear();
+                *stepping_over = None;
+                return Some(Resume::Detach);
+            }
+            b'H' => b"OK",
+            b'q' if args.starts_with(b"Supported") => b"PacketSize=1000",
+            b'q' if args == b"Attached" => b"1",
+            _ => b"",
+        };
+        response.push(reply);
+        None
+    }
+
+    /// # Resume
+    /// Lets the kernel go on as `resume` says. A breakpoint at RIP would trap right away, it
+    /// is lifted for one step and put back by the #DB of the step.
+    fn resume(&mut self, regs: &mut Registers, resume: Resume) {
+        self.resumed = resume != Resume::Detach;
+        self.stepping = resume == Resume::Step;
+        regs.rflags &= !RFLAGS_TF;
+        let rip = regs.rip;
+        if self.resumed && self.breakpoints.get(rip).is_some() {
+            self.breakpoints.unpatch(rip);
+            self.stepping_over = Some(rip);
+            regs.rflags |= RFLAGS_TF;
+        }
+        if self.stepping {
+            regs.rflags |= RFLAGS_TF;
+        }
+    }
+}
+
+/// `g`, the registers in the order GDB numbers them in for x86-64. GDB takes the ones after
+/// the segment selectors, those of the FPU, as unavailable.
+fn read_registers(response: &mut Response, regs: &Registers) {
+    for value in gdb_registers(regs) {
+        response.push_hex(&value.to_le_bytes());
+    }
+    response.push_hex(&({ regs.rflags } as u32).to_le_bytes());
+    let (ds, es, fs, gs) = data_segments();
+    for selector in [{ regs.cs }, { regs.ss }, ds, es, fs, gs] {
+        response.push_hex(&(selector as u32).to_le_bytes());
+    }
+}
+
+/// `m addr,len`
+fn read_memory(response: &mut Response, args: &[u8]) -> core::result::Result<(), &'static [u8]> {
+    let (addr, len) = split(args, b',').ok_or(ERROR_INVALID)?;
+    let addr = parse_hex(addr).ok_or(ERROR_INVALID)?;
+    let len = parse_hex(len).ok_or(ERROR_INVALID)?;
+    if len > (PACKET_SIZE / 2) as u64 {
+        return Err(ERROR_INVALID);
+    }
+    if !is_accessible(addr, len, false) {
+        return Err(ERROR_FAULT);
+    }
+    for offset in 0..len {
+        let byte = unsafe { core::ptr::read_volatile((addr + offset) as *const u8) };
+        response.push_hex(&[byte]);
+    }
+    Ok(())
+}
+
+/// The address of `Z0,addr,kind` and `z0,addr,kind`
+///
+/// ## Returns
+/// - None = The breakpoint is of another type, which the stub does not support
+fn parse_breakpoint(args: &[u8]) -> Option<core::result::Result<u64, &'static [u8]>> {
+    let (kind, args) = split(args, b',')?;
+    if kind != b"0" {
+        return None;
+    }
+    Some(
+        split(args, b',')
+            .and_then(|(addr, _)| parse_hex(addr))
+            .ok_or(ERROR_INVALID),
+    )
+}
+
+/// The general purpose registers in the order of the `g` packet, then RIP
+fn gdb_registers(regs: &Registers) -> [u64; 17] {
+    [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+    ]
+}
+
+/// `G`, which sets the registers `g` returns. The segment selectors are left alone.
+fn write_registers(regs: &mut Registers, args: &[u8]) -> Option<()> {
+    let mut bytes = [0; GDB_REGISTER_BYTES];
+    decode_hex(args.get(..GDB_REGISTER_BYTES * 2)?, &mut bytes)?;
+    let mut values = [0; 17];
+    for (value, chunk) in values.iter_mut().zip(bytes.chunks_exact(8)) {
+        *value = u64::from_le_bytes(chunk.try_into().ok()?);
+    }
+    let [rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8, r9, r10, r11, r12, r13, r14, r15, rip] =
+        values;
+    regs.rax = rax;
+    regs.rbx = rbx;
+    regs.rcx = rcx;
+    regs.rdx = rdx;
+    regs.rsi = rsi;
+    regs.rdi = rdi;
+    regs.rbp = rbp;
+    regs.rsp = rsp;
+    regs.r8 = r8;
+    regs.r9 = r9;
+    regs.r10 = r10;
+    regs.r11 = r11;
+    regs.r12 = r12;
+    regs.r13 = r13;
+    regs.r14 = r14;
+    regs.r15 = r15;
+    regs.rip = rip;
+    let rflags = u32::from_le_bytes(bytes[17 * 8..].try_into().ok()?);
+    regs.rflags = { regs.rflags } & !0xffff_ffff | rflags as u64;
+    Some(())
+}
+
+/// `M addr,len:bytes`
+fn write_memory(args: &[u8]) -> core::result::Result<(), &'static [u8]> {
+    let (range, hex) = split(args, b':').ok_or(ERROR_INVALID)?;
+    let (addr, len) = split(range, b',').ok_or(ERROR_INVALID)?;
+    let addr = parse_hex(addr).ok_or(ERROR_INVALID)?;
+    let len = parse_hex(len).ok_or(ERROR_INVALID)?;
+    if hex.len() as u64 != len * 2 {
+        return Err(ERROR_INVALID);
+    }
+    let mut bytes = [0; PACKET_SIZE / 2];
+    decode_hex(hex, &mut bytes).ok_or(ERROR_INVALID)?;
+    if !is_accessible(addr, len, true) {
+        return Err(ERROR_FAULT);
+    }
+    for (offset, byte) in bytes[..len as usize].iter().enumerate() {
+        unsafe { core::ptr::write_volatile((addr + offset as u64) as *mut u8, *byte) };
+    }
+    Ok(())
+}
+
+/// # Is Accessible
+/// Whether every page of the `len` bytes at `addr` is mapped for the kernel, and writable if
+/// `write` is set. User pages are not, SMAP would fault on them.
+pub fn is_accessible(addr: u64, len: u64, write: bool) -> bool {
+    let end = match addr.checked_add(len) {
+        Some(end) => end,
+        None => return false,
+    };
+    let mut page = addr & !(PAGE_SIZE - 1);
+    while page < end {
+        match effective_flags(page) {
+            Some(flags)
+                if !flags.contains(PageTableFlag::USER_ACCESSIBLE)
+                    && (!write || flags.contains(PageTableFlag::READ_WRITE)) => {}
+            _ => return false,
+        }
+        page += PAGE_SIZE;
+    }
+    true
+}
+
+fn split(data: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let idx = data.iter().position(|byte| *byte == separator)?;
+    Some((&data[..idx], &data[idx + 1..]))
+}
+
+/// The selectors in DS, ES, FS and GS, which the entry does not save as it does not change
+/// them
+fn data_segments() -> (u64, u64, u64, u64) {
+    let (ds, es, fs, gs): (u64, u64, u64, u64);
+    unsafe {
+        asm!(
+            "mov {0:x}, ds",
+            "mov {1:x}, es",
+            "mov {2:x}, fs",
+            "mov {3:x}, gs",
+            out(reg) ds,
+            out(reg) es,
+            out(reg) fs,
+            out(reg) gs,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    (ds & 0xffff, es & 0xffff, fs & 0xffff, gs & 0xffff)
+}
+
+/// # Serial Connection
+/// COM2, used without its lock: The stub owns the port, and the code holding the lock may be
+/// what stopped
+struct SerialConnection(Serial);
+
+impl Connection for SerialConnection {
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.0.read_byte() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.0.write_byte(byte);
+    }
+}
+
+static STUB: Mutex<Stub> = Mutex::new(Stub::new());
+/// The CPU that is stopped in the stub
+static OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Called by the entries of #DB and #BP with the registers they saved
+#[no_mangle]
+extern "C" fn gdbstub_trap(regs: *mut Registers, vector: usize) {
+    let _irq = IrqScope::enter(vector);
+    let regs = unsafe { &mut *regs };
+    let cpu = current_cpu();
+    // A panic in the stub itself stops on the `int3` of `on_panic()` again, but nobody can
+    // serve it
+    if OWNER.load(Ordering::Acquire) == cpu {
+        return;
+    }
+    let mut stub = STUB.lock();
+    OWNER.store(cpu, Ordering::Release);
+    stub.trap(
+        &mut SerialConnection(Serial::new(SerialPort::Com2)),
+        regs,
+        vector,
+    );
+    OWNER.store(NO_OWNER, Ordering::Release);
+}
+
+/// # Trap Entry
+/// An entry of #DB or #BP that saves every general purpose register below the frame the CPU
+/// pushed, which makes a `Registers`. The CPU aligned the stack before the 5 words of the
+/// frame, 15 more keep it aligned for the call.
+macro_rules! trap_entry {
+    ($name:ident, $vector:expr) => {
+        #[naked]
+        unsafe extern "C" fn $name() {
+            asm!(
+                "
+                push rax
+                push rcx
+                push rdx
+                push rdi
+                push rsi
+                push r8
+                push r9
+                push r10
+                push r11
+                push rbx
+                push rbp
+                push r12
+                push r13
+                push r14
+                push r15
+                mov rdi, rsp
+                mov rsi, {vector}
+                call gdbstub_trap
+                pop r15
+                pop r14
+                pop r13
+                pop r12
+                pop rbp
+                pop rbx
+                pop r11
+                pop r10
+                pop r9
+                pop r8
+                pop rsi
+                pop rdi
+                pop rdx
+                pop rcx
+                pop rax
+                iretq
+                ",
+                vector = const $vector,
+                options(noreturn),
+            );
+        }
+    };
+}
+
+trap_entry!(debug_entry, Debug);
+trap_entry!(breakpoint_entry, Breakpoint);
+
+/// # Breakpoint
+/// Stops in the stub, as if a breakpoint was hit here
+#[inline(always)]
+pub fn breakpoint() {
+    unsafe { asm!("int3") };
+}
+
+/// # On Panic
+/// Stops in the stub before the panic screen is drawn, GDB sees the panic handler with the
+/// code that panicked up the stack. Continuing draws the panic screen.
+pub fn on_panic() {
+    if INSTALLED.load(Ordering::Acquire) {
+        breakpoint();
+    }
+}
+
+/// # Init GDB Stub
+/// Sets up COM2 for GDB and takes over #DB and #BP
+fn init_gdbstub() -> Result<()> {
+    // GDB would read the log as garbled packets
+    if klog::unregister_sink("com2") {
+        warn!("gdbstub: COM2 is used for GDB, the log is not copied to it anymore");
+    }
+    if !SERIAL2.lock().init(BAUD) {
+        return Err(Error::NoSuchDevice);
+    }
+    unsafe {
+        set_raw_interrupt_handler(Debug as u64, IDTException::error_code(&Debug), debug_entry);
+        set_raw_interrupt_handler(
+            Breakpoint as u64,
+            IDTException::error_code(&Breakpoint),
+            breakpoint_entry,
+        );
+    }
+    INSTALLED.store(true, Ordering::Release);
+    info!("gdbstub: Listening on COM2 at {} baud", BAUD);
+    if cmdline::flag(OPTION) {
+        info!("gdbstub: Waiting for GDB");
+        breakpoint();
+    }
+    Ok(())
+}
+
+crate::initcall! {
+    name: "gdbstub",
+    stage: Late,
+    deps: ["serial"],
+    fatal: false,
+    feature: "gdbstub",
+    init: init_gdbstub,
+}
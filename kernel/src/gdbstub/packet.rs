@@ -0,0 +1,156 @@
+//! # Packets
+//! The framing of the GDB remote serial protocol: `$<data>#<checksum>`, the checksum being the
+//! sum of the data bytes modulo 256 as two hex digits. Every packet is acknowledged with `+`,
+//! or with `-` to have it sent again.
+//!
+//! The stub takes no binary packets, so there is nothing to unescape.
+
+/// The largest packet the stub takes, which it tells GDB in `qSupported`
+pub const PACKET_SIZE: usize = 0x1000;
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// # Connection
+/// Where the packets come from and go to
+pub trait Connection {
+    /// Waits for the next byte
+    fn read_byte(&mut self) -> u8;
+    fn write_byte(&mut self, byte: u8);
+}
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// # Read Packet
+/// Waits for a packet with a valid checksum, acknowledges it and copies its data into `buf`.
+/// Bytes between packets, such as the acknowledgements of GDB, are skipped. A packet that does
+/// not fit into `buf` is asked for again, like one that got garbled.
+///
+/// ## Returns
+/// - usize = The length of the data
+pub fn read_packet(conn: &mut impl Connection, buf: &mut [u8]) -> usize {
+    loop {
+        while conn.read_byte() != b'$' {}
+        let mut len = 0;
+        let mut sum = 0u8;
+        let mut fits = true;
+        loop {
+            let byte = conn.read_byte();
+            if byte == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(byte);
+            match buf.get_mut(len) {
+                Some(slot) => {
+                    *slot = byte;
+                    len += 1;
+                }
+                None => fits = false,
+            }
+        }
+        let high = hex_digit(conn.read_byte());
+        let low = hex_digit(conn.read_byte());
+        if fits && high.zip(low).map(|(high, low)| high << 4 | low) == Some(sum) {
+            conn.write_byte(b'+');
+            return len;
+        }
+        conn.write_byte(b'-');
+    }
+}
+
+/// # Write Packet
+/// Sends `data` as a packet until GDB acknowledges it
+pub fn write_packet(conn: &mut impl Connection, data: &[u8]) {
+    let sum = checksum(data);
+    loop {
+        conn.write_byte(b'$');
+        for byte in data {
+            conn.write_byte(*byte);
+        }
+        conn.write_byte(b'#');
+        conn.write_byte(HEX_DIGITS[(sum >> 4) as usize]);
+        conn.write_byte(HEX_DIGITS[(sum & 0xf) as usize]);
+        loop {
+            match conn.read_byte() {
+                b'+' => return,
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// # Parse Hex
+/// The number `hex` spells out, most significant digit first, as addresses and lengths are
+pub fn parse_hex(hex: &[u8]) -> Option<u64> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    hex.iter()
+        .try_fold(0, |value, byte| Some(value << 4 | hex_digit(*byte)? as u64))
+}
+
+/// # Decode Hex
+/// Decodes pairs of hex digits in `hex` into `out`, which has to have room for all of them
+///
+/// ## Returns
+/// - None = `hex` has an odd length or something other than hex digits
+pub fn decode_hex(hex: &[u8], out: &mut [u8]) -> Option<()> {
+    if hex.len() % 2 != 0 || hex.len() / 2 > out.len() {
+        return None;
+    }
+    for (pair, byte) in hex.chunks(2).zip(out.iter_mut()) {
+        *byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+    }
+    Some(())
+}
+
+/// # Response
+/// The data of the packet the stub answers with
+pub struct Response {
+    data: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Response {
+    pub const fn new() -> Self {
+        Self {
+            data: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends `bytes`, as much of them as fits
+    pub fn push(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(PACKET_SIZE - self.len);
+        self.data[self.len..self.len + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+    }
+
+    /// Appends every byte of `bytes` as two hex digits
+    pub fn push_hex(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.push(&[
+                HEX_DIGITS[(byte >> 4) as usize],
+                HEX_DIGITS[(byte & 0xf) as usize],
+            ]);
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
@@ -1,14 +1,33 @@
+//! # Heap
+//! The linked list heap the kernel started out with, a first fit allocator over a single list
+//! of segments. `segregated` replaced it, it is only the global allocator with the
+//! `linked-list-heap` feature.
+//!
+//! Its segments are aligned to `MIN_ALIGN` bytes. `malloc_aligned()` meets larger alignments
+//! by taking that much more and handing out the aligned address within, behind which it notes
+//! the address `malloc()` returned.
+use core::alloc::Layout;
 use core::mem::{size_of, MaybeUninit};
 
 use bks::PAGE_SIZE;
-use spin::Mutex;
 use unique::Unique;
 
 use crate::memory::paging::{
     page_frame_allocator::PAGE_FRAME_ALLOCATOR, page_table_manager::PAGE_TABLE_MANAGER,
 };
+use crate::scheduler::spin::Mutex;
+pub mod segregated;
+pub mod tag;
+
 pub static GLOBAL_HEAP: Mutex<MaybeUninit<Heap>> = Mutex::new(MaybeUninit::uninit());
 
+/// The alignment of the memory `Heap::malloc()` hands out
+pub const MIN_ALIGN: usize = 0x10;
+/// Two words in front of the memory `Heap::malloc_aligned()` hands out, followed by the address
+/// of the segment. Not a canonical address, so never the `last` link of a header, which is
+/// there for the memory `Heap::malloc()` hands out.
+const ALIGNED_MAGIC: u64 = 0xA116_0000_0000_A116;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct HeapSegmentHeader {
     len: usize,
@@ -235,6 +254,7 @@ impl<'header> Heap<'header> {
 
                 // It is a perfect fit
                 if current_segment.len == rounded_size {
+                    current_segment.free = false;
                     self.allocated += 1;
                     return current_segment.address() + size_of::<HeapSegmentHeader>() as u64;
                 }
@@ -253,6 +273,26 @@ impl<'header> Heap<'header> {
         return self.malloc(rounded_size);
     }
 
+    /// # Malloc Aligned
+    /// Allocates `size` bytes aligned to `align`, which has to be a power of two
+    ///
+    /// ## Returns
+    /// The address of the memory, 0 for a size of 0
+    pub unsafe fn malloc_aligned(&mut self, size: usize, align: usize) -> u64 {
+        if align <= MIN_ALIGN || size == 0 {
+            return self.malloc(size);
+        }
+        // Room for the magic and the address in front of the aligned memory
+        let segment = self.malloc(size + align + MIN_ALIGN);
+        if segment == 0 {
+            return 0;
+        }
+        let aligned = (segment + MIN_ALIGN as u64 + align as u64 - 1) & !(align as u64 - 1);
+        *((aligned - 16) as *mut u64) = ALIGNED_MAGIC;
+        *((aligned - 8) as *mut u64) = segment;
+        aligned
+    }
+
     /// # Split Header
     /// Splits the given header *and* sets the own last_header
     pub fn split_header(&mut self, header: &mut HeapSegmentHeader, length_after_split: usize) {
@@ -269,10 +309,17 @@ impl<'header> Heap<'header> {
         }
     }
 
-    pub fn free(&mut self, address: u64) {
+    pub fn free(&mut self, mut address: u64) {
         if address == 0 {
             return;
         }
+        unsafe {
+            let magic = (address - 16) as *mut u64;
+            if *magic == ALIGNED_MAGIC {
+                *magic = 0;
+                address = *((address - 8) as *const u64);
+            }
+        }
 
         self.freed += 1;
         let header = unsafe {
@@ -320,6 +367,10 @@ impl<'header> Heap<'header> {
         self.last_header = header;
     }
 
+    /// The number of bytes the heap currently spans
+    pub fn size(&self) -> u64 {
+        self.heap_end - self.heap_start
+    }
     pub fn freed(&self) -> u64 {
         self.freed
     }
@@ -333,8 +384,13 @@ impl<'header> Heap<'header> {
     }
 }
 
+/// # Malloc Pointer
+/// Allocates `size` bytes from the global allocator, aligned to 16 bytes
 pub unsafe fn malloc_ptr<T>(size: usize) -> *mut T {
-    GLOBAL_HEAP.lock().assume_init_mut().malloc(size) as *mut T
+    match Layout::from_size_align(size.max(1), 0x10) {
+        Ok(layout) => alloc::alloc::alloc(layout) as *mut T,
+        Err(_) => core::ptr::null_mut(),
+    }
 }
 
 pub fn malloc<'ptr_lifetime, T>() -> &'ptr_lifetime T {
@@ -346,5 +402,5 @@ pub fn malloc_mut<'ptr_lifetime, T>() -> &'ptr_lifetime mut T {
 }
 
 pub fn free(addr: u64) {
-    unsafe { GLOBAL_HEAP.lock().assume_init_mut().free(addr) }
+    unsafe { crate::memory::allocator::free(addr as *mut u8) }
 }
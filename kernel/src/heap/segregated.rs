@@ -0,0 +1,428 @@
+//! # Segregated Heap
+//! The kernel heap. Allocations of up to `MAX_CLASS_SIZE` bytes are rounded up to a power of
+//! two, their size class, and taken from the free list of that class. A class carves its
+//! blocks out of whole pages of the heap, which stay with it once taken. Larger allocations
+//! get physically contiguous pages of their own, outside of the heap. The frames of both are
+//! marked `SLAB` in their `FrameInfo`.
+//!
+//! Blocks of a class are aligned to their size, so an alignment larger than the size is met by
+//! rounding up to the class of the alignment.
+//!
+//! Debug builds fill freed blocks with `POISON`, behind the link of the free list, and check
+//! that they still hold it when they are handed out again. A block that does not was written
+//! to after it was freed, which is counted in `USE_AFTER_FREE`.
+//!
+//! Every block has the allocation tag it was allocated under, see `tag`. The tags of the small
+//! blocks are a byte per `MIN_CLASS_SIZE` bytes of the heap, in pages at its end.
+//!
+//! The linked list heap in `heap` is still built with the `linked-list-heap` feature, to
+//! compare the two.
+use core::alloc::Layout;
+
+use bks::PAGE_SIZE;
+
+use super::tag::UNTAGGED;
+use crate::arch::HEAP_LENGTH;
+use crate::memory::frame_info::{self, FrameFlags};
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::{phys_to_virt, virt_to_phys, PhysicalAddress, VirtualAddress};
+use crate::scheduler::spin::Mutex;
+
+/// The size of the smallest class, which holds the link of the free list
+pub const MIN_CLASS_SIZE: usize = 16;
+/// The size of the largest class, anything larger gets pages of its own
+pub const MAX_CLASS_SIZE: usize = PAGE_SIZE as usize;
+/// The number of size classes, `MIN_CLASS_SIZE` to `MAX_CLASS_SIZE`
+pub const CLASSES: usize = 9;
+/// The number of allocations larger than `MAX_CLASS_SIZE` that can be live at once
+pub const MAX_LARGE_ALLOCATIONS: usize = 1024;
+/// The class of a page of the heap no class has taken yet
+const NO_CLASS: u8 = u8::MAX;
+/// What freed blocks are filled with if `POISON_FREED`
+pub const POISON: u8 = 0xDE;
+/// Whether freed blocks are poisoned and checked when handed out again
+pub const POISON_FREED: bool = cfg!(debug_assertions);
+/// The bytes at the start of a free block that hold the link of the free list
+const LINK_SIZE: usize = core::mem::size_of::<u64>();
+
+crate::counter!(pub REALLOC_IN_PLACE = "heap.realloc_in_place");
+crate::counter!(pub REALLOC_MOVED = "heap.realloc_moved");
+// Blocks handed out that were written to after they were freed
+crate::counter!(pub USE_AFTER_FREE = "heap.use_after_free");
+
+pub static SEGREGATED_HEAP: Mutex<SegregatedHeap> = Mutex::new(SegregatedHeap::new());
+
+/// # Class Stats
+/// What a size class holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassStats {
+    /// The size of every block of the class
+    pub size: usize,
+    /// The pages of the heap the class carved blocks out of
+    pub pages: usize,
+    /// Blocks that were handed out and not freed yet
+    pub in_use: usize,
+    pub allocations: u64,
+    pub frees: u64,
+}
+
+impl ClassStats {
+    const fn new(size: usize) -> Self {
+        Self {
+            size,
+            pages: 0,
+            in_use: 0,
+            allocations: 0,
+            frees: 0,
+        }
+    }
+}
+
+/// # Large Allocation
+/// Pages of their own, `pages` of them at `address`. An `address` of 0 is an unused entry.
+#[derive(Debug, Clone, Copy)]
+struct LargeAllocation {
+    address: u64,
+    pages: usize,
+    tag: u8,
+}
+
+const NO_LARGE: LargeAllocation = LargeAllocation {
+    address: 0,
+    pages: 0,
+    tag: UNTAGGED,
+};
+
+pub struct SegregatedHeap {
+    /// The address of the first page of the heap
+    start: u64,
+    /// The number of pages of the heap blocks are carved out of
+    pages: usize,
+    /// The address of the allocation tags of the blocks, behind the last page of the heap
+    tags: u64,
+    /// The pages below this one have been taken by a class
+    next_page: usize,
+    /// The class of every page of the heap
+    page_classes: [u8; HEAP_LENGTH],
+    /// The first free block of every class, 0 if there is none. Every free block starts with
+    /// the address of the next one.
+    free_lists: [u64; CLASSES],
+    stats: [ClassStats; CLASSES],
+    large: [LargeAllocation; MAX_LARGE_ALLOCATIONS],
+    large_pages: usize,
+    /// The most memory the heap took at once, in bytes
+    peak_footprint: usize,
+    /// The last block found written to after it was freed, 0 if there was none
+    last_use_after_free: u64,
+}
+
+impl SegregatedHeap {
+    /// # New
+    /// A heap without any pages, every allocation fails until `init()`
+    pub const fn new() -> Self {
+        let mut stats = [ClassStats::new(0); CLASSES];
+        let mut class = 0;
+        while class < CLASSES {
+            stats[class] = ClassStats::new(MIN_CLASS_SIZE << class);
+            class += 1;
+        }
+        Self {
+            start: 0,
+            pages: 0,
+            tags: 0,
+            next_page: 0,
+            page_classes: [NO_CLASS; HEAP_LENGTH],
+            free_lists: [0; CLASSES],
+            stats,
+            large: [NO_LARGE; MAX_LARGE_ALLOCATIONS],
+            large_pages: 0,
+            peak_footprint: 0,
+            last_use_after_free: 0,
+        }
+    }
+
+    /// # Init
+    /// Hands the `pages` pages at `start` to the heap, at most `HEAP_LENGTH` of them. One in 257
+    /// pages holds the allocation tags of the others.
+    ///
+    /// ## Safety
+    /// The pages have to be mapped and must not be used by anything else
+    pub unsafe fn init(&mut self, start: u64, pages: usize) {
+        let pages = pages.min(HEAP_LENGTH);
+        // A page of tags covers `PAGE_SIZE / MIN_CLASS_SIZE` pages of blocks
+        let per_page = PAGE_SIZE as usize / MIN_CLASS_SIZE;
+        let tag_pages = (pages + per_page) / (per_page + 1);
+        self.start = start;
+        self.pages = pages - tag_pages;
+        self.tags = start + self.pages as u64 * PAGE_SIZE;
+        core::ptr::write_bytes(
+            self.tags as *mut u8,
+            UNTAGGED,
+            tag_pages * PAGE_SIZE as usize,
+        );
+    }
+
+    /// # Size Class
+    /// The class an allocation of `layout` is taken from, `None` if it gets pages of its own
+    pub fn size_class(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align()).max(MIN_CLASS_SIZE);
+        if size > MAX_CLASS_SIZE {
+            return None;
+        }
+        let class = size.next_power_of_two().trailing_zeros() - MIN_CLASS_SIZE.trailing_zeros();
+        Some(class as usize)
+    }
+
+    /// # Malloc
+    /// Allocates memory for `layout`. Once the heap is out of pages, small allocations get a
+    /// page of their own as well.
+    ///
+    /// ## Returns
+    /// The address of the memory, null if there is none left
+    pub unsafe fn malloc(&mut self, layout: Layout) -> *mut u8 {
+        let class = match Self::size_class(layout) {
+            Some(class) => class,
+            None => return self.malloc_large(layout.size(), layout.align()),
+        };
+        if self.free_lists[class] == 0 && !self.refill(class) {
+            return self.malloc_large(MAX_CLASS_SIZE, layout.align());
+        }
+        let block = self.free_lists[class];
+        self.free_lists[class] = *(block as *const u64);
+        if POISON_FREED && !Self::is_poisoned(block, self.stats[class].size) {
+            USE_AFTER_FREE.increment();
+            self.last_use_after_free = block;
+        }
+        let stats = &mut self.stats[class];
+        stats.in_use += 1;
+        stats.allocations += 1;
+        block as *mut u8
+    }
+
+    /// Carves the next page of the heap into blocks of `class`
+    unsafe fn refill(&mut self, class: usize) -> bool {
+        if self.next_page >= self.pages {
+            return false;
+        }
+        let page = self.start + self.next_page as u64 * PAGE_SIZE;
+        self.page_classes[self.next_page] = class as u8;
+        self.next_page += 1;
+        Self::mark_slab(virt_to_phys(VirtualAddress::new(page)).as_u64(), 1);
+        let size = self.stats[class].size;
+        if POISON_FREED {
+            core::ptr::write_bytes(page as *mut u8, POISON, MAX_CLASS_SIZE);
+        }
+        // Pushed from the end, so blocks are handed out in address order
+        for offset in (0..MAX_CLASS_SIZE).step_by(size).rev() {
+            let block = page + offset as u64;
+            *(block as *mut u64) = self.free_lists[class];
+            self.free_lists[class] = block;
+        }
+        self.stats[class].pages += 1;
+        self.update_peak();
+        true
+    }
+
+    unsafe fn malloc_large(&mut self, size: usize, align: usize) -> *mut u8 {
+        let slot = match self.large.iter().position(|large| large.address == 0) {
+            Some(slot) => slot,
+            None => return core::ptr::null_mut(),
+        };
+        let pages = Self::pages_for(size);
+        let phys = PAGE_FRAME_ALLOCATOR
+            .lock()
+            .assume_init_mut()
+            .request_contiguous_pages(pages, (align as u64).max(PAGE_SIZE), u64::MAX);
+        let address = match phys {
+            Some(phys) => {
+                Self::mark_slab(phys, pages);
+                phys_to_virt(PhysicalAddress::new(phys)).as_u64()
+            }
+            None => return core::ptr::null_mut(),
+        };
+        self.large[slot] = LargeAllocation {
+            address,
+            pages,
+            tag: UNTAGGED,
+        };
+        self.large_pages += pages;
+        self.update_peak();
+        address as *mut u8
+    }
+
+    /// Whether the free block at `block` of `size` bytes holds `POISON` behind its link
+    unsafe fn is_poisoned(block: u64, size: usize) -> bool {
+        core::slice::from_raw_parts((block as usize + LINK_SIZE) as *const u8, size - LINK_SIZE)
+            .iter()
+            .all(|byte| *byte == POISON)
+    }
+
+    /// Marks the `pages` frames from `phys` on as the heap's, freeing them clears the mark
+    fn mark_slab(phys: u64, pages: usize) {
+        for page in 0..pages as u64 {
+            if let Some(info) = frame_info::try_frame_info(phys + page * PAGE_SIZE) {
+                info.insert(FrameFlags::SLAB);
+            }
+        }
+    }
+
+    fn pages_for(size: usize) -> usize {
+        (size.max(1) + PAGE_SIZE as usize - 1) / PAGE_SIZE as usize
+    }
+
+    /// The class of the block at `address`, if it is in the heap
+    fn class_at(&self, address: u64) -> Option<usize> {
+        let end = self.start + self.next_page as u64 * PAGE_SIZE;
+        if address < self.start || address >= end {
+            return None;
+        }
+        match self.page_classes[((address - self.start) / PAGE_SIZE) as usize] {
+            NO_CLASS => None,
+            class => Some(class as usize),
+        }
+    }
+
+    fn large_at(&self, address: u64) -> Option<usize> {
+        if address == 0 {
+            return None;
+        }
+        self.large.iter().position(|large| large.address == address)
+    }
+
+    /// # Free
+    /// Returns the memory at `ptr` to the heap. Addresses the heap did not hand out are
+    /// ignored.
+    ///
+    /// ## Safety
+    /// `ptr` must not be used afterwards
+    pub unsafe fn free(&mut self, ptr: *mut u8) {
+        let address = ptr as u64;
+        if let Some(class) = self.class_at(address) {
+            if POISON_FREED {
+                let size = self.stats[class].size;
+                core::ptr::write_bytes(ptr.add(LINK_SIZE), POISON, size - LINK_SIZE);
+            }
+            *(address as *mut u64) = self.free_lists[class];
+            self.free_lists[class] = address;
+            let stats = &mut self.stats[class];
+            stats.in_use -= 1;
+            stats.frees += 1;
+        } else if let Some(slot) = self.large_at(address) {
+            let large = core::mem::replace(&mut self.large[slot], NO_LARGE);
+            let phys = virt_to_phys(VirtualAddress::new(large.address)).as_u64();
+            PAGE_FRAME_ALLOCATOR
+                .lock()
+                .assume_init_mut()
+                .free_pages(phys, large.pages);
+            self.large_pages -= large.pages;
+        }
+    }
+
+    /// # Usable Size
+    /// How many bytes the memory at `ptr` holds, `None` if the heap did not hand it out
+    pub fn usable_size(&self, ptr: *const u8) -> Option<usize> {
+        let address = ptr as u64;
+        match self.class_at(address) {
+            Some(class) => Some(self.stats[class].size),
+            None => self
+                .large_at(address)
+                .map(|slot| self.large[slot].pages * PAGE_SIZE as usize),
+        }
+    }
+
+    /// # Set Tag
+    /// Marks the memory at `ptr` as allocated under `tag`. Addresses the heap did not hand out
+    /// are ignored.
+    pub fn set_tag(&mut self, ptr: *const u8, tag: u8) {
+        let address = ptr as u64;
+        if self.class_at(address).is_some() {
+            let idx = (address - self.start) / MIN_CLASS_SIZE as u64;
+            unsafe { *((self.tags + idx) as *mut u8) = tag };
+        } else if let Some(slot) = self.large_at(address) {
+            self.large[slot].tag = tag;
+        }
+    }
+
+    /// # Tag Of
+    /// The allocation tag of the memory at `ptr`, `None` if the heap did not hand it out
+    pub fn tag_of(&self, ptr: *const u8) -> Option<u8> {
+        let address = ptr as u64;
+        match self.class_at(address) {
+            Some(_) => {
+                let idx = (address - self.start) / MIN_CLASS_SIZE as u64;
+                Some(unsafe { *((self.tags + idx) as *const u8) })
+            }
+            None => self.large_at(address).map(|slot| self.large[slot].tag),
+        }
+    }
+
+    /// # Realloc
+    /// Resizes the memory at `ptr`, allocated for `layout`, to `new_size` bytes. It stays
+    /// where it is if the new size belongs to the same class, or takes as many pages. Otherwise
+    /// it is moved and keeps its tag.
+    ///
+    /// ## Returns
+    /// The address of the memory, null if there is none left, in which case `ptr` is kept
+    pub unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let address = ptr as u64;
+        let in_place = match (self.class_at(address), Self::size_class(new_layout)) {
+            (Some(class), Some(new_class)) => class == new_class,
+            (None, None) => self.large_at(address).map_or(false, |slot| {
+                self.large[slot].pages == Self::pages_for(new_size)
+            }),
+            _ => false,
+        };
+        if in_place {
+            REALLOC_IN_PLACE.increment();
+            return ptr;
+        }
+        let new = self.malloc(new_layout);
+        if new.is_null() {
+            return new;
+        }
+        core::ptr::copy_nonoverlapping(ptr, new, layout.size().min(new_size));
+        if let Some(tag) = self.tag_of(ptr) {
+            self.set_tag(new, tag);
+        }
+        self.free(ptr);
+        REALLOC_MOVED.increment();
+        new
+    }
+
+    /// # Stats
+    /// What every size class holds, smallest first
+    pub fn stats(&self) -> [ClassStats; CLASSES] {
+        self.stats
+    }
+
+    /// # Large Pages
+    /// The pages of the allocations larger than `MAX_CLASS_SIZE`
+    pub fn large_pages(&self) -> usize {
+        self.large_pages
+    }
+
+    /// # Footprint
+    /// The memory the heap takes, in bytes: The pages the classes took and those of the large
+    /// allocations
+    pub fn footprint(&self) -> usize {
+        (self.next_page + self.large_pages) * PAGE_SIZE as usize
+    }
+
+    /// # Peak Footprint
+    /// The largest `footprint()` so far
+    pub fn peak_footprint(&self) -> usize {
+        self.peak_footprint
+    }
+
+    /// # Last Use After Free
+    /// The last block that was handed out again after something wrote to it while it was free,
+    /// only found if `POISON_FREED`
+    pub fn last_use_after_free(&self) -> Option<u64> {
+        (self.last_use_after_free != 0).then(|| self.last_use_after_free)
+    }
+
+    fn update_peak(&mut self) {
+        self.peak_footprint = self.peak_footprint.max(self.footprint());
+    }
+}
@@ -0,0 +1,212 @@
+//! # Allocation Tags
+//! Heap usage per subsystem. A subsystem tags the allocations of a section of code with
+//! `let _tag = alloc_tag!("vfs");`, every allocation made until the guard is dropped is
+//! accounted to "vfs", even if it is freed outside of the section. Allocations made outside of
+//! any tagged section go to `UNTAGGED`.
+//!
+//! The tag of the running code belongs to the CPU and is saved with the task on a context switch,
+//! so a tagged section may block. Accounting is a single atomic add on allocation and on free,
+//! the bytes and the count of a tag share one word. Only the segregated heap tracks tags, with
+//! the `linked-list-heap` feature nothing is accounted.
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::scheduler::spin::Mutex;
+use crate::smp::{current_cpu, MAX_CPUS};
+
+/// The number of tags that can be registered, later ones are accounted to `UNTAGGED`
+pub const MAX_TAGS: usize = 32;
+/// The tag of allocations made outside of any tagged section
+pub const UNTAGGED: u8 = 0;
+const UNTAGGED_NAME: &str = "untagged";
+/// The `AllocTag::id` of a tag that has not been registered yet
+const UNREGISTERED: u8 = u8::MAX;
+/// The count of a usage starts at this bit, the bytes are below it
+const COUNT_SHIFT: u32 = 40;
+const BYTES_MASK: u64 = (1 << COUNT_SHIFT) - 1;
+
+static NAMES: Mutex<[Option<&'static str>; MAX_TAGS]> = Mutex::new({
+    let mut names = [None; MAX_TAGS];
+    names[UNTAGGED as usize] = Some(UNTAGGED_NAME);
+    names
+});
+
+const NO_USAGE: AtomicU64 = AtomicU64::new(0);
+/// The live allocations of every tag, the count above `COUNT_SHIFT` and the bytes below
+static USAGE: [AtomicU64; MAX_TAGS] = [NO_USAGE; MAX_TAGS];
+
+const NOT_TAGGED: AtomicU8 = AtomicU8::new(UNTAGGED);
+/// The tag of the code running on every CPU
+static CURRENT: [AtomicU8; MAX_CPUS] = [NOT_TAGGED; MAX_CPUS];
+
+/// # Alloc Tag
+/// A named tag, registered the first time it is entered. Declared by `alloc_tag!`.
+pub struct AllocTag {
+    name: &'static str,
+    id: AtomicU8,
+}
+
+impl AllocTag {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            id: AtomicU8::new(UNREGISTERED),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// # Enter
+    /// Accounts the allocations of the calling code to this tag until the guard is dropped
+    pub fn enter(&self) -> AllocTagGuard {
+        let id = match self.id.load(Ordering::Relaxed) {
+            UNREGISTERED => {
+                let id = register(self.name);
+                self.id.store(id, Ordering::Relaxed);
+                id
+            }
+            id => id,
+        };
+        AllocTagGuard {
+            previous: CURRENT[current_cpu()].swap(id, Ordering::Relaxed),
+        }
+    }
+}
+
+/// # Alloc Tag Guard
+/// Restores the tag that was current before `AllocTag::enter()` when dropped
+#[must_use = "the tag is left as soon as the guard is dropped"]
+pub struct AllocTagGuard {
+    previous: u8,
+}
+
+impl Drop for AllocTagGuard {
+    fn drop(&mut self) {
+        // The task may have moved to another CPU in the meantime, its tag moved along
+        CURRENT[current_cpu()].store(self.previous, Ordering::Relaxed);
+    }
+}
+
+/// # Alloc Tag
+/// Accounts the allocations until the end of the scope to the tag `$name`
+/// ## Example
+/// ```
+/// let _tag = alloc_tag!("vfs");
+/// let buffer = alloc::vec![0u8; 512];
+/// ```
+#[macro_export]
+macro_rules! alloc_tag {
+    ($name:expr) => {{
+        static TAG: $crate::heap::tag::AllocTag = $crate::heap::tag::AllocTag::new($name);
+        TAG.enter()
+    }};
+}
+
+/// # Register
+/// The ID of the tag `name`, which is registered if it is not yet
+///
+/// ## Returns
+/// - u8 = The ID of the tag, `UNTAGGED` if all `MAX_TAGS` are taken
+pub fn register(name: &'static str) -> u8 {
+    let mut names = NAMES.lock();
+    if let Some(id) = names.iter().position(|tag| *tag == Some(name)) {
+        return id as u8;
+    }
+    match names.iter().position(|tag| tag.is_none()) {
+        Some(id) => {
+            names[id] = Some(name);
+            id as u8
+        }
+        None => UNTAGGED,
+    }
+}
+
+/// # Current
+/// The tag of the code running on the calling CPU
+#[inline]
+pub fn current() -> u8 {
+    CURRENT[current_cpu()].load(Ordering::Relaxed)
+}
+
+/// # Switch
+/// Makes `next` the tag of `cpu`, for the task switched to
+///
+/// ## Returns
+/// - u8 = The tag of the task switched away from
+pub fn switch(cpu: usize, next: u8) -> u8 {
+    CURRENT[cpu].swap(next, Ordering::Relaxed)
+}
+
+fn usage_of(tag: u8) -> &'static AtomicU64 {
+    USAGE.get(tag as usize).unwrap_or(&USAGE[UNTAGGED as usize])
+}
+
+/// # Charge
+/// Accounts an allocation of `size` bytes to `tag`
+#[inline]
+pub fn charge(tag: u8, size: usize) {
+    usage_of(tag).fetch_add((1 << COUNT_SHIFT) + size as u64, Ordering::Relaxed);
+}
+
+/// # Uncharge
+/// Removes an allocation of `size` bytes from `tag`
+#[inline]
+pub fn uncharge(tag: u8, size: usize) {
+    usage_of(tag).fetch_sub((1 << COUNT_SHIFT) + size as u64, Ordering::Relaxed);
+}
+
+/// # Resize
+/// Accounts an allocation of `tag` growing or shrinking from `old` to `new` bytes
+#[inline]
+pub fn resize(tag: u8, old: usize, new: usize) {
+    // Shrinking wraps around, which is a subtraction from the bytes without touching the count
+    let delta = (new as u64).wrapping_sub(old as u64);
+    usage_of(tag).fetch_add(delta, Ordering::Relaxed);
+}
+
+/// # Tag Usage
+/// What the live allocations of a tag take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagUsage {
+    pub name: &'static str,
+    pub bytes: u64,
+    /// The number of allocations
+    pub count: u64,
+}
+
+/// # Usage
+/// The usage of the tag `name`, `None` if it was never registered
+pub fn usage(name: &str) -> Option<TagUsage> {
+    usages().into_iter().find(|usage| usage.name == name)
+}
+
+/// # Usages
+/// The usage of every registered tag, the largest consumer first
+pub fn usages() -> Vec<TagUsage> {
+    let names = *NAMES.lock();
+    let mut usages: Vec<TagUsage> = names
+        .iter()
+        .zip(USAGE.iter())
+        .filter_map(|(name, usage)| {
+            let usage = usage.load(Ordering::Relaxed);
+            name.map(|name| TagUsage {
+                name,
+                bytes: usage & BYTES_MASK,
+                count: usage >> COUNT_SHIFT,
+            })
+        })
+        .collect();
+    usages.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    usages
+}
+
+/// # Total
+/// The bytes of all live allocations of the heap
+pub fn total() -> u64 {
+    USAGE
+        .iter()
+        .map(|usage| usage.load(Ordering::Relaxed) & BYTES_MASK)
+        .sum()
+}
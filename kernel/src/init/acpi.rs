@@ -1,22 +1,36 @@
+use crate::acpi::tables;
 use crate::config::handover;
-use crate::{
-    acpi::{
-        acpi_base::{ACPIFindable, ACPITable},
-        MCFGHeader, Rsdp2, SDTHeader,
-    },
-    info, kprint,
-    pci::PCI,
-};
-use bks::Handover;
+use crate::error::{Error, Result};
+use crate::info;
+use crate::init::error::{self, KernelInitError};
+use crate::memory::PhysicalAddress;
 
-pub fn init_acpi() {
-    let handover = handover();
+crate::initcall! {
+    name: "acpi",
+    stage: Platform,
+    deps: [],
+    fatal: true,
+    init: init_acpi,
+}
+
+/// # Init ACPI
+/// Registers the tables the XSDT, or the RSDT, lists through the RSDP the bootloader handed
+/// over. A failure ends the boot, see `KernelInitError`.
+///
+/// ## Returns
+/// - Error::NoSuchDevice = The tables cannot be read, if that is ever degradable
+pub fn init_acpi() -> Result<()> {
     info!("Preparing ACPI...");
-    let rsdp = Rsdp2::new(handover.rsdp).unwrap();
-    let xsdt = SDTHeader::new(rsdp.xsdt_address).unwrap();
-    // Print Tables of SDT
-    let mcfg = MCFGHeader::find_mut(xsdt).unwrap(); // Equivalent of xsdt.find_table::<MCFGHeader>()
+    let count = error::check("acpi", register_tables()).ok_or(Error::NoSuchDevice)?;
+    info!("acpi: {} tables", count);
+    Ok(())
+}
 
-    let pci = PCI::new();
-    pci.enumerate(mcfg);
+fn register_tables() -> core::result::Result<usize, KernelInitError> {
+    let rsdp = match handover().rsdp {
+        0 => return Err(KernelInitError::HandoverFieldMissing("rsdp")),
+        rsdp => PhysicalAddress::try_new(rsdp)
+            .map_err(|_| KernelInitError::AcpiError(Error::InvalidArgument))?,
+    };
+    tables::register(rsdp).map_err(KernelInitError::AcpiError)
 }
@@ -1,24 +1,79 @@
 use crate::{
+    cmdline,
     config::handover,
-    framebuffer::{Color, FramebufferGuard, FRAMEBUFFER_GUARD},
-    kprintln, success,
+    drivers::serial::init_serial,
+    earlylog,
+    framebuffer::{
+        self, font,
+        font::Psf,
+        rotation::{self, Rotation},
+        Color, FramebufferGuard, FRAMEBUFFER_GUARD,
+    },
+    info,
+    init::KernelInitError,
+    kprintln, success, warn,
 };
 use bks::Handover;
 
-pub fn init_common(handover: &mut Handover) {
+/// # Init Common
+/// Sets up the serial port and the framebuffer console. Everything logged until the console
+/// exists goes to the early log, which is replayed onto it at the end.
+///
+/// ## Returns
+/// - KernelInitError::FramebufferInvalid = The framebuffer is unusable, the console is COM1
+pub fn init_common(handover: &mut Handover) -> Result<(), KernelInitError> {
+    let has_serial = init_serial();
+    if has_serial {
+        earlylog::enable_serial();
+        info!("Found COM1");
+    } else {
+        warn!("No serial port found");
+    }
+    let framebuffer = *handover.framebuffer();
+    let boot_font = Psf::from_boot(handover.font());
+    if let Some(boot_font) = boot_font {
+        font::set_boot_font(boot_font);
+    }
+    // Fall back to a font built into the kernel if the bootloader's is unusable
+    let font = boot_font.or_else(|| font::embedded().next());
+
+    // A broken framebuffer must not take the kernel down with it, fall back to the serial port
+    let validation = framebuffer::validate(&framebuffer, font.as_ref());
+    if let Ok(info) = validation {
+        info!(
+            "Framebuffer: {}x{} at {:#x}",
+            info.width, info.height, info.base
+        );
+        if let (None, Some(font)) = (boot_font, font) {
+            warn!("Unusable boot font, using {}", font.name());
+        }
+    }
+    let mut guard = match (validation, font) {
+        (Ok(info), Some(font)) => {
+            FramebufferGuard::new(info, framebuffer, font, Color::Black, Color::White)
+        }
+        _ => FramebufferGuard::serial_only(framebuffer),
+    };
+    if let Some(value) = cmdline::value(rotation::OPTION) {
+        match value.parse().ok().and_then(Rotation::from_degrees) {
+            Some(rotation) => match guard.set_rotation(rotation) {
+                Ok(()) => info!("Framebuffer rotated by {} degrees", rotation.degrees()),
+                Err(e) => warn!("Cannot rotate the framebuffer by {}: {}", value, e),
+            },
+            None => warn!("Invalid rotation '{}', expected 90, 180 or 270", value),
+        }
+    }
     unsafe {
-        FRAMEBUFFER_GUARD.lock().write(FramebufferGuard::new(
-            *handover.framebuffer(),
-            *handover.font(),
-            Color::Black,
-            Color::White,
-        ));
+        FRAMEBUFFER_GUARD.lock().write(guard);
 
         FRAMEBUFFER_GUARD
             .lock()
             .assume_init_mut()
             .clear_color(Color::Black);
-
-        success!("Initialized Logging!");
     };
+    earlylog::finish();
+    success!("Initialized Logging!");
+    validation
+        .map(|_| ())
+        .map_err(KernelInitError::FramebufferInvalid)
 }
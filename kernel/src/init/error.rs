@@ -0,0 +1,149 @@
+//! # Init Errors
+//! What can go wrong while the kernel boots, before there is anything to fall back on. Every
+//! error is either fatal, which ends the boot on the boot failure screen, or degradable, which
+//! is logged and the kernel boots without what failed. `KernelInitError::class` is the one
+//! place that decides which, along with the hint the screen shows.
+//!
+//! The screen is drawn like the panic screen, on red, or written to COM1 while the early log is
+//! active or the console is serial only.
+use crate::error::Error;
+use crate::framebuffer::{clear_screen, Color, FramebufferError, FRAMEBUFFER_GUARD};
+use crate::{kcolorchange, kprintln, warn};
+
+/// # Kernel Init Error
+/// Why a step of the boot failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelInitError {
+    /// The bootloader did not hand over the field
+    HandoverFieldMissing(&'static str),
+    /// The ACPI tables cannot be read
+    AcpiError(Error),
+    /// The framebuffer cannot be drawn to
+    FramebufferInvalid(FramebufferError),
+    /// The memory map has no usable memory
+    MemoryMapEmpty,
+    /// The heap cannot be placed, with the reason
+    HeapInitFailed(&'static str),
+    /// The CPU has no local APIC
+    ApicUnsupported,
+    /// An init call the kernel cannot do without failed
+    InitCallFailed(&'static str, Error),
+}
+
+/// # Severity
+/// Whether the boot can go on after an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Fatal,
+    /// The kernel boots without what failed
+    Degradable,
+}
+
+impl KernelInitError {
+    /// # Class
+    /// How severe the error is and what the user can do about it
+    pub fn class(&self) -> (Severity, &'static str) {
+        use Severity::*;
+        match self {
+            Self::HandoverFieldMissing(_) => (
+                Fatal,
+                "The bootloader is older or newer than the kernel, rebuild both",
+            ),
+            Self::AcpiError(_) => (
+                Fatal,
+                "The firmware tables are broken, try updating the firmware or another machine",
+            ),
+            Self::FramebufferInvalid(_) => (
+                Degradable,
+                "The console is on COM1, try another resolution in the bootloader",
+            ),
+            Self::MemoryMapEmpty => (
+                Fatal,
+                "The firmware reported no usable memory, check the memory map of the bootloader",
+            ),
+            Self::HeapInitFailed(_) => (Fatal, "Give the machine more memory"),
+            Self::ApicUnsupported => (Fatal, "The kernel needs a CPU with a local APIC"),
+            Self::InitCallFailed(..) => (
+                Fatal,
+                "A driver the kernel cannot do without failed, see the log above",
+            ),
+        }
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        self.class().0 == Severity::Fatal
+    }
+
+    pub fn hint(&self) -> &'static str {
+        self.class().1
+    }
+}
+
+impl core::fmt::Display for KernelInitError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::HandoverFieldMissing(field) => {
+                write!(f, "the bootloader handed over no {}", field)
+            }
+            Self::AcpiError(e) => write!(f, "ACPI: {}", e.text()),
+            Self::FramebufferInvalid(e) => write!(f, "unusable framebuffer, {}", e),
+            Self::MemoryMapEmpty => write!(f, "the memory map has no usable memory"),
+            Self::HeapInitFailed(reason) => write!(f, "cannot set up the heap, {}", reason),
+            Self::ApicUnsupported => write!(f, "the CPU has no local APIC"),
+            Self::InitCallFailed(name, e) => write!(f, "{} failed: {}", name, e.text()),
+        }
+    }
+}
+
+/// # Check
+/// Ends the boot if the step `stage` failed with a fatal error. A degradable one is logged.
+///
+/// ## Returns
+/// - Some(T) = The step succeeded
+/// - None = The step failed, but the boot goes on
+pub fn check<T>(stage: &'static str, result: Result<T, KernelInitError>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) if e.is_fatal() => boot_failed(stage, &e),
+        Err(e) => {
+            warn!("init: {} degraded, {} ({})", stage, e, e.hint());
+            None
+        }
+    }
+}
+
+/// # Boot Failed
+/// Shows the boot failure screen for `error` in the step `stage` and halts
+pub fn boot_failed(stage: &'static str, error: &KernelInitError) -> ! {
+    if crate::earlylog::is_active() {
+        crate::earlylog::enable_serial();
+    } else {
+        kcolorchange!(bg: Color::Red, fg: Color::White);
+        let margin = unsafe {
+            let mut guard = FRAMEBUFFER_GUARD.lock();
+            match guard.assume_init_mut().font() {
+                Some(font) => (font.height(), font.width() * 2),
+                None => (0, 0),
+            }
+        };
+        clear_screen(Color::Red);
+        unsafe {
+            let mut guard = FRAMEBUFFER_GUARD.lock();
+            let guard = guard.assume_init_mut();
+            guard.set_location(margin.0, margin.1);
+            guard.set_column_starting_point(margin.1);
+        }
+    }
+    kprintln!("*+~*+~*+~*+~*+~*+~*+~*+~*+~ Boot Failed *+~*+~*+~*+~*+~*+~*+~*+~*+~");
+    kprintln!();
+    kprintln!("Stage: ");
+    kprintln!("\t-> {}", stage);
+    kprintln!("Error: ");
+    kprintln!("\t-> {}", error);
+    kprintln!("Hint: ");
+    kprintln!("\t-> {}", error.hint());
+    kprintln!();
+    kprintln!("*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~*+~");
+    crate::logdisk::panic_flush();
+    crate::panic::halt()
+}
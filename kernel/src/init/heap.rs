@@ -1,13 +1,42 @@
-use crate::arch::{HEAP_ADDRESS, HEAP_LENGTH};
-use crate::heap::Heap;
+use crate::arch::HEAP_LENGTH;
 use crate::info;
-use bks::Handover;
+use crate::init::KernelInitError;
+use crate::math::ByteSize;
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::{kaslr, VirtualAddress};
 
-pub fn init_heap() {
+/// # Init Heap
+/// Places the heap, at a random address with KASLR, and sets up the global allocator on it
+///
+/// ## Returns
+/// - KernelInitError::HeapInitFailed = The heap would reach beyond the memory that is mapped
+pub fn init_heap() -> Result<(), KernelInitError> {
     info!("Initializing Heap!");
-    unsafe {
-        crate::heap::GLOBAL_HEAP
-            .lock()
-            .write(Heap::new(HEAP_ADDRESS, HEAP_LENGTH));
+    let heap_address = kaslr::heap_base(HEAP_LENGTH);
+    let total_memory = unsafe { PAGE_FRAME_ALLOCATOR.lock().assume_init_mut().total_memory() };
+    if heap_address + HEAP_LENGTH as u64 * bks::PAGE_SIZE > total_memory {
+        return Err(KernelInitError::HeapInitFailed(
+            "it does not fit into physical memory",
+        ));
     }
+    unsafe { init_global_heap(heap_address) };
+    info!(
+        "heap: {} at {}",
+        ByteSize(HEAP_LENGTH as u64 * bks::PAGE_SIZE),
+        VirtualAddress::new(heap_address)
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "linked-list-heap"))]
+unsafe fn init_global_heap(heap_address: u64) {
+    crate::heap::segregated::SEGREGATED_HEAP
+        .lock()
+        .init(heap_address, HEAP_LENGTH);
+}
+
+#[cfg(feature = "linked-list-heap")]
+unsafe fn init_global_heap(heap_address: u64) {
+    let heap = crate::heap::Heap::new(heap_address, HEAP_LENGTH);
+    crate::heap::GLOBAL_HEAP.lock().write(heap);
 }
@@ -0,0 +1,289 @@
+//! # Init Calls
+//! Drivers and subsystems register their init functions with the `initcall!` macro, which
+//! places an `InitCall` into the `.initcalls` link section. `run_all()` calls them stage by
+//! stage, each after the ones it depends on, so adding a driver does not mean editing a
+//! central list of init functions.
+//!
+//! Subsystems that are only built with a cargo feature name it in their record, so the records
+//! tell which of them are in the kernel, and `outcome()` tells which of them came up.
+use alloc::vec::Vec;
+
+use crate::arch::tsc;
+use crate::error::Result;
+use crate::init::error::{boot_failed, KernelInitError};
+use crate::scheduler::spin::Mutex;
+use crate::{info, warn};
+
+/// # Init Stage
+/// When an init call runs. Stages run in the order they are declared in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InitStage {
+    /// Memory management beyond the heap
+    EarlyMemory,
+    /// Interrupt controllers and timers
+    Interrupts,
+    /// Firmware tables, such as ACPI
+    Platform,
+    /// Enumerating buses, such as PCI
+    Bus,
+    /// Drivers of devices found on the buses, or of legacy devices
+    Device,
+    /// Anything that needs the devices
+    Late,
+    /// Drivers that may block, such as on their locks, run by `run_scheduled()` once the
+    /// scheduler is running
+    Scheduled,
+}
+
+impl InitStage {
+    pub const ALL: [InitStage; 7] = [
+        InitStage::EarlyMemory,
+        InitStage::Interrupts,
+        InitStage::Platform,
+        InitStage::Bus,
+        InitStage::Device,
+        InitStage::Late,
+        InitStage::Scheduled,
+    ];
+}
+
+/// # Init Call
+/// The record placed into the `.initcalls` section by `initcall!`
+pub struct InitCall {
+    pub name: &'static str,
+    pub stage: InitStage,
+    /// The names of the init calls that have to run first, in this stage or an earlier one
+    pub deps: &'static [&'static str],
+    /// Whether the boot fails if the call fails, see `KernelInitError::InitCallFailed`.
+    /// Otherwise it and everything that depends on it is skipped.
+    pub fatal: bool,
+    /// The cargo feature the init call is only built with
+    pub feature: Option<&'static str>,
+    pub init_fn: fn() -> Result<()>,
+}
+
+/// # Outcome
+/// What became of an init call that `run_all()` got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    Failed,
+    /// An init call it depends on failed
+    Skipped,
+}
+
+impl Outcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Done => "done",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// The outcome of every init call `run_all()` got to, in the order they ran
+static OUTCOMES: Mutex<Vec<(&'static str, Outcome)>> = Mutex::new(Vec::new());
+
+/// # Sort Error
+/// Why init calls cannot be ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortError {
+    /// Two init calls have the same name
+    Duplicate(&'static str),
+    /// An init call depends on one that does not exist
+    UnknownDependency(&'static str, &'static str),
+    /// An init call depends on one that runs in a later stage
+    LaterStage(&'static str, &'static str),
+    /// The init call is part of a dependency cycle
+    Cycle(&'static str),
+}
+
+impl core::fmt::Display for SortError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SortError::Duplicate(name) => write!(f, "{} is registered twice", name),
+            SortError::UnknownDependency(name, dep) => {
+                write!(f, "{} depends on {}, which does not exist", name, dep)
+            }
+            SortError::LaterStage(name, dep) => {
+                write!(
+                    f,
+                    "{} depends on {}, which runs in a later stage",
+                    name, dep
+                )
+            }
+            SortError::Cycle(name) => write!(f, "{} is part of a dependency cycle", name),
+        }
+    }
+}
+
+extern "C" {
+    // Defined in the Linker Script
+    static __initcalls_start: InitCall;
+    static __initcalls_end: InitCall;
+}
+
+/// # Registered
+/// All init calls registered via `initcall!`, in no particular order
+pub fn registered() -> &'static [InitCall] {
+    unsafe {
+        let start = &__initcalls_start as *const InitCall;
+        let end = &__initcalls_end as *const InitCall;
+        let count = (end as usize - start as usize) / core::mem::size_of::<InitCall>();
+        core::slice::from_raw_parts(start, count)
+    }
+}
+
+/// # Outcome
+/// What became of the init call `name`, `None` if it did not run (yet)
+pub fn outcome(name: &str) -> Option<Outcome> {
+    OUTCOMES
+        .lock()
+        .iter()
+        .find(|(call, _)| *call == name)
+        .map(|(_, outcome)| *outcome)
+}
+
+fn record(name: &'static str, outcome: Outcome) {
+    OUTCOMES.lock().push((name, outcome));
+}
+
+/// # Sort
+/// Orders `calls` by stage, and within a stage so that every call comes after its
+/// dependencies
+pub fn sort(calls: &[InitCall]) -> core::result::Result<Vec<&InitCall>, SortError> {
+    for (idx, call) in calls.iter().enumerate() {
+        if calls[..idx].iter().any(|other| other.name == call.name) {
+            return Err(SortError::Duplicate(call.name));
+        }
+        for dep in call.deps {
+            match calls.iter().find(|other| other.name == *dep) {
+                None => return Err(SortError::UnknownDependency(call.name, *dep)),
+                Some(other) if other.stage > call.stage => {
+                    return Err(SortError::LaterStage(call.name, *dep))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    let mut order: Vec<&InitCall> = Vec::with_capacity(calls.len());
+    for stage in InitStage::ALL {
+        let mut pending: Vec<&InitCall> = calls.iter().filter(|call| call.stage == stage).collect();
+        while !pending.is_empty() {
+            let ready = pending.iter().position(|call| {
+                call.deps
+                    .iter()
+                    .all(|dep| order.iter().any(|done| done.name == *dep))
+            });
+            match ready {
+                Some(idx) => order.push(pending.remove(idx)),
+                None => return Err(SortError::Cycle(pending[0].name)),
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// # Run All
+/// Runs every registered init call up to the `Late` stage, logging how long each took. The
+/// boot fails if a fatal one fails.
+///
+/// ## Panics
+/// If the init calls cannot be ordered
+pub fn run_all() {
+    run_stages(|stage| stage != InitStage::Scheduled);
+}
+
+/// # Run Scheduled
+/// Runs the init calls of the `Scheduled` stage, called once the scheduler is running. The
+/// boot fails if a fatal one fails.
+///
+/// ## Panics
+/// If the init calls cannot be ordered
+pub fn run_scheduled() {
+    run_stages(|stage| stage == InitStage::Scheduled);
+}
+
+fn run_stages(include: impl Fn(InitStage) -> bool) {
+    let order = match sort(registered()) {
+        Ok(order) => order,
+        Err(e) => panic!("Cannot order the init calls: {}", e),
+    };
+    for call in order.into_iter().filter(|call| include(call.stage)) {
+        // A dependency in an earlier run has its outcome recorded as well
+        let failed_dep = call
+            .deps
+            .iter()
+            .find(|dep| matches!(outcome(dep), Some(Outcome::Failed | Outcome::Skipped)));
+        if let Some(dep) = failed_dep {
+            warn!("initcall: Skipping {}, {} failed", call.name, dep);
+            record(call.name, Outcome::Skipped);
+            continue;
+        }
+        let start = tsc::read();
+        let result = (call.init_fn)();
+        let cycles = tsc::read().wrapping_sub(start);
+        match result {
+            Ok(()) => {
+                info!(
+                    "initcall: {} ({:?}) done in {} cycles",
+                    call.name, call.stage, cycles
+                );
+                record(call.name, Outcome::Done);
+            }
+            Err(e) if call.fatal => {
+                boot_failed(call.name, &KernelInitError::InitCallFailed(call.name, e))
+            }
+            Err(e) => {
+                warn!("initcall: {} failed: {}", call.name, e);
+                record(call.name, Outcome::Failed);
+            }
+        }
+    }
+}
+
+/// # Init Call
+/// Registers an init function to be called by `run_all()`. A subsystem behind a cargo feature
+/// names it with `feature`.
+/// ## Example
+/// ```
+/// initcall! {
+///     name: "pci",
+///     stage: Bus,
+///     deps: ["acpi"],
+///     fatal: false,
+///     init: init_pci,
+/// }
+/// ```
+#[macro_export]
+macro_rules! initcall {
+    (
+        name: $name:expr,
+        stage: $stage:ident,
+        deps: [$($dep:expr),* $(,)?],
+        fatal: $fatal:expr,
+        $(feature: $feature:expr,)?
+        init: $init:path $(,)?
+    ) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".initcalls"]
+            static CALL: $crate::init::InitCall = $crate::init::InitCall {
+                name: $name,
+                stage: $crate::init::InitStage::$stage,
+                deps: &[$($dep),*],
+                fatal: $fatal,
+                feature: $crate::initcall!(@feature $($feature)?),
+                init_fn: $init,
+            };
+        };
+    };
+    (@feature $feature:expr) => {
+        Some($feature)
+    };
+    (@feature) => {
+        None
+    };
+}
@@ -1,4 +1,9 @@
 pub mod acpi;
 pub mod common;
 pub mod config;
+pub mod error;
 pub mod heap;
+pub mod initcall;
+
+pub use error::KernelInitError;
+pub use initcall::{outcome, run_all, InitCall, InitStage, Outcome};
@@ -2,9 +2,11 @@ use core::mem::MaybeUninit;
 
 use alloc::vec::Vec;
 use bks::Handover;
-use spin::Mutex;
 use tar::tar::*;
 
+use crate::fs::tarfs::TarFs;
+use crate::scheduler::spin::Mutex;
+use crate::warn;
 use crate::{config::handover, memory::paging::page_table_manager::PAGE_TABLE_MANAGER};
 
 pub static INITRAMFS: Mutex<MaybeUninit<InitRamFs>> = Mutex::new(MaybeUninit::uninit());
@@ -49,6 +51,10 @@ pub fn load_initramfs() {
 
     let tar = Tar::from_slice(slice);
     INITRAMFS.lock().write(InitRamFs::new(tar));
+    // Mounted at the root, the filesystems of disks have mount points of their own
+    if let Err(err) = crate::fs::mount("/", TarFs::new(tar)) {
+        warn!("Failed to mount the initramfs: {}", err.text());
+    }
 }
 
 pub struct InitRamFs<'tar> {
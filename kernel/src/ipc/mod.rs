@@ -1,9 +1,12 @@
 use alloc::vec;
 use alloc::vec::Vec;
 use esys::ipc::{IPCMessage, IPCQueueHeader};
-use spin::Mutex;
 use unique::Unique;
 
+use crate::scheduler::spin::Mutex;
+
+pub mod shm;
+
 pub static IPC_QUEUES: Mutex<Vec<u64>> = Mutex::new(vec![]);
 
 pub fn kernel_ipc_handler() {
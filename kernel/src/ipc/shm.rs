@@ -0,0 +1,144 @@
+//! # Shared Memory
+//! Memory objects that are mapped into several places at once, created by `shm_open()` under a
+//! name in one global namespace. Every mapping of an object maps the same frames, so a write
+//! through one mapping is seen through all others right away.
+//!
+//! An object is reference counted: The namespace holds it until the name is unlinked, every
+//! descriptor and every mapping holds it until it is closed or unmapped. Its frames are freed
+//! once the last of them is gone.
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bks::PAGE_SIZE;
+
+use crate::error::{Error, Result};
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::{phys_to_virt, Frame, PhysicalAddress};
+use crate::scheduler::spin::Mutex;
+
+/// Names are at most this long, without the leading slash
+pub const SHM_NAME_MAX: usize = 255;
+/// A flag of `shm_open()`: Create the object if there is none of that name
+pub const O_CREAT: u64 = 0o100;
+/// A flag of `shm_open()`: Together with `O_CREAT`, fail if the object exists
+pub const O_EXCL: u64 = 0o200;
+
+crate::counter!(pub SHM_OBJECTS = "ipc.shm_objects");
+
+/// # Shared Memory
+/// The frames of a shared memory object, zeroed when it is created
+#[derive(Debug)]
+pub struct SharedMemory {
+    frames: Vec<Frame>,
+    len: u64,
+}
+
+impl SharedMemory {
+    /// # New
+    /// An object of `len` bytes, which is rounded up to whole frames
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = `len` is zero
+    /// - Error::OutOfMemory = There are not enough free frames
+    pub fn new(len: u64) -> Result<Arc<Self>> {
+        if len == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let count = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut frames = Vec::with_capacity(count as usize);
+        {
+            let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+            let allocator = unsafe { allocator.assume_init_mut() };
+            // Running out of frames panics in the allocator
+            if allocator.get_free_memory() < (count * PAGE_SIZE) as i64 {
+                return Err(Error::OutOfMemory);
+            }
+            for _ in 0..count {
+                let start = PhysicalAddress::new(allocator.request_page());
+                frames.push(Frame::from_start_unchecked(start));
+            }
+        }
+        for frame in &frames {
+            let page = phys_to_virt(frame.start()).as_u64() as *mut u8;
+            unsafe { core::ptr::write_bytes(page, 0, PAGE_SIZE as usize) };
+        }
+        SHM_OBJECTS.increment();
+        Ok(Arc::new(Self { frames, len }))
+    }
+
+    /// # Len
+    /// The size of the object in bytes, as it was created
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// # Frames
+    /// The frames behind the object, in order
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+        let allocator = unsafe { allocator.assume_init_mut() };
+        for frame in &self.frames {
+            allocator.free_page(frame.start().as_u64());
+        }
+    }
+}
+
+/// The named objects
+static NAMESPACE: Mutex<BTreeMap<String, Arc<SharedMemory>>> = Mutex::new(BTreeMap::new());
+
+/// # Check Name
+/// Names start with a slash and have no other
+fn check_name(name: &str) -> Result<()> {
+    match name.strip_prefix('/') {
+        Some(rest) if rest.len() > SHM_NAME_MAX => Err(Error::FileNameTooLong),
+        Some(rest) if !rest.is_empty() && !rest.contains('/') => Ok(()),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// # Open
+/// Opens the object `name`, or creates one of `size` bytes with `O_CREAT`. The size of an
+/// existing object stays as it is.
+///
+/// ## Returns
+/// - Error::InvalidArgument = `name` is not a slash followed by a name without slashes, or an
+///   object of size zero would be created
+/// - Error::NoSuchFileOrDirectory = There is no object `name` and `O_CREAT` is not set
+/// - Error::AlreadyExists = The object exists and `O_CREAT` and `O_EXCL` are set
+pub fn open(name: &str, flags: u64, size: u64) -> Result<Arc<SharedMemory>> {
+    let _tag = crate::alloc_tag!("shm");
+    check_name(name)?;
+    let mut namespace = NAMESPACE.lock();
+    if let Some(object) = namespace.get(name) {
+        if flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL {
+            return Err(Error::AlreadyExists);
+        }
+        return Ok(object.clone());
+    }
+    if flags & O_CREAT == 0 {
+        return Err(Error::NoSuchFileOrDirectory);
+    }
+    let object = SharedMemory::new(size)?;
+    namespace.insert(String::from(name), object.clone());
+    Ok(object)
+}
+
+/// # Unlink
+/// Removes the name `name`. The object lives on until its descriptors are closed and its
+/// mappings are gone.
+///
+/// ## Returns
+/// - Error::NoSuchFileOrDirectory = There is no object `name`
+pub fn unlink(name: &str) -> Result<()> {
+    check_name(name)?;
+    // Dropped after the namespace is unlocked, which may free the frames
+    let object = NAMESPACE.lock().remove(name);
+    object.map(|_| ()).ok_or(Error::NoSuchFileOrDirectory)
+}
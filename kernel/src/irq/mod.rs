@@ -0,0 +1,159 @@
+//! # IRQ
+//! Interrupt vectors that are handed out at runtime. The kernel keeps its own vectors below
+//! `FIRST_DYNAMIC_VECTOR` and above `LAST_DYNAMIC_VECTOR`, the ones in between are taken by
+//! drivers, mostly for message signalled interrupts through `msi`. Every dynamic vector has an
+//! entry of its own that looks up the handler registered with it, accounts for the interrupt
+//! in the statistics and signals the end of it to the local APIC.
+use crate::arch::apic::local_apic;
+use crate::arch::interrupts::{
+    interrupt_frame::InterruptFrame, remove_handler_name, set_interrupt_handler, IrqScope,
+};
+use crate::error::{Error, Result};
+use crate::scheduler::IrqSpinLock;
+
+pub mod msi;
+pub mod softirq;
+
+pub const FIRST_DYNAMIC_VECTOR: u8 = 0x40;
+pub const LAST_DYNAMIC_VECTOR: u8 = 0xEF;
+pub const DYNAMIC_VECTORS: usize = (LAST_DYNAMIC_VECTOR - FIRST_DYNAMIC_VECTOR) as usize + 1;
+/// The most vectors a single allocation can take, which is what MSI can address
+pub const MAX_ALLOCATION: usize = 32;
+
+/// # Handler
+/// Called for every interrupt on an allocated vector, with the index of the vector in its
+/// allocation and the data it was allocated with. Runs in interrupt context.
+pub type Handler = fn(index: usize, data: usize);
+
+#[derive(Clone, Copy)]
+struct Slot {
+    handler: Handler,
+    data: usize,
+    /// The first vector of the allocation
+    first: u8,
+}
+
+static SLOTS: IrqSpinLock<[Option<Slot>; DYNAMIC_VECTORS]> =
+    crate::named_lock!("irq_handlers", [None; DYNAMIC_VECTORS]);
+
+crate::counter!(pub UNHANDLED = "irq.unhandled");
+
+extern "x86-interrupt" fn dynamic_entry<const VECTOR: u8>(_frame: InterruptFrame) {
+    dispatch(VECTOR);
+}
+
+/// The entries of 16 vectors starting at `$high * 16`, for each `$high`
+macro_rules! dynamic_entries {
+    ($($high:literal)*) => {
+        [$(
+            dynamic_entry::<{ $high * 16 }>,
+            dynamic_entry::<{ $high * 16 + 1 }>,
+            dynamic_entry::<{ $high * 16 + 2 }>,
+            dynamic_entry::<{ $high * 16 + 3 }>,
+            dynamic_entry::<{ $high * 16 + 4 }>,
+            dynamic_entry::<{ $high * 16 + 5 }>,
+            dynamic_entry::<{ $high * 16 + 6 }>,
+            dynamic_entry::<{ $high * 16 + 7 }>,
+            dynamic_entry::<{ $high * 16 + 8 }>,
+            dynamic_entry::<{ $high * 16 + 9 }>,
+            dynamic_entry::<{ $high * 16 + 10 }>,
+            dynamic_entry::<{ $high * 16 + 11 }>,
+            dynamic_entry::<{ $high * 16 + 12 }>,
+            dynamic_entry::<{ $high * 16 + 13 }>,
+            dynamic_entry::<{ $high * 16 + 14 }>,
+            dynamic_entry::<{ $high * 16 + 15 }>,
+        )*]
+    };
+}
+
+static ENTRIES: [extern "x86-interrupt" fn(InterruptFrame); DYNAMIC_VECTORS] =
+    dynamic_entries!(0x4 0x5 0x6 0x7 0x8 0x9 0xA 0xB 0xC 0xD 0xE);
+
+fn dispatch(vector: u8) {
+    let _irq = IrqScope::enter(vector as usize);
+    let slot = SLOTS.lock()[(vector - FIRST_DYNAMIC_VECTOR) as usize];
+    match slot {
+        Some(slot) => (slot.handler)((vector - slot.first) as usize, slot.data),
+        // Raised by a device before it was masked, after its vectors were freed
+        None => UNHANDLED.increment(),
+    }
+    if let Some(apic) = local_apic() {
+        apic.eoi();
+    }
+}
+
+/// # Find Free
+/// The index of the first run of `count` free slots in `used` that starts at a multiple of
+/// `align`
+pub fn find_free(used: &[bool], count: usize, align: usize) -> Option<usize> {
+    (0..used.len()).step_by(align.max(1)).find(|start| {
+        used.get(*start..start + count)
+            .map_or(false, |run| run.iter().all(|used| !used))
+    })
+}
+
+/// # Is Allocated
+/// Whether `vector` was handed out by `allocate_vectors()` and not freed again
+pub fn is_allocated(vector: usize) -> bool {
+    vector
+        .checked_sub(FIRST_DYNAMIC_VECTOR as usize)
+        .and_then(|idx| SLOTS.lock().get(idx).copied())
+        .flatten()
+        .is_some()
+}
+
+/// # Allocate Vectors
+/// Takes `count` consecutive free vectors and routes them to `handler`. The first one is a
+/// multiple of `count`, as a function with multiple MSI messages sets the low bits of the
+/// vector itself. `name` shows up in the interrupt statistics.
+///
+/// ## Returns
+/// - u8 = The first vector
+/// - Error::InvalidArgument = `count` is not a power of two up to `MAX_ALLOCATION`
+/// - Error::DeviceOrResourceBusy = There are not enough free vectors in a row
+pub fn allocate_vectors(
+    count: usize,
+    name: &'static str,
+    handler: Handler,
+    data: usize,
+) -> Result<u8> {
+    if !count.is_power_of_two() || count > MAX_ALLOCATION {
+        return Err(Error::InvalidArgument);
+    }
+    let first = {
+        let mut slots = SLOTS.lock();
+        let used = slots.map(|slot| slot.is_some());
+        // The range starts at a multiple of `MAX_ALLOCATION`, aligned indices are aligned vectors
+        let idx = find_free(&used, count, count).ok_or(Error::DeviceOrResourceBusy)?;
+        let first = FIRST_DYNAMIC_VECTOR + idx as u8;
+        for slot in &mut slots[idx..idx + count] {
+            *slot = Some(Slot {
+                handler,
+                data,
+                first,
+            });
+        }
+        first
+    };
+    for vector in first..first + count as u8 {
+        let idx = (vector - FIRST_DYNAMIC_VECTOR) as usize;
+        set_interrupt_handler(vector as u64, name, ENTRIES[idx]);
+    }
+    Ok(first)
+}
+
+/// # Free Vectors
+/// Returns the `count` vectors starting at `first` taken by `allocate_vectors()`. Whatever
+/// raises them has to be masked already, late interrupts are only counted as unhandled.
+pub fn free_vectors(first: u8, count: usize) {
+    let mut slots = SLOTS.lock();
+    for vector in first..first.saturating_add(count as u8) {
+        if let Some(slot) = vector
+            .checked_sub(FIRST_DYNAMIC_VECTOR)
+            .and_then(|idx| slots.get_mut(idx as usize))
+        {
+            *slot = None;
+            remove_handler_name(vector as usize);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! # MSI
+//! Message signalled interrupts of PCI functions. `allocate_msi()` takes vectors from `irq` and
+//! writes the message that raises them into the MSI-X table or the MSI capability of a function,
+//! which then stops using its interrupt pin. MSI-X is preferred, as every vector can be masked on
+//! its own.
+//!
+//! A message is a write of the vector to an address that selects the local APIC it is delivered
+//! to, fixed delivery and edge triggered. All vectors go to the CPU that allocated them.
+use crate::arch::apic::local_apic;
+use crate::error::{Error, Result};
+use crate::memory::paging::pat::MemoryType;
+use crate::memory::{map_mmio, PhysicalAddress};
+use crate::pci::{
+    MsiControl, MsiXControl, PciCapability, PciCommand, PciConfigRegister, PciDevice,
+};
+
+use super::{allocate_vectors, free_vectors, Handler, MAX_ALLOCATION};
+
+/// Messages written to this address range are delivered to a local APIC
+pub const MSI_ADDRESS_BASE: u64 = 0xFEE0_0000;
+/// Every MSI-X table entry holds address low, address high, data and vector control
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// # Message Address
+/// The address of the local APIC with the id `apic_id`, in physical destination mode
+pub fn message_address(apic_id: u32) -> u64 {
+    MSI_ADDRESS_BASE | (apic_id as u64 & 0xFF) << 12
+}
+
+/// # Message Data
+/// Raises `vector` with fixed delivery, edge triggered, which are all zero bits
+pub fn message_data(vector: u8) -> u32 {
+    vector as u32
+}
+
+/// How the vectors are raised
+enum Kind {
+    Msi {
+        capability: u64,
+    },
+    MsiX {
+        capability: u64,
+        /// The virtual address of the first entry of the table
+        table: u64,
+    },
+}
+
+/// # MSI Vectors
+/// The vectors of a function, masked and freed again when dropped. A driver keeps them for as
+/// long as it uses the function.
+pub struct MsiVectors {
+    device: PciDevice,
+    kind: Kind,
+    first: u8,
+    count: usize,
+    /// The vectors taken from `irq`, `count` rounded up to a power of two
+    allocated: usize,
+}
+
+impl MsiVectors {
+    /// # Vector
+    /// The vector the function raises for message `index`
+    pub fn vector(&self, index: usize) -> Option<u8> {
+        (index < self.count).then(|| self.first + index as u8)
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_msix(&self) -> bool {
+        matches!(self.kind, Kind::MsiX { .. })
+    }
+
+    fn msix_entry(table: u64, index: usize) -> *mut u32 {
+        (table + index as u64 * MSIX_ENTRY_SIZE) as *mut u32
+    }
+}
+
+impl Drop for MsiVectors {
+    fn drop(&mut self) {
+        // Masked first, so nothing raises the vectors once they belong to someone else
+        match self.kind {
+            Kind::MsiX { capability, table } => {
+                let control = self.device.read_u16(capability + 2);
+                self.device
+                    .write_u16(capability + 2, control | MsiXControl::FunctionMask);
+                for index in 0..self.count {
+                    let entry = Self::msix_entry(table, index);
+                    unsafe {
+                        let vector_control = core::ptr::read_volatile(entry.add(3));
+                        core::ptr::write_volatile(entry.add(3), vector_control | MSIX_ENTRY_MASKED);
+                    }
+                }
+                self.device.write_u16(
+                    capability + 2,
+                    control & !(MsiXControl::Enable | MsiXControl::FunctionMask),
+                );
+            }
+            Kind::Msi { capability } => {
+                let control = self.device.read_u16(capability + 2);
+                self.device
+                    .write_u16(capability + 2, control & !MsiControl::Enable);
+            }
+        }
+        free_vectors(self.first, self.allocated);
+    }
+}
+
+/// # Allocate MSI
+/// Takes `count` vectors for `device`, which it raises through MSI-X if it can, else through
+/// MSI, and routes them to `handler`. `name` shows up in the interrupt statistics.
+///
+/// ## Returns
+/// - Error::NoSuchDevice = The function can do neither, or there is no local APIC
+/// - Error::InvalidArgument = `count` is zero, or more than the function can raise. MSI only
+///   raises a power of two of vectors.
+/// - Error::DeviceOrResourceBusy = There are not enough free vectors
+pub fn allocate_msi(
+    device: &PciDevice,
+    count: usize,
+    name: &'static str,
+    handler: Handler,
+    data: usize,
+) -> Result<MsiVectors> {
+    if count == 0 || count > MAX_ALLOCATION {
+        return Err(Error::InvalidArgument);
+    }
+    let apic_id = local_apic().ok_or(Error::NoSuchDevice)?.id();
+    if let Some(capability) = device.capability(PciCapability::MsiX) {
+        let table_size = (device.read_u16(capability + 2) & MsiXControl::TableSizeMask) + 1;
+        if count > table_size as usize {
+            return Err(Error::InvalidArgument);
+        }
+        let table = map_msix_table(device, capability, count)?;
+        // The allocator hands out powers of two, the rest stay unused
+        let allocated = count.next_power_of_two();
+        let first = allocate_vectors(allocated, name, handler, data)?;
+        enable_msix(device, capability, table, first, count, apic_id);
+        return Ok(MsiVectors {
+            device: *device,
+            kind: Kind::MsiX { capability, table },
+            first,
+            count,
+            allocated,
+        });
+    }
+
+    let capability = device
+        .capability(PciCapability::Msi)
+        .ok_or(Error::NoSuchDevice)?;
+    let control = device.read_u16(capability + 2);
+    let capable = 1usize << ((control & MsiControl::MultipleMessageCapable) >> 1);
+    if !count.is_power_of_two() || count > capable {
+        return Err(Error::InvalidArgument);
+    }
+    let first = allocate_vectors(count, name, handler, data)?;
+    enable_msi(device, capability, first, count, apic_id);
+    Ok(MsiVectors {
+        device: *device,
+        kind: Kind::Msi { capability },
+        first,
+        count,
+        allocated: count,
+    })
+}
+
+/// # Is Enabled
+/// Whether `device` raises its interrupts through MSI-X or MSI, which it does for as long as a
+/// driver holds its `MsiVectors`
+pub fn is_enabled(device: &PciDevice) -> bool {
+    let msix = device
+        .capability(PciCapability::MsiX)
+        .map_or(false, |capability| {
+            device.read_u16(capability + 2) & MsiXControl::Enable != 0
+        });
+    let msi = device
+        .capability(PciCapability::Msi)
+        .map_or(false, |capability| {
+            device.read_u16(capability + 2) & MsiControl::Enable != 0
+        });
+    msix || msi
+}
+
+/// Maps the first `count` entries of the MSI-X table of `device`
+fn map_msix_table(device: &PciDevice, capability: u64, count: usize) -> Result<u64> {
+    // The table lives in one of the BARs: The BAR index is in the low 3 bits of the offset
+    let table_info = device.read_u32(capability + 4);
+    let table_phys = device.bar((table_info & 0b111) as u64) + (table_info & !0b111) as u64;
+    Ok(map_mmio(
+        PhysicalAddress::new(table_phys),
+        count as u64 * MSIX_ENTRY_SIZE,
+        MemoryType::Uncacheable,
+    )?
+    .as_u64())
+}
+
+/// Writes the messages of the `count` vectors from `first` into the MSI-X table at `table` and
+/// switches the function over
+fn enable_msix(
+    device: &PciDevice,
+    capability: u64,
+    table: u64,
+    first: u8,
+    count: usize,
+    apic_id: u32,
+) {
+    let control = device.read_u16(capability + 2);
+    // Mask the whole function while the entries are being written
+    device.write_u16(
+        capability + 2,
+        control | MsiXControl::Enable | MsiXControl::FunctionMask,
+    );
+    let address = message_address(apic_id);
+    for index in 0..count {
+        let entry = MsiVectors::msix_entry(table, index);
+        unsafe {
+            let vector_control = core::ptr::read_volatile(entry.add(3));
+            core::ptr::write_volatile(entry.add(3), vector_control | MSIX_ENTRY_MASKED);
+            core::ptr::write_volatile(entry, address as u32);
+            core::ptr::write_volatile(entry.add(1), (address >> 32) as u32);
+            core::ptr::write_volatile(entry.add(2), message_data(first + index as u8));
+            core::ptr::write_volatile(entry.add(3), vector_control & !MSIX_ENTRY_MASKED);
+        }
+    }
+    disable_pin(device);
+    device.write_u16(
+        capability + 2,
+        (control | MsiXControl::Enable) & !MsiXControl::FunctionMask,
+    );
+}
+
+/// Writes the message of the `count` vectors from `first` into the MSI capability and switches
+/// the function over
+fn enable_msi(device: &PciDevice, capability: u64, first: u8, count: usize, apic_id: u32) {
+    let control = device.read_u16(capability + 2) & !MsiControl::Enable;
+    // Disabled while the message is being written
+    device.write_u16(capability + 2, control);
+    let address = message_address(apic_id);
+    device.write_u32(capability + 4, address as u32);
+    let (data, mask) = if control & MsiControl::Address64 != 0 {
+        device.write_u32(capability + 8, (address >> 32) as u32);
+        (capability + 0xC, capability + 0x10)
+    } else {
+        (capability + 8, capability + 0xC)
+    };
+    device.write_u16(data, message_data(first) as u16);
+    if control & MsiControl::PerVectorMasking != 0 {
+        device.write_u32(mask, 0);
+    }
+    disable_pin(device);
+    let enabled = count.trailing_zeros() as u16;
+    device.write_u16(
+        capability + 2,
+        (control & !MsiControl::MultipleMessageEnable) | enabled << 4 | MsiControl::Enable,
+    );
+}
+
+fn disable_pin(device: &PciDevice) {
+    let command = device.read_u16(PciConfigRegister::Command);
+    device.write_u16(
+        PciConfigRegister::Command,
+        command | PciCommand::InterruptDisable,
+    );
+}
@@ -0,0 +1,291 @@
+//! # Softirq
+//! Work that interrupt handlers defer, so they only have to quiet their device with interrupts
+//! disabled. A handler `raise()`s a `SoftirqKind`, and the handler registered for it runs once
+//! the interrupt is over, with interrupts enabled.
+//!
+//! Pending softirqs run on the way out of an interrupt that arrived in user space or on a CPU
+//! waiting in its idle loop, where the interrupted code cannot hold a lock they might need.
+//! Every other interrupt leaves them to `ksoftirqd`, a low priority task, and so does an exit
+//! that already went through `MAX_RESTARTS` rounds of softirqs raised again while they ran.
+//! Only one CPU runs softirqs at a time, the others leave theirs to it.
+//!
+//! `Tasklet`s are one-shot work for drivers on top of `SoftirqKind::Tasklet`. A tasklet is
+//! queued once no matter how often it is scheduled before it runs, and never runs on two CPUs
+//! at once.
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+use crate::arch::tsc;
+use crate::error::Result;
+use crate::scheduler::{self, idle, preempt, IrqSpinLock, Priority, WaitQueue};
+use crate::smp::current_cpu;
+
+pub const SOFTIRQS: usize = 3;
+/// How often pending softirqs are run again before the rest is left to `ksoftirqd`
+pub const MAX_RESTARTS: usize = 8;
+
+// How often each softirq ran
+crate::counter_array!(pub SOFTIRQ_COUNT[SOFTIRQS] = "softirq.count");
+// The longest time from raising a softirq until it ran, in TSC cycles
+crate::counter_array!(pub SOFTIRQ_MAX_LATENCY[SOFTIRQS] = "softirq.max_latency_cycles");
+// The longest time a softirq ran, in TSC cycles
+crate::counter_array!(pub SOFTIRQ_MAX_CYCLES[SOFTIRQS] = "softirq.max_cycles");
+crate::counter!(pub KSOFTIRQD_WAKEUPS = "softirq.ksoftirqd_wakeups");
+crate::counter!(pub UNHANDLED = "softirq.unhandled");
+
+/// # Softirq Kind
+/// The softirqs, run in this order when several are pending
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftirqKind {
+    /// Processes received network frames
+    NetRx = 0,
+    /// Decodes the scancodes of the keyboard
+    Input = 1,
+    /// Runs the scheduled tasklets
+    Tasklet = 2,
+}
+
+impl SoftirqKind {
+    pub const ALL: [Self; SOFTIRQS] = [Self::NetRx, Self::Input, Self::Tasklet];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NetRx => "net-rx",
+            Self::Input => "input",
+            Self::Tasklet => "tasklet",
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+static PENDING: AtomicU32 = AtomicU32::new(0);
+/// The TSC when each softirq was raised while it was not pending
+static RAISED_AT: [AtomicU64; SOFTIRQS] = {
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; SOFTIRQS]
+};
+static HANDLERS: IrqSpinLock<[Option<fn()>; SOFTIRQS]> =
+    crate::named_lock!("softirq_handlers", [None, None, Some(run_tasklets)]);
+/// Set while a CPU runs softirqs
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static KSOFTIRQD: WaitQueue = WaitQueue::new();
+
+/// # Register
+/// Has `handler` run whenever `kind` was raised, replacing the handler it had
+pub fn register(kind: SoftirqKind, handler: fn()) {
+    HANDLERS.lock()[kind as usize] = Some(handler);
+}
+
+/// # Raise
+/// Marks `kind` pending, its handler runs once after the current interrupt at the latest.
+/// Raising it again before it ran does not make it run twice. May be called from interrupt
+/// handlers on any CPU.
+pub fn raise(kind: SoftirqKind) {
+    if PENDING.load(Ordering::Acquire) & kind.bit() == 0 {
+        RAISED_AT[kind as usize].store(tsc::read(), Ordering::Relaxed);
+    }
+    PENDING.fetch_or(kind.bit(), Ordering::AcqRel);
+}
+
+/// # Is Pending
+/// Whether any softirq was raised and did not run yet
+pub fn is_pending() -> bool {
+    PENDING.load(Ordering::Acquire) != 0
+}
+
+/// # Run
+/// Runs the handler of every pending softirq, and again for those raised while they ran, for
+/// at most `rounds` rounds. Returns right away if another CPU runs softirqs.
+///
+/// ## Returns
+/// - bool = Whether softirqs are still pending
+pub fn run(rounds: usize) -> bool {
+    // The other CPUs leave their softirqs to us, they must not wait for us to run again
+    let no_preempt = preempt::disable();
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return is_pending();
+    }
+    for _ in 0..rounds {
+        let pending = PENDING.swap(0, Ordering::AcqRel);
+        if pending == 0 {
+            break;
+        }
+        for kind in SoftirqKind::ALL {
+            if pending & kind.bit() != 0 {
+                run_one(kind);
+            }
+        }
+    }
+    RUNNING.store(false, Ordering::Release);
+    drop(no_preempt);
+    // A softirq raised while the flag was set may have been left to us
+    is_pending()
+}
+
+fn run_one(kind: SoftirqKind) {
+    let index = kind as usize;
+    let start = tsc::read();
+    SOFTIRQ_MAX_LATENCY.record_max(
+        index,
+        start.saturating_sub(RAISED_AT[index].load(Ordering::Relaxed)),
+    );
+    // Copied out, the lock would keep interrupts disabled while it runs
+    let handler = HANDLERS.lock()[index];
+    match handler {
+        Some(handler) => handler(),
+        None => UNHANDLED.increment(),
+    }
+    SOFTIRQ_COUNT.increment(index);
+    SOFTIRQ_MAX_CYCLES.record_max(index, tsc::read().saturating_sub(start));
+}
+
+/// # IRQ Exit
+/// Called at the end of every interrupt handler after its end of interrupt was signalled, with
+/// interrupts disabled. Runs the pending softirqs if the interrupted code is safe to run them
+/// on top of, `from_user` tells whether it was user space, else wakes `ksoftirqd`.
+pub fn irq_exit(from_user: bool) {
+    // The CPU running softirqs finds the new ones as well
+    if !is_pending() || RUNNING.load(Ordering::Acquire) {
+        return;
+    }
+    if !from_user && !idle::is_waiting(current_cpu()) {
+        wake_ksoftirqd();
+        return;
+    }
+    comasm::reload_interrupt_flags();
+    let pending = run(MAX_RESTARTS);
+    comasm::clear_interrupts();
+    if pending {
+        wake_ksoftirqd();
+    }
+}
+
+fn wake_ksoftirqd() {
+    if KSOFTIRQD.wake_one().is_some() {
+        KSOFTIRQD_WAKEUPS.increment();
+    }
+}
+
+crate::initcall! {
+    name: "ksoftirqd",
+    stage: Scheduled,
+    deps: [],
+    fatal: false,
+    init: init_ksoftirqd,
+}
+
+fn init_ksoftirqd() -> Result<()> {
+    let id = scheduler::spawn("ksoftirqd", ksoftirqd);
+    scheduler::set_priority(id, Priority::Low)
+}
+
+/// Runs the softirqs that interrupts could not run themselves
+fn ksoftirqd() {
+    loop {
+        KSOFTIRQD.wait_until(is_pending);
+        while run(MAX_RESTARTS) {
+            scheduler::yield_now();
+        }
+    }
+}
+
+/// Set from `schedule()` until the tasklet starts running
+const SCHEDULED: u8 = 1 << 0;
+/// Set while the tasklet runs
+const TASKLET_RUNNING: u8 = 1 << 1;
+
+/// The scheduled tasklets, the one scheduled last first
+static TASKLETS: AtomicPtr<Tasklet> = AtomicPtr::new(ptr::null_mut());
+
+/// # Tasklet
+/// A function run once from softirq context for every time it was scheduled while it was not
+/// scheduled already
+///
+/// ## Example
+/// ```
+/// static REFILL: Tasklet = Tasklet::new(refill);
+/// REFILL.schedule();
+/// ```
+pub struct Tasklet {
+    func: fn(),
+    state: AtomicU8,
+    /// The tasklet scheduled before this one
+    next: AtomicPtr<Tasklet>,
+}
+
+impl Tasklet {
+    pub const fn new(func: fn()) -> Self {
+        Self {
+            func,
+            state: AtomicU8::new(0),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// # Schedule
+    /// Queues the tasklet and raises `SoftirqKind::Tasklet`. May be called from interrupt
+    /// handlers on any CPU, as well as from the tasklet itself.
+    ///
+    /// ## Returns
+    /// - bool = Whether it was queued, false if it was scheduled already and did not run yet
+    pub fn schedule(&'static self) -> bool {
+        if self.state.fetch_or(SCHEDULED, Ordering::AcqRel) & SCHEDULED != 0 {
+            return false;
+        }
+        self.push();
+        raise(SoftirqKind::Tasklet);
+        true
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.state.load(Ordering::Acquire) & SCHEDULED != 0
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.load(Ordering::Acquire) & TASKLET_RUNNING != 0
+    }
+
+    /// Puts the tasklet on `TASKLETS`, which it is not on, as `SCHEDULED` was clear
+    fn push(&'static self) {
+        let this = self as *const Self as *mut Self;
+        let mut head = TASKLETS.load(Ordering::Acquire);
+        loop {
+            self.next.store(head, Ordering::Relaxed);
+            match TASKLETS.compare_exchange_weak(head, this, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+/// The handler of `SoftirqKind::Tasklet`
+fn run_tasklets() {
+    // Taken as a whole, so only pushes race with each other. Reversed to run them in the order
+    // they were scheduled.
+    let mut list = TASKLETS.swap(ptr::null_mut(), Ordering::AcqRel);
+    let mut ordered: *mut Tasklet = ptr::null_mut();
+    while let Some(tasklet) = unsafe { list.as_ref() } {
+        list = tasklet.next.load(Ordering::Relaxed);
+        tasklet.next.store(ordered, Ordering::Relaxed);
+        ordered = tasklet as *const Tasklet as *mut Tasklet;
+    }
+
+    // Every tasklet is a `&'static` handed to `schedule()`
+    while let Some(tasklet) = unsafe { ordered.as_ref::<'static>() } {
+        ordered = tasklet.next.load(Ordering::Relaxed);
+        if tasklet.state.fetch_or(TASKLET_RUNNING, Ordering::AcqRel) & TASKLET_RUNNING != 0 {
+            // It runs on another CPU, still scheduled, so it is tried again later
+            tasklet.push();
+            raise(SoftirqKind::Tasklet);
+            continue;
+        }
+        // Scheduling it from here on queues it again
+        tasklet.state.fetch_and(!SCHEDULED, Ordering::AcqRel);
+        (tasklet.func)();
+        tasklet.state.fetch_and(!TASKLET_RUNNING, Ordering::Release);
+    }
+}
@@ -0,0 +1,270 @@
+//! # Kernel Log
+//! Everything written to the console is kept in a ring buffer, which `dmesg` prints, and is
+//! passed on to the registered sinks, such as serial ports configured on the command line.
+//! The console itself is not a sink, it writes to the framebuffer (or to COM1 in serial-only
+//! mode) before the text reaches the log.
+//!
+//! Exception handlers never wait for the locks of the log, the code they interrupted may hold
+//! them. What they print while the console is held goes to COM1 directly and into an overflow
+//! buffer of their CPU, which the console takes the text from once it is free again.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::arch::interrupts::exception_safe_lock;
+use crate::error::{Error, Result};
+use crate::power::notifier::{self, PowerAction};
+use crate::scheduler::spin::Mutex;
+use crate::smp::{current_cpu, MAX_CPUS};
+
+/// The size of the ring buffer, older output is overwritten
+pub const LOG_SIZE: usize = 0x1_0000;
+/// The number of sinks that can be registered
+pub const MAX_SINKS: usize = 8;
+/// The size of the overflow buffer of every CPU, anything beyond it is dropped
+pub const OVERFLOW_SIZE: usize = 0x400;
+
+/// # Log Sink
+/// Somewhere the log is copied to
+pub trait LogSink: Sync {
+    /// The name `dmesg --sinks` shows, e.g. `com2`
+    fn name(&self) -> &str;
+    fn write_str(&self, s: &str);
+    /// Waits until everything written so far has left the sink
+    fn flush(&self) {}
+}
+
+/// # Log Buffer
+/// The last `LOG_SIZE` bytes written to the log
+struct LogBuffer {
+    data: [u8; LOG_SIZE],
+    /// The number of bytes ever written, the next one goes to `written % LOG_SIZE`
+    written: u64,
+}
+
+impl LogBuffer {
+    fn push(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.data[(self.written % LOG_SIZE as u64) as usize] = *byte;
+            self.written += 1;
+        }
+    }
+
+    /// The position of the oldest byte that has not been overwritten
+    fn oldest(&self) -> u64 {
+        self.written.saturating_sub(LOG_SIZE as u64)
+    }
+
+    fn read(&self, pos: u64, buf: &mut [u8]) -> (u64, usize) {
+        let pos = pos.max(self.oldest());
+        let len = ((self.written - pos.min(self.written)) as usize).min(buf.len());
+        for (idx, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = self.data[((pos + idx as u64) % LOG_SIZE as u64) as usize];
+        }
+        (pos, len)
+    }
+}
+
+static LOG: Mutex<LogBuffer> = Mutex::new(LogBuffer {
+    data: [0; LOG_SIZE],
+    written: 0,
+});
+const NO_SINK: Option<&'static dyn LogSink> = None;
+static SINKS: Mutex<[Option<&'static dyn LogSink>; MAX_SINKS]> = Mutex::new([NO_SINK; MAX_SINKS]);
+/// Set while a sink is written to, so a sink that logs does not recurse into itself
+static IN_SINKS: AtomicBool = AtomicBool::new(false);
+
+/// # Overflow
+/// What an exception handler printed while the console was held, see `push_overflow()`
+struct Overflow {
+    data: [u8; OVERFLOW_SIZE],
+    len: usize,
+}
+
+const EMPTY_OVERFLOW: Mutex<Overflow> = Mutex::new(Overflow {
+    data: [0; OVERFLOW_SIZE],
+    len: 0,
+});
+static OVERFLOW: [Mutex<Overflow>; MAX_CPUS] = [EMPTY_OVERFLOW; MAX_CPUS];
+/// The bytes in all overflow buffers, which spares the common case looking up the CPU
+static OVERFLOW_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+crate::counter!(pub OVERFLOW_DROPPED = "log.overflow_dropped");
+
+/// # Write
+/// Appends `s` to the log and passes it on to every sink. In exception context, a log that is
+/// held misses `s` and sinks that are held are skipped.
+pub fn write(s: &str) {
+    if let Some(mut log) = exception_safe_lock(&LOG) {
+        log.push(s.as_bytes());
+    }
+    if IN_SINKS.swap(true, Ordering::Acquire) {
+        return;
+    }
+    if let Some(sinks) = exception_safe_lock(&SINKS) {
+        for sink in sinks.iter().flatten() {
+            sink.write_str(s);
+        }
+    }
+    IN_SINKS.store(false, Ordering::Release);
+}
+
+/// # Push Overflow
+/// Keeps `s` in the overflow buffer of the calling CPU, for text that reached neither the
+/// console nor the log. `take_overflow()` gets it back.
+pub fn push_overflow(s: &str) {
+    let mut overflow = match OVERFLOW[current_cpu()].try_lock() {
+        Some(overflow) => overflow,
+        None => {
+            OVERFLOW_DROPPED.add(s.len() as u64);
+            return;
+        }
+    };
+    let mut len = s.len().min(OVERFLOW_SIZE - overflow.len);
+    // Only whole characters, so the buffer stays valid UTF-8
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    let start = overflow.len;
+    overflow.data[start..start + len].copy_from_slice(&s.as_bytes()[..len]);
+    overflow.len += len;
+    OVERFLOW_PENDING.fetch_add(len, Ordering::AcqRel);
+    OVERFLOW_DROPPED.add((s.len() - len) as u64);
+}
+
+/// # Take Overflow
+/// Empties the overflow buffer of the calling CPU and passes what was in it to `f`, if there
+/// was anything
+pub fn take_overflow(f: impl FnOnce(&str)) {
+    if OVERFLOW_PENDING.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    // Copied out, so an exception while `f` runs can fill the buffer again
+    let mut text = [0; OVERFLOW_SIZE];
+    let len = match OVERFLOW[current_cpu()].try_lock() {
+        Some(mut overflow) => {
+            let len = overflow.len;
+            text[..len].copy_from_slice(&overflow.data[..len]);
+            overflow.len = 0;
+            len
+        }
+        None => return,
+    };
+    if len == 0 {
+        return;
+    }
+    OVERFLOW_PENDING.fetch_sub(len, Ordering::AcqRel);
+    // `push_overflow()` only keeps whole characters
+    f(unsafe { core::str::from_utf8_unchecked(&text[..len]) });
+}
+
+/// # Register Sink
+/// Copies everything logged from now on to `sink` as well
+///
+/// ## Returns
+/// - Error::DeviceOrResourceBusy = A sink with the same name is registered already
+/// - Error::OutOfMemory = `MAX_SINKS` sinks are registered already
+pub fn register_sink(sink: &'static dyn LogSink) -> Result<()> {
+    let mut sinks = SINKS.lock();
+    if sinks
+        .iter()
+        .flatten()
+        .any(|other| other.name() == sink.name())
+    {
+        return Err(Error::DeviceOrResourceBusy);
+    }
+    let slot = sinks
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(Error::OutOfMemory)?;
+    *slot = Some(sink);
+    Ok(())
+}
+
+/// # Unregister Sink
+/// Stops copying the log to the sink called `name`
+///
+/// ## Returns
+/// - bool = Whether there was such a sink
+pub fn unregister_sink(name: &str) -> bool {
+    let mut sinks = SINKS.lock();
+    match sinks
+        .iter_mut()
+        .find(|slot| slot.map_or(false, |sink| sink.name() == name))
+    {
+        Some(slot) => {
+            *slot = None;
+            true
+        }
+        None => false,
+    }
+}
+
+/// # Sinks
+/// Calls `f` with every registered sink
+pub fn for_each_sink(mut f: impl FnMut(&dyn LogSink)) {
+    // Copied out, so `f` may log
+    let sinks = *SINKS.lock();
+    for sink in sinks.iter().flatten() {
+        f(*sink);
+    }
+}
+
+/// # Flush
+/// Passes what the calling CPU kept in its overflow buffer on to the log and waits for every sink
+/// to get rid of its text, before the machine powers off. The other CPUs may have been stopped
+/// while holding a lock, so sinks that are held are skipped.
+pub fn flush() {
+    take_overflow(write);
+    if let Some(sinks) = SINKS.try_lock() {
+        for sink in sinks.iter().flatten() {
+            sink.flush();
+        }
+    }
+}
+
+crate::initcall! {
+    name: "klog-flush",
+    stage: EarlyMemory,
+    deps: ["power-notifiers"],
+    fatal: false,
+    init: init_flush,
+}
+
+/// Flushes the log before the machine goes down, ahead of everything else
+fn init_flush() -> Result<()> {
+    notifier::register("klog", notifier::PRIORITY_FLUSH, |_: PowerAction| flush());
+    Ok(())
+}
+
+/// # Read
+/// Copies the log from `pos`, the number of bytes written before it, into `buf`. A position
+/// that has been overwritten already is moved up to the oldest byte that is left.
+///
+/// ## Returns
+/// - (u64, usize) = The position of the first byte copied and how many were copied
+pub fn read(pos: u64, buf: &mut [u8]) -> (u64, usize) {
+    LOG.lock().read(pos, buf)
+}
+
+/// # Try Read
+/// Like `read()`, but gives up instead of waiting if the log is locked
+pub fn try_read(pos: u64, buf: &mut [u8]) -> Option<(u64, usize)> {
+    Some(LOG.try_lock()?.read(pos, buf))
+}
+
+/// # Read Tail
+/// Copies the last `buf.len()` bytes of the log into `buf`, for the panic handler. Gives up
+/// instead of waiting if the log is locked, its holder may be what panicked.
+///
+/// ## Returns
+/// - usize = How many bytes were copied
+pub fn try_read_tail(buf: &mut [u8]) -> Option<usize> {
+    let log = LOG.try_lock()?;
+    let pos = log.written.saturating_sub(buf.len() as u64);
+    Some(log.read(pos, buf).1)
+}
+
+/// # Written
+/// The number of bytes ever written to the log, the end position for `read()`
+pub fn written() -> u64 {
+    LOG.lock().written
+}
@@ -0,0 +1,269 @@
+//! # Lockstat
+//! Contention statistics of `IrqSpinLock`s, built with the `lockstat` feature. Every lock
+//! belongs to a class, which counts how often its locks were taken, how often they had to be
+//! waited for and for how many TSC cycles. Locks made with `named_lock!` share the class of
+//! their name, the others get the class of the place they were first taken at. A wait of
+//! `TRACE_THRESHOLD` cycles or more is also recorded to the trace as `LockContended`, with the
+//! class and the place of the waiter.
+//!
+//! Without the feature the locks carry no class and nothing is counted. With it, taking a free
+//! lock costs a check whether its class is known yet and an atomic add.
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Once;
+
+use crate::scheduler::spin::Mutex;
+
+#[cfg(feature = "lockstat")]
+use core::{panic::Location, sync::atomic::AtomicUsize};
+
+/// Whether the kernel was built with the `lockstat` feature
+pub const ENABLED: bool = cfg!(feature = "lockstat");
+/// Waits of at least this many TSC cycles are recorded to the trace
+pub const TRACE_THRESHOLD: u64 = 10_000;
+/// The number of classes, locks of classes beyond it are counted in `OVERFLOW`
+pub const MAX_CLASSES: usize = 128;
+/// The number of places the trace tells waiters apart by
+pub const MAX_SITES: usize = 256;
+/// The slot of a `Registry` that takes everything that did not fit
+pub const OVERFLOW: usize = 0;
+
+/// # Key
+/// What a class or a site is told apart by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// The name given to `named_lock!`
+    Name(&'static str),
+    /// A file and a line
+    Location(&'static str, u32),
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Key::Name(name) => write!(f, "{}", name),
+            Key::Location(file, line) => write!(f, "{}:{}", file, line),
+        }
+    }
+}
+
+/// # Lock Stats
+/// What the locks of a class went through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LockStats {
+    pub acquisitions: u64,
+    /// The acquisitions that found the lock held
+    pub contended: u64,
+    /// The TSC cycles spent waiting in total
+    pub wait_cycles: u64,
+    pub max_wait: u64,
+}
+
+struct Entry {
+    key: Once<Key>,
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait_cycles: AtomicU64,
+    max_wait: AtomicU64,
+}
+
+impl Entry {
+    const fn new() -> Self {
+        Self {
+            key: Once::new(),
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            wait_cycles: AtomicU64::new(0),
+            max_wait: AtomicU64::new(0),
+        }
+    }
+}
+
+/// # Registry
+/// `N` classes or sites, which are registered once and never removed. Slot `OVERFLOW` has no
+/// key.
+pub struct Registry<const N: usize> {
+    /// The number of slots in use, `OVERFLOW` included. Held while registering.
+    used: Mutex<usize>,
+    entries: [Entry; N],
+}
+
+impl<const N: usize> Registry<N> {
+    pub const fn new() -> Self {
+        const EMPTY: Entry = Entry::new();
+        Self {
+            used: Mutex::new(OVERFLOW + 1),
+            entries: [EMPTY; N],
+        }
+    }
+
+    /// # Register
+    /// The slot of `key`, which takes a free one if the key has none yet
+    ///
+    /// ## Returns
+    /// - OVERFLOW = Every slot is taken
+    pub fn register(&self, key: Key) -> usize {
+        let mut used = self.used.lock();
+        if let Some(slot) = (OVERFLOW + 1..*used).find(|&slot| self.key(slot) == Some(key)) {
+            return slot;
+        }
+        if *used == N {
+            return OVERFLOW;
+        }
+        let slot = *used;
+        self.entries[slot].key.call_once(|| key);
+        *used += 1;
+        slot
+    }
+
+    /// The key of `slot`, `None` for `OVERFLOW` and free slots
+    pub fn key(&self, slot: usize) -> Option<Key> {
+        self.entries.get(slot)?.key.get().copied()
+    }
+
+    #[inline(always)]
+    pub fn acquired(&self, slot: usize) {
+        self.entries[slot]
+            .acquisitions
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a wait of `cycles` for a lock of `slot`
+    pub fn waited(&self, slot: usize, cycles: u64) {
+        let entry = &self.entries[slot];
+        entry.contended.fetch_add(1, Ordering::Relaxed);
+        entry.wait_cycles.fetch_add(cycles, Ordering::Relaxed);
+        entry.max_wait.fetch_max(cycles, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self, slot: usize) -> LockStats {
+        let entry = &self.entries[slot];
+        LockStats {
+            acquisitions: entry.acquisitions.load(Ordering::Relaxed),
+            contended: entry.contended.load(Ordering::Relaxed),
+            wait_cycles: entry.wait_cycles.load(Ordering::Relaxed),
+            max_wait: entry.max_wait.load(Ordering::Relaxed),
+        }
+    }
+
+    /// # Snapshot
+    /// The slot, key and stats of every slot in use
+    pub fn snapshot(&self) -> Vec<(usize, Option<Key>, LockStats)> {
+        let used = *self.used.lock();
+        (0..used)
+            .map(|slot| (slot, self.key(slot), self.stats(slot)))
+            .collect()
+    }
+
+    /// # Clear
+    /// Resets the stats, the slots stay registered
+    pub fn clear(&self) {
+        for entry in self.entries.iter() {
+            entry.acquisitions.store(0, Ordering::Relaxed);
+            entry.contended.store(0, Ordering::Relaxed);
+            entry.wait_cycles.store(0, Ordering::Relaxed);
+            entry.max_wait.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(feature = "lockstat")]
+static CLASSES: Registry<MAX_CLASSES> = Registry::new();
+#[cfg(feature = "lockstat")]
+static SITES: Registry<MAX_SITES> = Registry::new();
+
+/// # Lock Class
+/// The class of a lock, which is registered the first time the lock is taken
+#[cfg(feature = "lockstat")]
+pub struct LockClass {
+    name: Option<&'static str>,
+    /// The slot in `CLASSES` plus one, zero until it is registered
+    slot: AtomicUsize,
+}
+
+#[cfg(feature = "lockstat")]
+impl LockClass {
+    pub const fn new(name: Option<&'static str>) -> Self {
+        Self {
+            name,
+            slot: AtomicUsize::new(0),
+        }
+    }
+
+    /// # Acquired
+    /// Counts an acquisition of a lock of the class, which is taken at `location`
+    ///
+    /// ## Returns
+    /// - usize = The slot of the class
+    #[inline(always)]
+    pub fn acquired(&self, location: &'static Location<'static>) -> usize {
+        let slot = match self.slot.load(Ordering::Relaxed) {
+            0 => self.register(location),
+            slot => slot - 1,
+        };
+        CLASSES.acquired(slot);
+        slot
+    }
+
+    #[cold]
+    fn register(&self, location: &'static Location<'static>) -> usize {
+        let key = match self.name {
+            Some(name) => Key::Name(name),
+            None => Key::Location(location.file(), location.line()),
+        };
+        let slot = CLASSES.register(key);
+        self.slot.store(slot + 1, Ordering::Relaxed);
+        slot
+    }
+}
+
+/// # Contended
+/// Counts a wait of `cycles` for a lock of the class in `slot` at `waiter`, and records it to
+/// the trace if it took `TRACE_THRESHOLD` cycles or more
+#[cfg(feature = "lockstat")]
+#[cold]
+pub fn contended(slot: usize, waiter: &'static Location<'static>, cycles: u64) {
+    CLASSES.waited(slot, cycles);
+    if cycles >= TRACE_THRESHOLD {
+        let site = SITES.register(Key::Location(waiter.file(), waiter.line()));
+        crate::trace_event!(LockContended, slot | site << 32, cycles);
+    }
+}
+
+#[cfg(feature = "lockstat")]
+fn registries() -> Option<(&'static Registry<MAX_CLASSES>, &'static Registry<MAX_SITES>)> {
+    Some((&CLASSES, &SITES))
+}
+
+#[cfg(not(feature = "lockstat"))]
+fn registries() -> Option<(&'static Registry<MAX_CLASSES>, &'static Registry<MAX_SITES>)> {
+    None
+}
+
+/// # Classes
+/// The slot, key and stats of every class, empty without the `lockstat` feature
+pub fn classes() -> Vec<(usize, Option<Key>, LockStats)> {
+    registries().map_or_else(Vec::new, |(classes, _)| classes.snapshot())
+}
+
+/// # Sites
+/// The slot and key of every place a waiter was recorded to the trace at
+pub fn sites() -> Vec<(usize, Option<Key>)> {
+    registries().map_or_else(Vec::new, |(_, sites)| {
+        sites
+            .snapshot()
+            .into_iter()
+            .map(|(slot, key, _)| (slot, key))
+            .collect()
+    })
+}
+
+/// # Clear
+/// Resets the stats of every class
+pub fn clear() {
+    if let Some((classes, _)) = registries() {
+        classes.clear();
+    }
+}
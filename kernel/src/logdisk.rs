@@ -0,0 +1,449 @@
+//! # Log Disk
+//! Keeps the kernel log on a partition, so it survives a reboot or a crash. The partition is
+//! the one given by `logdev=` on the command line (e.g. `logdev=disk0p2`), or else the first
+//! one with the `Guid::ESQUE_LOG` type. A low priority task appends what was logged every
+//! `FLUSH_INTERVAL_MS`, and the panic handler appends the rest through the polled block path.
+//! `dmesg --previous` reads back what the previous boot logged.
+//!
+//! The partition is a ring of records, one per block after the superblock in block 0. Record
+//! `seq` lives in block `1 + seq % records`. The superblock holds the sequence number of the
+//! oldest record (the tail) and of the next one (the head), and is only written once per flush.
+//! Records written after it, by a flush that was cut short, are found again when the disk is
+//! opened by scanning forward from the head. Every record carries a checksum, so a record that
+//! was torn while being written ends the scan and is skipped when reading.
+//!
+//! Superblock, little endian:
+//! - 0: `MAGIC`
+//! - 8: CRC32 of bytes 12..64
+//! - 12: `VERSION`
+//! - 16: The block size the ring was formatted with
+//! - 20: The number of the boot, incremented every time the disk is opened
+//! - 24: The number of records in the ring
+//! - 32: The head, the sequence number of the next record
+//! - 40: The tail, the sequence number of the oldest record
+//! - 48: The sequence number of the first record of the current boot
+//!
+//! Record, little endian:
+//! - 0: `RECORD_MAGIC`
+//! - 4: CRC32 of the rest of the block, from byte 8 on
+//! - 8: The sequence number
+//! - 16: The number of the boot that wrote it
+//! - 20: The length of the text
+//! - 22: Reserved
+//! - 24: The text, a piece of the log that may end in the middle of a line or a character
+use alloc::sync::Arc;
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::block::partition::{Guid, PartitionType};
+use crate::block::{self, BlockDevice};
+use crate::error::{Error, Result};
+use crate::klog::{self, LogSink};
+use crate::math::crc32;
+use crate::scheduler::{self, sync, Priority};
+use crate::{cmdline, info, time, warn};
+
+/// The command line option naming the partition the log is kept on, `logdev=disk0p2`
+pub const OPTION: &str = "logdev";
+/// How often the flusher task appends what was logged
+pub const FLUSH_INTERVAL_MS: u64 = 5000;
+pub const MAGIC: [u8; 8] = *b"ESQLOGD1";
+pub const VERSION: u32 = 1;
+pub const RECORD_MAGIC: u32 = 0x4452_4C45;
+/// The size of the header in front of the text of a record
+pub const HEADER_SIZE: usize = 24;
+/// The largest block size a ring can be on, the buffers are on the stack so a panic does not
+/// allocate
+pub const MAX_BLOCK_SIZE: usize = 4096;
+/// The bytes of the log read at a time
+const CHUNK_SIZE: usize = 1024;
+/// The size of the part of block 0 the superblock uses
+const SUPERBLOCK_SIZE: usize = 64;
+/// The blocks a partition needs, the superblock and two records
+const MIN_BLOCKS: u64 = 3;
+
+crate::counter!(pub RECORDS = "logdisk.records");
+crate::counter!(pub WRITE_ERRORS = "logdisk.write_errors");
+crate::counter!(pub SKIPPED = "logdisk.skipped_records");
+
+/// # Superblock
+/// Block 0 of the partition, see the module documentation for the layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Superblock {
+    pub block_size: u32,
+    pub boot: u32,
+    pub records: u64,
+    pub head: u64,
+    pub tail: u64,
+    pub boot_start: u64,
+}
+
+impl Superblock {
+    /// # Encode
+    /// Writes the superblock to the start of `block`
+    pub fn encode(&self, block: &mut [u8]) {
+        block[..SUPERBLOCK_SIZE].fill(0);
+        block[0..8].copy_from_slice(&MAGIC);
+        block[12..16].copy_from_slice(&VERSION.to_le_bytes());
+        block[16..20].copy_from_slice(&self.block_size.to_le_bytes());
+        block[20..24].copy_from_slice(&self.boot.to_le_bytes());
+        block[24..32].copy_from_slice(&self.records.to_le_bytes());
+        block[32..40].copy_from_slice(&self.head.to_le_bytes());
+        block[40..48].copy_from_slice(&self.tail.to_le_bytes());
+        block[48..56].copy_from_slice(&self.boot_start.to_le_bytes());
+        let crc = crc32(&block[12..SUPERBLOCK_SIZE]);
+        block[8..12].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// # Decode
+    /// Reads the superblock from the start of `block`
+    ///
+    /// ## Returns
+    /// - None = There is no superblock of this version, or it is damaged
+    pub fn decode(block: &[u8]) -> Option<Self> {
+        if block[0..8] != MAGIC
+            || read_u32(block, 8) != crc32(&block[12..SUPERBLOCK_SIZE])
+            || read_u32(block, 12) != VERSION
+        {
+            return None;
+        }
+        Some(Self {
+            block_size: read_u32(block, 16),
+            boot: read_u32(block, 20),
+            records: read_u64(block, 24),
+            head: read_u64(block, 32),
+            tail: read_u64(block, 40),
+            boot_start: read_u64(block, 48),
+        })
+    }
+}
+
+/// # Record
+/// A record as read from the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub seq: u64,
+    pub boot: u32,
+    pub text: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// # Encode
+    /// Fills `block` with a record holding as much of `text` as fits
+    ///
+    /// ## Returns
+    /// - usize = How much of `text` the record holds
+    pub fn encode(block: &mut [u8], seq: u64, boot: u32, text: &[u8]) -> usize {
+        let len = text.len().min(block.len() - HEADER_SIZE);
+        block.fill(0);
+        block[0..4].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        block[8..16].copy_from_slice(&seq.to_le_bytes());
+        block[16..20].copy_from_slice(&boot.to_le_bytes());
+        block[20..22].copy_from_slice(&(len as u16).to_le_bytes());
+        block[HEADER_SIZE..HEADER_SIZE + len].copy_from_slice(&text[..len]);
+        let crc = crc32(&block[8..]);
+        block[4..8].copy_from_slice(&crc.to_le_bytes());
+        len
+    }
+
+    /// # Decode
+    /// Reads the record in `block`
+    ///
+    /// ## Returns
+    /// - None = The block holds no record, or one that is damaged or was torn while written
+    pub fn decode(block: &'a [u8]) -> Option<Self> {
+        if read_u32(block, 0) != RECORD_MAGIC || read_u32(block, 4) != crc32(&block[8..]) {
+            return None;
+        }
+        let len = u16::from_le_bytes(block[20..22].try_into().unwrap()) as usize;
+        Some(Self {
+            seq: read_u64(block, 8),
+            boot: read_u32(block, 16),
+            text: block.get(HEADER_SIZE..HEADER_SIZE + len)?,
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// # Log Disk
+/// The ring on a partition
+pub struct LogDisk {
+    device: Arc<dyn BlockDevice>,
+    superblock: Superblock,
+}
+
+impl LogDisk {
+    /// # Open
+    /// Opens the ring on `device` for a new boot, formatting it if it holds none. Records the
+    /// last flush wrote after the superblock are kept.
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = The device is too small for a ring, or its blocks are larger
+    ///   than `MAX_BLOCK_SIZE`
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        let block_size = device.block_size();
+        if device.block_count() < MIN_BLOCKS
+            || block_size <= HEADER_SIZE.max(SUPERBLOCK_SIZE)
+            || block_size > MAX_BLOCK_SIZE
+        {
+            return Err(Error::InvalidArgument);
+        }
+        let mut block = [0; MAX_BLOCK_SIZE];
+        let block = &mut block[..block_size];
+        device.read_blocks(0, block)?;
+        let records = device.block_count() - 1;
+        let superblock = match Superblock::decode(block) {
+            Some(superblock)
+                if superblock.block_size as usize == block_size
+                    && superblock.records == records =>
+            {
+                superblock
+            }
+            _ => {
+                info!("logdisk: Formatting a ring of {} records", records);
+                Superblock {
+                    block_size: block_size as u32,
+                    boot: 0,
+                    records,
+                    head: 0,
+                    tail: 0,
+                    boot_start: 0,
+                }
+            }
+        };
+
+        let mut disk = Self { device, superblock };
+        // Records written after the superblock, up to the first one that is missing or torn
+        loop {
+            let head = disk.superblock.head;
+            disk.read_record(head, block)?;
+            match Record::decode(block) {
+                Some(record) if record.seq == head && record.boot == superblock.boot => {
+                    disk.superblock.head += 1
+                }
+                _ => break,
+            }
+        }
+        let opened = &mut disk.superblock;
+        opened.tail = opened.tail.max(opened.head.saturating_sub(opened.records));
+        opened.boot = opened.boot.wrapping_add(1);
+        opened.boot_start = opened.head;
+        disk.write_superblock(block, false)?;
+        Ok(disk)
+    }
+
+    /// # Superblock
+    /// The superblock as it is on the disk after the last append
+    pub fn superblock(&self) -> Superblock {
+        self.superblock
+    }
+
+    /// # Append
+    /// Appends `text` in as many records as it takes, overwriting the oldest ones once the ring
+    /// is full, and then the superblock. A `polled` append uses `write_blocks_polled()`.
+    pub fn append(&mut self, text: &[u8], polled: bool) -> Result<()> {
+        let mut block = [0; MAX_BLOCK_SIZE];
+        let block = &mut block[..self.device.block_size()];
+        let mut done = 0;
+        while done < text.len() {
+            let seq = self.superblock.head;
+            done += Record::encode(block, seq, self.superblock.boot, &text[done..]);
+            self.write_block(1 + seq % self.superblock.records, block, polled)?;
+            RECORDS.increment();
+            self.superblock.head += 1;
+            if self.superblock.head - self.superblock.tail > self.superblock.records {
+                self.superblock.tail += 1;
+            }
+        }
+        self.write_superblock(block, polled)
+    }
+
+    /// # Read Boot
+    /// Calls `f` with the text of every record boot `boot` wrote that is still in the ring,
+    /// oldest first. Damaged records are skipped.
+    ///
+    /// ## Returns
+    /// - usize = The number of records read
+    pub fn read_boot(&self, boot: u32, mut f: impl FnMut(&[u8])) -> Result<usize> {
+        let mut block = [0; MAX_BLOCK_SIZE];
+        let block = &mut block[..self.device.block_size()];
+        let mut count = 0;
+        for seq in self.superblock.tail..self.superblock.head {
+            self.read_record(seq, block)?;
+            match Record::decode(block) {
+                Some(record) if record.seq == seq => {
+                    if record.boot == boot {
+                        f(record.text);
+                        count += 1;
+                    }
+                }
+                _ => SKIPPED.increment(),
+            }
+        }
+        Ok(count)
+    }
+
+    fn read_record(&self, seq: u64, block: &mut [u8]) -> Result<()> {
+        self.device
+            .read_blocks(1 + seq % self.superblock.records, block)
+    }
+
+    fn write_superblock(&self, block: &mut [u8], polled: bool) -> Result<()> {
+        block.fill(0);
+        self.superblock.encode(block);
+        self.write_block(0, block, polled)
+    }
+
+    fn write_block(&self, lba: u64, block: &[u8], polled: bool) -> Result<()> {
+        if polled {
+            self.device.write_blocks_polled(lba, block)
+        } else {
+            self.device.write_blocks(lba, block)
+        }
+    }
+}
+
+/// The ring the log is appended to, once the flusher task found it
+static DISK: sync::Mutex<Option<LogDisk>> = sync::Mutex::new(None);
+/// The position in the log up to which it was appended
+static FLUSHED: AtomicU64 = AtomicU64::new(0);
+
+/// # Log Disk Sink
+/// Lets `klog::flush()` append the rest of the log before the machine goes down
+struct LogDiskSink;
+
+impl LogSink for LogDiskSink {
+    fn name(&self) -> &str {
+        "logdisk"
+    }
+
+    /// The text is read from the log when it is flushed
+    fn write_str(&self, _s: &str) {}
+
+    /// Called while the log holds the sinks, so this never waits. If the flusher task is
+    /// appending, the rest is left to it.
+    fn flush(&self) {
+        let _ = flush(true);
+    }
+}
+
+static SINK: LogDiskSink = LogDiskSink;
+
+crate::initcall! {
+    name: "logdisk",
+    stage: Scheduled,
+    deps: [],
+    fatal: false,
+    init: init_logdisk,
+}
+
+fn init_logdisk() -> Result<()> {
+    klog::register_sink(&SINK)?;
+    let id = scheduler::spawn("logdisk", flush_task);
+    scheduler::set_priority(id, Priority::Low)
+}
+
+/// Looks for the partition until it appears, then appends the log every `FLUSH_INTERVAL_MS`
+fn flush_task() {
+    loop {
+        let attached = DISK.lock().is_some();
+        if attached || attach() {
+            if flush(false).is_err() {
+                WRITE_ERRORS.increment();
+            }
+        }
+        time::sleep_ms(FLUSH_INTERVAL_MS);
+    }
+}
+
+/// Opens the partition the log is kept on, if there is one
+///
+/// ## Returns
+/// - bool = Whether the log is appended to a partition now
+fn attach() -> bool {
+    let device = match cmdline::value(OPTION) {
+        Some(name) => block::find(name),
+        None => block::devices()
+            .into_iter()
+            .find(|entry| entry.partition == Some(PartitionType::Gpt(Guid::ESQUE_LOG)))
+            .map(|entry| entry.device),
+    };
+    let device = match device {
+        Some(device) => device,
+        None => return false,
+    };
+    let mut disk = DISK.lock();
+    match LogDisk::open(device) {
+        Ok(opened) => {
+            let superblock = opened.superblock();
+            *disk = Some(opened);
+            drop(disk);
+            info!(
+                "logdisk: Appending boot {} to a ring of {} records",
+                superblock.boot, superblock.records
+            );
+            true
+        }
+        Err(e) => {
+            drop(disk);
+            warn!("logdisk: Failed to open the partition: {}", e.text());
+            false
+        }
+    }
+}
+
+/// Appends what was logged since the last flush. A `polled` flush gives up instead of waiting
+/// for the disk or the log, uses the polled block path and does not allocate.
+fn flush(polled: bool) -> Result<()> {
+    let mut guard = if polled {
+        DISK.try_lock().ok_or(Error::OperationWouldBlock)?
+    } else {
+        DISK.lock()
+    };
+    let disk = guard.as_mut().ok_or(Error::NoSuchDevice)?;
+    let end = if polled { u64::MAX } else { klog::written() };
+    let mut buf = [0; CHUNK_SIZE];
+    let mut pos = FLUSHED.load(Ordering::Acquire);
+    while pos < end {
+        let read = if polled {
+            klog::try_read(pos, &mut buf).ok_or(Error::OperationWouldBlock)?
+        } else {
+            klog::read(pos, &mut buf)
+        };
+        let (start, len) = read;
+        if len == 0 {
+            break;
+        }
+        disk.append(&buf[..len], polled)?;
+        pos = start + len as u64;
+        FLUSHED.store(pos, Ordering::Release);
+    }
+    Ok(())
+}
+
+/// # Panic Flush
+/// Appends the rest of the log from the panic handler, without waiting for anything
+pub fn panic_flush() {
+    if flush(true).is_err() {
+        WRITE_ERRORS.increment();
+    }
+}
+
+/// # Read Previous
+/// Calls `f` with the text the previous boot appended, oldest first
+///
+/// ## Returns
+/// - usize = The number of records read
+/// - Error::NoSuchDevice = The log is not kept on a partition
+pub fn read_previous(f: impl FnMut(&[u8])) -> Result<usize> {
+    let guard = DISK.lock();
+    let disk = guard.as_ref().ok_or(Error::NoSuchDevice)?;
+    disk.read_boot(disk.superblock.boot.wrapping_sub(1), f)
+}
@@ -13,6 +13,7 @@
 #![feature(stmt_expr_attributes)]
 #![feature(custom_test_frameworks)]
 #![feature(const_ptr_offset_from)]
+#![feature(const_refs_to_cell)]
 #![feature(int_log)]
 #![feature(slice_pattern)]
 #![feature(const_btree_new)]
@@ -30,6 +31,7 @@
 #![reexport_test_harness_main = "test_main"]
 
 pub mod arch;
+pub mod earlylog;
 pub mod error;
 pub mod math;
 extern crate alloc;
@@ -44,18 +46,44 @@ pub mod tests;
 use alloc::vec::Vec;
 pub use bks::Handover;
 pub mod acpi;
+pub mod bench;
+pub mod block;
+pub mod boot_modules;
+pub mod breadcrumb;
+pub mod buildinfo;
+pub mod cmdline;
 pub mod config;
+pub mod crashlog;
 pub mod device;
 pub mod drivers;
+pub mod entropy;
 pub mod env;
+pub mod fs;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
 pub mod heap;
 pub mod initramfs;
 pub mod iobus;
+pub mod klog;
+pub mod lockstat;
+pub mod logdisk;
+pub mod net;
+pub mod power;
+pub mod profile;
+pub mod rand;
 pub mod scheduler;
+pub mod shell;
 pub mod smp;
+pub mod stats;
+pub mod symbols;
 #[cfg(test)]
 pub mod test;
+pub mod time;
+pub mod trace;
+pub mod tty;
+pub mod uefi_rt;
 pub mod userspace;
+pub mod watchdog;
 use bks::PAGE_SIZE;
 pub use config::config;
 pub use esys::process::Process;
@@ -64,21 +92,37 @@ use userspace::launchpad::Launchpad;
 pub use userspace::pid::{KernelPid, Pid};
 
 pub mod ipc;
+pub mod irq;
 pub mod syscall;
 
 pub fn main() -> ! {
-    init::acpi::init_acpi();
+    profile::init_profile();
     // -#---#@@- Enables Memory Allocation -@@#---#-
-    init::heap::init_heap();
+    init::error::check("heap", init::heap::init_heap());
+    memory::userspace::init_mmap_base();
+    framebuffer::enable_backing_store();
+    init::run_all();
+    scheduler::init_scheduler();
+    time::init_timers();
+    rand::init_reseed();
+    watchdog::init_watchdog();
 
     Thread::new(ipc::kernel_ipc_handler).launch();
 
-    drivers::init_drivers();
+    init::initcall::run_scheduled();
+    fs::init_fs();
+    net::init_net();
     initramfs::load_initramfs();
 
     // -#---#@@- Enables System Calls -@@#---#-
     arch::init::syscall::init_syscalls();
     initramfs::load_system_space_applications();
+    breadcrumb::reach(breadcrumb::Milestone::BootComplete);
+
+    if cmdline::flag(bench::BENCH_FLAG) {
+        bench::run_all();
+        test::QemuExitCode::Success.exit_qemu();
+    }
 
     for i in unsafe { initramfs::INITRAMFS.lock().assume_init_mut().entries() } {
         debug!("{:?}", i.filename);
@@ -104,7 +148,11 @@ pub fn main() -> ! {
         load_userspace();
     }
 
+    shell::init();
     loop {
+        drivers::virtio::console::poll();
+        shell::poll();
+        scheduler::yield_now();
         unsafe { comasm::halt() };
     }
 }
@@ -27,3 +27,134 @@ pub const fn align_down(addr: u64, align: u64) -> u64 {
 pub const fn is_aligned(addr: u64, align: u64) -> bool {
     align_down(addr, align) == addr
 }
+
+/// The lookup table of the reflected CRC32 (IEEE 802.3) polynomial
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// # CRC32
+/// The CRC32 checksum used by GPT, Ethernet and zlib
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// # Parse U64
+/// A number in hex with `0x`, as addresses are given, or in decimal
+pub fn parse_u64(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// # Grouped Hex
+/// Displays a number in hexadecimal with its digits in groups of four, e.g. `0xffff_8000_0010_0000`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupedHex(pub u64);
+
+impl core::fmt::Display for GroupedHex {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let digits = (64 - self.0.leading_zeros() as usize + 3) / 4;
+        f.write_str("0x")?;
+        for idx in (0..digits.max(1)).rev() {
+            write!(f, "{:x}", (self.0 >> (idx * 4)) & 0xF)?;
+            if idx != 0 && idx % 4 == 0 {
+                f.write_str("_")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+const BYTE_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// # Byte Size
+/// Displays a number of bytes in the largest binary unit it reaches, e.g. `4 KiB` or
+/// `1.50 MiB`. Fractions are cut off after two decimals, so the size is never overstated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl core::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let exponent = ((63 - self.0.max(1).leading_zeros()) / 10) as usize;
+        let unit = 1u64 << (exponent * 10);
+        let whole = self.0 / unit;
+        let rest = self.0 % unit;
+        if rest == 0 {
+            write!(f, "{} {}", whole, BYTE_UNITS[exponent])
+        } else {
+            let hundredths = rest as u128 * 100 / unit as u128;
+            write!(f, "{}.{:02} {}", whole, hundredths, BYTE_UNITS[exponent])
+        }
+    }
+}
+
+/// The bytes in a row of a hexdump
+pub const HEXDUMP_ROW: usize = 16;
+
+/// # Hex Row
+/// Displays a row of a hexdump: the address of its first byte, up to `HEXDUMP_ROW` bytes in
+/// hex and the same bytes as ASCII, with `.` for those that are not printable. A short last
+/// row is padded, so its ASCII lines up with the rows above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HexRow<'a> {
+    pub addr: u64,
+    pub bytes: &'a [u8],
+}
+
+impl core::fmt::Display for HexRow<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:#018x}:", self.addr)?;
+        for idx in 0..HEXDUMP_ROW {
+            if idx == HEXDUMP_ROW / 2 {
+                f.write_str(" ")?;
+            }
+            match self.bytes.get(idx) {
+                Some(byte) => write!(f, " {:02x}", byte)?,
+                None => f.write_str("   ")?,
+            }
+        }
+        f.write_str("  |")?;
+        for byte in self.bytes.iter().take(HEXDUMP_ROW) {
+            match *byte {
+                b' '..=b'~' => write!(f, "{}", *byte as char)?,
+                _ => f.write_str(".")?,
+            }
+        }
+        f.write_str("|")
+    }
+}
+
+/// # Hexdump
+/// Passes `bytes` to `sink` in rows of `HEXDUMP_ROW`, the first of them labelled `addr_label`.
+/// Nothing is allocated, so it works wherever the rows can be written, e.g. to a pager.
+///
+/// ## Returns
+/// - bool = Whether all rows were passed, false once `sink` returned false
+pub fn hexdump(addr_label: u64, bytes: &[u8], mut sink: impl FnMut(HexRow) -> bool) -> bool {
+    bytes.chunks(HEXDUMP_ROW).enumerate().all(|(row, bytes)| {
+        sink(HexRow {
+            addr: addr_label.wrapping_add((row * HEXDUMP_ROW) as u64),
+            bytes,
+        })
+    })
+}
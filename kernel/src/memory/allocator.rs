@@ -1,6 +1,15 @@
-use core::alloc::GlobalAlloc;
+//! # Allocator
+//! The global allocator, the segregated heap or, with the `linked-list-heap` feature, the
+//! linked list heap it replaced
+//!
+//! The segregated heap accounts every allocation to the tag it was made under, see
+//! `heap::tag`. They are accounted by their usable size, so no layout is needed to free them.
+use core::alloc::{GlobalAlloc, Layout};
 
+#[cfg(feature = "linked-list-heap")]
 use crate::heap::GLOBAL_HEAP;
+#[cfg(not(feature = "linked-list-heap"))]
+use crate::heap::{segregated::SEGREGATED_HEAP, tag};
 
 #[global_allocator]
 static ALLOCATOR: HeapAllocator = HeapAllocator;
@@ -8,17 +17,65 @@ static ALLOCATOR: HeapAllocator = HeapAllocator;
 #[derive(Copy, Clone, Default, Debug)]
 pub struct HeapAllocator;
 
+#[cfg(not(feature = "linked-list-heap"))]
 unsafe impl GlobalAlloc for HeapAllocator {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let ptr = GLOBAL_HEAP.lock().assume_init_mut().malloc(layout.size());
-        ptr as *mut u8
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let tag = tag::current();
+        let mut heap = SEGREGATED_HEAP.lock();
+        let ptr = heap.malloc(layout);
+        if let Some(size) = heap.usable_size(ptr) {
+            heap.set_tag(ptr, tag);
+            tag::charge(tag, size);
+        }
+        ptr
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: core::alloc::Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        free(ptr);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let mut heap = SEGREGATED_HEAP.lock();
+        let old = heap.usable_size(ptr).zip(heap.tag_of(ptr));
+        let new = heap.realloc(ptr, layout, new_size);
+        if let (Some((old, tag)), Some(size)) = (old, heap.usable_size(new)) {
+            tag::resize(tag, old, size);
+        }
+        new
+    }
+}
+
+#[cfg(feature = "linked-list-heap")]
+unsafe impl GlobalAlloc for HeapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = GLOBAL_HEAP.lock();
+        heap.assume_init_mut()
+            .malloc_aligned(layout.size(), layout.align()) as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
         GLOBAL_HEAP.lock().assume_init_mut().free(ptr as u64);
     }
 }
 
+/// # Free
+/// Returns `ptr` to the global allocator without its layout, which neither heap needs
+///
+/// ## Safety
+/// `ptr` has to come from the global allocator and must not be used afterwards
+pub unsafe fn free(ptr: *mut u8) {
+    #[cfg(not(feature = "linked-list-heap"))]
+    {
+        let mut heap = SEGREGATED_HEAP.lock();
+        if let (Some(size), Some(tag)) = (heap.usable_size(ptr), heap.tag_of(ptr)) {
+            tag::uncharge(tag, size);
+        }
+        heap.free(ptr);
+    }
+    #[cfg(feature = "linked-list-heap")]
+    GLOBAL_HEAP.lock().assume_init_mut().free(ptr as u64);
+}
+
 #[alloc_error_handler]
 fn out_of_memory(layout: ::core::alloc::Layout) -> ! {
     panic!(
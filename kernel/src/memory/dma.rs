@@ -0,0 +1,325 @@
+//! # DMA
+//! Physically contiguous memory for devices that access memory on their own.
+//!
+//! DMA on x86_64 is cache coherent, so buffers are used through the write-back direct map.
+//! Several structures a device needs can share an allocation, `DmaLayout` places them.
+//!
+//! There is no IOMMU to remap memory a device cannot reach, such as memory above 4 GiB for a
+//! device with 32 bit addresses. A `BounceBuffer` stages such transfers in memory it can reach.
+use bks::PAGE_SIZE;
+
+use super::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use super::{phys_to_virt, virt_to_phys, PhysicalAddress, VirtualAddress};
+use crate::error::{Error, Result};
+
+crate::counter!(pub DMA_DIRECT = "dma.direct");
+crate::counter!(pub DMA_BOUNCED = "dma.bounced");
+crate::counter!(pub DMA_BOUNCE_BYTES = "dma.bounce_bytes");
+
+/// # DMA Constraints
+/// Where a device can access memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaConstraints {
+    /// The highest address the device can reach
+    pub max_phys_addr: u64,
+    /// The alignment of the start of the buffer, a power of two. Buffers are at least page
+    /// aligned.
+    pub align: u64,
+}
+
+impl DmaConstraints {
+    /// Anywhere in physical memory
+    pub const ANY: Self = Self {
+        max_phys_addr: u64::MAX,
+        align: PAGE_SIZE,
+    };
+    /// Below 4 GiB, for devices that take 32 bit addresses
+    pub const BELOW_4GIB: Self = Self {
+        max_phys_addr: u32::MAX as u64,
+        align: PAGE_SIZE,
+    };
+}
+
+impl DmaConstraints {
+    /// # Reaches
+    /// Whether the device can access the `len` bytes at the kernel address `addr`, which only
+    /// have to be virtually contiguous
+    pub fn reaches(&self, addr: u64, len: usize) -> bool {
+        let end = addr + len as u64;
+        let mut page = addr & !(PAGE_SIZE - 1);
+        while page < end {
+            let last = (page + PAGE_SIZE).min(end) - 1;
+            if virt_to_phys(VirtualAddress::new(last)).as_u64() > self.max_phys_addr {
+                return false;
+            }
+            page += PAGE_SIZE;
+        }
+        true
+    }
+}
+
+impl Default for DmaConstraints {
+    fn default() -> Self {
+        Self::ANY
+    }
+}
+
+/// # DMA Buffer
+/// Physically contiguous memory, freed when dropped
+pub struct DmaBuffer {
+    phys: PhysicalAddress,
+    pages: usize,
+    len: usize,
+}
+
+impl DmaBuffer {
+    /// # New
+    /// Allocates `len` bytes that meet `constraints`, their content is undefined
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = `len` is 0 or the alignment is not a power of two
+    /// - Error::OutOfMemory = There is no free range that meets `constraints`
+    pub fn new(len: usize, constraints: DmaConstraints) -> Result<Self> {
+        if len == 0 || !constraints.align.is_power_of_two() {
+            return Err(Error::InvalidArgument);
+        }
+        let pages = (len + PAGE_SIZE as usize - 1) / PAGE_SIZE as usize;
+        let align = constraints.align.max(PAGE_SIZE);
+        let phys = {
+            let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+            let allocator = unsafe { allocator.assume_init_mut() };
+            if constraints.max_phys_addr == u64::MAX {
+                allocator.request_contiguous_pages(pages, align, constraints.max_phys_addr)
+            } else {
+                allocator.request_contiguous_pages_below(pages, align, constraints.max_phys_addr)
+            }
+        }
+        .ok_or(Error::OutOfMemory)?;
+        Ok(Self {
+            phys: PhysicalAddress::new(phys),
+            pages,
+            len,
+        })
+    }
+
+    /// # New Zeroed
+    /// Like `new()`, but the buffer is zeroed
+    pub fn new_zeroed(len: usize, constraints: DmaConstraints) -> Result<Self> {
+        let mut buffer = Self::new(len, constraints)?;
+        buffer.as_mut_slice().fill(0);
+        Ok(buffer)
+    }
+
+    /// # With Layout
+    /// Allocates a zeroed buffer for the sub-buffers of `layout` that meets `constraints`
+    pub fn with_layout(layout: &DmaLayout, constraints: DmaConstraints) -> Result<Self> {
+        Self::new_zeroed(
+            layout.size(),
+            DmaConstraints {
+                align: constraints.align.max(layout.align()),
+                ..constraints
+            },
+        )
+    }
+
+    /// The address the device has to be given
+    pub fn phys(&self) -> PhysicalAddress {
+        self.phys
+    }
+
+    /// The address the kernel accesses the buffer at
+    pub fn virt(&self) -> VirtualAddress {
+        phys_to_virt(self.phys)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_ptr<T>(&self) -> *const T {
+        self.virt().as_u64() as *const T
+    }
+
+    pub fn as_mut_ptr<T>(&self) -> *mut T {
+        self.virt().as_u64() as *mut T
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+
+    fn check(&self, range: DmaRange) {
+        assert!(
+            range.offset + range.len <= self.len,
+            "DMA range {:?} exceeds the buffer of {} bytes",
+            range,
+            self.len
+        );
+    }
+
+    /// # Phys Of
+    /// The address the device has to be given for `range`
+    pub fn phys_of(&self, range: DmaRange) -> PhysicalAddress {
+        self.check(range);
+        PhysicalAddress::new(self.phys.as_u64() + range.offset as u64)
+    }
+
+    pub fn ptr_of<T>(&self, range: DmaRange) -> *mut T {
+        self.check(range);
+        (self.virt().as_u64() + range.offset as u64) as *mut T
+    }
+
+    pub fn slice(&self, range: DmaRange) -> &[u8] {
+        &self.as_slice()[range.offset..range.offset + range.len]
+    }
+
+    pub fn slice_mut(&mut self, range: DmaRange) -> &mut [u8] {
+        &mut self.as_mut_slice()[range.offset..range.offset + range.len]
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            PAGE_FRAME_ALLOCATOR
+                .lock()
+                .assume_init_mut()
+                .free_pages(self.phys.as_u64(), self.pages);
+        }
+    }
+}
+
+/// # Bounce Buffer
+/// The memory a device transfers from or to in place of a buffer it may not be able to reach.
+/// If it can reach the buffer, that is used directly.
+pub struct BounceBuffer {
+    addr: u64,
+    len: usize,
+    /// Whether the device reads the buffer, rather than writing it
+    to_device: bool,
+    bounce: Option<DmaBuffer>,
+}
+
+impl BounceBuffer {
+    /// # New
+    /// Prepares the `len` bytes at the kernel address `addr` for a transfer by a device with
+    /// `constraints`. If they are out of its reach, they are staged in a buffer that is not,
+    /// and copied there right away if the device reads them (`to_device`).
+    ///
+    /// ## Returns
+    /// - Error::OutOfMemory = There is no free memory the device can reach
+    ///
+    /// ## Safety
+    /// `addr` has to be valid for `len` bytes until the transfer is finished
+    pub unsafe fn new(
+        addr: u64,
+        len: usize,
+        constraints: DmaConstraints,
+        to_device: bool,
+    ) -> Result<Self> {
+        if len == 0 || constraints.reaches(addr, len) {
+            DMA_DIRECT.increment();
+            return Ok(Self {
+                addr,
+                len,
+                to_device,
+                bounce: None,
+            });
+        }
+        let mut bounce = DmaBuffer::new(len, constraints)?;
+        if to_device {
+            core::ptr::copy_nonoverlapping(addr as *const u8, bounce.as_mut_ptr(), len);
+        }
+        DMA_BOUNCED.increment();
+        DMA_BOUNCE_BYTES.add(len as u64);
+        Ok(Self {
+            addr,
+            len,
+            to_device,
+            bounce: Some(bounce),
+        })
+    }
+
+    /// The kernel address the device has to be given, translated like any other buffer
+    pub fn addr(&self) -> u64 {
+        match &self.bounce {
+            Some(bounce) => bounce.virt().as_u64(),
+            None => self.addr,
+        }
+    }
+
+    pub fn is_bounced(&self) -> bool {
+        self.bounce.is_some()
+    }
+
+    /// # Finish
+    /// Completes a successful transfer: What the device wrote into the bounce buffer is copied
+    /// to the original buffer
+    pub fn finish(self) {
+        if let (Some(bounce), false) = (&self.bounce, self.to_device) {
+            unsafe {
+                core::ptr::copy_nonoverlapping(bounce.as_ptr(), self.addr as *mut u8, self.len)
+            };
+        }
+    }
+}
+
+/// # DMA Range
+/// A sub-buffer placed by `DmaLayout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaRange {
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// # DMA Layout
+/// Places sub-buffers one after another, each aligned as the device requires, so they can
+/// share a single `DmaBuffer`
+#[derive(Debug, Clone, Copy)]
+pub struct DmaLayout {
+    size: usize,
+    align: u64,
+}
+
+impl DmaLayout {
+    pub const fn new() -> Self {
+        Self {
+            size: 0,
+            align: PAGE_SIZE,
+        }
+    }
+
+    /// # Push
+    /// Places `len` bytes aligned to `align`, which must be a power of two
+    pub fn push(&mut self, len: usize, align: usize) -> DmaRange {
+        assert!(align.is_power_of_two());
+        let offset = (self.size + align - 1) & !(align - 1);
+        self.size = offset + len;
+        self.align = self.align.max(align as u64);
+        DmaRange { offset, len }
+    }
+
+    /// The number of bytes the sub-buffers take up
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The alignment the buffer has to start at
+    pub fn align(&self) -> u64 {
+        self.align
+    }
+}
+
+impl Default for DmaLayout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
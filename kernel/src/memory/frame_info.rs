@@ -0,0 +1,239 @@
+//! # Frame Info
+//! What is known about every frame of physical memory: How many references it has, what it is
+//! used for and who owns it. The array has an entry for each frame below the top of RAM, holes
+//! of the memory map included, so the entry of a frame is found by its number alone. It is
+//! placed by `PageFrameAllocator::read_memory_map()`, right behind the bitmap of the allocator.
+//!
+//! The allocator keeps the entries in step with its bitmap: A frame it hands out has a
+//! reference and no flags, a frame it frees is `FREE` and one it reserves `RESERVED`. The users
+//! of a frame set the flags of what they use it for, and take and drop further references of
+//! frames they share. `verify()` cross-checks the array against the bitmap.
+use alloc::vec::Vec;
+use bks::PAGE_SIZE;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use spin::Once;
+
+use super::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use super::{phys_to_virt, Frame, PhysicalAddress};
+
+/// The number of mismatches `verify()` keeps, the others are only counted
+pub const MAX_REPORTED: usize = 16;
+
+static FRAMES: Once<&'static [FrameInfo]> = Once::new();
+
+bitflags::bitflags! {
+    pub struct FrameFlags: u32 {
+        /// The allocator may hand the frame out
+        const FREE = 1;
+        /// Backs the heap, the pages of its classes or a large allocation
+        const SLAB = 1 << 1;
+        /// Holds a page of the page cache, the owner is the id of the device
+        const PAGE_CACHE = 1 << 2;
+        /// Not RAM, or RAM that is never handed out
+        const RESERVED = 1 << 3;
+        /// The zero frame, shared by every untouched anonymous page that was read
+        const ZERO = 1 << 4;
+    }
+}
+
+/// # Frame Info
+/// The metadata of a frame
+#[repr(C)]
+pub struct FrameInfo {
+    refcount: AtomicU32,
+    flags: AtomicU32,
+    /// What the frame belongs to, its meaning depends on the flags
+    owner: AtomicU64,
+}
+
+impl FrameInfo {
+    const fn new(flags: FrameFlags) -> Self {
+        Self {
+            refcount: AtomicU32::new(0),
+            flags: AtomicU32::new(flags.bits()),
+            owner: AtomicU64::new(0),
+        }
+    }
+
+    pub fn refcount(&self) -> u32 {
+        self.refcount.load(Ordering::Acquire)
+    }
+
+    /// # Get
+    /// Takes a reference of the frame
+    ///
+    /// ## Returns
+    /// - u32 = The references there are now
+    pub fn get(&self) -> u32 {
+        self.refcount.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// # Put
+    /// Drops a reference of the frame, the one who drops the last may free it
+    ///
+    /// ## Returns
+    /// - u32 = The references that are left
+    pub fn put(&self) -> u32 {
+        let old = self.refcount.fetch_sub(1, Ordering::AcqRel);
+        debug_assert!(
+            old > 0,
+            "frame_info: Dropped a reference of an unreferenced frame"
+        );
+        old - 1
+    }
+
+    pub fn flags(&self) -> FrameFlags {
+        FrameFlags::from_bits_truncate(self.flags.load(Ordering::Acquire))
+    }
+
+    pub fn insert(&self, flags: FrameFlags) {
+        self.flags.fetch_or(flags.bits(), Ordering::AcqRel);
+    }
+
+    pub fn remove(&self, flags: FrameFlags) {
+        self.flags.fetch_and(!flags.bits(), Ordering::AcqRel);
+    }
+
+    pub fn owner(&self) -> u64 {
+        self.owner.load(Ordering::Acquire)
+    }
+
+    pub fn set_owner(&self, owner: u64) {
+        self.owner.store(owner, Ordering::Release);
+    }
+
+    /// # Reset
+    /// Gives the frame `flags` and `refcount` references and no owner, for the allocator
+    pub(crate) fn reset(&self, flags: FrameFlags, refcount: u32) {
+        self.owner.store(0, Ordering::Relaxed);
+        self.flags.store(flags.bits(), Ordering::Relaxed);
+        self.refcount.store(refcount, Ordering::Release);
+    }
+}
+
+/// # Init
+/// Places the array of `count` entries at `base`, every frame `FREE`, like the bitmap of the
+/// allocator after it was cleared
+///
+/// ## Safety
+/// `base` must be usable memory of at least `size_for(count)` bytes that nothing else uses
+pub(crate) unsafe fn init(base: PhysicalAddress, count: usize) {
+    FRAMES.call_once(|| {
+        let entries = phys_to_virt(base).as_u64() as *mut FrameInfo;
+        for i in 0..count {
+            entries.add(i).write(FrameInfo::new(FrameFlags::FREE));
+        }
+        core::slice::from_raw_parts(entries, count)
+    });
+}
+
+/// # Size For
+/// The bytes the array takes for `count` frames
+pub const fn size_for(count: usize) -> usize {
+    count * core::mem::size_of::<FrameInfo>()
+}
+
+/// # Frames
+/// The entry of every frame, by frame number. Empty before the allocator read the memory map.
+pub fn frames() -> &'static [FrameInfo] {
+    FRAMES.get().copied().unwrap_or(&[])
+}
+
+/// # Frame Info
+/// The entry of `frame`
+///
+/// ## Panics
+/// If `frame` is beyond the top of RAM, or the array was not placed yet
+pub fn frame_info(frame: Frame) -> &'static FrameInfo {
+    let frames = frames();
+    let index = (frame.start().as_u64() / PAGE_SIZE) as usize;
+    debug_assert!(
+        index < frames.len(),
+        "frame_info: {} is beyond the top of RAM",
+        frame.start()
+    );
+    &frames[index]
+}
+
+/// # Try Frame Info
+/// The entry of the frame at `addr`, `None` if it is beyond the top of RAM, such as the memory
+/// of a device
+pub fn try_frame_info(addr: u64) -> Option<&'static FrameInfo> {
+    frames().get((addr / PAGE_SIZE) as usize)
+}
+
+/// # Report
+/// What `verify()` found
+#[derive(Debug, Default, Clone)]
+pub struct Report {
+    pub frames: usize,
+    pub free: usize,
+    pub reserved: usize,
+    pub slab: usize,
+    pub page_cache: usize,
+    /// The frames marked `ZERO`, which should be one at most
+    pub zero: usize,
+    /// The references of the zero frame
+    pub zero_refs: u32,
+    pub mismatches: usize,
+    /// The first `MAX_REPORTED` mismatches and what is wrong with them
+    pub reported: Vec<(Frame, &'static str)>,
+}
+
+impl Report {
+    fn mismatch(&mut self, frame: Frame, problem: &'static str) {
+        self.mismatches += 1;
+        if self.reported.len() < MAX_REPORTED {
+            self.reported.push((frame, problem));
+        }
+    }
+}
+
+/// # Verify
+/// Walks the array with the allocator locked and checks every entry against the bitmap of the
+/// allocator and itself
+pub fn verify() -> Report {
+    // Nothing may be allocated with the allocator locked, the heap may need it
+    let mut report = Report {
+        reported: Vec::with_capacity(MAX_REPORTED),
+        ..Report::default()
+    };
+    let allocator = PAGE_FRAME_ALLOCATOR.lock();
+    let allocator = unsafe { allocator.assume_init_ref() };
+    for (index, info) in frames().iter().enumerate() {
+        let addr = index as u64 * PAGE_SIZE;
+        let frame = Frame::from_start_unchecked(PhysicalAddress::new(addr));
+        let flags = info.flags();
+        let refcount = info.refcount();
+        report.frames += 1;
+        if flags.contains(FrameFlags::FREE) {
+            report.free += 1;
+        }
+        if flags.contains(FrameFlags::RESERVED) {
+            report.reserved += 1;
+        }
+        if flags.contains(FrameFlags::SLAB) {
+            report.slab += 1;
+        }
+        if flags.contains(FrameFlags::PAGE_CACHE) {
+            report.page_cache += 1;
+        }
+        if flags.contains(FrameFlags::ZERO) {
+            report.zero += 1;
+            report.zero_refs += refcount;
+        }
+
+        match (allocator.is_free(addr), flags.contains(FrameFlags::FREE)) {
+            (true, false) => report.mismatch(frame, "free in the bitmap, but not FREE"),
+            (false, true) => report.mismatch(frame, "FREE, but allocated in the bitmap"),
+            _ => {}
+        }
+        if flags.contains(FrameFlags::FREE) && (flags != FrameFlags::FREE || refcount != 0) {
+            report.mismatch(frame, "FREE, but in use");
+        }
+        if !flags.intersects(FrameFlags::FREE | FrameFlags::RESERVED) && refcount == 0 {
+            report.mismatch(frame, "allocated without a reference");
+        }
+    }
+    report
+}
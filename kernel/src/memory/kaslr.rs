@@ -0,0 +1,86 @@
+//! # KASLR
+//! Randomizes where the heap, the kernel stacks and the mmap region are placed, so their
+//! addresses cannot be guessed. `nokaslr` on the command line places them at fixed addresses,
+//! which keeps backtraces reproducible.
+use bks::PAGE_SIZE;
+
+use crate::arch::HEAP_ADDRESS;
+use crate::cmdline;
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::VirtualAddress;
+use crate::rand;
+use crate::{info, warn};
+
+/// The command line flag that disables the randomization
+pub const OPTION: &str = "nokaslr";
+/// The alignment of randomized bases
+pub const ALIGN: u64 = 0x20_0000;
+/// The randomized part of the stack top of kernel tasks, in bytes
+pub const MAX_STACK_OFFSET: usize = 0x400;
+/// The stack has to stay aligned to this
+const STACK_ALIGN: usize = 16;
+
+pub fn is_enabled() -> bool {
+    !cmdline::flag(OPTION)
+}
+
+/// # Random Slot
+/// A random `ALIGN`ed address in `lo..hi`, `lo` rounded up if there is none
+pub fn random_slot(lo: u64, hi: u64) -> u64 {
+    let first = (lo + ALIGN - 1) / ALIGN;
+    let end = hi / ALIGN;
+    if first >= end {
+        return first * ALIGN;
+    }
+    rand::range(first, end) * ALIGN
+}
+
+/// # Randomize
+/// `base` moved up by a random multiple of `ALIGN` less than `span`. Logged as `name`.
+pub fn randomize(name: &str, base: u64, span: u64) -> u64 {
+    if !is_enabled() {
+        return base;
+    }
+    let randomized = random_slot(base, base + span);
+    info!("kaslr: {} at {}", name, VirtualAddress::new(randomized));
+    randomized
+}
+
+/// # Heap Base
+/// Where the heap of `pages` pages is placed: In free memory at a random `ALIGN`ed address,
+/// or at `HEAP_ADDRESS` without KASLR
+pub fn heap_base(pages: usize) -> u64 {
+    if !is_enabled() {
+        return HEAP_ADDRESS;
+    }
+    let size = pages as u64 * PAGE_SIZE;
+    let base = {
+        let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+        let allocator = unsafe { allocator.assume_init_mut() };
+        let start = random_slot(0, allocator.total_memory().saturating_sub(size));
+        // Anything below the random start is taken if everything above it is in use
+        allocator
+            .request_contiguous_pages_from(pages, ALIGN, start, u64::MAX)
+            .or_else(|| allocator.request_contiguous_pages_from(pages, ALIGN, 0, u64::MAX))
+    };
+    match base {
+        Some(base) => {
+            info!("kaslr: heap at {}", VirtualAddress::new(base));
+            base
+        }
+        None => {
+            warn!("kaslr: No room for the heap, placing it at the default address");
+            HEAP_ADDRESS
+        }
+    }
+}
+
+/// # Stack Offset
+/// How far below the top of its stack a kernel task starts, a random multiple of 16 below
+/// `MAX_STACK_OFFSET`
+pub fn stack_offset() -> usize {
+    if !is_enabled() {
+        return 0;
+    }
+    rand::range(0, (MAX_STACK_OFFSET / STACK_ALIGN) as u64) as usize * STACK_ALIGN
+}
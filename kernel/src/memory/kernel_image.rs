@@ -0,0 +1,199 @@
+//! # Kernel Image
+//! Where the sections of the kernel are loaded, as the linker script records it. Everything that
+//! needs to know whether an address is kernel code, or which memory the kernel itself occupies,
+//! asks `sections()` instead of declaring the linker symbols again.
+//!
+//! The map is checked once at boot: Every section has to lie in the image, start above the one
+//! before it and, where the linker script aligns it, start and end on a page boundary. A map
+//! that does not is a broken linker script, so `init` panics.
+use bks::PAGE_SIZE;
+
+use crate::debug;
+use crate::math::ByteSize;
+use crate::memory::{virt_to_phys, PhysicalAddress, VirtualAddress};
+
+extern "C" {
+    // Defined in the Linker Script
+    static _KERNEL_START: u8;
+    static _KERNEL_END: u8;
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __tdata_start: u8;
+    static __tdata_end: u8;
+    static __data_start: u8;
+    static __data_end: u8;
+    static __bss_start: u8;
+    static __bss_end: u8;
+    static __stack_start: u8;
+    static __stack_end: u8;
+}
+
+/// # Section
+/// The addresses `start..end` a part of the kernel is loaded at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub name: &'static str,
+    pub start: VirtualAddress,
+    pub end: VirtualAddress,
+}
+
+impl Section {
+    fn new(name: &'static str, start: &'static u8, end: &'static u8) -> Self {
+        Self {
+            name,
+            start: VirtualAddress::new(start as *const u8 as u64),
+            end: VirtualAddress::new(end as *const u8 as u64),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.end.as_u64().saturating_sub(self.start.as_u64())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        (self.start.as_u64()..self.end.as_u64()).contains(&address)
+    }
+
+    /// # Physical
+    /// Where the section is in physical memory
+    pub fn physical(&self) -> PhysicalAddress {
+        virt_to_phys(self.start)
+    }
+}
+
+/// # Invalid Map
+/// What is wrong with a section map, naming the sections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidMap {
+    /// The section ends before it starts
+    Reversed(&'static str),
+    /// The section does not start or end on a page boundary
+    Unaligned(&'static str),
+    /// The second section starts before the first one ends
+    Overlapping(&'static str, &'static str),
+    /// The section is not part of the image
+    OutsideImage(&'static str),
+}
+
+impl core::fmt::Display for InvalidMap {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Reversed(name) => write!(f, "{} ends before it starts", name),
+            Self::Unaligned(name) => write!(f, "{} is not page aligned", name),
+            Self::Overlapping(first, second) => write!(f, "{} overlaps {}", second, first),
+            Self::OutsideImage(name) => write!(f, "{} is outside the kernel image", name),
+        }
+    }
+}
+
+/// # Section Map
+/// The sections of the kernel image. Sections the image does not need to know about, like the
+/// unwind tables or the counters of `stats`, are only part of `image`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionMap {
+    /// Everything the kernel occupies
+    pub image: Section,
+    pub text: Section,
+    pub rodata: Section,
+    pub data: Section,
+    pub bss: Section,
+    /// The interrupt stacks of the boot CPU
+    pub stack: Section,
+    /// The template of the thread local data every CPU gets a copy of
+    pub percpu: Section,
+}
+
+impl SectionMap {
+    /// # In Order
+    /// The sections in the order the linker script lays them out, and whether their end is
+    /// page aligned
+    pub fn in_order(&self) -> [(Section, bool); 6] {
+        [
+            (self.text, true),
+            (self.rodata, true),
+            // Only as long as the data it holds
+            (self.percpu, false),
+            (self.data, true),
+            (self.bss, true),
+            (self.stack, true),
+        ]
+    }
+
+    /// # Validate
+    /// Checks that the sections are in the image, ascending and do not overlap, and that they
+    /// are page aligned where the linker script aligns them
+    pub fn validate(&self) -> Result<(), InvalidMap> {
+        let aligned = |address: VirtualAddress| address.as_u64() % PAGE_SIZE == 0;
+        if self.image.end < self.image.start {
+            return Err(InvalidMap::Reversed(self.image.name));
+        }
+        let mut previous: Option<Section> = None;
+        for (section, end_aligned) in self.in_order() {
+            if section.end < section.start {
+                return Err(InvalidMap::Reversed(section.name));
+            }
+            if !aligned(section.start) || (end_aligned && !aligned(section.end)) {
+                return Err(InvalidMap::Unaligned(section.name));
+            }
+            if section.start < self.image.start || section.end > self.image.end {
+                return Err(InvalidMap::OutsideImage(section.name));
+            }
+            if let Some(previous) = previous {
+                if section.start < previous.end {
+                    return Err(InvalidMap::Overlapping(previous.name, section.name));
+                }
+            }
+            previous = Some(section);
+        }
+        Ok(())
+    }
+}
+
+/// # Sections
+/// The section map of the running kernel
+pub fn sections() -> SectionMap {
+    unsafe {
+        SectionMap {
+            image: Section::new("image", &_KERNEL_START, &_KERNEL_END),
+            text: Section::new(".text", &__text_start, &__text_end),
+            rodata: Section::new(".rodata", &__rodata_start, &__rodata_end),
+            data: Section::new(".data", &__data_start, &__data_end),
+            bss: Section::new(".bss", &__bss_start, &__bss_end),
+            stack: Section::new(".stack", &__stack_start, &__stack_end),
+            percpu: Section::new(".tdata", &__tdata_start, &__tdata_end),
+        }
+    }
+}
+
+/// # Is Text
+/// Whether `address` is kernel code
+pub fn is_text(address: u64) -> bool {
+    sections().text.contains(address)
+}
+
+/// # Init
+/// Checks the section map and logs it
+///
+/// ## Panics
+/// If the map is not valid, the linker script or the linking went wrong
+pub fn init() {
+    let map = sections();
+    if let Err(err) = map.validate() {
+        panic!("kernel_image: Invalid section map, {}: {:#x?}", err, map);
+    }
+    for section in core::iter::once(map.image).chain(map.in_order().map(|(section, _)| section)) {
+        debug!(
+            "kernel_image: {:<8} {:#x}..{:#x} ({})",
+            section.name,
+            section.start.as_u64(),
+            section.end.as_u64(),
+            ByteSize(section.len())
+        );
+    }
+}
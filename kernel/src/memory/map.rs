@@ -0,0 +1,254 @@
+//! # Memory Map
+//! A normalized view of the UEFI memory map.
+//!
+//! The firmware is free to hand over entries unsorted and even overlapping. `MemoryMap` sorts
+//! them, resolves overlaps in favour of the more restrictive kind (see `MemoryKind::precedence`)
+//! and merges neighbouring regions of the same kind. This is done without allocating, as the
+//! map is needed before the heap exists.
+use bks::{EfiMemoryDescriptor, EfiMemoryDescriptors, Handover, MemoryType, PAGE_SIZE};
+
+use crate::math::ByteSize;
+use crate::memory::PhysicalAddress;
+
+/// The maximum number of descriptors taken into account, every further one is ignored
+pub const MAX_DESCRIPTORS: usize = 256;
+const MAX_BOUNDARIES: usize = MAX_DESCRIPTORS * 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Free to be used by the frame allocator
+    Usable,
+    /// Must never be touched
+    Reserved,
+    /// Holds ACPI tables, can be reused once they have been parsed
+    AcpiReclaimable,
+    /// Must be preserved for the firmware
+    AcpiNvs,
+    /// Memory mapped devices, no RAM
+    Mmio,
+    /// The kernel image, the initramfs and everything else the bootloader loaded for us
+    KernelAndModules,
+    /// The bootloader and the UEFI boot services, can be reused once the handover has been
+    /// consumed and the kernel runs on its own stack
+    BootloaderReclaimable,
+}
+
+impl MemoryKind {
+    /// # From EFI
+    /// Collapses a UEFI memory type into a `MemoryKind`. Returns `None` for entries that do not
+    /// describe any memory.
+    pub fn from_efi(ty: MemoryType) -> Option<Self> {
+        Some(match ty {
+            MemoryType::ConventialMemory => Self::Usable,
+            // The boot services data still contains the stack we are running on
+            MemoryType::LoaderCode
+            | MemoryType::BootServicesCode
+            | MemoryType::BootServicesData => Self::BootloaderReclaimable,
+            MemoryType::LoaderData => Self::KernelAndModules,
+            MemoryType::ACPIReclaimMemory => Self::AcpiReclaimable,
+            MemoryType::ACPIMemoryNVS => Self::AcpiNvs,
+            MemoryType::MemoryMappedIO | MemoryType::MemoryMappedIOPortSpace => Self::Mmio,
+            MemoryType::EmptyTemporaryMemory => return None,
+            _ => Self::Reserved,
+        })
+    }
+
+    /// # Precedence
+    /// Which kind wins if two entries overlap: The higher, the more restrictive
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::Usable => 0,
+            Self::BootloaderReclaimable => 1,
+            Self::AcpiReclaimable => 2,
+            Self::KernelAndModules => 3,
+            Self::AcpiNvs => 4,
+            Self::Mmio => 5,
+            Self::Reserved => 6,
+        }
+    }
+
+    /// Whether this kind describes RAM (as opposed to device memory)
+    pub fn is_ram(self) -> bool {
+        self != Self::Mmio
+    }
+}
+
+/// # Memory Region
+/// A page aligned, contiguous range of physical memory of a single kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: PhysicalAddress,
+    pub pages: u64,
+    pub kind: MemoryKind,
+}
+
+impl MemoryRegion {
+    pub fn size(&self) -> u64 {
+        self.pages * PAGE_SIZE
+    }
+
+    /// The first address after the region
+    pub fn end(&self) -> PhysicalAddress {
+        PhysicalAddress::new(self.start.as_u64() + self.size())
+    }
+}
+
+/// # Memory Map
+/// An iterator over the normalized memory map, yielding sorted and non-overlapping regions
+#[derive(Clone)]
+pub struct MemoryMap {
+    descriptors: EfiMemoryDescriptors,
+    /// Every address at which a region starts or ends, sorted and deduplicated
+    boundaries: [u64; MAX_BOUNDARIES],
+    len: usize,
+    idx: usize,
+}
+
+/// # Memory Map
+/// Returns the normalized memory map of `handover`
+pub fn memory_map(handover: &mut Handover) -> MemoryMap {
+    MemoryMap::new(handover.descriptors())
+}
+
+impl MemoryMap {
+    pub fn new(descriptors: EfiMemoryDescriptors) -> Self {
+        let mut map = Self {
+            descriptors,
+            boundaries: [0; MAX_BOUNDARIES],
+            len: 0,
+            idx: 0,
+        };
+
+        for desc in descriptors.take(MAX_DESCRIPTORS) {
+            if let Some((start, end, _)) = Self::range_of(&desc) {
+                map.boundaries[map.len] = start;
+                map.boundaries[map.len + 1] = end;
+                map.len += 2;
+            }
+        }
+
+        // Insertion sort, there are only a few hundred entries at most
+        for i in 1..map.len {
+            let mut j = i;
+            while j > 0 && map.boundaries[j - 1] > map.boundaries[j] {
+                map.boundaries.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+
+        // Deduplicate
+        let mut unique = 0;
+        for i in 0..map.len {
+            if unique == 0 || map.boundaries[unique - 1] != map.boundaries[i] {
+                map.boundaries[unique] = map.boundaries[i];
+                unique += 1;
+            }
+        }
+        map.len = unique;
+        map
+    }
+
+    /// # Range Of
+    /// The page aligned range (`start..end`) and the kind of a descriptor
+    fn range_of(desc: &EfiMemoryDescriptor) -> Option<(u64, u64, MemoryKind)> {
+        let kind = MemoryKind::from_efi(desc.ty)?;
+        if desc.page_count == 0 {
+            return None;
+        }
+        let start = desc.phys_base & !(PAGE_SIZE - 1);
+        let end = start.checked_add(desc.page_count.checked_mul(PAGE_SIZE)?)?;
+        Some((start, end, kind))
+    }
+
+    /// # Kind At
+    /// The most restrictive kind of all descriptors containing `addr`
+    fn kind_at(&self, addr: u64) -> Option<MemoryKind> {
+        self.descriptors
+            .take(MAX_DESCRIPTORS)
+            .filter_map(|desc| Self::range_of(&desc))
+            .filter(|(start, end, _)| (*start..*end).contains(&addr))
+            .map(|(_, _, kind)| kind)
+            .max_by_key(|kind| kind.precedence())
+    }
+
+    /// # Summary
+    /// Sums up the sizes of the regions of this map
+    pub fn summary(&self) -> MemorySummary {
+        let mut summary = MemorySummary::default();
+        for region in self.clone() {
+            if !region.kind.is_ram() {
+                continue;
+            }
+            summary.total += region.size();
+            match region.kind {
+                MemoryKind::Usable => summary.usable += region.size(),
+                _ => summary.reserved += region.size(),
+            }
+        }
+        summary
+    }
+
+    /// # Top Of RAM
+    /// The first address after the highest region of RAM
+    pub fn top_of_ram(&self) -> u64 {
+        self.clone()
+            .filter(|region| region.kind.is_ram())
+            .map(|region| region.end().as_u64())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl Iterator for MemoryMap {
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.idx + 1 < self.len {
+            let start = self.boundaries[self.idx];
+            let mut end = self.boundaries[self.idx + 1];
+            self.idx += 1;
+
+            // As every start and end is a boundary, a descriptor either covers the whole
+            // interval or nothing of it
+            let kind = match self.kind_at(start) {
+                Some(kind) => kind,
+                None => continue, // A hole
+            };
+
+            // Merge with the following intervals of the same kind
+            while self.idx + 1 < self.len && self.kind_at(end) == Some(kind) {
+                end = self.boundaries[self.idx + 1];
+                self.idx += 1;
+            }
+
+            return Some(MemoryRegion {
+                start: PhysicalAddress::new(start),
+                pages: (end - start) / PAGE_SIZE,
+                kind,
+            });
+        }
+        None
+    }
+}
+
+/// # Memory Summary
+/// Sizes (in bytes) of the RAM described by a memory map
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemorySummary {
+    pub total: u64,
+    pub usable: u64,
+    /// Everything that is RAM but not usable
+    pub reserved: u64,
+}
+
+impl core::fmt::Display for MemorySummary {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "total {}, usable {}, reserved {}",
+            ByteSize(self.total),
+            ByteSize(self.usable),
+            ByteSize(self.reserved)
+        )
+    }
+}
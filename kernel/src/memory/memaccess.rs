@@ -0,0 +1,151 @@
+//! # Memory Access
+//! Reading and writing arbitrary physical memory, for debugging: Memory beyond the direct map,
+//! device registers, firmware tables. The pages are mapped uncached into a scratch window in
+//! the higher half for as long as the closure runs, and unmapped again afterwards.
+//!
+//! The window is split into `SLOTS` slots of `SLOT_PAGES` pages. A caller takes the first free
+//! slot, and waits for the one of its CPU if all of them are taken, so concurrent users never
+//! share a mapping.
+use bks::PAGE_SIZE;
+
+use super::paging::page_table_manager::{PageTableFlag, PAGE_TABLE_MANAGER};
+use super::paging::pat::MemoryType;
+use super::paging::tlb;
+use super::{PhysicalAddress, VirtualAddress};
+use crate::arch::PHYS_WINDOW_ADDRESS;
+use crate::error::{Error, Result};
+use crate::scheduler::spin::{Mutex, MutexGuard};
+use crate::smp;
+
+/// The number of slots, and so the number of mappings that can exist at once
+pub const SLOTS: usize = 4;
+/// The pages of a slot
+pub const SLOT_PAGES: usize = 16;
+/// The most bytes that can be mapped at once, wherever in a page they start
+pub const MAX_LEN: u64 = (SLOT_PAGES as u64 - 1) * PAGE_SIZE + 1;
+
+static SLOT_LOCKS: [Mutex<()>; SLOTS] = {
+    const FREE: Mutex<()> = Mutex::new(());
+    [FREE; SLOTS]
+};
+
+crate::counter!(pub MAPPINGS = "memaccess.mappings");
+
+/// # Mapping
+/// The pages of a slot that are mapped, unmapped when it is dropped
+struct Mapping {
+    slot: usize,
+    pages: u64,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Mapping {
+    /// # New
+    /// Maps the `len` bytes at `phys` into a free slot
+    ///
+    /// ## Returns
+    /// - (Mapping, VirtualAddress) = The mapping and the address `phys` is at
+    /// - Error::InvalidArgument = `len` is zero, or the range is too long or not addressable
+    fn new(phys: PhysicalAddress, len: u64) -> Result<(Self, VirtualAddress)> {
+        let end = phys
+            .as_u64()
+            .checked_add(len)
+            .filter(|_| len > 0)
+            .ok_or(Error::InvalidArgument)?;
+        PhysicalAddress::try_new(end - 1).map_err(|_| Error::InvalidArgument)?;
+        let start = phys.as_u64() & !(PAGE_SIZE - 1);
+        let pages = (end - start + PAGE_SIZE - 1) / PAGE_SIZE;
+        if pages > SLOT_PAGES as u64 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let (slot, lock) = take_slot();
+        let base = slot_address(slot);
+        let flags =
+            PageTableFlag::PRESENT | PageTableFlag::READ_WRITE | MemoryType::Uncacheable.flags();
+        {
+            let mut manager = PAGE_TABLE_MANAGER.lock();
+            let manager = unsafe { manager.assume_init_mut() };
+            for page in 0..pages {
+                let virt = base + page * PAGE_SIZE;
+                manager.map_page(virt, start + page * PAGE_SIZE, flags);
+                tlb::flush_page(VirtualAddress::new(virt));
+            }
+        }
+        MAPPINGS.increment();
+        let mapping = Self {
+            slot,
+            pages,
+            _lock: lock,
+        };
+        Ok((mapping, VirtualAddress::new(base + phys.as_u64() - start)))
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        let base = slot_address(self.slot);
+        {
+            let mut manager = PAGE_TABLE_MANAGER.lock();
+            let manager = unsafe { manager.assume_init_mut() };
+            for page in 0..self.pages {
+                let virt = base + page * PAGE_SIZE;
+                manager.unmap_page(virt);
+                tlb::flush_page(VirtualAddress::new(virt));
+            }
+        }
+        // Other CPUs may have fetched the translations speculatively, the next user of the
+        // slot must not reach the old pages through them
+        if smp::online_count() > 1 {
+            tlb::shootdown_all();
+        }
+    }
+}
+
+/// # Slot Address
+/// Where slot `slot` starts
+pub fn slot_address(slot: usize) -> u64 {
+    PHYS_WINDOW_ADDRESS + (slot * SLOT_PAGES) as u64 * PAGE_SIZE
+}
+
+/// The first free slot, or the one of the calling CPU once it is free
+fn take_slot() -> (usize, MutexGuard<'static, ()>) {
+    for (slot, lock) in SLOT_LOCKS.iter().enumerate() {
+        if let Some(guard) = lock.try_lock() {
+            return (slot, guard);
+        }
+    }
+    let slot = smp::current_cpu() % SLOTS;
+    (slot, SLOT_LOCKS[slot].lock())
+}
+
+/// # With Physical Mapping
+/// Calls `f` with the `len` bytes at `phys`, mapped uncached. `f` should read them with volatile
+/// reads of the width the memory expects, as device registers may not be read byte by byte.
+/// A caller that maps again from within `f` takes another slot, more than `SLOTS` nested
+/// mappings deadlock.
+///
+/// ## Returns
+/// - Error::InvalidArgument = `len` is zero, or the range is longer than `MAX_LEN` allows or
+///   not addressable
+pub fn with_phys_mapping<R>(
+    phys: PhysicalAddress,
+    len: u64,
+    f: impl FnOnce(&[u8]) -> R,
+) -> Result<R> {
+    let (_mapping, virt) = Mapping::new(phys, len)?;
+    let bytes = unsafe { core::slice::from_raw_parts(virt.as_ptr::<u8>(), len as usize) };
+    Ok(f(bytes))
+}
+
+/// # With Physical Mapping Mut
+/// Like `with_phys_mapping()`, but `f` can write to the memory
+pub fn with_phys_mapping_mut<R>(
+    phys: PhysicalAddress,
+    len: u64,
+    f: impl FnOnce(&mut [u8]) -> R,
+) -> Result<R> {
+    let (_mapping, virt) = Mapping::new(phys, len)?;
+    let bytes = unsafe { core::slice::from_raw_parts_mut(virt.as_mut_ptr::<u8>(), len as usize) };
+    Ok(f(bytes))
+}
@@ -0,0 +1,192 @@
+//! # Memtest
+//! `memtest=fast|full` on the command line tests every free frame at boot, after the frame
+//! allocator has read the memory map and before the heap or any driver takes memory.
+//!
+//! Every frame is filled with a pattern through the direct map and read back. The stores are
+//! non-temporal, so the reads come from RAM rather than from a cache that would hide a broken
+//! cell. `fast` writes the address of every word into it, then 0x55 and 0xAA, `full` also walks
+//! a single one through all 64 bits. A frame that fails is reserved for good and its address
+//! logged. Frames below 1 MiB are left alone, that is where the SMP trampoline has to live, and
+//! so is the kernel image even if the frame allocator were to think it free.
+use core::arch::asm;
+
+use bks::PAGE_SIZE;
+
+use crate::arch::tsc;
+use crate::cmdline;
+use crate::math::ByteSize;
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::{kernel_image, phys_to_virt, PhysicalAddress};
+use crate::{counter, error, info, success, warn};
+
+/// The command line option that selects the mode
+pub const MEMTEST_OPTION: &str = "memtest";
+/// Frames below are never tested
+pub const LOW_MEMORY: u64 = 0x10_0000;
+/// Progress is reported whenever this much more memory has been tested
+pub const PROGRESS_INTERVAL: u64 = 256 * 0x10_0000;
+const WORDS: usize = PAGE_SIZE as usize / 8;
+
+counter!(pub BAD_FRAMES = "memtest.bad_frames");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Fast,
+    Full,
+}
+
+impl Mode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "fast" => Some(Self::Fast),
+            "full" => Some(Self::Full),
+            _ => None,
+        }
+    }
+
+    /// # Patterns
+    /// The patterns every frame is tested with, in order
+    pub fn patterns(self) -> impl Iterator<Item = Pattern> {
+        let walking_ones = match self {
+            Self::Fast => 0,
+            Self::Full => 64,
+        };
+        [
+            Pattern::Address,
+            Pattern::Fill(0x5555_5555_5555_5555),
+            Pattern::Fill(0xAAAA_AAAA_AAAA_AAAA),
+        ]
+        .into_iter()
+        .chain((0..walking_ones).map(|bit| Pattern::Fill(1 << bit)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// Every word holds its own physical address
+    Address,
+    /// Every word holds the same value
+    Fill(u64),
+}
+
+impl Pattern {
+    /// The value of the word at the physical address `addr`
+    pub fn word(self, addr: u64) -> u64 {
+        match self {
+            Self::Address => addr,
+            Self::Fill(value) => value,
+        }
+    }
+}
+
+/// # Write
+/// Fills the frame at `frame`, whose physical address is `phys`, with `pattern` using
+/// non-temporal stores
+///
+/// ## Safety
+/// `frame` has to point to a whole frame nothing else uses
+pub unsafe fn write(frame: *mut u64, phys: u64, pattern: Pattern) {
+    for i in 0..WORDS {
+        asm!(
+            "movnti [{dst}], {value}",
+            dst = in(reg) frame.add(i),
+            value = in(reg) pattern.word(phys + i as u64 * 8),
+            options(nostack)
+        );
+    }
+    // Non-temporal stores are weakly ordered
+    asm!("sfence", options(nostack));
+}
+
+/// # Verify
+/// Whether the frame at `frame`, whose physical address is `phys`, holds `pattern`
+///
+/// ## Safety
+/// `frame` has to point to a whole frame
+pub unsafe fn verify(frame: *const u64, phys: u64, pattern: Pattern) -> bool {
+    (0..WORDS).all(|i| frame.add(i).read_volatile() == pattern.word(phys + i as u64 * 8))
+}
+
+/// # Test Frame
+/// Writes and verifies every pattern of `mode`, stopping at the first one that does not read
+/// back
+///
+/// ## Safety
+/// `frame` has to point to a whole frame nothing else uses, its contents are lost
+pub unsafe fn test_frame(frame: *mut u64, phys: u64, mode: Mode) -> bool {
+    mode.patterns().all(|pattern| {
+        write(frame, phys, pattern);
+        verify(frame, phys, pattern)
+    })
+}
+
+/// # Run
+/// Tests every free frame above `LOW_MEMORY` and outside the kernel image if `memtest=` asks for it
+///
+/// ## Notes
+/// Has to run while only the boot CPU is up and nothing allocates, the allocator stays locked
+/// the whole time.
+pub fn run() {
+    let mode = match cmdline::value(MEMTEST_OPTION) {
+        Some(value) => match Mode::parse(value) {
+            Some(mode) => mode,
+            None => {
+                warn!("memtest: unknown mode {:?}, expected fast or full", value);
+                return;
+            }
+        },
+        None => return,
+    };
+    // The timers come later, the TSC can be measured against the PIT already
+    if tsc::khz().is_none() && tsc::calibrate().is_err() {
+        warn!("memtest: no TSC frequency, the duration is in cycles");
+    }
+    info!("memtest: testing free memory ({:?})", mode);
+
+    let image = kernel_image::sections().image;
+    let image = image.physical().as_u64()..image.physical().as_u64() + image.len();
+
+    let start = tsc::read();
+    let mut tested = 0;
+    let mut bad = 0;
+    let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+    let allocator = unsafe { allocator.assume_init_mut() };
+    for phys in (LOW_MEMORY..allocator.total_memory()).step_by(PAGE_SIZE as usize) {
+        if image.contains(&phys) || !allocator.is_free(phys) {
+            continue;
+        }
+        let frame = phys_to_virt(PhysicalAddress::new(phys)).as_u64() as *mut u64;
+        if !unsafe { test_frame(frame, phys, mode) } {
+            error!("memtest: bad frame at {}", PhysicalAddress::new(phys));
+            allocator.reserve_pages(phys, 1);
+            BAD_FRAMES.increment();
+            bad += 1;
+        }
+        tested += PAGE_SIZE;
+        if tested % PROGRESS_INTERVAL == 0 {
+            info!("memtest: {} tested", ByteSize(tested));
+        }
+    }
+
+    let cycles = tsc::read() - start;
+    let duration = match tsc::cycles_to_ns(cycles) {
+        Some(ns) => (ns / 1_000_000, "ms"),
+        None => (cycles, "cycles"),
+    };
+    if bad == 0 {
+        success!(
+            "memtest: {} tested in {} {}, no bad frames",
+            ByteSize(tested),
+            duration.0,
+            duration.1
+        );
+    } else {
+        error!(
+            "memtest: {} tested in {} {}, {} bad frames reserved",
+            ByteSize(tested),
+            duration.0,
+            duration.1,
+            bad
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! # MMIO
+//! Mapping device registers and memory with the right memory type.
+//!
+//! The direct map caches everything as write-back, which devices must never be accessed
+//! through. Their pages are remapped in place with a type that suits them.
+use bks::PAGE_SIZE;
+
+use super::paging::page_table_manager::{PageTableFlag, PAGE_TABLE_MANAGER};
+use super::paging::pat::{CacheType, MemoryType};
+use super::paging::{mtrr, tlb};
+use super::{phys_to_virt, PhysicalAddress, VirtualAddress};
+use crate::error::{Error, Result};
+use crate::{smp, warn};
+
+/// # Map MMIO
+/// Maps the `len` bytes at `phys` with the memory type `ty`, which cannot be `WriteBack`
+///
+/// ## Returns
+/// - VirtualAddress = The address `phys` can be accessed at
+pub fn map_mmio(phys: PhysicalAddress, len: u64, ty: MemoryType) -> Result<VirtualAddress> {
+    if ty == MemoryType::WriteBack || len == 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let start = phys.as_u64() & !(PAGE_SIZE - 1);
+    let end = phys.as_u64() + len;
+    let flags = PageTableFlag::PRESENT | PageTableFlag::READ_WRITE | ty.flags();
+
+    let effective = ty.effective(mtrr::memory_type(start));
+    if effective != ty.cache_type() {
+        warn!(
+            "mmio: {:#x} is {} instead of {} because of the MTRRs",
+            start,
+            CacheType::abbreviation(effective),
+            CacheType::abbreviation(ty.cache_type()),
+        );
+    }
+
+    {
+        let mut manager = PAGE_TABLE_MANAGER.lock();
+        let manager = unsafe { manager.assume_init_mut() };
+        for page in (start..end).step_by(PAGE_SIZE as usize) {
+            let virt = phys_to_virt(PhysicalAddress::new(page));
+            manager.map_page(virt.as_u64(), page, flags);
+            tlb::flush_page(virt);
+        }
+    }
+    // Other CPUs may have cached the old type
+    if smp::online_count() > 1 {
+        tlb::shootdown_all();
+    }
+    Ok(phys_to_virt(phys))
+}
+
+/// # Unmap MMIO
+/// Removes the mapping of the `len` bytes at `phys` that `map_mmio()` made, accessing them
+/// faults afterwards. Only for whole pages a single device owns, as a page shared with another
+/// device would go away for that one as well.
+///
+/// ## Returns
+/// - Error::InvalidArgument = `phys` or `len` is not page aligned, or `len` is zero
+pub fn unmap_mmio(phys: PhysicalAddress, len: u64) -> Result<()> {
+    if phys.as_u64() % PAGE_SIZE != 0 || len % PAGE_SIZE != 0 || len == 0 {
+        return Err(Error::InvalidArgument);
+    }
+    {
+        let mut manager = PAGE_TABLE_MANAGER.lock();
+        let manager = unsafe { manager.assume_init_mut() };
+        for page in (phys.as_u64()..phys.as_u64() + len).step_by(PAGE_SIZE as usize) {
+            let virt = phys_to_virt(PhysicalAddress::new(page));
+            manager.unmap_page(virt.as_u64());
+            tlb::flush_page(virt);
+        }
+    }
+    if smp::online_count() > 1 {
+        tlb::shootdown_all();
+    }
+    Ok(())
+}
@@ -1,12 +1,42 @@
 pub mod bitmap;
+pub mod dma;
+pub mod frame_info;
+pub mod kaslr;
+pub mod kernel_image;
+pub mod map;
+pub mod memaccess;
 pub mod memset;
+pub mod memtest;
+pub mod mmio;
 pub mod paging;
+pub mod pressure;
+pub mod register;
+pub mod reserved;
 pub mod structures;
 pub use memset::memset;
+pub use mmio::{map_mmio, unmap_mmio};
 pub mod allocator;
+pub mod usermem;
 pub mod userspace;
+pub mod vmm;
 pub use structures::*;
 
+/// The virtual address at which all of physical memory is mapped.
+/// The kernel currently identity maps everything, so this is zero.
+pub const DIRECT_MAP_OFFSET: u64 = 0;
+
+/// # Physical To Virtual
+/// Returns the address through which `addr` can be accessed in the direct map
+pub fn phys_to_virt(addr: PhysicalAddress) -> VirtualAddress {
+    VirtualAddress::new(addr.as_u64() + DIRECT_MAP_OFFSET)
+}
+
+/// # Virtual To Physical
+/// Returns the physical address behind `addr`, which has to be a kernel address
+pub fn virt_to_phys(addr: VirtualAddress) -> PhysicalAddress {
+    PhysicalAddress::new(addr.as_u64() - DIRECT_MAP_OFFSET)
+}
+
 #[macro_export]
 macro_rules! address_of {
     ($x:expr) => {
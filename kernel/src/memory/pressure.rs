@@ -0,0 +1,113 @@
+//! # Pressure
+//! Gives memory back once free frames run low. Caches that can drop what they hold register a
+//! shrinker, which is asked for a number of pages and returns how many it freed.
+//!
+//! The frame allocator calls `check()` with its lock held, so it only records that free memory
+//! fell below `LOW_WATERMARK`. The shrinkers run later, without the lock, from the reclaim task
+//! or from `relieve()` called by an allocation that would grow a cache.
+use alloc::vec::Vec;
+use bks::PAGE_SIZE;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Result;
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::scheduler::spin::Mutex;
+use crate::scheduler::{self, Priority};
+use crate::{debug, time};
+
+/// Free memory below this many bytes asks the shrinkers for pages
+pub const LOW_WATERMARK: i64 = 8 << 20;
+/// The shrinkers are asked to free memory up to this many bytes
+pub const HIGH_WATERMARK: i64 = 2 * LOW_WATERMARK;
+/// How often the reclaim task looks for pressure
+const RECLAIM_INTERVAL_MS: u64 = 100;
+
+crate::counter!(pub EVENTS = "mm.pressure_events");
+crate::counter!(pub RECLAIMED = "mm.pressure_reclaimed_pages");
+
+/// # Shrinker
+/// Frees up to the given number of pages, returning how many it freed
+#[derive(Clone, Copy)]
+pub struct Shrinker {
+    pub name: &'static str,
+    pub shrink: fn(usize) -> usize,
+}
+
+static SHRINKERS: Mutex<Vec<Shrinker>> = Mutex::new(Vec::new());
+/// Set by `check()` until the shrinkers ran
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// # Register
+/// Has `shrink` called when memory runs low, after the shrinkers registered before it
+pub fn register(name: &'static str, shrink: fn(usize) -> usize) {
+    SHRINKERS.lock().push(Shrinker { name, shrink });
+}
+
+/// # Check
+/// Called by the frame allocator after every allocation with the free memory in bytes
+#[inline]
+pub fn check(free: i64) {
+    if free < LOW_WATERMARK && !PENDING.load(Ordering::Relaxed) {
+        PENDING.store(true, Ordering::Release);
+        EVENTS.increment();
+    }
+}
+
+/// # Is Pending
+/// Whether free memory fell below `LOW_WATERMARK` and the shrinkers did not run since
+pub fn is_pending() -> bool {
+    PENDING.load(Ordering::Acquire)
+}
+
+/// # Relieve
+/// Runs the shrinkers if memory ran low, until free memory is back at `HIGH_WATERMARK`. The
+/// frame allocator must not be locked by the caller.
+///
+/// ## Returns
+/// - usize = The number of pages freed
+pub fn relieve() -> usize {
+    if !PENDING.swap(false, Ordering::AcqRel) {
+        return 0;
+    }
+    let free = unsafe {
+        PAGE_FRAME_ALLOCATOR
+            .lock()
+            .assume_init_ref()
+            .get_free_memory()
+    };
+    let mut wanted = ((HIGH_WATERMARK - free).max(0) as u64 / PAGE_SIZE) as usize;
+    let shrinkers = SHRINKERS.lock().clone();
+    let mut freed = 0;
+    for shrinker in shrinkers {
+        if wanted == 0 {
+            break;
+        }
+        let pages = (shrinker.shrink)(wanted).min(wanted);
+        debug!("pressure: {} freed {} pages", shrinker.name, pages);
+        freed += pages;
+        wanted -= pages;
+    }
+    RECLAIMED.add(freed as u64);
+    freed
+}
+
+crate::initcall! {
+    name: "reclaim",
+    stage: Scheduled,
+    deps: [],
+    fatal: false,
+    init: init_reclaim,
+}
+
+fn init_reclaim() -> Result<()> {
+    let id = scheduler::spawn("reclaim", reclaim_task);
+    scheduler::set_priority(id, Priority::Low)
+}
+
+/// Runs the shrinkers whenever the frame allocator ran low
+fn reclaim_task() {
+    loop {
+        relieve();
+        time::sleep_ms(RECLAIM_INTERVAL_MS);
+    }
+}
@@ -0,0 +1,125 @@
+//! # Registers
+//! Typed access to memory mapped registers. A driver describes the register window of its
+//! device with `register_block!` and casts the mapped address to it, every register then is a
+//! field that can only be accessed with volatile reads and writes, and only in the directions
+//! the device allows.
+//!
+//! MMIO drivers use register blocks instead of `read_volatile` on a base address plus an
+//! offset: The offsets from the datasheet are checked against the layout of the struct when
+//! the kernel is built, so a wrong offset or a forgotten padding fails the build instead of
+//! poking the wrong register.
+use core::cell::UnsafeCell;
+
+/// # Volatile Cell
+/// A register that can be read and written
+#[repr(transparent)]
+pub struct VolatileCell<T: Copy> {
+    value: UnsafeCell<T>,
+}
+
+// Registers are shared by every CPU, and so is the device behind them
+unsafe impl<T: Copy> Sync for VolatileCell<T> {}
+
+impl<T: Copy> VolatileCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { core::ptr::read_volatile(self.value.get()) }
+    }
+
+    #[inline]
+    pub fn write(&self, value: T) {
+        unsafe { core::ptr::write_volatile(self.value.get(), value) }
+    }
+
+    /// Writes what `f` makes of the current value
+    #[inline]
+    pub fn update(&self, f: impl FnOnce(T) -> T) {
+        self.write(f(self.read()))
+    }
+}
+
+/// # Read Only
+/// A register that can only be read
+#[repr(transparent)]
+pub struct ReadOnly<T: Copy>(VolatileCell<T>);
+
+impl<T: Copy> ReadOnly<T> {
+    pub const fn new(value: T) -> Self {
+        Self(VolatileCell::new(value))
+    }
+
+    #[inline]
+    pub fn read(&self) -> T {
+        self.0.read()
+    }
+}
+
+/// # Write Only
+/// A register that can only be written, reading it returns garbage or has side effects
+#[repr(transparent)]
+pub struct WriteOnly<T: Copy>(VolatileCell<T>);
+
+impl<T: Copy> WriteOnly<T> {
+    pub const fn new(value: T) -> Self {
+        Self(VolatileCell::new(value))
+    }
+
+    #[inline]
+    pub fn write(&self, value: T) {
+        self.0.write(value)
+    }
+}
+
+/// # Register Block
+/// Defines a `#[repr(C)]` struct of registers. Every field is given with the offset the
+/// datasheet lists for it, and the block with its total size. The gaps between registers have
+/// to be filled with reserved fields, e.g. `[u8; 12]`, the build fails if any offset or the
+/// size does not match.
+/// ## Example
+/// ```
+/// register_block! {
+///     /// The registers of a timer
+///     pub struct TimerRegisters: 0x10 {
+///         0x0 => pub count: ReadOnly<u32>,
+///         0x4 => _reserved0: [u8; 4],
+///         0x8 => pub reload: VolatileCell<u64>,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! register_block {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident: $size:literal {
+            $(
+                $(#[$field_attr:meta])*
+                $offset:literal => $field_vis:vis $field:ident: $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[repr(C)]
+        $vis struct $name {
+            $(
+                $(#[$field_attr])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        const _: () = {
+            ::static_assertions::const_assert_eq!(::core::mem::size_of::<$name>(), $size);
+            $(
+                ::static_assertions::const_assert_eq!(
+                    ::memoffset::offset_of!($name, $field),
+                    $offset
+                );
+            )*
+        };
+    };
+}
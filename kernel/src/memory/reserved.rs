@@ -0,0 +1,86 @@
+//! # Reserved Memory
+//! Physical memory the frame allocator must never hand out, even if the memory map says it is
+//! usable (boot modules, the initramfs, ...).
+//!
+//! Regions registered before the frame allocator is initialized are applied by
+//! `PageFrameAllocator::read_memory_map`, later ones are reserved right away.
+use bks::PAGE_SIZE;
+
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::PhysicalAddress;
+use crate::scheduler::spin::Mutex;
+
+/// The maximum number of reserved regions, the list is used before the heap exists
+pub const MAX_RESERVED_REGIONS: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReservedRegion {
+    pub name: &'static str,
+    pub start: PhysicalAddress,
+    pub len: u64,
+}
+
+impl ReservedRegion {
+    /// The number of pages touched by the region
+    pub fn pages(&self) -> u64 {
+        let start = self.start.as_u64() & !(PAGE_SIZE - 1);
+        let end = self.start.as_u64() + self.len;
+        (end - start + PAGE_SIZE - 1) / PAGE_SIZE
+    }
+}
+
+struct ReservedRegions {
+    regions: [Option<ReservedRegion>; MAX_RESERVED_REGIONS],
+    /// Set once the frame allocator has applied the list
+    applied: bool,
+}
+
+static RESERVED_REGIONS: Mutex<ReservedRegions> = Mutex::new(ReservedRegions {
+    regions: [None; MAX_RESERVED_REGIONS],
+    applied: false,
+});
+
+/// # Reserve
+/// Registers a region that must never be handed out by the frame allocator.
+///
+/// ## Panics
+/// If more than `MAX_RESERVED_REGIONS` regions are registered
+pub fn reserve(name: &'static str, start: PhysicalAddress, len: u64) {
+    let region = ReservedRegion { name, start, len };
+    let applied = {
+        let mut reserved = RESERVED_REGIONS.lock();
+        let slot = reserved
+            .regions
+            .iter_mut()
+            .find(|region| region.is_none())
+            .expect("Too many reserved memory regions");
+        *slot = Some(region);
+        reserved.applied
+    };
+
+    if applied {
+        unsafe {
+            PAGE_FRAME_ALLOCATOR
+                .lock()
+                .assume_init_mut()
+                .reserve_pages(region.start.as_u64(), region.pages() as usize);
+        }
+    }
+}
+
+/// # Regions
+/// Calls `func` for every reserved region
+pub fn for_each(mut func: impl FnMut(&ReservedRegion)) {
+    for region in RESERVED_REGIONS.lock().regions.iter().flatten() {
+        func(region);
+    }
+}
+
+/// # Mark Applied
+/// Marks the list as applied and returns it, so that the frame allocator can reserve every
+/// region registered so far
+pub(crate) fn mark_applied() -> [Option<ReservedRegion>; MAX_RESERVED_REGIONS] {
+    let mut reserved = RESERVED_REGIONS.lock();
+    reserved.applied = true;
+    reserved.regions
+}
@@ -0,0 +1,157 @@
+//! # User Memory
+//! The only way the kernel accesses memory of user space. Under SMAP every other access to a
+//! user page faults, see `arch::smap`.
+//!
+//! Every function takes a `UserVirtualAddress`, so system calls turn the pointers they are
+//! passed into one with `user_address()` first, and a pointer into the kernel half is refused
+//! with `Error::BadFault` before anything could access it.
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::arch::smap::{clac, stac};
+use crate::error::{Error, Result};
+use crate::memory::{UserVirtualAddress, VirtualAddress};
+
+pub use crate::arch::USER_END;
+
+/// # User Access Guard
+/// Allows accesses to user pages while it lives
+pub struct UserAccessGuard {
+    _private: (),
+}
+
+impl UserAccessGuard {
+    fn new() -> Self {
+        stac();
+        Self { _private: () }
+    }
+}
+
+impl Drop for UserAccessGuard {
+    fn drop(&mut self) {
+        clac();
+    }
+}
+
+/// # User Address
+/// The pointer `addr` a system call was passed, as a `UserVirtualAddress`
+///
+/// ## Returns
+/// - Error::BadFault = `addr` is not canonical or in the kernel half
+pub fn user_address(addr: u64) -> Result<UserVirtualAddress> {
+    VirtualAddress::try_new(addr)
+        .ok()
+        .and_then(|addr| UserVirtualAddress::try_from(addr).ok())
+        .ok_or(Error::BadFault)
+}
+
+/// # Check Range
+/// Checks that the `len` bytes at `addr` are in user space
+///
+/// ## Returns
+/// - Error::BadFault = `addr` is null or the range reaches beyond `USER_END`
+pub fn check_range(addr: UserVirtualAddress, len: usize) -> Result<()> {
+    match addr.as_u64().checked_add(len as u64) {
+        Some(end) if !addr.is_null() && end <= USER_END => Ok(()),
+        _ => Err(Error::BadFault),
+    }
+}
+
+/// # Copy From User
+/// Fills `dst` with the bytes at the user address `src`
+pub fn copy_from_user(dst: &mut [u8], src: UserVirtualAddress) -> Result<()> {
+    if dst.is_empty() {
+        return Ok(());
+    }
+    check_range(src, dst.len())?;
+    let _guard = UserAccessGuard::new();
+    unsafe {
+        core::ptr::copy_nonoverlapping(src.as_u64() as *const u8, dst.as_mut_ptr(), dst.len())
+    };
+    Ok(())
+}
+
+/// # Copy To User
+/// Copies `src` to the user address `dst`
+pub fn copy_to_user(dst: UserVirtualAddress, src: &[u8]) -> Result<()> {
+    if src.is_empty() {
+        return Ok(());
+    }
+    check_range(dst, src.len())?;
+    let _guard = UserAccessGuard::new();
+    unsafe { core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_u64() as *mut u8, src.len()) };
+    Ok(())
+}
+
+/// # Read User
+/// Reads a `T` from the user address `src`, which does not have to be aligned. `T` has to be
+/// valid for any bit pattern.
+pub fn read_user<T: Copy>(src: UserVirtualAddress) -> Result<T> {
+    check_range(src, core::mem::size_of::<T>())?;
+    let _guard = UserAccessGuard::new();
+    Ok(unsafe { (src.as_u64() as *const T).read_unaligned() })
+}
+
+/// # Write User
+/// Writes `value` to the user address `dst`, which does not have to be aligned
+pub fn write_user<T: Copy>(dst: UserVirtualAddress, value: T) -> Result<()> {
+    check_range(dst, core::mem::size_of::<T>())?;
+    let _guard = UserAccessGuard::new();
+    unsafe { (dst.as_u64() as *mut T).write_unaligned(value) };
+    Ok(())
+}
+
+/// # Strncpy From User
+/// Copies the NUL terminated string at the user address `src` into `dst`, without the NUL. A
+/// string that does not fit is cut off.
+///
+/// ## Returns
+/// - usize = The number of bytes copied, `dst.len()` if the string was cut off
+/// - Error::BadFault = The string is not in user space
+pub fn strncpy_from_user(dst: &mut [u8], src: UserVirtualAddress) -> Result<usize> {
+    check_range(src, 1)?;
+    let src = src.as_u64();
+    let _guard = UserAccessGuard::new();
+    for (idx, byte) in dst.iter_mut().enumerate() {
+        let addr = src + idx as u64;
+        if addr >= USER_END {
+            return Err(Error::BadFault);
+        }
+        match unsafe { *(addr as *const u8) } {
+            0 => return Ok(idx),
+            value => *byte = value,
+        }
+    }
+    Ok(dst.len())
+}
+
+/// # Read User C String
+/// Reads the NUL terminated string at the user address `src`, which is shorter than `max`
+/// bytes
+///
+/// ## Returns
+/// - Error::BadFault = The string is not in user space
+/// - Error::FileNameTooLong = There is no NUL in the first `max` bytes
+/// - Error::InvalidArgument = The string is not UTF-8
+pub fn read_user_c_str(src: UserVirtualAddress, max: usize) -> Result<String> {
+    check_range(src, 1)?;
+    let src = src.as_u64();
+    // Nothing but the copy itself runs while user pages are accessible
+    let mut bytes = Vec::with_capacity(max);
+    {
+        let _guard = UserAccessGuard::new();
+        for idx in 0..max as u64 {
+            if src + idx >= USER_END {
+                return Err(Error::BadFault);
+            }
+            match unsafe { *((src + idx) as *const u8) } {
+                0 => break,
+                byte => bytes.push(byte),
+            }
+        }
+    }
+    if bytes.len() == max {
+        return Err(Error::FileNameTooLong);
+    }
+    String::from_utf8(bytes).map_err(|_| Error::InvalidArgument)
+}
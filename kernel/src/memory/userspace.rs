@@ -1,5 +1,15 @@
-use spin::Mutex;
+use crate::memory::kaslr;
+use crate::scheduler::spin::Mutex;
 
-use crate::arch::init::memory::{_KERNEL_END, _KERNEL_START};
+/// The span the start of the mmap region is randomized in
+const MMAP_RANDOM_SPAN: u64 = 0x4000_0000;
 
-pub static LAST_VIRT_MEM: Mutex<u64> = Mutex::new((_KERNEL_START + _KERNEL_END) + (10 * 1024));
+/// The next free virtual address of the mmap region
+pub static LAST_VIRT_MEM: Mutex<u64> = Mutex::new(10 * 1024);
+
+/// # Init Mmap Base
+/// Moves the start of the mmap region by a random amount, unless KASLR is disabled
+pub fn init_mmap_base() {
+    let mut last = LAST_VIRT_MEM.lock();
+    *last = kaslr::randomize("mmap base", *last, MMAP_RANDOM_SPAN);
+}
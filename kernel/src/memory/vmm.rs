@@ -0,0 +1,498 @@
+//! # VMM
+//! The regions `mmap()` placed in the address space and what backs them.
+//!
+//! There are no per-process address spaces yet, every task maps into the page tables of the
+//! kernel, so there is one `ADDRESS_SPACE`. Mapping the same object twice gives two regions.
+//! Regions are placed in `MMAP_START..MMAP_END`, far above physical memory and with it the
+//! direct map.
+//!
+//! Device regions map physical memory of a device, such as the framebuffer, with the memory
+//! type it asks for. Its frames are neither allocated nor freed here and do not count as
+//! resident.
+//!
+//! Anonymous regions get their pages on demand, in `handle_fault()`. A read maps the one
+//! global zero frame read-only, only a write allocates a frame of the page's own. Reading
+//! memory that was never written so costs nothing but page tables. The zero frame is never
+//! freed, it is marked `ZERO` in its `FrameInfo` and has a reference for every page mapping it
+//! besides its own. Unmapping drops the reference of a page, a frame is only freed once the
+//! last one is gone.
+//!
+//! `VmStats` counts the pages of the address space as they are mapped, faulted in and
+//! unmapped, for `vmstat()`. `is_resident()` asks the page tables instead, for `mincore()`.
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bks::PAGE_SIZE;
+use spin::Once;
+
+use super::frame_info::{frame_info, FrameFlags, FrameInfo};
+use super::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use super::paging::page_table_manager::{
+    effective_flags, translate, PageTableFlag, PAGE_TABLE_MANAGER,
+};
+use super::paging::pat::MemoryType;
+use super::paging::{tlb, walk};
+use super::usermem::USER_END;
+use super::{phys_to_virt, Frame, PhysicalAddress, VirtualAddress};
+use crate::error::{Error, Result};
+use crate::ipc::shm::SharedMemory;
+use crate::scheduler::spin::Mutex;
+use crate::smp;
+
+/// Where the first mapping is placed
+pub const MMAP_START: u64 = 0x4000_0000_0000;
+/// Mappings end below this
+pub const MMAP_END: u64 = USER_END;
+/// Marks a read-only mapping of the zero frame in a writable region, a write to it gets the
+/// page a frame of its own
+pub const COW: PageTableFlag = PageTableFlag::BIT_9;
+
+static ZERO_FRAME: Once<u64> = Once::new();
+
+/// # Backing
+/// What the pages of a region are
+#[derive(Debug, Clone)]
+pub enum Backing {
+    /// The frames of a shared memory object from `offset` on. Writes go to the object itself,
+    /// so every other mapping of it sees them.
+    Shared {
+        object: Arc<SharedMemory>,
+        offset: u64,
+    },
+    /// Private memory that reads as zero until it is written, with the frames in the page
+    /// tables
+    Anonymous,
+    /// `len` bytes of device memory from the physical address `phys` on, which is page aligned
+    Device {
+        phys: u64,
+        len: u64,
+        memory_type: MemoryType,
+    },
+}
+
+/// # Region
+/// A range of whole pages mapped by one call of `mmap()`, or what unmapping left of it
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub start: u64,
+    pub len: u64,
+    pub writable: bool,
+    /// False for `PROT_NONE`, where the range is reserved but nothing is mapped
+    pub accessible: bool,
+    pub backing: Backing,
+}
+
+impl Region {
+    pub fn end(&self) -> u64 {
+        self.start + self.len
+    }
+
+    /// # Frame
+    /// The frame behind the page at `addr`, which has to be in the region. `None` for an
+    /// anonymous page that was not accessed yet.
+    pub fn frame(&self, addr: u64) -> Option<Frame> {
+        match &self.backing {
+            Backing::Shared { object, offset } => {
+                Some(object.frames()[((addr - self.start + offset) / PAGE_SIZE) as usize])
+            }
+            Backing::Anonymous => {
+                translate(addr).map(|phys| Frame::which_contains(PhysicalAddress::new(phys)))
+            }
+            Backing::Device { phys, .. } => Some(Frame::which_contains(PhysicalAddress::new(
+                phys + (addr - self.start),
+            ))),
+        }
+    }
+
+    /// # Slice
+    /// The part of the region in `start..end`, which has to be inside it
+    fn slice(&self, start: u64, end: u64) -> Self {
+        let backing = match &self.backing {
+            Backing::Shared { object, offset } => Backing::Shared {
+                object: object.clone(),
+                offset: offset + (start - self.start),
+            },
+            Backing::Anonymous => Backing::Anonymous,
+            Backing::Device {
+                phys,
+                len,
+                memory_type,
+            } => Backing::Device {
+                phys: phys + (start - self.start),
+                len: len - (start - self.start),
+                memory_type: *memory_type,
+            },
+        };
+        Self {
+            start,
+            len: end - start,
+            writable: self.writable,
+            accessible: self.accessible,
+            backing,
+        }
+    }
+
+    /// The pages of the region
+    pub fn pages(&self) -> u64 {
+        self.len / PAGE_SIZE
+    }
+
+    /// The pages of a new region that have a frame mapped
+    pub fn resident_pages(&self) -> u64 {
+        match self.backing {
+            Backing::Shared { .. } if self.accessible => self.pages(),
+            _ => 0,
+        }
+    }
+
+    /// The pages of a new region that are only mapped on their first access
+    pub fn lazy_pages(&self) -> u64 {
+        match self.backing {
+            Backing::Anonymous if self.accessible => self.pages(),
+            _ => 0,
+        }
+    }
+
+    /// # Backing Len
+    /// The bytes the backing has from the start of the region on
+    fn backing_len(backing: &Backing) -> u64 {
+        match backing {
+            Backing::Shared { object, offset } => {
+                (object.frames().len() as u64 * PAGE_SIZE).saturating_sub(*offset)
+            }
+            Backing::Anonymous => u64::MAX,
+            Backing::Device { len, .. } => *len,
+        }
+    }
+
+    fn map_pages(&self) {
+        if !self.accessible {
+            return;
+        }
+        let mut flags = PageTableFlag::PRESENT | PageTableFlag::USER_ACCESSIBLE;
+        flags.set(PageTableFlag::READ_WRITE, self.writable);
+        if let Backing::Device { memory_type, .. } = self.backing {
+            flags |= memory_type.flags();
+        }
+        let mut manager = PAGE_TABLE_MANAGER.lock();
+        let manager = unsafe { manager.assume_init_mut() };
+        for page in (self.start..self.end()).step_by(PAGE_SIZE as usize) {
+            if let Some(frame) = self.frame(page) {
+                manager.map_page(page, frame.start().as_u64(), flags);
+            }
+        }
+    }
+
+    /// # Unmap Pages
+    /// Removes the pages of the region from the page tables and their counts from `stats`
+    ///
+    /// ## Returns
+    /// - Vec<u64> = The frames of anonymous pages whose last reference is gone, which may
+    ///   only be freed once no CPU can reach them anymore
+    fn unmap_pages(&self, stats: &mut VmStats) -> Vec<u64> {
+        stats.mapped -= self.pages();
+        let mut frames = Vec::new();
+        if !self.accessible {
+            return frames;
+        }
+        let mut manager = PAGE_TABLE_MANAGER.lock();
+        let manager = unsafe { manager.assume_init_mut() };
+        for page in (self.start..self.end()).step_by(PAGE_SIZE as usize) {
+            match (&self.backing, translate(page)) {
+                (Backing::Shared { .. }, _) => stats.resident -= 1,
+                (Backing::Anonymous, None) => stats.lazy -= 1,
+                (Backing::Anonymous, Some(frame)) => {
+                    let info = info(frame);
+                    if info.flags().contains(FrameFlags::ZERO) {
+                        stats.zero -= 1;
+                    } else {
+                        stats.resident -= 1;
+                    }
+                    if info.put() == 0 {
+                        frames.push(frame);
+                    }
+                }
+                (Backing::Device { .. }, _) => {}
+            }
+            manager.unmap_page(page);
+            tlb::flush_page(VirtualAddress::new(page));
+        }
+        frames
+    }
+}
+
+/// # VM Stats
+/// The page counts of an address space
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VmStats {
+    /// The pages of every region, `PROT_NONE` ones included
+    pub mapped: u64,
+    /// The pages that have a frame of their own mapped
+    pub resident: u64,
+    /// The pages shared copy-on-write with another address space. There is no copy-on-write
+    /// between address spaces yet, so this stays zero.
+    pub cow_shared: u64,
+    /// The anonymous pages that were not accessed yet
+    pub lazy: u64,
+    /// The anonymous pages that were read but not written, which map the zero frame
+    pub zero: u64,
+    pub regions: u64,
+}
+
+/// # Address Space
+/// The regions of an address space, by their start
+pub struct AddressSpace {
+    regions: BTreeMap<u64, Region>,
+    stats: VmStats,
+}
+
+impl AddressSpace {
+    pub const fn new() -> Self {
+        Self {
+            regions: BTreeMap::new(),
+            stats: VmStats {
+                mapped: 0,
+                resident: 0,
+                cow_shared: 0,
+                lazy: 0,
+                zero: 0,
+                regions: 0,
+            },
+        }
+    }
+
+    /// # Stats
+    /// The page counts, kept up to date by `map()` and `unmap()`
+    pub fn stats(&self) -> VmStats {
+        VmStats {
+            regions: self.regions.len() as u64,
+            ..self.stats
+        }
+    }
+
+    /// # Map
+    /// Maps `len` bytes of `backing`, rounded up to whole pages, at the lowest free address
+    ///
+    /// ## Returns
+    /// - u64 = The start of the mapping
+    /// - Error::InvalidArgument = `len` is zero or reaches beyond the end of `backing`
+    /// - Error::OutOfMemory = There is no free range that large
+    pub fn map(
+        &mut self,
+        len: u64,
+        writable: bool,
+        accessible: bool,
+        backing: Backing,
+    ) -> Result<u64> {
+        let len = page_len(len)?;
+        if len > Region::backing_len(&backing) {
+            return Err(Error::InvalidArgument);
+        }
+        let start = self.free_range(len).ok_or(Error::OutOfMemory)?;
+        let region = Region {
+            start,
+            len,
+            writable,
+            accessible,
+            backing,
+        };
+        region.map_pages();
+        self.stats.mapped += region.pages();
+        self.stats.resident += region.resident_pages();
+        self.stats.lazy += region.lazy_pages();
+        self.regions.insert(start, region);
+        Ok(start)
+    }
+
+    /// # Unmap
+    /// Unmaps the `len` bytes at `addr`, rounded up to whole pages. Regions that are only
+    /// partly in the range keep the rest, and a range without regions is fine.
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = `addr` is not page aligned or `len` is zero
+    pub fn unmap(&mut self, addr: u64, len: u64) -> Result<()> {
+        if addr % PAGE_SIZE != 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let end = addr
+            .checked_add(page_len(len)?)
+            .ok_or(Error::InvalidArgument)?;
+        let overlapping: Vec<u64> = self
+            .regions
+            .range(..end)
+            .filter(|(_, region)| region.end() > addr)
+            .map(|(start, _)| *start)
+            .collect();
+        let mut removed = Vec::with_capacity(overlapping.len());
+        let mut frames = Vec::new();
+        for start in overlapping {
+            let region = self.regions.remove(&start).unwrap();
+            if region.start < addr {
+                self.regions
+                    .insert(region.start, region.slice(region.start, addr));
+            }
+            if region.end() > end {
+                self.regions.insert(end, region.slice(end, region.end()));
+            }
+            let gone = region.slice(region.start.max(addr), region.end().min(end));
+            frames.extend(gone.unmap_pages(&mut self.stats));
+            removed.push(gone);
+        }
+        // Nothing may be freed before no CPU can reach it anymore
+        if !removed.is_empty() && smp::online_count() > 1 {
+            tlb::shootdown_all();
+        }
+        drop(removed);
+        if !frames.is_empty() {
+            let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+            let allocator = unsafe { allocator.assume_init_mut() };
+            for frame in frames {
+                allocator.free_page(frame);
+            }
+        }
+        Ok(())
+    }
+
+    /// # Region
+    /// The region `addr` is in
+    pub fn region(&self, addr: u64) -> Option<&Region> {
+        self.regions
+            .range(..=addr)
+            .next_back()
+            .map(|(_, region)| region)
+            .filter(|region| addr < region.end())
+    }
+
+    /// # Regions
+    /// Every region, ordered by address
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.regions.values()
+    }
+
+    /// # Free Range
+    /// The lowest start of `len` free bytes in the mmap window
+    fn free_range(&self, len: u64) -> Option<u64> {
+        let mut start = MMAP_START;
+        for region in self.regions.values() {
+            if region.start >= start + len {
+                break;
+            }
+            start = start.max(region.end());
+        }
+        (start.checked_add(len)? <= MMAP_END).then(|| start)
+    }
+}
+
+/// # Zero Frame
+/// The frame every read of an untouched anonymous page maps, allocated on the first call
+pub fn zero_frame() -> u64 {
+    *ZERO_FRAME.call_once(|| {
+        let frame = unsafe { PAGE_FRAME_ALLOCATOR.lock().assume_init_mut().request_page() };
+        zero(frame);
+        // Its own reference from the allocator keeps it from ever being freed
+        info(frame).insert(FrameFlags::ZERO);
+        frame
+    })
+}
+
+/// # Handle Fault
+/// Resolves a page fault at `addr` in an anonymous region of `ADDRESS_SPACE`: A read of an
+/// untouched page maps the zero frame, a write allocates a zeroed frame for the page. Called
+/// by the page fault handler, so the address space must not be locked while user memory is
+/// accessed.
+///
+/// ## Returns
+/// - bool = Whether the fault was resolved, false if the access is not allowed or there is no
+///   free frame left
+pub fn handle_fault(addr: u64, write: bool) -> bool {
+    let page = addr & !(PAGE_SIZE - 1);
+    let mut space = ADDRESS_SPACE.lock();
+    let writable = match space.region(page) {
+        Some(region) if region.accessible && matches!(region.backing, Backing::Anonymous) => {
+            region.writable
+        }
+        _ => return false,
+    };
+    if write && !writable {
+        return false;
+    }
+    let shared_zero = match effective_flags(page) {
+        None => false,
+        Some(flags) if write && flags.contains(COW) => true,
+        // Another CPU resolved it first
+        Some(flags) => return !write || flags.contains(PageTableFlag::READ_WRITE),
+    };
+
+    let mut flags = PageTableFlag::PRESENT | PageTableFlag::USER_ACCESSIBLE;
+    let frame = if write {
+        let frame = {
+            let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+            let allocator = unsafe { allocator.assume_init_mut() };
+            // Running out of frames panics in the allocator
+            if allocator.get_free_memory() < PAGE_SIZE as i64 {
+                return false;
+            }
+            allocator.request_page()
+        };
+        zero(frame);
+        flags |= PageTableFlag::READ_WRITE;
+        frame
+    } else {
+        flags.set(COW, writable);
+        let frame = zero_frame();
+        info(frame).get();
+        frame
+    };
+    {
+        let mut manager = PAGE_TABLE_MANAGER.lock();
+        unsafe { manager.assume_init_mut() }.map_page(page, frame, flags);
+    }
+    if shared_zero {
+        info(zero_frame()).put();
+        space.stats.zero -= 1;
+    } else {
+        space.stats.lazy -= 1;
+    }
+    if write {
+        space.stats.resident += 1;
+    } else {
+        space.stats.zero += 1;
+    }
+    drop(space);
+    // Entries that were not present are never cached, the zero frame may be
+    if shared_zero {
+        tlb::shootdown_page(VirtualAddress::new(page));
+    }
+    true
+}
+
+/// The metadata of the frame at `frame`
+fn info(frame: u64) -> &'static FrameInfo {
+    frame_info(Frame::which_contains(PhysicalAddress::new(frame)))
+}
+
+fn zero(frame: u64) {
+    let page = phys_to_virt(PhysicalAddress::new(frame)).as_u64() as *mut u8;
+    unsafe { core::ptr::write_bytes(page, 0, PAGE_SIZE as usize) };
+}
+
+/// # Is Resident
+/// Whether the page tables at `pml4` map the page at `addr` to a frame
+pub fn is_resident(pml4: u64, addr: u64) -> bool {
+    walk(pml4, addr, |_| {}).is_some()
+}
+
+/// # Page Len
+/// `len` rounded up to whole pages
+///
+/// ## Returns
+/// - Error::InvalidArgument = `len` is zero or does not fit the address space
+fn page_len(len: u64) -> Result<u64> {
+    if len == 0 || len > MMAP_END - MMAP_START {
+        return Err(Error::InvalidArgument);
+    }
+    Ok((len + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE)
+}
+
+/// The address space every task maps into
+pub static ADDRESS_SPACE: Mutex<AddressSpace> = Mutex::new(AddressSpace::new());
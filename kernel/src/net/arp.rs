@@ -0,0 +1,137 @@
+//! # ARP
+//! Address resolution for IPv4 over Ethernet (RFC 826). Requests for the address of an
+//! interface are answered and the senders of all packets addressed to us are remembered.
+//! IPv4 packets for addresses that are not resolved yet wait until the reply arrives.
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use super::ethernet::EtherType;
+use super::{Interface, Ipv4Address, MacAddress, RxError};
+use crate::error::Result;
+use crate::scheduler::spin::Mutex;
+
+const HARDWARE_ETHERNET: u16 = 1;
+const PACKET_SIZE: usize = 28;
+/// The number of packets waiting for resolution, the oldest ones are dropped first
+const MAX_PENDING: usize = 16;
+
+enumtastic::const_enum! {
+    pub enum ArpOperation: u16 => {
+        Request = 1,
+        Reply = 2,
+    }
+
+    impl {}
+}
+
+static ARP_CACHE: Mutex<BTreeMap<Ipv4Address, MacAddress>> = Mutex::new(BTreeMap::new());
+/// IPv4 packets and the address they wait for
+static PENDING: Mutex<Vec<(Ipv4Address, Vec<u8>)>> = Mutex::new(Vec::new());
+
+/// # Lookup
+/// The hardware address of `ip`, if it is known
+pub fn lookup(ip: Ipv4Address) -> Option<MacAddress> {
+    ARP_CACHE.lock().get(&ip).copied()
+}
+
+/// # Resolve
+/// Asks for the hardware address of `ip` and sends the IPv4 `packet` once it is known
+pub fn resolve(interface: &Interface, ip: Ipv4Address, packet: Vec<u8>) -> Result<()> {
+    {
+        let mut pending = PENDING.lock();
+        if pending.len() >= MAX_PENDING {
+            pending.remove(0);
+        }
+        pending.push((ip, packet));
+    }
+    let request = ArpPacket {
+        operation: ArpOperation::Request,
+        sender_mac: interface.mac(),
+        sender_ip: interface.ip().unwrap_or(Ipv4Address::UNSPECIFIED),
+        target_mac: MacAddress([0; 6]),
+        target_ip: ip,
+    };
+    interface.send(MacAddress::BROADCAST, EtherType::Arp, &request.to_bytes())
+}
+
+/// # ARP Packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Address,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Address,
+}
+
+impl ArpPacket {
+    pub fn parse(data: &[u8]) -> Result<Self, RxError> {
+        if data.len() < PACKET_SIZE {
+            return Err(RxError::BadLength);
+        }
+        let hardware = u16::from_be_bytes([data[0], data[1]]);
+        let protocol = u16::from_be_bytes([data[2], data[3]]);
+        if hardware != HARDWARE_ETHERNET || protocol != EtherType::Ipv4 {
+            return Err(RxError::Unsupported);
+        }
+        // The address lengths have to match the types
+        if data[4] != 6 || data[5] != 4 {
+            return Err(RxError::BadLength);
+        }
+        Ok(Self {
+            operation: u16::from_be_bytes([data[6], data[7]]),
+            sender_mac: MacAddress(data[8..14].try_into().unwrap()),
+            sender_ip: Ipv4Address(data[14..18].try_into().unwrap()),
+            target_mac: MacAddress(data[18..24].try_into().unwrap()),
+            target_ip: Ipv4Address(data[24..28].try_into().unwrap()),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; PACKET_SIZE] {
+        let mut data = [0u8; PACKET_SIZE];
+        data[0..2].copy_from_slice(&HARDWARE_ETHERNET.to_be_bytes());
+        data[2..4].copy_from_slice(&EtherType::Ipv4.to_be_bytes());
+        data[4] = 6;
+        data[5] = 4;
+        data[6..8].copy_from_slice(&self.operation.to_be_bytes());
+        data[8..14].copy_from_slice(&self.sender_mac.0);
+        data[14..18].copy_from_slice(&self.sender_ip.0);
+        data[18..24].copy_from_slice(&self.target_mac.0);
+        data[24..28].copy_from_slice(&self.target_ip.0);
+        data
+    }
+}
+
+/// # Handle
+/// Processes an ARP packet received on `interface`
+pub fn handle(interface: &Interface, data: &[u8]) -> Result<(), RxError> {
+    let packet = ArpPacket::parse(data)?;
+    let ip = interface.ip().ok_or(RxError::Unsupported)?;
+    if packet.target_ip != ip {
+        return Err(RxError::Unsupported);
+    }
+    ARP_CACHE.lock().insert(packet.sender_ip, packet.sender_mac);
+    let resolved = {
+        let mut pending = PENDING.lock();
+        let (resolved, waiting): (Vec<_>, Vec<_>) = pending
+            .drain(..)
+            .partition(|(ip, _)| *ip == packet.sender_ip);
+        *pending = waiting;
+        resolved
+    };
+    for (_, ip_packet) in resolved {
+        let _ = interface.send(packet.sender_mac, EtherType::Ipv4, &ip_packet);
+    }
+
+    if packet.operation == ArpOperation::Request {
+        let reply = ArpPacket {
+            operation: ArpOperation::Reply,
+            sender_mac: interface.mac(),
+            sender_ip: ip,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+        // A lost reply is not worth dropping the request over, the peer asks again
+        let _ = interface.send(packet.sender_mac, EtherType::Arp, &reply.to_bytes());
+    }
+    Ok(())
+}
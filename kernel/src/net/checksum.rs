@@ -0,0 +1,21 @@
+//! # Checksum
+//! The internet checksum (RFC 1071) used by IPv4, ICMP, UDP and TCP.
+
+/// # Checksum
+/// The ones' complement of the ones' complement sum of all 16 bit big endian words in `data`.
+/// An odd trailing byte is padded with zero. Data that already contains its correct checksum
+/// sums up to zero.
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|word| match word {
+            [high, low] => u16::from_be_bytes([*high, *low]) as u32,
+            [high] => (*high as u32) << 8,
+            _ => unreachable!(),
+        })
+        .fold(0u32, |sum, word| sum + word);
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
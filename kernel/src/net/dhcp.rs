@@ -0,0 +1,492 @@
+//! # DHCP
+//! A DHCP client (RFC 2131) configuring the first interface.
+//!
+//! The client discovers a server, requests the offered address and renews the lease at T1
+//! (half of the lease time) with the server that granted it, or at T2 (seven eighths of the
+//! lease time) with any server. Until the first lease is granted, messages are retransmitted
+//! with exponential backoff. If no lease is granted after `MAX_ATTEMPTS` messages, the static
+//! configuration from the command line is used instead.
+use alloc::{sync::Arc, vec::Vec};
+
+use super::udp::{self, Endpoint, UdpSocket};
+use super::{Interface, Ipv4Address, Ipv4Config, MacAddress};
+use crate::{info, time, warn};
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+/// The number of messages sent before giving up on getting a lease
+pub const MAX_ATTEMPTS: u32 = 5;
+/// Seconds until the first retransmission, doubled with every attempt
+const INITIAL_TIMEOUT: f64 = 1.0;
+/// Seconds between retransmissions while renewing or rebinding, at least
+const MIN_RENEW_TIMEOUT: f64 = 60.0;
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HARDWARE_ETHERNET: u8 = 1;
+/// Asks the server to broadcast its reply, as we can not receive unicasts without an address
+const FLAG_BROADCAST: u16 = 1 << 15;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// The size of the fixed part of a message, including the magic cookie
+const FIXED_SIZE: usize = 240;
+
+enumtastic::const_enum! {
+    pub enum MessageType: u8 => {
+        Discover = 1,
+        Offer = 2,
+        Request = 3,
+        Decline = 4,
+        Ack = 5,
+        Nak = 6,
+        Release = 7,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum DhcpOption: u8 => {
+        Pad = 0,
+        SubnetMask = 1,
+        Router = 3,
+        DomainNameServer = 6,
+        RequestedAddress = 50,
+        LeaseTime = 51,
+        MessageType = 53,
+        ServerIdentifier = 54,
+        ParameterRequestList = 55,
+        End = 255,
+    }
+
+    impl {}
+}
+
+/// # Reply
+/// The parts of a message from a server the client cares about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    pub message_type: u8,
+    pub xid: u32,
+    pub client: MacAddress,
+    /// The address offered to or assigned to the client
+    pub your_address: Ipv4Address,
+    pub server: Option<Ipv4Address>,
+    pub netmask: Option<u32>,
+    pub router: Option<Ipv4Address>,
+    pub dns: Option<Ipv4Address>,
+    /// In seconds
+    pub lease_time: Option<u32>,
+}
+
+impl Reply {
+    /// # Parse
+    /// Parses a message from a server. Options that are not understood are skipped.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < FIXED_SIZE
+            || data[0] != OP_REPLY
+            || data[1] != HARDWARE_ETHERNET
+            || data[2] != 6
+            || data[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let address = |offset: usize| Ipv4Address(data[offset..offset + 4].try_into().unwrap());
+        let mut reply = Self {
+            message_type: 0,
+            xid: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            client: MacAddress(data[28..34].try_into().unwrap()),
+            your_address: address(16),
+            server: None,
+            netmask: None,
+            router: None,
+            dns: None,
+            lease_time: None,
+        };
+
+        let mut options = &data[FIXED_SIZE..];
+        loop {
+            let (code, rest) = options.split_first()?;
+            match *code {
+                DhcpOption::End => break,
+                DhcpOption::Pad => {
+                    options = rest;
+                    continue;
+                }
+                _ => {}
+            }
+            let (length, rest) = rest.split_first()?;
+            let value = rest.get(..*length as usize)?;
+            options = &rest[*length as usize..];
+            let as_address = || Some(Ipv4Address(value.get(..4)?.try_into().unwrap()));
+            let as_u32 = || Some(u32::from_be_bytes(value.get(..4)?.try_into().unwrap()));
+            match *code {
+                DhcpOption::MessageType => reply.message_type = *value.first()?,
+                DhcpOption::SubnetMask => reply.netmask = as_u32(),
+                DhcpOption::Router => reply.router = as_address(),
+                DhcpOption::DomainNameServer => reply.dns = as_address(),
+                DhcpOption::LeaseTime => reply.lease_time = as_u32(),
+                DhcpOption::ServerIdentifier => reply.server = as_address(),
+                _ => {}
+            }
+        }
+        match reply.message_type {
+            0 => None,
+            _ => Some(reply),
+        }
+    }
+
+    /// # Config
+    /// The configuration the reply assigns, if the subnet mask is valid
+    pub fn config(&self) -> Option<Ipv4Config> {
+        let prefix = match self.netmask {
+            Some(netmask) if netmask.leading_ones() == netmask.count_ones() => {
+                netmask.count_ones() as u8
+            }
+            Some(_) => return None,
+            None => super::DEFAULT_PREFIX,
+        };
+        Some(Ipv4Config {
+            address: self.your_address,
+            prefix,
+            router: self.router,
+            dns: self.dns,
+        })
+    }
+}
+
+/// # Build
+/// Assembles a message from the client. `client_address` is only set while renewing or
+/// rebinding, `requested` and `server` only while requesting an offered address.
+pub fn build(
+    message_type: u8,
+    xid: u32,
+    mac: MacAddress,
+    client_address: Option<Ipv4Address>,
+    requested: Option<Ipv4Address>,
+    server: Option<Ipv4Address>,
+) -> Vec<u8> {
+    let mut message = alloc::vec![0u8; FIXED_SIZE];
+    message[0] = OP_REQUEST;
+    message[1] = HARDWARE_ETHERNET;
+    message[2] = 6;
+    message[4..8].copy_from_slice(&xid.to_be_bytes());
+    if client_address.is_none() {
+        message[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+    }
+    if let Some(address) = client_address {
+        message[12..16].copy_from_slice(&address.0);
+    }
+    message[28..34].copy_from_slice(&mac.0);
+    message[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+    message.extend_from_slice(&[DhcpOption::MessageType, 1, message_type]);
+    if let Some(address) = requested {
+        message.extend_from_slice(&[DhcpOption::RequestedAddress, 4]);
+        message.extend_from_slice(&address.0);
+    }
+    if let Some(address) = server {
+        message.extend_from_slice(&[DhcpOption::ServerIdentifier, 4]);
+        message.extend_from_slice(&address.0);
+    }
+    message.extend_from_slice(&[
+        DhcpOption::ParameterRequestList,
+        4,
+        DhcpOption::SubnetMask,
+        DhcpOption::Router,
+        DhcpOption::DomainNameServer,
+        DhcpOption::LeaseTime,
+    ]);
+    message.push(DhcpOption::End);
+    message
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    /// Waiting for an offer
+    Selecting,
+    /// Waiting for the server to acknowledge the offered address
+    Requesting {
+        address: Ipv4Address,
+        server: Ipv4Address,
+    },
+    Bound,
+    /// Extending the lease with the server that granted it
+    Renewing,
+    /// Extending the lease with any server
+    Rebinding,
+    /// No lease could be acquired, the static configuration is used if there is one
+    Failed,
+}
+
+/// # Lease
+/// The times (in seconds since boot) the lease has to be renewed at and ends at
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Lease {
+    server: Ipv4Address,
+    renew: f64,
+    rebind: f64,
+    expiry: f64,
+}
+
+/// # Client
+/// The state of the DHCP client of `interface`. Times are passed in as seconds since boot.
+pub struct Client {
+    interface: Arc<Interface>,
+    fallback: Option<Ipv4Config>,
+    xid: u32,
+    state: State,
+    lease: Option<Lease>,
+    /// The number of messages sent in the current state
+    attempts: u32,
+    /// When the next message is sent
+    deadline: f64,
+}
+
+impl Client {
+    pub fn new(interface: Arc<Interface>, fallback: Option<Ipv4Config>, xid: u32) -> Self {
+        Self {
+            interface,
+            fallback,
+            xid,
+            state: State::Selecting,
+            lease: None,
+            attempts: 0,
+            deadline: 0.0,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// When `tick()` has something to do next, in seconds since boot
+    pub fn deadline(&self) -> f64 {
+        self.deadline
+    }
+
+    /// # Start
+    /// Starts looking for a server
+    pub fn start(&mut self, now: f64) {
+        self.enter(State::Selecting, now);
+    }
+
+    fn enter(&mut self, state: State, now: f64) {
+        self.state = state;
+        self.attempts = 0;
+        if state == State::Selecting {
+            self.xid = self.xid.wrapping_add(1);
+        }
+        self.transmit(now);
+    }
+
+    /// Sends the message of the current state and schedules its retransmission
+    fn transmit(&mut self, now: f64) {
+        let mac = self.interface.mac();
+        let own_address = self.interface.ip();
+        let (message, destination) = match (self.state, self.lease) {
+            (State::Selecting, _) => (
+                build(MessageType::Discover, self.xid, mac, None, None, None),
+                Ipv4Address::BROADCAST,
+            ),
+            (State::Requesting { address, server }, _) => (
+                build(
+                    MessageType::Request,
+                    self.xid,
+                    mac,
+                    None,
+                    Some(address),
+                    Some(server),
+                ),
+                Ipv4Address::BROADCAST,
+            ),
+            (State::Renewing, Some(lease)) => (
+                build(MessageType::Request, self.xid, mac, own_address, None, None),
+                lease.server,
+            ),
+            (State::Rebinding, _) => (
+                build(MessageType::Request, self.xid, mac, own_address, None, None),
+                Ipv4Address::BROADCAST,
+            ),
+            _ => return,
+        };
+        let destination = Endpoint {
+            address: destination,
+            port: SERVER_PORT,
+        };
+        if let Err(err) = udp::send_via(&self.interface, CLIENT_PORT, destination, &message) {
+            warn!(
+                "{}: Failed to send DHCP message: {}",
+                self.interface.name(),
+                err.text()
+            );
+        }
+        self.attempts += 1;
+
+        self.deadline = match (self.state, self.lease) {
+            (State::Renewing, Some(lease)) => {
+                (now + ((lease.rebind - now) / 2.0).max(MIN_RENEW_TIMEOUT)).min(lease.rebind)
+            }
+            (State::Rebinding, Some(lease)) => {
+                (now + ((lease.expiry - now) / 2.0).max(MIN_RENEW_TIMEOUT)).min(lease.expiry)
+            }
+            _ => now + INITIAL_TIMEOUT * (1 << (self.attempts - 1)) as f64,
+        };
+    }
+
+    /// # Handle
+    /// Processes a message received on the client port
+    pub fn handle(&mut self, data: &[u8], now: f64) {
+        let reply = match Reply::parse(data) {
+            Some(reply) if reply.xid == self.xid && reply.client == self.interface.mac() => reply,
+            _ => return,
+        };
+        match (self.state, reply.message_type) {
+            (State::Selecting, MessageType::Offer) => {
+                if let Some(server) = reply.server {
+                    let address = reply.your_address;
+                    self.enter(State::Requesting { address, server }, now);
+                }
+            }
+            (State::Requesting { .. } | State::Renewing | State::Rebinding, MessageType::Ack) => {
+                self.bind(&reply, now)
+            }
+            (State::Requesting { .. } | State::Renewing | State::Rebinding, MessageType::Nak) => {
+                warn!(
+                    "{}: DHCP server declined the request",
+                    self.interface.name()
+                );
+                if self.lease.take().is_some() {
+                    self.interface.set_config(None);
+                }
+                self.enter(State::Selecting, now);
+            }
+            _ => {}
+        }
+    }
+
+    fn bind(&mut self, reply: &Reply, now: f64) {
+        let config = match reply.config() {
+            Some(config) => config,
+            None => return,
+        };
+        let server = match (reply.server, self.lease, self.state) {
+            (Some(server), _, _) => server,
+            (None, _, State::Requesting { server, .. }) => server,
+            (None, Some(lease), _) => lease.server,
+            (None, None, _) => return,
+        };
+        let lease_time = reply.lease_time.unwrap_or(u32::MAX) as f64;
+        self.lease = Some(Lease {
+            server,
+            renew: now + lease_time / 2.0,
+            rebind: now + lease_time * 7.0 / 8.0,
+            expiry: now + lease_time,
+        });
+        if self.interface.config() != Some(config) {
+            self.interface.set_config(Some(config));
+            info!(
+                "{}: {} via DHCP from {}, router {}, DNS {}, lease {}s",
+                self.interface.name(),
+                config,
+                server,
+                OptionalAddress(config.router),
+                OptionalAddress(config.dns),
+                lease_time
+            );
+        }
+        self.state = State::Bound;
+        self.attempts = 0;
+        self.deadline = now + lease_time / 2.0;
+    }
+
+    /// # Tick
+    /// Retransmits and moves on to the next state once the current one timed out
+    pub fn tick(&mut self, now: f64) {
+        if now < self.deadline {
+            return;
+        }
+        match (self.state, self.lease) {
+            (State::Failed, _) => {}
+            (State::Selecting | State::Requesting { .. }, _) if self.attempts >= MAX_ATTEMPTS => {
+                self.fail()
+            }
+            (State::Bound | State::Renewing | State::Rebinding, Some(lease))
+                if now >= lease.expiry =>
+            {
+                warn!("{}: DHCP lease expired", self.interface.name());
+                self.lease = None;
+                self.interface.set_config(None);
+                self.enter(State::Selecting, now);
+            }
+            (State::Bound | State::Renewing, Some(lease)) if now >= lease.rebind => {
+                self.enter(State::Rebinding, now)
+            }
+            (State::Bound, _) => self.enter(State::Renewing, now),
+            _ => self.transmit(now),
+        }
+    }
+
+    fn fail(&mut self) {
+        self.state = State::Failed;
+        match self.fallback {
+            Some(config) => {
+                warn!(
+                    "{}: No DHCP lease, using the static configuration {}",
+                    self.interface.name(),
+                    config
+                );
+                self.interface.set_config(Some(config));
+            }
+            None => warn!("{}: No DHCP lease", self.interface.name()),
+        }
+    }
+}
+
+/// Displays a missing address as `none`
+struct OptionalAddress(Option<Ipv4Address>);
+
+impl core::fmt::Display for OptionalAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(address) => write!(f, "{}", address),
+            None => write!(f, "none"),
+        }
+    }
+}
+
+fn now() -> f64 {
+    time::uptime_ns() as f64 / 1_000_000_000.0
+}
+
+/// # DHCP Task
+/// Configures the first interface and keeps its lease, until a lease can not be acquired
+pub fn dhcp_task() {
+    let _tag = crate::alloc_tag!("net");
+    let interface = match super::interfaces().into_iter().next() {
+        Some(interface) => interface,
+        None => return,
+    };
+    let socket = UdpSocket::new();
+    if let Err(err) = socket.bind(CLIENT_PORT) {
+        warn!("dhcp: Failed to bind port {}: {}", CLIENT_PORT, err.text());
+        interface.set_config(super::static_config());
+        return;
+    }
+    // Only has to differ between clients and boots, the MAC and the TSC take care of that
+    let mac = interface.mac().0;
+    let xid =
+        u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]) ^ crate::arch::tsc::read() as u32;
+
+    let mut client = Client::new(interface, super::static_config(), xid);
+    client.start(now());
+    let mut buf = [0u8; udp::MAX_PAYLOAD];
+    while client.state() != State::Failed {
+        while let Ok((len, _)) = socket.receive_from(&mut buf, true) {
+            client.handle(&buf[..len], now());
+        }
+        client.tick(now());
+        // Sleeps until a reply arrives or the next retransmission, renewal or expiry is due.
+        // Rounded up, so the deadline has passed once the time runs out.
+        let timeout_ms = ((client.deadline() - now()) * 1000.0).max(0.0) as u64 + 1;
+        socket.wait(timeout_ms);
+    }
+}
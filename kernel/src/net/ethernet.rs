@@ -0,0 +1,58 @@
+//! # Ethernet
+//! Ethernet II frames, without the frame check sequence (devices strip and add it).
+use alloc::vec::Vec;
+
+use super::{MacAddress, RxError};
+
+pub const HEADER_SIZE: usize = 14;
+/// The largest payload of a frame
+pub const MTU: usize = 1500;
+
+enumtastic::const_enum! {
+    pub enum EtherType: u16 => {
+        Ipv4 = 0x0800,
+        Arp = 0x0806,
+    }
+
+    impl {}
+}
+
+/// # Frame
+/// A parsed Ethernet frame, borrowing its payload
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a> {
+    pub destination: MacAddress,
+    pub source: MacAddress,
+    pub ether_type: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, RxError> {
+        if data.len() < HEADER_SIZE || data.len() > HEADER_SIZE + MTU {
+            return Err(RxError::BadLength);
+        }
+        Ok(Self {
+            destination: MacAddress(data[0..6].try_into().unwrap()),
+            source: MacAddress(data[6..12].try_into().unwrap()),
+            ether_type: u16::from_be_bytes([data[12], data[13]]),
+            payload: &data[HEADER_SIZE..],
+        })
+    }
+}
+
+/// # Build
+/// Assembles a frame around `payload`
+pub fn build(
+    destination: MacAddress,
+    source: MacAddress,
+    ether_type: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&destination.0);
+    frame.extend_from_slice(&source.0);
+    frame.extend_from_slice(&ether_type.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
@@ -0,0 +1,40 @@
+//! # ICMP
+//! Answers echo requests (RFC 792), everything else is ignored.
+use super::checksum::checksum;
+use super::ipv4::{self, IpProtocol, Packet};
+use super::{Interface, MacAddress, RxError};
+
+pub const HEADER_SIZE: usize = 8;
+
+enumtastic::const_enum! {
+    pub enum IcmpType: u8 => {
+        EchoReply = 0,
+        EchoRequest = 8,
+    }
+
+    impl {}
+}
+
+/// # Handle
+/// Processes an ICMP message that arrived in `packet`
+pub fn handle(interface: &Interface, source: MacAddress, packet: &Packet) -> Result<(), RxError> {
+    let message = packet.payload;
+    if message.len() < HEADER_SIZE {
+        return Err(RxError::BadLength);
+    }
+    if checksum(message) != 0 {
+        return Err(RxError::BadChecksum);
+    }
+    if message[0] != IcmpType::EchoRequest || message[1] != 0 {
+        return Err(RxError::Unsupported);
+    }
+
+    // The identifier, the sequence number and the data are echoed back unchanged
+    let mut reply = message.to_vec();
+    reply[0] = IcmpType::EchoReply;
+    reply[2..4].copy_from_slice(&[0, 0]);
+    let sum = checksum(&reply);
+    reply[2..4].copy_from_slice(&sum.to_be_bytes());
+    let _ = ipv4::send(interface, source, packet.source, IpProtocol::Icmp, &reply);
+    Ok(())
+}
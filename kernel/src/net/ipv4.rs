@@ -0,0 +1,149 @@
+//! # IPv4
+//! Unfragmented IPv4 packets (RFC 791). Fragments are not reassembled but dropped and counted.
+use alloc::vec::Vec;
+
+use super::checksum::checksum;
+use super::ethernet::EtherType;
+use super::{arp, icmp, udp, Interface, Ipv4Address, MacAddress, RxError};
+use crate::error::{Error, Result};
+
+pub const HEADER_SIZE: usize = 20;
+const VERSION: u8 = 4;
+const DEFAULT_TTL: u8 = 64;
+/// The don't fragment flag in the flags and fragment offset field
+const DONT_FRAGMENT: u16 = 1 << 14;
+/// The more fragments flag and the fragment offset
+const FRAGMENT_MASK: u16 = 0x3FFF;
+
+enumtastic::const_enum! {
+    pub enum IpProtocol: u8 => {
+        Icmp = 1,
+        Udp = 17,
+    }
+
+    impl {}
+}
+
+/// # Packet
+/// A parsed IPv4 packet, borrowing its payload
+#[derive(Debug, Clone, Copy)]
+pub struct Packet<'a> {
+    pub source: Ipv4Address,
+    pub destination: Ipv4Address,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    /// # Parse
+    /// Validates the header (including its checksum) and strips the Ethernet padding
+    pub fn parse(data: &'a [u8]) -> Result<Self, RxError> {
+        if data.len() < HEADER_SIZE {
+            return Err(RxError::BadLength);
+        }
+        if data[0] >> 4 != VERSION {
+            return Err(RxError::Unsupported);
+        }
+        let header_size = (data[0] & 0xF) as usize * 4;
+        let total_size = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if header_size < HEADER_SIZE || total_size < header_size || total_size > data.len() {
+            return Err(RxError::BadLength);
+        }
+        if checksum(&data[..header_size]) != 0 {
+            return Err(RxError::BadChecksum);
+        }
+        if u16::from_be_bytes([data[6], data[7]]) & FRAGMENT_MASK != 0 {
+            return Err(RxError::Fragmented);
+        }
+        Ok(Self {
+            source: Ipv4Address(data[12..16].try_into().unwrap()),
+            destination: Ipv4Address(data[16..20].try_into().unwrap()),
+            protocol: data[9],
+            ttl: data[8],
+            payload: &data[header_size..total_size],
+        })
+    }
+}
+
+/// # Build
+/// Assembles a packet around `payload`
+pub fn build(
+    source: Ipv4Address,
+    destination: Ipv4Address,
+    protocol: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_SIZE + payload.len());
+    packet.push(VERSION << 4 | (HEADER_SIZE / 4) as u8);
+    packet.push(0); // Type of service
+    packet.extend_from_slice(&((HEADER_SIZE + payload.len()) as u16).to_be_bytes());
+    packet.extend_from_slice(&0u16.to_be_bytes()); // Identification
+    packet.extend_from_slice(&DONT_FRAGMENT.to_be_bytes());
+    packet.push(DEFAULT_TTL);
+    packet.push(protocol);
+    packet.extend_from_slice(&0u16.to_be_bytes()); // Checksum
+    packet.extend_from_slice(&source.0);
+    packet.extend_from_slice(&destination.0);
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// # Send
+/// Sends `payload` to `destination`, whose hardware address has to be known already
+pub fn send(
+    interface: &Interface,
+    next_hop: MacAddress,
+    destination: Ipv4Address,
+    protocol: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let source = interface.ip().unwrap_or(Ipv4Address::UNSPECIFIED);
+    let packet = build(source, destination, protocol, payload);
+    interface.send(next_hop, EtherType::Ipv4, &packet)
+}
+
+/// # Transmit
+/// Sends `payload` to `destination` through `interface`, directly if `destination` is on the link
+/// and through the router otherwise. If the hardware address of the next hop is not known yet,
+/// the packet is sent once ARP resolved it.
+pub fn transmit(
+    interface: &Interface,
+    destination: Ipv4Address,
+    protocol: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let source = interface.ip().unwrap_or(Ipv4Address::UNSPECIFIED);
+    let packet = build(source, destination, protocol, payload);
+    let is_broadcast = destination == Ipv4Address::BROADCAST
+        || interface
+            .config()
+            .map_or(false, |config| destination == config.broadcast());
+    if is_broadcast {
+        return interface.send(MacAddress::BROADCAST, EtherType::Ipv4, &packet);
+    }
+    let next_hop = interface
+        .config()
+        .and_then(|config| config.next_hop(destination))
+        .ok_or(Error::NetworkIsUnreachable)?;
+    match arp::lookup(next_hop) {
+        Some(mac) => interface.send(mac, EtherType::Ipv4, &packet),
+        None => arp::resolve(interface, next_hop, packet),
+    }
+}
+
+/// # Handle
+/// Processes an IPv4 packet received on `interface` from the hardware address `source`
+pub fn handle(interface: &Interface, source: MacAddress, data: &[u8]) -> Result<(), RxError> {
+    let packet = Packet::parse(data)?;
+    if !interface.accepts(packet.destination) {
+        return Err(RxError::Unsupported);
+    }
+    match packet.protocol {
+        IpProtocol::Icmp => icmp::handle(interface, source, &packet),
+        IpProtocol::Udp => udp::handle(&packet),
+        _ => Err(RxError::Unsupported),
+    }
+}
@@ -0,0 +1,383 @@
+//! # Net
+//! A minimal IPv4 network stack on top of Ethernet.
+//!
+//! Network drivers register their devices with `register_device`, which creates an `Interface`
+//! for each of them. The first interface is configured through DHCP, falling back to the `ip=`
+//! option on the command line (e.g. `ip=10.0.2.15/24`) if there is no DHCP server. With the
+//! `nodhcp` flag the static configuration is used right away. Received frames are processed by the
+//! `SoftirqKind::NetRx` softirq for devices that raise an interrupt, and by a kernel task that
+//! polls the others. Frames that are malformed or not meant for us are counted and dropped.
+//!
+//! There is no routing table yet: Packets are sent through the first interface whose subnet
+//! contains the destination, or through the router of the first interface that has one.
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+
+use crate::error::Result;
+use crate::irq::softirq::{self, SoftirqKind};
+use crate::scheduler::spin::Mutex;
+use crate::{cmdline, counter, info, scheduler, warn};
+
+pub mod arp;
+pub mod checksum;
+pub mod dhcp;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod udp;
+
+counter!(pub RX_FRAMES = "net.rx.frames");
+counter!(pub TX_FRAMES = "net.tx.frames");
+counter!(pub RX_BAD_LENGTH = "net.rx.bad_length");
+counter!(pub RX_BAD_CHECKSUM = "net.rx.bad_checksum");
+counter!(pub RX_IGNORED = "net.rx.ignored");
+counter!(pub RX_FRAGMENTS = "net.rx.fragments");
+
+/// The command line option holding the static configuration of the first interface
+pub const IP_OPTION: &str = "ip";
+/// The command line flag that turns off DHCP
+pub const NO_DHCP_FLAG: &str = "nodhcp";
+/// The prefix length used if `ip=` does not specify one
+const DEFAULT_PREFIX: u8 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: Self = Self([0xFF; 6]);
+}
+
+impl core::fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Self = Self([0; 4]);
+    pub const BROADCAST: Self = Self([0xFF; 4]);
+
+    /// # Parse
+    /// Parses the dotted decimal notation, e.g. `10.0.2.15`
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut address = [0u8; 4];
+        let mut parts = text.split('.');
+        for byte in address.iter_mut() {
+            *byte = parts.next()?.parse().ok()?;
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Self(address)),
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+}
+
+impl core::fmt::Display for Ipv4Address {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+/// # IPv4 Config
+/// The address of an interface, the size of its subnet and the servers it uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Config {
+    pub address: Ipv4Address,
+    pub prefix: u8,
+    /// The default gateway
+    pub router: Option<Ipv4Address>,
+    pub dns: Option<Ipv4Address>,
+}
+
+impl Ipv4Config {
+    /// # Parse
+    /// Parses `address/prefix`, the prefix may be left out
+    pub fn parse(text: &str) -> Option<Self> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, prefix.parse().ok()?),
+            None => (text, DEFAULT_PREFIX),
+        };
+        if prefix > 32 {
+            return None;
+        }
+        Some(Self {
+            address: Ipv4Address::parse(address)?,
+            prefix,
+            router: None,
+            dns: None,
+        })
+    }
+
+    pub fn netmask(&self) -> u32 {
+        u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+    }
+
+    /// Whether `address` is part of the subnet
+    pub fn contains(&self, address: Ipv4Address) -> bool {
+        (address.to_u32() ^ self.address.to_u32()) & self.netmask() == 0
+    }
+
+    /// The directed broadcast address of the subnet
+    pub fn broadcast(&self) -> Ipv4Address {
+        Ipv4Address((self.address.to_u32() | !self.netmask()).to_be_bytes())
+    }
+
+    /// # Next Hop
+    /// Where packets to `destination` are sent: The destination itself if it is on the link,
+    /// otherwise the router
+    pub fn next_hop(&self, destination: Ipv4Address) -> Option<Ipv4Address> {
+        if self.contains(destination) || destination == Ipv4Address::BROADCAST {
+            Some(destination)
+        } else {
+            self.router
+        }
+    }
+}
+
+impl core::fmt::Display for Ipv4Config {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+/// Why a received frame was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RxError {
+    /// A length field does not match the data
+    BadLength,
+    BadChecksum,
+    /// IPv4 fragments are not reassembled
+    Fragmented,
+    /// Not addressed to us or a protocol that is not handled
+    Unsupported,
+}
+
+/// # Network Device
+/// A device sending and receiving Ethernet frames
+pub trait NetworkDevice: Send + Sync {
+    fn mac(&self) -> MacAddress;
+    /// Sends a complete frame, without the frame check sequence
+    fn send(&self, frame: &[u8]) -> Result<()>;
+    /// Takes the next received frame, if there is one
+    fn receive(&self) -> Option<Vec<u8>>;
+    /// Whether the device raises `SoftirqKind::NetRx` when it received frames, else it is polled
+    fn raises_interrupts(&self) -> bool {
+        false
+    }
+}
+
+/// # Interface
+/// A network device together with its IPv4 configuration
+pub struct Interface {
+    name: String,
+    device: Arc<dyn NetworkDevice>,
+    config: Mutex<Option<Ipv4Config>>,
+}
+
+impl Interface {
+    pub fn new(name: &str, device: Arc<dyn NetworkDevice>) -> Self {
+        Self {
+            name: name.into(),
+            device,
+            config: Mutex::new(None),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mac(&self) -> MacAddress {
+        self.device.mac()
+    }
+
+    pub fn raises_interrupts(&self) -> bool {
+        self.device.raises_interrupts()
+    }
+
+    pub fn config(&self) -> Option<Ipv4Config> {
+        *self.config.lock()
+    }
+
+    pub fn set_config(&self, config: Option<Ipv4Config>) {
+        *self.config.lock() = config;
+    }
+
+    pub fn ip(&self) -> Option<Ipv4Address> {
+        self.config().map(|config| config.address)
+    }
+
+    /// Whether packets sent to `destination` are meant for this interface
+    pub fn accepts(&self, destination: Ipv4Address) -> bool {
+        match self.config() {
+            Some(config) => {
+                destination == config.address
+                    || destination == config.broadcast()
+                    || destination == Ipv4Address::BROADCAST
+            }
+            None => destination == Ipv4Address::BROADCAST,
+        }
+    }
+
+    /// # Send
+    /// Sends `payload` in a frame to `destination`
+    pub fn send(&self, destination: MacAddress, ether_type: u16, payload: &[u8]) -> Result<()> {
+        let frame = ethernet::build(destination, self.mac(), ether_type, payload);
+        self.device.send(&frame)?;
+        TX_FRAMES.increment();
+        Ok(())
+    }
+
+    /// # Handle Frame
+    /// Processes a received frame, counting it if it is dropped
+    pub fn handle_frame(&self, data: &[u8]) {
+        RX_FRAMES.increment();
+        match self.process(data) {
+            Ok(()) => {}
+            Err(RxError::BadLength) => RX_BAD_LENGTH.increment(),
+            Err(RxError::BadChecksum) => RX_BAD_CHECKSUM.increment(),
+            Err(RxError::Fragmented) => RX_FRAGMENTS.increment(),
+            Err(RxError::Unsupported) => RX_IGNORED.increment(),
+        }
+    }
+
+    fn process(&self, data: &[u8]) -> core::result::Result<(), RxError> {
+        let frame = ethernet::Frame::parse(data)?;
+        if frame.destination != self.mac() && frame.destination != MacAddress::BROADCAST {
+            return Err(RxError::Unsupported);
+        }
+        match frame.ether_type {
+            ethernet::EtherType::Arp => arp::handle(self, frame.payload),
+            ethernet::EtherType::Ipv4 => ipv4::handle(self, frame.source, frame.payload),
+            _ => Err(RxError::Unsupported),
+        }
+    }
+
+    /// # Poll
+    /// Processes every frame the device received so far
+    pub fn poll(&self) {
+        while let Some(frame) = self.device.receive() {
+            self.handle_frame(&frame);
+        }
+    }
+}
+
+static INTERFACES: Mutex<Vec<Arc<Interface>>> = Mutex::new(Vec::new());
+
+/// # Register Device
+/// Creates an interface for `device`. The first one is configured from the command line.
+pub fn register_device(device: Arc<dyn NetworkDevice>) -> Arc<Interface> {
+    let mut interfaces = INTERFACES.lock();
+    let interface = Arc::new(Interface::new(&format!("eth{}", interfaces.len()), device));
+    if interfaces.is_empty() && cmdline::flag(NO_DHCP_FLAG) {
+        interface.set_config(static_config());
+    }
+    match interface.config() {
+        Some(config) => info!("{}: {} {}", interface.name(), interface.mac(), config),
+        None => info!("{}: {} unconfigured", interface.name(), interface.mac()),
+    }
+    interfaces.push(interface.clone());
+    interface
+}
+
+/// # Static Config
+/// The configuration given by the `ip=` command line option
+pub fn static_config() -> Option<Ipv4Config> {
+    let option = cmdline::value(IP_OPTION)?;
+    let config = Ipv4Config::parse(option);
+    if config.is_none() {
+        warn!("Invalid {}={}", IP_OPTION, option);
+    }
+    config
+}
+
+/// # Interfaces
+/// All registered interfaces
+pub fn interfaces() -> Vec<Arc<Interface>> {
+    INTERFACES.lock().clone()
+}
+
+/// # Route
+/// The interface through which `destination` is reached, preferring interfaces on the same link
+pub fn route(destination: Ipv4Address) -> Option<Arc<Interface>> {
+    let interfaces = interfaces();
+    let on_link = interfaces.iter().find(|interface| {
+        interface.config().map_or(false, |config| {
+            config.contains(destination) || destination == Ipv4Address::BROADCAST
+        })
+    });
+    on_link
+        .or_else(|| {
+            interfaces.iter().find(|interface| {
+                interface
+                    .config()
+                    .map_or(false, |config| config.router.is_some())
+            })
+        })
+        .cloned()
+}
+
+/// # Poll
+/// Processes the received frames of every interface
+pub fn poll() {
+    for interface in interfaces() {
+        interface.poll();
+    }
+}
+
+/// The handler of `SoftirqKind::NetRx`
+fn receive_softirq() {
+    for interface in interfaces() {
+        if interface.raises_interrupts() {
+            interface.poll();
+        }
+    }
+}
+
+/// Polls the interfaces that raise no interrupt
+fn net_task() {
+    let _tag = crate::alloc_tag!("net");
+    loop {
+        for interface in interfaces() {
+            if !interface.raises_interrupts() {
+                interface.poll();
+            }
+        }
+        scheduler::yield_now();
+    }
+}
+
+/// # Init Net
+/// Starts processing received frames, if there is any interface
+pub fn init_net() {
+    let interfaces = interfaces();
+    if !interfaces.is_empty() {
+        softirq::register(SoftirqKind::NetRx, receive_softirq);
+        // Frames may have arrived before there was a handler
+        softirq::raise(SoftirqKind::NetRx);
+        if interfaces
+            .iter()
+            .any(|interface| !interface.raises_interrupts())
+        {
+            scheduler::spawn("net", net_task);
+        }
+        if !cmdline::flag(NO_DHCP_FLAG) {
+            scheduler::spawn("dhcp", dhcp::dhcp_task);
+        }
+        if cmdline::value(udp::ECHO_OPTION).is_some() {
+            scheduler::spawn("udp-echo", udp::echo_task);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! # UDP
+//! Datagrams (RFC 768) and the sockets they are delivered to.
+//!
+//! Every socket is bound to a port, sockets that send before binding get an ephemeral one.
+//! Received datagrams are queued on their socket until they are read, if the queue is full they
+//! are dropped.
+use alloc::{collections::BTreeMap, collections::VecDeque, sync::Arc, sync::Weak, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use super::checksum::checksum;
+use super::ipv4::{self, IpProtocol, Packet};
+use super::{ethernet, Interface, Ipv4Address, RxError};
+use crate::error::{Error, Result};
+use crate::scheduler::spin::Mutex;
+use crate::scheduler::WaitQueue;
+use crate::{cmdline, counter, info, warn};
+
+pub const HEADER_SIZE: usize = 8;
+/// The largest payload that fits into an unfragmented packet
+pub const MAX_PAYLOAD: usize = ethernet::MTU - ipv4::HEADER_SIZE - HEADER_SIZE;
+/// The number of datagrams a socket holds until they are read
+const MAX_QUEUED: usize = 64;
+/// Ports that are handed out to sockets which do not pick one, up to the last port
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+const EPHEMERAL_PORTS: u16 = 16384;
+/// The command line option that starts an echo server on the given port, e.g. `udp.echo=7`
+pub const ECHO_OPTION: &str = "udp.echo";
+
+counter!(pub RX_QUEUE_FULL = "net.udp.rx.queue_full");
+counter!(pub RX_NO_SOCKET = "net.udp.rx.no_socket");
+
+/// # Endpoint
+/// An address and a port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Endpoint {
+    pub address: Ipv4Address,
+    pub port: u16,
+}
+
+/// # Datagram
+/// A parsed UDP datagram, borrowing its payload
+#[derive(Debug, Clone, Copy)]
+pub struct Datagram<'a> {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Datagram<'a> {
+    /// # Parse
+    /// Validates the length and the checksum of the datagram in `packet`. A checksum of zero
+    /// means that the sender did not compute one.
+    pub fn parse(packet: &Packet<'a>) -> Result<Self, RxError> {
+        let data = packet.payload;
+        if data.len() < HEADER_SIZE {
+            return Err(RxError::BadLength);
+        }
+        let length = u16::from_be_bytes([data[4], data[5]]) as usize;
+        if length < HEADER_SIZE || length > data.len() {
+            return Err(RxError::BadLength);
+        }
+        let data = &data[..length];
+        let sum = u16::from_be_bytes([data[6], data[7]]);
+        if sum != 0 && pseudo_checksum(packet.source, packet.destination, data) != 0 {
+            return Err(RxError::BadChecksum);
+        }
+        Ok(Self {
+            source_port: u16::from_be_bytes([data[0], data[1]]),
+            destination_port: u16::from_be_bytes([data[2], data[3]]),
+            payload: &data[HEADER_SIZE..],
+        })
+    }
+}
+
+/// The checksum of `datagram` including the pseudo header made up of the addresses, the
+/// protocol and the length
+fn pseudo_checksum(source: Ipv4Address, destination: Ipv4Address, datagram: &[u8]) -> u16 {
+    let mut data = Vec::with_capacity(12 + datagram.len());
+    data.extend_from_slice(&source.0);
+    data.extend_from_slice(&destination.0);
+    data.push(0);
+    data.push(IpProtocol::Udp);
+    data.extend_from_slice(&(datagram.len() as u16).to_be_bytes());
+    data.extend_from_slice(datagram);
+    checksum(&data)
+}
+
+/// # Build
+/// Assembles a datagram around `payload`
+pub fn build(source: Endpoint, destination: Endpoint, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_SIZE + payload.len());
+    datagram.extend_from_slice(&source.port.to_be_bytes());
+    datagram.extend_from_slice(&destination.port.to_be_bytes());
+    datagram.extend_from_slice(&((HEADER_SIZE + payload.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&0u16.to_be_bytes()); // Checksum
+    datagram.extend_from_slice(payload);
+    // Zero means no checksum, so a checksum of zero is sent as its complement
+    let sum = match pseudo_checksum(source.address, destination.address, &datagram) {
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    datagram
+}
+
+/// # Send Via
+/// Sends `payload` from `source_port` to `destination` through `interface`
+pub fn send_via(
+    interface: &Interface,
+    source_port: u16,
+    destination: Endpoint,
+    payload: &[u8],
+) -> Result<()> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(Error::MessageTooLong);
+    }
+    let source = Endpoint {
+        address: interface.ip().unwrap_or(Ipv4Address::UNSPECIFIED),
+        port: source_port,
+    };
+    let datagram = build(source, destination, payload);
+    ipv4::transmit(interface, destination.address, IpProtocol::Udp, &datagram)
+}
+
+/// # Handle
+/// Delivers the datagram in `packet` to the socket bound to its port
+pub fn handle(packet: &Packet) -> Result<(), RxError> {
+    let datagram = Datagram::parse(packet)?;
+    let socket = match PORTS
+        .lock()
+        .get(&datagram.destination_port)
+        .and_then(Weak::upgrade)
+    {
+        Some(socket) => socket,
+        None => {
+            RX_NO_SOCKET.increment();
+            return Err(RxError::Unsupported);
+        }
+    };
+    let source = Endpoint {
+        address: packet.source,
+        port: datagram.source_port,
+    };
+    socket.deliver(source, datagram.payload);
+    Ok(())
+}
+
+/// The sockets by the port they are bound to
+static PORTS: Mutex<BTreeMap<u16, Weak<UdpSocket>>> = Mutex::new(BTreeMap::new());
+/// Where the search for a free ephemeral port starts
+static NEXT_EPHEMERAL: AtomicU16 = AtomicU16::new(FIRST_EPHEMERAL_PORT);
+
+/// # Reserve Port
+/// Enters `socket` into the port table, at a free ephemeral port if `port` is zero
+fn reserve_port(socket: &Arc<UdpSocket>, port: u16) -> Result<u16> {
+    let mut ports = PORTS.lock();
+    let port = match port {
+        0 => {
+            let start = NEXT_EPHEMERAL.load(Ordering::Relaxed) - FIRST_EPHEMERAL_PORT;
+            let port = (0..EPHEMERAL_PORTS)
+                .map(|offset| FIRST_EPHEMERAL_PORT + (start + offset) % EPHEMERAL_PORTS)
+                .find(|port| !ports.contains_key(port))
+                .ok_or(Error::AddressAlreadyInUse)?;
+            let next = (port - FIRST_EPHEMERAL_PORT + 1) % EPHEMERAL_PORTS;
+            NEXT_EPHEMERAL.store(FIRST_EPHEMERAL_PORT + next, Ordering::Relaxed);
+            port
+        }
+        port if ports.contains_key(&port) => return Err(Error::AddressAlreadyInUse),
+        port => port,
+    };
+    ports.insert(port, Arc::downgrade(socket));
+    Ok(port)
+}
+
+/// # UDP Socket
+pub struct UdpSocket {
+    port: Mutex<Option<u16>>,
+    received: Mutex<VecDeque<(Endpoint, Vec<u8>)>>,
+    waiters: WaitQueue,
+    nonblocking: AtomicBool,
+}
+
+impl UdpSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            port: Mutex::new(None),
+            received: Mutex::new(VecDeque::new()),
+            waiters: WaitQueue::new(),
+            nonblocking: AtomicBool::new(false),
+        })
+    }
+
+    /// The port the socket is bound to
+    pub fn port(&self) -> Option<u16> {
+        *self.port.lock()
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+
+    /// # Bind
+    /// Binds the socket to `port`, or to a free ephemeral port if `port` is zero
+    ///
+    /// ## Returns
+    /// - u16 = The port the socket is bound to
+    pub fn bind(self: &Arc<Self>, port: u16) -> Result<u16> {
+        let mut own_port = self.port.lock();
+        if own_port.is_some() {
+            return Err(Error::InvalidArgument);
+        }
+        let port = reserve_port(self, port)?;
+        *own_port = Some(port);
+        Ok(port)
+    }
+
+    /// # Send To
+    /// Sends `data` as a single datagram to `destination`, binding the socket first if needed
+    pub fn send_to(self: &Arc<Self>, data: &[u8], destination: Endpoint) -> Result<usize> {
+        let port = {
+            let mut own_port = self.port.lock();
+            match *own_port {
+                Some(port) => port,
+                None => *own_port.insert(reserve_port(self, 0)?),
+            }
+        };
+        let interface = super::route(destination.address).ok_or(Error::NetworkIsUnreachable)?;
+        send_via(&interface, port, destination, data)?;
+        Ok(data.len())
+    }
+
+    /// # Receive From
+    /// Takes the next datagram, waiting for one unless the socket is non-blocking or
+    /// `nonblocking` is set. A datagram that is larger than `buf` is truncated.
+    ///
+    /// ## Returns
+    /// - (usize, Endpoint) = The number of bytes copied into `buf` and the sender
+    pub fn receive_from(&self, buf: &mut [u8], nonblocking: bool) -> Result<(usize, Endpoint)> {
+        let nonblocking = nonblocking || self.nonblocking.load(Ordering::Relaxed);
+        loop {
+            if let Some((source, data)) = self.received.lock().pop_front() {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                return Ok((len, source));
+            }
+            if nonblocking {
+                return Err(Error::OperationWouldBlock);
+            }
+            self.waiters.wait_until(|| !self.received.lock().is_empty());
+        }
+    }
+
+    /// # Wait
+    /// Blocks until a datagram is queued, for at most `timeout_ms` milliseconds
+    ///
+    /// ## Returns
+    /// - bool = Whether a datagram is queued, false if the time ran out
+    pub fn wait(&self, timeout_ms: u64) -> bool {
+        self.waiters
+            .wait_until_timeout(|| !self.received.lock().is_empty(), timeout_ms)
+    }
+
+    fn deliver(&self, source: Endpoint, data: &[u8]) {
+        {
+            let mut received = self.received.lock();
+            if received.len() >= MAX_QUEUED {
+                RX_QUEUE_FULL.increment();
+                return;
+            }
+            received.push_back((source, data.to_vec()));
+        }
+        self.waiters.wake_all();
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Some(port) = *self.port.get_mut() {
+            PORTS.lock().remove(&port);
+        }
+    }
+}
+
+/// # Echo Task
+/// Sends every datagram received on the port given by `ECHO_OPTION` back to its sender
+pub fn echo_task() {
+    let port = match cmdline::value(ECHO_OPTION).and_then(|port| port.parse().ok()) {
+        Some(port) => port,
+        None => {
+            warn!("Invalid {}", ECHO_OPTION);
+            return;
+        }
+    };
+    let socket = UdpSocket::new();
+    if let Err(err) = socket.bind(port) {
+        warn!("udp-echo: Failed to bind port {}: {}", port, err.text());
+        return;
+    }
+    info!("udp-echo: Listening on port {}", port);
+    let mut buf = [0u8; MAX_PAYLOAD];
+    loop {
+        match socket.receive_from(&mut buf, false) {
+            Ok((len, source)) => {
+                let _ = socket.send_to(&buf[..len], source);
+            }
+            Err(err) => warn!("udp-echo: {}", err.text()),
+        }
+    }
+}
@@ -1,14 +1,65 @@
-use crate::framebuffer::clear_screen;
+//! # Panic
+//! The panic screen: The panic message, a backtrace and a QR code of a crash record that can be
+//! scanned off the screen, for machines without a serial port. The next boot gets to see the
+//! panic as well, see `crashlog`.
+//!
+//! The heap may be what is broken, so everything the panic screen needs is static.
+use crate::arch::{backtrace, unwind};
+use crate::framebuffer::qr::{QrCode, MAX_PAYLOAD, QUIET_ZONE};
 use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::framebuffer::{clear_screen, lock_console};
 use crate::kcolorchange;
-use crate::{kprint, kprintln};
+use crate::kprintln;
+use crate::scheduler::spin::Mutex;
+use core::fmt::Write;
 use core::panic::PanicInfo;
 
+/// The number of backtrace frames shown and recorded
+const PANIC_FRAMES: usize = 8;
+/// The largest size of a QR code module in pixels
+const MAX_MODULE_SCALE: usize = 6;
+const BACKGROUND: u32 = 0x020936;
+const FOREGROUND: u32 = 0xfac102;
+
+/// # Panic Screen
+/// The buffers of the panic screen
+struct PanicScreen {
+    frames: [u64; PANIC_FRAMES],
+    frame_count: usize,
+    record: Record,
+    qr: QrCode,
+}
+
+static PANIC_SCREEN: Mutex<PanicScreen> = Mutex::new(PanicScreen {
+    frames: [0; PANIC_FRAMES],
+    frame_count: 0,
+    record: Record {
+        buffer: [0; MAX_PAYLOAD],
+        len: 0,
+    },
+    qr: QrCode::new(),
+});
+
+/// # Record
+/// The crash record, cut off once it is too long for a QR code
+struct Record {
+    buffer: [u8; MAX_PAYLOAD],
+    len: usize,
+}
+
+impl Write for Record {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let len = s.len().min(MAX_PAYLOAD - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
 #[panic_handler]
 fn panic_handler(info: &PanicInfo) -> ! {
     // This is not performant code - This creates a pretty panic-screen.
     // This is the last thing that this OS does - It does not have to be performant
-    use core::fmt::Write;
 
     // If we are testing harshly, exit qemu here
     #[cfg(feature = "harsh-tests")]
@@ -16,34 +67,83 @@ fn panic_handler(info: &PanicInfo) -> ! {
         test::QemuExitCode::TotalFailure.exit_qemu()
     }
 
-    kcolorchange!(bg: 0x020936_u32, fg: 0xfac102_u32);
+    // There is no framebuffer to draw the panic screen to yet
+    if crate::earlylog::is_active() {
+        crate::earlylog::panic(info);
+        halt();
+    }
+
+    // GDB gets to look at the panic first, with the code that panicked up the stack
+    #[cfg(feature = "gdbstub")]
+    crate::gdbstub::on_panic();
 
+    let rip = backtrace::instruction_pointer();
+    // Held by a panic while drawing the panic screen
+    let mut screen = match PANIC_SCREEN.try_lock() {
+        Some(screen) => screen,
+        None => halt(),
+    };
+    let screen = &mut *screen;
+    screen.frame_count = 0;
+    let (rsp, rbp) = (backtrace::stack_pointer(), backtrace::frame_pointer());
+    unwind::backtrace_dwarf(rip, rsp, rbp, |address| {
+        if screen.frame_count < PANIC_FRAMES {
+            screen.frames[screen.frame_count] = address;
+            screen.frame_count += 1;
+        }
+    });
+    let frames = &screen.frames[..screen.frame_count];
     let (file, line, col) = match info.location() {
         Some(loc) => (loc.file(), loc.line(), loc.column()),
         None => ("Unknown", 0, 0),
     };
+
+    // The code an exception interrupted holds the console, so there is no panic screen. The
+    // text goes to COM1 instead.
+    if lock_console().is_none() {
+        print_report(info, file, line, col, frames);
+        crate::crashlog::save(info);
+        crate::logdisk::panic_flush();
+        halt();
+    }
+
+    kcolorchange!(bg: BACKGROUND, fg: FOREGROUND);
+    let margin = unsafe {
+        let mut guard = FRAMEBUFFER_GUARD.lock();
+        let guard = guard.assume_init_mut();
+        match guard.font() {
+            Some(font) => (font.height(), font.width() * 2),
+            None => (0, 0),
+        }
+    };
+    clear_screen(BACKGROUND);
     unsafe {
-        let width = FRAMEBUFFER_GUARD.lock().assume_init_mut().resolution().0;
-        let height = FRAMEBUFFER_GUARD.lock().assume_init_mut().resolution().1;
-        clear_screen(0x020936_u32);
-
-        let by_how_much = if width / 2 > 200 && height / 2 > 200 {
-            200_usize
-        } else {
-            0_usize
-        };
-
-        FRAMEBUFFER_GUARD
-            .lock()
-            .assume_init_mut()
-            .set_location(height / 2 - by_how_much, width / 2 - by_how_much);
-
-        FRAMEBUFFER_GUARD
-            .lock()
-            .assume_init_mut()
-            .set_column_starting_point(width / 2 - by_how_much);
+        let mut guard = FRAMEBUFFER_GUARD.lock();
+        let guard = guard.assume_init_mut();
+        guard.set_location(margin.0, margin.1);
+        guard.set_column_starting_point(margin.1);
     }
 
+    print_report(info, file, line, col, frames);
+    crate::crashlog::save(info);
+    crate::logdisk::panic_flush();
+
+    screen.record.len = 0;
+    let _ = write_record(&mut screen.record, file, line, col, rip, frames);
+    if screen
+        .qr
+        .encode(&screen.record.buffer[..screen.record.len])
+        .is_ok()
+    {
+        draw_qr(&screen.qr, margin);
+    }
+
+    halt()
+}
+
+/// # Print Report
+/// Prints where the kernel panicked, the message and the backtrace
+fn print_report(info: &PanicInfo, file: &str, line: u32, col: u32, frames: &[u64]) {
     kprintln!("*+~*+~*+~*+~*+~*+~*+~*+~*+~ Kernel Panic *+~*+~*+~*+~*+~*+~*+~*+~");
     kprintln!();
     kprintln!("At: ");
@@ -51,21 +151,63 @@ fn panic_handler(info: &PanicInfo) -> ! {
     kprintln!("\t-> Line    :: {}", line);
     kprintln!("\t-> Column  :: {}", col);
     kprintln!("Message: ");
-    if let Some(args) = info.message() {
-        kprint!("\t-> ");
-        unsafe {
-            FRAMEBUFFER_GUARD
-                .lock()
-                .assume_init_mut()
-                .write_fmt(*args)
-                .unwrap()
-        }
-        kprint!("\n");
-    } else {
-        kprintln!("\t-> No Message provided");
+    match info.message() {
+        Some(args) => kprintln!("\t-> {}", args),
+        None => kprintln!("\t-> No Message provided"),
+    }
+    kprintln!("Backtrace: ");
+    for address in frames {
+        kprintln!("\t-> {:#018x}", address);
     }
     kprintln!();
     kprintln!("*+~*+~*+~*+~*+~*+~*+~*+~*+~ Panic End *+~*+~*+~*+~*+~*+~*+~*+~*+~");
+}
+
+/// # Write Record
+/// The crash record: The kernel version, where the kernel panicked and the backtrace, one per
+/// line with addresses in hex
+fn write_record(
+    record: &mut Record,
+    file: &str,
+    line: u32,
+    col: u32,
+    rip: u64,
+    frames: &[u64],
+) -> core::fmt::Result {
+    writeln!(record, "esque {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(record, "at {}:{}:{}", file, line, col)?;
+    writeln!(record, "rip {:x}", rip)?;
+    write!(record, "bt")?;
+    for address in frames {
+        write!(record, " {:x}", address)?;
+    }
+    Ok(())
+}
+
+/// # Draw QR
+/// Draws `code` below the text, as large as fits up to `MAX_MODULE_SCALE`
+fn draw_qr(code: &QrCode, margin: (usize, usize)) {
+    let mut guard = FRAMEBUFFER_GUARD.lock();
+    let guard = unsafe { guard.assume_init_mut() };
+    let font = match guard.font() {
+        Some(font) => font,
+        None => return,
+    };
+    let (width, height) = guard.size();
+    let top = (guard.cursor().0 + 1) * font.height();
+    let modules = code.size() + 2 * QUIET_ZONE;
+    let room = height
+        .saturating_sub(top + margin.0)
+        .min(width.saturating_sub(2 * margin.1));
+    let scale = (room / modules).min(MAX_MODULE_SCALE);
+    if scale == 0 {
+        return;
+    }
+    guard.draw_qr(code, margin.1, top, scale);
+}
+
+/// Halts the calling CPU for good
+pub(crate) fn halt() -> ! {
     unsafe {
         comasm::clear_interrupts();
     };
@@ -1,15 +1,766 @@
-use core::mem::size_of;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bks::PAGE_SIZE;
 
 use pci_lookup::{
     get_device_name, get_prog_if_name, get_subclass_name, get_vendor_name, DEVICE_CLASSES,
 };
 
+use crate::scheduler::spin::Mutex;
 use crate::{
-    acpi::{config::DeviceConfig, ACPITable, MCFGHeader},
-    address_of, from_addr,
-    memory::paging::page_table_manager::PAGE_TABLE_MANAGER,
+    acpi::{config::DeviceConfig, ACPIFindable, MCFGHeader, Table},
+    device::tree::{self, DeviceClass, DeviceId, Resource},
+    error::{Error, Result},
+    from_addr, info,
+    irq::msi,
+    memory::paging::pat::MemoryType,
+    memory::{map_mmio, unmap_mmio, PhysicalAddress},
+    scheduler::sync,
+    warn,
 };
 
+/// The maximum number of functions kept in the registry, every further one is ignored
+pub const MAX_PCI_DEVICES: usize = 64;
+/// The size of the configuration space of a function in the ECAM
+const CONFIG_SPACE_SIZE: u64 = 0x1000;
+/// Where the extended capabilities of a PCIe function start, behind the 256 bytes of PCI
+pub const EXTENDED_CAPABILITIES_START: u64 = 0x100;
+/// Set in the status register if the function has a list of capabilities
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+/// Protects against malformed capability lists that loop
+const MAX_CAPABILITIES: usize = 48;
+/// Protects against malformed extended capability lists that loop, every one takes a dword
+const MAX_EXTENDED_CAPABILITIES: usize =
+    ((CONFIG_SPACE_SIZE - EXTENDED_CAPABILITIES_START) / 4) as usize;
+/// The base address registers of a function that is not a bridge
+const BAR_COUNT: u64 = 6;
+
+enumtastic::const_enum! {
+    /// Offsets into the configuration space of a function
+    pub enum PciConfigRegister: u64 => {
+        Command = 0x04,
+        Status = 0x06,
+        Bar0 = 0x10,
+        CapabilitiesPointer = 0x34,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum PciCapability: u8 => {
+        PowerManagement = 0x01,
+        Msi = 0x05,
+        VendorSpecific = 0x09,
+        PciExpress = 0x10,
+        MsiX = 0x11,
+        Sata = 0x12,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    /// The ids of the capabilities in the extended configuration space of a PCIe function
+    pub enum PciExtendedCapability: u16 => {
+        AdvancedErrorReporting = 0x0001,
+        VirtualChannel = 0x0002,
+        SerialNumber = 0x0003,
+        AccessControlServices = 0x000D,
+        AlternativeRoutingId = 0x000E,
+        AddressTranslationServices = 0x000F,
+        SrIov = 0x0010,
+        LatencyTolerance = 0x0018,
+        SecondaryPciExpress = 0x0019,
+        L1Substates = 0x001E,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    /// Offsets into the PCI Express capability
+    pub enum PcieRegister: u64 => {
+        Capabilities = 0x02,
+        LinkCapabilities = 0x0C,
+        LinkStatus = 0x12,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    /// Offsets into the Advanced Error Reporting capability
+    pub enum AerRegister: u64 => {
+        UncorrectableStatus = 0x04,
+        UncorrectableMask = 0x08,
+        UncorrectableSeverity = 0x0C,
+        CorrectableStatus = 0x10,
+        CorrectableMask = 0x14,
+        CapabilitiesControl = 0x18,
+        HeaderLog = 0x1C,
+    }
+
+    impl {}
+}
+
+/// # Capability Name
+/// What the capability `id` is called, for `lspci`
+pub fn capability_name(id: u8) -> &'static str {
+    match id {
+        PciCapability::PowerManagement => "Power Management",
+        PciCapability::Msi => "MSI",
+        PciCapability::VendorSpecific => "Vendor Specific",
+        PciCapability::PciExpress => "PCI Express",
+        PciCapability::MsiX => "MSI-X",
+        PciCapability::Sata => "SATA",
+        _ => "Unknown",
+    }
+}
+
+/// # Extended Capability Name
+/// What the extended capability `id` is called, for `lspci`
+pub fn extended_capability_name(id: u16) -> &'static str {
+    match id {
+        PciExtendedCapability::AdvancedErrorReporting => "Advanced Error Reporting",
+        PciExtendedCapability::VirtualChannel => "Virtual Channel",
+        PciExtendedCapability::SerialNumber => "Device Serial Number",
+        PciExtendedCapability::AccessControlServices => "Access Control Services",
+        PciExtendedCapability::AlternativeRoutingId => "Alternative Routing-ID",
+        PciExtendedCapability::AddressTranslationServices => "Address Translation Services",
+        PciExtendedCapability::SrIov => "SR-IOV",
+        PciExtendedCapability::LatencyTolerance => "Latency Tolerance Reporting",
+        PciExtendedCapability::SecondaryPciExpress => "Secondary PCI Express",
+        PciExtendedCapability::L1Substates => "L1 PM Substates",
+        _ => "Unknown",
+    }
+}
+
+/// # Extended Capability
+/// A capability in the extended configuration space, found by `PciDevice::extended_capabilities()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    /// Where it is in the configuration space
+    pub offset: u64,
+    /// Where the next one is, 0 for the last one
+    pub next: u64,
+}
+
+impl ExtendedCapability {
+    /// # From Header
+    /// The capability whose header, the first dword, is `header` at `offset`
+    ///
+    /// ## Returns
+    /// - None = There is no capability, as the function has no extended configuration space
+    pub fn from_header(header: u32, offset: u64) -> Option<Self> {
+        if header == 0 || header == u32::MAX {
+            return None;
+        }
+        let next = (header >> 20) as u64 & !0b11;
+        Some(Self {
+            id: header as u16,
+            version: (header >> 16) as u8 & 0xF,
+            offset,
+            // Every capability is behind the start, anything else ends the list
+            next: if next < EXTENDED_CAPABILITIES_START {
+                0
+            } else {
+                next
+            },
+        })
+    }
+}
+
+/// # Link Speed
+/// The transfer rate of every lane of a PCIe link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LinkSpeed {
+    Gen1 = 1,
+    Gen2 = 2,
+    Gen3 = 3,
+    Gen4 = 4,
+    Gen5 = 5,
+    Gen6 = 6,
+}
+
+impl LinkSpeed {
+    /// # From Code
+    /// The speed the link registers encode as `code`
+    pub fn from_code(code: u8) -> Option<Self> {
+        [
+            Self::Gen1,
+            Self::Gen2,
+            Self::Gen3,
+            Self::Gen4,
+            Self::Gen5,
+            Self::Gen6,
+        ]
+        .into_iter()
+        .find(|speed| *speed as u8 == code)
+    }
+
+    pub fn transfer_rate(&self) -> &'static str {
+        match self {
+            Self::Gen1 => "2.5 GT/s",
+            Self::Gen2 => "5 GT/s",
+            Self::Gen3 => "8 GT/s",
+            Self::Gen4 => "16 GT/s",
+            Self::Gen5 => "32 GT/s",
+            Self::Gen6 => "64 GT/s",
+        }
+    }
+}
+
+/// # Link Status
+/// What a PCIe link negotiated, and what it could do at most
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkStatus {
+    /// None if the function reports a speed this kernel does not know
+    pub speed: Option<LinkSpeed>,
+    /// The number of lanes
+    pub width: u8,
+    pub max_speed: Option<LinkSpeed>,
+    pub max_width: u8,
+}
+
+impl LinkStatus {
+    /// # Decode
+    /// The status in the link capabilities and the link status register of the PCI Express
+    /// capability
+    pub fn decode(capabilities: u32, status: u16) -> Self {
+        Self {
+            speed: LinkSpeed::from_code(status as u8 & 0xF),
+            width: (status >> 4) as u8 & 0x3F,
+            max_speed: LinkSpeed::from_code(capabilities as u8 & 0xF),
+            max_width: (capabilities >> 4) as u8 & 0x3F,
+        }
+    }
+
+    /// Whether the link trained below what both ends of it can do, on one of them at least
+    pub fn is_degraded(&self) -> bool {
+        self.speed < self.max_speed || self.width < self.max_width
+    }
+}
+
+impl core::fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let rate =
+            |speed: Option<LinkSpeed>| speed.map_or("unknown speed", |speed| speed.transfer_rate());
+        write!(
+            f,
+            "{} x{} (capable of {} x{})",
+            rate(self.speed),
+            self.width,
+            rate(self.max_speed),
+            self.max_width
+        )
+    }
+}
+
+/// # AER Status
+/// The error registers of the Advanced Error Reporting capability
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AerStatus {
+    /// The uncorrectable errors that occurred, one bit per kind
+    pub uncorrectable: u32,
+    /// The uncorrectable errors that are fatal rather than not
+    pub severity: u32,
+    /// The correctable errors that occurred, one bit per kind
+    pub correctable: u32,
+    /// The bit of the uncorrectable error that occurred first
+    pub first_error: u8,
+    /// The header of the packet that caused the first uncorrectable error
+    pub header_log: [u32; 4],
+}
+
+impl AerStatus {
+    pub fn has_errors(&self) -> bool {
+        self.uncorrectable != 0 || self.correctable != 0
+    }
+
+    /// The uncorrectable errors that occurred and are fatal
+    pub fn fatal(&self) -> u32 {
+        self.uncorrectable & self.severity
+    }
+}
+
+enumtastic::const_enum! {
+    /// The message control register of the MSI capability
+    pub enum MsiControl: u16 => {
+        Enable = 1 << 0,
+        /// log2 of the number of vectors the function can raise, in bits 1-3
+        MultipleMessageCapable = 0b111 << 1,
+        /// log2 of the number of vectors the function may raise, in bits 4-6
+        MultipleMessageEnable = 0b111 << 4,
+        Address64 = 1 << 7,
+        PerVectorMasking = 1 << 8,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    /// The message control register of the MSI-X capability
+    pub enum MsiXControl: u16 => {
+        TableSizeMask = 0x7FF,
+        FunctionMask = 1 << 14,
+        Enable = 1 << 15,
+    }
+
+    impl {}
+}
+
+enumtastic::const_enum! {
+    pub enum PciCommand: u16 => {
+        IoSpace = 1 << 0,
+        MemorySpace = 1 << 1,
+        BusMaster = 1 << 2,
+        InterruptDisable = 1 << 10,
+    }
+
+    impl {}
+}
+
+/// # PCI Device
+/// A function found while enumerating the buses
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice {
+    /// The address of the configuration space of the function
+    pub address: u64,
+    pub bus: u8,
+    /// The device number on the bus
+    pub slot: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub program_interface: u8,
+}
+
+impl PciDevice {
+    /// # Location
+    /// Where the function is, as `bus:slot.function`, e.g. `00:1f.2`
+    pub fn location(&self) -> String {
+        format!("{:02x}:{:02x}.{}", self.bus, self.slot, self.function)
+    }
+
+    /// # Node
+    /// The node of the function in the device tree, `/pci/<location>`
+    pub fn node(&self) -> Option<DeviceId> {
+        tree::find(&["/pci/", &self.location()].concat())
+    }
+
+    /// # Device Class
+    /// What the function is in the device tree
+    pub fn device_class(&self) -> DeviceClass {
+        match self.class {
+            0x01 => DeviceClass::Storage,
+            0x02 => DeviceClass::Network,
+            0x03 => DeviceClass::Display,
+            0x06 => DeviceClass::Bridge,
+            0x07 => DeviceClass::Serial,
+            0x09 => DeviceClass::Input,
+            0x0C => DeviceClass::Bus,
+            _ => DeviceClass::Other,
+        }
+    }
+
+    pub fn read_u8(&self, offset: u64) -> u8 {
+        unsafe { core::ptr::read_volatile((self.address + offset) as *const u8) }
+    }
+
+    pub fn read_u16(&self, offset: u64) -> u16 {
+        unsafe { core::ptr::read_volatile((self.address + offset) as *const u16) }
+    }
+
+    pub fn write_u16(&self, offset: u64, value: u16) {
+        unsafe { core::ptr::write_volatile((self.address + offset) as *mut u16, value) }
+    }
+
+    pub fn read_u32(&self, offset: u64) -> u32 {
+        unsafe { core::ptr::read_volatile((self.address + offset) as *const u32) }
+    }
+
+    pub fn write_u32(&self, offset: u64, value: u32) {
+        unsafe { core::ptr::write_volatile((self.address + offset) as *mut u32, value) }
+    }
+
+    /// # BAR
+    /// The base address in the base address register `idx`, with the flag bits masked off.
+    /// 64 bit memory BARs are combined with the following register.
+    pub fn bar(&self, idx: u64) -> u64 {
+        let offset = PciConfigRegister::Bar0 + idx * 4;
+        let low = self.read_u32(offset);
+        if low & 1 != 0 {
+            // I/O space
+            return (low & !0b11) as u64;
+        }
+        let base = (low & !0b1111) as u64;
+        match (low >> 1) & 0b11 {
+            0b10 => base | (self.read_u32(offset + 4) as u64) << 32,
+            _ => base,
+        }
+    }
+
+    /// # Enable Bus Mastering
+    /// Allows the function to access memory on its own (DMA) and enables its memory space
+    pub fn enable_bus_mastering(&self) {
+        let command = self.read_u16(PciConfigRegister::Command);
+        self.write_u16(
+            PciConfigRegister::Command,
+            command | PciCommand::MemorySpace | PciCommand::BusMaster,
+        );
+    }
+
+    /// # Disable
+    /// Stops the function from decoding its BARs and from accessing memory on its own
+    pub fn disable(&self) {
+        let command = self.read_u16(PciConfigRegister::Command);
+        self.write_u16(
+            PciConfigRegister::Command,
+            command & !(PciCommand::IoSpace | PciCommand::MemorySpace | PciCommand::BusMaster),
+        );
+    }
+
+    pub fn is_bus_master(&self) -> bool {
+        self.read_u16(PciConfigRegister::Command) & PciCommand::BusMaster != 0
+    }
+
+    /// # Memory BARs
+    /// The base and the size of every memory BAR. Sizing overwrites the BARs for a moment, so
+    /// the function must not decode them (see `disable()`).
+    pub fn memory_bars(&self) -> Vec<(u64, u64)> {
+        let mut bars = Vec::new();
+        let mut idx = 0;
+        while idx < BAR_COUNT {
+            let offset = PciConfigRegister::Bar0 + idx * 4;
+            let low = self.read_u32(offset);
+            let is_64 = low & 1 == 0 && (low >> 1) & 0b11 == 0b10;
+            idx += if is_64 { 2 } else { 1 };
+            if low & 1 != 0 {
+                continue;
+            }
+            // The bits that read back as zero after writing ones are the size
+            self.write_u32(offset, u32::MAX);
+            let mut mask = (self.read_u32(offset) & !0b1111) as u64;
+            self.write_u32(offset, low);
+            if is_64 {
+                let high = self.read_u32(offset + 4);
+                self.write_u32(offset + 4, u32::MAX);
+                mask |= (self.read_u32(offset + 4) as u64) << 32;
+                self.write_u32(offset + 4, high);
+            } else if mask != 0 {
+                mask |= 0xFFFF_FFFF_0000_0000;
+            }
+            // Not implemented
+            if mask == 0 {
+                continue;
+            }
+            bars.push((self.bar(idx - if is_64 { 2 } else { 1 }), !mask + 1));
+        }
+        bars
+    }
+
+    /// # Capabilities
+    /// The id and the offset in the configuration space of every capability of the function
+    pub fn capabilities(&self) -> impl Iterator<Item = (u8, u64)> {
+        let device = *self;
+        let has_list = device.read_u16(PciConfigRegister::Status) & STATUS_CAPABILITIES_LIST != 0;
+        let mut offset = if has_list {
+            (device.read_u8(PciConfigRegister::CapabilitiesPointer) & 0xFC) as u64
+        } else {
+            0
+        };
+        core::iter::from_fn(move || {
+            if offset == 0 {
+                return None;
+            }
+            let capability = (device.read_u8(offset), offset);
+            offset = (device.read_u8(offset + 1) & 0xFC) as u64;
+            Some(capability)
+        })
+        .take(MAX_CAPABILITIES)
+    }
+
+    /// # Capability
+    /// The offset of the capability `id` in the configuration space, if the function has it
+    pub fn capability(&self, id: u8) -> Option<u64> {
+        self.capabilities()
+            .find(|(capability, _)| *capability == id)
+            .map(|(_, offset)| offset)
+    }
+
+    /// # Extended Capabilities
+    /// The capabilities in the extended configuration space, which only PCIe functions have
+    pub fn extended_capabilities(&self) -> impl Iterator<Item = ExtendedCapability> {
+        let device = *self;
+        let mut offset = EXTENDED_CAPABILITIES_START;
+        core::iter::from_fn(move || {
+            if offset == 0 {
+                return None;
+            }
+            let capability = ExtendedCapability::from_header(device.read_u32(offset), offset)?;
+            offset = capability.next;
+            Some(capability)
+        })
+        .take(MAX_EXTENDED_CAPABILITIES)
+    }
+
+    /// # Extended Capability
+    /// The offset of the extended capability `id`, if the function has it
+    pub fn extended_capability(&self, id: u16) -> Option<u64> {
+        self.extended_capabilities()
+            .find(|capability| capability.id == id)
+            .map(|capability| capability.offset)
+    }
+
+    /// # Is PCIe
+    /// Whether the function is a PCI Express one
+    pub fn is_pcie(&self) -> bool {
+        self.capability(PciCapability::PciExpress).is_some()
+    }
+
+    /// # Link Status
+    /// The speed and the width the link of the function trained to
+    ///
+    /// ## Returns
+    /// - None = The function is no PCIe function, or one without a link of its own, like the
+    ///   endpoints integrated into the root complex
+    pub fn link_status(&self) -> Option<LinkStatus> {
+        let pcie = self.capability(PciCapability::PciExpress)?;
+        // The device or port type, of which integrated endpoints and event collectors have no link
+        let port_type = (self.read_u16(pcie + PcieRegister::Capabilities) >> 4) & 0xF;
+        if matches!(port_type, 0x9 | 0xA) {
+            return None;
+        }
+        let status = LinkStatus::decode(
+            self.read_u32(pcie + PcieRegister::LinkCapabilities),
+            self.read_u16(pcie + PcieRegister::LinkStatus),
+        );
+        (status.max_width != 0).then(|| status)
+    }
+
+    /// # AER Status
+    /// The errors the Advanced Error Reporting capability recorded, if the function has it
+    pub fn aer_status(&self) -> Option<AerStatus> {
+        let aer = self.extended_capability(PciExtendedCapability::AdvancedErrorReporting)?;
+        let mut header_log = [0; 4];
+        for (i, dword) in header_log.iter_mut().enumerate() {
+            *dword = self.read_u32(aer + AerRegister::HeaderLog + i as u64 * 4);
+        }
+        Some(AerStatus {
+            uncorrectable: self.read_u32(aer + AerRegister::UncorrectableStatus),
+            severity: self.read_u32(aer + AerRegister::UncorrectableSeverity),
+            correctable: self.read_u32(aer + AerRegister::CorrectableStatus),
+            first_error: self.read_u32(aer + AerRegister::CapabilitiesControl) as u8 & 0x1F,
+            header_log,
+        })
+    }
+
+    /// # Clear AER Status
+    /// Clears the errors in `status`, read with `aer_status()`, so only new ones are reported.
+    /// Errors recorded since are kept.
+    pub fn clear_aer_status(&self, status: &AerStatus) {
+        if let Some(aer) = self.extended_capability(PciExtendedCapability::AdvancedErrorReporting) {
+            // Both are cleared by writing ones
+            self.write_u32(aer + AerRegister::UncorrectableStatus, status.uncorrectable);
+            self.write_u32(aer + AerRegister::CorrectableStatus, status.correctable);
+        }
+    }
+}
+
+struct PciRegistry {
+    devices: [Option<PciDevice>; MAX_PCI_DEVICES],
+    /// The name of the driver bound to the function at the same index
+    drivers: [Option<&'static str>; MAX_PCI_DEVICES],
+    len: usize,
+}
+
+impl PciRegistry {
+    fn index_of(&self, device: &PciDevice) -> Option<usize> {
+        self.devices[..self.len]
+            .iter()
+            .position(|found| found.map_or(false, |found| found.address == device.address))
+    }
+}
+
+/// Every function found by `PCI::enumerate`
+static PCI_DEVICES: Mutex<PciRegistry> = Mutex::new(PciRegistry {
+    devices: [None; MAX_PCI_DEVICES],
+    drivers: [None; MAX_PCI_DEVICES],
+    len: 0,
+});
+
+/// Every registered driver. Held while a driver attaches or detaches, so a function is only
+/// ever bound or unbound once at a time.
+static PCI_DRIVERS: sync::Mutex<Vec<&'static dyn PciDriver>> = sync::Mutex::new(Vec::new());
+
+/// # PCI Driver
+/// A driver for PCI functions. `register_driver()` binds it to every function it matches that
+/// no other driver has, `unbind()` and `rebind()` move functions between drivers at runtime.
+pub trait PciDriver: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether the driver can handle `device`
+    fn matches(&self, device: &PciDevice) -> bool;
+
+    /// # Attach
+    /// Sets up `device`, which no other driver uses
+    fn attach(&self, device: PciDevice) -> Result<()>;
+
+    /// # Detach
+    /// Stops using `device`. The driver has to stop the device first, so no DMA is outstanding
+    /// and none is started anymore, and only then free the memory the device accessed and drop
+    /// its `MsiVectors`. Bus mastering is cleared and the BARs are unmapped afterwards.
+    ///
+    /// ## Returns
+    /// - Error::DeviceOrResourceBusy = The device is in use and stays bound. Drivers that
+    ///   cannot let go of their devices keep this default.
+    fn detach(&self, _device: &PciDevice) -> Result<()> {
+        Err(Error::DeviceOrResourceBusy)
+    }
+}
+
+/// # Devices
+/// All functions found on the PCI buses
+pub fn devices() -> impl Iterator<Item = PciDevice> {
+    let devices = PCI_DEVICES.lock().devices;
+    devices.into_iter().flatten()
+}
+
+/// # Find Device
+/// The function at `location`, written as `bus:slot.function` like `PciDevice::location()`
+pub fn find_device(location: &str) -> Option<PciDevice> {
+    devices().find(|device| device.location() == location)
+}
+
+/// # Driver Of
+/// The name of the driver bound to `device`
+pub fn driver_of(device: &PciDevice) -> Option<&'static str> {
+    let registry = PCI_DEVICES.lock();
+    registry
+        .index_of(device)
+        .and_then(|idx| registry.drivers[idx])
+}
+
+fn set_driver(device: &PciDevice, driver: Option<&'static str>) {
+    let mut registry = PCI_DEVICES.lock();
+    if let Some(idx) = registry.index_of(device) {
+        registry.drivers[idx] = driver;
+    }
+}
+
+/// # Register Driver
+/// Adds `driver` and binds it to every function it matches that has no driver yet. Failing to
+/// attach to a function is logged, the function stays unbound.
+///
+/// ## Returns
+/// - usize = The number of functions the driver was bound to
+/// - Error::AlreadyExists = There is a driver with the same name
+pub fn register_driver(driver: &'static dyn PciDriver) -> Result<usize> {
+    let mut drivers = PCI_DRIVERS.lock();
+    if drivers.iter().any(|other| other.name() == driver.name()) {
+        return Err(Error::AlreadyExists);
+    }
+    drivers.push(driver);
+    let mut bound = 0;
+    for device in devices() {
+        if driver_of(&device).is_some() || !driver.matches(&device) {
+            continue;
+        }
+        match driver.attach(device) {
+            Ok(()) => {
+                set_driver(&device, Some(driver.name()));
+                bound += 1;
+            }
+            Err(err) => warn!(
+                "pci: {} cannot attach to {}: {}",
+                driver.name(),
+                device.location(),
+                err.text()
+            ),
+        }
+    }
+    Ok(bound)
+}
+
+/// # Unbind
+/// Detaches the driver of `device`, then clears bus mastering, unmaps the BARs and removes the
+/// shutdown hook of the function. The function can be bound again with `rebind()`.
+///
+/// Memory BARs smaller than a page stay mapped, they may share the page with another function.
+///
+/// ## Returns
+/// - Error::NoSuchDevice = No driver is bound to `device`
+/// - Error::DeviceOrResourceBusy = The driver cannot let go of the function, it stays bound
+pub fn unbind(device: &PciDevice) -> Result<()> {
+    let drivers = PCI_DRIVERS.lock();
+    let name = driver_of(device).ok_or(Error::NoSuchDevice)?;
+    let driver = drivers
+        .iter()
+        .find(|driver| driver.name() == name)
+        .ok_or(Error::NoSuchDevice)?;
+    driver.detach(device)?;
+    // The driver freed its DMA memory, so it must have stopped the device from using it
+    debug_assert!(
+        !msi::is_enabled(device),
+        "pci: {} still has MSI enabled on {}",
+        name,
+        device.location()
+    );
+    device.disable();
+    for (base, size) in device.memory_bars() {
+        // A BAR is aligned to its size, so one of at least a page has its pages to itself
+        if size >= PAGE_SIZE {
+            if let Err(err) = unmap_mmio(PhysicalAddress::new(base), size) {
+                warn!("pci: Cannot unmap {:#x}: {}", base, err.text());
+            }
+        }
+    }
+    if let Some(node) = device.node() {
+        tree::clear_shutdown_hook(node)?;
+    }
+    set_driver(device, None);
+    info!("pci: Unbound {} from {}", device.location(), name);
+    Ok(())
+}
+
+/// # Rebind
+/// Binds `device`, which has no driver, to the driver called `name`
+///
+/// ## Returns
+/// - Error::DeviceOrResourceBusy = A driver is bound to `device` already
+/// - Error::NoSuchDevice = There is no driver called `name`
+/// - Error::InvalidArgument = The driver does not handle `device`
+pub fn rebind(device: &PciDevice, name: &str) -> Result<()> {
+    let drivers = PCI_DRIVERS.lock();
+    if driver_of(device).is_some() {
+        return Err(Error::DeviceOrResourceBusy);
+    }
+    let driver = drivers
+        .iter()
+        .find(|driver| driver.name() == name)
+        .ok_or(Error::NoSuchDevice)?;
+    if !driver.matches(device) {
+        return Err(Error::InvalidArgument);
+    }
+    driver.attach(*device)?;
+    set_driver(device, Some(driver.name()));
+    info!("pci: Bound {} to {}", device.location(), driver.name());
+    Ok(())
+}
+
+/// # Devices Of Class
+/// All functions with the given class and subclass
+pub fn devices_of_class(class: u8, subclass: u8) -> impl Iterator<Item = PciDevice> {
+    devices().filter(move |device| device.class == class && device.subclass == subclass)
+}
+
+#[repr(C)]
 struct PCIDeviceHeader {
     pub vendor_id: u16,
     pub device_id: u16,
@@ -48,41 +799,80 @@ impl core::fmt::Display for PCIDeviceHeader {
     }
 }
 
+crate::initcall! {
+    name: "pci",
+    stage: Bus,
+    deps: ["acpi"],
+    fatal: false,
+    init: init_pci,
+}
+
+/// # Init PCI
+/// Scans the buses the MCFG lists for functions
+///
+/// and adds them to the device tree, below a `pci` node that holds the configuration space
+///
+/// ## Returns
+/// - Error::NoSuchDevice = There is no MCFG, so the configuration space cannot be reached
+pub fn init_pci() -> Result<()> {
+    let _tag = crate::alloc_tag!("pci");
+    let mcfg = MCFGHeader::find().ok_or(Error::NoSuchDevice)?;
+    let windows = PCI::new().enumerate(&mcfg);
+    info!("pci: {} functions", devices().count());
+    let root = tree::register("pci", DeviceClass::Bus, None, "pci", &windows)?;
+    for device in devices() {
+        if let Err(e) = tree::register(
+            &device.location(),
+            device.device_class(),
+            Some(root),
+            "pci",
+            &[],
+        ) {
+            warn!(
+                "pci: Cannot add {} to the device tree: {}",
+                device.location(),
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
 pub struct PCI {}
 
 impl PCI {
     pub fn new() -> Self {
         Self {}
     }
-    pub fn enumerate(&self, mcfg: &mut MCFGHeader) {
-        let mcfg_entry_count = (mcfg.sdt_header.length - size_of::<MCFGHeader>() as u32)
-            / size_of::<DeviceConfig>() as u32;
-        for i in 0..mcfg_entry_count {
-            let config = DeviceConfig::new(
-                address_of!(mcfg) // At MCFG
-                    + size_of::<MCFGHeader>() as u64 // Offset by the MCFGHeader
-                    + (size_of::<DeviceConfig>() as u64 * i as u64), // Skip the already iterated ones,
-            )
-            .unwrap();
+    /// # Enumerate
+    /// Scans every bus of the configuration space windows in `mcfg`
+    ///
+    /// ## Returns
+    /// The windows, as MMIO ranges
+    pub fn enumerate(&self, mcfg: &Table<MCFGHeader>) -> Vec<Resource> {
+        let mut windows = Vec::new();
+        for config in mcfg.entries::<DeviceConfig>() {
+            let (base, start_bus, end_bus) = (config.base, config.start_bus, config.end_bus);
+            windows.push(Resource::Mmio {
+                base: base + ((start_bus as u64) << 20),
+                len: (end_bus as u64 + 1).saturating_sub(start_bus as u64) << 20,
+            });
 
             // Iterate over all of the buses
-            for bus in config.start_bus..config.end_bus {
-                self.enumerate_bus(config.base, bus as u64);
+            for bus in start_bus..end_bus {
+                self.enumerate_bus(base, bus as u64);
             }
         }
+        windows
     }
 
     fn enumerate_bus(&self, base: u64, bus: u64) {
         let offset = bus << 20;
         let address = base + offset; //  The Address of the bus
-        unsafe {
-            PAGE_TABLE_MANAGER
-                .lock()
-                .assume_init_mut()
-                .map_memory(address as u64, address as u64);
-        }
-
-        let header: &PCIDeviceHeader = from_addr!(address);
+        let header: &PCIDeviceHeader = match map_config_space(address) {
+            Some(virt) => from_addr!(virt),
+            None => return,
+        };
 
         // Check for invalid Device IDs
         if header.device_id == 0 || header.device_id == 0xFFFF {
@@ -91,20 +881,16 @@ impl PCI {
 
         // 32 Devices per Bus
         for device in 0..32 {
-            self.enumerate_device(address, device)
+            self.enumerate_device(address, bus, device)
         }
     }
-    fn enumerate_device(&self, bus_address: u64, device: u64) {
+    fn enumerate_device(&self, bus_address: u64, bus: u64, device: u64) {
         let offset = device << 15;
         let address = bus_address + offset; //  The Address of the bus
-        unsafe {
-            PAGE_TABLE_MANAGER
-                .lock()
-                .assume_init_mut()
-                .map_memory(address as u64, address as u64);
-        }
-
-        let header: &PCIDeviceHeader = from_addr!(address);
+        let header: &PCIDeviceHeader = match map_config_space(address) {
+            Some(virt) => from_addr!(virt),
+            None => return,
+        };
 
         // Check for invalid Device IDs
         if header.device_id == 0 || header.device_id == 0xFFFF {
@@ -112,21 +898,17 @@ impl PCI {
         }
         // 8 Functions per Device
         for function in 0..8 {
-            self.enumerate_function(address, function)
+            self.enumerate_function(address, bus, device, function)
         }
     }
 
-    fn enumerate_function(&self, device_addr: u64, func: u64) {
+    fn enumerate_function(&self, device_addr: u64, bus: u64, device: u64, func: u64) {
         let offset = func << 12;
         let address = device_addr + offset; //  The Address of the bus
-        unsafe {
-            PAGE_TABLE_MANAGER
-                .lock()
-                .assume_init_mut()
-                .map_memory(address as u64, address as u64);
-        }
-
-        let header: &PCIDeviceHeader = from_addr!(address);
+        let header: &PCIDeviceHeader = match map_config_space(address) {
+            Some(virt) => from_addr!(virt),
+            None => return,
+        };
 
         // Check for invalid Device IDs
         if header.device_id == 0 || header.device_id == 0xFFFF {
@@ -135,5 +917,33 @@ impl PCI {
 
         // Print all PCI Devices
         //debug!("{}", header);
+
+        let mut registry = PCI_DEVICES.lock();
+        if registry.len < MAX_PCI_DEVICES {
+            let idx = registry.len;
+            registry.devices[idx] = Some(PciDevice {
+                address,
+                bus: bus as u8,
+                slot: device as u8,
+                function: func as u8,
+                vendor_id: header.vendor_id,
+                device_id: header.device_id,
+                class: header.class,
+                subclass: header.subclass,
+                program_interface: header.program_interface,
+            });
+            registry.len += 1;
+        }
     }
 }
+
+/// Maps the configuration space of a function, returning the address it can be read at
+fn map_config_space(address: u64) -> Option<u64> {
+    map_mmio(
+        PhysicalAddress::new(address),
+        CONFIG_SPACE_SIZE,
+        MemoryType::Uncacheable,
+    )
+    .ok()
+    .map(|virt| virt.as_u64())
+}
@@ -0,0 +1,356 @@
+//! # Power
+//! Turning the machine off and restarting it through the fixed hardware ACPI describes in the
+//! FADT. Turning it off means entering the sleep state S5, by writing its sleep type to the PM1
+//! control registers. The sleep type is in the `\_S5` package of the DSDT, which is AML. There
+//! is no AML interpreter, so `parse_s5()` picks the package out of the bytecode, which works for
+//! the plain `Name (_S5, Package () { ... })` firmware declares it with.
+//!
+//! A restart goes through the reset register of the FADT if it has one, then through the
+//! keyboard controller and finally through a triple fault. Both run the notifiers registered
+//! with `notifier::register()` first, which flush the log, quiesce the devices and stop every
+//! other CPU.
+use spin::Once;
+
+use crate::acpi::fadt::{AddressSpace, Fadt, FadtExtension, FadtFlag};
+use crate::acpi::{tables, ACPIFindable, ACPITable, SDTHeader};
+use crate::error::{Error, Result};
+use crate::iobus::{inw, outb, outw};
+use crate::memory::paging::pat::MemoryType;
+use crate::memory::{map_mmio, PhysicalAddress};
+use crate::{info, klog, warn};
+
+pub mod notifier;
+
+pub use notifier::PowerAction;
+
+/// PM1 control: Events raise SCIs instead of SMIs, the OS owns the power management
+const SCI_EN: u16 = 1 << 0;
+/// PM1 control: The sleep state entered when `SLP_EN` is written
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_TYP_MASK: u16 = 0b111 << SLP_TYP_SHIFT;
+/// PM1 control: Enters the sleep state in `SLP_TYP`
+const SLP_EN: u16 = 1 << 13;
+/// How often `SCI_EN` is checked after asking the firmware to hand over the power management
+const ACPI_ENABLE_SPINS: usize = 10_000_000;
+/// How long to wait for the machine to go away before giving up on the way it was asked to
+const SETTLE_SPINS: usize = 10_000_000;
+
+/// The command port of the keyboard controller, and the command that pulses the reset line
+const KBC_COMMAND: u16 = 0x64;
+const KBC_RESET: u8 = 0xFE;
+
+// The AML opcodes `\_S5` is declared with
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_WORD_PREFIX: u8 = 0x0B;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ROOT_PREFIX: u8 = b'\\';
+
+/// # Sleep Type
+/// The values of `SLP_TYP` for the PM1a and the PM1b control register to enter a sleep state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SleepType {
+    pub a: u8,
+    pub b: u8,
+}
+
+/// # Reset Register
+/// The register of the FADT that restarts the machine when `value` is written to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetRegister {
+    Io {
+        port: u16,
+        value: u8,
+    },
+    /// `address` is virtual, it is mapped when the FADT is read
+    Memory {
+        address: u64,
+        value: u8,
+    },
+}
+
+/// # Power Registers
+/// What the FADT says about the power management
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerRegisters {
+    pub pm1a_control: u16,
+    /// 0 if there is no PM1b
+    pub pm1b_control: u16,
+    pub smi_command: u16,
+    pub acpi_enable: u8,
+    pub s5: Option<SleepType>,
+    pub reset: Option<ResetRegister>,
+}
+
+static POWER: Once<PowerRegisters> = Once::new();
+
+crate::initcall! {
+    name: "power",
+    stage: Platform,
+    deps: ["acpi"],
+    fatal: false,
+    init: init_power,
+}
+
+/// # Init Power
+/// Reads the registers of the power management from the FADT and the sleep type of S5 from the
+/// DSDT
+///
+/// ## Returns
+/// - Error::NoSuchDevice = There is no FADT, or it has no PM1 control register as the hardware
+///   is reduced
+fn init_power() -> Result<()> {
+    let fadt = Fadt::find().ok_or(Error::NoSuchDevice)?;
+    if fadt.has_flag(FadtFlag::HardwareReducedAcpi) || fadt.pm1a_control_block == 0 {
+        return Err(Error::NoSuchDevice);
+    }
+    let extension = FadtExtension::from_bytes(fadt.tail()).ok();
+
+    let dsdt = extension
+        .map(|extension| extension.x_dsdt)
+        .filter(|dsdt| *dsdt != 0)
+        .unwrap_or(fadt.dsdt as u64);
+    let s5 = match tables::table_at(dsdt).and_then(|dsdt| dsdt.map::<SDTHeader>()) {
+        Ok(dsdt) => parse_s5(dsdt.tail()),
+        Err(err) => {
+            warn!("power: Cannot map the DSDT at {:#x}: {}", dsdt, err);
+            None
+        }
+    };
+
+    let reset = match extension {
+        Some(extension) if fadt.has_flag(FadtFlag::ResetRegSupported) => reset_register(extension)
+            .unwrap_or_else(|err| {
+                warn!("power: Cannot use the reset register: {}", err);
+                None
+            }),
+        _ => None,
+    };
+
+    let power = POWER.call_once(|| PowerRegisters {
+        pm1a_control: fadt.pm1a_control_block as u16,
+        pm1b_control: fadt.pm1b_control_block as u16,
+        smi_command: fadt.smi_command as u16,
+        acpi_enable: fadt.acpi_enable,
+        s5,
+        reset,
+    });
+    match power.s5 {
+        Some(s5) => info!("power: S5 is {}/{}", s5.a, s5.b),
+        None => warn!("power: The DSDT has no \\_S5, the machine cannot turn itself off"),
+    }
+    if power.reset.is_some() {
+        info!("power: Restarting through the reset register");
+    }
+    Ok(())
+}
+
+/// The reset register of `extension`, mapped if it is memory
+fn reset_register(extension: &FadtExtension) -> Result<Option<ResetRegister>> {
+    let register = extension.reset_register;
+    let (space, address, value) = (
+        register.address_space,
+        register.address,
+        extension.reset_value,
+    );
+    Ok(match space {
+        AddressSpace::Io => Some(ResetRegister::Io {
+            port: address as u16,
+            value,
+        }),
+        AddressSpace::Memory => {
+            let phys = PhysicalAddress::try_new(address).map_err(|_| Error::InvalidArgument)?;
+            let address = map_mmio(phys, 1, MemoryType::Uncacheable)?.as_u64();
+            Some(ResetRegister::Memory { address, value })
+        }
+        // PCI configuration space would need the bus, which is not up yet
+        _ => None,
+    })
+}
+
+/// # Parse S5
+/// The sleep type of S5 in the AML `aml`, from the first `\_S5` package with at least two
+/// integers
+pub fn parse_s5(aml: &[u8]) -> Option<SleepType> {
+    (0..aml.len())
+        .filter(|at| aml[*at..].starts_with(b"_S5_"))
+        .find_map(|at| parse_s5_at(aml, at))
+}
+
+fn parse_s5_at(aml: &[u8], at: usize) -> Option<SleepType> {
+    if !matches!(
+        aml[..at],
+        [.., AML_NAME_OP] | [.., AML_NAME_OP, AML_ROOT_PREFIX]
+    ) {
+        return None;
+    }
+    let mut bytes = aml.get(at + 4..)?.iter().copied();
+    if bytes.next()? != AML_PACKAGE_OP {
+        return None;
+    }
+    // The top two bits of the first byte of the package length are the number of bytes that
+    // follow it
+    let lead = bytes.next()?;
+    for _ in 0..lead >> 6 {
+        bytes.next()?;
+    }
+    let _elements = bytes.next()?;
+    Some(SleepType {
+        a: aml_integer(&mut bytes)?,
+        b: aml_integer(&mut bytes)?,
+    })
+}
+
+/// The low byte of the integer constant at the start of `bytes`
+fn aml_integer(bytes: &mut impl Iterator<Item = u8>) -> Option<u8> {
+    match bytes.next()? {
+        AML_ZERO_OP => Some(0),
+        AML_ONE_OP => Some(1),
+        AML_BYTE_PREFIX => bytes.next(),
+        AML_WORD_PREFIX => {
+            let low = bytes.next()?;
+            bytes.next()?;
+            Some(low)
+        }
+        _ => None,
+    }
+}
+
+pub fn registers() -> Option<&'static PowerRegisters> {
+    POWER.get()
+}
+
+/// # Can Power Off
+/// Whether `enter_s5()` has what it needs
+pub fn can_power_off() -> bool {
+    registers().map_or(false, |power| power.s5.is_some())
+}
+
+/// # Enable ACPI
+/// Asks the firmware to hand the power management over, if it has not yet
+///
+/// ## Returns
+/// - Error::NoSuchDevice = The firmware has no SMI command port to ask through
+/// - Error::ConnectionTimedOut = The firmware did not hand it over
+fn enable_acpi(power: &PowerRegisters) -> Result<()> {
+    if inw(power.pm1a_control) & SCI_EN != 0 {
+        return Ok(());
+    }
+    if power.smi_command == 0 || power.acpi_enable == 0 {
+        return Err(Error::NoSuchDevice);
+    }
+    outb(power.smi_command, power.acpi_enable);
+    for _ in 0..ACPI_ENABLE_SPINS {
+        if inw(power.pm1a_control) & SCI_EN != 0 {
+            return Ok(());
+        }
+        comasm::pause();
+    }
+    Err(Error::ConnectionTimedOut)
+}
+
+fn write_sleep_type(port: u16, sleep_type: u8) {
+    let control = inw(port) & !SLP_TYP_MASK;
+    let sleep_type = ((sleep_type as u16) << SLP_TYP_SHIFT) & SLP_TYP_MASK;
+    outw(port, control | sleep_type | SLP_EN);
+}
+
+/// # Enter S5
+/// Turns the machine off right away, without preparing anything
+///
+/// ## Returns
+/// - Error::NoSuchDevice = There is no FADT or no `\_S5`
+/// - Error::ConnectionTimedOut = The firmware did not hand over the power management
+/// - Error::IOError = The machine is still running
+pub fn enter_s5() -> Result<()> {
+    let power = registers().ok_or(Error::NoSuchDevice)?;
+    let s5 = power.s5.ok_or(Error::NoSuchDevice)?;
+    enable_acpi(power)?;
+    write_sleep_type(power.pm1a_control, s5.a);
+    if power.pm1b_control != 0 {
+        write_sleep_type(power.pm1b_control, s5.b);
+    }
+    for _ in 0..SETTLE_SPINS {
+        comasm::pause();
+    }
+    Err(Error::IOError)
+}
+
+/// # Reset
+/// Restarts the machine right away, without preparing anything
+pub fn reset() -> ! {
+    comasm::write_back_and_invalidate_caches();
+    match registers().and_then(|power| power.reset) {
+        Some(ResetRegister::Io { port, value }) => outb(port, value),
+        Some(ResetRegister::Memory { address, value }) => unsafe {
+            core::ptr::write_volatile(address as *mut u8, value)
+        },
+        None => {}
+    }
+    settle();
+    outb(KBC_COMMAND, KBC_RESET);
+    settle();
+    // An empty IDT turns the breakpoint into a triple fault, which resets the CPU
+    let idt = [0u16; 5];
+    unsafe { core::arch::asm!("lidt [{}]", "int3", in(reg) idt.as_ptr()) };
+    halt_now()
+}
+
+fn settle() {
+    for _ in 0..SETTLE_SPINS {
+        comasm::pause();
+    }
+}
+
+/// # Halt Now
+/// Stops the calling CPU for good
+pub fn halt_now() -> ! {
+    loop {
+        comasm::clear_interrupts();
+        comasm::halt();
+    }
+}
+
+/// Does `action` once the notifiers ran, with interrupts disabled
+fn finish(action: PowerAction) -> ! {
+    comasm::clear_interrupts();
+    klog::flush();
+    match action {
+        PowerAction::Restart => reset(),
+        PowerAction::PowerOff => {
+            if let Err(err) = enter_s5() {
+                warn!("power: Cannot power off: {}, halting instead", err);
+                klog::flush();
+            }
+            halt_now()
+        }
+        PowerAction::Halt => {
+            info!("power: The system is halted");
+            klog::flush();
+            halt_now()
+        }
+    }
+}
+
+/// # Shutdown
+/// Turns the machine off. Halts if that fails.
+pub fn shutdown() -> ! {
+    info!("power: Powering off");
+    notifier::run(PowerAction::PowerOff)
+}
+
+/// # Reboot
+/// Restarts the machine
+pub fn reboot() -> ! {
+    info!("power: Restarting");
+    notifier::run(PowerAction::Restart)
+}
+
+/// # Halt
+/// Stops the machine without turning it off
+pub fn halt() -> ! {
+    info!("power: Halting");
+    notifier::run(PowerAction::Halt)
+}
@@ -0,0 +1,191 @@
+//! # Notifier
+//! The callbacks subsystems register to run before the machine restarts, powers off or halts.
+//! They run highest priority first: The log and the console are flushed, then the devices are
+//! quiesced and finally the other CPUs are parked.
+//!
+//! Every callback gets `TIMEOUT_MS` to return, enforced through a deadline of the watchdog. The
+//! timer interrupt abandons a callback that hangs and continues with the next one on the stack
+//! it interrupted, which is never returned to. If the callback returns after all, the task that
+//! ran it blocks for good. The timer only interrupts the bootstrap CPU, so the chain moves there
+//! first, and a callback that hangs with interrupts disabled still blocks it.
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Once;
+
+use crate::arch::pic::end_main_pic;
+use crate::device::tree;
+use crate::error::Result;
+use crate::scheduler::spin::Mutex;
+use crate::scheduler::{self, WaitQueue};
+use crate::smp::{self, current_cpu, CpuMask};
+use crate::{debug, klog, warn, watchdog};
+
+/// The priority of the callbacks that write out what is buffered, such as the log
+pub const PRIORITY_FLUSH: u32 = 300;
+/// The priority of the callbacks that quiesce devices, such as flushing the cache of a disk
+pub const PRIORITY_DEVICES: u32 = 200;
+/// The priority of parking the other CPUs, interrupts stay disabled after it
+pub const PRIORITY_PARK: u32 = 100;
+/// How long a callback may take before the chain goes on without it
+pub const TIMEOUT_MS: u64 = 2000;
+
+crate::counter!(pub TIMEOUTS = "power.notifier_timeouts");
+
+/// # Power Action
+/// What the machine does once the chain ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    Restart,
+    PowerOff,
+    Halt,
+}
+
+/// # Notifier
+/// A callback run before the machine goes down
+#[derive(Debug, Clone, Copy)]
+pub struct Notifier {
+    pub name: &'static str,
+    pub priority: u32,
+    pub callback: fn(PowerAction),
+}
+
+/// Sorted by priority, highest first, in the order of registration otherwise
+static NOTIFIERS: Mutex<Vec<Notifier>> = Mutex::new(Vec::new());
+/// Set by the first CPU that runs the chain
+static STARTED: AtomicBool = AtomicBool::new(false);
+/// What the machine does and the notifiers, as they were when the chain started
+static CHAIN: Once<(PowerAction, Vec<Notifier>)> = Once::new();
+/// The index of the notifier that runs
+static STEP: AtomicUsize = AtomicUsize::new(0);
+/// Never woken, the tasks of abandoned callbacks that returned wait on it
+static ABANDONED: WaitQueue = WaitQueue::new();
+
+crate::initcall! {
+    name: "power-notifiers",
+    stage: EarlyMemory,
+    deps: [],
+    fatal: false,
+    init: init_notifiers,
+}
+
+fn init_notifiers() -> Result<()> {
+    register("device-tree", PRIORITY_DEVICES, run_shutdown_hooks);
+    register("smp-park", PRIORITY_PARK, park_others);
+    Ok(())
+}
+
+/// # Register
+/// Runs `callback` before the machine goes down, after the callbacks of a higher priority and
+/// the ones of the same priority registered before
+pub fn register(name: &'static str, priority: u32, callback: fn(PowerAction)) {
+    let mut notifiers = NOTIFIERS.lock();
+    let at = notifiers
+        .iter()
+        .position(|notifier| notifier.priority < priority)
+        .unwrap_or(notifiers.len());
+    notifiers.insert(
+        at,
+        Notifier {
+            name,
+            priority,
+            callback,
+        },
+    );
+}
+
+/// # Unregister
+/// Removes the notifiers called `name`
+///
+/// ## Returns
+/// - bool = Whether there was one
+pub fn unregister(name: &'static str) -> bool {
+    let mut notifiers = NOTIFIERS.lock();
+    let before = notifiers.len();
+    notifiers.retain(|notifier| notifier.name != name);
+    notifiers.len() != before
+}
+
+/// # Notifiers
+/// The registered notifiers, in the order they run in
+pub fn notifiers() -> Vec<Notifier> {
+    NOTIFIERS.lock().clone()
+}
+
+/// # Run
+/// Runs the chain and then does `action`. A CPU that calls this while another one runs the
+/// chain waits to be parked.
+pub fn run(action: PowerAction) -> ! {
+    if STARTED.swap(true, Ordering::AcqRel) {
+        loop {
+            comasm::enable_interrupts_and_halt();
+        }
+    }
+    klog::flush();
+    if scheduler::is_running() && current_cpu() != 0 {
+        scheduler::set_affinity(scheduler::current(), CpuMask::single(0));
+    }
+    if current_cpu() != 0 {
+        warn!("power: Not on the bootstrap CPU, notifiers that hang are not skipped");
+    }
+    CHAIN.call_once(|| (action, notifiers()));
+    comasm::reload_interrupt_flags();
+    continue_chain()
+}
+
+/// Runs the notifiers from `STEP` on
+fn continue_chain() -> ! {
+    let (action, notifiers) = CHAIN.get().expect("The chain has not started");
+    loop {
+        let step = STEP.load(Ordering::Acquire);
+        let notifier = match notifiers.get(step) {
+            Some(notifier) => notifier,
+            None => break,
+        };
+        debug!("power: Running the {} notifier", notifier.name);
+        if current_cpu() == 0 {
+            watchdog::set_deadline(TIMEOUT_MS, skip_notifier);
+        }
+        (notifier.callback)(*action);
+        watchdog::clear_deadline();
+        if STEP
+            .compare_exchange(step, step + 1, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            // Skipped by the timer interrupt, the chain goes on without this task
+            loop {
+                ABANDONED.wait_until(|| false);
+            }
+        }
+    }
+    super::finish(*action)
+}
+
+/// The deadline of a notifier, abandons it and continues the chain from the timer interrupt
+fn skip_notifier() {
+    let step = STEP.fetch_add(1, Ordering::AcqRel);
+    TIMEOUTS.increment();
+    if let Some((_, notifiers)) = CHAIN.get() {
+        warn!(
+            "power: The {} notifier did not return within {} ms, skipping it",
+            notifiers[step].name, TIMEOUT_MS
+        );
+    }
+    end_main_pic();
+    comasm::reload_interrupt_flags();
+    continue_chain()
+}
+
+/// Runs the shutdown hooks of the device tree
+fn run_shutdown_hooks(_: PowerAction) {
+    let hooks = tree::run_shutdown_hooks(None);
+    debug!("power: Ran {} shutdown hooks", hooks);
+}
+
+/// Stops every other CPU, interrupts stay disabled from then on
+fn park_others(_: PowerAction) {
+    comasm::clear_interrupts();
+    let running = smp::stop_others();
+    if running != 0 {
+        warn!("power: {} CPUs did not stop", running);
+    }
+}
@@ -0,0 +1,247 @@
+//! # Profile
+//! A sampling profiler for finding where the time goes, e.g. during boot. While it runs, every
+//! tick of the PIT records the address the interrupted code was at in a histogram, and
+//! `report()` adds the samples up per function through the symbol table. The PIT only
+//! interrupts the bootstrap CPU, so only it is sampled. `profile=on` on the command line starts
+//! the profiler before anything else in `main`.
+//!
+//! The histogram is a fixed open addressing table, so recording never allocates. A sample that
+//! finds no free bucket within `MAX_PROBES` is only counted as dropped.
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::interrupts::interrupt_frame::InterruptFrame;
+use crate::arch::scheduler::pit;
+use crate::arch::tsc;
+use crate::{cmdline, info, symbols};
+
+/// The command line option that starts the profiler at boot, `profile=on`
+pub const OPTION: &str = "profile";
+/// The number of distinct addresses the histogram can hold
+pub const BUCKETS: usize = 4096;
+/// The buckets a sample looks at before it is dropped
+const MAX_PROBES: usize = 16;
+/// The functions `profile report` shows
+pub const TOP_FUNCTIONS: usize = 20;
+/// What samples without a function are counted as
+pub const UNKNOWN: &str = "[unknown]";
+
+/// Set while the profiler runs
+const RUNNING: u64 = 1;
+/// What every tick that is recording adds to `STATE`
+const IN_FLIGHT: u64 = 2;
+
+/// `RUNNING`, plus `IN_FLIGHT` for every tick that is recording. Stopping waits until no tick
+/// is, so the histogram is not written to once `stop()` returned.
+static STATE: AtomicU64 = AtomicU64::new(0);
+static HISTOGRAM: Histogram<BUCKETS> = Histogram::new();
+static USER_SAMPLES: AtomicU64 = AtomicU64::new(0);
+static DROPPED_SAMPLES: AtomicU64 = AtomicU64::new(0);
+/// The TSC when the profiler was started
+static STARTED: AtomicU64 = AtomicU64::new(0);
+
+crate::counter!(pub SAMPLES = "profile.samples");
+
+/// # Bucket
+/// An address and the samples at it, the address is zero while the bucket is free
+struct Bucket {
+    address: AtomicU64,
+    count: AtomicU64,
+}
+
+/// # Histogram
+/// The number of samples at every address
+pub struct Histogram<const N: usize> {
+    buckets: [Bucket; N],
+}
+
+impl<const N: usize> Histogram<N> {
+    pub const fn new() -> Self {
+        const FREE: Bucket = Bucket {
+            address: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        };
+        Self { buckets: [FREE; N] }
+    }
+
+    /// # Record
+    /// Counts a sample at `address`, which cannot be zero
+    ///
+    /// ## Returns
+    /// - bool = false if every bucket it may go to holds another address
+    pub fn record(&self, address: u64) -> bool {
+        let start = hash(address) % N;
+        for probe in 0..MAX_PROBES.min(N) {
+            let bucket = &self.buckets[(start + probe) % N];
+            match bucket
+                .address
+                .compare_exchange(0, address, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => {}
+                Err(other) if other == address => {}
+                Err(_) => continue,
+            }
+            bucket.count.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    /// # Entries
+    /// Every address with its samples
+    pub fn entries(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.buckets
+            .iter()
+            .map(|bucket| {
+                (
+                    bucket.address.load(Ordering::Relaxed),
+                    bucket.count.load(Ordering::Relaxed),
+                )
+            })
+            .filter(|(address, count)| *address != 0 && *count != 0)
+    }
+
+    pub fn clear(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.count.store(0, Ordering::Relaxed);
+            bucket.address.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Fibonacci hashing, code addresses are too regular to be used as they are
+fn hash(address: u64) -> usize {
+    (address.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as usize
+}
+
+/// # Function Samples
+/// The samples that fell into a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionSamples {
+    pub name: &'static str,
+    pub samples: u64,
+}
+
+/// # Aggregate
+/// Adds up the samples of `entries` per function, the functions with the most samples first.
+/// `resolve` names the function of an address, addresses it cannot name are `UNKNOWN`.
+pub fn aggregate(
+    entries: impl Iterator<Item = (u64, u64)>,
+    resolve: impl Fn(u64) -> Option<&'static str>,
+) -> Vec<FunctionSamples> {
+    let mut functions: BTreeMap<&'static str, u64> = BTreeMap::new();
+    for (address, count) in entries {
+        *functions
+            .entry(resolve(address).unwrap_or(UNKNOWN))
+            .or_default() += count;
+    }
+    let mut functions: Vec<FunctionSamples> = functions
+        .into_iter()
+        .map(|(name, samples)| FunctionSamples { name, samples })
+        .collect();
+    functions.sort_by(|a, b| b.samples.cmp(&a.samples).then(a.name.cmp(b.name)));
+    functions
+}
+
+/// # Report
+/// What the profiler recorded
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Every function with samples, the most first
+    pub functions: Vec<FunctionSamples>,
+    /// The samples in the kernel
+    pub kernel_samples: u64,
+    pub user_samples: u64,
+    pub dropped_samples: u64,
+    /// The time between two samples
+    pub period_us: u64,
+    /// Since the profiler was started
+    pub elapsed_us: u64,
+}
+
+impl Report {
+    pub fn total_samples(&self) -> u64 {
+        self.kernel_samples + self.user_samples + self.dropped_samples
+    }
+}
+
+/// # Init Profile
+/// Starts the profiler if `OPTION` is `on`
+pub fn init_profile() {
+    if cmdline::value(OPTION) == Some("on") {
+        start();
+        info!("profile: Sampling every {} us", period_us());
+    }
+}
+
+/// # Start
+/// Starts the profiler over with an empty histogram
+pub fn start() {
+    stop();
+    HISTOGRAM.clear();
+    USER_SAMPLES.store(0, Ordering::Relaxed);
+    DROPPED_SAMPLES.store(0, Ordering::Relaxed);
+    STARTED.store(tsc::read(), Ordering::Relaxed);
+    STATE.fetch_or(RUNNING, Ordering::Release);
+}
+
+/// # Stop
+/// Stops the profiler and waits for the ticks that are still recording
+///
+/// ## Returns
+/// - bool = Whether the profiler was running
+pub fn stop() -> bool {
+    let state = STATE.fetch_and(!RUNNING, Ordering::AcqRel);
+    while STATE.load(Ordering::Acquire) >= IN_FLIGHT {
+        core::hint::spin_loop();
+    }
+    state & RUNNING != 0
+}
+
+pub fn is_running() -> bool {
+    STATE.load(Ordering::Relaxed) & RUNNING != 0
+}
+
+/// # Tick
+/// Records where the timer interrupt with `frame` interrupted, called by the timer interrupt
+pub fn tick(frame: &InterruptFrame) {
+    if !is_running() {
+        return;
+    }
+    let state = STATE.fetch_add(IN_FLIGHT, Ordering::Acquire);
+    if state & RUNNING != 0 {
+        SAMPLES.increment();
+        if frame.cs & 3 != 0 {
+            USER_SAMPLES.fetch_add(1, Ordering::Relaxed);
+        } else if !HISTOGRAM.record(frame.rip) {
+            DROPPED_SAMPLES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    STATE.fetch_sub(IN_FLIGHT, Ordering::Release);
+}
+
+fn period_us() -> u64 {
+    1_000_000 / pit::get_frequency().max(1)
+}
+
+/// # Report
+/// The samples so far, by function. Can be taken while the profiler runs.
+pub fn report() -> Report {
+    let functions = aggregate(HISTOGRAM.entries(), |address| {
+        symbols::resolve(address).map(|(symbol, _)| symbol.name)
+    });
+    let started = STARTED.load(Ordering::Relaxed);
+    Report {
+        kernel_samples: functions.iter().map(|function| function.samples).sum(),
+        functions,
+        user_samples: USER_SAMPLES.load(Ordering::Relaxed),
+        dropped_samples: DROPPED_SAMPLES.load(Ordering::Relaxed),
+        period_us: period_us(),
+        elapsed_us: if started == 0 {
+            0
+        } else {
+            tsc::cycles_to_ns(tsc::read().saturating_sub(started)).unwrap_or(0) / 1000
+        },
+    }
+}
@@ -0,0 +1,59 @@
+//! # ChaCha20
+//! The block function of RFC 8439, which `rand` turns into a stream of random bytes
+/// The words of the key
+pub const KEY_WORDS: usize = 8;
+/// The words of the nonce
+pub const NONCE_WORDS: usize = 3;
+/// The bytes of a block
+pub const BLOCK_SIZE: usize = 64;
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+/// Every double round is a column round and a diagonal round
+const DOUBLE_ROUNDS: usize = 10;
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// # Block
+/// The block `counter` of the key stream of `key` and `nonce`
+pub fn block(key: &[u32; KEY_WORDS], counter: u32, nonce: &[u32; NONCE_WORDS]) -> [u32; 16] {
+    let mut input = [0; 16];
+    input[..4].copy_from_slice(&CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter;
+    input[13..].copy_from_slice(nonce);
+
+    let mut state = input;
+    for _ in 0..DOUBLE_ROUNDS {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, input) in state.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*input);
+    }
+    state
+}
+
+/// # Block Bytes
+/// A block serialized as little endian words, as the key stream is defined
+pub fn block_bytes(block: &[u32; 16]) -> [u8; BLOCK_SIZE] {
+    let mut bytes = [0; BLOCK_SIZE];
+    for (chunk, word) in bytes.chunks_exact_mut(4).zip(block.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    bytes
+}
@@ -0,0 +1,167 @@
+//! # Random Numbers
+//! A ChaCha20 based CSPRNG for everything in the kernel that needs random numbers, such as KASLR,
+//! and for `getrandom()`. Its key is seeded from the hardware RNG, from TSC jitter on CPUs that
+//! have none, and reseeded every `RESEED_INTERVAL_MS` by a timer.
+//!
+//! After every request the generator replaces its key with key stream it never handed out, so
+//! its state does not tell what it returned before.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::drivers::hwrng;
+use crate::entropy;
+use crate::info;
+use crate::scheduler::sync::IrqSpinLock;
+use crate::time::timer::Timer;
+
+pub mod chacha;
+
+use chacha::{BLOCK_SIZE, KEY_WORDS};
+
+/// How often the key is reseeded
+pub const RESEED_INTERVAL_MS: u64 = 10_000;
+/// The 64 bit words of a seed
+pub const SEED_WORDS: usize = 4;
+/// The nonce the key is derived with when reseeding, which output never uses
+const RESEED_NONCE: [u32; 3] = [u32::MAX; 3];
+
+crate::counter!(pub RESEEDS = "rand.reseeds");
+
+static RNG: IrqSpinLock<Csprng> = crate::named_lock!("rng", Csprng::new());
+/// Whether the generator got a seed `getrandom()` may rely on: one from the hardware RNG, or the
+/// jitter of a periodic reseed on top of the jitter at boot
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// # CSPRNG
+/// The key stream of ChaCha20 under a key that is replaced after every request
+pub struct Csprng {
+    key: [u32; KEY_WORDS],
+    counter: u64,
+    seeded: bool,
+}
+
+impl Csprng {
+    /// # New
+    /// An unseeded generator, which must be reseeded before its output is of any use
+    pub const fn new() -> Self {
+        Self {
+            key: [0; KEY_WORDS],
+            counter: 0,
+            seeded: false,
+        }
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        self.seeded
+    }
+
+    /// # Reseed
+    /// Derives a new key from the current one and `seed`, so a weak seed cannot make the key
+    /// weaker
+    pub fn reseed(&mut self, seed: &[u64; SEED_WORDS]) {
+        let mut key = self.key;
+        for (words, seed) in key.chunks_exact_mut(2).zip(seed.iter()) {
+            words[0] ^= *seed as u32;
+            words[1] ^= (*seed >> 32) as u32;
+        }
+        let block = chacha::block(&key, 0, &RESEED_NONCE);
+        self.key.copy_from_slice(&block[..KEY_WORDS]);
+        self.counter = 0;
+        self.seeded = true;
+    }
+
+    /// # Fill
+    /// Fills `buf` with key stream, then replaces the key
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(BLOCK_SIZE) {
+            let block = chacha::block_bytes(&self.next_block());
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        let block = self.next_block();
+        self.key.copy_from_slice(&block[..KEY_WORDS]);
+    }
+
+    fn next_block(&mut self) -> [u32; 16] {
+        let nonce = [(self.counter >> 32) as u32, 0, 0];
+        let block = chacha::block(&self.key, self.counter as u32, &nonce);
+        self.counter += 1;
+        block
+    }
+}
+
+/// # Gather Seed
+/// A seed from the hardware RNG, with TSC jitter for the words it cannot provide
+///
+/// ## Returns
+/// - bool = Whether every word came from the hardware RNG
+fn gather_seed() -> ([u64; SEED_WORDS], bool) {
+    let mut seed = [0; SEED_WORDS];
+    let mut hardware = true;
+    for word in seed.iter_mut() {
+        *word = match hwrng::read_u64() {
+            Some((value, _)) => value,
+            None => {
+                hardware = false;
+                entropy::jitter()
+            }
+        };
+    }
+    (seed, hardware)
+}
+
+/// # Reseed
+/// Mixes a fresh seed into the generator, called by the reseed timer
+pub fn reseed() {
+    let (seed, hardware) = gather_seed();
+    let mut rng = RNG.lock();
+    let first = !rng.is_seeded();
+    rng.reseed(&seed);
+    drop(rng);
+    // Jitter only counts once it was gathered twice, at different times
+    if hardware || !first {
+        READY.store(true, Ordering::Release);
+    }
+    RESEEDS.increment();
+}
+
+/// # Is Ready
+/// Whether the generator was seeded well enough for `getrandom()` without `GRND_INSECURE`
+pub fn is_ready() -> bool {
+    READY.load(Ordering::Acquire)
+}
+
+/// # Fill
+/// Fills `buf` with random bytes, seeding the generator first if nothing did yet
+pub fn fill(buf: &mut [u8]) {
+    if !RNG.lock().is_seeded() {
+        reseed();
+    }
+    RNG.lock().fill(buf);
+}
+
+/// # Next U64
+/// A random number
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// # Range
+/// A random number in `lo..hi`, which must not be empty
+pub fn range(lo: u64, hi: u64) -> u64 {
+    assert!(lo < hi, "empty range {}..{}", lo, hi);
+    // The high half of the product is evenly spread over the range, up to a negligible bias
+    let span = (hi - lo) as u128;
+    lo + ((next_u64() as u128 * span) >> 64) as u64
+}
+
+/// # Init Reseed
+/// Reseeds the generator every `RESEED_INTERVAL_MS`, called once timers work
+pub fn init_reseed() {
+    Timer::schedule_periodic(RESEED_INTERVAL_MS, reseed);
+    info!(
+        "rand: Reseeding every {} s{}",
+        RESEED_INTERVAL_MS / 1000,
+        if is_ready() { "" } else { ", not ready yet" }
+    );
+}
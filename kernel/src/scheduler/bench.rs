@@ -0,0 +1,159 @@
+//! # Scheduler Benchmark
+//! Two tasks hand a ball back and forth over a `WaitQueue`: Each handoff wakes the other task
+//! and blocks the current one, so it costs one context switch. Both tasks and the caller are
+//! pinned to the calling CPU, which keeps work stealing out of the measurement.
+//!
+//! `switch_latencies()` additionally times every single handoff, from right before the wakeup
+//! until the other task runs, for the percentiles of `crate::bench`.
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use super::spin::Mutex;
+use super::{current, set_affinity, spawn_pinned, switch_count, task_infos, WaitQueue};
+use crate::arch::tsc;
+use crate::cmdline;
+use crate::error::{Error, Result};
+use crate::smp::{current_cpu, CpuMask};
+
+/// The command line option holding the most nanoseconds a switch may take in the self-test
+pub const BUDGET_OPTION: &str = "sched_bench_budget";
+/// Generous enough for QEMU without KVM
+pub const DEFAULT_BUDGET_NS: u64 = 20_000;
+pub const DEFAULT_HANDOFFS: u64 = 1_000_000;
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static BALL: WaitQueue = WaitQueue::new();
+static DONE: WaitQueue = WaitQueue::new();
+/// The number of handoffs so far, the first task plays the even ones
+static TURN: AtomicU64 = AtomicU64::new(0);
+static TOTAL: AtomicU64 = AtomicU64::new(0);
+static FINISHED: AtomicUsize = AtomicUsize::new(0);
+/// The TSC right before the last handoff
+static HANDED_AT: AtomicU64 = AtomicU64::new(0);
+/// Where every handoff is timed, if it is. Allocated up front, so recording never allocates.
+static LATENCIES: Mutex<Option<Vec<u64>>> = Mutex::new(None);
+
+/// # Ping Pong
+/// The outcome of `ping_pong()`
+#[derive(Debug, Clone, Copy)]
+pub struct PingPong {
+    pub handoffs: u64,
+    /// The context switches on all CPUs while the benchmark ran, at least `handoffs`
+    pub switches: u64,
+    pub cycles: u64,
+}
+
+impl PingPong {
+    pub fn cycles_per_switch(&self) -> u64 {
+        self.cycles / self.handoffs.max(1)
+    }
+
+    /// `None` if the TSC has not been calibrated
+    pub fn ns_per_switch(&self) -> Option<u64> {
+        tsc::cycles_to_ns(self.cycles_per_switch())
+    }
+}
+
+/// # Budget
+/// The most nanoseconds a context switch may take, `BUDGET_OPTION` or `DEFAULT_BUDGET_NS`
+pub fn budget_ns() -> u64 {
+    cmdline::value(BUDGET_OPTION)
+        .and_then(|budget| budget.parse().ok())
+        .unwrap_or(DEFAULT_BUDGET_NS)
+}
+
+fn play(parity: u64) {
+    let total = TOTAL.load(Ordering::Relaxed);
+    loop {
+        let mut turn = 0;
+        BALL.wait_until(|| {
+            turn = TURN.load(Ordering::Relaxed);
+            turn >= total || turn % 2 == parity
+        });
+        if turn >= total {
+            break;
+        }
+        let now = tsc::read();
+        if let Some(latencies) = LATENCIES.lock().as_mut() {
+            // The first turn was not handed over by anyone
+            if turn > 0 && latencies.len() < latencies.capacity() {
+                latencies.push(now.saturating_sub(HANDED_AT.load(Ordering::Relaxed)));
+            }
+        }
+        TURN.store(turn + 1, Ordering::Relaxed);
+        HANDED_AT.store(tsc::read(), Ordering::Relaxed);
+        BALL.wake_one();
+    }
+    if FINISHED.fetch_add(1, Ordering::SeqCst) == 1 {
+        DONE.wake_one();
+    }
+}
+
+fn ping() {
+    play(0)
+}
+
+fn pong() {
+    play(1)
+}
+
+/// # Ping Pong
+/// Measures `handoffs` handoffs between two tasks on the calling CPU. Blocks until they are
+/// done.
+///
+/// ## Returns
+/// - Error::DeviceOrResourceBusy = The benchmark is already running
+pub fn ping_pong(handoffs: u64) -> Result<PingPong> {
+    run(handoffs, None).map(|(result, _)| result)
+}
+
+/// # Switch Latencies
+/// Like `ping_pong()`, but times every handoff
+///
+/// ## Returns
+/// - Vec<u64> = The cycles from every handoff until the other task ran, `handoffs - 1` of them
+/// - Error::DeviceOrResourceBusy = The benchmark is already running
+pub fn switch_latencies(handoffs: u64) -> Result<Vec<u64>> {
+    let latencies = Vec::with_capacity(handoffs.saturating_sub(1) as usize);
+    run(handoffs, Some(latencies)).map(|(_, latencies)| latencies.unwrap_or_default())
+}
+
+fn run(handoffs: u64, latencies: Option<Vec<u64>>) -> Result<(PingPong, Option<Vec<u64>>)> {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return Err(Error::DeviceOrResourceBusy);
+    }
+    *LATENCIES.lock() = latencies;
+    TURN.store(0, Ordering::Relaxed);
+    TOTAL.store(handoffs, Ordering::Relaxed);
+    FINISHED.store(0, Ordering::SeqCst);
+
+    // With everything on one CPU, no wakeup can slip in between checking a condition and
+    // going to sleep
+    let caller = current();
+    let affinity = task_infos()
+        .into_iter()
+        .find(|task| task.id == caller)
+        .map_or(CpuMask::all(), |task| task.affinity);
+    let cpu = current_cpu();
+    set_affinity(caller, CpuMask::single(cpu));
+
+    let switches = switch_count();
+    let start = tsc::read();
+    spawn_pinned("ping", ping, CpuMask::single(cpu));
+    spawn_pinned("pong", pong, CpuMask::single(cpu));
+    DONE.wait_until(|| FINISHED.load(Ordering::SeqCst) == 2);
+    let cycles = tsc::read() - start;
+    let switches = switch_count() - switches;
+
+    set_affinity(caller, affinity);
+    let latencies = LATENCIES.lock().take();
+    RUNNING.store(false, Ordering::Release);
+    Ok((
+        PingPong {
+            handoffs,
+            switches,
+            cycles,
+        },
+        latencies,
+    ))
+}
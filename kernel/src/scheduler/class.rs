@@ -0,0 +1,97 @@
+//! # Scheduling Class
+//! How the scheduler orders the ready tasks of the same priority. Round-robin runs them in the
+//! order they were queued. The fair class runs the one with the smallest virtual runtime: The
+//! cycles a task ran for, scaled by the weight of its nice value, so a task with a higher nice
+//! value ages faster and gets a smaller share of the CPU. Run queues are kept sorted by virtual
+//! runtime, so picking the next task stays the same in both classes.
+//!
+//! A task of the fair class runs for `TIME_SLICE_NS` at most while another one is ready, then
+//! the timer tick preempts it. Round-robin tasks run until they yield or block.
+//!
+//! `sched=fair` or `sched=rr` on the command line chooses the class, round-robin is the default.
+//! The virtual runtime is accounted in both classes, so the class can be changed while tasks
+//! are queued, the run queues are sorted again as their tasks are queued anew.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{cmdline, info};
+
+/// The command line option choosing the `SchedClass`, `fair` or `rr`
+pub const SCHED_OPTION: &str = "sched";
+
+/// The nice value of the highest weight
+pub const NICE_MIN: i8 = -20;
+/// The nice value of the lowest weight
+pub const NICE_MAX: i8 = 19;
+/// The weight of a task with nice value 0, which ages at the rate it runs
+pub const NICE_0_WEIGHT: u64 = 1024;
+/// How long a task of the fair class runs before the tick preempts it for a ready task of at
+/// least its priority
+pub const TIME_SLICE_NS: u64 = 10_000_000;
+
+/// The weights of the nice values from `NICE_MIN` on, every step is about a tenth of the CPU.
+/// They are the weights Linux uses.
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// Whether the fair class is in use
+static FAIR: AtomicBool = AtomicBool::new(false);
+
+/// # Sched Class
+/// How ready tasks of the same priority are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedClass {
+    /// In the order they were queued
+    RoundRobin,
+    /// By virtual runtime
+    Fair,
+}
+
+impl SchedClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RoundRobin => "rr",
+            Self::Fair => "fair",
+        }
+    }
+}
+
+/// # Init
+/// Chooses the class given on the command line
+pub fn init() {
+    let class = match cmdline::value(SCHED_OPTION) {
+        Some("fair") => SchedClass::Fair,
+        _ => SchedClass::RoundRobin,
+    };
+    set_class(class);
+    info!("Scheduling class {}", class.name());
+}
+
+/// # Class
+/// The class the scheduler orders ready tasks by
+pub fn class() -> SchedClass {
+    match FAIR.load(Ordering::Relaxed) {
+        true => SchedClass::Fair,
+        false => SchedClass::RoundRobin,
+    }
+}
+
+/// # Set Class
+/// Changes the class, which takes effect for the tasks queued from now on
+pub fn set_class(class: SchedClass) {
+    FAIR.store(class == SchedClass::Fair, Ordering::Relaxed);
+}
+
+/// # Weight
+/// The weight of `nice`, clamped to `NICE_MIN..=NICE_MAX`
+pub fn weight(nice: i8) -> u64 {
+    WEIGHTS[(nice.clamp(NICE_MIN, NICE_MAX) - NICE_MIN) as usize]
+}
+
+/// # Virtual Runtime
+/// `cycles` a task of `nice` ran for, scaled by its weight
+pub fn virtual_runtime(cycles: u64, nice: i8) -> u64 {
+    (cycles as u128 * NICE_0_WEIGHT as u128 / weight(nice) as u128) as u64
+}
@@ -0,0 +1,175 @@
+//! # CPU Time
+//! How much time the tasks spent in user space and in the kernel. Every CPU accounts the time of
+//! the task it runs in TSC cycles, switching between user and kernel time at system call and
+//! interrupt entry and exit, and hands what it accounted to the task when it is switched away
+//! from. The TSCs of different CPUs need not be synchronized, so only readings of the same CPU
+//! are ever subtracted from each other, and a task that migrates is still accounted correctly.
+//!
+//! The time a CPU waits in `idle::wait()` is not accounted to the blocked task it waits on.
+use core::ops::{Add, AddAssign};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::arch::tsc;
+use crate::smp::{current_cpu, MAX_CPUS};
+
+static CPUS: [CpuAccount; MAX_CPUS] = {
+    const CPU: CpuAccount = CpuAccount::new();
+    [CPU; MAX_CPUS]
+};
+
+/// # CPU Time
+/// TSC cycles, split by where they were spent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuTime {
+    pub user: u64,
+    pub kernel: u64,
+}
+
+impl CpuTime {
+    pub fn total(&self) -> u64 {
+        self.user + self.kernel
+    }
+}
+
+impl Add for CpuTime {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            user: self.user + other.user,
+            kernel: self.kernel + other.kernel,
+        }
+    }
+}
+
+impl AddAssign for CpuTime {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+/// # Permille
+/// `cycles` out of `elapsed` in tenths of a percent
+pub fn permille(cycles: u64, elapsed: u64) -> u64 {
+    if elapsed == 0 {
+        return 0;
+    }
+    (cycles as u128 * 1000 / elapsed as u128) as u64
+}
+
+/// # CPU Account
+/// The time a CPU accounted to its current task so far. Only written by the CPU itself.
+struct CpuAccount {
+    /// Whether the current task is in user space
+    in_user: AtomicBool,
+    /// The TSC of the CPU when time was last accounted, zero before it joined the scheduler
+    since: AtomicU64,
+    user: AtomicU64,
+    kernel: AtomicU64,
+}
+
+impl CpuAccount {
+    const fn new() -> Self {
+        Self {
+            in_user: AtomicBool::new(false),
+            since: AtomicU64::new(0),
+            user: AtomicU64::new(0),
+            kernel: AtomicU64::new(0),
+        }
+    }
+
+    /// Accounts the time since the last call to user or kernel time, whichever the task is in
+    fn account(&self) {
+        let now = tsc::read();
+        let since = self.since.swap(now, Ordering::Relaxed);
+        if since == 0 {
+            return;
+        }
+        let cycles = now.saturating_sub(since);
+        if self.in_user.load(Ordering::Relaxed) {
+            self.user.fetch_add(cycles, Ordering::Relaxed);
+        } else {
+            self.kernel.fetch_add(cycles, Ordering::Relaxed);
+        }
+    }
+
+    fn accounted(&self) -> CpuTime {
+        CpuTime {
+            user: self.user.load(Ordering::Relaxed),
+            kernel: self.kernel.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// # Init CPU
+/// Starts accounting on the calling CPU, `cpu`
+pub(super) fn init_cpu(cpu: usize) {
+    CPUS[cpu].since.store(tsc::read(), Ordering::Relaxed);
+}
+
+/// # Enter Kernel
+/// Called on entry from user space, the time until `exit_to_user()` is kernel time
+///
+/// ## Returns
+/// - bool = Whether the CPU was in user space, only then `exit_to_user()` must be called
+pub fn enter_kernel() -> bool {
+    let account = &CPUS[current_cpu()];
+    if !account.in_user.load(Ordering::Relaxed) {
+        return false;
+    }
+    account.account();
+    account.in_user.store(false, Ordering::Relaxed);
+    true
+}
+
+/// # Exit To User
+/// Called right before returning to user space, the time from now on is user time
+pub fn exit_to_user() {
+    let account = &CPUS[current_cpu()];
+    account.account();
+    account.in_user.store(true, Ordering::Relaxed);
+}
+
+/// # Collect
+/// The time accounted to the current task of `cpu`, the calling CPU, that it has not been
+/// handed yet. It keeps running and accounting starts over.
+pub(super) fn collect(cpu: usize) -> CpuTime {
+    let account = &CPUS[cpu];
+    account.account();
+    CpuTime {
+        user: account.user.swap(0, Ordering::Relaxed),
+        kernel: account.kernel.swap(0, Ordering::Relaxed),
+    }
+}
+
+/// # Take
+/// The time accounted to the current task of `cpu`, the calling CPU, which is about to be
+/// switched away from. The next task starts in the kernel, from the context switch.
+pub(super) fn take(cpu: usize) -> CpuTime {
+    let ran = collect(cpu);
+    CPUS[cpu].in_user.store(false, Ordering::Relaxed);
+    ran
+}
+
+/// # Pending
+/// The time accounted to the current task of `cpu` that it has not been handed yet. The time
+/// since the last accounting is only included on `cpu` itself, as it takes reading its TSC.
+pub(super) fn pending(cpu: usize) -> CpuTime {
+    let account = &CPUS[cpu];
+    if cpu == current_cpu() {
+        account.account();
+    }
+    account.accounted()
+}
+
+/// # Pause
+/// Accounts the time so far before `cpu`, the calling CPU, waits idle
+pub(super) fn pause(cpu: usize) {
+    CPUS[cpu].account();
+}
+
+/// # Resume
+/// Starts accounting again after `cpu`, the calling CPU, waited idle
+pub(super) fn resume(cpu: usize) {
+    CPUS[cpu].since.store(tsc::read(), Ordering::Relaxed);
+}
@@ -0,0 +1,230 @@
+//! # Idle
+//! What a CPU does when its run queue is empty. By default it waits with MONITOR/MWAIT on a
+//! word of its own if the CPU has them, so `wake()` only has to write that word, or with `hlt`
+//! otherwise, which takes a reschedule IPI to end. `idle=poll` on the command line keeps it
+//! spinning instead, for latency measurements.
+//!
+//! The scheduler marks the run queue idle under its lock and calls `wait()` after releasing it,
+//! with interrupts still disabled: A task queued in between either ends the wait through the
+//! wake word or leaves the IPI pending until `sti` lets it in, so no wakeup is lost.
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use spin::Once;
+
+use super::cputime;
+use crate::arch::{mwait, tsc};
+use crate::smp::MAX_CPUS;
+use crate::{cmdline, info};
+
+/// The command line option choosing the `IdleMode`, `poll`, `halt` or `mwait`
+pub const IDLE_OPTION: &str = "idle";
+
+/// The CPU is not waiting
+const RUNNING: u32 = 0;
+/// The CPU waits on its wake word and ends the wait once it is written
+const WATCHING: u32 = 1;
+/// Work was queued for the CPU since it decided to wait
+const WOKEN: u32 = 2;
+
+static MODE: Once<IdleMode> = Once::new();
+static CPUS: [CpuIdle; MAX_CPUS] = {
+    const CPU: CpuIdle = CpuIdle::new();
+    [CPU; MAX_CPUS]
+};
+
+/// # Idle Mode
+/// How an idle CPU waits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleMode {
+    /// Spin with `pause`, the fastest to wake up and the most expensive
+    Poll,
+    /// `hlt`, woken by an interrupt
+    Halt,
+    /// MONITOR/MWAIT in C1, woken by a write to the wake word or an interrupt
+    Mwait,
+}
+
+impl IdleMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Poll => "poll",
+            Self::Halt => "halt",
+            Self::Mwait => "mwait",
+        }
+    }
+
+    /// Whether a write to the wake word ends the wait, so no IPI is needed
+    fn watches(&self) -> bool {
+        *self != Self::Halt
+    }
+}
+
+/// # Wake Word
+/// Has a cache line to itself, as any write to the line ends an MWAIT, even one to the
+/// statistics next to it
+#[repr(C, align(64))]
+struct WakeWord(AtomicU32);
+
+/// # CPU Idle
+/// The wake word and the statistics of a CPU
+struct CpuIdle {
+    wake: WakeWord,
+    /// Set for the whole of `wait()`, interrupts that end the wait interrupted no task
+    waiting: AtomicBool,
+    /// The TSC when the CPU joined the scheduler
+    since: AtomicU64,
+    idle_cycles: AtomicU64,
+    polls: AtomicU64,
+    halts: AtomicU64,
+    mwaits: AtomicU64,
+}
+
+impl CpuIdle {
+    const fn new() -> Self {
+        Self {
+            wake: WakeWord(AtomicU32::new(RUNNING)),
+            waiting: AtomicBool::new(false),
+            since: AtomicU64::new(0),
+            idle_cycles: AtomicU64::new(0),
+            polls: AtomicU64::new(0),
+            halts: AtomicU64::new(0),
+            mwaits: AtomicU64::new(0),
+        }
+    }
+}
+
+/// # Idle Stats
+/// How much a CPU was idle since it joined the scheduler, and how often it entered each state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdleStats {
+    pub idle_cycles: u64,
+    pub total_cycles: u64,
+    pub polls: u64,
+    pub halts: u64,
+    pub mwaits: u64,
+}
+
+impl IdleStats {
+    pub fn busy_cycles(&self) -> u64 {
+        self.total_cycles.saturating_sub(self.idle_cycles)
+    }
+
+    /// The idle time in tenths of a percent
+    pub fn idle_permille(&self) -> u64 {
+        if self.total_cycles == 0 {
+            return 0;
+        }
+        (self.idle_cycles.min(self.total_cycles) as u128 * 1000 / self.total_cycles as u128) as u64
+    }
+}
+
+/// # Mode
+/// The `IdleMode` from the command line, else the best one the CPU supports
+pub fn mode() -> IdleMode {
+    *MODE.call_once(|| {
+        let supported = mwait::is_supported();
+        let mode = match cmdline::value(IDLE_OPTION) {
+            Some("poll") => IdleMode::Poll,
+            Some("halt") => IdleMode::Halt,
+            _ if supported => IdleMode::Mwait,
+            _ => IdleMode::Halt,
+        };
+        info!("Idle CPUs wait with {}", mode.name());
+        mode
+    })
+}
+
+/// # Init CPU
+/// Starts the statistics of the calling CPU
+pub fn init_cpu(cpu: usize) {
+    mode();
+    CPUS[cpu].since.store(tsc::read(), Ordering::Relaxed);
+}
+
+/// # Wait
+/// Waits until work might have been queued for `cpu`, the calling CPU. The caller has to look
+/// at the run queue again afterwards, the wait may also end for an unrelated interrupt.
+///
+/// ## Safety
+/// Must be called with interrupts disabled, after the run queue was marked idle. Interrupts are
+/// disabled again on return.
+pub unsafe fn wait(cpu: usize) {
+    let state = &CPUS[cpu];
+    cputime::pause(cpu);
+    state.waiting.store(true, Ordering::Relaxed);
+    let start = tsc::read();
+    let mode = mode();
+    if mode.watches() {
+        let watching = state
+            .wake
+            .0
+            .compare_exchange(RUNNING, WATCHING, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok();
+        if watching && mode == IdleMode::Mwait {
+            state.mwaits.fetch_add(1, Ordering::Relaxed);
+            mwait::monitor(&state.wake as *const WakeWord as *const u8);
+            // A write before the monitor was armed would not end the wait
+            if state.wake.0.load(Ordering::SeqCst) == WATCHING {
+                mwait::enable_interrupts_and_wait(mwait::HINT_C1);
+            }
+            comasm::clear_interrupts();
+        } else if watching {
+            state.polls.fetch_add(1, Ordering::Relaxed);
+            comasm::reload_interrupt_flags();
+            while state.wake.0.load(Ordering::Acquire) == WATCHING {
+                comasm::pause();
+            }
+            comasm::clear_interrupts();
+        }
+        state.wake.0.store(RUNNING, Ordering::SeqCst);
+    } else {
+        state.halts.fetch_add(1, Ordering::Relaxed);
+        comasm::enable_interrupts_and_halt();
+        comasm::clear_interrupts();
+    }
+    state
+        .idle_cycles
+        .fetch_add(tsc::read().saturating_sub(start), Ordering::Relaxed);
+    state.waiting.store(false, Ordering::Relaxed);
+    cputime::resume(cpu);
+}
+
+/// # Is Waiting
+/// Whether `cpu` is in `wait()`. On the calling CPU, called from an interrupt handler, this
+/// tells that the interrupt arrived while the CPU was idle and holds no locks.
+pub fn is_waiting(cpu: usize) -> bool {
+    CPUS[cpu].waiting.load(Ordering::Relaxed)
+}
+
+/// # Wake
+/// Ends the wait of `cpu` if it watches its wake word. Called by the scheduler under its lock
+/// for a CPU whose run queue is idle, which either waits already or is about to: In the latter
+/// case it sees the word written and does not wait at all.
+///
+/// ## Returns
+/// - bool = Whether the CPU needs an interrupt to wake up, as it waits in `hlt`
+pub fn wake(cpu: usize) -> bool {
+    if !mode().watches() {
+        return true;
+    }
+    CPUS[cpu].wake.0.store(WOKEN, Ordering::SeqCst);
+    false
+}
+
+/// # Stats
+/// The idle statistics of `cpu`
+pub fn stats(cpu: usize) -> IdleStats {
+    let state = &CPUS[cpu];
+    let since = state.since.load(Ordering::Relaxed);
+    IdleStats {
+        idle_cycles: state.idle_cycles.load(Ordering::Relaxed),
+        total_cycles: if since == 0 {
+            0
+        } else {
+            tsc::read().saturating_sub(since)
+        },
+        polls: state.polls.load(Ordering::Relaxed),
+        halts: state.halts.load(Ordering::Relaxed),
+        mwaits: state.mwaits.load(Ordering::Relaxed),
+    }
+}
@@ -1 +1,927 @@
+//! # Scheduler
+//! A scheduler for kernel tasks with one run queue per CPU.
+//!
+//! Tasks give up the CPU by calling `yield_now()` or by blocking on a `WaitQueue`. The next task
+//! is the first one in the run queue out of those with the highest priority, a task that yields
+//! keeps running if all of them have a lower priority than its own. The run queues are in the
+//! order the tasks were queued, or by virtual runtime with the fair class, see `class`. A CPU
+//! whose own run queue is empty steals a task from the busiest one, honouring the affinity of
+//! the task.
+//!
+//! With the fair class, a task that used up its time slice is preempted by the timer tick when
+//! a task of at least its priority is ready, see `tick()`. The interrupted task has to have run
+//! with interrupts enabled and must not hold a spinlock, see `preempt`.
+//!
+//! A task holding a `sync::Mutex` inherits the priority of the tasks waiting for it, so a task of
+//! a middle priority cannot keep it from releasing the mutex to a waiter of a higher priority.
+//! All scheduler state lives behind an `IrqSpinLock`, as wakeups may come from interrupt handlers
+//! on any CPU.
 pub use crate::arch::scheduler;
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::{
+    mem::MaybeUninit,
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::arch::apic::{local_apic, RESCHEDULE_VECTOR};
+use crate::arch::fpu::{self, FpuState};
+use crate::arch::gdt;
+use crate::arch::interrupts::{self, without_interrupts};
+use crate::arch::syscall;
+use crate::arch::tss::{IoBitmap, IO_PORTS};
+use crate::breadcrumb::{self, Milestone};
+use crate::error::{Error, Result};
+use crate::heap::tag;
+use crate::smp::{self, current_cpu, CpuMask, MAX_CPUS};
+use crate::{counter, info, time, watchdog};
+
+pub mod bench;
+pub mod class;
+pub mod cputime;
+pub mod idle;
+pub mod preempt;
+pub mod signal;
+pub mod spin;
+pub mod sync;
+pub mod task;
+pub mod wait_queue;
+
+pub use class::SchedClass;
+pub use cputime::CpuTime;
+pub use sync::IrqSpinLock;
+pub use task::{Priority, Task, TaskId, TaskState};
+pub use wait_queue::WaitQueue;
+
+pub static SCHEDULER: IrqSpinLock<MaybeUninit<Scheduler>> =
+    crate::named_lock!("scheduler", MaybeUninit::uninit());
+static IS_RUNNING: AtomicBool = AtomicBool::new(false);
+/// The number of traced tasks, so untraced system calls do not take the scheduler lock
+static TRACED_TASKS: AtomicUsize = AtomicUsize::new(0);
+const NO_RESCHED: AtomicBool = AtomicBool::new(false);
+/// Set by `tick()` for a CPU whose current task is to be preempted, see `preempt()`
+static NEED_RESCHED: [AtomicBool; MAX_CPUS] = [NO_RESCHED; MAX_CPUS];
+
+counter!(pub CONTEXT_SWITCHES = "sched.context_switches");
+counter!(pub TASKS_STOLEN = "sched.tasks_stolen");
+counter!(pub PRIORITY_BOOSTS = "sched.priority_boosts");
+counter!(pub PREEMPTIONS = "sched.preemptions");
+
+/// How many holders a boost is passed on to, through holders that wait for another `Mutex`
+const MAX_INHERIT_DEPTH: usize = 8;
+/// The interrupt flag in RFLAGS
+const RFLAGS_IF: u64 = 1 << 9;
+
+/// # Run Queue
+/// The scheduling state of a single CPU
+struct RunQueue {
+    ready: Vec<TaskId>,
+    /// `None` until the CPU joined the scheduler
+    current: Option<TaskId>,
+    /// The task that was just switched away from, see `Scheduler::finish_switch`
+    prev: Option<TaskId>,
+    /// Whether the CPU is halted because there is nothing to run
+    idle: bool,
+    /// The virtual runtime of the tasks the CPU switched to never went below this, tasks
+    /// queued on the CPU start from it
+    min_vruntime: u64,
+    /// The uptime in nanoseconds the current task was switched to at, where its slice started
+    slice_start: u64,
+}
+
+impl RunQueue {
+    fn new() -> Self {
+        Self {
+            ready: Vec::new(),
+            current: None,
+            prev: None,
+            idle: false,
+            min_vruntime: 0,
+            slice_start: 0,
+        }
+    }
+}
+
+/// # Switch
+/// The saved state of the two tasks of a context switch. The tasks are boxed, so the pointers
+/// stay valid after the scheduler lock is released.
+struct Switch {
+    old_rsp: *mut u64,
+    new_rsp: u64,
+    old_fpu: *mut FpuState,
+    new_fpu: *mut FpuState,
+    old_preempt: *mut usize,
+    new_preempt: usize,
+}
+
+pub struct Scheduler {
+    tasks: BTreeMap<TaskId, Box<Task>>,
+    run_queues: Vec<RunQueue>,
+    last_id: u64,
+    /// Exited tasks whose stacks can be freed once they are no longer running
+    zombies: Vec<TaskId>,
+}
+
+impl Scheduler {
+    fn new() -> Self {
+        let mut scheduler = Self {
+            tasks: BTreeMap::new(),
+            run_queues: (0..MAX_CPUS).map(|_| RunQueue::new()).collect(),
+            last_id: 0,
+            zombies: Vec::new(),
+        };
+        scheduler.add_boot_task("kmain", current_cpu());
+        scheduler
+    }
+
+    fn next_id(&mut self) -> TaskId {
+        let id = TaskId::new(self.last_id);
+        self.last_id += 1;
+        id
+    }
+
+    fn add_boot_task(&mut self, name: &'static str, cpu: usize) {
+        let id = self.next_id();
+        self.tasks.insert(id, Box::new(Task::boot(id, name, cpu)));
+        self.run_queues[cpu].current = Some(id);
+    }
+
+    pub fn tasks(&self) -> impl Iterator<Item = &Task> {
+        self.tasks.values().map(|task| &**task)
+    }
+
+    fn current(&self, cpu: usize) -> TaskId {
+        self.run_queues[cpu]
+            .current
+            .expect("The CPU has not joined the scheduler")
+    }
+
+    fn task(&mut self, id: TaskId) -> &mut Task {
+        self.tasks.get_mut(&id).unwrap()
+    }
+
+    /// # Place
+    /// The online CPU out of `affinity` with the fewest ready tasks. If none of them is online,
+    /// the task is placed on the calling CPU.
+    fn place(&self, affinity: CpuMask) -> usize {
+        smp::online_cpus()
+            .filter(|cpu| affinity.contains(*cpu))
+            .min_by_key(|cpu| self.run_queues[*cpu].ready.len())
+            .unwrap_or_else(current_cpu)
+    }
+
+    /// # Enqueue
+    /// Appends the task to the run queue of `cpu`, or with the fair class inserts it after the
+    /// tasks with a virtual runtime up to its own, and wakes the CPU up if it is idle. A task
+    /// that slept, or was queued on another CPU, does not get to catch up on what it missed.
+    fn enqueue(&mut self, id: TaskId, cpu: usize) {
+        let min_vruntime = self.run_queues[cpu].min_vruntime;
+        let task = self.task(id);
+        task.state = TaskState::Ready;
+        task.cpu = cpu;
+        task.vruntime = task.vruntime.max(min_vruntime);
+        let vruntime = task.vruntime;
+        let tasks = &self.tasks;
+        let ready = &mut self.run_queues[cpu].ready;
+        match class::class() {
+            SchedClass::RoundRobin => ready.push(id),
+            SchedClass::Fair => {
+                let pos = ready.partition_point(|queued| tasks[queued].vruntime <= vruntime);
+                ready.insert(pos, id);
+            }
+        }
+        self.wake_cpu(cpu);
+    }
+
+    /// # Wake CPU
+    /// Ends the wait of `cpu` if it is idle, with an IPI unless it watches its wake word
+    fn wake_cpu(&self, cpu: usize) {
+        if self.run_queues[cpu].idle && idle::wake(cpu) && cpu != current_cpu() {
+            kick(cpu);
+        }
+    }
+
+    /// # Pick Next
+    /// Takes the next task for `cpu` with a priority of at least `floor` out of its run queue or
+    /// steals one from the busiest other run queue if there is nothing to do
+    fn pick_next(&mut self, cpu: usize, floor: Priority) -> Option<TaskId> {
+        let tasks = &self.tasks;
+        let own = &mut self.run_queues[cpu].ready;
+        let mut best: Option<(usize, Priority)> = None;
+        for (pos, id) in own.iter().enumerate() {
+            let task = &tasks[id];
+            if !task.on_cpu
+                && task.priority >= floor
+                && best.map_or(true, |(_, priority)| task.priority > priority)
+            {
+                best = Some((pos, task.priority));
+            }
+        }
+        if let Some((pos, _)) = best {
+            return Some(own.remove(pos));
+        }
+
+        // Work stealing, the most recently queued task is the least likely to be cache hot
+        let (victim, pos) = smp::online_cpus()
+            .filter(|victim| *victim != cpu)
+            .filter_map(|victim| {
+                let pos = self.run_queues[victim].ready.iter().rposition(|id| {
+                    let task = &tasks[id];
+                    !task.on_cpu && task.priority >= floor && task.affinity.contains(cpu)
+                })?;
+                Some((victim, pos))
+            })
+            .max_by_key(|(victim, _)| self.run_queues[*victim].ready.len())?;
+        let id = self.run_queues[victim].ready.remove(pos);
+        self.task(id).cpu = cpu;
+        TASKS_STOLEN.increment();
+        Some(id)
+    }
+
+    /// # Switch To
+    /// Makes `next` the running task of `cpu`. The current task has to be put into its new state
+    /// by the caller beforehand.
+    ///
+    /// ## Returns
+    /// - Switch = Where to save the current task and what to load
+    fn switch_to(&mut self, cpu: usize, next: TaskId) -> Switch {
+        let old = self.current(cpu);
+        crate::trace_event!(SchedSwitch, old.inner(), next.inner());
+        self.account(cpu, cputime::take(cpu));
+        let next_vruntime = self.task(next).vruntime;
+        let queue = &mut self.run_queues[cpu];
+        queue.current = Some(next);
+        queue.prev = Some(old);
+        queue.idle = false;
+        queue.min_vruntime = queue.min_vruntime.max(next_vruntime);
+        queue.slice_start = time::uptime_ns();
+
+        let next_task = self.task(next);
+        next_task.state = TaskState::Running;
+        next_task.on_cpu = true;
+        next_task.cpu = cpu;
+        let new_rsp = next_task.rsp;
+        let new_fpu = &mut next_task.fpu as *mut FpuState;
+        let new_tag = next_task.alloc_tag;
+        let new_preempt = next_task.preempt_count;
+        syscall::set_kernel_stack(cpu, next_task.stack_top());
+        gdt::local::load_io_bitmap(cpu, next_task.io_bitmap.as_ref());
+        let old_task = self.task(old);
+        old_task.alloc_tag = tag::switch(cpu, new_tag);
+        Switch {
+            old_rsp: &mut old_task.rsp,
+            new_rsp,
+            old_fpu: &mut old_task.fpu,
+            new_fpu,
+            old_preempt: &mut old_task.preempt_count,
+            new_preempt,
+        }
+    }
+
+    /// # Account
+    /// Hands `ran`, the time the current task of `cpu` ran for since it was last accounted, to
+    /// the task, which advances its virtual runtime
+    fn account(&mut self, cpu: usize, ran: CpuTime) {
+        let current = self.current(cpu);
+        let task = self.task(current);
+        task.time += ran;
+        task.vruntime += class::virtual_runtime(ran.total(), task.nice);
+    }
+
+    /// # Finish Switch
+    /// Runs on `cpu` right after a context switch: The previous task has been saved and may be
+    /// picked up by other CPUs from now on, or freed if it exited.
+    fn finish_switch(&mut self, cpu: usize) {
+        if let Some(prev) = self.run_queues[cpu].prev.take() {
+            let task = self.task(prev);
+            task.on_cpu = false;
+            // It may have been queued on another CPU, which skipped it until now
+            let queued_on = task.cpu;
+            if task.state == TaskState::Ready && queued_on != cpu {
+                self.wake_cpu(queued_on);
+            }
+        }
+        self.reap_zombies();
+    }
+
+    /// # Inherit
+    /// Raises the holder `owner` of the `Mutex` at `mutex` to `priority` for as long as it holds
+    /// it, and whoever holds what `owner` waits for in turn
+    fn inherit(&mut self, mut mutex: usize, mut owner: TaskId, priority: Priority) {
+        for _ in 0..MAX_INHERIT_DEPTH {
+            let task = match self.tasks.get_mut(&owner) {
+                Some(task) => task,
+                None => return,
+            };
+            match task.boosts.iter_mut().find(|(held, _)| *held == mutex) {
+                Some((_, boost)) => *boost = priority.max(*boost),
+                None => task.boosts.push((mutex, priority)),
+            }
+            if task.priority >= priority {
+                return;
+            }
+            task.priority = priority;
+            PRIORITY_BOOSTS.increment();
+            match task.waiting_for {
+                Some((next_mutex, next_owner)) => {
+                    mutex = next_mutex;
+                    owner = next_owner;
+                }
+                None => return,
+            }
+        }
+    }
+
+    fn reap_zombies(&mut self) {
+        let tasks = &mut self.tasks;
+        self.zombies.retain(|id| {
+            if tasks[id].on_cpu {
+                return true;
+            }
+            tasks.remove(id);
+            false
+        });
+    }
+}
+
+/// # Kick
+/// Sends a reschedule IPI to `cpu`, waking it up if it is halted
+fn kick(cpu: usize) {
+    if let Some(apic) = local_apic() {
+        apic.send_ipi(smp::percpu::cpu(cpu).apic_id(), RESCHEDULE_VECTOR);
+    }
+}
+
+/// # Init Scheduler
+/// Turns the currently running code into the boot task. Requires the heap.
+pub fn init_scheduler() {
+    info!("Initializing the Scheduler");
+    class::init();
+    SCHEDULER.lock().write(Scheduler::new());
+    cputime::init_cpu(current_cpu());
+    idle::init_cpu(current_cpu());
+    IS_RUNNING.store(true, Ordering::SeqCst);
+}
+
+/// # Init CPU
+/// Turns the code running on an application processor into its first task, after which the
+/// CPU takes part in scheduling. Requires `init_scheduler()` to have run on the boot CPU.
+pub fn init_cpu() {
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    scheduler.add_boot_task("idle", current_cpu());
+    cputime::init_cpu(current_cpu());
+    idle::init_cpu(current_cpu());
+}
+
+pub fn is_running() -> bool {
+    IS_RUNNING.load(Ordering::Relaxed)
+}
+
+/// # Switch Count
+/// The number of context switches on all CPUs since boot. Cheap enough for hot paths, it is a
+/// single relaxed load.
+#[inline]
+pub fn switch_count() -> u64 {
+    CONTEXT_SWITCHES.get()
+}
+
+/// # Current
+/// The id of the task running on the calling CPU
+pub fn current() -> TaskId {
+    unsafe { SCHEDULER.lock().assume_init_ref().current(current_cpu()) }
+}
+
+/// # Spawn
+/// Creates a new task running `entry` and queues it on the least busy CPU
+pub fn spawn(name: &'static str, entry: fn()) -> TaskId {
+    spawn_pinned(name, entry, CpuMask::all())
+}
+
+/// # Spawn Pinned
+/// Creates a new task running `entry` that never runs outside of `affinity`, unlike a task
+/// that is pinned after `spawn()` returned. It joins the process group and the session of the
+/// current task and inherits its nice value.
+pub fn spawn_pinned(name: &'static str, entry: fn(), affinity: CpuMask) -> TaskId {
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let id = scheduler.next_id();
+    let mut task = Box::new(Task::new(id, name, entry));
+    task.affinity = affinity;
+    let parent = scheduler.run_queues[current_cpu()]
+        .current
+        .and_then(|parent| scheduler.tasks.get(&parent));
+    if let Some(parent) = parent {
+        task.pgid = parent.pgid;
+        task.sid = parent.sid;
+        task.nice = parent.nice;
+    }
+    scheduler.tasks.insert(id, task);
+    let cpu = scheduler.place(affinity);
+    scheduler.enqueue(id, cpu);
+    id
+}
+
+/// # Set Affinity
+/// Restricts the task `id` to the CPUs in `affinity`. A queued task is moved right away, the
+/// current task yields and a task running on another CPU moves the next time it yields.
+pub fn set_affinity(id: TaskId, affinity: CpuMask) {
+    let must_yield = {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = unsafe { guard.assume_init_mut() };
+        let task = match scheduler.tasks.get_mut(&id) {
+            Some(task) => task,
+            None => return,
+        };
+        task.affinity = affinity;
+        let (state, cpu, on_cpu) = (task.state, task.cpu, task.on_cpu);
+        if affinity.contains(cpu) {
+            false
+        } else if state == TaskState::Ready && !on_cpu {
+            scheduler.run_queues[cpu]
+                .ready
+                .retain(|queued| *queued != id);
+            let target = scheduler.place(affinity);
+            crate::trace_event!(SchedWake, id.inner(), target);
+            scheduler.enqueue(id, target);
+            false
+        } else {
+            state == TaskState::Running && scheduler.current(current_cpu()) == id
+        }
+    };
+    if must_yield {
+        yield_now();
+    }
+}
+
+/// # Set Priority
+/// Sets the base priority of the task `id`, which it runs with unless it inherited a higher one
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`
+pub fn set_priority(id: TaskId, priority: Priority) -> Result<()> {
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let task = scheduler.tasks.get_mut(&id).ok_or(Error::NoSuchProcess)?;
+    task.base_priority = priority;
+    task.update_priority();
+    Ok(())
+}
+
+/// # Nice
+/// The nice value of the task `id`, see `set_nice()`
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`
+pub fn nice(id: TaskId) -> Result<i8> {
+    let guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_ref() };
+    scheduler
+        .tasks
+        .get(&id)
+        .map(|task| task.nice)
+        .ok_or(Error::NoSuchProcess)
+}
+
+/// # Set Nice
+/// Sets the nice value of the task `id`, clamped to `NICE_MIN..=NICE_MAX`, which weighs its
+/// virtual runtime in the fair class. It takes effect from the next time the task is switched
+/// away from.
+///
+/// ## Returns
+/// - i8 = The nice value the task got
+/// - Error::NoSuchProcess = There is no task `id`
+pub fn set_nice(id: TaskId, nice: i8) -> Result<i8> {
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let task = scheduler.tasks.get_mut(&id).ok_or(Error::NoSuchProcess)?;
+    task.nice = nice.clamp(class::NICE_MIN, class::NICE_MAX);
+    Ok(task.nice)
+}
+
+/// # Wait For Mutex
+/// Registers that the current task waits for the `Mutex` at `mutex`, which `owner` holds, and
+/// lends `owner` its priority. Called with interrupts disabled.
+fn wait_for_mutex(mutex: usize, owner: TaskId) {
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let current = scheduler.current(current_cpu());
+    let current = scheduler.task(current);
+    current.waiting_for = Some((mutex, owner));
+    let priority = current.priority;
+    scheduler.inherit(mutex, owner, priority);
+}
+
+/// # Hand Over Mutex
+/// Drops what the current task inherited through the `Mutex` at `mutex`, which it handed over to
+/// `next`. The tasks still `waiting` lend `next` their priority.
+fn hand_over_mutex(mutex: usize, next: TaskId, waiting: &[TaskId]) {
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let current = scheduler.current(current_cpu());
+    let current = scheduler.task(current);
+    current.boosts.retain(|(held, _)| *held != mutex);
+    current.update_priority();
+    if let Some(task) = scheduler.tasks.get_mut(&next) {
+        task.waiting_for = None;
+    }
+    let priority = waiting
+        .iter()
+        .filter_map(|id| scheduler.tasks.get(id))
+        .map(|task| task.priority)
+        .max();
+    if let Some(priority) = priority {
+        scheduler.inherit(mutex, next, priority);
+    }
+}
+
+/// # Set Traced
+/// Starts or stops logging the system calls of the task `id`
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`
+pub fn set_traced(id: TaskId, traced: bool) -> Result<()> {
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let task = scheduler.tasks.get_mut(&id).ok_or(Error::NoSuchProcess)?;
+    if task.traced != traced {
+        task.traced = traced;
+        if traced {
+            TRACED_TASKS.fetch_add(1, Ordering::Relaxed);
+        } else {
+            TRACED_TASKS.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// # Set I/O Permission
+/// Allows or denies the current task access to the I/O ports `ports` from user space. The TSS
+/// of the calling CPU is updated right away, the scheduler loads the ports of every task it
+/// switches to.
+///
+/// ## Returns
+/// - Error::InvalidArgument = `ports` reaches beyond the last port
+pub fn set_io_permission(ports: Range<u32>, allowed: bool) -> Result<()> {
+    if ports.start > ports.end || ports.end > IO_PORTS {
+        return Err(Error::InvalidArgument);
+    }
+    // Allocated before the lock is taken, and dropped after it is released if it is not used
+    let mut fresh = allowed.then(IoBitmap::new);
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let cpu = current_cpu();
+    let current = scheduler.current(cpu);
+    let task = scheduler.task(current);
+    let mut bitmap = match task.io_bitmap.take().or_else(|| fresh.take()) {
+        Some(bitmap) => bitmap,
+        // Every port is denied already
+        None => return Ok(()),
+    };
+    let result = bitmap.set(ports, allowed);
+    // A task that may access no port does not pay for the copy on every switch
+    task.io_bitmap = bitmap.allows_any().then(|| bitmap);
+    gdt::local::load_io_bitmap(cpu, task.io_bitmap.as_ref());
+    result
+}
+
+/// # Is Current Traced
+/// Whether the system calls of the task running on the calling CPU are logged
+pub fn is_current_traced() -> bool {
+    if TRACED_TASKS.load(Ordering::Relaxed) == 0 || !is_running() {
+        return false;
+    }
+    let guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_ref() };
+    let id = scheduler.current(current_cpu());
+    scheduler.tasks.get(&id).map_or(false, |task| task.traced)
+}
+
+/// # Task Info
+/// A copy of the bookkeeping of a task
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub state: TaskState,
+    pub cpu: usize,
+    pub affinity: CpuMask,
+    /// The time the task has run for, see `Task::time()`
+    pub time: CpuTime,
+    pub base_priority: Priority,
+    /// The priority the task runs with, higher than its base while it inherited one
+    pub priority: Priority,
+    pub nice: i8,
+}
+
+/// # Task Infos
+/// The bookkeeping of every task, empty before the scheduler runs
+pub fn task_infos() -> Vec<TaskInfo> {
+    if !is_running() {
+        return Vec::new();
+    }
+    let guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_ref() };
+    scheduler
+        .tasks()
+        .map(|task| TaskInfo {
+            id: task.id,
+            name: task.name,
+            state: task.state,
+            cpu: task.cpu(),
+            affinity: task.affinity(),
+            time: task.time(),
+            base_priority: task.base_priority(),
+            priority: task.priority(),
+            nice: task.nice(),
+        })
+        .collect()
+}
+
+/// # Task Time
+/// The time the task `id` has run for, see `Task::time()`
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`
+pub fn task_time(id: TaskId) -> Result<CpuTime> {
+    if !is_running() {
+        return Err(Error::NoSuchProcess);
+    }
+    let guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_ref() };
+    scheduler
+        .tasks
+        .get(&id)
+        .map(|task| task.time())
+        .ok_or(Error::NoSuchProcess)
+}
+
+/// # Yield Now
+/// Moves the current task to the end of a run queue and runs the next one, if there is any with
+/// at least its priority
+pub fn yield_now() {
+    watchdog::touch();
+    if !is_running() {
+        return;
+    }
+    without_interrupts(reschedule)
+}
+
+/// # Reschedule
+/// Queues the current task and switches to the next one, unless there is none with at least its
+/// priority. Called with interrupts disabled.
+fn reschedule() {
+    let targets = {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = unsafe { guard.assume_init_mut() };
+        let cpu = current_cpu();
+        let current = scheduler.current(cpu);
+        let floor = scheduler.task(current).priority;
+        let affinity = scheduler.task(current).affinity;
+        let target = if affinity.contains(cpu) {
+            cpu
+        } else {
+            scheduler.place(affinity)
+        };
+        // Accounted and queued first, so the fair class orders it by all it ran. It stays marked
+        // as on the CPU, so nobody picks it up before it is saved, this CPU included.
+        scheduler.account(cpu, cputime::collect(cpu));
+        scheduler.enqueue(current, target);
+        let next = match scheduler.pick_next(cpu, floor) {
+            Some(next) => next,
+            None => {
+                scheduler.run_queues[target]
+                    .ready
+                    .retain(|id| *id != current);
+                let task = scheduler.task(current);
+                task.state = TaskState::Running;
+                task.cpu = cpu;
+                return;
+            }
+        };
+        scheduler.switch_to(cpu, next)
+    };
+    unsafe { switch(targets) };
+}
+
+/// # Tick
+/// Called by the timer interrupt. With the fair class, marks every CPU whose current task used
+/// up its slice for `preempt()` if a task of at least its priority is ready there, and sends
+/// the other CPUs among them a reschedule IPI. The task on the calling CPU is accounted first.
+pub fn tick() {
+    if !is_running() || class::class() != SchedClass::Fair {
+        return;
+    }
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let this_cpu = current_cpu();
+    if scheduler.run_queues[this_cpu].current.is_some() {
+        scheduler.account(this_cpu, cputime::collect(this_cpu));
+    }
+    let now = time::uptime_ns();
+    for cpu in smp::online_cpus() {
+        let queue = &scheduler.run_queues[cpu];
+        let current = match queue.current {
+            Some(current) if !queue.idle => current,
+            _ => continue,
+        };
+        if now.saturating_sub(queue.slice_start) < class::TIME_SLICE_NS {
+            continue;
+        }
+        let priority = scheduler.tasks[&current].priority;
+        let contended = queue.ready.iter().any(|id| {
+            let task = &scheduler.tasks[id];
+            !task.on_cpu && task.priority >= priority
+        });
+        if contended && !NEED_RESCHED[cpu].swap(true, Ordering::AcqRel) && cpu != this_cpu {
+            kick(cpu);
+        }
+    }
+}
+
+/// # Preempt
+/// Called at the very end of the timer and reschedule interrupts, with `rflags` of the code they
+/// interrupted. Switches away from the task `tick()` marked the calling CPU for, unless it may
+/// not be preempted right now, then the next tick tries again.
+pub fn preempt(rflags: u64) {
+    let cpu = current_cpu();
+    if !NEED_RESCHED[cpu].swap(false, Ordering::AcqRel) || !is_preemptible(cpu, rflags) {
+        return;
+    }
+    PREEMPTIONS.increment();
+    reschedule();
+}
+
+/// # Is Preemptible
+/// Whether the code interrupted on `cpu` can be switched away from: It ran with interrupts
+/// enabled, so it holds no `IrqSpinLock`, it is neither an exception handler nor the idle loop,
+/// and it holds no other spinlock and does not run softirqs, see `preempt`
+fn is_preemptible(cpu: usize, rflags: u64) -> bool {
+    rflags & RFLAGS_IF != 0
+        && !interrupts::in_exception_context()
+        && !idle::is_waiting(cpu)
+        && preempt::count(cpu) == 0
+}
+
+/// # Block Current
+/// Takes the current task off the CPU until `wake()` is called for it. If no other task is ready,
+/// the CPU is halted until an interrupt handler wakes one.
+///
+/// ## Safety
+/// Must be called with interrupts disabled, after the task has been registered somewhere that
+/// will eventually wake it.
+pub unsafe fn block_current() {
+    {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.assume_init_mut();
+        let current = scheduler.current(current_cpu());
+        let task = scheduler.task(current);
+        if task.wakeup_pending {
+            // Another CPU woke us before we got here
+            task.wakeup_pending = false;
+            return;
+        }
+        task.state = TaskState::Blocked;
+    }
+    loop {
+        let targets = {
+            let mut guard = SCHEDULER.lock();
+            let scheduler = guard.assume_init_mut();
+            let cpu = current_cpu();
+            let current = scheduler.current(cpu);
+            let task = scheduler.task(current);
+            if task.state == TaskState::Ready {
+                // Woken before anything else could run, it may have been queued anywhere
+                let queued_on = task.cpu;
+                task.state = TaskState::Running;
+                task.cpu = cpu;
+                scheduler.run_queues[queued_on]
+                    .ready
+                    .retain(|id| *id != current);
+                scheduler.run_queues[cpu].idle = false;
+                return;
+            }
+            debug_assert!(task.state == TaskState::Blocked);
+            match scheduler.pick_next(cpu, Priority::Low) {
+                Some(next) => Some(scheduler.switch_to(cpu, next)),
+                None => {
+                    scheduler.run_queues[cpu].idle = true;
+                    None
+                }
+            }
+        };
+        if let Some(targets) = targets {
+            switch(targets);
+            return;
+        }
+        // Nothing to run, wait until somebody is queued
+        watchdog::idle();
+        idle::wait(current_cpu());
+    }
+}
+
+/// # Wake
+/// Puts a blocked task back into a run queue. May be called from interrupt handlers on any CPU.
+pub fn wake(id: TaskId) {
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let task = match scheduler.tasks.get_mut(&id) {
+        Some(task) => task,
+        None => return,
+    };
+    match task.state {
+        TaskState::Blocked => {
+            let affinity = task.affinity;
+            let target = scheduler.place(affinity);
+            scheduler.enqueue(id, target);
+        }
+        // Registered with a wait queue but not blocked yet
+        TaskState::Running => task.wakeup_pending = true,
+        _ => {}
+    }
+}
+
+/// # Exit
+/// Ends the current task
+pub fn exit() -> ! {
+    comasm::clear_interrupts();
+    {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = unsafe { guard.assume_init_mut() };
+        let current = scheduler.current(current_cpu());
+        let task = scheduler.task(current);
+        task.state = TaskState::Exited;
+        if core::mem::replace(&mut task.traced, false) {
+            TRACED_TASKS.fetch_sub(1, Ordering::Relaxed);
+        }
+        signal::discard(task);
+        scheduler.zombies.push(current);
+    }
+    loop {
+        let targets = {
+            let mut guard = SCHEDULER.lock();
+            let scheduler = unsafe { guard.assume_init_mut() };
+            let cpu = current_cpu();
+            match scheduler.pick_next(cpu, Priority::Low) {
+                Some(next) => Some(scheduler.switch_to(cpu, next)),
+                None => {
+                    scheduler.run_queues[cpu].idle = true;
+                    None
+                }
+            }
+        };
+        if let Some(targets) = targets {
+            unsafe { switch(targets) };
+            unreachable!("Switched back to an exited task");
+        }
+        // The stack stays alive until another task ran, so interrupts can still use it
+        watchdog::idle();
+        unsafe { idle::wait(current_cpu()) };
+    }
+}
+
+/// # Task Entry
+/// The first Rust code every spawned task runs
+pub fn task_entry(id: u64) -> ! {
+    let entry = without_interrupts(|| unsafe {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.assume_init_mut();
+        scheduler.finish_switch(current_cpu());
+        scheduler.tasks[&TaskId::new(id)].entry
+    });
+    breadcrumb::reach(Milestone::FirstTaskStarted);
+    if let Some(entry) = entry {
+        entry();
+    }
+    exit()
+}
+
+unsafe fn switch(targets: Switch) {
+    CONTEXT_SWITCHES.increment();
+    watchdog::touch();
+    fpu::switch(targets.old_fpu, targets.new_fpu);
+    preempt::switch(targets.old_preempt, targets.new_preempt);
+    scheduler::context::switch_context(targets.old_rsp, targets.new_rsp);
+    // We are back, possibly on another CPU
+    SCHEDULER
+        .lock()
+        .assume_init_mut()
+        .finish_switch(current_cpu());
+}
+
+/// # Assert Can Block
+/// Panics if the current context is not allowed to sleep: Before the scheduler runs there is no
+/// task to put to sleep and with interrupts disabled (in particular inside of interrupt handlers)
+/// nothing could ever wake us up again.
+pub fn assert_can_block(what: &str) {
+    if !is_running() {
+        panic!("{} may not block before the scheduler is running", what);
+    }
+    if !interrupts::are_enabled() {
+        panic!(
+            "{} may not block in interrupt context or with interrupts disabled",
+            what
+        );
+    }
+}
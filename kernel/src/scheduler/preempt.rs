@@ -0,0 +1,50 @@
+//! # Preempt
+//! Counts per CPU what keeps its current task from being preempted. Every guard of the
+//! spinlocks in `spin` raises the count for as long as it lives, and so does running softirqs.
+//! A task switched away from while holding a spinlock would leave everybody else spinning on it
+//! until it runs again, which may be never if the spinning happens with interrupts disabled.
+//!
+//! The count belongs to the task, not the CPU: A task that gives up the CPU on its own while
+//! the count is raised takes it along, see `switch()`.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::interrupts::without_interrupts;
+use crate::smp::{current_cpu, MAX_CPUS};
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static COUNT: [AtomicUsize; MAX_CPUS] = [ZERO; MAX_CPUS];
+
+/// # Disable
+/// Keeps the current task from being preempted until the guard is dropped. Guards nest.
+pub fn disable() -> PreemptGuard {
+    // Not preempted in between, or the count of another CPU would be raised
+    without_interrupts(|| COUNT[current_cpu()].fetch_add(1, Ordering::Relaxed));
+    PreemptGuard(())
+}
+
+/// # Count
+/// How many guards of `disable()` the current task of `cpu` holds
+pub fn count(cpu: usize) -> usize {
+    COUNT[cpu].load(Ordering::Relaxed)
+}
+
+/// # Switch
+/// Stores the count of the calling CPU at `old` and continues with `new`, the count of the task
+/// it switches to. Called with interrupts disabled, right before the context switch.
+///
+/// ## Safety
+/// `old` has to be valid for writes
+pub(super) unsafe fn switch(old: *mut usize, new: usize) {
+    *old = COUNT[current_cpu()].swap(new, Ordering::Relaxed);
+}
+
+/// # Preempt Guard
+/// Keeps the current task from being preempted for as long as it lives, see `disable()`
+#[must_use]
+pub struct PreemptGuard(());
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        without_interrupts(|| COUNT[current_cpu()].fetch_sub(1, Ordering::Relaxed));
+    }
+}
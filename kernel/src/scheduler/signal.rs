@@ -0,0 +1,348 @@
+//! # Signal
+//! Process groups, sessions and the signals sent to them. Tasks stand in for processes: The id
+//! of a task is its pid, and every task is in a process group and a session, which the tasks it
+//! spawns inherit. A group or a session is named after the task that created it. The boot tasks
+//! start their own, so every kernel task is in the group and the session of `kmain`.
+//!
+//! There are no signal handlers yet, a signal only has its default action, see `Action`. It is
+//! taken when the task returns from a system call, so a task that never makes one is never
+//! terminated or stopped. A stopped task waits until it is sent `SIGCONT` or `SIGKILL`. Exited
+//! tasks that were not reaped yet are skipped, they cannot be signalled.
+//!
+//! A fault in user space, like a general protection fault for a port the task may not access,
+//! is the exception: The task is sent the signal of the fault and acts on it right away, see
+//! `fault`.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{is_running, Task, TaskId, TaskState, WaitQueue, SCHEDULER};
+use crate::error::{Error, Result};
+use crate::smp::current_cpu;
+use crate::{counter, counter_array, debug};
+
+pub use ::abi::signal::{Signal, SIGNAL_MAX};
+
+counter!(pub SIGNALS_SENT = "signal.sent");
+counter_array!(pub FAULT_SIGNALS[32] = "signal.faults");
+
+/// The number of tasks with pending signals, so system calls do not take the scheduler lock
+/// when there are none
+static PENDING_TASKS: AtomicUsize = AtomicUsize::new(0);
+/// The stopped tasks
+static STOPPED: WaitQueue = WaitQueue::new();
+
+/// # Action
+/// What a signal does to the task it is sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Terminate,
+    /// Waits for `SIGCONT`
+    Stop,
+    /// Ends a stop, discarding the stop signals that are pending
+    Continue,
+    Ignore,
+}
+
+impl Action {
+    pub fn of(signal: u32) -> Self {
+        match signal {
+            Signal::SIGCHLD | Signal::SIGWINCH => Self::Ignore,
+            Signal::SIGCONT => Self::Continue,
+            Signal::SIGSTOP | Signal::SIGTSTP => Self::Stop,
+            _ => Self::Terminate,
+        }
+    }
+}
+
+const fn mask(signal: u32) -> u32 {
+    1 << signal
+}
+
+/// The signals that stop a task
+const STOP_SIGNALS: u32 = mask(Signal::SIGSTOP) | mask(Signal::SIGTSTP);
+/// The signals that end a stop
+const RESUME_SIGNALS: u32 = mask(Signal::SIGCONT) | mask(Signal::SIGKILL);
+
+/// # Check
+/// Whether `signal` can be sent. Zero is no signal, sending it only checks whether the target
+/// exists.
+///
+/// ## Returns
+/// - Error::InvalidArgument = `signal` is above `SIGNAL_MAX`
+pub fn check(signal: u32) -> Result<()> {
+    if signal > SIGNAL_MAX {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// Makes `signal` pending for `task`, false if it exited
+fn post(task: &mut Task, signal: u32) -> bool {
+    if task.state == TaskState::Exited {
+        return false;
+    }
+    if signal == 0 {
+        return true;
+    }
+    let mut pending = task.pending_signals;
+    // A stop and a continue cancel each other out
+    match Action::of(signal) {
+        Action::Continue => pending &= !STOP_SIGNALS,
+        Action::Stop => pending &= !mask(Signal::SIGCONT),
+        _ => {}
+    }
+    pending |= mask(signal);
+    if task.pending_signals == 0 {
+        PENDING_TASKS.fetch_add(1, Ordering::Relaxed);
+    }
+    task.pending_signals = pending;
+    true
+}
+
+/// # Discard
+/// Drops the pending signals of a task that exits
+pub(super) fn discard(task: &mut Task) {
+    if core::mem::take(&mut task.pending_signals) != 0 {
+        PENDING_TASKS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts the signal and lets stopped tasks check whether they can continue
+fn sent(signal: u32, count: usize) {
+    if signal != 0 {
+        SIGNALS_SENT.add(count as u64);
+        STOPPED.wake_all();
+    }
+}
+
+/// # Send
+/// Sends `signal` to the task `id`
+///
+/// ## Returns
+/// - Error::InvalidArgument = `signal` is above `SIGNAL_MAX`
+/// - Error::NoSuchProcess = There is no task `id`, or it exited
+pub fn send(id: TaskId, signal: u32) -> Result<()> {
+    check(signal)?;
+    if !is_running() {
+        return Err(Error::NoSuchProcess);
+    }
+    {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = unsafe { guard.assume_init_mut() };
+        let task = scheduler.tasks.get_mut(&id).ok_or(Error::NoSuchProcess)?;
+        if !post(task, signal) {
+            return Err(Error::NoSuchProcess);
+        }
+    }
+    sent(signal, 1);
+    Ok(())
+}
+
+/// # Send Group
+/// Sends `signal` to every task in the process group `pgid`
+///
+/// ## Returns
+/// - usize = The number of tasks signalled
+/// - Error::InvalidArgument = `signal` is above `SIGNAL_MAX`
+/// - Error::NoSuchProcess = No task that did not exit is in the group
+pub fn send_group(pgid: TaskId, signal: u32) -> Result<usize> {
+    check(signal)?;
+    if !is_running() {
+        return Err(Error::NoSuchProcess);
+    }
+    let count = {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = unsafe { guard.assume_init_mut() };
+        let mut count = 0;
+        for task in scheduler.tasks.values_mut() {
+            if task.pgid == pgid && post(task, signal) {
+                count += 1;
+            }
+        }
+        count
+    };
+    if count == 0 {
+        return Err(Error::NoSuchProcess);
+    }
+    debug!(
+        "signal: Sent {} to {} tasks of group {}",
+        signal,
+        count,
+        pgid.inner()
+    );
+    sent(signal, count);
+    Ok(count)
+}
+
+/// Runs `f` on the task `id` that did not exit
+fn with_task<T>(id: TaskId, f: impl FnOnce(&Task) -> T) -> Result<T> {
+    if !is_running() {
+        return Err(Error::NoSuchProcess);
+    }
+    let guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_ref() };
+    match scheduler.tasks.get(&id) {
+        Some(task) if task.state != TaskState::Exited => Ok(f(task)),
+        _ => Err(Error::NoSuchProcess),
+    }
+}
+
+/// # Pending
+/// The signals sent to the task `id` it did not act on yet, bit `n` for signal `n`
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`, or it exited
+pub fn pending(id: TaskId) -> Result<u32> {
+    with_task(id, |task| task.pending_signals)
+}
+
+/// # Pgid
+/// The process group of the task `id`
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`, or it exited
+pub fn pgid(id: TaskId) -> Result<TaskId> {
+    with_task(id, Task::pgid)
+}
+
+/// # Sid
+/// The session of the task `id`
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`, or it exited
+pub fn sid(id: TaskId) -> Result<TaskId> {
+    with_task(id, Task::sid)
+}
+
+/// # Group Exists
+/// Whether a task that did not exit is in the process group `pgid`
+pub fn group_exists(pgid: TaskId) -> bool {
+    if !is_running() {
+        return false;
+    }
+    let guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_ref() };
+    scheduler
+        .tasks()
+        .any(|task| task.pgid == pgid && task.state != TaskState::Exited)
+}
+
+/// # Set Pgid
+/// Moves the task `id` into the process group `pgid`, a new one if `pgid` is `id`
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`, or it exited
+/// - Error::OperationNotPermitted = The task leads its session, or no task of its session is
+///   in the group `pgid`
+pub fn set_pgid(id: TaskId, pgid: TaskId) -> Result<()> {
+    if !is_running() {
+        return Err(Error::NoSuchProcess);
+    }
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let sid = match scheduler.tasks.get(&id) {
+        Some(task) if task.state != TaskState::Exited => task.sid,
+        _ => return Err(Error::NoSuchProcess),
+    };
+    if sid == id {
+        return Err(Error::OperationNotPermitted);
+    }
+    let joinable = pgid == id
+        || scheduler
+            .tasks()
+            .any(|task| task.pgid == pgid && task.sid == sid && task.state != TaskState::Exited);
+    if !joinable {
+        return Err(Error::OperationNotPermitted);
+    }
+    scheduler.task(id).pgid = pgid;
+    Ok(())
+}
+
+/// # Set Sid
+/// Makes the task `id` the leader of a new session and of a new process group in it
+///
+/// ## Returns
+/// - TaskId = The new session
+/// - Error::NoSuchProcess = There is no task `id`, or it exited
+/// - Error::OperationNotPermitted = A process group is named after the task already
+pub fn set_sid(id: TaskId) -> Result<TaskId> {
+    if !is_running() {
+        return Err(Error::NoSuchProcess);
+    }
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    match scheduler.tasks.get(&id) {
+        Some(task) if task.state != TaskState::Exited => {}
+        _ => return Err(Error::NoSuchProcess),
+    }
+    if scheduler.tasks().any(|task| task.pgid == id) {
+        return Err(Error::OperationNotPermitted);
+    }
+    let task = scheduler.task(id);
+    task.pgid = id;
+    task.sid = id;
+    Ok(id)
+}
+
+/// Takes the pending signals of the current task, or only looks at them
+fn current_pending(take: bool) -> u32 {
+    let mut guard = SCHEDULER.lock();
+    let scheduler = unsafe { guard.assume_init_mut() };
+    let current = scheduler.current(current_cpu());
+    let task = scheduler.task(current);
+    if !take {
+        return task.pending_signals;
+    }
+    let pending = task.pending_signals;
+    discard(task);
+    pending
+}
+
+/// # Handle Pending
+/// Takes the default action of every signal pending for the current task: Terminates it, or
+/// waits while it is stopped. Called when a system call returns.
+pub fn handle_pending() {
+    if PENDING_TASKS.load(Ordering::Relaxed) == 0 || !is_running() {
+        return;
+    }
+    loop {
+        let pending = current_pending(true);
+        if pending == 0 {
+            return;
+        }
+        let mut stop = false;
+        for signal in (1..=SIGNAL_MAX).filter(|signal| pending & mask(*signal) != 0) {
+            match Action::of(signal) {
+                Action::Terminate => {
+                    debug!(
+                        "signal: Task {} terminated by {}",
+                        super::current().inner(),
+                        signal
+                    );
+                    super::exit()
+                }
+                Action::Stop => stop = true,
+                Action::Continue | Action::Ignore => {}
+            }
+        }
+        if !stop {
+            return;
+        }
+        STOPPED.wait_until(|| current_pending(false) & RESUME_SIGNALS != 0);
+    }
+}
+
+/// # Fault
+/// Sends `signal` to the current task, which faulted in user space, and takes its action. A
+/// fault cannot be returned to, so the task exits even if the signal would be ignored.
+pub fn fault(signal: u32) -> ! {
+    FAULT_SIGNALS.increment(signal as usize);
+    let current = super::current();
+    debug!(
+        "signal: Task {} faulted, sending {}",
+        current.inner(),
+        signal
+    );
+    let _ = send(current, signal);
+    handle_pending();
+    super::exit()
+}
@@ -0,0 +1,188 @@
+//! # Spin
+//! The spinlocks of the `spin` crate, with guards that keep the holder from being preempted, see
+//! `preempt`. Everything but `IrqSpinLock` uses these instead of the ones of the crate.
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+use super::preempt::{self, PreemptGuard};
+
+/// # Mutex
+/// A spinlock whose holder is not preempted
+#[derive(Default)]
+pub struct Mutex<T: ?Sized> {
+    inner: spin::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: spin::Mutex::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// # Lock
+    /// Spins until the lock is free
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let preempt = preempt::disable();
+        MutexGuard {
+            guard: self.inner.lock(),
+            _preempt: preempt,
+        }
+    }
+
+    /// # Try Lock
+    /// Takes the lock if it is free
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let preempt = preempt::disable();
+        Some(MutexGuard {
+            guard: self.inner.try_lock()?,
+            _preempt: preempt,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// # Force Unlock
+    /// Releases the lock without its guard. Dropping the guard later still lowers the preempt
+    /// count, a guard that was forgotten leaves it raised.
+    ///
+    /// ## Safety
+    /// Nobody may use the data through the guard anymore
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+/// The lock is released before the holder may be preempted again
+pub struct MutexGuard<'a, T: ?Sized> {
+    guard: spin::MutexGuard<'a, T>,
+    _preempt: PreemptGuard,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// # RwLock
+/// A readers-writer spinlock whose readers and writer are not preempted
+#[derive(Default)]
+pub struct RwLock<T: ?Sized> {
+    inner: spin::RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: spin::RwLock::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// # Read
+    /// Spins until there is no writer
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let preempt = preempt::disable();
+        RwLockReadGuard {
+            guard: self.inner.read(),
+            _preempt: preempt,
+        }
+    }
+
+    /// # Write
+    /// Spins until there are neither readers nor a writer
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let preempt = preempt::disable();
+        RwLockWriteGuard {
+            guard: self.inner.write(),
+            _preempt: preempt,
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let preempt = preempt::disable();
+        Some(RwLockReadGuard {
+            guard: self.inner.try_read()?,
+            _preempt: preempt,
+        })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let preempt = preempt::disable();
+        Some(RwLockWriteGuard {
+            guard: self.inner.try_write()?,
+            _preempt: preempt,
+        })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    guard: spin::RwLockReadGuard<'a, T>,
+    _preempt: PreemptGuard,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    guard: spin::RwLockWriteGuard<'a, T>,
+    _preempt: PreemptGuard,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
@@ -0,0 +1,417 @@
+//! # Sync
+//! Locks that put the waiting task to sleep instead of spinning.
+//!
+//! Both hand ownership directly to the woken task on release, so waiters are served in FIFO order
+//! and a task that did not wait cannot steal the lock in between. The holder of a `Mutex` runs
+//! with the highest priority among its waiters until it hands the mutex over, see the scheduler.
+//! Spinlocks are still the right choice for short critical sections. Anything that is touched
+//! from interrupt handlers has to use an `IrqSpinLock`, otherwise an interrupt arriving while
+//! the lock is held on the same CPU deadlocks.
+//!
+//! Debug builds track which `IrqSpinLock`s every CPU holds and where they were taken, see
+//! `held_locks`. With the `lockstat` feature, how often they are contended is counted, see
+//! `lockstat`.
+use core::{
+    cell::UnsafeCell,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use super::{assert_can_block, TaskId, WaitQueue};
+use crate::arch::interrupts::{self, without_interrupts};
+use crate::smp::MAX_CPUS;
+
+#[cfg(feature = "lockstat")]
+use crate::{arch::tsc, lockstat};
+
+/// The number of nested `IrqSpinLock`s tracked per CPU, locks beyond that are not recorded
+const MAX_TRACKED_LOCKS: usize = 8;
+
+/// # Held Locks
+/// The addresses of the locks a CPU holds and the locations they were taken at
+struct HeldLocks {
+    locks: [AtomicUsize; MAX_TRACKED_LOCKS],
+    locations: [AtomicPtr<Location<'static>>; MAX_TRACKED_LOCKS],
+}
+
+impl HeldLocks {
+    const fn new() -> Self {
+        const FREE: AtomicUsize = AtomicUsize::new(0);
+        const NOWHERE: AtomicPtr<Location<'static>> = AtomicPtr::new(core::ptr::null_mut());
+        Self {
+            locks: [FREE; MAX_TRACKED_LOCKS],
+            locations: [NOWHERE; MAX_TRACKED_LOCKS],
+        }
+    }
+}
+
+const NONE_HELD: HeldLocks = HeldLocks::new();
+static HELD_LOCKS: [HeldLocks; MAX_CPUS] = [NONE_HELD; MAX_CPUS];
+
+/// # Track
+/// Records that the calling CPU took `lock` at `location`
+///
+/// ## Returns
+/// - Option<(usize, usize)> = The CPU and the slot the lock was recorded in
+#[cfg(debug_assertions)]
+fn track(lock: usize, location: &'static Location<'static>) -> Option<(usize, usize)> {
+    let cpu = crate::smp::current_cpu();
+    let held = &HELD_LOCKS[cpu];
+    let slot = held.locks.iter().position(|slot| {
+        slot.compare_exchange(0, lock, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    })?;
+    held.locations[slot].store(location as *const _ as *mut _, Ordering::Relaxed);
+    Some((cpu, slot))
+}
+
+#[cfg(debug_assertions)]
+fn untrack((cpu, slot): (usize, usize)) {
+    HELD_LOCKS[cpu].locks[slot].store(0, Ordering::Relaxed);
+}
+
+/// # Held Locks
+/// The `IrqSpinLock`s `cpu` holds, as the address of the lock and where it was taken. Always
+/// empty in release builds.
+pub fn held_locks(cpu: usize) -> impl Iterator<Item = (usize, &'static Location<'static>)> {
+    let held = &HELD_LOCKS[cpu.min(MAX_CPUS - 1)];
+    held.locks
+        .iter()
+        .zip(held.locations.iter())
+        .filter_map(|(lock, location)| {
+            let lock = lock.load(Ordering::Relaxed);
+            let location = location.load(Ordering::Relaxed);
+            if lock == 0 || location.is_null() {
+                return None;
+            }
+            Some((lock, unsafe { &*location }))
+        })
+}
+
+/// # Named Lock
+/// An `IrqSpinLock` around `$data`, which `lockstat` counts under `$name` along with every
+/// other lock of that name. Without the `lockstat` feature it is `IrqSpinLock::new()`.
+/// ## Example
+/// ```
+/// static TIMERS: IrqSpinLock<Timers> = named_lock!("timers", Timers::new());
+/// ```
+#[macro_export]
+macro_rules! named_lock {
+    ($name:literal, $data:expr) => {
+        $crate::scheduler::IrqSpinLock::named($name, $data)
+    };
+}
+
+/// # IRQ Spin Lock
+/// A spinlock that keeps interrupts disabled on the holding CPU for as long as it is held
+pub struct IrqSpinLock<T: ?Sized> {
+    #[cfg(feature = "lockstat")]
+    class: lockstat::LockClass,
+    inner: spin::Mutex<T>,
+}
+
+impl<T> IrqSpinLock<T> {
+    /// # New
+    /// A lock that `lockstat` counts under the place it is first taken at, see `named_lock!`
+    pub const fn new(data: T) -> Self {
+        Self {
+            #[cfg(feature = "lockstat")]
+            class: lockstat::LockClass::new(None),
+            inner: spin::Mutex::new(data),
+        }
+    }
+
+    /// # Named
+    /// A lock that `lockstat` counts under `name`, use `named_lock!`
+    pub const fn named(name: &'static str, data: T) -> Self {
+        #[cfg(not(feature = "lockstat"))]
+        let _ = name;
+        Self {
+            #[cfg(feature = "lockstat")]
+            class: lockstat::LockClass::new(Some(name)),
+            inner: spin::Mutex::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqSpinLock<T> {
+    /// # Lock
+    /// Disables interrupts and spins until the lock is free. The previous interrupt state is
+    /// restored once the guard is dropped.
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let were_enabled = interrupts::are_enabled();
+        if were_enabled {
+            comasm::clear_interrupts();
+        }
+        #[cfg(feature = "lockstat")]
+        let guard = self.lock_counted(Location::caller());
+        #[cfg(not(feature = "lockstat"))]
+        let guard = self.inner.lock();
+        let guard = ManuallyDrop::new(guard);
+        IrqSpinLockGuard {
+            guard,
+            were_enabled,
+            #[cfg(debug_assertions)]
+            tracked: track(
+                self as *const Self as *const () as usize,
+                Location::caller(),
+            ),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// # Lock Counted
+    /// Takes the lock for `location`, counting the acquisition and the wait if it is held
+    #[cfg(feature = "lockstat")]
+    #[inline(always)]
+    fn lock_counted(&self, location: &'static Location<'static>) -> spin::MutexGuard<'_, T> {
+        let class = self.class.acquired(location);
+        match self.inner.try_lock() {
+            Some(guard) => guard,
+            None => self.lock_contended(class, location),
+        }
+    }
+
+    #[cfg(feature = "lockstat")]
+    #[cold]
+    #[inline(never)]
+    fn lock_contended(
+        &self,
+        class: usize,
+        location: &'static Location<'static>,
+    ) -> spin::MutexGuard<'_, T> {
+        let start = tsc::read();
+        let guard = self.inner.lock();
+        lockstat::contended(class, location, tsc::read() - start);
+        guard
+    }
+}
+
+pub struct IrqSpinLockGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    were_enabled: bool,
+    #[cfg(debug_assertions)]
+    tracked: Option<(usize, usize)>,
+}
+
+impl<T: ?Sized> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        if let Some(tracked) = self.tracked {
+            untrack(tracked);
+        }
+        // The lock has to be released before interrupts come back
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.were_enabled {
+            comasm::reload_interrupt_flags();
+        }
+    }
+}
+
+/// # Mutex
+/// A mutual exclusion lock that blocks the task when contended
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    /// The task holding the lock, `None` if it was taken before the scheduler ran. Held while
+    /// the lock is taken, waited for or handed over.
+    owner: IrqSpinLock<Option<TaskId>>,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            owner: crate::named_lock!("mutex_owner", None),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// # Lock
+    /// Acquires the lock, sleeping until it is free.
+    ///
+    /// ## Panics
+    /// When called from interrupt context or before the scheduler is running
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        assert_can_block("Mutex::lock");
+        let current = super::current();
+        without_interrupts(|| {
+            {
+                let mut owner = self.owner.lock();
+                if self.try_take() {
+                    *owner = Some(current);
+                    return;
+                }
+                // `unlock` hands the lock over without ever clearing `locked`
+                self.waiters.enqueue(current);
+                if let Some(owner) = *owner {
+                    super::wait_for_mutex(self.address(), owner);
+                }
+            }
+            unsafe { super::block_current() };
+        });
+        MutexGuard { mutex: self }
+    }
+
+    /// # Try Lock
+    /// Acquires the lock if it is free, never blocks
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut owner = self.owner.lock();
+        if !self.try_take() {
+            return None;
+        }
+        // Taken with interrupts disabled, e.g. by the panic handler, there is no task to boost
+        *owner = (super::is_running() && interrupts::are_enabled()).then(super::current);
+        Some(MutexGuard { mutex: self })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn try_take(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// The address of the lock, which identifies it to the scheduler
+    fn address(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+
+    fn unlock(&self) {
+        let mut owner = self.owner.lock();
+        match self.waiters.wake_one() {
+            Some(next) => {
+                *owner = Some(next);
+                super::hand_over_mutex(self.address(), next, &self.waiters.waiting());
+            }
+            None => {
+                *owner = None;
+                self.locked.store(false, Ordering::Release);
+            }
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock()
+    }
+}
+
+/// # Semaphore
+/// A counting semaphore that blocks the task while no permits are available
+pub struct Semaphore {
+    /// Held while a permit is taken, waited for or handed over
+    permits: IrqSpinLock<usize>,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: crate::named_lock!("semaphore_permits", permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// # Acquire
+    /// Takes a permit, sleeping until one is available.
+    ///
+    /// ## Panics
+    /// When called from interrupt context or before the scheduler is running
+    pub fn acquire(&self) {
+        assert_can_block("Semaphore::acquire");
+        let current = super::current();
+        without_interrupts(|| {
+            {
+                let mut permits = self.permits.lock();
+                if *permits > 0 {
+                    *permits -= 1;
+                    return;
+                }
+                // `release` hands its permit over without ever adding it to `permits`
+                self.waiters.enqueue(current);
+            }
+            unsafe { super::block_current() };
+        })
+    }
+
+    /// # Try Acquire
+    /// Takes a permit if one is available, never blocks
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.lock();
+        if *permits == 0 {
+            return false;
+        }
+        *permits -= 1;
+        true
+    }
+
+    /// # Release
+    /// Returns a permit, waking the longest waiting task if there is any.
+    /// May be called from interrupt handlers.
+    pub fn release(&self) {
+        let mut permits = self.permits.lock();
+        if self.waiters.wake_one().is_none() {
+            *permits += 1;
+        }
+    }
+
+    pub fn available(&self) -> usize {
+        *self.permits.lock()
+    }
+}
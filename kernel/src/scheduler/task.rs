@@ -0,0 +1,247 @@
+use alloc::vec::Vec;
+
+use super::cputime::{self, CpuTime};
+use crate::arch::fpu::FpuState;
+use crate::arch::tss::IoBitmap;
+use crate::heap::tag;
+use crate::memory::kaslr;
+use crate::smp::CpuMask;
+
+num_backed::num_backed!(pub TaskId backed by u64);
+
+/// The size of the kernel stack every spawned task gets
+pub const KERNEL_STACK_SIZE: usize = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Waiting in a run queue
+    Ready,
+    Running,
+    /// Waiting on a `WaitQueue`, not in any run queue
+    Blocked,
+    /// Finished, the stack is freed by the next task that gets scheduled
+    Exited,
+}
+
+/// # Priority
+/// Which of the ready tasks runs next: The first one in the run queue out of those with the
+/// highest priority, see `class`. Tasks start out with `Normal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+    Realtime,
+}
+
+impl Priority {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+            Self::Realtime => "rt",
+        }
+    }
+}
+
+/// # Task
+/// A kernel thread of execution with its own stack
+pub struct Task {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub state: TaskState,
+    /// The saved stack pointer, only valid while the task is not running
+    pub(super) rsp: u64,
+    /// `None` for the boot tasks, which keep running on the stack they were handed
+    stack: Option<Vec<u8>>,
+    pub(super) entry: Option<fn()>,
+    /// The CPUs the task may run on
+    pub(super) affinity: CpuMask,
+    /// The CPU whose run queue the task is in or which it is running on
+    pub(super) cpu: usize,
+    /// Set from the moment the task is picked until its context has been saved again after
+    /// being switched away from. No other CPU may pick the task up in the meantime.
+    pub(super) on_cpu: bool,
+    /// Set if the task was woken while it was still on its way to block
+    pub(super) wakeup_pending: bool,
+    /// The time the task ran for, up to its last switch-out, see `cputime`
+    pub(super) time: CpuTime,
+    /// The FPU registers, only valid while the task is not running
+    pub(super) fpu: FpuState,
+    /// Whether the system calls of the task are logged, see `syscall::trace`
+    pub(super) traced: bool,
+    /// The allocation tag of the task, only valid while the task is not running
+    pub(super) alloc_tag: u8,
+    /// The priority the task was given, see `set_priority()`
+    pub(super) base_priority: Priority,
+    /// The priority the task is scheduled with, its base priority unless it holds a `Mutex`
+    /// that a task of a higher priority waits for
+    pub(super) priority: Priority,
+    /// The `Mutex`es the task holds that others wait for, by address, and the highest priority
+    /// among their waiters
+    pub(super) boosts: Vec<(usize, Priority)>,
+    /// The `Mutex` the task waits for, by address, and the task holding it
+    pub(super) waiting_for: Option<(usize, TaskId)>,
+    /// The process group, see `signal`
+    pub(super) pgid: TaskId,
+    /// The session, the id of the task that created it
+    pub(super) sid: TaskId,
+    /// The signals sent but not acted on yet, bit `n` for signal `n`
+    pub(super) pending_signals: u32,
+    /// The I/O ports the task may access from user space, `None` for none
+    pub(super) io_bitmap: Option<IoBitmap>,
+    /// The weight of the task in the fair class, see `class::weight()`
+    pub(super) nice: i8,
+    /// The cycles the task ran for, scaled by its weight, see `class`
+    pub(super) vruntime: u64,
+    /// The preempt count of the task, only valid while the task is not running, see `preempt`
+    pub(super) preempt_count: usize,
+}
+
+impl Task {
+    /// # Boot
+    /// The task representing the code that initialized the scheduler on `cpu`
+    pub(super) fn boot(id: TaskId, name: &'static str, cpu: usize) -> Self {
+        Self {
+            id,
+            name,
+            state: TaskState::Running,
+            rsp: 0,
+            stack: None,
+            entry: None,
+            affinity: CpuMask::single(cpu),
+            cpu,
+            on_cpu: true,
+            wakeup_pending: false,
+            time: CpuTime::default(),
+            fpu: FpuState::new(),
+            traced: false,
+            alloc_tag: tag::UNTAGGED,
+            base_priority: Priority::Normal,
+            priority: Priority::Normal,
+            boosts: Vec::new(),
+            waiting_for: None,
+            pgid: id,
+            sid: id,
+            pending_signals: 0,
+            io_bitmap: None,
+            nice: 0,
+            vruntime: 0,
+            preempt_count: 0,
+        }
+    }
+
+    pub(super) fn new(id: TaskId, name: &'static str, entry: fn()) -> Self {
+        // The top is lowered by a random offset, the space below it stays KERNEL_STACK_SIZE
+        let stack = alloc::vec![0u8; KERNEL_STACK_SIZE + kaslr::MAX_STACK_OFFSET];
+        let stack_top = stack.as_ptr() as u64 + (stack.len() - kaslr::stack_offset()) as u64;
+        Self {
+            id,
+            name,
+            state: TaskState::Ready,
+            rsp: unsafe { crate::arch::scheduler::context::prepare_stack(stack_top, id.inner()) },
+            stack: Some(stack),
+            entry: Some(entry),
+            affinity: CpuMask::all(),
+            cpu: 0,
+            on_cpu: false,
+            wakeup_pending: false,
+            time: CpuTime::default(),
+            fpu: FpuState::new(),
+            traced: false,
+            alloc_tag: tag::UNTAGGED,
+            base_priority: Priority::Normal,
+            priority: Priority::Normal,
+            boosts: Vec::new(),
+            waiting_for: None,
+            pgid: id,
+            sid: id,
+            pending_signals: 0,
+            io_bitmap: None,
+            nice: 0,
+            vruntime: 0,
+            preempt_count: 0,
+        }
+    }
+
+    /// # Stack Top
+    /// The top of the kernel stack, `None` for the boot tasks. A task that entered userspace has
+    /// left its kernel frames behind, so its system calls run from here.
+    pub fn stack_top(&self) -> Option<u64> {
+        self.stack
+            .as_ref()
+            .map(|stack| stack.as_ptr() as u64 + stack.len() as u64)
+    }
+
+    /// The CPU the task last ran on or is queued for
+    pub fn cpu(&self) -> usize {
+        self.cpu
+    }
+
+    pub fn affinity(&self) -> CpuMask {
+        self.affinity
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    pub fn base_priority(&self) -> Priority {
+        self.base_priority
+    }
+
+    pub fn nice(&self) -> i8 {
+        self.nice
+    }
+
+    pub fn vruntime(&self) -> u64 {
+        self.vruntime
+    }
+
+    /// Sets the priority to the highest out of the base priority and the boosts
+    pub(super) fn update_priority(&mut self) {
+        self.priority = self
+            .boosts
+            .iter()
+            .map(|(_, priority)| *priority)
+            .fold(self.base_priority, Priority::max);
+    }
+
+    /// The process group, which spawned tasks inherit
+    pub fn pgid(&self) -> TaskId {
+        self.pgid
+    }
+
+    /// The session, which spawned tasks inherit
+    pub fn sid(&self) -> TaskId {
+        self.sid
+    }
+
+    pub fn is_traced(&self) -> bool {
+        self.traced
+    }
+
+    /// # Time
+    /// The time the task has run for, including the current run if it is running. Of a task
+    /// running on another CPU, only the time up to its last system call, interrupt from user
+    /// space or idle wait is known.
+    pub fn time(&self) -> CpuTime {
+        match self.state {
+            TaskState::Running => self.time + cputime::pending(self.cpu),
+            _ => self.time,
+        }
+    }
+
+    /// # Runtime
+    /// The TSC cycles the task has run for, see `time()`
+    pub fn runtime(&self) -> u64 {
+        self.time().total()
+    }
+}
+
+/// # Migrate
+/// Pins the task `id` to `cpu`. A task running on another CPU moves the next time it yields.
+pub fn migrate(id: TaskId, cpu: usize) {
+    super::set_affinity(id, CpuMask::single(cpu));
+}
@@ -0,0 +1,144 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{IrqSpinLock, TaskId};
+use crate::arch::interrupts::without_interrupts;
+use crate::time::{self, Timer};
+
+/// # Wait Queue
+/// A FIFO list of blocked tasks waiting for some condition
+pub struct WaitQueue {
+    waiters: IrqSpinLock<Vec<TaskId>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: crate::named_lock!("wait_queue", Vec::new()),
+        }
+    }
+
+    /// # Sleep
+    /// Blocks the current task until it is woken through this queue.
+    ///
+    /// ## Safety
+    /// Interrupts must be disabled by the caller, which must have checked the condition it waits
+    /// for in the same interrupt-free section. Otherwise the wakeup may be lost.
+    pub unsafe fn sleep(&self) {
+        self.waiters.lock().push(super::current());
+        super::block_current();
+    }
+
+    /// # Enqueue
+    /// Registers `id` as waiting without blocking it, `super::block_current()` has to follow
+    pub(super) fn enqueue(&self, id: TaskId) {
+        self.waiters.lock().push(id);
+    }
+
+    /// The tasks waiting, longest first
+    pub(super) fn waiting(&self) -> Vec<TaskId> {
+        self.waiters.lock().clone()
+    }
+
+    /// # Wait Until
+    /// Blocks the current task until `condition` returns true
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        super::assert_can_block("WaitQueue::wait_until");
+        without_interrupts(|| {
+            while !condition() {
+                unsafe { self.sleep() };
+            }
+        })
+    }
+
+    /// # Wait Until Timeout
+    /// Blocks the current task until `condition` returns true, for at most `timeout_ms`
+    /// milliseconds
+    ///
+    /// ## Returns
+    /// - bool = Whether `condition` returned true, false if the time ran out
+    pub fn wait_until_timeout(&self, mut condition: impl FnMut() -> bool, timeout_ms: u64) -> bool {
+        super::assert_can_block("WaitQueue::wait_until_timeout");
+        let id = super::current();
+        let deadline = time::now_ms().saturating_add(timeout_ms);
+        let timed_out = Arc::new(AtomicBool::new(false));
+        // The timer is cancelled before returning, so the queue outlives it
+        let queue = self as *const Self as usize;
+        let timer = {
+            let timed_out = timed_out.clone();
+            Timer::schedule(timeout_ms, move || {
+                let queue = unsafe { &*(queue as *const WaitQueue) };
+                if queue.remove(id) {
+                    timed_out.store(true, Ordering::Relaxed);
+                    super::wake(id);
+                }
+            })
+        };
+        // Whether the last wakeup came through the queue rather than from the timer
+        let mut woken = false;
+        let met = without_interrupts(|| loop {
+            if condition() {
+                break true;
+            }
+            {
+                // The timer fires at the deadline at the earliest. Checked with the waiters
+                // locked, it either fired before or finds the task registered.
+                let mut waiters = self.waiters.lock();
+                if time::now_ms() >= deadline {
+                    break false;
+                }
+                waiters.push(id);
+            }
+            unsafe { super::block_current() };
+            woken = !timed_out.load(Ordering::Relaxed);
+        });
+        self.remove(id);
+        timer.cancel();
+        if !met && woken {
+            // The wakeup was meant for whoever waits next
+            self.wake_one();
+        }
+        met
+    }
+
+    /// Takes `id` off the queue, returns whether it was waiting
+    fn remove(&self, id: TaskId) -> bool {
+        let mut waiters = self.waiters.lock();
+        match waiters.iter().position(|waiter| *waiter == id) {
+            Some(idx) => {
+                waiters.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// # Wake One
+    /// Wakes the task that has been waiting the longest. May be called from interrupt handlers
+    /// on any CPU.
+    ///
+    /// ## Returns
+    /// - Option<TaskId> = The task that was woken
+    pub fn wake_one(&self) -> Option<TaskId> {
+        let id = {
+            let mut waiters = self.waiters.lock();
+            if waiters.is_empty() {
+                return None;
+            }
+            waiters.remove(0)
+        };
+        super::wake(id);
+        Some(id)
+    }
+
+    /// # Wake All
+    /// Wakes every waiting task
+    pub fn wake_all(&self) {
+        while self.wake_one().is_some() {}
+    }
+
+    pub fn has_waiters(&self) -> bool {
+        !self.waiters.lock().is_empty()
+    }
+}
@@ -0,0 +1,113 @@
+use alloc::format;
+use alloc::string::ToString;
+
+use crate::arch::mem::{self, CopyVariant};
+use crate::arch::tsc;
+use crate::bench::{self as suite, Benchmark, BENCHMARKS, MEM_SIZES};
+use crate::kprintln;
+use crate::math::ByteSize;
+use crate::scheduler::bench;
+
+pub fn bench(args: &[&str]) {
+    match args.first() {
+        Some(&"sched") => sched(&args[1..]),
+        Some(&"mem") => mem_copy(),
+        Some(&"all") => BENCHMARKS.iter().for_each(run),
+        Some(name) => match suite::find(name) {
+            Some(benchmark) => run(benchmark),
+            None => usage(),
+        },
+        None => usage(),
+    }
+}
+
+fn usage() {
+    kprintln!("bench: Usage: bench sched [handoffs] | bench mem | bench all | bench <name>");
+    for benchmark in BENCHMARKS {
+        kprintln!("  {:<8} {}", benchmark.name, benchmark.help);
+    }
+}
+
+fn run(benchmark: &Benchmark) {
+    match suite::run(benchmark) {
+        Ok(summary) => kprintln!(
+            "{:<8} median {} cycles, p99 {} cycles over {} runs",
+            benchmark.name,
+            summary.median,
+            summary.p99,
+            suite::ITERATIONS
+        ),
+        Err(e) => kprintln!("bench: {}: {}", benchmark.name, e),
+    }
+}
+
+fn sched(args: &[&str]) {
+    let handoffs = match args.first().map(|handoffs| handoffs.parse::<u64>()) {
+        None => bench::DEFAULT_HANDOFFS,
+        Some(Ok(handoffs)) if handoffs > 0 => handoffs,
+        Some(_) => {
+            kprintln!("bench: Invalid number of handoffs");
+            return;
+        }
+    };
+    let result = match bench::ping_pong(handoffs) {
+        Ok(result) => result,
+        Err(e) => {
+            kprintln!("bench: {}", e);
+            return;
+        }
+    };
+    kprintln!(
+        "{} handoffs ({} context switches) in {} cycles",
+        result.handoffs,
+        result.switches,
+        result.cycles
+    );
+    match result.ns_per_switch() {
+        Some(ns) => kprintln!(
+            "{} cycles, {} ns per switch (budget {} ns, TSC at {} kHz)",
+            result.cycles_per_switch(),
+            ns,
+            bench::budget_ns(),
+            tsc::khz().unwrap_or(0)
+        ),
+        None => kprintln!(
+            "{} cycles per switch, the TSC is not calibrated",
+            result.cycles_per_switch()
+        ),
+    }
+}
+
+/// Prints the median cycles of every copy variant for every size, `*` marks the variant
+/// `copy_fast()` picks
+fn mem_copy() {
+    kprintln!(
+        "ERMS: {}, non-temporal from {}",
+        if mem::has_erms() { "yes" } else { "no" },
+        ByteSize(mem::nontemporal_threshold() as u64).to_string()
+    );
+    kprintln!(
+        "{:>10} {:>14} {:>14} {:>14} {:>14}",
+        "SIZE",
+        CopyVariant::Builtin.name(),
+        CopyVariant::RepMovsb.name(),
+        CopyVariant::Sse2.name(),
+        CopyVariant::NonTemporal.name()
+    );
+    for size in MEM_SIZES {
+        let mut line = format!("{:>10}", ByteSize(size as u64).to_string());
+        for variant in CopyVariant::ALL {
+            let cell = match suite::mem_copy(variant, size) {
+                Ok(summary) if mem::select(size) == variant => format!("*{}", summary.median),
+                Ok(summary) => summary.median.to_string(),
+                Err(e) => {
+                    kprintln!("bench: {} of {} bytes: {}", variant.name(), size, e);
+                    "-".to_string()
+                }
+            };
+            line.push_str(&format!(" {:>14}", cell));
+        }
+        kprintln!("{}", line);
+    }
+    kprintln!("Median cycles over {} copies", suite::MEM_ITERATIONS);
+}
@@ -0,0 +1,23 @@
+use crate::kprintln;
+use crate::pci;
+
+pub fn bind(args: &[&str]) {
+    let (location, driver) = match args {
+        [location, driver] => (*location, *driver),
+        _ => {
+            kprintln!("Usage: bind <bus:slot.function> <driver>");
+            return;
+        }
+    };
+    let device = match pci::find_device(location) {
+        Some(device) => device,
+        None => {
+            kprintln!("bind: There is no PCI function {}", location);
+            return;
+        }
+    };
+    match pci::rebind(&device, driver) {
+        Ok(()) => kprintln!("bind: Bound {} to {}", location, driver),
+        Err(e) => kprintln!("bind: Cannot bind {} to {}: {}", location, driver, e),
+    }
+}
@@ -0,0 +1,80 @@
+use alloc::string::String;
+
+use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::klog::{self, LogSink};
+use crate::{kprintln, logdisk};
+
+/// The bytes of the log printed at a time
+const CHUNK_SIZE: usize = 256;
+
+pub fn dmesg(args: &[&str]) {
+    match args {
+        [] => print_log(),
+        ["--sinks"] => print_sinks(),
+        ["--previous"] => print_previous(),
+        _ => kprintln!("Usage: dmesg [--sinks|--previous]"),
+    }
+}
+
+/// Prints the log to the console only, so it is not logged a second time
+fn print_log() {
+    let end = klog::written();
+    let mut pos = 0;
+    let mut buf = [0; CHUNK_SIZE];
+    // Bytes of a character split between two chunks are kept for the next one
+    let mut carry = 0;
+    while pos < end {
+        let (start, len) = klog::read(pos, &mut buf[carry..]);
+        if len == 0 {
+            break;
+        }
+        pos = start + len as u64;
+        let filled = carry + len;
+        // How much is printed, and how much of the chunk is done with
+        let (valid, used) = match core::str::from_utf8(&buf[..filled]) {
+            Ok(s) => (s.len(), s.len()),
+            Err(e) => match e.error_len() {
+                None => (e.valid_up_to(), e.valid_up_to()),
+                // Not UTF-8, which only happens where older output was overwritten
+                Some(skip) => (e.valid_up_to(), e.valid_up_to() + skip),
+            },
+        };
+        let text = core::str::from_utf8(&buf[..valid]).unwrap_or("");
+        unsafe {
+            FRAMEBUFFER_GUARD
+                .lock()
+                .assume_init_mut()
+                .write_console(text)
+        };
+        buf.copy_within(used..filled, 0);
+        carry = filled - used;
+    }
+}
+
+fn print_sinks() {
+    let serial_only = unsafe { FRAMEBUFFER_GUARD.lock().assume_init_ref().is_serial_only() };
+    kprintln!(
+        "console  {}",
+        if serial_only { "com1" } else { "framebuffer" }
+    );
+    klog::for_each_sink(|sink| kprintln!("sink     {}", sink.name()));
+}
+
+/// Prints what the previous boot appended to the log disk
+fn print_previous() {
+    let result = logdisk::read_previous(|text| {
+        // A record may end in the middle of a character
+        let text = String::from_utf8_lossy(text);
+        unsafe {
+            FRAMEBUFFER_GUARD
+                .lock()
+                .assume_init_mut()
+                .write_console(&text)
+        };
+    });
+    match result {
+        Ok(0) => kprintln!("dmesg: The previous boot left no log"),
+        Ok(_) => {}
+        Err(e) => kprintln!("dmesg: {}", e.text()),
+    }
+}
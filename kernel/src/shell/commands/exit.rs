@@ -0,0 +1,6 @@
+use crate::{kprintln, shell};
+
+pub fn exit(_: &[&str]) {
+    kprintln!("Leaving the shell, the console is back in canonical mode");
+    shell::exit();
+}
@@ -0,0 +1,16 @@
+use crate::{framebuffer::FRAMEBUFFER_GUARD, kprintln};
+
+pub fn fbinfo(_: &[&str]) {
+    let (info, rotation) = {
+        let guard = FRAMEBUFFER_GUARD.lock();
+        let guard = unsafe { guard.assume_init_ref() };
+        (guard.info(), guard.rotation())
+    };
+    match info {
+        Some(info) => {
+            kprintln!("{}", info);
+            kprintln!("Rotation: {} degrees", rotation.degrees());
+        }
+        None => kprintln!("No usable framebuffer, running in serial-only mode"),
+    }
+}
@@ -0,0 +1,45 @@
+use crate::framebuffer::font::{find, fonts};
+use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::kprintln;
+
+pub fn font(args: &[&str]) {
+    let current = unsafe { FRAMEBUFFER_GUARD.lock().assume_init_ref().font() };
+    let current = match current {
+        Some(current) => current,
+        None => {
+            kprintln!("No usable framebuffer, running in serial-only mode");
+            return;
+        }
+    };
+    let name = match args.first() {
+        Some(name) => *name,
+        None => {
+            for font in fonts() {
+                let marker = if font.name() == current.name() {
+                    '*'
+                } else {
+                    ' '
+                };
+                kprintln!(
+                    "{} {:<16} {}x{}",
+                    marker,
+                    font.name(),
+                    font.width(),
+                    font.height()
+                );
+            }
+            return;
+        }
+    };
+    let font = match find(name) {
+        Some(font) => font,
+        None => {
+            kprintln!("font: No font named {}", name);
+            return;
+        }
+    };
+    let result = unsafe { FRAMEBUFFER_GUARD.lock().assume_init_mut().set_font(&font) };
+    if let Err(e) = result {
+        kprintln!("font: Cannot switch to {}: {}", name, e);
+    }
+}
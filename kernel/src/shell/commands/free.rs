@@ -0,0 +1,69 @@
+use alloc::string::ToString;
+
+use crate::heap::segregated::SEGREGATED_HEAP;
+use crate::kprintln;
+use crate::math::ByteSize;
+use crate::syscall::sysinfo::sysinfo;
+
+pub fn free(args: &[&str]) {
+    match args {
+        [] => print_memory(),
+        ["--heap"] => print_heap(),
+        _ => kprintln!("Usage: free [--heap]"),
+    }
+}
+
+fn print_memory() {
+    let info = sysinfo();
+    kprintln!("{:<6} {:>14} {:>14} {:>14}", "", "TOTAL", "USED", "FREE");
+    kprintln!(
+        "{:<6} {:>14} {:>14} {:>14}",
+        "Mem:",
+        ByteSize(info.total_ram).to_string(),
+        ByteSize(info.total_ram - info.free_ram).to_string(),
+        ByteSize(info.free_ram).to_string()
+    );
+    kprintln!(
+        "Page size {}, {} tasks, up {} s",
+        info.page_size,
+        info.procs,
+        info.uptime_secs
+    );
+}
+
+/// Prints the size classes of the heap
+fn print_heap() {
+    let (stats, large_pages, footprint, peak) = {
+        let heap = SEGREGATED_HEAP.lock();
+        (
+            heap.stats(),
+            heap.large_pages(),
+            heap.footprint(),
+            heap.peak_footprint(),
+        )
+    };
+    kprintln!(
+        "{:>6} {:>6} {:>8} {:>10} {:>10}",
+        "CLASS",
+        "PAGES",
+        "IN USE",
+        "ALLOCS",
+        "FREES"
+    );
+    for class in stats.iter().filter(|class| class.allocations > 0) {
+        kprintln!(
+            "{:>6} {:>6} {:>8} {:>10} {:>10}",
+            class.size,
+            class.pages,
+            class.in_use,
+            class.allocations,
+            class.frees
+        );
+    }
+    kprintln!(
+        "{} in large allocations, {} in total, at most {}",
+        ByteSize(large_pages as u64 * bks::PAGE_SIZE),
+        ByteSize(footprint as u64),
+        ByteSize(peak as u64)
+    );
+}
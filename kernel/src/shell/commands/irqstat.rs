@@ -0,0 +1,35 @@
+use crate::arch::interrupts::exceptions::IDTException;
+use crate::arch::interrupts::{handler_name, VECTORS};
+use crate::stats::{IRQ_COUNT, IRQ_MAX_CYCLES};
+use crate::{irq, kprintln};
+
+pub fn irqstat(_: &[&str]) {
+    kprintln!(
+        "{:<6} {:<26} {:<16} {:>12} {:>14}",
+        "VECTOR",
+        "NAME",
+        "HANDLER",
+        "COUNT",
+        "MAX (CYCLES)"
+    );
+    for vector in 0..VECTORS {
+        let name = handler_name(vector);
+        let count = IRQ_COUNT.get(vector);
+        if name.is_none() && count == 0 {
+            continue;
+        }
+        let exception = IDTException::try_from_value(vector);
+        kprintln!(
+            "{:<#6x} {:<26} {:<16} {:>12} {:>14}",
+            vector,
+            match exception {
+                Some(exception) => IDTException::name(&exception),
+                None if irq::is_allocated(vector) => "dynamic",
+                None => "-",
+            },
+            name.unwrap_or("-"),
+            count,
+            IRQ_MAX_CYCLES.get(vector)
+        );
+    }
+}
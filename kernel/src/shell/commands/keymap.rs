@@ -0,0 +1,21 @@
+use keyboard_layout::KEYBOARD_LAYOUTS;
+
+use crate::drivers::input::ps2_keyboard::{layout, set_layout};
+use crate::kprintln;
+
+pub fn keymap(args: &[&str]) {
+    let name = match args.first() {
+        Some(name) => *name,
+        None => {
+            let current = layout().name;
+            for layout in KEYBOARD_LAYOUTS {
+                let marker = if layout.name == current { '*' } else { ' ' };
+                kprintln!("{} {:<4} {}", marker, layout.name, layout.description);
+            }
+            return;
+        }
+    };
+    if let Err(e) = set_layout(name) {
+        kprintln!("keymap: Cannot switch to {}: {}", name, e);
+    }
+}
@@ -0,0 +1,63 @@
+use alloc::string::ToString;
+
+use crate::{kprintln, lockstat};
+
+/// The number of locks printed without a count
+const DEFAULT_COUNT: usize = 10;
+
+pub fn lockstat(args: &[&str]) {
+    if !lockstat::ENABLED {
+        kprintln!("lockstat: The kernel was built without the lockstat feature");
+        return;
+    }
+    let count = match args {
+        [] => DEFAULT_COUNT,
+        ["--reset"] => {
+            lockstat::clear();
+            kprintln!("lockstat: Reset the statistics");
+            return;
+        }
+        [count] => match count.parse() {
+            Ok(count) => count,
+            Err(_) => {
+                kprintln!("Usage: lockstat [count] [--reset]");
+                return;
+            }
+        },
+        _ => {
+            kprintln!("Usage: lockstat [count] [--reset]");
+            return;
+        }
+    };
+    let mut classes = lockstat::classes();
+    classes.retain(|(_, _, stats)| stats.contended > 0);
+    if classes.is_empty() {
+        kprintln!("lockstat: No lock was contended");
+        return;
+    }
+    classes.sort_unstable_by_key(|(_, _, stats)| core::cmp::Reverse(stats.wait_cycles));
+    kprintln!(
+        "{:<40} {:>12} {:>12} {:>6} {:>16} {:>14}",
+        "LOCK",
+        "ACQUIRED",
+        "CONTENDED",
+        "%",
+        "WAITED (CYCLES)",
+        "MAX (CYCLES)"
+    );
+    for (_, key, stats) in classes.iter().take(count) {
+        let name = match key {
+            Some(key) => key.to_string(),
+            None => "(other)".to_string(),
+        };
+        kprintln!(
+            "{:<40} {:>12} {:>12} {:>6} {:>16} {:>14}",
+            name,
+            stats.acquisitions,
+            stats.contended,
+            stats.contended * 100 / stats.acquisitions.max(1),
+            stats.wait_cycles,
+            stats.max_wait
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::acpi::tables::{self, checksum_ok};
+use crate::acpi::SDTHeader;
+use crate::kprintln;
+use crate::math::hexdump;
+use crate::shell::pager::Pager;
+
+pub fn lsacpi(args: &[&str]) {
+    match args {
+        [] => list(),
+        ["dump", signature] => dump(signature, "0"),
+        ["dump", signature, index] => dump(signature, index),
+        _ => kprintln!("Usage: lsacpi [dump <signature> [index]]"),
+    }
+}
+
+fn list() {
+    match tables::platform() {
+        Some(platform) => kprintln!(
+            "RSDP revision {}, OEM {}, {} at {:#x}",
+            platform.revision,
+            platform.oem_id(),
+            if platform.has_xsdt { "XSDT" } else { "RSDT" },
+            platform.root
+        ),
+        None => {
+            kprintln!("lsacpi: No ACPI tables");
+            return;
+        }
+    }
+    kprintln!(
+        "{:<4} {:<6} {:>3} {:>8} {:>18} {}",
+        "SIG",
+        "OEM",
+        "REV",
+        "LENGTH",
+        "ADDRESS",
+        "CHECKSUM"
+    );
+    for table in tables::tables() {
+        // The checksum is recomputed, the firmware or a stray write may have changed the table
+        let header = match table.map::<SDTHeader>() {
+            Ok(header) => header,
+            Err(e) => {
+                kprintln!("{:<4} cannot be mapped: {}", table.name(), e);
+                continue;
+            }
+        };
+        kprintln!(
+            "{:<4} {:<6} {:>3} {:>8} {:>#18x} {}",
+            table.name(),
+            core::str::from_utf8(&header.oem_id).unwrap_or("??????"),
+            header.revision,
+            table.len,
+            table.phys.as_u64(),
+            if checksum_ok(header.bytes()) {
+                "ok"
+            } else {
+                "bad"
+            }
+        );
+    }
+}
+
+fn dump(signature: &str, index: &str) {
+    let index = match index.parse() {
+        Ok(index) => index,
+        Err(_) => {
+            kprintln!("lsacpi: Invalid index {}", index);
+            return;
+        }
+    };
+    let table = match tables::nth(signature, index) {
+        Some(table) => table,
+        None => {
+            kprintln!("lsacpi: There is no {} {}", signature, index);
+            return;
+        }
+    };
+    let bytes = match tables::raw_bytes(signature, index) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            kprintln!("lsacpi: Cannot read {} {}: {}", signature, index, e);
+            return;
+        }
+    };
+    // Labelled with physical addresses, as the tables are listed
+    let mut pager = Pager::new();
+    hexdump(table.phys.as_u64(), bytes, |row| {
+        pager.line(format_args!("{}", row))
+    });
+}
@@ -0,0 +1,12 @@
+use crate::{block, kprintln};
+
+pub fn lsblk(_: &[&str]) {
+    kprintln!("{:<12} {:>12} {}", "NAME", "SIZE (KiB)", "TYPE");
+    for entry in block::devices() {
+        let size = entry.device.size() / 1024;
+        match entry.partition {
+            Some(ty) => kprintln!("{:<12} {:>12} {}", entry.name, size, ty),
+            None => kprintln!("{:<12} {:>12} disk", entry.name, size),
+        }
+    }
+}
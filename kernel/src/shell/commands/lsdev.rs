@@ -0,0 +1,34 @@
+use alloc::string::{String, ToString};
+
+use crate::device::tree;
+use crate::kprintln;
+
+/// How far every level of the tree is indented
+const INDENT: usize = 2;
+
+pub fn lsdev(_: &[&str]) {
+    kprintln!(
+        "{:<24} {:<8} {:<14} {}",
+        "NAME",
+        "CLASS",
+        "DRIVER",
+        "RESOURCES"
+    );
+    tree::walk(|depth, _, node| {
+        let name = [" ".repeat(depth * INDENT), node.name.clone()].concat();
+        let mut resources = String::new();
+        for resource in &node.resources {
+            if !resources.is_empty() {
+                resources.push_str(", ");
+            }
+            resources.push_str(&resource.to_string());
+        }
+        kprintln!(
+            "{:<24} {:<8} {:<14} {}",
+            name,
+            node.class.name(),
+            node.driver,
+            resources
+        );
+    });
+}
@@ -0,0 +1,60 @@
+use pci_lookup::{get_device_name, get_subclass_name, get_vendor_name};
+
+use crate::kprintln;
+use crate::pci::{self, PciDevice};
+
+pub fn lspci(args: &[&str]) {
+    let verbose = match args {
+        [] => false,
+        ["-v"] => true,
+        _ => {
+            kprintln!("Usage: lspci [-v]");
+            return;
+        }
+    };
+    for device in pci::devices() {
+        kprintln!(
+            "{} {} [{:04x}:{:04x}]: {} {} ({})",
+            device.location(),
+            get_subclass_name(device.class, device.subclass),
+            device.vendor_id,
+            device.device_id,
+            get_vendor_name(device.vendor_id),
+            get_device_name(device.vendor_id, device.device_id),
+            pci::driver_of(&device).unwrap_or("no driver")
+        );
+        if verbose {
+            print_details(&device);
+        }
+    }
+}
+
+fn print_details(device: &PciDevice) {
+    for (id, offset) in device.capabilities() {
+        kprintln!(
+            "    Capability [{:03x}] {}",
+            offset,
+            pci::capability_name(id)
+        );
+    }
+    for capability in device.extended_capabilities() {
+        kprintln!(
+            "    Extended capability [{:03x}] {} v{}",
+            capability.offset,
+            pci::extended_capability_name(capability.id),
+            capability.version
+        );
+    }
+    if let Some(link) = device.link_status() {
+        let degraded = if link.is_degraded() { ", degraded" } else { "" };
+        kprintln!("    Link: {}{}", link, degraded);
+    }
+    if let Some(aer) = device.aer_status() {
+        kprintln!(
+            "    AER: uncorrectable {:#010x} (fatal {:#010x}), correctable {:#010x}",
+            aer.uncorrectable,
+            aer.fatal(),
+            aer.correctable
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use crate::kprintln;
+use crate::scheduler::{self, class, idle, TaskState};
+use crate::smp;
+
+pub fn lstask(_: &[&str]) {
+    kprintln!(
+        "{:>5} {:<16} {:<8} {:<7} {:>4} {:>3} {:>18} {:>16}",
+        "ID",
+        "NAME",
+        "STATE",
+        "PRIO",
+        "NICE",
+        "CPU",
+        "AFFINITY",
+        "RUNTIME (CYCLES)"
+    );
+    for task in scheduler::task_infos() {
+        let state = match task.state {
+            TaskState::Ready => "ready",
+            TaskState::Running => "running",
+            TaskState::Blocked => "blocked",
+            TaskState::Exited => "exited",
+        };
+        // An inherited priority is marked, see `scheduler::sync::Mutex`
+        let inherited = if task.priority != task.base_priority {
+            "*"
+        } else {
+            ""
+        };
+        kprintln!(
+            "{:>5} {:<16} {:<8} {:<6}{:<1} {:>4} {:>3} {:>#18x} {:>16}",
+            task.id.inner(),
+            task.name,
+            state,
+            task.priority.name(),
+            inherited,
+            task.nice,
+            task.cpu,
+            task.affinity.bits(),
+            task.time.total()
+        );
+    }
+
+    kprintln!();
+    kprintln!(
+        "{:>3} {:>8} {:>8} {:>12} {:>12} {:>12}   idle={} sched={}",
+        "CPU",
+        "IDLE",
+        "BUSY",
+        "POLLS",
+        "HALTS",
+        "MWAITS",
+        idle::mode().name(),
+        class::class().name()
+    );
+    for cpu in smp::online_cpus() {
+        let stats = idle::stats(cpu);
+        let permille = stats.idle_permille();
+        kprintln!(
+            "{:>3} {:>5}.{}% {:>5}.{}% {:>12} {:>12} {:>12}",
+            cpu,
+            permille / 10,
+            permille % 10,
+            (1000 - permille) / 10,
+            (1000 - permille) % 10,
+            stats.polls,
+            stats.halts,
+            stats.mwaits
+        );
+    }
+}
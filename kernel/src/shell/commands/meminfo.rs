@@ -0,0 +1,18 @@
+use alloc::string::ToString;
+
+use crate::heap::tag;
+use crate::kprintln;
+use crate::math::ByteSize;
+
+pub fn meminfo(_: &[&str]) {
+    kprintln!("{:<16} {:>14} {:>10}", "TAG", "BYTES", "ALLOCS");
+    for usage in tag::usages().iter().filter(|usage| usage.count > 0) {
+        kprintln!(
+            "{:<16} {:>14} {:>10}",
+            usage.name,
+            ByteSize(usage.bytes).to_string(),
+            usage.count
+        );
+    }
+    kprintln!("{} of heap in use", ByteSize(tag::total()));
+}
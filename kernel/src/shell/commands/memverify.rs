@@ -0,0 +1,69 @@
+use crate::block::cache;
+use crate::kprintln;
+use crate::memory::frame_info::{self, MAX_REPORTED};
+use crate::memory::vmm::ADDRESS_SPACE;
+
+pub fn memverify(args: &[&str]) {
+    if !args.is_empty() {
+        kprintln!("Usage: memverify");
+        return;
+    }
+    // Read before `verify()` rather than during it, faults lock the allocator with the
+    // address space held
+    let zero_pages = ADDRESS_SPACE.lock().stats().zero;
+    let cached = cache::pages();
+    let report = frame_info::verify();
+    kprintln!(
+        "{} frames: {} free, {} reserved, {} slab, {} page cache",
+        report.frames,
+        report.free,
+        report.reserved,
+        report.slab,
+        report.page_cache
+    );
+
+    let mut problems = report.mismatches;
+    for (frame, problem) in report.reported.iter() {
+        kprintln!("memverify: {}: {}", frame.start(), problem);
+    }
+    if report.mismatches > MAX_REPORTED {
+        kprintln!(
+            "memverify: {} more mismatches",
+            report.mismatches - MAX_REPORTED
+        );
+    }
+    if report.page_cache != cached {
+        kprintln!(
+            "memverify: {} frames are marked PAGE_CACHE, the page cache holds {} pages",
+            report.page_cache,
+            cached
+        );
+        problems += 1;
+    }
+    // The zero frame has a reference of its own and one for every page mapping it
+    let expected_refs = match report.zero {
+        0 => 0,
+        _ => zero_pages + 1,
+    };
+    if report.zero > 1 || (report.zero == 0 && zero_pages > 0) {
+        kprintln!(
+            "memverify: {} frames are marked ZERO for {} zero pages",
+            report.zero,
+            zero_pages
+        );
+        problems += 1;
+    } else if report.zero_refs as u64 != expected_refs {
+        kprintln!(
+            "memverify: The zero frame has {} references, expected {}",
+            report.zero_refs,
+            expected_refs
+        );
+        problems += 1;
+    }
+
+    if problems == 0 {
+        kprintln!("memverify: Everything matches");
+    } else {
+        kprintln!("memverify: {} problems", problems);
+    }
+}
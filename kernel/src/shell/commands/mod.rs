@@ -0,0 +1,220 @@
+use super::Command;
+use crate::kprintln;
+
+pub mod bench;
+pub mod bind;
+pub mod dmesg;
+pub mod exit;
+pub mod fbinfo;
+pub mod font;
+pub mod free;
+pub mod irqstat;
+pub mod keymap;
+pub mod lockstat;
+pub mod lsacpi;
+pub mod lsblk;
+pub mod lsdev;
+pub mod lspci;
+pub mod lstask;
+pub mod meminfo;
+pub mod memverify;
+pub mod mount;
+pub mod peekphys;
+pub mod pokephys;
+pub mod poweroff;
+pub mod profile;
+pub mod ptdump;
+pub mod ptwalk;
+pub mod reboot;
+pub mod serial;
+pub mod stat;
+pub mod strace;
+pub mod top;
+pub mod tracedump;
+pub mod unbind;
+pub mod version;
+pub mod vmstat;
+
+/// All commands known to the shell
+pub static COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "Lists all commands",
+        func: help,
+    },
+    Command {
+        name: "bench",
+        help: "bench sched [handoffs] | mem | all | <name> - Measures context switches, system calls, mmap() and memcpy",
+        func: bench::bench,
+    },
+    Command {
+        name: "bind",
+        help: "bind <bus:slot.function> <driver> - Binds a PCI function without a driver",
+        func: bind::bind,
+    },
+    Command {
+        name: "dmesg",
+        help: "dmesg [--sinks|--previous] - Prints the kernel log, its sinks or the previous boot's log",
+        func: dmesg::dmesg,
+    },
+    Command {
+        name: "exit",
+        help: "Leaves the shell and gives the console to programs in canonical mode",
+        func: exit::exit,
+    },
+    Command {
+        name: "fbinfo",
+        help: "Prints the geometry of the framebuffer",
+        func: fbinfo::fbinfo,
+    },
+    Command {
+        name: "font",
+        help: "font [name] - Lists the console fonts or switches to one",
+        func: font::font,
+    },
+    Command {
+        name: "free",
+        help: "free [--heap] - Prints how much memory is in use and free, or the heap classes",
+        func: free::free,
+    },
+    Command {
+        name: "irqstat",
+        help: "Prints the count and the slowest run of every interrupt handler",
+        func: irqstat::irqstat,
+    },
+    Command {
+        name: "keymap",
+        help: "keymap [name] - Lists the keyboard layouts or switches to one",
+        func: keymap::keymap,
+    },
+    Command {
+        name: "lockstat",
+        help: "lockstat [count] [--reset] - Prints the most contended locks, by the cycles waited for them",
+        func: lockstat::lockstat,
+    },
+    Command {
+        name: "lsacpi",
+        help: "lsacpi [dump <signature> [index]] - Lists the ACPI tables or dumps one of them",
+        func: lsacpi::lsacpi,
+    },
+    Command {
+        name: "lsblk",
+        help: "Lists all block devices and their partitions",
+        func: lsblk::lsblk,
+    },
+    Command {
+        name: "lsdev",
+        help: "Prints the device tree with the resources of every device",
+        func: lsdev::lsdev,
+    },
+    Command {
+        name: "lspci",
+        help: "lspci [-v] - Lists the PCI functions, with -v their capabilities, PCIe link and AER status",
+        func: lspci::lspci,
+    },
+    Command {
+        name: "lstask",
+        help: "Lists all tasks with their state, CPU and runtime",
+        func: lstask::lstask,
+    },
+    Command {
+        name: "meminfo",
+        help: "Prints the heap usage of every allocation tag, the largest first",
+        func: meminfo::meminfo,
+    },
+    Command {
+        name: "memverify",
+        help: "Checks the metadata of every frame against the frame allocator and its users",
+        func: memverify::memverify,
+    },
+    Command {
+        name: "mount",
+        help: "Lists the mounted filesystems and how many open files use each",
+        func: mount::mount,
+    },
+    Command {
+        name: "peekphys",
+        help: "peekphys <address> [length] - Dumps physical memory as 32 bit words",
+        func: peekphys::peekphys,
+    },
+    Command {
+        name: "pokephys",
+        help: "pokephys <address> <value> [width] [--yes] - Writes to physical memory",
+        func: pokephys::pokephys,
+    },
+    Command {
+        name: "poweroff",
+        help: "Flushes the log, shuts down the devices and turns the machine off",
+        func: poweroff::poweroff,
+    },
+    Command {
+        name: "profile",
+        help: "profile [start|stop|report] - Samples where the kernel spends its time",
+        func: profile::profile,
+    },
+    Command {
+        name: "ptdump",
+        help: "ptdump [--pml4 <address>] [<start> <end>] - Prints the mappings, a screen at a time",
+        func: ptdump::ptdump,
+    },
+    Command {
+        name: "ptwalk",
+        help: "ptwalk [--pml4 <address>] <address> - Prints the entries that translate an address",
+        func: ptwalk::ptwalk,
+    },
+    Command {
+        name: "reboot",
+        help: "Flushes the log, shuts down the devices and restarts the machine",
+        func: reboot::reboot,
+    },
+    Command {
+        name: "serial",
+        help: "serial [port baud] - Lists the serial ports or changes the baud rate of one",
+        func: serial::serial,
+    },
+    Command {
+        name: "stat",
+        help: "stat [prefix] - Prints all non-zero statistics counters",
+        func: stat::stat,
+    },
+    Command {
+        name: "strace",
+        help: "strace <id> [on|off] - Logs the system calls of a task at debug level",
+        func: strace::strace,
+    },
+    Command {
+        name: "top",
+        help: "top [--once] - Shows the CPU usage of every task each second until a key is pressed",
+        func: top::top,
+    },
+    Command {
+        name: "tracedump",
+        help: "Writes the event trace to COM1 as hex, for scripts/tracedecode.py",
+        func: tracedump::tracedump,
+    },
+    Command {
+        name: "unbind",
+        help: "unbind <bus:slot.function> - Detaches the driver of a PCI function",
+        func: unbind::unbind,
+    },
+    Command {
+        name: "version",
+        help: "version [-v] - Prints the build, or also which subsystems are built in and came up",
+        func: version::version,
+    },
+    Command {
+        name: "vmstat",
+        help: "vmstat <id> - Prints the page counts of the address space of a task",
+        func: vmstat::vmstat,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+fn help(_: &[&str]) {
+    for command in COMMANDS {
+        kprintln!("{:<12} {}", command.name, command.help);
+    }
+}
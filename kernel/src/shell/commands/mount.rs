@@ -0,0 +1,8 @@
+use crate::{fs, kprintln};
+
+pub fn mount(_: &[&str]) {
+    kprintln!("{:<24} {:<8} {}", "MOUNT POINT", "FS", "USERS");
+    for mount in fs::mounts() {
+        kprintln!("{:<24} {:<8} {}", mount.path, mount.fs, mount.users);
+    }
+}
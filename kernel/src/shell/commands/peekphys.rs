@@ -0,0 +1,68 @@
+use crate::math::parse_u64;
+use crate::memory::memaccess::with_phys_mapping;
+use crate::memory::PhysicalAddress;
+use crate::{kprint, kprintln};
+
+/// The bytes dumped unless a length is given
+const DEFAULT_LEN: u64 = 64;
+/// The most bytes dumped at once
+pub const MAX_LEN: u64 = 4096;
+/// Memory is read in words of this size, device registers are rarely narrower
+const WORD: usize = 4;
+const WORDS_PER_LINE: usize = 4;
+
+pub fn peekphys(args: &[&str]) {
+    let (address, len) = match args {
+        [address] => (parse_u64(address), Some(DEFAULT_LEN)),
+        [address, len] => (parse_u64(address), parse_u64(len)),
+        _ => {
+            kprintln!("Usage: peekphys <address> [length]");
+            return;
+        }
+    };
+    let (address, len) = match (address, len) {
+        (Some(address), Some(len)) if len > 0 && len <= MAX_LEN => (address, len),
+        _ => {
+            kprintln!(
+                "peekphys: Invalid address or length, at most {} bytes",
+                MAX_LEN
+            );
+            return;
+        }
+    };
+    // Whole words around the bytes asked for
+    let start = address & !(WORD as u64 - 1);
+    let end = match address.checked_add(len + WORD as u64 - 1) {
+        Some(end) => end & !(WORD as u64 - 1),
+        None => {
+            kprintln!(
+                "peekphys: {:#x} is beyond the physical address width",
+                address
+            );
+            return;
+        }
+    };
+    let phys = match PhysicalAddress::try_new(start) {
+        Ok(phys) => phys,
+        Err(_) => {
+            kprintln!(
+                "peekphys: {:#x} is beyond the physical address width",
+                start
+            );
+            return;
+        }
+    };
+    let result = with_phys_mapping(phys, end - start, |bytes| {
+        let words = bytes.as_ptr() as *const u32;
+        for first in (0..bytes.len() / WORD).step_by(WORDS_PER_LINE) {
+            kprint!("{:#018x}:", start + (first * WORD) as u64);
+            for idx in first..(first + WORDS_PER_LINE).min(bytes.len() / WORD) {
+                kprint!(" {:08x}", unsafe { words.add(idx).read_volatile() });
+            }
+            kprintln!();
+        }
+    });
+    if let Err(e) = result {
+        kprintln!("peekphys: Cannot map {:#x}: {}", start, e);
+    }
+}
@@ -0,0 +1,68 @@
+use alloc::vec::Vec;
+
+use crate::kprintln;
+use crate::math::parse_u64;
+use crate::memory::memaccess::with_phys_mapping_mut;
+use crate::memory::PhysicalAddress;
+
+/// The flag that confirms the write, without it the command only says what it would write
+pub const CONFIRM: &str = "--yes";
+/// The width of the write in bytes unless one is given
+const DEFAULT_WIDTH: u64 = 4;
+
+pub fn pokephys(args: &[&str]) {
+    let confirmed = args.contains(&CONFIRM);
+    let args: Vec<&str> = args.iter().copied().filter(|arg| *arg != CONFIRM).collect();
+    let (address, value, width) = match args.as_slice() {
+        [address, value] => (parse_u64(address), parse_u64(value), Some(DEFAULT_WIDTH)),
+        [address, value, width] => (parse_u64(address), parse_u64(value), parse_u64(width)),
+        _ => {
+            kprintln!("Usage: pokephys <address> <value> [1|2|4|8] [{}]", CONFIRM);
+            return;
+        }
+    };
+    let (address, value, width) = match (address, value, width) {
+        (Some(address), Some(value), Some(width @ (1 | 2 | 4 | 8)))
+            if address % width == 0 && (width == 8 || value >> (width * 8) == 0) =>
+        {
+            (address, value, width)
+        }
+        _ => {
+            kprintln!("pokephys: The value has to fit the width, and the address be aligned to it");
+            return;
+        }
+    };
+    let phys = match PhysicalAddress::try_new(address) {
+        Ok(phys) => phys,
+        Err(_) => {
+            kprintln!(
+                "pokephys: {:#x} is beyond the physical address width",
+                address
+            );
+            return;
+        }
+    };
+    if !confirmed {
+        kprintln!(
+            "pokephys: Would write {:#x} to {:#x} ({} bytes), add {} to write it",
+            value,
+            address,
+            width,
+            CONFIRM
+        );
+        return;
+    }
+    let result = with_phys_mapping_mut(phys, width, |bytes| unsafe {
+        let ptr = bytes.as_mut_ptr();
+        match width {
+            1 => ptr.write_volatile(value as u8),
+            2 => (ptr as *mut u16).write_volatile(value as u16),
+            4 => (ptr as *mut u32).write_volatile(value as u32),
+            _ => (ptr as *mut u64).write_volatile(value),
+        }
+    });
+    match result {
+        Ok(()) => kprintln!("pokephys: Wrote {:#x} to {:#x}", value, address),
+        Err(e) => kprintln!("pokephys: Cannot map {:#x}: {}", address, e),
+    }
+}
@@ -0,0 +1,13 @@
+use crate::kprintln;
+use crate::power;
+use crate::syscall::{self, RebootCommand, REBOOT_MAGIC1, REBOOT_MAGIC2};
+
+pub fn poweroff(_: &[&str]) {
+    if !power::can_power_off() {
+        kprintln!("poweroff: The firmware does not say how to turn the machine off");
+        return;
+    }
+    if let Err(e) = syscall::sys_reboot(REBOOT_MAGIC1, REBOOT_MAGIC2, RebootCommand::PowerOff) {
+        kprintln!("poweroff: {}", e);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::kprintln;
+use crate::profile::{self, TOP_FUNCTIONS};
+
+pub fn profile(args: &[&str]) {
+    match args {
+        [] => kprintln!(
+            "profile: {}",
+            if profile::is_running() {
+                "Running"
+            } else {
+                "Stopped"
+            }
+        ),
+        ["start"] => {
+            profile::start();
+            kprintln!("profile: Started");
+        }
+        ["stop"] => {
+            if profile::stop() {
+                kprintln!("profile: Stopped");
+            } else {
+                kprintln!("profile: Not running");
+            }
+        }
+        ["report"] => report(),
+        _ => kprintln!("Usage: profile [start|stop|report]"),
+    }
+}
+
+fn report() {
+    let report = profile::report();
+    let total = report.total_samples();
+    if total == 0 {
+        kprintln!("profile: No samples, start the profiler with `profile start`");
+        return;
+    }
+    kprintln!(
+        "profile: {} samples in {} us, {} in user space, {} dropped",
+        total,
+        report.elapsed_us,
+        report.user_samples,
+        report.dropped_samples
+    );
+    kprintln!(
+        "{:>8} {:>7} {:>12} {}",
+        "SAMPLES",
+        "PERCENT",
+        "TIME (US)",
+        "FUNCTION"
+    );
+    for function in report.functions.iter().take(TOP_FUNCTIONS) {
+        let permille = function.samples * 1000 / total;
+        kprintln!(
+            "{:>8} {:>5}.{}% {:>12} {}",
+            function.samples,
+            permille / 10,
+            permille % 10,
+            function.samples * report.period_us,
+            function.name
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::ops::Range;
+
+use crate::kprintln;
+use crate::math::{parse_u64, ByteSize};
+use crate::memory::paging::page_table_manager::PageTableFlag;
+use crate::memory::paging::{self, Mapping};
+use crate::memory::PhysicalAddress;
+use crate::shell::pager::Pager;
+
+/// The option that selects the tables to look at instead of the active ones
+pub const PML4_OPTION: &str = "--pml4";
+
+pub fn ptdump(args: &[&str]) {
+    let (pml4, args) = match split_pml4(args) {
+        Some(split) => split,
+        None => {
+            kprintln!("Usage: ptdump [--pml4 <address>] [<start> <end>]");
+            return;
+        }
+    };
+    let range = match args {
+        [] => 0..u64::MAX,
+        [start, end] => match (parse_u64(start), parse_u64(end)) {
+            (Some(start), Some(end)) if start < end => start..end,
+            _ => {
+                kprintln!("ptdump: Invalid range");
+                return;
+            }
+        },
+        _ => {
+            kprintln!("Usage: ptdump [--pml4 <address>] [<start> <end>]");
+            return;
+        }
+    };
+    dump(pml4, range);
+}
+
+fn dump(pml4: u64, range: Range<u64>) {
+    let mut pager = Pager::new();
+    pager.line(format_args!(
+        "{:<37} {:<37} {:>10} {}",
+        "VIRTUAL", "PHYSICAL", "SIZE", "FLAGS"
+    ));
+    let mut mappings = 0;
+    let mut mapped = 0;
+    paging::dump(pml4, range, |mapping| {
+        mappings += 1;
+        mapped += mapping.len;
+        let phys = match mapping.phys {
+            Some(phys) => format!("{:#018x}-{:#018x}", phys, phys + mapping.len),
+            None => String::from("non-contiguous"),
+        };
+        pager.line(format_args!(
+            "{:#018x}-{:#018x} {:<37} {:>10} {}",
+            mapping.start,
+            mapping.end(),
+            phys,
+            ByteSize(mapping.len).to_string(),
+            flags(mapping)
+        ));
+    });
+    pager.line(format_args!(
+        "{} mappings, {} mapped",
+        mappings,
+        ByteSize(mapped)
+    ));
+}
+
+/// # Split PML4
+/// Takes `--pml4 <address>` off the front of `args`
+///
+/// ## Returns
+/// - (u64, &[&str]) = The PML4, the active one without the option, and the other arguments
+/// - None = The address is invalid
+pub fn split_pml4<'a>(args: &'a [&'a str]) -> Option<(u64, &'a [&'a str])> {
+    match args {
+        [option, address, rest @ ..] if *option == PML4_OPTION => {
+            let address = parse_u64(address)?;
+            // The tables are read through the direct map, so they have to be in it
+            PhysicalAddress::try_new(address).ok()?;
+            (address % 0x1000 == 0).then(|| (address, rest))
+        }
+        _ => Some((paging::active_pml4(), args)),
+    }
+}
+
+/// The flags of a mapping, e.g. `RW- S G -- PAT0`
+fn flags(mapping: &Mapping) -> String {
+    let flag = |flag: PageTableFlag, set: &'static str, unset: &'static str| {
+        if mapping.flags.contains(flag) {
+            set
+        } else {
+            unset
+        }
+    };
+    format!(
+        "R{}{} {} {} {} PAT{}",
+        flag(PageTableFlag::READ_WRITE, "W", "-"),
+        flag(PageTableFlag::NO_EXECUTE, "-", "X"),
+        flag(PageTableFlag::USER_ACCESSIBLE, "U", "S"),
+        flag(PageTableFlag::GLOBAL, "G", "-"),
+        if mapping.pat_index & 0b010 != 0 {
+            "NC"
+        } else {
+            "--"
+        },
+        mapping.pat_index
+    )
+}
@@ -0,0 +1,84 @@
+use alloc::string::String;
+
+use super::ptdump::split_pml4;
+use crate::kprintln;
+use crate::math::parse_u64;
+use crate::memory::paging::page_table_manager::{PageTableFlag, LEVEL_NAMES};
+use crate::memory::paging::{self, WalkStep};
+
+/// The names of the bits of an entry, from bit 0 on
+const FLAG_NAMES: [(PageTableFlag, &str); 10] = [
+    (PageTableFlag::PRESENT, "P"),
+    (PageTableFlag::READ_WRITE, "W"),
+    (PageTableFlag::USER_ACCESSIBLE, "U"),
+    (PageTableFlag::WRITE_THROUGH, "PWT"),
+    (PageTableFlag::NO_CACHE, "PCD"),
+    (PageTableFlag::ACCESSED, "A"),
+    (PageTableFlag::DIRTY, "D"),
+    (PageTableFlag::LARGE_PAGE, "PS"),
+    (PageTableFlag::GLOBAL, "G"),
+    (PageTableFlag::NO_EXECUTE, "NX"),
+];
+
+pub fn ptwalk(args: &[&str]) {
+    let (pml4, address) = match split_pml4(args) {
+        Some((pml4, [address])) => (pml4, parse_u64(address)),
+        _ => {
+            kprintln!("Usage: ptwalk [--pml4 <address>] <address>");
+            return;
+        }
+    };
+    let address = match address {
+        Some(address) => address,
+        None => {
+            kprintln!("ptwalk: Invalid address");
+            return;
+        }
+    };
+    kprintln!("PML4 at {:#x}", pml4);
+    let mut last = None;
+    let phys = paging::walk(pml4, address, |step| {
+        kprintln!(
+            "{:<4}[{:>3}] at {:#x}: {:#018x} {}",
+            LEVEL_NAMES[step.level],
+            step.index,
+            step.table + step.index as u64 * 8,
+            step.entry.bits(),
+            flags(step)
+        );
+        last = Some(*step);
+    });
+    match (phys, last) {
+        (Some(phys), _) => kprintln!("{:#x} -> {:#x}", address, phys),
+        (None, Some(step)) => kprintln!(
+            "{:#x}: Not present in the {}, the translation fails here",
+            address,
+            LEVEL_NAMES[step.level]
+        ),
+        (None, None) => kprintln!(
+            "{:#x}: Not canonical, the translation fails before the PML4",
+            address
+        ),
+    }
+}
+
+/// The names of the flags of the entry of `step`. Bit 7 is `PAT` in the entry of a 4 KiB page.
+fn flags(step: &WalkStep) -> String {
+    let flags = step.entry.flags();
+    let mut names = String::new();
+    for (flag, name) in FLAG_NAMES {
+        if !flags.contains(flag) {
+            continue;
+        }
+        if !names.is_empty() {
+            names.push(' ');
+        }
+        let large_page = flag == PageTableFlag::LARGE_PAGE;
+        names.push_str(if large_page && step.level == 3 {
+            "PAT"
+        } else {
+            name
+        });
+    }
+    names
+}
@@ -0,0 +1,8 @@
+use crate::kprintln;
+use crate::syscall::{self, RebootCommand, REBOOT_MAGIC1, REBOOT_MAGIC2};
+
+pub fn reboot(_: &[&str]) {
+    if let Err(e) = syscall::sys_reboot(REBOOT_MAGIC1, REBOOT_MAGIC2, RebootCommand::Restart) {
+        kprintln!("reboot: {}", e);
+    }
+}
@@ -0,0 +1,52 @@
+use crate::drivers::serial::{find_port, ports};
+use crate::klog::{self, LogSink};
+use crate::kprintln;
+
+pub fn serial(args: &[&str]) {
+    let (name, baud) = match args {
+        [] => {
+            kprintln!("{:<6} {:<8} {:>8} {}", "PORT", "PRESENT", "BAUD", "SINK");
+            for (name, serial, _) in ports() {
+                let (present, baud) = {
+                    let serial = serial.lock();
+                    (serial.is_present(), serial.baud())
+                };
+                let mut sink = false;
+                klog::for_each_sink(|other| sink |= other.name() == name);
+                kprintln!(
+                    "{:<6} {:<8} {:>8} {}",
+                    name,
+                    if present { "yes" } else { "no" },
+                    baud,
+                    if sink { "yes" } else { "no" }
+                );
+            }
+            return;
+        }
+        [name, baud] => (*name, *baud),
+        _ => {
+            kprintln!("Usage: serial [<port> <baud>]");
+            return;
+        }
+    };
+    let serial = match find_port(name) {
+        Some((serial, _)) => serial,
+        None => {
+            kprintln!("serial: No port named {}", name);
+            return;
+        }
+    };
+    let baud = match baud.parse() {
+        Ok(baud) => baud,
+        Err(_) => {
+            kprintln!("serial: Invalid baud rate {}", baud);
+            return;
+        }
+    };
+    // Bound to a variable, so the port is unlocked before anything is logged to it
+    let result = serial.lock().set_baud(baud);
+    match result {
+        Ok(()) => kprintln!("serial: {} runs at {} baud", name, baud),
+        Err(e) => kprintln!("serial: Cannot set {} to {} baud: {}", name, baud, e),
+    }
+}
@@ -0,0 +1,11 @@
+use crate::{kprintln, stats};
+
+pub fn stat(args: &[&str]) {
+    let prefix = args.first().copied().unwrap_or("");
+    for stat in stats::snapshot() {
+        if stat.value == 0 || !stat.name.starts_with(prefix) {
+            continue;
+        }
+        kprintln!("{}", stat);
+    }
+}
@@ -0,0 +1,27 @@
+use crate::kprintln;
+use crate::scheduler::TaskId;
+use crate::syscall::trace;
+
+pub fn strace(args: &[&str]) {
+    let (id, enable) = match args {
+        [id] => (*id, true),
+        [id, "on"] => (*id, true),
+        [id, "off"] => (*id, false),
+        _ => {
+            kprintln!("Usage: strace <id> [on|off]");
+            return;
+        }
+    };
+    let id = match id.parse() {
+        Ok(id) => TaskId::new(id),
+        Err(_) => {
+            kprintln!("strace: Invalid task id {}", id);
+            return;
+        }
+    };
+    match trace::trace(id, enable) {
+        Ok(()) if enable => kprintln!("strace: Tracing the system calls of {}", id.inner()),
+        Ok(()) => kprintln!("strace: Stopped tracing {}", id.inner()),
+        Err(e) => kprintln!("strace: Cannot trace {}: {}", id.inner(), e),
+    }
+}
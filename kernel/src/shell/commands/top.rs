@@ -0,0 +1,116 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::arch::tsc;
+use crate::scheduler::{self, cputime, CpuTime, TaskId, TaskState};
+use crate::{kprintln, shell, time};
+
+/// The time between two refreshes
+const INTERVAL_MS: u64 = 1000;
+/// How often a key press is looked for while waiting
+const POLL_MS: u64 = 50;
+
+pub fn top(args: &[&str]) {
+    let once = match args {
+        [] => false,
+        ["--once"] => true,
+        _ => {
+            kprintln!("Usage: top [--once]");
+            return;
+        }
+    };
+    let khz = match tsc::khz() {
+        Some(khz) => khz,
+        None => {
+            kprintln!("top: The TSC is not calibrated");
+            return;
+        }
+    };
+    let mut before = times();
+    let mut start = time::now_ms();
+    loop {
+        if wait_for_key() {
+            return;
+        }
+        let after = times();
+        let now = time::now_ms();
+        show(&before, &after, (now - start) * khz);
+        if once {
+            return;
+        }
+        before = after;
+        start = now;
+    }
+}
+
+/// Waits for `INTERVAL_MS`, returns whether a key was pressed in the meantime
+fn wait_for_key() -> bool {
+    let mut waited = 0;
+    while waited < INTERVAL_MS {
+        time::sleep_ms(POLL_MS);
+        waited += POLL_MS;
+        if shell::key_pressed() {
+            return true;
+        }
+    }
+    false
+}
+
+fn times() -> BTreeMap<TaskId, CpuTime> {
+    scheduler::task_infos()
+        .into_iter()
+        .map(|task| (task.id, task.time))
+        .collect()
+}
+
+/// Prints every task with its share of the `elapsed` cycles between `before` and `after`
+fn show(before: &BTreeMap<TaskId, CpuTime>, after: &BTreeMap<TaskId, CpuTime>, elapsed: u64) {
+    let mut tasks: Vec<_> = scheduler::task_infos()
+        .into_iter()
+        .filter(|task| task.state != TaskState::Exited)
+        .map(|task| {
+            let now = after.get(&task.id).copied().unwrap_or(task.time);
+            let then = before.get(&task.id).copied().unwrap_or_default();
+            let busy = now.total().saturating_sub(then.total());
+            (task, now, cputime::permille(busy, elapsed))
+        })
+        .collect();
+    tasks.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.id.cmp(&b.0.id)));
+
+    kprintln!();
+    kprintln!(
+        "{:>5} {:<16} {:<8} {:>3} {:>6} {:>10} {:>10} {:>10}",
+        "ID",
+        "NAME",
+        "STATE",
+        "CPU",
+        "%CPU",
+        "USER MS",
+        "KERNEL MS",
+        "TOTAL MS"
+    );
+    for (task, time, permille) in tasks {
+        let state = match task.state {
+            TaskState::Ready => "ready",
+            TaskState::Running => "running",
+            TaskState::Blocked => "blocked",
+            TaskState::Exited => "exited",
+        };
+        kprintln!(
+            "{:>5} {:<16} {:<8} {:>3} {:>4}.{} {:>10} {:>10} {:>10}",
+            task.id.inner(),
+            task.name,
+            state,
+            task.cpu,
+            permille / 10,
+            permille % 10,
+            ms(time.user),
+            ms(time.kernel),
+            ms(time.total())
+        );
+    }
+}
+
+fn ms(cycles: u64) -> u64 {
+    tsc::cycles_to_ns(cycles).unwrap_or(0) / 1_000_000
+}
@@ -0,0 +1,20 @@
+use crate::drivers::serial;
+use crate::kprintln;
+use crate::trace;
+
+pub fn tracedump(_: &[&str]) {
+    if !trace::ENABLED {
+        kprintln!("tracedump: The kernel was built without the trace feature");
+        return;
+    }
+    if !serial::is_present() {
+        kprintln!("tracedump: There is no COM1 to write the trace to");
+        return;
+    }
+    let records = trace::dump();
+    kprintln!(
+        "tracedump: Wrote {} records to COM1, {} older ones were overwritten",
+        records,
+        trace::overwritten()
+    );
+}
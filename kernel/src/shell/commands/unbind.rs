@@ -0,0 +1,25 @@
+use crate::kprintln;
+use crate::pci;
+
+pub fn unbind(args: &[&str]) {
+    let location = match args {
+        [location] => *location,
+        _ => {
+            kprintln!("Usage: unbind <bus:slot.function>");
+            return;
+        }
+    };
+    let device = match pci::find_device(location) {
+        Some(device) => device,
+        None => {
+            kprintln!("unbind: There is no PCI function {}", location);
+            return;
+        }
+    };
+    let driver = pci::driver_of(&device);
+    match (pci::unbind(&device), driver) {
+        (Ok(()), Some(driver)) => kprintln!("unbind: Unbound {} from {}", location, driver),
+        (Ok(()), None) => kprintln!("unbind: Unbound {}", location),
+        (Err(e), _) => kprintln!("unbind: Cannot unbind {}: {}", location, e),
+    }
+}
@@ -0,0 +1,50 @@
+use alloc::format;
+
+use crate::buildinfo::{self, Banner, FEATURES};
+use crate::init::initcall::{self, registered};
+use crate::kprintln;
+
+pub fn version(args: &[&str]) {
+    match args {
+        [] => kprintln!("{}", Banner),
+        ["-v"] => print_verbose(),
+        _ => kprintln!("Usage: version [-v]"),
+    }
+}
+
+/// Prints the build, then every init call compiled in and what became of it
+fn print_verbose() {
+    kprintln!("{} {}", buildinfo::NAME, buildinfo::VERSION);
+    kprintln!("Commit:   {}", buildinfo::GIT_HASH);
+    kprintln!("Built:    {} UTC", buildinfo::build_time());
+    kprintln!("Target:   {} ({})", buildinfo::TARGET, buildinfo::PROFILE);
+    if FEATURES.is_empty() {
+        kprintln!("Features: none");
+    } else {
+        kprintln!("Features: {}", FEATURES.join(" "));
+    }
+    let calls = match initcall::sort(registered()) {
+        Ok(calls) => calls,
+        Err(e) => {
+            kprintln!("version: {}", e);
+            return;
+        }
+    };
+    kprintln!();
+    kprintln!(
+        "{:<16} {:<12} {:<10} {}",
+        "INIT CALL",
+        "STAGE",
+        "FEATURE",
+        "OUTCOME"
+    );
+    for call in calls {
+        kprintln!(
+            "{:<16} {:<12} {:<10} {}",
+            call.name,
+            format!("{:?}", call.stage),
+            call.feature.unwrap_or("-"),
+            initcall::outcome(call.name).map_or("not run", |outcome| outcome.name())
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use alloc::string::ToString;
+
+use crate::kprintln;
+use crate::math::ByteSize;
+use crate::scheduler::TaskId;
+use crate::syscall::vmstat::vmstat as task_vmstat;
+
+pub fn vmstat(args: &[&str]) {
+    let id = match args {
+        [id] => *id,
+        _ => {
+            kprintln!("Usage: vmstat <id>");
+            return;
+        }
+    };
+    let id = match id.parse() {
+        Ok(id) => TaskId::new(id),
+        Err(_) => {
+            kprintln!("vmstat: Invalid task id {}", id);
+            return;
+        }
+    };
+    let stat = match task_vmstat(id) {
+        Ok(stat) => stat,
+        Err(e) => {
+            kprintln!("vmstat: Cannot read task {}: {}", id.inner(), e);
+            return;
+        }
+    };
+    kprintln!("{:<10} {:>10} {:>14}", "", "PAGES", "SIZE");
+    for (name, pages) in [
+        ("Mapped:", stat.mapped_pages),
+        ("Resident:", stat.resident_pages),
+        ("CoW:", stat.cow_shared_pages),
+        ("Lazy:", stat.lazy_pages),
+        ("Zero:", stat.zero_pages),
+    ] {
+        kprintln!(
+            "{:<10} {:>10} {:>14}",
+            name,
+            pages,
+            ByteSize(pages * bks::PAGE_SIZE).to_string()
+        );
+    }
+    kprintln!("{} regions", stat.regions);
+}
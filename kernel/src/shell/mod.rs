@@ -0,0 +1,189 @@
+//! # Kernel Shell
+//! A minimal line based shell running inside the kernel.
+//! It puts the `tty` into raw mode and edits the line itself: the idle loop in `main` calls
+//! `poll()`, which passes what was typed to `input()`, echoes it and executes completed lines.
+//! `exit()` gives the console back in canonical mode.
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::kprint;
+use crate::scheduler::spin::Mutex;
+use crate::tty::{self, Mode};
+
+pub use crate::tty::Echo;
+
+pub mod commands;
+pub mod pager;
+
+pub const PROMPT: &str = "esque> ";
+/// The maximum length of a single line, everything after it is dropped
+pub const MAX_LINE_LENGTH: usize = 256;
+
+/// # Command
+/// A single shell command
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// Called with all arguments (excluding the name of the command itself)
+    pub func: fn(&[&str]),
+}
+
+struct ShellInput {
+    buffer: [u8; MAX_LINE_LENGTH],
+    len: usize,
+    /// Set once the user pressed enter, cleared by `poll()`
+    pending: bool,
+    /// Whether the last character passed to `input()` was a carriage return
+    after_return: bool,
+}
+
+static INPUT: Mutex<ShellInput> = Mutex::new(ShellInput {
+    buffer: [0; MAX_LINE_LENGTH],
+    len: 0,
+    pending: false,
+    after_return: false,
+});
+/// Whether the shell reads the console, from `init()` until `exit()`
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// # Init
+/// Switches the console to raw mode for the line editor and prints the first prompt
+pub fn init() {
+    tty::set_mode(Mode::Raw);
+    ACTIVE.store(true, Ordering::Relaxed);
+    kprint!("{}", PROMPT);
+}
+
+/// # Exit
+/// Stops reading the console and restores canonical mode, leaving the console to programs
+pub fn exit() {
+    ACTIVE.store(false, Ordering::Relaxed);
+    tty::set_mode(Mode::Canonical);
+}
+
+/// # Push Char
+/// Appends a character to the current line. Non-ASCII characters and characters typed while a
+/// line is still waiting to be executed are dropped.
+///
+/// Returns whether the character was accepted (and should therefore be echoed)
+pub fn push_char(c: char) -> bool {
+    let mut input = INPUT.lock();
+    if !c.is_ascii() || input.pending || input.len >= MAX_LINE_LENGTH {
+        return false;
+    }
+    let len = input.len;
+    input.buffer[len] = c as u8;
+    input.len += 1;
+    true
+}
+
+/// # Pop Char
+/// Removes the last character of the current line.
+///
+/// Returns whether a character was removed (and should therefore be erased from the screen)
+pub fn pop_char() -> bool {
+    let mut input = INPUT.lock();
+    if input.pending || input.len == 0 {
+        return false;
+    }
+    input.len -= 1;
+    true
+}
+
+/// # Submit
+/// Marks the current line as complete
+pub fn submit() {
+    INPUT.lock().pending = true;
+}
+
+/// # Input
+/// Passes a character read from the console to the current line. Carriage returns and line
+/// feeds submit it, a line feed that follows a carriage return is part of the same line break.
+/// Backspace and delete remove the last character.
+///
+/// ## Returns
+/// - Echo = What to show for `c`
+pub fn input(c: char) -> Echo {
+    let after_return = core::mem::replace(&mut INPUT.lock().after_return, c == '\r');
+    match c {
+        '\n' if after_return => Echo::Nothing,
+        '\r' | '\n' => {
+            submit();
+            Echo::NewLine
+        }
+        '\x08' | '\x7F' if pop_char() => Echo::Erase(1),
+        '\x08' | '\x7F' => Echo::Nothing,
+        c if !c.is_control() && push_char(c) => Echo::Char(c),
+        _ => Echo::Nothing,
+    }
+}
+
+/// # Key Pressed
+/// Whether a key was pressed since the last call or since the running command started, for
+/// commands that run until one is. Takes everything typed in the meantime.
+pub fn key_pressed() -> bool {
+    let mut pressed = false;
+    while read_key().is_some() {
+        pressed = true;
+    }
+    pressed
+}
+
+/// # Read Key
+/// The next character typed since the running command started, for commands that wait for a
+/// specific one
+pub fn read_key() -> Option<char> {
+    let mut byte = [0];
+    match tty::read(&mut byte, true) {
+        Ok(1) => Some(byte[0] as char),
+        _ => None,
+    }
+}
+
+/// # Poll
+/// Passes what was typed to the current line and executes it once it was submitted. Must not
+/// be called from interrupt context.
+pub fn poll() {
+    while ACTIVE.load(Ordering::Relaxed) && !INPUT.lock().pending {
+        match read_key() {
+            Some(c) => tty::echo(input(c)),
+            None => break,
+        }
+    }
+    let (line, len) = {
+        let input = INPUT.lock();
+        if !input.pending {
+            return;
+        }
+        (input.buffer, input.len)
+    };
+
+    let line = core::str::from_utf8(&line[..len]).unwrap_or("");
+    key_pressed();
+    execute(line);
+
+    {
+        let mut input = INPUT.lock();
+        input.len = 0;
+        input.pending = false;
+    }
+    if ACTIVE.load(Ordering::Relaxed) {
+        kprint!("{}", PROMPT);
+    }
+}
+
+/// # Execute
+/// Looks up and runs the command given in `line`
+pub fn execute(line: &str) {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        None => return,
+    };
+    let args: Vec<&str> = words.collect();
+
+    match commands::find(name) {
+        Some(command) => (command.func)(&args),
+        None => crate::kprintln!("{}: command not found (try `help`)", name),
+    }
+}
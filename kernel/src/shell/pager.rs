@@ -0,0 +1,84 @@
+//! # Pager
+//! Lets commands with long output print it a screen at a time. After every screen, the pager
+//! waits for a key: `q` stops the output, any other key shows the next screen.
+use core::fmt::Arguments;
+
+use crate::drivers::virtio;
+use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::{kprint, kprintln, shell, time};
+
+/// The rows of a screen in serial-only mode, where the size of the terminal is unknown
+pub const DEFAULT_ROWS: usize = 24;
+/// How often a key press is looked for while waiting
+const POLL_MS: u64 = 20;
+const QUIT_KEY: char = 'q';
+
+/// # Pager
+/// Counts the lines a command printed
+pub struct Pager {
+    /// The lines that are printed before waiting
+    page: usize,
+    printed: usize,
+    quit: bool,
+}
+
+impl Pager {
+    /// # New
+    /// A pager for screens of the size of the console, keeping a row for the prompt
+    pub fn new() -> Self {
+        let rows = {
+            let guard = FRAMEBUFFER_GUARD.lock();
+            unsafe { guard.assume_init_ref() }.geometry().1
+        };
+        Self::with_rows(if rows == 0 { DEFAULT_ROWS } else { rows })
+    }
+
+    pub fn with_rows(rows: usize) -> Self {
+        Self {
+            page: rows.saturating_sub(1).max(1),
+            printed: 0,
+            quit: false,
+        }
+    }
+
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// # Line
+    /// Prints a line, after waiting for a key if the screen is full
+    ///
+    /// ## Returns
+    /// - bool = Whether the line was printed, false once the user stopped the output
+    pub fn line(&mut self, args: Arguments) -> bool {
+        if self.quit {
+            return false;
+        }
+        if self.printed == self.page {
+            kprint!("-- More -- ({} quits)", QUIT_KEY);
+            let key = wait_for_key();
+            kprintln!();
+            if key == QUIT_KEY {
+                self.quit = true;
+                return false;
+            }
+            self.printed = 0;
+        }
+        kprintln!("{}", args);
+        self.printed += 1;
+        true
+    }
+}
+
+/// Waits for a key on any source of input
+fn wait_for_key() -> char {
+    shell::read_key();
+    loop {
+        // The idle loop, which polls the virtio console otherwise, waits for the command
+        virtio::console::poll();
+        if let Some(key) = shell::read_key() {
+            return key;
+        }
+        time::sleep_ms(POLL_MS);
+    }
+}
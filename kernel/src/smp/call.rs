@@ -0,0 +1,161 @@
+//! # Call
+//! Running functions on other CPUs.
+//!
+//! The function is appended to the call queue of the target CPU, which is then interrupted with
+//! `CALL_FUNCTION_VECTOR` and drains its queue inside of the interrupt handler. Queued functions
+//! therefore run with interrupts disabled and must neither block nor wait for other calls.
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::percpu::{cpu, current_cpu, online_cpus};
+use crate::arch::apic::{local_apic, CALL_FUNCTION_VECTOR};
+use crate::arch::interrupts::without_interrupts;
+
+/// How often `stop_others()` checks whether the other CPUs stopped before giving up
+const STOP_TIMEOUT_SPINS: usize = 10_000_000;
+
+pub(super) struct Call {
+    func: fn(usize),
+    pending: Arc<AtomicUsize>,
+}
+
+impl Call {
+    fn run(self, cpu: usize) {
+        (self.func)(cpu);
+        self.pending.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// # Call Handle
+/// Tracks how many CPUs have yet to run a call
+#[must_use = "Dropping the handle does not wait for the call to finish, use `wait()` for that"]
+pub struct CallHandle {
+    pending: Arc<AtomicUsize>,
+}
+
+impl CallHandle {
+    /// Whether every targeted CPU has run the function
+    pub fn is_done(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+
+    /// # Wait
+    /// Spins until every targeted CPU has run the function.
+    ///
+    /// ## Notes
+    /// Waiting with interrupts disabled deadlocks if another CPU waits for us at the same time
+    pub fn wait(self) {
+        while !self.is_done() {
+            comasm::pause();
+        }
+    }
+}
+
+/// # Call On
+/// Runs `f` on the CPU `target`, passing it the number of the CPU. If `target` is the calling
+/// CPU, `f` runs right away.
+///
+/// ## Panics
+/// If `target` is not online
+pub fn call_on(target: usize, f: fn(usize)) -> CallHandle {
+    assert!(
+        cpu(target).is_online(),
+        "Cannot call a function on offline CPU {}",
+        target
+    );
+    let pending = Arc::new(AtomicUsize::new(1));
+    without_interrupts(|| {
+        let call = Call {
+            func: f,
+            pending: pending.clone(),
+        };
+        if target == current_cpu() {
+            call.run(target);
+        } else {
+            queue(target, call);
+        }
+    });
+    CallHandle { pending }
+}
+
+/// # Call All
+/// Runs `f` on every online CPU, including the calling one
+pub fn call_all(f: fn(usize)) -> CallHandle {
+    without_interrupts(|| {
+        let this_cpu = current_cpu();
+        let pending = Arc::new(AtomicUsize::new(online_cpus().count()));
+        for target in online_cpus().filter(|target| *target != this_cpu) {
+            queue(
+                target,
+                Call {
+                    func: f,
+                    pending: pending.clone(),
+                },
+            );
+        }
+        Call {
+            func: f,
+            pending: pending.clone(),
+        }
+        .run(this_cpu);
+        CallHandle { pending }
+    })
+}
+
+/// # Stop Others
+/// Parks every other online CPU for good, with interrupts disabled, before the machine powers
+/// off or restarts. A stopped CPU is no longer online.
+///
+/// ## Returns
+/// - usize = The number of CPUs that did not stop in time
+pub fn stop_others() -> usize {
+    let this_cpu = current_cpu();
+    for target in online_cpus().filter(|target| *target != this_cpu) {
+        // Never done, the CPU does not return from `park()`
+        let _ = call_on(target, park);
+    }
+    for _ in 0..STOP_TIMEOUT_SPINS {
+        if online_cpus().all(|cpu| cpu == this_cpu) {
+            return 0;
+        }
+        comasm::pause();
+    }
+    online_cpus().filter(|cpu| *cpu != this_cpu).count()
+}
+
+fn park(this_cpu: usize) {
+    cpu(this_cpu).set_offline();
+    loop {
+        comasm::clear_interrupts();
+        comasm::halt();
+    }
+}
+
+fn queue(target: usize, call: Call) {
+    let target_cpu = cpu(target);
+    target_cpu.calls.lock().push(call);
+    local_apic()
+        .expect("Cross-CPU calls require the local APIC")
+        .send_ipi(target_cpu.apic_id(), CALL_FUNCTION_VECTOR);
+}
+
+/// # Handle Calls
+/// Runs every function queued for the calling CPU. Called by the call function IPI handler.
+pub fn handle_calls() {
+    let this_cpu = current_cpu();
+    loop {
+        // Do not hold the lock while running the call, it may queue further calls
+        let call = {
+            let mut calls = cpu(this_cpu).calls.lock();
+            if calls.is_empty() {
+                None
+            } else {
+                Some(calls.remove(0))
+            }
+        };
+        match call {
+            Some(call) => call.run(this_cpu),
+            None => break,
+        }
+    }
+}
@@ -1,3 +1,9 @@
+pub mod call;
+pub mod percpu;
+
+pub use call::{call_all, call_on, stop_others, CallHandle};
+pub use percpu::{current_cpu, online_count, online_cpus, CpuMask, MAX_CPUS};
+
 pub struct Thread {
     func: fn(),
 }
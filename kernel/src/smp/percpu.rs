@@ -0,0 +1,138 @@
+//! # Per-CPU
+//! Bookkeeping for every CPU known to the kernel. CPUs are numbered in the order in which they
+//! came online, the bootstrap processor always being CPU 0.
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+
+use super::call::Call;
+use crate::arch::apic::local_apic;
+use crate::arch::gdt::CpuLocalGdt;
+use crate::scheduler::spin::Mutex;
+
+/// The maximum number of CPUs the kernel can handle, every further one stays offline
+pub const MAX_CPUS: usize = 64;
+
+pub struct Cpu {
+    apic_id: AtomicU32,
+    online: AtomicBool,
+    /// The GDT and TSS the CPU loaded, see `CpuLocalGdt::init`
+    gdt: AtomicPtr<CpuLocalGdt>,
+    /// Functions queued by `call_on` and `call_all`, run by the call function IPI handler
+    pub(super) calls: Mutex<Vec<Call>>,
+}
+
+impl Cpu {
+    const fn new() -> Self {
+        Self {
+            apic_id: AtomicU32::new(0),
+            online: AtomicBool::new(false),
+            gdt: AtomicPtr::new(core::ptr::null_mut()),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    pub(super) fn set_offline(&self) {
+        self.online.store(false, Ordering::Release);
+    }
+
+    pub fn gdt(&self) -> Option<&'static CpuLocalGdt> {
+        unsafe { self.gdt.load(Ordering::Acquire).as_ref() }
+    }
+
+    pub(crate) fn set_gdt(&self, gdt: &'static CpuLocalGdt) {
+        self.gdt.store(
+            gdt as *const CpuLocalGdt as *mut CpuLocalGdt,
+            Ordering::Release,
+        );
+    }
+}
+
+/// # CPU Mask
+/// A set of CPU numbers, e.g. the CPUs a task may run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuMask(u64);
+
+impl CpuMask {
+    pub const fn all() -> Self {
+        Self(u64::MAX)
+    }
+
+    pub const fn none() -> Self {
+        Self(0)
+    }
+
+    pub const fn single(cpu: usize) -> Self {
+        Self(1 << cpu)
+    }
+
+    pub fn contains(&self, cpu: usize) -> bool {
+        cpu < MAX_CPUS && self.0 & (1 << cpu) != 0
+    }
+
+    pub fn insert(&mut self, cpu: usize) {
+        self.0 |= 1 << cpu;
+    }
+
+    pub fn remove(&mut self, cpu: usize) {
+        self.0 &= !(1 << cpu);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+}
+
+const OFFLINE: Cpu = Cpu::new();
+static CPUS: [Cpu; MAX_CPUS] = [OFFLINE; MAX_CPUS];
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// # Register CPU
+/// Marks the calling CPU with the APIC id `apic_id` as online.
+///
+/// ## Returns
+/// - usize = The number of the CPU
+pub fn register_cpu(apic_id: u32) -> usize {
+    let idx = CPU_COUNT.fetch_add(1, Ordering::SeqCst);
+    assert!(idx < MAX_CPUS, "More than {} CPUs came online", MAX_CPUS);
+    CPUS[idx].apic_id.store(apic_id, Ordering::Relaxed);
+    CPUS[idx].online.store(true, Ordering::Release);
+    idx
+}
+
+pub fn cpu(idx: usize) -> &'static Cpu {
+    &CPUS[idx]
+}
+
+/// # Current CPU
+/// The number of the calling CPU. Before the local APIC is set up, only the bootstrap processor
+/// is running.
+pub fn current_cpu() -> usize {
+    let apic_id = match local_apic() {
+        Some(apic) => apic.id(),
+        None => return 0,
+    };
+    online_cpus()
+        .find(|idx| CPUS[*idx].apic_id() == apic_id)
+        .unwrap_or(0)
+}
+
+/// The numbers of all CPUs that are online
+pub fn online_cpus() -> impl Iterator<Item = usize> {
+    (0..CPU_COUNT.load(Ordering::Acquire).min(MAX_CPUS)).filter(|idx| CPUS[*idx].is_online())
+}
+
+pub fn online_count() -> usize {
+    online_cpus().count()
+}
@@ -0,0 +1,217 @@
+//! # Statistics
+//! Lightweight, lock-free counters for hot paths (interrupts, page faults, syscalls, ...).
+//!
+//! Counters are declared with the `counter!` and `counter_array!` macros, which additionally place a
+//! `StatEntry` into the `.kstats` link section. `snapshot()` walks that section, so every declared
+//! counter shows up without a central list having to be maintained.
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+/// # Counter
+/// A single relaxed atomic counter. Incrementing never takes a lock and may therefore be done
+/// from interrupt handlers.
+pub struct Counter {
+    #[doc(hidden)]
+    pub value: AtomicU64,
+}
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self { value: ZERO }
+    }
+
+    #[inline(always)]
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    #[inline(always)]
+    pub fn add(&self, amount: u64) {
+        self.value.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// # Counter Array
+/// A group of counters sharing one name, indexed by a number such as an interrupt vector or
+/// a syscall number
+pub struct CounterArray<const N: usize> {
+    #[doc(hidden)]
+    pub values: [AtomicU64; N],
+}
+
+impl<const N: usize> CounterArray<N> {
+    pub const fn new() -> Self {
+        Self { values: [ZERO; N] }
+    }
+
+    /// # Increment
+    /// Increments the counter at `index`. Out of range indices are ignored, as a bad syscall
+    /// number must not be able to panic the kernel.
+    #[inline(always)]
+    pub fn increment(&self, index: usize) {
+        self.add(index, 1);
+    }
+
+    #[inline(always)]
+    pub fn add(&self, index: usize, amount: u64) {
+        if let Some(value) = self.values.get(index) {
+            value.fetch_add(amount, Ordering::Relaxed);
+        }
+    }
+
+    /// # Record Max
+    /// Raises the value at `index` to `value` if it is larger, for worst-case values
+    #[inline(always)]
+    pub fn record_max(&self, index: usize, value: u64) {
+        if let Some(max) = self.values.get(index) {
+            max.fetch_max(value, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> u64 {
+        self.values
+            .get(index)
+            .map(|value| value.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    pub const fn len(&self) -> usize {
+        N
+    }
+}
+
+/// # Stat Values
+/// What a registered entry points to
+pub enum StatValues {
+    Single(&'static AtomicU64),
+    Array(&'static [AtomicU64]),
+}
+
+/// # Stat Entry
+/// The record placed into the `.kstats` section by the registration macros
+pub struct StatEntry {
+    pub name: &'static str,
+    pub values: StatValues,
+}
+
+/// # Stat
+/// A single value of a snapshot
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    pub name: &'static str,
+    /// The index within a `CounterArray`, `None` for plain counters
+    pub index: Option<usize>,
+    pub value: u64,
+}
+
+impl core::fmt::Display for Stat {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.index {
+            Some(idx) => write!(f, "{}[{:#x}] = {}", self.name, idx, self.value),
+            None => write!(f, "{} = {}", self.name, self.value),
+        }
+    }
+}
+
+extern "C" {
+    // Defined in the Linker Script
+    static __kstats_start: StatEntry;
+    static __kstats_end: StatEntry;
+}
+
+/// # Entries
+/// All entries registered via `counter!` and `counter_array!`
+pub fn entries() -> &'static [StatEntry] {
+    unsafe {
+        let start = &__kstats_start as *const StatEntry;
+        let end = &__kstats_end as *const StatEntry;
+        let count = (end as usize - start as usize) / core::mem::size_of::<StatEntry>();
+        core::slice::from_raw_parts(start, count)
+    }
+}
+
+/// # Snapshot
+/// Returns every registered value as `Stat`. The values are read one after another with relaxed
+/// ordering, so the snapshot is not atomic as a whole.
+pub fn snapshot() -> impl Iterator<Item = Stat> {
+    entries().iter().flat_map(|entry| {
+        let values: &'static [AtomicU64] = match entry.values {
+            StatValues::Single(value) => core::slice::from_ref(value),
+            StatValues::Array(values) => values,
+        };
+        let is_array = matches!(entry.values, StatValues::Array(_));
+        values.iter().enumerate().map(move |(idx, value)| Stat {
+            name: entry.name,
+            index: if is_array { Some(idx) } else { None },
+            value: value.load(Ordering::Relaxed),
+        })
+    })
+}
+
+/// # Find
+/// Returns the (summed up, for arrays) value of the counter named `name`
+pub fn find(name: &str) -> Option<u64> {
+    let entry = entries().iter().find(|entry| entry.name == name)?;
+    Some(match entry.values {
+        StatValues::Single(value) => value.load(Ordering::Relaxed),
+        StatValues::Array(values) => values.iter().map(|v| v.load(Ordering::Relaxed)).sum(),
+    })
+}
+
+/// # Counter
+/// Declares a named `Counter` and registers it
+/// ## Example
+/// ```
+/// counter!(pub PAGE_FAULTS = "mm.page_faults");
+/// PAGE_FAULTS.increment();
+/// ```
+#[macro_export]
+macro_rules! counter {
+    ($visi:vis $ident:ident = $name:expr) => {
+        $visi static $ident: $crate::stats::Counter = $crate::stats::Counter::new();
+        const _: () = {
+            #[used]
+            #[link_section = ".kstats"]
+            static ENTRY: $crate::stats::StatEntry = $crate::stats::StatEntry {
+                name: $name,
+                values: $crate::stats::StatValues::Single(&$ident.value),
+            };
+        };
+    };
+}
+
+/// # Counter Array
+/// Declares a named `CounterArray` with `$len` entries and registers it
+/// ## Example
+/// ```
+/// counter_array!(pub IRQ_COUNT[256] = "irq.count");
+/// IRQ_COUNT.increment(0x21);
+/// ```
+#[macro_export]
+macro_rules! counter_array {
+    ($visi:vis $ident:ident[$len:expr] = $name:expr) => {
+        $visi static $ident: $crate::stats::CounterArray<$len> =
+            $crate::stats::CounterArray::new();
+        const _: () = {
+            #[used]
+            #[link_section = ".kstats"]
+            static ENTRY: $crate::stats::StatEntry = $crate::stats::StatEntry {
+                name: $name,
+                values: $crate::stats::StatValues::Array(&$ident.values),
+            };
+        };
+    };
+}
+
+// Counters that do not belong to a single subsystem
+crate::counter_array!(pub IRQ_COUNT[256] = "irq.count");
+// The longest time any handler of a vector took, in TSC cycles
+crate::counter_array!(pub IRQ_MAX_CYCLES[256] = "irq.max_cycles");
+crate::counter_array!(pub SYSCALL_COUNT[512] = "syscall.count");
@@ -0,0 +1,116 @@
+//! # Symbols
+//! Resolves code addresses to function names. The kernel has no symbol table of its own, the
+//! build writes one with `nm --numeric-sort --demangle` into the boot module called `symbols`:
+//! One symbol per line, its address in hex, its type and its name.
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::boot_modules;
+
+/// The name of the boot module holding the symbol table
+pub const SYMBOLS_MODULE: &str = "symbols";
+/// The length of the hash rustc appends to symbol names, `::h` and 16 hex digits
+const HASH_LEN: usize = 19;
+
+static SYMBOLS: Once<SymbolTable> = Once::new();
+
+/// # Symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub address: u64,
+    /// The demangled name, without the hash
+    pub name: &'static str,
+    /// Whether the symbol is in a code section, addresses in data are never resolved
+    pub code: bool,
+}
+
+/// # Symbol Table
+/// Every symbol, by address
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    /// # Parse
+    /// Reads the output of `nm`, skipping lines it does not understand
+    pub fn parse(text: &'static str) -> Self {
+        let mut symbols: Vec<Symbol> = text.lines().filter_map(parse_line).collect();
+        symbols.sort_by_key(|symbol| symbol.address);
+        Self { symbols }
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// # Resolve
+    /// The function `address` is in, which is the last symbol before it, if that is code
+    ///
+    /// ## Returns
+    /// - (Symbol, u64) = The function and the offset of `address` into it
+    pub fn resolve(&self, address: u64) -> Option<(Symbol, u64)> {
+        let idx = match self
+            .symbols
+            .binary_search_by_key(&address, |symbol| symbol.address)
+        {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        let symbol = self.symbols[idx];
+        symbol.code.then(|| (symbol, address - symbol.address))
+    }
+}
+
+fn parse_line(line: &'static str) -> Option<Symbol> {
+    let mut words = line.splitn(3, ' ');
+    let address = u64::from_str_radix(words.next()?, 16).ok()?;
+    let kind = words.next()?;
+    let name = words.next()?.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(Symbol {
+        address,
+        name: trim_hash(name),
+        code: matches!(kind, "T" | "t" | "W" | "w"),
+    })
+}
+
+/// `name` without the `::h0123456789abcdef` rustc appends
+fn trim_hash(name: &str) -> &str {
+    let split = name.len().saturating_sub(HASH_LEN);
+    match (name.get(..split), name.get(split..)) {
+        (Some(base), Some(hash))
+            if !base.is_empty()
+                && hash.starts_with("::h")
+                && hash[3..].bytes().all(|byte| byte.is_ascii_hexdigit()) =>
+        {
+            base
+        }
+        _ => name,
+    }
+}
+
+/// # Symbols
+/// The symbol table of the kernel, empty without the boot module
+pub fn symbols() -> &'static SymbolTable {
+    SYMBOLS.call_once(|| {
+        boot_modules::module(SYMBOLS_MODULE)
+            .and_then(|module| core::str::from_utf8(module).ok())
+            .map(SymbolTable::parse)
+            .unwrap_or_default()
+    })
+}
+
+/// # Resolve
+/// The function of the kernel `address` is in, see `SymbolTable::resolve()`
+pub fn resolve(address: u64) -> Option<(Symbol, u64)> {
+    symbols().resolve(address)
+}
@@ -0,0 +1,82 @@
+//! # ABI
+//! The kernel keeps its own copies of the structs system calls exchange with userspace, so it
+//! can give them methods. Here they are checked against their definitions in the `abi` crate
+//! when the kernel is built: A struct that no longer matches fails the build instead of
+//! corrupting user memory.
+use core::mem::{align_of, size_of};
+use memoffset::offset_of;
+use static_assertions::const_assert_eq;
+
+use super::stat::{Dirent, Stat};
+use super::sysinfo::{SysInfo, SysInfoTag};
+use super::uname::Utsname;
+use super::vmstat::VmStat;
+use crate::error::Result;
+use crate::framebuffer::device::FbInfo;
+use crate::time::Timespec;
+
+/// # Assert Same Layout
+/// Fails the build unless `$kernel` and `$abi` have the same size, alignment and offset of
+/// every field in `$field`
+macro_rules! assert_same_layout {
+    ($kernel:ty, $abi:ty, [$($field:ident),* $(,)?]) => {
+        const_assert_eq!(size_of::<$kernel>(), size_of::<$abi>());
+        const_assert_eq!(align_of::<$kernel>(), align_of::<$abi>());
+        $(const_assert_eq!(offset_of!($kernel, $field), offset_of!($abi, $field));)*
+    };
+}
+
+assert_same_layout!(Timespec, ::abi::Timespec, [tv_sec, tv_nsec]);
+assert_same_layout!(SysInfoTag, ::abi::SysInfoTag, [name, bytes, count]);
+assert_same_layout!(
+    SysInfo,
+    ::abi::SysInfo,
+    [
+        size,
+        _reserved,
+        valid,
+        total_ram,
+        free_ram,
+        uptime_secs,
+        procs,
+        page_size,
+        heap_bytes,
+        top_consumers,
+    ]
+);
+
+assert_same_layout!(
+    Stat,
+    ::abi::Stat,
+    [
+        dev, ino, mode, nlink, uid, gid, size, blksize, blocks, mtime, mtime_nsec, atime,
+        atime_nsec, ctime, ctime_nsec,
+    ]
+);
+assert_same_layout!(Dirent, ::abi::Dirent, [ino, reclen, ty, name]);
+
+assert_same_layout!(
+    Utsname,
+    ::abi::Utsname,
+    [sysname, nodename, release, version, machine, domainname]
+);
+assert_same_layout!(
+    VmStat,
+    ::abi::VmStat,
+    [
+        mapped_pages,
+        resident_pages,
+        cow_shared_pages,
+        lazy_pages,
+        regions,
+        zero_pages,
+    ]
+);
+
+assert_same_layout!(FbInfo, ::abi::FbInfo, [size, width, height, pitch, format]);
+
+/// # API Version
+/// `api_version()`, the `ABI_VERSION` the kernel was built with
+pub fn sys_api_version() -> Result<i32> {
+    Ok(::abi::ABI_VERSION as i32)
+}
@@ -0,0 +1,216 @@
+//! # Futex
+//! `futex(uaddr, op, val, timeout)`, the blocking primitive userspace builds its locks on. A
+//! task waits on a 32 bit word of user memory as long as it holds an expected value, another
+//! task that changed the word wakes it.
+//!
+//! Waiters are kept in a table of buckets, hashed by the physical address of the word, so a
+//! word mapped into several address spaces is one futex. Words in anonymous mappings are
+//! hashed by their virtual address instead, their pages share the zero frame until the first
+//! write moves them to a frame of their own (see `memory::vmm`). A waiter checks the word and queues
+//! itself with its bucket locked, and a waker takes the same lock, so no wakeup gets lost
+//! between the check and the sleep. Spurious wakeups are possible, userspace checks its word
+//! again anyway.
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::arch::interrupts::without_interrupts;
+use crate::arch::paging::page_table_manager::translate;
+use crate::error::{Error, Result};
+use crate::memory::usermem;
+use crate::memory::vmm::{self, Backing, Region, ADDRESS_SPACE};
+use crate::memory::UserVirtualAddress;
+use crate::scheduler::{self, IrqSpinLock, TaskId};
+use crate::time::{self, Timer, Timespec};
+
+/// Wait while the word holds `val`
+pub const FUTEX_WAIT: u64 = 0;
+/// Wake up to `val` waiters
+pub const FUTEX_WAKE: u64 = 1;
+/// Set by userspace for futexes that are not shared between processes, which changes nothing
+/// here
+pub const FUTEX_PRIVATE_FLAG: u64 = 128;
+/// The number of buckets of the wait table
+pub const FUTEX_BUCKETS: usize = 64;
+
+crate::counter!(pub FUTEX_WAITS = "futex.waits");
+crate::counter!(pub FUTEX_WAKES = "futex.wakes");
+
+/// What ended a wait, set by whoever takes the waiter out of its bucket
+const WAITING: u8 = 0;
+const WOKEN: u8 = 1;
+const TIMED_OUT: u8 = 2;
+
+struct Waiter {
+    /// What the futex word is told apart by, see `key()`
+    key: u64,
+    task: TaskId,
+    outcome: Arc<AtomicU8>,
+}
+
+const EMPTY_BUCKET: IrqSpinLock<Vec<Waiter>> = crate::named_lock!("futex_bucket", Vec::new());
+/// The waiters of every bucket, in the order they started waiting in
+static BUCKETS: [IrqSpinLock<Vec<Waiter>>; FUTEX_BUCKETS] = [EMPTY_BUCKET; FUTEX_BUCKETS];
+
+fn bucket(key: u64) -> &'static IrqSpinLock<Vec<Waiter>> {
+    // Words next to each other end up in different buckets
+    let hash = (key >> 2).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    &BUCKETS[(hash >> 58) as usize % FUTEX_BUCKETS]
+}
+
+/// # Key
+/// What the futex word at `uaddr` is told apart by: Its virtual address in an anonymous
+/// region, as there is one address space, and its physical address anywhere else. Anonymous
+/// regions are placed far above physical memory, so the two never collide.
+///
+/// ## Returns
+/// - Error::InvalidArgument = `uaddr` is not aligned to 4 bytes
+/// - Error::BadFault = `uaddr` is not user memory or not mapped
+fn key(uaddr: UserVirtualAddress) -> Result<u64> {
+    if uaddr.as_u64() % 4 != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    usermem::check_range(uaddr, 4)?;
+    let addr = uaddr.as_u64();
+    let anonymous = matches!(
+        ADDRESS_SPACE.lock().region(addr),
+        Some(Region {
+            backing: Backing::Anonymous,
+            accessible: true,
+            ..
+        })
+    );
+    if anonymous {
+        // An untouched page is faulted in here, `wait()` reads the word with its bucket locked
+        if translate(addr).is_none() && !vmm::handle_fault(addr, false) {
+            return Err(Error::BadFault);
+        }
+        return Ok(addr);
+    }
+    translate(addr).ok_or(Error::BadFault)
+}
+
+/// # Wait
+/// Blocks the current task while the word at `uaddr` holds `val`, for at most `timeout_ms`
+///
+/// ## Returns
+/// - Error::TryAgain = The word did not hold `val`
+/// - Error::ConnectionTimedOut = The time ran out, which is `ETIMEDOUT`
+pub fn wait(uaddr: UserVirtualAddress, val: u32, timeout_ms: Option<u64>) -> Result<()> {
+    scheduler::assert_can_block("futex::wait");
+    let key = key(uaddr)?;
+    let bucket = bucket(key);
+    let task = scheduler::current();
+    let outcome = Arc::new(AtomicU8::new(WAITING));
+    // Taken before the timer is scheduled, so it never fires before the deadline passed
+    let deadline = timeout_ms.map(|timeout_ms| time::now_ms().saturating_add(timeout_ms));
+    let timer = timeout_ms.map(|timeout_ms| {
+        let outcome = outcome.clone();
+        Timer::schedule(timeout_ms, move || {
+            let mut waiters = bucket.lock();
+            if let Some(idx) = waiters
+                .iter()
+                .position(|waiter| Arc::ptr_eq(&waiter.outcome, &outcome))
+            {
+                waiters.remove(idx);
+                outcome.store(TIMED_OUT, Ordering::Relaxed);
+                scheduler::wake(task);
+            }
+        })
+    });
+    FUTEX_WAITS.increment();
+    let result = without_interrupts(|| {
+        {
+            let mut waiters = bucket.lock();
+            if usermem::read_user::<u32>(uaddr)? != val {
+                return Err(Error::TryAgain);
+            }
+            if deadline.map_or(false, |deadline| time::now_ms() >= deadline) {
+                return Err(Error::ConnectionTimedOut);
+            }
+            waiters.push(Waiter {
+                key,
+                task,
+                outcome: outcome.clone(),
+            });
+        }
+        // A wakeup between here and blocking makes blocking return right away
+        unsafe { scheduler::block_current() };
+        Ok(())
+    });
+    if let Some(timer) = timer {
+        timer.cancel();
+    }
+    // Still queued after a spurious wakeup
+    let mut waiters = bucket.lock();
+    if let Some(idx) = waiters
+        .iter()
+        .position(|waiter| Arc::ptr_eq(&waiter.outcome, &outcome))
+    {
+        waiters.remove(idx);
+    }
+    drop(waiters);
+    match (result, outcome.load(Ordering::Relaxed)) {
+        (Err(e), _) => Err(e),
+        (Ok(()), TIMED_OUT) => Err(Error::ConnectionTimedOut),
+        (Ok(()), _) => Ok(()),
+    }
+}
+
+/// # Wake
+/// Wakes up to `count` of the tasks waiting on the word at `uaddr`, those that waited the
+/// longest first
+///
+/// ## Returns
+/// - usize = The number of tasks woken
+pub fn wake(uaddr: UserVirtualAddress, count: usize) -> Result<usize> {
+    let key = key(uaddr)?;
+    let mut woken = 0;
+    let mut waiters = bucket(key).lock();
+    let mut idx = 0;
+    while idx < waiters.len() && woken < count {
+        if waiters[idx].key != key {
+            idx += 1;
+            continue;
+        }
+        let waiter = waiters.remove(idx);
+        waiter.outcome.store(WOKEN, Ordering::Relaxed);
+        scheduler::wake(waiter.task);
+        woken += 1;
+    }
+    FUTEX_WAKES.add(woken as u64);
+    Ok(woken)
+}
+
+/// # Waiters
+/// The number of tasks waiting on the word at `uaddr`
+pub fn waiters(uaddr: UserVirtualAddress) -> Result<usize> {
+    let key = key(uaddr)?;
+    Ok(bucket(key)
+        .lock()
+        .iter()
+        .filter(|waiter| waiter.key == key)
+        .count())
+}
+
+/// # Futex
+/// `futex(uaddr, op, val, timeout)`. The timeout of `FUTEX_WAIT` is relative, a null
+/// `timeout` waits forever. Other operations ignore `timeout`, C libraries leave whatever is in
+/// the register, so it only becomes a `UserVirtualAddress` for `FUTEX_WAIT`.
+///
+/// ## Returns
+/// - i32 = 0 for `FUTEX_WAIT`, the number of woken tasks for `FUTEX_WAKE`
+/// - Error::InvalidArgument = `op` is not supported or the timeout is invalid
+pub fn sys_futex(uaddr: UserVirtualAddress, op: u64, val: u64, timeout: u64) -> Result<i32> {
+    match op & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => {
+            let timeout_ms = match usermem::user_address(timeout)? {
+                timeout if timeout.is_null() => None,
+                timeout => Some(usermem::read_user::<Timespec>(timeout)?.to_ms()?),
+            };
+            wait(uaddr, val as u32, timeout_ms).map(|_| 0)
+        }
+        FUTEX_WAKE => wake(uaddr, val as u32 as usize).map(|woken| woken as i32),
+        _ => Err(Error::InvalidArgument),
+    }
+}
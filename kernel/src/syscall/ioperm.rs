@@ -0,0 +1,24 @@
+//! # I/O Permissions
+//! `ioperm()`, which lets a task access legacy I/O ports from user space without running in
+//! ring 0. The ports it may access are in the I/O permission bitmap of the TSS, which the
+//! scheduler loads for every task it switches to, see `scheduler::set_io_permission`.
+use crate::error::{Error, Result};
+use crate::scheduler;
+
+/// # Ioperm
+/// `ioperm(from, num, turn_on)`, allows the calling task to access the `num` ports from `from`
+/// on if `turn_on` is nonzero, or denies it again. Every port is denied to start with, and the
+/// tasks a task spawns do not inherit its ports.
+///
+/// ## Returns
+/// - Error::InvalidArgument = The range reaches beyond the last port
+///
+/// ## Notes
+/// Anyone may call it for now, it becomes a privilege of root once there are users
+pub fn sys_ioperm(from: u64, num: u64, turn_on: u64) -> Result<i32> {
+    let end = from.checked_add(num).ok_or(Error::InvalidArgument)?;
+    let from = u32::try_from(from).map_err(|_| Error::InvalidArgument)?;
+    let end = u32::try_from(end).map_err(|_| Error::InvalidArgument)?;
+    scheduler::set_io_permission(from..end, turn_on != 0)?;
+    Ok(0)
+}
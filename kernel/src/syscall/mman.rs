@@ -0,0 +1,121 @@
+//! # Memory Mappings
+//! `mmap()` and `munmap()`, along with `shm_open()` and `shm_unlink()` for the shared memory
+//! objects they map. `MAP_SHARED` maps shared memory objects and device nodes that can be
+//! mapped, such as `/dev/fb0`, `MAP_PRIVATE | MAP_ANONYMOUS` private memory that gets its
+//! frames on the first access.
+//! `mincore()` tells which pages of the mappings have a frame.
+//!
+//! Linux has no system calls for shared memory objects, its C libraries open files in
+//! `/dev/shm` instead. They get numbers above the ones of Linux here.
+use alloc::vec::Vec;
+
+use crate::error::{Error, Result};
+use crate::fs;
+use crate::ipc::shm;
+use crate::memory::paging;
+use crate::memory::usermem;
+use crate::memory::vmm::{self, Backing, ADDRESS_SPACE};
+use crate::memory::UserVirtualAddress;
+
+pub const PROT_NONE: u64 = 0;
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+/// Accepted, but pages are executable either way
+pub const PROT_EXEC: u64 = 4;
+/// Writes go to the mapped object, every other mapping of it sees them
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+/// Not supported, the address passed to `mmap()` is never more than a hint
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+/// Names passed to `shm_open()` may not be longer than this, including the slash and the NUL
+pub const SHM_PATH_MAX: usize = shm::SHM_NAME_MAX + 2;
+
+/// # Memory Map
+/// `mmap(addr, len, prot, flags, fd, offset)`, maps `len` bytes of the shared memory object
+/// or device `fd` from `offset` on, or with `MAP_PRIVATE | MAP_ANONYMOUS` zeroed memory, ignoring `fd`
+/// and `offset`. `addr` is ignored, the mapping goes to the lowest free address.
+///
+/// ## Returns
+/// - u64 = The address of the mapping
+/// - Error::InvalidArgument = `flags` is neither `MAP_SHARED` nor `MAP_PRIVATE |
+///   MAP_ANONYMOUS`, `prot` has unknown bits, `offset` is not page aligned or the mapping
+///   reaches beyond the end of the object
+/// - Error::NoSuchDevice = `fd` is neither a shared memory object nor a device that can be
+///   mapped
+/// - Error::OutOfMemory = There is no free range that large
+pub fn sys_mmap(
+    _addr: UserVirtualAddress,
+    len: u64,
+    prot: u64,
+    flags: u64,
+    fd: u64,
+    offset: u64,
+) -> Result<u64> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let backing = match flags {
+        MAP_SHARED if offset % bks::PAGE_SIZE == 0 => fs::mmap_fd(fd, offset)?,
+        flags if flags == MAP_PRIVATE | MAP_ANONYMOUS => Backing::Anonymous,
+        _ => return Err(Error::InvalidArgument),
+    };
+    ADDRESS_SPACE
+        .lock()
+        .map(len, prot & PROT_WRITE != 0, prot != PROT_NONE, backing)
+}
+
+/// # Memory Unmap
+/// `munmap(addr, len)`, unmaps every page in the range
+///
+/// ## Returns
+/// - Error::InvalidArgument = `addr` is not page aligned or `len` is zero
+pub fn sys_munmap(addr: UserVirtualAddress, len: u64) -> Result<i32> {
+    ADDRESS_SPACE.lock().unmap(addr.as_u64(), len).map(|_| 0)
+}
+
+/// # Memory In Core
+/// `mincore(addr, len, vec)`, writes a byte for every page in the range to `vec`: 1 if the page
+/// has a frame mapped, 0 if not
+///
+/// ## Returns
+/// - Error::InvalidArgument = `addr` is not page aligned or `len` is zero
+/// - Error::OutOfMemory = A page in the range is not part of a mapping
+pub fn sys_mincore(addr: UserVirtualAddress, len: u64, vec: UserVirtualAddress) -> Result<i32> {
+    let start = addr.as_u64();
+    if start % bks::PAGE_SIZE != 0 || len == 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let pages = (len + bks::PAGE_SIZE - 1) / bks::PAGE_SIZE;
+    usermem::check_range(vec, pages as usize)?;
+    let residency = {
+        let space = ADDRESS_SPACE.lock();
+        let pml4 = paging::active_pml4();
+        (0..pages)
+            .map(|page| {
+                let page = start + page * bks::PAGE_SIZE;
+                space
+                    .region(page)
+                    .map(|_| vmm::is_resident(pml4, page) as u8)
+                    .ok_or(Error::OutOfMemory)
+            })
+            .collect::<Result<Vec<u8>>>()?
+    };
+    usermem::copy_to_user(vec, &residency).map(|_| 0)
+}
+
+/// # Shared Memory Open
+/// `shm_open(name, flags, size)`, opens the shared memory object `name` and returns a
+/// descriptor for it. With `O_CREAT` an object of `size` bytes is created if there is none.
+pub fn sys_shm_open(name: UserVirtualAddress, flags: u64, size: u64) -> Result<i32> {
+    let name = usermem::read_user_c_str(name, SHM_PATH_MAX)?;
+    let object = shm::open(&name, flags, size)?;
+    fs::shared_memory_fd(object).map(|fd| fd as i32)
+}
+
+/// # Shared Memory Unlink
+/// `shm_unlink(name)`, removes the name of a shared memory object. Its mappings stay.
+pub fn sys_shm_unlink(name: UserVirtualAddress) -> Result<i32> {
+    let name = usermem::read_user_c_str(name, SHM_PATH_MAX)?;
+    shm::unlink(&name).map(|_| 0)
+}
@@ -1,4 +1,45 @@
+use alloc::vec;
+
 use crate::arch::interrupts::register::Registers;
+use crate::error::{Error, Result, UnixError};
+use crate::fs;
+use crate::memory::usermem::{self, user_address};
+use crate::memory::UserVirtualAddress;
+use crate::net::ipv4::IpProtocol;
+use crate::net::udp::{self, Endpoint, UdpSocket};
+use crate::net::{self, Ipv4Address};
+use crate::power;
+use crate::scheduler;
+use crate::time::{self, Timespec};
+
+pub mod abi;
+pub mod futex;
+pub mod ioperm;
+pub mod mman;
+pub mod random;
+pub mod sched;
+pub mod signal;
+pub mod stat;
+pub mod sysinfo;
+pub mod trace;
+pub mod uname;
+pub mod vmstat;
+
+/// Paths passed to system calls may not be longer than this, including the terminating NUL
+pub const PATH_MAX: usize = 4096;
+/// The most bytes a single `read()` returns
+pub const READ_MAX: usize = 0x1_0000;
+/// The IPv4 address family
+pub const AF_INET: u64 = 2;
+/// Datagram sockets, which are always UDP
+pub const SOCK_DGRAM: u64 = 2;
+/// Part of the type passed to `socket()`: Operations on the socket never block
+pub const SOCK_NONBLOCK: u64 = 0o4000;
+/// A flag of `recvfrom()`: Do not block for this call only
+pub const MSG_DONTWAIT: u64 = 0x40;
+
+pub use ::abi::reboot::{RebootCommand, REBOOT_MAGIC1, REBOOT_MAGIC2};
+pub use ::abi::SyscallNumber;
 
 pub fn syscall(
     rax: u64,
@@ -11,5 +52,285 @@ pub fn syscall(
     rbp: u64,
     regs: &mut Registers,
 ) -> u64 {
-    22
+    crate::trace_event!(SyscallEntry, rax, rdi);
+    let traced = scheduler::is_current_traced();
+    if traced {
+        trace::entry(rax, &[rdi, rsi, rdx, r10, r8, r9]);
+    }
+    let value = match rax {
+        // The only call that returns an address, which does not fit the `i32` of the others
+        SyscallNumber::Mmap => UnixError::encode_address(
+            user_address(rdi).and_then(|addr| mman::sys_mmap(addr, rsi, rdx, r10, r8, r9)),
+        ),
+        _ => UnixError::encode(dispatch(rax, rdi, rsi, rdx, r10, r8, r9)) as i64 as u64,
+    };
+    if traced {
+        trace::exit(rax, value);
+    }
+    crate::trace_event!(SyscallExit, rax, value);
+    scheduler::signal::handle_pending();
+    value
+}
+
+/// # Dispatch
+/// Runs the system call `rax`. Pointers are turned into `UserVirtualAddress`es before the
+/// handler is called, so one into the kernel half fails with `Error::BadFault` right away.
+fn dispatch(rax: u64, rdi: u64, rsi: u64, rdx: u64, r10: u64, r8: u64, r9: u64) -> Result<i32> {
+    let user = user_address;
+    match rax {
+        SyscallNumber::Read => sys_read(rdi, user(rsi)?, rdx as usize),
+        SyscallNumber::Open => sys_open(user(rdi)?),
+        SyscallNumber::Close => sys_close(rdi),
+        SyscallNumber::Stat => stat::sys_stat(user(rdi)?, user(rsi)?),
+        SyscallNumber::Fstat => stat::sys_fstat(rdi, user(rsi)?),
+        SyscallNumber::Munmap => mman::sys_munmap(user(rdi)?, rsi),
+        SyscallNumber::Ioctl => sys_ioctl(rdi, rsi, rdx),
+        SyscallNumber::Mincore => mman::sys_mincore(user(rdi)?, rsi, user(rdx)?),
+        SyscallNumber::NanoSleep => sys_nanosleep(user(rdi)?, user(rsi)?),
+        SyscallNumber::Socket => sys_socket(rdi, rsi, rdx),
+        SyscallNumber::Bind => sys_bind(rdi, user(rsi)?, rdx as usize),
+        SyscallNumber::Uname => uname::sys_uname(user(rdi)?),
+        SyscallNumber::GetDents => stat::sys_getdents(rdi, user(rsi)?, rdx as usize),
+        SyscallNumber::SendTo => sys_sendto(rdi, user(rsi)?, rdx as usize, user(r8)?, r9 as usize),
+        SyscallNumber::RecvFrom => {
+            sys_recvfrom(rdi, user(rsi)?, rdx as usize, r10, user(r8)?, user(r9)?)
+        }
+        SyscallNumber::SysInfo => sysinfo::sys_sysinfo(user(rdi)?),
+        SyscallNumber::Reboot => sys_reboot(rdi, rsi, rdx),
+        SyscallNumber::Ioperm => ioperm::sys_ioperm(rdi, rsi, rdx),
+        SyscallNumber::Futex => futex::sys_futex(user(rdi)?, rsi, rdx, r10),
+        SyscallNumber::GetRandom => random::sys_getrandom(user(rdi)?, rsi as usize, rdx),
+        SyscallNumber::ShmOpen => mman::sys_shm_open(user(rdi)?, rsi, rdx),
+        SyscallNumber::ShmUnlink => mman::sys_shm_unlink(user(rdi)?),
+        SyscallNumber::ApiVersion => abi::sys_api_version(),
+        SyscallNumber::TraceDump => sys_trace_dump(),
+        SyscallNumber::VmStat => vmstat::sys_vmstat(rdi, user(rsi)?),
+        SyscallNumber::Nice => sched::sys_nice(rdi),
+        SyscallNumber::Kill => signal::sys_kill(rdi, rsi),
+        SyscallNumber::SetPgid => signal::sys_setpgid(rdi, rsi),
+        SyscallNumber::GetPgid => signal::sys_getpgid(rdi),
+        SyscallNumber::SetSid => signal::sys_setsid(),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// # Open
+/// `open(path)`, returns the new file descriptor
+fn sys_open(path: UserVirtualAddress) -> Result<i32> {
+    let path = usermem::read_user_c_str(path, PATH_MAX)?;
+    fs::open_fd(&path).map(|fd| fd as i32)
+}
+
+/// # Read
+/// `read(fd, buf, count)`, returns the number of bytes read. At most `READ_MAX` bytes are read
+/// at once.
+fn sys_read(fd: u64, buf: UserVirtualAddress, count: usize) -> Result<i32> {
+    usermem::check_range(buf, count)?;
+    let mut data = vec![0u8; count.min(READ_MAX)];
+    let read = fs::read_fd(fd, &mut data)?;
+    usermem::copy_to_user(buf, &data[..read])?;
+    Ok(read as i32)
+}
+
+/// # Ioctl
+/// `ioctl(fd, request, arg)`, handled by the file behind `fd`: The console takes the
+/// `TtyRequest`s, `/dev/fb0` the `FbRequest`s. `arg` is a pointer for the requests that pass
+/// a struct.
+///
+/// ## Returns
+/// - Error::NotTTY = The file takes no requests
+/// - Error::InvalidArgument = The file does not know the request
+fn sys_ioctl(fd: u64, request: u64, arg: u64) -> Result<i32> {
+    fs::ioctl_fd(fd, request, arg)
+}
+
+/// # Close
+/// `close(fd)`
+fn sys_close(fd: u64) -> Result<i32> {
+    fs::close_fd(fd).map(|_| 0)
+}
+
+/// # Reboot Command
+/// The `RebootCommand` of a call to `reboot(magic1, magic2, cmd)`
+///
+/// ## Returns
+/// - Error::InvalidArgument = A magic number is wrong, or `cmd` is not a `RebootCommand`
+pub fn reboot_command(magic1: u64, magic2: u64, cmd: u64) -> Result<u64> {
+    if magic1 != REBOOT_MAGIC1 || magic2 != REBOOT_MAGIC2 {
+        return Err(Error::InvalidArgument);
+    }
+    match cmd {
+        RebootCommand::Restart | RebootCommand::Halt | RebootCommand::PowerOff => Ok(cmd),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// # Reboot
+/// `reboot(magic1, magic2, cmd)`, restarts, halts or powers off the machine, after flushing the
+/// log, stopping the other CPUs and shutting down the devices. Only returns on invalid
+/// arguments, a machine that cannot power off is halted.
+pub fn sys_reboot(magic1: u64, magic2: u64, cmd: u64) -> Result<i32> {
+    match reboot_command(magic1, magic2, cmd)? {
+        RebootCommand::Restart => power::reboot(),
+        RebootCommand::Halt => power::halt(),
+        _ => power::shutdown(),
+    }
+}
+
+/// # Trace Dump
+/// `trace_dump()`, writes the event trace to COM1 and returns the number of records
+///
+/// ## Returns
+/// - Error::InvalidArgument = The kernel was built without the `trace` feature, which makes
+///   the call as unknown as any other it does not have
+fn sys_trace_dump() -> Result<i32> {
+    if !crate::trace::ENABLED {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(crate::trace::dump() as i32)
+}
+
+/// # Nano Sleep
+/// `nanosleep(req, rem)`, sleeps for the `Timespec` at `req` rounded up to milliseconds. Sleeps
+/// are never interrupted, so the `Timespec` at `rem`, if any, is zeroed.
+///
+/// ## Returns
+/// - Error::InvalidArgument = The time is negative or its nanoseconds are out of range
+fn sys_nanosleep(req: UserVirtualAddress, rem: UserVirtualAddress) -> Result<i32> {
+    let millis = usermem::read_user::<Timespec>(req)?.to_ms()?;
+    time::sleep_ms(millis);
+    if !rem.is_null() {
+        usermem::write_user(rem, Timespec::default())?;
+    }
+    Ok(0)
+}
+
+/// # Sockaddr In
+/// `struct sockaddr_in`, the port and the address are in network byte order
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SockAddrIn {
+    pub family: u16,
+    pub port: [u8; 2],
+    pub address: [u8; 4],
+    pub zero: [u8; 8],
+}
+
+impl SockAddrIn {
+    /// # Read
+    /// Reads the address at the user address `ptr`, which is `len` bytes long
+    fn read(ptr: UserVirtualAddress, len: usize) -> Result<Endpoint> {
+        usermem::check_range(ptr, len)?;
+        if len < core::mem::size_of::<Self>() {
+            return Err(Error::InvalidArgument);
+        }
+        let addr = usermem::read_user::<Self>(ptr)?;
+        if addr.family as u64 != AF_INET {
+            return Err(Error::AddressFamilyNotSupportedByProtocol);
+        }
+        Ok(Endpoint {
+            address: Ipv4Address(addr.address),
+            port: u16::from_be_bytes(addr.port),
+        })
+    }
+
+    fn new(endpoint: Endpoint) -> Self {
+        Self {
+            family: AF_INET as u16,
+            port: endpoint.port.to_be_bytes(),
+            address: endpoint.address.0,
+            zero: [0; 8],
+        }
+    }
+}
+
+/// # Socket
+/// `socket(domain, type, protocol)`, only UDP sockets (`AF_INET`, `SOCK_DGRAM`) are supported
+fn sys_socket(domain: u64, ty: u64, protocol: u64) -> Result<i32> {
+    if domain != AF_INET {
+        return Err(Error::AddressFamilyNotSupportedByProtocol);
+    }
+    if ty & !SOCK_NONBLOCK != SOCK_DGRAM || (protocol != 0 && protocol != IpProtocol::Udp as u64) {
+        return Err(Error::ProtocolNotSupported);
+    }
+    let socket = UdpSocket::new();
+    socket.set_nonblocking(ty & SOCK_NONBLOCK != 0);
+    fs::socket_fd(socket).map(|fd| fd as i32)
+}
+
+/// # Bind
+/// `bind(fd, addr, addrlen)`, a port of zero picks a free one
+fn sys_bind(fd: u64, addr: UserVirtualAddress, len: usize) -> Result<i32> {
+    let endpoint = SockAddrIn::read(addr, len)?;
+    let socket = fs::socket(fd)?;
+    // Sockets receive on every interface, so only the unspecified address or an address of an
+    // interface can be bound
+    if endpoint.address != Ipv4Address::UNSPECIFIED
+        && !net::interfaces()
+            .iter()
+            .any(|interface| interface.ip() == Some(endpoint.address))
+    {
+        return Err(Error::CannotAssignRequestAddress);
+    }
+    socket.bind(endpoint.port).map(|_| 0)
+}
+
+/// # Send To
+/// `sendto(fd, buf, len, flags, dest_addr, addrlen)`, returns the number of bytes sent
+fn sys_sendto(
+    fd: u64,
+    buf: UserVirtualAddress,
+    len: usize,
+    addr: UserVirtualAddress,
+    addr_len: usize,
+) -> Result<i32> {
+    if buf.is_null() && len != 0 {
+        return Err(Error::BadFault);
+    }
+    if addr.is_null() {
+        return Err(Error::DestinationAddressRequired);
+    }
+    let destination = SockAddrIn::read(addr, addr_len)?;
+    let socket = fs::socket(fd)?;
+    if len > udp::MAX_PAYLOAD {
+        return Err(Error::MessageTooLong);
+    }
+    let mut data = vec![0u8; len];
+    usermem::copy_from_user(&mut data, buf)?;
+    socket.send_to(&data, destination).map(|sent| sent as i32)
+}
+
+/// # Receive From
+/// `recvfrom(fd, buf, len, flags, src_addr, addrlen)`, returns the number of bytes received.
+/// Blocks until a datagram arrives unless the socket is non-blocking or `MSG_DONTWAIT` is set.
+fn sys_recvfrom(
+    fd: u64,
+    buf: UserVirtualAddress,
+    len: usize,
+    flags: u64,
+    addr: UserVirtualAddress,
+    addr_len: UserVirtualAddress,
+) -> Result<i32> {
+    if buf.is_null() && len != 0 {
+        return Err(Error::BadFault);
+    }
+    let socket = fs::socket(fd)?;
+    // No datagram is larger than the largest payload
+    let mut data = vec![0u8; len.min(udp::MAX_PAYLOAD)];
+    let (received, source) = socket.receive_from(&mut data, flags & MSG_DONTWAIT != 0)?;
+    usermem::copy_to_user(buf, &data[..received])?;
+    if !addr.is_null() {
+        if addr_len.is_null() {
+            return Err(Error::BadFault);
+        }
+        // The address is truncated to the space the caller has, its full size is reported
+        let source = SockAddrIn::new(source);
+        let size = core::mem::size_of::<SockAddrIn>();
+        let space = (usermem::read_user::<u32>(addr_len)? as usize).min(size);
+        let bytes =
+            unsafe { core::slice::from_raw_parts(&source as *const SockAddrIn as *const u8, size) };
+        usermem::copy_to_user(addr, &bytes[..space])?;
+        usermem::write_user(addr_len, size as u32)?;
+    }
+    Ok(received as i32)
 }
@@ -0,0 +1,59 @@
+//! # Random
+//! `getrandom(buf, len, flags)` hands out the output of `rand`. Like on Linux, it waits until
+//! the generator is seeded unless `GRND_NONBLOCK` or `GRND_INSECURE` is given.
+use alloc::vec;
+
+use crate::error::{Error, Result};
+use crate::memory::usermem;
+use crate::memory::UserVirtualAddress;
+use crate::rand;
+use crate::time;
+
+pub use abi::random::{GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM};
+
+/// The most bytes a single `getrandom()` returns
+pub const GETRANDOM_MAX: usize = 0x1_0000;
+/// The bytes generated at a time, so the generator is not locked for long
+const CHUNK_SIZE: usize = 256;
+/// How often a blocking call checks whether the generator is seeded
+const READY_POLL_MS: u64 = 100;
+
+/// # Check Flags
+/// Whether the caller may be handed random bytes now
+///
+/// ## Returns
+/// - Error::InvalidArgument = `flags` has unknown bits, or both `GRND_RANDOM` and `GRND_INSECURE`
+/// - Error::TryAgain = The generator is not seeded and `GRND_NONBLOCK` is given
+pub fn check_flags(flags: u64) -> Result<()> {
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0
+        || flags & (GRND_RANDOM | GRND_INSECURE) == GRND_RANDOM | GRND_INSECURE
+    {
+        return Err(Error::InvalidArgument);
+    }
+    if flags & GRND_INSECURE != 0 || rand::is_ready() {
+        return Ok(());
+    }
+    if flags & GRND_NONBLOCK != 0 {
+        return Err(Error::TryAgain);
+    }
+    while !rand::is_ready() {
+        time::sleep_ms(READY_POLL_MS);
+    }
+    Ok(())
+}
+
+/// # Get Random
+/// `getrandom(buf, len, flags)`, fills `buf` with up to `GETRANDOM_MAX` random bytes
+///
+/// ## Returns
+/// - i32 = The number of bytes written
+pub fn sys_getrandom(buf: UserVirtualAddress, len: usize, flags: u64) -> Result<i32> {
+    check_flags(flags)?;
+    usermem::check_range(buf, len)?;
+    let mut data = vec![0u8; len.min(GETRANDOM_MAX)];
+    for chunk in data.chunks_mut(CHUNK_SIZE) {
+        rand::fill(chunk);
+    }
+    usermem::copy_to_user(buf, &data)?;
+    Ok(data.len() as i32)
+}
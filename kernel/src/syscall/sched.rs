@@ -0,0 +1,25 @@
+//! # Sched
+//! The system calls that tune how the calling task is scheduled
+use crate::error::Result;
+use crate::scheduler;
+
+/// # Nice
+/// `nice(inc)`, adds `inc` to the nice value of the calling task, clamped to
+/// `NICE_MIN..=NICE_MAX`. A lower value gets the task a larger share of the CPU with the fair
+/// class, see `scheduler::class`.
+///
+/// ## Returns
+/// - i32 = `20 - nice` of the new nice value, so it is never mistaken for an errno, like the
+///   nice system call of Linux returns it
+///
+/// ## Notes
+/// Anyone may lower their nice value for now, it becomes a privilege of root once there are
+/// users
+pub fn sys_nice(inc: u64) -> Result<i32> {
+    let id = scheduler::current();
+    let nice = scheduler::nice(id)?;
+    // Out of range increments only clamp
+    let inc = (inc as i64).clamp(-40, 40) as i8;
+    let nice = scheduler::set_nice(id, nice.saturating_add(inc))?;
+    Ok(20 - nice as i32)
+}
@@ -0,0 +1,75 @@
+//! # Signal
+//! `kill()` and the calls that move tasks between process groups and sessions, see
+//! `scheduler::signal`. A pid of zero is the calling task, or its group for `kill()`.
+use crate::error::{Error, Result};
+use crate::scheduler::{self, signal, TaskId};
+
+/// The pid, zero for the calling task
+fn task(pid: u64) -> Result<TaskId> {
+    match pid as i64 {
+        0 => Ok(scheduler::current()),
+        pid if pid > 0 => Ok(TaskId::new(pid as u64)),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// # Kill
+/// `kill(pid, sig)`, sends `sig` to the task `pid`, to the process group `-pid` for a negative
+/// `pid` or to the group of the caller for zero. A `sig` of zero only checks whether there is
+/// someone to send it to.
+///
+/// ## Returns
+/// - Error::InvalidArgument = `sig` is not a signal, or `pid` is -1, which would be every task
+/// - Error::NoSuchProcess = No task that did not exit is the task or in the group
+pub fn sys_kill(pid: u64, sig: u64) -> Result<i32> {
+    let signal = u32::try_from(sig).map_err(|_| Error::InvalidArgument)?;
+    signal::check(signal)?;
+    match pid as i64 {
+        0 => signal::send_group(signal::pgid(scheduler::current())?, signal)?,
+        -1 => return Err(Error::InvalidArgument),
+        pid if pid < 0 => signal::send_group(TaskId::new(pid.unsigned_abs()), signal)?,
+        pid => {
+            signal::send(TaskId::new(pid as u64), signal)?;
+            1
+        }
+    };
+    Ok(0)
+}
+
+/// # Set Pgid
+/// `setpgid(pid, pgid)`, moves the task `pid` into the group `pgid`, a group of its own if
+/// `pgid` is zero
+///
+/// ## Returns
+/// - Error::InvalidArgument = `pid` or `pgid` is negative
+/// - Error::NoSuchProcess = There is no task `pid`
+/// - Error::OperationNotPermitted = The task leads its session, or the group is not in it
+pub fn sys_setpgid(pid: u64, pgid: u64) -> Result<i32> {
+    let id = task(pid)?;
+    let pgid = match pgid as i64 {
+        0 => id,
+        pgid if pgid > 0 => TaskId::new(pgid as u64),
+        _ => return Err(Error::InvalidArgument),
+    };
+    signal::set_pgid(id, pgid).map(|_| 0)
+}
+
+/// # Get Pgid
+/// `getpgid(pid)`, returns the process group of the task `pid`
+///
+/// ## Returns
+/// - Error::InvalidArgument = `pid` is negative
+/// - Error::NoSuchProcess = There is no task `pid`
+pub fn sys_getpgid(pid: u64) -> Result<i32> {
+    signal::pgid(task(pid)?).map(|pgid| pgid.inner() as i32)
+}
+
+/// # Set Sid
+/// `setsid()`, makes the caller the leader of a new session and a new group, returns the
+/// session
+///
+/// ## Returns
+/// - Error::OperationNotPermitted = The caller leads a process group already
+pub fn sys_setsid() -> Result<i32> {
+    signal::set_sid(scheduler::current()).map(|sid| sid.inner() as i32)
+}
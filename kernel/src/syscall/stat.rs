@@ -0,0 +1,151 @@
+//! # Stat
+//! `stat(path, buf)` and `fstat(fd, buf)` fill a `Stat` with the metadata of a file,
+//! `getdents(fd, buf, len)` lists a directory in `Dirent` records. Every filesystem is
+//! read-only, so files are never writable and the times are all the time of the last change.
+use alloc::vec;
+use memoffset::offset_of;
+
+use super::{PATH_MAX, READ_MAX};
+use crate::error::{Error, Result};
+use crate::fs::{self, DirEntry, FileType, Metadata};
+use crate::memory::usermem;
+use crate::memory::UserVirtualAddress;
+
+pub use ::abi::dirent::{DT_CHR, DT_DIR, DT_REG, DT_SOCK};
+pub use ::abi::stat::{S_IFCHR, S_IFDIR, S_IFMT, S_IFREG, S_IFSOCK};
+
+/// The block size `Stat::blocks` counts in
+pub const STAT_BLOCK_SIZE: u64 = 512;
+
+/// # Stat
+/// The struct `stat()` and `fstat()` fill
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub blksize: u32,
+    pub blocks: u64,
+    pub mtime: u64,
+    pub mtime_nsec: u32,
+    pub atime: u64,
+    pub atime_nsec: u32,
+    pub ctime: u64,
+    pub ctime_nsec: u32,
+}
+
+impl From<Metadata> for Stat {
+    fn from(metadata: Metadata) -> Self {
+        let mode = match metadata.ty {
+            FileType::File => S_IFREG | 0o444,
+            FileType::Directory => S_IFDIR | 0o555,
+            FileType::CharacterDevice => S_IFCHR | 0o666,
+            FileType::Socket => S_IFSOCK | 0o666,
+        };
+        // Times before 1970 are not representable
+        let time = metadata.mtime.unwrap_or(0).max(0) as u64;
+        Self {
+            ino: metadata.inode,
+            mode,
+            nlink: 1,
+            size: metadata.size,
+            blksize: bks::PAGE_SIZE as u32,
+            blocks: (metadata.size + STAT_BLOCK_SIZE - 1) / STAT_BLOCK_SIZE,
+            mtime: time,
+            atime: time,
+            ctime: time,
+            ..Self::default()
+        }
+    }
+}
+
+/// # Dirent
+/// The head of a record `getdents()` fills, followed by the NUL terminated name
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dirent {
+    pub ino: u64,
+    pub reclen: u16,
+    pub ty: u8,
+    pub name: [u8; 0],
+}
+
+impl Dirent {
+    /// # Record Length
+    /// The length of the record for a name of `len` bytes, rounded up to keep the next one
+    /// aligned
+    pub fn record_len(len: usize) -> usize {
+        let align = core::mem::align_of::<Self>();
+        (offset_of!(Self, name) + len + 1 + align - 1) / align * align
+    }
+
+    /// # Encode
+    /// Writes the record for `entry` to the start of `buf`, which has to be long enough
+    fn encode(entry: &DirEntry, buf: &mut [u8]) -> usize {
+        let len = Self::record_len(entry.name.len());
+        let ty = match entry.metadata.ty {
+            FileType::File => DT_REG,
+            FileType::Directory => DT_DIR,
+            FileType::CharacterDevice => DT_CHR,
+            FileType::Socket => DT_SOCK,
+        };
+        let (ino, reclen, name) = (
+            offset_of!(Self, ino),
+            offset_of!(Self, reclen),
+            offset_of!(Self, name),
+        );
+        buf[..len].fill(0);
+        buf[ino..ino + 8].copy_from_slice(&entry.metadata.inode.to_ne_bytes());
+        buf[reclen..reclen + 2].copy_from_slice(&(len as u16).to_ne_bytes());
+        buf[offset_of!(Self, ty)] = ty;
+        buf[name..name + entry.name.len()].copy_from_slice(entry.name.as_bytes());
+        len
+    }
+}
+
+/// # Stat
+/// `stat(path, buf)`, fills the `Stat` at `buf` for the file at `path`
+pub fn sys_stat(path: UserVirtualAddress, buf: UserVirtualAddress) -> Result<i32> {
+    let path = usermem::read_user_c_str(path, PATH_MAX)?;
+    usermem::write_user(buf, Stat::from(fs::stat(&path)?))?;
+    Ok(0)
+}
+
+/// # Fstat
+/// `fstat(fd, buf)`, fills the `Stat` at `buf` for the file behind `fd`
+pub fn sys_fstat(fd: u64, buf: UserVirtualAddress) -> Result<i32> {
+    usermem::write_user(buf, Stat::from(fs::stat_fd(fd)?))?;
+    Ok(0)
+}
+
+/// # Get Dents
+/// `getdents(fd, buf, len)`, fills `buf` with as many `Dirent` records as fit, from the offset
+/// of `fd` on, and advances the offset past them. At most `READ_MAX` bytes are filled at once.
+///
+/// ## Returns
+/// - i32 = The number of bytes filled, 0 at the end of the directory
+/// - Error::InvalidArgument = Not even the next record fits into `len` bytes
+pub fn sys_getdents(fd: u64, buf: UserVirtualAddress, len: usize) -> Result<i32> {
+    usermem::check_range(buf, len)?;
+    let mut data = vec![0u8; len.min(READ_MAX)];
+    let mut filled = 0;
+    let mut too_small = false;
+    fs::read_dir_fd(fd, |entry| {
+        if filled + Dirent::record_len(entry.name.len()) > data.len() {
+            too_small = filled == 0;
+            return false;
+        }
+        filled += Dirent::encode(entry, &mut data[filled..]);
+        true
+    })?;
+    if too_small {
+        return Err(Error::InvalidArgument);
+    }
+    usermem::copy_to_user(buf, &data[..filled])?;
+    Ok(filled as i32)
+}
@@ -0,0 +1,196 @@
+//! # System Information
+//! `sysinfo(info)` tells userspace, e.g. an allocator sizing its arenas, how much memory there
+//! is. The caller sets `SysInfo::size` to the size of the struct it knows, the kernel fills
+//! what fits and marks the fields it filled in `SysInfo::valid`. Fields are only ever appended,
+//! so old binaries keep working and new ones see which fields an old kernel left out.
+use bks::PAGE_SIZE;
+use memoffset::offset_of;
+
+use crate::error::{Error, Result};
+use crate::heap::tag::{self, TagUsage};
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::usermem;
+use crate::memory::UserVirtualAddress;
+use crate::scheduler::{self, idle, TaskState};
+use crate::smp;
+use crate::time;
+
+bitflags::bitflags! {
+    /// The fields of a `SysInfo` the kernel filled
+    #[repr(transparent)]
+    pub struct SysInfoFields: u64 {
+        const TOTAL_RAM = 1;
+        const FREE_RAM = 1 << 1;
+        const UPTIME_SECS = 1 << 2;
+        const PROCS = 1 << 3;
+        const PAGE_SIZE = 1 << 4;
+        const HEAP_BYTES = 1 << 5;
+        const TOP_CONSUMERS = 1 << 6;
+        const IDLE_PERMILLE = 1 << 7;
+    }
+}
+
+pub use abi::sysinfo::{SYSINFO_TAG_NAME_LEN, SYSINFO_TOP_CONSUMERS};
+
+/// # Sys Info Tag
+/// The heap usage of an allocation tag
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SysInfoTag {
+    /// The name of the tag, padded with zeroes. An unused entry has an empty name.
+    pub name: [u8; SYSINFO_TAG_NAME_LEN],
+    pub bytes: u64,
+    /// The number of live allocations
+    pub count: u64,
+}
+
+impl SysInfoTag {
+    fn new(usage: &TagUsage) -> Self {
+        let mut name = [0; SYSINFO_TAG_NAME_LEN];
+        let len = usage.name.len().min(SYSINFO_TAG_NAME_LEN);
+        name[..len].copy_from_slice(&usage.name.as_bytes()[..len]);
+        Self {
+            name,
+            bytes: usage.bytes,
+            count: usage.count,
+        }
+    }
+
+    /// # Name
+    /// The name without its padding
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(SYSINFO_TAG_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+/// # Sys Info
+/// The struct `sysinfo()` fills, its layout never changes
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SysInfo {
+    /// The size of the struct as the caller knows it, set by the caller
+    pub size: u32,
+    pub _reserved: u32,
+    /// The `SysInfoFields` that are valid
+    pub valid: u64,
+    /// The usable RAM in bytes
+    pub total_ram: u64,
+    /// The RAM no one allocated in bytes
+    pub free_ram: u64,
+    /// The seconds since boot
+    pub uptime_secs: u64,
+    /// The number of tasks that did not exit
+    pub procs: u64,
+    pub page_size: u64,
+    /// The bytes of all live heap allocations
+    pub heap_bytes: u64,
+    /// The allocation tags using the most heap, the largest first
+    pub top_consumers: [SysInfoTag; SYSINFO_TOP_CONSUMERS],
+    /// The time all online CPUs were idle since they joined the scheduler, in tenths of a
+    /// percent
+    pub idle_permille: u64,
+}
+
+/// Every field after the header, with the offset of its end
+const FIELDS: [(SysInfoFields, usize); 8] = [
+    (SysInfoFields::TOTAL_RAM, offset_of!(SysInfo, total_ram) + 8),
+    (SysInfoFields::FREE_RAM, offset_of!(SysInfo, free_ram) + 8),
+    (
+        SysInfoFields::UPTIME_SECS,
+        offset_of!(SysInfo, uptime_secs) + 8,
+    ),
+    (SysInfoFields::PROCS, offset_of!(SysInfo, procs) + 8),
+    (SysInfoFields::PAGE_SIZE, offset_of!(SysInfo, page_size) + 8),
+    (
+        SysInfoFields::HEAP_BYTES,
+        offset_of!(SysInfo, heap_bytes) + 8,
+    ),
+    (
+        SysInfoFields::TOP_CONSUMERS,
+        offset_of!(SysInfo, top_consumers)
+            + core::mem::size_of::<[SysInfoTag; SYSINFO_TOP_CONSUMERS]>(),
+    ),
+    (
+        SysInfoFields::IDLE_PERMILLE,
+        offset_of!(SysInfo, idle_permille) + 8,
+    ),
+];
+
+/// The size of `size`, `_reserved` and `valid`, the smallest struct a caller can pass
+pub const SYSINFO_HEADER_SIZE: usize = offset_of!(SysInfo, total_ram);
+
+/// # Sys Info
+/// The current values of every field
+pub fn sysinfo() -> SysInfo {
+    let (total_ram, free_ram) = {
+        let allocator = PAGE_FRAME_ALLOCATOR.lock();
+        let allocator = unsafe { allocator.assume_init_ref() };
+        let free = allocator.get_free_memory().max(0) as u64;
+        (free + allocator.get_used_memory().max(0) as u64, free)
+    };
+    let procs = scheduler::task_infos()
+        .iter()
+        .filter(|task| task.state != TaskState::Exited)
+        .count();
+    let mut top_consumers = [SysInfoTag::default(); SYSINFO_TOP_CONSUMERS];
+    for (entry, usage) in top_consumers.iter_mut().zip(tag::usages().iter()) {
+        *entry = SysInfoTag::new(usage);
+    }
+    SysInfo {
+        size: core::mem::size_of::<SysInfo>() as u32,
+        _reserved: 0,
+        valid: SysInfoFields::all().bits(),
+        total_ram,
+        free_ram,
+        uptime_secs: time::uptime_ns() / 1_000_000_000,
+        procs: procs as u64,
+        page_size: PAGE_SIZE,
+        heap_bytes: tag::total(),
+        top_consumers,
+        idle_permille: idle_permille(),
+    }
+}
+
+/// # Idle Permille
+/// The idle time of all online CPUs together, in tenths of a percent
+fn idle_permille() -> u64 {
+    let (idle, total) = smp::online_cpus()
+        .map(idle::stats)
+        .fold((0, 0), |(idle, total), stats| {
+            (idle + stats.idle_cycles, total + stats.total_cycles)
+        });
+    idle::IdleStats {
+        idle_cycles: idle,
+        total_cycles: total,
+        ..idle::IdleStats::default()
+    }
+    .idle_permille()
+}
+
+/// # Sys Info
+/// `sysinfo(info)`, fills as much of the `SysInfo` at `info` as its `size` allows
+///
+/// ## Returns
+/// - Error::InvalidArgument = `size` is smaller than the header
+pub fn sys_sysinfo(ptr: UserVirtualAddress) -> Result<i32> {
+    let size = usermem::read_user::<u32>(ptr)? as usize;
+    if size < SYSINFO_HEADER_SIZE {
+        return Err(Error::InvalidArgument);
+    }
+    let mut info = sysinfo();
+    let size = size.min(core::mem::size_of::<SysInfo>());
+    info.size = size as u32;
+    info.valid = FIELDS
+        .iter()
+        .filter(|(_, end)| *end <= size)
+        .fold(SysInfoFields::empty(), |valid, (field, _)| valid | *field)
+        .bits();
+    let bytes = unsafe { core::slice::from_raw_parts(&info as *const SysInfo as *const u8, size) };
+    usermem::copy_to_user(ptr, bytes)?;
+    Ok(0)
+}
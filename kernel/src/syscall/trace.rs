@@ -0,0 +1,277 @@
+//! # Trace
+//! Logs the system calls of traced tasks at debug level, like `strace`: The call with its
+//! decoded arguments on entry, the return value or the errno on exit. How the arguments are
+//! decoded comes from `SYSCALLS`, a system call without an entry gets its raw registers logged.
+//!
+//! Each line is formatted before it is logged, so no user memory is read while the console is
+//! locked, and a traced write to the console does not deadlock with its own trace.
+use alloc::string::{String, ToString};
+use core::fmt::Write;
+
+use super::SyscallNumber;
+use crate::debug;
+use crate::error::ErrorCode;
+use crate::memory::usermem;
+use crate::scheduler::{self, TaskId};
+
+/// The longest user string that is logged, longer ones are cut off
+pub const TRACE_STRING_MAX: usize = 64;
+/// The number of registers system calls take their arguments in
+pub const SYSCALL_ARGS: usize = 6;
+
+/// # Arg Kind
+/// How an argument of a system call is logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    Fd,
+    /// A signed number
+    Int,
+    /// A length or count
+    Size,
+    /// An address, the memory is not read
+    Pointer,
+    /// A NUL terminated string in user memory
+    Path,
+    /// Bit flags, logged in hex
+    Flags,
+}
+
+/// How the arguments of a system call without metadata are logged
+const RAW_ARGS: &[ArgKind] = &[ArgKind::Pointer; SYSCALL_ARGS];
+
+/// # Syscall Meta
+/// The arguments a system call takes
+pub struct SyscallMeta {
+    pub number: u64,
+    pub args: &'static [ArgKind],
+}
+
+/// Every system call whose arguments are decoded
+pub static SYSCALLS: &[SyscallMeta] = {
+    use ArgKind::*;
+    &[
+        SyscallMeta {
+            number: SyscallNumber::Read,
+            args: &[Fd, Pointer, Size],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Write,
+            args: &[Fd, Pointer, Size],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Open,
+            args: &[Path],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Close,
+            args: &[Fd],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Stat,
+            args: &[Path, Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Fstat,
+            args: &[Fd, Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Mmap,
+            args: &[Pointer, Size, Flags, Flags, Fd, Size],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Munmap,
+            args: &[Pointer, Size],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Ioctl,
+            args: &[Fd, Flags, Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Mincore,
+            args: &[Pointer, Size, Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::NanoSleep,
+            args: &[Pointer, Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Socket,
+            args: &[Int, Flags, Int],
+        },
+        SyscallMeta {
+            number: SyscallNumber::SendTo,
+            args: &[Fd, Pointer, Size, Flags, Pointer, Size],
+        },
+        SyscallMeta {
+            number: SyscallNumber::RecvFrom,
+            args: &[Fd, Pointer, Size, Flags, Pointer, Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Bind,
+            args: &[Fd, Pointer, Size],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Kill,
+            args: &[Int, Int],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Uname,
+            args: &[Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::GetDents,
+            args: &[Fd, Pointer, Size],
+        },
+        SyscallMeta {
+            number: SyscallNumber::SysInfo,
+            args: &[Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::SetPgid,
+            args: &[Int, Int],
+        },
+        SyscallMeta {
+            number: SyscallNumber::SetSid,
+            args: &[],
+        },
+        SyscallMeta {
+            number: SyscallNumber::GetPgid,
+            args: &[Int],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Reboot,
+            args: &[Flags, Flags, Flags],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Ioperm,
+            args: &[Int, Size, Int],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Futex,
+            args: &[Pointer, Int, Int, Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::GetRandom,
+            args: &[Pointer, Size, Flags],
+        },
+        SyscallMeta {
+            number: SyscallNumber::ShmOpen,
+            args: &[Path, Flags, Size],
+        },
+        SyscallMeta {
+            number: SyscallNumber::ShmUnlink,
+            args: &[Path],
+        },
+        SyscallMeta {
+            number: SyscallNumber::ApiVersion,
+            args: &[],
+        },
+        SyscallMeta {
+            number: SyscallNumber::TraceDump,
+            args: &[],
+        },
+        SyscallMeta {
+            number: SyscallNumber::VmStat,
+            args: &[Int, Pointer],
+        },
+        SyscallMeta {
+            number: SyscallNumber::Nice,
+            args: &[Int],
+        },
+    ]
+};
+
+/// # Meta
+/// How the arguments of the system call `number` are decoded, `None` if they are not
+pub fn meta(number: u64) -> Option<&'static SyscallMeta> {
+    SYSCALLS.iter().find(|meta| meta.number == number)
+}
+
+/// # Name
+/// The name of the system call `number`, e.g. `recvfrom`
+pub fn name(number: u64) -> String {
+    match SyscallNumber::try_from_value(number as usize) {
+        Some(number) => SyscallNumber::name(&number).to_lowercase(),
+        None => alloc::format!("syscall_{}", number),
+    }
+}
+
+fn format_arg(out: &mut String, kind: ArgKind, value: u64) {
+    let _ = match kind {
+        ArgKind::Fd | ArgKind::Int => write!(out, "{}", value as i64),
+        ArgKind::Size => write!(out, "{}", value),
+        ArgKind::Pointer if value == 0 => write!(out, "NULL"),
+        ArgKind::Pointer | ArgKind::Flags => write!(out, "{:#x}", value),
+        ArgKind::Path => {
+            let mut buf = [0; TRACE_STRING_MAX];
+            let copied = usermem::user_address(value)
+                .and_then(|addr| usermem::strncpy_from_user(&mut buf, addr));
+            match copied {
+                Ok(len) => {
+                    let text = String::from_utf8_lossy(&buf[..len]);
+                    let cut = if len == buf.len() { "..." } else { "" };
+                    write!(out, "{:?}{}", text, cut)
+                }
+                // Logged like the pointer it is
+                Err(_) => write!(out, "{:#x}", value),
+            }
+        }
+    };
+}
+
+/// # Format Entry
+/// The system call `number` with its arguments, e.g. `read(3, 0x1000, 512)`
+pub fn format_entry(number: u64, args: &[u64; SYSCALL_ARGS]) -> String {
+    let mut out = name(number);
+    out.push('(');
+    let kinds = meta(number).map_or(RAW_ARGS, |meta| meta.args);
+    for (idx, (kind, value)) in kinds.iter().zip(args).enumerate() {
+        if idx != 0 {
+            out.push_str(", ");
+        }
+        format_arg(&mut out, *kind, *value);
+    }
+    out.push(')');
+    out
+}
+
+/// # Format Result
+/// The value a system call returned, with the name of the errno if it failed, e.g. `-EBADF`
+pub fn format_result(value: u64) -> String {
+    let value = value as i64;
+    if value >= 0 {
+        return value.to_string();
+    }
+    match ErrorCode::try_from_value(-value as usize) {
+        Some(code) => alloc::format!("-{}", ErrorCode::name(&code)),
+        None => value.to_string(),
+    }
+}
+
+/// # Trace
+/// Starts or stops logging the system calls of the task `id`
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`
+pub fn trace(id: TaskId, enable: bool) -> crate::error::Result<()> {
+    scheduler::set_traced(id, enable)
+}
+
+/// # Entry
+/// Logs that the current task entered the system call `number`
+pub fn entry(number: u64, args: &[u64; SYSCALL_ARGS]) {
+    let line = format_entry(number, args);
+    debug!("strace: [{}] {}", scheduler::current().inner(), line);
+}
+
+/// # Exit
+/// Logs that the system call `number` of the current task returned `value`
+pub fn exit(number: u64, value: u64) {
+    let (name, result) = (name(number), format_result(value));
+    debug!(
+        "strace: [{}] {} = {}",
+        scheduler::current().inner(),
+        name,
+        result
+    );
+}
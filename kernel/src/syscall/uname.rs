@@ -0,0 +1,102 @@
+//! # Uname
+//! `uname(buf)` tells userspace which kernel it runs on, from the build info
+use core::fmt::Write;
+
+use crate::buildinfo;
+use crate::error::Result;
+use crate::memory::usermem;
+use crate::memory::UserVirtualAddress;
+
+pub use abi::utsname::UTSNAME_FIELD_LEN;
+
+/// What `Utsname::nodename` holds, as there are no host names
+pub const NODENAME: &str = "esque";
+/// What `Utsname::domainname` holds, like on a Linux without a domain
+pub const DOMAINNAME: &str = "(none)";
+
+/// # Utsname
+/// The struct `uname()` fills, its layout never changes
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Utsname {
+    /// The name of the kernel
+    pub sysname: [u8; UTSNAME_FIELD_LEN],
+    /// The name of the machine on the network
+    pub nodename: [u8; UTSNAME_FIELD_LEN],
+    /// The version of the kernel
+    pub release: [u8; UTSNAME_FIELD_LEN],
+    /// The build of the kernel: its commit and when it was built
+    pub version: [u8; UTSNAME_FIELD_LEN],
+    /// The architecture
+    pub machine: [u8; UTSNAME_FIELD_LEN],
+    pub domainname: [u8; UTSNAME_FIELD_LEN],
+}
+
+/// # Field
+/// A field that is written to, cutting off what does not fit before the terminating NUL
+struct Field<'a> {
+    bytes: &'a mut [u8; UTSNAME_FIELD_LEN],
+    len: usize,
+}
+
+impl Write for Field<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let len = s.len().min(UTSNAME_FIELD_LEN - 1 - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+fn field(args: core::fmt::Arguments) -> [u8; UTSNAME_FIELD_LEN] {
+    let mut bytes = [0; UTSNAME_FIELD_LEN];
+    let _ = Field {
+        bytes: &mut bytes,
+        len: 0,
+    }
+    .write_fmt(args);
+    bytes
+}
+
+impl Utsname {
+    /// # Field Str
+    /// A field without its terminating NUL
+    pub fn field_str(field: &[u8; UTSNAME_FIELD_LEN]) -> &str {
+        let len = field
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(UTSNAME_FIELD_LEN);
+        core::str::from_utf8(&field[..len]).unwrap_or("")
+    }
+}
+
+/// # Uname
+/// The `Utsname` of the running kernel
+pub fn uname() -> Utsname {
+    Utsname {
+        sysname: field(format_args!("{}", buildinfo::NAME)),
+        nodename: field(format_args!("{}", NODENAME)),
+        release: field(format_args!("{}", buildinfo::VERSION)),
+        version: field(format_args!(
+            "#{} {} UTC",
+            buildinfo::GIT_HASH,
+            buildinfo::build_time()
+        )),
+        machine: field(format_args!("{}", buildinfo::machine())),
+        domainname: field(format_args!("{}", DOMAINNAME)),
+    }
+}
+
+/// # Uname
+/// `uname(buf)`, fills the `Utsname` at `buf`
+pub fn sys_uname(ptr: UserVirtualAddress) -> Result<i32> {
+    let info = uname();
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &info as *const Utsname as *const u8,
+            core::mem::size_of::<Utsname>(),
+        )
+    };
+    usermem::copy_to_user(ptr, bytes)?;
+    Ok(0)
+}
@@ -0,0 +1,63 @@
+//! # VM Stat
+//! `vmstat(pid, buf)` reports the page counts of the address space of a task, for debugging
+//! memory usage. Every task shares one address space for now, so every task reports the same.
+use crate::error::{Error, Result};
+use crate::memory::usermem;
+use crate::memory::vmm::{VmStats, ADDRESS_SPACE};
+use crate::memory::UserVirtualAddress;
+use crate::scheduler::{self, TaskId};
+
+/// # VM Stat
+/// The struct `vmstat()` fills, its layout never changes
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VmStat {
+    /// The pages of every mapping, `PROT_NONE` ones included
+    pub mapped_pages: u64,
+    /// The pages that have a frame of their own mapped
+    pub resident_pages: u64,
+    /// The pages shared copy-on-write with another process
+    pub cow_shared_pages: u64,
+    /// The pages that are only mapped on their first access and were not accessed yet
+    pub lazy_pages: u64,
+    /// The number of mappings
+    pub regions: u64,
+    /// The pages that were read but not written, which map the shared zero frame
+    pub zero_pages: u64,
+}
+
+impl From<VmStats> for VmStat {
+    fn from(stats: VmStats) -> Self {
+        Self {
+            mapped_pages: stats.mapped,
+            resident_pages: stats.resident,
+            cow_shared_pages: stats.cow_shared,
+            lazy_pages: stats.lazy,
+            regions: stats.regions,
+            zero_pages: stats.zero,
+        }
+    }
+}
+
+/// # VM Stat
+/// The page counts of the address space of the task `id`
+///
+/// ## Returns
+/// - Error::NoSuchProcess = There is no task `id`
+pub fn vmstat(id: TaskId) -> Result<VmStat> {
+    if !scheduler::task_infos().iter().any(|task| task.id == id) {
+        return Err(Error::NoSuchProcess);
+    }
+    Ok(ADDRESS_SPACE.lock().stats().into())
+}
+
+/// # VM Stat
+/// `vmstat(pid, buf)`, fills the `VmStat` at `buf` for the task `pid`, 0 for the caller
+pub fn sys_vmstat(pid: u64, ptr: UserVirtualAddress) -> Result<i32> {
+    let id = match pid {
+        0 => scheduler::current(),
+        pid => TaskId::new(pid),
+    };
+    usermem::write_user(ptr, vmstat(id)?)?;
+    Ok(0)
+}
@@ -9,7 +9,6 @@ pub const IS_TESTING_HARSHLY: bool = true;
 pub const IS_TESTING_HARSHLY: bool = false;
 
 #[repr(u32)]
-#[cfg(feature = "harsh-tests")]
 // QEMU will execute `exit(((code << 1) | 1))`
 pub enum QemuExitCode {
     // Note: exit(0) is not supported
@@ -20,12 +19,13 @@ pub enum QemuExitCode {
     Mixed = 0x44,        // At least one test failed
 }
 
-#[cfg(feature = "harsh-tests")]
 impl QemuExitCode {
     pub fn exit_qemu(self) -> ! {
         use crate::iobus::outl;
 
         outl(0xf4, self as u32);
+        // Without the isa-debug-exit device, ACPI still ends the VM, only with another status
+        let _ = crate::power::enter_s5();
 
         loop {
             unsafe {
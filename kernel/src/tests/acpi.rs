@@ -0,0 +1,190 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use crate::acpi::config::DeviceConfig;
+use crate::acpi::tables::{self, checksum_ok, map_table, Platform};
+use crate::acpi::{ACPIFindable, ACPITable, MCFGHeader, SDTHeader, Table};
+use crate::config::handover;
+use crate::error::Error;
+use crate::memory::paging::page_table_manager::translate;
+use crate::memory::{phys_to_virt, PhysicalAddress};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_acpi_parse_on_kernel_tables() {
+    // The tests run long after the kernel switched to its own page tables, which is when
+    // the tables used to be reached through the mapping of the bootloader
+    let registered = tables::tables();
+    if registered.is_empty() {
+        return 1;
+    }
+    let parsed = tables::parse(PhysicalAddress::new(handover().rsdp));
+    check_eq!(parsed.as_deref(), Ok(registered));
+    check!(registered[0].name() == "XSDT" || registered[0].name() == "RSDT");
+    for table in registered {
+        let header = table.map::<SDTHeader>();
+        check!(header.is_ok());
+        if let Ok(header) = header {
+            check_eq!(header.signature, table.signature);
+            check_eq!({ header.length }, table.len);
+        }
+        let last = table.phys + (table.len as u64 - 1);
+        check_eq!(translate(phys_to_virt(last).as_u64()), Some(last.as_u64()));
+    }
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_acpi_find() {
+    check_eq!(MCFGHeader::find().is_some(), tables::find("MCFG").is_some());
+    check!(tables::find("ESQT").is_none());
+    // Mapping what is mapped already changes nothing
+    if let Some(table) = tables::tables().first() {
+        let before = tables::MAPPED_PAGES.get();
+        check_eq!(
+            map_table(table.phys, table.len as u64).map(|virt| translate(virt.as_u64())),
+            Ok(Some(table.phys.as_u64()))
+        );
+        check_eq!(tables::MAPPED_PAGES.get(), before);
+    }
+    check!(map_table(PhysicalAddress::new(0x1000), 0).is_err());
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_acpi_raw_bytes() {
+    let registered = tables::tables();
+    let platform = tables::platform();
+    check_eq!(platform.is_some(), !registered.is_empty());
+    if let Some(platform) = platform {
+        check_eq!(
+            Platform::at(PhysicalAddress::new(handover().rsdp)).as_ref(),
+            Ok(platform)
+        );
+        check_eq!(registered[0].phys.as_u64(), platform.root);
+        check_eq!(registered[0].name() == "XSDT", platform.has_xsdt);
+    }
+    for (idx, table) in registered.iter().enumerate() {
+        // Tables with the same signature are counted in the order they are listed
+        let index = registered[..idx]
+            .iter()
+            .filter(|other| other.signature == table.signature)
+            .count();
+        check_eq!(tables::nth(table.name(), index), Some(table));
+        let bytes = tables::raw_bytes(table.name(), index);
+        check_eq!(bytes.map(|bytes| bytes.len()), Ok(table.len as usize));
+        if let Ok(bytes) = bytes {
+            check_eq!(bytes[..4], table.signature);
+        }
+    }
+    check_eq!(tables::raw_bytes("ESQT", 0), Err(Error::NoSuchDevice));
+    if let Some(table) = registered.first() {
+        let count = registered
+            .iter()
+            .filter(|other| other.signature == table.signature)
+            .count();
+        check_eq!(
+            tables::raw_bytes(table.name(), count),
+            Err(Error::NoSuchDevice)
+        );
+    }
+
+    check!(checksum_ok(&[]));
+    check!(checksum_ok(&[0x80, 0x7F, 0x01]));
+    check!(!checksum_ok(&[0x80, 0x7F]));
+    all_good!()
+}
+
+/// An MCFG with `allocations` allocations, whose header says it is `len` bytes long
+fn mcfg(allocations: usize, len: usize) -> Vec<u8> {
+    let mut bytes = alloc::vec![0u8; size_of::<MCFGHeader>()];
+    bytes[..4].copy_from_slice(b"MCFG");
+    bytes[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+    for allocation in 0..allocations {
+        let mut config = [0u8; size_of::<DeviceConfig>()];
+        config[..8].copy_from_slice(&(0xE000_0000u64 + allocation as u64).to_le_bytes());
+        config[11] = 0xFF;
+        bytes.extend_from_slice(&config);
+    }
+    bytes
+}
+
+#[esqtest::test]
+pub fn test_acpi_truncated_tables() {
+    let full = size_of::<MCFGHeader>() + 2 * size_of::<DeviceConfig>();
+    let bytes = mcfg(2, full);
+    let table = Table::<MCFGHeader>::new(&bytes);
+    check!(table.is_ok());
+    if let Ok(table) = table {
+        check_eq!(table.len(), full);
+        check_eq!(table.sdt_header.signature, *b"MCFG");
+        let bases: Vec<u64> = table
+            .entries::<DeviceConfig>()
+            .map(|config| config.base)
+            .collect();
+        check_eq!(bases, [0xE000_0000, 0xE000_0001]);
+    }
+
+    // The bytes end before the length in the header does
+    for len in 0..full {
+        check!(Table::<MCFGHeader>::new(&bytes[..len]).is_err());
+    }
+    // The header says the table ends before its fixed part or in the middle of an allocation
+    for len in 0..=full {
+        let bytes = mcfg(2, len);
+        match Table::<MCFGHeader>::new(&bytes) {
+            Ok(table) => {
+                check!(len >= size_of::<MCFGHeader>());
+                check_eq!(
+                    table.entries::<DeviceConfig>().count(),
+                    (len - size_of::<MCFGHeader>()) / size_of::<DeviceConfig>()
+                );
+            }
+            Err(e) => {
+                check!(len < size_of::<MCFGHeader>());
+                check_eq!(e, Error::InvalidArgument);
+            }
+        }
+    }
+    // A plain header is too short to be an MCFG
+    check!(SDTHeader::from_bytes(&bytes[..size_of::<SDTHeader>()]).is_ok());
+    check!(MCFGHeader::from_bytes(&bytes[..size_of::<SDTHeader>()]).is_err());
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_acpi_records() {
+    let records = |tail: &[u8]| {
+        let mut bytes = mcfg(0, size_of::<MCFGHeader>() + tail.len());
+        bytes.extend_from_slice(tail);
+        let table = match Table::<MCFGHeader>::new(&bytes) {
+            Ok(table) => table,
+            Err(_) => return Vec::new(),
+        };
+        table
+            .records()
+            .map(|record| record.map(|record| (record.kind, record.bytes.len())))
+            .collect::<Vec<_>>()
+    };
+    let ok = |kind: u8, len: usize| Ok::<_, Error>((kind, len));
+    check_eq!(
+        records(&[0, 8, 1, 2, 3, 4, 5, 6, 1, 2]),
+        [ok(0, 8), ok(1, 2)]
+    );
+    check!(records(&[]).is_empty());
+    // A length too short for the record header would never advance
+    check_eq!(
+        records(&[0, 2, 5, 0, 1, 2]),
+        [ok(0, 2), Err(Error::InvalidArgument)]
+    );
+    // A record running past the end of the table
+    check_eq!(
+        records(&[0, 9, 1, 2, 3]),
+        [Err::<(u8, usize), _>(Error::InvalidArgument)]
+    );
+    check_eq!(
+        records(&[7]),
+        [Err::<(u8, usize), _>(Error::InvalidArgument)]
+    );
+    all_good!()
+}
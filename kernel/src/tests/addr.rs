@@ -0,0 +1,91 @@
+use crate::memory::{PhysicalAddress, VirtualAddress};
+use esqtest::*;
+
+/// The address `VirtualAddress::try_new` should give for each input, `Err` with bits 47..64 if
+/// the input is rejected
+const CASES: &[(u64, Result<u64, u64>)] = &[
+    // Lower half
+    (0, Ok(0)),
+    (0x1000, Ok(0x1000)),
+    (0x0000_7fff_ffff_ffff, Ok(0x0000_7fff_ffff_ffff)),
+    // Bit 47 without its sign extension
+    (0x0000_8000_0000_0000, Ok(0xffff_8000_0000_0000)),
+    (0x0000_ffff_ffff_ffff, Ok(0xffff_ffff_ffff_ffff)),
+    // The hole between the halves
+    (0x0001_0000_0000_0000, Err(0x2)),
+    (0x7fff_ffff_ffff_ffff, Err(0xffff)),
+    (0x8000_0000_0000_0000, Err(0x10000)),
+    (0xffff_7fff_ffff_ffff, Err(0x1fffe)),
+    (0xfffe_8000_0000_0000, Err(0x1fffd)),
+    // Higher half
+    (0xffff_8000_0000_0000, Ok(0xffff_8000_0000_0000)),
+    (0xffff_8000_0010_0000, Ok(0xffff_8000_0010_0000)),
+    (0xffff_ffff_ffff_ffff, Ok(0xffff_ffff_ffff_ffff)),
+];
+
+#[esqtest::test]
+pub fn test_virtual_address_try_new() {
+    for (addr, expected) in CASES {
+        check_eq!(
+            VirtualAddress::try_new(*addr).map(|addr| addr.as_u64()),
+            *expected
+        );
+    }
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_virtual_address_try_set() {
+    for (addr, expected) in CASES {
+        let mut virt = VirtualAddress::new(0x1000);
+        let result = virt.try_set(*addr);
+        match expected {
+            Ok(expected) => {
+                check_eq!(result, Ok(()));
+                check_eq!(virt.as_u64(), *expected);
+            }
+            Err(bad) => {
+                check_eq!(result, Err((*addr, *bad)));
+                // Rejected addresses leave it unchanged
+                check_eq!(virt.as_u64(), 0x1000);
+            }
+        }
+    }
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_physical_address_width() {
+    let width = PhysicalAddress::max_width();
+    // Every address the test creates is valid at the narrowest width CPUs report, so nothing
+    // else breaks while it runs
+    PhysicalAddress::set_max_width(36);
+    check_eq!(PhysicalAddress::max().as_u64(), 0xf_ffff_ffff);
+    check!(PhysicalAddress::try_new(0xf_ffff_f000).is_ok());
+    // Valid at 52 bits, but not reachable with 36
+    check_eq!(
+        PhysicalAddress::try_new(0x10_0000_0000).map(|addr| addr.as_u64()),
+        Err((0x1, 0))
+    );
+    check_eq!(
+        PhysicalAddress::try_new(0x000f_ff00_0000_1000).map(|addr| addr.as_u64()),
+        Err((0xfff0, 0x1000))
+    );
+    let mut phys = PhysicalAddress::new(0x1000);
+    check_eq!(phys.try_set(0x40_0000_2000), Err((0x40_0000_2000, 0x4)));
+    check_eq!(phys.as_u64(), 0x1000);
+
+    // Never wider than the architecture allows
+    PhysicalAddress::set_max_width(64);
+    check_eq!(
+        PhysicalAddress::max_width(),
+        PhysicalAddress::ARCH_MAX_WIDTH
+    );
+    check!(PhysicalAddress::try_new(0x000f_ffff_ffff_f000).is_ok());
+    check!(PhysicalAddress::try_new(0x0010_0000_0000_0000).is_err());
+
+    PhysicalAddress::set_max_width(width);
+    all_good!()
+}
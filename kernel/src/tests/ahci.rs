@@ -0,0 +1,21 @@
+use crate::block::BlockDevice;
+use crate::drivers::ahci::{AHCI_DISKS, SECTOR_SIZE};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_ahci_read_mbr() {
+    let disk = match AHCI_DISKS.lock().first().cloned() {
+        Some(disk) => disk,
+        // Nothing to test without a drive
+        None => {
+            all_good!()
+        }
+    };
+    // Heap allocations are aligned well enough for DMA
+    let mut sector = alloc::vec![0u8; SECTOR_SIZE];
+    check!(disk.read_blocks(0, &mut sector).is_ok());
+    check_eq!(sector[510], 0x55);
+    check_eq!(sector[511], 0xAA);
+
+    all_good!()
+}
@@ -0,0 +1,66 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::heap::segregated::SEGREGATED_HEAP;
+use crate::heap::tag::{self, TagUsage, UNTAGGED};
+use esqtest::*;
+
+fn usage(name: &str) -> (u64, u64) {
+    tag::usage(name).map_or((0, 0), |usage| (usage.bytes, usage.count))
+}
+
+#[esqtest::test]
+pub fn test_alloc_tag() {
+    if cfg!(feature = "linked-list-heap") {
+        return 1;
+    }
+    let outside = tag::current();
+    let block = {
+        let _tag = crate::alloc_tag!("test.alloctag");
+        check_neq!(tag::current(), outside);
+        Box::new([0u8; 100])
+    };
+    check_eq!(tag::current(), outside);
+    // Usages are read outside of the section, reading them allocates
+    check_eq!(usage("test.alloctag"), (128, 1));
+    let block_tag = SEGREGATED_HEAP.lock().tag_of(block.as_ptr());
+    check_eq!(block_tag, Some(tag::register("test.alloctag")));
+    // Freed outside of the section, it is still taken off the tag
+    drop(block);
+    check_eq!(usage("test.alloctag"), (0, 0));
+
+    // Growing keeps the tag of the allocation, not the one of the code that grows it
+    let mut vec: Vec<u8> = {
+        let _tag = crate::alloc_tag!("test.alloctag");
+        Vec::with_capacity(16)
+    };
+    check_eq!(usage("test.alloctag"), (16, 1));
+    vec.extend_from_slice(&[0; 1000]);
+    check_eq!(usage("test.alloctag"), (1024, 1));
+    drop(vec);
+    check_eq!(usage("test.alloctag"), (0, 0));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_alloc_tag_nesting() {
+    let outside = tag::current();
+    {
+        let _outer = crate::alloc_tag!("test.outer");
+        let outer = tag::current();
+        {
+            let _inner = crate::alloc_tag!("test.inner");
+            check_neq!(tag::current(), outer);
+        }
+        check_eq!(tag::current(), outer);
+        // Entering the same tag again finds the registered one
+        check_eq!(tag::register("test.outer"), outer);
+    }
+    check_eq!(tag::current(), outside);
+    check!(tag::usage("untagged").is_some());
+    check_eq!(tag::register("untagged"), UNTAGGED);
+
+    let usages: Vec<TagUsage> = tag::usages();
+    check!(usages.windows(2).all(|pair| pair[0].bytes >= pair[1].bytes));
+    all_good!()
+}
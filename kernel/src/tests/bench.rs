@@ -0,0 +1,38 @@
+use alloc::vec::Vec;
+
+use crate::bench::{find, percentile, Summary, BENCHMARKS, ITERATIONS};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_percentiles() {
+    let mut samples: Vec<u64> = (1..=100).rev().collect();
+    check_eq!(
+        Summary::of(&mut samples),
+        Some(Summary {
+            median: 50,
+            p99: 99
+        })
+    );
+    check_eq!(percentile(&samples, 100), Some(100));
+    check_eq!(percentile(&samples, 0), Some(1));
+    check_eq!(percentile(&[7], 99), Some(7));
+    check_eq!(Summary::of(&mut []), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_null_syscall_bench() {
+    check_eq!(BENCHMARKS.len(), 3);
+    let mut samples = match find("syscall").map(|bench| (bench.run)()) {
+        Some(Ok(samples)) => samples,
+        _ => return 1,
+    };
+    check_eq!(samples.len(), ITERATIONS);
+    let summary = Summary::of(&mut samples).unwrap_or(Summary { median: 1, p99: 0 });
+    check!(summary.median > 0);
+    check!(summary.median <= summary.p99);
+    check!(find("pipe").is_none());
+
+    all_good!()
+}
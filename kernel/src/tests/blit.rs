@@ -0,0 +1,116 @@
+use alloc::vec;
+
+use crate::arch::tsc;
+use crate::framebuffer::blit::{draw_glyph, draw_glyph_per_pixel, ColorTable, GlyphCache};
+use crate::framebuffer::font::Psf;
+use crate::framebuffer::{Cell, Color};
+use crate::info;
+use esqtest::*;
+
+static PSF1: &[u8] = include_bytes!("../../../binaries/font/font.psf");
+static PSF2: &[u8] = include_bytes!("../../../binaries/font/font-16x32.psf");
+
+/// The number of characters the blitters are measured with
+const BENCH_CHARS: usize = 10_000;
+
+fn cell(chr: u8, foreground: u32, background: u32) -> Cell {
+    Cell {
+        chr,
+        foreground,
+        background,
+    }
+}
+
+#[esqtest::test]
+pub fn test_blit_matches_per_pixel() {
+    for data in [PSF1, PSF2] {
+        let font = match Psf::parse("test", data) {
+            Some(font) => font,
+            None => return 1,
+        };
+        let (width, height) = (font.width(), font.height());
+        // A stride wider than a glyph catches writes past its right edge
+        let stride = width + 3;
+        let mut cache = GlyphCache::new(&font);
+        for (foreground, background) in [
+            (Color::White as u32, Color::Black as u32),
+            (Color::Orange as u32, Color::DarkBlue as u32),
+        ] {
+            let table = ColorTable::new(foreground, background);
+            for chr in 0x20..0x7F {
+                let cell = cell(chr, foreground, background);
+                let mut expected = vec![1u32; stride * height];
+                let mut rows = vec![1u32; stride * height];
+                let mut cached = vec![1u32; stride * height];
+                let mut cleared = vec![background; stride * height];
+                unsafe {
+                    draw_glyph_per_pixel(expected.as_mut_ptr(), stride, &font, cell);
+                    draw_glyph(rows.as_mut_ptr(), stride, &font, cell, &table, false);
+                    // The second draw comes from the cache
+                    cache.draw(cached.as_mut_ptr(), stride, &font, cell, &table, false);
+                    cache.draw(cached.as_mut_ptr(), stride, &font, cell, &table, false);
+                    draw_glyph(cleared.as_mut_ptr(), stride, &font, cell, &table, true);
+                }
+                check_eq!(rows, expected);
+                check_eq!(cached, expected);
+                for y in 0..height {
+                    let row = y * stride;
+                    check_eq!(&cleared[row..row + width], &expected[row..row + width]);
+                }
+            }
+        }
+    }
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_blit_speed() {
+    let font = match Psf::parse("test", PSF1) {
+        Some(font) => font,
+        None => return 1,
+    };
+    let (columns, rows) = (80, 30);
+    let stride = columns * font.width();
+    let mut pixels = vec![0u32; stride * rows * font.height()];
+    let (foreground, background) = (Color::White as u32, Color::Black as u32);
+    let table = ColorTable::new(foreground, background);
+    let mut cache = GlyphCache::new(&font);
+    let chr = |idx: usize| b' ' + (idx % 95) as u8;
+    let dst = |pixels: &mut [u32], idx: usize| {
+        let (row, col) = ((idx / columns) % rows, idx % columns);
+        unsafe {
+            pixels
+                .as_mut_ptr()
+                .add(row * font.height() * stride + col * font.width())
+        }
+    };
+
+    let start = tsc::read();
+    for idx in 0..BENCH_CHARS {
+        let cell = cell(chr(idx), foreground, background);
+        unsafe { draw_glyph_per_pixel(dst(&mut pixels, idx), stride, &font, cell) };
+    }
+    let per_pixel = tsc::read() - start;
+
+    let start = tsc::read();
+    for idx in 0..BENCH_CHARS {
+        let cell = cell(chr(idx), foreground, background);
+        unsafe { draw_glyph(dst(&mut pixels, idx), stride, &font, cell, &table, false) };
+    }
+    let by_row = tsc::read() - start;
+
+    let start = tsc::read();
+    for idx in 0..BENCH_CHARS {
+        let cell = cell(chr(idx), foreground, background);
+        unsafe { cache.draw(dst(&mut pixels, idx), stride, &font, cell, &table, false) };
+    }
+    let cached = tsc::read() - start;
+
+    info!(
+        "blit: {} characters in {} cycles per pixel, {} by row, {} cached",
+        BENCH_CHARS, per_pixel, by_row, cached
+    );
+    check!(by_row < per_pixel);
+    check!(cached < per_pixel);
+    all_good!()
+}
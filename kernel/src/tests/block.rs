@@ -0,0 +1,184 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::block::partition::{scan, Guid, PartitionType};
+use crate::block::{self, BlockDevice, Partition};
+use crate::error::{Error, Result};
+use crate::math::crc32;
+use crate::scheduler::spin::Mutex;
+use esqtest::*;
+
+const BLOCK_SIZE: usize = 512;
+const BLOCKS: u64 = 64;
+
+/// A disk in memory
+pub struct MemDisk {
+    data: Mutex<Vec<u8>>,
+    /// How many times `read_blocks()` was called
+    reads: AtomicUsize,
+}
+
+impl MemDisk {
+    pub fn new() -> Self {
+        Self::from_bytes(&vec![0; BLOCKS as usize * BLOCK_SIZE])
+    }
+
+    /// A disk holding a copy of `image`, which has to be a multiple of the block size
+    pub fn from_bytes(image: &[u8]) -> Self {
+        assert_eq!(image.len() % BLOCK_SIZE, 0);
+        Self {
+            data: Mutex::new(image.to_vec()),
+            reads: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn reads(&self) -> usize {
+        self.reads.load(Ordering::Relaxed)
+    }
+}
+
+impl BlockDevice for MemDisk {
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.lock().len() / BLOCK_SIZE) as u64
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        let start = lba as usize * BLOCK_SIZE;
+        buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        let start = lba as usize * BLOCK_SIZE;
+        self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn write_blocks_polled(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        self.write_blocks(lba, buf)
+    }
+}
+
+fn write_mbr(disk: &MemDisk, ty: u8, start: u32, count: u32) {
+    let mut mbr = vec![0u8; BLOCK_SIZE];
+    mbr[0x1BE + 4] = ty;
+    mbr[0x1BE + 8..0x1BE + 12].copy_from_slice(&start.to_le_bytes());
+    mbr[0x1BE + 12..0x1BE + 16].copy_from_slice(&count.to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    disk.write_blocks(0, &mbr).unwrap();
+}
+
+/// Writes a GPT header at `lba` with a single entry table at `entries_lba`
+fn write_gpt(disk: &MemDisk, lba: u64, entries_lba: u64, ty: Guid, first: u64, last: u64) {
+    let mut entries = vec![0u8; 4 * 128];
+    entries[0..16].copy_from_slice(&ty.0);
+    entries[32..40].copy_from_slice(&first.to_le_bytes());
+    entries[40..48].copy_from_slice(&last.to_le_bytes());
+    disk.write_blocks(entries_lba, &entries).unwrap();
+
+    let mut header = vec![0u8; BLOCK_SIZE];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&lba.to_le_bytes());
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&4u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    header[88..92].copy_from_slice(&crc32(&entries).to_le_bytes());
+    let crc = crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    disk.write_blocks(lba, &header).unwrap();
+}
+
+#[esqtest::test]
+pub fn test_crc32() {
+    check_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    check_eq!(crc32(b""), 0);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_mbr() {
+    let disk = MemDisk::new();
+    write_mbr(&disk, 0x0C, 8, 16);
+    let partitions = scan(&disk).unwrap();
+    check_eq!(partitions.len(), 1);
+    check_eq!(partitions[0].index, 1);
+    check_eq!(partitions[0].start, 8);
+    check_eq!(partitions[0].count, 16);
+    check_eq!(partitions[0].ty, PartitionType::Mbr(0x0C));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_gpt_backup() {
+    let disk = MemDisk::new();
+    write_mbr(&disk, 0xEE, 1, BLOCKS as u32 - 1);
+    write_gpt(&disk, 1, 2, Guid::EFI_SYSTEM, 10, 19);
+    write_gpt(&disk, BLOCKS - 1, BLOCKS - 2, Guid::EFI_SYSTEM, 10, 19);
+
+    let partitions = scan(&disk).unwrap();
+    check_eq!(partitions.len(), 1);
+    check_eq!(partitions[0].count, 10);
+    check_eq!(partitions[0].ty, PartitionType::Gpt(Guid::EFI_SYSTEM));
+
+    // Corrupt the primary header, the backup has to be used
+    let mut header = vec![0u8; BLOCK_SIZE];
+    disk.read_blocks(1, &mut header).unwrap();
+    header[40] ^= 0xFF;
+    disk.write_blocks(1, &header).unwrap();
+    let partitions = scan(&disk).unwrap();
+    check_eq!(partitions.len(), 1);
+    check_eq!(partitions[0].start, 10);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_partition_bounds() {
+    let disk: Arc<dyn BlockDevice> = Arc::new(MemDisk::new());
+    let partition = Partition::new(disk.clone(), 8, 4);
+    let mut block = vec![0xAB; BLOCK_SIZE];
+    check!(partition.write_blocks(3, &block).is_ok());
+    check!(partition.write_blocks(4, &block).is_err());
+    check!(partition.read_blocks(0, &mut block[..100]).is_err());
+
+    disk.read_blocks(11, &mut block).unwrap();
+    check_eq!(block[0], 0xAB);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_unregister_disk() {
+    let disk = MemDisk::new();
+    write_mbr(&disk, 0x0C, 8, 16);
+    let disk = Arc::new(disk);
+    let name = block::register_disk(disk.clone());
+    let partition_name = alloc::format!("{}p1", name);
+    check!(block::find(&partition_name).is_some());
+
+    // Held outside the registry, so still in use
+    let partition = block::find(&partition_name);
+    check_eq!(
+        block::unregister_disks(&[&*disk]),
+        Err(Error::DeviceOrResourceBusy)
+    );
+    check!(block::find(&name).is_some());
+    drop(partition);
+
+    check_eq!(block::unregister_disks(&[&*disk]), Ok(()));
+    check!(block::find(&name).is_none());
+    check!(block::find(&partition_name).is_none());
+    check_eq!(block::unregister_disks(&[&*disk]), Err(Error::NoSuchDevice));
+    check_eq!(Arc::strong_count(&disk), 1);
+
+    all_good!()
+}
@@ -0,0 +1,70 @@
+use crate::breadcrumb::{self, Breadcrumb, Milestone, MAGIC};
+use crate::cmdline;
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_breadcrumb_codes() {
+    // The codes are read by other kernels, they must never change
+    let codes = Milestone::ALL.map(|milestone| milestone as u8);
+    check_eq!(codes, [1, 2, 3, 4, 5, 6]);
+    for milestone in Milestone::ALL {
+        check_eq!(Milestone::from_code(milestone as u8), Some(milestone));
+    }
+    check_eq!(Milestone::from_code(0), None);
+    check_eq!(Milestone::from_code(0xFF), None);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_breadcrumb_round_trip() {
+    let breadcrumb = Breadcrumb {
+        milestone: Milestone::PagingSwitched as u8,
+        failed_boots: 3,
+    };
+    let raw = breadcrumb.encode();
+    check_eq!(raw[0], MAGIC);
+    check_eq!(Breadcrumb::decode(raw), Some(breadcrumb));
+    check!(breadcrumb.is_incomplete());
+
+    // A milestone of a newer kernel still decodes
+    let unknown = Breadcrumb {
+        milestone: 200,
+        failed_boots: 0,
+    };
+    check_eq!(Breadcrumb::decode(unknown.encode()), Some(unknown));
+
+    // Registers that were never written, or hold something else
+    check_eq!(Breadcrumb::decode([0; 4]), None);
+    check_eq!(Breadcrumb::decode([0xFF; 4]), None);
+    for i in 0..raw.len() {
+        let mut flipped = raw;
+        flipped[i] ^= 1;
+        check_eq!(Breadcrumb::decode(flipped), None);
+    }
+
+    let complete = Breadcrumb {
+        milestone: Milestone::BootComplete as u8,
+        failed_boots: 0,
+    };
+    check!(!complete.is_incomplete());
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_breadcrumb_this_boot() {
+    // The tests run after the boot completed
+    for milestone in Milestone::ALL {
+        check!(breadcrumb::has_reached(milestone));
+    }
+    if cmdline::value(breadcrumb::OPTION) == Some("off") {
+        all_good!()
+    }
+    check_eq!(
+        breadcrumb::read(),
+        Some(Breadcrumb {
+            milestone: Milestone::BootComplete as u8,
+            failed_boots: 0,
+        })
+    );
+    all_good!()
+}
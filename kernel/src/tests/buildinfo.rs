@@ -0,0 +1,38 @@
+use alloc::string::ToString;
+
+use crate::buildinfo::{self, Banner, FEATURES};
+use crate::init::initcall::{self, registered};
+use crate::syscall::uname::{uname, Utsname};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_buildinfo_uname() {
+    let info = uname();
+    check_eq!(Utsname::field_str(&info.sysname), buildinfo::NAME);
+    check_eq!(Utsname::field_str(&info.release), buildinfo::VERSION);
+    check_eq!(Utsname::field_str(&info.machine), "x86_64");
+    check!(Utsname::field_str(&info.version).contains(buildinfo::GIT_HASH));
+    // Every field is terminated, even if its value was cut off
+    check_eq!(info.version[info.version.len() - 1], 0);
+
+    let banner = Banner.to_string();
+    check!(banner.starts_with("esque "));
+    check!(banner.contains(buildinfo::TARGET));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_buildinfo_initcalls() {
+    // Only init calls of enabled features are compiled in
+    for call in registered() {
+        if let Some(feature) = call.feature {
+            check!(buildinfo::has_feature(feature));
+        }
+        // Every stage ran before the tests
+        check_neq!(initcall::outcome(call.name), None);
+    }
+    check!(FEATURES.windows(2).all(|pair| pair[0] < pair[1]));
+    check!(!buildinfo::has_feature("no-such-feature"));
+    check_eq!(initcall::outcome("no-such-call"), None);
+    all_good!()
+}
@@ -0,0 +1,122 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use bks::{Framebuffer, PixelFormat};
+
+use crate::framebuffer::cells::{CellBuffer, SCROLLBACK_SCREENS};
+use crate::framebuffer::font::Psf;
+use crate::framebuffer::{self, Cell, Color, FramebufferGuard, BYTES_PER_PIXEL};
+use esqtest::*;
+
+static PSF1: &[u8] = include_bytes!("../../../binaries/font/font.psf");
+
+/// Whether the character cell at `row`, `col` of `pixels` shows `chr` in white on black
+fn shows(pixels: &[u32], stride: usize, font: &Psf, row: usize, col: usize, chr: u8) -> bool {
+    let glyph = font.glyph(chr);
+    (0..font.height()).all(|y| {
+        (0..font.width()).all(|x| {
+            let expected = if font.is_set(glyph, x, y) {
+                Color::White as u32
+            } else {
+                Color::Black as u32
+            };
+            let (px, py) = (col * font.width() + x, row * font.height() + y);
+            pixels[py * stride + px] == expected
+        })
+    })
+}
+
+#[esqtest::test]
+pub fn test_cells_scrollback() {
+    let font = Psf::parse("small", PSF1).unwrap();
+    let (width, height) = (64, 160);
+    let mut pixels: Vec<u32> = vec![0u32; width * height];
+    let raw = Framebuffer::new(
+        pixels.as_mut_ptr() as u64,
+        pixels.len() * BYTES_PER_PIXEL,
+        width,
+        height,
+        width,
+        PixelFormat::Rgb,
+    );
+    let info = match framebuffer::validate(&raw, Some(&font)) {
+        Ok(info) => info,
+        Err(_) => return 1,
+    };
+    let mut guard = FramebufferGuard::new(info, raw, font, Color::Black, Color::White);
+    guard.enable_backing_store();
+    check_eq!(guard.geometry(), (8, 10));
+
+    // Twelve lines on a screen of ten rows, the last one is left for the cursor
+    for chr in b"0123456789ab" {
+        check!(writeln!(guard, "{}", *chr as char).is_ok());
+    }
+    check_eq!(guard.cursor(), (9, 0));
+    check_eq!(guard.scrollback(), 3);
+    check_eq!(guard.cells()[0].chr, b'3');
+    check_eq!(guard.cells()[8 * 8].chr, b'b');
+    check!(shows(&pixels, width, &font, 0, 0, b'3'));
+    check!(shows(&pixels, width, &font, 8, 0, b'b'));
+    check!(shows(&pixels, width, &font, 9, 0, b' '));
+
+    // The view cannot go further back than the scrollback
+    guard.scroll_view(2);
+    check_eq!(guard.view(), 2);
+    check!(shows(&pixels, width, &font, 0, 0, b'1'));
+    guard.scroll_view(100);
+    check_eq!(guard.view(), 3);
+    check!(shows(&pixels, width, &font, 0, 0, b'0'));
+    check!(shows(&pixels, width, &font, 9, 0, b'9'));
+    guard.scroll_view(-1);
+    check_eq!(guard.view(), 2);
+
+    // Output goes to the screen, not the view
+    check!(guard.write_str("c").is_ok());
+    check_eq!(guard.view(), 0);
+    check!(shows(&pixels, width, &font, 0, 0, b'3'));
+    check!(shows(&pixels, width, &font, 9, 0, b'c'));
+
+    // Anything drawn over the screen is undone by a redraw
+    pixels.fill(Color::Red as u32);
+    guard.redraw();
+    check!(shows(&pixels, width, &font, 0, 0, b'3'));
+    check!(shows(&pixels, width, &font, 9, 0, b'c'));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_cells_buffer() {
+    let blank = Cell {
+        chr: b' ',
+        foreground: Color::White as u32,
+        background: Color::Black as u32,
+    };
+    let mut cells = CellBuffer::disabled();
+    check!(!cells.is_enabled());
+    cells.scroll_up(blank);
+    check!(cells.cells().is_empty());
+
+    // The scrollback is limited to a number of screens
+    cells.upgrade(2, 2, blank, SCROLLBACK_SCREENS);
+    check!(cells.is_enabled());
+    for chr in 0..(SCROLLBACK_SCREENS * 2 + 5) as u8 {
+        cells.set(1, 0, Cell { chr, ..blank });
+        cells.scroll_up(blank);
+    }
+    check_eq!(cells.history_len(), SCROLLBACK_SCREENS * 2);
+    check_eq!(cells.visible_row(1), &[blank, blank][..]);
+    check!(cells.scroll_view(1));
+    check_eq!(
+        cells.visible_row(1)[0].chr,
+        (SCROLLBACK_SCREENS * 2 + 4) as u8
+    );
+
+    // Out of bounds writes are ignored
+    cells.set(2, 0, Cell { chr: b'x', ..blank });
+    cells.set(0, 2, Cell { chr: b'x', ..blank });
+    check!(cells.cells().iter().all(|cell| cell.chr != b'x'));
+
+    all_good!()
+}
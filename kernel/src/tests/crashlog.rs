@@ -0,0 +1,102 @@
+use alloc::alloc::{alloc_zeroed, Layout};
+use alloc::boxed::Box;
+
+use crate::crashlog::{CrashRecord, RecordState, MESSAGE_SIZE};
+use crate::klog;
+use crate::stats::Stat;
+use esqtest::*;
+
+/// An empty record, which is too large for the stack
+fn zeroed_record() -> Box<CrashRecord> {
+    unsafe { Box::from_raw(alloc_zeroed(Layout::new::<CrashRecord>()) as *mut CrashRecord) }
+}
+
+fn stats() -> impl Iterator<Item = Stat> {
+    [
+        Stat {
+            name: "test.one",
+            index: None,
+            value: 1,
+        },
+        Stat {
+            name: "test.zero",
+            index: None,
+            value: 0,
+        },
+    ]
+    .into_iter()
+}
+
+fn write_log(buffer: &mut [u8]) -> usize {
+    let log = b"first line\nsecond line\n";
+    buffer[..log.len()].copy_from_slice(log);
+    log.len()
+}
+
+#[esqtest::test]
+pub fn test_crashlog_round_trip() {
+    let mut record = zeroed_record();
+    check_eq!(record.state(), RecordState::Empty);
+
+    record.write(&"something broke", write_log, stats());
+    check_eq!(record.state(), RecordState::Valid);
+    check_eq!(record.message(), "something broke");
+    check_eq!(record.log(), "first line\nsecond line\n");
+    // Zeros are left out
+    check_eq!(record.stats(), "test.one = 1\n");
+
+    record.clear();
+    check_eq!(record.state(), RecordState::Empty);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_crashlog_corrupt() {
+    let mut record = zeroed_record();
+    record.write(&"something broke", write_log, stats());
+    let bytes = &mut *record as *mut CrashRecord as *mut u8;
+
+    // A flipped bit in the log
+    unsafe { *bytes.add(MESSAGE_SIZE + 40) ^= 1 };
+    check_eq!(record.state(), RecordState::Corrupt);
+    unsafe { *bytes.add(MESSAGE_SIZE + 40) ^= 1 };
+    check_eq!(record.state(), RecordState::Valid);
+
+    // Another version
+    unsafe { *bytes.add(8) += 1 };
+    check_eq!(record.state(), RecordState::Corrupt);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_crashlog_truncation() {
+    let mut record = zeroed_record();
+    let long = "é".repeat(MESSAGE_SIZE);
+    // A tail that starts in the middle of a character
+    record.write(
+        &long,
+        |buffer| {
+            buffer[..4].copy_from_slice(&[0xA9, b'o', b'k', 0xC3]);
+            4
+        },
+        core::iter::empty(),
+    );
+    check_eq!(record.state(), RecordState::Valid);
+    check_eq!(record.message().len(), MESSAGE_SIZE);
+    check_eq!(record.log(), "ok");
+    check_eq!(record.stats(), "");
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_crashlog_log_tail() {
+    let mut tail = [0u8; 16];
+    let len = klog::try_read_tail(&mut tail);
+    check!(len.is_some());
+    check!(len.unwrap_or(0) <= tail.len());
+
+    all_good!()
+}
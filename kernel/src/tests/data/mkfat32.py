@@ -0,0 +1,143 @@
+#!/usr/bin/env python3
+"""Generates fat32.img, the FAT32 image used by the filesystem tests.
+
+The image is deliberately tiny (one sector per cluster) and contains
+8.3 names, long file names, a subdirectory, a multi-cluster file and
+two files with corrupt cluster chains.
+"""
+import struct
+import sys
+
+SECTOR = 512
+TOTAL_SECTORS = 128
+RESERVED = 8
+FATS = 2
+FAT_SECTORS = 1
+DATA_START = RESERVED + FATS * FAT_SECTORS
+CLUSTERS = TOTAL_SECTORS - DATA_START
+EOC = 0x0FFFFFFF
+
+ATTR_DIRECTORY = 0x10
+ATTR_ARCHIVE = 0x20
+ATTR_VOLUME_ID = 0x08
+ATTR_LFN = 0x0F
+
+image = bytearray(TOTAL_SECTORS * SECTOR)
+fat = [0] * (FAT_SECTORS * SECTOR // 4)
+fat[0] = 0x0FFFFFF8
+fat[1] = EOC
+
+
+def cluster_offset(cluster):
+    return (DATA_START + cluster - 2) * SECTOR
+
+
+def write_chain(clusters, data=b""):
+    for i, cluster in enumerate(clusters):
+        if i + 1 < len(clusters):
+            fat[cluster] = clusters[i + 1]
+        else:
+            fat[cluster] = EOC
+        chunk = data[i * SECTOR:(i + 1) * SECTOR]
+        image[cluster_offset(cluster):cluster_offset(cluster) + len(chunk)] = chunk
+
+
+def short_entry(name, attr, cluster, size, nt_flags=0):
+    return struct.pack("<11sBBBHHHHHHHI", name, attr, nt_flags, 0, 0, 0, 0,
+                       cluster >> 16, 0, 0, cluster & 0xFFFF, size)
+
+
+def checksum(name):
+    total = 0
+    for byte in name:
+        total = (((total & 1) << 7) + (total >> 1) + byte) & 0xFF
+    return total
+
+
+def lfn_entries(long_name, short_name, corrupt=False):
+    chars = [ord(c) for c in long_name]
+    # Names filling the last entry exactly are not terminated
+    if len(chars) % 13:
+        chars.append(0)
+    while len(chars) % 13:
+        chars.append(0xFFFF)
+    parts = [chars[i:i + 13] for i in range(0, len(chars), 13)]
+    check = checksum(short_name) ^ (0xFF if corrupt else 0)
+    entries = []
+    for seq, part in enumerate(parts, start=1):
+        order = seq | (0x40 if seq == len(parts) else 0)
+        entry = struct.pack("<B10sBBB12sH4s", order,
+                            struct.pack("<5H", *part[0:5]), ATTR_LFN, 0, check,
+                            struct.pack("<6H", *part[5:11]), 0,
+                            struct.pack("<2H", *part[11:13]))
+        entries.append(entry)
+    return b"".join(reversed(entries))
+
+
+def directory(entries, cluster):
+    data = b"".join(entries)
+    assert len(data) <= SECTOR, "directories span a single cluster"
+    write_chain([cluster], data)
+
+
+kernel = bytes(i % 251 for i in range(1300))
+
+root = [
+    short_entry(b"ESQUE TEST ", ATTR_VOLUME_ID, 0, 0),
+    short_entry(b"HELLO   TXT", ATTR_ARCHIVE, 3, 14),
+    short_entry(b"BOOT       ", ATTR_DIRECTORY, 4, 0),
+    lfn_entries("A long file name.txt", b"ALONGF~1TXT"),
+    short_entry(b"ALONGF~1TXT", ATTR_ARCHIVE, 8, 10),
+    lfn_entries("Ünïcode.txt", b"NCODE~1 TXT"),
+    short_entry(b"NCODE~1 TXT", ATTR_ARCHIVE, 9, 8),
+    short_entry(b"\xe5ELETED TXT", ATTR_ARCHIVE, 0, 0),
+    short_entry(b"LOOP    BIN", ATTR_ARCHIVE, 10, 4096),
+    short_entry(b"RANGE   BIN", ATTR_ARCHIVE, 12, 1024),
+    lfn_entries("bad checksum name.txt", b"BADCHE~1TXT", corrupt=True),
+    short_entry(b"BADCHE~1TXT", ATTR_ARCHIVE, 13, 1),
+    # Lowercase base name and extension
+    short_entry(b"LOWER   TXT", ATTR_ARCHIVE, 14, 6, nt_flags=0x18),
+]
+directory(root, 2)
+write_chain([3], b"Hello, FAT32!\n")
+directory([
+    short_entry(b".          ", ATTR_DIRECTORY, 4, 0),
+    short_entry(b"..         ", ATTR_DIRECTORY, 0, 0),
+    short_entry(b"KERNEL  ELF", ATTR_ARCHIVE, 5, len(kernel)),
+], 4)
+write_chain([5, 6, 7], kernel)
+write_chain([8], b"long name\n")
+write_chain([9], b"unicode\n")
+write_chain([10, 11])
+fat[11] = 10  # A loop
+write_chain([12])
+fat[12] = 5000  # Out of range
+write_chain([13], b"x")
+write_chain([14], b"lower\n")
+
+bpb = struct.pack(
+    "<3s8sHBHBHHBHHHII",
+    b"\xeb\x58\x90", b"ESQUE   ", SECTOR, 1, RESERVED, FATS, 0, 0, 0xF8, 0,
+    32, 2, 0, TOTAL_SECTORS)
+bpb += struct.pack("<IHHIHH12sBBBI11s8s", FAT_SECTORS, 0, 0, 2, 1, 6,
+                   b"\0" * 12, 0x80, 0, 0x29, 0x12345678, b"ESQUE TEST ",
+                   b"FAT32   ")
+image[0:len(bpb)] = bpb
+image[510:512] = b"\x55\xaa"
+image[6 * SECTOR:7 * SECTOR] = image[0:SECTOR]
+
+fsinfo = bytearray(SECTOR)
+fsinfo[0:4] = struct.pack("<I", 0x41615252)
+fsinfo[484:492] = struct.pack("<II", 0x61417272, 0xFFFFFFFF)
+fsinfo[492:496] = struct.pack("<I", 0xFFFFFFFF)
+fsinfo[508:512] = struct.pack("<I", 0xAA550000)
+image[SECTOR:2 * SECTOR] = fsinfo
+
+fat_bytes = struct.pack("<%dI" % len(fat), *fat)
+for i in range(FATS):
+    offset = (RESERVED + i * FAT_SECTORS) * SECTOR
+    image[offset:offset + len(fat_bytes)] = fat_bytes
+
+out = sys.argv[1] if len(sys.argv) > 1 else "fat32.img"
+with open(out, "wb") as f:
+    f.write(image)
@@ -0,0 +1,182 @@
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+
+use super::user;
+use crate::arch::paging::page_table_manager::translate;
+use crate::error::Error;
+use crate::framebuffer::device::{FbFormat, FbInfo, FbRequest};
+use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::fs::devfs::{self, Null};
+use crate::fs::{self, FileType};
+use crate::memory::usermem::copy_to_user;
+use crate::memory::vmm::ADDRESS_SPACE;
+use crate::memory::UserVirtualAddress;
+use crate::syscall::mman::{sys_mmap, sys_munmap, MAP_SHARED, PROT_READ, PROT_WRITE};
+use crate::tty::{self, TtyRequest};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_devfs_nodes() {
+    let names: Vec<String> = match fs::open("/dev").and_then(|dev| dev.read_dir(0)) {
+        Ok(entries) => entries.into_iter().map(|entry| entry.name).collect(),
+        Err(_) => return 1,
+    };
+    for name in ["console", "null", "zero"] {
+        check!(names.iter().any(|entry| entry == name));
+    }
+    check_eq!(
+        fs::stat("/dev/null").map(|metadata| metadata.ty),
+        Ok(FileType::CharacterDevice)
+    );
+    check!(
+        fs::stat("/dev/null").map(|metadata| metadata.inode)
+            != fs::stat("/dev/zero").map(|metadata| metadata.inode)
+    );
+    check_eq!(
+        fs::stat("/dev/none").err(),
+        Some(Error::NoSuchFileOrDirectory)
+    );
+    check_eq!(fs::stat("/dev/null/x").err(), Some(Error::NotADirectory));
+    check_eq!(
+        devfs::register("null", Arc::new(Null)),
+        Err(Error::AlreadyExists)
+    );
+    check_eq!(
+        devfs::register("a/b", Arc::new(Null)),
+        Err(Error::InvalidArgument)
+    );
+
+    let zero = fs::open_fd("/dev/zero");
+    let null = fs::open_fd("/dev/null");
+    check!(zero.is_ok() && null.is_ok());
+    let (zero, null) = (zero.unwrap_or(0), null.unwrap_or(0));
+    let mut buf = vec![0xAAu8; 100];
+    check_eq!(fs::read_fd(zero, &mut buf), Ok(100));
+    check!(buf.iter().all(|byte| *byte == 0));
+    check_eq!(fs::read_fd(null, &mut buf), Ok(0));
+    // Neither takes requests or can be mapped
+    check_eq!(
+        fs::ioctl_fd(null, TtyRequest::GetMode, 0),
+        Err(Error::NotTTY)
+    );
+    check_eq!(fs::mmap_fd(zero, 0).err(), Some(Error::NoSuchDevice));
+    check_eq!(fs::close_fd(zero), Ok(()));
+    check_eq!(fs::close_fd(null), Ok(()));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_devfs_console_ioctl() {
+    let console = match fs::open_fd("/dev/console") {
+        Ok(fd) => fd,
+        Err(_) => return 1,
+    };
+    let mut mode = u32::MAX;
+    // Kernel memory is in the lower half as well, so it stands in for user memory
+    check_eq!(
+        fs::ioctl_fd(
+            console,
+            TtyRequest::GetMode,
+            user(&mut mode as *mut u32 as u64).as_u64()
+        ),
+        Ok(0)
+    );
+    check_eq!(mode, tty::mode().to_abi());
+    // The same requests as on the standard streams
+    let mut again = u32::MAX;
+    check_eq!(
+        fs::ioctl_fd(
+            0,
+            TtyRequest::GetMode,
+            user(&mut again as *mut u32 as u64).as_u64()
+        ),
+        Ok(0)
+    );
+    check_eq!(again, mode);
+    check_eq!(
+        fs::ioctl_fd(console, 0xDEAD, 0),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(fs::close_fd(console), Ok(()));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fb_device() {
+    let expected = unsafe { FRAMEBUFFER_GUARD.lock().assume_init_ref().info() };
+    let expected = match expected {
+        Some(info) => info,
+        // Serial only, there is no /dev/fb0
+        None => {
+            check_eq!(
+                fs::stat("/dev/fb0").err(),
+                Some(Error::NoSuchFileOrDirectory)
+            );
+            all_good!()
+        }
+    };
+    let fd = match fs::open_fd("/dev/fb0") {
+        Ok(fd) => fd,
+        Err(_) => return 1,
+    };
+    let mut info = FbInfo::default();
+    check_eq!(
+        fs::ioctl_fd(
+            fd,
+            FbRequest::GetInfo,
+            user(&mut info as *mut FbInfo as u64).as_u64()
+        ),
+        Ok(0)
+    );
+    check_eq!(info, FbInfo::from(expected));
+    check_eq!(fs::ioctl_fd(fd, 0xDEAD, 0), Err(Error::InvalidArgument));
+
+    let rw = PROT_READ | PROT_WRITE;
+    let null = UserVirtualAddress::null();
+    check_eq!(
+        sys_mmap(
+            null,
+            info.size,
+            rw,
+            MAP_SHARED,
+            fd,
+            info.size + bks::PAGE_SIZE
+        ),
+        Err(Error::InvalidArgument)
+    );
+    let resident = ADDRESS_SPACE.lock().stats().resident;
+    let addr = match sys_mmap(null, info.size, rw, MAP_SHARED, fd, 0) {
+        Ok(addr) => addr,
+        Err(_) => return 1,
+    };
+    // The framebuffer itself, which does not count as memory of the process
+    check_eq!(translate(addr), Some(expected.base));
+    check_eq!(ADDRESS_SPACE.lock().stats().resident, resident);
+
+    // A gradient from black on the left to blue on the right, drawn through the mapping
+    let width = info.width as usize;
+    let row: Vec<u8> = (0..width)
+        .flat_map(|x| {
+            let blue = (x * 255 / width.max(2).saturating_sub(1)) as u8;
+            match info.format {
+                FbFormat::Bgrx => [blue, 0, 0, 0],
+                _ => [0, 0, blue, 0],
+            }
+        })
+        .collect();
+    for y in 0..info.height as u64 {
+        check_eq!(
+            copy_to_user(user(addr + y * info.pitch as u64), &row),
+            Ok(())
+        );
+    }
+    let last = (expected.base + (info.width as u64 - 1) * 4) as *const u32;
+    let mut pixel = [0; 4];
+    pixel.copy_from_slice(&row[row.len() - 4..]);
+    check_eq!(unsafe { last.read_volatile() }, u32::from_le_bytes(pixel));
+    // Give the screen back to the console
+    unsafe { FRAMEBUFFER_GUARD.lock().assume_init_mut().redraw() };
+
+    check_eq!(sys_munmap(user(addr), info.size), Ok(0));
+    check_eq!(fs::close_fd(fd), Ok(()));
+    all_good!()
+}
@@ -0,0 +1,150 @@
+use alloc::vec::Vec;
+
+use crate::device::tree::{self, DeviceClass, Resource};
+use crate::error::Error;
+use crate::scheduler::spin::Mutex;
+use esqtest::*;
+
+/// Ports no real device of the test machines uses
+const TEST_PORT: u16 = 0xFE00;
+
+#[esqtest::test]
+pub fn test_resource_overlap() {
+    let io = Resource::IoPorts {
+        base: TEST_PORT,
+        len: 8,
+    };
+    check!(io.overlaps(&Resource::IoPorts {
+        base: TEST_PORT + 7,
+        len: 1
+    }));
+    check!(!io.overlaps(&Resource::IoPorts {
+        base: TEST_PORT + 8,
+        len: 4
+    }));
+    check!(!io.overlaps(&Resource::IoPorts {
+        base: TEST_PORT,
+        len: 0
+    }));
+    // Ports and memory are different address spaces
+    check!(!io.overlaps(&Resource::Mmio {
+        base: TEST_PORT as u64,
+        len: 8
+    }));
+    check!(!Resource::Irq(4).overlaps(&Resource::Irq(4)));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_device_tree() {
+    let io = Resource::IoPorts {
+        base: TEST_PORT,
+        len: 8,
+    };
+    let root = tree::register("test-bus", DeviceClass::Bus, None, "test", &[]).unwrap();
+    let child = tree::register("child", DeviceClass::Other, Some(root), "test", &[io]).unwrap();
+    check_eq!(tree::path(child).as_deref(), Some("/test-bus/child"));
+    check_eq!(tree::find("/test-bus/child"), Some(child));
+    check_eq!(tree::find("/test-bus/missing"), None);
+    check_eq!(
+        tree::register("child", DeviceClass::Other, Some(root), "test", &[]),
+        Err(Error::AlreadyExists)
+    );
+    check_eq!(
+        tree::register("a/b", DeviceClass::Other, Some(root), "test", &[]),
+        Err(Error::InvalidArgument)
+    );
+
+    // Overlapping ports are refused, a shared IRQ is not
+    let overlapping = Resource::IoPorts {
+        base: TEST_PORT + 4,
+        len: 8,
+    };
+    check_eq!(
+        tree::register(
+            "other",
+            DeviceClass::Other,
+            Some(root),
+            "test",
+            &[overlapping]
+        ),
+        Err(Error::DeviceOrResourceBusy)
+    );
+    check_eq!(tree::find("/test-bus/other"), None);
+    let sharing = tree::register(
+        "sharing",
+        DeviceClass::Other,
+        Some(root),
+        "test",
+        &[Resource::Irq(9)],
+    )
+    .unwrap();
+
+    let mut seen = alloc::vec::Vec::new();
+    tree::walk(|depth, id, _| {
+        if id == root || id == child || id == sharing {
+            seen.push((depth, id));
+        }
+    });
+    check_eq!(seen, [(0, root), (1, child), (1, sharing)]);
+
+    check_eq!(tree::unregister(root), Err(Error::DeviceOrResourceBusy));
+    check_eq!(tree::unregister(child), Ok(()));
+    check_eq!(tree::unregister(sharing), Ok(()));
+    check_eq!(tree::unregister(root), Ok(()));
+    check_eq!(tree::get(child).map(|node| node.name), None);
+    // The ports are free again
+    let again = tree::register("again", DeviceClass::Other, None, "test", &[io]).unwrap();
+    check_eq!(tree::unregister(again), Ok(()));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_platform_devices() {
+    // Registered by the PIT driver, which has to succeed for the kernel to boot
+    let pit = tree::find("/platform/pit").and_then(tree::get);
+    check_eq!(
+        pit.as_ref().map(|node| node.class),
+        Some(DeviceClass::Timer)
+    );
+    check!(pit.map_or(false, |node| node.resources.contains(&Resource::Irq(0))));
+    all_good!()
+}
+
+/// The data of every shutdown hook that ran, in order
+static HOOKS_RUN: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+fn record_hook(data: usize) {
+    HOOKS_RUN.lock().push(data);
+}
+
+#[esqtest::test]
+pub fn test_shutdown_hooks() {
+    let root = tree::register("test-hooks", DeviceClass::Bus, None, "test", &[]).unwrap();
+    let bridge = tree::register("bridge", DeviceClass::Bridge, Some(root), "test", &[]).unwrap();
+    let disk = tree::register("disk", DeviceClass::Storage, Some(bridge), "test", &[]).unwrap();
+    let plain = tree::register("plain", DeviceClass::Other, Some(root), "test", &[]).unwrap();
+    check_eq!(tree::set_shutdown_hook(root, record_hook, 0), Ok(()));
+    check_eq!(tree::set_shutdown_hook(bridge, record_hook, 1), Ok(()));
+    check_eq!(tree::set_shutdown_hook(disk, record_hook, 2), Ok(()));
+    check!(tree::get(disk).map_or(false, |node| node.shutdown.is_some()));
+    check!(tree::get(plain).map_or(false, |node| node.shutdown.is_none()));
+
+    // Children before their parents, and nothing outside of the subtree
+    HOOKS_RUN.lock().clear();
+    check_eq!(tree::run_shutdown_hooks(Some(root)), 3);
+    check_eq!(HOOKS_RUN.lock().as_slice(), &[2, 1, 0]);
+    HOOKS_RUN.lock().clear();
+    check_eq!(tree::run_shutdown_hooks(Some(bridge)), 2);
+    check_eq!(HOOKS_RUN.lock().as_slice(), &[2, 1]);
+    check_eq!(tree::run_shutdown_hooks(Some(plain)), 0);
+
+    for id in [disk, bridge, plain, root] {
+        check_eq!(tree::unregister(id), Ok(()));
+    }
+    check_eq!(
+        tree::set_shutdown_hook(disk, record_hook, 2),
+        Err(Error::NoSuchDevice)
+    );
+    all_good!()
+}
@@ -0,0 +1,159 @@
+use bks::PAGE_SIZE;
+
+use crate::error::Error;
+use crate::memory::dma::{
+    BounceBuffer, DmaBuffer, DmaConstraints, DmaLayout, DMA_BOUNCED, DMA_BOUNCE_BYTES, DMA_DIRECT,
+};
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::phys_to_virt;
+use esqtest::*;
+
+fn free_memory() -> i64 {
+    unsafe {
+        PAGE_FRAME_ALLOCATOR
+            .lock()
+            .assume_init_mut()
+            .get_free_memory()
+    }
+}
+
+#[esqtest::test]
+pub fn test_dma_constraints() {
+    let free = free_memory();
+    {
+        let buffer = match DmaBuffer::new_zeroed(3 * PAGE_SIZE as usize + 1, DmaConstraints::ANY) {
+            Ok(buffer) => buffer,
+            Err(_) => return 1,
+        };
+        check_eq!(buffer.len(), 3 * PAGE_SIZE as usize + 1);
+        check_eq!(buffer.virt(), phys_to_virt(buffer.phys()));
+        check!(buffer.as_slice().iter().all(|byte| *byte == 0));
+        check_eq!(free_memory(), free - 4 * PAGE_SIZE as i64);
+
+        let constraints = DmaConstraints {
+            align: 0x10000,
+            ..DmaConstraints::BELOW_4GIB
+        };
+        let aligned = match DmaBuffer::new(PAGE_SIZE as usize, constraints) {
+            Ok(buffer) => buffer,
+            Err(_) => return 1,
+        };
+        check_eq!(aligned.phys().as_u64() % 0x10000, 0);
+        check!(aligned.phys().as_u64() + PAGE_SIZE <= 1 << 32);
+    }
+    // The frames are freed on drop
+    check_eq!(free_memory(), free);
+
+    check_eq!(
+        DmaBuffer::new(0, DmaConstraints::ANY).err(),
+        Some(Error::InvalidArgument)
+    );
+    let misaligned = DmaConstraints {
+        align: 3 * PAGE_SIZE,
+        ..DmaConstraints::ANY
+    };
+    check_eq!(
+        DmaBuffer::new(1, misaligned).err(),
+        Some(Error::InvalidArgument)
+    );
+    // Nothing fits below the first page
+    let tiny = DmaConstraints {
+        max_phys_addr: 0xFFF,
+        ..DmaConstraints::ANY
+    };
+    check_eq!(
+        DmaBuffer::new(2 * PAGE_SIZE as usize, tiny).err(),
+        Some(Error::OutOfMemory)
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_dma_layout() {
+    let mut layout = DmaLayout::new();
+    let list = layout.push(1024, 1024);
+    let fis = layout.push(256, 256);
+    let table = layout.push(100, 128);
+    let big = layout.push(16, 0x2000);
+    check_eq!((list.offset, fis.offset, table.offset), (0, 1024, 1280));
+    check_eq!(big.offset, 0x2000);
+    check_eq!(layout.size(), 0x2010);
+    check_eq!(layout.align(), 0x2000);
+
+    let mut buffer = match DmaBuffer::with_layout(&layout, DmaConstraints::ANY) {
+        Ok(buffer) => buffer,
+        Err(_) => return 1,
+    };
+    check_eq!(buffer.phys().as_u64() % 0x2000, 0);
+    check_eq!(
+        buffer.phys_of(table).as_u64(),
+        buffer.phys().as_u64() + 1280
+    );
+    check_eq!(buffer.phys_of(big).as_u64() % 0x2000, 0);
+    buffer.slice_mut(fis).fill(0xAB);
+    check_eq!(buffer.as_slice()[1023], 0);
+    check_eq!(buffer.as_slice()[1024], 0xAB);
+    check_eq!(buffer.slice(fis).len(), 256);
+    check_eq!(buffer.as_slice()[1280], 0);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_dma_bounce() {
+    // Freeing a buffer below the data guarantees that there is room for a bounce buffer
+    let low = match DmaBuffer::new(PAGE_SIZE as usize, DmaConstraints::ANY) {
+        Ok(buffer) => buffer,
+        Err(_) => return 1,
+    };
+    let mut data = match DmaBuffer::new(PAGE_SIZE as usize, DmaConstraints::ANY) {
+        Ok(buffer) => buffer,
+        Err(_) => return 1,
+    };
+    if data.phys() <= low.phys() {
+        return 1;
+    }
+    drop(low);
+    let addr = data.virt().as_u64();
+    let below = DmaConstraints {
+        max_phys_addr: data.phys().as_u64() - 1,
+        ..DmaConstraints::ANY
+    };
+    check!(DmaConstraints::ANY.reaches(addr, 16));
+    check!(!below.reaches(addr, 16));
+
+    let (direct, bounced, bytes) = (DMA_DIRECT.get(), DMA_BOUNCED.get(), DMA_BOUNCE_BYTES.get());
+    let unbounced = match unsafe { BounceBuffer::new(addr, 16, DmaConstraints::ANY, true) } {
+        Ok(buffer) => buffer,
+        Err(_) => return 1,
+    };
+    check!(!unbounced.is_bounced());
+    check_eq!(unbounced.addr(), addr);
+    check_eq!(DMA_DIRECT.get(), direct + 1);
+
+    // Out to the device, the data is copied into the bounce buffer
+    data.as_mut_slice()[..4].copy_from_slice(b"dma!");
+    let out = match unsafe { BounceBuffer::new(addr, 4, below, true) } {
+        Ok(buffer) => buffer,
+        Err(_) => return 1,
+    };
+    check!(out.is_bounced());
+    check!(below.reaches(out.addr(), 4));
+    check_eq!(unsafe { *(out.addr() as *const [u8; 4]) }, *b"dma!");
+    out.finish();
+
+    // In from the device, what it wrote is copied back when the transfer finishes
+    let input = match unsafe { BounceBuffer::new(addr, 4, below, false) } {
+        Ok(buffer) => buffer,
+        Err(_) => return 1,
+    };
+    unsafe { *(input.addr() as *mut [u8; 4]) = *b"back" };
+    check_eq!(&data.as_slice()[..4], b"dma!");
+    input.finish();
+    check_eq!(&data.as_slice()[..4], b"back");
+    check_eq!(DMA_BOUNCED.get(), bounced + 2);
+    check_eq!(DMA_BOUNCE_BYTES.get(), bytes + 8);
+
+    all_good!()
+}
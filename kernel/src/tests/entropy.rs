@@ -0,0 +1,28 @@
+use crate::memory::kaslr::{random_slot, ALIGN};
+use crate::rand::{next_u64, range};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_entropy_range() {
+    for _ in 0..256 {
+        let value = range(10, 20);
+        check!((10..20).contains(&value));
+    }
+    check_eq!(range(7, 8), 7);
+    // Ten draws of 64 bits that are all the same are not random
+    let first = next_u64();
+    check!((0..10).any(|_| next_u64() != first));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_kaslr_slot() {
+    for _ in 0..64 {
+        let slot = random_slot(ALIGN + 1, 16 * ALIGN);
+        check_eq!(slot % ALIGN, 0);
+        check!((2 * ALIGN..16 * ALIGN).contains(&slot));
+    }
+    // An empty span rounds up the start
+    check_eq!(random_slot(ALIGN + 1, ALIGN + 2), 2 * ALIGN);
+    all_good!()
+}
@@ -0,0 +1,59 @@
+use crate::arch::interrupts::exceptions::IDTException;
+use crate::error::Error;
+use crate::memory::paging::pat::CacheType;
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_const_enum_all() {
+    check_eq!(IDTException::ALL.len(), IDTException::len);
+    check_eq!(IDTException::ALL[0], IDTException::DivideByZero);
+    check_eq!(
+        IDTException::ALL.last().copied(),
+        Some(IDTException::SecurityException)
+    );
+    check_eq!(
+        CacheType::ALL,
+        &[
+            CacheType::Uncacheable,
+            CacheType::WriteCombining,
+            CacheType::WriteThrough,
+            CacheType::WriteProtect,
+            CacheType::WriteBack,
+            CacheType::UncacheableMinus,
+        ]
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_const_enum_name() {
+    check_eq!(IDTException::name(&IDTException::PageFault), "PageFault");
+    check_eq!(IDTException::name(&0x1D), "VMMCommunicationException");
+    check_eq!(IDTException::name(&0xF), "Unknown");
+    check_eq!(CacheType::name(&CacheType::WriteBack), "WriteBack");
+    check_eq!(Error::name(&Error::OutOfMemory), "OutOfMemory");
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_const_enum_try_from_value() {
+    for exception in IDTException::ALL {
+        check_eq!(IDTException::try_from_value(*exception), Some(*exception));
+    }
+    // Reserved vectors
+    check_eq!(IDTException::try_from_value(0x9), None);
+    check_eq!(IDTException::try_from_value(0xF), None);
+    for vector in 0x16..=0x1B {
+        check_eq!(IDTException::try_from_value(vector), None);
+    }
+    check_eq!(IDTException::try_from_value(0x1F), None);
+    check_eq!(IDTException::try_from_value(0x20), None);
+
+    check_eq!(CacheType::try_from_value(6), Some(CacheType::WriteBack));
+    check_eq!(CacheType::try_from_value(0x100), None);
+    check_eq!(Error::try_from_value(12), Some(Error::OutOfMemory));
+
+    all_good!()
+}
@@ -0,0 +1,103 @@
+use alloc::format;
+
+use crate::arch::interrupts::exceptions::{
+    DescriptorTable, ExceptionType, IDTException, SelectorErrorCode,
+};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_exception_error_codes() {
+    let with_error_code = [0x8, 0xA, 0xB, 0xC, 0xD, 0xE, 0x11, 0x15, 0x1D, 0x1E];
+    for vector in 0..0x20 {
+        check_eq!(
+            IDTException::has_error_code(&vector),
+            with_error_code.contains(&vector)
+        );
+    }
+    // Interrupts never get one
+    check!(!IDTException::has_error_code(&0x20));
+    check!(IDTException::has_selector_error_code(
+        &IDTException::GeneralProtectionFault
+    ));
+    check!(!IDTException::has_selector_error_code(
+        &IDTException::PageFault
+    ));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_selector_error_code() {
+    // Not caused by a segment
+    let code = SelectorErrorCode(0);
+    check!(!code.external());
+    check_eq!(code.table(), DescriptorTable::Gdt);
+    check_eq!(code.index(), 0);
+
+    // GDT entry 2, e.g. a bad data segment selector of 0x10
+    let code = SelectorErrorCode(0x10);
+    check_eq!(code.table(), DescriptorTable::Gdt);
+    check_eq!(code.index(), 2);
+
+    // IDT vector 0x21 while delivering an external interrupt
+    let code = SelectorErrorCode(0x21 << 3 | 0b011);
+    check!(code.external());
+    check_eq!(code.table(), DescriptorTable::Idt);
+    check_eq!(code.index(), 0x21);
+    check_eq!(
+        format!("{:?}", code),
+        "SelectorErrorCode { external: true, table: Idt, index: 0x21 }"
+    );
+
+    // Both encodings with bit 1 set refer to the IDT
+    check_eq!(SelectorErrorCode(0b110).table(), DescriptorTable::Idt);
+    check_eq!(SelectorErrorCode(0b100).table(), DescriptorTable::Ldt);
+    check_eq!(SelectorErrorCode(0xFFFF).index(), 0x1FFF);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_exception_types() {
+    use ExceptionType::*;
+    // Intel SDM Vol. 3A, Table 6-1, reserved vectors are aborts. Review changes to this table.
+    let expected = [
+        Fault,     // 0x00 #DE
+        Trap,      // 0x01 #DB
+        Interrupt, // 0x02 NMI
+        Trap,      // 0x03 #BP
+        Trap,      // 0x04 #OF
+        Fault,     // 0x05 #BR
+        Fault,     // 0x06 #UD
+        Fault,     // 0x07 #NM
+        Abort,     // 0x08 #DF
+        Abort,     // 0x09 reserved
+        Fault,     // 0x0A #TS
+        Fault,     // 0x0B #NP
+        Fault,     // 0x0C #SS
+        Fault,     // 0x0D #GP
+        Fault,     // 0x0E #PF
+        Abort,     // 0x0F reserved
+        Fault,     // 0x10 #MF
+        Fault,     // 0x11 #AC
+        Abort,     // 0x12 #MC
+        Fault,     // 0x13 #XM
+        Fault,     // 0x14 #VE
+        Fault,     // 0x15 #CP
+        Abort,     // 0x16 reserved
+        Abort,     // 0x17 reserved
+        Abort,     // 0x18 reserved
+        Abort,     // 0x19 reserved
+        Abort,     // 0x1A reserved
+        Abort,     // 0x1B reserved
+        Fault,     // 0x1C #HV
+        Fault,     // 0x1D #VC
+        Fault,     // 0x1E #SX
+        Abort,     // 0x1F reserved
+    ];
+    for (vector, ty) in expected.iter().enumerate() {
+        check_eq!(IDTException::type_(&vector), *ty);
+        check_eq!(IDTException::is_recoverable(&vector), *ty != Abort);
+    }
+    all_good!()
+}
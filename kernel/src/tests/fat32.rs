@@ -0,0 +1,147 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use super::block::MemDisk;
+use crate::block::{cache, BlockDevice};
+use crate::error::Error;
+use crate::fs::fat32::{self, Fat32};
+use crate::fs::{self, File, FileSystem, FileType};
+use esqtest::*;
+
+/// Generated by `data/mkfat32.py`
+static IMAGE: &[u8] = include_bytes!("data/fat32.img");
+
+fn volume() -> Arc<Fat32> {
+    Fat32::new(Arc::new(MemDisk::from_bytes(IMAGE))).unwrap()
+}
+
+fn read(fs: &Fat32, path: &[&str]) -> Result<Vec<u8>, Error> {
+    let file = fs.open(path)?;
+    let mut data = alloc::vec![0u8; file.metadata().size as usize];
+    let read = file.read_at(0, &mut data)?;
+    data.truncate(read);
+    Ok(data)
+}
+
+#[esqtest::test]
+pub fn test_fat32_read() {
+    let fs = volume();
+    check_eq!(read(&fs, &["HELLO.TXT"]).unwrap(), b"Hello, FAT32!\n");
+    // Names are case insensitive
+    check_eq!(read(&fs, &["hello.txt"]).unwrap(), b"Hello, FAT32!\n");
+
+    // Spans three clusters
+    let kernel = read(&fs, &["BOOT", "KERNEL.ELF"]).unwrap();
+    check_eq!(kernel.len(), 1300);
+    check!(kernel
+        .iter()
+        .enumerate()
+        .all(|(idx, byte)| *byte as usize == idx % 251));
+
+    let file = fs.open(&["BOOT", "KERNEL.ELF"]).unwrap();
+    let mut buf = [0u8; 8];
+    check_eq!(file.read_at(1296, &mut buf).unwrap(), 4);
+    check_eq!(buf[0], (1296 % 251) as u8);
+    check_eq!(file.read_at(1300, &mut buf).unwrap(), 0);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fat32_names() {
+    let fs = volume();
+    let names: Vec<String> = fs
+        .open(&[])
+        .unwrap()
+        .read_dir(0)
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    check!(names.iter().any(|name| name == "A long file name.txt"));
+    check!(names.iter().any(|name| name == "Ünïcode.txt"));
+    // The long name has a wrong checksum, so the short name is used
+    check!(names.iter().any(|name| name == "BADCHE~1.TXT"));
+    check!(names.iter().any(|name| name == "lower.txt"));
+    check!(!names.iter().any(|name| name == "." || name == ".."));
+
+    check_eq!(
+        read(&fs, &["A long file name.txt"]).unwrap(),
+        b"long name\n"
+    );
+    // The short alias of a long name works as well
+    check_eq!(read(&fs, &["ALONGF~1.TXT"]).unwrap(), b"long name\n");
+    check_eq!(read(&fs, &["Ünïcode.txt"]).unwrap(), b"unicode\n");
+    check_eq!(read(&fs, &["BOOT", "..", "lower.txt"]).unwrap(), b"lower\n");
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fat32_cached_reads() {
+    let disk = Arc::new(MemDisk::from_bytes(IMAGE));
+    let fs = Fat32::new(disk.clone()).unwrap();
+    let first = read(&fs, &["BOOT", "KERNEL.ELF"]).unwrap();
+    let reads = disk.reads();
+    let hits = cache::HITS.get();
+    let chain_hits = fat32::CHAIN_HITS.get();
+
+    // Everything the second read needs is cached, so the disk is not asked again
+    check_eq!(read(&fs, &["BOOT", "KERNEL.ELF"]).unwrap(), first);
+    check_eq!(disk.reads(), reads);
+    check!(cache::HITS.get() > hits);
+    // Neither is the chain of the file walked again
+    check!(fat32::CHAIN_HITS.get() > chain_hits);
+
+    let device: Arc<dyn BlockDevice> = disk.clone();
+    check!(cache::invalidate(&device) > 0);
+    check_eq!(read(&fs, &["BOOT", "KERNEL.ELF"]).unwrap(), first);
+    check!(disk.reads() > reads);
+    cache::invalidate(&device);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fat32_errors() {
+    let fs = volume();
+    // Chains that loop or leave the volume must fail instead of hanging
+    check_eq!(read(&fs, &["LOOP.BIN"]), Err(Error::IOError));
+    check_eq!(read(&fs, &["RANGE.BIN"]), Err(Error::IOError));
+
+    check_eq!(
+        fs.open(&["MISSING.TXT"]).err(),
+        Some(Error::NoSuchFileOrDirectory)
+    );
+    check_eq!(
+        fs.open(&["HELLO.TXT", "X"]).err(),
+        Some(Error::NotADirectory)
+    );
+    check_eq!(read(&fs, &["BOOT"]), Err(Error::IsADirectory));
+
+    // Not a FAT32 volume
+    let empty = Arc::new(MemDisk::from_bytes(&[0; 4096]));
+    check!(Fat32::new(empty).is_err());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fat32_vfs() {
+    fs::mount("/fat32test", volume()).unwrap();
+    let boot = fs::open("/fat32test/BOOT").unwrap();
+    check_eq!(boot.metadata().ty, FileType::Directory);
+    check_eq!(
+        fs::read_to_end("/fat32test/HELLO.TXT").unwrap(),
+        b"Hello, FAT32!\n"
+    );
+
+    let fd = fs::open_fd("/fat32test/BOOT/KERNEL.ELF").unwrap();
+    let mut buf = [0u8; 1000];
+    check_eq!(fs::read_fd(fd, &mut buf).unwrap(), 1000);
+    check_eq!(fs::read_fd(fd, &mut buf).unwrap(), 300);
+    check_eq!(fs::read_fd(fd, &mut buf).unwrap(), 0);
+    check!(fs::close_fd(fd).is_ok());
+    check_eq!(fs::read_fd(fd, &mut buf), Err(Error::BadFileNumber));
+
+    all_good!()
+}
@@ -0,0 +1,290 @@
+//! # Fault Injection
+//! Causes recoverable exceptions on purpose and checks that their handlers see them as they
+//! should. Every fault happens in a leaf function written in assembly, which the handler
+//! returns from once `set_test_expectation()` told it the fault is expected.
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::arch::interrupts::exceptions::{
+    register_recovery_handler, set_test_expectation, take_caught_fault,
+    unregister_recovery_handler, AlignmentCheck, Breakpoint, CaughtFault, DivideByZero,
+    GeneralProtectionFault, InvalidOpcode, PageFault, PageFaultErrorCode,
+};
+use crate::arch::interrupts::in_exception_context;
+use crate::arch::interrupts::interrupt_frame::InterruptFrame;
+use crate::drivers::serial::{self, RAW_BYTES};
+use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::klog;
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::paging::page_table_manager::{PageTableFlag, PAGE_TABLE_MANAGER};
+use crate::memory::paging::tlb;
+use crate::memory::VirtualAddress;
+use crate::stats::IRQ_COUNT;
+use esqtest::*;
+
+const CR0_WP: u64 = 1 << 16;
+const NON_CANONICAL: u64 = 0x8000_0000_0000_0000;
+
+global_asm!(
+    "
+.global faultinject_divide_by_zero
+faultinject_divide_by_zero:
+    xor ecx, ecx
+    xor edx, edx
+    mov eax, 1
+    div rcx
+    ret
+
+.global faultinject_ud2
+faultinject_ud2:
+    ud2
+    ret
+
+.global faultinject_write
+faultinject_write:
+    mov byte ptr [rdi], 1
+    ret
+
+.global faultinject_read
+faultinject_read:
+    mov rax, [rdi]
+    ret
+
+.global faultinject_unaligned_with_ac
+faultinject_unaligned_with_ac:
+    pushfq
+    or qword ptr [rsp], 0x40000
+    popfq
+    mov eax, dword ptr [rdi + 1]
+    pushfq
+    and qword ptr [rsp], -0x40001
+    popfq
+    ret
+"
+);
+
+extern "C" {
+    fn faultinject_divide_by_zero();
+    fn faultinject_ud2();
+    fn faultinject_write(addr: u64);
+    fn faultinject_read(addr: u64) -> u64;
+    fn faultinject_unaligned_with_ac(addr: u64) -> u32;
+}
+
+/// # Inject
+/// Runs `fault` expecting the exception `vector`
+///
+/// ## Returns
+/// - Option<CaughtFault> = The fault the handler caught, `None` if there was none
+pub fn inject(vector: usize, fault: impl FnOnce()) -> Option<CaughtFault> {
+    set_test_expectation(vector);
+    fault();
+    take_caught_fault()
+}
+
+/// # With Read Only Page
+/// Runs `f` with a fresh page that is mapped read-only and write protection enforced in the
+/// kernel
+pub fn with_read_only_page<R>(f: impl FnOnce(u64) -> R) -> R {
+    let page = unsafe { PAGE_FRAME_ALLOCATOR.lock().assume_init_mut().request_page() };
+    let remap = |flags| {
+        let mut manager = PAGE_TABLE_MANAGER.lock();
+        unsafe { manager.assume_init_mut() }.map_page(page, page, flags);
+        tlb::flush_page(VirtualAddress::new(page));
+    };
+    remap(PageTableFlag::PRESENT);
+    let cr0 = read_cr0();
+    unsafe { write_cr0(cr0 | CR0_WP) };
+
+    let ret = f(page);
+
+    unsafe { write_cr0(cr0) };
+    remap(PageTableFlag::PRESENT | PageTableFlag::READ_WRITE);
+    unsafe {
+        PAGE_FRAME_ALLOCATOR
+            .lock()
+            .assume_init_mut()
+            .free_page(page)
+    };
+    ret
+}
+
+fn read_cr0() -> u64 {
+    let cr0: u64;
+    unsafe { asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags)) };
+    cr0
+}
+
+unsafe fn write_cr0(cr0: u64) {
+    asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+}
+
+#[esqtest::test]
+pub fn test_fault_divide_by_zero() {
+    let count = IRQ_COUNT.get(DivideByZero);
+    let caught = inject(DivideByZero, || unsafe { faultinject_divide_by_zero() });
+    check_eq!(caught.map(|fault| fault.vector), Some(DivideByZero));
+    check_eq!(caught.and_then(|fault| fault.error_code), None);
+    let start = faultinject_divide_by_zero as usize as u64;
+    check_eq!(
+        caught.map(|fault| (start..start + 16).contains(&fault.rip)),
+        Some(true)
+    );
+    check_eq!(IRQ_COUNT.get(DivideByZero), count + 1);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fault_invalid_opcode() {
+    let count = IRQ_COUNT.get(InvalidOpcode);
+    let caught = inject(InvalidOpcode, || unsafe { faultinject_ud2() });
+    check_eq!(
+        caught.map(|fault| fault.rip),
+        Some(faultinject_ud2 as usize as u64)
+    );
+    check_eq!(IRQ_COUNT.get(InvalidOpcode), count + 1);
+    // The expectation is used up by the fault
+    check_eq!(take_caught_fault(), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fault_read_only_write() {
+    let count = IRQ_COUNT.get(PageFault);
+    let (page, caught) = with_read_only_page(|page| {
+        let caught = inject(PageFault, || unsafe { faultinject_write(page) });
+        (page, caught)
+    });
+    let write =
+        PageFaultErrorCode::PAGE_PROTECTON_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE_ACCESS;
+    check_eq!(caught.map(|fault| fault.vector), Some(PageFault));
+    check_eq!(
+        caught.and_then(|fault| fault.error_code),
+        Some(write.bits())
+    );
+    check_eq!(caught.and_then(|fault| fault.address), Some(page));
+    check_eq!(
+        caught.map(|fault| fault.rip),
+        Some(faultinject_write as usize as u64)
+    );
+    check_eq!(IRQ_COUNT.get(PageFault), count + 1);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fault_non_canonical() {
+    let count = IRQ_COUNT.get(GeneralProtectionFault);
+    let caught = inject(GeneralProtectionFault, || unsafe {
+        faultinject_read(NON_CANONICAL);
+    });
+    check_eq!(
+        caught.map(|fault| fault.vector),
+        Some(GeneralProtectionFault)
+    );
+    // A non-canonical address is not a segment, so the error code is zero
+    check_eq!(caught.and_then(|fault| fault.error_code), Some(0));
+    check_eq!(IRQ_COUNT.get(GeneralProtectionFault), count + 1);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fault_alignment_check_in_kernel() {
+    // Alignment checks only apply at CPL 3, so the kernel reads unaligned data even with AC set
+    let data = [0x11u8, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+    let mut value = 0;
+    let caught = inject(AlignmentCheck, || {
+        value = unsafe { faultinject_unaligned_with_ac(data.as_ptr() as u64) };
+    });
+    check_eq!(caught, None);
+    check_eq!(value, 0x5544_3322);
+
+    all_good!()
+}
+
+static RECOVERED: AtomicUsize = AtomicUsize::new(0);
+
+/// Recovers from the `ud2` of `faultinject_ud2()` by skipping it
+fn skip_ud2(frame: &mut InterruptFrame, _: Option<u64>) -> bool {
+    RECOVERED.fetch_add(1, Ordering::SeqCst);
+    unsafe {
+        let rip = frame.rip;
+        core::ptr::write_volatile(core::ptr::addr_of_mut!(frame.rip), rip + 2);
+    }
+    true
+}
+
+#[esqtest::test]
+pub fn test_fault_recovery_handler() {
+    RECOVERED.store(0, Ordering::SeqCst);
+    let previous = register_recovery_handler(InvalidOpcode, skip_ud2);
+    unsafe { faultinject_ud2() };
+    match previous {
+        Some(previous) => register_recovery_handler(InvalidOpcode, previous),
+        None => unregister_recovery_handler(InvalidOpcode),
+    };
+    check_eq!(RECOVERED.load(Ordering::SeqCst), 1);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_trap_continues() {
+    // The stub waits for GDB on every `int3`
+    if cfg!(feature = "gdbstub") {
+        return 1;
+    }
+    // Traps are logged and execution continues after the trapping instruction
+    let before = IRQ_COUNT.get(Breakpoint);
+    unsafe { asm!("int3") };
+    check_eq!(IRQ_COUNT.get(Breakpoint), before + 1);
+    all_good!()
+}
+
+/// Stands in for the panic message, a real panic would halt the kernel
+const HELD_CONSOLE_MESSAGE: &str = "Triggered Fault InvalidOpcode with the console held";
+static REPORTED_IN_EXCEPTION: AtomicBool = AtomicBool::new(false);
+
+/// Prints like the handler of an unexpected fault does when it panics, then skips the `ud2`
+fn report_ud2(frame: &mut InterruptFrame, error_code: Option<u64>) -> bool {
+    REPORTED_IN_EXCEPTION.store(in_exception_context(), Ordering::SeqCst);
+    crate::kprintln!("{}", HELD_CONSOLE_MESSAGE);
+    skip_ud2(frame, error_code)
+}
+
+#[esqtest::test]
+pub fn test_fault_with_console_held() {
+    // The message can only be seen on COM1
+    if !serial::is_present() {
+        return 1;
+    }
+    check!(!in_exception_context());
+    REPORTED_IN_EXCEPTION.store(false, Ordering::SeqCst);
+    let raw = RAW_BYTES.get();
+    let written = klog::written();
+    let previous = register_recovery_handler(InvalidOpcode, report_ud2);
+    {
+        // Printing from the handler waited for this forever
+        let _console = FRAMEBUFFER_GUARD.lock();
+        unsafe { faultinject_ud2() };
+    }
+    match previous {
+        Some(previous) => register_recovery_handler(InvalidOpcode, previous),
+        None => unregister_recovery_handler(InvalidOpcode),
+    };
+    check!(REPORTED_IN_EXCEPTION.load(Ordering::SeqCst));
+    check!(!in_exception_context());
+    // With the newline
+    check!(RAW_BYTES.get() >= raw + HELD_CONSOLE_MESSAGE.len() as u64 + 1);
+
+    // The next print takes the message from the overflow buffer into the log
+    crate::kprint!("");
+    let mut log = [0; 1024];
+    let (_, len) = klog::read(written, &mut log);
+    check!(log[..len]
+        .windows(HELD_CONSOLE_MESSAGE.len())
+        .any(|window| window == HELD_CONSOLE_MESSAGE.as_bytes()));
+    all_good!()
+}
@@ -0,0 +1,107 @@
+use alloc::vec::Vec;
+use alloc::{format, string::String};
+
+use crate::math::{hexdump, parse_u64, ByteSize, GroupedHex, HEXDUMP_ROW};
+use crate::memory::{PhysicalAddress, VirtualAddress};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_byte_size() {
+    check_eq!(format!("{}", ByteSize(0)), "0 B");
+    check_eq!(format!("{}", ByteSize(1)), "1 B");
+    check_eq!(format!("{}", ByteSize(1023)), "1023 B");
+    check_eq!(format!("{}", ByteSize(1024)), "1 KiB");
+    check_eq!(format!("{}", ByteSize(1025)), "1.00 KiB");
+    check_eq!(format!("{}", ByteSize(4096)), "4 KiB");
+    check_eq!(format!("{}", ByteSize(1024 * 1024 - 1)), "1023.99 KiB");
+    check_eq!(format!("{}", ByteSize(1024 * 1024)), "1 MiB");
+    check_eq!(format!("{}", ByteSize(1536 * 1024)), "1.50 MiB");
+    check_eq!(format!("{}", ByteSize(2 << 30)), "2 GiB");
+    check_eq!(format!("{}", ByteSize(1 << 40)), "1 TiB");
+    check_eq!(format!("{}", ByteSize(1 << 50)), "1 PiB");
+    check_eq!(format!("{}", ByteSize(1 << 60)), "1 EiB");
+    // Fractions are cut off, not rounded up
+    check_eq!(format!("{}", ByteSize(u64::MAX)), "15.99 EiB");
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_grouped_hex() {
+    check_eq!(format!("{}", GroupedHex(0)), "0x0");
+    check_eq!(format!("{}", GroupedHex(0xFFFF)), "0xffff");
+    check_eq!(format!("{}", GroupedHex(0x1_0000)), "0x1_0000");
+    check_eq!(format!("{}", GroupedHex(0x10_0000)), "0x10_0000");
+    check_eq!(format!("{}", GroupedHex(u64::MAX)), "0xffff_ffff_ffff_ffff");
+
+    check_eq!(
+        format!("{}", VirtualAddress::new(0x7FFF_DEAD_B000)),
+        "0x7fff_dead_b000"
+    );
+    check_eq!(
+        format!("{}", VirtualAddress::new(0xFFFF_8000_0010_0000)),
+        "0xffff_8000_0010_0000"
+    );
+    check_eq!(format!("{}", VirtualAddress::new(0)), "0x0");
+    check_eq!(
+        format!("{}", PhysicalAddress::new(0xFEE0_0000)),
+        "0xfee0_0000"
+    );
+    // Debug keeps the wrapper
+    check_eq!(
+        format!("{:?}", PhysicalAddress::new(0x1000)),
+        "PhysAddr(0x1000)"
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_parse_u64() {
+    check_eq!(parse_u64("0x2000000"), Some(0x200_0000));
+    check_eq!(parse_u64("0xFEE0_0000"), None);
+    check_eq!(parse_u64("4096"), Some(4096));
+    check_eq!(parse_u64("0x"), None);
+    check_eq!(parse_u64("0xzz"), None);
+    check_eq!(parse_u64("off"), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_hexdump() {
+    let bytes: Vec<u8> = (0..20u8)
+        .map(|idx| b'@' + idx)
+        .chain([0, 0x7F, b' '])
+        .collect();
+    let mut rows = Vec::new();
+    check!(hexdump(0x1000, &bytes, |row| {
+        rows.push(format!("{}", row));
+        true
+    }));
+    check_eq!(
+        rows,
+        [
+            "0x0000000000001000: 40 41 42 43 44 45 46 47  48 49 4a 4b 4c 4d 4e 4f  |@ABCDEFGHIJKLMNO|",
+            "0x0000000000001010: 50 51 52 53 00 7f 20                              |PQRS.. |",
+        ]
+    );
+
+    // The sink stops the dump
+    let mut count = 0;
+    check!(!hexdump(0, &[0; 4 * HEXDUMP_ROW], |_| {
+        count += 1;
+        count < 2
+    }));
+    check_eq!(count, 2);
+    check!(hexdump(0, &[], |_| false));
+    // The label is only a label, it may wrap
+    let mut last = String::new();
+    hexdump(u64::MAX, &[0; HEXDUMP_ROW + 1], |row| {
+        last = format!("{:x}", row.addr);
+        true
+    });
+    check_eq!(last, "f");
+
+    all_good!()
+}
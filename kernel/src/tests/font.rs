@@ -0,0 +1,146 @@
+use alloc::vec;
+use core::fmt::Write;
+
+use bks::{Framebuffer, PixelFormat};
+
+use crate::framebuffer::font::Psf;
+use crate::framebuffer::{self, Color, FramebufferError, FramebufferGuard, BYTES_PER_PIXEL};
+use esqtest::*;
+
+static PSF1: &[u8] = include_bytes!("../../../binaries/font/font.psf");
+static PSF2: &[u8] = include_bytes!("../../../binaries/font/font-16x32.psf");
+
+#[esqtest::test]
+pub fn test_psf_parse() {
+    let small = match Psf::parse("small", PSF1) {
+        Some(font) => font,
+        None => return 1,
+    };
+    check_eq!((small.width(), small.height()), (8, 16));
+    check_eq!(small.glyph_count(), 256);
+    check_eq!(small.glyph(b'A').len(), 16);
+
+    let large = match Psf::parse("large", PSF2) {
+        Some(font) => font,
+        None => return 1,
+    };
+    check_eq!((large.width(), large.height()), (16, 32));
+    check_eq!(large.glyph(b'A').len(), 64);
+    // The large font is the small one scaled up
+    let (a_small, a_large) = (small.glyph(b'A'), large.glyph(b'A'));
+    for y in 0..32 {
+        for x in 0..16 {
+            check_eq!(
+                large.is_set(a_large, x, y),
+                small.is_set(a_small, x / 2, y / 2)
+            );
+        }
+    }
+
+    check!(Psf::parse("garbage", b"not a font").is_none());
+    check!(Psf::parse("truncated", &PSF1[..100]).is_none());
+    check!(Psf::parse("truncated", &PSF2[..100]).is_none());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_set_font() {
+    let small = Psf::parse("small", PSF1).unwrap();
+    let large = Psf::parse("large", PSF2).unwrap();
+    let (width, height) = (64, 160);
+    let mut pixels = vec![0u32; width * height];
+    let raw = Framebuffer::new(
+        pixels.as_mut_ptr() as u64,
+        pixels.len() * BYTES_PER_PIXEL,
+        width,
+        height,
+        width,
+        PixelFormat::Rgb,
+    );
+    let info = match framebuffer::validate(&raw, Some(&small)) {
+        Ok(info) => info,
+        Err(_) => return 1,
+    };
+    let mut guard = FramebufferGuard::new(info, raw, small, Color::Black, Color::White);
+    guard.enable_backing_store();
+    check_eq!(guard.geometry(), (8, 10));
+
+    // The second line fills all eight columns and wraps
+    check!(guard.write_str("ab\ncdefghij").is_ok());
+    check_eq!(guard.cursor(), (2, 0));
+    check_eq!(guard.cells()[0].chr, b'a');
+    check_eq!(guard.cells()[8].chr, b'c');
+    check_eq!(guard.cells()[15].chr, b'j');
+
+    // Four columns with the large font, the rest of the second line is cut off
+    check!(guard.set_font(&large).is_ok());
+    check_eq!(guard.geometry(), (4, 5));
+    check_eq!(guard.cursor(), (2, 0));
+    check_eq!(guard.cells()[0].chr, b'a');
+    check_eq!(guard.cells()[4].chr, b'c');
+    check_eq!(guard.cells()[7].chr, b'f');
+    check_eq!(guard.cells()[8].chr, b' ');
+    // The screen shows the large glyphs
+    let glyph = large.glyph(b'c');
+    for y in 0..32 {
+        for x in 0..16 {
+            let expected = if large.is_set(glyph, x, y) {
+                Color::White as u32
+            } else {
+                Color::Black as u32
+            };
+            check_eq!(pixels[(32 + y) * width + x], expected);
+        }
+    }
+
+    check!(guard.set_font(&small).is_ok());
+    check_eq!(guard.cursor(), (2, 0));
+    check_eq!(guard.cells()[8].chr, b'c');
+    check_eq!(guard.cells()[12].chr, b' ');
+
+    // The cursor row does not fit with the large font, the first line goes to the scrollback
+    check!(guard.write_str("\n\n\n").is_ok());
+    check_eq!(guard.cursor(), (5, 0));
+    check!(guard.set_font(&large).is_ok());
+    check_eq!(guard.cursor(), (4, 0));
+    check_eq!(guard.cells()[0].chr, b'c');
+    check_eq!(guard.scrollback(), 1);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_set_font_too_large() {
+    let small = Psf::parse("small", PSF1).unwrap();
+    let large = Psf::parse("large", PSF2).unwrap();
+    let (width, height) = (64, 80);
+    let mut pixels = vec![0u32; width * height];
+    let raw = Framebuffer::new(
+        pixels.as_mut_ptr() as u64,
+        pixels.len() * BYTES_PER_PIXEL,
+        width,
+        height,
+        width,
+        PixelFormat::Rgb,
+    );
+    check!(framebuffer::validate(&raw, None).is_err());
+    let info = match framebuffer::validate(&raw, Some(&small)) {
+        Ok(info) => info,
+        Err(_) => return 1,
+    };
+    let mut guard = FramebufferGuard::new(info, raw, small, Color::Black, Color::White);
+    // Three lines of 32 pixels do not fit
+    check!(matches!(
+        guard.set_font(&large),
+        Err(FramebufferError::BadResolution { .. })
+    ));
+    check_eq!(guard.font().map(|font| font.name()), Some("small"));
+
+    // Nothing to redraw without a framebuffer
+    let mut serial = FramebufferGuard::serial_only(raw);
+    check!(serial.set_font(&large).is_ok());
+    check!(serial.font().is_none());
+
+    all_good!()
+}
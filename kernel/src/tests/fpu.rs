@@ -0,0 +1,64 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::fpu::{self, FpuMode, LAZY_RESTORES};
+use crate::scheduler;
+use crate::smp::{current_cpu, CpuMask};
+use esqtest::*;
+
+const ROUNDS: u64 = 1000;
+const PENDING: u64 = u64::MAX;
+static SUM_ONE: AtomicU64 = AtomicU64::new(PENDING);
+static SUM_THREE: AtomicU64 = AtomicU64::new(PENDING);
+
+/// # Accumulate
+/// Sums `i * scale` in XMM7 while yielding after every addition. The kernel is built without
+/// SSE, so nothing but the other tasks touches the register in between.
+fn accumulate(scale: u64) -> u64 {
+    unsafe { asm!("xorpd xmm7, xmm7", options(nomem, nostack)) };
+    for i in 1..=ROUNDS {
+        unsafe {
+            asm!(
+                "cvtsi2sd xmm6, {}",
+                "addsd xmm7, xmm6",
+                in(reg) i * scale,
+                options(nomem, nostack)
+            )
+        };
+        scheduler::yield_now();
+    }
+    let bits: u64;
+    unsafe { asm!("movq {}, xmm7", out(reg) bits, options(nomem, nostack)) };
+    bits
+}
+
+fn accumulate_one() {
+    SUM_ONE.store(accumulate(1), Ordering::SeqCst);
+}
+
+fn accumulate_three() {
+    SUM_THREE.store(accumulate(3), Ordering::SeqCst);
+}
+
+#[esqtest::test]
+pub fn test_fpu_state_across_switches() {
+    if fpu::mode() == FpuMode::None {
+        return 1;
+    }
+    let restores = LAZY_RESTORES.get();
+    let cpu = CpuMask::single(current_cpu());
+    scheduler::spawn_pinned("fpu-one", accumulate_one, cpu);
+    scheduler::spawn_pinned("fpu-three", accumulate_three, cpu);
+    while SUM_ONE.load(Ordering::SeqCst) == PENDING || SUM_THREE.load(Ordering::SeqCst) == PENDING {
+        scheduler::yield_now();
+    }
+
+    let expected = |scale: u64| ((scale * ROUNDS * (ROUNDS + 1) / 2) as f64).to_bits();
+    check_eq!(SUM_ONE.load(Ordering::SeqCst), expected(1));
+    check_eq!(SUM_THREE.load(Ordering::SeqCst), expected(3));
+    if fpu::is_lazy() {
+        check!(LAZY_RESTORES.get() > restores);
+    }
+
+    all_good!()
+}
@@ -0,0 +1,92 @@
+use super::user;
+use crate::block::cache;
+use crate::memory::frame_info::{self, frame_info, FrameFlags, FrameInfo};
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::paging::page_table_manager::translate;
+use crate::memory::usermem::{read_user, write_user};
+use crate::memory::vmm::{self, ADDRESS_SPACE};
+use crate::memory::{Frame, PhysicalAddress, UserVirtualAddress};
+use crate::syscall::mman::{
+    sys_mmap, sys_munmap, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE,
+};
+use esqtest::*;
+
+fn info(frame: u64) -> &'static FrameInfo {
+    frame_info(Frame::which_contains(PhysicalAddress::new(frame)))
+}
+
+#[esqtest::test]
+pub fn test_frame_info_allocator() {
+    let total = unsafe { PAGE_FRAME_ALLOCATOR.lock().assume_init_ref().total_memory() };
+    check_eq!(frame_info::frames().len() as u64, total / bks::PAGE_SIZE);
+    // Nothing is handed out of the region at address zero
+    check!(info(0).flags().contains(FrameFlags::RESERVED));
+
+    let frame = unsafe { PAGE_FRAME_ALLOCATOR.lock().assume_init_mut().request_page() };
+    check_eq!(info(frame).flags(), FrameFlags::empty());
+    check_eq!(info(frame).refcount(), 1);
+    check_eq!(info(frame).get(), 2);
+    check_eq!(info(frame).put(), 1);
+    unsafe {
+        PAGE_FRAME_ALLOCATOR
+            .lock()
+            .assume_init_mut()
+            .free_page(frame)
+    };
+    check_eq!(info(frame).flags(), FrameFlags::FREE);
+    check_eq!(info(frame).refcount(), 0);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_frame_info_zero_frame() {
+    let zero = info(vmm::zero_frame());
+    check_eq!(zero.flags(), FrameFlags::ZERO);
+    let refs = zero.refcount();
+    let addr = match sys_mmap(
+        UserVirtualAddress::null(),
+        bks::PAGE_SIZE,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        u64::MAX,
+        0,
+    ) {
+        Ok(addr) => addr,
+        Err(_) => return 1,
+    };
+
+    // A read shares the zero frame, a write breaks it off
+    check_eq!(read_user::<u64>(user(addr)), Ok(0));
+    check_eq!(zero.refcount(), refs + 1);
+    check_eq!(write_user(user(addr), 1u64), Ok(()));
+    check_eq!(zero.refcount(), refs);
+    let frame = match translate(addr) {
+        Some(frame) => frame,
+        None => return 1,
+    };
+    check_eq!(info(frame).refcount(), 1);
+
+    // The last reference frees the frame
+    check_eq!(sys_munmap(user(addr), bks::PAGE_SIZE), Ok(0));
+    check_eq!(info(frame).flags(), FrameFlags::FREE);
+    check_eq!(zero.refcount(), refs);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_frame_info_verify() {
+    let zero_pages = ADDRESS_SPACE.lock().stats().zero;
+    let cached = cache::pages();
+    let report = frame_info::verify();
+    check_eq!(report.reported, alloc::vec![]);
+    check_eq!(report.mismatches, 0);
+    check_eq!(report.page_cache, cached);
+    check!(report.zero <= 1);
+    if report.zero == 1 {
+        check_eq!(report.zero_refs as u64, zero_pages + 1);
+    }
+
+    all_good!()
+}
@@ -0,0 +1,142 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use super::{user, wait_for, PATIENCE_MS};
+use crate::error::Error;
+use crate::memory::usermem::{user_address, write_user};
+use crate::memory::UserVirtualAddress;
+use crate::scheduler;
+use crate::syscall::futex::{self, sys_futex, FUTEX_PRIVATE_FLAG, FUTEX_WAKE};
+use crate::syscall::mman::{
+    sys_mmap, sys_munmap, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE,
+};
+use esqtest::*;
+
+const ROUNDS: u32 = 100;
+
+// Kernel memory is in the lower half as well, so it stands in for user memory
+static WORD: AtomicU32 = AtomicU32::new(0);
+static ACK: AtomicU32 = AtomicU32::new(0);
+static DONE: AtomicBool = AtomicBool::new(false);
+/// The word in an anonymous mapping `anonymous_waiter()` waits on
+static ANONYMOUS: AtomicU64 = AtomicU64::new(0);
+static ANONYMOUS_WOKEN: AtomicBool = AtomicBool::new(false);
+
+fn addr(word: &AtomicU32) -> UserVirtualAddress {
+    user_address(word as *const AtomicU32 as u64).unwrap_or(UserVirtualAddress::null())
+}
+
+#[esqtest::test]
+pub fn test_futex_errors() {
+    WORD.store(5, Ordering::SeqCst);
+    check_eq!(futex::wait(addr(&WORD), 4, None), Err(Error::TryAgain));
+    check_eq!(
+        futex::wait(addr(&WORD), 5, Some(10)),
+        Err(Error::ConnectionTimedOut)
+    );
+    check_eq!(futex::waiters(addr(&WORD)), Ok(0));
+    check_eq!(
+        futex::wait(
+            addr(&WORD)
+                .checked_add(1)
+                .unwrap_or(UserVirtualAddress::null()),
+            5,
+            None
+        ),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        futex::wake(UserVirtualAddress::null(), 1),
+        Err(Error::BadFault)
+    );
+    check_eq!(sys_futex(addr(&WORD), 9, 0, 0), Err(Error::InvalidArgument));
+    // Nobody waits, nobody is woken. The timeout is not looked at, not even when it points
+    // into the kernel.
+    check_eq!(
+        sys_futex(
+            addr(&WORD),
+            FUTEX_WAKE | FUTEX_PRIVATE_FLAG,
+            1,
+            0xffff_8000_0000_0000
+        ),
+        Ok(0)
+    );
+    all_good!()
+}
+
+fn ping_pong() {
+    for round in 1..=ROUNDS {
+        loop {
+            let word = WORD.load(Ordering::SeqCst);
+            if word >= round {
+                break;
+            }
+            // Changed in the meantime is fine, the word is read again
+            let _ = futex::wait(addr(&WORD), word, None);
+        }
+        ACK.store(round, Ordering::SeqCst);
+        let _ = futex::wake(addr(&ACK), 1);
+    }
+    DONE.store(true, Ordering::SeqCst);
+}
+
+#[esqtest::test]
+pub fn test_futex_wake() {
+    WORD.store(0, Ordering::SeqCst);
+    ACK.store(0, Ordering::SeqCst);
+    DONE.store(false, Ordering::SeqCst);
+    scheduler::spawn("futex-waiter", ping_pong);
+    // Every round wakes the other side right after changing the word, without waiting for it
+    // to sleep first. A lost wakeup leaves one side waiting until the timeout.
+    for round in 1..=ROUNDS {
+        WORD.store(round, Ordering::SeqCst);
+        check!(futex::wake(addr(&WORD), 1).is_ok());
+        loop {
+            let ack = ACK.load(Ordering::SeqCst);
+            if ack >= round {
+                break;
+            }
+            let waited = futex::wait(addr(&ACK), ack, Some(PATIENCE_MS));
+            check_neq!(waited, Err(Error::ConnectionTimedOut));
+        }
+    }
+    check!(wait_for(|| DONE.load(Ordering::SeqCst)));
+    check_eq!(futex::waiters(addr(&WORD)), Ok(0));
+    check_eq!(futex::waiters(addr(&ACK)), Ok(0));
+    all_good!()
+}
+
+fn anonymous_waiter() {
+    let word = user(ANONYMOUS.load(Ordering::SeqCst));
+    if futex::wait(word, 0, Some(PATIENCE_MS)).is_ok() {
+        ANONYMOUS_WOKEN.store(true, Ordering::SeqCst);
+    }
+}
+
+#[esqtest::test]
+pub fn test_futex_anonymous() {
+    let addr = match sys_mmap(
+        UserVirtualAddress::null(),
+        bks::PAGE_SIZE,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        u64::MAX,
+        0,
+    ) {
+        Ok(addr) => addr,
+        Err(_) => return 1,
+    };
+    ANONYMOUS.store(addr, Ordering::SeqCst);
+    ANONYMOUS_WOKEN.store(false, Ordering::SeqCst);
+    // The page was never touched, the waiter faults it in onto the zero frame
+    scheduler::spawn("futex-anonymous", anonymous_waiter);
+    check!(wait_for(|| futex::waiters(user(addr)) == Ok(1)));
+    // Words sharing the zero frame are still different futexes
+    check_eq!(futex::waiters(user(addr + 4)), Ok(0));
+
+    // The write moves the word to a frame of its own, the wakeup has to find the waiter anyway
+    check_eq!(write_user(user(addr), 1u32), Ok(()));
+    check_eq!(futex::wake(user(addr), 1), Ok(1));
+    check!(wait_for(|| ANONYMOUS_WOKEN.load(Ordering::SeqCst)));
+    check_eq!(sys_munmap(user(addr), bks::PAGE_SIZE), Ok(0));
+    all_good!()
+}
@@ -0,0 +1,247 @@
+use alloc::vec::Vec;
+
+use crate::arch::interrupts::exceptions::{Breakpoint, Debug};
+use crate::arch::interrupts::register::Registers;
+use crate::gdbstub::packet::{checksum, read_packet, write_packet, Connection, Response};
+use crate::gdbstub::{Stub, INT3};
+use crate::scheduler::spin::Mutex;
+use esqtest::*;
+
+/// What GDB sends once a script ran out, so that a stub waiting for more detaches instead of
+/// hanging the tests
+const DETACH: &[u8] = b"$D#44+";
+const RFLAGS_TF: u64 = 1 << 8;
+
+static STUB: Mutex<Stub> = Mutex::new(Stub::new());
+static mut MEMORY: [u8; 8] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+static mut CODE: [u8; 2] = [0x90, 0x90];
+
+/// # Scripted Connection
+/// Plays GDB: Feeds the stub a script and keeps what it answers
+struct ScriptedConnection {
+    input: Vec<u8>,
+    pos: usize,
+    output: Vec<u8>,
+}
+
+impl ScriptedConnection {
+    fn new(input: &[u8]) -> Self {
+        Self {
+            input: input.to_vec(),
+            pos: 0,
+            output: Vec::new(),
+        }
+    }
+
+    /// Sends every command of `commands` as a packet and acknowledges every reply
+    fn commands(commands: &[&[u8]]) -> Self {
+        let mut input = Vec::new();
+        for command in commands {
+            input.extend_from_slice(&packet(command));
+            input.push(b'+');
+        }
+        Self::new(&input)
+    }
+
+    /// The data of every packet the stub sent
+    fn replies(&self) -> Vec<Vec<u8>> {
+        self.output
+            .split(|byte| *byte == b'$')
+            .skip(1)
+            .filter_map(|packet| {
+                let end = packet.iter().position(|byte| *byte == b'#')?;
+                Some(packet[..end].to_vec())
+            })
+            .collect()
+    }
+}
+
+impl Connection for ScriptedConnection {
+    fn read_byte(&mut self) -> u8 {
+        let byte = match self.input.get(self.pos) {
+            Some(byte) => *byte,
+            None => DETACH[(self.pos - self.input.len()) % DETACH.len()],
+        };
+        self.pos += 1;
+        byte
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+}
+
+fn packet(data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::new();
+    packet.push(b'$');
+    packet.extend_from_slice(data);
+    packet.extend_from_slice(alloc::format!("#{:02x}", checksum(data)).as_bytes());
+    packet
+}
+
+fn hex(bytes: &[u8]) -> Vec<u8> {
+    let mut response = Response::new();
+    response.push_hex(bytes);
+    response.as_bytes().to_vec()
+}
+
+fn registers() -> Registers {
+    Registers {
+        r15: 15,
+        r14: 14,
+        r13: 13,
+        r12: 12,
+        rbp: 0xb9,
+        rbx: 0xb7,
+        r11: 11,
+        r10: 10,
+        r9: 9,
+        r8: 8,
+        rsi: 0x51,
+        rdi: 0xd1,
+        rdx: 0xd7,
+        rcx: 0xc7,
+        rax: 0xa7,
+        rip: 0xffff_8000_0000_1000,
+        cs: 0x08,
+        rflags: 0x202,
+        rsp: 0xffff_8000_0000_8000,
+        ss: 0x10,
+    }
+}
+
+#[esqtest::test]
+pub fn test_gdbstub_packets() {
+    // A garbled packet is asked for again
+    let mut conn = ScriptedConnection::new(b"+$OK#00$OK#9a");
+    let mut buf = [0; 16];
+    let len = read_packet(&mut conn, &mut buf);
+    check_eq!(&buf[..len], b"OK");
+    check_eq!(conn.output, b"-+");
+    // And sent again if GDB asks for it
+    let mut conn = ScriptedConnection::new(b"-+");
+    write_packet(&mut conn, b"OK");
+    check_eq!(conn.output, b"$OK#9a$OK#9a");
+    // A packet too large for the buffer is refused like a garbled one
+    let mut conn = ScriptedConnection::new(b"$OK#9a$K#4b");
+    let mut buf = [0; 1];
+    let len = read_packet(&mut conn, &mut buf);
+    check_eq!(&buf[..len], b"K");
+    check_eq!(conn.output, b"-+");
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_gdbstub_registers() {
+    let mut regs = registers();
+    let mut written = Vec::new();
+    for value in [0xdead_u64, 0xb7, 0xc7, 0xd7, 0x51, 0xd1, 0xb9, 0xbeef] {
+        written.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in 8..16_u64 {
+        written.extend_from_slice(&value.to_le_bytes());
+    }
+    written.extend_from_slice(&0x4000_u64.to_le_bytes());
+    written.extend_from_slice(&0x246_u32.to_le_bytes());
+    let mut write = b"G".to_vec();
+    write.extend_from_slice(&hex(&written));
+
+    let mut conn = ScriptedConnection::commands(&[b"g", &write, b"G1234"]);
+    STUB.lock().serve(&mut conn, &mut regs);
+    let replies = conn.replies();
+    check_eq!(replies.len(), 4);
+    let read = replies.first().cloned().unwrap_or_default();
+    // RAX first, RIP after R15, then RFLAGS and the selectors of CS and SS
+    check_eq!(read.len(), (17 * 8 + 7 * 4) * 2);
+    check_eq!(read.get(..16), Some(&hex(&0xa7_u64.to_le_bytes())[..]));
+    check_eq!(
+        read.get(16 * 16..17 * 16),
+        Some(&hex(&0xffff_8000_0000_1000_u64.to_le_bytes())[..])
+    );
+    check_eq!(
+        read.get(17 * 16..17 * 16 + 24),
+        Some(&hex(&[0x02, 0x02, 0, 0, 0x08, 0, 0, 0, 0x10, 0, 0, 0])[..])
+    );
+    check_eq!(replies.get(1).map(|reply| &reply[..]), Some(&b"OK"[..]));
+    check_eq!(replies.get(2).map(|reply| &reply[..]), Some(&b"E16"[..]));
+    check_eq!({ regs.rax }, 0xdead);
+    check_eq!({ regs.rsp }, 0xbeef);
+    check_eq!({ regs.r15 }, 15);
+    check_eq!({ regs.rip }, 0x4000);
+    check_eq!({ regs.rflags }, 0x246);
+    check_eq!({ regs.cs }, 0x08);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_gdbstub_memory() {
+    let addr = unsafe { core::ptr::addr_of!(MEMORY) } as u64;
+    let read = alloc::format!("m{:x},8", addr);
+    let write = alloc::format!("M{:x},2:abcd", addr + 1);
+    let mut regs = registers();
+    let mut conn = ScriptedConnection::commands(&[
+        read.as_bytes(),
+        write.as_bytes(),
+        read.as_bytes(),
+        b"m0,8",
+        b"M0,1:00",
+    ]);
+    STUB.lock().serve(&mut conn, &mut regs);
+    let replies = conn.replies();
+    check_eq!(replies.len(), 6);
+    check_eq!(
+        replies.first().map(|reply| &reply[..]),
+        Some(&b"1122334455667788"[..])
+    );
+    check_eq!(replies.get(1).map(|reply| &reply[..]), Some(&b"OK"[..]));
+    check_eq!(
+        replies.get(2).map(|reply| &reply[..]),
+        Some(&b"11abcd4455667788"[..])
+    );
+    // The null page is not mapped
+    check_eq!(replies.get(3).map(|reply| &reply[..]), Some(&b"E0e"[..]));
+    check_eq!(replies.get(4).map(|reply| &reply[..]), Some(&b"E0e"[..]));
+    check_eq!(unsafe { MEMORY[2] }, 0xcd);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_gdbstub_breakpoints() {
+    let addr = unsafe { core::ptr::addr_of!(CODE) } as u64;
+    let set = alloc::format!("Z0,{:x},1", addr);
+    let remove = alloc::format!("z0,{:x},1", addr);
+    let mut stub = STUB.lock();
+    let mut regs = registers();
+    let mut conn = ScriptedConnection::commands(&[set.as_bytes(), b"Z2,1000,8", b"c"]);
+    stub.serve(&mut conn, &mut regs);
+    check_eq!(conn.replies(), [b"OK".to_vec(), Vec::new()]);
+    check_eq!(unsafe { CODE[0] }, INT3);
+    check_eq!(stub.breakpoints.iter().count(), 1);
+
+    // Hitting it reports it at its address
+    regs.rip = addr + 1;
+    let mut conn = ScriptedConnection::commands(&[b"c"]);
+    stub.trap(&mut conn, &mut regs, Breakpoint);
+    check_eq!(conn.replies(), [b"S05".to_vec()]);
+    // Continuing from it steps over it first, with the original byte back in place
+    check_eq!({ regs.rip }, addr);
+    check_eq!({ regs.rflags } & RFLAGS_TF, RFLAGS_TF);
+    check_eq!(unsafe { CODE[0] }, 0x90);
+    // The step puts it back without bothering GDB
+    regs.rip = addr + 1;
+    let mut conn = ScriptedConnection::new(b"");
+    stub.trap(&mut conn, &mut regs, Debug);
+    check!(conn.output.is_empty());
+    check_eq!({ regs.rflags } & RFLAGS_TF, 0);
+    check_eq!(unsafe { CODE[0] }, INT3);
+
+    let mut conn = ScriptedConnection::commands(&[remove.as_bytes()]);
+    stub.trap(&mut conn, &mut regs, Debug);
+    check_eq!(
+        conn.replies().get(1).map(|reply| &reply[..]),
+        Some(&b"OK"[..])
+    );
+    check_eq!(unsafe { CODE[0] }, 0x90);
+    check_eq!(stub.breakpoints.iter().count(), 0);
+    all_good!()
+}
@@ -0,0 +1,58 @@
+use crate::arch::gdt::local::{current, InterruptStack};
+use crate::arch::gdt::{GdtEntryType, GlobalDescriptorTable, Ring};
+use crate::arch::segment::{store_task_register, Segment};
+use crate::arch::syscall::SYSCALL_CPUS;
+use crate::smp::current_cpu;
+use core::sync::atomic::Ordering;
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_tss_descriptor() {
+    let mut gdt = GlobalDescriptorTable::new();
+    check!(!gdt.has_tss());
+    gdt.set_tss(0xFFFF_8000_1234_5670, 103);
+    check!(gdt.has_tss());
+    check_eq!(gdt.tss_base(), 0xFFFF_8000_1234_5670);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_current_gdt() {
+    let gdt = match current() {
+        Some(gdt) => gdt,
+        None => return 1,
+    };
+    check_eq!(gdt.cpu(), current_cpu());
+    check!(gdt.is_loaded());
+    check!(gdt.gdt().has_tss());
+    check_eq!(gdt.gdt().tss_base(), gdt.tss_address());
+    check_eq!(
+        store_task_register().bits(),
+        Segment::new(Ring::Ring0, GdtEntryType::Tss).bits()
+    );
+
+    // Every exception that needs one has a stack of its own
+    let stacks = [
+        InterruptStack::DoubleFault,
+        InterruptStack::NonMaskable,
+        InterruptStack::MachineCheck,
+    ]
+    .map(|stack| gdt.interrupt_stack(stack).unwrap_or(0));
+    for (idx, top) in stacks.iter().enumerate() {
+        check_neq!(*top, 0);
+        check_eq!(*top % 16, 0);
+        check!(!stacks[..idx].contains(top));
+    }
+    check_eq!(gdt.interrupt_stack(0), None);
+
+    // The scheduler keeps the TSS in step with the system call entry
+    check_eq!(
+        gdt.kernel_stack(),
+        SYSCALL_CPUS[current_cpu()]
+            .kernel_rsp
+            .load(Ordering::Relaxed)
+    );
+
+    all_good!()
+}
@@ -0,0 +1,377 @@
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+
+use bks::PAGE_SIZE;
+
+use crate::heap::segregated::{
+    SegregatedHeap, CLASSES, MAX_CLASS_SIZE, POISON, POISON_FREED, USE_AFTER_FREE,
+};
+use crate::heap::Heap;
+use crate::info;
+use crate::memory::allocator::HeapAllocator;
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::scheduler::spin::Mutex;
+use esqtest::*;
+
+/// The pages handed to the heaps of the tests
+const TEST_HEAP_PAGES: usize = 256;
+
+static TEST_HEAP: Mutex<SegregatedHeap> = Mutex::new(SegregatedHeap::new());
+static STRESS_HEAP: Mutex<SegregatedHeap> = Mutex::new(SegregatedHeap::new());
+static CONFORMANCE_HEAP: Mutex<SegregatedHeap> = Mutex::new(SegregatedHeap::new());
+static POISON_HEAP: Mutex<SegregatedHeap> = Mutex::new(SegregatedHeap::new());
+
+fn request_pages(pages: usize) -> Option<u64> {
+    let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+    unsafe { allocator.assume_init_mut() }.request_contiguous_pages(pages, PAGE_SIZE, u64::MAX)
+}
+
+fn free_pages(address: u64, pages: usize) {
+    let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+    unsafe { allocator.assume_init_mut() }.free_pages(address, pages);
+}
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+#[esqtest::test]
+pub fn test_size_classes() {
+    check_eq!(SegregatedHeap::size_class(layout(1, 1)), Some(0));
+    check_eq!(SegregatedHeap::size_class(layout(16, 8)), Some(0));
+    check_eq!(SegregatedHeap::size_class(layout(17, 8)), Some(1));
+    check_eq!(
+        SegregatedHeap::size_class(layout(MAX_CLASS_SIZE, 8)),
+        Some(CLASSES - 1)
+    );
+    check_eq!(
+        SegregatedHeap::size_class(layout(MAX_CLASS_SIZE + 1, 8)),
+        None
+    );
+    // The alignment picks the class if it is larger than the size
+    check_eq!(SegregatedHeap::size_class(layout(8, 64)), Some(2));
+    check_eq!(SegregatedHeap::size_class(layout(8, 8192)), None);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_segregated_heap() {
+    let arena = match request_pages(TEST_HEAP_PAGES) {
+        Some(arena) => arena,
+        None => return 1,
+    };
+    let mut heap = TEST_HEAP.lock();
+    unsafe { heap.init(arena, TEST_HEAP_PAGES) };
+    let in_heap = |ptr: *mut u8| {
+        let ptr = ptr as u64;
+        ptr >= arena && ptr < arena + (TEST_HEAP_PAGES as u64) * PAGE_SIZE
+    };
+
+    let a = unsafe { heap.malloc(layout(24, 8)) };
+    let b = unsafe { heap.malloc(layout(24, 8)) };
+    check!(in_heap(a) && in_heap(b));
+    check_neq!(a, b);
+    check_eq!(a as u64 % 32, 0);
+    check_eq!(heap.usable_size(a), Some(32));
+    check_eq!(heap.stats()[1].in_use, 2);
+    check_eq!(heap.stats()[1].pages, 1);
+
+    // A freed block is the next one handed out
+    unsafe { heap.free(a) };
+    check_eq!(heap.stats()[1].in_use, 1);
+    let c = unsafe { heap.malloc(layout(20, 4)) };
+    check_eq!(c, a);
+
+    // Aligned beyond the size
+    let aligned = unsafe { heap.malloc(layout(8, 256)) };
+    check_eq!(aligned as u64 % 256, 0);
+
+    // Growing within the class stays in place, beyond it moves the data
+    unsafe { *c = 0xAB };
+    let grown = unsafe { heap.realloc(c, layout(20, 4), 30) };
+    check_eq!(grown, c);
+    let moved = unsafe { heap.realloc(grown, layout(30, 4), 100) };
+    check_neq!(moved, grown);
+    check_eq!(unsafe { *moved }, 0xAB);
+    check_eq!(heap.usable_size(moved), Some(128));
+
+    // Large allocations get pages of their own
+    let large = unsafe { heap.malloc(layout(10_000, 8)) };
+    check!(!large.is_null() && !in_heap(large));
+    check_eq!(large as u64 % PAGE_SIZE, 0);
+    check_eq!(heap.large_pages(), 3);
+    let footprint = heap.footprint();
+    let same_pages = unsafe { heap.realloc(large, layout(10_000, 8), 12_000) };
+    check_eq!(same_pages, large);
+    unsafe { heap.free(large) };
+    check_eq!(heap.large_pages(), 0);
+    check_eq!(heap.footprint(), footprint - 3 * PAGE_SIZE as usize);
+    check!(heap.peak_footprint() >= footprint);
+
+    for ptr in [b, aligned, moved] {
+        unsafe { heap.free(ptr) };
+    }
+    check!(heap.stats().iter().all(|class| class.in_use == 0));
+    drop(heap);
+    free_pages(arena, TEST_HEAP_PAGES);
+    all_good!()
+}
+
+enum Op {
+    Alloc(usize),
+    Free(u64),
+}
+
+/// # Workload
+/// Many small allocations with a few large ones in between, freed in a random order, as the
+/// PCI registry and the VFS allocate. `op` allocates or frees and returns the address of an
+/// allocation.
+///
+/// ## Returns
+/// - bool = Whether every allocation succeeded
+fn workload(mut op: impl FnMut(Op) -> u64) -> bool {
+    // A linear congruential generator, so both heaps see the same workload
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut next = move |bound: u64| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 33) % bound
+    };
+    let mut live: Vec<u64> = Vec::with_capacity(256);
+    for step in 0..4000 {
+        let size = if step % 97 == 0 {
+            8192 + next(16384) as usize
+        } else {
+            16 + next(240) as usize
+        };
+        let ptr = op(Op::Alloc(size));
+        if ptr == 0 {
+            return false;
+        }
+        live.push(ptr);
+        // Frees a little less than it allocates, so the heap grows
+        while live.len() > 200 || (!live.is_empty() && next(100) < 45) {
+            let idx = next(live.len() as u64) as usize;
+            op(Op::Free(live.swap_remove(idx)));
+        }
+    }
+    for ptr in live {
+        op(Op::Free(ptr));
+    }
+    true
+}
+
+#[esqtest::test]
+pub fn test_heap_fragmentation() {
+    // The linked list heap cannot grow safely, it gets enough room to never have to
+    const LINKED_LIST_PAGES: usize = 1024;
+    let arena = match request_pages(LINKED_LIST_PAGES) {
+        Some(arena) => arena,
+        None => return 1,
+    };
+    let mut linked_list = unsafe { Heap::new(arena, LINKED_LIST_PAGES) };
+    // The end of the highest allocation
+    let mut high_water = arena;
+    let linked_list_ok = workload(|op| match op {
+        Op::Alloc(size) => {
+            let ptr = unsafe { linked_list.malloc(size) };
+            high_water = high_water.max(ptr + size as u64);
+            ptr
+        }
+        Op::Free(ptr) => {
+            linked_list.free(ptr);
+            0
+        }
+    });
+    check!(linked_list_ok);
+    check!(high_water <= arena + linked_list.size());
+    let linked_list_peak = (high_water - arena) as usize;
+    free_pages(arena, LINKED_LIST_PAGES);
+
+    let arena = match request_pages(TEST_HEAP_PAGES) {
+        Some(arena) => arena,
+        None => return 1,
+    };
+    let mut segregated = STRESS_HEAP.lock();
+    unsafe { segregated.init(arena, TEST_HEAP_PAGES) };
+    let segregated_ok = workload(|op| match op {
+        Op::Alloc(size) => unsafe { segregated.malloc(layout(size, 8)) as u64 },
+        Op::Free(ptr) => {
+            unsafe { segregated.free(ptr as *mut u8) };
+            0
+        }
+    });
+    check!(segregated_ok);
+    check!(segregated.stats().iter().all(|class| class.in_use == 0));
+    check_eq!(segregated.large_pages(), 0);
+    let segregated_peak = segregated.peak_footprint();
+    drop(segregated);
+    free_pages(arena, TEST_HEAP_PAGES);
+
+    info!(
+        "heap: Peak footprint of the workload: {} bytes with the linked list, {} segregated",
+        linked_list_peak, segregated_peak
+    );
+    check!(segregated_peak > 0);
+    all_good!()
+}
+
+/// A heap of the tests behind the interface of the global allocator
+struct TestAllocator(&'static Mutex<SegregatedHeap>);
+
+unsafe impl GlobalAlloc for TestAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock().malloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        self.0.lock().free(ptr)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.0.lock().realloc(ptr, layout, new_size)
+    }
+}
+
+/// The byte every allocation of the conformance test is filled with, from its number
+fn pattern(id: usize) -> u8 {
+    (id as u8).wrapping_mul(31) | 1
+}
+
+/// # Conformance
+/// Random allocations, frees and reallocations of assorted sizes and alignments. Every
+/// allocation is filled with its own pattern, which has to survive until it is freed.
+///
+/// ## Returns
+/// - bool = Whether every pointer was aligned and every pattern intact
+fn conformance(allocator: &dyn GlobalAlloc, seed: u64) -> bool {
+    let mut state = seed;
+    let mut next = move |bound: u64| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 33) % bound
+    };
+    let intact = |ptr: *mut u8, size: usize, byte: u8| unsafe {
+        core::slice::from_raw_parts(ptr, size)
+            .iter()
+            .all(|b| *b == byte)
+    };
+    // The pointer, layout and number of every live allocation
+    let mut live: Vec<(*mut u8, Layout, usize)> = Vec::with_capacity(128);
+    let mut ok = true;
+    for id in 0..2000 {
+        match next(10) {
+            0..=4 => {
+                let size = match next(20) {
+                    0 => 4097 + next(3 * PAGE_SIZE) as usize,
+                    1..=4 => 1 + next(PAGE_SIZE) as usize,
+                    _ => 1 + next(256) as usize,
+                };
+                let align = 1usize << next(13);
+                let layout = layout(size, align);
+                let ptr = unsafe { allocator.alloc(layout) };
+                if ptr.is_null() {
+                    return false;
+                }
+                ok &= ptr as usize % align == 0;
+                unsafe { ptr.write_bytes(pattern(id), size) };
+                live.push((ptr, layout, id));
+            }
+            5..=7 if !live.is_empty() => {
+                let (ptr, layout, id) = live.swap_remove(next(live.len() as u64) as usize);
+                ok &= intact(ptr, layout.size(), pattern(id));
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
+            _ if !live.is_empty() => {
+                let idx = next(live.len() as u64) as usize;
+                let (ptr, old, id) = live[idx];
+                let new_size = 1 + next(2 * PAGE_SIZE) as usize;
+                let new = unsafe { allocator.realloc(ptr, old, new_size) };
+                if new.is_null() {
+                    return false;
+                }
+                ok &= new as usize % old.align() == 0;
+                ok &= intact(new, old.size().min(new_size), pattern(id));
+                unsafe { new.write_bytes(pattern(id), new_size) };
+                live[idx] = (new, layout(new_size, old.align()), id);
+            }
+            _ => {}
+        }
+    }
+    for (ptr, layout, id) in live {
+        ok &= intact(ptr, layout.size(), pattern(id));
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    ok
+}
+
+#[esqtest::test]
+pub fn test_heap_conformance() {
+    let arena = match request_pages(TEST_HEAP_PAGES) {
+        Some(arena) => arena,
+        None => return 1,
+    };
+    unsafe { CONFORMANCE_HEAP.lock().init(arena, TEST_HEAP_PAGES) };
+    let use_after_free = USE_AFTER_FREE.get();
+    check!(conformance(
+        &TestAllocator(&CONFORMANCE_HEAP),
+        0x9E37_79B9_7F4A_7C15
+    ));
+    {
+        let heap = CONFORMANCE_HEAP.lock();
+        check!(heap.stats().iter().all(|class| class.in_use == 0));
+        check_eq!(heap.large_pages(), 0);
+        check_eq!(heap.last_use_after_free(), None);
+    }
+    free_pages(arena, TEST_HEAP_PAGES);
+
+    // The global allocator, whichever heap it is
+    check!(conformance(&HeapAllocator, 0x2545_F491_4F6C_DD1D));
+    check_eq!(USE_AFTER_FREE.get(), use_after_free);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_heap_poison() {
+    let arena = match request_pages(TEST_HEAP_PAGES) {
+        Some(arena) => arena,
+        None => return 1,
+    };
+    let mut heap = POISON_HEAP.lock();
+    unsafe { heap.init(arena, TEST_HEAP_PAGES) };
+    let block = unsafe { heap.malloc(layout(64, 8)) };
+    check!(!block.is_null());
+    unsafe {
+        block.write_bytes(0x11, 64);
+        heap.free(block);
+    }
+    // Behind the link of the free list
+    let freed = unsafe { core::slice::from_raw_parts(block.add(8), 56) };
+    check_eq!(freed.iter().all(|byte| *byte == POISON), POISON_FREED);
+
+    // Handing it out again finds nothing wrong
+    let use_after_free = USE_AFTER_FREE.get();
+    let again = unsafe { heap.malloc(layout(64, 8)) };
+    check_eq!(again, block);
+    check_eq!(USE_AFTER_FREE.get(), use_after_free);
+
+    // A write after free is found once the block is handed out again
+    unsafe {
+        heap.free(again);
+        *again.add(40) = 0x42;
+    }
+    let corrupted = unsafe { heap.malloc(layout(64, 8)) };
+    check_eq!(corrupted, block);
+    if POISON_FREED {
+        check_eq!(USE_AFTER_FREE.get(), use_after_free + 1);
+        check_eq!(heap.last_use_after_free(), Some(block as u64));
+    } else {
+        check_eq!(heap.last_use_after_free(), None);
+    }
+    unsafe { heap.free(corrupted) };
+    drop(heap);
+    free_pages(arena, TEST_HEAP_PAGES);
+    all_good!()
+}
@@ -0,0 +1,89 @@
+use alloc::vec::Vec;
+
+use crate::error::{Error, Result};
+use crate::framebuffer::FramebufferError;
+use crate::init::error::{check, KernelInitError, Severity};
+use crate::init::initcall::{registered, sort, SortError};
+use crate::init::{InitCall, InitStage};
+use esqtest::*;
+
+fn nop() -> Result<()> {
+    Ok(())
+}
+
+const fn call(name: &'static str, stage: InitStage, deps: &'static [&'static str]) -> InitCall {
+    InitCall {
+        name,
+        stage,
+        deps,
+        fatal: false,
+        feature: None,
+        init_fn: nop,
+    }
+}
+
+fn names(calls: &[InitCall]) -> core::result::Result<Vec<&'static str>, SortError> {
+    sort(calls).map(|order| order.iter().map(|call| call.name).collect())
+}
+
+#[esqtest::test]
+pub fn test_initcall_order() {
+    let calls = [
+        call("nic", InitStage::Device, &["pci", "dma"]),
+        call("dma", InitStage::Device, &[]),
+        call("pci", InitStage::Bus, &["acpi"]),
+        call("acpi", InitStage::Platform, &[]),
+        call("net", InitStage::Late, &["nic"]),
+    ];
+    check_eq!(
+        names(&calls),
+        Ok(alloc::vec!["acpi", "pci", "dma", "nic", "net"])
+    );
+    // The calls registered in the kernel can be ordered
+    check!(sort(registered()).is_ok());
+    check!(registered().iter().any(|call| call.name == "pci"));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_initcall_errors() {
+    let cycle = [
+        call("a", InitStage::Device, &["b"]),
+        call("b", InitStage::Device, &["a"]),
+    ];
+    check_eq!(names(&cycle), Err(SortError::Cycle("a")));
+    let unknown = [call("a", InitStage::Device, &["missing"])];
+    check_eq!(
+        names(&unknown),
+        Err(SortError::UnknownDependency("a", "missing"))
+    );
+    let later = [
+        call("bus", InitStage::Bus, &["dev"]),
+        call("dev", InitStage::Device, &[]),
+    ];
+    check_eq!(names(&later), Err(SortError::LaterStage("bus", "dev")));
+    let twice = [
+        call("a", InitStage::Late, &[]),
+        call("a", InitStage::Late, &[]),
+    ];
+    check_eq!(names(&twice), Err(SortError::Duplicate("a")));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_init_error_classes() {
+    // The kernel boots without a framebuffer, not without memory
+    let degradable = KernelInitError::FramebufferInvalid(FramebufferError::NullBase);
+    check_eq!(degradable.class().0, Severity::Degradable);
+    check!(!degradable.is_fatal());
+    check!(KernelInitError::MemoryMapEmpty.is_fatal());
+    check!(KernelInitError::ApicUnsupported.is_fatal());
+    check!(KernelInitError::AcpiError(Error::InvalidArgument).is_fatal());
+    check!(KernelInitError::InitCallFailed("acpi", Error::NoSuchDevice).is_fatal());
+    check!(!KernelInitError::HeapInitFailed("").hint().is_empty());
+
+    // A degradable error is logged, and the boot goes on
+    check_eq!(check("test", Ok::<_, KernelInitError>(7)), Some(7));
+    check_eq!(check::<()>("test", Err(degradable)), None);
+    all_good!()
+}
@@ -0,0 +1,156 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use bks::PAGE_SIZE;
+
+use super::{exited, wait_for};
+use crate::arch::gdt::local::current;
+use crate::arch::gdt::{GdtEntryType, Ring};
+use crate::arch::segment::Segment;
+use crate::arch::tss::{IoBitmap, IO_BITMAP_OFFSET, TSS64_LIMIT};
+use crate::error::Error;
+use crate::memory::usermem::{copy_to_user, user_address};
+use crate::memory::vmm::{Backing, ADDRESS_SPACE};
+use crate::scheduler;
+use crate::scheduler::signal::{Signal, FAULT_SIGNALS};
+use crate::smp::{current_cpu, CpuMask};
+use crate::syscall::ioperm::sys_ioperm;
+use esqtest::*;
+
+/// What the task on the same CPU saw
+static SEEN: AtomicBool = AtomicBool::new(true);
+static SEEN_DONE: AtomicBool = AtomicBool::new(false);
+/// The start of the user mapping of the ring 3 task, zero if it could not map it
+static USER_REGION: AtomicU64 = AtomicU64::new(0);
+
+/// `mov dx, 0x80`, `in al, dx`, `ud2`
+const USER_CODE: [u8; 7] = [0x66, 0xBA, 0x80, 0x00, 0xEC, 0x0F, 0x0B];
+const USER_PORT: u16 = 0x80;
+
+fn allowed(port: u16) -> bool {
+    current().map_or(false, |gdt| gdt.io_port_allowed(port))
+}
+
+fn look_at_port() {
+    SEEN.store(allowed(0x3C0), Ordering::Relaxed);
+    SEEN_DONE.store(true, Ordering::Release);
+}
+
+#[esqtest::test]
+pub fn test_io_bitmap() {
+    let mut bitmap = IoBitmap::new();
+    check!(!bitmap.allows_any());
+    check!(!bitmap.is_allowed(0));
+    check_eq!(bitmap.set(0x3F8..0x400, true), Ok(()));
+    check!(bitmap.is_allowed(0x3F8) && bitmap.is_allowed(0x3FF));
+    check!(!bitmap.is_allowed(0x3F7) && !bitmap.is_allowed(0x400));
+    check!(bitmap.allows_any());
+    check_eq!(bitmap.set(0xFFFF..0x1_0000, true), Ok(()));
+    check!(bitmap.is_allowed(0xFFFF));
+    check_eq!(
+        bitmap.set(0xFFFF..0x1_0001, true),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(bitmap.set(0x3F8..0x1_0000, false), Ok(()));
+    check!(!bitmap.allows_any());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_ioperm_tss() {
+    let gdt = match current() {
+        Some(gdt) => gdt,
+        None => return 1,
+    };
+    check_eq!(gdt.io_bitmap_offset(), IO_BITMAP_OFFSET);
+    check_eq!(gdt.gdt().tss_limit(), TSS64_LIMIT);
+    check!(!allowed(0x3C0));
+
+    check_eq!(sys_ioperm(0x3C0, 0x20, 1), Ok(0));
+    check!(allowed(0x3C0) && allowed(0x3DF));
+    check!(!allowed(0x3E0));
+    // Another task on the same CPU does not get the ports
+    SEEN_DONE.store(false, Ordering::Release);
+    scheduler::spawn_pinned("ioperm", look_at_port, CpuMask::single(current_cpu()));
+    check!(wait_for(|| SEEN_DONE.load(Ordering::Acquire)));
+    check!(!SEEN.load(Ordering::Relaxed));
+    check!(allowed(0x3C0));
+
+    check_eq!(sys_ioperm(0x3C0, 0x20, 0), Ok(0));
+    check!(!allowed(0x3C0));
+    check_eq!(sys_ioperm(0xFFFF, 2, 1), Err(Error::InvalidArgument));
+    check_eq!(sys_ioperm(u64::MAX, 2, 1), Err(Error::InvalidArgument));
+
+    all_good!()
+}
+
+/// Copies `USER_CODE` into a fresh user mapping and runs it in ring 3, never returns
+fn enter_user() {
+    let start = match ADDRESS_SPACE
+        .lock()
+        .map(2 * PAGE_SIZE, true, true, Backing::Anonymous)
+    {
+        Ok(start) => start,
+        Err(_) => return,
+    };
+    let code = match user_address(start) {
+        Ok(code) => code,
+        Err(_) => return,
+    };
+    if copy_to_user(code, &USER_CODE).is_err() {
+        return;
+    }
+    USER_REGION.store(start, Ordering::Release);
+    unsafe {
+        asm!(
+            "push {ss}",
+            "push {stack}",
+            "push 0x2",
+            "push {cs}",
+            "push {rip}",
+            "iretq",
+            ss = in(reg) Segment::new(Ring::Ring3, GdtEntryType::UserData).bits() as u64,
+            stack = in(reg) start + 2 * PAGE_SIZE - 16,
+            cs = in(reg) Segment::new(Ring::Ring3, GdtEntryType::UserCode).bits() as u64,
+            rip = in(reg) start,
+            options(noreturn),
+        )
+    }
+}
+
+fn in_allowed() {
+    if sys_ioperm(USER_PORT as u64, 1, 1).is_ok() {
+        enter_user()
+    }
+}
+
+/// Runs `entry` and returns the signal its fault sent, if it faulted exactly once
+fn fault_of(entry: fn()) -> Option<u32> {
+    let before = [Signal::SIGSEGV, Signal::SIGILL].map(|sig| FAULT_SIGNALS.get(sig as usize));
+    USER_REGION.store(0, Ordering::Release);
+    let id = scheduler::spawn("ioperm-user", entry);
+    if !wait_for(|| exited(id)) {
+        return None;
+    }
+    let start = USER_REGION.load(Ordering::Acquire);
+    if start != 0 {
+        let _ = ADDRESS_SPACE.lock().unmap(start, 2 * PAGE_SIZE);
+    }
+    let after = [Signal::SIGSEGV, Signal::SIGILL].map(|sig| FAULT_SIGNALS.get(sig as usize));
+    match (after[0] - before[0], after[1] - before[1]) {
+        (1, 0) => Some(Signal::SIGSEGV),
+        (0, 1) => Some(Signal::SIGILL),
+        _ => None,
+    }
+}
+
+#[esqtest::test]
+pub fn test_ioperm_user() {
+    // Without the port `in` is a general protection fault
+    check_eq!(fault_of(enter_user), Some(Signal::SIGSEGV));
+    // With it `in` goes through and the task runs into `ud2`
+    check_eq!(fault_of(in_allowed), Some(Signal::SIGILL));
+
+    all_good!()
+}
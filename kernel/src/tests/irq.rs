@@ -0,0 +1,122 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::apic::local_apic;
+use crate::arch::interrupts::{self, handler_name};
+use crate::error::Error;
+use crate::irq::msi::{message_address, message_data, MSI_ADDRESS_BASE};
+use crate::irq::{
+    allocate_vectors, find_free, free_vectors, is_allocated, FIRST_DYNAMIC_VECTOR,
+    LAST_DYNAMIC_VECTOR, MAX_ALLOCATION,
+};
+use crate::stats::IRQ_COUNT;
+use esqtest::*;
+
+static RAISED: AtomicUsize = AtomicUsize::new(0);
+
+fn count_raised(index: usize, data: usize) {
+    RAISED.fetch_add(data + index, Ordering::Relaxed);
+}
+
+#[esqtest::test]
+pub fn test_irq_find_free() {
+    let mut used = [false; 16];
+    check_eq!(find_free(&used, 4, 4), Some(0));
+    used[1] = true;
+    check_eq!(find_free(&used, 1, 1), Some(0));
+    check_eq!(find_free(&used, 2, 2), Some(2));
+    check_eq!(find_free(&used, 4, 4), Some(4));
+    // A free run that is not aligned is skipped
+    used[8] = true;
+    check_eq!(find_free(&used, 8, 8), None);
+    check_eq!(find_free(&used, 4, 1), Some(2));
+    check_eq!(find_free(&used, 16, 16), None);
+    check_eq!(find_free(&used, 0, 1), Some(0));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_irq_allocate_vectors() {
+    check_eq!(
+        allocate_vectors(3, "test", count_raised, 0),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        allocate_vectors(0, "test", count_raised, 0),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        allocate_vectors(MAX_ALLOCATION * 2, "test", count_raised, 0),
+        Err(Error::InvalidArgument)
+    );
+
+    let first = match allocate_vectors(4, "test", count_raised, 0) {
+        Ok(first) => first,
+        // Every vector is taken by drivers
+        Err(_) => return 1,
+    };
+    check_eq!(first % 4, 0);
+    check!(first >= FIRST_DYNAMIC_VECTOR && first + 3 <= LAST_DYNAMIC_VECTOR);
+    for vector in first..first + 4 {
+        check!(is_allocated(vector as usize));
+        check_eq!(handler_name(vector as usize), Some("test"));
+    }
+    let other = allocate_vectors(1, "test", count_raised, 0);
+    check!(other.map_or(true, |other| !(first..first + 4).contains(&other)));
+    if let Ok(other) = other {
+        free_vectors(other, 1);
+    }
+
+    free_vectors(first, 4);
+    for vector in first..first + 4 {
+        check!(!is_allocated(vector as usize));
+        check_eq!(handler_name(vector as usize), None);
+    }
+    // Freed vectors are handed out again
+    let again = allocate_vectors(4, "test", count_raised, 0);
+    check_eq!(again, Ok(first));
+    free_vectors(first, 4);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_irq_dispatch() {
+    let apic = match local_apic() {
+        Some(apic) => apic,
+        None => return 1,
+    };
+    if !interrupts::are_enabled() {
+        return 1;
+    }
+    let first = match allocate_vectors(2, "test", count_raised, 10) {
+        Ok(first) => first,
+        Err(_) => return 1,
+    };
+    RAISED.store(0, Ordering::Relaxed);
+    let count = IRQ_COUNT.get(first as usize + 1);
+    // The handler is told which vector of the allocation was raised
+    apic.send_ipi(apic.id(), first + 1);
+    for _ in 0..1_000_000 {
+        if RAISED.load(Ordering::Relaxed) != 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    check_eq!(RAISED.load(Ordering::Relaxed), 11);
+    check_eq!(IRQ_COUNT.get(first as usize + 1), count + 1);
+    free_vectors(first, 2);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_irq_msi_message() {
+    check_eq!(message_address(0), MSI_ADDRESS_BASE);
+    check_eq!(message_address(3), 0xFEE0_3000);
+    // Only 8 bits of destination fit into the address
+    check_eq!(message_address(0x1FF), 0xFEEF_F000);
+    check_eq!(message_data(0x41), 0x41);
+
+    all_good!()
+}
@@ -0,0 +1,76 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::gdt::local::{INTERRUPT_STACKS, INTERRUPT_STACK_SIZE};
+use crate::memory::kernel_image::{self, InvalidMap, Section};
+use crate::memory::VirtualAddress;
+use esqtest::*;
+
+static READ_ONLY: [u64; 4] = [1, 2, 3, 4];
+static INITIALIZED: AtomicU64 = AtomicU64::new(0x1234);
+static ZEROED: AtomicU64 = AtomicU64::new(0);
+
+fn moved(section: Section, start: u64, end: u64) -> Section {
+    Section {
+        start: VirtualAddress::new(start),
+        end: VirtualAddress::new(end),
+        ..section
+    }
+}
+
+#[esqtest::test]
+pub fn test_section_map() {
+    let map = kernel_image::sections();
+    check_eq!(map.validate(), Ok(()));
+    check!(!map.text.is_empty());
+    check!(map.stack.len() >= (INTERRUPT_STACKS * INTERRUPT_STACK_SIZE) as u64);
+    // Everything lands where the linker script puts it
+    check!(kernel_image::is_text(test_section_map as usize as u64));
+    check!(map.rodata.contains(READ_ONLY.as_ptr() as u64));
+    check!(map.data.contains(&INITIALIZED as *const AtomicU64 as u64));
+    check!(map.bss.contains(&ZEROED as *const AtomicU64 as u64));
+    check!(!kernel_image::is_text(READ_ONLY.as_ptr() as u64));
+    check!(!kernel_image::is_text(0));
+    check!(map.image.contains(map.stack.end.as_u64() - 1));
+    // Keeps the statics from being optimized out
+    check_eq!(
+        INITIALIZED.load(Ordering::Relaxed) + ZEROED.load(Ordering::Relaxed),
+        0x1234
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_section_map_invalid() {
+    let map = kernel_image::sections();
+    let (text, rodata) = (map.text, map.rodata);
+
+    let mut broken = map;
+    broken.rodata = moved(rodata, text.end.as_u64() - 0x1000, rodata.end.as_u64());
+    check_eq!(
+        broken.validate(),
+        Err(InvalidMap::Overlapping(".text", ".rodata"))
+    );
+
+    let mut broken = map;
+    broken.rodata = moved(rodata, rodata.start.as_u64() + 8, rodata.end.as_u64());
+    check_eq!(broken.validate(), Err(InvalidMap::Unaligned(".rodata")));
+    // The end of the thread local data does not have to be aligned
+    let mut unaligned = map;
+    unaligned.percpu = moved(map.percpu, rodata.end.as_u64(), rodata.end.as_u64() + 8);
+    check_eq!(unaligned.validate(), Ok(()));
+
+    let mut broken = map;
+    broken.text = moved(text, text.end.as_u64(), text.start.as_u64());
+    check_eq!(broken.validate(), Err(InvalidMap::Reversed(".text")));
+
+    let mut broken = map;
+    broken.image = moved(
+        map.image,
+        map.image.start.as_u64(),
+        map.stack.start.as_u64(),
+    );
+    check_eq!(broken.validate(), Err(InvalidMap::OutsideImage(".stack")));
+
+    all_good!()
+}
@@ -0,0 +1,163 @@
+use keyboard_layout::{find_layout, translate, Modifiers, KEYBOARD_LAYOUTS};
+
+use crate::drivers::input::ps2_keyboard::{
+    control_char, layout, set_layout, typematic, CommandQueue, KeyboardCommand, KeyboardResponse,
+    KeyboardState, ScancodeBuffer, COMMAND_QUEUE_SIZE, MAX_RESENDS, SCANCODE_BUFFER_SIZE,
+};
+use crate::error::Error;
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_keyboard_command_queue() {
+    let mut queue = CommandQueue::new();
+    // Nothing is waiting for an answer
+    check_eq!(queue.respond(KeyboardResponse::Ack), None);
+    check_eq!(
+        queue.push(&[KeyboardCommand::SetLeds, 0b100]),
+        Ok(Some(0xED))
+    );
+    check_eq!(queue.push(&[KeyboardCommand::SetTypematic, 0x20]), Ok(None));
+    check_eq!(queue.len(), 2);
+    // The data byte follows its command, the next command only follows the data byte
+    check_eq!(queue.respond(KeyboardResponse::Resend), Some(0xED));
+    check_eq!(queue.respond(KeyboardResponse::Ack), Some(0b100));
+    check_eq!(queue.respond(KeyboardResponse::Ack), Some(0xF3));
+    check_eq!(queue.len(), 1);
+    // A command that keeps being refused is dropped as a whole
+    for _ in 0..MAX_RESENDS {
+        check_eq!(queue.respond(KeyboardResponse::Resend), Some(0xF3));
+    }
+    check_eq!(queue.respond(KeyboardResponse::Resend), None);
+    check!(queue.is_empty());
+
+    check_eq!(queue.push(&[]), Err(Error::InvalidArgument));
+    check_eq!(queue.push(&[1, 2, 3]), Err(Error::InvalidArgument));
+    for idx in 0..COMMAND_QUEUE_SIZE {
+        check!(queue.push(&[0xF4]).is_ok());
+        check_eq!(queue.len(), idx + 1);
+    }
+    check_eq!(queue.push(&[0xF4]), Err(Error::DeviceOrResourceBusy));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_keyboard_scancode_buffer() {
+    let mut buffer = ScancodeBuffer::new();
+    check_eq!(buffer.pop(), None);
+    // Wraps around with scancodes in flight
+    for scancode in 0..SCANCODE_BUFFER_SIZE as u8 / 2 {
+        check!(buffer.push(scancode));
+        check_eq!(buffer.pop(), Some(scancode));
+    }
+    for scancode in 0..SCANCODE_BUFFER_SIZE as u8 {
+        check!(buffer.push(scancode));
+    }
+    // A full buffer drops what comes next, not what it holds
+    check!(!buffer.push(0xFF));
+    check_eq!(buffer.len(), SCANCODE_BUFFER_SIZE);
+    for scancode in 0..SCANCODE_BUFFER_SIZE as u8 {
+        check_eq!(buffer.pop(), Some(scancode));
+    }
+    check!(buffer.is_empty());
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_keyboard_typematic() {
+    // 30 characters per second and 2 per second are the extremes
+    check_eq!(typematic(250, 30), 0x00);
+    check_eq!(typematic(1000, 2), 0x7F);
+    check_eq!(typematic(500, 10), 0x20 | 0x0C);
+    // Out of range delays are clamped
+    check_eq!(typematic(0, 30) >> 5, 0);
+    check_eq!(typematic(5000, 30) >> 5, 3);
+
+    let state = KeyboardState {
+        caps_lock: true,
+        scroll_lock: true,
+        ..KeyboardState::new()
+    };
+    check_eq!(state.leds(), 0b101);
+    check!(!state.modifiers().shift);
+    check!(!state.control());
+    check!(KeyboardState {
+        right_control: true,
+        ..state
+    }
+    .control());
+    // Ctrl types control characters, regardless of Shift
+    check_eq!(control_char('c'), Some('\x03'));
+    check_eq!(control_char('Z'), Some('\x1A'));
+    check_eq!(control_char('['), Some('\x1B'));
+    check_eq!(control_char('1'), None);
+    check_eq!(control_char('ä'), None);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_keyboard_layouts() {
+    let us = KEYBOARD_LAYOUTS[find_layout("us").unwrap()];
+    let de = KEYBOARD_LAYOUTS[find_layout("de").unwrap()];
+    let none = Modifiers::default();
+    let shift = Modifiers {
+        shift: true,
+        ..none
+    };
+    let altgr = Modifiers {
+        altgr: true,
+        ..none
+    };
+    let caps_lock = Modifiers {
+        caps_lock: true,
+        ..none
+    };
+    let num_lock = Modifiers {
+        num_lock: true,
+        ..none
+    };
+
+    check_eq!(translate(us, 0x15, none), Some('y'));
+    check_eq!(translate(de, 0x15, none), Some('z'));
+    check_eq!(translate(de, 0x2C, shift), Some('Y'));
+    check_eq!(translate(us, 0x03, shift), Some('@'));
+    check_eq!(translate(de, 0x03, shift), Some('"'));
+    check_eq!(translate(de, 0x10, altgr), Some('@'));
+    check_eq!(translate(de, 0x08, altgr), Some('{'));
+    check_eq!(translate(us, 0x10, altgr), None);
+    // CapsLock only applies to letters, and Shift undoes it
+    check_eq!(translate(de, 0x1A, caps_lock), Some('Ü'));
+    check_eq!(translate(de, 0x0C, caps_lock), Some('ß'));
+    check_eq!(
+        translate(
+            de,
+            0x1E,
+            Modifiers {
+                shift: true,
+                caps_lock: true,
+                ..none
+            }
+        ),
+        Some('a')
+    );
+    // The digits of the keypad need NumLock, its operators do not
+    check_eq!(translate(us, 0x47, none), None);
+    check_eq!(translate(us, 0x47, num_lock), Some('7'));
+    check_eq!(translate(us, 0x4E, none), Some('+'));
+    check_eq!(translate(de, 0x53, num_lock), Some(','));
+    // Keys that type nothing
+    check_eq!(translate(us, 0x01, none), None);
+    check_eq!(translate(us, 0x3B, none), None);
+    check_eq!(find_layout("xx"), None);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_keyboard_set_layout() {
+    let previous = layout().name;
+    check_eq!(set_layout("de"), Ok(()));
+    check_eq!(layout().name, "de");
+    check_eq!(set_layout("xx"), Err(Error::NoSuchFileOrDirectory));
+    check_eq!(layout().name, "de");
+    check_eq!(set_layout(previous), Ok(()));
+    all_good!()
+}
@@ -0,0 +1,93 @@
+use alloc::string::String;
+
+use crate::drivers::serial::{divisor, parse_option};
+use crate::earlylog;
+use crate::error::Error;
+use crate::klog::{self, LogSink, LOG_SIZE};
+use crate::scheduler::spin::Mutex;
+use esqtest::*;
+
+struct TestSink {
+    text: Mutex<String>,
+}
+
+impl LogSink for TestSink {
+    fn name(&self) -> &str {
+        "test"
+    }
+
+    fn write_str(&self, s: &str) {
+        self.text.lock().push_str(s);
+    }
+}
+
+static SINK: TestSink = TestSink {
+    text: Mutex::new(String::new()),
+};
+
+#[esqtest::test]
+pub fn test_klog_sinks() {
+    check!(klog::register_sink(&SINK).is_ok());
+    check_eq!(klog::register_sink(&SINK), Err(Error::DeviceOrResourceBusy));
+    let mut found = false;
+    klog::for_each_sink(|sink| found |= sink.name() == "test");
+    check!(found);
+
+    let start = klog::written();
+    klog::write("klog test line\n");
+    check!(klog::unregister_sink("test"));
+    klog::write("after unregistering\n");
+    check!(!klog::unregister_sink("test"));
+
+    let text = core::mem::take(&mut *SINK.text.lock());
+    check!(text.contains("klog test line\n"));
+    check!(!text.contains("after unregistering"));
+
+    // Both lines are in the log, even if something else was logged in between
+    let mut buf = [0; 64];
+    let (pos, len) = klog::read(start, &mut buf);
+    check_eq!(pos, start);
+    check!(len > 0);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_klog_wrap() {
+    // Anything older than the ring buffer has been overwritten and is skipped
+    let end = klog::written();
+    let mut buf = [0; 16];
+    let (pos, len) = klog::read(0, &mut buf);
+    check!(pos >= end.saturating_sub(LOG_SIZE as u64));
+    check_eq!(len, buf.len());
+    // Nothing is read at the end
+    check_eq!(klog::read(u64::MAX, &mut buf).1, 0);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_serial_option() {
+    check_eq!(divisor(115_200), Ok(1));
+    check_eq!(divisor(57_600), Ok(2));
+    check_eq!(divisor(38_400), Ok(3));
+    check_eq!(divisor(9_600), Ok(12));
+    check_eq!(divisor(0), Err(Error::InvalidArgument));
+    check_eq!(divisor(56_000), Err(Error::InvalidArgument));
+    check_eq!(divisor(230_400), Err(Error::InvalidArgument));
+
+    check_eq!(parse_option("com1,57600"), Ok(("com1", 57_600)));
+    check_eq!(parse_option("com2,115200"), Ok(("com2", 115_200)));
+    check_eq!(parse_option("com2"), Ok(("com2", 38_400)));
+    check_eq!(parse_option("com3,9600"), Err(Error::NoSuchDevice));
+    check_eq!(parse_option("com1,fast"), Err(Error::InvalidArgument));
+    check_eq!(parse_option("com1,1000"), Err(Error::InvalidArgument));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_earlylog_replayed() {
+    check!(!earlylog::is_active());
+    // init_common logs about the serial port before the console exists
+    let (text, _) = earlylog::contents();
+    check!(text.contains("COM1") || text.contains("serial port"));
+    all_good!()
+}
@@ -0,0 +1,106 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::tsc;
+use crate::lockstat::{self, Key, LockStats, Registry, OVERFLOW, TRACE_THRESHOLD};
+use crate::scheduler::{self, IrqSpinLock};
+use crate::smp::{self, current_cpu, CpuMask};
+use crate::time;
+use esqtest::*;
+
+static LOCK: IrqSpinLock<()> = crate::named_lock!("lockstat_test", ());
+/// Set by the waiter right before it takes `LOCK`
+static WAITING: AtomicBool = AtomicBool::new(false);
+static DONE: AtomicBool = AtomicBool::new(false);
+
+fn stats() -> LockStats {
+    lockstat::classes()
+        .into_iter()
+        .find(|(_, key, _)| *key == Some(Key::Name("lockstat_test")))
+        .map_or(LockStats::default(), |(_, _, stats)| stats)
+}
+
+fn waiter() {
+    let start = time::now_ms();
+    while !LOCK.is_locked() && time::now_ms() - start < 1000 {
+        comasm::pause();
+    }
+    WAITING.store(true, Ordering::Release);
+    drop(LOCK.lock());
+    DONE.store(true, Ordering::Release);
+}
+
+/// Spins for `cycles` TSC cycles, or until `condition` holds
+fn spin(cycles: u64, condition: impl Fn() -> bool) {
+    let start = tsc::read();
+    while !condition() && tsc::read() - start < cycles {
+        comasm::pause();
+    }
+}
+
+#[esqtest::test]
+pub fn test_lockstat_registry() {
+    let registry: Registry<4> = Registry::new();
+    check_eq!(registry.register(Key::Name("a")), 1);
+    check_eq!(registry.register(Key::Location("b.rs", 3)), 2);
+    check_eq!(registry.register(Key::Name("a")), 1);
+    check_eq!(registry.register(Key::Location("b.rs", 4)), 3);
+    // Full, what does not fit is counted together
+    check_eq!(registry.register(Key::Name("c")), OVERFLOW);
+    check_eq!(registry.key(OVERFLOW), None);
+    check_eq!(registry.key(2), Some(Key::Location("b.rs", 3)));
+
+    registry.acquired(1);
+    registry.acquired(1);
+    registry.waited(1, 30);
+    registry.waited(1, 10);
+    let expected = LockStats {
+        acquisitions: 2,
+        contended: 2,
+        wait_cycles: 40,
+        max_wait: 30,
+    };
+    check_eq!(registry.stats(1), expected);
+    check_eq!(registry.snapshot().len(), 4);
+    registry.clear();
+    check_eq!(registry.stats(1), LockStats::default());
+    check_eq!(registry.key(1), Some(Key::Name("a")));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_lockstat_contention() {
+    if !lockstat::ENABLED {
+        check!(lockstat::classes().is_empty());
+        all_good!()
+    }
+    let before = stats();
+    drop(LOCK.lock());
+    let after = stats();
+    check_eq!(after.acquisitions, before.acquisitions + 1);
+    check_eq!(after.contended, before.contended);
+
+    // The waiter needs a CPU of its own
+    let other = smp::online_cpus().find(|&cpu| cpu != current_cpu());
+    if other.is_none() {
+        all_good!()
+    }
+    let second = tsc::khz().unwrap_or(1_000_000) * 1000;
+    WAITING.store(false, Ordering::Release);
+    DONE.store(false, Ordering::Release);
+    scheduler::spawn_pinned("lockstat", waiter, CpuMask::single(other.unwrap_or(0)));
+    {
+        let _guard = LOCK.lock();
+        spin(second, || WAITING.load(Ordering::Acquire));
+        // Long enough for the waiter to be recorded to the trace as well
+        spin(TRACE_THRESHOLD * 10, || false);
+    }
+    spin(second, || DONE.load(Ordering::Acquire));
+    check!(DONE.load(Ordering::Acquire));
+    let contended = stats();
+    check_eq!(contended.contended, after.contended + 1);
+    check!(contended.max_wait >= TRACE_THRESHOLD);
+    check!(contended.wait_cycles >= after.wait_cycles + TRACE_THRESHOLD);
+
+    all_good!()
+}
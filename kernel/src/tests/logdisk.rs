@@ -0,0 +1,105 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use crate::block::BlockDevice;
+use crate::logdisk::{LogDisk, Record, Superblock, HEADER_SIZE};
+use crate::tests::block::MemDisk;
+use esqtest::*;
+
+const BLOCK_SIZE: usize = 512;
+
+/// The text `boot` of `disk` appended, in one piece
+fn read_text(disk: &LogDisk, boot: u32) -> Vec<u8> {
+    let mut text = Vec::new();
+    disk.read_boot(boot, |record| text.extend_from_slice(record))
+        .unwrap();
+    text
+}
+
+#[esqtest::test]
+pub fn test_superblock_roundtrip() {
+    let superblock = Superblock {
+        block_size: BLOCK_SIZE as u32,
+        boot: 7,
+        records: 63,
+        head: 100,
+        tail: 37,
+        boot_start: 90,
+    };
+    let mut block = vec![0u8; BLOCK_SIZE];
+    superblock.encode(&mut block);
+    check_eq!(Superblock::decode(&block), Some(superblock));
+    block[33] ^= 1;
+    check_eq!(Superblock::decode(&block), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_previous_boot() {
+    let device: Arc<dyn BlockDevice> = Arc::new(MemDisk::new());
+    let mut disk = LogDisk::open(device.clone()).unwrap();
+    check_eq!(disk.superblock().boot, 1);
+    // Longer than a record, so it is split
+    let text: Vec<u8> = (0..1200).map(|i| b'a' + (i % 26) as u8).collect();
+    disk.append(&text, false).unwrap();
+    check_eq!(disk.superblock().head, 3);
+
+    let mut disk = LogDisk::open(device.clone()).unwrap();
+    check_eq!(disk.superblock().boot, 2);
+    check_eq!(disk.superblock().boot_start, 3);
+    disk.append(b"second boot\n", true).unwrap();
+    check_eq!(read_text(&disk, 1), text);
+    check_eq!(read_text(&disk, 2), b"second boot\n".to_vec());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_wrap_around() {
+    let device: Arc<dyn BlockDevice> = Arc::new(MemDisk::new());
+    let mut disk = LogDisk::open(device).unwrap();
+    let records = disk.superblock().records;
+    for i in 0..records + 5 {
+        disk.append(&i.to_le_bytes(), false).unwrap();
+    }
+    check_eq!(disk.superblock().head, records + 5);
+    check_eq!(disk.superblock().tail, 5);
+    let mut first = None;
+    let count = disk
+        .read_boot(1, |text| {
+            first.get_or_insert_with(|| text.to_vec());
+        })
+        .unwrap();
+    check_eq!(count, records as usize);
+    check_eq!(first, Some(5u64.to_le_bytes().to_vec()));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_torn_records() {
+    let device: Arc<dyn BlockDevice> = Arc::new(MemDisk::new());
+    let mut disk = LogDisk::open(device.clone()).unwrap();
+    disk.append(b"kept", false).unwrap();
+    let head = disk.superblock().head;
+
+    // A flush that wrote two records but not the superblock, the second one torn
+    let mut block = vec![0u8; BLOCK_SIZE];
+    Record::encode(&mut block, head, 1, b"recovered");
+    device.write_blocks(1 + head, &block).unwrap();
+    Record::encode(&mut block, head + 1, 1, b"torn");
+    block[BLOCK_SIZE - 1] ^= 0xFF;
+    device.write_blocks(2 + head, &block).unwrap();
+
+    let disk = LogDisk::open(device.clone()).unwrap();
+    check_eq!(disk.superblock().head, head + 1);
+    check_eq!(read_text(&disk, 1), b"keptrecovered".to_vec());
+
+    // A record that was damaged later is skipped
+    device.read_blocks(1, &mut block).unwrap();
+    block[HEADER_SIZE] ^= 0xFF;
+    device.write_blocks(1, &block).unwrap();
+    check_eq!(read_text(&disk, 1), b"recovered".to_vec());
+
+    all_good!()
+}
@@ -0,0 +1,64 @@
+use alloc::vec::Vec;
+
+use crate::arch::mem::{self, copy_with, set_fast, CopyVariant, SMALL_COPY};
+use esqtest::*;
+
+/// A buffer whose every byte differs from its neighbours
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|idx| (idx * 7 + 3) as u8).collect()
+}
+
+#[esqtest::test]
+pub fn test_mem_copy() {
+    for variant in CopyVariant::ALL {
+        if !variant.is_supported() {
+            continue;
+        }
+        // Sizes around the block sizes, from unaligned addresses
+        for len in [0, 1, 15, 63, 64, 65, 200, 4097] {
+            for offset in [0, 3] {
+                let src = pattern(len + offset);
+                let mut dst = alloc::vec![0u8; len + 5];
+                unsafe { copy_with(variant, dst[5..].as_mut_ptr(), src[offset..].as_ptr(), len) };
+                check_eq!(dst[5..], src[offset..]);
+                check_eq!(dst[..5], [0; 5]);
+            }
+        }
+    }
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_mem_overlap() {
+    for variant in CopyVariant::ALL {
+        if !variant.is_supported() {
+            continue;
+        }
+        let original = pattern(1024);
+        // Down, which every variant does forwards
+        let mut buf = original.clone();
+        unsafe { copy_with(variant, buf.as_mut_ptr(), buf.as_ptr().add(100), 900) };
+        check_eq!(buf[..900], original[100..1000]);
+        // Up, which has to be done backwards
+        let mut buf = original.clone();
+        unsafe { copy_with(variant, buf.as_mut_ptr().add(100), buf.as_ptr(), 900) };
+        check_eq!(buf[100..1000], original[..900]);
+        check_eq!(buf[..100], original[..100]);
+    }
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_mem_set_and_select() {
+    let mut buf = alloc::vec![0u8; 1000];
+    unsafe { set_fast(buf[1..].as_mut_ptr(), 0x5A, 998) };
+    check_eq!(buf[0], 0);
+    check!(buf[1..999].iter().all(|byte| *byte == 0x5A));
+    check_eq!(buf[999], 0);
+
+    check_eq!(mem::select(SMALL_COPY), CopyVariant::Builtin);
+    let threshold = mem::nontemporal_threshold().max(SMALL_COPY + 1);
+    check_eq!(mem::select(threshold), CopyVariant::NonTemporal);
+    check!(mem::select(SMALL_COPY + 1).is_supported());
+    all_good!()
+}
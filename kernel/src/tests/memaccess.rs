@@ -0,0 +1,76 @@
+use alloc::boxed::Box;
+
+use bks::PAGE_SIZE;
+
+use crate::error::Error;
+use crate::memory::memaccess::{slot_address, with_phys_mapping, MAX_LEN, SLOTS, SLOT_PAGES};
+use crate::memory::paging::page_table_manager::translate;
+use crate::memory::PhysicalAddress;
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_memaccess_read() {
+    let buffer = Box::new(*b"esque memaccess!");
+    let addr = buffer.as_ptr() as u64;
+    // The buffer has to be contiguous in physical memory
+    let phys = match translate(addr) {
+        Some(phys) if translate(addr + 15) == Some(phys + 15) => PhysicalAddress::new(phys),
+        _ => return 1,
+    };
+    let copy = with_phys_mapping(phys, buffer.len() as u64, |bytes| {
+        let mut copy = [0u8; 16];
+        for (byte, mapped) in copy.iter_mut().zip(bytes) {
+            *byte = unsafe { (mapped as *const u8).read_volatile() };
+        }
+        copy
+    });
+    check_eq!(copy, Ok(*buffer));
+
+    // Unmapped again
+    for slot in 0..SLOTS {
+        check_eq!(translate(slot_address(slot)), None);
+    }
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_memaccess_slots() {
+    let phys = PhysicalAddress::new(0x1000);
+    // Nested mappings take different slots
+    let addresses = with_phys_mapping(phys, 1, |outer| {
+        with_phys_mapping(phys, 1, |inner| {
+            (outer.as_ptr() as u64, inner.as_ptr() as u64)
+        })
+    });
+    check!(matches!(addresses, Ok(Ok((outer, inner))) if outer != inner));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_memaccess_limits() {
+    let phys = PhysicalAddress::new(0x1000);
+    check_eq!(
+        with_phys_mapping(phys, 0, |_| ()),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(with_phys_mapping(phys, MAX_LEN, |_| ()), Ok(()));
+    // The worst offset in a page still fits
+    let last = PhysicalAddress::new(0x1fff);
+    check_eq!(with_phys_mapping(last, MAX_LEN, |_| ()), Ok(()));
+    check_eq!(
+        with_phys_mapping(last, MAX_LEN + 1, |_| ()),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        with_phys_mapping(phys, SLOT_PAGES as u64 * PAGE_SIZE + 1, |_| ()),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        with_phys_mapping(PhysicalAddress::max(), 2, |_| ()),
+        Err(Error::InvalidArgument)
+    );
+
+    all_good!()
+}
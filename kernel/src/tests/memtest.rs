@@ -0,0 +1,32 @@
+use alloc::vec;
+
+use crate::memory::memtest::{test_frame, verify, write, Mode, Pattern};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_memtest_patterns() {
+    check_eq!(Mode::parse("fast"), Some(Mode::Fast));
+    check_eq!(Mode::parse("full"), Some(Mode::Full));
+    check_eq!(Mode::parse("slow"), None);
+    check_eq!(Mode::Fast.patterns().count(), 3);
+    check_eq!(Mode::Full.patterns().count(), 3 + 64);
+    check_eq!(
+        Mode::Full.patterns().last(),
+        Some(Pattern::Fill(0x8000_0000_0000_0000))
+    );
+
+    // Any memory will do, the physical address only seeds the pattern
+    let mut frame = vec![0u64; 512];
+    let phys = 0x20_0000;
+    check!(unsafe { test_frame(frame.as_mut_ptr(), phys, Mode::Full) });
+
+    unsafe { write(frame.as_mut_ptr(), phys, Pattern::Address) };
+    check_eq!(frame[3], phys + 24);
+    check!(unsafe { verify(frame.as_ptr(), phys, Pattern::Address) });
+    // A stuck bit is found
+    frame[100] ^= 1 << 17;
+    check!(!unsafe { verify(frame.as_ptr(), phys, Pattern::Address) });
+    check!(!unsafe { verify(frame.as_ptr(), phys, Pattern::Fill(0x5555_5555_5555_5555)) });
+
+    all_good!()
+}
@@ -0,0 +1,138 @@
+use crate::arch::apic::local_apic;
+use crate::error::Error;
+use crate::memory::map_mmio;
+use crate::memory::paging::mtrr::{overlap, VariableRange};
+use crate::memory::paging::page_table_manager::PageTableFlag;
+use crate::memory::paging::pat::{CacheType, MemoryType};
+use crate::memory::register::{ReadOnly, VolatileCell, WriteOnly};
+use crate::memory::PhysicalAddress;
+use crate::smp;
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_mmio_flags() {
+    check_eq!(MemoryType::WriteBack.flags(), PageTableFlag::empty());
+    check_eq!(
+        MemoryType::WriteThrough.flags(),
+        PageTableFlag::WRITE_THROUGH
+    );
+    check_eq!(
+        MemoryType::Uncacheable.flags(),
+        PageTableFlag::WRITE_THROUGH | PageTableFlag::NO_CACHE
+    );
+    // PAT entry 4, or uncacheable on CPUs without a PAT
+    let flags = MemoryType::WriteCombining.flags();
+    check!(flags == PageTableFlag::PAT || flags == MemoryType::Uncacheable.flags());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_mmio_effective_type() {
+    check_eq!(
+        MemoryType::Uncacheable.effective(CacheType::WriteBack),
+        CacheType::Uncacheable
+    );
+    check_eq!(
+        MemoryType::WriteCombining.effective(CacheType::WriteBack),
+        CacheType::WriteCombining
+    );
+    check_eq!(
+        MemoryType::WriteCombining.effective(CacheType::Uncacheable),
+        CacheType::WriteCombining
+    );
+    check_eq!(
+        MemoryType::WriteThrough.effective(CacheType::WriteBack),
+        CacheType::WriteThrough
+    );
+    check_eq!(
+        MemoryType::WriteThrough.effective(CacheType::WriteCombining),
+        CacheType::Uncacheable
+    );
+    check_eq!(
+        MemoryType::WriteThrough.effective(CacheType::WriteProtect),
+        CacheType::WriteProtect
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_mmio_mtrr_ranges() {
+    let range = VariableRange {
+        base: 0xC000_0000,
+        mask: 0xF_C000_0000,
+        ty: CacheType::Uncacheable,
+    };
+    check!(range.contains(0xC000_0000));
+    check!(range.contains(0xFFFF_FFFF));
+    check!(!range.contains(0xBFFF_FFFF));
+    check_eq!(range.size(), 0x4000_0000);
+
+    check_eq!(
+        overlap(CacheType::WriteBack, CacheType::Uncacheable),
+        CacheType::Uncacheable
+    );
+    check_eq!(
+        overlap(CacheType::WriteBack, CacheType::WriteThrough),
+        CacheType::WriteThrough
+    );
+    check_eq!(
+        overlap(CacheType::WriteBack, CacheType::WriteCombining),
+        CacheType::Uncacheable
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_mmio_rejects_write_back() {
+    let phys = PhysicalAddress::new(0xFEE0_0000);
+    check_eq!(
+        map_mmio(phys, 0x1000, MemoryType::WriteBack).err(),
+        Some(Error::InvalidArgument)
+    );
+    check_eq!(
+        map_mmio(phys, 0, MemoryType::Uncacheable).err(),
+        Some(Error::InvalidArgument)
+    );
+
+    all_good!()
+}
+
+crate::register_block! {
+    /// Stands in for the registers of a device, in RAM
+    struct TestRegisters: 0x20 {
+        0x00 => status: ReadOnly<u32>,
+        0x04 => _reserved0: [u8; 4],
+        0x08 => control: VolatileCell<u64>,
+        0x10 => doorbell: WriteOnly<u16>,
+        0x12 => _reserved1: [u8; 0xE],
+    }
+}
+
+#[esqtest::test]
+pub fn test_mmio_register_block() {
+    let regs = TestRegisters {
+        status: ReadOnly::new(0x5eed),
+        _reserved0: [0; 4],
+        control: VolatileCell::new(1),
+        doorbell: WriteOnly::new(0),
+        _reserved1: [0; 0xE],
+    };
+    check_eq!(regs.status.read(), 0x5eed);
+    regs.control.update(|control| control << 4 | 2);
+    check_eq!(regs.control.read(), 0x12);
+    regs.doorbell.write(0xabcd);
+    let base = &regs as *const TestRegisters as *const u8;
+    check_eq!(
+        unsafe { core::ptr::read_volatile(base.add(0x10) as *const u16) },
+        0xabcd
+    );
+
+    // The local APIC reports the id the CPU was registered with
+    if let Some(apic) = local_apic() {
+        check_eq!(apic.id(), smp::cpu(smp::current_cpu()).apic_id());
+    }
+    all_good!()
+}
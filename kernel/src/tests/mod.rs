@@ -1,3 +1,106 @@
+pub mod acpi;
+pub mod addr;
+pub mod ahci;
 pub mod alloc;
+pub mod alloctag;
+pub mod bench;
+pub mod blit;
+pub mod block;
 pub mod bounds;
+pub mod breadcrumb;
+pub mod buildinfo;
+pub mod cells;
+pub mod crashlog;
+pub mod devfs;
+pub mod devices;
+pub mod dma;
+pub mod entropy;
+pub mod enums;
 pub mod env;
+pub mod exceptions;
+pub mod fat32;
+pub mod faultinject;
+pub mod fmt;
+pub mod font;
+pub mod fpu;
+pub mod frameinfo;
+pub mod futex;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
+pub mod gdt;
+pub mod heap;
+pub mod initcall;
+pub mod ioperm;
+pub mod irq;
+pub mod kernel_image;
+pub mod keyboard;
+pub mod klog;
+pub mod lockstat;
+pub mod logdisk;
+pub mod mem;
+pub mod memaccess;
+pub mod memtest;
+pub mod mmio;
+pub mod net;
+#[cfg(feature = "nvme")]
+pub mod nvme;
+pub mod paging;
+pub mod pci;
+pub mod power;
+pub mod profile;
+pub mod qr;
+pub mod rand;
+pub mod rotation;
+pub mod sched;
+pub mod shell;
+pub mod shm;
+pub mod signal;
+pub mod smp;
+pub mod softirq;
+pub mod stat;
+pub mod stats;
+pub mod strace;
+pub mod sync;
+pub mod syscall;
+pub mod sysinfo;
+pub mod timers;
+pub mod trace;
+pub mod tty;
+pub mod uefi_rt;
+pub mod unwind;
+pub mod usermem;
+pub mod vfs;
+pub mod virtio;
+pub mod vmstat;
+pub mod watchdog;
+
+use crate::error::Error;
+use crate::memory::usermem::user_address;
+use crate::memory::UserVirtualAddress;
+use crate::scheduler::{signal, TaskId};
+use crate::time;
+
+/// How long the tests wait for something that should have happened long ago
+pub const PATIENCE_MS: u64 = 2000;
+
+/// Waits until `condition` holds, for at most `PATIENCE_MS`
+pub fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+    let deadline = time::now_ms() + PATIENCE_MS;
+    while !condition() {
+        if time::now_ms() >= deadline {
+            return false;
+        }
+        time::sleep_ms(1);
+    }
+    true
+}
+
+/// An address that is not in the lower half turns into null, which every access refuses
+pub fn user(addr: u64) -> UserVirtualAddress {
+    user_address(addr).unwrap_or(UserVirtualAddress::null())
+}
+
+/// Whether the task `id` is gone
+pub fn exited(id: TaskId) -> bool {
+    signal::pending(id) == Err(Error::NoSuchProcess)
+}
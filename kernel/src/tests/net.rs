@@ -0,0 +1,546 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use super::PATIENCE_MS;
+use crate::error::{Error, Result};
+use crate::net::arp::{ArpOperation, ArpPacket};
+use crate::net::checksum::checksum;
+use crate::net::dhcp::{self, Client, DhcpOption, MessageType, Reply, State};
+use crate::net::ethernet::{self, EtherType, Frame};
+use crate::net::icmp::IcmpType;
+use crate::net::ipv4::{self, IpProtocol, Packet};
+use crate::net::udp::{self, Datagram, Endpoint, UdpSocket};
+use crate::net::{self, Interface, Ipv4Address, Ipv4Config, MacAddress, NetworkDevice};
+use crate::scheduler::spin::Mutex;
+use esqtest::*;
+
+const LOCAL_MAC: MacAddress = MacAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x01]);
+const PEER_MAC: MacAddress = MacAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x02]);
+const LOCAL_IP: Ipv4Address = Ipv4Address([10, 0, 2, 15]);
+const PEER_IP: Ipv4Address = Ipv4Address([10, 0, 2, 2]);
+
+/// Records every frame that is sent instead of sending it
+#[derive(Default)]
+struct RecordingDevice {
+    sent: Mutex<Vec<Vec<u8>>>,
+}
+
+impl NetworkDevice for RecordingDevice {
+    fn mac(&self) -> MacAddress {
+        LOCAL_MAC
+    }
+
+    fn send(&self, frame: &[u8]) -> Result<()> {
+        self.sent.lock().push(frame.to_vec());
+        Ok(())
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+fn interface() -> (Interface, Arc<RecordingDevice>) {
+    let device = Arc::new(RecordingDevice::default());
+    let interface = Interface::new("test0", device.clone());
+    interface.set_config(Ipv4Config::parse("10.0.2.15/24"));
+    (interface, device)
+}
+
+fn echo_request() -> Vec<u8> {
+    let mut message = vec![IcmpType::EchoRequest, 0, 0, 0, 0x12, 0x34, 0, 1];
+    message.extend_from_slice(b"ping");
+    let sum = checksum(&message);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    let packet = ipv4::build(PEER_IP, LOCAL_IP, IpProtocol::Icmp, &message);
+    ethernet::build(LOCAL_MAC, PEER_MAC, EtherType::Ipv4, &packet)
+}
+
+fn udp_frame(port: u16, payload: &[u8]) -> Vec<u8> {
+    let source = Endpoint {
+        address: PEER_IP,
+        port: 5000,
+    };
+    let destination = Endpoint {
+        address: LOCAL_IP,
+        port,
+    };
+    let datagram = udp::build(source, destination, payload);
+    let packet = ipv4::build(PEER_IP, LOCAL_IP, IpProtocol::Udp, &datagram);
+    ethernet::build(LOCAL_MAC, PEER_MAC, EtherType::Ipv4, &packet)
+}
+
+#[esqtest::test]
+pub fn test_net_checksum() {
+    // The example from RFC 1071
+    check_eq!(
+        checksum(&[0x00, 0x01, 0xF2, 0x03, 0xF4, 0xF5, 0xF6, 0xF7]),
+        !0xDDF2
+    );
+    // Odd lengths are padded with zero
+    check_eq!(checksum(&[0x12, 0x34, 0x56]), !0x6834);
+    check_eq!(checksum(&[]), 0xFFFF);
+
+    let packet = ipv4::build(PEER_IP, LOCAL_IP, IpProtocol::Udp, &[1, 2, 3]);
+    check_eq!(checksum(&packet[..ipv4::HEADER_SIZE]), 0);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_net_addresses() {
+    check_eq!(Ipv4Address::parse("10.0.2.15"), Some(LOCAL_IP));
+    check_eq!(Ipv4Address::parse("10.0.2"), None);
+    check_eq!(Ipv4Address::parse("10.0.2.15.1"), None);
+    check_eq!(Ipv4Address::parse("10.0.2.256"), None);
+
+    let config = Ipv4Config::parse("192.168.1.10/16").unwrap();
+    check_eq!(config.address, Ipv4Address([192, 168, 1, 10]));
+    check_eq!(config.netmask(), 0xFFFF_0000);
+    check_eq!(config.broadcast(), Ipv4Address([192, 168, 255, 255]));
+    check_eq!(Ipv4Config::parse("10.0.2.15").unwrap().prefix, 24);
+    check_eq!(Ipv4Config::parse("10.0.2.15/0").unwrap().netmask(), 0);
+    check_eq!(Ipv4Config::parse("10.0.2.15/33"), None);
+
+    check_eq!(alloc::format!("{}", PEER_MAC), "52:54:00:00:00:02");
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_net_arp_reply() {
+    let (interface, device) = interface();
+    let request = ArpPacket {
+        operation: ArpOperation::Request,
+        sender_mac: PEER_MAC,
+        sender_ip: PEER_IP,
+        target_mac: MacAddress([0; 6]),
+        target_ip: LOCAL_IP,
+    };
+    interface.handle_frame(&ethernet::build(
+        MacAddress::BROADCAST,
+        PEER_MAC,
+        EtherType::Arp,
+        &request.to_bytes(),
+    ));
+    check_eq!(net::arp::lookup(PEER_IP), Some(PEER_MAC));
+
+    let sent = device.sent.lock();
+    check_eq!(sent.len(), 1);
+    let frame = Frame::parse(&sent[0]).unwrap();
+    check_eq!(frame.destination, PEER_MAC);
+    check_eq!(frame.source, LOCAL_MAC);
+    check_eq!(frame.ether_type, EtherType::Arp);
+    let reply = ArpPacket::parse(frame.payload).unwrap();
+    check_eq!(
+        reply,
+        ArpPacket {
+            operation: ArpOperation::Reply,
+            sender_mac: LOCAL_MAC,
+            sender_ip: LOCAL_IP,
+            target_mac: PEER_MAC,
+            target_ip: PEER_IP,
+        }
+    );
+    drop(sent);
+
+    // Requests for other addresses are not answered
+    let other = ArpPacket {
+        target_ip: Ipv4Address([10, 0, 2, 99]),
+        ..request
+    };
+    interface.handle_frame(&ethernet::build(
+        MacAddress::BROADCAST,
+        PEER_MAC,
+        EtherType::Arp,
+        &other.to_bytes(),
+    ));
+    check_eq!(device.sent.lock().len(), 1);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_net_icmp_echo() {
+    let (interface, device) = interface();
+    interface.handle_frame(&echo_request());
+
+    let sent = device.sent.lock();
+    check_eq!(sent.len(), 1);
+    let frame = Frame::parse(&sent[0]).unwrap();
+    check_eq!(frame.destination, PEER_MAC);
+    check_eq!(frame.ether_type, EtherType::Ipv4);
+    // Parsing verifies the header checksum
+    let packet = Packet::parse(frame.payload).unwrap();
+    check_eq!(packet.source, LOCAL_IP);
+    check_eq!(packet.destination, PEER_IP);
+    check_eq!(packet.protocol, IpProtocol::Icmp);
+
+    let message = packet.payload;
+    check_eq!(message[0], IcmpType::EchoReply);
+    check_eq!(checksum(message), 0);
+    // The identifier, the sequence number and the data are echoed
+    check_eq!(
+        &message[4..],
+        &[0x12, 0x34, 0, 1, b'p', b'i', b'n', b'g'][..]
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_net_drops() {
+    let (interface, device) = interface();
+
+    let bad_checksum = net::RX_BAD_CHECKSUM.get();
+    let mut frame = echo_request();
+    frame[ethernet::HEADER_SIZE + 10] ^= 0xFF;
+    interface.handle_frame(&frame);
+    check_eq!(net::RX_BAD_CHECKSUM.get(), bad_checksum + 1);
+
+    let bad_length = net::RX_BAD_LENGTH.get();
+    interface.handle_frame(&echo_request()[..ethernet::HEADER_SIZE + 10]);
+    interface.handle_frame(&[0; 8]);
+    check_eq!(net::RX_BAD_LENGTH.get(), bad_length + 2);
+
+    // Not addressed to us
+    let ignored = net::RX_IGNORED.get();
+    let mut frame = echo_request();
+    frame[..6].copy_from_slice(&[0x52, 0x54, 0x00, 0x00, 0x00, 0x03]);
+    interface.handle_frame(&frame);
+    check_eq!(net::RX_IGNORED.get(), ignored + 1);
+
+    // Fragments are not reassembled
+    let fragments = net::RX_FRAGMENTS.get();
+    let mut frame = udp_frame(4000, b"fragment");
+    let header = &mut frame[ethernet::HEADER_SIZE..ethernet::HEADER_SIZE + ipv4::HEADER_SIZE];
+    header[6] |= 0x20; // More fragments
+    header[10..12].copy_from_slice(&[0, 0]);
+    let sum = checksum(header);
+    header[10..12].copy_from_slice(&sum.to_be_bytes());
+    interface.handle_frame(&frame);
+    check_eq!(net::RX_FRAGMENTS.get(), fragments + 1);
+
+    check!(device.sent.lock().is_empty());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_net_udp_datagrams() {
+    let frame = udp_frame(4000, b"hello");
+    let packet = Packet::parse(&frame[ethernet::HEADER_SIZE..]).unwrap();
+    let datagram = Datagram::parse(&packet).unwrap();
+    check_eq!(datagram.source_port, 5000);
+    check_eq!(datagram.destination_port, 4000);
+    check_eq!(datagram.payload, b"hello");
+
+    // A checksum of zero means there is none
+    let mut data = packet.payload.to_vec();
+    data[6..8].copy_from_slice(&[0, 0]);
+    let unchecked = Packet {
+        payload: &data,
+        ..packet
+    };
+    check_eq!(Datagram::parse(&unchecked).unwrap().payload, b"hello");
+
+    data[8] ^= 0xFF;
+    data[6..8].copy_from_slice(&packet.payload[6..8]);
+    let corrupted = Packet {
+        payload: &data,
+        ..packet
+    };
+    check!(matches!(
+        Datagram::parse(&corrupted),
+        Err(net::RxError::BadChecksum)
+    ));
+
+    data[4..6].copy_from_slice(&100u16.to_be_bytes());
+    let too_long = Packet {
+        payload: &data,
+        ..packet
+    };
+    check!(matches!(
+        Datagram::parse(&too_long),
+        Err(net::RxError::BadLength)
+    ));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_net_udp_sockets() {
+    let socket = UdpSocket::new();
+    check_eq!(socket.bind(40007), Ok(40007));
+    check_eq!(socket.port(), Some(40007));
+    check_eq!(
+        UdpSocket::new().bind(40007),
+        Err(Error::AddressAlreadyInUse)
+    );
+    check_eq!(socket.bind(40008), Err(Error::InvalidArgument));
+
+    let ephemeral = UdpSocket::new();
+    let port = ephemeral.bind(0).unwrap();
+    check!(port >= 49152);
+    // Closing a socket frees its port
+    drop(ephemeral);
+    check_eq!(UdpSocket::new().bind(port), Ok(port));
+
+    let (interface, _) = interface();
+    let mut buf = [0u8; 16];
+    check_eq!(
+        socket.receive_from(&mut buf, true),
+        Err(Error::OperationWouldBlock)
+    );
+    check!(!socket.wait(10));
+    interface.handle_frame(&udp_frame(40007, b"first"));
+    interface.handle_frame(&udp_frame(40007, b"second datagram"));
+    check!(socket.wait(PATIENCE_MS));
+    let (len, source) = socket.receive_from(&mut buf, true).unwrap();
+    check_eq!(&buf[..len], b"first");
+    check_eq!(
+        source,
+        Endpoint {
+            address: PEER_IP,
+            port: 5000
+        }
+    );
+    // Datagrams are truncated to the buffer
+    let (len, _) = socket.receive_from(&mut buf[..6], true).unwrap();
+    check_eq!(&buf[..len], b"second");
+
+    // Nobody listens on this port
+    let no_socket = udp::RX_NO_SOCKET.get();
+    interface.handle_frame(&udp_frame(40009, b"lost"));
+    check_eq!(udp::RX_NO_SOCKET.get(), no_socket + 1);
+
+    socket.set_nonblocking(true);
+    check_eq!(
+        socket.receive_from(&mut buf, false),
+        Err(Error::OperationWouldBlock)
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_net_udp_send() {
+    let (interface, device) = interface();
+    let unresolved = Ipv4Address([10, 0, 2, 3]);
+    let peer_mac = MacAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x03]);
+    let destination = Endpoint {
+        address: unresolved,
+        port: 7,
+    };
+    check_eq!(
+        udp::send_via(&interface, 40010, destination, &[0; udp::MAX_PAYLOAD + 1]),
+        Err(Error::MessageTooLong)
+    );
+    check_eq!(
+        udp::send_via(&interface, 40010, destination, b"echo"),
+        Ok(())
+    );
+
+    // The address is unknown, so it is resolved first
+    {
+        let sent = device.sent.lock();
+        check_eq!(sent.len(), 1);
+        let frame = Frame::parse(&sent[0]).unwrap();
+        check_eq!(frame.destination, MacAddress::BROADCAST);
+        let request = ArpPacket::parse(frame.payload).unwrap();
+        check_eq!(request.operation, ArpOperation::Request);
+        check_eq!(request.target_ip, unresolved);
+    }
+
+    // The reply releases the datagram
+    let reply = ArpPacket {
+        operation: ArpOperation::Reply,
+        sender_mac: peer_mac,
+        sender_ip: unresolved,
+        target_mac: LOCAL_MAC,
+        target_ip: LOCAL_IP,
+    };
+    interface.handle_frame(&ethernet::build(
+        LOCAL_MAC,
+        peer_mac,
+        EtherType::Arp,
+        &reply.to_bytes(),
+    ));
+    let sent = device.sent.lock();
+    check_eq!(sent.len(), 2);
+    let frame = Frame::parse(&sent[1]).unwrap();
+    check_eq!(frame.destination, peer_mac);
+    let packet = Packet::parse(frame.payload).unwrap();
+    check_eq!(packet.protocol, IpProtocol::Udp);
+    let datagram = Datagram::parse(&packet).unwrap();
+    check_eq!(datagram.source_port, 40010);
+    check_eq!(datagram.destination_port, 7);
+    check_eq!(datagram.payload, b"echo");
+
+    all_good!()
+}
+
+const DNS_IP: Ipv4Address = Ipv4Address([10, 0, 2, 3]);
+
+/// An answer of the DHCP server at `PEER_IP`, which hands out `LOCAL_IP`
+fn dhcp_reply(message_type: u8, xid: u32) -> Vec<u8> {
+    let mut message = vec![0u8; 240];
+    message[0..3].copy_from_slice(&[2, 1, 6]);
+    message[4..8].copy_from_slice(&xid.to_be_bytes());
+    message[16..20].copy_from_slice(&LOCAL_IP.0);
+    message[28..34].copy_from_slice(&LOCAL_MAC.0);
+    message[236..240].copy_from_slice(&[99, 130, 83, 99]);
+    message.extend_from_slice(&[DhcpOption::MessageType, 1, message_type]);
+    message.extend_from_slice(&[DhcpOption::ServerIdentifier, 4, 10, 0, 2, 2]);
+    message.extend_from_slice(&[DhcpOption::Pad, DhcpOption::SubnetMask, 4, 255, 255, 255, 0]);
+    message.extend_from_slice(&[DhcpOption::Router, 4, 10, 0, 2, 2]);
+    message.extend_from_slice(&[DhcpOption::DomainNameServer, 8, 10, 0, 2, 3, 1, 1, 1, 1]);
+    message.extend_from_slice(&[DhcpOption::LeaseTime, 4, 0, 0, 0x0E, 0x10]);
+    message.push(DhcpOption::End);
+    message
+}
+
+/// The DHCP messages the client sent as (destination, message type, xid)
+fn dhcp_sent(device: &RecordingDevice) -> Vec<(Ipv4Address, u8, u32)> {
+    device
+        .sent
+        .lock()
+        .iter()
+        .filter_map(|frame| {
+            let frame = Frame::parse(frame).ok()?;
+            let packet = Packet::parse(frame.payload).ok()?;
+            let datagram = Datagram::parse(&packet).ok()?;
+            if datagram.destination_port != dhcp::SERVER_PORT {
+                return None;
+            }
+            let message = datagram.payload;
+            let xid = u32::from_be_bytes(message[4..8].try_into().unwrap());
+            Some((packet.destination, message[242], xid))
+        })
+        .collect()
+}
+
+#[esqtest::test]
+pub fn test_net_dhcp_reply() {
+    let reply = Reply::parse(&dhcp_reply(MessageType::Ack, 42)).unwrap();
+    check_eq!(reply.message_type, MessageType::Ack);
+    check_eq!(reply.xid, 42);
+    check_eq!(reply.server, Some(PEER_IP));
+    check_eq!(reply.lease_time, Some(3600));
+    check_eq!(
+        reply.config(),
+        Some(Ipv4Config {
+            address: LOCAL_IP,
+            prefix: 24,
+            router: Some(PEER_IP),
+            dns: Some(DNS_IP),
+        })
+    );
+
+    // The netmask has to be contiguous
+    let invalid = Reply {
+        netmask: Some(0xFF00_FF00),
+        ..reply
+    };
+    check_eq!(invalid.config(), None);
+
+    // Options may not run past the end
+    let mut truncated = dhcp_reply(MessageType::Ack, 42);
+    truncated.truncate(truncated.len() - 4);
+    check_eq!(Reply::parse(&truncated), None);
+    check_eq!(Reply::parse(&truncated[..100]), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_net_dhcp_lease() {
+    let device = Arc::new(RecordingDevice::default());
+    let interface = Arc::new(Interface::new("test0", device.clone()));
+    let mut client = Client::new(interface.clone(), None, 0);
+
+    client.start(0.0);
+    let sent = dhcp_sent(&device);
+    check_eq!(sent.len(), 1);
+    let (destination, message_type, xid) = sent[0];
+    check_eq!(destination, Ipv4Address::BROADCAST);
+    check_eq!(message_type, MessageType::Discover);
+
+    // Replies to other transactions are ignored
+    client.handle(&dhcp_reply(MessageType::Offer, xid + 1), 0.5);
+    check_eq!(client.state(), State::Selecting);
+
+    client.handle(&dhcp_reply(MessageType::Offer, xid), 0.5);
+    check_eq!(
+        client.state(),
+        State::Requesting {
+            address: LOCAL_IP,
+            server: PEER_IP
+        }
+    );
+    check_eq!(
+        dhcp_sent(&device)[1],
+        (Ipv4Address::BROADCAST, MessageType::Request, xid)
+    );
+
+    client.handle(&dhcp_reply(MessageType::Ack, xid), 1.0);
+    check_eq!(client.state(), State::Bound);
+    let config = interface.config().unwrap();
+    check_eq!(config.address, LOCAL_IP);
+    check_eq!(config.router, Some(PEER_IP));
+    check_eq!(config.dns, Some(DNS_IP));
+
+    // The lease is renewed at T1, with the server that granted it
+    check_eq!(client.deadline(), 1801.0);
+    client.tick(1800.0);
+    check_eq!(client.state(), State::Bound);
+    client.tick(1801.0);
+    check_eq!(client.state(), State::Renewing);
+    client.handle(&dhcp_reply(MessageType::Ack, xid), 1802.0);
+    check_eq!(client.state(), State::Bound);
+
+    // Without an answer, any server may extend the lease from T2 on
+    client.tick(3602.0);
+    check_eq!(client.state(), State::Renewing);
+    client.tick(1802.0 + 3150.0);
+    check_eq!(client.state(), State::Rebinding);
+    check_eq!(
+        dhcp_sent(&device)
+            .last()
+            .map(|(destination, _, _)| *destination),
+        Some(Ipv4Address::BROADCAST)
+    );
+
+    // Losing the lease unconfigures the interface
+    client.handle(&dhcp_reply(MessageType::Nak, xid), 5000.0);
+    check_eq!(client.state(), State::Selecting);
+    check_eq!(interface.config(), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_net_dhcp_fallback() {
+    let device = Arc::new(RecordingDevice::default());
+    let interface = Arc::new(Interface::new("test0", device.clone()));
+    let fallback = Ipv4Config::parse("10.0.2.15/24");
+    let mut client = Client::new(interface.clone(), fallback, 0);
+
+    client.start(0.0);
+    // Retransmissions back off exponentially
+    let mut now = 0.0;
+    for attempt in 1..dhcp::MAX_ATTEMPTS {
+        now += (1 << (attempt - 1)) as f64;
+        client.tick(now - 0.1);
+        check_eq!(dhcp_sent(&device).len(), attempt as usize);
+        client.tick(now);
+        check_eq!(dhcp_sent(&device).len(), attempt as usize + 1);
+    }
+    check_eq!(client.state(), State::Selecting);
+    check_eq!(interface.config(), None);
+
+    client.tick(now + 16.0);
+    check_eq!(client.state(), State::Failed);
+    check_eq!(interface.config(), fallback);
+
+    all_good!()
+}
@@ -0,0 +1,77 @@
+use crate::block::BlockDevice;
+use crate::drivers::nvme::NVME_NAMESPACES;
+use crate::error::Error;
+use crate::irq::msi;
+use crate::pci;
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_nvme_read_write() {
+    let namespace = match NVME_NAMESPACES.lock().first().cloned() {
+        Some(namespace) => namespace,
+        // Nothing to test without a controller
+        None => {
+            all_good!()
+        }
+    };
+    // Spans more than two pages, so a PRP list is needed
+    let size = namespace.block_size() * 24;
+    let last = namespace.block_count() - 24;
+    let mut original = alloc::vec![0u8; size];
+    check!(namespace.read_blocks(last, &mut original).is_ok());
+
+    let pattern: alloc::vec::Vec<u8> = (0..size).map(|idx| (idx % 253) as u8).collect();
+    check!(namespace.write_blocks(last, &pattern).is_ok());
+    let mut read = alloc::vec![0u8; size];
+    check!(namespace.read_blocks(last, &mut read).is_ok());
+    check!(read == pattern);
+    check!(namespace.write_blocks(last, &original).is_ok());
+
+    check!(namespace
+        .read_blocks(namespace.block_count(), &mut read)
+        .is_err());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_nvme_unbind() {
+    let device = match pci::devices().find(|device| pci::driver_of(device) == Some("nvme")) {
+        Some(device) => device,
+        None => {
+            all_good!()
+        }
+    };
+    let namespaces = NVME_NAMESPACES.lock().len();
+    let namespace = NVME_NAMESPACES.lock().first().cloned();
+    if namespace.is_some() {
+        check_eq!(pci::unbind(&device), Err(Error::DeviceOrResourceBusy));
+        check_eq!(pci::driver_of(&device), Some("nvme"));
+        check_eq!(NVME_NAMESPACES.lock().len(), namespaces);
+    }
+    drop(namespace);
+
+    match pci::unbind(&device) {
+        // A filesystem is mounted from it
+        Err(Error::DeviceOrResourceBusy) => {
+            all_good!()
+        }
+        result => check_eq!(result, Ok(())),
+    }
+    check_eq!(pci::driver_of(&device), None);
+    check!(!device.is_bus_master());
+    check!(!msi::is_enabled(&device));
+    check!(NVME_NAMESPACES.lock().len() < namespaces || namespaces == 0);
+    check_eq!(pci::unbind(&device), Err(Error::NoSuchDevice));
+    check_eq!(pci::rebind(&device, "ahci"), Err(Error::InvalidArgument));
+
+    check_eq!(pci::rebind(&device, "nvme"), Ok(()));
+    check_eq!(pci::driver_of(&device), Some("nvme"));
+    check_eq!(NVME_NAMESPACES.lock().len(), namespaces);
+    if let Some(namespace) = NVME_NAMESPACES.lock().last().cloned() {
+        let mut block = alloc::vec![0u8; namespace.block_size()];
+        check!(namespace.read_blocks(0, &mut block).is_ok());
+    }
+
+    all_good!()
+}
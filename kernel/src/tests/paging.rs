@@ -0,0 +1,158 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use bks::PAGE_SIZE;
+
+use crate::arch::init::memory::switch_pml4;
+use crate::error::Error;
+use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::memory::paging::page_frame_allocator::request_page;
+use crate::memory::paging::page_table_manager::{
+    translate, PageTable, PageTableFlag, PageTableManager,
+};
+use crate::memory::paging::pat::MemoryType;
+use crate::memory::paging::{active_pml4, dump, walk, Mapping};
+use crate::memory::{memset, VirtualAddress};
+use crate::{address_of, klog, kprintln};
+use esqtest::*;
+
+/// Tables of their own, which are never loaded
+fn private_tables() -> u64 {
+    let pml4 = request_page::<PageTable>();
+    unsafe { memset(address_of!(pml4), 0, 0x1000) };
+    let base = address_of!(pml4);
+    let mut manager = PageTableManager::new(pml4);
+    let rw = PageTableFlag::PRESENT | PageTableFlag::READ_WRITE;
+    manager.map_page(0x1000, 0x10000, rw);
+    manager.map_page(0x2000, 0x11000, rw);
+    manager.map_page(0x3000, 0x50000, rw);
+    manager.map_page(0x4000, 0x51000, PageTableFlag::PRESENT);
+    manager.map_page(0xFFFF_8000_0000_0000, 0x20000, rw | PageTableFlag::NO_CACHE);
+    base
+}
+
+/// A copy of the active PML4, sharing the tables below it
+fn copied_pml4() -> u64 {
+    let pml4 = request_page::<PageTable>();
+    let base = address_of!(pml4);
+    unsafe { core::ptr::copy_nonoverlapping(active_pml4() as *const u8, base as *mut u8, 0x1000) };
+    base
+}
+
+fn mappings(pml4: u64, start: u64, end: u64) -> Vec<Mapping> {
+    let mut mappings = Vec::new();
+    dump(pml4, start..end, |mapping| mappings.push(*mapping));
+    mappings
+}
+
+#[esqtest::test]
+pub fn test_paging_walk() {
+    let value = Box::new(0u64);
+    let addr = address_of!(&*value);
+    let mut levels = 0;
+    check_eq!(walk(active_pml4(), addr, |_| levels += 1), translate(addr));
+    check!(levels >= 2);
+
+    let pml4 = private_tables();
+    check_eq!(walk(pml4, 0x2234, |_| {}), Some(0x11234));
+    // Fails in the page table, then in the PML4, then before it
+    let mut failed = None;
+    check_eq!(walk(pml4, 0x5000, |step| failed = Some(step.level)), None);
+    check_eq!(failed, Some(3));
+    check_eq!(
+        walk(pml4, 0x80_0000_0000, |step| failed = Some(step.level)),
+        None
+    );
+    check_eq!(failed, Some(0));
+    failed = None;
+    check_eq!(
+        walk(pml4, 0x8000_0000_0000, |step| failed = Some(step.level)),
+        None
+    );
+    check_eq!(failed, None);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_paging_dump() {
+    let pml4 = private_tables();
+    let all = mappings(pml4, 0, u64::MAX);
+    check_eq!(all.len(), 3);
+    // The same flags are merged, no matter where the memory is
+    check_eq!(all[0].start, 0x1000);
+    check_eq!(all[0].len, 0x3000);
+    check_eq!(all[0].phys, None);
+    check!(all[0].flags.contains(PageTableFlag::READ_WRITE));
+    check_eq!(all[1].start, 0x4000);
+    check!(!all[1].flags.contains(PageTableFlag::READ_WRITE));
+    check_eq!(all[2].start, 0xFFFF_8000_0000_0000);
+    check_eq!(all[2].phys, Some(0x20000));
+    check_eq!(all[2].pat_index, 0b010);
+
+    let contiguous = mappings(pml4, 0x1800, 0x3000);
+    check_eq!(contiguous.len(), 1);
+    check_eq!(contiguous[0].start, 0x1800);
+    check_eq!(contiguous[0].len, 0x1800);
+    check_eq!(contiguous[0].phys, Some(0x10800));
+    check!(mappings(pml4, 0x5000, 0x8000_0000).is_empty());
+
+    // The active tables map the kernel heap
+    let value = Box::new(0u64);
+    let addr = address_of!(&*value);
+    let own = mappings(active_pml4(), addr, addr + 8);
+    check_eq!(own.len(), 1);
+    check_eq!(own[0].phys, translate(addr));
+    check!(own[0].flags.contains(PageTableFlag::PRESENT));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_framebuffer_handover() {
+    let (info, virt) = {
+        let guard = FRAMEBUFFER_GUARD.lock();
+        let guard = unsafe { guard.assume_init_ref() };
+        (guard.info(), guard.mapped_base())
+    };
+    // Serial-only, there is no mapping to hand over
+    let info = match info {
+        Some(info) => info,
+        None => return 1,
+    };
+    // The console draws through a write-combining mapping in the kernel tables
+    let wc = MemoryType::WriteCombining.flags();
+    let wc = (wc.contains(PageTableFlag::PAT) as u8) << 2
+        | (wc.contains(PageTableFlag::NO_CACHE) as u8) << 1
+        | wc.contains(PageTableFlag::WRITE_THROUGH) as u8;
+    let console = mappings(active_pml4(), virt, virt + info.mapped_len());
+    check!(!console.is_empty());
+    check!(console.iter().all(|mapping| mapping.pat_index == wc));
+    check_eq!(translate(virt), Some(info.base));
+
+    // Logging goes on across switching to other tables and back, nothing is checked in
+    // between as the test must not return on them
+    let kernel = active_pml4();
+    let copy = copied_pml4();
+    let written = klog::written();
+    check_eq!(
+        unsafe { switch_pml4(copy, Some(VirtualAddress::new(virt))) },
+        Ok(())
+    );
+    kprintln!("Logging on a copy of the kernel page tables");
+    let on_copy = active_pml4();
+    check_eq!(
+        unsafe { switch_pml4(kernel, Some(VirtualAddress::new(virt))) },
+        Ok(())
+    );
+    check_eq!(on_copy, copy);
+    check!(klog::written() > written);
+
+    // An address that maps other memory is refused, and the tables stay as they were
+    check_eq!(
+        unsafe { switch_pml4(copy, Some(VirtualAddress::new(virt + PAGE_SIZE))) },
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(active_pml4(), kernel);
+    let mapped = unsafe { FRAMEBUFFER_GUARD.lock().assume_init_ref().mapped_base() };
+    check_eq!(mapped, virt);
+    all_good!()
+}
@@ -0,0 +1,122 @@
+use crate::drivers::ahci::AHCI_DRIVER;
+use crate::error::Error;
+use crate::pci::{
+    self, ExtendedCapability, LinkSpeed, LinkStatus, PciExtendedCapability,
+    EXTENDED_CAPABILITIES_START,
+};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_pci_bindings() {
+    check_eq!(
+        pci::register_driver(&AHCI_DRIVER),
+        Err(Error::AlreadyExists)
+    );
+    check!(pci::find_device("ff:1f.7").is_none());
+
+    for device in pci::devices() {
+        check!(pci::find_device(&device.location()).is_some());
+        match pci::driver_of(&device) {
+            // Neither gives its devices back
+            Some(driver @ ("ahci" | "virtio-blk" | "virtio-net" | "virtio-console")) => {
+                check_eq!(pci::unbind(&device), Err(Error::DeviceOrResourceBusy));
+                check_eq!(pci::driver_of(&device), Some(driver));
+                check!(device.is_bus_master());
+            }
+            Some(driver) => {
+                check_eq!(
+                    pci::rebind(&device, driver),
+                    Err(Error::DeviceOrResourceBusy)
+                );
+            }
+            None => {
+                check_eq!(pci::unbind(&device), Err(Error::NoSuchDevice));
+                check_eq!(
+                    pci::rebind(&device, "no-such-driver"),
+                    Err(Error::NoSuchDevice)
+                );
+            }
+        }
+    }
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_pci_extended_capability_header() {
+    // AER, version 2, followed by a capability at 0x148
+    let aer = ExtendedCapability::from_header(0x1482_0001, 0x100);
+    check_eq!(
+        aer,
+        Some(ExtendedCapability {
+            id: PciExtendedCapability::AdvancedErrorReporting,
+            version: 2,
+            offset: 0x100,
+            next: 0x148,
+        })
+    );
+    // The last one, and one pointing back into the PCI configuration space
+    check_eq!(
+        ExtendedCapability::from_header(0x0001_0010, 0x148).map(|cap| cap.next),
+        Some(0)
+    );
+    check_eq!(
+        ExtendedCapability::from_header(0x0401_0010, 0x148).map(|cap| cap.next),
+        Some(0)
+    );
+    // A function without extended configuration space, or nothing at all
+    check_eq!(ExtendedCapability::from_header(0, 0x100), None);
+    check_eq!(ExtendedCapability::from_header(u32::MAX, 0x100), None);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_pci_link_status_decode() {
+    // Capable of 16 GT/s x16, trained to 8 GT/s x4
+    let link = LinkStatus::decode(0x0000_0104, 0x0043);
+    check_eq!(link.speed, Some(LinkSpeed::Gen3));
+    check_eq!(link.width, 4);
+    check_eq!(link.max_speed, Some(LinkSpeed::Gen4));
+    check_eq!(link.max_width, 16);
+    check!(link.is_degraded());
+
+    let full = LinkStatus::decode(0x0000_0011, 0x0011);
+    check_eq!(full.speed, Some(LinkSpeed::Gen1));
+    check_eq!(full.width, 1);
+    check!(!full.is_degraded());
+
+    check_eq!(LinkSpeed::from_code(0), None);
+    check_eq!(LinkSpeed::from_code(7), None);
+    check_eq!(LinkSpeed::Gen6.transfer_rate(), "64 GT/s");
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_pci_extended_capabilities() {
+    for device in pci::devices() {
+        let mut last = 0;
+        for capability in device.extended_capabilities() {
+            check!(capability.offset >= EXTENDED_CAPABILITIES_START);
+            check!(capability.offset < 0x1000);
+            check_eq!(capability.offset % 4, 0);
+            check_neq!(capability.offset, last);
+            check!(device.extended_capability(capability.id).is_some());
+            last = capability.offset;
+        }
+        // Only PCIe functions have extended configuration space or a link
+        if !device.is_pcie() {
+            check_eq!(device.extended_capabilities().count(), 0);
+            check_eq!(device.link_status(), None);
+        }
+        if let Some(link) = device.link_status() {
+            check!(link.width <= 32 && link.max_width <= 32);
+        }
+        check_eq!(
+            device.aer_status().is_some(),
+            device
+                .extended_capability(PciExtendedCapability::AdvancedErrorReporting)
+                .is_some()
+        );
+    }
+    all_good!()
+}
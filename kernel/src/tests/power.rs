@@ -0,0 +1,111 @@
+use crate::acpi::fadt::Fadt;
+use crate::acpi::ACPIFindable;
+use crate::error::Error;
+use crate::power::notifier::{self, PowerAction, PRIORITY_DEVICES, PRIORITY_FLUSH};
+use crate::power::{self, parse_s5, SleepType};
+use crate::syscall::{reboot_command, RebootCommand, REBOOT_MAGIC1, REBOOT_MAGIC2};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_parse_s5() {
+    // Name (\_S5, Package (0x04) { Zero, Zero, Zero, Zero }), the sleep type QEMU uses
+    let qemu = [
+        0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00,
+    ];
+    check_eq!(parse_s5(&qemu), Some(SleepType { a: 0, b: 0 }));
+    // Byte constants, and a package length with a byte following its lead byte
+    let bytes = [
+        0x10, 0x08, b'_', b'S', b'5', b'_', 0x12, 0x40, 0x01, 0x02, 0x0A, 0x05, 0x0A, 0x07,
+    ];
+    check_eq!(parse_s5(&bytes), Some(SleepType { a: 5, b: 7 }));
+    // A reference to the name is skipped, the declaration after it is used
+    let reference = [
+        0x70, b'_', b'S', b'5', b'_', 0x60, 0x08, b'_', b'S', b'5', b'_', 0x12, 0x05, 0x02, 0x01,
+        0x0B, 0x03, 0x00,
+    ];
+    check_eq!(parse_s5(&reference), Some(SleepType { a: 1, b: 3 }));
+    // Cut off, or no package
+    check_eq!(parse_s5(&qemu[..10]), None);
+    check_eq!(parse_s5(&[0x08, b'_', b'S', b'5', b'_', 0x0A, 0x05]), None);
+    check_eq!(parse_s5(b"_S5_"), None);
+    check_eq!(parse_s5(&[]), None);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_reboot_command() {
+    for cmd in [
+        RebootCommand::Restart,
+        RebootCommand::Halt,
+        RebootCommand::PowerOff,
+    ] {
+        check_eq!(reboot_command(REBOOT_MAGIC1, REBOOT_MAGIC2, cmd), Ok(cmd));
+    }
+    check_eq!(
+        reboot_command(REBOOT_MAGIC2, REBOOT_MAGIC1, RebootCommand::PowerOff),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        reboot_command(REBOOT_MAGIC1, 0, RebootCommand::Restart),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        reboot_command(REBOOT_MAGIC1, REBOOT_MAGIC2, 0),
+        Err(Error::InvalidArgument)
+    );
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_power_registers() {
+    // The test machines have a FADT whose DSDT declares \_S5, which `exit_qemu()` relies on
+    if Fadt::find().is_none() {
+        return 1;
+    }
+    let registers = power::registers();
+    check!(registers.map_or(false, |power| power.pm1a_control != 0));
+    check!(power::can_power_off());
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_notifier_order() {
+    fn nothing(_: PowerAction) {}
+
+    notifier::register("test-late", PRIORITY_DEVICES - 1, nothing);
+    notifier::register("test-first", PRIORITY_FLUSH + 1, nothing);
+    notifier::register("test-device", PRIORITY_DEVICES, nothing);
+    notifier::register("test-second", PRIORITY_FLUSH + 1, nothing);
+    let order = notifier::notifiers();
+    check!(order
+        .windows(2)
+        .all(|pair| pair[0].priority >= pair[1].priority));
+    let names = order
+        .iter()
+        .map(|notifier| notifier.name)
+        .filter(|name| name.starts_with("test-"))
+        .collect::<alloc::vec::Vec<_>>();
+    check_eq!(
+        names,
+        ["test-first", "test-second", "test-device", "test-late"]
+    );
+    // Registered ones come first within their priority
+    let devices = order
+        .iter()
+        .position(|notifier| notifier.name == "device-tree")
+        .unwrap_or(usize::MAX);
+    let device = order
+        .iter()
+        .position(|notifier| notifier.name == "test-device")
+        .unwrap_or(0);
+    check!(devices < device);
+
+    for name in ["test-first", "test-second", "test-device", "test-late"] {
+        check!(notifier::unregister(name));
+    }
+    check!(!notifier::unregister("test-first"));
+    check!(!notifier::notifiers()
+        .iter()
+        .any(|notifier| notifier.name.starts_with("test-")));
+    all_good!()
+}
@@ -0,0 +1,105 @@
+use alloc::vec::Vec;
+
+use crate::profile::{self, aggregate, FunctionSamples, Histogram, UNKNOWN};
+use crate::symbols::SymbolTable;
+use esqtest::*;
+
+const SYMBOLS: &str = "\
+0000000000101000 T kernel::main::h0123456789abcdef
+0000000000101080 t kernel::helper
+0000000000101100 T <kernel::Foo as core::fmt::Display>::fmt::h00000000000000ff
+0000000000102000 D kernel::DATA
+0000000000103000
+zzzz T bad_address
+";
+
+#[esqtest::test]
+pub fn test_profile_symbols() {
+    let table = SymbolTable::parse(SYMBOLS);
+    check_eq!(table.len(), 4);
+    let name = |address| {
+        table
+            .resolve(address)
+            .map(|(symbol, offset)| (symbol.name, offset))
+    };
+    check_eq!(name(0x100fff), None);
+    check_eq!(name(0x101000), Some(("kernel::main", 0)));
+    check_eq!(name(0x10107f), Some(("kernel::main", 0x7f)));
+    check_eq!(name(0x101090), Some(("kernel::helper", 0x10)));
+    check_eq!(
+        name(0x101200),
+        Some(("<kernel::Foo as core::fmt::Display>::fmt", 0x100))
+    );
+    // Data is not code
+    check_eq!(name(0x102010), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_profile_histogram() {
+    let histogram: Histogram<64> = Histogram::new();
+    for _ in 0..3 {
+        check!(histogram.record(0x101000));
+    }
+    check!(histogram.record(0x101004));
+    let mut entries: Vec<(u64, u64)> = histogram.entries().collect();
+    entries.sort_unstable();
+    check_eq!(entries, [(0x101000, 3), (0x101004, 1)]);
+
+    // A full table drops what does not fit
+    let full: Histogram<4> = Histogram::new();
+    for address in 1..=4 {
+        check!(full.record(address));
+    }
+    check!(!full.record(5));
+    check!(full.record(4));
+
+    histogram.clear();
+    check_eq!(histogram.entries().count(), 0);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_profile_aggregate() {
+    let table = SymbolTable::parse(SYMBOLS);
+    let entries = [(0x101000, 2), (0x101010, 3), (0x101090, 4), (0x10, 1)];
+    let functions = aggregate(entries.into_iter(), |address| {
+        table.resolve(address).map(|(symbol, _)| symbol.name)
+    });
+    check_eq!(
+        functions,
+        [
+            FunctionSamples {
+                name: "kernel::main",
+                samples: 5
+            },
+            FunctionSamples {
+                name: "kernel::helper",
+                samples: 4
+            },
+            FunctionSamples {
+                name: UNKNOWN,
+                samples: 1
+            },
+        ]
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_profile_start_stop() {
+    let was_running = profile::is_running();
+    profile::start();
+    check!(profile::is_running());
+    check!(profile::stop());
+    check!(!profile::is_running());
+    check!(!profile::stop());
+    if was_running {
+        profile::start();
+    }
+
+    all_good!()
+}
@@ -0,0 +1,60 @@
+use crate::error::Error;
+use crate::framebuffer::qr::{capacity, QrCode, MAX_PAYLOAD, MAX_VERSION};
+use esqtest::*;
+
+/// Whether the finder pattern with its top left corner at `x`, `y` is intact
+fn has_finder(code: &QrCode, x: usize, y: usize) -> bool {
+    (0..7).all(|dy| {
+        (0..7).all(|dx| {
+            let distance = (dx as isize - 3).abs().max((dy as isize - 3).abs());
+            code.is_dark(x + dx, y + dy) == (distance != 2)
+        })
+    })
+}
+
+#[esqtest::test]
+pub fn test_qr_versions() {
+    check_eq!(capacity(1), 17);
+    check_eq!(capacity(7), 154);
+    check_eq!(capacity(MAX_VERSION), MAX_PAYLOAD);
+
+    let mut code = QrCode::new();
+    let payload = [b'x'; MAX_PAYLOAD + 1];
+    for (len, version) in [
+        (0, 1),
+        (17, 1),
+        (18, 2),
+        (154, 7),
+        (155, 8),
+        (MAX_PAYLOAD, 10),
+    ] {
+        check!(code.encode(&payload[..len]).is_ok());
+        check_eq!(code.version(), version);
+        check_eq!(code.size(), 17 + 4 * version);
+    }
+    check_eq!(code.encode(&payload).err(), Some(Error::Overflow));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_qr_patterns() {
+    let mut code = QrCode::new();
+    check!(code
+        .encode(b"esque 0.1.0\nat kernel/src/main.rs:1:1")
+        .is_ok());
+    let size = code.size();
+    check!(has_finder(&code, 0, 0));
+    check!(has_finder(&code, size - 7, 0));
+    check!(has_finder(&code, 0, size - 7));
+    // Timing patterns between the finder patterns
+    for i in 8..size - 8 {
+        check_eq!(code.is_dark(i, 6), i % 2 == 0);
+        check_eq!(code.is_dark(6, i), i % 2 == 0);
+    }
+    // The dark module next to the bottom left finder pattern
+    check!(code.is_dark(8, size - 8));
+    check!(!code.is_dark(size, 0));
+
+    all_good!()
+}
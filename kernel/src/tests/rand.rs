@@ -0,0 +1,84 @@
+use crate::drivers::hwrng::{RepetitionCountTest, REPETITION_CUTOFF};
+use crate::error::Error;
+use crate::rand::chacha::{block, block_bytes};
+use crate::rand::{self, Csprng};
+use crate::syscall::random::{check_flags, GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_rand_chacha() {
+    // RFC 8439 2.3.2
+    let mut key = [0; 8];
+    for (idx, word) in key.iter_mut().enumerate() {
+        let base = idx as u8 * 4;
+        *word = u32::from_le_bytes([base, base + 1, base + 2, base + 3]);
+    }
+    let block = block(&key, 1, &[0x0900_0000, 0x4a00_0000, 0]);
+    check_eq!(
+        block,
+        [
+            0xe4e7f110, 0x15593bd1, 0x1fdd0f50, 0xc47120a3, 0xc7f4d1c7, 0x0368c033, 0x9aaa2204,
+            0x4e6cd4c3, 0x466482d2, 0x09aa9f07, 0x05d7c214, 0xa2028bd9, 0xd19c12b5, 0xb94e16de,
+            0xe883d0cb, 0x4e3c50a2,
+        ]
+    );
+    check_eq!(block_bytes(&block)[..4], [0x10, 0xf1, 0xe7, 0xe4]);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_rand_csprng() {
+    let mut first = Csprng::new();
+    let mut second = Csprng::new();
+    check!(!first.is_seeded());
+    first.reseed(&[1, 2, 3, 4]);
+    second.reseed(&[1, 2, 3, 4]);
+    check!(first.is_seeded());
+    let (mut a, mut b) = ([0u8; 100], [0u8; 100]);
+    first.fill(&mut a);
+    second.fill(&mut b);
+    // The same seed gives the same stream, and the key changes after every request
+    check_eq!(a, b);
+    first.fill(&mut b);
+    check_neq!(a, b);
+    second.reseed(&[5, 6, 7, 8]);
+    second.fill(&mut a);
+    check_neq!(a, b);
+
+    let mut buf = [0u8; 64];
+    rand::fill(&mut buf);
+    check!(buf.iter().any(|byte| *byte != 0));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_rand_health_test() {
+    let mut test = RepetitionCountTest::new();
+    check!(test.sample(0));
+    check!(test.sample(1));
+    check!(test.sample(0));
+    // A repeated sample fails once it was seen `REPETITION_CUTOFF` times in a row
+    let mut passed = true;
+    for _ in 1..REPETITION_CUTOFF {
+        passed = test.sample(0);
+    }
+    check!(!passed);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_rand_getrandom_flags() {
+    check_eq!(check_flags(0x8), Err(Error::InvalidArgument));
+    check_eq!(
+        check_flags(GRND_RANDOM | GRND_INSECURE),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(check_flags(GRND_INSECURE | GRND_NONBLOCK), Ok(()));
+    let expected = if rand::is_ready() {
+        Ok(())
+    } else {
+        Err(Error::TryAgain)
+    };
+    check_eq!(check_flags(GRND_NONBLOCK), expected);
+    all_good!()
+}
@@ -0,0 +1,171 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use bks::{Framebuffer, PixelFormat};
+
+use crate::framebuffer::font::Psf;
+use crate::framebuffer::rotation::Rotation;
+use crate::framebuffer::{self, Color, FramebufferGuard, BYTES_PER_PIXEL};
+use esqtest::*;
+
+static PSF1: &[u8] = include_bytes!("../../../binaries/font/font.psf");
+
+const ROTATIONS: [Rotation; 4] = [
+    Rotation::Rotate0,
+    Rotation::Rotate90,
+    Rotation::Rotate180,
+    Rotation::Rotate270,
+];
+
+/// Whether the logical character cell at `row`, `col` of a `width`x`height` framebuffer shows
+/// `chr` in white on black
+fn shows(
+    pixels: &[u32],
+    (width, height): (usize, usize),
+    rotation: Rotation,
+    font: &Psf,
+    (row, col): (usize, usize),
+    chr: u8,
+) -> bool {
+    let glyph = font.glyph(chr);
+    (0..font.height()).all(|y| {
+        (0..font.width()).all(|x| {
+            let expected = if font.is_set(glyph, x, y) {
+                Color::White as u32
+            } else {
+                Color::Black as u32
+            };
+            let (lx, ly) = (col * font.width() + x, row * font.height() + y);
+            let (px, py) = rotation.to_physical(lx, ly, width, height);
+            pixels[py * width + px] == expected
+        })
+    })
+}
+
+fn framebuffer(pixels: &mut [u32], width: usize, height: usize) -> Framebuffer {
+    Framebuffer::new(
+        pixels.as_mut_ptr() as u64,
+        pixels.len() * BYTES_PER_PIXEL,
+        width,
+        height,
+        width,
+        PixelFormat::Rgb,
+    )
+}
+
+#[esqtest::test]
+pub fn test_rotation_mapping() {
+    let (width, height) = (160, 64);
+    check_eq!(Rotation::from_degrees(90), Some(Rotation::Rotate90));
+    check_eq!(Rotation::from_degrees(45), None);
+    check_eq!(
+        Rotation::Rotate90.logical_size(width, height),
+        (height, width)
+    );
+    check_eq!(Rotation::Rotate0.to_physical(0, 0, width, height), (0, 0));
+    check_eq!(
+        Rotation::Rotate90.to_physical(0, 0, width, height),
+        (width - 1, 0)
+    );
+    check_eq!(
+        Rotation::Rotate180.to_physical(0, 0, width, height),
+        (width - 1, height - 1)
+    );
+    check_eq!(
+        Rotation::Rotate270.to_physical(0, 0, width, height),
+        (0, height - 1)
+    );
+
+    // The steps agree with the mapping
+    for rotation in ROTATIONS {
+        let (dx, dy) = rotation.steps(width);
+        let index = |x, y| {
+            let (px, py) = rotation.to_physical(x, y, width, height);
+            (py * width + px) as isize
+        };
+        check_eq!(index(6, 5) - index(5, 5), dx);
+        check_eq!(index(5, 6) - index(5, 5), dy);
+    }
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_rotated_console() {
+    let font = Psf::parse("small", PSF1).unwrap();
+    let (width, height) = (160, 64);
+    for rotation in ROTATIONS {
+        let mut pixels: Vec<u32> = vec![0u32; width * height];
+        let raw = framebuffer(&mut pixels, width, height);
+        let info = match framebuffer::validate(&raw, Some(&font)) {
+            Ok(info) => info,
+            Err(_) => return 1,
+        };
+        let mut guard = FramebufferGuard::new(info, raw, font, Color::Black, Color::White);
+        check!(guard.set_rotation(rotation).is_ok());
+        check_eq!(guard.rotation(), rotation);
+        guard.enable_backing_store();
+        let geometry = if rotation.is_swapped() {
+            (8, 10)
+        } else {
+            (20, 4)
+        };
+        check_eq!(guard.geometry(), geometry);
+
+        // Enough lines to scroll
+        for chr in b"0123456789ab" {
+            check!(writeln!(guard, "{}", *chr as char).is_ok());
+        }
+        let rows = geometry.1;
+        let top = b"0123456789ab"[12 - (rows - 1)];
+        check_eq!(guard.cells()[0].chr, top);
+        check!(shows(
+            &pixels,
+            (width, height),
+            rotation,
+            &font,
+            (0, 0),
+            top
+        ));
+        check!(shows(
+            &pixels,
+            (width, height),
+            rotation,
+            &font,
+            (rows - 2, 0),
+            b'b'
+        ));
+        check!(shows(
+            &pixels,
+            (width, height),
+            rotation,
+            &font,
+            (rows - 1, 0),
+            b' '
+        ));
+
+        // Rectangles are in logical coordinates as well
+        guard.fill_rect(0, 0, 1, 1, Color::Red as u32);
+        let (px, py) = rotation.to_physical(0, 0, width, height);
+        check_eq!(pixels[py * width + px], Color::Red as u32);
+    }
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_rotation_too_small() {
+    let font = Psf::parse("small", PSF1).unwrap();
+    // Three rows fit upright, but not on their side
+    let (width, height) = (40, 48);
+    let mut pixels: Vec<u32> = vec![0u32; width * height];
+    let raw = framebuffer(&mut pixels, width, height);
+    let info = match framebuffer::validate(&raw, Some(&font)) {
+        Ok(info) => info,
+        Err(_) => return 1,
+    };
+    let mut guard = FramebufferGuard::new(info, raw, font, Color::Black, Color::White);
+    check!(guard.set_rotation(Rotation::Rotate270).is_err());
+    check_eq!(guard.rotation(), Rotation::Rotate0);
+    check_eq!(guard.geometry(), (5, 3));
+    all_good!()
+}
@@ -0,0 +1,282 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::error::Error;
+use crate::scheduler::class::{self, SchedClass, NICE_0_WEIGHT};
+use crate::scheduler::cputime::{self, CpuTime};
+use crate::scheduler::idle::{self, IdleMode, IdleStats};
+use crate::scheduler::spin::{Mutex, RwLock};
+use crate::scheduler::{self, bench, preempt, task::migrate, TaskId};
+use crate::smp::{current_cpu, CpuMask};
+use crate::syscall::sched::sys_nice;
+use crate::time;
+use esqtest::*;
+
+static RAN_ON: AtomicUsize = AtomicUsize::new(usize::MAX);
+/// Ends the CPU-bound tasks of the fairness test
+static STOP_HOGS: AtomicBool = AtomicBool::new(false);
+/// The TSC cycles a CPU-bound task runs before it yields
+const HOG_SLICE: u64 = 50_000;
+/// How long the CPU-bound tasks compete
+const FAIRNESS_MS: u64 = 2000;
+/// Ends the task of the preemption test, which never yields
+static STOP_SPINNER: AtomicBool = AtomicBool::new(false);
+/// How often the preemption test sleeps while the spinner runs
+const PREEMPT_ROUNDS: u64 = 10;
+
+fn record_cpu() {
+    RAN_ON.store(current_cpu(), Ordering::SeqCst);
+}
+
+/// Burns the CPU, yielding every `HOG_SLICE` cycles
+fn hog() {
+    while !STOP_HOGS.load(Ordering::Relaxed) {
+        let start = crate::arch::tsc::read();
+        while crate::arch::tsc::read() - start < HOG_SLICE {
+            core::hint::spin_loop();
+        }
+        scheduler::yield_now();
+    }
+}
+
+/// Burns the CPU without ever yielding
+fn spinner() {
+    while !STOP_SPINNER.load(Ordering::Relaxed) {
+        core::hint::spin_loop();
+    }
+}
+
+#[esqtest::test]
+pub fn test_cpu_mask() {
+    let mut mask = CpuMask::single(3);
+    check!(mask.contains(3));
+    check!(!mask.contains(2));
+    mask.insert(2);
+    mask.remove(3);
+    check!(mask.contains(2) && !mask.contains(3));
+    check!(!CpuMask::all().contains(crate::smp::MAX_CPUS));
+    check!(CpuMask::none().is_empty());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_migrate() {
+    let cpu = current_cpu();
+    let id = scheduler::spawn("pinned", record_cpu);
+    migrate(id, cpu);
+    while RAN_ON.load(Ordering::SeqCst) == usize::MAX {
+        scheduler::yield_now();
+    }
+    check_eq!(RAN_ON.load(Ordering::SeqCst), cpu);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_task_runtime() {
+    let current = scheduler::current();
+    let runtime = |id| {
+        scheduler::task_infos()
+            .into_iter()
+            .find(|task| task.id == id)
+            .map(|task| task.time.total())
+    };
+    let before = runtime(current);
+    check!(before.is_some());
+    scheduler::yield_now();
+    check!(runtime(current) > before);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_task_time() {
+    let current = scheduler::current();
+    let before = scheduler::task_time(current);
+    check!(before.is_ok());
+    // The time since the last switch counts on the own CPU
+    let start = crate::arch::tsc::read();
+    while crate::arch::tsc::read() - start < 100_000 {
+        core::hint::spin_loop();
+    }
+    let after = scheduler::task_time(current);
+    check!(matches!((before, after), (Ok(before), Ok(after)) if after.kernel > before.kernel));
+    // Kernel code never enters user space
+    check!(!cputime::enter_kernel());
+    check_eq!(
+        scheduler::task_time(TaskId::new(u64::MAX)),
+        Err(Error::NoSuchProcess)
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_cpu_time() {
+    let time = CpuTime { user: 1, kernel: 2 } + CpuTime { user: 3, kernel: 4 };
+    check_eq!(time, CpuTime { user: 4, kernel: 6 });
+    check_eq!(time.total(), 10);
+    check_eq!(cputime::permille(250, 1000), 250);
+    check_eq!(cputime::permille(1, 3), 333);
+    check_eq!(cputime::permille(1, 0), 0);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_switch_latency() {
+    let result = match bench::ping_pong(bench::DEFAULT_HANDOFFS) {
+        Ok(result) => result,
+        Err(_) => return 1,
+    };
+    check!(result.switches >= result.handoffs);
+    let ns = match result.ns_per_switch() {
+        Some(ns) => ns,
+        None => return 1,
+    };
+    check!(ns <= bench::budget_ns());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_idle_stats() {
+    let stats = IdleStats {
+        idle_cycles: 250,
+        total_cycles: 1000,
+        ..IdleStats::default()
+    };
+    check_eq!(stats.idle_permille(), 250);
+    check_eq!(stats.busy_cycles(), 750);
+    check_eq!(IdleStats::default().idle_permille(), 0);
+
+    // Sleeping leaves the CPU with nothing to do, unless other tasks are ready
+    let cpu = current_cpu();
+    let before = idle::stats(cpu);
+    time::sleep_ms(20);
+    let after = idle::stats(cpu);
+    check!(after.total_cycles > before.total_cycles);
+    check!(after.idle_cycles >= before.idle_cycles);
+    check!(after.idle_permille() <= 1000);
+    let waits = |stats: IdleStats| stats.polls + stats.halts + stats.mwaits;
+    check!(waits(after) >= waits(before));
+    if idle::mode() == IdleMode::Halt {
+        check_eq!(after.mwaits, 0);
+    }
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_nice() {
+    check_eq!(class::weight(0), NICE_0_WEIGHT);
+    check!(class::weight(-20) > class::weight(0) && class::weight(0) > class::weight(19));
+    check_eq!(class::weight(100), class::weight(19));
+    check_eq!(class::virtual_runtime(1000, 0), 1000);
+    check!(class::virtual_runtime(1000, 10) > 9000);
+
+    let current = scheduler::current();
+    let before = match scheduler::nice(current) {
+        Ok(nice) => nice,
+        Err(_) => return 1,
+    };
+    check_eq!(scheduler::set_nice(current, 0), Ok(0));
+    check_eq!(sys_nice(5), Ok(15));
+    check_eq!(scheduler::nice(current), Ok(5));
+    check_eq!(sys_nice(-3i64 as u64), Ok(18));
+    // Clamped to the range
+    check_eq!(sys_nice(100), Ok(1));
+    check_eq!(sys_nice(-100i64 as u64), Ok(40));
+    check_eq!(
+        scheduler::set_nice(TaskId::new(u64::MAX), 0),
+        Err(Error::NoSuchProcess)
+    );
+    check_eq!(scheduler::set_nice(current, before), Ok(before));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_fair_shares() {
+    let previous = class::class();
+    class::set_class(SchedClass::Fair);
+    STOP_HOGS.store(false, Ordering::Relaxed);
+    // Pinned to this CPU, which has nothing else to do while the test sleeps
+    let cpu = CpuMask::single(current_cpu());
+    let nices = [0, 0, 10];
+    let hogs = nices.map(|nice| {
+        let id = scheduler::spawn_pinned("hog", hog, cpu);
+        scheduler::set_nice(id, nice).map(|_| id)
+    });
+    let runtime = |id: &TaskId| scheduler::task_time(*id).map_or(0, |time| time.total());
+    time::sleep_ms(FAIRNESS_MS);
+    let ran = hogs.map(|hog| hog.as_ref().map_or(0, runtime));
+    STOP_HOGS.store(true, Ordering::Relaxed);
+    time::sleep_ms(10);
+    class::set_class(previous);
+    if hogs.iter().any(Result::is_err) {
+        return 1;
+    }
+
+    // Every task gets its weight out of the total weight, give or take a tenth of that
+    let total: u64 = ran.iter().sum();
+    let weights = nices.map(class::weight);
+    let total_weight: u64 = weights.iter().sum();
+    for (ran, weight) in ran.iter().zip(weights) {
+        let share = cputime::permille(*ran, total);
+        let expected = weight * 1000 / total_weight;
+        check!((share.max(expected) - share.min(expected)) * 10 <= expected);
+    }
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_preempt() {
+    let previous = class::class();
+    class::set_class(SchedClass::Fair);
+    STOP_SPINNER.store(false, Ordering::Relaxed);
+    // Both on this CPU, so only the tick gets this task back on it after every sleep
+    let cpu = CpuMask::single(current_cpu());
+    scheduler::set_affinity(scheduler::current(), cpu);
+    let preemptions = scheduler::PREEMPTIONS.get();
+    let spinner = scheduler::spawn_pinned("spinner", spinner, cpu);
+    for _ in 0..PREEMPT_ROUNDS {
+        time::sleep_ms(1);
+    }
+    let ran = scheduler::task_time(spinner).map_or(0, |time| time.total());
+    let preempted = scheduler::PREEMPTIONS.get() - preemptions;
+    STOP_SPINNER.store(true, Ordering::Relaxed);
+    time::sleep_ms(10);
+    scheduler::set_affinity(scheduler::current(), CpuMask::all());
+    class::set_class(previous);
+
+    check!(ran > 0);
+    check!(preempted >= PREEMPT_ROUNDS);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_preempt_count() {
+    let mutex = Mutex::new(0);
+    let rwlock = RwLock::new(0);
+    check_eq!(preempt::count(current_cpu()), 0);
+    {
+        let _guard = mutex.lock();
+        // Not preempted from here on, so the CPU stays the same
+        let cpu = current_cpu();
+        check_eq!(preempt::count(cpu), 1);
+        check!(mutex.try_lock().is_none());
+        check_eq!(preempt::count(cpu), 1);
+        {
+            let _read = rwlock.read();
+            let _again = rwlock.read();
+            check_eq!(preempt::count(cpu), 3);
+        }
+        check_eq!(preempt::count(cpu), 1);
+    }
+    check_eq!(preempt::count(current_cpu()), 0);
+
+    all_good!()
+}
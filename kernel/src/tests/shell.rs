@@ -0,0 +1,31 @@
+use crate::shell::{self, Echo};
+use crate::tty::{self, Mode};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_shell_input() {
+    check_eq!(shell::input('\x7F'), Echo::Nothing);
+    check_eq!(shell::input('h'), Echo::Char('h'));
+    check_eq!(shell::input('\x1B'), Echo::Nothing);
+    check_eq!(shell::input('ä'), Echo::Nothing);
+    check_eq!(shell::input('\x08'), Echo::Erase(1));
+    check_eq!(shell::input('\x08'), Echo::Nothing);
+    // CR LF is a single line break
+    check_eq!(shell::input('\r'), Echo::NewLine);
+    check_eq!(shell::input('\n'), Echo::Nothing);
+    // Nothing is taken while the line waits to be executed
+    check_eq!(shell::input('x'), Echo::Nothing);
+    // Commands see what is typed on the console in the meantime
+    let previous = tty::set_mode(Mode::Raw);
+    check!(!shell::key_pressed());
+    tty::input('q');
+    check!(shell::key_pressed());
+    tty::input('q');
+    check_eq!(shell::read_key(), Some('q'));
+    check_eq!(shell::read_key(), None);
+    tty::set_mode(previous);
+    shell::poll();
+    check_eq!(shell::input('\n'), Echo::NewLine);
+    shell::poll();
+    all_good!()
+}
@@ -0,0 +1,205 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::{user, wait_for, PATIENCE_MS};
+use crate::arch::paging::page_table_manager::translate;
+use crate::error::Error;
+use crate::fs;
+use crate::ipc::shm::{self, O_CREAT, O_EXCL};
+use crate::memory::usermem::{copy_from_user, copy_to_user, read_user, write_user};
+use crate::memory::vmm::{ADDRESS_SPACE, MMAP_START};
+use crate::memory::UserVirtualAddress;
+use crate::scheduler;
+use crate::syscall::futex;
+use crate::syscall::mman::{
+    sys_mmap, sys_munmap, sys_shm_open, MAP_PRIVATE, MAP_SHARED, PROT_READ, PROT_WRITE,
+};
+use crate::time;
+use esqtest::*;
+
+const RW: u64 = PROT_READ | PROT_WRITE;
+
+// Kernel memory is in the lower half as well, so it stands in for user memory
+static NAMESPACE_NAME: &[u8] = b"/esq-test-namespace\0";
+static SHARED_NAME: &[u8] = b"/esq-test-shared\0";
+
+fn name(name: &'static [u8]) -> &'static str {
+    core::str::from_utf8(&name[..name.len() - 1]).unwrap()
+}
+
+#[esqtest::test]
+pub fn test_shm_namespace() {
+    let path = name(NAMESPACE_NAME);
+    check_eq!(
+        shm::open(path, 0, 0).map(|_| ()),
+        Err(Error::NoSuchFileOrDirectory)
+    );
+    for bad in ["esq", "/", "/esq/test"] {
+        check_eq!(
+            shm::open(bad, O_CREAT, 4096).map(|_| ()),
+            Err(Error::InvalidArgument)
+        );
+    }
+    check_eq!(
+        shm::open(path, O_CREAT, 0).map(|_| ()),
+        Err(Error::InvalidArgument)
+    );
+    let object = match shm::open(path, O_CREAT | O_EXCL, 5000) {
+        Ok(object) => object,
+        Err(_) => return 1,
+    };
+    check_eq!(object.len(), 5000);
+    check_eq!(object.frames().len(), 2);
+    check_eq!(
+        shm::open(path, O_CREAT | O_EXCL, 5000).map(|_| ()),
+        Err(Error::AlreadyExists)
+    );
+    check!(shm::open(path, O_CREAT, 1).map_or(false, |again| Arc::ptr_eq(&again, &object)));
+    let object = Arc::downgrade(&object);
+
+    let fd = sys_shm_open(user(NAMESPACE_NAME.as_ptr() as u64), 0, 0);
+    check!(fd.is_ok());
+    let fd = fd.unwrap_or(0) as u64;
+    check_eq!(
+        sys_mmap(UserVirtualAddress::null(), 4096, RW, MAP_PRIVATE, fd, 0),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        sys_mmap(UserVirtualAddress::null(), 4096, RW, MAP_SHARED, fd, 100),
+        Err(Error::InvalidArgument)
+    );
+    // Beyond the last frame
+    check_eq!(
+        sys_mmap(UserVirtualAddress::null(), 8192, RW, MAP_SHARED, fd, 4096),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        sys_mmap(UserVirtualAddress::null(), 4096, RW, MAP_SHARED, 999, 0),
+        Err(Error::BadFileNumber)
+    );
+    let addr = match sys_mmap(UserVirtualAddress::null(), 5000, RW, MAP_SHARED, fd, 0) {
+        Ok(addr) => addr,
+        Err(_) => return 1,
+    };
+    check!(addr >= MMAP_START);
+    check_eq!(
+        ADDRESS_SPACE
+            .lock()
+            .region(addr + 4096)
+            .map(|region| region.len),
+        Some(8192)
+    );
+    // Written through the mapping, read through the frame
+    check_eq!(write_user(user(addr + 4100), 0x5eed_u32), Ok(()));
+    let frame = object
+        .upgrade()
+        .map(|object| object.frames()[1].start().as_u64());
+    check_eq!(
+        frame.map(|frame| translate(addr + 4100) == Some(frame + 4)),
+        Some(true)
+    );
+
+    // The name, the descriptor and the mapping keep the object alive, in any order
+    check_eq!(shm::unlink(path), Ok(()));
+    check_eq!(shm::unlink(path), Err(Error::NoSuchFileOrDirectory));
+    check_eq!(fs::close_fd(fd), Ok(()));
+    check!(object.upgrade().is_some());
+    check_eq!(sys_munmap(user(addr), 4096), Ok(0));
+    check!(ADDRESS_SPACE.lock().region(addr).is_none());
+    check_eq!(translate(addr), None);
+    check_eq!(read_user::<u32>(user(addr + 4100)).ok(), Some(0x5eed));
+    check!(object.upgrade().is_some());
+    check_eq!(sys_munmap(user(addr + 4096), 4096), Ok(0));
+    check!(object.upgrade().is_none());
+    check_eq!(translate(addr + 4096), None);
+    all_good!()
+}
+
+/// The values of the word at the start of the shared page, which both tasks wait on
+const REQUEST: u32 = 1;
+const REPLY: u32 = 2;
+/// Where the messages are in the shared page
+const MESSAGE: u64 = 64;
+
+static PEER_ADDR: AtomicU64 = AtomicU64::new(0);
+static PEER_PHYS: AtomicU64 = AtomicU64::new(0);
+static PEER_DONE: AtomicBool = AtomicBool::new(false);
+
+/// Waits until the word at `addr` is `value`
+fn wait_for_word(addr: u64, value: u32) -> bool {
+    let deadline = time::now_ms() + PATIENCE_MS;
+    loop {
+        let word = match read_user::<u32>(user(addr)) {
+            Ok(word) => word,
+            Err(_) => return false,
+        };
+        if word == value {
+            return true;
+        }
+        if time::now_ms() >= deadline {
+            return false;
+        }
+        let _ = futex::wait(user(addr), word, Some(PATIENCE_MS));
+    }
+}
+
+/// The other side, with a descriptor and a mapping of its own
+fn peer() {
+    let fd = match sys_shm_open(user(SHARED_NAME.as_ptr() as u64), 0, 0) {
+        Ok(fd) => fd as u64,
+        Err(_) => return,
+    };
+    let addr = match sys_mmap(UserVirtualAddress::null(), 4096, RW, MAP_SHARED, fd, 0) {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+    let _ = fs::close_fd(fd);
+    PEER_ADDR.store(addr, Ordering::SeqCst);
+    PEER_PHYS.store(translate(addr).unwrap_or(0), Ordering::SeqCst);
+    if wait_for_word(addr, REQUEST) {
+        let mut request = [0u8; 4];
+        let _ = copy_from_user(&mut request, user(addr + MESSAGE));
+        request.reverse();
+        let _ = copy_to_user(user(addr + MESSAGE), &request);
+        let _ = write_user(user(addr), REPLY);
+        let _ = futex::wake(user(addr), 1);
+    }
+    let _ = sys_munmap(user(addr), 4096);
+    PEER_DONE.store(true, Ordering::SeqCst);
+}
+
+#[esqtest::test]
+pub fn test_shm_two_tasks() {
+    PEER_ADDR.store(0, Ordering::SeqCst);
+    PEER_PHYS.store(0, Ordering::SeqCst);
+    PEER_DONE.store(false, Ordering::SeqCst);
+    let fd = match sys_shm_open(user(SHARED_NAME.as_ptr() as u64), O_CREAT | O_EXCL, 4096) {
+        Ok(fd) => fd as u64,
+        Err(_) => return 1,
+    };
+    let addr = match sys_mmap(UserVirtualAddress::null(), 4096, RW, MAP_SHARED, fd, 0) {
+        Ok(addr) => addr,
+        Err(_) => return 1,
+    };
+    let object = fs::shared_memory(fd).map(|object| Arc::downgrade(&object));
+    check_eq!(fs::close_fd(fd), Ok(()));
+    scheduler::spawn("shm-peer", peer);
+
+    // The peer waits on its own mapping and is woken through this one, and the other way round
+    check_eq!(copy_to_user(user(addr + MESSAGE), b"ping"), Ok(()));
+    check_eq!(write_user(user(addr), REQUEST), Ok(()));
+    check!(futex::wake(user(addr), 1).is_ok());
+    check!(wait_for_word(addr, REPLY));
+    let mut reply = [0u8; 4];
+    check_eq!(copy_from_user(&mut reply, user(addr + MESSAGE)), Ok(()));
+    check_eq!(&reply, b"gnip");
+    // The peer opened the object by its name by now
+    check_eq!(shm::unlink(name(SHARED_NAME)), Ok(()));
+
+    check!(wait_for(|| PEER_DONE.load(Ordering::SeqCst)));
+    check_neq!(PEER_ADDR.load(Ordering::SeqCst), addr);
+    check_eq!(translate(addr), Some(PEER_PHYS.load(Ordering::SeqCst)));
+    check_eq!(sys_munmap(user(addr), 4096), Ok(0));
+    check!(object.map_or(false, |object| object.upgrade().is_none()));
+    all_good!()
+}
@@ -0,0 +1,185 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::{exited, wait_for};
+use crate::error::Error;
+use crate::scheduler::signal::{self, Action, Signal};
+use crate::scheduler::{self, TaskId};
+use crate::syscall::signal::{sys_getpgid, sys_kill, sys_setpgid, sys_setsid};
+use crate::time;
+use crate::tty::{self, Echo, Mode};
+use esqtest::*;
+
+/// Ends the workers that were not terminated
+static RELEASE: AtomicBool = AtomicBool::new(false);
+/// Counts the rounds of all workers
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Acts on its signals like a task returning from system calls would
+fn worker() {
+    while !RELEASE.load(Ordering::Acquire) {
+        signal::handle_pending();
+        TICKS.fetch_add(1, Ordering::Relaxed);
+        time::sleep_ms(1);
+    }
+}
+
+fn bit(signal: u32) -> u32 {
+    1 << signal
+}
+
+/// Whether the workers advance within 20 ms
+fn ticking() -> bool {
+    let ticks = TICKS.load(Ordering::Relaxed);
+    time::sleep_ms(20);
+    TICKS.load(Ordering::Relaxed) != ticks
+}
+
+#[esqtest::test]
+pub fn test_signal_actions() {
+    check_eq!(Action::of(Signal::SIGINT), Action::Terminate);
+    check_eq!(Action::of(Signal::SIGKILL), Action::Terminate);
+    check_eq!(Action::of(Signal::SIGTSTP), Action::Stop);
+    check_eq!(Action::of(Signal::SIGCONT), Action::Continue);
+    check_eq!(Action::of(Signal::SIGCHLD), Action::Ignore);
+    check_eq!(signal::check(0), Ok(()));
+    check_eq!(
+        signal::check(signal::SIGNAL_MAX + 1),
+        Err(Error::InvalidArgument)
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_process_groups() {
+    RELEASE.store(false, Ordering::Release);
+    let leader = scheduler::spawn("pgrp-leader", worker);
+    let member = scheduler::spawn("pgrp-member", worker);
+    let current = scheduler::current();
+    // Spawned tasks inherit the group and the session
+    check_eq!(signal::pgid(leader), signal::pgid(current));
+    check_eq!(signal::sid(leader), signal::sid(current));
+
+    check_eq!(signal::set_pgid(leader, leader), Ok(()));
+    check_eq!(signal::set_pgid(member, leader), Ok(()));
+    check_eq!(signal::pgid(member), Ok(leader));
+    check!(signal::group_exists(leader));
+    // Only groups of the same session can be joined
+    check_eq!(
+        signal::set_pgid(member, TaskId::new(u64::MAX)),
+        Err(Error::OperationNotPermitted)
+    );
+    check_eq!(
+        signal::set_pgid(TaskId::new(u64::MAX), leader),
+        Err(Error::NoSuchProcess)
+    );
+    // A group leader cannot start a session, a member can
+    check_eq!(signal::set_sid(leader), Err(Error::OperationNotPermitted));
+
+    // Signal zero only checks
+    check_eq!(signal::send_group(leader, 0), Ok(2));
+    check_eq!(signal::pending(member), Ok(0));
+    check_eq!(signal::send_group(leader, Signal::SIGCHLD), Ok(2));
+    check!(wait_for(|| signal::pending(member) == Ok(0)));
+
+    // Stopped until continued
+    check_eq!(signal::send_group(leader, Signal::SIGTSTP), Ok(2));
+    check!(wait_for(
+        || signal::pending(leader) == Ok(0) && signal::pending(member) == Ok(0)
+    ));
+    check!(!ticking());
+    check_eq!(signal::send_group(leader, Signal::SIGCONT), Ok(2));
+    check!(ticking());
+
+    // A continue cancels a pending stop
+    check_eq!(signal::send(member, Signal::SIGSTOP), Ok(()));
+    check_eq!(signal::send(member, Signal::SIGCONT), Ok(()));
+    check_eq!(
+        signal::pending(member).map(|pending| pending & bit(Signal::SIGSTOP)),
+        Ok(0)
+    );
+
+    // The member leaves, the leader is terminated, the rest of the group is the member
+    check_eq!(signal::set_sid(member), Ok(member));
+    check_eq!(signal::pgid(member), Ok(member));
+    check_eq!(signal::send_group(leader, Signal::SIGINT), Ok(1));
+    check!(wait_for(|| exited(leader)));
+    // A group of exited tasks is empty
+    check_eq!(
+        signal::send_group(leader, Signal::SIGINT),
+        Err(Error::NoSuchProcess)
+    );
+    check!(!signal::group_exists(leader));
+    check_eq!(signal::send(leader, 0), Err(Error::NoSuchProcess));
+
+    check_eq!(signal::send(member, Signal::SIGKILL), Ok(()));
+    check!(wait_for(|| exited(member)));
+
+    RELEASE.store(true, Ordering::Release);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_kill_syscalls() {
+    RELEASE.store(false, Ordering::Release);
+    let id = scheduler::spawn("kill", worker);
+    let pid = id.inner();
+    check_eq!(sys_setpgid(pid, 0), Ok(0));
+    check_eq!(sys_getpgid(pid), Ok(pid as i32));
+    check_eq!(
+        sys_getpgid(0),
+        signal::pgid(scheduler::current()).map(|pgid| pgid.inner() as i32)
+    );
+    check_eq!(sys_getpgid(-2i64 as u64), Err(Error::InvalidArgument));
+    check_eq!(sys_setpgid(pid, -1i64 as u64), Err(Error::InvalidArgument));
+    // The tests run in `kmain`, which leads its group already
+    check_eq!(sys_setsid(), Err(Error::OperationNotPermitted));
+
+    check_eq!(sys_kill(pid, 0), Ok(0));
+    check_eq!(sys_kill(-1i64 as u64, 0), Err(Error::InvalidArgument));
+    check_eq!(
+        sys_kill(pid, signal::SIGNAL_MAX as u64 + 1),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        sys_kill(-(u32::MAX as i64) as u64, 0),
+        Err(Error::NoSuchProcess)
+    );
+    // To the whole group
+    check_eq!(
+        sys_kill(-(pid as i64) as u64, Signal::SIGTERM as u64),
+        Ok(0)
+    );
+    check!(wait_for(|| exited(id)));
+    check_eq!(sys_kill(pid, 0), Err(Error::NoSuchProcess));
+
+    RELEASE.store(true, Ordering::Release);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_tty_signals() {
+    RELEASE.store(false, Ordering::Release);
+    let previous_mode = tty::set_mode(Mode::Canonical);
+    let previous_group = tty::foreground_group();
+    let id = scheduler::spawn("foreground", worker);
+    if signal::set_pgid(id, id).is_err() {
+        return 1;
+    }
+    tty::set_foreground_group(id.inner());
+
+    check_eq!(tty::input('\x1A'), Echo::Suspend);
+    check!(wait_for(|| signal::pending(id) == Ok(0)));
+    check!(!ticking());
+    check_eq!(signal::send(id, Signal::SIGCONT), Ok(()));
+    check!(ticking());
+    check_eq!(tty::input('\x03'), Echo::Interrupt);
+    check!(wait_for(|| exited(id)));
+    // The group is gone, Ctrl-C signals no one
+    check_eq!(tty::input('\x03'), Echo::Interrupt);
+
+    tty::set_foreground_group(previous_group);
+    tty::set_mode(previous_mode);
+    RELEASE.store(true, Ordering::Release);
+    all_good!()
+}
@@ -0,0 +1,25 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::smp::{call_all, call_on, current_cpu, online_count, online_cpus, MAX_CPUS};
+use esqtest::*;
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static RESPONSES: [AtomicUsize; MAX_CPUS] = [ZERO; MAX_CPUS];
+
+fn respond(cpu: usize) {
+    RESPONSES[cpu].fetch_add(1, Ordering::SeqCst);
+}
+
+#[esqtest::test]
+pub fn test_call_all() {
+    check!(online_count() > 0);
+    call_all(respond).wait();
+    for cpu in online_cpus() {
+        check_eq!(RESPONSES[cpu].load(Ordering::SeqCst), 1);
+    }
+
+    call_on(current_cpu(), respond).wait();
+    check_eq!(RESPONSES[current_cpu()].load(Ordering::SeqCst), 2);
+
+    all_good!()
+}
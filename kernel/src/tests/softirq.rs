@@ -0,0 +1,59 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::irq::softirq::{self, SoftirqKind, Tasklet, MAX_RESTARTS, SOFTIRQ_COUNT};
+use crate::scheduler;
+use esqtest::*;
+
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+static TASKLET: Tasklet = Tasklet::new(count_run);
+/// Schedules itself again until it ran `REPEATS` times
+static REPEATING: Tasklet = Tasklet::new(repeat);
+static REPEATS: AtomicUsize = AtomicUsize::new(0);
+
+fn count_run() {
+    RUNS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn repeat() {
+    if REPEATS.fetch_add(1, Ordering::Relaxed) + 1 < 3 {
+        REPEATING.schedule();
+    }
+}
+
+/// Runs softirqs until `done` returns true, another CPU or ksoftirqd may take them first
+fn run_until(done: impl Fn() -> bool) -> bool {
+    for _ in 0..1000 {
+        if done() {
+            return true;
+        }
+        softirq::run(MAX_RESTARTS);
+        scheduler::yield_now();
+    }
+    done()
+}
+
+#[esqtest::test]
+pub fn test_tasklet_runs_once() {
+    let runs = RUNS.load(Ordering::Relaxed);
+    let count = SOFTIRQ_COUNT.get(SoftirqKind::Tasklet as usize);
+    check!(TASKLET.schedule());
+    // Scheduled already, so it is not queued twice
+    check!(!TASKLET.schedule());
+    check!(run_until(
+        || !TASKLET.is_scheduled() && !TASKLET.is_running()
+    ));
+    check_eq!(RUNS.load(Ordering::Relaxed), runs + 1);
+    check!(SOFTIRQ_COUNT.get(SoftirqKind::Tasklet as usize) > count);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_tasklet_reschedules_itself() {
+    REPEATS.store(0, Ordering::Relaxed);
+    check!(REPEATING.schedule());
+    check!(run_until(|| REPEATS.load(Ordering::Relaxed) == 3
+        && !REPEATING.is_scheduled()
+        && !REPEATING.is_running()));
+    check_eq!(REPEATS.load(Ordering::Relaxed), 3);
+    all_good!()
+}
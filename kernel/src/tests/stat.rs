@@ -0,0 +1,216 @@
+use alloc::{boxed::Box, string::String, sync::Arc, vec, vec::Vec};
+use tar::tar::Tar;
+
+use super::block::MemDisk;
+use super::user;
+use crate::error::Error;
+use crate::fs::{self, fat32::Fat32, tarfs::TarFs, File, FileSystem, FileType};
+use crate::memory::usermem::read_user;
+use crate::memory::UserVirtualAddress;
+use crate::syscall::stat::{
+    sys_fstat, sys_getdents, sys_stat, Dirent, Stat, DT_DIR, DT_REG, S_IFDIR, S_IFMT, S_IFREG,
+};
+use esqtest::*;
+
+/// Generated by `data/mkfat32.py`
+static IMAGE: &[u8] = include_bytes!("data/fat32.img");
+
+/// # Tar
+/// An archive of `(name, mtime, data)`, leaked so it lives as long as the initramfs
+pub fn tar(entries: &[(&str, u64, &[u8])]) -> Tar<'static> {
+    let mut archive = Vec::new();
+    for (name, mtime, data) in entries {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(alloc::format!("{:011o}", data.len()).as_bytes());
+        header[136..147].copy_from_slice(alloc::format!("{:011o}", mtime).as_bytes());
+        header[156] = if name.ends_with('/') { b'5' } else { b'0' };
+        header[257..263].copy_from_slice(b"ustar\0");
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(data);
+        archive.resize((archive.len() + 511) / 512 * 512, 0);
+    }
+    archive.resize(archive.len() + 1024, 0);
+    Tar::from_slice(Box::leak(archive.into_boxed_slice()))
+}
+
+fn names(file: &dyn File, offset: u64) -> Vec<String> {
+    file.read_dir(offset)
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect()
+}
+
+/// The records `getdents()` filled into `buf`, as (inode, type, name)
+fn parse_dirents(buf: &[u8]) -> Vec<(u64, u8, String)> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < buf.len() {
+        let head = unsafe { (buf[offset..].as_ptr() as *const Dirent).read_unaligned() };
+        let name = &buf[offset + 11..offset + head.reclen as usize];
+        let len = name.iter().position(|byte| *byte == 0).unwrap();
+        records.push((
+            head.ino,
+            head.ty,
+            String::from_utf8(name[..len].to_vec()).unwrap(),
+        ));
+        offset += head.reclen as usize;
+    }
+    records
+}
+
+#[esqtest::test]
+pub fn test_tarfs() {
+    let fs = TarFs::new(tar(&[
+        ("./", 0, b""),
+        ("./etc/", 1_600_000_000, b""),
+        ("./etc/motd", 1_700_000_000, b"hello\n"),
+        // No entry for `bin`
+        ("./bin/init", 0, b"\x7fELF"),
+        ("./bin/sh", 0, b""),
+    ]));
+    let root = fs.open(&[]).unwrap();
+    check_eq!(root.metadata().ty, FileType::Directory);
+    check_eq!(names(&*root, 0), vec!["bin", "etc"]);
+    check_eq!(names(&*root, 1), vec!["etc"]);
+    check!(names(&*root, 2).is_empty());
+
+    let etc = fs.open(&["etc"]).unwrap().metadata();
+    check_eq!(etc.ty, FileType::Directory);
+    check_eq!(etc.mtime, Some(1_600_000_000));
+    let motd = fs.open(&["etc", "motd"]).unwrap();
+    check_eq!(motd.metadata().size, 6);
+    check_eq!(motd.metadata().mtime, Some(1_700_000_000));
+    let mut buf = [0u8; 16];
+    check_eq!(motd.read_at(0, &mut buf), Ok(6));
+    check_eq!(&buf[..6], b"hello\n");
+    check_eq!(motd.read_at(6, &mut buf), Ok(0));
+
+    // The same file by another path has the same inode
+    let bin = fs.open(&["bin"]).unwrap();
+    check_eq!(bin.metadata().ty, FileType::Directory);
+    check_eq!(bin.metadata().mtime, None);
+    check_eq!(names(&*bin, 0), vec!["init", "sh"]);
+    check_eq!(
+        fs.open(&["bin", "..", "etc", "motd"]).unwrap().metadata(),
+        motd.metadata()
+    );
+    check_eq!(root.read_dir(0).unwrap()[1].metadata, etc);
+
+    check_eq!(
+        fs.open(&["etc", "issue"]).err(),
+        Some(Error::NoSuchFileOrDirectory)
+    );
+    check_eq!(
+        fs.open(&["etc", "motd", "x"]).err(),
+        Some(Error::NotADirectory)
+    );
+    check_eq!(motd.read_dir(0).err(), Some(Error::NotADirectory));
+    check_eq!(bin.read_at(0, &mut buf), Err(Error::IsADirectory));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_stat() {
+    // The modification time of `HELLO.TXT`, 2024-02-29 12:34:56
+    let mut image = IMAGE.to_vec();
+    let entry = image
+        .windows(11)
+        .position(|name| name == b"HELLO   TXT")
+        .unwrap();
+    image[entry + 22..entry + 24].copy_from_slice(&(12u16 << 11 | 34 << 5 | 28).to_le_bytes());
+    image[entry + 24..entry + 26].copy_from_slice(&(44u16 << 9 | 2 << 5 | 29).to_le_bytes());
+    let volume = Fat32::new(Arc::new(MemDisk::from_bytes(&image))).unwrap();
+    fs::mount("/stattest", volume).unwrap();
+
+    let mut stat = Stat::default();
+    let ptr = user(&mut stat as *mut Stat as u64);
+    check_eq!(
+        sys_stat(user(b"/stattest/HELLO.TXT\0".as_ptr() as u64), ptr),
+        Ok(0)
+    );
+    let hello = read_user::<Stat>(ptr).unwrap();
+    check_eq!(hello.mode & S_IFMT, S_IFREG);
+    check_eq!(hello.size, 14);
+    check_eq!(hello.blocks, 1);
+    check_eq!(hello.mtime, 1709210096);
+
+    let fd = fs::open_fd("/stattest/hello.txt").unwrap();
+    check_eq!(sys_fstat(fd, ptr), Ok(0));
+    check_eq!(read_user::<Stat>(ptr), Ok(hello));
+    fs::close_fd(fd).unwrap();
+
+    check_eq!(
+        sys_stat(user(b"/stattest/BOOT/..\0".as_ptr() as u64), ptr),
+        Ok(0)
+    );
+    let root = read_user::<Stat>(ptr).unwrap();
+    check_eq!(root.mode & S_IFMT, S_IFDIR);
+    check_eq!(root.mtime, 0);
+    check_eq!(sys_stat(user(b"/stattest\0".as_ptr() as u64), ptr), Ok(0));
+    check_eq!(read_user::<Stat>(ptr).unwrap().ino, root.ino);
+    check!(root.ino != hello.ino);
+
+    check_eq!(
+        sys_stat(user(b"/stattest/MISSING\0".as_ptr() as u64), ptr),
+        Err(Error::NoSuchFileOrDirectory)
+    );
+    check_eq!(sys_fstat(u64::MAX, ptr), Err(Error::BadFileNumber));
+    check_eq!(
+        sys_fstat(0, UserVirtualAddress::null()),
+        Err(Error::BadFault)
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_getdents() {
+    fs::mount(
+        "/getdentstest",
+        Fat32::new(Arc::new(MemDisk::from_bytes(IMAGE))).unwrap(),
+    )
+    .unwrap();
+    let expected: Vec<String> = fs::open("/getdentstest")
+        .unwrap()
+        .read_dir(0)
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    let fd = fs::open_fd("/getdentstest").unwrap();
+
+    // Too small for even one record, which leaves the offset alone
+    let mut buf = vec![0u8; 4096];
+    let ptr = user(buf.as_mut_ptr() as u64);
+    check_eq!(sys_getdents(fd, ptr, 8), Err(Error::InvalidArgument));
+
+    // One record at a time resumes where the last call stopped
+    let first = Dirent::record_len(expected[0].len());
+    check_eq!(sys_getdents(fd, ptr, first + 7), Ok(first as i32));
+    let mut records = parse_dirents(&buf[..first]);
+    let filled = sys_getdents(fd, ptr, buf.len()).unwrap() as usize;
+    records.extend(parse_dirents(&buf[..filled]));
+    check_eq!(sys_getdents(fd, ptr, buf.len()), Ok(0));
+
+    check_eq!(
+        records.iter().map(|(_, _, name)| name).collect::<Vec<_>>(),
+        expected.iter().collect::<Vec<_>>()
+    );
+    let boot = records.iter().find(|(_, _, name)| name == "BOOT").unwrap();
+    check_eq!(boot.1, DT_DIR);
+    check_eq!(boot.0, fs::stat("/getdentstest/BOOT").unwrap().inode);
+    check!(records
+        .iter()
+        .any(|(_, ty, name)| name == "HELLO.TXT" && *ty == DT_REG));
+    fs::close_fd(fd).unwrap();
+
+    let fd = fs::open_fd("/getdentstest/HELLO.TXT").unwrap();
+    check_eq!(sys_getdents(fd, ptr, buf.len()), Err(Error::NotADirectory));
+    fs::close_fd(fd).unwrap();
+    check_eq!(sys_getdents(0, ptr, buf.len()), Err(Error::NotADirectory));
+
+    all_good!()
+}
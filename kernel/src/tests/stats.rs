@@ -0,0 +1,54 @@
+use crate::arch::interrupts::{handler_name, IrqScope};
+use crate::arch::scheduler::pit::PIT_INTERRUPT;
+use crate::stats::{self, Counter, CounterArray, IRQ_COUNT};
+use esqtest::*;
+
+crate::counter!(TEST_COUNTER = "test.counter");
+crate::counter_array!(TEST_ARRAY[4] = "test.array");
+
+#[esqtest::test]
+pub fn test_counters() {
+    let counter = Counter::new();
+    counter.increment();
+    counter.add(4);
+    check_eq!(counter.get(), 5);
+
+    let array = CounterArray::<4>::new();
+    array.increment(1);
+    array.add(3, 2);
+    // Out of range indices are ignored
+    array.increment(4);
+    check_eq!(array.get(1), 1);
+    check_eq!(array.get(3), 2);
+    check_eq!(array.get(4), 0);
+
+    array.record_max(0, 7);
+    array.record_max(0, 5);
+    check_eq!(array.get(0), 7);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_irq_stats() {
+    check_eq!(handler_name(PIT_INTERRUPT as usize), Some("pit"));
+    check_eq!(handler_name(0xF), None);
+    {
+        let _irq = IrqScope::enter(0xF);
+    }
+    check_eq!(IRQ_COUNT.get(0xF), 1);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_stat_registry() {
+    TEST_COUNTER.add(3);
+    TEST_ARRAY.increment(2);
+    check_eq!(stats::find("test.counter"), Some(3));
+    check_eq!(stats::find("test.array"), Some(1));
+    check_eq!(stats::find("test.does_not_exist"), None);
+    check!(stats::snapshot().any(|stat| stat.name == "test.array" && stat.index == Some(2)));
+
+    all_good!()
+}
@@ -0,0 +1,60 @@
+use crate::error::Error;
+use crate::scheduler::{self, TaskId};
+use crate::syscall::trace::{format_entry, format_result, trace, TRACE_STRING_MAX};
+use crate::syscall::SyscallNumber;
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_strace_format() {
+    check_eq!(
+        format_entry(SyscallNumber::Read, &[3, 0x1000, 512, 7, 7, 7]),
+        "read(3, 0x1000, 512)"
+    );
+    check_eq!(
+        format_entry(SyscallNumber::SendTo, &[4, 0, 0, 0x40, 0x2000, 16]),
+        "sendto(4, NULL, 0, 0x40, 0x2000, 16)"
+    );
+    // Kernel memory is in the lower half as well, so it stands in for user memory
+    let path = b"/boot/init\0";
+    check_eq!(
+        format_entry(SyscallNumber::Open, &[path.as_ptr() as u64, 0, 0, 0, 0, 0]),
+        "open(\"/boot/init\")"
+    );
+    let long = [b'a'; TRACE_STRING_MAX + 8];
+    let traced = format_entry(SyscallNumber::Open, &[long.as_ptr() as u64, 0, 0, 0, 0, 0]);
+    check!(traced.ends_with("aaa\"...)"));
+    check_eq!(
+        format_entry(SyscallNumber::Open, &[0, 0, 0, 0, 0, 0]),
+        "open(0x0)"
+    );
+    // System calls without metadata get their raw registers logged
+    check_eq!(
+        format_entry(1234, &[1, 2, 3, 4, 5, 6]),
+        "syscall_1234(0x1, 0x2, 0x3, 0x4, 0x5, 0x6)"
+    );
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_strace_result() {
+    check_eq!(format_result(3), "3");
+    check_eq!(format_result(-9i64 as u64), "-EBADF");
+    check_eq!(format_result(-2i64 as u64), "-ENOENT");
+    check_eq!(format_result(-10_000i64 as u64), "-10000");
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_strace_toggle() {
+    check_eq!(
+        trace(TaskId::new(u64::MAX), true),
+        Err(Error::NoSuchProcess)
+    );
+    let current = scheduler::current();
+    check!(!scheduler::is_current_traced());
+    check_eq!(trace(current, true), Ok(()));
+    check!(scheduler::is_current_traced());
+    check_eq!(trace(current, false), Ok(()));
+    check!(!scheduler::is_current_traced());
+    all_good!()
+}
@@ -0,0 +1,189 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::wait_for;
+use crate::error::Error;
+use crate::scheduler::sync::{Mutex, Semaphore};
+use crate::scheduler::{self, Priority, TaskId};
+use crate::smp::{current_cpu, CpuMask};
+use esqtest::*;
+
+/// How often the low priority task yields while it holds the lock
+const HOLD_YIELDS: usize = 16;
+/// How often the middle priority task yields before it is done
+const HOG_ROUNDS: usize = 256;
+const PENDING: usize = usize::MAX;
+
+static INVERSION_MUTEX: Mutex<()> = Mutex::new(());
+/// The same lock without an owner, which the waiter cannot lend its priority to
+static INVERSION_SEMAPHORE: Semaphore = Semaphore::new(1);
+static USE_SEMAPHORE: AtomicBool = AtomicBool::new(false);
+static HELD: AtomicBool = AtomicBool::new(false);
+static LOW_DONE: AtomicBool = AtomicBool::new(false);
+/// The priorities the low priority task ran with while it held the lock and afterwards
+static LOW_PEAK: AtomicUsize = AtomicUsize::new(0);
+static LOW_AFTER: AtomicUsize = AtomicUsize::new(0);
+/// The rounds of the middle priority task so far, and when the high priority task got the lock
+static ROUNDS: AtomicUsize = AtomicUsize::new(0);
+static ACQUIRED_AT: AtomicUsize = AtomicUsize::new(PENDING);
+static HOG_DONE: AtomicBool = AtomicBool::new(false);
+
+/// The rounds of the semaphore ping-pong
+const PING_ROUNDS: usize = 200;
+static PING: Semaphore = Semaphore::new(0);
+static PONG: Semaphore = Semaphore::new(0);
+static PING_DONE: AtomicBool = AtomicBool::new(false);
+
+fn own_priority() -> Priority {
+    let id = scheduler::current();
+    scheduler::task_infos()
+        .into_iter()
+        .find(|task| task.id == id)
+        .map_or(Priority::Low, |task| task.priority)
+}
+
+fn with_lock(critical: impl FnOnce()) {
+    if USE_SEMAPHORE.load(Ordering::SeqCst) {
+        INVERSION_SEMAPHORE.acquire();
+        critical();
+        INVERSION_SEMAPHORE.release();
+    } else {
+        let _guard = INVERSION_MUTEX.lock();
+        critical();
+    }
+}
+
+fn low() {
+    with_lock(|| {
+        HELD.store(true, Ordering::SeqCst);
+        for _ in 0..HOLD_YIELDS {
+            scheduler::yield_now();
+            LOW_PEAK.fetch_max(own_priority() as usize, Ordering::SeqCst);
+        }
+    });
+    LOW_AFTER.store(own_priority() as usize, Ordering::SeqCst);
+    LOW_DONE.store(true, Ordering::SeqCst);
+}
+
+fn middle() {
+    for round in 1..=HOG_ROUNDS {
+        ROUNDS.store(round, Ordering::SeqCst);
+        scheduler::yield_now();
+    }
+    HOG_DONE.store(true, Ordering::SeqCst);
+}
+
+fn high() {
+    with_lock(|| ACQUIRED_AT.store(ROUNDS.load(Ordering::SeqCst), Ordering::SeqCst));
+}
+
+/// Runs the three tasks on the calling CPU, the low priority one taking the lock first
+///
+/// ## Returns
+/// - usize = The rounds the middle priority task made before the high priority one got the lock
+fn run_inversion(semaphore: bool) -> usize {
+    USE_SEMAPHORE.store(semaphore, Ordering::SeqCst);
+    for flag in [&HELD, &LOW_DONE, &HOG_DONE] {
+        flag.store(false, Ordering::SeqCst);
+    }
+    for value in [&LOW_PEAK, &LOW_AFTER, &ROUNDS] {
+        value.store(0, Ordering::SeqCst);
+    }
+    ACQUIRED_AT.store(PENDING, Ordering::SeqCst);
+
+    let cpu = CpuMask::single(current_cpu());
+    let spawn = |name: &'static str, entry: fn(), priority: Priority| -> TaskId {
+        let id = scheduler::spawn_pinned(name, entry, cpu);
+        let _ = scheduler::set_priority(id, priority);
+        id
+    };
+    // Normal like the test, so other busy tasks on the CPU cannot starve it
+    spawn("inversion-low", low, Priority::Normal);
+    while !HELD.load(Ordering::SeqCst) {
+        scheduler::yield_now();
+    }
+    spawn("inversion-high", high, Priority::Realtime);
+    spawn("inversion-mid", middle, Priority::High);
+    while !(LOW_DONE.load(Ordering::SeqCst)
+        && HOG_DONE.load(Ordering::SeqCst)
+        && ACQUIRED_AT.load(Ordering::SeqCst) != PENDING)
+    {
+        scheduler::yield_now();
+    }
+    ACQUIRED_AT.load(Ordering::SeqCst)
+}
+
+#[esqtest::test]
+pub fn test_priority_inheritance() {
+    // The semaphore has no owner to boost: The middle task keeps the low one from releasing it
+    check_eq!(run_inversion(true), HOG_ROUNDS);
+    check_eq!(LOW_PEAK.load(Ordering::SeqCst), Priority::Normal as usize);
+
+    // The holder of the mutex runs with the priority of its waiter until it releases it
+    check!(run_inversion(false) < HOG_ROUNDS);
+    check_eq!(LOW_PEAK.load(Ordering::SeqCst), Priority::Realtime as usize);
+    check_eq!(LOW_AFTER.load(Ordering::SeqCst), Priority::Normal as usize);
+
+    let id = scheduler::current();
+    check!(scheduler::set_priority(id, Priority::High).is_ok());
+    check_eq!(own_priority(), Priority::High);
+    check!(scheduler::set_priority(id, Priority::Normal).is_ok());
+    check_eq!(
+        scheduler::set_priority(TaskId::new(u64::MAX), Priority::Low),
+        Err(Error::NoSuchProcess)
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_mutex() {
+    let mutex = Mutex::new(5);
+    {
+        let mut guard = mutex.lock();
+        *guard += 1;
+        check!(mutex.is_locked());
+        check!(mutex.try_lock().is_none());
+    }
+    check!(!mutex.is_locked());
+    check_eq!(*mutex.try_lock().unwrap(), 6);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_semaphore() {
+    let semaphore = Semaphore::new(2);
+    check!(semaphore.try_acquire());
+    semaphore.acquire();
+    check!(!semaphore.try_acquire());
+    semaphore.release();
+    check_eq!(semaphore.available(), 1);
+    check!(semaphore.try_acquire());
+
+    all_good!()
+}
+
+fn pong() {
+    for _ in 0..PING_ROUNDS {
+        PING.acquire();
+        PONG.release();
+    }
+    PING_DONE.store(true, Ordering::SeqCst);
+}
+
+#[esqtest::test]
+pub fn test_semaphore_wakeup() {
+    PING_DONE.store(false, Ordering::SeqCst);
+    scheduler::spawn("semaphore-pong", pong);
+    // Every release races the other side going to sleep, a lost wakeup leaves it asleep with
+    // a permit available
+    for _ in 0..PING_ROUNDS {
+        PING.release();
+        check!(wait_for(|| PONG.try_acquire()));
+    }
+    check!(wait_for(|| PING_DONE.load(Ordering::SeqCst)));
+    check_eq!(PING.available(), 0);
+    check_eq!(PONG.available(), 0);
+
+    all_good!()
+}
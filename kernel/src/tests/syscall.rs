@@ -0,0 +1,160 @@
+use crate::arch::interrupts::register::Registers;
+use crate::arch::iobus::msr::{read_msr, MsrRegister};
+use crate::arch::smap::RFLAGS_AC;
+use crate::arch::syscall::{syscall_dispatcher, STACK_ALIGN, SYSCALL_CPUS};
+use crate::error::ErrorCode;
+use crate::smp::current_cpu;
+use crate::syscall::futex::FUTEX_WAKE;
+use crate::syscall::mman::{MAP_SHARED, PROT_READ};
+use crate::syscall::SyscallNumber;
+use core::sync::atomic::Ordering;
+use esqtest::*;
+
+const RFLAGS_TF: u64 = 1 << 8;
+const RFLAGS_IF: u64 = 1 << 9;
+
+#[esqtest::test]
+pub fn test_syscall_entry_setup() {
+    let area = &SYSCALL_CPUS[current_cpu()];
+    // `swapgs` on entry makes GS point to the area of this CPU
+    check_eq!(read_msr(MsrRegister::KernelBase), area as *const _ as u64);
+    let kernel_rsp = area.kernel_rsp.load(Ordering::Relaxed);
+    check!(kernel_rsp != 0);
+    check_eq!(kernel_rsp % STACK_ALIGN, 0);
+    // Nothing may interrupt the entry before it switched stacks, and user flags stay behind
+    let mask = read_msr(MsrRegister::SyscallMask);
+    check_eq!(
+        mask & (RFLAGS_IF | RFLAGS_TF | RFLAGS_AC),
+        RFLAGS_IF | RFLAGS_TF | RFLAGS_AC
+    );
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_syscall_tiny_user_stack() {
+    // A user stack with 16 bytes of room, the entry path never writes to it
+    let user_stack = [0xA5u8; 16];
+    let user_rsp = user_stack.as_ptr() as u64 + 8;
+    let mut regs = Registers {
+        r15: 15,
+        r14: 14,
+        r13: 13,
+        r12: 12,
+        rbp: user_rsp,
+        rbx: 3,
+        r11: 0x202,
+        r10: 0,
+        r9: 0,
+        r8: 0,
+        rsi: 0,
+        rdi: 0,
+        rdx: 0,
+        rcx: 0x40_0000,
+        rax: 999,
+        rip: 0x40_0000,
+        cs: 0x33,
+        rflags: 0x202,
+        rsp: user_rsp,
+        ss: 0x2B,
+    };
+    unsafe { syscall_dispatcher(&mut regs) };
+    check_eq!({ regs.rax } as i64, -(ErrorCode::EINVAL as i64));
+    // The saved user state is returned as it was
+    check_eq!({ regs.rsp }, user_rsp);
+    check_eq!({ regs.rip }, 0x40_0000);
+    check_eq!({ regs.rbx }, 3);
+    check_eq!({ regs.r15 }, 15);
+    check!(user_stack.iter().all(|byte| *byte == 0xA5));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_syscall_api_version() {
+    let user_stack = [0u8; 16];
+    let mut regs = Registers {
+        r15: 0,
+        r14: 0,
+        r13: 0,
+        r12: 0,
+        rbp: 0,
+        rbx: 0,
+        r11: 0x202,
+        r10: 0,
+        r9: 0,
+        r8: 0,
+        rsi: 0,
+        rdi: 0,
+        rdx: 0,
+        rcx: 0x40_0000,
+        rax: SyscallNumber::ApiVersion,
+        rip: 0x40_0000,
+        cs: 0x33,
+        rflags: 0x202,
+        rsp: user_stack.as_ptr() as u64 + 8,
+        ss: 0x2B,
+    };
+    unsafe { syscall_dispatcher(&mut regs) };
+    check_eq!({ regs.rax }, abi::ABI_VERSION as u64);
+    all_good!()
+}
+
+/// An address in the kernel half, where user pointers may never point
+const KERNEL_POINTER: u64 = 0xffff_8000_0000_1000;
+
+/// # Call
+/// Makes the system call `number` with `args` through the syscall entry, as user space would
+fn call(number: u64, args: [u64; 6]) -> i64 {
+    let user_stack = [0u8; 16];
+    let mut regs = Registers {
+        r15: 0,
+        r14: 0,
+        r13: 0,
+        r12: 0,
+        rbp: 0,
+        rbx: 0,
+        r11: 0x202,
+        r10: args[3],
+        r9: args[5],
+        r8: args[4],
+        rsi: args[1],
+        rdi: args[0],
+        rdx: args[2],
+        rcx: 0x40_0000,
+        rax: number,
+        rip: 0x40_0000,
+        cs: 0x33,
+        rflags: 0x202,
+        rsp: user_stack.as_ptr() as u64 + 8,
+        ss: 0x2B,
+    };
+    unsafe { syscall_dispatcher(&mut regs) };
+    regs.rax as i64
+}
+
+#[esqtest::test]
+pub fn test_syscall_kernel_pointers() {
+    let efault = -(ErrorCode::EFAULT as i64);
+    let calls: [(u64, [u64; 6]); 9] = [
+        (SyscallNumber::Open, [KERNEL_POINTER, 0, 0, 0, 0, 0]),
+        (SyscallNumber::Read, [0, KERNEL_POINTER, 16, 0, 0, 0]),
+        (SyscallNumber::NanoSleep, [KERNEL_POINTER, 0, 0, 0, 0, 0]),
+        (SyscallNumber::SysInfo, [KERNEL_POINTER, 0, 0, 0, 0, 0]),
+        (
+            SyscallNumber::Futex,
+            [KERNEL_POINTER, FUTEX_WAKE, 1, 0, 0, 0],
+        ),
+        (SyscallNumber::ShmOpen, [KERNEL_POINTER, 0, 0, 0, 0, 0]),
+        (
+            SyscallNumber::Mmap,
+            [KERNEL_POINTER, 4096, PROT_READ, MAP_SHARED, 0, 0],
+        ),
+        // Bit 47 alone is sign extended into the kernel half
+        (SyscallNumber::Open, [0x0000_8000_0000_0000, 0, 0, 0, 0, 0]),
+        // Not canonical at all
+        (SyscallNumber::Open, [0x8000_0000_0000_0000, 0, 0, 0, 0, 0]),
+    ];
+    for (number, args) in calls {
+        check_eq!(call(number, args), efault);
+    }
+    all_good!()
+}
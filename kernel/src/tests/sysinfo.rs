@@ -0,0 +1,67 @@
+use bks::PAGE_SIZE;
+
+use crate::error::Error;
+use crate::memory::usermem::user_address;
+use crate::memory::UserVirtualAddress;
+use crate::syscall::sysinfo::{sys_sysinfo, SysInfo, SysInfoFields, SYSINFO_HEADER_SIZE};
+use esqtest::*;
+
+fn user(info: &mut SysInfo) -> UserVirtualAddress {
+    user_address(info as *mut SysInfo as u64).unwrap_or(UserVirtualAddress::null())
+}
+
+#[esqtest::test]
+pub fn test_sysinfo() {
+    // Kernel memory is in the lower half as well, so it stands in for user memory
+    let mut info = SysInfo {
+        size: core::mem::size_of::<SysInfo>() as u32,
+        ..SysInfo::default()
+    };
+    check_eq!(sys_sysinfo(user(&mut info)), Ok(0));
+    check_eq!(info.valid, SysInfoFields::all().bits());
+    check!(info.total_ram > 0);
+    check!(info.free_ram <= info.total_ram);
+    check!(info.procs > 0);
+    check_eq!(info.page_size, PAGE_SIZE);
+    // Only the segregated heap accounts its allocations
+    check!(info.heap_bytes > 0 || cfg!(feature = "linked-list-heap"));
+    check!(info
+        .top_consumers
+        .windows(2)
+        .all(|pair| pair[0].bytes >= pair[1].bytes));
+    check!(info.idle_permille <= 1000);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_sysinfo_old_binary() {
+    // A binary that only knows about `total_ram` and `free_ram`
+    let mut info = SysInfo {
+        size: (SYSINFO_HEADER_SIZE + 16) as u32,
+        uptime_secs: 0xdead,
+        ..SysInfo::default()
+    };
+    check_eq!(sys_sysinfo(user(&mut info)), Ok(0));
+    check_eq!(
+        info.valid,
+        (SysInfoFields::TOTAL_RAM | SysInfoFields::FREE_RAM).bits()
+    );
+    check!(info.total_ram > 0);
+    // Nothing beyond the size it passed is written
+    check_eq!(info.uptime_secs, 0xdead);
+    check_eq!(info.page_size, 0);
+
+    let mut too_small = SysInfo {
+        size: 4,
+        ..SysInfo::default()
+    };
+    check_eq!(
+        sys_sysinfo(user(&mut too_small)),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        sys_sysinfo(UserVirtualAddress::null()),
+        Err(Error::BadFault)
+    );
+    all_good!()
+}
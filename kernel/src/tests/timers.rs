@@ -0,0 +1,221 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::{wait_for, PATIENCE_MS};
+use crate::arch::interrupts::without_interrupts;
+use crate::arch::scheduler::pit::BASE_FREQUENCY;
+use crate::arch::tsc;
+use crate::error::Error;
+use crate::scheduler::{self, WaitQueue};
+use crate::time::clock::{self, ClockSource, MAX_ADJUST_PPB};
+use crate::time::wheel::{TimerWheel, WheelEntry, MAX_DELAY, SLOTS};
+use crate::time::{self, Timer, Timespec};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_timer_wheel() {
+    let mut wheel = TimerWheel::new();
+    let mut expired = Vec::new();
+    // One timer for every level, and one beyond the highest
+    let expiries = [5, SLOTS as u64 + 7, 5000, 300_000, MAX_DELAY + 1000];
+    for (id, expires) in expiries.iter().enumerate() {
+        wheel.insert(id as u64, *expires);
+    }
+    check_eq!(wheel.len(), expiries.len());
+    check_eq!(wheel.next_event(), Some(5));
+    wheel.advance(4, &mut expired);
+    check!(expired.is_empty());
+    wheel.advance(5, &mut expired);
+    check_eq!(expired.as_slice(), &[WheelEntry { id: 0, expires: 5 }]);
+
+    // Uneven steps, every timer has to expire at the first step that reaches it
+    let mut now = 5;
+    let mut step = 1;
+    while !wheel.is_empty() {
+        check!(wheel.next_event().map_or(false, |next| next > now));
+        expired.clear();
+        let last = now;
+        now += step;
+        step = step * 3 % 9973 + 1;
+        wheel.advance(now, &mut expired);
+        for entry in &expired {
+            check_eq!(entry.expires, expiries[entry.id as usize]);
+            check!(entry.expires > last && entry.expires <= now);
+        }
+    }
+    check!(now >= MAX_DELAY + 1000);
+    check_eq!(wheel.next_event(), None);
+
+    // Timers that expired already expire right away
+    wheel.insert(9, 0);
+    expired.clear();
+    wheel.advance(now + 1, &mut expired);
+    check_eq!(expired.as_slice(), &[WheelEntry { id: 9, expires: 0 }]);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_timer_wheel_order() {
+    let mut wheel = TimerWheel::new();
+    let mut expired = Vec::new();
+    let mut seed = 0x2545f491u64;
+    for id in 0..500 {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        wheel.insert(id, (seed >> 33) % 100_000);
+    }
+    wheel.advance(100_000, &mut expired);
+    check_eq!(expired.len(), 500);
+    check!(expired
+        .windows(2)
+        .all(|pair| pair[0].expires <= pair[1].expires));
+    all_good!()
+}
+
+static FIRED: AtomicU64 = AtomicU64::new(0);
+
+#[esqtest::test]
+pub fn test_timer_cancel() {
+    FIRED.store(0, Ordering::SeqCst);
+    let timer = Timer::schedule(5, || {
+        FIRED.fetch_add(1, Ordering::SeqCst);
+    });
+    check!(wait_for(|| FIRED.load(Ordering::SeqCst) == 1));
+    check!(!timer.is_pending());
+    check!(!timer.cancel());
+
+    let cancelled = Timer::schedule(20, || {
+        FIRED.fetch_add(1, Ordering::SeqCst);
+    });
+    check!(cancelled.is_pending());
+    check!(cancelled.cancel());
+    check!(!cancelled.cancel());
+    time::sleep_ms(50);
+    check_eq!(FIRED.load(Ordering::SeqCst), 1);
+    all_good!()
+}
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+#[esqtest::test]
+pub fn test_timer_cancel_while_expiring() {
+    STARTED.store(false, Ordering::SeqCst);
+    FINISHED.store(false, Ordering::SeqCst);
+    let timer = Timer::schedule(0, || {
+        STARTED.store(true, Ordering::SeqCst);
+        let end = time::now_ms() + 20;
+        while time::now_ms() < end {
+            scheduler::yield_now();
+        }
+        FINISHED.store(true, Ordering::SeqCst);
+    });
+    check!(wait_for(|| STARTED.load(Ordering::SeqCst)));
+    // The callback is running, cancelling waits for it and does not prevent anything
+    check!(!timer.cancel());
+    check!(FINISHED.load(Ordering::SeqCst));
+
+    FIRED.store(0, Ordering::SeqCst);
+    let periodic = Timer::schedule_periodic(1, || {
+        FIRED.fetch_add(1, Ordering::SeqCst);
+    });
+    check!(wait_for(|| FIRED.load(Ordering::SeqCst) >= 3));
+    check!(periodic.is_pending());
+    check!(periodic.cancel());
+    let runs = FIRED.load(Ordering::SeqCst);
+    time::sleep_ms(20);
+    check_eq!(FIRED.load(Ordering::SeqCst), runs);
+    all_good!()
+}
+
+static QUEUE: WaitQueue = WaitQueue::new();
+static READY: AtomicBool = AtomicBool::new(false);
+
+#[esqtest::test]
+pub fn test_wait_timeout() {
+    let start = time::now_ms();
+    check!(!QUEUE.wait_until_timeout(|| false, 20));
+    check!(time::now_ms() >= start + 20);
+    check!(!QUEUE.has_waiters());
+
+    READY.store(false, Ordering::SeqCst);
+    let timer = Timer::schedule(5, || {
+        READY.store(true, Ordering::SeqCst);
+        QUEUE.wake_all();
+    });
+    check!(QUEUE.wait_until_timeout(|| READY.load(Ordering::SeqCst), PATIENCE_MS));
+    timer.cancel();
+
+    let start = time::now_ms();
+    time::sleep_ms(30);
+    check!(time::now_ms() >= start + 30);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_timespec() {
+    let timespec = |tv_sec, tv_nsec| Timespec { tv_sec, tv_nsec };
+    check_eq!(timespec(0, 0).to_ms(), Ok(0));
+    check_eq!(timespec(2, 1).to_ms(), Ok(2001));
+    check_eq!(timespec(0, 999_999_999).to_ms(), Ok(1000));
+    check_eq!(timespec(-1, 0).to_ms(), Err(Error::InvalidArgument));
+    check_eq!(
+        timespec(0, 1_000_000_000).to_ms(),
+        Err(Error::InvalidArgument)
+    );
+    all_good!()
+}
+
+/// How long the clock test keeps interrupts disabled
+const BLACKOUT_MS: u64 = 50;
+
+#[esqtest::test]
+pub fn test_clock_without_interrupts() {
+    if clock::source() != ClockSource::Tsc {
+        // Counting ticks is what loses the time, there is nothing to test
+        all_good!();
+    }
+    // The PIT counts the time, the ticks the clock used to count are not coming
+    let latch = (BASE_FREQUENCY * BLACKOUT_MS / 1000) as u16;
+    let (before, measured, after) = without_interrupts(|| {
+        let before = time::uptime_ns();
+        let measured = tsc::measure(latch);
+        (before, measured, time::uptime_ns())
+    });
+    check!(measured.is_ok());
+    let elapsed_ms = (after - before) / 1_000_000;
+    check!(elapsed_ms >= BLACKOUT_MS - BLACKOUT_MS / 20);
+    check!(elapsed_ms <= BLACKOUT_MS + BLACKOUT_MS / 5);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_clock_adjust() {
+    check_eq!(clock::tsc_offset(0), 0);
+    check_eq!(
+        clock::adjust_freq_ppb(MAX_ADJUST_PPB + 1),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        clock::adjust_freq_ppb(-MAX_ADJUST_PPB - 1),
+        Err(Error::InvalidArgument)
+    );
+    if clock::source() != ClockSource::Tsc {
+        check_eq!(clock::adjust_freq_ppb(0), Err(Error::NoSuchDevice));
+        all_good!();
+    }
+
+    let mut last = time::uptime_ns();
+    for ppb in [MAX_ADJUST_PPB, -MAX_ADJUST_PPB, 0] {
+        check_eq!(clock::adjust_freq_ppb(ppb), Ok(()));
+        check_eq!(clock::freq_adjustment_ppb(), ppb);
+        // Changing the rate never turns the clock back
+        for _ in 0..1000 {
+            let now = time::uptime_ns();
+            check!(now >= last);
+            last = now;
+        }
+    }
+    all_good!()
+}
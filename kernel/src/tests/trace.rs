@@ -0,0 +1,55 @@
+use crate::trace::{self, Event, Record, Ring, RECORD_SIZE};
+use esqtest::*;
+
+fn record(tsc: u64) -> Record {
+    Record::new(tsc, 1, Event::SyscallEntry, [tsc * 2, tsc * 3])
+}
+
+#[esqtest::test]
+pub fn test_trace_ring_overwrite() {
+    let ring: Ring<4> = Ring::new();
+    check!(ring.snapshot().is_empty());
+    for tsc in 0..3 {
+        ring.push(record(tsc));
+    }
+    check_eq!(ring.snapshot(), [record(0), record(1), record(2)]);
+    check_eq!(ring.overwritten(), 0);
+    // The oldest records make room, the rest stay in order
+    for tsc in 3..6 {
+        ring.push(record(tsc));
+    }
+    check_eq!(
+        ring.snapshot(),
+        [record(2), record(3), record(4), record(5)]
+    );
+    check_eq!(ring.overwritten(), 2);
+    ring.clear();
+    check!(ring.snapshot().is_empty());
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_trace_record_layout() {
+    let bytes = Record::new(0x1122, 3, Event::IrqExit, [0xaa, 0xbb]).to_bytes();
+    check_eq!(bytes.len(), RECORD_SIZE);
+    check_eq!(&bytes[..8], &0x1122_u64.to_le_bytes());
+    check_eq!(&bytes[8..12], &[3, 0, Event::IrqExit as u8, 0]);
+    check_eq!(&bytes[12..16], &[0; 4]);
+    check_eq!(&bytes[16..24], &0xaa_u64.to_le_bytes());
+    check_eq!(&bytes[24..], &0xbb_u64.to_le_bytes());
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_trace_event() {
+    if !trace::ENABLED {
+        return 1;
+    }
+    crate::trace_event!(SchedWake, 0x5eed, 7);
+    let records = trace::records();
+    check!(records
+        .iter()
+        .any(|record| record.event == Event::SchedWake as u16 && record.args == [0x5eed, 7]));
+    check!(records.windows(2).all(|pair| pair[0].tsc <= pair[1].tsc));
+    all_good!()
+}
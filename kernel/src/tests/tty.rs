@@ -0,0 +1,87 @@
+use crate::error::Error;
+use crate::tty::{self, Echo, Mode, INTERRUPTS};
+use esqtest::*;
+
+/// Reads without waiting
+fn read(len: usize) -> Result<alloc::vec::Vec<u8>, Error> {
+    let mut buf = alloc::vec![0; len];
+    tty::read(&mut buf, true).map(|read| buf[..read].to_vec())
+}
+
+#[esqtest::test]
+pub fn test_tty_canonical() {
+    let previous = tty::set_mode(Mode::Canonical);
+    check_eq!(tty::input('h'), Echo::Char('h'));
+    check_eq!(tty::input('i'), Echo::Char('i'));
+    // Nothing is delivered before enter
+    check_eq!(read(16), Err(Error::TryAgain));
+    check_eq!(tty::input('\x7F'), Echo::Erase(1));
+    check_eq!(tty::input('ä'), Echo::Char('ä'));
+    check_eq!(tty::input('\x1B'), Echo::Nothing);
+    // Ctrl-U erases characters, not bytes
+    check_eq!(tty::input('\x15'), Echo::Erase(2));
+    check_eq!(tty::input('\x08'), Echo::Nothing);
+    for c in "ok".chars() {
+        check_eq!(tty::input(c), Echo::Char(c));
+    }
+    check_eq!(tty::input('\r'), Echo::NewLine);
+    check_eq!(tty::input('\n'), Echo::Nothing);
+    check_eq!(tty::input('\n'), Echo::NewLine);
+    // A line is read in parts if the buffer is short, but never together with the next one
+    check_eq!(read(1), Ok(b"o".to_vec()));
+    check_eq!(read(16), Ok(b"k\n".to_vec()));
+    check_eq!(read(16), Ok(b"\n".to_vec()));
+    check_eq!(read(16), Err(Error::TryAgain));
+    check_eq!(read(0), Ok(alloc::vec::Vec::new()));
+
+    // Ctrl-C discards the line
+    let interrupts = INTERRUPTS.get();
+    check_eq!(tty::input('x'), Echo::Char('x'));
+    check_eq!(tty::input('\x03'), Echo::Interrupt);
+    check_eq!(INTERRUPTS.get(), interrupts + 1);
+    check_eq!(tty::input('\r'), Echo::NewLine);
+    check_eq!(read(16), Ok(b"\n".to_vec()));
+
+    // A line longer than the limit is cut off
+    for _ in 0..tty::MAX_LINE {
+        check_eq!(tty::input('a'), Echo::Char('a'));
+    }
+    check_eq!(tty::input('a'), Echo::Nothing);
+    check_eq!(tty::input('\r'), Echo::NewLine);
+    check_eq!(
+        read(2 * tty::MAX_LINE).map(|line| line.len()),
+        Ok(tty::MAX_LINE + 1)
+    );
+
+    tty::set_mode(previous);
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_tty_raw() {
+    let previous = tty::set_mode(Mode::Raw);
+    check_eq!(tty::mode(), Mode::Raw);
+    let interrupts = INTERRUPTS.get();
+    for c in ['a', '\x03', '\x7F', 'ä', '\r'] {
+        check_eq!(tty::input(c), Echo::Nothing);
+    }
+    check_eq!(INTERRUPTS.get(), interrupts);
+    check_eq!(read(3), Ok(alloc::vec![b'a', 3, 0x7F]));
+    check_eq!(read(16), Ok(alloc::vec![0xC3, 0xA4, b'\r']));
+    check_eq!(read(16), Err(Error::TryAgain));
+
+    // Switching discards what was not read
+    tty::input('b');
+    check_eq!(tty::set_mode(Mode::Canonical), Mode::Raw);
+    check_eq!(read(16), Err(Error::TryAgain));
+
+    check_eq!(Mode::from_abi(Mode::Raw.to_abi()), Ok(Mode::Raw));
+    check_eq!(Mode::from_abi(7), Err(Error::InvalidArgument));
+    let group = tty::foreground_group();
+    tty::set_foreground_group(42);
+    check_eq!(tty::foreground_group(), 42);
+    tty::set_foreground_group(group);
+
+    tty::set_mode(previous);
+    all_good!()
+}
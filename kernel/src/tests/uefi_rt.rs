@@ -0,0 +1,122 @@
+use crate::error::Error;
+use crate::time::rtc::{decode, HOUR_PM, STATUS_24_HOUR, STATUS_BINARY};
+use crate::time::{days_in_month, DateTime};
+use crate::uefi_rt::{self, check_status, encode_name, EfiStatus, Variable, MAX_NAME_LENGTH};
+use esqtest::*;
+
+fn date(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+    DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+}
+
+#[esqtest::test]
+pub fn test_date_time() {
+    check_eq!(date(1970, 1, 1, 0, 0, 0).unix_seconds(), 0);
+    check_eq!(date(2000, 3, 1, 0, 0, 0).unix_seconds(), 951868800);
+    check_eq!(date(2024, 2, 29, 12, 34, 56).unix_seconds(), 1709210096);
+    check_eq!(date(1969, 12, 31, 23, 59, 59).unix_seconds(), -1);
+    check_eq!(
+        DateTime::from_unix_seconds(1709210096),
+        date(2024, 2, 29, 12, 34, 56)
+    );
+    check_eq!(
+        DateTime::from_unix_seconds(-1),
+        date(1969, 12, 31, 23, 59, 59)
+    );
+    check_eq!(
+        DateTime::from_unix_seconds(951868800),
+        date(2000, 3, 1, 0, 0, 0)
+    );
+
+    check_eq!(days_in_month(2024, 2), 29);
+    check_eq!(days_in_month(2100, 2), 28);
+    check_eq!(days_in_month(2000, 2), 29);
+    check_eq!(days_in_month(2023, 13), 0);
+    check!(date(2024, 2, 29, 0, 0, 0).is_valid());
+    check!(!date(2023, 2, 29, 0, 0, 0).is_valid());
+    check!(!date(2023, 0, 1, 0, 0, 0).is_valid());
+    check!(!date(2023, 1, 1, 24, 0, 0).is_valid());
+
+    check_eq!(
+        alloc::format!("{}", date(2024, 2, 9, 1, 2, 3)),
+        "2024-02-09 01:02:03"
+    );
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_rtc_decode() {
+    // Seconds, minutes, hours, day, month, year
+    check_eq!(
+        decode([0x56, 0x34, 0x12, 0x29, 0x02, 0x24], STATUS_24_HOUR),
+        Some(date(2024, 2, 29, 12, 34, 56))
+    );
+    check_eq!(
+        decode([56, 34, 12, 29, 2, 24], STATUS_24_HOUR | STATUS_BINARY),
+        Some(date(2024, 2, 29, 12, 34, 56))
+    );
+    // 12 hour mode: 12 AM is midnight, 12 PM is noon
+    check_eq!(
+        decode([0, 0, 0x12, 1, 1, 0x23], 0).map(|time| time.hour),
+        Some(0)
+    );
+    check_eq!(
+        decode([0, 0, 0x12 | HOUR_PM, 1, 1, 0x23], 0).map(|time| time.hour),
+        Some(12)
+    );
+    check_eq!(
+        decode([0, 0, 0x07 | HOUR_PM, 1, 1, 0x23], 0).map(|time| time.hour),
+        Some(19)
+    );
+    check_eq!(decode([0, 0, 0, 0x31, 0x04, 0x23], STATUS_24_HOUR), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_uefi_rt_status() {
+    check_eq!(check_status(EfiStatus::Success), Ok(()));
+    // Warnings do not have the error bit set
+    check_eq!(check_status(4), Ok(()));
+    check_eq!(
+        check_status(EfiStatus::NotFound),
+        Err(Error::NoSuchFileOrDirectory)
+    );
+    check_eq!(
+        check_status(EfiStatus::BufferTooSmall),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(check_status(EfiStatus::DeviceError), Err(Error::IOError));
+
+    let name = encode_name("Abc").unwrap();
+    check_eq!(&name[..4], &[b'A' as u16, b'b' as u16, b'c' as u16, 0]);
+    check!(encode_name(Variable::LastBootFailed.name()).is_some());
+    check!(encode_name(&"x".repeat(MAX_NAME_LENGTH - 1)).is_some());
+    check!(encode_name(&"x".repeat(MAX_NAME_LENGTH)).is_none());
+    check!(encode_name("\u{1F600}").is_none());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_uefi_rt_get_time() {
+    if !uefi_rt::is_available() {
+        check_eq!(uefi_rt::get_time(), Err(Error::NoSuchDevice));
+        let mut buffer = [0];
+        check_eq!(
+            uefi_rt::get_variable(Variable::LastBootFailed, &mut buffer),
+            Err(Error::NoSuchDevice)
+        );
+        all_good!()
+    }
+    check!(uefi_rt::get_time().map_or(false, |time| time.is_valid()));
+
+    all_good!()
+}
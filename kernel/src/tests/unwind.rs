@@ -0,0 +1,200 @@
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::arch::backtrace;
+use crate::arch::unwind::cfi::{EhFrame, Row, Rule, RBP, RSP};
+use crate::arch::unwind::{self, Registers};
+use esqtest::*;
+
+/// Where `EH_FRAME` pretends to be
+const BASE: u64 = 0x1000;
+/// The function `EH_FRAME` describes
+const FUNCTION: u64 = 0x2000;
+const FUNCTION_LEN: u64 = 0x20;
+/// The offset of the FDE
+const FDE: usize = 24;
+
+/// A CIE like the ones LLVM emits and the FDE of a function that pushes `rbp`, makes it the
+/// frame pointer and switches back to `rsp` for a moment in between
+#[rustfmt::skip]
+const EH_FRAME: [u8; 60] = [
+    // CIE: Length, id, version, "zR", code and data alignment, return register
+    0x14, 0, 0, 0, 0, 0, 0, 0, 1, b'z', b'R', 0, 1, 0x78, 16,
+    // Augmentation data: FDE addresses are absolute 32 bit values
+    1, 0x03,
+    // def_cfa rsp+8, offset ra at cfa-8, nop, nop
+    0x0C, 7, 8, 0x90, 1, 0, 0,
+    // FDE: Length, CIE pointer, start, length, no augmentation data
+    0x1C, 0, 0, 0, 0x1C, 0, 0, 0, 0x00, 0x20, 0, 0, 0x20, 0, 0, 0, 0,
+    // advance 1, def_cfa_offset 16, offset rbp at cfa-16
+    0x41, 0x0E, 16, 0x86, 2,
+    // advance 3, def_cfa_register rbp
+    0x43, 0x0D, 6,
+    // advance 10, remember_state, def_cfa rsp+8, advance 1, restore_state
+    0x4A, 0x0A, 0x0C, 7, 8, 0x41, 0x0B,
+    // Terminator
+    0, 0, 0, 0,
+];
+
+/// The CFA register and offset and the rules of `rbp` and the return address at `pc`
+fn row(pc: u64) -> Option<(u16, i64, Rule, Rule)> {
+    let fde = EhFrame::new(&EH_FRAME, BASE).find_fde(pc)?;
+    let Row {
+        cfa_register,
+        cfa_offset,
+        rbp,
+        return_address,
+        ..
+    } = fde.row(pc)?;
+    Some((cfa_register, cfa_offset, rbp, return_address))
+}
+
+#[esqtest::test]
+pub fn test_cfi_rows() {
+    let eh_frame = EhFrame::new(&EH_FRAME, BASE);
+    let fde = match eh_frame.fde_at(FDE) {
+        Some(fde) => fde,
+        None => return 1,
+    };
+    check_eq!(fde.start, FUNCTION);
+    check_eq!(fde.len, FUNCTION_LEN);
+    check_eq!(fde.cie.data_alignment, -8);
+    check!(eh_frame.fde_at_address(BASE + FDE as u64).is_some());
+    // A CIE is no FDE
+    check!(eh_frame.fde_at(0).is_none());
+
+    let ra = Rule::Offset(-8);
+    let pushed = Rule::Offset(-16);
+    check_eq!(row(FUNCTION), Some((RSP, 8, Rule::SameValue, ra)));
+    check_eq!(row(FUNCTION + 1), Some((RSP, 16, pushed, ra)));
+    check_eq!(row(FUNCTION + 3), Some((RSP, 16, pushed, ra)));
+    check_eq!(row(FUNCTION + 4), Some((RBP, 16, pushed, ra)));
+    check_eq!(row(FUNCTION + 0xE), Some((RSP, 8, pushed, ra)));
+    // restore_state brings back the CFA as well
+    check_eq!(row(FUNCTION + 0xF), Some((RBP, 16, pushed, ra)));
+    check_eq!(
+        row(FUNCTION + FUNCTION_LEN - 1),
+        Some((RBP, 16, pushed, ra))
+    );
+    check_eq!(row(FUNCTION + FUNCTION_LEN), None);
+    check_eq!(row(FUNCTION - 1), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_cfi_malformed() {
+    // Cut off anywhere, the tables end early instead of panicking
+    for len in 0..EH_FRAME.len() - 4 {
+        let eh_frame = EhFrame::new(&EH_FRAME[..len], BASE);
+        check!(eh_frame
+            .find_fde(FUNCTION + 1)
+            .and_then(|fde| fde.row(FUNCTION + 1))
+            .is_none());
+    }
+
+    // A CIE pointer that points before the section
+    let mut broken = EH_FRAME;
+    broken[FDE + 4] = 0xFF;
+    check!(EhFrame::new(&broken, BASE).fde_at(FDE).is_none());
+
+    // More restore_state than remember_state
+    let mut broken = EH_FRAME;
+    broken[FDE + 0x1A] = 0x0B;
+    check!(EhFrame::new(&broken, BASE)
+        .fde_at(FDE)
+        .and_then(|fde| fde.row(FUNCTION + FUNCTION_LEN - 1))
+        .is_none());
+
+    // Garbage, which must only not panic
+    let mut seed = 0x2545_F491_4F6C_DD1Du64;
+    for _ in 0..0x400 {
+        let mut garbage = EH_FRAME;
+        for byte in garbage.iter_mut().skip(4) {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            *byte = seed as u8;
+        }
+        let eh_frame = EhFrame::new(&garbage, BASE);
+        if let Some(fde) = eh_frame.find_fde(seed & 0xFFFF) {
+            let _ = fde.row(fde.start.wrapping_add(seed >> 48));
+        }
+    }
+
+    all_good!()
+}
+
+/// Calls `f` `depth` calls deep
+#[inline(never)]
+fn deep(depth: usize, f: &mut dyn FnMut()) {
+    if depth == 0 {
+        f();
+    } else {
+        deep(depth - 1, f);
+    }
+    // Keeps the call out of the tail position, so every level has a frame
+    compiler_fence(Ordering::SeqCst);
+}
+
+#[esqtest::test]
+pub fn test_walkers_agree() {
+    const DEPTH: usize = 12;
+    // The closure, every level of `deep` and the test
+    const FRAMES: usize = DEPTH + 3;
+
+    check!(unwind::has_tables());
+
+    let mut tables = [0; FRAMES];
+    let mut tables_len = 0;
+    let mut frame_pointers = [0; FRAMES];
+    let mut frame_pointers_len = 0;
+    let mut combined = [0; FRAMES];
+    let mut combined_len = 0;
+    let mut fallbacks = None;
+    deep(DEPTH, &mut || {
+        // What the panic handler does
+        let (rip, rsp, rbp) = (
+            backtrace::instruction_pointer(),
+            backtrace::stack_pointer(),
+            backtrace::frame_pointer(),
+        );
+        let before = unwind::FALLBACK_FRAMES.get();
+        unwind::backtrace_dwarf(rip, rsp, rbp, |address| {
+            if combined_len < FRAMES {
+                combined[combined_len] = address;
+                combined_len += 1;
+                // Only the frames of the chain have to be covered by the tables
+                if combined_len == FRAMES {
+                    fallbacks = Some(unwind::FALLBACK_FRAMES.get() - before);
+                }
+            }
+        });
+        backtrace::walk(rbp, |address| {
+            if frame_pointers_len < FRAMES {
+                frame_pointers[frame_pointers_len] = address;
+                frame_pointers_len += 1;
+            }
+        });
+        // Only the tables
+        let mut regs = Registers { rip, rsp, rbp };
+        while tables_len < FRAMES {
+            regs = match unwind::step_dwarf(regs, tables_len == 0) {
+                Some(caller) => caller,
+                None => break,
+            };
+            tables[tables_len] = regs.rip;
+            tables_len += 1;
+        }
+    });
+
+    check_eq!(tables_len, FRAMES);
+    check_eq!(frame_pointers_len, FRAMES);
+    check_eq!(combined_len, FRAMES);
+    check_eq!(tables, frame_pointers);
+    check_eq!(combined, frame_pointers);
+    // The levels of the chain return to the same place
+    check!(tables[1..=DEPTH].windows(2).all(|pair| pair[0] == pair[1]));
+    check_eq!(fallbacks, Some(0));
+
+    all_good!()
+}
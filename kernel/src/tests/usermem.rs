@@ -0,0 +1,88 @@
+use crate::error::Error;
+use crate::memory::usermem::{
+    check_range, copy_from_user, copy_to_user, read_user, read_user_c_str, strncpy_from_user,
+    user_address, write_user, USER_END,
+};
+use crate::memory::{UserVirtualAddress, VirtualAddress};
+use esqtest::*;
+
+/// Kernel memory is in the lower half as well, so it stands in for user memory
+fn user<T>(ptr: *const T) -> UserVirtualAddress {
+    user_address(ptr as u64).expect("Kernel memory is in the lower half")
+}
+
+#[esqtest::test]
+pub fn test_user_virtual_address() {
+    let lower = VirtualAddress::new(0x40_0000);
+    let higher = VirtualAddress::new(0xffff_8000_0000_0000);
+    check!(lower.is_user() && !lower.is_kernel());
+    check!(higher.is_kernel() && !higher.is_user());
+    check!(VirtualAddress::new(USER_END - 1).is_user());
+    check_eq!(
+        UserVirtualAddress::try_from(lower).map(|addr| addr.as_u64()),
+        Ok(0x40_0000)
+    );
+    check_eq!(UserVirtualAddress::try_from(higher), Err(higher));
+    check!(UserVirtualAddress::null().is_null());
+    check_eq!(
+        user_address(USER_END - 1).map(|addr| addr.checked_add(1)),
+        Ok(None)
+    );
+    check_eq!(user_address(USER_END), Err(Error::BadFault));
+    check_eq!(user_address(0xffff_8000_0000_0000), Err(Error::BadFault));
+    check_eq!(user_address(0x8000_0000_0000_0000), Err(Error::BadFault));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_usermem_range() {
+    let end = user_address(USER_END - 8).expect("The end is in the lower half");
+    check_eq!(
+        check_range(UserVirtualAddress::null(), 1),
+        Err(Error::BadFault)
+    );
+    check_eq!(check_range(user(0x1000 as *const u8), 0x1000), Ok(()));
+    check_eq!(check_range(end, 8), Ok(()));
+    check_eq!(check_range(end, 9), Err(Error::BadFault));
+    check_eq!(check_range(end, usize::MAX), Err(Error::BadFault));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_usermem_copy() {
+    let source = *b"esque\0";
+    let mut target = [0u8; 6];
+    check_eq!(copy_from_user(&mut target, user(source.as_ptr())), Ok(()));
+    check_eq!(target, source);
+    target = [0; 6];
+    check_eq!(copy_to_user(user(target.as_mut_ptr()), b"kern"), Ok(()));
+    check_eq!(&target[..4], b"kern");
+    check_eq!(
+        read_user_c_str(user(source.as_ptr()), 6).as_deref(),
+        Ok("esque")
+    );
+    check_eq!(
+        read_user_c_str(user(source.as_ptr()), 5),
+        Err(Error::FileNameTooLong)
+    );
+    let mut value = 0u32;
+    check_eq!(
+        write_user(user(core::ptr::addr_of_mut!(value)), 0xdead_beef_u32),
+        Ok(())
+    );
+    check_eq!(read_user::<u32>(user(&value)), Ok(0xdead_beef));
+    check_eq!(
+        copy_to_user(UserVirtualAddress::null(), b"x"),
+        Err(Error::BadFault)
+    );
+    // Strings that do not fit are cut off
+    let mut short = [0u8; 3];
+    check_eq!(strncpy_from_user(&mut short, user(source.as_ptr())), Ok(3));
+    check_eq!(&short, b"esq");
+    check_eq!(strncpy_from_user(&mut target, user(source.as_ptr())), Ok(5));
+    check_eq!(
+        strncpy_from_user(&mut target, UserVirtualAddress::null()),
+        Err(Error::BadFault)
+    );
+    all_good!()
+}
@@ -0,0 +1,79 @@
+use alloc::vec;
+
+use super::stat::tar;
+use crate::error::Error;
+use crate::fs::{self, tarfs::TarFs, FileType};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_nested_mounts() {
+    let outer = TarFs::new(tar(&[("a", 0, b"outer"), ("inner/hidden", 0, b"")]));
+    let inner = TarFs::new(tar(&[("b", 0, b"inner")]));
+    fs::mount("/vfstest", outer.clone()).unwrap();
+    fs::mount("/vfstest/inner", inner).unwrap();
+    check_eq!(
+        fs::mount("/vfstest/./inner/", outer).err(),
+        Some(Error::DeviceOrResourceBusy)
+    );
+
+    // The inner filesystem hides what is below its mount point
+    check_eq!(fs::read_to_end("/vfstest/inner/b").unwrap(), b"inner");
+    check_eq!(
+        fs::open("/vfstest/inner/hidden").err(),
+        Some(Error::NoSuchFileOrDirectory)
+    );
+
+    // `..` at the root of a mount leads to the directory it is mounted on
+    let outer_root = fs::stat("/vfstest").unwrap();
+    check_eq!(fs::stat("/vfstest/inner/..").unwrap(), outer_root);
+    check_eq!(fs::read_to_end("/vfstest/inner/../a").unwrap(), b"outer");
+    check_eq!(
+        fs::read_to_end("/vfstest/inner/../inner/./b").unwrap(),
+        b"inner"
+    );
+    check_eq!(
+        fs::stat("/vfstest/../../..").unwrap(),
+        fs::stat("/").unwrap()
+    );
+    check_eq!(fs::stat("/vfstest/a/..").err(), Some(Error::NotADirectory));
+    check_eq!(
+        fs::stat("/vfstest/missing/../a").err(),
+        Some(Error::NoSuchFileOrDirectory)
+    );
+    check_eq!(fs::stat("vfstest").err(), Some(Error::InvalidArgument));
+    check_eq!(fs::stat("/vfstest/inner").unwrap().ty, FileType::Directory);
+
+    fs::umount("/vfstest/inner").unwrap();
+    fs::umount("/vfstest").unwrap();
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_umount_busy() {
+    fs::mount("/umounttest", TarFs::new(tar(&[("file", 0, b"data")]))).unwrap();
+    fs::mount("/umounttest/nested", TarFs::new(tar(&[]))).unwrap();
+    let fd = fs::open_fd("/umounttest/file").unwrap();
+    let info = fs::mounts()
+        .into_iter()
+        .find(|mount| mount.path == "/umounttest")
+        .unwrap();
+    check_eq!(info.fs, "tarfs");
+    check_eq!(info.users, 1);
+
+    // Busy with another filesystem inside and with a file open
+    check_eq!(fs::umount("/umounttest"), Err(Error::DeviceOrResourceBusy));
+    fs::umount("/umounttest/nested").unwrap();
+    check_eq!(fs::umount("/umounttest"), Err(Error::DeviceOrResourceBusy));
+    let mut buf = vec![0u8; 8];
+    check_eq!(fs::read_fd(fd, &mut buf), Ok(4));
+    fs::close_fd(fd).unwrap();
+    check_eq!(fs::umount("/umounttest/nested/.."), Ok(()));
+
+    check_eq!(fs::umount("/umounttest"), Err(Error::InvalidArgument));
+    check_eq!(
+        fs::open("/umounttest/file").err(),
+        Some(Error::NoSuchFileOrDirectory)
+    );
+    check!(!fs::mounts().iter().any(|mount| mount.path == "/umounttest"));
+    all_good!()
+}
@@ -0,0 +1,287 @@
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use crate::block::BlockDevice;
+use crate::drivers::virtio::blk::{SECTOR_SIZE, VIRTIO_BLK_DISKS};
+use crate::drivers::virtio::queue::{
+    Buffer, Descriptor, DescriptorToken, UsedElement, Virtqueue, MAX_QUEUE_SIZE,
+};
+use crate::error::Error;
+use crate::memory::dma::{DmaBuffer, DmaConstraints};
+use crate::memory::{phys_to_virt, PhysicalAddress};
+use esqtest::*;
+
+/// # Fake Device
+/// The device side of a split virtqueue, working on the rings through their physical addresses
+/// like a device would. Copies what a request gives it to the buffers it may write, a loopback.
+struct FakeDevice {
+    size: u16,
+    descriptors: *const Descriptor,
+    /// flags, idx, ring[size]
+    driver: *const u16,
+    /// flags, idx, ring[size]
+    device: *mut u16,
+    /// The index in the driver ring up to which requests have been taken
+    last_available: u16,
+}
+
+impl FakeDevice {
+    fn new(queue: &Virtqueue) -> Self {
+        let ptr = |phys| phys_to_virt(PhysicalAddress::new(phys)).as_u64();
+        Self {
+            size: queue.size(),
+            descriptors: ptr(queue.descriptors_phys()) as *const Descriptor,
+            driver: ptr(queue.driver_phys()) as *const u16,
+            device: ptr(queue.device_phys()) as *mut u16,
+            last_available: 0,
+        }
+    }
+
+    /// Takes the next request the driver made available and the chain of descriptors it is
+    fn take(&mut self) -> Option<(u32, Vec<Descriptor>)> {
+        let available = unsafe { self.driver.add(1).read_volatile() };
+        if available == self.last_available {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let head = unsafe {
+            self.driver
+                .add(2 + (self.last_available % self.size) as usize)
+                .read_volatile()
+        };
+        self.last_available = self.last_available.wrapping_add(1);
+        let mut chain = Vec::new();
+        let mut idx = head;
+        loop {
+            let descriptor = unsafe { self.descriptors.add(idx as usize).read_volatile() };
+            chain.push(descriptor);
+            if !descriptor.has_next() {
+                break;
+            }
+            idx = descriptor.next;
+        }
+        Some((head as u32, chain))
+    }
+
+    /// Copies the readable buffers of `chain` to its writable ones and completes it
+    fn complete(&mut self, head: u32, chain: &[Descriptor]) {
+        let mut data = Vec::new();
+        for descriptor in chain.iter().filter(|d| !d.device_writable()) {
+            let src = phys_to_virt(PhysicalAddress::new(descriptor.addr)).as_u64() as *const u8;
+            data.extend_from_slice(unsafe {
+                core::slice::from_raw_parts(src, descriptor.len as usize)
+            });
+        }
+        let mut written = 0;
+        for descriptor in chain.iter().filter(|d| d.device_writable()) {
+            let len = (descriptor.len as usize).min(data.len() - written);
+            let dst = phys_to_virt(PhysicalAddress::new(descriptor.addr)).as_u64() as *mut u8;
+            unsafe { core::ptr::copy_nonoverlapping(data[written..].as_ptr(), dst, len) };
+            written += len;
+        }
+        unsafe {
+            let used = self.device.add(1).read_volatile();
+            let ring = self.device.add(2) as *mut UsedElement;
+            ring.add((used % self.size) as usize)
+                .write_volatile(UsedElement {
+                    id: head,
+                    len: written as u32,
+                });
+            fence(Ordering::SeqCst);
+            self.device.add(1).write_volatile(used.wrapping_add(1));
+        }
+    }
+
+    /// Takes and completes every request the driver made available, in order
+    fn run(&mut self) {
+        while let Some((head, chain)) = self.take() {
+            self.complete(head, &chain);
+        }
+    }
+}
+
+fn new_queue(size: u16) -> Virtqueue {
+    let (layout, _) = Virtqueue::layout(size);
+    let rings = DmaBuffer::with_layout(&layout, DmaConstraints::ANY).unwrap();
+    Virtqueue::new(size, rings).unwrap()
+}
+
+/// A readable buffer holding `text` at `offset` of `data` and a writable one of `len` bytes
+/// after it
+fn request(data: &mut DmaBuffer, offset: usize, text: &[u8], len: usize) -> [Buffer; 2] {
+    data.as_mut_slice()[offset..offset + text.len()].copy_from_slice(text);
+    data.as_mut_slice()[offset + text.len()..offset + text.len() + len].fill(0);
+    let phys = data.phys().as_u64() + offset as u64;
+    [
+        Buffer {
+            phys,
+            len: text.len() as u32,
+            device_writable: false,
+        },
+        Buffer {
+            phys: phys + text.len() as u64,
+            len: len as u32,
+            device_writable: true,
+        },
+    ]
+}
+
+#[esqtest::test]
+pub fn test_virtqueue_loopback() {
+    let mut queue = new_queue(8);
+    let mut device = FakeDevice::new(&queue);
+    let mut data = DmaBuffer::new_zeroed(256, DmaConstraints::ANY).unwrap();
+
+    // A chain of three: Two readable buffers and a writable one
+    let mut chain = request(&mut data, 0, b"ping", 16).to_vec();
+    chain.insert(1, request(&mut data, 64, b"-pong", 0)[0]);
+    let token = queue.add_buffer(&chain).unwrap();
+    check_eq!(queue.free_descriptors(), 5);
+    check_eq!(queue.poll_used(), None);
+
+    device.run();
+    check_eq!(queue.poll_used(), Some((token, 9)));
+    check_eq!(&data.as_slice()[4..13], b"ping-pong");
+    check_eq!(queue.free_descriptors(), 8);
+    check_eq!(queue.poll_used(), None);
+
+    // Not enough descriptors left for the chain
+    let long = [chain[0]; 9];
+    check_eq!(queue.add_buffer(&long), Err(Error::NoBufferSpaceAvailable));
+    check_eq!(queue.add_buffer(&[]), Err(Error::InvalidArgument));
+
+    let (layout, _) = Virtqueue::layout(8);
+    let rings = DmaBuffer::with_layout(&layout, DmaConstraints::ANY).unwrap();
+    check!(Virtqueue::new(6, rings).is_err());
+    let rings = DmaBuffer::new_zeroed(16, DmaConstraints::ANY).unwrap();
+    check!(Virtqueue::new(8, rings).is_err());
+    check!(MAX_QUEUE_SIZE.is_power_of_two());
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_virtqueue_out_of_order() {
+    let mut queue = new_queue(8);
+    let mut device = FakeDevice::new(&queue);
+    let mut data = DmaBuffer::new_zeroed(256, DmaConstraints::ANY).unwrap();
+
+    let texts: [&[u8]; 3] = [b"one", b"two", b"three"];
+    let mut tokens = Vec::new();
+    for (idx, text) in texts.iter().enumerate() {
+        let chain = request(&mut data, idx * 64, text, 8);
+        tokens.push(queue.add_buffer(&chain).unwrap());
+    }
+    let mut taken: Vec<_> = core::iter::from_fn(|| device.take()).collect();
+    check_eq!(taken.len(), 3);
+
+    // The device finishes the last request first
+    let (head, chain) = taken.remove(2);
+    device.complete(head, &chain);
+    check_eq!(queue.poll_used(), Some((tokens[2], 5)));
+    check_eq!(queue.free_descriptors(), 4);
+    let (head, chain) = taken.remove(0);
+    device.complete(head, &chain);
+    let (head, chain) = taken.remove(0);
+    device.complete(head, &chain);
+    check_eq!(queue.poll_used(), Some((tokens[0], 3)));
+    check_eq!(queue.poll_used(), Some((tokens[1], 3)));
+    check_eq!(&data.as_slice()[128 + 5..128 + 10], b"three");
+    check_eq!(queue.free_descriptors(), 8);
+
+    // The descriptors freed out of order are handed out again
+    for _ in 0..4 {
+        let chain = request(&mut data, 192, b"again", 8);
+        check!(queue.add_buffer(&chain).is_ok());
+    }
+    check_eq!(queue.free_descriptors(), 0);
+    device.run();
+    check_eq!(core::iter::from_fn(|| queue.poll_used()).count(), 4);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_virtqueue_wrap_around() {
+    let mut queue = new_queue(4);
+    let mut device = FakeDevice::new(&queue);
+    let mut data = DmaBuffer::new_zeroed(64, DmaConstraints::ANY).unwrap();
+
+    // Past the end of the rings many times, and past the 16 bit indices once
+    for round in 0..u16::MAX as u32 + 8 {
+        let text = round.to_le_bytes();
+        let chain = request(&mut data, 0, &text, 4);
+        let token = queue.add_buffer(&chain).unwrap();
+        device.run();
+        check_eq!(queue.poll_used(), Some((token, 4)));
+        check_eq!(&data.as_slice()[4..8], &text);
+    }
+    check_eq!(queue.free_descriptors(), 4);
+
+    all_good!()
+}
+
+static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+fn count_completion(_: DescriptorToken, len: u32) {
+    COMPLETED.fetch_add(len as usize, Ordering::Relaxed);
+}
+
+#[esqtest::test]
+pub fn test_virtqueue_completion() {
+    let mut queue = new_queue(8);
+    let mut device = FakeDevice::new(&queue);
+    let mut data = DmaBuffer::new_zeroed(128, DmaConstraints::ANY).unwrap();
+
+    let chain = request(&mut data, 0, b"polled", 8);
+    queue.add_buffer(&chain).unwrap();
+    device.run();
+    // Without a callback, completions are left for polling
+    check_eq!(queue.handle_interrupt(), 0);
+    check!(queue.poll_used().is_some());
+
+    COMPLETED.store(0, Ordering::Relaxed);
+    queue.set_completion(count_completion);
+    for offset in [0, 32] {
+        let chain = request(&mut data, offset, b"irq", 4);
+        queue.add_buffer(&chain).unwrap();
+    }
+    device.run();
+    check_eq!(queue.handle_interrupt(), 2);
+    check_eq!(COMPLETED.load(Ordering::Relaxed), 6);
+    check_eq!(queue.poll_used(), None);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_virtio_blk_read() {
+    let disk = match VIRTIO_BLK_DISKS.lock().first().cloned() {
+        Some(disk) => disk,
+        // Nothing to test without a drive
+        None => {
+            all_good!()
+        }
+    };
+    let mut sectors = vec![0u8; 3 * SECTOR_SIZE];
+    check!(disk
+        .read_blocks(0, &mut sectors[..disk.block_size()])
+        .is_ok());
+    check_eq!(sectors[510], 0x55);
+    check_eq!(sectors[511], 0xAA);
+
+    // A buffer that is not sector aligned is staged, and reads the same
+    let mut unaligned = vec![0u8; 3 * SECTOR_SIZE + 1];
+    check!(disk
+        .read_blocks(0, &mut unaligned[1..1 + disk.block_size()])
+        .is_ok());
+    check_eq!(
+        &unaligned[1..1 + disk.block_size()],
+        &sectors[..disk.block_size()]
+    );
+    check!(disk
+        .read_blocks(disk.block_count(), &mut sectors[..disk.block_size()])
+        .is_err());
+
+    all_good!()
+}
@@ -0,0 +1,226 @@
+use alloc::vec::Vec;
+
+use super::user;
+use crate::error::Error;
+use crate::fs;
+use crate::ipc::shm::SharedMemory;
+use crate::memory::paging::page_frame_allocator::PAGE_FRAME_ALLOCATOR;
+use crate::memory::usermem::{copy_from_user, read_user, write_user};
+use crate::memory::vmm::ADDRESS_SPACE;
+use crate::memory::UserVirtualAddress;
+use crate::scheduler::{self, TaskId};
+use crate::syscall::mman::{
+    sys_mincore, sys_mmap, sys_munmap, MAP_ANONYMOUS, MAP_PRIVATE, MAP_SHARED, PROT_NONE,
+    PROT_READ, PROT_WRITE,
+};
+use crate::syscall::vmstat::{sys_vmstat, vmstat, VmStat};
+use esqtest::*;
+
+const PAGES: u64 = 4;
+const SIZE: u64 = PAGES * bks::PAGE_SIZE;
+
+fn stat() -> VmStat {
+    ADDRESS_SPACE.lock().stats().into()
+}
+
+fn free_frames() -> u64 {
+    let free = unsafe {
+        PAGE_FRAME_ALLOCATOR
+            .lock()
+            .assume_init_ref()
+            .get_free_memory()
+    };
+    free as u64 / bks::PAGE_SIZE
+}
+
+#[esqtest::test]
+pub fn test_vmstat_counts() {
+    let fd = match SharedMemory::new(SIZE).and_then(fs::shared_memory_fd) {
+        Ok(fd) => fd,
+        Err(_) => return 1,
+    };
+    let before = stat();
+    let readable = sys_mmap(
+        UserVirtualAddress::null(),
+        SIZE,
+        PROT_READ,
+        MAP_SHARED,
+        fd,
+        0,
+    );
+    let hidden = sys_mmap(
+        UserVirtualAddress::null(),
+        SIZE,
+        PROT_NONE,
+        MAP_SHARED,
+        fd,
+        0,
+    );
+    let (readable, hidden) = match (readable, hidden) {
+        (Ok(readable), Ok(hidden)) => (readable, hidden),
+        _ => return 1,
+    };
+    let mapped = stat();
+    check_eq!(mapped.mapped_pages, before.mapped_pages + 2 * PAGES);
+    // A PROT_NONE mapping has no frames
+    check_eq!(mapped.resident_pages, before.resident_pages + PAGES);
+    check_eq!(mapped.regions, before.regions + 2);
+    check_eq!(mapped.cow_shared_pages, 0);
+    check_eq!(mapped.lazy_pages, 0);
+
+    // Splitting a region counts the pages on both sides once
+    check_eq!(
+        sys_munmap(user(readable + bks::PAGE_SIZE), bks::PAGE_SIZE),
+        Ok(0)
+    );
+    let split = stat();
+    check_eq!(split.mapped_pages, mapped.mapped_pages - 1);
+    check_eq!(split.resident_pages, mapped.resident_pages - 1);
+    check_eq!(split.regions, mapped.regions + 1);
+
+    check_eq!(sys_munmap(user(readable), SIZE), Ok(0));
+    check_eq!(sys_munmap(user(hidden), SIZE), Ok(0));
+    check_eq!(stat(), before);
+    check_eq!(fs::close_fd(fd), Ok(()));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_vmstat_task() {
+    check_eq!(vmstat(scheduler::current()), Ok(stat()));
+    check_eq!(vmstat(TaskId::new(u64::MAX)), Err(Error::NoSuchProcess));
+
+    let mut out = VmStat::default();
+    let ptr = user(&mut out as *mut VmStat as u64);
+    check_eq!(sys_vmstat(0, ptr), Ok(0));
+    check_eq!(read_user::<VmStat>(ptr), Ok(stat()));
+    check_eq!(sys_vmstat(u64::MAX, ptr), Err(Error::NoSuchProcess));
+    check_eq!(
+        sys_vmstat(0, UserVirtualAddress::null()),
+        Err(Error::BadFault)
+    );
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_mincore() {
+    let fd = match SharedMemory::new(SIZE).and_then(fs::shared_memory_fd) {
+        Ok(fd) => fd,
+        Err(_) => return 1,
+    };
+    let readable = sys_mmap(
+        UserVirtualAddress::null(),
+        SIZE,
+        PROT_READ,
+        MAP_SHARED,
+        fd,
+        0,
+    );
+    let hidden = sys_mmap(
+        UserVirtualAddress::null(),
+        SIZE,
+        PROT_NONE,
+        MAP_SHARED,
+        fd,
+        0,
+    );
+    let (readable, hidden) = match (readable, hidden) {
+        (Ok(readable), Ok(hidden)) => (readable, hidden),
+        _ => return 1,
+    };
+    let mut vec = [0xFFu8; PAGES as usize];
+    let vec_ptr = user(vec.as_mut_ptr() as u64);
+    let residency = |addr: u64, len: u64| -> Option<Vec<u8>> {
+        sys_mincore(user(addr), len, vec_ptr).ok()?;
+        let mut out = alloc::vec![0u8; ((len + bks::PAGE_SIZE - 1) / bks::PAGE_SIZE) as usize];
+        copy_from_user(&mut out, vec_ptr).ok()?;
+        Some(out)
+    };
+    check_eq!(
+        residency(readable, SIZE),
+        Some(alloc::vec![1; PAGES as usize])
+    );
+    check_eq!(
+        residency(hidden, SIZE),
+        Some(alloc::vec![0; PAGES as usize])
+    );
+    // A partial page counts as a whole one
+    check_eq!(residency(readable, 1), Some(alloc::vec![1]));
+
+    check_eq!(
+        sys_mincore(user(readable + 1), bks::PAGE_SIZE, vec_ptr),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        sys_mincore(user(readable), 0, vec_ptr),
+        Err(Error::InvalidArgument)
+    );
+    check_eq!(
+        sys_munmap(user(readable + bks::PAGE_SIZE), bks::PAGE_SIZE),
+        Ok(0)
+    );
+    // The hole is not part of any mapping
+    check_eq!(
+        sys_mincore(user(readable), SIZE, vec_ptr),
+        Err(Error::OutOfMemory)
+    );
+
+    check_eq!(sys_munmap(user(readable), SIZE), Ok(0));
+    check_eq!(sys_munmap(user(hidden), SIZE), Ok(0));
+    check_eq!(fs::close_fd(fd), Ok(()));
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_zero_page() {
+    const LAZY_SIZE: u64 = 64 * 0x10_0000;
+    const LAZY_PAGES: u64 = LAZY_SIZE / bks::PAGE_SIZE;
+    let before = stat();
+    let frames = free_frames();
+    let addr = match sys_mmap(
+        UserVirtualAddress::null(),
+        LAZY_SIZE,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        u64::MAX,
+        0,
+    ) {
+        Ok(addr) => addr,
+        Err(_) => return 1,
+    };
+    check_eq!(stat().lazy_pages, before.lazy_pages + LAZY_PAGES);
+    check_eq!(stat().resident_pages, before.resident_pages);
+
+    // Reading everything maps the zero frame, only the page tables take frames
+    let pages = (0..LAZY_PAGES).map(|page| addr + page * bks::PAGE_SIZE);
+    check!(pages
+        .clone()
+        .all(|page| read_user::<u64>(user(page)) == Ok(0)));
+    let read = stat();
+    check_eq!(read.lazy_pages, before.lazy_pages);
+    check_eq!(read.zero_pages, before.zero_pages + LAZY_PAGES);
+    check_eq!(read.resident_pages, before.resident_pages);
+    let tables = LAZY_SIZE / 0x20_0000 + 2;
+    check!(frames.saturating_sub(free_frames()) <= tables + 1);
+
+    // Writing every other page gives only those a frame of their own
+    let frames = free_frames();
+    for page in pages.clone().step_by(2) {
+        check_eq!(write_user(user(page + 8), page), Ok(()));
+    }
+    let written = stat();
+    check_eq!(written.zero_pages, before.zero_pages + LAZY_PAGES / 2);
+    check_eq!(
+        written.resident_pages,
+        before.resident_pages + LAZY_PAGES / 2
+    );
+    check!(frames.saturating_sub(free_frames()) >= LAZY_PAGES / 2);
+    check_eq!(read_user::<u64>(user(addr + 8)), Ok(addr));
+    check_eq!(read_user::<u64>(user(addr + bks::PAGE_SIZE + 8)), Ok(0));
+
+    let frames = free_frames();
+    check_eq!(sys_munmap(user(addr), LAZY_SIZE), Ok(0));
+    check_eq!(stat(), before);
+    check!(free_frames().saturating_sub(frames) >= LAZY_PAGES / 2);
+    all_good!()
+}
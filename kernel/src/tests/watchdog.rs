@@ -0,0 +1,63 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::backtrace;
+use crate::scheduler::sync::{held_locks, IrqSpinLock};
+use crate::smp::current_cpu;
+use crate::{time, watchdog};
+use esqtest::*;
+
+#[esqtest::test]
+pub fn test_backtrace() {
+    let mut frames = 0;
+    let mut zero = false;
+    backtrace::walk(backtrace::frame_pointer(), |address| {
+        zero |= address == 0;
+        frames += 1;
+    });
+    check!(frames > 0);
+    check!(!zero);
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_held_locks() {
+    static LOCK: IrqSpinLock<()> = IrqSpinLock::new(());
+    let address = &LOCK as *const _ as usize;
+    {
+        let _guard = LOCK.lock();
+        let held = held_locks(current_cpu()).any(|(lock, _)| lock == address);
+        // Only debug builds track locks
+        check_eq!(held, cfg!(debug_assertions));
+    }
+    check!(!held_locks(current_cpu()).any(|(lock, _)| lock == address));
+
+    all_good!()
+}
+
+#[esqtest::test]
+pub fn test_deadline() {
+    static EXPIRED: AtomicBool = AtomicBool::new(false);
+    fn expire() {
+        EXPIRED.store(true, Ordering::Release);
+    }
+
+    // A cleared deadline never runs
+    watchdog::set_deadline(0, expire);
+    watchdog::clear_deadline();
+    let start = time::now_ms();
+    while time::now_ms() - start < 50 {
+        comasm::pause();
+    }
+    check!(!EXPIRED.load(Ordering::Acquire));
+
+    watchdog::set_deadline(20, expire);
+    let start = time::now_ms();
+    while !EXPIRED.load(Ordering::Acquire) && time::now_ms() - start < 1000 {
+        comasm::pause();
+    }
+    check!(EXPIRED.load(Ordering::Acquire));
+    check!(time::now_ms() - start >= 20);
+
+    all_good!()
+}
@@ -0,0 +1,201 @@
+//! # Clock
+//! The monotonic clock behind `uptime_ns()`. Counting timer interrupts loses time whenever
+//! interrupts are disabled for longer than a tick, so once the TSC is calibrated the time is
+//! read from it instead and the tick only drives scheduling and the timers. Until then, or if
+//! it cannot be calibrated, the clock counts the ticks.
+//!
+//! The time is `base_ns + (tsc - base_tsc) * mult >> SHIFT`. Changing the frequency with
+//! `adjust_freq_ppb()` first moves the base to the current time, so the clock changes its rate
+//! but never jumps. Readers see the three values through a sequence count and retry if they
+//! were written in the meantime.
+//!
+//! The TSCs of the CPUs need not agree: every CPU corrects its own by the offset to the TSC of
+//! the bootstrap processor it measured when it came online, see `measure_tsc_offset()`. What
+//! error remains is hidden by never returning less than was returned before, on any CPU.
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, AtomicI64, AtomicU64, Ordering};
+
+use crate::arch::interrupts::without_interrupts;
+use crate::arch::tsc;
+use crate::error::{Error, Result};
+use crate::scheduler::IrqSpinLock;
+use crate::smp::{self, current_cpu, MAX_CPUS};
+
+/// `mult` is in nanoseconds per cycle, shifted by this many bits
+const SHIFT: u32 = 32;
+/// The largest frequency adjustment, the limit NTP uses as well
+pub const MAX_ADJUST_PPB: i64 = 500_000;
+const PPB: i128 = 1_000_000_000;
+/// The round trips the TSC offset of a CPU is measured over, the fastest one is used
+const OFFSET_ROUNDS: usize = 8;
+
+/// The nanoseconds counted by the timer interrupt
+static TICK_NS: AtomicU64 = AtomicU64::new(0);
+/// The largest time returned so far
+static LAST_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Odd while the base is written
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+static BASE_TSC: AtomicU64 = AtomicU64::new(0);
+static BASE_NS: AtomicU64 = AtomicU64::new(0);
+/// Zero while the ticks are counted
+static MULT: AtomicU64 = AtomicU64::new(0);
+/// The `mult` of the calibrated frequency, which adjustments are relative to, and the current
+/// adjustment. Held while the base is written.
+static NOMINAL: IrqSpinLock<(u64, i64)> = crate::named_lock!("clock_nominal", (0, 0));
+
+/// What every CPU adds to its TSC to get the one of the bootstrap processor
+static TSC_OFFSETS: [AtomicI64; MAX_CPUS] = {
+    const ZERO: AtomicI64 = AtomicI64::new(0);
+    [ZERO; MAX_CPUS]
+};
+/// The TSC of the bootstrap processor, as read for a CPU measuring its offset
+static BSP_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// # Clock Source
+/// What the clock is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    Ticks,
+    Tsc,
+}
+
+/// # Source
+/// What the clock is currently read from
+pub fn source() -> ClockSource {
+    if MULT.load(Ordering::Acquire) == 0 {
+        ClockSource::Ticks
+    } else {
+        ClockSource::Tsc
+    }
+}
+
+/// # Uptime Ns
+/// The nanoseconds since boot. Never less than on any earlier call, on any CPU.
+pub fn uptime_ns() -> u64 {
+    let now = tsc_ns().unwrap_or_else(|| TICK_NS.load(Ordering::Relaxed));
+    now.max(LAST_NS.fetch_max(now, Ordering::Relaxed))
+}
+
+/// # Tick
+/// Counts the `nanos` nanoseconds between two timer interrupts, which is what the clock is
+/// until the TSC is used
+pub fn tick(nanos: u64) {
+    TICK_NS.fetch_add(nanos, Ordering::Relaxed);
+}
+
+/// The current TSC of the calling CPU, corrected to the one of the bootstrap processor
+fn corrected_tsc() -> u64 {
+    // Not moved to another CPU between reading the TSC and its offset
+    without_interrupts(|| {
+        tsc::read().wrapping_add(TSC_OFFSETS[current_cpu()].load(Ordering::Relaxed) as u64)
+    })
+}
+
+/// The time read from the TSC, `None` while the ticks are counted
+fn tsc_ns() -> Option<u64> {
+    loop {
+        let sequence = SEQUENCE.load(Ordering::Acquire);
+        if sequence & 1 == 1 {
+            spin_loop();
+            continue;
+        }
+        let base_tsc = BASE_TSC.load(Ordering::Relaxed);
+        let base_ns = BASE_NS.load(Ordering::Relaxed);
+        let mult = MULT.load(Ordering::Relaxed);
+        let tsc = corrected_tsc();
+        fence(Ordering::Acquire);
+        if SEQUENCE.load(Ordering::Relaxed) != sequence {
+            continue;
+        }
+        if mult == 0 {
+            return None;
+        }
+        // The offset of another CPU may put its TSC slightly before the base
+        let cycles = (tsc.wrapping_sub(base_tsc) as i64).max(0) as u128;
+        return Some(base_ns + ((cycles * mult as u128) >> SHIFT) as u64);
+    }
+}
+
+/// Moves the base to the current time and continues at `mult`
+fn rebase(mult: u64) {
+    let now = uptime_ns();
+    let sequence = SEQUENCE.load(Ordering::Relaxed);
+    SEQUENCE.store(sequence + 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    BASE_TSC.store(corrected_tsc(), Ordering::Relaxed);
+    BASE_NS.store(now, Ordering::Relaxed);
+    MULT.store(mult, Ordering::Relaxed);
+    SEQUENCE.store(sequence + 2, Ordering::Release);
+}
+
+/// # Use TSC
+/// Switches the clock to the TSC, which ticks at `khz` kHz, continuing from the time counted
+/// so far
+pub fn use_tsc(khz: u64) {
+    if khz == 0 {
+        return;
+    }
+    let mult = ((1_000_000u128 << SHIFT) / khz as u128) as u64;
+    let mut nominal = NOMINAL.lock();
+    *nominal = (mult, 0);
+    rebase(mult);
+}
+
+/// # Adjust Frequency
+/// Runs the clock `ppb` parts per billion faster than the TSC was calibrated to, or slower if
+/// negative, replacing the previous adjustment. Meant for disciplining the clock against an
+/// outside reference later on.
+///
+/// ## Returns
+/// - Error::InvalidArgument = `ppb` is beyond `MAX_ADJUST_PPB`
+/// - Error::NoSuchDevice = The clock counts ticks, whose length is fixed
+pub fn adjust_freq_ppb(ppb: i64) -> Result<()> {
+    if !(-MAX_ADJUST_PPB..=MAX_ADJUST_PPB).contains(&ppb) {
+        return Err(Error::InvalidArgument);
+    }
+    let mut nominal = NOMINAL.lock();
+    if nominal.0 == 0 {
+        return Err(Error::NoSuchDevice);
+    }
+    nominal.1 = ppb;
+    rebase((nominal.0 as i128 * (PPB + ppb as i128) / PPB) as u64);
+    Ok(())
+}
+
+/// # Frequency Adjustment
+/// The adjustment last made with `adjust_freq_ppb()`, in parts per billion
+pub fn freq_adjustment_ppb() -> i64 {
+    NOMINAL.lock().1
+}
+
+/// # TSC Offset
+/// What the CPU `cpu` adds to its TSC to get the one of the bootstrap processor
+pub fn tsc_offset(cpu: usize) -> i64 {
+    TSC_OFFSETS[cpu].load(Ordering::Relaxed)
+}
+
+/// # Measure TSC Offset
+/// Measures the offset of the TSC of the calling CPU to the one of the bootstrap processor, by
+/// asking it for its TSC and assuming it read it halfway through the round trip. Called by
+/// every CPU as it comes online, with interrupts enabled on the bootstrap processor.
+///
+/// ## Returns
+/// - i64 = The offset, zero on the bootstrap processor itself
+pub fn measure_tsc_offset() -> i64 {
+    let cpu = current_cpu();
+    if cpu == 0 {
+        return 0;
+    }
+    let mut fastest = (u64::MAX, 0);
+    for _ in 0..OFFSET_ROUNDS {
+        let start = tsc::read();
+        smp::call_on(0, |_| BSP_TSC.store(tsc::read(), Ordering::Relaxed)).wait();
+        let end = tsc::read();
+        let middle = start + end.wrapping_sub(start) / 2;
+        let offset = BSP_TSC.load(Ordering::Relaxed).wrapping_sub(middle) as i64;
+        fastest = fastest.min((end.wrapping_sub(start), offset));
+    }
+    TSC_OFFSETS[cpu].store(fastest.1, Ordering::Relaxed);
+    fastest.1
+}
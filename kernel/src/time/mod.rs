@@ -0,0 +1,179 @@
+//! # Time
+//! The time since boot as read from the monotonic clock in `clock`, and kernel timers on top of
+//! it, driven by the timer interrupt. The timers are kept in the hierarchical timer wheel in
+//! `wheel` and their callbacks run in a task of their own, see `timer`. Sleeping and wait queue
+//! timeouts are built on the timers.
+//!
+//! The wall-clock time comes from the real-time clock in `rtc`, or from the UEFI runtime services,
+//! see `uefi_rt`.
+use core::fmt::Display;
+
+use crate::error::{Error, Result};
+use crate::scheduler::{self, WaitQueue};
+
+pub mod clock;
+pub mod rtc;
+pub mod timer;
+pub mod wheel;
+
+pub use clock::{adjust_freq_ppb, uptime_ns};
+pub use timer::{init_timers, Timer, TimerHandle};
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// The queue sleeping tasks wait on, nobody ever wakes it
+static SLEEPERS: WaitQueue = WaitQueue::new();
+
+/// # Now Ms
+/// The milliseconds since boot, see `uptime_ns()`
+#[inline]
+pub fn now_ms() -> u64 {
+    uptime_ns() / NANOS_PER_MILLI
+}
+
+/// # Tick
+/// Counts the `nanos` nanoseconds between two timer interrupts, in case the clock is not read
+/// from the TSC, and wakes the timer task if a timer expired
+///
+/// ## Notes
+/// Only called by the timer interrupt
+pub fn tick(nanos: u64) {
+    clock::tick(nanos);
+    timer::tick(now_ms());
+}
+
+/// # Sleep Ms
+/// Blocks the current task for at least `millis` milliseconds. Before the scheduler runs, the
+/// CPU halts until the time passed instead.
+pub fn sleep_ms(millis: u64) {
+    if !scheduler::is_running() {
+        let end = now_ms() + millis;
+        while now_ms() < end {
+            unsafe { comasm::halt() };
+        }
+        return;
+    }
+    SLEEPERS.wait_until_timeout(|| false, millis);
+}
+
+/// # Timespec
+/// A `struct timespec` as passed to system calls
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl Timespec {
+    /// # To Ms
+    /// The time in milliseconds, rounded up
+    ///
+    /// ## Returns
+    /// - Error::InvalidArgument = The time is negative or the nanoseconds are out of range
+    pub fn to_ms(&self) -> Result<u64> {
+        if self.tv_sec < 0 || !(0..NANOS_PER_SEC as i64).contains(&self.tv_nsec) {
+            return Err(Error::InvalidArgument);
+        }
+        let millis = (self.tv_nsec as u64 + NANOS_PER_MILLI - 1) / NANOS_PER_MILLI;
+        Ok((self.tv_sec as u64)
+            .saturating_mul(1000)
+            .saturating_add(millis))
+    }
+}
+
+/// # Date Time
+/// A date and time of day in the Gregorian calendar, in whatever time zone the clock it was read
+/// from keeps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// # Unix Seconds
+    /// The seconds since 1970-01-01 00:00:00, taking the time to be UTC
+    pub fn unix_seconds(&self) -> i64 {
+        // Counting years from March, the leap day is the last day of the year
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = (self.month as i64 + 9) % 12;
+        let day_of_year = (153 * month + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        // 719468 days lie between 0000-03-01 and 1970-01-01
+        let days = era * 146097 + day_of_era - 719468;
+        days * SECONDS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64
+    }
+
+    /// # From Unix Seconds
+    /// The UTC date and time `secs` seconds after 1970-01-01 00:00:00, the inverse of
+    /// `unix_seconds()`
+    pub fn from_unix_seconds(secs: i64) -> Self {
+        let days = secs.div_euclid(SECONDS_PER_DAY) + 719468;
+        let time = secs.rem_euclid(SECONDS_PER_DAY);
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        // Counting from March again
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = era * 400 + year_of_era + (month <= 2) as i64;
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time % 3600 / 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+pub fn is_leap_year(year: u16) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// The number of days in `month` of `year`, 0 if there is no such month
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
@@ -0,0 +1,133 @@
+//! # RTC
+//! The real-time clock of the CMOS, read through ports 0x70 and 0x71. It only knows the last two
+//! digits of the year, which are taken to be in this century, and has no notion of a time zone.
+use super::DateTime;
+use crate::arch::interrupts::without_interrupts;
+use crate::iobus::{inb, outb};
+use crate::scheduler::spin::Mutex;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// How often the clock is read before giving up on two reads that agree
+const MAX_ATTEMPTS: usize = 16;
+
+enumtastic::const_enum! {
+    pub enum RtcRegister: u8 => {
+        Seconds = 0x00,
+        Minutes = 0x02,
+        Hours = 0x04,
+        Day = 0x07,
+        Month = 0x08,
+        Year = 0x09,
+        StatusA = 0x0A,
+        StatusB = 0x0B,
+    }
+
+    impl {}
+}
+
+/// Status A: The clock is being updated, the time registers may be inconsistent
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Status B: The hours count from 0 to 23, instead of 1 to 12 with `HOUR_PM`
+pub const STATUS_24_HOUR: u8 = 1 << 1;
+/// Status B: The registers are binary, instead of BCD
+pub const STATUS_BINARY: u8 = 1 << 2;
+/// Set in the hours in 12 hour mode after noon
+pub const HOUR_PM: u8 = 1 << 7;
+
+/// The time registers, in the order `decode()` takes them
+const TIME_REGISTERS: [u8; 6] = [
+    RtcRegister::Seconds,
+    RtcRegister::Minutes,
+    RtcRegister::Hours,
+    RtcRegister::Day,
+    RtcRegister::Month,
+    RtcRegister::Year,
+];
+
+/// Held from selecting a register until it was accessed, another CPU could select another one
+/// in between
+static CMOS: Mutex<()> = Mutex::new(());
+
+/// # Read Register
+/// The value of the CMOS `register`
+pub fn read_register(register: u8) -> u8 {
+    without_interrupts(|| {
+        let _guard = CMOS.lock();
+        outb(CMOS_ADDRESS, register);
+        inb(CMOS_DATA)
+    })
+}
+
+/// # Write Register
+/// Sets the CMOS `register` to `value`. The firmware keeps its settings in the CMOS as well,
+/// only the registers nothing else uses may be written.
+pub fn write_register(register: u8, value: u8) {
+    without_interrupts(|| {
+        let _guard = CMOS.lock();
+        outb(CMOS_ADDRESS, register);
+        outb(CMOS_DATA, value);
+    })
+}
+
+fn read_raw() -> Option<[u8; 6]> {
+    if read_register(RtcRegister::StatusA) & UPDATE_IN_PROGRESS != 0 {
+        return None;
+    }
+    Some(TIME_REGISTERS.map(read_register))
+}
+
+pub fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xF)
+}
+
+/// # Decode
+/// The time in the `raw` registers, seconds to year, in the format `status_b` describes
+///
+/// ## Returns
+/// - None = The registers do not hold a valid date
+pub fn decode(raw: [u8; 6], status_b: u8) -> Option<DateTime> {
+    let convert = |value: u8| {
+        if status_b & STATUS_BINARY != 0 {
+            value
+        } else {
+            bcd_to_binary(value)
+        }
+    };
+    let mut hour = convert(raw[2] & !HOUR_PM);
+    if status_b & STATUS_24_HOUR == 0 {
+        hour %= 12;
+        if raw[2] & HOUR_PM != 0 {
+            hour += 12;
+        }
+    }
+    let time = DateTime {
+        year: 2000 + convert(raw[5]) as u16,
+        month: convert(raw[4]),
+        day: convert(raw[3]),
+        hour,
+        minute: convert(raw[1]),
+        second: convert(raw[0]),
+    };
+    time.is_valid().then(|| time)
+}
+
+/// # Read
+/// The current time of the clock. It is read until two reads in a row agree, as it may tick in
+/// between the registers.
+///
+/// ## Returns
+/// - None = The clock never settled or holds no valid date
+pub fn read() -> Option<DateTime> {
+    without_interrupts(|| {
+        let mut last = None;
+        for _ in 0..MAX_ATTEMPTS {
+            let raw = read_raw();
+            if raw.is_some() && raw == last {
+                return decode(raw?, read_register(RtcRegister::StatusB));
+            }
+            last = raw;
+        }
+        None
+    })
+}
@@ -0,0 +1,233 @@
+//! # Timers
+//! Callbacks that run after a delay, once or periodically. They run in the `timers` task rather
+//! than in the timer interrupt, so they may take blocking locks and allocate. The interrupt only
+//! wakes the task once the wheel has something to do.
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::now_ms;
+use super::wheel::{TimerWheel, WheelEntry};
+use crate::scheduler::{self, IrqSpinLock, TaskId, WaitQueue};
+
+crate::counter!(pub TIMERS_FIRED = "time.timers_fired");
+crate::counter!(pub TIMERS_CANCELLED = "time.timers_cancelled");
+
+type Callback = Box<dyn FnMut() + Send>;
+
+struct Entry {
+    expires: u64,
+    /// The period of a periodic timer
+    period: Option<u64>,
+    /// Taken while the callback runs
+    callback: Option<Callback>,
+}
+
+struct Timers {
+    wheel: TimerWheel,
+    entries: BTreeMap<u64, Entry>,
+    next_id: u64,
+    /// The timer whose callback runs right now
+    running: Option<u64>,
+}
+
+static TIMERS: IrqSpinLock<Timers> = crate::named_lock!(
+    "timers",
+    Timers {
+        wheel: TimerWheel::new(),
+        entries: BTreeMap::new(),
+        next_id: 1,
+        running: None,
+    }
+);
+
+/// The millisecond at which the wheel has something to do next, `u64::MAX` if it is empty
+static NEXT_EVENT: AtomicU64 = AtomicU64::new(u64::MAX);
+/// Set by the timer interrupt when the timer task has work to do
+static PENDING: AtomicBool = AtomicBool::new(false);
+static TIMER_QUEUE: WaitQueue = WaitQueue::new();
+/// The ID of the timer task, 0 until it is spawned
+static TIMER_TASK: AtomicU64 = AtomicU64::new(0);
+
+/// # Timer
+/// Schedules callbacks, see `TimerHandle` to cancel them
+pub struct Timer;
+
+impl Timer {
+    /// # Schedule
+    /// Runs `callback` once, `after_ms` milliseconds from now
+    pub fn schedule(after_ms: u64, callback: impl FnMut() + Send + 'static) -> TimerHandle {
+        Self::insert(after_ms, None, Box::new(callback))
+    }
+
+    /// # Schedule Periodic
+    /// Runs `callback` every `period_ms` milliseconds, at least every millisecond, until the
+    /// timer is cancelled. Periods that were missed while the callbacks fell behind are skipped.
+    pub fn schedule_periodic(
+        period_ms: u64,
+        callback: impl FnMut() + Send + 'static,
+    ) -> TimerHandle {
+        let period_ms = period_ms.max(1);
+        Self::insert(period_ms, Some(period_ms), Box::new(callback))
+    }
+
+    fn insert(after_ms: u64, period: Option<u64>, callback: Callback) -> TimerHandle {
+        let expires = now_ms().saturating_add(after_ms);
+        let mut timers = TIMERS.lock();
+        let id = timers.next_id;
+        timers.next_id += 1;
+        timers.entries.insert(
+            id,
+            Entry {
+                expires,
+                period,
+                callback: Some(callback),
+            },
+        );
+        timers.wheel.insert(id, expires);
+        update_next_event(&timers.wheel);
+        TimerHandle { id }
+    }
+}
+
+/// # Timer Handle
+/// A scheduled timer. Dropping the handle does not cancel the timer.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TimerHandle {
+    id: u64,
+}
+
+impl TimerHandle {
+    /// # Cancel
+    /// Stops the timer. If its callback is running on another task right now, this waits for it
+    /// to return, so nothing the callback uses may be gone before. A callback may cancel its
+    /// own timer.
+    ///
+    /// ## Returns
+    /// - bool = Whether a run of the callback was prevented, false if the timer already expired
+    ///   or was cancelled
+    pub fn cancel(&self) -> bool {
+        let (prevented, running) = {
+            let mut timers = TIMERS.lock();
+            let prevented = match timers.entries.remove(&self.id) {
+                // Taken callbacks are running, only a periodic one would run again
+                Some(entry) => entry.callback.is_some() || entry.period.is_some(),
+                None => false,
+            };
+            (prevented, timers.running == Some(self.id))
+        };
+        if prevented {
+            TIMERS_CANCELLED.increment();
+        }
+        // Only the timer task runs callbacks, so the scheduler is running
+        if running && TIMER_TASK.load(Ordering::Relaxed) != scheduler::current().inner() {
+            while TIMERS.lock().running == Some(self.id) {
+                scheduler::yield_now();
+                core::hint::spin_loop();
+            }
+        }
+        prevented
+    }
+
+    /// # Is Pending
+    /// Whether the callback is going to run, again for a periodic timer
+    pub fn is_pending(&self) -> bool {
+        TIMERS.lock().entries.get(&self.id).map_or(false, |entry| {
+            entry.callback.is_some() || entry.period.is_some()
+        })
+    }
+}
+
+fn update_next_event(wheel: &TimerWheel) {
+    let next = wheel.next_event().unwrap_or(u64::MAX);
+    NEXT_EVENT.store(next, Ordering::Relaxed);
+    if next <= now_ms() {
+        kick();
+    }
+}
+
+fn kick() {
+    PENDING.store(true, Ordering::Release);
+    TIMER_QUEUE.wake_one();
+}
+
+/// # Tick
+/// Wakes the timer task if the wheel has something to do at the millisecond `now`. Called from
+/// the timer interrupt.
+pub(super) fn tick(now: u64) {
+    if now >= NEXT_EVENT.load(Ordering::Relaxed) {
+        kick();
+    }
+}
+
+/// # Run Expired
+/// Runs the callbacks of every timer that expired by now, rearming the periodic ones
+fn run_expired() {
+    let mut expired: Vec<WheelEntry> = Vec::new();
+    {
+        let mut timers = TIMERS.lock();
+        timers.wheel.advance(now_ms(), &mut expired);
+        update_next_event(&timers.wheel);
+    }
+    for WheelEntry { id, expires } in expired {
+        let callback = {
+            let mut timers = TIMERS.lock();
+            let callback = match timers.entries.get_mut(&id) {
+                // Anything else was cancelled in the meantime
+                Some(entry) if entry.expires == expires => entry.callback.take(),
+                _ => None,
+            };
+            if callback.is_some() {
+                timers.running = Some(id);
+            }
+            callback
+        };
+        let mut callback = match callback {
+            Some(callback) => callback,
+            None => continue,
+        };
+        callback();
+        TIMERS_FIRED.increment();
+        let mut timers = TIMERS.lock();
+        timers.running = None;
+        let rearm = match timers.entries.get_mut(&id) {
+            Some(entry) => match entry.period {
+                Some(period) => {
+                    let now = now_ms();
+                    let missed = now.saturating_sub(expires) / period;
+                    entry.expires = expires + (missed + 1) * period;
+                    entry.callback = Some(callback);
+                    Some(entry.expires)
+                }
+                None => None,
+            },
+            // Cancelled by the callback itself
+            None => continue,
+        };
+        match rearm {
+            Some(expires) => {
+                timers.wheel.insert(id, expires);
+                update_next_event(&timers.wheel);
+            }
+            None => {
+                timers.entries.remove(&id);
+            }
+        }
+    }
+}
+
+fn timer_task() {
+    loop {
+        run_expired();
+        TIMER_QUEUE.wait_until(|| PENDING.swap(false, Ordering::Acquire));
+    }
+}
+
+/// # Init Timers
+/// Starts the task that runs the callbacks, timers scheduled before only fire from then on
+pub fn init_timers() {
+    let id: TaskId = scheduler::spawn("timers", timer_task);
+    TIMER_TASK.store(id.inner(), Ordering::Relaxed);
+    kick();
+}
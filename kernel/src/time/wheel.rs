@@ -0,0 +1,150 @@
+//! # Timer Wheel
+//! A hierarchical timing wheel with a resolution of a millisecond. Every level has `SLOTS`
+//! slots, a slot of level 0 covers a millisecond, one of level 1 the whole of level 0 and so
+//! on. A timer goes to the lowest level that reaches its expiry and moves down a level whenever
+//! the wheel below it has turned once, until it expires from level 0. Inserting and expiring a
+//! timer costs the same no matter how many are pending.
+//!
+//! The wheel only holds IDs and expiries, what a timer does is up to its user. Removing a timer
+//! is left to the user as well, by ignoring it when it expires.
+use alloc::vec::Vec;
+
+/// The bits of the expiry that select a slot of a level
+const SLOT_BITS: u32 = 6;
+/// The number of slots of every level
+pub const SLOTS: usize = 1 << SLOT_BITS;
+/// The number of levels
+pub const LEVELS: usize = 4;
+/// The delay in milliseconds the highest level reaches, about 4.6 hours. Timers that expire
+/// later are parked in its last slot and placed again once they are reached.
+pub const MAX_DELAY: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+
+/// # Wheel Entry
+/// A timer in the wheel: Its ID and the millisecond it expires at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WheelEntry {
+    pub id: u64,
+    pub expires: u64,
+}
+
+const EMPTY_SLOT: Vec<WheelEntry> = Vec::new();
+const EMPTY_LEVEL: [Vec<WheelEntry>; SLOTS] = [EMPTY_SLOT; SLOTS];
+
+pub struct TimerWheel {
+    /// The next millisecond to expire, every earlier one has been processed
+    clock: u64,
+    levels: [[Vec<WheelEntry>; SLOTS]; LEVELS],
+    len: usize,
+}
+
+impl TimerWheel {
+    /// # New
+    /// An empty wheel starting at millisecond 0
+    pub const fn new() -> Self {
+        Self {
+            clock: 0,
+            levels: [EMPTY_LEVEL; LEVELS],
+            len: 0,
+        }
+    }
+
+    /// # Clock
+    /// The next millisecond `advance()` processes
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    /// # Len
+    /// The number of timers in the wheel
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of milliseconds a slot of `level` covers, as a shift
+    fn shift(level: usize) -> u32 {
+        SLOT_BITS * level as u32
+    }
+
+    /// # Insert
+    /// Adds the timer `id` expiring at the millisecond `expires`. A timer that expired already
+    /// expires with the next `advance()`.
+    pub fn insert(&mut self, id: u64, expires: u64) {
+        let due = expires.max(self.clock);
+        let delta = due - self.clock;
+        let level = (0..LEVELS)
+            .find(|level| delta < 1 << Self::shift(level + 1))
+            .unwrap_or(LEVELS - 1);
+        let slot_of = due.min(self.clock + MAX_DELAY - 1);
+        let slot = (slot_of >> Self::shift(level)) as usize % SLOTS;
+        self.levels[level][slot].push(WheelEntry { id, expires });
+        self.len += 1;
+    }
+
+    /// # Advance
+    /// Expires every timer up to and including the millisecond `now`, appending them to
+    /// `expired` in the order they expired in
+    pub fn advance(&mut self, now: u64, expired: &mut Vec<WheelEntry>) {
+        loop {
+            // Nothing happens in between, so the clock may skip ahead
+            match self.next_event() {
+                Some(next) if next <= now => self.clock = next,
+                _ => {
+                    self.clock = self.clock.max(now + 1);
+                    return;
+                }
+            }
+            // Moves timers down from every level whose lower levels turned once, top first
+            let cascades = (1..LEVELS)
+                .take_while(|level| self.clock & ((1 << Self::shift(*level)) - 1) == 0)
+                .count();
+            for level in (1..=cascades).rev() {
+                let slot = (self.clock >> Self::shift(level)) as usize % SLOTS;
+                self.reinsert(level, slot, None);
+            }
+            let slot = self.clock as usize % SLOTS;
+            self.reinsert(0, slot, Some(&mut *expired));
+            self.clock += 1;
+        }
+    }
+
+    /// Empties a slot and inserts its timers again, those that expired go to `expired`
+    fn reinsert(&mut self, level: usize, slot: usize, mut expired: Option<&mut Vec<WheelEntry>>) {
+        let entries = core::mem::take(&mut self.levels[level][slot]);
+        self.len -= entries.len();
+        for entry in entries {
+            match expired.as_mut() {
+                Some(expired) if entry.expires <= self.clock => expired.push(entry),
+                _ => self.insert(entry.id, entry.expires),
+            }
+        }
+    }
+
+    /// # Next Event
+    /// The earliest millisecond at which `advance()` has work to do, which is when a timer
+    /// expires or when timers move down a level. `None` if the wheel is empty.
+    pub fn next_event(&self) -> Option<u64> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut next = (self.clock..self.clock + SLOTS as u64)
+            .find(|ms| !self.levels[0][*ms as usize % SLOTS].is_empty());
+        for level in 1..LEVELS {
+            let shift = Self::shift(level);
+            // The first turn of the levels below that starts at or after the clock
+            let turn = (self.clock + (1 << shift) - 1) >> shift;
+            for (slot, entries) in self.levels[level].iter().enumerate() {
+                if entries.is_empty() {
+                    continue;
+                }
+                let ahead = (slot as u64).wrapping_sub(turn) % SLOTS as u64;
+                let cascade = (turn + ahead) << shift;
+                next = Some(next.map_or(cascade, |next| next.min(cascade)));
+            }
+        }
+        next
+    }
+}
@@ -0,0 +1,297 @@
+//! # Trace
+//! Low overhead event tracing for performance work, built with the `trace` feature. Every CPU
+//! has a ring of fixed size binary records, `trace_event!` stores one and compiles to nothing
+//! without the feature. Once a ring is full, the oldest records are overwritten.
+//!
+//! `tracedump` writes the records as hex to COM1, `scripts/tracedecode.py` decodes them on the
+//! host. A record is 32 bytes, little endian: The TSC, the CPU as `u16`, the `Event` as `u16`,
+//! 4 reserved bytes and two `u64` arguments. After the records, the dump names the lock
+//! classes and waiters `LockContended` records refer to, see `lockstat`.
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::mem::size_of;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+use static_assertions::const_assert_eq;
+
+use crate::arch::tsc;
+use crate::drivers::serial::SERIAL;
+use crate::{lockstat, smp};
+
+/// Whether the kernel was built with the `trace` feature
+pub const ENABLED: bool = cfg!(feature = "trace");
+/// The records every CPU keeps
+pub const RECORDS_PER_CPU: usize = 512;
+pub const RECORD_SIZE: usize = 32;
+/// The version of the dump format, which `tracedecode.py` checks
+pub const FORMAT_VERSION: u32 = 1;
+/// What every line of a dump starts with, so it can be told apart from the log on the port
+pub const DUMP_PREFIX: &str = "esque-trace";
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// # Event
+/// What a record is about. `tracedecode.py` has a copy of the ids.
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The previous and the next task id
+    SchedSwitch = 1,
+    /// The task id and the CPU it was queued on
+    SchedWake = 2,
+    /// The faulting address and the error code
+    PageFault = 3,
+    /// The system call number and its first argument
+    SyscallEntry = 4,
+    /// The system call number and its return value
+    SyscallExit = 5,
+    /// The vector
+    IrqEntry = 6,
+    /// The vector and the cycles the handler took
+    IrqExit = 7,
+    /// The lock class in the low and the waiter in the high 32 bits, and the cycles it waited
+    LockContended = 8,
+}
+
+/// # Record
+/// One event, as it is dumped
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub tsc: u64,
+    pub cpu: u16,
+    pub event: u16,
+    _reserved: u32,
+    pub args: [u64; 2],
+}
+
+const_assert_eq!(size_of::<Record>(), RECORD_SIZE);
+
+impl Record {
+    pub fn new(tsc: u64, cpu: u16, event: Event, args: [u64; 2]) -> Self {
+        Self {
+            tsc,
+            cpu,
+            event: event as u16,
+            _reserved: 0,
+            args,
+        }
+    }
+
+    fn to_words(self) -> [u64; 4] {
+        [
+            self.tsc,
+            self.cpu as u64 | (self.event as u64) << 16,
+            self.args[0],
+            self.args[1],
+        ]
+    }
+
+    fn from_words(words: [u64; 4]) -> Self {
+        Self {
+            tsc: words[0],
+            cpu: words[1] as u16,
+            event: (words[1] >> 16) as u16,
+            _reserved: 0,
+            args: [words[2], words[3]],
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        for (chunk, word) in bytes.chunks_exact_mut(8).zip(self.to_words()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// # Slot
+/// A record in a ring. It is written like a seqlock, so a reader can tell a record that was
+/// overwritten while it copied it.
+struct Slot {
+    /// The index of the record in the slot plus one, zero while it is written
+    seq: AtomicU64,
+    words: [AtomicU64; 4],
+}
+
+impl Slot {
+    const fn new() -> Self {
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        Self {
+            seq: AtomicU64::new(0),
+            words: [ZERO; 4],
+        }
+    }
+
+    /// The record with the index `seq - 1`, if the slot still holds all of it
+    fn read(&self, seq: u64) -> Option<Record> {
+        if self.seq.load(Ordering::Acquire) != seq {
+            return None;
+        }
+        let mut words = [0; 4];
+        for (word, slot) in words.iter_mut().zip(self.words.iter()) {
+            *word = slot.load(Ordering::Relaxed);
+        }
+        fence(Ordering::Acquire);
+        (self.seq.load(Ordering::Relaxed) == seq).then(|| Record::from_words(words))
+    }
+}
+
+/// # Ring
+/// The last `N` records of a CPU. Writers take a slot each, so interrupt handlers that record
+/// while the code they interrupted does never share one.
+pub struct Ring<const N: usize> {
+    /// The number of records ever pushed
+    head: AtomicU64,
+    slots: [Slot; N],
+}
+
+impl<const N: usize> Ring<N> {
+    pub const fn new() -> Self {
+        const EMPTY: Slot = Slot::new();
+        Self {
+            head: AtomicU64::new(0),
+            slots: [EMPTY; N],
+        }
+    }
+
+    #[inline(always)]
+    pub fn push(&self, record: Record) {
+        let idx = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[(idx % N as u64) as usize];
+        slot.seq.store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (slot, word) in slot.words.iter().zip(record.to_words()) {
+            slot.store(word, Ordering::Relaxed);
+        }
+        slot.seq.store(idx + 1, Ordering::Release);
+    }
+
+    /// # Snapshot
+    /// The records in the ring, the oldest first. Records that are overwritten while they are
+    /// copied are left out.
+    pub fn snapshot(&self) -> Vec<Record> {
+        let head = self.head.load(Ordering::Acquire);
+        (head.saturating_sub(N as u64)..head)
+            .filter_map(|idx| self.slots[(idx % N as u64) as usize].read(idx + 1))
+            .collect()
+    }
+
+    /// The number of records that were overwritten
+    pub fn overwritten(&self) -> u64 {
+        self.head.load(Ordering::Relaxed).saturating_sub(N as u64)
+    }
+
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.seq.store(0, Ordering::Relaxed);
+        }
+        self.head.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(feature = "trace")]
+static RINGS: [Ring<RECORDS_PER_CPU>; smp::MAX_CPUS] = {
+    const EMPTY: Ring<RECORDS_PER_CPU> = Ring::new();
+    [EMPTY; smp::MAX_CPUS]
+};
+
+#[cfg(feature = "trace")]
+fn rings() -> &'static [Ring<RECORDS_PER_CPU>] {
+    &RINGS
+}
+
+#[cfg(not(feature = "trace"))]
+fn rings() -> &'static [Ring<RECORDS_PER_CPU>] {
+    &[]
+}
+
+/// # Trace Event
+/// Records `$event` of `Event` with up to two arguments, which are cast to `u64`. Without
+/// the `trace` feature, nothing is recorded and the arguments are not evaluated.
+/// ## Example
+/// ```
+/// trace_event!(SchedSwitch, prev.inner(), next.inner());
+/// ```
+#[macro_export]
+macro_rules! trace_event {
+    ($event:ident) => {
+        $crate::trace_event!($event, 0, 0)
+    };
+    ($event:ident, $arg:expr) => {
+        $crate::trace_event!($event, $arg, 0)
+    };
+    ($event:ident, $first:expr, $second:expr) => {{
+        #[cfg(feature = "trace")]
+        $crate::trace::record($crate::trace::Event::$event, $first as u64, $second as u64);
+    }};
+}
+
+/// # Record
+/// Pushes an event to the ring of the calling CPU, see `trace_event!`
+#[cfg(feature = "trace")]
+#[inline(always)]
+pub fn record(event: Event, first: u64, second: u64) {
+    let cpu = smp::current_cpu();
+    RINGS[cpu].push(Record::new(tsc::read(), cpu as u16, event, [first, second]));
+}
+
+/// # Records
+/// The records of every online CPU, by their TSC
+pub fn records() -> Vec<Record> {
+    let mut records: Vec<Record> = smp::online_cpus()
+        .filter_map(|cpu| rings().get(cpu))
+        .flat_map(Ring::snapshot)
+        .collect();
+    records.sort_unstable_by_key(|record| record.tsc);
+    records
+}
+
+/// The number of records that were overwritten on all CPUs
+pub fn overwritten() -> u64 {
+    rings().iter().map(Ring::overwritten).sum()
+}
+
+/// # Dump
+/// Writes every record to COM1, between a line with the format and the TSC frequency and an
+/// end line, as one line of hex each. The lock classes and waiters follow the records as
+/// `class <slot> <name>` and `site <slot> <file:line>` lines.
+///
+/// ## Returns
+/// - usize = The number of records written
+pub fn dump() -> usize {
+    let records = records();
+    let khz = tsc::khz().unwrap_or(0);
+    let _ = writeln!(
+        SERIAL.lock(),
+        "{} begin version={} records={} overwritten={} tsc_khz={}",
+        DUMP_PREFIX,
+        FORMAT_VERSION,
+        records.len(),
+        overwritten(),
+        khz
+    );
+    let mut line = [0u8; RECORD_SIZE * 2];
+    for record in records.iter() {
+        for (hex, byte) in line.chunks_exact_mut(2).zip(record.to_bytes()) {
+            hex.copy_from_slice(&[
+                HEX_DIGITS[(byte >> 4) as usize],
+                HEX_DIGITS[(byte & 0xf) as usize],
+            ]);
+        }
+        let line = core::str::from_utf8(&line).unwrap_or("");
+        let _ = writeln!(SERIAL.lock(), "{} {}", DUMP_PREFIX, line);
+    }
+    for (slot, key, _) in lockstat::classes() {
+        if let Some(key) = key {
+            let _ = writeln!(SERIAL.lock(), "{} class {} {}", DUMP_PREFIX, slot, key);
+        }
+    }
+    for (slot, key) in lockstat::sites() {
+        if let Some(key) = key {
+            let _ = writeln!(SERIAL.lock(), "{} site {} {}", DUMP_PREFIX, slot, key);
+        }
+    }
+    let _ = writeln!(SERIAL.lock(), "{} end", DUMP_PREFIX);
+    records.len()
+}
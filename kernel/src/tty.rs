@@ -0,0 +1,402 @@
+//! # TTY
+//! The line discipline between the sources of console input, the PS/2 keyboard and the virtio
+//! console, and whoever reads it: the shell, or a program through the descriptors of the console.
+//!
+//! In canonical mode, what is typed is collected into a line that backspace and Ctrl-U edit, is
+//! echoed by the source it came from, and is delivered once enter is pressed. In raw mode,
+//! every byte is delivered as it comes, without echo, and the reader does the editing.
+//!
+//! In canonical mode, Ctrl-C discards the line and sends `SIGINT` to every task of the
+//! foreground process group, Ctrl-Z sends it `SIGTSTP`. The signals are sent once the TTY is
+//! unlocked. Zero stands for no foreground group, so the group of `kmain`, which the kernel
+//! tasks are in, is never signalled.
+use crate::drivers::virtio;
+use crate::error::{Error, Result};
+use crate::framebuffer::FRAMEBUFFER_GUARD;
+use crate::memory::usermem::{self, user_address};
+use crate::scheduler::signal::{self, Signal};
+use crate::scheduler::{IrqSpinLock, TaskId, WaitQueue};
+use crate::{debug, kprint, kprintln};
+
+pub use ::abi::tty::{TtyMode, TtyRequest};
+
+/// The longest line in canonical mode, everything typed after it is dropped
+pub const MAX_LINE: usize = 256;
+/// The bytes delivered but not read yet, everything typed after them is dropped
+pub const BUFFER_SIZE: usize = 4096;
+
+const INTERRUPT: char = '\x03';
+const KILL_LINE: char = '\x15';
+const SUSPEND: char = '\x1A';
+
+crate::counter!(pub INTERRUPTS = "tty.interrupts");
+crate::counter!(pub SUSPENDS = "tty.suspends");
+
+/// # Mode
+/// How input is passed on, see the module documentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Canonical,
+    Raw,
+}
+
+impl Mode {
+    pub fn from_abi(mode: u32) -> Result<Self> {
+        match mode {
+            TtyMode::Canonical => Ok(Self::Canonical),
+            TtyMode::Raw => Ok(Self::Raw),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+
+    pub fn to_abi(self) -> u32 {
+        match self {
+            Self::Canonical => TtyMode::Canonical,
+            Self::Raw => TtyMode::Raw,
+        }
+    }
+}
+
+/// # Echo
+/// What a source of input shows for a character it passed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Echo {
+    Nothing,
+    Char(char),
+    /// Erase this many of the last characters shown
+    Erase(usize),
+    NewLine,
+    /// Show `^C` and a line break
+    Interrupt,
+    /// Show `^Z` and a line break
+    Suspend,
+}
+
+impl Echo {
+    /// The signal the foreground process group is sent along with the echo
+    fn signal(&self) -> Option<u32> {
+        match self {
+            Self::Interrupt => Some(Signal::SIGINT),
+            Self::Suspend => Some(Signal::SIGTSTP),
+            _ => None,
+        }
+    }
+}
+
+/// # Ring
+/// The bytes delivered to readers, oldest first
+struct Ring {
+    bytes: [u8; BUFFER_SIZE],
+    start: usize,
+    len: usize,
+    /// The line breaks among the bytes, which is how many lines a canonical reader can take
+    lines: usize,
+}
+
+impl Ring {
+    fn free(&self) -> usize {
+        BUFFER_SIZE - self.len
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.bytes[(self.start + self.len) % BUFFER_SIZE] = byte;
+        self.len += 1;
+        if byte == b'\n' {
+            self.lines += 1;
+        }
+    }
+
+    fn pop(&mut self) -> u8 {
+        let byte = self.bytes[self.start];
+        self.start = (self.start + 1) % BUFFER_SIZE;
+        self.len -= 1;
+        if byte == b'\n' {
+            self.lines -= 1;
+        }
+        byte
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.lines = 0;
+    }
+}
+
+struct Tty {
+    mode: Mode,
+    line: [u8; MAX_LINE],
+    line_len: usize,
+    ready: Ring,
+    /// Whether the last character was a carriage return
+    after_return: bool,
+    /// The process group Ctrl-C and Ctrl-Z signal, zero if there is none
+    foreground: u64,
+}
+
+impl Tty {
+    /// Whether a reader in the current mode has something to take
+    fn has_input(&self) -> bool {
+        match self.mode {
+            Mode::Canonical => self.ready.lines > 0,
+            Mode::Raw => self.ready.len > 0,
+        }
+    }
+
+    /// The characters of the current line, as many as have to be erased to remove it
+    fn line_chars(&self) -> usize {
+        self.line[..self.line_len]
+            .iter()
+            .filter(|byte| !is_continuation(**byte))
+            .count()
+    }
+
+    /// Removes the last character of the current line
+    fn erase(&mut self) -> bool {
+        if self.line_len == 0 {
+            return false;
+        }
+        self.line_len -= 1;
+        while self.line_len > 0 && is_continuation(self.line[self.line_len]) {
+            self.line_len -= 1;
+        }
+        true
+    }
+
+    fn canonical(&mut self, c: char) -> Echo {
+        match c {
+            '\r' | '\n' => {
+                // The line break always fits, as the line only grows while it does
+                for idx in 0..self.line_len {
+                    let byte = self.line[idx];
+                    self.ready.push(byte);
+                }
+                self.ready.push(b'\n');
+                self.line_len = 0;
+                Echo::NewLine
+            }
+            '\x08' | '\x7F' if self.erase() => Echo::Erase(1),
+            KILL_LINE => {
+                let chars = self.line_chars();
+                self.line_len = 0;
+                Echo::Erase(chars)
+            }
+            INTERRUPT => {
+                self.line_len = 0;
+                INTERRUPTS.increment();
+                Echo::Interrupt
+            }
+            SUSPEND => {
+                self.line_len = 0;
+                SUSPENDS.increment();
+                Echo::Suspend
+            }
+            c if !c.is_control() => {
+                let mut bytes = [0; 4];
+                let bytes = c.encode_utf8(&mut bytes).as_bytes();
+                let len = self.line_len + bytes.len();
+                if len > MAX_LINE || len + 1 > self.ready.free() {
+                    return Echo::Nothing;
+                }
+                self.line[self.line_len..len].copy_from_slice(bytes);
+                self.line_len = len;
+                Echo::Char(c)
+            }
+            _ => Echo::Nothing,
+        }
+    }
+
+    fn raw(&mut self, c: char) -> Echo {
+        let mut bytes = [0; 4];
+        let bytes = c.encode_utf8(&mut bytes).as_bytes();
+        if bytes.len() <= self.ready.free() {
+            bytes.iter().for_each(|byte| self.ready.push(*byte));
+        }
+        Echo::Nothing
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+static TTY: IrqSpinLock<Tty> = crate::named_lock!(
+    "tty",
+    Tty {
+        mode: Mode::Canonical,
+        line: [0; MAX_LINE],
+        line_len: 0,
+        ready: Ring {
+            bytes: [0; BUFFER_SIZE],
+            start: 0,
+            len: 0,
+            lines: 0,
+        },
+        after_return: false,
+        foreground: 0,
+    }
+);
+/// The tasks waiting in `read()`
+static READERS: WaitQueue = WaitQueue::new();
+
+/// # Input
+/// Passes a character typed on any source of input through the line discipline. A line feed
+/// that follows a carriage return is part of the same line break. Ctrl-C and Ctrl-Z signal the
+/// foreground process group.
+///
+/// ## Returns
+/// - Echo = What the source should show for `c`
+pub fn input(c: char) -> Echo {
+    let (echo, wake, foreground) = {
+        let mut tty = TTY.lock();
+        let after_return = core::mem::replace(&mut tty.after_return, c == '\r');
+        let echo = match tty.mode {
+            Mode::Canonical if c == '\n' && after_return => Echo::Nothing,
+            Mode::Canonical => tty.canonical(c),
+            Mode::Raw => tty.raw(c),
+        };
+        (echo, tty.has_input(), tty.foreground)
+    };
+    if wake {
+        READERS.wake_all();
+    }
+    if let Some(signal) = echo.signal().filter(|_| foreground != 0) {
+        debug!("tty: Sending {} to process group {}", signal, foreground);
+        // The group may have exited since it was made the foreground group
+        let _ = signal::send_group(TaskId::new(foreground), signal);
+    }
+    echo
+}
+
+/// # Read
+/// Takes what was delivered: in canonical mode at most one line, including its line break, in
+/// raw mode whatever bytes there are. Waits until there is something unless `nonblocking`.
+///
+/// ## Returns
+/// - usize = The bytes read, only zero if `buf` is empty
+/// - Error::TryAgain = There is nothing to read and `nonblocking` is set
+pub fn read(buf: &mut [u8], nonblocking: bool) -> Result<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    loop {
+        {
+            let mut tty = TTY.lock();
+            if tty.has_input() {
+                let mode = tty.mode;
+                let mut read = 0;
+                while read < buf.len() && tty.ready.len > 0 {
+                    buf[read] = tty.ready.pop();
+                    read += 1;
+                    if mode == Mode::Canonical && buf[read - 1] == b'\n' {
+                        break;
+                    }
+                }
+                return Ok(read);
+            }
+        }
+        if nonblocking {
+            return Err(Error::TryAgain);
+        }
+        READERS.wait_until(|| TTY.lock().has_input());
+    }
+}
+
+pub fn mode() -> Mode {
+    TTY.lock().mode
+}
+
+/// # Set Mode
+/// Switches to `mode`, discarding the current line and what was not read yet
+///
+/// ## Returns
+/// - Mode = The previous mode
+pub fn set_mode(mode: Mode) -> Mode {
+    let mut tty = TTY.lock();
+    tty.line_len = 0;
+    tty.ready.clear();
+    core::mem::replace(&mut tty.mode, mode)
+}
+
+/// # Foreground Group
+/// The process group Ctrl-C and Ctrl-Z signal, zero if there is none
+pub fn foreground_group() -> u64 {
+    TTY.lock().foreground
+}
+
+pub fn set_foreground_group(group: u64) {
+    TTY.lock().foreground = group;
+}
+
+/// # Ioctl
+/// Handles `ioctl(fd, request, arg)` on a descriptor of the console. `arg` is a pointer for
+/// the requests that return something.
+///
+/// ## Returns
+/// - Error::InvalidArgument = The request or the mode is unknown, or the process group negative
+/// - Error::OperationNotPermitted = No task is in the process group to make the foreground one
+pub fn ioctl(request: u64, arg: u64) -> Result<i32> {
+    match request {
+        TtyRequest::GetMode => usermem::write_user(user_address(arg)?, mode().to_abi())?,
+        TtyRequest::SetMode => {
+            let mode = u32::try_from(arg).map_err(|_| Error::InvalidArgument)?;
+            set_mode(Mode::from_abi(mode)?);
+        }
+        TtyRequest::GetForegroundGroup => {
+            usermem::write_user(user_address(arg)?, foreground_group() as i32)?
+        }
+        TtyRequest::SetForegroundGroup => {
+            let group = usermem::read_user::<i32>(user_address(arg)?)?;
+            if group < 0 {
+                return Err(Error::InvalidArgument);
+            }
+            if group != 0 && !signal::group_exists(TaskId::new(group as u64)) {
+                return Err(Error::OperationNotPermitted);
+            }
+            set_foreground_group(group as u64);
+        }
+        _ => return Err(Error::InvalidArgument),
+    }
+    Ok(0)
+}
+
+/// # Echo
+/// Shows `echo` on the console and on the virtio console, for readers in raw mode that echo
+/// what they read themselves
+pub fn echo(echo: Echo) {
+    let mut bytes = [0; 4];
+    let text = match echo {
+        Echo::Nothing => return,
+        Echo::Char(c) => {
+            let c = c.encode_utf8(&mut bytes);
+            kprint!("{}", c);
+            &*c
+        }
+        Echo::Erase(chars) => {
+            for _ in 0..chars {
+                unsafe { FRAMEBUFFER_GUARD.lock().assume_init_mut() }.clear_last_char();
+            }
+            "\x08 \x08"
+        }
+        Echo::NewLine => {
+            kprintln!();
+            "\n"
+        }
+        Echo::Interrupt => {
+            kprintln!("^C");
+            "^C\n"
+        }
+        Echo::Suspend => {
+            kprintln!("^Z");
+            "^Z\n"
+        }
+    };
+    if let Some(console) = virtio::console::console() {
+        let count = match echo {
+            Echo::Erase(chars) => chars,
+            _ => 1,
+        };
+        for _ in 0..count {
+            console.write(text);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! # UEFI Runtime Services
+//! The services the firmware keeps around after the bootloader exited its boot services. The
+//! bootloader hands over their table and whether it switched them to virtual addresses with
+//! `SetVirtualAddressMap`, which it does with an identity map. Without it they run in physical
+//! mode, which works all the same, as the kernel identity maps all of memory.
+//!
+//! Only `GetTime`, which is checked against the RTC at boot, and the variables of the kernel in
+//! the `VENDOR` namespace are used. The services are not reentrant, so every call is serialized
+//! behind `RUNTIME`, which also keeps interrupts disabled during the call as the specification
+//! requires. Without runtime services, every call fails with `Error::NoSuchDevice`.
+use crate::config::handover;
+use crate::error::{Error, Result};
+use crate::scheduler::IrqSpinLock;
+use crate::time::{rtc, DateTime};
+use crate::{info, warn};
+
+/// "RUNTSERV"
+pub const SIGNATURE: u64 = 0x5652_4553_544E_5552;
+/// The longest variable name, in UCS-2 characters with the terminating zero
+pub const MAX_NAME_LENGTH: usize = 32;
+/// How far `GetTime` and the RTC may be apart before it is worth a warning
+pub const MAX_CLOCK_SKEW_SECS: i64 = 2;
+
+/// The namespace of the variables of the kernel
+pub const VENDOR: Guid = Guid {
+    data1: 0x3F1C_9A52,
+    data2: 0x7D04,
+    data3: 0x4E8B,
+    data4: [0x9A, 0x61, 0x2C, 0x5E, 0x0B, 0x77, 0xD3, 0x18],
+};
+
+/// Kept across power cycles, and visible to the boot services and the runtime services
+const VARIABLE_ATTRIBUTES: u32 = 0x1 | 0x2 | 0x4;
+
+pub type Status = usize;
+
+const STATUS_ERROR: Status = 1 << 63;
+
+enumtastic::const_enum! {
+    pub enum EfiStatus: usize => {
+        Success = 0,
+        InvalidParameter = STATUS_ERROR | 2,
+        Unsupported = STATUS_ERROR | 3,
+        BufferTooSmall = STATUS_ERROR | 5,
+        DeviceError = STATUS_ERROR | 7,
+        WriteProtected = STATUS_ERROR | 8,
+        OutOfResources = STATUS_ERROR | 9,
+        NotFound = STATUS_ERROR | 14,
+    }
+
+    impl {}
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid {
+    pub data1: u32,
+    pub data2: u16,
+    pub data3: u16,
+    pub data4: [u8; 8],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct EfiTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    _pad1: u8,
+    nanosecond: u32,
+    time_zone: i16,
+    daylight: u8,
+    _pad2: u8,
+}
+
+#[repr(C)]
+struct TableHeader {
+    signature: u64,
+    revision: u32,
+    header_size: u32,
+    crc32: u32,
+    _reserved: u32,
+}
+
+/// # Runtime Services
+/// The start of the table, up to the last service that is used
+#[repr(C)]
+struct RuntimeServices {
+    header: TableHeader,
+    get_time: unsafe extern "win64" fn(time: *mut EfiTime, capabilities: *mut u8) -> Status,
+    _set_time: usize,
+    _get_wakeup_time: usize,
+    _set_wakeup_time: usize,
+    _set_virtual_address_map: usize,
+    _convert_pointer: usize,
+    get_variable: unsafe extern "win64" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: *mut u32,
+        size: *mut usize,
+        data: *mut u8,
+    ) -> Status,
+    _get_next_variable_name: usize,
+    set_variable: unsafe extern "win64" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: u32,
+        size: usize,
+        data: *const u8,
+    ) -> Status,
+}
+
+static RUNTIME: IrqSpinLock<Option<&'static RuntimeServices>> =
+    crate::named_lock!("uefi_runtime", None);
+
+crate::initcall! {
+    name: "uefi_rt",
+    stage: Platform,
+    deps: [],
+    fatal: false,
+    init: init_uefi_rt,
+}
+
+/// # Variable
+/// A variable of the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    /// Set by a boot that panicked, reported and cleared by the next one. Unlike the crash log,
+    /// it survives a power cycle.
+    LastBootFailed,
+}
+
+impl Variable {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LastBootFailed => "EsqLastBootFailed",
+        }
+    }
+}
+
+/// # Check Status
+/// `status` as a result. Warnings count as success.
+pub fn check_status(status: Status) -> Result<()> {
+    if status & STATUS_ERROR == 0 {
+        return Ok(());
+    }
+    Err(match status {
+        EfiStatus::InvalidParameter | EfiStatus::BufferTooSmall => Error::InvalidArgument,
+        EfiStatus::Unsupported => Error::NoSuchDevice,
+        EfiStatus::WriteProtected => Error::ReadOnlyFileSystem,
+        EfiStatus::OutOfResources => Error::NoSpaceLeftOnDevice,
+        EfiStatus::NotFound => Error::NoSuchFileOrDirectory,
+        _ => Error::IOError,
+    })
+}
+
+/// # Encode Name
+/// `name` in UCS-2 with a terminating zero, as the firmware takes it
+///
+/// ## Returns
+/// - None = `name` does not fit into `MAX_NAME_LENGTH` characters, or is not in the BMP
+pub fn encode_name(name: &str) -> Option<[u16; MAX_NAME_LENGTH]> {
+    let mut encoded = [0; MAX_NAME_LENGTH];
+    for (idx, c) in name.chars().enumerate() {
+        if idx + 1 >= MAX_NAME_LENGTH || c.len_utf16() != 1 || c == '\0' {
+            return None;
+        }
+        encoded[idx] = c as u16;
+    }
+    Some(encoded)
+}
+
+/// # Init UEFI Runtime
+/// Picks up the runtime services the bootloader handed over, if there are any, compares their
+/// clock with the RTC and reports a failed previous boot
+fn init_uefi_rt() -> Result<()> {
+    let (table, virtual_map) = {
+        let handover = handover();
+        (handover.runtime_services, handover.runtime_virtual_map)
+    };
+    if table == 0 {
+        info!("uefi: No runtime services");
+        return Ok(());
+    }
+    // Mapped at the same address either way
+    let services = unsafe { &*(table as *const RuntimeServices) };
+    if services.header.signature != SIGNATURE {
+        warn!("uefi: Invalid runtime services table at {:#x}", table);
+        return Err(Error::InvalidArgument);
+    }
+    *RUNTIME.lock() = Some(services);
+    info!(
+        "uefi: Runtime services {}.{}{}",
+        services.header.revision >> 16,
+        services.header.revision & 0xFFFF,
+        if virtual_map { "" } else { " in physical mode" }
+    );
+
+    match (get_time(), rtc::read()) {
+        (Ok(firmware), Some(clock)) => {
+            let skew = (firmware.unix_seconds() - clock.unix_seconds()).abs();
+            if skew > MAX_CLOCK_SKEW_SECS {
+                warn!(
+                    "uefi: The firmware time {} is {} s off the RTC time {}",
+                    firmware, skew, clock
+                );
+            } else {
+                info!("uefi: The time is {}", firmware);
+            }
+        }
+        (Ok(firmware), None) => info!("uefi: The time is {}, the RTC is unreadable", firmware),
+        (Err(err), _) => warn!("uefi: GetTime failed: {}", err.text()),
+    }
+
+    let mut failed = [0];
+    if let Ok(len) = get_variable(Variable::LastBootFailed, &mut failed) {
+        if len != 0 && failed[0] != 0 {
+            warn!("uefi: The previous boot failed");
+        }
+        if let Err(err) = set_variable(Variable::LastBootFailed, &[]) {
+            warn!("uefi: Failed to clear the failed boot: {}", err.text());
+        }
+    }
+    Ok(())
+}
+
+pub fn is_available() -> bool {
+    RUNTIME.lock().is_some()
+}
+
+/// # Get Time
+/// The time of the firmware clock, in its time zone
+///
+/// ## Returns
+/// - Error::NoSuchDevice = There are no runtime services
+/// - Error::IOError = The firmware failed to read its clock, or returned an invalid time
+pub fn get_time() -> Result<DateTime> {
+    let runtime = RUNTIME.lock();
+    let services = runtime.ok_or(Error::NoSuchDevice)?;
+    let mut time = EfiTime::default();
+    check_status(unsafe { (services.get_time)(&mut time, core::ptr::null_mut()) })?;
+    let time = DateTime {
+        year: time.year,
+        month: time.month,
+        day: time.day,
+        hour: time.hour,
+        minute: time.minute,
+        second: time.second,
+    };
+    if !time.is_valid() {
+        return Err(Error::IOError);
+    }
+    Ok(time)
+}
+
+/// # Get Variable
+/// Reads `variable` into `buffer`
+///
+/// ## Returns
+/// - usize = The size of the variable
+/// - Error::NoSuchDevice = There are no runtime services
+/// - Error::NoSuchFileOrDirectory = The variable is not set
+/// - Error::InvalidArgument = The variable is larger than `buffer`
+pub fn get_variable(variable: Variable, buffer: &mut [u8]) -> Result<usize> {
+    let name = encode_name(variable.name()).ok_or(Error::InvalidArgument)?;
+    let runtime = RUNTIME.lock();
+    let services = runtime.ok_or(Error::NoSuchDevice)?;
+    let mut attributes = 0;
+    let mut size = buffer.len();
+    check_status(unsafe {
+        (services.get_variable)(
+            name.as_ptr(),
+            &VENDOR,
+            &mut attributes,
+            &mut size,
+            buffer.as_mut_ptr(),
+        )
+    })?;
+    Ok(size)
+}
+
+/// # Set Variable
+/// Writes `data` to `variable`, which is kept across power cycles. Empty `data` deletes it.
+///
+/// ## Returns
+/// - Error::NoSuchDevice = There are no runtime services
+/// - Error::NoSuchFileOrDirectory = `data` is empty and the variable is not set
+/// - Error::NoSpaceLeftOnDevice = The firmware has no room for the variable
+pub fn set_variable(variable: Variable, data: &[u8]) -> Result<()> {
+    let name = encode_name(variable.name()).ok_or(Error::InvalidArgument)?;
+    let runtime = RUNTIME.lock();
+    let services = runtime.ok_or(Error::NoSuchDevice)?;
+    check_status(unsafe {
+        (services.set_variable)(
+            name.as_ptr(),
+            &VENDOR,
+            VARIABLE_ATTRIBUTES,
+            data.len(),
+            data.as_ptr(),
+        )
+    })
+}
+
+/// # Record Failed Boot
+/// Sets `Variable::LastBootFailed`, called by the panic handler. Gives up instead of waiting if
+/// the panic interrupted a call to the runtime services.
+pub fn record_failed_boot() {
+    if RUNTIME.is_locked() {
+        return;
+    }
+    let _ = set_variable(Variable::LastBootFailed, &[1]);
+}
@@ -31,6 +31,7 @@ pub fn jump_to_userspace(location: u32, argv: Vec<&str>, stack: u32) {
             .collect::<Vec<*const u8>>()
             .as_ptr();
         let stack_ptr = stack as *mut u32;
+        crate::scheduler::cputime::exit_to_user();
         jump_to_userspace_inner(loc, argc as u32, args, stack_ptr);
     }
 }
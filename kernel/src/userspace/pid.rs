@@ -1,6 +1,6 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::arch::scheduler::pit::TIME_SINCE_BOOT;
+use crate::time;
 
 pub static LAST_PID: AtomicUsize = AtomicUsize::new(0);
 pub use esys::process::pid::Pid;
@@ -28,7 +28,7 @@ impl KernelPid for Pid {
     fn random() -> Self {
         let last_pid = LAST_PID.load(Ordering::SeqCst);
         // Pseudo-Random PID
-        let time = (TIME_SINCE_BOOT.lock().read() * 100000000.0) as usize;
+        let time = (time::uptime_ns() / 10) as usize;
         let new_pid_full =
             (((((time / 3) as usize | last_pid) & !last_pid) % (time / 2) as usize) << 4) | 3;
         let lo = new_pid_full & 0x00ff;
@@ -0,0 +1,208 @@
+//! # Watchdog
+//! Reports CPUs that stopped making progress, e.g. because they spin with interrupts disabled
+//! or wait for a lock that is never released.
+//!
+//! Task context bumps a per-CPU heartbeat whenever it passes through the scheduler or calls
+//! `touch()`. The timer interrupt checks the heartbeats of all CPUs, a CPU whose heartbeat did
+//! not change for the number of seconds given by `watchdog=<seconds>` is reported over the
+//! serial port: Where it is, which `IrqSpinLock`s it holds and a backtrace. Other CPUs are made
+//! to report themselves through an NMI. Halted CPUs are not expected to make progress.
+//!
+//! The timer interrupt also enforces deadlines, see `set_deadline()`, which are checked whether
+//! or not the watchdog is enabled.
+//!
+//! ## Notes
+//! The timer only interrupts the bootstrap CPU, so it goes unnoticed if the bootstrap CPU
+//! itself hangs with interrupts disabled.
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::arch::apic::local_apic;
+use crate::arch::backtrace;
+use crate::arch::interrupts::interrupt_frame::InterruptFrame;
+use crate::arch::scheduler::pit;
+use crate::drivers::serial::{Serial, SerialPort};
+use crate::scheduler::sync::held_locks;
+use crate::scheduler::IrqSpinLock;
+use crate::smp::{self, current_cpu, online_cpus, MAX_CPUS};
+use crate::{cmdline, counter, info, warn};
+
+/// The command line option that enables the watchdog, e.g. `watchdog=5` to report CPUs that
+/// are stuck for five seconds
+pub const OPTION: &str = "watchdog";
+
+counter!(pub STUCK_CPUS = "watchdog.stuck");
+
+/// # CPU Watch
+/// The watchdog state of a single CPU
+struct CpuWatch {
+    heartbeat: AtomicU64,
+    /// Set while the CPU is halted waiting for an interrupt
+    idle: AtomicBool,
+    /// The heartbeat seen by the last check and the tick it was first seen at
+    seen: AtomicU64,
+    seen_since: AtomicU64,
+    /// Set once the CPU has been reported, until its heartbeat moves again
+    reported: AtomicBool,
+    /// Set when the CPU is sent an NMI to report itself
+    report_requested: AtomicBool,
+}
+
+impl CpuWatch {
+    const fn new() -> Self {
+        Self {
+            heartbeat: AtomicU64::new(0),
+            idle: AtomicBool::new(false),
+            seen: AtomicU64::new(0),
+            seen_since: AtomicU64::new(0),
+            reported: AtomicBool::new(false),
+            report_requested: AtomicBool::new(false),
+        }
+    }
+}
+
+const UNWATCHED: CpuWatch = CpuWatch::new();
+static WATCHES: [CpuWatch; MAX_CPUS] = [UNWATCHED; MAX_CPUS];
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// The number of timer ticks a heartbeat may stand still for
+static THRESHOLD_TICKS: AtomicU64 = AtomicU64::new(0);
+static TICKS_PER_SECOND: AtomicU64 = AtomicU64::new(1);
+/// The timer ticks seen by `tick()`
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// When the deadline passes, in milliseconds since boot, and what to run then
+static DEADLINE: IrqSpinLock<Option<(u64, fn())>> = crate::named_lock!("watchdog", None);
+
+/// # Init Watchdog
+/// Starts watching the CPUs if `OPTION` is given
+pub fn init_watchdog() {
+    let seconds = match cmdline::value(OPTION).map(str::parse::<u64>) {
+        None | Some(Ok(0)) => return,
+        Some(Ok(seconds)) => seconds,
+        Some(Err(_)) => {
+            warn!("Invalid {}", OPTION);
+            return;
+        }
+    };
+    let frequency = pit::get_frequency().max(1);
+    TICKS_PER_SECOND.store(frequency, Ordering::Relaxed);
+    THRESHOLD_TICKS.store(seconds * frequency, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Release);
+    info!("watchdog: Reporting CPUs that are stuck for {} s", seconds);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// # Touch
+/// Tells the watchdog that the calling CPU makes progress. Code that legitimately keeps the CPU
+/// busy for a long time without going through the scheduler has to call this regularly.
+pub fn touch() {
+    let watch = &WATCHES[current_cpu()];
+    watch.heartbeat.fetch_add(1, Ordering::Relaxed);
+    watch.idle.store(false, Ordering::Relaxed);
+}
+
+/// # Idle
+/// Tells the watchdog that the calling CPU is about to halt until an interrupt arrives
+pub fn idle() {
+    touch();
+    WATCHES[current_cpu()].idle.store(true, Ordering::Relaxed);
+}
+
+/// # Set Deadline
+/// Runs `expired` from the timer interrupt once `ms` milliseconds passed, unless the deadline is
+/// cleared or replaced before. It runs with interrupts disabled, on the bootstrap CPU, and may
+/// not return if it ends the interrupt of the timer itself.
+pub fn set_deadline(ms: u64, expired: fn()) {
+    *DEADLINE.lock() = Some((crate::time::now_ms() + ms, expired));
+}
+
+pub fn clear_deadline() {
+    DEADLINE.lock().take();
+}
+
+/// The callback of the deadline if it passed, which is cleared
+fn expired_deadline() -> Option<fn()> {
+    let mut deadline = DEADLINE.lock();
+    match *deadline {
+        Some((at, expired)) if crate::time::now_ms() >= at => {
+            deadline.take();
+            Some(expired)
+        }
+        _ => None,
+    }
+}
+
+/// # Tick
+/// Runs the deadline if it passed and checks the heartbeats of all CPUs, called by the timer
+/// interrupt with its `frame`
+pub fn tick(frame: &InterruptFrame) {
+    if let Some(expired) = expired_deadline() {
+        expired();
+    }
+    if !is_enabled() {
+        return;
+    }
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    let threshold = THRESHOLD_TICKS.load(Ordering::Relaxed);
+    let this_cpu = current_cpu();
+    for cpu in online_cpus() {
+        let watch = &WATCHES[cpu];
+        let heartbeat = watch.heartbeat.load(Ordering::Relaxed);
+        if heartbeat != watch.seen.load(Ordering::Relaxed) || watch.idle.load(Ordering::Relaxed) {
+            watch.seen.store(heartbeat, Ordering::Relaxed);
+            watch.seen_since.store(now, Ordering::Relaxed);
+            watch.reported.store(false, Ordering::Relaxed);
+            continue;
+        }
+        if now - watch.seen_since.load(Ordering::Relaxed) < threshold
+            || watch.reported.swap(true, Ordering::Relaxed)
+        {
+            continue;
+        }
+        STUCK_CPUS.increment();
+        if cpu == this_cpu {
+            report(cpu, frame.rip, backtrace::frame_pointer());
+        } else if let Some(apic) = local_apic() {
+            watch.report_requested.store(true, Ordering::Release);
+            apic.send_nmi(smp::percpu::cpu(cpu).apic_id());
+        }
+    }
+}
+
+/// # Handle NMI
+/// Reports the calling CPU if the watchdog asked it to
+///
+/// ## Returns
+/// - bool = Whether the NMI came from the watchdog
+pub fn handle_nmi(frame: &InterruptFrame) -> bool {
+    let cpu = current_cpu();
+    if !WATCHES[cpu].report_requested.swap(false, Ordering::Acquire) {
+        return false;
+    }
+    report(cpu, frame.rip, backtrace::frame_pointer());
+    true
+}
+
+/// # Report
+/// Writes what `cpu` is doing to the serial port. The stuck CPU may hold the lock of the serial
+/// port, so the port is written to without it.
+fn report(cpu: usize, rip: u64, rbp: u64) {
+    let mut serial = Serial::new(SerialPort::Com1);
+    let since = WATCHES[cpu].seen_since.load(Ordering::Relaxed);
+    let seconds =
+        (TICKS.load(Ordering::Relaxed) - since) / TICKS_PER_SECOND.load(Ordering::Relaxed);
+    let _ = writeln!(
+        serial,
+        "watchdog: CPU {} made no progress for {} s, at {:#x}",
+        cpu, seconds, rip
+    );
+    for (lock, location) in held_locks(cpu) {
+        let _ = writeln!(serial, "  holding lock {:#x} taken at {}", lock, location);
+    }
+    let _ = writeln!(serial, "  backtrace:");
+    backtrace::walk(rbp, |address| {
+        let _ = writeln!(serial, "    {:#x}", address);
+    });
+}
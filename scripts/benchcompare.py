@@ -0,0 +1,71 @@
+#!/usr/bin/env python3
+"""Compares the results of two benchmark runs.
+
+The kernel writes one line `BENCH <name> <median> <p99>` per benchmark to COM1, `./y.py bench`
+collects them in build/bench.txt. Pass the results of the baseline first:
+
+    scripts/benchcompare.py baseline.txt build/bench.txt
+    scripts/benchcompare.py --tolerance 25 baseline.txt build/bench.txt
+
+Exits with 1 if the median or the p99 of a benchmark grew by more than the tolerance, in
+percent, or a benchmark of the baseline is missing.
+"""
+import argparse
+import sys
+
+PREFIX = "BENCH"
+
+
+def parse(path):
+    """The median and the p99 of every benchmark in the file at `path`, the last run wins"""
+    results = {}
+    with open(path, errors="replace") as lines:
+        for line in lines:
+            fields = line.split()
+            if len(fields) != 4 or fields[0] != PREFIX:
+                continue
+            try:
+                results[fields[1]] = (int(fields[2]), int(fields[3]))
+            except ValueError:
+                continue
+    return results
+
+
+def change(old, new):
+    """The change from `old` to `new` in percent"""
+    if old == 0:
+        return 0.0 if new == 0 else float("inf")
+    return (new - old) * 100.0 / old
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Compares two benchmark runs")
+    parser.add_argument("baseline")
+    parser.add_argument("current")
+    parser.add_argument("--tolerance", type=float, default=10.0,
+                        help="How many percent slower a result may get (default: 10)")
+    args = parser.parse_args()
+
+    baseline = parse(args.baseline)
+    current = parse(args.current)
+    failed = False
+    print(f"{'name':<10} {'median':>12} {'change':>8} {'p99':>12} {'change':>8}")
+    for name, (old_median, old_p99) in sorted(baseline.items()):
+        if name not in current:
+            print(f"{name:<10} missing")
+            failed = True
+            continue
+        median, p99 = current[name]
+        median_change = change(old_median, median)
+        p99_change = change(old_p99, p99)
+        regressed = median_change > args.tolerance or p99_change > args.tolerance
+        failed |= regressed
+        print(f"{name:<10} {median:>12} {median_change:>+7.1f}% {p99:>12} {p99_change:>+7.1f}%"
+              + ("  REGRESSION" if regressed else ""))
+    for name in sorted(current.keys() - baseline.keys()):
+        print(f"{name:<10} new")
+    return 1 if failed else 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
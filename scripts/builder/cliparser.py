@@ -18,6 +18,7 @@ SUBCOMMANDS = [
     "doc",
     "setup",
     "test",
+    "bench",
     "debug",
     "apps",
     "new-app",
@@ -41,6 +42,7 @@ SUBCOMMANDS_TO_FN = {
     "doc": sc.build_docs,
     "setup": sc.setup,
     "test": sc.test,
+    "bench": sc.bench,
     "debug": sc.debug,
     "apps": sc.apps,
     "new-app": sc.new_app,
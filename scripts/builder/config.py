@@ -27,6 +27,7 @@ QEMU_MACHINE = "q35"
 QEMU_OPTS = []
 QEMU_SHOULD_LOG = True
 QEMU_SMP = 1
+QEMU_DISK_IF = "ide"
 SHOULD_RUN: bool = False
 NEVER_RUN: bool = False
 OUT_IMG: str = ""
@@ -148,6 +149,7 @@ def parse_config(config_path):
     global QEMU_MACHINE
     global QEMU_OPTS
     global QEMU_SMP
+    global QEMU_DISK_IF
     global QEMU_SHOULD_LOG
     global SHOULD_RUN
     global NEVER_RUN
@@ -204,6 +206,7 @@ def parse_config(config_path):
         QEMU_LOGFILE = cfg["qemu"]["logfile"]
         QEMU_SHOULD_LOG = cfg["qemu"]["should-log"]
         QEMU_SMP = cfg["qemu"]["smp"]
+        QEMU_DISK_IF = cfg["qemu"].get("disk-interface", "ide")
         QEMU_MACHINE = cfg["qemu"]["machine"]
         QEMU_OPTS = cfg["qemu"]["qemu-opts"]
 
@@ -67,7 +67,7 @@ def run_qemu(in_background=False, return_exit_code=False, exit_on_error=True) ->
         return 1
 
     QEMU_FLAGS = [
-        f"-drive file={config.OUT_IMG},format=raw" if config.OUT_IMG != "" else "-drive file=build/esque-m,format=raw",
+        f"-drive file={config.OUT_IMG or 'build/esque-m'},format=raw,if={config.QEMU_DISK_IF}",
         f"-m {config.MEMLIM}",
         "-enable-kvm" if config.QEMU_KVM else f"-cpu {config.QEMU_CPU}",
         f"-machine {config.QEMU_MACHINE},accel=kvm:tcg",
@@ -117,6 +117,11 @@ def debug() -> int:
 
 def strip() -> int:
     run(["objcopy", "--only-keep-debug", "build/esque", "build/esque.sym"])
+    # The kernel resolves code addresses through this module, e.g. for `profile report`
+    os.makedirs("build/modules", exist_ok=True)
+    with open("build/modules/symbols", "w") as symbols:
+        subprocess.run(["nm", "--defined-only", "--numeric-sort", "--demangle", "build/esque"],
+                       stdout=symbols, check=True)
     if config.STRIP:
         run(["objcopy", "--strip-debug", "build/esque"])
         info("Striping binaries...")
@@ -136,6 +141,14 @@ def image():
     run(["mcopy", "-i", f"{config.OUT_IMG}", "binaries/font/font.psf", "::"])
     run(["mcopy", "-i", f"{config.OUT_IMG}", "binaries/efi-shell/startup.nsh", "::"])
     run(["mcopy", "-i", f"{config.OUT_IMG}", "build/initramfs.tar", "::"])
+    # Asks the kernel to mount this partition at /disk
+    pathlib.Path("build/ESQUE.MNT").touch()
+    run(["mcopy", "-i", f"{config.OUT_IMG}", "build/ESQUE.MNT", "::"])
+    # Every file in build/modules is handed over to the kernel as a boot module
+    if os.path.isdir("build/modules") and os.listdir("build/modules"):
+        run(["mmd", "-i", f"{config.OUT_IMG}", "::/modules"])
+        for module in os.listdir("build/modules"):
+            run(["mcopy", "-i", f"{config.OUT_IMG}", f"build/modules/{module}", "::/modules"])
     success(f"Successfully made image ({config.OUT_IMG})")
     return 0
 
@@ -203,6 +216,35 @@ def test_inner(should_run=False) -> int:
 def test() -> int:
     return test_inner(True)
 
+def bench() -> int:
+    # The `bench` flag makes the kernel run every benchmark at boot and leave QEMU
+    os.makedirs("build/modules", exist_ok=True)
+    cmdline_path = "build/modules/cmdline"
+    previous = None
+    if os.path.exists(cmdline_path):
+        with open(cmdline_path) as cmdline:
+            previous = cmdline.read()
+    with open(cmdline_path, "w") as cmdline:
+        cmdline.write(((previous or "") + " bench").strip())
+
+    code = build()
+    if previous is None:
+        os.remove(cmdline_path)
+    else:
+        with open(cmdline_path, "w") as cmdline:
+            cmdline.write(previous)
+    if code != 0:
+        return code
+
+    config.QEMU_OPTS += ["-serial", "file:build/bench.log", "-display", "none"]
+    run_qemu(return_exit_code=True, exit_on_error=False)
+    with open("build/bench.log", errors="replace") as log, open("build/bench.txt", "w") as results:
+        for line in log:
+            if line.startswith("BENCH "):
+                results.write(line)
+    success("Wrote the results to build/bench.txt, compare them with scripts/benchcompare.py")
+    return 0
+
 def apps() -> int:
     path = pathlib.Path("apps")
     apps = [f for f in path.iterdir() if f.is_dir()]
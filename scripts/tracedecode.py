@@ -0,0 +1,124 @@
+#!/usr/bin/env python3
+"""Decodes the event trace `tracedump` writes to COM1.
+
+Pass the captured serial output, e.g. the file of `-serial file:serial.log`, or pipe it in:
+
+    scripts/tracedecode.py serial.log
+    scripts/tracedecode.py --csv serial.log > trace.csv
+
+The last dump in the input is decoded. The event ids and the record layout have to match
+`kernel/src/trace.rs`.
+"""
+import argparse
+import struct
+import sys
+
+PREFIX = "esque-trace"
+FORMAT_VERSION = 1
+# tsc, cpu, event, reserved, first argument, second argument
+RECORD = struct.Struct("<QHHIQQ")
+
+EVENTS = {
+    1: ("sched_switch", "prev", "next"),
+    2: ("sched_wake", "task", "cpu"),
+    3: ("page_fault", "addr", "error"),
+    4: ("syscall_entry", "nr", "arg0"),
+    5: ("syscall_exit", "nr", "ret"),
+    6: ("irq_entry", "vector", None),
+    7: ("irq_exit", "vector", "cycles"),
+    8: ("lock_contended", "lock", "cycles"),
+}
+
+
+def parse_header(line):
+    fields = dict(field.split("=", 1) for field in line.split()[2:] if "=" in field)
+    return {key: int(value) for key, value in fields.items()}
+
+
+def last_dump(lines):
+    """The header, the records and the lock names of the last complete dump"""
+    dump = None
+    current = None
+    for line in lines:
+        line = line.strip()
+        if not line.startswith(PREFIX):
+            continue
+        words = line.split()
+        if len(words) < 2:
+            continue
+        if words[1] == "begin":
+            current = (parse_header(line), [], {"class": {}, "site": {}})
+        elif words[1] == "end":
+            if current is not None:
+                dump = current
+            current = None
+        elif words[1] in ("class", "site") and len(words) == 4 and current is not None:
+            current[2][words[1]][int(words[2])] = words[3]
+        elif current is not None:
+            try:
+                data = bytes.fromhex(words[1])
+            except ValueError:
+                continue
+            if len(data) == RECORD.size:
+                current[1].append(RECORD.unpack(data))
+    return dump
+
+
+HEX_ARGS = ("addr", "error", "vector", "arg0")
+
+
+def format_arg(name, value, names):
+    if name == "lock":
+        # The class in the low and the waiter in the high 32 bits
+        lock = names["class"].get(value & 0xFFFFFFFF, "?")
+        site = names["site"].get(value >> 32, "?")
+        return f"lock={lock} waiter={site}"
+    if name in HEX_ARGS:
+        return f"{name}={value:#x}"
+    if name == "ret":
+        # Errors are negative errno values
+        return f"{name}={struct.unpack('<q', struct.pack('<Q', value))[0]}"
+    return f"{name}={value}"
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("input", nargs="?", help="The captured serial output, stdin if omitted")
+    parser.add_argument("--csv", action="store_true", help="Print CSV instead of text")
+    args = parser.parse_args()
+
+    source = open(args.input, errors="replace") if args.input else sys.stdin
+    with source:
+        dump = last_dump(source)
+    if dump is None:
+        sys.exit("tracedecode: No complete trace dump in the input")
+    header, records, names = dump
+    if header.get("version") != FORMAT_VERSION:
+        sys.exit(f"tracedecode: Unsupported dump version {header.get('version')}")
+    if len(records) != header.get("records"):
+        print(f"tracedecode: Expected {header.get('records')} records, got {len(records)}",
+              file=sys.stderr)
+    if header.get("overwritten"):
+        print(f"tracedecode: {header['overwritten']} older records were overwritten",
+              file=sys.stderr)
+
+    records.sort(key=lambda record: record[0])
+    khz = header.get("tsc_khz", 0)
+    start = records[0][0] if records else 0
+    if args.csv:
+        print("tsc,us,cpu,event,arg0,arg1")
+    for tsc, cpu, event, _, first, second in records:
+        us = (tsc - start) * 1000 / khz if khz else 0.0
+        name, first_name, second_name = EVENTS.get(event, (f"event_{event}", "arg0", "arg1"))
+        if args.csv:
+            print(f"{tsc},{us:.3f},{cpu},{name},{first},{second}")
+            continue
+        fields = [format_arg(first_name, first, names)]
+        if second_name is not None:
+            fields.append(format_arg(second_name, second, names))
+        time = f"{us:12.3f}us" if khz else f"{tsc - start:14}"
+        print(f"{time} cpu{cpu:<3} {name:<14} {' '.join(fields)}")
+
+
+if __name__ == "__main__":
+    main()